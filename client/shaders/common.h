@@ -11,6 +11,10 @@ layout(set = 0, binding = 0) uniform Common {
     mat4 inverse_projection;
     float fog_density;
     float time;
+    // Color of the sky and fog
+    vec4 sky_color;
+    // Direction towards the sun in the viewpoint's node space, with the strength of direct sunlight in w
+    vec4 sun;
 };
 
 #endif
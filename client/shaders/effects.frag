@@ -0,0 +1,34 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    mat4 transform;
+    vec4 color;
+    vec4 params;
+};
+
+layout(location = 0) in vec2 uv;
+
+// Premultiplied alpha, allowing both darkening decals and additive particles
+layout(location = 0) out vec4 color_out;
+
+void main() {
+    if (params.x == 0.0) {
+        // Jagged lines radiating from the center of the face
+        float r = length(uv);
+        float angle = atan(uv.y, uv.x) + params.w * 6.2831853;
+        float wobble = 0.25 * sin(r * 17.0 + angle * 3.0);
+        float ray = abs(sin(2.5 * angle + wobble));
+        float line = 1.0 - smoothstep(0.04, 0.09, ray);
+        float alpha = color.a * line * (1.0 - smoothstep(0.8, 1.0, r));
+        color_out = vec4(color.rgb * alpha, alpha);
+    } else if (params.x == 1.0) {
+        if (dot(uv, uv) > 1.0) {
+            discard;
+        }
+        color_out = vec4(color.rgb * color.a * (1.0 - params.y), 0);
+    } else {
+        // The whole face, fading out
+        float alpha = color.a * (1.0 - params.y);
+        color_out = vec4(color.rgb * alpha, alpha);
+    }
+}
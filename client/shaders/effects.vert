@@ -0,0 +1,42 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    // Maps the anchor's frame to clip space
+    mat4 transform;
+    vec4 color;
+    // x: 0 for cracks, 1 for bursts, 2 for tints; y: fraction of lifetime elapsed; z: half-extent of the face in
+    // the anchor's Beltrami-Klein coordinates; w: random seed
+    vec4 params;
+};
+
+layout(location = 0) out vec2 uv;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1, -1), vec2(1, -1), vec2(-1, 1),
+    vec2(-1, 1), vec2(1, -1), vec2(1, 1)
+);
+
+float hash(float x) {
+    return fract(sin(x * 12.9898 + params.w * 78.233) * 43758.5453);
+}
+
+void main() {
+    uv = CORNERS[gl_VertexIndex];
+    float extent = params.z;
+    vec3 pos;
+    if (params.x != 1.0) {
+        // Lift the decal slightly off the face to avoid z-fighting
+        pos = vec3(uv * extent, extent * 0.01);
+    } else {
+        // Each instance is a particle flung outward from the face, falling back as it fades. Motion
+        // is approximated as euclidean, which is accurate at the scale of a voxel.
+        float i = float(gl_InstanceIndex);
+        vec3 velocity = vec3(hash(i) * 2.0 - 1.0, hash(i + 0.31) * 2.0 - 1.0, 1.0 + hash(i + 0.67));
+        float t = params.y;
+        vec3 center = extent * (2.0 * velocity * t - vec3(0, 0, 3.0 * t * t));
+        float size = extent * 0.12 * (1.0 - t);
+        // Quads lie in the plane of the face
+        pos = center + vec3(uv * size, 0);
+    }
+    gl_Position = transform * vec4(pos, 1);
+}
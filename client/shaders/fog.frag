@@ -17,5 +17,5 @@ void main() {
     // Convert to true hyperbolic distance, taking care to respect atanh's domain
     float dist = view_length >= 1.0 ? INFINITY : atanh(view_length);
     // Exponential^k fog
-    fog = vec4(0.5, 0.65, 0.9, exp(-pow(dist * fog_density, 5)));
+    fog = vec4(sky_color.rgb, exp(-pow(dist * fog_density, 5)));
 }
@@ -5,14 +5,12 @@
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec2 texcoords;
 layout(location = 2) in vec3 normal;
+// Per-instance transform from the mesh's frame to the view's node
+layout(location = 3) in mat4 transform;
 
 layout(location = 0) out vec2 texcoords_out;
 layout(location = 1) out vec4 normal_out;
 
-layout(push_constant) uniform PushConstants {
-    mat4 transform;
-};
-
 void main() {
     gl_Position = view_projection * transform * vec4(position, 1);
     texcoords_out = texcoords;
@@ -0,0 +1,17 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    vec4 disk;
+    vec4 color;
+};
+
+layout(location = 0) in vec2 offset;
+
+layout(location = 0) out vec4 color_out;
+
+void main() {
+    if (dot(offset, offset) > 1.0) {
+        discard;
+    }
+    color_out = color;
+}
@@ -0,0 +1,19 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    // xy: center in clip space, zw: radii in clip space
+    vec4 disk;
+    vec4 color;
+};
+
+layout(location = 0) out vec2 offset;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1, -1), vec2(1, -1), vec2(-1, 1),
+    vec2(-1, 1), vec2(1, -1), vec2(1, 1)
+);
+
+void main() {
+    offset = CORNERS[gl_VertexIndex];
+    gl_Position = vec4(disk.xy + offset * disk.zw, 0, 1);
+}
@@ -0,0 +1,23 @@
+#version 450
+
+#include "font.h"
+
+layout(location = 0) in vec2 uv;
+layout(location = 1) flat in uint glyph;
+layout(location = 2) flat in vec4 text_color;
+
+// Premultiplied alpha
+layout(location = 0) out vec4 color_out;
+
+void main() {
+    uvec2 texel = min(uvec2(uv * 8.0), uvec2(7u));
+    // Rows are stored from the top down
+    uint row = 7u - texel.y;
+    uint bits = FONT[2u * glyph + row / 4u] >> (8u * (row % 4u));
+    bool lit = ((bits >> texel.x) & 1u) != 0u;
+    // Light text on a translucent dark backing, legible against any background
+    // Rendering is in linear intensities, so the sRGB text color is approximately decoded
+    color_out = lit ? vec4(pow(text_color.rgb, vec3(2.2)), 1) : vec4(0, 0, 0, 0.4);
+    // Faded as a whole, which premultiplied alpha makes a single multiplication
+    color_out *= text_color.a;
+}
@@ -0,0 +1,30 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    // Maps the tag's frame, in which glyphs are one unit square, to clip space
+    mat4 transform;
+    // Glyph indices packed four to a word, starting from the least significant byte
+    uint glyphs[12];
+    uint len;
+    // sRGB color of the text and opacity of the whole label, packed as by unpackUnorm4x8
+    uint color;
+};
+
+layout(location = 0) out vec2 uv;
+layout(location = 1) flat out uint glyph;
+layout(location = 2) flat out vec4 text_color;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(0, 0), vec2(1, 0), vec2(0, 1),
+    vec2(0, 1), vec2(1, 0), vec2(1, 1)
+);
+
+void main() {
+    // Each glyph is a quad of six vertices, laid out left to right and centered on the origin
+    uint i = uint(gl_VertexIndex) / 6u;
+    uv = CORNERS[gl_VertexIndex % 6];
+    glyph = (glyphs[i / 4u] >> (8u * (i % 4u))) & 0xFFu;
+    text_color = unpackUnorm4x8(color);
+    vec2 pos = vec2(float(i) - 0.5 * float(len), -0.5) + uv;
+    gl_Position = transform * vec4(pos, 0, 1);
+}
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec2 texcoords;
+
+layout(location = 0) out vec4 color_out;
+
+layout(set = 0, binding = 0) uniform sampler2D rendered;
+
+layout(push_constant) uniform PushConstants {
+    float inverse_gamma;
+    float brightness;
+};
+
+void main() {
+    // Sampling decodes sRGB, so this operates on linear intensities
+    vec3 color = texture(rendered, texcoords).rgb * brightness;
+    color_out = vec4(pow(color, vec3(inverse_gamma)), 1.0);
+}
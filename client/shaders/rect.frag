@@ -0,0 +1,12 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    vec4 rect;
+    vec4 color;
+};
+
+layout(location = 0) out vec4 color_out;
+
+void main() {
+    color_out = color;
+}
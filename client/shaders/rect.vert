@@ -0,0 +1,16 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    // xy: top-left corner in clip space, zw: bottom-right corner in clip space
+    vec4 rect;
+    vec4 color;
+};
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(0, 0), vec2(1, 0), vec2(0, 1),
+    vec2(0, 1), vec2(1, 0), vec2(1, 1)
+);
+
+void main() {
+    gl_Position = vec4(mix(rect.xy, rect.zw, CORNERS[gl_VertexIndex]), 0, 1);
+}
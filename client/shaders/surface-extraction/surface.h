@@ -5,7 +5,7 @@
 struct Surface {
     // From most to least significant byte, (axis, z, y, x)
     uint pos_axis;
-    // From most to least significant byte, (occlusion, <padding>, mat, mat)
+    // From most to least significant byte, (occlusion, part, mat, mat)
     uint occlusion_mat;
 };
 
@@ -30,17 +30,48 @@ uint get_mat(Surface s) {
     return s.occlusion_mat & 0xFFFF;
 }
 
-float get_occlusion(Surface s, uvec2 texcoords) {
-    return float((s.occlusion_mat >> (24 + 2 * (texcoords.x | texcoords.y << 1))) & 0x03) / 3.0 * 0.95 + 0.05;
+// Which part of the voxel's face the surface covers, for voxels that aren't full cubes. Bits 0-1 and
+// 2-3 give its extent along the face's U and V axes respectively, as 0 for all of it, 1 for the lower
+// half, or 2 for the upper half. Bit 4 is set if the surface lies halfway through the voxel rather
+// than on its boundary.
+uint get_part(Surface s) {
+    return (s.occlusion_mat >> 16) & 0xFF;
 }
 
-Surface surface(uvec3 pos, uint axis, bool reverse, uint mat, uvec4 occlusion) {
+// Corners of the part of the face covered, in [0,1]^2 along the face's U and V axes
+vec2 part_min(uint part) {
+    return vec2(equal(uvec2(part & 0x03, (part >> 2) & 0x03), uvec2(2))) * 0.5;
+}
+
+vec2 part_max(uint part) {
+    return 1.0 - vec2(equal(uvec2(part & 0x03, (part >> 2) & 0x03), uvec2(1))) * 0.5;
+}
+
+// Offset of the surface from the voxel's boundary along the face's axis
+float part_depth(uint part) {
+    return float((part >> 4) & 1) * 0.5;
+}
+
+float corner_occlusion(Surface s, uvec2 corner) {
+    return float((s.occlusion_mat >> (24 + 2 * (corner.x | corner.y << 1))) & 0x03) / 3.0 * 0.95 + 0.05;
+}
+
+// Occlusion at `uv` in [0,1]^2 on the voxel's face, interpolated from that at its corners
+float get_occlusion(Surface s, vec2 uv) {
+    return mix(
+        mix(corner_occlusion(s, uvec2(0, 0)), corner_occlusion(s, uvec2(1, 0)), uv.x),
+        mix(corner_occlusion(s, uvec2(0, 1)), corner_occlusion(s, uvec2(1, 1)), uv.x),
+        uv.y
+    );
+}
+
+Surface surface(uvec3 pos, uint axis, bool reverse, uint mat, uint part, uvec4 occlusion) {
     Surface result;
     // Flip the quad if necessary to prevent the triangle dividing line from being parallel to the
     // gradient of ambient occlusion, ensuring isotropy.
     axis += 3 * uint(reverse) + 6 * uint(occlusion.y + occlusion.z > occlusion.x + occlusion.w);
     result.pos_axis = pos.x | pos.y << 8 | pos.z << 16 | axis << 24;
-    result.occlusion_mat = mat | occlusion.x << 24 | occlusion.y << 26 | occlusion.z << 28 | occlusion.w << 30;
+    result.occlusion_mat = mat | part << 16 | occlusion.x << 24 | occlusion.y << 26 | occlusion.z << 28 | occlusion.w << 30;
     return result;
 }
 
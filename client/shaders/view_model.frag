@@ -0,0 +1,35 @@
+#version 450
+
+#include "common.h"
+
+layout(push_constant) uniform PushConstants {
+    mat4 transform;
+    mat4 model;
+    vec4 params;
+};
+
+layout(location = 0) in vec3 texcoords;
+layout(location = 1) in vec3 normal;
+layout(location = 0) out vec4 color;
+
+layout(set = 1, binding = 0) uniform sampler2DArray textures;
+
+// As in voxels.frag
+const float AMBIENT = 0.4;
+const float NIGHT = 0.2;
+const float PLACEHOLDER_LAYER = 126.0;
+
+void main() {
+    // Lit from over the shoulder, since the block isn't in the world to be lit by the sun
+    float facing = max(dot(normalize(normal), normalize(vec3(-0.3, 1.0, 0.6))), 0.0);
+    float light = mix(AMBIENT, 1.0, facing) * mix(NIGHT, 1.0, sun.w);
+    vec4 albedo;
+    if (texcoords.z > PLACEHOLDER_LAYER - 0.5) {
+        bool odd = mod(floor(texcoords.x * 4.0) + floor(texcoords.y * 4.0), 2.0) > 0.5;
+        albedo = odd ? vec4(0.9, 0.1, 0.9, 1.0) : vec4(0.1, 0.1, 0.1, 1.0);
+    } else {
+        albedo = texture(textures, texcoords);
+    }
+    // Drawn opaque even for transparent materials, so the block reads as something held
+    color = vec4(albedo.rgb * light, 1.0);
+}
@@ -0,0 +1,36 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    // Maps the unit cube, spanning -1 to 1 on each axis, to clip space
+    mat4 transform;
+    // Maps the unit cube to view space, for lighting
+    mat4 model;
+    // x: texture layer of the held material
+    vec4 params;
+};
+
+layout(location = 0) out vec3 texcoords;
+layout(location = 1) out vec3 normal;
+
+// Two triangles per face, as fractions of the way along its tangents
+const vec2 CORNERS[6] = vec2[](
+    vec2(0, 0), vec2(1, 0), vec2(1, 1),
+    vec2(0, 0), vec2(1, 1), vec2(0, 1)
+);
+
+void main() {
+    uint face = gl_VertexIndex / 6;
+    vec2 corner = CORNERS[gl_VertexIndex % 6];
+    uint axis = face % 3;
+    float side = face < 3 ? 1.0 : -1.0;
+    vec3 n = vec3(0);
+    n[axis] = side;
+    vec3 u = vec3(0);
+    u[(axis + 1) % 3] = side;
+    vec3 v = vec3(0);
+    v[(axis + 2) % 3] = 1.0;
+    vec3 pos = n + (corner.x * 2.0 - 1.0) * u + (corner.y * 2.0 - 1.0) * v;
+    texcoords = vec3(corner, params.x);
+    normal = mat3(model) * n;
+    gl_Position = transform * vec4(pos, 1);
+}
@@ -1,11 +1,31 @@
 #version 450
 
+#include "common.h"
+
 layout(location = 0) in vec3 texcoords;
 layout(location = 1) in float occlusion;
+layout(location = 2) in float sunlight;
 layout(location = 0) out vec4 color;
 
 layout(set = 1, binding = 1) uniform sampler2DArray textures;
 
+// Fraction of full brightness received by surfaces facing away from the sun
+const float AMBIENT = 0.4;
+// Fraction of daytime brightness remaining at night
+const float NIGHT = 0.2;
+// Texture layer of `PLACEHOLDER_MATERIAL`, which stands in for materials this build doesn't know
+const float PLACEHOLDER_LAYER = 126.0;
+
 void main() {
-    color = texture(textures, texcoords) * occlusion;
+    float light = mix(AMBIENT, 1.0, sunlight) * mix(NIGHT, 1.0, sun.w);
+    vec4 albedo;
+    if (texcoords.z > PLACEHOLDER_LAYER - 0.5) {
+        // A checkerboard no real material looks like, with no texture to sample
+        bool odd = mod(floor(texcoords.x * 4.0) + floor(texcoords.y * 4.0), 2.0) > 0.5;
+        albedo = odd ? vec4(0.9, 0.1, 0.9, 1.0) : vec4(0.1, 0.1, 0.1, 1.0);
+    } else {
+        albedo = texture(textures, texcoords);
+    }
+    // Alpha only matters to transparent materials, which are blended by it
+    color = vec4(albedo.rgb * occlusion * light, albedo.a);
 }
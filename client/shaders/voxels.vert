@@ -8,6 +8,7 @@ layout(location = 0) in mat4 transform;
 
 layout(location = 0) out vec3 texcoords_out;
 layout(location = 1) out float occlusion;
+layout(location = 2) out float sunlight;
 
 layout(set = 1, binding = 0) readonly restrict buffer Surfaces {
     Surface surfaces[];
@@ -17,29 +18,11 @@ layout(push_constant) uniform PushConstants {
     uint dimension;
 };
 
-// Each set of 6 vertices makes a ring around the quad, with the middle and start/end vertices
-// duplicated. Note that the sign only indicates the winding of the face; all faces contain the
-// origin regardless.
-const uvec3 vertices[12][6] = {
-    {{0, 0, 0}, {0, 0, 1}, {0, 1, 1}, {0, 1, 1}, {0, 1, 0}, {0, 0, 0}}, // -X
-    {{0, 0, 0}, {1, 0, 0}, {1, 0, 1}, {1, 0, 1}, {0, 0, 1}, {0, 0, 0}}, // -Y
-    {{0, 0, 0}, {0, 1, 0}, {1, 1, 0}, {1, 1, 0}, {1, 0, 0}, {0, 0, 0}}, // -Z
-
-    {{0, 0, 0}, {0, 1, 0}, {0, 1, 1}, {0, 1, 1}, {0, 0, 1}, {0, 0, 0}}, // +X
-    {{0, 0, 0}, {0, 0, 1}, {1, 0, 1}, {1, 0, 1}, {1, 0, 0}, {0, 0, 0}}, // +Y
-    {{0, 0, 0}, {1, 0, 0}, {1, 1, 0}, {1, 1, 0}, {0, 1, 0}, {0, 0, 0}}, // +Z
-
-    // Versions of the above rotated 90 degrees so the diagonal goes the other way, used to improve
-    // the consistency of barycentric interpolation of ambient occlusion
-    {{0, 0, 1}, {0, 1, 1}, {0, 1, 0}, {0, 1, 0}, {0, 0, 0}, {0, 0, 1}}, // -X
-    {{1, 0, 0}, {1, 0, 1}, {0, 0, 1}, {0, 0, 1}, {0, 0, 0}, {1, 0, 0}}, // -Y
-    {{0, 1, 0}, {1, 1, 0}, {1, 0, 0}, {1, 0, 0}, {0, 0, 0}, {0, 1, 0}}, // -Z
-
-    {{0, 0, 1}, {0, 0, 0}, {0, 1, 0}, {0, 1, 0}, {0, 1, 1}, {0, 0, 1}}, // +X
-    {{1, 0, 0}, {0, 0, 0}, {0, 0, 1}, {0, 0, 1}, {1, 0, 1}, {1, 0, 0}}, // +Y
-    {{0, 1, 0}, {0, 0, 0}, {1, 0, 0}, {1, 0, 0}, {1, 1, 0}, {0, 1, 0}}  // +Z
-};
-
+// Each set of 6 corners makes a ring around the quad, with the middle and start/end corners
+// duplicated, given along the face's U and V axes, which follow its own axis in XYZ order. The
+// winding differs between -X/-Y/-Z and +X/+Y/+Z faces, and the rotated versions are used so the
+// diagonal goes the other way, which improves the consistency of barycentric interpolation of
+// ambient occlusion.
 const uvec2 texcoords[4][6] = {
     {{0, 0}, {0, 1}, {1, 1}, {1, 1}, {1, 0}, {0, 0}},
     {{0, 0}, {1, 0}, {1, 1}, {1, 1}, {0, 1}, {0, 0}},
@@ -54,9 +37,22 @@ void main()  {
     Surface s = surfaces[index];
     uvec3 pos = get_pos(s);
     uint axis = get_axis(s);
-    uvec2 uv = texcoords[axis / 3][vertex];
+    uint part = get_part(s);
+    // Faces of voxels that aren't full cubes may cover only part of the voxel's face
+    vec2 uv = mix(part_min(part), part_max(part), vec2(texcoords[axis / 3][vertex]));
     texcoords_out = vec3(uv, get_mat(s) - 1);
     occlusion = get_occlusion(s, uv);
-    vec3 relative_coords = vertices[axis][vertex] + pos;
-    gl_Position = view_projection * transform * vec4(relative_coords / dimension, 1);
+    vec3 relative_coords = vec3(pos);
+    relative_coords[axis % 3] += part_depth(part);
+    relative_coords[(axis + 1) % 3] += uv.x;
+    relative_coords[(axis + 2) % 3] += uv.y;
+    vec4 node_pos = transform * vec4(relative_coords / dimension, 1);
+    gl_Position = view_projection * node_pos;
+
+    // Approximate the face normal in node space by offsetting along it in cube space
+    vec3 cube_normal = vec3(0);
+    cube_normal[axis % 3] = (axis / 3) % 2 == 0u ? -1.0 : 1.0;
+    vec4 offset_pos = transform * vec4((relative_coords + cube_normal) / dimension, 1);
+    vec3 normal = normalize(offset_pos.xyz / offset_pos.w - node_pos.xyz / node_pos.w);
+    sunlight = sun.w * max(dot(normal, sun.xyz), 0);
 }
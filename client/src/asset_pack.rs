@@ -0,0 +1,460 @@
+//! Where assets are read from
+//!
+//! Assets are named by their paths within a pack, like `materials/00001_dirt.png`. Each is read
+//! from the first of an ordered list of sources that has a usable copy: the pack suggested by the
+//! server, if the player allows it, then the player's own pack, then the built-in assets. A pack
+//! need only supply the assets it changes, and an asset it supplies that turns out to be unusable
+//! is taken from the next source instead, so a broken asset never costs more than itself.
+
+use std::{
+    fmt, fs,
+    fs::File,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use tracing::{debug, info, warn};
+
+use common::proto::{AssetPackOffer, PackHash};
+
+use crate::Config;
+
+/// Largest asset pack that will be downloaded
+const MAX_DOWNLOAD_BYTES: u64 = 512 * 1024 * 1024;
+
+/// A directory or zip archive of assets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackSource {
+    Dir(PathBuf),
+    Zip(PathBuf),
+}
+
+impl PackSource {
+    /// The pack at `path`, which is taken to be a zip archive if its name ends in `.zip`
+    pub fn open(path: PathBuf) -> Self {
+        if path
+            .extension()
+            .map_or(false, |x| x.eq_ignore_ascii_case("zip"))
+        {
+            Self::Zip(path)
+        } else {
+            Self::Dir(path)
+        }
+    }
+
+    /// Contents of the asset `key`, if this source has it
+    pub fn read(&self, key: &Path) -> Result<Option<Vec<u8>>> {
+        match *self {
+            Self::Dir(ref dir) => match fs::read(dir.join(key)) {
+                Ok(x) => Ok(Some(x)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            Self::Zip(ref path) => {
+                let mut archive = open_zip(path)?;
+                let mut file = match archive.by_name(&zip_name(key)?) {
+                    Ok(x) => x,
+                    Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                let mut data = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut data)?;
+                Ok(Some(data))
+            }
+        }
+    }
+
+    /// Names of the files directly within the directory `key` of this source, if any
+    pub fn list(&self, key: &Path) -> Result<Vec<String>> {
+        match *self {
+            Self::Dir(ref dir) => {
+                let entries = match fs::read_dir(dir.join(key)) {
+                    Ok(x) => x,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+                    Err(e) => return Err(e.into()),
+                };
+                let mut names = Vec::new();
+                for entry in entries {
+                    let entry = entry?;
+                    if let Ok(name) = entry.file_name().into_string() {
+                        names.push(name);
+                    }
+                }
+                Ok(names)
+            }
+            Self::Zip(ref path) => {
+                let prefix = format!("{}/", zip_name(key)?);
+                Ok(open_zip(path)?
+                    .file_names()
+                    .filter_map(|name| name.strip_prefix(&prefix))
+                    .filter(|name| !name.is_empty() && !name.contains('/'))
+                    .map(String::from)
+                    .collect())
+            }
+        }
+    }
+}
+
+impl fmt::Display for PackSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Dir(ref x) | Self::Zip(ref x) => x.display().fmt(f),
+        }
+    }
+}
+
+fn open_zip(path: &Path) -> Result<zip::ZipArchive<File>> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    zip::ZipArchive::new(file).with_context(|| format!("reading {}", path.display()))
+}
+
+/// The name under which the asset `key` is stored in a zip archive
+fn zip_name(key: &Path) -> Result<String> {
+    let mut parts = Vec::new();
+    for component in key.components() {
+        match component {
+            Component::Normal(x) => parts.push(
+                x.to_str()
+                    .ok_or_else(|| anyhow!("{} is not valid UTF-8", key.display()))?,
+            ),
+            _ => bail!("{} is not a plain relative path", key.display()),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+/// The sources assets are read from, in order of preference
+#[derive(Debug, Clone)]
+pub struct AssetSources {
+    sources: Vec<PackSource>,
+}
+
+impl AssetSources {
+    /// Sources preferring `server_pack`, if any, then the pack configured in `cfg`, then the
+    /// built-in assets
+    pub fn new(cfg: &Config, server_pack: Option<PathBuf>) -> Self {
+        Self::from_sources(
+            server_pack
+                .into_iter()
+                .chain(cfg.asset_pack.clone())
+                .map(PackSource::open)
+                .chain(cfg.data_dirs.iter().cloned().map(PackSource::Dir))
+                .collect(),
+        )
+    }
+
+    pub fn from_sources(sources: Vec<PackSource>) -> Self {
+        Self { sources }
+    }
+
+    /// Read the asset `key` from the first source that has it and whose copy `accept` can make
+    /// use of
+    ///
+    /// Copies that can't be read or aren't accepted are passed over with a warning.
+    pub fn resolve<T>(
+        &self,
+        key: &Path,
+        accept: impl FnMut(&PackSource, Vec<u8>) -> Result<T>,
+    ) -> Result<T> {
+        self.first_usable(&key.display(), |_| Ok(Some(key.to_owned())), accept)
+    }
+
+    /// Like `resolve`, for the first file in the directory `dir` of each source whose name starts
+    /// with `prefix`
+    pub fn resolve_prefixed<T>(
+        &self,
+        dir: &Path,
+        prefix: &str,
+        accept: impl FnMut(&PackSource, Vec<u8>) -> Result<T>,
+    ) -> Result<T> {
+        self.first_usable(
+            &format_args!("{}/{}*", dir.display(), prefix),
+            |source| {
+                let mut names = source.list(dir)?;
+                names.sort();
+                Ok(names
+                    .into_iter()
+                    .find(|x| x.starts_with(prefix))
+                    .map(|x| dir.join(x)))
+            },
+            accept,
+        )
+    }
+
+    fn first_usable<T>(
+        &self,
+        name: &dyn fmt::Display,
+        mut locate: impl FnMut(&PackSource) -> Result<Option<PathBuf>>,
+        mut accept: impl FnMut(&PackSource, Vec<u8>) -> Result<T>,
+    ) -> Result<T> {
+        for source in &self.sources {
+            let result = locate(source).and_then(|key| {
+                let Some(key) = key else { return Ok(None) };
+                let Some(data) = source.read(&key)? else {
+                    return Ok(None);
+                };
+                accept(source, data)
+                    .map(Some)
+                    .with_context(|| key.display().to_string())
+            });
+            match result {
+                Ok(Some(x)) => {
+                    debug!(%name, %source, "resolved asset");
+                    return Ok(x);
+                }
+                Ok(None) => {}
+                Err(e) => warn!(%name, %source, "passing over unusable asset: {:#}", e),
+            }
+        }
+        Err(anyhow!("no usable copy of {} found", name))
+    }
+}
+
+/// Downloaded asset packs, kept by hash
+pub struct PackCache {
+    dir: PathBuf,
+    /// Total size of cached packs beyond which the least recently used are deleted
+    max_bytes: u64,
+}
+
+impl PackCache {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self { dir, max_bytes }
+    }
+
+    fn path(&self, hash: &PackHash) -> PathBuf {
+        self.dir.join(format!("{hash}.zip"))
+    }
+
+    /// Path of the pack `offer` describes, if it's already cached intact
+    pub fn get(&self, offer: &AssetPackOffer) -> Option<PathBuf> {
+        let path = self.path(&offer.hash);
+        let data = fs::read(&path).ok()?;
+        if let Err(e) = verify(offer, &data) {
+            warn!(path = %path.display(), "discarding cached asset pack: {:#}", e);
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        // Mark it as recently used, so it's the last to be evicted
+        if let Ok(file) = File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(path)
+    }
+
+    /// Verify `data` as the pack `offer` describes and store it, returning where it's stored
+    pub fn insert(&self, offer: &AssetPackOffer, data: &[u8]) -> Result<PathBuf> {
+        verify(offer, data)?;
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("creating {}", self.dir.display()))?;
+        let path = self.path(&offer.hash);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, data).with_context(|| format!("writing {}", partial.display()))?;
+        fs::rename(&partial, &path).with_context(|| format!("writing {}", path.display()))?;
+        if let Err(e) = self.evict(&path) {
+            warn!("failed to evict old asset packs: {:#}", e);
+        }
+        Ok(path)
+    }
+
+    /// Delete the least recently used packs other than `keep` until the cache fits in `max_bytes`
+    fn evict(&self, keep: &Path) -> Result<()> {
+        let mut packs = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                packs.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        let mut total = packs.iter().map(|&(_, len, _)| len).sum::<u64>();
+        packs.sort();
+        for (_, len, path) in packs {
+            if total <= self.max_bytes {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            info!(path = %path.display(), "evicting cached asset pack");
+            fs::remove_file(&path)?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+/// Check that `data` is the pack `offer` describes
+fn verify(offer: &AssetPackOffer, data: &[u8]) -> Result<()> {
+    let actual = PackHash::of(data);
+    if actual != offer.hash {
+        bail!(
+            "asset pack {} has hash {}, but {} was expected",
+            offer.name,
+            actual,
+            offer.hash
+        );
+    }
+    Ok(())
+}
+
+/// Download the pack `offer` describes into `cache`, returning where it's stored
+///
+/// Blocks until the download completes.
+pub fn download(cache: &PackCache, offer: &AssetPackOffer) -> Result<PathBuf> {
+    info!(name = %offer.name, url = %offer.url, "downloading asset pack");
+    let response = ureq::get(&offer.url)
+        .call()
+        .with_context(|| format!("requesting {}", offer.url))?;
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_DOWNLOAD_BYTES + 1)
+        .read_to_end(&mut data)
+        .with_context(|| format!("downloading {}", offer.url))?;
+    if data.len() as u64 > MAX_DOWNLOAD_BYTES {
+        bail!("{} is larger than {MAX_DOWNLOAD_BYTES} bytes", offer.url);
+    }
+    cache.insert(offer, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for &(name, data) in files {
+            zip.start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_dir(dir: &Path, files: &[(&str, &[u8])]) {
+        for &(name, data) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+    }
+
+    #[test]
+    fn sources_resolved_in_order() {
+        let root = tempfile::tempdir().unwrap();
+        let server = root.path().join("server.zip");
+        write_zip(&server, &[("materials/00002_sand.png", b"server sand")]);
+        let user = root.path().join("user");
+        write_dir(
+            &user,
+            &[
+                ("materials/00001_dirt.png", b"user dirt"),
+                ("materials/00002_sand.png", b"user sand"),
+            ],
+        );
+        let builtin = root.path().join("builtin");
+        write_dir(
+            &builtin,
+            &[
+                ("materials/00001_dirt.png", b"builtin dirt"),
+                ("materials/00002_sand.png", b"builtin sand"),
+                ("character.glb", b"builtin character"),
+            ],
+        );
+        let server = PackSource::open(server);
+        assert!(matches!(server, PackSource::Zip(_)));
+        let builtin = PackSource::open(builtin);
+        assert!(matches!(builtin, PackSource::Dir(_)));
+        let sources = AssetSources::from_sources(vec![
+            server.clone(),
+            PackSource::open(user),
+            builtin.clone(),
+        ]);
+
+        let read = |key: &str| {
+            sources
+                .resolve(Path::new(key), |_, data| Ok(String::from_utf8(data)?))
+                .unwrap()
+        };
+        assert_eq!(read("materials/00002_sand.png"), "server sand");
+        assert_eq!(read("materials/00001_dirt.png"), "user dirt");
+        assert_eq!(read("character.glb"), "builtin character");
+        assert!(sources
+            .resolve(Path::new("missing.png"), |_, _| Ok(()))
+            .is_err());
+
+        // Copies that aren't accepted fall through to the next source
+        let mut offered = Vec::new();
+        let dirt = sources
+            .resolve(Path::new("materials/00001_dirt.png"), |source, data| {
+                offered.push(source.clone());
+                if data.starts_with(b"user") {
+                    bail!("rejected");
+                }
+                Ok(data)
+            })
+            .unwrap();
+        assert_eq!(dirt, b"builtin dirt");
+        assert_eq!(offered.len(), 2);
+
+        assert_eq!(
+            server.list(Path::new("materials")).unwrap(),
+            ["00002_sand.png"]
+        );
+        assert!(builtin.list(Path::new("sounds")).unwrap().is_empty());
+    }
+
+    fn offer(data: &[u8]) -> AssetPackOffer {
+        AssetPackOffer {
+            name: "test".into(),
+            hash: PackHash::of(data),
+            url: "https://example.com/test.zip".into(),
+        }
+    }
+
+    #[test]
+    fn tampered_pack_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PackCache::new(dir.path().join("packs"), u64::MAX);
+        let genuine = b"genuine pack".as_slice();
+        let offer = offer(genuine);
+        assert!(cache.insert(&offer, b"tampered pack").is_err());
+        assert_eq!(cache.get(&offer), None);
+
+        let path = cache.insert(&offer, genuine).unwrap();
+        assert_eq!(cache.get(&offer), Some(path.clone()));
+
+        // Corrupted after being cached
+        fs::write(&path, b"tampered pack").unwrap();
+        assert_eq!(cache.get(&offer), None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PackCache::new(dir.path().to_owned(), 25);
+        let packs = [b"first pack".as_slice(), b"second pack", b"third pack"];
+        let offers = packs.map(offer);
+        let old = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        for (i, (offer, data)) in offers.iter().zip(packs).enumerate() {
+            let path = cache.insert(offer, data).unwrap();
+            // Each older than the next, whatever the file system's timestamp resolution
+            File::options()
+                .append(true)
+                .open(path)
+                .unwrap()
+                .set_modified(old + std::time::Duration::from_secs(i as u64))
+                .unwrap();
+        }
+        // The first was evicted to make room for the third
+        assert_eq!(cache.get(&offers[0]), None);
+        assert!(cache.get(&offers[1]).is_some());
+        assert!(cache.get(&offers[2]).is_some());
+    }
+}
@@ -0,0 +1,279 @@
+//! Sounds heard by the player, placed around them in the world
+//!
+//! The server tells each client of the sounds made near its character, including those of the
+//! character's own actions. Clients play those they predict straight away rather than waiting to
+//! hear back, so `SoundQueue` drops the server's word of them when it arrives.
+
+use tracing::trace;
+
+use common::{
+    collision_math::Ray,
+    coords::voxel_center_position,
+    dodeca,
+    graph::Graph,
+    graph_ray_casting, math,
+    node::{ChunkId, Coords},
+    proto::{Position, SoundEvent, SoundKind, SoundSource},
+    traversal::nearby_nodes,
+    SimConfig, Step,
+};
+
+/// Steps after which a sound is no longer worth playing, so that a burst of them arriving after a
+/// hitch isn't played all at once
+pub const STALE_STEPS: Step = 5;
+
+/// Steps within which the server is expected to report a sound the client predicted
+///
+/// Allows for round trips of several seconds at the default step rate. Any report arriving later
+/// is played as a sound of its own.
+pub const PREDICTED_STEPS: Step = 30;
+
+/// Fraction of the gain left to sounds with blocks between them and the listener
+const OCCLUDED_GAIN: f32 = 0.3;
+
+/// Plays sounds through whatever the platform offers
+pub trait AudioOutput {
+    fn play(&mut self, playback: &Playback);
+}
+
+/// Plays nothing, standing in for a real audio backend
+pub struct Silence;
+
+impl AudioOutput for Silence {
+    fn play(&mut self, playback: &Playback) {
+        trace!(?playback, "sound");
+    }
+}
+
+/// A sound as heard from where the player is
+#[derive(Debug, Clone)]
+pub struct Playback {
+    pub kind: SoundKind,
+    /// Unit vector towards the sound in the listener's frame, or zero if it's right there
+    pub direction: na::Vector3<f32>,
+    /// Loudness after spreading out over distance and passing through any obstacles
+    pub gain: f32,
+}
+
+/// Sounds waiting to be played, and those predicted of the local character's actions
+pub struct SoundQueue {
+    pending: Vec<SoundEvent>,
+    /// Sounds played before the server reported them, with the latest step known when they were
+    predicted: Vec<(SoundKind, ChunkId, Coords, Step)>,
+}
+
+impl SoundQueue {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            predicted: Vec::new(),
+        }
+    }
+
+    /// Play the sound of a block the local character changed at once, `step` being the latest
+    /// step known
+    pub fn predict(&mut self, kind: SoundKind, chunk: ChunkId, coords: Coords, step: Step) {
+        self.predicted.push((kind, chunk, coords, step));
+        self.pending.push(SoundEvent {
+            kind,
+            source: SoundSource::Voxel(chunk, coords),
+            intensity: 1.0,
+            step,
+        });
+    }
+
+    /// Queue a sound the server reported, unless it was already played as predicted
+    pub fn receive(&mut self, event: SoundEvent) {
+        if let SoundSource::Voxel(chunk, coords) = event.source {
+            // The server makes the sound later than the client predicted it, by about a round trip
+            let predicted = self.predicted.iter().position(|&x| {
+                let elapsed = event.step.wrapping_sub(x.3);
+                x.0 == event.kind
+                    && x.1 == chunk
+                    && x.2 == coords
+                    && (0..=PREDICTED_STEPS).contains(&elapsed)
+            });
+            if let Some(i) = predicted {
+                self.predicted.swap_remove(i);
+                return;
+            }
+        }
+        self.pending.push(event);
+    }
+
+    /// Take the sounds to play now, `step` being the latest step known
+    ///
+    /// Sounds made more than `STALE_STEPS` before `step` are dropped unplayed.
+    pub fn drain(&mut self, step: Step) -> Vec<SoundEvent> {
+        self.predicted
+            .retain(|x| step.wrapping_sub(x.3) <= PREDICTED_STEPS);
+        let mut events = std::mem::take(&mut self.pending);
+        events.retain(|x| step.wrapping_sub(x.step) <= STALE_STEPS);
+        events
+    }
+}
+
+/// How `event` sounds from `listener`, or `None` if it's out of earshot
+///
+/// Sounds spread out over the surface of a sphere around their source, which grows exponentially
+/// with distance in hyperbolic space, so they fade faster than they would in flat space. They're
+/// muffled by any block between the source and the listener, except within a meter of the source,
+/// so that a block doesn't muffle the sound of its own placement.
+pub fn spatialize(
+    cfg: &SimConfig,
+    graph: &Graph,
+    listener: &Position,
+    event: &SoundEvent,
+) -> Option<Playback> {
+    let source = match event.source {
+        SoundSource::Position(position) => position,
+        SoundSource::Voxel(chunk, coords) => voxel_center_position(graph.layout(), chunk, coords),
+    };
+    // The server only reports sounds within the view distance of the character
+    let reach = f64::from(cfg.view_distance) + dodeca::BOUNDING_SPHERE_RADIUS_F64;
+    let (_, transform) = nearby_nodes(graph, listener, reach)
+        .into_iter()
+        .find(|&(node, _)| node == source.node)?;
+    let point = math::lorentz_normalize(
+        &(math::mtranspose(&listener.local) * transform * source.local * math::origin()),
+    );
+    let distance = math::distance(&math::origin(), &point);
+    let direction = point.xyz().try_normalize(1e-6).unwrap_or_else(na::zero);
+
+    // The power of a sound spreads over a sphere of area proportional to sinh² of its radius
+    let reference = cfg.meters_to_absolute;
+    let spread = (reference.sinh() / distance.max(reference).sinh()).powi(2);
+    let unobstructed = distance - reference;
+    let occluded = unobstructed > 0.0
+        && graph_ray_casting::ray_cast(
+            graph,
+            listener,
+            &Ray::new(math::origin(), direction.push(0.0)),
+            unobstructed.tanh(),
+        )
+        // Chunks not yet generated are assumed to be open
+        .is_ok_and(|hit| hit.is_some());
+    let occlusion = if occluded { OCCLUDED_GAIN } else { 1.0 };
+    Some(Playback {
+        kind: event.kind,
+        direction,
+        gain: event.intensity * spread * occlusion,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+    use common::{
+        coords::locate_voxel,
+        dodeca::Vertex,
+        graph::NodeId,
+        node::{populate_fresh_nodes, VoxelData},
+        traversal::ensure_nearby,
+        world::{Material, Shape},
+        SimConfigRaw,
+    };
+
+    fn voxel_sound(kind: SoundKind, chunk: ChunkId, coords: Coords, step: Step) -> SoundEvent {
+        SoundEvent {
+            kind,
+            source: SoundSource::Voxel(chunk, coords),
+            intensity: 1.0,
+            step,
+        }
+    }
+
+    fn teleport_sound(step: Step) -> SoundEvent {
+        SoundEvent {
+            kind: SoundKind::Teleport,
+            source: SoundSource::Position(Position::origin()),
+            intensity: 1.0,
+            step,
+        }
+    }
+
+    #[test]
+    fn predicted_sounds_played_once() {
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let (here, there) = (Coords([1, 2, 3]), Coords([4, 5, 6]));
+        let mut queue = SoundQueue::new();
+        queue.predict(SoundKind::BlockBroken, chunk, here, 10);
+        assert_eq!(queue.drain(10).len(), 1);
+
+        // The server's report of it a round trip later has already been played
+        queue.receive(voxel_sound(SoundKind::BlockBroken, chunk, here, 13));
+        assert!(queue.drain(13).is_empty());
+
+        // Later changes to the same block are heard, as are those of other kinds
+        queue.receive(voxel_sound(SoundKind::BlockBroken, chunk, here, 14));
+        queue.receive(voxel_sound(SoundKind::BlockPlaced, chunk, there, 14));
+        assert_eq!(queue.drain(14).len(), 2);
+
+        // Predictions the server never confirms are eventually forgotten
+        queue.predict(SoundKind::BlockPlaced, chunk, there, 20);
+        assert_eq!(queue.drain(20).len(), 1);
+        let late = 20 + PREDICTED_STEPS + 1;
+        assert!(queue.drain(late).is_empty());
+        queue.receive(voxel_sound(SoundKind::BlockPlaced, chunk, there, late));
+        assert_eq!(queue.drain(late).len(), 1);
+    }
+
+    #[test]
+    fn stale_sounds_dropped() {
+        let mut queue = SoundQueue::new();
+        queue.receive(teleport_sound(10));
+        queue.receive(teleport_sound(10 + STALE_STEPS));
+        let played = queue.drain(10 + STALE_STEPS + 1);
+        assert_eq!(played.len(), 1);
+        assert_eq!(played[0].step, 10 + STALE_STEPS);
+
+        // Ages are reckoned across the step counter wrapping
+        queue.receive(teleport_sound(Step::MAX));
+        assert_eq!(queue.drain(Step::MIN + 1).len(), 1);
+    }
+
+    #[test]
+    fn sounds_fade_and_muffle() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let mut graph = Graph::new(cfg.chunk_size);
+        ensure_nearby(&mut graph, &Position::origin(), 1.0);
+        populate_fresh_nodes(&mut graph);
+        for (node, _) in nearby_nodes(&graph, &Position::origin(), 1.0) {
+            for vertex in Vertex::iter() {
+                let chunk = ChunkId::new(node, vertex);
+                graph.populate_chunk(chunk, VoxelData::Solid(Material::Void), false);
+            }
+        }
+        // Off every axis, so as not to run along the boundaries of voxels
+        let direction = na::Vector3::new(0.3, -0.2, -1.0).normalize();
+        let at = |meters: f32| Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&(direction * meters * m)),
+        };
+        let listener = Position::origin();
+        let hear = |graph: &Graph, meters: f32| {
+            let event = SoundEvent {
+                source: SoundSource::Position(at(meters)),
+                ..teleport_sound(0)
+            };
+            spatialize(&cfg, graph, &listener, &event).unwrap()
+        };
+
+        let right_here = hear(&graph, 0.0);
+        assert_eq!(right_here.direction, na::Vector3::zeros());
+        assert_eq!(right_here.gain, 1.0);
+        let near = hear(&graph, 2.0);
+        let far = hear(&graph, 8.0);
+        assert_abs_diff_eq!(near.direction, direction, epsilon = 1e-4);
+        assert_abs_diff_eq!(far.direction, direction, epsilon = 1e-4);
+        assert!(far.gain < near.gain && near.gain < 1.0);
+
+        // A block between the source and the listener muffles it
+        let (chunk, coords, _) = locate_voxel(&graph, graph.layout(), &at(5.0)).unwrap();
+        graph.set_block(chunk, coords, Material::Dirt, Shape::FULL);
+        assert_eq!(hear(&graph, 2.0).gain, near.gain);
+        assert_relative_eq!(hear(&graph, 8.0).gain, far.gain * OCCLUDED_GAIN);
+    }
+}
@@ -0,0 +1,265 @@
+use tracing::debug;
+
+use common::{
+    graph::Graph,
+    node::BlockUpdateOutcome,
+    proto::BlockUpdate,
+    world::{Material, Shape},
+};
+
+/// Predicts the result of block updates in-flight to the server
+///
+/// Block updates requested by the local character are applied to the graph immediately by
+/// `predict`, which also assigns them the sequence numbers sent alongside them. The server echoes
+/// the sequence number of each update it accepts, and reports each that it rejects, so that every
+/// prediction can be resolved precisely even when several are pending for the same voxel.
+pub struct PredictedBlocks {
+    next_sequence: u32,
+    /// Updates yet to be resolved by the server, oldest first
+    pending: Vec<PendingUpdate>,
+}
+
+struct PendingUpdate {
+    update: BlockUpdate,
+    /// Material and shape the voxel should revert to if this update is rejected
+    fallback: (Material, Shape),
+}
+
+impl PredictedBlocks {
+    pub fn new() -> Self {
+        Self {
+            next_sequence: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Sequence number to assign to the next update passed to `predict`
+    pub fn next_sequence(&self) -> u32 {
+        self.next_sequence
+    }
+
+    /// Take the next sequence number for an edit that isn't predicted, like a fill
+    pub fn skip_sequence(&mut self) -> u32 {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        sequence
+    }
+
+    /// Apply `update`, which is about to be sent to the server, to `graph`
+    pub fn predict(&mut self, graph: &mut Graph, update: &BlockUpdate) -> BlockUpdateOutcome {
+        assert_eq!(
+            update.sequence, self.next_sequence,
+            "block updates must be predicted in the order they're sent"
+        );
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let (Some(material), Some(shape)) = (
+            graph.get_block(update.chunk_id, update.coords),
+            graph.get_shape(update.chunk_id, update.coords),
+        ) else {
+            // The server will have to decide without us
+            return BlockUpdateOutcome::ChunkMissing;
+        };
+        self.pending.push(PendingUpdate {
+            update: update.clone(),
+            fallback: (material, shape),
+        });
+        graph.update_block(update)
+    }
+
+    /// Apply an update accepted by the server to `graph`, resolving the matching prediction if
+    /// `local` indicates that it was requested by the local character
+    ///
+    /// The voxel is left alone while later predictions for it are pending.
+    pub fn accept(
+        &mut self,
+        graph: &mut Graph,
+        update: &BlockUpdate,
+        local: bool,
+    ) -> BlockUpdateOutcome {
+        if local {
+            match self.find(update.sequence) {
+                Some(index) => {
+                    self.pending.remove(index);
+                }
+                None => debug!(sequence = update.sequence, "unexpected block update echo"),
+            }
+        }
+        if let Some(later) = self.pending_for(update, 0) {
+            later.fallback = (update.new_material, update.new_shape);
+            return BlockUpdateOutcome::NoChange;
+        }
+        graph.update_block(update)
+    }
+
+    /// Undo the prediction of a block update the server refused
+    pub fn reject(&mut self, graph: &mut Graph, sequence: u32) {
+        let Some(index) = self.find(sequence) else {
+            debug!(sequence, "unexpected block update rejection");
+            return;
+        };
+        let PendingUpdate { update, fallback } = self.pending.remove(index);
+        if let Some(later) = self.pending_for(&update, index) {
+            later.fallback = fallback;
+            return;
+        }
+        // If the chunk's gone, there's nothing to undo
+        let _ = graph.update_block(&BlockUpdate {
+            new_material: fallback.0,
+            new_shape: fallback.1,
+            ..update
+        });
+    }
+
+    /// Number of updates awaiting a response from the server
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    fn find(&self, sequence: u32) -> Option<usize> {
+        self.pending
+            .iter()
+            .position(|x| x.update.sequence == sequence)
+    }
+
+    /// The oldest pending update from `start` onwards that affects the same voxel as `update`
+    fn pending_for(&mut self, update: &BlockUpdate, start: usize) -> Option<&mut PendingUpdate> {
+        self.pending[start..]
+            .iter_mut()
+            .find(|x| x.update.chunk_id == update.chunk_id && x.update.coords == update.coords)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        dodeca::Vertex,
+        graph::NodeId,
+        node::{populate_fresh_nodes, ChunkId, CoordAxis, CoordDirection, Coords, VoxelData},
+    };
+
+    const CHUNK: ChunkId = ChunkId {
+        node: NodeId::ROOT,
+        vertex: Vertex::A,
+    };
+
+    fn graph() -> Graph {
+        let mut graph = Graph::new(4);
+        populate_fresh_nodes(&mut graph);
+        graph.populate_chunk(CHUNK, VoxelData::Solid(Material::Void), false);
+        graph
+    }
+
+    fn update(predicted: &PredictedBlocks, new_material: Material) -> BlockUpdate {
+        BlockUpdate {
+            chunk_id: CHUNK,
+            coords: Coords([1, 2, 3]),
+            new_material,
+            new_shape: Shape::FULL,
+            sequence: predicted.next_sequence(),
+        }
+    }
+
+    fn block(graph: &Graph) -> Material {
+        graph.get_block(CHUNK, Coords([1, 2, 3])).unwrap()
+    }
+
+    #[test]
+    fn rapid_edits_resolve_in_order() {
+        let mut graph = graph();
+        let mut predicted = PredictedBlocks::new();
+        let dirt = update(&predicted, Material::Dirt);
+        assert_eq!(
+            predicted.predict(&mut graph, &dirt),
+            BlockUpdateOutcome::Applied {
+                previous: Material::Void,
+                previous_shape: Shape::FULL,
+            }
+        );
+        let sand = update(&predicted, Material::Sand);
+        assert_eq!(
+            predicted.predict(&mut graph, &sand),
+            BlockUpdateOutcome::Applied {
+                previous: Material::Dirt,
+                previous_shape: Shape::FULL,
+            }
+        );
+        assert_eq!(block(&graph), Material::Sand);
+        assert_eq!(predicted.len(), 2);
+
+        // The echo of the first edit doesn't clobber the second
+        assert_eq!(
+            predicted.accept(&mut graph, &dirt, true),
+            BlockUpdateOutcome::NoChange
+        );
+        assert_eq!(block(&graph), Material::Sand);
+        assert_eq!(predicted.len(), 1);
+
+        // The echo of the second matches the prediction
+        assert_eq!(
+            predicted.accept(&mut graph, &sand, true),
+            BlockUpdateOutcome::NoChange
+        );
+        assert_eq!(block(&graph), Material::Sand);
+        assert!(predicted.is_empty());
+    }
+
+    #[test]
+    fn rejection_restores_authoritative_material() {
+        let mut graph = graph();
+        let mut predicted = PredictedBlocks::new();
+        let dirt = update(&predicted, Material::Dirt);
+        let _ = predicted.predict(&mut graph, &dirt);
+        let sand = update(&predicted, Material::Sand);
+        let _ = predicted.predict(&mut graph, &sand);
+
+        // The second edit is refused after the first is accepted
+        let _ = predicted.accept(&mut graph, &dirt, true);
+        predicted.reject(&mut graph, sand.sequence);
+        assert_eq!(block(&graph), Material::Dirt);
+        assert!(predicted.is_empty());
+
+        // The first edit is refused while the second is pending, and someone else's edit wins
+        let void = update(&predicted, Material::Void);
+        let _ = predicted.predict(&mut graph, &void);
+        let sand = update(&predicted, Material::Sand);
+        let _ = predicted.predict(&mut graph, &sand);
+        predicted.reject(&mut graph, void.sequence);
+        assert_eq!(block(&graph), Material::Sand);
+        let wood = BlockUpdate {
+            sequence: 0,
+            ..update(&predicted, Material::Wood)
+        };
+        let _ = predicted.accept(&mut graph, &wood, false);
+        assert_eq!(block(&graph), Material::Sand);
+        predicted.reject(&mut graph, sand.sequence);
+        assert_eq!(block(&graph), Material::Wood);
+        assert!(predicted.is_empty());
+    }
+
+    #[test]
+    fn rejection_restores_shape() {
+        let mut graph = graph();
+        let mut predicted = PredictedBlocks::new();
+        let slab = Shape::slab(CoordAxis::Y, CoordDirection::Minus);
+        let placed = BlockUpdate {
+            new_shape: slab,
+            ..update(&predicted, Material::Dirt)
+        };
+        let _ = predicted.predict(&mut graph, &placed);
+        let _ = predicted.accept(&mut graph, &placed, true);
+        assert_eq!(graph.get_shape(CHUNK, Coords([1, 2, 3])), Some(slab));
+
+        // Replacing the slab with a full block is refused
+        let full = update(&predicted, Material::Sand);
+        let _ = predicted.predict(&mut graph, &full);
+        assert_eq!(graph.get_shape(CHUNK, Coords([1, 2, 3])), Some(Shape::FULL));
+        predicted.reject(&mut graph, full.sequence);
+        assert_eq!(block(&graph), Material::Dirt);
+        assert_eq!(graph.get_shape(CHUNK, Coords([1, 2, 3])), Some(slab));
+    }
+}
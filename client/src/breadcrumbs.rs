@@ -0,0 +1,282 @@
+//! Client-side record of where the player has been on each server
+//!
+//! A player who crashes and rejoins a server that doesn't remember positions is put back at spawn,
+//! and in hyperbolic space there's no coordinate they could have written down to find their way
+//! back. Instead, the route to the character's node is recorded periodically, so that a waypoint
+//! can lead them home.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use common::{
+    graph::{Graph, NodeId},
+    map_projection::MapFrame,
+    math,
+    node_path::NodePath,
+    proto::Position,
+};
+
+use crate::server_files::per_server_file_name;
+
+/// Number of positions retained per server, bounding the size of each file
+const MAX_BREADCRUMBS: usize = 16;
+
+/// A recorded position
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Breadcrumb {
+    /// Route to the node the position was in
+    pub path: NodePath,
+    /// The position in that node's coordinates
+    pub point: [f32; 4],
+}
+
+impl Breadcrumb {
+    pub fn new(graph: &Graph, position: &Position) -> Self {
+        Self {
+            path: NodePath::to(graph, position.node),
+            point: (position.local * math::origin()).into(),
+        }
+    }
+
+    /// This position in the coordinates of `node`
+    ///
+    /// The result is scaled to remain representable however far away the position is, so only
+    /// its direction is meaningful.
+    pub fn locate(&self, graph: &Graph, node: NodeId) -> na::Vector4<f32> {
+        let point = NodePath::to(graph, node).transform_from(&self.path)
+            * na::Vector4::from(self.point).cast::<f64>();
+        (point / point.w).cast()
+    }
+
+    /// Distance from `position` in absolute units
+    pub fn distance(&self, graph: &Graph, position: &Position) -> f32 {
+        math::distance(
+            &(position.local * math::origin()),
+            &math::lorentz_normalize(&self.locate(graph, position.node)),
+        )
+    }
+
+    /// Direction towards this position on a map centered on `view`, if one can be drawn
+    pub fn direction(&self, graph: &Graph, view: &Position) -> Option<na::UnitVector2<f32>> {
+        MapFrame::new(graph, view)?.compass(&self.locate(graph, view.node))
+    }
+}
+
+/// Positions recorded on one server, oldest first
+pub struct Trail {
+    file: PathBuf,
+    breadcrumbs: VecDeque<Breadcrumb>,
+    /// Time between recorded positions
+    interval: Duration,
+    since_recorded: Duration,
+}
+
+impl Trail {
+    /// Load the trail left on the server identified by `server` from `dir`, or start a new one
+    pub fn open(dir: &Path, server: &str, interval: Duration) -> Self {
+        let file = dir.join(per_server_file_name(server, "toml"));
+        let breadcrumbs = match fs::read_to_string(&file) {
+            Ok(data) => match toml::from_str::<TrailFile>(&data) {
+                Ok(x) => x.breadcrumbs.into(),
+                Err(e) => {
+                    warn!(
+                        "ignoring malformed breadcrumbs in {}: {}",
+                        file.display(),
+                        e
+                    );
+                    VecDeque::new()
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => {
+                warn!("couldn't read breadcrumbs from {}: {}", file.display(), e);
+                VecDeque::new()
+            }
+        };
+        Self {
+            file,
+            breadcrumbs,
+            interval,
+            since_recorded: Duration::ZERO,
+        }
+    }
+
+    /// The most recently recorded position
+    pub fn last(&self) -> Option<&Breadcrumb> {
+        self.breadcrumbs.back()
+    }
+
+    /// Advance time by `dt`, recording `position` if it's been long enough since the last
+    pub fn update(&mut self, dt: Duration, graph: &Graph, position: &Position) -> io::Result<()> {
+        self.since_recorded += dt;
+        if self.interval.is_zero() || self.since_recorded < self.interval {
+            return Ok(());
+        }
+        self.since_recorded = Duration::ZERO;
+        self.record(Breadcrumb::new(graph, position))
+    }
+
+    /// Append `breadcrumb` to the trail, forgetting the oldest if there are too many
+    pub fn record(&mut self, breadcrumb: Breadcrumb) -> io::Result<()> {
+        if self.breadcrumbs.len() == MAX_BREADCRUMBS {
+            self.breadcrumbs.pop_front();
+        }
+        self.breadcrumbs.push_back(breadcrumb);
+        self.save()
+    }
+
+    /// Replace the file atomically, so a crash mid-write leaves the previous trail intact
+    fn save(&self) -> io::Result<()> {
+        let data = toml::to_string(&TrailFile {
+            breadcrumbs: self.breadcrumbs.iter().cloned().collect(),
+        })
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = self.file.with_extension("toml.tmp");
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.file)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TrailFile {
+    breadcrumbs: Vec<Breadcrumb>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+    use common::{
+        dodeca::Side, node::populate_fresh_nodes, test_graphs::walk, traversal::nearby_nodes,
+    };
+
+    const INTERVAL: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut graph = Graph::new(4);
+        let node = walk(&mut graph, &[Side::A, Side::B, Side::C]);
+        let position = Position {
+            node,
+            local: math::translate_along(&na::Vector3::new(0.1, -0.2, 0.3)),
+        };
+
+        let mut trail = Trail::open(dir.path(), "example.com:1234", INTERVAL);
+        assert_eq!(trail.last(), None);
+        // Nothing is recorded until the interval has passed
+        trail
+            .update(INTERVAL / 2, &graph, &Position::origin())
+            .unwrap();
+        assert_eq!(trail.last(), None);
+        trail.update(INTERVAL / 2, &graph, &position).unwrap();
+        let recorded = trail.last().unwrap().clone();
+        assert_eq!(recorded.path.resolve(&graph), Some(node));
+
+        let reopened = Trail::open(dir.path(), "example.com:1234", INTERVAL);
+        assert_eq!(reopened.last(), Some(&recorded));
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let graph = Graph::new(4);
+        let mut trail = Trail::open(dir.path(), "local", INTERVAL);
+        for i in 0..2 * MAX_BREADCRUMBS {
+            let position = Position {
+                node: NodeId::ROOT,
+                local: math::translate_along(&(na::Vector3::x() * i as f32 * 0.01)),
+            };
+            trail.record(Breadcrumb::new(&graph, &position)).unwrap();
+        }
+        let reopened = Trail::open(dir.path(), "local", INTERVAL);
+        assert_eq!(reopened.breadcrumbs.len(), MAX_BREADCRUMBS);
+        assert_eq!(reopened.last(), trail.last());
+    }
+
+    #[test]
+    fn servers_kept_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let graph = Graph::new(4);
+        // Names differing only in characters that can't appear in file names
+        let servers = ["[::1]:1234", "[::1]_1234"];
+        let mut trails = servers.map(|server| Trail::open(dir.path(), server, INTERVAL));
+        for (i, trail) in trails.iter_mut().enumerate() {
+            let position = Position {
+                node: NodeId::ROOT,
+                local: math::translate_along(&(na::Vector3::y() * (i + 1) as f32 * 0.1)),
+            };
+            trail.record(Breadcrumb::new(&graph, &position)).unwrap();
+        }
+        for (server, trail) in servers.iter().zip(&trails) {
+            assert_eq!(
+                Trail::open(dir.path(), server, INTERVAL).last(),
+                trail.last()
+            );
+        }
+        assert_ne!(trails[0].last(), trails[1].last());
+        assert_eq!(Trail::open(dir.path(), "elsewhere", INTERVAL).last(), None);
+    }
+
+    #[test]
+    fn direction_matches_compass() {
+        let mut graph = Graph::new(4);
+        let far = walk(&mut graph, &[Side::A, Side::B, Side::C, Side::D, Side::E]);
+        let neighbor = graph.ensure_neighbor(NodeId::ROOT, Side::F);
+        populate_fresh_nodes(&mut graph);
+        let breadcrumb = Breadcrumb::new(
+            &graph,
+            &Position {
+                node: far,
+                local: math::translate_along(&na::Vector3::new(0.2, 0.1, -0.3)),
+            },
+        );
+
+        for node in [NodeId::ROOT, neighbor] {
+            let view = Position {
+                node,
+                local: math::translate_along(&na::Vector3::new(0.05, 0.0, 0.1))
+                    * na::Rotation3::from_euler_angles(0.0, 0.7, 0.0).to_homogeneous(),
+            };
+            // Where the graph itself says the recorded position is
+            let far_transform = nearby_nodes(&graph, &view, 20.0)
+                .into_iter()
+                .find(|&(id, _)| id == far)
+                .unwrap()
+                .1;
+            let point = far_transform * na::Vector4::from(breadcrumb.point);
+            let expected = MapFrame::new(&graph, &view)
+                .unwrap()
+                .compass(&point)
+                .unwrap();
+
+            let direction = breadcrumb.direction(&graph, &view).unwrap();
+            assert_abs_diff_eq!(
+                direction.into_inner(),
+                expected.into_inner(),
+                epsilon = 1e-3
+            );
+            let distance = math::distance(&(view.local * math::origin()), &point);
+            assert_relative_eq!(
+                breadcrumb.distance(&graph, &view),
+                distance,
+                max_relative = 1e-3
+            );
+        }
+    }
+}
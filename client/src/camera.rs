@@ -0,0 +1,344 @@
+//! Smoothing of the camera's motion
+//!
+//! The predicted view position jumps whenever the server corrects the prediction, and shakes with
+//! small collision adjustments. Rather than render from it directly, the camera chases it, closing
+//! the distance between them exponentially along the geodesic connecting them. Only rendering sees
+//! the camera; prediction, picking, and everything sent to the server use the view position.
+
+use common::{
+    dodeca::Side,
+    graph::{Graph, NodeId},
+    math,
+    proto::Position,
+};
+
+/// How the camera follows the view position
+#[derive(Debug, Clone)]
+pub struct CameraConfig {
+    /// Time in seconds for the distance between the camera and the view position to halve
+    pub half_life: f32,
+    /// Like `half_life`, for the view position rising above the camera, as when climbing steps
+    pub rise_half_life: f32,
+    /// Distance in absolute units beyond which the camera jumps straight to the view position
+    pub snap_distance: f32,
+    /// Depth in absolute units to which the camera dips with each footstep, or zero to disable
+    /// view bobbing
+    pub bob_depth: f32,
+    /// Distance in absolute units walked per footstep
+    pub stride: f32,
+    /// Whether the view position is interpolated between the two latest predicted steps, rather
+    /// than extrapolated by simulating the part of a step that has elapsed since the last
+    pub interpolate_view: bool,
+    /// Distance in absolute units the camera is held behind a character it follows, terrain
+    /// permitting
+    pub follow_distance: f32,
+}
+
+pub struct Camera {
+    cfg: CameraConfig,
+    state: Option<State>,
+}
+
+struct State {
+    /// Node that `camera` and `target` are relative to
+    node: NodeId,
+    /// Location of the camera, excluding view bobbing
+    camera: na::Vector4<f32>,
+    /// Location of the view position at the last update
+    target: na::Vector4<f32>,
+    /// Distance walked since the last footstep
+    walked: f32,
+    /// Velocity of the view position over the last update in absolute units per second, relative
+    /// to the view
+    velocity: na::Vector3<f32>,
+    /// Where the camera is rendered from
+    view: Position,
+}
+
+impl Camera {
+    pub fn new(cfg: CameraConfig) -> Self {
+        Self { cfg, state: None }
+    }
+
+    /// Where to render from, if `update` has been called
+    pub fn view(&self) -> Option<Position> {
+        self.state.as_ref().map(|x| x.view)
+    }
+
+    /// Velocity of the view position over the last update in absolute units per second, relative
+    /// to the view
+    pub fn velocity(&self) -> na::Vector3<f32> {
+        self.state.as_ref().map_or_else(na::zero, |x| x.velocity)
+    }
+
+    /// Fraction of the current footstep walked, in [0, 1), which stays at zero while view bobbing
+    /// is disabled
+    pub fn footstep_phase(&self) -> f32 {
+        self.state
+            .as_ref()
+            .map_or(0.0, |x| x.walked / self.cfg.stride)
+    }
+
+    /// Jump straight to the view position at the next `update`, as when the character is teleported
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+
+    /// Advance the camera by `dt` seconds towards `target`, the latest view position
+    pub fn update(&mut self, graph: &Graph, target: &Position, on_ground: bool, dt: f32) {
+        let here = target.local * math::origin();
+        let Some(up) = graph.get_relative_up(target) else {
+            // Lost track of the graph; just follow along until it's back
+            self.snap(target, here);
+            return;
+        };
+        let from_target = math::mtranspose(&target.local);
+        // Locations from the previous update, relative to the view
+        let Some((camera, last_target, walked)) = self.state.as_ref().and_then(|state| {
+            let transform = from_target * transition(graph, state.node, target.node)?;
+            Some((
+                transform * state.camera,
+                transform * state.target,
+                state.walked,
+            ))
+        }) else {
+            self.snap(target, here);
+            return;
+        };
+        let camera = tangent(&camera);
+        if camera.norm() > self.cfg.snap_distance {
+            // Following along would only be disorienting
+            self.snap(target, here);
+            return;
+        }
+
+        // Dip with each footstep, easing back up when the character stops walking
+        let moved = tangent(&last_target);
+        let walked = if on_ground && self.cfg.bob_depth > 0.0 && moved.norm() > 1e-6 {
+            (walked + (moved - *up * moved.dot(&up)).norm()) % self.cfg.stride
+        } else {
+            0.0
+        };
+        let bob =
+            -*up * (self.cfg.bob_depth * (std::f32::consts::PI * walked / self.cfg.stride).sin());
+
+        // Close the gap exponentially, which can never overshoot
+        let error = camera - bob;
+        let rise = error.dot(&up);
+        let vertical_half_life = if rise < 0.0 {
+            self.cfg.rise_half_life
+        } else {
+            self.cfg.half_life
+        };
+        let error = (error - *up * rise) * decay(self.cfg.half_life, dt)
+            + *up * (rise * decay(vertical_half_life, dt));
+
+        let local = target.local * math::translate_along(&(bob + error));
+        self.state = Some(State {
+            node: target.node,
+            camera: target.local * math::translate_along(&error) * math::origin(),
+            target: here,
+            walked,
+            velocity: if dt > 0.0 { -moved / dt } else { na::zero() },
+            view: Position {
+                node: target.node,
+                local,
+            },
+        });
+    }
+
+    fn snap(&mut self, target: &Position, here: na::Vector4<f32>) {
+        self.state = Some(State {
+            node: target.node,
+            camera: here,
+            target: here,
+            walked: 0.0,
+            velocity: na::zero(),
+            view: *target,
+        });
+    }
+}
+
+/// Transform from the space of `from` to the space of `to`, if they're the same or adjacent
+pub(crate) fn transition(graph: &Graph, from: NodeId, to: NodeId) -> Option<na::Matrix4<f32>> {
+    if from == to {
+        return Some(na::Matrix4::identity());
+    }
+    let side = Side::iter().find(|&side| graph.neighbor(from, side) == Some(to))?;
+    Some(*side.reflection_f32())
+}
+
+/// Vector in the tangent space at the origin which `translate_along` maps to `point`
+pub(crate) fn tangent(point: &na::Vector4<f32>) -> na::Vector3<f32> {
+    // Unlike the distance's hyperbolic cosine, its sine stays precise when the point is close
+    let direction = math::lorentz_normalize(point).xyz();
+    let sinh_distance = direction.norm();
+    if sinh_distance == 0.0 {
+        return na::zero();
+    }
+    direction * (sinh_distance.asinh() / sinh_distance)
+}
+
+/// Fraction of an exponentially decaying quantity remaining after `dt` seconds
+fn decay(half_life: f32, dt: f32) -> f32 {
+    if half_life <= 0.0 {
+        return 0.0;
+    }
+    0.5f32.powf(dt / half_life)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+    use common::node::populate_fresh_nodes;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn cfg() -> CameraConfig {
+        CameraConfig {
+            half_life: 0.05,
+            rise_half_life: 0.15,
+            snap_distance: 0.5,
+            bob_depth: 0.0,
+            stride: 0.1,
+            interpolate_view: false,
+            follow_distance: 0.5,
+        }
+    }
+
+    fn graph() -> Graph {
+        let mut graph = Graph::new(4);
+        populate_fresh_nodes(&mut graph);
+        graph
+    }
+
+    fn at(offset: na::Vector3<f32>) -> Position {
+        Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&offset),
+        }
+    }
+
+    /// Distance from `target` to the camera
+    fn error(camera: &Camera, target: &Position) -> f32 {
+        let view = camera.view().unwrap();
+        assert_eq!(view.node, target.node);
+        tangent(&(math::mtranspose(&target.local) * view.local * math::origin())).norm()
+    }
+
+    #[test]
+    fn reconciliation_decays_exponentially() {
+        let graph = graph();
+        let up = graph.get_relative_up(&Position::origin()).unwrap();
+        // Horizontal, so that the rise half-life doesn't come into play
+        let across = if up.x.abs() < 0.5 {
+            up.cross(&na::Vector3::x())
+        } else {
+            up.cross(&na::Vector3::z())
+        }
+        .normalize();
+        let mut camera = Camera::new(cfg());
+
+        // Walk steadily, then get corrected by the server
+        let walk = (0..30).map(|i| at(across * (i as f32 * 0.001)));
+        for target in walk {
+            camera.update(&graph, &target, true, DT);
+        }
+        let end = 0.029 + 0.2;
+        let corrected = at(across * end);
+        let initial = error(&camera, &corrected);
+        assert_relative_eq!(initial, 0.2, epsilon = 0.01);
+
+        for step in 1..60 {
+            camera.update(&graph, &corrected, true, DT);
+            let expected = initial * 0.5f32.powf(step as f32 * DT / cfg().half_life);
+            assert_relative_eq!(error(&camera, &corrected), expected, epsilon = 1e-4);
+            // Never passes the target
+            let view = camera.view().unwrap().local * math::origin();
+            assert!(tangent(&view).dot(&across) <= end + 1e-5);
+        }
+    }
+
+    #[test]
+    fn steps_rise_slowly() {
+        let graph = graph();
+        let up = graph.get_relative_up(&Position::origin()).unwrap();
+        for (height, half_life) in [(0.1, cfg().rise_half_life), (-0.1, cfg().half_life)] {
+            let mut camera = Camera::new(cfg());
+            camera.update(&graph, &Position::origin(), true, DT);
+            let target = at(*up * height);
+            for _ in 0..(half_life / DT).round() as usize {
+                camera.update(&graph, &target, true, DT);
+            }
+            assert_relative_eq!(error(&camera, &target), 0.05, epsilon = 1e-3);
+        }
+    }
+
+    #[test]
+    fn reports_walking() {
+        let graph = graph();
+        let up = graph.get_relative_up(&Position::origin()).unwrap();
+        let across = if up.x.abs() < 0.5 {
+            up.cross(&na::Vector3::x())
+        } else {
+            up.cross(&na::Vector3::z())
+        }
+        .normalize();
+        let mut camera = Camera::new(CameraConfig {
+            bob_depth: 0.01,
+            ..cfg()
+        });
+        for i in 0..30 {
+            camera.update(&graph, &at(across * (i as f32 * 0.001)), true, DT);
+        }
+        assert_relative_eq!(camera.velocity(), across * (0.001 / DT), epsilon = 1e-3);
+        assert_relative_eq!(camera.footstep_phase(), 0.29, epsilon = 1e-3);
+
+        // Standing still, or bobbing disabled, stops the footsteps
+        camera.update(&graph, &at(across * 0.029), true, DT);
+        assert_eq!(camera.footstep_phase(), 0.0);
+        assert_relative_eq!(camera.velocity(), na::zero(), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn snap_threshold() {
+        let graph = graph();
+        let snap = cfg().snap_distance;
+        for (distance, snapped) in [(snap - 1e-3, false), (snap + 1e-3, true)] {
+            let mut camera = Camera::new(cfg());
+            camera.update(&graph, &Position::origin(), true, DT);
+            let target = at(na::Vector3::x() * distance);
+            camera.update(&graph, &target, true, DT);
+            assert_eq!(error(&camera, &target) < 1e-4, snapped, "{distance}");
+        }
+    }
+
+    #[test]
+    fn follows_across_nodes() {
+        let mut graph = graph();
+        let neighbor = graph.ensure_neighbor(NodeId::ROOT, Side::A);
+        populate_fresh_nodes(&mut graph);
+        let reflection = Side::A.reflection_f32();
+        let target = at(na::Vector3::x() * 0.1);
+        let mut cameras = [Camera::new(cfg()), Camera::new(cfg())];
+        for camera in &mut cameras {
+            camera.update(&graph, &Position::origin(), true, DT);
+            camera.update(&graph, &target, true, DT);
+        }
+
+        // The same target, expressed relative to a different node
+        cameras[0].update(&graph, &target, true, DT);
+        let renormalized = Position {
+            node: neighbor,
+            local: reflection * target.local,
+        };
+        cameras[1].update(&graph, &renormalized, true, DT);
+        assert!(error(&cameras[1], &renormalized) > 0.01);
+        assert_abs_diff_eq!(
+            cameras[1].view().unwrap().local,
+            reflection * cameras[0].view().unwrap().local,
+            epsilon = 1e-4
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Placement of other characters' models and name tags
+
+use common::math;
+
+/// Height in meters of a name tag's center above its character's position
+pub const NAME_TAG_ELEVATION: f32 = 0.8;
+/// Height in meters of a name tag's letters when seen from up close
+pub const NAME_TAG_LETTER_HEIGHT: f32 = 0.15;
+/// Distance in meters beyond which name tags grow to keep a constant apparent size
+const NAME_TAG_LEGIBLE_DISTANCE: f32 = 8.0;
+/// Maximum number of characters displayed in a name tag, which must match `nametag.vert`
+pub const NAME_TAG_MAX_LEN: usize = 48;
+
+/// A character near enough to be drawn
+pub struct VisibleCharacter {
+    pub entity: hecs::Entity,
+    /// Transform from the character's upright body frame to the frame of the view's node
+    pub body: na::Matrix4<f32>,
+    /// Distance from the viewer in absolute units
+    pub distance: f32,
+    pub name: String,
+    /// Whether the server reports the character's player as away
+    pub afk: bool,
+}
+
+/// Orientation of a character's body facing where `orientation` looks, but standing upright along
+/// `up`
+///
+/// Characters look up and down by pitching their heads, not by tipping over, so only the yaw of
+/// `orientation` about `up` is kept. Both arguments are relative to the character's position.
+pub fn body_orientation(
+    up: &na::UnitVector3<f32>,
+    orientation: &na::UnitQuaternion<f32>,
+) -> na::UnitQuaternion<f32> {
+    let look = orientation * -na::Vector3::z();
+    let mut forward = look - up.into_inner() * look.dot(up);
+    if forward.norm_squared() < 1e-6 {
+        // Looking straight up or down, the top of the head points backward or forward instead
+        let head = orientation * na::Vector3::y();
+        forward = (head - up.into_inner() * head.dot(up)) * -look.dot(up).signum();
+    }
+    na::UnitQuaternion::face_towards(&-forward, up)
+}
+
+/// Scale factor applied to a name tag `distance` away from the camera, in absolute units
+///
+/// Nearby tags keep a fixed size in the world. In hyperbolic space, apparent size falls off with
+/// the hyperbolic sine of distance, so farther tags grow accordingly to remain legible.
+pub fn name_tag_scale(distance: f32, meters_to_absolute: f32) -> f32 {
+    let legible = NAME_TAG_LEGIBLE_DISTANCE * meters_to_absolute;
+    (distance.sinh() / legible.sinh()).max(1.0)
+}
+
+/// Transform in view space placing a billboard's origin at `center` with its +Z axis pointing back
+/// at the camera and its +Y axis as close to the camera's up as possible
+pub fn billboard(center: &na::Vector4<f32>) -> na::Matrix4<f32> {
+    let center = math::lorentz_normalize(center);
+    // Translation along the line of sight leaves directions through the camera unchanged, so the
+    // billboard must face back along the direction in which it was translated
+    let direction = center.xyz().normalize();
+    let facing = if direction.iter().all(|x| x.is_finite()) {
+        na::UnitQuaternion::face_towards(&-direction, &na::Vector3::y())
+    } else {
+        na::UnitQuaternion::identity()
+    };
+    math::translate(&math::origin(), &center) * facing.to_homogeneous()
+}
+
+/// Glyph indices of `name` packed four to a word, as expected by `nametag.vert`, and the number of
+/// glyphs
+///
+/// Glyphs are indexed from the space character, covering printable ASCII. Anything else is shown
+/// as a question mark, and names are truncated to `NAME_TAG_MAX_LEN` characters.
+pub fn name_tag_glyphs(name: &str) -> ([u32; NAME_TAG_MAX_LEN / 4], u32) {
+    let mut packed = [0; NAME_TAG_MAX_LEN / 4];
+    let mut len = 0;
+    for (i, c) in name.chars().take(NAME_TAG_MAX_LEN).enumerate() {
+        let glyph = match c {
+            ' '..='~' => c as u32 - ' ' as u32,
+            _ => '?' as u32 - ' ' as u32,
+        };
+        packed[i / 4] |= glyph << (8 * (i % 4));
+        len = i as u32 + 1;
+    }
+    (packed, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    #[test]
+    fn bodies_stay_upright() {
+        let ups = [
+            na::Vector3::y_axis(),
+            na::UnitVector3::new_normalize(na::Vector3::new(0.3, 1.0, -0.2)),
+            na::UnitVector3::new_normalize(na::Vector3::new(-1.0, 0.1, 0.4)),
+        ];
+        for up in ups {
+            // An orientation standing upright on `up`
+            let upright = na::UnitQuaternion::rotation_between(&na::Vector3::y(), &up)
+                .unwrap_or_else(na::UnitQuaternion::identity);
+            for yaw in -4..=4 {
+                let yaw = yaw as f32 * 0.7;
+                let expected = upright * na::UnitQuaternion::from_euler_angles(0.0, yaw, 0.0);
+                // Looking all the way up and down, and everywhere between
+                for pitch in -8..=8 {
+                    let pitch = pitch as f32 * std::f32::consts::FRAC_PI_2 / 8.0;
+                    let orientation =
+                        expected * na::UnitQuaternion::from_euler_angles(pitch, 0.0, 0.0);
+                    let body = body_orientation(&up, &orientation);
+                    assert_abs_diff_eq!(body * na::Vector3::y(), up.into_inner(), epsilon = 1e-4);
+                    assert_abs_diff_eq!(
+                        body * na::Vector3::z(),
+                        expected * na::Vector3::z(),
+                        epsilon = 1e-3
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn billboards_face_camera() {
+        for direction in [
+            na::Vector3::new(0.0, 0.0, -1.0),
+            na::Vector3::new(0.5, 0.2, -1.0),
+            na::Vector3::new(-1.0, 0.3, 0.2),
+            na::Vector3::new(0.1, -0.4, 1.0),
+        ] {
+            for distance in [0.01, 0.5, 3.0] {
+                let center =
+                    math::translate_along(&(direction.normalize() * distance)) * math::origin();
+                let frame = billboard(&center);
+                assert_abs_diff_eq!(frame * math::origin(), center, epsilon = 1e-3);
+                // The way back to the camera, in the tangent space at the billboard
+                let to_camera = math::origin() + center * math::mip(&math::origin(), &center);
+                let to_camera = to_camera / math::mip(&to_camera, &to_camera).sqrt();
+                let normal = frame * na::Vector4::z();
+                assert_abs_diff_eq!(normal, to_camera, epsilon = 1e-3);
+                // Upright relative to the camera, rather than rolled
+                let right = frame * na::Vector4::x();
+                assert_abs_diff_eq!(right.y, 0.0, epsilon = 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn name_tags_stay_legible() {
+        let m = 0.1;
+        // Up close, tags are ordinary objects
+        assert_eq!(name_tag_scale(m, m), 1.0);
+        assert_eq!(name_tag_scale(NAME_TAG_LEGIBLE_DISTANCE * m, m), 1.0);
+        // Beyond that, apparent size is preserved
+        let apparent = |distance: f32| name_tag_scale(distance, m) / distance.sinh();
+        let reference = apparent(NAME_TAG_LEGIBLE_DISTANCE * m);
+        for distance in [1.0, 2.0, 5.0] {
+            assert_relative_eq!(apparent(distance), reference, max_relative = 1e-4);
+        }
+    }
+
+    #[test]
+    fn glyph_packing() {
+        let (packed, len) = name_tag_glyphs("Ab ~é");
+        assert_eq!(len, 5);
+        assert_eq!(
+            packed[0],
+            u32::from_le_bytes([b'A' - b' ', b'b' - b' ', 0, b'~' - b' '])
+        );
+        assert_eq!(packed[1], u32::from(b'?' - b' '));
+        assert!(packed[2..].iter().all(|&x| x == 0));
+
+        let (_, len) = name_tag_glyphs(&"x".repeat(2 * NAME_TAG_MAX_LEN));
+        assert_eq!(len as usize, NAME_TAG_MAX_LEN);
+    }
+}
@@ -0,0 +1,588 @@
+//! How soon chunks near the view should be generated, uploaded, and meshed
+//!
+//! Every stage that prepares chunks for drawing orders its work by the same score, so that a chunk
+//! generated early for being in view isn't then left waiting behind chunks that aren't. Scores are
+//! computed once per node from the transforms the view's node keeps to its neighbors, and reused
+//! until the view moves or turns enough to change them.
+
+use fxhash::FxHashMap;
+use metrics::counter;
+
+use common::{
+    dodeca::{Vertex, VERTEX_COUNT},
+    graph::NodeId,
+    math,
+    node::ChunkId,
+    proto::Position,
+};
+
+/// Angle in radians the view may turn from where scores were computed before they're recomputed
+pub const FACING_THRESHOLD: f32 = 0.1;
+/// Distance the view may move from where scores were computed before they're recomputed
+pub const MOVE_THRESHOLD: f32 = 0.05;
+/// Speed in radians per second above which the view is considered to be turning quickly
+pub const TURN_THRESHOLD: f32 = 3.0;
+/// How far around from straight ahead chunks count as ahead while turning quickly, from 0 for not
+/// at all to 1 for chunks at right angles counting half as much as those straight ahead
+pub const TURN_WIDENING: f32 = 1.0;
+/// Seconds for which chunks around the view keep their boost after it stops turning quickly
+pub const TURN_BOOST_DURATION: f32 = 0.25;
+/// Amount by which a chunk's score falls for each frame it waits, so that none waits forever
+pub const AGING_RATE: f32 = 1e-3;
+
+/// Scores of the chunks around a view, cached by node
+pub struct ChunkPriority {
+    /// View the cached scores were computed from
+    reference: Option<Reference>,
+    /// Score of each chunk of each node scored since `reference` was set
+    scores: FxHashMap<NodeId, [f32; VERTEX_COUNT]>,
+    /// View as of the previous update, to tell how fast it's turning
+    previous: Option<Position>,
+    /// Seconds left until chunks around the view lose their boost
+    boost_remaining: f32,
+    /// Number of times cached scores have been discarded
+    rescores: u64,
+}
+
+struct Reference {
+    node: NodeId,
+    /// Transform from the view's node to the view
+    local_to_view: na::Matrix4<f32>,
+    /// `TURN_WIDENING` if the view was turning quickly, and 0 otherwise
+    widening: f32,
+}
+
+impl ChunkPriority {
+    pub fn new() -> Self {
+        Self {
+            reference: None,
+            scores: FxHashMap::default(),
+            previous: None,
+            boost_remaining: 0.0,
+            rescores: 0,
+        }
+    }
+
+    /// Account for the view having reached `view` `dt` seconds after the previous update
+    ///
+    /// Cached scores are discarded if the view is now in a different node, has moved or turned
+    /// beyond `MOVE_THRESHOLD` or `FACING_THRESHOLD` since they were computed, or has started or
+    /// stopped turning quickly.
+    pub fn update(&mut self, view: &Position, dt: f32) {
+        self.boost_remaining = (self.boost_remaining - dt).max(0.0);
+        if let Some(ref previous) = self.previous {
+            // Turns across node boundaries are rare enough to miss
+            if previous.node == view.node && dt > 0.0 {
+                let turned = angle(&(math::mtranspose(&previous.local) * view.local));
+                if turned / dt > TURN_THRESHOLD {
+                    self.boost_remaining = TURN_BOOST_DURATION;
+                }
+            }
+        }
+        self.previous = Some(*view);
+
+        let widening = if self.boost_remaining > 0.0 {
+            TURN_WIDENING
+        } else {
+            0.0
+        };
+        let stale = match self.reference {
+            None => true,
+            Some(ref reference) => {
+                let relative = reference.local_to_view * view.local;
+                reference.node != view.node
+                    || reference.widening != widening
+                    || math::distance(&math::origin(), &(relative * math::origin()))
+                        > MOVE_THRESHOLD
+                    || angle(&relative) > FACING_THRESHOLD
+            }
+        };
+        if stale {
+            self.reference = Some(Reference {
+                node: view.node,
+                local_to_view: math::mtranspose(&view.local),
+                widening,
+            });
+            self.scores.clear();
+            self.rescores += 1;
+            counter!("voxels.priority.rescored", 1);
+        }
+    }
+
+    /// Score of the chunk at `vertex` of `node`, whose transform into the view's node is
+    /// `node_transform`. Lower is sooner.
+    ///
+    /// Scores are relative to the view as of the last time cached scores were discarded.
+    pub fn score(
+        &mut self,
+        node: NodeId,
+        node_transform: &na::Matrix4<f32>,
+        vertex: Vertex,
+    ) -> f32 {
+        let Some(ref reference) = self.reference else {
+            return score(&chunk_center(node_transform, vertex), 0.0);
+        };
+        self.scores.entry(node).or_insert_with(|| {
+            let node_to_view = reference.local_to_view * node_transform;
+            let mut scores = [0.0; VERTEX_COUNT];
+            for vertex in Vertex::iter() {
+                scores[vertex as usize] =
+                    score(&chunk_center(&node_to_view, vertex), reference.widening);
+            }
+            scores
+        })[vertex as usize]
+    }
+
+    /// Whether chunks around the view are currently boosted for its turning quickly
+    pub fn turning(&self) -> bool {
+        self.boost_remaining > 0.0
+    }
+
+    /// Number of times cached scores have been discarded
+    pub fn rescores(&self) -> u64 {
+        self.rescores
+    }
+}
+
+/// Angle in radians between the -Z axis and its image under `transform`
+fn angle(transform: &na::Matrix4<f32>) -> f32 {
+    let forward = (transform * -na::Vector4::z()).xyz();
+    match forward.try_normalize(1e-6) {
+        Some(forward) => (-forward.z).clamp(-1.0, 1.0).acos(),
+        None => 0.0,
+    }
+}
+
+/// How soon a chunk whose center is `center`, normalized and relative to a view looking along -Z,
+/// should be prepared. Lower is sooner.
+///
+/// Proportional to distance, as chunks are loaded, but with chunks behind the view waiting up to
+/// twice as long as those straight ahead. With a nonzero `widening`, chunks off to the side count
+/// as more nearly ahead, so that a quickly turning view finds them ready.
+pub fn score(center: &na::Vector4<f32>, widening: f32) -> f32 {
+    let distance = center.w.max(1.0).acosh();
+    let direction = center.xyz();
+    let facing = match direction.try_normalize(1e-6) {
+        Some(direction) => -direction.z,
+        // At the view itself
+        None => 1.0,
+    };
+    let facing = (facing + widening) / (1.0 + widening);
+    distance * (1.5 - 0.5 * facing)
+}
+
+/// Score of something first wanted with `score` `waited` frames ago
+pub fn aged(score: f32, waited: u64) -> f32 {
+    score - AGING_RATE * waited as f32
+}
+
+/// Center of the chunk at `vertex` of a node, in the space `node_to_view` maps the node into
+pub fn chunk_center(node_to_view: &na::Matrix4<f32>, vertex: Vertex) -> na::Vector4<f32> {
+    let chunk_to_node = vertex.chunk_to_node_f32();
+    math::lorentz_normalize(&(node_to_view * chunk_to_node * na::Vector4::new(0.5, 0.5, 0.5, 1.0)))
+}
+
+/// Chunks wanted by one stage of preparation for drawing, each with a value of `T`
+///
+/// Chunks must be pushed again in every frame they're still wanted, with their current score, and
+/// are forgotten otherwise. They're taken in order of their score, aged by how long they've been
+/// continuously wanted.
+pub struct ChunkQueue<T> {
+    entries: FxHashMap<ChunkId, Queued<T>>,
+    /// Number of the current frame
+    frame: u64,
+}
+
+struct Queued<T> {
+    value: T,
+    score: f32,
+    /// Frame in which the chunk was first queued
+    since: u64,
+    /// Frame in which the chunk was last queued
+    frame: u64,
+}
+
+impl<T> ChunkQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            entries: FxHashMap::default(),
+            frame: 0,
+        }
+    }
+
+    /// Begin collecting the chunks wanted by a new frame
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Want `chunk` with `value` at `score`, returning the value it was already queued with, if
+    /// any
+    pub fn push(&mut self, chunk: ChunkId, value: T, score: f32) -> Option<T> {
+        let frame = self.frame;
+        match self.entries.get_mut(&chunk) {
+            Some(queued) => {
+                queued.score = score;
+                queued.frame = frame;
+                Some(std::mem::replace(&mut queued.value, value))
+            }
+            None => {
+                self.entries.insert(
+                    chunk,
+                    Queued {
+                        value,
+                        score,
+                        since: frame,
+                        frame,
+                    },
+                );
+                None
+            }
+        }
+    }
+
+    /// Forget chunks not wanted since the last `begin_frame`, returning how many there were
+    pub fn expire(&mut self) -> usize {
+        let frame = self.frame;
+        let before = self.entries.len();
+        self.entries.retain(|_, x| x.frame == frame);
+        before - self.entries.len()
+    }
+
+    /// Every queued chunk, most urgent first
+    pub fn ordered(&self) -> Vec<ChunkId> {
+        let mut order = self
+            .entries
+            .iter()
+            .map(|(&chunk, queued)| (chunk, aged(queued.score, self.frame - queued.since)))
+            .collect::<Vec<_>>();
+        order.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+        order.into_iter().map(|(chunk, _)| chunk).collect()
+    }
+
+    pub fn get(&self, chunk: ChunkId) -> Option<&T> {
+        self.entries.get(&chunk).map(|x| &x.value)
+    }
+
+    /// Stop waiting on `chunk`, returning its value if it was queued
+    pub fn remove(&mut self, chunk: ChunkId) -> Option<T> {
+        self.entries.remove(&chunk).map(|x| x.value)
+    }
+
+    /// Number of chunks waiting
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fxhash::FxHashSet;
+
+    use super::*;
+    use common::{
+        graph::Graph,
+        traversal::{ensure_nearby, nearby_nodes},
+    };
+
+    const DT: f32 = 1.0 / 60.0;
+
+    /// A view at the origin of the root node, turned `yaw` radians from looking along -Z
+    fn view(yaw: f32) -> Position {
+        Position {
+            node: NodeId::ROOT,
+            local: na::Rotation3::from_axis_angle(&na::Vector3::y_axis(), yaw).to_homogeneous(),
+        }
+    }
+
+    fn chunk(i: usize) -> ChunkId {
+        ChunkId::new(NodeId::ROOT, Vertex::iter().nth(i % VERTEX_COUNT).unwrap())
+    }
+
+    #[test]
+    fn ahead_sooner() {
+        let at = |x: f32, z: f32| math::lorentz_normalize(&na::Vector4::new(x, 0.0, z, 1.0));
+        let ahead = score(&at(0.0, -0.5), 0.0);
+        let beside = score(&at(0.5, 0.0), 0.0);
+        let behind = score(&at(0.0, 0.5), 0.0);
+        assert!(ahead < beside && beside < behind);
+        assert!((behind - 2.0 * ahead).abs() < 1e-5);
+        // Farther is later
+        assert!(ahead < score(&at(0.0, -0.7), 0.0));
+
+        // Turning brings what's beside forward without changing the order
+        let widened = |x, z| score(&at(x, z), TURN_WIDENING);
+        assert!(widened(0.5, 0.0) < beside);
+        assert!(widened(0.0, -0.5) < widened(0.5, 0.0));
+        assert!(widened(0.5, 0.0) < widened(0.0, 0.5));
+    }
+
+    #[test]
+    fn rescored_beyond_thresholds() {
+        let mut priority = ChunkPriority::new();
+        // Slow enough not to count as turning quickly
+        let dt = 1.0;
+        priority.update(&view(0.0), dt);
+        assert_eq!(priority.rescores(), 1);
+
+        // Turning a little at a time, until the total passes the threshold
+        let steps = 4;
+        let step = 0.9 * FACING_THRESHOLD / (steps - 1) as f32;
+        for i in 1..steps {
+            priority.update(&view(i as f32 * step), dt);
+            assert_eq!(priority.rescores(), 1, "turned {}", i as f32 * step);
+        }
+        priority.update(&view(steps as f32 * step), dt);
+        assert_eq!(priority.rescores(), 2);
+
+        // Moving
+        let turned = view(steps as f32 * step);
+        let moved = |distance: f32| Position {
+            local: math::translate_along(&(na::Vector3::x() * distance)) * turned.local,
+            ..turned
+        };
+        priority.update(&moved(0.9 * MOVE_THRESHOLD), dt);
+        assert_eq!(priority.rescores(), 2);
+        priority.update(&moved(1.1 * MOVE_THRESHOLD), dt);
+        assert_eq!(priority.rescores(), 3);
+
+        // Entering another node
+        let mut graph = Graph::new(12);
+        ensure_nearby(&mut graph, &Position::origin(), 2.0);
+        let (neighbor, _) = nearby_nodes(&graph, &Position::origin(), 2.0)
+            .into_iter()
+            .find(|&(node, _)| node != NodeId::ROOT)
+            .unwrap();
+        priority.update(
+            &Position {
+                node: neighbor,
+                local: na::Matrix4::identity(),
+            },
+            dt,
+        );
+        assert_eq!(priority.rescores(), 4);
+
+        // Scores are kept until then
+        let mut priority = ChunkPriority::new();
+        priority.update(&view(0.0), dt);
+        let transform = na::Matrix4::identity();
+        let before = priority.score(NodeId::ROOT, &transform, Vertex::A);
+        priority.update(&view(0.5 * FACING_THRESHOLD), dt);
+        assert_eq!(priority.score(NodeId::ROOT, &transform, Vertex::A), before);
+    }
+
+    #[test]
+    fn boosted_while_turning() {
+        let mut priority = ChunkPriority::new();
+        priority.update(&view(0.0), DT);
+        priority.update(&view(0.5 * TURN_THRESHOLD * DT), DT);
+        assert!(!priority.turning());
+        let rescores = priority.rescores();
+
+        // A quick turn widens the scoring at once
+        priority.update(&view(2.0 * TURN_THRESHOLD * DT), DT);
+        assert!(priority.turning());
+        assert_eq!(priority.rescores(), rescores + 1);
+
+        // And for a moment after the view stops
+        let frames = (TURN_BOOST_DURATION / DT) as usize;
+        for _ in 1..frames {
+            priority.update(&view(2.0 * TURN_THRESHOLD * DT), DT);
+            assert!(priority.turning());
+        }
+        for _ in 0..3 {
+            priority.update(&view(2.0 * TURN_THRESHOLD * DT), DT);
+        }
+        assert!(!priority.turning());
+        assert_eq!(priority.rescores(), rescores + 2);
+    }
+
+    #[test]
+    fn queue_ages() {
+        let mut queue = ChunkQueue::new();
+        queue.begin_frame();
+        queue.push(chunk(0), 0, 2.0);
+        queue.push(chunk(1), 0, 1.0);
+        assert_eq!(queue.ordered(), [chunk(1), chunk(0)]);
+        assert_eq!(queue.push(chunk(0), 1, 2.0), Some(0));
+        assert_eq!(queue.get(chunk(0)), Some(&1));
+
+        // Chunk 0 has waited long enough to go ahead of chunk 1 wanted anew, until it's forgotten
+        // for not being wanted
+        let frames = (1.5 / AGING_RATE) as u64;
+        for _ in 0..frames {
+            queue.begin_frame();
+            queue.push(chunk(0), 1, 2.0);
+            queue.expire();
+        }
+        assert_eq!(queue.ordered(), [chunk(0)]);
+        queue.push(chunk(1), 0, 1.0);
+        assert_eq!(queue.ordered(), [chunk(0), chunk(1)]);
+        queue.begin_frame();
+        queue.push(chunk(1), 0, 1.0);
+        assert_eq!(queue.expire(), 1);
+        assert_eq!(queue.ordered(), [chunk(1)]);
+        assert_eq!(queue.remove(chunk(1)), Some(0));
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn least_urgent_not_starved() {
+        // One chunk is taken per frame, and each frame brings a new chunk more urgent than an old
+        // one that's always wanted
+        let mut queue = ChunkQueue::new();
+        let low = chunk(VERTEX_COUNT - 1);
+        let low_score = 10.0;
+        let mut waiting = Vec::new();
+        let mut taken_at = None;
+        for frame in 0..(2.0 * low_score / AGING_RATE) as usize {
+            queue.begin_frame();
+            waiting.push(chunk(frame % (VERTEX_COUNT - 1)));
+            for &urgent in &waiting {
+                queue.push(urgent, (), 0.0);
+            }
+            queue.push(low, (), low_score);
+            queue.expire();
+            let next = queue.ordered()[0];
+            queue.remove(next);
+            if next == low {
+                taken_at = Some(frame);
+                break;
+            }
+            waiting.retain(|&x| x != next);
+        }
+        let taken_at = taken_at.expect("starved");
+        assert!(taken_at as f32 <= low_score / AGING_RATE + 1.0);
+    }
+
+    /// One stage of preparation, recording when chunks enter and leave it
+    struct Stage {
+        queue: ChunkQueue<()>,
+        /// Frame in which each chunk was first wanted by the stage
+        entered: FxHashMap<ChunkId, usize>,
+        /// Chunks in the order they left the stage, with the frames they left in
+        left: Vec<(ChunkId, usize)>,
+        done: FxHashSet<ChunkId>,
+    }
+
+    impl Stage {
+        fn new() -> Self {
+            Self {
+                queue: ChunkQueue::new(),
+                entered: FxHashMap::default(),
+                left: Vec::new(),
+                done: FxHashSet::default(),
+            }
+        }
+
+        fn push(&mut self, frame: usize, chunk: ChunkId, score: f32) {
+            self.queue.push(chunk, (), score);
+            self.entered.entry(chunk).or_insert(frame);
+        }
+
+        fn take(&mut self, frame: usize, count: usize) {
+            self.queue.expire();
+            for chunk in self.queue.ordered().into_iter().take(count) {
+                self.queue.remove(chunk);
+                self.done.insert(chunk);
+                self.left.push((chunk, frame));
+            }
+        }
+    }
+
+    /// Chunks passing through generation and then upload, each of which takes only a few chunks per
+    /// frame
+    struct Pipeline {
+        nodes: Vec<(NodeId, na::Matrix4<f32>)>,
+        priority: ChunkPriority,
+        frame: usize,
+        generation: Stage,
+        upload: Stage,
+    }
+
+    impl Pipeline {
+        fn new(distance: f64) -> Self {
+            let mut graph = Graph::new(12);
+            ensure_nearby(&mut graph, &Position::origin(), distance);
+            Self {
+                nodes: nearby_nodes(&graph, &Position::origin(), distance),
+                priority: ChunkPriority::new(),
+                frame: 0,
+                generation: Stage::new(),
+                upload: Stage::new(),
+            }
+        }
+
+        fn step(&mut self, view: &Position, generate: usize, upload: usize) {
+            self.frame += 1;
+            self.priority.update(view, DT);
+            self.generation.queue.begin_frame();
+            self.upload.queue.begin_frame();
+            for &(node, ref transform) in &self.nodes {
+                for vertex in Vertex::iter() {
+                    let chunk = ChunkId::new(node, vertex);
+                    let score = self.priority.score(node, transform, vertex);
+                    if !self.generation.done.contains(&chunk) {
+                        self.generation.push(self.frame, chunk, score);
+                    } else if !self.upload.done.contains(&chunk) {
+                        self.upload.push(self.frame, chunk, score);
+                    }
+                }
+            }
+            self.generation.take(self.frame, generate);
+            self.upload.take(self.frame, upload);
+        }
+
+        /// Distance to the center of `chunk` and the cosine of its angle from the direction
+        /// `view` faces
+        fn placement(&self, view: &Position, chunk: ChunkId) -> (f32, f32) {
+            let (_, transform) = self.nodes.iter().find(|x| x.0 == chunk.node).unwrap();
+            let center = chunk_center(&(math::mtranspose(&view.local) * transform), chunk.vertex);
+            let facing = -center.xyz().normalize().z;
+            (center.w.acosh(), facing)
+        }
+    }
+
+    #[test]
+    fn turning_around_reorders_every_stage() {
+        let mut pipeline = Pipeline::new(2.0);
+        // Everything is wanted while facing one way, but little is done
+        for _ in 0..5 {
+            pipeline.step(&view(0.0), 4, 2);
+        }
+
+        // Turn around in a fifth of a second, then keep still
+        let turn = 12;
+        for i in 1..=turn {
+            pipeline.step(&view(std::f32::consts::PI * i as f32 / turn as f32), 4, 2);
+        }
+        assert!(pipeline.priority.turning());
+        let around = view(std::f32::consts::PI);
+        let start = [pipeline.generation.left.len(), pipeline.upload.left.len()];
+        for _ in 0..30 {
+            pipeline.step(&around, 4, 2);
+        }
+
+        // Nothing clearly behind leaves a stage while something clearly in front and no farther
+        // away is waiting in it, however long either has waited
+        for (name, stage, start) in [
+            ("generation", &pipeline.generation, start[0]),
+            ("upload", &pipeline.upload, start[1]),
+        ] {
+            let trace = &stage.left[start..];
+            assert!(trace.len() > 20, "{name} stalled");
+            for (i, &(behind, left)) in trace.iter().enumerate() {
+                let (behind_distance, behind_facing) = pipeline.placement(&around, behind);
+                if behind_facing > -0.5 {
+                    continue;
+                }
+                for &(ahead, _) in &trace[i + 1..] {
+                    let (distance, facing) = pipeline.placement(&around, ahead);
+                    assert!(
+                        facing < 0.5 || distance > behind_distance || stage.entered[&ahead] > left,
+                        "{name}: behind at {behind_distance} before ahead at {distance}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,401 @@
+//! The config file as a TOML document, independent of what each setting means
+//!
+//! A document is upgraded to the current schema version as it's parsed, then split into the
+//! settings of the current schema and everything else. Settings this client doesn't recognize,
+//! like those of a newer version, are carried along untouched so that writing the document back
+//! doesn't lose them.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use toml::{Table, Value};
+
+use super::{
+    migrate::{self, CURRENT_VERSION},
+    validate::Problem,
+};
+
+/// Key of the schema version a config file was written for, absent from files predating it
+pub const VERSION_KEY: &str = "config_version";
+
+/// A table of the current schema and the names of the settings it holds
+pub struct Section {
+    /// Keys leading to the table from the top of the document
+    pub path: &'static [&'static str],
+    pub keys: &'static [&'static str],
+}
+
+impl Section {
+    /// The table at `path` holding the fields of `T`
+    pub fn of<T: DeserializeOwned>(path: &'static [&'static str]) -> Self {
+        Self {
+            path,
+            keys: fields::<T>(),
+        }
+    }
+}
+
+/// A config file's contents, upgraded to the current schema version
+pub struct Document {
+    /// Schema version the file was written for
+    pub written_version: u32,
+    /// Settings of the current schema
+    known: Table,
+    /// Settings this client doesn't recognize, in the same tables they were found in
+    extras: Table,
+    /// Dotted paths of the settings in `extras`
+    pub unknown: Vec<String>,
+    /// Problems found before any setting was interpreted
+    problems: Vec<Problem>,
+}
+
+impl Document {
+    /// Parse `text`, upgrading it to the current version and setting aside whatever isn't part of
+    /// the schema described by `sections`
+    pub fn parse(text: &str, sections: &[Section]) -> Result<Self, toml::de::Error> {
+        let mut known = toml::from_str::<Table>(text)?;
+        let mut problems = Vec::new();
+        let written_version = match known.remove(VERSION_KEY) {
+            None => 0,
+            Some(Value::Integer(x)) if u32::try_from(x).is_ok() => x as u32,
+            Some(value) => {
+                problems.push(Problem::new(
+                    VERSION_KEY,
+                    &value,
+                    format!("expected a whole number from 0 to {CURRENT_VERSION}"),
+                ));
+                // Better to read it as it stands than to rearrange it on a guess
+                CURRENT_VERSION
+            }
+        };
+        migrate::upgrade(&mut known, written_version);
+        let mut extras = Table::new();
+        let mut unknown = Vec::new();
+        for section in sections {
+            let Some(table) = table_at(&mut known, section.path) else {
+                continue;
+            };
+            let strays = table
+                .keys()
+                .filter(|key| !section.keys.contains(&key.as_str()))
+                .cloned()
+                .collect::<Vec<_>>();
+            for key in strays {
+                let value = table.remove(&key).unwrap();
+                unknown.push(dotted(section.path, &key));
+                insert_at(&mut extras, section.path, key, value);
+            }
+        }
+        Ok(Self {
+            written_version,
+            known,
+            extras,
+            unknown,
+            problems,
+        })
+    }
+
+    /// Whether the file was written for an earlier version of the schema
+    pub fn upgraded(&self) -> bool {
+        self.written_version < CURRENT_VERSION
+    }
+
+    /// Whether the file was written for a version of the schema this client predates
+    pub fn newer(&self) -> bool {
+        self.written_version > CURRENT_VERSION
+    }
+
+    /// Interpret the known settings as a `T`, leaving any that can't be interpreted at their
+    /// defaults
+    ///
+    /// Each setting is tried on its own, so every unusable one is found at once. `T` must have a
+    /// default for every setting.
+    pub fn settings<T: DeserializeOwned + Default>(
+        &self,
+        sections: &[Section],
+    ) -> (T, Vec<Problem>) {
+        let mut problems = self.problems.clone();
+        let mut usable = self.known.clone();
+        for section in sections {
+            let Some(table) = table_at(&mut usable, section.path) else {
+                continue;
+            };
+            let keys = table.keys().cloned().collect::<Vec<_>>();
+            for key in keys {
+                let is_section = sections
+                    .iter()
+                    .any(|x| x.path.split_last() == Some((&key.as_str(), section.path)));
+                let value = &table[&key];
+                if is_section && value.is_table() {
+                    // Checked setting by setting in turn
+                    continue;
+                }
+                let mut alone = Table::new();
+                insert_at(&mut alone, section.path, key.clone(), value.clone());
+                if let Err(e) = Value::Table(alone).try_into::<T>() {
+                    problems.push(Problem::new(
+                        &dotted(section.path, &key),
+                        value,
+                        e.message().into(),
+                    ));
+                    table.remove(&key);
+                }
+            }
+        }
+        let settings = Value::Table(usable)
+            .try_into()
+            .expect("settings usable one at a time are usable together");
+        (settings, problems)
+    }
+
+    /// The document as it would be written at the current version, settings in a stable order
+    pub fn to_toml(&self) -> String {
+        let mut table = self.known.clone();
+        merge(&mut table, &self.extras);
+        let body = toml::to_string(&table).expect("tables of TOML values always serialize");
+        let version = self.written_version.max(CURRENT_VERSION);
+        // First, where it's easily found, rather than wherever the key happens to sort
+        format!("{VERSION_KEY} = {version}\n{body}")
+    }
+}
+
+/// Replace the file at `path` with `text` atomically, so a crash mid-write leaves the previous
+/// contents intact
+pub fn write_atomic(path: &Path, text: &str) -> io::Result<()> {
+    let temp = stage(path, text)?;
+    fs::rename(&temp, path)
+}
+
+/// Write `text` to a file beside `path`, ready to be renamed over it
+fn stage(path: &Path, text: &str) -> io::Result<PathBuf> {
+    let temp = path.with_extension("toml.tmp");
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+    Ok(temp)
+}
+
+/// The table reached from `table` by following `path`, if there is one
+fn table_at<'a>(table: &'a mut Table, path: &[&str]) -> Option<&'a mut Table> {
+    match path.split_first() {
+        None => Some(table),
+        Some((first, rest)) => match table.get_mut(*first)? {
+            Value::Table(x) => table_at(x, rest),
+            _ => None,
+        },
+    }
+}
+
+/// Insert `value` under `key` in the table reached from `table` by following `path`, creating
+/// tables along the way as needed
+fn insert_at(table: &mut Table, path: &[&str], key: String, value: Value) {
+    let mut target = table;
+    for &step in path {
+        target = match target
+            .entry(step)
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(x) => x,
+            _ => unreachable!("settings are only set aside from tables"),
+        };
+    }
+    target.insert(key, value);
+}
+
+/// Add everything in `extras` to `table`, descending into tables both have
+fn merge(table: &mut Table, extras: &Table) {
+    for (key, value) in extras {
+        match (table.get_mut(key), value) {
+            (Some(Value::Table(existing)), Value::Table(value)) => merge(existing, value),
+            _ => {
+                table.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn dotted(path: &[&str], key: &str) -> String {
+    let mut result = path.join(".");
+    if !result.is_empty() {
+        result.push('.');
+    }
+    result.push_str(key);
+    result
+}
+
+/// Names of the fields of the struct that `T` is deserialized as
+fn fields<T: DeserializeOwned>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    // Derived implementations name their fields up front, so nothing more is needed
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// A deserializer that records the fields of the struct asked of it, deserializing nothing
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("only the field names were wanted"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Default, Debug, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    struct Settings {
+        speed: Option<f32>,
+        name: Option<String>,
+        #[serde(default)]
+        inner: Inner,
+    }
+
+    #[derive(Deserialize, Default, Debug, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    struct Inner {
+        depth: Option<u32>,
+    }
+
+    fn sections() -> [Section; 2] {
+        [
+            Section::of::<Settings>(&[]),
+            Section::of::<Inner>(&["inner"]),
+        ]
+    }
+
+    #[test]
+    fn field_names() {
+        assert_eq!(fields::<Settings>(), ["speed", "name", "inner"]);
+        assert_eq!(fields::<Inner>(), ["depth"]);
+    }
+
+    #[test]
+    fn unknown_settings_survive_round_trip() {
+        let text = format!(
+            "{VERSION_KEY} = {CURRENT_VERSION}\n\
+             speed = 2.0\n\
+             colour = \"red\"\n\
+             [inner]\n\
+             depth = 3\n\
+             width = 4\n\
+             [bindings]\n\
+             jump = \"Space\"\n"
+        );
+        let document = Document::parse(&text, &sections()).unwrap();
+        assert_eq!(document.unknown, ["bindings", "colour", "inner.width"]);
+        let (settings, problems) = document.settings::<Settings>(&sections());
+        assert!(problems.is_empty());
+        assert_eq!(
+            settings,
+            Settings {
+                speed: Some(2.0),
+                name: None,
+                inner: Inner { depth: Some(3) },
+            }
+        );
+
+        // Written back, nothing is lost, and reading it again finds the same
+        let written = document.to_toml();
+        assert!(written.starts_with(&format!("{VERSION_KEY} = {CURRENT_VERSION}\n")));
+        assert_eq!(
+            toml::from_str::<Table>(&written).unwrap(),
+            toml::from_str::<Table>(&text).unwrap()
+        );
+        let reread = Document::parse(&written, &sections()).unwrap();
+        assert_eq!(reread.unknown, document.unknown);
+        assert_eq!(reread.to_toml(), written);
+    }
+
+    #[test]
+    fn newer_version_kept() {
+        let version = CURRENT_VERSION + 3;
+        let text = format!("{VERSION_KEY} = {version}\nspeed = 1.0\nfuture = true\n");
+        let document = Document::parse(&text, &sections()).unwrap();
+        assert!(document.newer());
+        assert!(!document.upgraded());
+        assert_eq!(document.unknown, ["future"]);
+        // Still written as the newer version it is
+        assert!(document
+            .to_toml()
+            .starts_with(&format!("{VERSION_KEY} = {version}\n")));
+    }
+
+    #[test]
+    fn each_unusable_setting_found() {
+        let text = "speed = \"fast\"\nname = 7\n[inner]\ndepth = -1\n";
+        let document = Document::parse(text, &sections()).unwrap();
+        let (settings, problems) = document.settings::<Settings>(&sections());
+        assert_eq!(settings, Settings::default());
+        let paths = problems.iter().map(|x| x.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["name", "speed", "inner.depth"]);
+        assert_eq!(problems[1].value, "\"fast\"");
+        // The unusable settings are still written back as they were
+        assert_eq!(
+            toml::from_str::<Table>(&document.to_toml()).unwrap()["inner"]["depth"],
+            Value::Integer(-1)
+        );
+    }
+
+    #[test]
+    fn illegible_version_reported() {
+        let text = format!("{VERSION_KEY} = \"two\"\nspeed = 1.0\n");
+        let document = Document::parse(&text, &sections()).unwrap();
+        assert!(!document.upgraded());
+        let (settings, problems) = document.settings::<Settings>(&sections());
+        assert_eq!(settings.speed, Some(1.0));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path, VERSION_KEY);
+    }
+
+    #[test]
+    fn crash_leaves_old_or_new() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("client.toml");
+        let old = "speed = 1.0\n";
+        let new = "speed = 2.0\nname = \"new\"\n";
+        fs::write(&path, old).unwrap();
+        let intact = |path: &Path| {
+            let text = fs::read_to_string(path).unwrap();
+            assert!(text == old || text == new, "torn config: {text:?}");
+            text
+        };
+
+        // Crashing at any point while the replacement is being written leaves the old file
+        for written in 0..new.len() {
+            fs::write(path.with_extension("toml.tmp"), &new[..written]).unwrap();
+            assert_eq!(intact(&path), old);
+        }
+        // As does crashing after it's written but before it takes the old one's place
+        stage(&path, new).unwrap();
+        assert_eq!(intact(&path), old);
+        // Leftovers of an interrupted write don't get in the way of the next
+        write_atomic(&path, new).unwrap();
+        assert_eq!(intact(&path), new);
+        assert!(!path.with_extension("toml.tmp").exists());
+    }
+}
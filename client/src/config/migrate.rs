@@ -0,0 +1,162 @@
+//! Upgrades of config files written for earlier versions of the schema
+//!
+//! Each change to the schema comes with a migration rewriting a file of the previous version to
+//! suit, so a file of any version is brought up to date by applying those that follow it in turn.
+//! Migrations never replace a setting the file already has, so they're safe to apply to files
+//! whose authors got ahead of the version they declare.
+
+use toml::{Table, Value};
+
+/// Version of the schema this client reads and writes
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Migrations from each version to the next, indexed by the earlier version
+const MIGRATIONS: [fn(&mut Table); CURRENT_VERSION as usize] = [group_camera, group_view_distance];
+
+/// Upgrade `table`, written for schema `version`, to the current version. Tables written for the
+/// current version or a newer one are left alone.
+pub fn upgrade(table: &mut Table, version: u32) {
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        migration(table);
+    }
+}
+
+/// Version 1 gathers the camera's settings into a `camera` table
+pub fn group_camera(table: &mut Table) {
+    relocate(table, "camera_half_life", &["camera", "half_life"]);
+    relocate(
+        table,
+        "camera_rise_half_life",
+        &["camera", "rise_half_life"],
+    );
+    relocate(table, "camera_snap_distance", &["camera", "snap_distance"]);
+    relocate(table, "view_bobbing", &["camera", "view_bobbing"]);
+    relocate(table, "interpolate_view", &["camera", "interpolate_view"]);
+}
+
+/// Version 2 gathers the settings of the adaptive view distance into a `view_distance` table
+pub fn group_view_distance(table: &mut Table) {
+    relocate(table, "min_view_distance", &["view_distance", "min"]);
+    relocate(table, "fog_margin", &["view_distance", "fog_margin"]);
+    relocate(
+        table,
+        "max_frame_time",
+        &["view_distance", "max_frame_time"],
+    );
+}
+
+/// Move the setting `from` to the end of `to`, creating tables along the way as needed, unless
+/// something is already in the way
+fn relocate(table: &mut Table, from: &str, to: &[&str]) {
+    if !table.contains_key(from) || !vacant(table, to) {
+        // Left where it is, to be reported as unknown
+        return;
+    }
+    let value = table.remove(from).unwrap();
+    let (key, parents) = to.split_last().expect("relocated to nowhere");
+    let mut target = table;
+    for &parent in parents {
+        target = match target
+            .entry(parent)
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(x) => x,
+            _ => unreachable!("checked by vacant"),
+        };
+    }
+    target.insert(key.to_string(), value);
+}
+
+/// Whether `path` could be filled in `table` without replacing anything
+fn vacant(table: &Table, path: &[&str]) -> bool {
+    match path {
+        [] => false,
+        [key] => !table.contains_key(*key),
+        [parent, rest @ ..] => match table.get(*parent) {
+            None => true,
+            Some(Value::Table(x)) => vacant(x, rest),
+            Some(_) => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(text: &str) -> Table {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn camera_grouped() {
+        let mut old = table(
+            "name = \"x\"\n\
+             camera_half_life = 0.1\n\
+             camera_snap_distance = 3.0\n\
+             view_bobbing = true\n",
+        );
+        group_camera(&mut old);
+        assert_eq!(
+            old,
+            table(
+                "name = \"x\"\n\
+                 [camera]\n\
+                 half_life = 0.1\n\
+                 snap_distance = 3.0\n\
+                 view_bobbing = true\n"
+            )
+        );
+    }
+
+    #[test]
+    fn view_distance_grouped() {
+        let mut old = table(
+            "min_view_distance = 20.0\n\
+             max_frame_time = 0.02\n\
+             [camera]\n\
+             half_life = 0.1\n",
+        );
+        group_view_distance(&mut old);
+        assert_eq!(
+            old,
+            table(
+                "[camera]\n\
+                 half_life = 0.1\n\
+                 [view_distance]\n\
+                 min = 20.0\n\
+                 max_frame_time = 0.02\n"
+            )
+        );
+    }
+
+    #[test]
+    fn nothing_overwritten() {
+        // A setting already in its new place wins, and one with nowhere to go stays put
+        let text = "camera_half_life = 0.1\n\
+                    min_view_distance = 20.0\n\
+                    view_distance = 50.0\n\
+                    [camera]\n\
+                    half_life = 0.2\n";
+        let mut old = table(text);
+        upgrade(&mut old, 0);
+        assert_eq!(old, table(text));
+    }
+
+    #[test]
+    fn current_and_newer_untouched() {
+        let text = "camera_half_life = 0.1\nmin_view_distance = 20.0\n";
+        for version in [CURRENT_VERSION, CURRENT_VERSION + 1] {
+            let mut new = table(text);
+            upgrade(&mut new, version);
+            assert_eq!(new, table(text));
+        }
+        // Version 1 files only need the later migration
+        let mut old = table(text);
+        upgrade(&mut old, 1);
+        assert_eq!(
+            old,
+            table("camera_half_life = 0.1\n[view_distance]\nmin = 20.0\n")
+        );
+    }
+}
@@ -0,0 +1,603 @@
+//! Client settings, read from a TOML file in the platform's config directory
+//!
+//! The file is versioned by its `config_version` setting. Files written for earlier versions are
+//! upgraded as they're read and written back, keeping the original beside them. Settings that
+//! can't be used are reported and left at their defaults rather than discarding the whole file,
+//! and unrecognized settings are kept through upgrades so a newer client's settings survive an
+//! older client.
+
+mod file;
+mod migrate;
+mod validate;
+
+use std::{
+    env, fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use common::{CharacterConfigRaw, SimConfig, SimConfigRaw, Step};
+
+use file::{Document, Section};
+pub use validate::ConfigReport;
+
+use crate::{
+    camera::CameraConfig, graphics::DisplaySettings, pending_nodes::DEFAULT_RESYNC_STEPS,
+    view_distance::ViewDistanceConfig,
+};
+
+pub struct Config {
+    pub name: Arc<str>,
+    pub data_dirs: Vec<PathBuf>,
+    /// Directory or zip archive of assets preferred to the built-in ones
+    pub asset_pack: Option<PathBuf>,
+    /// Whether asset packs suggested by servers are downloaded and used
+    pub accept_server_asset_packs: bool,
+    /// Where asset packs downloaded from servers are kept
+    pub asset_pack_cache_dir: PathBuf,
+    /// Total size of downloaded asset packs beyond which the least recently used are deleted
+    pub asset_pack_cache_bytes: u64,
+    pub chunk_load_parallelism: u32,
+    /// Most bytes of chunk voxels uploaded to the GPU for surface extraction in each frame
+    pub upload_budget_bytes: u64,
+    /// Time after which a chunk still being generated is presumed lost and requested again
+    pub chunk_generation_timeout: Duration,
+    /// Maximum size of generated chunk data retained for reuse after leaving the graph
+    pub worldgen_cache_bytes: usize,
+    /// Size of dense voxel data beyond which uniform chunks are compacted and distant unmodified
+    /// chunks evicted, if any
+    pub dense_voxel_budget_bytes: Option<usize>,
+    /// Size of device memory allocations beyond which the world is drawn in less detail to make
+    /// room, if not most of the GPU's own memory
+    pub gpu_memory_budget_bytes: Option<u64>,
+    /// Steps a node may wait for the server to send the node it branches from before the server
+    /// is asked for it again
+    pub node_resync_steps: Step,
+    pub server: Option<SocketAddr>,
+    pub local_simulation: SimConfig,
+    /// Distance from the viewpoint covered by the minimap, in absolute units
+    pub minimap_distance: f32,
+    /// Bounds within which the distance the world is drawn to adapts to the frame rate
+    pub view_distance: ViewDistanceConfig,
+    pub camera: CameraConfig,
+    /// Initial display settings, which may be reloaded while running
+    pub display: DisplaySettings,
+    /// Time between recordings of the character's position, or zero to record none
+    pub breadcrumb_interval: Duration,
+    /// Where recorded positions are kept
+    pub breadcrumb_dir: PathBuf,
+    /// Where the nodes explored on each server are kept
+    pub exploration_dir: PathBuf,
+    /// Where the waypoints made by the player on each server are kept
+    pub waypoint_dir: PathBuf,
+    /// Where the templates copied by the player are kept, for any server
+    pub template_dir: PathBuf,
+    /// Name under which positions recorded on `server` are kept, fixed before any local server is
+    /// substituted for a missing one
+    pub server_identity: String,
+    /// Where the config was loaded from
+    path: PathBuf,
+}
+
+impl Config {
+    /// Where the config file is kept
+    pub fn file(dirs: &directories::ProjectDirs) -> PathBuf {
+        // Future work: search $XDG_CONFIG_DIRS
+        dirs.config_dir().join("client.toml")
+    }
+
+    pub fn load(dirs: &directories::ProjectDirs) -> Self {
+        let path = Self::file(dirs);
+        // Read and parse config file
+        let RawConfig {
+            name,
+            data_dir,
+            asset_pack,
+            accept_server_asset_packs,
+            asset_pack_cache_megabytes,
+            local_simulation,
+            chunk_load_parallelism,
+            upload_budget_kilobytes,
+            chunk_generation_timeout,
+            worldgen_cache_megabytes,
+            dense_voxel_budget_megabytes,
+            gpu_memory_budget_megabytes,
+            node_resync_steps,
+            server,
+            minimap_distance,
+            breadcrumb_interval,
+            camera:
+                RawCamera {
+                    half_life: camera_half_life,
+                    rise_half_life: camera_rise_half_life,
+                    snap_distance: camera_snap_distance,
+                    view_bobbing,
+                    interpolate_view,
+                    follow_distance,
+                },
+            view_distance:
+                RawViewDistance {
+                    min: min_view_distance,
+                    fog_margin,
+                    max_frame_time,
+                },
+            display,
+        } = read_raw(&path, true);
+        let mut data_dirs = Vec::new();
+        if let Some(dir) = data_dir {
+            data_dirs.push(dir);
+        }
+        data_dirs.push(dirs.data_dir().into());
+        if let Ok(path) = env::current_exe() {
+            if let Some(dir) = path.parent() {
+                data_dirs.push(dir.into());
+            }
+        }
+        #[cfg(feature = "use-repo-assets")]
+        {
+            data_dirs.push(
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .parent()
+                    .unwrap()
+                    .join("assets"),
+            );
+        }
+        // Massage into final form
+        let local_simulation = SimConfig::from_raw(&local_simulation);
+        let meters_to_absolute = local_simulation.meters_to_absolute;
+        let camera = CameraConfig {
+            half_life: camera_half_life.unwrap_or(0.03),
+            rise_half_life: camera_rise_half_life.unwrap_or(0.08),
+            snap_distance: camera_snap_distance.unwrap_or(2.0) * meters_to_absolute,
+            bob_depth: if view_bobbing.unwrap_or(false) {
+                0.03 * meters_to_absolute
+            } else {
+                0.0
+            },
+            stride: 0.8 * meters_to_absolute,
+            interpolate_view: interpolate_view.unwrap_or(false),
+            follow_distance: follow_distance.unwrap_or(4.0) * meters_to_absolute,
+        };
+        Config {
+            name: name.unwrap_or_else(|| whoami::username().into()),
+            data_dirs,
+            asset_pack,
+            accept_server_asset_packs: accept_server_asset_packs.unwrap_or(false),
+            asset_pack_cache_dir: dirs.cache_dir().join("asset_packs"),
+            asset_pack_cache_bytes: u64::from(asset_pack_cache_megabytes.unwrap_or(256))
+                * 1024
+                * 1024,
+            chunk_load_parallelism: chunk_load_parallelism.unwrap_or(256),
+            upload_budget_bytes: u64::from(upload_budget_kilobytes.unwrap_or(512)) * 1024,
+            chunk_generation_timeout: chunk_generation_timeout
+                .map_or(Duration::from_secs(10), |x| {
+                    Duration::try_from_secs_f32(x).unwrap_or_default()
+                }),
+            worldgen_cache_bytes: worldgen_cache_megabytes.unwrap_or(64) as usize * 1024 * 1024,
+            dense_voxel_budget_bytes: dense_voxel_budget_megabytes
+                .map(|x| x as usize * 1024 * 1024),
+            gpu_memory_budget_bytes: gpu_memory_budget_megabytes
+                .map(|x| u64::from(x) * 1024 * 1024),
+            node_resync_steps: node_resync_steps.map_or(DEFAULT_RESYNC_STEPS, |x| {
+                Step::try_from(x).unwrap_or(Step::MAX)
+            }),
+            server,
+            minimap_distance: minimap_distance.unwrap_or(90.0) * meters_to_absolute,
+            view_distance: ViewDistanceConfig {
+                min: (min_view_distance.unwrap_or(30.0) * meters_to_absolute)
+                    .min(local_simulation.view_distance),
+                max: local_simulation.view_distance,
+                fog_margin: fog_margin.unwrap_or(5.0) * meters_to_absolute,
+                frame_time_cap: max_frame_time.and_then(|x| Duration::try_from_secs_f32(x).ok()),
+            },
+            local_simulation,
+            camera,
+            display: display.settings(),
+            breadcrumb_interval: breadcrumb_interval.map_or(Duration::from_secs(10), |x| {
+                Duration::try_from_secs_f32(x).unwrap_or_default()
+            }),
+            breadcrumb_dir: dirs.data_local_dir().join("breadcrumbs"),
+            exploration_dir: dirs.data_local_dir().join("exploration"),
+            waypoint_dir: dirs.data_local_dir().join("waypoints"),
+            template_dir: dirs.data_local_dir().join("templates"),
+            server_identity: server.map_or_else(|| "local".into(), |x| x.to_string()),
+            path,
+        }
+    }
+
+    /// Read the display settings from the config file again, so they can be changed while running
+    pub fn reload_display(&self) -> DisplaySettings {
+        read_raw(&self.path, false).display.settings()
+    }
+
+    /// Report everything noteworthy about the config file, without changing it
+    pub fn check(dirs: &directories::ProjectDirs) -> Result<ConfigReport, ConfigError> {
+        let text = fs::read_to_string(Self::file(dirs)).map_err(ConfigError::Io)?;
+        let (_, _, report) = parse(&text).map_err(ConfigError::Syntax)?;
+        Ok(report)
+    }
+
+    /// The config file as this client reads it, upgraded to the current version, without changing
+    /// it
+    pub fn print(dirs: &directories::ProjectDirs) -> Result<String, ConfigError> {
+        let text = match fs::read_to_string(Self::file(dirs)) {
+            Ok(x) => x,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(ConfigError::Io(e)),
+        };
+        let document = Document::parse(&text, &schema()).map_err(ConfigError::Syntax)?;
+        Ok(document.to_toml())
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Syntax(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref e) => write!(f, "couldn't read config: {e}"),
+            ConfigError::Syntax(ref e) => write!(f, "config isn't valid TOML: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Read and parse the config file at `path`, falling back to defaults for whatever can't be used
+///
+/// A file written for an earlier version is written back upgraded if `upgrade` is set.
+fn read_raw(path: &Path, upgrade: bool) -> RawConfig {
+    let text = match fs::read_to_string(path) {
+        Ok(x) => {
+            info!("found config at {}", path.display());
+            x
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            info!("{} not found, using defaults", path.display());
+            return RawConfig::default();
+        }
+        Err(e) => {
+            error!(path = %path.display(), error = %e, "failed to read config");
+            return RawConfig::default();
+        }
+    };
+    let (raw, document, report) = match parse(&text) {
+        Ok(x) => x,
+        Err(e) => {
+            error!(error = %e, "failed to parse config");
+            return RawConfig::default();
+        }
+    };
+    if document.newer() {
+        warn!(
+            version = report.version,
+            "config written for a newer version; settings this version doesn't know are ignored"
+        );
+    }
+    for setting in &report.unknown {
+        warn!(%setting, "ignoring unknown setting");
+    }
+    for problem in &report.problems {
+        warn!("{problem}; using the default");
+    }
+    if upgrade && document.upgraded() {
+        let backup = path.with_extension(format!("v{}.toml", document.written_version));
+        match fs::copy(path, &backup).and_then(|_| file::write_atomic(path, &document.to_toml())) {
+            Ok(()) => info!(
+                from = document.written_version,
+                to = migrate::CURRENT_VERSION,
+                backup = %backup.display(),
+                "upgraded config"
+            ),
+            Err(e) => error!(error = %e, "failed to write upgraded config"),
+        }
+    }
+    raw
+}
+
+/// Parse the text of a config file, upgrading it to the current version
+fn parse(text: &str) -> Result<(RawConfig, Document, ConfigReport), toml::de::Error> {
+    let schema = schema();
+    let document = Document::parse(text, &schema)?;
+    let (mut raw, mut problems) = document.settings::<RawConfig>(&schema);
+    raw.validate(&mut problems);
+    let report = ConfigReport {
+        version: document.written_version,
+        unknown: document.unknown.clone(),
+        problems,
+    };
+    Ok((raw, document, report))
+}
+
+/// Tables of the current version of the config file
+fn schema() -> [Section; 6] {
+    [
+        Section::of::<RawConfig>(&[]),
+        Section::of::<RawCamera>(&["camera"]),
+        Section::of::<RawViewDistance>(&["view_distance"]),
+        Section::of::<RawDisplay>(&["display"]),
+        Section::of::<SimConfigRaw>(&["local_simulation"]),
+        Section::of::<CharacterConfigRaw>(&["local_simulation", "character"]),
+    ]
+}
+
+/// Data as parsed directly out of the config file
+///
+/// Changing the layout of the file calls for a migration in `migrate`.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    name: Option<Arc<str>>,
+    data_dir: Option<PathBuf>,
+    /// Directory or zip archive whose assets replace the built-in ones. Assets it lacks or that
+    /// can't be used are taken from the built-in assets instead.
+    asset_pack: Option<PathBuf>,
+    /// Whether to download and use the asset pack a server suggests, which takes precedence over
+    /// `asset_pack`
+    accept_server_asset_packs: Option<bool>,
+    /// Total size in megabytes of downloaded asset packs to keep
+    asset_pack_cache_megabytes: Option<u32>,
+    chunk_load_parallelism: Option<u32>,
+    /// Most kilobytes of chunk voxels uploaded to the GPU in each frame, beyond which chunks wait
+    /// for later frames, nearest first
+    upload_budget_kilobytes: Option<u32>,
+    /// Time in seconds after which a chunk still being generated is requested again
+    chunk_generation_timeout: Option<f32>,
+    /// Maximum size in megabytes of generated chunk data retained for reuse
+    worldgen_cache_megabytes: Option<u32>,
+    /// Size in megabytes of voxel data beyond which memory is reclaimed from distant chunks, which
+    /// should leave room for `worldgen_cache_megabytes`, since cached data counts towards it
+    dense_voxel_budget_megabytes: Option<u32>,
+    /// Size in megabytes of GPU memory allocations beyond which the world is drawn to a shorter
+    /// distance and at a lower resolution to make room. Defaults to three quarters of the GPU's
+    /// own memory.
+    gpu_memory_budget_megabytes: Option<u32>,
+    /// Steps a node may wait for the server to send the node it branches from before the server
+    /// is asked for it again
+    node_resync_steps: Option<u32>,
+    server: Option<SocketAddr>,
+    /// Distance in meters from the viewpoint covered by the minimap
+    minimap_distance: Option<f32>,
+    /// Time in seconds between recordings of the character's position, used to find the way back
+    /// after reconnecting
+    breadcrumb_interval: Option<f32>,
+    #[serde(default)]
+    camera: RawCamera,
+    #[serde(default)]
+    view_distance: RawViewDistance,
+    #[serde(default)]
+    display: RawDisplay,
+    #[serde(default)]
+    local_simulation: SimConfigRaw,
+}
+
+/// Camera settings as parsed directly out of the config file's `camera` table
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawCamera {
+    /// Time in seconds for the camera to close half of its distance from the character
+    half_life: Option<f32>,
+    /// Like `half_life`, for the character rising above the camera, as when climbing steps
+    rise_half_life: Option<f32>,
+    /// Distance in meters from the character beyond which the camera stops following smoothly
+    snap_distance: Option<f32>,
+    /// Whether the camera dips slightly with each footstep
+    view_bobbing: Option<bool>,
+    /// Whether the character's position is shown interpolated between simulation steps, adding a
+    /// step of latency, rather than predicted ahead every frame
+    interpolate_view: Option<bool>,
+    /// Distance in meters the camera is held behind another character while following it
+    follow_distance: Option<f32>,
+}
+
+/// Adaptive view distance settings as parsed directly out of the config file's `view_distance`
+/// table
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawViewDistance {
+    /// Nearest in meters that the world is drawn to when frames are slow. The farthest is the
+    /// local simulation's `view_distance`.
+    min: Option<f32>,
+    /// Distance in meters past where the fog becomes opaque out to which chunks are kept
+    fog_margin: Option<f32>,
+    /// Longest in seconds each frame should take, if shorter than the display's refresh interval
+    max_frame_time: Option<f32>,
+}
+
+/// Display settings as parsed directly out of the config file's `display` table
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawDisplay {
+    /// Exponent by which displayed intensities are flattened, brightening dark regions
+    gamma: Option<f32>,
+    /// Factor by which rendered intensities are scaled
+    brightness: Option<f32>,
+    /// Vertical field of view in degrees
+    fov: Option<f32>,
+    /// Time in seconds over which changes to the field of view take effect
+    fov_transition: Option<f32>,
+    /// Vertical field of view in degrees of the held block, kept apart from `fov` so that it isn't
+    /// distorted by a wide view of the world
+    viewmodel_fov: Option<f32>,
+    /// Size of the rendered image relative to the window, trading detail for speed
+    render_scale: Option<f32>,
+}
+
+impl RawDisplay {
+    fn settings(&self) -> DisplaySettings {
+        let default = DisplaySettings::default();
+        DisplaySettings {
+            gamma: self.gamma.unwrap_or(default.gamma),
+            brightness: self.brightness.unwrap_or(default.brightness),
+            fov: self.fov.map_or(default.fov, f32::to_radians),
+            fov_transition: self.fov_transition.map_or(default.fov_transition, |x| {
+                Duration::try_from_secs_f32(x).unwrap_or_default()
+            }),
+            viewmodel_fov: self
+                .viewmodel_fov
+                .map_or(default.viewmodel_fov, f32::to_radians),
+            render_scale: self.render_scale.unwrap_or(default.render_scale),
+        }
+        .sanitize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use migrate::CURRENT_VERSION;
+
+    /// Parse `text`, expecting nothing to be reported but its version
+    fn parse_clean(text: &str) -> (RawConfig, Document) {
+        let (raw, document, report) = parse(text).unwrap();
+        assert!(report.unknown.is_empty(), "{report}");
+        assert!(report.is_valid(), "{report}");
+        (raw, document)
+    }
+
+    /// Parse `text`, check it's upgraded from `version`, and check the result reads the same once
+    /// written back
+    fn upgrade(text: &str, version: u32, check: impl Fn(&RawConfig)) {
+        let (raw, document) = parse_clean(text);
+        assert_eq!(document.written_version, version);
+        assert!(document.upgraded());
+        check(&raw);
+        let (raw, document) = parse_clean(&document.to_toml());
+        assert_eq!(document.written_version, CURRENT_VERSION);
+        check(&raw);
+    }
+
+    #[test]
+    fn historical_files_upgraded() {
+        upgrade(include_str!("fixtures/v0-early.toml"), 0, |raw| {
+            assert_eq!(raw.name.as_deref(), Some("early"));
+            assert_eq!(raw.server, Some("127.0.0.1:1234".parse().unwrap()));
+            assert_eq!(raw.chunk_load_parallelism, Some(64));
+            assert_eq!(raw.local_simulation.view_distance, Some(60.0));
+            assert_eq!(raw.camera.half_life, None);
+        });
+        upgrade(include_str!("fixtures/v0-camera.toml"), 0, |raw| {
+            assert_eq!(raw.minimap_distance, Some(120.0));
+            assert_eq!(raw.camera.half_life, Some(0.05));
+            assert_eq!(raw.camera.snap_distance, Some(4.0));
+            assert_eq!(raw.camera.view_bobbing, Some(true));
+            assert_eq!(raw.breadcrumb_interval, Some(5.0));
+            assert_eq!(raw.display.gamma, Some(1.2));
+            assert_eq!(raw.display.fov, Some(90.0));
+        });
+        upgrade(include_str!("fixtures/v0-recent.toml"), 0, |raw| {
+            assert_eq!(raw.asset_pack, Some(PathBuf::from("packs/crisp.zip")));
+            assert_eq!(raw.accept_server_asset_packs, Some(true));
+            assert_eq!(raw.gpu_memory_budget_megabytes, Some(2048));
+            assert_eq!(raw.camera.half_life, Some(0.02));
+            assert_eq!(raw.camera.rise_half_life, Some(0.1));
+            assert_eq!(raw.camera.interpolate_view, Some(true));
+            assert_eq!(raw.view_distance.min, Some(25.0));
+            assert_eq!(raw.view_distance.fog_margin, Some(8.0));
+            assert_eq!(raw.view_distance.max_frame_time, Some(0.02));
+            assert_eq!(raw.display.render_scale, Some(0.75));
+            assert_eq!(raw.local_simulation.rate, Some(20));
+            assert_eq!(raw.local_simulation.character.max_ground_speed, Some(5.0));
+        });
+        upgrade(include_str!("fixtures/v1.toml"), 1, |raw| {
+            assert_eq!(raw.name.as_deref(), Some("grouped"));
+            assert_eq!(raw.camera.half_life, Some(0.04));
+            assert_eq!(raw.camera.view_bobbing, Some(false));
+            assert_eq!(raw.view_distance.min, Some(35.0));
+            assert_eq!(raw.view_distance.max_frame_time, Some(0.025));
+        });
+    }
+
+    #[test]
+    fn every_problem_reported() {
+        let text = "config_version = 2\n\
+                    name = \"\"\n\
+                    chunk_load_parallelism = 0\n\
+                    keybindings = \"vim\"\n\
+                    [camera]\n\
+                    half_life = -1.0\n\
+                    view_bobbing = \"yes\"\n\
+                    [view_distance]\n\
+                    min = \"far\"\n\
+                    [display]\n\
+                    fov = 200.0\n\
+                    viewmodel_fov = 5.0\n\
+                    gamma = 1.5\n";
+        let (raw, _, report) = parse(text).unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.version, CURRENT_VERSION);
+        assert_eq!(report.unknown, ["keybindings"]);
+        let problems = report
+            .problems
+            .iter()
+            .map(|x| (x.path.as_str(), x.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                ("camera.view_bobbing", "\"yes\""),
+                ("view_distance.min", "\"far\""),
+                ("name", "\"\""),
+                ("chunk_load_parallelism", "0"),
+                ("camera.half_life", "-1"),
+                ("display.fov", "200"),
+                ("display.viewmodel_fov", "5"),
+            ]
+        );
+        // Each says what would be accepted instead
+        assert_eq!(
+            report.problems[3].to_string(),
+            "`chunk_load_parallelism` = 0: expected at least 1"
+        );
+        assert_eq!(
+            report.problems[5].to_string(),
+            "`display.fov` = 200: expected 10 to 170"
+        );
+        // All left at their defaults, while the rest is used
+        assert_eq!(raw.name, None);
+        assert_eq!(raw.chunk_load_parallelism, None);
+        assert_eq!(raw.camera.half_life, None);
+        assert_eq!(raw.camera.view_bobbing, None);
+        assert_eq!(raw.view_distance.min, None);
+        assert_eq!(raw.display.fov, None);
+        assert_eq!(raw.display.viewmodel_fov, None);
+        assert_eq!(raw.display.gamma, Some(1.5));
+        // And all listed together
+        let text = report.to_string();
+        assert_eq!(text.lines().count(), 8, "{text}");
+        assert!(text.contains("`keybindings` isn't a known setting"));
+    }
+
+    #[test]
+    fn upgrade_written_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("client.toml");
+        let original = include_str!("fixtures/v0-recent.toml");
+        fs::write(&path, original).unwrap();
+
+        let raw = read_raw(&path, true);
+        assert_eq!(raw.view_distance.min, Some(25.0));
+        // The original is kept beside the upgraded file
+        let backup = path.with_extension("v0.toml");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), original);
+        let upgraded = fs::read_to_string(&path).unwrap();
+        let (raw, document) = parse_clean(&upgraded);
+        assert_eq!(document.written_version, CURRENT_VERSION);
+        assert_eq!(raw.view_distance.min, Some(25.0));
+
+        // Current files are left alone
+        fs::remove_file(&backup).unwrap();
+        read_raw(&path, true);
+        assert_eq!(fs::read_to_string(&path).unwrap(), upgraded);
+        assert!(!backup.exists());
+    }
+}
@@ -0,0 +1,239 @@
+//! Checks that settings are within the ranges they're meaningful in, reporting all that aren't
+
+use std::fmt;
+
+use super::{migrate::CURRENT_VERSION, RawConfig};
+
+/// A setting that can't be used as written, and is left at its default instead
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// Dotted path of the setting, like `display.fov`
+    pub path: String,
+    /// The setting's value as written
+    pub value: String,
+    /// What's wrong with it, or what would be accepted instead
+    pub reason: String,
+}
+
+impl Problem {
+    pub fn new(path: &str, value: &impl fmt::Display, reason: String) -> Self {
+        Self {
+            path: path.into(),
+            value: value.to_string(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` = {}: {}", self.path, self.value, self.reason)
+    }
+}
+
+/// What was found in reading a config file, beyond the settings themselves
+#[derive(Debug, Clone, Default)]
+pub struct ConfigReport {
+    /// Schema version the file was written for
+    pub version: u32,
+    /// Dotted paths of settings this client doesn't recognize, which are kept but ignored
+    pub unknown: Vec<String>,
+    /// Settings left at their defaults because they can't be used as written
+    pub problems: Vec<Problem>,
+}
+
+impl ConfigReport {
+    /// Whether every recognized setting can be used as written
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.version < CURRENT_VERSION {
+            writeln!(
+                f,
+                "written for version {}, upgraded to version {CURRENT_VERSION} when next loaded",
+                self.version
+            )?;
+        } else if self.version > CURRENT_VERSION {
+            writeln!(
+                f,
+                "written for version {}, newer than this client's version {CURRENT_VERSION}",
+                self.version
+            )?;
+        }
+        for path in &self.unknown {
+            writeln!(f, "`{path}` isn't a known setting; it's kept, but ignored")?;
+        }
+        for problem in &self.problems {
+            writeln!(f, "{problem}")?;
+        }
+        if self.problems.is_empty() {
+            writeln!(f, "no problems found")?;
+        }
+        Ok(())
+    }
+}
+
+/// Values a numeric setting accepts
+#[derive(Debug, Copy, Clone)]
+enum Accepted {
+    AtLeast(f64),
+    Above(f64),
+    Between(f64, f64),
+}
+
+impl Accepted {
+    fn admits(self, x: f64) -> bool {
+        match self {
+            Accepted::AtLeast(min) => x >= min,
+            Accepted::Above(min) => x > min,
+            Accepted::Between(min, max) => (min..=max).contains(&x),
+        }
+    }
+}
+
+impl fmt::Display for Accepted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Accepted::AtLeast(min) => write!(f, "expected at least {min}"),
+            Accepted::Above(min) => write!(f, "expected more than {min}"),
+            Accepted::Between(min, max) => write!(f, "expected {min} to {max}"),
+        }
+    }
+}
+
+/// Report `setting` at `path` and reset it to its default unless `accepted` admits it
+fn check<T: Copy + Into<f64> + fmt::Display>(
+    problems: &mut Vec<Problem>,
+    path: &str,
+    setting: &mut Option<T>,
+    accepted: Accepted,
+) {
+    let Some(value) = *setting else {
+        return;
+    };
+    // Also catches NaN, which no range admits
+    if !accepted.admits(value.into()) {
+        problems.push(Problem::new(path, &value, accepted.to_string()));
+        *setting = None;
+    }
+}
+
+impl RawConfig {
+    /// Report every setting outside the range it's meaningful in, resetting it to its default
+    pub(super) fn validate(&mut self, problems: &mut Vec<Problem>) {
+        use Accepted::*;
+        if self.name.as_deref() == Some("") {
+            problems.push(Problem::new(
+                "name",
+                &"\"\"",
+                "expected a name that isn't empty".into(),
+            ));
+            self.name = None;
+        }
+        let p = problems;
+        check(
+            p,
+            "chunk_load_parallelism",
+            &mut self.chunk_load_parallelism,
+            AtLeast(1.0),
+        );
+        check(
+            p,
+            "upload_budget_kilobytes",
+            &mut self.upload_budget_kilobytes,
+            AtLeast(1.0),
+        );
+        check(
+            p,
+            "chunk_generation_timeout",
+            &mut self.chunk_generation_timeout,
+            Above(0.0),
+        );
+        check(
+            p,
+            "node_resync_steps",
+            &mut self.node_resync_steps,
+            AtLeast(1.0),
+        );
+        check(
+            p,
+            "minimap_distance",
+            &mut self.minimap_distance,
+            Above(0.0),
+        );
+        check(
+            p,
+            "breadcrumb_interval",
+            &mut self.breadcrumb_interval,
+            AtLeast(0.0),
+        );
+
+        let camera = &mut self.camera;
+        check(p, "camera.half_life", &mut camera.half_life, AtLeast(0.0));
+        check(
+            p,
+            "camera.rise_half_life",
+            &mut camera.rise_half_life,
+            AtLeast(0.0),
+        );
+        check(
+            p,
+            "camera.snap_distance",
+            &mut camera.snap_distance,
+            AtLeast(0.0),
+        );
+        check(
+            p,
+            "camera.follow_distance",
+            &mut camera.follow_distance,
+            AtLeast(0.0),
+        );
+
+        let view_distance = &mut self.view_distance;
+        check(p, "view_distance.min", &mut view_distance.min, Above(0.0));
+        check(
+            p,
+            "view_distance.fog_margin",
+            &mut view_distance.fog_margin,
+            AtLeast(0.0),
+        );
+        check(
+            p,
+            "view_distance.max_frame_time",
+            &mut view_distance.max_frame_time,
+            Above(0.0),
+        );
+
+        let display = &mut self.display;
+        check(p, "display.gamma", &mut display.gamma, AtLeast(0.1));
+        check(
+            p,
+            "display.brightness",
+            &mut display.brightness,
+            AtLeast(0.0),
+        );
+        check(p, "display.fov", &mut display.fov, Between(10.0, 170.0));
+        check(
+            p,
+            "display.viewmodel_fov",
+            &mut display.viewmodel_fov,
+            Between(10.0, 170.0),
+        );
+        check(
+            p,
+            "display.fov_transition",
+            &mut display.fov_transition,
+            AtLeast(0.0),
+        );
+        check(
+            p,
+            "display.render_scale",
+            &mut display.render_scale,
+            Between(0.25, 2.0),
+        );
+    }
+}
@@ -0,0 +1,431 @@
+//! Judging how far the server's updates can be trusted to keep the local character's prediction
+//! honest
+//!
+//! The server sends a `StateDelta` every step, each acknowledging the latest input it's processed.
+//! When they stop arriving, or stop acknowledging input, the prediction runs on guesses. A short
+//! gap is routine, so motion is still predicted and only edits, which the server would likely
+//! reject or lose, are held back. A longer one freezes the prediction where it stands until the
+//! server responds, rather than carrying the character ever further from wherever the server has
+//! it. Thresholds scale with the step interval and the measured round trip, so that a distant
+//! server isn't held to a nearby one's standards.
+
+use std::{cmp::Ordering, collections::VecDeque, time::Duration};
+
+use common::SimConfig;
+use tracing::{info, warn};
+
+/// Steps' worth of lateness, beyond the usual jitter, after which edits are held back
+const DEGRADED_STEPS: u32 = 3;
+/// Steps' worth of lateness, beyond a round trip and the usual jitter, after which prediction is
+/// frozen
+const STALLED_STEPS: u32 = 10;
+/// Steps between updates, beyond the usual jitter, within which an update counts as on time
+const TIMELY_STEPS: u32 = 2;
+/// Steps' worth of updates on time, at least, after which a degraded connection is trusted again
+const RECOVERY_STEPS: u32 = 10;
+/// Most inputs to remember the sending of, covering far more than any usable round trip
+const MAX_IN_FLIGHT: usize = 1024;
+
+/// How far the server's updates can be trusted, from best to worst
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConnectionQuality {
+    /// Updates arrive as expected
+    Healthy,
+    /// Updates are late. Motion is still predicted, but edits are held back.
+    Degraded,
+    /// Updates have stopped. Prediction is frozen until the server responds.
+    Stalled,
+}
+
+impl ConnectionQuality {
+    /// Whether edits to the world should be sent to the server
+    pub fn allows_edits(self) -> bool {
+        self == ConnectionQuality::Healthy
+    }
+}
+
+/// Tracks the timing of the server's updates to judge the `ConnectionQuality`
+///
+/// Time is measured by a clock advanced with each step, so that judgements don't depend on when
+/// they're made within a frame.
+pub struct QualityMonitor {
+    step_interval: Duration,
+    /// Longest lateness tolerated before the connection is judged stalled, keeping well clear of
+    /// the point at which prediction gives up on its own
+    max_stalled_after: Duration,
+    quality: ConnectionQuality,
+    now: Duration,
+    /// When the latest `StateDelta` arrived, once one has
+    last_delta: Option<Duration>,
+    /// Generations of input sent and when, oldest first, until acknowledged
+    in_flight: VecDeque<(u16, Duration)>,
+    /// Smoothed round trip time, once measured
+    srtt: Option<Duration>,
+    /// Smoothed deviation of round trip times from `srtt`
+    rttvar: Duration,
+    /// When updates started arriving on time without a break, if they have since the connection
+    /// last got worse
+    timely_since: Option<Duration>,
+}
+
+impl QualityMonitor {
+    pub fn new(cfg: &SimConfig) -> Self {
+        Self {
+            step_interval: cfg.step_interval,
+            max_stalled_after: cfg.max_prediction_latency * 3 / 4,
+            quality: ConnectionQuality::Healthy,
+            now: Duration::ZERO,
+            last_delta: None,
+            in_flight: VecDeque::new(),
+            srtt: None,
+            rttvar: Duration::ZERO,
+            timely_since: None,
+        }
+    }
+
+    /// The quality as of the latest `update`
+    pub fn get(&self) -> ConnectionQuality {
+        self.quality
+    }
+
+    /// Smoothed time between sending input and the server acknowledging it, once measured
+    pub fn round_trip(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// Advance the clock updates are timed by
+    pub fn advance(&mut self, dt: Duration) {
+        self.now += dt;
+    }
+
+    /// Note that input tagged `generation` is being sent
+    pub fn sent(&mut self, generation: u16) {
+        if self.in_flight.len() == MAX_IN_FLIGHT {
+            self.in_flight.pop_front();
+        }
+        self.in_flight.push_back((generation, self.now));
+    }
+
+    /// Note the arrival of a `StateDelta` acknowledging input up to and including `acknowledged`
+    pub fn received(&mut self, acknowledged: u16) {
+        let late = self
+            .last_delta
+            .map_or(true, |last| self.now - last > self.timely_within());
+        if late || self.timely_since.is_none() {
+            self.timely_since = Some(self.now);
+        }
+        self.last_delta = Some(self.now);
+        while let Some(&(generation, sent)) = self.in_flight.front() {
+            if precedes(acknowledged, generation) {
+                break;
+            }
+            self.in_flight.pop_front();
+            if generation == acknowledged {
+                self.sample(self.now - sent);
+            }
+        }
+    }
+
+    /// Fold a measured round trip into the smoothed estimates, as TCP does (RFC 6298)
+    fn sample(&mut self, rtt: Duration) {
+        let Some(srtt) = self.srtt else {
+            self.srtt = Some(rtt);
+            self.rttvar = rtt / 2;
+            return;
+        };
+        let deviation = if rtt > srtt { rtt - srtt } else { srtt - rtt };
+        self.rttvar = (self.rttvar * 3 + deviation) / 4;
+        self.srtt = Some((srtt * 7 + rtt) / 8);
+    }
+
+    /// Judge the connection as of now, given whether prediction has given up on its own for want
+    /// of acknowledgements
+    pub fn update(&mut self, prediction_stalled: bool) -> ConnectionQuality {
+        use ConnectionQuality::*;
+        let lateness = self.lateness();
+        if lateness > self.timely_within() {
+            self.timely_since = None;
+        }
+        let (degraded_after, stalled_after) = self.thresholds();
+        let next = if prediction_stalled || lateness > stalled_after {
+            Stalled
+        } else {
+            match self.quality {
+                Healthy if lateness > degraded_after => Degraded,
+                Healthy => Healthy,
+                // Trusted again only once updates have kept coming on time for a while
+                Degraded if self.recovered() => Healthy,
+                Degraded => Degraded,
+                // The server has responded, but may yet falter again
+                Stalled if lateness <= degraded_after => Degraded,
+                Stalled => Stalled,
+            }
+        };
+        match next.cmp(&self.quality) {
+            Ordering::Less => info!(quality = ?next, "connection improved"),
+            Ordering::Equal => {}
+            Ordering::Greater => {
+                self.timely_since = None;
+                match next {
+                    Degraded => warn!(?lateness, "server updates late; holding back edits"),
+                    _ => warn!(
+                        ?lateness,
+                        "server updates stopped; freezing motion prediction"
+                    ),
+                }
+            }
+        }
+        self.quality = next;
+        next
+    }
+
+    /// How far behind the server is: the longer of the time since its latest update, and the time
+    /// the oldest input it has yet to acknowledge has been waiting beyond a round trip
+    fn lateness(&self) -> Duration {
+        let Some(last_delta) = self.last_delta else {
+            // Nothing is expected of the server until it starts sending updates
+            return Duration::ZERO;
+        };
+        let silence = self.now - last_delta;
+        // Updates that keep coming but acknowledge no new input are no better than none, though
+        // that can't be told from an ordinary round trip until one has been measured
+        let unacknowledged = match (self.srtt, self.in_flight.front()) {
+            (Some(srtt), Some(&(_, sent))) => (self.now - sent).saturating_sub(srtt),
+            _ => Duration::ZERO,
+        };
+        silence.max(unacknowledged)
+    }
+
+    /// Lateness beyond which the connection is judged degraded, and stalled
+    fn thresholds(&self) -> (Duration, Duration) {
+        let jitter = self.rttvar * 4;
+        let stalled = (self.step_interval * STALLED_STEPS + self.srtt.unwrap_or_default() + jitter)
+            .min(self.max_stalled_after);
+        let degraded = (self.step_interval * DEGRADED_STEPS + jitter).min(stalled);
+        (degraded, stalled)
+    }
+
+    /// Longest time between updates that counts as on time
+    fn timely_within(&self) -> Duration {
+        self.step_interval * TIMELY_STEPS + self.rttvar * 4
+    }
+
+    /// Whether updates have come on time for long enough to trust a degraded connection again
+    fn recovered(&self) -> bool {
+        let needed = (self.step_interval * RECOVERY_STEPS).max(self.srtt.unwrap_or_default() * 2);
+        self.timely_since
+            .is_some_and(|since| self.now - since >= needed)
+    }
+}
+
+/// Whether generation `a` was sent before generation `b`
+fn precedes(a: u16, b: u16) -> bool {
+    (b.wrapping_sub(a) as i16) > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::SimConfigRaw;
+    use ConnectionQuality::*;
+
+    const FRAME: Duration = Duration::from_millis(10);
+    const STEP: Duration = Duration::from_millis(100);
+
+    /// What happens to the server's updates in a frame
+    #[derive(Copy, Clone)]
+    enum Link {
+        /// Arriving a round trip after the input they acknowledge
+        Up,
+        /// Lost
+        Down,
+        /// Arriving, but acknowledging no new input, as when the server stops hearing from us
+        Deaf,
+    }
+
+    /// A client stepping at 10 Hz in 10 ms frames against a server 100 ms away
+    struct Trace {
+        monitor: QualityMonitor,
+        now: Duration,
+        generation: u16,
+        acknowledged: u16,
+        /// Updates on their way, with when they'll arrive and the generation they acknowledge
+        updates: VecDeque<(Duration, u16)>,
+        /// Whether prediction is suspended, as `Sim` suspends it while stalled
+        prediction_stalled: bool,
+    }
+
+    impl Trace {
+        const RTT: Duration = Duration::from_millis(100);
+
+        fn new() -> Self {
+            let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+            assert_eq!(cfg.step_interval, STEP);
+            Self {
+                monitor: QualityMonitor::new(&cfg),
+                now: Duration::ZERO,
+                generation: 0,
+                acknowledged: 0,
+                updates: VecDeque::new(),
+                prediction_stalled: false,
+            }
+        }
+
+        fn frame(&mut self, link: Link) -> ConnectionQuality {
+            self.now += FRAME;
+            self.monitor.advance(FRAME);
+            while self.updates.front().is_some_and(|&(at, _)| at <= self.now) {
+                let (_, generation) = self.updates.pop_front().unwrap();
+                match link {
+                    Link::Up => self.acknowledged = generation,
+                    Link::Down => continue,
+                    Link::Deaf => {}
+                }
+                self.monitor.received(self.acknowledged);
+                // The server's state is adopted, resuming prediction
+                self.prediction_stalled = false;
+            }
+            let quality = self.monitor.update(self.prediction_stalled);
+            self.prediction_stalled |= quality == Stalled;
+            if self.now.as_nanos() % STEP.as_nanos() == 0 {
+                self.generation = self.generation.wrapping_add(1);
+                self.monitor.sent(self.generation);
+                self.updates
+                    .push_back((self.now + Self::RTT, self.generation));
+            }
+            quality
+        }
+
+        /// Run `frames` frames, returning the quality after each
+        fn run(&mut self, frames: usize, link: Link) -> Vec<ConnectionQuality> {
+            (0..frames).map(|_| self.frame(link)).collect()
+        }
+    }
+
+    /// Number of frames before `quality` is first reached, if it is
+    fn until(qualities: &[ConnectionQuality], quality: ConnectionQuality) -> Option<Duration> {
+        let frames = qualities.iter().position(|&x| x == quality)?;
+        Some(FRAME * frames as u32)
+    }
+
+    #[test]
+    fn round_trip_measured() {
+        let mut trace = Trace::new();
+        assert!(trace.run(300, Link::Up).iter().all(|&x| x == Healthy));
+        assert_eq!(trace.monitor.round_trip(), Some(Trace::RTT));
+        assert!(trace.monitor.rttvar < Duration::from_millis(1));
+    }
+
+    #[test]
+    fn each_transition_and_back() {
+        let mut trace = Trace::new();
+        trace.run(300, Link::Up);
+        let (degraded_after, stalled_after) = trace.monitor.thresholds();
+        assert!(degraded_after > STEP * DEGRADED_STEPS);
+        assert!(stalled_after > STEP * STALLED_STEPS + Trace::RTT);
+
+        // Updates stop; the last arrived at the end of the previous frame
+        let gap = trace.run(200, Link::Down);
+        let degraded = until(&gap, Degraded).unwrap();
+        let stalled = until(&gap, Stalled).unwrap();
+        assert!(degraded + FRAME > degraded_after && degraded <= degraded_after);
+        assert!(stalled + FRAME > stalled_after && stalled <= stalled_after);
+        assert!(gap[degraded.as_millis() as usize / 10..]
+            .iter()
+            .all(|&x| x >= Degraded));
+        assert!(gap[stalled.as_millis() as usize / 10..]
+            .iter()
+            .all(|&x| x == Stalled));
+
+        // Updates resume: at once less wary, but healthy only after a while
+        let recovery = trace.run(200, Link::Up);
+        let responded = until(&recovery, Degraded).unwrap();
+        assert!(responded <= Trace::RTT + STEP);
+        let healthy = until(&recovery, Healthy).unwrap();
+        let needed = STEP * RECOVERY_STEPS;
+        assert!(healthy - responded >= needed);
+        assert!(healthy - responded <= needed + STEP);
+        assert!(recovery[healthy.as_millis() as usize / 10..]
+            .iter()
+            .all(|&x| x == Healthy));
+    }
+
+    #[test]
+    fn brief_gaps_tolerated() {
+        let mut trace = Trace::new();
+        trace.run(300, Link::Up);
+        for _ in 0..5 {
+            // Two updates lost in a row
+            assert!(trace.run(20, Link::Down).iter().all(|&x| x == Healthy));
+            assert!(trace.run(30, Link::Up).iter().all(|&x| x == Healthy));
+        }
+    }
+
+    #[test]
+    fn recovery_restarts_when_late() {
+        let mut trace = Trace::new();
+        trace.run(300, Link::Up);
+        trace.run(60, Link::Down);
+        assert_eq!(trace.monitor.get(), Degraded);
+        let resumed = trace.run(50, Link::Up);
+        assert!(resumed.iter().all(|&x| x == Degraded));
+
+        // A gap tolerated while healthy is still too long to count towards recovery
+        assert!(trace.run(20, Link::Down).iter().all(|&x| x == Degraded));
+        let resumed = trace.run(200, Link::Up);
+        let healthy = until(&resumed, Healthy).unwrap();
+        assert!(healthy >= STEP * RECOVERY_STEPS);
+    }
+
+    #[test]
+    fn unacknowledged_input_degrades() {
+        let mut trace = Trace::new();
+        trace.run(300, Link::Up);
+        let (degraded_after, stalled_after) = trace.monitor.thresholds();
+        let deaf = trace.run(200, Link::Deaf);
+        // Judged by how long the first unacknowledged input has waited beyond a round trip, which
+        // was sent as the last acknowledgement arrived
+        let degraded = until(&deaf, Degraded).unwrap() - Trace::RTT;
+        let stalled = until(&deaf, Stalled).unwrap() - Trace::RTT;
+        assert!(degraded + FRAME > degraded_after && degraded <= degraded_after);
+        assert!(stalled + FRAME > stalled_after && stalled <= stalled_after);
+        // Updates still arriving don't thaw the prediction while they acknowledge nothing
+        assert!(deaf[(stalled + Trace::RTT).as_millis() as usize / 10..]
+            .iter()
+            .all(|&x| x == Stalled));
+    }
+
+    #[test]
+    fn stalled_prediction_overrides() {
+        let mut trace = Trace::new();
+        trace.run(100, Link::Up);
+        // Prediction gave up on its own, as when the thresholds are longer than it retains input
+        trace.monitor.advance(FRAME);
+        assert_eq!(trace.monitor.update(true), Stalled);
+        assert_eq!(trace.monitor.update(true), Stalled);
+        // Thawed once the server responds, but only as far as degraded
+        trace.monitor.received(trace.acknowledged);
+        assert_eq!(trace.monitor.update(false), Degraded);
+    }
+
+    #[test]
+    fn silent_until_first_update() {
+        let mut trace = Trace::new();
+        // Nothing is judged before the server starts sending updates
+        assert!(trace.run(500, Link::Down).iter().all(|&x| x == Healthy));
+    }
+
+    #[test]
+    fn stalled_before_prediction_gives_up() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw {
+            rate: Some(60),
+            max_prediction_latency_ms: Some(400),
+            ..Default::default()
+        });
+        let mut monitor = QualityMonitor::new(&cfg);
+        monitor.sample(Duration::from_millis(300));
+        let (degraded, stalled) = monitor.thresholds();
+        assert_eq!(stalled, Duration::from_millis(300));
+        assert!(degraded <= stalled);
+    }
+}
@@ -0,0 +1,818 @@
+//! Commands typed into the terminal the client was started from
+
+use std::{
+    fmt,
+    io::{self, BufRead},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+};
+
+use common::{
+    block_entity::SIGN_LINES,
+    node::CoordAxis,
+    region::{line_extents, plane_extents},
+    template::Orientation,
+    world::Material,
+};
+use tracing::warn;
+
+use crate::sim::PasteOrientation;
+
+/// Color of waypoints made without one
+pub const DEFAULT_WAYPOINT_COLOR: [u8; 3] = [255, 200, 60];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// List every waypoint with its distance
+    ListWaypoints,
+    /// Make a waypoint at the character's position, shared with everyone on the server if `shared`
+    AddWaypoint {
+        name: String,
+        shared: bool,
+        color: [u8; 3],
+    },
+    RemoveWaypoint {
+        name: String,
+        shared: bool,
+    },
+    /// Point the compass at a waypoint
+    SelectWaypoint {
+        name: String,
+    },
+    /// Fix how far the world is drawn, or with `None` adapt it to the frame rate
+    SetViewDistance {
+        meters: Option<u32>,
+    },
+    /// Ask the server to protect the region around the origin of the character's node, so that
+    /// only the `allowed` players may change blocks there
+    ProtectRegion {
+        name: String,
+        meters: u32,
+        allowed: Vec<String>,
+    },
+    /// Ask the server to lift the protection of a region
+    UnprotectRegion {
+        name: String,
+    },
+    /// Ask the server to undo blocks changed within the latest `seconds`, except those changed
+    /// again since unless `force` is set
+    Rollback {
+        target: RollbackTarget,
+        seconds: u32,
+        force: bool,
+    },
+    /// Record collisions over the latest `steps` steps, or stop if 0, of the local character's
+    /// predicted movement, or of the named character on the server
+    TraceCollisions {
+        steps: u32,
+        character: Option<String>,
+    },
+    /// Write the collisions recorded of the local character's predicted movement, or of the named
+    /// character on the server, to a file
+    DumpCollisionTrace {
+        path: PathBuf,
+        character: Option<String>,
+    },
+    /// Break down the memory in use by what it's for
+    ReportMemory,
+    /// Detach the camera from the character to fly it freely, or return it to the character
+    Observe,
+    /// Trail the named character with the observer's camera, facing wherever it does if `locked`
+    Follow {
+        name: String,
+        locked: bool,
+    },
+    /// Stop trailing a character, leaving the camera to fly freely
+    Unfollow,
+    /// Fill a box spanning `extents` voxels from the block the character is looking at, as
+    /// `Graph::edit_box` would, with `material`
+    Fill {
+        extents: [u32; 3],
+        material: Material,
+    },
+    /// Mark the block the character is looking at as one of the two opposite corners, 0 or 1, of
+    /// the box to copy as a template
+    MarkTemplateCorner {
+        corner: usize,
+    },
+    /// Copy the box between the marked corners as a template saved under `name`
+    CopyTemplate {
+        name: String,
+    },
+    /// Ask the server to paste the template saved under `name` in front of the block the
+    /// character is looking at
+    PasteTemplate {
+        name: String,
+        orientation: PasteOrientation,
+    },
+    /// Log what the given directives permit besides what's logged by default, or with `None` just
+    /// the default
+    SetLogFilter {
+        directives: Option<String>,
+    },
+    /// Ask the server to write `lines` on the sign the character is looking at
+    WriteSign {
+        lines: [String; SIGN_LINES],
+    },
+}
+
+/// Whose block changes `Command::Rollback` undoes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RollbackTarget {
+    /// Those of the named player
+    Player(String),
+    /// Those within `meters` of the named waypoint, or of the character if `None`
+    Region {
+        waypoint: Option<String>,
+        meters: u32,
+    },
+}
+
+/// Interpret a line of input, if it isn't blank
+///
+/// ```text
+/// waypoints
+/// waypoint add [--shared] [#rrggbb] <name>
+/// waypoint remove [--shared] <name>
+/// waypoint select <name>
+/// view-distance <meters> | auto
+/// region add <meters> [--allow <player>,...] <name>
+/// region remove <name>
+/// rollback player <name> <duration> [--force]
+/// rollback region <waypoint> | here <meters> <duration> [--force]
+/// trace <steps> | off [<character>]
+/// trace dump <path> [<character>]
+/// memory
+/// observe
+/// follow [--locked] <name>
+/// unfollow
+/// log <directive>... | default
+/// fill box <x> <y> <z> <material>
+/// fill line x | y | z <length> <material>
+/// fill plane x | y | z <a> <b> <material>
+/// template corner 1 | 2
+/// template copy <name>
+/// template paste [--turn <quarter turns> | --orientation <0-23>] <name>
+/// sign [<line>|<line>|...]
+/// ```
+///
+/// Names take up the rest of the line, or all of it between the command and its trailing
+/// arguments, so they may contain spaces. Durations are seconds, or a number followed by `s`, `m`,
+/// or `h`. Materials are named as in `Material`, in any case, and fill planes span the axes after
+/// their normal, in the order X, Y, Z, X. Templates are pasted upright unless given one of
+/// the 24 orientations of `Orientation::all` by number. Signs take up to four lines, separated
+/// by `|`, and are cleared by giving none.
+pub fn parse(line: &str) -> Result<Option<Command>, ParseError> {
+    let mut words = line.split_whitespace().peekable();
+    let Some(command) = words.next() else {
+        return Ok(None);
+    };
+    match command {
+        "waypoints" => match words.next() {
+            None => Ok(Some(Command::ListWaypoints)),
+            Some(x) => Err(ParseError::Unexpected(x.into())),
+        },
+        "waypoint" => {
+            let action = words.next().ok_or(ParseError::Usage)?;
+            let shared = words.next_if_eq(&"--shared").is_some();
+            let color = words
+                .next_if(|x| x.starts_with('#'))
+                .map(parse_color)
+                .transpose()?;
+            let name = words.collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err(ParseError::MissingName);
+            }
+            match action {
+                "add" => Ok(Some(Command::AddWaypoint {
+                    name,
+                    shared,
+                    color: color.unwrap_or(DEFAULT_WAYPOINT_COLOR),
+                })),
+                "remove" if color.is_none() => Ok(Some(Command::RemoveWaypoint { name, shared })),
+                "select" if !shared && color.is_none() => {
+                    Ok(Some(Command::SelectWaypoint { name }))
+                }
+                "remove" | "select" => Err(ParseError::Usage),
+                _ => Err(ParseError::Unexpected(action.into())),
+            }
+        }
+        "view-distance" => {
+            let meters = match words.next().ok_or(ParseError::Usage)? {
+                "auto" => None,
+                x => Some(x.parse().map_err(|_| ParseError::Unexpected(x.into()))?),
+            };
+            match words.next() {
+                None => Ok(Some(Command::SetViewDistance { meters })),
+                Some(x) => Err(ParseError::Unexpected(x.into())),
+            }
+        }
+        "region" => {
+            let action = words.next().ok_or(ParseError::Usage)?;
+            match action {
+                "add" => {
+                    let meters = words.next().ok_or(ParseError::Usage)?;
+                    let meters = meters
+                        .parse()
+                        .map_err(|_| ParseError::Unexpected(meters.into()))?;
+                    let allowed = match words.next_if_eq(&"--allow") {
+                        Some(_) => words
+                            .next()
+                            .ok_or(ParseError::Usage)?
+                            .split(',')
+                            .filter(|x| !x.is_empty())
+                            .map(String::from)
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    let name = words.collect::<Vec<_>>().join(" ");
+                    if name.is_empty() {
+                        return Err(ParseError::MissingName);
+                    }
+                    Ok(Some(Command::ProtectRegion {
+                        name,
+                        meters,
+                        allowed,
+                    }))
+                }
+                "remove" => {
+                    let name = words.collect::<Vec<_>>().join(" ");
+                    if name.is_empty() {
+                        return Err(ParseError::MissingName);
+                    }
+                    Ok(Some(Command::UnprotectRegion { name }))
+                }
+                _ => Err(ParseError::Unexpected(action.into())),
+            }
+        }
+        "rollback" => {
+            let action = words.next().ok_or(ParseError::Usage)?;
+            let mut rest = words.collect::<Vec<_>>();
+            let force = rest.last() == Some(&"--force");
+            if force {
+                rest.pop();
+            }
+            let seconds = parse_duration(rest.pop().ok_or(ParseError::Usage)?)?;
+            let target = match action {
+                "player" if rest.is_empty() => return Err(ParseError::MissingName),
+                "player" => RollbackTarget::Player(rest.join(" ")),
+                "region" => {
+                    let meters = rest.pop().ok_or(ParseError::Usage)?;
+                    let meters = meters
+                        .parse()
+                        .map_err(|_| ParseError::Unexpected(meters.into()))?;
+                    let waypoint = match &*rest {
+                        [] => return Err(ParseError::MissingName),
+                        ["here"] => None,
+                        _ => Some(rest.join(" ")),
+                    };
+                    RollbackTarget::Region { waypoint, meters }
+                }
+                _ => return Err(ParseError::Unexpected(action.into())),
+            };
+            Ok(Some(Command::Rollback {
+                target,
+                seconds,
+                force,
+            }))
+        }
+        "trace" => {
+            let action = words.next().ok_or(ParseError::Usage)?;
+            let path = match action {
+                "dump" => Some(PathBuf::from(words.next().ok_or(ParseError::Usage)?)),
+                _ => None,
+            };
+            let character = words.collect::<Vec<_>>().join(" ");
+            let character = (!character.is_empty()).then_some(character);
+            match path {
+                Some(path) => Ok(Some(Command::DumpCollisionTrace { path, character })),
+                None => {
+                    let steps = match action {
+                        "off" => 0,
+                        x => x.parse().map_err(|_| ParseError::Unexpected(x.into()))?,
+                    };
+                    Ok(Some(Command::TraceCollisions { steps, character }))
+                }
+            }
+        }
+        "memory" => match words.next() {
+            None => Ok(Some(Command::ReportMemory)),
+            Some(x) => Err(ParseError::Unexpected(x.into())),
+        },
+        "observe" => match words.next() {
+            None => Ok(Some(Command::Observe)),
+            Some(x) => Err(ParseError::Unexpected(x.into())),
+        },
+        "follow" => {
+            let locked = words.next_if_eq(&"--locked").is_some();
+            let name = words.collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err(ParseError::MissingName);
+            }
+            Ok(Some(Command::Follow { name, locked }))
+        }
+        "unfollow" => match words.next() {
+            None => Ok(Some(Command::Unfollow)),
+            Some(x) => Err(ParseError::Unexpected(x.into())),
+        },
+        "log" => {
+            let directives = words.collect::<Vec<_>>().join(",");
+            match &*directives {
+                "" => Err(ParseError::Usage),
+                "default" => Ok(Some(Command::SetLogFilter { directives: None })),
+                _ => Ok(Some(Command::SetLogFilter {
+                    directives: Some(directives),
+                })),
+            }
+        }
+        "fill" => {
+            let shape = words.next().ok_or(ParseError::Usage)?;
+            let mut rest = words.collect::<Vec<_>>();
+            let material = parse_material(rest.pop().ok_or(ParseError::Usage)?)?;
+            let extents = match (shape, &*rest) {
+                ("box", &[x, y, z]) => [parse_extent(x)?, parse_extent(y)?, parse_extent(z)?],
+                ("line", &[axis, length]) => line_extents(parse_axis(axis)?, parse_extent(length)?),
+                ("plane", &[normal, a, b]) => {
+                    plane_extents(parse_axis(normal)?, [parse_extent(a)?, parse_extent(b)?])
+                }
+                ("box" | "line" | "plane", _) => return Err(ParseError::Usage),
+                _ => return Err(ParseError::Unexpected(shape.into())),
+            };
+            Ok(Some(Command::Fill { extents, material }))
+        }
+        "template" => {
+            let action = words.next().ok_or(ParseError::Usage)?;
+            match action {
+                "corner" => {
+                    let corner = match words.next().ok_or(ParseError::Usage)? {
+                        "1" => 0,
+                        "2" => 1,
+                        x => return Err(ParseError::Unexpected(x.into())),
+                    };
+                    match words.next() {
+                        None => Ok(Some(Command::MarkTemplateCorner { corner })),
+                        Some(x) => Err(ParseError::Unexpected(x.into())),
+                    }
+                }
+                "copy" | "paste" => {
+                    let orientation = match (action, words.next_if(|x| x.starts_with("--"))) {
+                        (_, None) => PasteOrientation::Upright { quarter_turns: 0 },
+                        ("paste", Some("--turn")) => {
+                            let x = words.next().ok_or(ParseError::Usage)?;
+                            let quarter_turns =
+                                x.parse().map_err(|_| ParseError::Unexpected(x.into()))?;
+                            PasteOrientation::Upright { quarter_turns }
+                        }
+                        ("paste", Some("--orientation")) => {
+                            let x = words.next().ok_or(ParseError::Usage)?;
+                            let orientation = x
+                                .parse::<u8>()
+                                .ok()
+                                .and_then(|n| Orientation::try_from(n).ok())
+                                .ok_or_else(|| ParseError::Unexpected(x.into()))?;
+                            PasteOrientation::Exact(orientation)
+                        }
+                        (_, Some(x)) => return Err(ParseError::Unexpected(x.into())),
+                    };
+                    let name = words.collect::<Vec<_>>().join(" ");
+                    if name.is_empty() {
+                        return Err(ParseError::MissingName);
+                    }
+                    if action == "copy" {
+                        return Ok(Some(Command::CopyTemplate { name }));
+                    }
+                    Ok(Some(Command::PasteTemplate { name, orientation }))
+                }
+                _ => Err(ParseError::Unexpected(action.into())),
+            }
+        }
+        "sign" => {
+            let text = words.collect::<Vec<_>>().join(" ");
+            let mut lines: [String; SIGN_LINES] = Default::default();
+            if !text.is_empty() {
+                let mut given = text.split('|').map(str::trim);
+                for (line, text) in lines.iter_mut().zip(&mut given) {
+                    *line = text.into();
+                }
+                if given.next().is_some() {
+                    return Err(ParseError::Usage);
+                }
+            }
+            Ok(Some(Command::WriteSign { lines }))
+        }
+        _ => Err(ParseError::Unexpected(command.into())),
+    }
+}
+
+fn parse_material(x: &str) -> Result<Material, ParseError> {
+    Material::VALUES
+        .into_iter()
+        .find(|material| format!("{material:?}").eq_ignore_ascii_case(x))
+        .ok_or_else(|| ParseError::BadMaterial(x.into()))
+}
+
+fn parse_axis(x: &str) -> Result<CoordAxis, ParseError> {
+    match x {
+        "x" | "X" => Ok(CoordAxis::X),
+        "y" | "Y" => Ok(CoordAxis::Y),
+        "z" | "Z" => Ok(CoordAxis::Z),
+        _ => Err(ParseError::Unexpected(x.into())),
+    }
+}
+
+/// A number of voxels along an axis, which must be at least one
+fn parse_extent(x: &str) -> Result<u32, ParseError> {
+    x.parse()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| ParseError::Unexpected(x.into()))
+}
+
+fn parse_duration(x: &str) -> Result<u32, ParseError> {
+    let (digits, scale) = match x.as_bytes().last() {
+        Some(b's') => (&x[..x.len() - 1], 1),
+        Some(b'm') => (&x[..x.len() - 1], 60),
+        Some(b'h') => (&x[..x.len() - 1], 3600),
+        _ => (x, 1),
+    };
+    digits
+        .parse::<u32>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(|| ParseError::BadDuration(x.into()))
+}
+
+fn parse_color(x: &str) -> Result<[u8; 3], ParseError> {
+    let digits = &x[1..];
+    if digits.len() != 6 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseError::BadColor(x.into()));
+    }
+    let value = u32::from_str_radix(digits, 16).unwrap();
+    let [_, r, g, b] = value.to_be_bytes();
+    Ok([r, g, b])
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Unexpected(String),
+    MissingName,
+    BadColor(String),
+    BadDuration(String),
+    BadMaterial(String),
+    Usage,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ParseError::Unexpected(ref x) => write!(f, "unexpected {x:?}"),
+            ParseError::MissingName => f.pad("missing name"),
+            ParseError::BadColor(ref x) => write!(f, "{x:?} is not a color like #ff8800"),
+            ParseError::BadDuration(ref x) => write!(f, "{x:?} is not a duration like 10m"),
+            ParseError::BadMaterial(ref x) => write!(f, "{x:?} is not a material like granite"),
+            ParseError::Usage => f.pad(
+                "usage: waypoints | waypoint add [--shared] [#rrggbb] <name> \
+                 | waypoint remove [--shared] <name> | waypoint select <name> \
+                 | view-distance <meters> | auto \
+                 | region add <meters> [--allow <player>,...] <name> | region remove <name> \
+                 | rollback player <name> <duration> [--force] \
+                 | rollback region <waypoint> | here <meters> <duration> [--force] \
+                 | trace <steps> | off [<character>] | trace dump <path> [<character>] \
+                 | memory | observe | follow [--locked] <name> | unfollow \
+                 | log <directive>... | default \
+                 | fill box <x> <y> <z> <material> | fill line x|y|z <length> <material> \
+                 | fill plane x|y|z <a> <b> <material> | template corner 1|2 \
+                 | template copy <name> \
+                 | template paste [--turn <quarter turns> | --orientation <0-23>] <name> \
+                 | sign [<line>|<line>|...]",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Lines read from standard input in the background
+pub struct Console {
+    recv: mpsc::Receiver<String>,
+}
+
+impl Console {
+    pub fn spawn() -> Self {
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if send.send(line).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("stopped reading commands: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+        Self { recv }
+    }
+
+    /// The next command entered, if any, logging any that can't be understood
+    pub fn poll(&self) -> Option<Command> {
+        while let Ok(line) = self.recv.try_recv() {
+            match parse(&line) {
+                Ok(Some(command)) => return Some(command),
+                Ok(None) => {}
+                Err(e) => warn!("{}", e),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse("  "), Ok(None));
+        assert_eq!(parse("waypoints"), Ok(Some(Command::ListWaypoints)));
+        assert_eq!(
+            parse("waypoint add big cave"),
+            Ok(Some(Command::AddWaypoint {
+                name: "big cave".into(),
+                shared: false,
+                color: DEFAULT_WAYPOINT_COLOR,
+            }))
+        );
+        assert_eq!(
+            parse("waypoint add --shared #ff8000 spawn"),
+            Ok(Some(Command::AddWaypoint {
+                name: "spawn".into(),
+                shared: true,
+                color: [255, 128, 0],
+            }))
+        );
+        assert_eq!(
+            parse("waypoint remove --shared spawn"),
+            Ok(Some(Command::RemoveWaypoint {
+                name: "spawn".into(),
+                shared: true,
+            }))
+        );
+        assert_eq!(
+            parse("waypoint select big  cave"),
+            Ok(Some(Command::SelectWaypoint {
+                name: "big cave".into()
+            }))
+        );
+        assert_eq!(parse("waypoint add"), Err(ParseError::MissingName));
+        assert_eq!(
+            parse("waypoint add #ff80 x"),
+            Err(ParseError::BadColor("#ff80".into()))
+        );
+        assert_eq!(parse("waypoint select --shared x"), Err(ParseError::Usage));
+        assert_eq!(
+            parse("view-distance 60"),
+            Ok(Some(Command::SetViewDistance { meters: Some(60) }))
+        );
+        assert_eq!(
+            parse("view-distance auto"),
+            Ok(Some(Command::SetViewDistance { meters: None }))
+        );
+        assert_eq!(parse("view-distance"), Err(ParseError::Usage));
+        assert_eq!(
+            parse("view-distance far"),
+            Err(ParseError::Unexpected("far".into()))
+        );
+        assert_eq!(
+            parse("region add 40 --allow alice,bob town square"),
+            Ok(Some(Command::ProtectRegion {
+                name: "town square".into(),
+                meters: 40,
+                allowed: vec!["alice".into(), "bob".into()],
+            }))
+        );
+        assert_eq!(
+            parse("region add 40 spawn"),
+            Ok(Some(Command::ProtectRegion {
+                name: "spawn".into(),
+                meters: 40,
+                allowed: Vec::new(),
+            }))
+        );
+        assert_eq!(parse("region add 40"), Err(ParseError::MissingName));
+        assert_eq!(
+            parse("region add wide spawn"),
+            Err(ParseError::Unexpected("wide".into()))
+        );
+        assert_eq!(
+            parse("region remove spawn"),
+            Ok(Some(Command::UnprotectRegion {
+                name: "spawn".into()
+            }))
+        );
+        assert_eq!(
+            parse("rollback player Ada Lovelace 10m"),
+            Ok(Some(Command::Rollback {
+                target: RollbackTarget::Player("Ada Lovelace".into()),
+                seconds: 600,
+                force: false,
+            }))
+        );
+        assert_eq!(
+            parse("rollback region here 20 90 --force"),
+            Ok(Some(Command::Rollback {
+                target: RollbackTarget::Region {
+                    waypoint: None,
+                    meters: 20,
+                },
+                seconds: 90,
+                force: true,
+            }))
+        );
+        assert_eq!(
+            parse("rollback region big cave 20 2h"),
+            Ok(Some(Command::Rollback {
+                target: RollbackTarget::Region {
+                    waypoint: Some("big cave".into()),
+                    meters: 20,
+                },
+                seconds: 7200,
+                force: false,
+            }))
+        );
+        assert_eq!(parse("rollback player 10m"), Err(ParseError::MissingName));
+        assert_eq!(
+            parse("rollback player ada soon"),
+            Err(ParseError::BadDuration("soon".into()))
+        );
+        assert_eq!(parse("rollback region 10m"), Err(ParseError::Usage));
+        assert_eq!(
+            parse("trace 200"),
+            Ok(Some(Command::TraceCollisions {
+                steps: 200,
+                character: None,
+            }))
+        );
+        assert_eq!(
+            parse("trace off Ada Lovelace"),
+            Ok(Some(Command::TraceCollisions {
+                steps: 0,
+                character: Some("Ada Lovelace".into()),
+            }))
+        );
+        assert_eq!(
+            parse("trace dump /tmp/trace.json ada"),
+            Ok(Some(Command::DumpCollisionTrace {
+                path: "/tmp/trace.json".into(),
+                character: Some("ada".into()),
+            }))
+        );
+        assert_eq!(parse("trace dump"), Err(ParseError::Usage));
+        assert_eq!(
+            parse("trace lots"),
+            Err(ParseError::Unexpected("lots".into()))
+        );
+        assert_eq!(parse("memory"), Ok(Some(Command::ReportMemory)));
+        assert_eq!(
+            parse("memory gpu"),
+            Err(ParseError::Unexpected("gpu".into()))
+        );
+        assert_eq!(parse("observe"), Ok(Some(Command::Observe)));
+        assert_eq!(
+            parse("follow Big Bob"),
+            Ok(Some(Command::Follow {
+                name: "Big Bob".into(),
+                locked: false,
+            }))
+        );
+        assert_eq!(
+            parse("follow --locked bob"),
+            Ok(Some(Command::Follow {
+                name: "bob".into(),
+                locked: true,
+            }))
+        );
+        assert_eq!(parse("follow --locked"), Err(ParseError::MissingName));
+        assert_eq!(parse("unfollow"), Ok(Some(Command::Unfollow)));
+        assert_eq!(
+            parse("unfollow bob"),
+            Err(ParseError::Unexpected("bob".into()))
+        );
+        assert_eq!(
+            parse("log server=debug client::sim=trace"),
+            Ok(Some(Command::SetLogFilter {
+                directives: Some("server=debug,client::sim=trace".into()),
+            }))
+        );
+        assert_eq!(
+            parse("log default"),
+            Ok(Some(Command::SetLogFilter { directives: None }))
+        );
+        assert_eq!(parse("log"), Err(ParseError::Usage));
+        assert_eq!(
+            parse("fill box 5 3 5 granite"),
+            Ok(Some(Command::Fill {
+                extents: [5, 3, 5],
+                material: Material::Granite,
+            }))
+        );
+        assert_eq!(
+            parse("fill line y 8 WhiteBrick"),
+            Ok(Some(Command::Fill {
+                extents: [1, 8, 1],
+                material: Material::WhiteBrick,
+            }))
+        );
+        assert_eq!(
+            parse("fill plane y 4 6 void"),
+            Ok(Some(Command::Fill {
+                extents: [6, 1, 4],
+                material: Material::Void,
+            }))
+        );
+        assert_eq!(parse("fill box 5 3 granite"), Err(ParseError::Usage));
+        assert_eq!(
+            parse("fill box 5 0 5 granite"),
+            Err(ParseError::Unexpected("0".into()))
+        );
+        assert_eq!(
+            parse("fill line w 8 dirt"),
+            Err(ParseError::Unexpected("w".into()))
+        );
+        assert_eq!(
+            parse("fill box 5 3 5 stone"),
+            Err(ParseError::BadMaterial("stone".into()))
+        );
+        assert_eq!(
+            parse("template corner 2"),
+            Ok(Some(Command::MarkTemplateCorner { corner: 1 }))
+        );
+        assert_eq!(
+            parse("template corner 3"),
+            Err(ParseError::Unexpected("3".into()))
+        );
+        assert_eq!(
+            parse("template copy tiny hut"),
+            Ok(Some(Command::CopyTemplate {
+                name: "tiny hut".into()
+            }))
+        );
+        assert_eq!(
+            parse("template paste tiny hut"),
+            Ok(Some(Command::PasteTemplate {
+                name: "tiny hut".into(),
+                orientation: PasteOrientation::Upright { quarter_turns: 0 },
+            }))
+        );
+        assert_eq!(
+            parse("template paste --turn 3 hut"),
+            Ok(Some(Command::PasteTemplate {
+                name: "hut".into(),
+                orientation: PasteOrientation::Upright { quarter_turns: 3 },
+            }))
+        );
+        assert_eq!(
+            parse("template paste --orientation 5 hut"),
+            Ok(Some(Command::PasteTemplate {
+                name: "hut".into(),
+                orientation: PasteOrientation::Exact(Orientation::all().nth(5).unwrap()),
+            }))
+        );
+        assert_eq!(
+            parse("template paste --orientation 24 hut"),
+            Err(ParseError::Unexpected("24".into()))
+        );
+        assert_eq!(
+            parse("template copy --turn 1 hut"),
+            Err(ParseError::Unexpected("--turn".into()))
+        );
+        assert_eq!(parse("template paste"), Err(ParseError::MissingName));
+        assert_eq!(
+            parse("sign  North |  to the  sea"),
+            Ok(Some(Command::WriteSign {
+                lines: [
+                    "North".into(),
+                    "to the sea".into(),
+                    String::new(),
+                    String::new()
+                ],
+            }))
+        );
+        assert_eq!(
+            parse("sign"),
+            Ok(Some(Command::WriteSign {
+                lines: Default::default(),
+            }))
+        );
+        assert_eq!(parse("sign a|b|c|d|e"), Err(ParseError::Usage));
+        assert_eq!(
+            parse("teleport"),
+            Err(ParseError::Unexpected("teleport".into()))
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Updates heard of before the nodes they refer to
+//!
+//! Entities, nodes, and edits to the world travel on separate streams, so an entity can be spawned
+//! in a node, or a block changed in one, before the client has heard of the node. Such updates
+//! wait here, and are applied in the order they arrived as soon as the node is added to the graph.
+
+use fxhash::FxHashMap;
+
+use common::{
+    graph::{Graph, NodeId},
+    node::ChunkId,
+    proto::{BlockFill, BlockUpdate, ChunkDiff, Component, SerializableVoxelData},
+    EntityId, Step,
+};
+
+/// Steps an edit waits for its node before being forgotten, for clients sent the graph by region
+///
+/// Such clients only hear of the nodes near them, and a region carries every change made to its
+/// chunks when it's sent, so edits to nodes that never arrive needn't be kept.
+pub const EDIT_EXPIRY_STEPS: Step = 100;
+
+/// An update waiting for the node it refers to
+#[derive(Debug)]
+pub enum DeferredUpdate {
+    Spawn(EntityId, Vec<Component>),
+    /// Made at the request of the given character
+    BlockUpdate(EntityId, BlockUpdate),
+    /// Made at the request of the given character
+    BlockFill(EntityId, BlockFill),
+    ChunkDiff(ChunkDiff),
+    ModifiedChunk(ChunkId, SerializableVoxelData),
+}
+
+/// Updates waiting for nodes to be added to the graph
+#[derive(Default)]
+pub struct DeferredUpdates {
+    /// Updates waiting on each node, oldest first, with the steps they were sent as of
+    waiting: FxHashMap<NodeId, Vec<(Step, DeferredUpdate)>>,
+}
+
+impl DeferredUpdates {
+    /// Hold `update`, sent as of `step`, until `node` is added to the graph
+    pub fn defer(&mut self, node: NodeId, step: Step, update: DeferredUpdate) {
+        self.waiting.entry(node).or_default().push((step, update));
+    }
+
+    /// Take the updates waiting on nodes `graph` now has, oldest first for each node
+    pub fn take_ready(&mut self, graph: &Graph) -> Vec<(Step, DeferredUpdate)> {
+        if self.waiting.is_empty() {
+            return Vec::new();
+        }
+        let ready = self
+            .waiting
+            .keys()
+            .copied()
+            .filter(|&node| graph.contains(node))
+            .collect::<Vec<_>>();
+        ready
+            .into_iter()
+            .flat_map(|node| self.waiting.remove(&node).unwrap())
+            .collect()
+    }
+
+    /// Forget the spawn of `id` if it's waiting, as when it's despawned first, returning whether
+    /// it was
+    pub fn cancel_spawn(&mut self, id: EntityId) -> bool {
+        let mut found = false;
+        for updates in self.waiting.values_mut() {
+            updates.retain(|(_, update)| {
+                let matches = matches!(*update, DeferredUpdate::Spawn(x, _) if x == id);
+                found |= matches;
+                !matches
+            });
+        }
+        self.waiting.retain(|_, updates| !updates.is_empty());
+        found
+    }
+
+    /// Forget edits that have waited more than `timeout` steps as of `step`
+    pub fn expire_edits(&mut self, step: Step, timeout: Step) {
+        for updates in self.waiting.values_mut() {
+            updates.retain(|&(sent, ref update)| {
+                matches!(*update, DeferredUpdate::Spawn(..)) || step.wrapping_sub(sent) <= timeout
+            });
+        }
+        self.waiting.retain(|_, updates| !updates.is_empty());
+    }
+
+    /// Number of updates waiting
+    pub fn len(&self) -> usize {
+        self.waiting.values().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        dodeca::{Side, Vertex},
+        proto::Position,
+    };
+
+    fn spawn(id: u64) -> DeferredUpdate {
+        DeferredUpdate::Spawn(
+            EntityId::from_bits(id),
+            vec![Component::Position(Position::origin())],
+        )
+    }
+
+    fn diff(node: NodeId) -> DeferredUpdate {
+        DeferredUpdate::ChunkDiff(ChunkDiff {
+            chunk: ChunkId::new(node, Vertex::A),
+            changes: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn released_in_order_when_node_arrives() {
+        let mut graph = Graph::new(12);
+        let node = graph.ensure_neighbor(NodeId::ROOT, Side::A);
+        let far = graph.ensure_neighbor(node, Side::B);
+        let mut deferred = DeferredUpdates::default();
+        let mut client = Graph::new(12);
+        deferred.defer(node, 1, spawn(1));
+        deferred.defer(far, 1, spawn(2));
+        deferred.defer(node, 2, diff(node));
+        assert!(deferred.take_ready(&client).is_empty());
+        assert_eq!(deferred.len(), 3);
+
+        client.ensure_neighbor(NodeId::ROOT, Side::A);
+        let ready = deferred.take_ready(&client);
+        assert!(matches!(
+            ready[..],
+            [
+                (1, DeferredUpdate::Spawn(..)),
+                (2, DeferredUpdate::ChunkDiff(_))
+            ]
+        ));
+        assert_eq!(deferred.len(), 1);
+    }
+
+    #[test]
+    fn despawn_cancels_waiting_spawn() {
+        let node = Graph::new(12).ensure_neighbor(NodeId::ROOT, Side::A);
+        let mut deferred = DeferredUpdates::default();
+        deferred.defer(node, 1, spawn(1));
+        deferred.defer(node, 1, spawn(2));
+        assert!(deferred.cancel_spawn(EntityId::from_bits(1)));
+        assert!(!deferred.cancel_spawn(EntityId::from_bits(1)));
+        assert_eq!(deferred.len(), 1);
+    }
+
+    #[test]
+    fn only_edits_expire() {
+        let node = Graph::new(12).ensure_neighbor(NodeId::ROOT, Side::A);
+        let mut deferred = DeferredUpdates::default();
+        deferred.defer(node, 10, spawn(1));
+        deferred.defer(node, 10, diff(node));
+        deferred.expire_edits(15, 5);
+        assert_eq!(deferred.len(), 2);
+        deferred.expire_edits(16, 5);
+        assert_eq!(deferred.len(), 1);
+    }
+}
@@ -0,0 +1,456 @@
+//! Short-lived visual effects attached to voxel faces
+
+use std::time::{Duration, Instant};
+
+use common::{
+    graph::Graph,
+    graph_ray_casting::GraphCastHit,
+    math,
+    node::{ChunkId, ChunkLayout, CoordAxis, CoordDirection, Coords},
+    world::Material,
+};
+
+/// Maximum number of simultaneously active effects. The oldest effect is dropped to make room for
+/// a new one.
+const MAX_EFFECTS: usize = 32;
+/// How long cracks linger after their face stops being targeted
+const CRACK_LIFETIME: Duration = Duration::from_millis(100);
+const BURST_LIFETIME: Duration = Duration::from_millis(600);
+const OBSTRUCTED_LIFETIME: Duration = Duration::from_millis(250);
+
+/// A frame at the center of a voxel face, fixed relative to the node containing it
+#[derive(Debug, Clone)]
+pub struct FaceAnchor {
+    pub chunk: ChunkId,
+    pub coords: Coords,
+    pub face_axis: CoordAxis,
+    pub face_direction: CoordDirection,
+    /// Transform from the anchor's frame to the space of `chunk.node`
+    ///
+    /// The anchor's origin is the center of the face and its +Z axis is the outward face normal,
+    /// so its X and Y axes span the face.
+    pub transform: na::Matrix4<f32>,
+    /// Distance from the center of the face to the midpoints of its edges, in Beltrami-Klein
+    /// coordinates of the anchor's frame
+    pub half_extent: f32,
+}
+
+impl FaceAnchor {
+    pub fn new(
+        layout: &ChunkLayout,
+        chunk: ChunkId,
+        coords: Coords,
+        face_axis: CoordAxis,
+        face_direction: CoordDirection,
+    ) -> Self {
+        let dual_to_grid = f64::from(layout.dual_to_grid_factor());
+        // Homogeneous dual coordinates of a point given in grid coordinates
+        let dual = |grid: na::Vector3<f64>| (grid / dual_to_grid).push(1.0);
+
+        let mut grid = na::Vector3::from(coords.0.map(|x| f64::from(x) + 0.5));
+        grid[face_axis as usize] += match face_direction {
+            CoordDirection::Plus => 0.5,
+            CoordDirection::Minus => -0.5,
+        };
+        let center = math::lorentz_normalize(&dual(grid));
+        // The face lies in the plane where the face axis's dual coordinate is `k`, which is
+        // orthogonal to this vector
+        let k = grid[face_axis as usize] / dual_to_grid;
+        let mut normal = na::Vector4::zeros();
+        normal[face_axis as usize] = 1.0;
+        normal.w = k;
+        let normal = math::lorentz_normalize(&normal) * f64::from(face_direction as i8);
+
+        // Complete an orthonormal frame with the face's tangent directions
+        let [u_axis, v_axis] = face_axis.other_axes();
+        let mut tangents = [na::Vector4::zeros(); 2];
+        for (i, axis) in [u_axis, v_axis].into_iter().enumerate() {
+            let mut t = na::Vector4::zeros();
+            t[axis as usize] = 1.0;
+            t += center * math::mip(&t, &center);
+            for basis in std::iter::once(normal).chain(tangents[..i].iter().copied()) {
+                t -= basis * math::mip(&t, &basis);
+            }
+            tangents[i] = math::lorentz_normalize(&t);
+        }
+        let frame = na::Matrix4::from_columns(&[tangents[0], tangents[1], normal, center]);
+
+        let mut edge = grid;
+        edge[u_axis as usize] += 0.5;
+        let half_extent = math::distance(&center, &dual(edge)).tanh();
+
+        Self {
+            chunk,
+            coords,
+            face_axis,
+            face_direction,
+            transform: (chunk.vertex.dual_to_node_f64() * frame).cast(),
+            half_extent: half_extent as f32,
+        }
+    }
+
+    /// Anchor on the face struck by a ray
+    pub fn from_hit(layout: &ChunkLayout, hit: &GraphCastHit) -> Self {
+        Self::new(
+            layout,
+            hit.chunk,
+            hit.voxel_coords,
+            hit.face_axis,
+            hit.face_direction,
+        )
+    }
+
+    fn same_face(&self, hit: &GraphCastHit) -> bool {
+        self.chunk == hit.chunk
+            && self.coords == hit.voxel_coords
+            && self.face_axis == hit.face_axis
+            && self.face_direction == hit.face_direction
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EffectKind {
+    /// A decal on the face of a block targeted for breaking
+    Crack,
+    /// Particles thrown off the face of a broken block
+    Burst,
+    /// A tint over the face a block couldn't be placed against, for a character was in the way
+    Obstructed,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnchoredEffect {
+    pub anchor: FaceAnchor,
+    pub kind: EffectKind,
+    started: Instant,
+    lifetime: Duration,
+    /// Material the anchoring voxel must retain for the effect to remain meaningful
+    required_material: Option<Material>,
+    /// Distinguishes the random variations of otherwise identical effects
+    pub seed: f32,
+}
+
+impl AnchoredEffect {
+    /// Fraction of the effect's lifetime that has elapsed at `now`
+    pub fn progress(&self, now: Instant) -> f32 {
+        now.saturating_duration_since(self.started).as_secs_f32() / self.lifetime.as_secs_f32()
+    }
+
+    fn is_live(&self, graph: &Graph, now: Instant) -> bool {
+        now.saturating_duration_since(self.started) < self.lifetime
+            && self.required_material.map_or(true, |material| {
+                graph.get_block(self.anchor.chunk, self.anchor.coords) == Some(material)
+            })
+    }
+}
+
+/// The set of effects currently being displayed
+#[derive(Default)]
+pub struct EffectPool {
+    effects: Vec<AnchoredEffect>,
+    spawned: u32,
+}
+
+impl EffectPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Show cracks on `target`, the face of the block that would be broken, if any
+    pub fn set_target(&mut self, graph: &Graph, target: Option<&GraphCastHit>, now: Instant) {
+        let Some(target) = target else {
+            return;
+        };
+        if let Some(existing) = self
+            .effects
+            .iter_mut()
+            .find(|x| x.kind == EffectKind::Crack && x.anchor.same_face(target))
+        {
+            existing.started = now;
+            return;
+        }
+        let Some(material) = graph.get_block(target.chunk, target.voxel_coords) else {
+            return;
+        };
+        self.spawn(AnchoredEffect {
+            anchor: FaceAnchor::from_hit(graph.layout(), target),
+            kind: EffectKind::Crack,
+            started: now,
+            lifetime: CRACK_LIFETIME,
+            required_material: Some(material),
+            seed: 0.0,
+        });
+    }
+
+    /// Throw particles off the face of a block that was just broken
+    pub fn burst(&mut self, graph: &Graph, hit: &GraphCastHit, now: Instant) {
+        // Cracks on a broken block are moot
+        self.effects
+            .retain(|x| x.kind != EffectKind::Crack || !x.anchor.same_face(hit));
+        self.spawn(AnchoredEffect {
+            anchor: FaceAnchor::from_hit(graph.layout(), hit),
+            kind: EffectKind::Burst,
+            started: now,
+            lifetime: BURST_LIFETIME,
+            // The block is expected to vanish once the server confirms the break
+            required_material: None,
+            seed: 0.0,
+        });
+    }
+
+    /// Tint the face a block was to be placed against, to show that placing it was refused
+    pub fn obstructed(&mut self, graph: &Graph, hit: &GraphCastHit, now: Instant) {
+        if let Some(existing) = self
+            .effects
+            .iter_mut()
+            .find(|x| x.kind == EffectKind::Obstructed && x.anchor.same_face(hit))
+        {
+            existing.started = now;
+            return;
+        }
+        self.spawn(AnchoredEffect {
+            anchor: FaceAnchor::from_hit(graph.layout(), hit),
+            kind: EffectKind::Obstructed,
+            started: now,
+            lifetime: OBSTRUCTED_LIFETIME,
+            required_material: graph.get_block(hit.chunk, hit.voxel_coords),
+            seed: 0.0,
+        });
+    }
+
+    /// Drop effects that have expired or whose voxel has changed
+    pub fn update(&mut self, graph: &Graph, now: Instant) {
+        self.effects.retain(|x| x.is_live(graph, now));
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &AnchoredEffect> {
+        self.effects.iter()
+    }
+
+    fn spawn(&mut self, mut effect: AnchoredEffect) {
+        if self.effects.len() == MAX_EFFECTS {
+            let oldest = self
+                .effects
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, x)| x.started)
+                .map(|(i, _)| i)
+                .unwrap();
+            self.effects.swap_remove(oldest);
+        }
+        // Golden ratio spacing keeps consecutive seeds well apart
+        effect.seed = (self.spawned as f32 * 0.618_034).fract();
+        self.spawned = self.spawned.wrapping_add(1);
+        self.effects.push(effect);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+    use common::{
+        dodeca::{self, Vertex},
+        graph::NodeId,
+        node::{populate_fresh_nodes, BlockUpdateOutcome, VoxelData},
+        proto::{BlockUpdate, Position},
+        traversal::ensure_nearby,
+        world::Shape,
+    };
+
+    const DIMENSION: u8 = 4;
+
+    /// Point in node space at the given euclidean grid coordinates of `chunk`
+    fn grid_to_node(chunk: ChunkId, grid: na::Vector3<f64>) -> na::Vector4<f64> {
+        math::lorentz_normalize(
+            &(chunk.vertex.chunk_to_node_f64() * (grid / f64::from(DIMENSION)).push(1.0)),
+        )
+    }
+
+    /// Grid coordinates of a point in node space
+    fn node_to_grid(chunk: ChunkId, point: &na::Vector4<f64>) -> na::Vector3<f64> {
+        let chunk_pos = chunk.vertex.node_to_chunk_f64() * point;
+        chunk_pos.xyz() / chunk_pos.w * f64::from(DIMENSION)
+    }
+
+    #[test]
+    fn anchor_transform() {
+        let layout = ChunkLayout::new(DIMENSION);
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::F);
+        let coords = Coords([1, 2, 3]);
+        for axis in CoordAxis::iter() {
+            for direction in CoordDirection::iter() {
+                let anchor = FaceAnchor::new(&layout, chunk, coords, axis, direction);
+                let transform = anchor.transform.cast::<f64>();
+
+                // The origin lands on the center of the face
+                let mut expected = na::Vector3::new(1.5, 2.5, 3.5);
+                expected[axis as usize] += 0.5 * f64::from(direction as i8);
+                let center = transform * math::origin();
+                assert_abs_diff_eq!(center, grid_to_node(chunk, expected), epsilon = 1e-5);
+
+                // The frame is an isometry
+                assert_abs_diff_eq!(
+                    math::mtranspose(&transform) * transform,
+                    na::Matrix4::identity(),
+                    epsilon = 1e-5
+                );
+
+                // +Z leads out of the voxel, across the face
+                let step = 0.1 * f64::from(anchor.half_extent);
+                let outside = transform * math::translate_along(&(na::Vector3::z() * step));
+                let grid = node_to_grid(chunk, &(outside * math::origin()));
+                let mut expected_voxel = na::Vector3::new(1, 2, 3);
+                expected_voxel[axis as usize] += direction as i32;
+                assert_eq!(grid.map(|x| x.floor() as i32), expected_voxel);
+
+                // X and Y run along the face
+                for tangent in [na::Vector3::x(), na::Vector3::y()] {
+                    let along =
+                        transform * math::translate_along(&(tangent * step)) * math::origin();
+                    let grid = node_to_grid(chunk, &along);
+                    assert_abs_diff_eq!(
+                        grid[axis as usize],
+                        expected[axis as usize],
+                        epsilon = 1e-4
+                    );
+                }
+
+                // Half extent reaches the edge of the face
+                let edge = transform * na::Vector4::new(anchor.half_extent as f64, 0.0, 0.0, 1.0);
+                let grid = node_to_grid(chunk, &math::lorentz_normalize(&edge));
+                let offset = grid - expected;
+                assert_abs_diff_eq!(offset.amax(), 0.5, epsilon = 1e-3);
+            }
+        }
+    }
+
+    fn test_graph() -> Graph {
+        let mut graph = Graph::new(DIMENSION);
+        ensure_nearby(
+            &mut graph,
+            &Position::origin(),
+            dodeca::BOUNDING_SPHERE_RADIUS_F64,
+        );
+        populate_fresh_nodes(&mut graph);
+        graph
+    }
+
+    fn hit(chunk: ChunkId, coords: Coords) -> GraphCastHit {
+        GraphCastHit {
+            tanh_distance: 0.1,
+            chunk,
+            voxel_coords: coords,
+            face_axis: CoordAxis::Y,
+            face_direction: CoordDirection::Plus,
+        }
+    }
+
+    #[test]
+    fn cracks_invalidated_by_edit() {
+        let mut graph = test_graph();
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let coords = Coords([1, 2, 1]);
+        let now = Instant::now();
+        let mut pool = EffectPool::new();
+
+        // Nothing to crack until the chunk is populated
+        pool.set_target(&graph, Some(&hit(chunk, coords)), now);
+        assert_eq!(pool.iter().len(), 0);
+
+        graph.populate_chunk(chunk, VoxelData::Solid(Material::Dirt), false);
+        pool.set_target(&graph, Some(&hit(chunk, coords)), now);
+        pool.set_target(&graph, Some(&hit(chunk, coords)), now);
+        assert_eq!(pool.iter().len(), 1);
+        pool.update(&graph, now);
+        assert_eq!(pool.iter().len(), 1);
+
+        // Edits elsewhere don't matter
+        assert_eq!(
+            graph.update_block(&BlockUpdate {
+                chunk_id: chunk,
+                coords: Coords([2, 1, 2]),
+                new_material: Material::Void,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            }),
+            BlockUpdateOutcome::Applied {
+                previous: Material::Dirt,
+                previous_shape: Shape::FULL,
+            }
+        );
+        pool.update(&graph, now);
+        assert_eq!(pool.iter().len(), 1);
+
+        // The block is gone by the time the effect would be drawn
+        assert_eq!(
+            graph.update_block(&BlockUpdate {
+                chunk_id: chunk,
+                coords,
+                new_material: Material::Void,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            }),
+            BlockUpdateOutcome::Applied {
+                previous: Material::Dirt,
+                previous_shape: Shape::FULL,
+            }
+        );
+        pool.update(&graph, now);
+        assert_eq!(pool.iter().len(), 0);
+    }
+
+    #[test]
+    fn expiry() {
+        let mut graph = test_graph();
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        graph.populate_chunk(chunk, VoxelData::Solid(Material::Dirt), false);
+        let coords = Coords([1, 2, 1]);
+        let start = Instant::now();
+        let mut pool = EffectPool::new();
+        pool.set_target(&graph, Some(&hit(chunk, coords)), start);
+        pool.burst(&graph, &hit(chunk, coords), start);
+        // The burst replaces the cracks
+        assert_eq!(pool.iter().len(), 1);
+        assert_eq!(pool.iter().next().unwrap().kind, EffectKind::Burst);
+
+        // Breaking doesn't invalidate the burst
+        assert_eq!(
+            graph.update_block(&BlockUpdate {
+                chunk_id: chunk,
+                coords,
+                new_material: Material::Void,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            }),
+            BlockUpdateOutcome::Applied {
+                previous: Material::Dirt,
+                previous_shape: Shape::FULL,
+            }
+        );
+        let halfway = start + BURST_LIFETIME / 2;
+        pool.update(&graph, halfway);
+        assert_abs_diff_eq!(
+            pool.iter().next().unwrap().progress(halfway),
+            0.5,
+            epsilon = 1e-6
+        );
+        pool.update(&graph, start + BURST_LIFETIME);
+        assert_eq!(pool.iter().len(), 0);
+
+        // Refused placements against the same face refresh a single tint
+        pool.obstructed(&graph, &hit(chunk, coords), start);
+        pool.obstructed(&graph, &hit(chunk, coords), start + OBSTRUCTED_LIFETIME / 2);
+        assert_eq!(pool.iter().len(), 1);
+        pool.update(&graph, start + OBSTRUCTED_LIFETIME);
+        assert_eq!(pool.iter().len(), 1);
+        pool.update(&graph, start + OBSTRUCTED_LIFETIME * 3 / 2);
+        assert_eq!(pool.iter().len(), 0);
+
+        // The pool is bounded
+        for _ in 0..2 * MAX_EFFECTS {
+            pool.burst(&graph, &hit(chunk, coords), start);
+        }
+        assert_eq!(pool.iter().len(), MAX_EFFECTS);
+    }
+}
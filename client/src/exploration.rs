@@ -0,0 +1,526 @@
+//! Client-side record of which parts of the world the player has seen on each server
+//!
+//! Unloaded nodes are forgotten by the graph, but the map can still show where the player has been
+//! by remembering a small summary of every node they came near, keyed by its route from the root.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use common::{
+    dodeca::{Side, Vertex},
+    graph::{Graph, NodeId},
+    map_projection::{self, MapFrame},
+    math,
+    node::{Chunk, ChunkId, VoxelData},
+    node_path::NodePath,
+    proto::Position,
+    world::Material,
+};
+
+use crate::server_files::per_server_file_name;
+
+/// Number of nodes remembered per server, bounding the size of each file
+const MAX_EXPLORED: usize = 8192;
+/// Number of steps through the graph from the viewpoint's node within which nodes are explored
+const EXPLORE_RADIUS: u32 = 1;
+/// Minimum time between writes of newly explored nodes to disk
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What a node looked like when it was explored
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeSummary {
+    /// Most common material on the node's terrain surface, if any was found
+    pub material: Option<Material>,
+    /// Signed distance from the node's center to its terrain surface
+    pub elevation: f32,
+}
+
+impl NodeSummary {
+    /// Summarize `node`, or return `None` if any of its chunks have yet to be generated
+    pub fn sample(graph: &Graph, node: NodeId) -> Option<Self> {
+        let state = &graph.get(node).as_ref()?.state;
+        let dimension = graph.layout().dimension();
+        let mut counts = [0; Material::COUNT];
+        for vertex in Vertex::iter() {
+            let Some(Chunk::Populated { voxels, .. }) = graph.get_chunk(ChunkId::new(node, vertex))
+            else {
+                return None;
+            };
+            tally_surface(voxels, dimension, &mut counts);
+        }
+        Some(Self {
+            material: dominant(&counts),
+            elevation: state.elevation(),
+        })
+    }
+}
+
+/// Count the solid voxels in `voxels` that lie next to empty space, by material
+fn tally_surface(voxels: &VoxelData, dimension: u8, counts: &mut [u32; Material::COUNT]) {
+    // Uniform chunks have no surface of their own. Voxels on a chunk's boundary are only compared
+    // against their neighbors within it, since margins aren't necessarily filled in.
+    if voxels.is_solid() {
+        return;
+    }
+    let view = voxels.view(dimension);
+    for (coords, material) in view.iter() {
+        if material == Material::Void {
+            continue;
+        }
+        let exposed = (0..3).any(|axis| {
+            [-1i16, 1].into_iter().any(|offset| {
+                let mut neighbor = coords;
+                let coord = i16::from(neighbor.0[axis]) + offset;
+                if coord < 0 || coord >= i16::from(dimension) {
+                    return false;
+                }
+                neighbor.0[axis] = coord as u8;
+                view.get(neighbor) == Material::Void
+            })
+        });
+        // Materials this build doesn't know have no color to show on the map
+        if exposed && material.is_known() {
+            counts[usize::from(material.id())] += 1;
+        }
+    }
+}
+
+/// The material counted most often, if any were counted
+fn dominant(counts: &[u32; Material::COUNT]) -> Option<Material> {
+    let (index, &count) = counts
+        .iter()
+        .enumerate()
+        .max_by_key(|&(i, &count)| (count, std::cmp::Reverse(i)))?;
+    (count > 0).then_some(Material::VALUES[index])
+}
+
+/// An explored node drawn on the map because it's no longer loaded
+#[derive(Debug, Clone)]
+pub struct ExploredMapNode {
+    pub center: na::Point2<f32>,
+    pub radius: f32,
+    pub summary: NodeSummary,
+}
+
+/// Nodes explored on one server
+pub struct Exploration {
+    file: PathBuf,
+    nodes: FxHashMap<NodePath, NodeSummary>,
+    /// Number of nodes remembered, beyond which those farthest from the root are forgotten
+    capacity: usize,
+    /// Nodes previously explored, until they've been read from disk
+    loading: Option<thread::JoinHandle<FxHashMap<NodePath, NodeSummary>>>,
+    /// The viewpoint's node as of the last update
+    center: Option<NodeId>,
+    /// Nodes near `center` that couldn't be summarized yet
+    pending: Vec<NodeId>,
+    /// Whether `nodes` has changed since it was last saved
+    dirty: bool,
+    since_saved: Duration,
+}
+
+impl Exploration {
+    /// Begin loading the nodes explored on the server identified by `server` from `dir`
+    ///
+    /// Loading happens in the background, so a long history doesn't hold anything up. Nodes
+    /// explored in the meantime are merged in once it's done.
+    pub fn open(dir: &Path, server: &str) -> Self {
+        let file = dir.join(per_server_file_name(server, "toml"));
+        let loading = {
+            let file = file.clone();
+            thread::spawn(move || load(&file))
+        };
+        Self {
+            file,
+            nodes: FxHashMap::default(),
+            capacity: MAX_EXPLORED,
+            loading: Some(loading),
+            center: None,
+            pending: Vec::new(),
+            dirty: false,
+            since_saved: Duration::ZERO,
+        }
+    }
+
+    /// Advance time by `dt`, exploring nodes near `view` and periodically saving the result
+    pub fn update(&mut self, dt: Duration, graph: &Graph, view: NodeId) -> io::Result<()> {
+        self.poll_loading(false);
+        if self.center != Some(view) {
+            self.center = Some(view);
+            self.pending = nodes_within(graph, view, EXPLORE_RADIUS);
+        }
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.retain(|&node| {
+            let Some(summary) = NodeSummary::sample(graph, node) else {
+                return true;
+            };
+            self.record(NodePath::to(graph, node), summary);
+            false
+        });
+        self.pending = pending;
+
+        self.since_saved += dt;
+        if !self.dirty || self.loading.is_some() || self.since_saved < SAVE_INTERVAL {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    /// Remember `summary` for the node at the end of `path`, unless it's already been explored
+    pub fn record(&mut self, path: NodePath, summary: NodeSummary) {
+        if self.nodes.contains_key(&path) {
+            return;
+        }
+        self.nodes.insert(path, summary);
+        self.evict();
+        self.dirty = true;
+    }
+
+    /// Explored nodes around `view` that the graph no longer has populated, as seen on a map
+    /// extending `extent` from its center in the Klein model
+    pub fn map(
+        &self,
+        graph: &Graph,
+        view: &Position,
+        frame: &MapFrame,
+        extent: f32,
+    ) -> Vec<ExploredMapNode> {
+        let view_path = NodePath::to(graph, view.node);
+        let mut result = Vec::new();
+        for (path, summary) in &self.nodes {
+            if path
+                .resolve(graph)
+                .map_or(false, |node| graph.get(node).is_some())
+            {
+                // Drawn with everything else that's loaded
+                continue;
+            }
+            let point = view_path.transform_from(path) * math::origin::<f64>();
+            let center = frame.project(&(point / point.w).cast::<f32>());
+            if center.coords.norm() > extent {
+                continue;
+            }
+            result.push(ExploredMapNode {
+                center,
+                radius: map_projection::node_radius(&center),
+                summary: *summary,
+            });
+        }
+        result
+    }
+
+    /// Write everything explored so far to disk
+    pub fn save(&mut self) -> io::Result<()> {
+        self.poll_loading(true);
+        self.dirty = false;
+        self.since_saved = Duration::ZERO;
+        let data = toml::to_string(&ExplorationFile {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|(path, summary)| Record {
+                    path: path.clone(),
+                    material: summary.material,
+                    elevation: summary.elevation,
+                })
+                .collect(),
+        })
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Replace the file atomically, so a crash mid-write leaves the previous map intact
+        let temp = self.file.with_extension("toml.tmp");
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.file)
+    }
+
+    /// Merge in previously explored nodes if they've been loaded, waiting for them if `block`
+    fn poll_loading(&mut self, block: bool) {
+        let Some(loading) = self.loading.take() else {
+            return;
+        };
+        if !block && !loading.is_finished() {
+            self.loading = Some(loading);
+            return;
+        }
+        let loaded = loading.join().unwrap_or_else(|_| {
+            warn!("failed to load explored nodes");
+            FxHashMap::default()
+        });
+        for (path, summary) in loaded {
+            self.nodes.entry(path).or_insert(summary);
+        }
+        self.evict();
+    }
+
+    /// Forget the nodes farthest from the root until no more than `capacity` remain
+    fn evict(&mut self) {
+        let excess = self.nodes.len().saturating_sub(self.capacity);
+        if excess == 0 {
+            return;
+        }
+        let mut paths = self.nodes.keys().cloned().collect::<Vec<_>>();
+        // Routes follow parents, so their lengths are distances from the root through the graph
+        paths.sort_unstable_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.cmp(b)));
+        for path in &paths[..excess] {
+            self.nodes.remove(path);
+        }
+    }
+}
+
+/// Nodes no more than `radius` steps through `graph` from `center`
+fn nodes_within(graph: &Graph, center: NodeId, radius: u32) -> Vec<NodeId> {
+    let mut result = vec![center];
+    let mut frontier = vec![center];
+    for _ in 0..radius {
+        let mut next = Vec::new();
+        for node in frontier {
+            for side in Side::iter() {
+                let Some(neighbor) = graph.neighbor(node, side) else {
+                    continue;
+                };
+                if !result.contains(&neighbor) {
+                    result.push(neighbor);
+                    next.push(neighbor);
+                }
+            }
+        }
+        frontier = next;
+    }
+    result
+}
+
+fn load(file: &Path) -> FxHashMap<NodePath, NodeSummary> {
+    let data = match fs::read_to_string(file) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return FxHashMap::default(),
+        Err(e) => {
+            warn!(
+                "couldn't read explored nodes from {}: {}",
+                file.display(),
+                e
+            );
+            return FxHashMap::default();
+        }
+    };
+    match toml::from_str::<ExplorationFile>(&data) {
+        Ok(x) => x
+            .nodes
+            .into_iter()
+            .map(|record| {
+                (
+                    record.path,
+                    NodeSummary {
+                        material: record.material,
+                        elevation: record.elevation,
+                    },
+                )
+            })
+            .collect(),
+        Err(e) => {
+            warn!(
+                "ignoring malformed explored nodes in {}: {}",
+                file.display(),
+                e
+            );
+            FxHashMap::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExplorationFile {
+    nodes: Vec<Record>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    path: NodePath,
+    material: Option<Material>,
+    elevation: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        node::populate_fresh_nodes,
+        test_graphs::walk,
+        traversal::{ensure_nearby, nearby_nodes},
+        worldgen::{ChunkParams, TerrainPassKind},
+    };
+
+    fn summary(material: Material, elevation: f32) -> NodeSummary {
+        NodeSummary {
+            material: Some(material),
+            elevation,
+        }
+    }
+
+    /// An exploration whose history has finished loading
+    fn open(dir: &Path, server: &str) -> Exploration {
+        let mut exploration = Exploration::open(dir, server);
+        exploration.poll_loading(true);
+        exploration
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut graph = Graph::new(4);
+        let a = walk(&mut graph, &[Side::A, Side::B, Side::C]);
+        let b = walk(&mut graph, &[Side::D]);
+        let mut exploration = open(dir.path(), "example.com:1234");
+        assert_eq!(exploration.nodes.len(), 0);
+        exploration.record(NodePath::to(&graph, a), summary(Material::Sand, -0.5));
+        exploration.record(
+            NodePath::to(&graph, b),
+            NodeSummary {
+                material: None,
+                elevation: 2.0,
+            },
+        );
+        // Only the first visit counts
+        exploration.record(NodePath::to(&graph, a), summary(Material::Snow, 1.0));
+        exploration.save().unwrap();
+
+        let reopened = open(dir.path(), "example.com:1234");
+        assert_eq!(reopened.nodes.len(), 2);
+        assert_eq!(
+            reopened.nodes.get(&NodePath::to(&graph, a)),
+            Some(&summary(Material::Sand, -0.5))
+        );
+        assert_eq!(
+            reopened
+                .nodes
+                .get(&NodePath::to(&graph, b))
+                .unwrap()
+                .material,
+            None
+        );
+        assert_eq!(open(dir.path(), "elsewhere").nodes.len(), 0);
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn merges_with_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut graph = Graph::new(4);
+        let old = walk(&mut graph, &[Side::A]);
+        let new = walk(&mut graph, &[Side::B]);
+        let mut exploration = open(dir.path(), "local");
+        exploration.record(NodePath::to(&graph, old), summary(Material::Dirt, 0.0));
+        exploration.save().unwrap();
+
+        // Exploring before the history has been read neither loses it nor is lost to it
+        let mut exploration = Exploration::open(dir.path(), "local");
+        exploration.record(NodePath::to(&graph, new), summary(Material::Grass, 0.0));
+        exploration.save().unwrap();
+        let reopened = open(dir.path(), "local");
+        assert_eq!(reopened.nodes.len(), 2);
+        assert!(reopened.nodes.get(&NodePath::to(&graph, old)).is_some());
+        assert!(reopened.nodes.get(&NodePath::to(&graph, new)).is_some());
+    }
+
+    #[test]
+    fn evicts_farthest_from_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut exploration = open(dir.path(), "local");
+        exploration.capacity = 4;
+        let path = |len: usize| NodePath(vec![Side::B; len]);
+        for len in [3, 1, 4, 0, 2, 5] {
+            exploration.record(path(len), summary(Material::Dirt, 0.0));
+            assert!(exploration.nodes.len() <= 4);
+        }
+        for len in 0..4 {
+            assert!(exploration.nodes.get(&path(len)).is_some());
+        }
+        for len in 4..6 {
+            assert!(exploration.nodes.get(&path(len)).is_none());
+        }
+
+        // A newly explored node farther out than everything else is forgotten immediately
+        exploration.record(path(6), summary(Material::Dirt, 0.0));
+        assert!(exploration.nodes.get(&path(6)).is_none());
+        // One nearer the root displaces the farthest
+        exploration.record(NodePath(vec![Side::C]), summary(Material::Dirt, 0.0));
+        assert!(exploration.nodes.get(&NodePath(vec![Side::C])).is_some());
+        assert!(exploration.nodes.get(&path(3)).is_none());
+        assert!(exploration.nodes.get(&path(2)).is_some());
+    }
+
+    #[test]
+    fn surface_of_generated_chunk() {
+        let dimension = 12;
+        let mut graph = Graph::new(dimension);
+        ensure_nearby(&mut graph, &Position::origin(), 3.0);
+        populate_fresh_nodes(&mut graph);
+        let passes = [TerrainPassKind::Flat {
+            height: 0.05,
+            material: Material::Sand,
+        }];
+
+        let mut counts = [0; Material::COUNT];
+        let mut dense = 0;
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(NodeId::ROOT, vertex);
+            let voxels = ChunkParams::new(dimension, &graph, chunk, &passes)
+                .unwrap()
+                .generate_voxels();
+            if !voxels.is_solid() {
+                dense += 1;
+            }
+            let before = counts;
+            tally_surface(&voxels, dimension, &mut counts);
+            if voxels.is_solid() {
+                // Uniform chunks have no surface
+                assert_eq!(counts, before);
+            }
+            graph.populate_chunk(chunk, voxels, false);
+        }
+        assert!(dense > 0, "no chunk intersects the surface");
+        assert!(counts[usize::from(Material::Sand.id())] > 0);
+        assert_eq!(
+            counts.iter().sum::<u32>(),
+            counts[usize::from(Material::Sand.id())]
+        );
+
+        let summary = NodeSummary::sample(&graph, NodeId::ROOT).unwrap();
+        assert_eq!(summary.material, Some(Material::Sand));
+        assert_eq!(
+            summary.elevation,
+            graph.get(NodeId::ROOT).as_ref().unwrap().state.elevation()
+        );
+        // Nodes whose chunks haven't all been generated can't be summarized yet
+        let neighbor = nearby_nodes(&graph, &Position::origin(), 1.5)
+            .into_iter()
+            .find(|&(node, _)| node != NodeId::ROOT)
+            .unwrap()
+            .0;
+        assert_eq!(NodeSummary::sample(&graph, neighbor), None);
+    }
+
+    #[test]
+    fn dominant_material() {
+        let mut counts = [0; Material::COUNT];
+        assert_eq!(dominant(&counts), None);
+        counts[usize::from(Material::Snow.id())] = 3;
+        counts[usize::from(Material::Dirt.id())] = 3;
+        counts[usize::from(Material::Grass.id())] = 1;
+        // Ties go to the material listed first
+        assert_eq!(dominant(&counts), Some(Material::Dirt));
+    }
+}
@@ -0,0 +1,109 @@
+//! Carrying remote entities along between the server's updates of them
+//!
+//! The server updates distant entities only every few steps, so each entity is drawn where its
+//! last known velocity would have taken it since. Extrapolation runs no further than the interval
+//! the server has been leaving between that entity's updates, so an entity whose updates stop
+//! coming halts rather than drifting off.
+
+use std::time::Duration;
+
+use common::{math, proto::Position, Step, MAX_UPDATE_STRIDE};
+
+/// When the server last reported an entity's position, and how long it's been waiting between
+/// reports
+pub struct UpdateTiming {
+    step: Step,
+    /// Steps between the last two reports
+    interval: Step,
+}
+
+impl UpdateTiming {
+    /// Timing of an entity spawned at `step`, not yet known to move
+    pub fn new(step: Step) -> Self {
+        Self { step, interval: 1 }
+    }
+
+    /// Note a report of the entity's position as of `step`
+    pub fn observe(&mut self, step: Step) {
+        self.interval = step.wrapping_sub(self.step).clamp(1, MAX_UPDATE_STRIDE);
+        self.step = step;
+    }
+
+    /// Seconds to carry the entity along as of the latest step `latest`
+    pub fn elapsed(&self, latest: Step, step_interval: Duration) -> f32 {
+        let steps = latest.wrapping_sub(self.step).clamp(0, self.interval);
+        steps as f32 * step_interval.as_secs_f32()
+    }
+}
+
+/// Where an entity at `position`, moving at `velocity` in its own frame, is after `dt` seconds
+pub fn extrapolate(position: &Position, velocity: &na::Vector3<f32>, dt: f32) -> Position {
+    Position {
+        node: position.node,
+        local: position.local * math::translate_along(&(velocity * dt)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{graph::NodeId, SimConfig, SimConfigRaw};
+
+    #[test]
+    fn elapsed_clamped_to_stride() {
+        let step_interval = Duration::from_millis(125);
+        let mut timing = UpdateTiming::new(10);
+        // Extrapolated by no more than a step until it's known how often updates come
+        assert_eq!(timing.elapsed(12, step_interval), 0.125);
+
+        timing.observe(14);
+        assert_eq!(timing.elapsed(14, step_interval), 0.0);
+        assert_eq!(timing.elapsed(17, step_interval), 0.375);
+        assert_eq!(timing.elapsed(30, step_interval), 0.5);
+
+        // Ages are reckoned across the step counter wrapping
+        timing.observe(Step::MAX);
+        timing.observe(Step::MIN + 1);
+        assert_eq!(timing.interval, 2);
+        assert_eq!(timing.elapsed(Step::MIN + 2, step_interval), 0.125);
+    }
+
+    #[test]
+    fn circular_motion_tracked() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let radius = 10.0 * m;
+        let speed = 4.0 * m;
+        let angular_velocity = speed / radius.sinh();
+        // Circling the origin, moving towards -z in its own frame
+        let at = |time: f32| Position {
+            node: NodeId::ROOT,
+            local: na::Rotation3::from_axis_angle(&na::Vector3::y_axis(), angular_velocity * time)
+                .to_homogeneous()
+                * math::translate_along(&(na::Vector3::x() * radius)),
+        };
+        let velocity = -na::Vector3::z() * speed;
+        let error = |a: &Position, b: &Position| {
+            math::distance(&(a.local * math::origin()), &(b.local * math::origin())) / m
+        };
+
+        let step_interval = cfg.step_interval;
+        // Already being updated at the longest stride
+        let mut timing = UpdateTiming::new(-MAX_UPDATE_STRIDE);
+        let mut known = at(0.0);
+        let (mut worst, mut worst_held) = (0.0f32, 0.0f32);
+        for step in 0..80 {
+            if step % MAX_UPDATE_STRIDE == 0 {
+                timing.observe(step);
+                known = at(step as f32 * step_interval.as_secs_f32());
+            }
+            let truth = at(step as f32 * step_interval.as_secs_f32());
+            let drawn = extrapolate(&known, &velocity, timing.elapsed(step, step_interval));
+            worst = worst.max(error(&drawn, &truth));
+            worst_held = worst_held.max(error(&known, &truth));
+        }
+        // Within half a meter, where merely holding the last known position lags by meters
+        assert!(worst < 0.5, "extrapolated {worst} m from the truth");
+        assert!(worst_held > 2.5, "held {worst_held} m from the truth");
+    }
+}
@@ -0,0 +1,160 @@
+//! A camera trailing another character, for spectators to watch another player from
+
+use hecs::Entity;
+
+use common::{collision_math::Ray, graph::Graph, graph_ray_casting, math, proto::Position};
+
+/// Watching another character from a camera held some way behind it
+pub struct Follow {
+    /// The character watched
+    pub target: Entity,
+    /// Its name, for reporting that it was lost
+    pub name: String,
+    /// Which way the camera faces while free to orbit, relative to the target's frame
+    orbit: na::UnitQuaternion<f32>,
+    /// Whether the camera faces wherever the target does, rather than orbiting at will
+    locked: bool,
+}
+
+impl Follow {
+    /// Start watching `target`, facing the way it does
+    pub fn new(
+        target: Entity,
+        name: String,
+        facing: na::UnitQuaternion<f32>,
+        locked: bool,
+    ) -> Self {
+        Self {
+            target,
+            name,
+            orbit: facing,
+            locked,
+        }
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Switch between facing wherever the target does and orbiting freely, starting from
+    /// `facing`, the way it does now, returning whether the camera is now locked
+    pub fn toggle_lock(&mut self, facing: na::UnitQuaternion<f32>) -> bool {
+        if self.locked {
+            self.orbit = facing;
+        }
+        self.locked = !self.locked;
+        self.locked
+    }
+
+    /// Orbit the camera around the target, turning as freely as an observer's view, unless locked
+    pub fn look(&mut self, delta_yaw: f32, delta_pitch: f32, delta_roll: f32) {
+        let rotation = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), delta_yaw)
+            * na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), delta_pitch)
+            * na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), delta_roll);
+        self.orbit *= rotation;
+    }
+
+    /// Which way the camera faces, relative to the frame of a target facing `facing`
+    pub fn orientation(&self, facing: &na::UnitQuaternion<f32>) -> na::UnitQuaternion<f32> {
+        if self.locked {
+            *facing
+        } else {
+            self.orbit
+        }
+    }
+
+    /// Where to watch the target at `target`, facing `facing`, from: `distance` behind it, or
+    /// `margin` short of any terrain in between
+    pub fn view(
+        &self,
+        graph: &Graph,
+        target: &Position,
+        facing: &na::UnitQuaternion<f32>,
+        distance: f32,
+        margin: f32,
+    ) -> Position {
+        let pivot = Position {
+            node: target.node,
+            local: target.local * self.orientation(facing).to_homogeneous(),
+        };
+        let view = boom(&pivot, clearance(graph, &pivot, distance, margin));
+        let (node, transform) = graph.normalize_transform(view.node, &view.local);
+        Position {
+            node,
+            local: transform * view.local,
+        }
+    }
+}
+
+/// `pivot` moved `distance` back along its z axis, which is behind it for a view looking along -z
+fn boom(pivot: &Position, distance: f32) -> Position {
+    Position {
+        node: pivot.node,
+        local: pivot.local * math::translate_along(&(na::Vector3::z() * distance)),
+    }
+}
+
+/// How far behind `pivot` the camera can go, up to `distance`, keeping `margin` from terrain
+fn clearance(graph: &Graph, pivot: &Position, distance: f32, margin: f32) -> f32 {
+    let ray = Ray::new(math::origin(), na::Vector4::z());
+    match graph_ray_casting::ray_cast(graph, pivot, &ray, distance.tanh()) {
+        Ok(Some(hit)) => (hit.tanh_distance.atanh() - margin).max(0.0),
+        // Nothing generated there yet to get in the way
+        Ok(None) | Err(_) => distance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use common::graph::NodeId;
+
+    fn follow(locked: bool) -> Follow {
+        Follow::new(Entity::DANGLING, "bob".into(), na::one(), locked)
+    }
+
+    #[test]
+    fn camera_looks_at_target() {
+        let target = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&na::Vector3::new(0.1, 0.0, 0.0)),
+        };
+        let center = target.local * math::origin();
+        let facing = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.5);
+        let distance = 0.2;
+        let mut follow = follow(false);
+        follow.look(1.0, -0.3, 0.0);
+        for locked in [false, true] {
+            if follow.locked() != locked {
+                follow.toggle_lock(facing);
+            }
+            let pivot = Position {
+                node: target.node,
+                local: target.local * follow.orientation(&facing).to_homogeneous(),
+            };
+            let view = boom(&pivot, distance);
+            let eye = view.local * math::origin();
+            assert_abs_diff_eq!(math::distance(&eye, &center), distance, epsilon = 1e-5);
+            // The target lies straight ahead
+            let ahead = view.local * math::translate_along(&(-na::Vector3::z() * distance));
+            assert_abs_diff_eq!(ahead * math::origin(), center, epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    fn locked_camera_turns_with_target() {
+        let mut follow = follow(true);
+        // Looking around does nothing while locked
+        follow.look(1.0, 0.5, 0.0);
+        let facing = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.7);
+        assert_eq!(follow.orientation(&facing), facing);
+
+        // Unlocking orbits from wherever the target faced
+        assert!(!follow.toggle_lock(facing));
+        assert_eq!(follow.orientation(&na::one()), facing);
+        follow.look(0.2, 0.0, 0.0);
+        let turned = facing * na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.2);
+        assert_abs_diff_eq!(follow.orientation(&na::one()), turned, epsilon = 1e-6);
+    }
+}
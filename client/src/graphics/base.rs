@@ -3,10 +3,11 @@
 use std::ffi::CStr;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::{fs, io, ptr};
+use std::{fmt, fs, io, ptr};
 use tracing::{error, info, trace, warn};
 
-use ash::{vk, Device};
+use ash::{prelude::VkResult, vk, Device};
+use common::mem_budget::{self, Pool, Reservation};
 
 use super::Core;
 
@@ -25,14 +26,22 @@ pub struct Base {
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
     /// Cache used to speed up graphics pipeline construction
     pub pipeline_cache: vk::PipelineCache,
-    /// Context in which the main rendering work occurs
+    /// Context in which the main rendering work occurs, producing an image to be sampled by
+    /// `post_render_pass`
     pub render_pass: vk::RenderPass,
+    /// Context in which the rendered image is adjusted and scaled to fit the window
+    pub post_render_pass: vk::RenderPass,
     /// A reasonable general-purpose texture sampler
     pub linear_sampler: vk::Sampler,
     /// Layout of common shader resources, such as the common uniform buffer
     pub common_layout: vk::DescriptorSetLayout,
+    /// Layout of the rendered image as read by the post-processing pass
+    pub post_layout: vk::DescriptorSetLayout,
     pub limits: vk::PhysicalDeviceLimits,
     pub timestamp_bits: u32,
+    /// Bytes of device memory beyond which `try_reserve_memory` refuses further allocations, so
+    /// that memory runs short in a way we can recover from before the device itself runs out
+    pub memory_budget: vk::DeviceSize,
     pipeline_cache_path: Option<PathBuf>,
 }
 
@@ -45,9 +54,12 @@ impl Drop for Base {
             self.device
                 .destroy_pipeline_cache(self.pipeline_cache, None);
             self.device.destroy_render_pass(self.render_pass, None);
+            self.device.destroy_render_pass(self.post_render_pass, None);
             self.device.destroy_sampler(self.linear_sampler, None);
             self.device
                 .destroy_descriptor_set_layout(self.common_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.post_layout, None);
             self.device.destroy_device(None);
         }
     }
@@ -57,6 +69,7 @@ impl Base {
     pub fn new(
         core: Arc<Core>,
         pipeline_cache_path: Option<PathBuf>,
+        memory_budget: Option<vk::DeviceSize>,
         device_exts: &[&CStr],
         mut device_filter: impl FnMut(vk::PhysicalDevice, u32) -> bool,
     ) -> Option<Self> {
@@ -162,7 +175,7 @@ impl Base {
                                 load_op: vk::AttachmentLoadOp::CLEAR,
                                 store_op: vk::AttachmentStoreOp::STORE,
                                 initial_layout: vk::ImageLayout::UNDEFINED,
-                                final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                                final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
                                 ..Default::default()
                             },
                             vk::AttachmentDescription {
@@ -187,6 +200,18 @@ impl Base {
                                 })
                                 .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
                                 .build(),
+                            // Translucent effects, depth tested against opaque geometry
+                            vk::SubpassDescription::builder()
+                                .color_attachments(&[vk::AttachmentReference {
+                                    attachment: 0,
+                                    layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                                }])
+                                .depth_stencil_attachment(&vk::AttachmentReference {
+                                    attachment: 1,
+                                    layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                                })
+                                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                                .build(),
                             vk::SubpassDescription::builder()
                                 .color_attachments(&[vk::AttachmentReference {
                                     attachment: 0,
@@ -203,7 +228,10 @@ impl Base {
                             vk::SubpassDependency {
                                 src_subpass: vk::SUBPASS_EXTERNAL,
                                 dst_subpass: 0,
-                                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                                // Don't overwrite the image before the last frame's post-processing
+                                // is done with it
+                                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
                                 dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
                                 dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
                                     | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
@@ -212,17 +240,82 @@ impl Base {
                             vk::SubpassDependency {
                                 src_subpass: 0,
                                 dst_subpass: 1,
+                                src_stage_mask: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS // depth write
+                                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                                dst_stage_mask: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS // depth test
+                                    | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, // blending
+                                src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                                dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                                    | vk::AccessFlags::COLOR_ATTACHMENT_READ
+                                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                                dependency_flags: vk::DependencyFlags::BY_REGION,
+                            },
+                            vk::SubpassDependency {
+                                src_subpass: 0,
+                                dst_subpass: 2,
                                 src_stage_mask: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS, // depth write
                                 dst_stage_mask: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS, // depth read
                                 src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
                                 dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
                                 dependency_flags: vk::DependencyFlags::BY_REGION,
                             },
+                            vk::SubpassDependency {
+                                src_subpass: 1,
+                                dst_subpass: 2,
+                                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                                dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                                dependency_flags: vk::DependencyFlags::BY_REGION,
+                            },
+                            vk::SubpassDependency {
+                                src_subpass: 2,
+                                dst_subpass: vk::SUBPASS_EXTERNAL,
+                                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                                ..Default::default()
+                            },
                         ]),
                     None,
                 )
                 .unwrap();
 
+            let post_render_pass = device
+                .create_render_pass(
+                    &vk::RenderPassCreateInfo::builder()
+                        .attachments(&[vk::AttachmentDescription {
+                            format: COLOR_FORMAT,
+                            samples: vk::SampleCountFlags::TYPE_1,
+                            // Every pixel is overwritten
+                            load_op: vk::AttachmentLoadOp::DONT_CARE,
+                            store_op: vk::AttachmentStoreOp::STORE,
+                            initial_layout: vk::ImageLayout::UNDEFINED,
+                            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                            ..Default::default()
+                        }])
+                        .subpasses(&[vk::SubpassDescription::builder()
+                            .color_attachments(&[vk::AttachmentReference {
+                                attachment: 0,
+                                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                            }])
+                            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                            .build()])
+                        .dependencies(&[vk::SubpassDependency {
+                            src_subpass: vk::SUBPASS_EXTERNAL,
+                            dst_subpass: 0,
+                            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                            ..Default::default()
+                        }]),
+                    None,
+                )
+                .unwrap();
+
             let linear_sampler = device
                 .create_sampler(
                     &vk::SamplerCreateInfo::builder()
@@ -261,6 +354,22 @@ impl Base {
                 )
                 .unwrap();
 
+            let post_layout = device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[
+                        // Rendered image
+                        vk::DescriptorSetLayoutBinding {
+                            binding: 0,
+                            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                            descriptor_count: 1,
+                            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                            p_immutable_samplers: ptr::null(),
+                        },
+                    ]),
+                    None,
+                )
+                .unwrap();
+
             Some(Self {
                 core,
                 physical,
@@ -270,11 +379,15 @@ impl Base {
                 memory_properties,
                 pipeline_cache,
                 render_pass,
+                post_render_pass,
                 linear_sampler,
                 common_layout,
+                post_layout,
                 pipeline_cache_path,
                 limits: physical_properties.properties.limits,
                 timestamp_bits: queue_family_properties.timestamp_valid_bits,
+                memory_budget: memory_budget
+                    .unwrap_or_else(|| default_memory_budget(&memory_properties)),
             })
         }
     }
@@ -299,6 +412,25 @@ impl Base {
         }
     }
 
+    /// Count `size` bytes of device memory towards `pool` until the result is dropped, for keeping
+    /// alongside the buffers or images they were allocated for
+    pub fn reserve_memory(&self, pool: &'static Pool, size: vk::DeviceSize) -> Reservation {
+        pool.reserve(size as usize)
+    }
+
+    /// Like `reserve_memory`, unless that would take the device memory counted by every pool past
+    /// `memory_budget`
+    pub fn try_reserve_memory(
+        &self,
+        pool: &'static Pool,
+        size: vk::DeviceSize,
+    ) -> Result<Reservation, OutOfDeviceMemory> {
+        if (mem_budget::gpu_bytes() as vk::DeviceSize).saturating_add(size) > self.memory_budget {
+            return Err(OutOfDeviceMemory { requested: size });
+        }
+        Ok(self.reserve_memory(pool, size))
+    }
+
     /// Set an object's name for use in diagnostics
     pub unsafe fn set_name<T: vk::Handle>(&self, object: T, name: &CStr) {
         let ex = match self.core.debug_utils.as_ref() {
@@ -318,9 +450,48 @@ impl Base {
     /// Convenience constructor for tests and benchmarks
     pub fn headless() -> Self {
         let core = Core::new(&[]);
-        Self::new(Arc::new(core), None, &[], |_, _| true).unwrap()
+        Self::new(Arc::new(core), None, None, &[], |_, _| true).unwrap()
+    }
+}
+
+/// Most of the largest heap local to the device, leaving the rest for the driver and for other
+/// applications
+fn default_memory_budget(properties: &vk::PhysicalDeviceMemoryProperties) -> vk::DeviceSize {
+    properties.memory_heaps[..properties.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size / 4 * 3)
+        .max()
+        .unwrap_or(vk::DeviceSize::MAX)
+}
+
+/// Device memory couldn't be found for an allocation, either because the device ran out or because
+/// the allocation would have exceeded `Base::memory_budget`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OutOfDeviceMemory {
+    /// Bytes the failed operation needed in all
+    pub requested: vk::DeviceSize,
+}
+
+impl OutOfDeviceMemory {
+    /// Pass along the result of a Vulkan call made for an operation needing `requested` bytes,
+    /// treating any failure other than running out of device memory as a bug
+    pub fn check<T>(result: VkResult<T>, requested: vk::DeviceSize) -> Result<T, Self> {
+        match result {
+            Ok(x) => Ok(x),
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => Err(Self { requested }),
+            Err(e) => panic!("{e}"),
+        }
     }
 }
 
+impl fmt::Display for OutOfDeviceMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "out of device memory for {} bytes", self.requested)
+    }
+}
+
+impl std::error::Error for OutOfDeviceMemory {}
+
 /// The pixel format we render in
 pub const COLOR_FORMAT: vk::Format = vk::Format::B8G8R8A8_SRGB;
@@ -0,0 +1,398 @@
+//! Player-adjustable presentation of the rendered image
+//!
+//! None of these settings affect what's simulated, and all of them may change from one frame to
+//! the next.
+
+use std::time::{Duration, Instant};
+
+use ash::vk;
+
+use super::Frustum;
+
+/// Limits on `DisplaySettings::render_scale`, beyond which the image is either unrecognizable or
+/// needlessly expensive
+const RENDER_SCALE_RANGE: (f32, f32) = (0.25, 2.0);
+
+/// Factor by which `Display::reduce_render_scale` scales the render scale
+const RENDER_SCALE_REDUCTION: f32 = 0.75;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DisplaySettings {
+    /// Exponent by which displayed intensities are flattened, where values above 1 brighten dark
+    /// regions and 1 leaves the image unchanged
+    pub gamma: f32,
+    /// Factor by which the rendered linear intensities are scaled
+    pub brightness: f32,
+    /// Vertical field of view in radians
+    pub fov: f32,
+    /// Time over which the field of view eases to a new value, or zero to change it immediately
+    pub fov_transition: Duration,
+    /// Vertical field of view in radians the held block is drawn with, regardless of `fov`
+    pub viewmodel_fov: f32,
+    /// Size of the rendered image relative to the window
+    pub render_scale: f32,
+}
+
+impl DisplaySettings {
+    /// Clamp out-of-range values to something renderable
+    pub fn sanitize(self) -> Self {
+        Self {
+            gamma: self.gamma.max(0.1),
+            brightness: self.brightness.max(0.0),
+            fov: self.fov.clamp(0.1, std::f32::consts::PI - 0.1),
+            fov_transition: self.fov_transition,
+            viewmodel_fov: self.viewmodel_fov.clamp(0.1, std::f32::consts::PI - 0.1),
+            render_scale: self
+                .render_scale
+                .clamp(RENDER_SCALE_RANGE.0, RENDER_SCALE_RANGE.1),
+        }
+    }
+
+    /// Size of the image to render for a window of size `extent`
+    pub fn render_extent(&self, extent: vk::Extent2D) -> vk::Extent2D {
+        let scale = |x: u32| ((x as f32 * self.render_scale).round() as u32).max(1);
+        vk::Extent2D {
+            width: scale(extent.width),
+            height: scale(extent.height),
+        }
+    }
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            brightness: 1.0,
+            fov: 108f32.to_radians(),
+            fov_transition: Duration::from_millis(200),
+            viewmodel_fov: 70f32.to_radians(),
+            render_scale: 1.0,
+        }
+    }
+}
+
+/// Current display settings, including any field of view transition in progress
+pub struct Display {
+    settings: DisplaySettings,
+    /// Field of view being transitioned away from, and when the transition began
+    fov_from: Option<(f32, Instant)>,
+}
+
+impl Display {
+    pub fn new(settings: DisplaySettings) -> Self {
+        Self {
+            settings: settings.sanitize(),
+            fov_from: None,
+        }
+    }
+
+    pub fn settings(&self) -> &DisplaySettings {
+        &self.settings
+    }
+
+    /// Adopt new settings, easing into a new field of view starting at `now`
+    pub fn set(&mut self, settings: DisplaySettings, now: Instant) {
+        let settings = settings.sanitize();
+        if settings.fov != self.settings.fov {
+            // Start from wherever an interrupted transition got to
+            self.fov_from = Some((self.fov(now), now));
+        }
+        self.settings = settings;
+    }
+
+    /// Render at a lower resolution to free the memory held by the rendered image, returning false
+    /// if it's already as low as it goes
+    pub fn reduce_render_scale(&mut self) -> bool {
+        let scale = (self.settings.render_scale * RENDER_SCALE_REDUCTION).max(RENDER_SCALE_RANGE.0);
+        if scale >= self.settings.render_scale {
+            return false;
+        }
+        self.settings.render_scale = scale;
+        true
+    }
+
+    /// Vertical field of view to render with at `now`
+    pub fn fov(&self, now: Instant) -> f32 {
+        let Some((from, start)) = self.fov_from else {
+            return self.settings.fov;
+        };
+        let elapsed = now.saturating_duration_since(start);
+        if elapsed >= self.settings.fov_transition {
+            return self.settings.fov;
+        }
+        let t = elapsed.as_secs_f32() / self.settings.fov_transition.as_secs_f32();
+        // Smoothstep, so the view neither lurches into motion nor stops abruptly
+        let t = t * t * (3.0 - 2.0 * t);
+        from + (self.settings.fov - from) * t
+    }
+
+    /// Frustum to render and cull with at `now`, for an image of the given aspect ratio
+    pub fn frustum(&self, aspect_ratio: f32, now: Instant) -> Frustum {
+        Frustum::from_vfov(self.fov(now) * 0.5, aspect_ratio)
+    }
+
+    /// Frustum to draw the held block with, for an image of the given aspect ratio
+    ///
+    /// Unlike `frustum`, this never changes with the world's field of view, so widening the view
+    /// doesn't stretch the block towards the edge of the screen.
+    pub fn view_model_frustum(&self, aspect_ratio: f32) -> Frustum {
+        Frustum::from_vfov(self.settings.viewmodel_fov * 0.5, aspect_ratio)
+    }
+
+    /// Parameters for the final pass
+    pub fn post_constants(&self) -> PostConstants {
+        PostConstants {
+            inverse_gamma: self.settings.gamma.recip(),
+            brightness: self.settings.brightness,
+        }
+    }
+}
+
+/// Push constants for `post.frag`, which outputs `(brightness * color) ^ inverse_gamma` for each
+/// linear color channel
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PostConstants {
+    pub inverse_gamma: f32,
+    pub brightness: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+    use common::math;
+
+    /// What `post.frag` does to a linear color channel
+    fn post(constants: &PostConstants, x: f32) -> f32 {
+        (x * constants.brightness).powf(constants.inverse_gamma)
+    }
+
+    #[test]
+    fn post_constants() {
+        let neutral = Display::new(DisplaySettings::default()).post_constants();
+        for x in [0.0, 0.2, 0.5, 1.0] {
+            assert_eq!(post(&neutral, x), x);
+        }
+
+        let display = Display::new(DisplaySettings {
+            gamma: 2.0,
+            brightness: 1.5,
+            ..DisplaySettings::default()
+        });
+        let constants = display.post_constants();
+        assert_relative_eq!(post(&constants, 0.25), 0.375f32.sqrt());
+        // Brightening dark regions preserves black and order
+        assert_eq!(post(&constants, 0.0), 0.0);
+        assert!(post(&constants, 0.1) > 0.1 && post(&constants, 0.1) < post(&constants, 0.2));
+
+        // Laid out as the shader expects
+        let bytes = unsafe { crate::graphics::as_bytes(&constants) };
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(bytes[..4], 0.5f32.to_ne_bytes());
+        assert_eq!(bytes[4..], 1.5f32.to_ne_bytes());
+
+        // Nonsense is tamed
+        let display = Display::new(DisplaySettings {
+            gamma: 0.0,
+            brightness: -1.0,
+            ..DisplaySettings::default()
+        });
+        let constants = display.post_constants();
+        assert!(constants.inverse_gamma.is_finite());
+        assert_eq!(constants.brightness, 0.0);
+    }
+
+    #[test]
+    fn render_extent() {
+        let extent = vk::Extent2D {
+            width: 1920,
+            height: 1080,
+        };
+        let scaled = |render_scale| {
+            DisplaySettings {
+                render_scale,
+                ..DisplaySettings::default()
+            }
+            .sanitize()
+            .render_extent(extent)
+        };
+        assert_eq!(scaled(1.0), extent);
+        assert_eq!(
+            scaled(0.5),
+            vk::Extent2D {
+                width: 960,
+                height: 540
+            }
+        );
+        assert_eq!(scaled(0.0), scaled(RENDER_SCALE_RANGE.0));
+        assert_eq!(scaled(100.0), scaled(RENDER_SCALE_RANGE.1));
+        let tiny = DisplaySettings::default().render_extent(vk::Extent2D {
+            width: 1,
+            height: 0,
+        });
+        assert_eq!(
+            tiny,
+            vk::Extent2D {
+                width: 1,
+                height: 1
+            }
+        );
+
+        // Reductions to free memory stop at the lower limit
+        let mut display = Display::new(DisplaySettings::default());
+        let mut reductions = 0;
+        while display.reduce_render_scale() {
+            reductions += 1;
+        }
+        assert_eq!(reductions, 5);
+        assert_eq!(display.settings().render_scale, RENDER_SCALE_RANGE.0);
+    }
+
+    #[test]
+    fn fov_transition() {
+        let start = Instant::now();
+        let initial = DisplaySettings::default();
+        let mut display = Display::new(initial);
+        let wide = DisplaySettings {
+            fov: 2.0,
+            ..initial
+        };
+        display.set(wide, start);
+        let at = |secs: f32| start + Duration::from_secs_f32(secs);
+        let duration = initial.fov_transition.as_secs_f32();
+
+        assert_eq!(display.fov(start), initial.fov);
+        let mut last = initial.fov;
+        for i in 1..=10 {
+            let fov = display.fov(at(duration * i as f32 / 10.0));
+            assert!(fov > last);
+            last = fov;
+        }
+        assert_relative_eq!(last, wide.fov, epsilon = 1e-5);
+        assert_eq!(display.fov(at(10.0)), wide.fov);
+
+        // Interrupting a transition starts the next from where it got to
+        display.set(wide, start);
+        display.set(initial, at(duration / 2.0));
+        let midway = display.fov(at(duration / 2.0));
+        assert!(midway > initial.fov && midway < wide.fov);
+
+        // Disabled transitions snap
+        display.set(
+            DisplaySettings {
+                fov_transition: Duration::ZERO,
+                ..wide
+            },
+            start,
+        );
+        assert_eq!(display.fov(start), wide.fov);
+    }
+
+    #[test]
+    fn projection_tracks_fov() {
+        let start = Instant::now();
+        let mut display = Display::new(DisplaySettings::default());
+        display.set(
+            DisplaySettings {
+                fov: 1.0,
+                ..DisplaySettings::default()
+            },
+            start,
+        );
+        for secs in [0.0, 0.05, 0.1, 1.0] {
+            let now = start + Duration::from_secs_f32(secs);
+            let projection = display.frustum(1.5, now).projection(1.0e-4);
+            let focal_length = (display.fov(now) * 0.5).tan().recip();
+            assert_relative_eq!(projection[(1, 1)], -focal_length, epsilon = 1e-5);
+            assert_relative_eq!(projection[(0, 0)], focal_length / 1.5, epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    fn view_model_projection_independent_of_fov() {
+        let start = Instant::now();
+        let mut display = Display::new(DisplaySettings::default());
+        let projection = |display: &Display| display.view_model_frustum(1.5).projection(1.0e-2);
+        let initial = projection(&display);
+        let focal_length = (display.settings().viewmodel_fov * 0.5).tan().recip();
+        assert_relative_eq!(initial[(1, 1)], -focal_length, epsilon = 1e-5);
+        assert_relative_eq!(initial[(0, 0)], focal_length / 1.5, epsilon = 1e-5);
+
+        // Neither a new field of view for the world nor the transition into it affects the block
+        display.set(
+            DisplaySettings {
+                fov: 2.5,
+                ..DisplaySettings::default()
+            },
+            start,
+        );
+        for secs in [0.0, 0.1, 1.0] {
+            let now = start + Duration::from_secs_f32(secs);
+            assert_ne!(display.frustum(1.5, now).projection(1.0e-2), initial);
+            assert_eq!(projection(&display), initial);
+        }
+
+        // While its own setting does, immediately
+        display.set(
+            DisplaySettings {
+                fov: 2.5,
+                viewmodel_fov: 1.0,
+                ..DisplaySettings::default()
+            },
+            start,
+        );
+        let focal_length = 0.5f32.tan().recip();
+        assert_relative_eq!(projection(&display)[(1, 1)], -focal_length, epsilon = 1e-5);
+        // Within reason
+        display.set(
+            DisplaySettings {
+                viewmodel_fov: 10.0,
+                ..DisplaySettings::default()
+            },
+            start,
+        );
+        assert!(projection(&display)[(1, 1)].is_finite());
+    }
+
+    /// Culling must agree with what's drawn, including while the field of view changes
+    #[test]
+    fn culling_matches_projection() {
+        let start = Instant::now();
+        let mut display = Display::new(DisplaySettings {
+            fov: 1.0,
+            ..DisplaySettings::default()
+        });
+        display.set(
+            DisplaySettings {
+                fov: 2.0,
+                ..DisplaySettings::default()
+            },
+            start,
+        );
+        for secs in [0.0, 0.1, 1.0] {
+            // The frustum a frame is both culled and projected with
+            let frustum = display.frustum(1.5, start + Duration::from_secs_f32(secs));
+            let planes = frustum.planes();
+            let projection = frustum.projection(1.0e-4);
+            for yaw in -8..=8 {
+                for pitch in -8..=8 {
+                    let direction = na::UnitQuaternion::from_euler_angles(
+                        pitch as f32 * 0.15,
+                        yaw as f32 * 0.15,
+                        0.0,
+                    ) * -na::Vector3::z();
+                    let point = math::translate_along(&(direction * 0.5)) * math::origin();
+                    let klein = na::Point3::from(point.xyz() / point.w);
+                    let ndc = projection.transform_point(&klein);
+                    let visible = ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0;
+                    // Points close to the edge could go either way due to rounding
+                    if (ndc.x.abs() - 1.0).abs() < 1e-3 || (ndc.y.abs() - 1.0).abs() < 1e-3 {
+                        continue;
+                    }
+                    assert_eq!(planes.contain(&point, 0.0), visible, "{secs}s: {direction}");
+                }
+            }
+        }
+    }
+}
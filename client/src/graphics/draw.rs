@@ -1,15 +1,29 @@
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use ash::vk;
-use common::traversal::nearby_nodes;
 use lahar::Staged;
 use metrics::histogram;
 
-use super::{fog, voxels, Base, Fog, Frustum, GltfScene, Meshes, Voxels};
-use crate::{Asset, Config, Loader, Sim};
-use common::proto::{Character, Position};
-use common::{math, SimConfig};
+use super::{
+    display::PostConstants, fog, sky, targets::RenderTarget, view_model, voxels, Base, Effects,
+    Fog, Frustum, GltfScene, HeldBlock, Instances, Meshes, Minimap, NameTags, PassTimer, Post,
+    TimingOverlay, Voxels,
+};
+use crate::{
+    breadcrumbs::Breadcrumb,
+    characters::NAME_TAG_ELEVATION,
+    effects::EffectPool,
+    exploration::Exploration,
+    look::LookInput,
+    metrics::{FrameTimings, Pass},
+    view_model::ViewModel,
+    waypoints::{self, PersonalWaypoints},
+    Asset, Config, Loader, Sim,
+};
+use common::proto::Position;
+use common::{math, world::Material, SimConfig};
 
 /// Manages rendering, independent of what is being rendered to
 pub struct Draw {
@@ -17,14 +31,24 @@ pub struct Draw {
     cfg: Arc<Config>,
     /// Used to allocate the command buffers we render with
     cmd_pool: vk::CommandPool,
-    /// Allows accurate frame timing information to be recorded
-    timestamp_pool: vk::QueryPool,
+    /// Records how long each pass of each frame takes
+    timer: PassTimer,
     /// State that varies per frame in flight
     states: Vec<State>,
     /// The index of the next element of `states` to use
     next_state: usize,
+    /// Number of frames submitted so far, each identified by its position in that sequence
+    submitted: u64,
+    /// Latest frame known to have completed, and all frames before it
+    completed: u64,
     /// A reference time
     epoch: Instant,
+    /// Distance from the view out to which the world is drawn
+    view_distance: f32,
+    /// Distance from the view at which the fog becomes opaque
+    fog_distance: f32,
+    /// CPU time spent in the latest call to `draw`
+    cpu_time: Duration,
     /// The lowest common denominator between the interfaces of our graphics pipelines
     ///
     /// Represents e.g. the binding for common uniforms
@@ -41,7 +65,20 @@ pub struct Draw {
     /// Populated after connect, once the voxel configuration is known
     voxels: Option<Voxels>,
     meshes: Meshes,
+    effects: Effects,
+    held_block: HeldBlock,
+    name_tags: NameTags,
     fog: Fog,
+    minimap: Minimap,
+    post: Post,
+    timing_overlay: TimingOverlay,
+
+    /// Effects anchored to voxel faces that are currently visible
+    effect_pool: EffectPool,
+    /// Animation of the block held in the first-person view
+    view_model: ViewModel,
+    /// When `view_model` was last advanced
+    view_model_updated: Instant,
 
     /// Reusable storage for barriers that prevent races between image upload and read
     image_barriers: Vec<vk::ImageMemoryBarrier>,
@@ -54,7 +91,10 @@ pub struct Draw {
 
 /// Maximum number of simultaneous frames in flight
 const PIPELINE_DEPTH: u32 = 2;
-const TIMESTAMPS_PER_FRAME: u32 = 3;
+
+/// Angle in radians the view may turn between culling and writing the uniforms, beyond which
+/// geometry turned into view may be missing for a frame
+const LATE_LATCH_MARGIN: f32 = 0.05;
 
 impl Draw {
     pub fn new(gfx: Arc<Base>, cfg: Arc<Config>) -> Self {
@@ -80,15 +120,7 @@ impl Draw {
                 )
                 .unwrap();
 
-            let timestamp_pool = device
-                .create_query_pool(
-                    &vk::QueryPoolCreateInfo::builder()
-                        .query_type(vk::QueryType::TIMESTAMP)
-                        .query_count(TIMESTAMPS_PER_FRAME * PIPELINE_DEPTH),
-                    None,
-                )
-                .unwrap();
-            gfx.set_name(timestamp_pool, cstr!("timestamp pool"));
+            let timer = PassTimer::new(&gfx, PIPELINE_DEPTH);
 
             let common_pipeline_layout = device
                 .create_pipeline_layout(
@@ -162,10 +194,11 @@ impl Draw {
                             )
                             .unwrap(),
                         uniforms,
-                        used: false,
                         in_flight: false,
+                        frame: 0,
 
                         voxels: None,
+                        characters: Instances::new(&gfx),
                     };
                     gfx.set_name(x.cmd, cstr!("frame"));
                     gfx.set_name(x.post_cmd, cstr!("post-frame"));
@@ -178,8 +211,18 @@ impl Draw {
 
             let meshes = Meshes::new(&gfx, loader.ctx().mesh_ds_layout);
 
+            let effects = Effects::new(&gfx);
+            let held_block = HeldBlock::new(&gfx);
+            let name_tags = NameTags::new(&gfx);
+
             let fog = Fog::new(&gfx);
 
+            let minimap = Minimap::new(&gfx);
+
+            let post = Post::new(&gfx);
+
+            let timing_overlay = TimingOverlay::new(&gfx);
+
             gfx.save_pipeline_cache();
 
             let character_model = loader.load(
@@ -193,10 +236,15 @@ impl Draw {
                 gfx,
                 cfg,
                 cmd_pool,
-                timestamp_pool,
+                timer,
                 states,
                 next_state: 0,
+                submitted: 0,
+                completed: 0,
                 epoch: Instant::now(),
+                view_distance: cfg.local_simulation.view_distance,
+                fog_distance: cfg.local_simulation.view_distance,
+                cpu_time: Duration::ZERO,
                 common_pipeline_layout,
                 common_descriptor_pool,
 
@@ -204,7 +252,17 @@ impl Draw {
 
                 voxels: None,
                 meshes,
+                effects,
+                held_block,
+                name_tags,
                 fog,
+                minimap,
+                post,
+                timing_overlay,
+
+                effect_pool: EffectPool::new(),
+                view_model: ViewModel::new(Material::Void),
+                view_model_updated: Instant::now(),
 
                 buffer_barriers: Vec::new(),
                 image_barriers: Vec::new(),
@@ -214,6 +272,11 @@ impl Draw {
         }
     }
 
+    /// Prefer assets from the pack at `path` when loading those that depend on the server
+    pub fn set_server_pack(&mut self, path: Option<PathBuf>) {
+        self.loader.set_server_pack(path);
+    }
+
     /// Called with server-defined world parameters once they're known
     pub fn configure(&mut self, cfg: &SimConfig) {
         let voxels = Voxels::new(
@@ -237,6 +300,59 @@ impl Draw {
         let state = &mut self.states[self.next_state];
         device.wait_for_fences(&[state.fence], true, !0).unwrap();
         state.in_flight = false;
+        // Frames complete in the order they're submitted
+        self.completed = self.completed.max(state.frame);
+    }
+
+    /// Number of frames submitted so far, which is also the most recent frame's sequence number
+    pub fn submitted(&self) -> u64 {
+        self.submitted
+    }
+
+    /// Sequence number of the latest frame known to have completed
+    ///
+    /// Updated by `wait` and `wait_idle`.
+    pub fn completed(&self) -> u64 {
+        self.completed
+    }
+
+    /// Set the distance out to which the world is drawn, and where the fog hiding its edge becomes
+    /// opaque
+    pub fn set_view_distance(&mut self, view_distance: f32, fog_distance: f32) {
+        self.view_distance = view_distance;
+        self.fog_distance = fog_distance;
+    }
+
+    /// CPU time spent in the latest call to `draw`
+    pub fn cpu_time(&self) -> Duration {
+        self.cpu_time
+    }
+
+    /// GPU time spent on the latest frame known to have completed, or zero if the device can't
+    /// tell
+    pub fn gpu_time(&self) -> Duration {
+        self.timer
+            .history()
+            .latest()
+            .and_then(FrameTimings::gpu_total)
+            .unwrap_or_default()
+    }
+
+    /// Show or hide the breakdown of recent frames' times by pass
+    pub fn toggle_timing_overlay(&mut self) {
+        self.timing_overlay.toggle();
+    }
+
+    /// Time the latest call to `draw` spent preparing chunk surfaces for the GPU
+    pub fn upload_time(&self) -> Duration {
+        self.voxels
+            .as_ref()
+            .map_or(Duration::ZERO, Voxels::upload_time)
+    }
+
+    /// Fraction of the capacity for generating chunks that's in use
+    pub fn backlog(&self) -> f32 {
+        self.voxels.as_ref().map_or(0.0, Voxels::backlog)
     }
 
     /// Semaphore that must be signaled when an output framebuffer can be rendered to
@@ -248,27 +364,88 @@ impl Draw {
 
     /// Submit commands to the GPU to draw a frame
     ///
-    /// `framebuffer` must have a color and depth buffer attached and have the dimensions specified
-    /// in `extent`. The `present` semaphore is signaled when rendering is complete and the color
-    /// image can be presented.
+    /// The world is rendered to `target`, then scaled to fill `output`, which must be a framebuffer
+    /// for `Base::post_render_pass` with the dimensions specified in `output_extent`. The `present`
+    /// semaphore is signaled when rendering is complete and the output image can be presented.
     ///
-    /// Submits commands that wait on `image_acquired` before writing to `framebuffer`'s color
+    /// Submits commands that wait on `image_acquired` before writing to `output`'s color
     /// attachment.
+    #[allow(clippy::too_many_arguments)]
     pub unsafe fn draw(
         &mut self,
         mut sim: Option<&mut Sim>,
-        framebuffer: vk::Framebuffer,
-        depth_view: vk::ImageView,
-        extent: vk::Extent2D,
+        exploration: Option<&Exploration>,
+        personal_waypoints: Option<&PersonalWaypoints>,
+        target: &RenderTarget,
+        output: vk::Framebuffer,
+        output_extent: vk::Extent2D,
         present: vk::Semaphore,
         frustum: &Frustum,
+        view_model_frustum: &Frustum,
+        post: &PostConstants,
+        look: &LookInput,
     ) {
+        let extent = target.extent;
         let draw_started = Instant::now();
-        let view = sim.as_ref().map_or_else(Position::origin, |sim| sim.view());
+        let view = sim.as_ref().and_then(|sim| sim.camera().position());
+        if view.is_none() {
+            // Nowhere to see the world from yet, so only the sky is drawn, as before connecting
+            sim = None;
+        }
+        let view = view.unwrap_or_else(Position::origin);
         let projection = frustum.projection(1.0e-4);
         let view_projection = projection.matrix() * math::mtranspose(&view.local);
+        // Culling must keep whatever the view may yet turn towards before the uniforms are written
+        let frustum_planes = frustum.planes().turned(LATE_LATCH_MARGIN);
+        // Lighting follows the day/night cycle, relative to the terrain of the viewpoint's node
+        let world_time = sim.as_ref().map_or(0.25, |sim| sim.world_time());
+        let up = sim
+            .as_ref()
+            .and_then(|sim| sim.graph.get(view.node).as_ref())
+            .map_or_else(na::Vector3::y_axis, |node| {
+                na::UnitVector3::new_normalize(node.state.up_direction().xyz())
+            });
         self.loader.drive();
 
+        let now = Instant::now();
+        let view_distance = f64::from(self.view_distance);
+        let nodes = sim
+            .as_deref_mut()
+            .map_or_else(Vec::new, |sim| sim.nearby_nodes(view_distance));
+        if let Some(sim) = sim.as_mut() {
+            self.effect_pool.update(&sim.graph, now);
+            let target = sim.target().ok().flatten();
+            self.effect_pool
+                .set_target(&sim.graph, target.as_ref(), now);
+            for hit in sim.take_broken_faces() {
+                self.effect_pool.burst(&sim.graph, &hit, now);
+            }
+            for hit in sim.take_obstructed_faces() {
+                self.effect_pool.obstructed(&sim.graph, &hit, now);
+            }
+        }
+        // Gestures first, so a block placed with the last of a material is seen being placed
+        // before it's lowered out of view
+        let held = sim.as_mut().and_then(|sim| {
+            for gesture in sim.take_gestures() {
+                self.view_model.gesture(gesture);
+            }
+            sim.held_in_view()
+        });
+        if let Some(material) = held {
+            self.view_model.select(material);
+        }
+        let (velocity, footstep_phase) = sim
+            .as_ref()
+            .map_or((na::zero(), 0.0), |sim| sim.camera_motion());
+        self.view_model.update(
+            now.saturating_duration_since(self.view_model_updated)
+                .as_secs_f32(),
+            &velocity,
+            footstep_phase,
+        );
+        self.view_model_updated = now;
+
         let device = &*self.gfx.device;
         let state_index = self.next_state;
         let state = &mut self.states[self.next_state];
@@ -287,36 +464,16 @@ impl Draw {
                 .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
                 .image_info(&[vk::DescriptorImageInfo {
                     sampler: vk::Sampler::null(),
-                    image_view: depth_view,
+                    image_view: target.depth_view,
                     image_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
                 }])
                 .build()],
             &[],
         );
 
-        // Handle completed queries
-        let first_query = state_index as u32 * TIMESTAMPS_PER_FRAME;
-        if state.used {
-            // Collect timestamps from the last time we drew this frame
-            let mut queries = [0u64; TIMESTAMPS_PER_FRAME as usize];
-            // `WAIT` is guaranteed not to block here because `Self::draw` is only called after
-            // `Self::wait` ensures that the prior instance of this frame is complete.
-            device
-                .get_query_pool_results(
-                    self.timestamp_pool,
-                    first_query,
-                    TIMESTAMPS_PER_FRAME,
-                    &mut queries,
-                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
-                )
-                .unwrap();
-            let draw_seconds =
-                self.gfx.limits.timestamp_period as f64 * 1e-9 * (queries[1] - queries[0]) as f64;
-            let after_seconds =
-                self.gfx.limits.timestamp_period as f64 * 1e-9 * (queries[2] - queries[1]) as f64;
-            histogram!("frame.gpu.draw", draw_seconds);
-            histogram!("frame.gpu.after_draw", after_seconds);
-        }
+        // Collect timings of frames since completed. `Self::wait` ensures that includes the prior
+        // instance of this frame, whose queries are about to be reused.
+        self.timer.resolve(device, self.completed);
 
         device
             .begin_command_buffer(
@@ -333,57 +490,59 @@ impl Draw {
             )
             .unwrap();
 
-        device.cmd_reset_query_pool(cmd, self.timestamp_pool, first_query, TIMESTAMPS_PER_FRAME);
-        let mut timestamp_index = first_query;
-        device.cmd_write_timestamp(
-            cmd,
-            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-            self.timestamp_pool,
-            timestamp_index,
-        );
-        timestamp_index += 1;
-
-        // Schedule transfer of uniform data. Note that we defer actually preparing the data to just
-        // before submitting the command buffer so time-sensitive values can be set with minimum
-        // latency.
-        state.uniforms.record_transfer(device, cmd);
-        self.buffer_barriers.push(
-            vk::BufferMemoryBarrier::builder()
-                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .dst_access_mask(vk::AccessFlags::SHADER_READ)
-                .buffer(state.uniforms.buffer())
-                .size(vk::WHOLE_SIZE)
-                .build(),
-        );
+        self.timer
+            .begin_frame(device, cmd, self.submitted + 1, state_index as u32);
 
+        // Chunk surfaces are uploaded and extracted on `post_cmd`, after the frame is drawn
         if let (Some(voxels), Some(sim)) = (self.voxels.as_mut(), sim.as_mut()) {
+            let _pass = self
+                .timer
+                .pass_scope(device, state.post_cmd, Pass::Transfer);
             voxels.prepare(
                 device,
                 state.voxels.as_mut().unwrap(),
                 sim,
                 state.post_cmd,
-                frustum,
+                &frustum_planes,
+                self.view_distance,
             );
         }
 
-        // Ensure reads of just-transferred memory wait until it's ready
-        device.cmd_pipeline_barrier(
-            cmd,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
-            vk::DependencyFlags::default(),
-            &[],
-            &self.buffer_barriers,
-            &self.image_barriers,
-        );
-        self.buffer_barriers.clear();
-        self.image_barriers.clear();
+        {
+            let _pass = self.timer.pass_scope(device, cmd, Pass::Transfer);
+
+            // Schedule transfer of uniform data. Note that we defer actually preparing the data to
+            // just before submitting the command buffer so time-sensitive values can be set with
+            // minimum latency.
+            state.uniforms.record_transfer(device, cmd);
+            self.buffer_barriers.push(
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .buffer(state.uniforms.buffer())
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            );
+
+            // Ensure reads of just-transferred memory wait until it's ready
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::default(),
+                &[],
+                &self.buffer_barriers,
+                &self.image_barriers,
+            );
+            self.buffer_barriers.clear();
+            self.image_barriers.clear();
+        }
 
         device.cmd_begin_render_pass(
             cmd,
             &vk::RenderPassBeginInfo::builder()
                 .render_pass(self.gfx.render_pass)
-                .framebuffer(framebuffer)
+                .framebuffer(target.buffer)
                 .render_area(vk::Rect2D {
                     offset: vk::Offset2D::default(),
                     extent,
@@ -425,75 +584,284 @@ impl Draw {
 
         // Record the actual rendering commands
         if let Some(ref mut voxels) = self.voxels {
+            let _pass = self.timer.pass_scope(device, cmd, Pass::Voxels);
             voxels.draw(
                 device,
                 &self.loader,
                 state.common_ds,
                 state.voxels.as_ref().unwrap(),
                 cmd,
+                voxels::Pass::Opaque,
             );
         }
 
+        // Other characters, out to the radius within which the server reports them
+        let local_to_view = math::mtranspose(&view.local);
+        let characters = sim.as_deref().map_or_else(Vec::new, |sim| {
+            let mut characters = sim.visible_characters(
+                &nodes,
+                &(view.local * math::origin()),
+                sim.cfg().view_distance,
+            );
+            // Enough to keep the name tag above a character's head in view
+            let radius = sim.cfg().character.character_radius
+                + NAME_TAG_ELEVATION * sim.cfg().meters_to_absolute;
+            characters.retain(|ch| {
+                frustum_planes.contain(&(local_to_view * ch.body * math::origin()), radius)
+            });
+            characters
+        });
         if let Some(sim) = sim.as_deref() {
-            for (node, transform) in nearby_nodes(
-                &sim.graph,
-                &view,
-                f64::from(self.cfg.local_simulation.view_distance),
-            ) {
-                for &entity in sim.graph_entities.get(node) {
-                    if sim.local_character == Some(entity) {
-                        // Don't draw ourself
-                        continue;
-                    }
-                    let pos = sim
-                        .world
-                        .get::<&Position>(entity)
-                        .expect("positionless entity in graph");
-                    if let Some(character_model) = self.loader.get(self.character_model) {
-                        if let Ok(ch) = sim.world.get::<&Character>(entity) {
-                            let transform = transform
-                                * pos.local
-                                * na::Matrix4::new_scaling(sim.cfg().meters_to_absolute)
-                                * ch.state.orientation.to_homogeneous();
-                            for mesh in &character_model.0 {
-                                self.meshes
-                                    .draw(device, state.common_ds, cmd, mesh, &transform);
-                            }
-                        }
-                    }
+            let _pass = self.timer.pass_scope(device, cmd, Pass::Meshes);
+            state.characters.clear();
+            let scale = na::Matrix4::new_scaling(sim.cfg().meters_to_absolute);
+            for ch in &characters {
+                if !state.characters.push(&(ch.body * scale)) {
+                    break;
+                }
+            }
+            if let Some(character_model) = self.loader.get(self.character_model) {
+                for mesh in &character_model.0 {
+                    self.meshes
+                        .draw(device, state.common_ds, cmd, mesh, &state.characters);
                 }
             }
         }
 
+        // Blended over everything opaque
+        if let Some(ref mut voxels) = self.voxels {
+            let _pass = self.timer.pass_scope(device, cmd, Pass::Transparent);
+            voxels.draw(
+                device,
+                &self.loader,
+                state.common_ds,
+                state.voxels.as_ref().unwrap(),
+                cmd,
+                voxels::Pass::Transparent,
+            );
+        }
+
+        // Held in front of everything, in a sliver of depth the world never reaches
+        if let (Some(layer), Some(materials)) = (
+            self.view_model.layer().filter(|_| held.is_some()),
+            self.voxels.as_ref().and_then(Voxels::materials),
+        ) {
+            let _pass = self.timer.pass_scope(device, cmd, Pass::Meshes);
+            device.cmd_set_viewport(
+                cmd,
+                0,
+                &[vk::Viewport {
+                    min_depth: view_model::MIN_DEPTH,
+                    ..viewports[0]
+                }],
+            );
+            self.held_block.draw(
+                device,
+                state.common_ds,
+                cmd,
+                materials,
+                view_model_frustum.projection(view_model::ZNEAR).matrix(),
+                &self.view_model.transform(),
+                layer,
+            );
+            device.cmd_set_viewport(cmd, 0, &viewports);
+        }
+
         device.cmd_next_subpass(cmd, vk::SubpassContents::INLINE);
 
-        self.fog.draw(device, state.common_ds, cmd);
+        {
+            let _pass = self.timer.pass_scope(device, cmd, Pass::Overlay);
+
+            self.effects.draw(
+                device,
+                cmd,
+                &self.effect_pool,
+                &nodes,
+                &view_projection,
+                &local_to_view,
+                &frustum_planes,
+                now,
+            );
+
+            if let Some(sim) = sim.as_deref() {
+                let projectiles = sim.visible_projectiles(
+                    &nodes,
+                    &(view.local * math::origin()),
+                    sim.cfg().view_distance,
+                );
+                self.effects.draw_projectiles(
+                    device,
+                    cmd,
+                    &projectiles,
+                    projection.matrix(),
+                    &local_to_view,
+                    &frustum_planes,
+                    sim.cfg().projectile_radius,
+                );
+            }
+
+            if let Some(sim) = sim.as_deref() {
+                self.name_tags.draw(
+                    device,
+                    cmd,
+                    &characters,
+                    projection.matrix(),
+                    &local_to_view,
+                    sim.cfg().meters_to_absolute,
+                );
+
+                let mut signs = sim.visible_signs(&nodes, &(view.local * math::origin()));
+                // Enough to keep a whole face in view
+                let radius = sim.cfg().meters_to_absolute;
+                signs.retain(|sign| {
+                    let center = math::lorentz_normalize(&(sign.frame * math::origin()));
+                    frustum_planes.contain(&(local_to_view * center), radius)
+                });
+                self.name_tags
+                    .draw_signs(device, cmd, &signs, &view_projection);
+
+                let waypoints = sim
+                    .shared_waypoints()
+                    .chain(
+                        personal_waypoints
+                            .into_iter()
+                            .flat_map(PersonalWaypoints::iter),
+                    )
+                    .map(|waypoint| {
+                        let breadcrumb = Breadcrumb::from(waypoint);
+                        // Where the graph itself places the waypoint, if its node is rendered
+                        let rendered = waypoint.path.resolve(&sim.graph).and_then(|node| {
+                            nodes
+                                .iter()
+                                .find(|&&(id, _)| id == node)
+                                .map(|&(_, transform)| transform * waypoint.point())
+                        });
+                        let point =
+                            rendered.unwrap_or_else(|| breadcrumb.locate(&sim.graph, view.node));
+                        let marker = waypoints::marker(
+                            projection.matrix(),
+                            &(local_to_view * point),
+                            rendered.is_some(),
+                        );
+                        (marker, waypoint, breadcrumb.distance(&sim.graph, &view))
+                    })
+                    .collect::<Vec<_>>();
+                self.name_tags.draw_waypoints(
+                    device,
+                    cmd,
+                    &waypoints,
+                    projection.matrix(),
+                    sim.cfg().meters_to_absolute,
+                    extent.width as f32 / extent.height as f32,
+                );
+                self.name_tags.draw_connection(
+                    device,
+                    cmd,
+                    sim.connection_quality(),
+                    extent.width as f32 / extent.height as f32,
+                );
+                if let Some(name) = sim.follow_lost() {
+                    self.name_tags.draw_notice(
+                        device,
+                        cmd,
+                        &format!("lost {name}"),
+                        extent.width as f32 / extent.height as f32,
+                    );
+                }
+            }
+        }
+
+        device.cmd_next_subpass(cmd, vk::SubpassContents::INLINE);
+
+        {
+            let _pass = self.timer.pass_scope(device, cmd, Pass::Post);
+            self.fog.draw(device, state.common_ds, cmd);
+        }
+
+        if let Some(sim) = sim.as_deref() {
+            let _pass = self.timer.pass_scope(device, cmd, Pass::Overlay);
+            self.minimap
+                .update(sim, exploration, self.cfg.minimap_distance);
+            self.minimap.draw(device, cmd, extent);
+        }
+
+        // Untimed, as it reads the timer
+        self.timing_overlay.draw(device, cmd, self.timer.history());
 
-        // Finish up
         device.cmd_end_render_pass(cmd);
-        device.cmd_write_timestamp(
-            cmd,
-            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-            self.timestamp_pool,
-            timestamp_index,
-        );
-        timestamp_index += 1;
-        device.end_command_buffer(cmd).unwrap();
 
-        device.cmd_write_timestamp(
-            state.post_cmd,
-            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-            self.timestamp_pool,
-            timestamp_index,
-        );
+        // Fit the rendered image to the window
+        {
+            let _pass = self.timer.pass_scope(device, cmd, Pass::Post);
+            device.cmd_begin_render_pass(
+                cmd,
+                &vk::RenderPassBeginInfo::builder()
+                    .render_pass(self.gfx.post_render_pass)
+                    .framebuffer(output)
+                    .render_area(vk::Rect2D {
+                        offset: vk::Offset2D::default(),
+                        extent: output_extent,
+                    }),
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_set_viewport(
+                cmd,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: output_extent.width as f32,
+                    height: output_extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(
+                cmd,
+                0,
+                &[vk::Rect2D {
+                    offset: vk::Offset2D::default(),
+                    extent: output_extent,
+                }],
+            );
+            self.post.draw(device, target.post_ds, cmd, post);
+            device.cmd_end_render_pass(cmd);
+        }
+
+        // Finish up
+        device.end_command_buffer(cmd).unwrap();
         device.end_command_buffer(state.post_cmd).unwrap();
 
+        // Turn the view by whatever look input arrived while the frame was recorded. Only the
+        // uniforms see the turn; overlays drawn with push constants, like effects, name tags, and
+        // waypoint markers, keep the orientation the frame was recorded with.
+        let view_projection = match sim.as_deref_mut() {
+            Some(sim) => {
+                if let Some(delta) = look.drain() {
+                    sim.look(delta.yaw, delta.pitch, delta.roll);
+                }
+                match sim.latched_camera().position() {
+                    Some(latched) if latched.node == view.node => {
+                        projection.matrix() * math::mtranspose(&latched.local)
+                    }
+                    _ => view_projection,
+                }
+            }
+            None => view_projection,
+        };
+        if let Some(latency) = look.rendered(Instant::now()) {
+            histogram!("input.look_latency", latency);
+        }
+
         // Specify the uniform data before actually submitting the command to transfer it
         state.uniforms.write(Uniforms {
             view_projection,
             inverse_projection: *projection.inverse().matrix(),
-            fog_density: fog::density(self.cfg.local_simulation.view_distance, 1e-3, 5.0),
+            fog_density: fog::density(self.fog_distance, 1e-3, 5.0),
             time: self.epoch.elapsed().as_secs_f32().fract(),
+            _padding: [0.0; 2],
+            sky_color: sky::color(world_time).push(1.0),
+            sun: sky::sun(world_time, &up),
         });
 
         // Submit the commands to the GPU
@@ -514,21 +882,25 @@ impl Draw {
                 state.fence,
             )
             .unwrap();
-        state.used = true;
+        self.submitted += 1;
+        state.frame = self.submitted;
         state.in_flight = true;
-        histogram!("frame.cpu", draw_started.elapsed());
+        self.timer.end_frame();
+        self.cpu_time = draw_started.elapsed();
+        histogram!("frame.cpu", self.cpu_time);
     }
 
     /// Wait for all drawing to complete
     ///
     /// Useful to e.g. ensure it's safe to deallocate an image that's being rendered to
-    pub fn wait_idle(&self) {
+    pub fn wait_idle(&mut self) {
         let device = &*self.gfx.device;
         for state in &self.states {
             unsafe {
                 device.wait_for_fences(&[state.fence], true, !0).unwrap();
             }
         }
+        self.completed = self.submitted;
     }
 }
 
@@ -544,15 +916,22 @@ impl Drop for Draw {
                 device.destroy_semaphore(state.image_acquired, None);
                 device.destroy_fence(state.fence, None);
                 state.uniforms.destroy(device);
+                state.characters.destroy(device);
                 if let Some(mut voxels) = state.voxels.take() {
                     voxels.destroy(device);
                 }
             }
             device.destroy_command_pool(self.cmd_pool, None);
-            device.destroy_query_pool(self.timestamp_pool, None);
+            self.timer.destroy(device);
             device.destroy_descriptor_pool(self.common_descriptor_pool, None);
             device.destroy_pipeline_layout(self.common_pipeline_layout, None);
+            self.effects.destroy(device);
+            self.held_block.destroy(device);
+            self.name_tags.destroy(device);
             self.fog.destroy(device);
+            self.minimap.destroy(device);
+            self.post.destroy(device);
+            self.timing_overlay.destroy(device);
             self.meshes.destroy(device);
             if let Some(mut voxels) = self.voxels.take() {
                 voxels.destroy(device);
@@ -574,17 +953,17 @@ struct State {
     common_ds: vk::DescriptorSet,
     /// The common uniform buffer
     uniforms: Staged<Uniforms>,
-    /// Whether this state has been previously used
-    ///
-    /// Indicates that e.g. valid timestamps are associated with this query
-    used: bool,
     /// Whether this state is currently being accessed by the GPU
     ///
     /// True for the period between `cmd` being submitted and `fence` being waited.
     in_flight: bool,
+    /// Sequence number of the last frame drawn with this state
+    frame: u64,
 
     // Per-pipeline states
     voxels: Option<voxels::Frame>,
+    /// Transforms of the characters drawn this frame
+    characters: Instances,
 }
 
 /// Data stored in the common uniform buffer
@@ -600,4 +979,10 @@ struct Uniforms {
     fog_density: f32,
     /// Cycles through [0,1) once per second for simple animation effects
     time: f32,
+    _padding: [f32; 2],
+    /// Color of the sky and fog. The w component is unused.
+    sky_color: na::Vector4<f32>,
+    /// Direction towards the sun in the viewpoint node's coordinates, with the strength of direct
+    /// sunlight in the w component
+    sun: na::Vector4<f32>,
 }
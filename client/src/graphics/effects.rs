@@ -0,0 +1,260 @@
+use std::mem;
+use std::time::Instant;
+
+use ash::{vk, Device};
+use vk_shader_macros::include_glsl;
+
+use super::{frustum::FrustumPlanes, minimap::material_color, Base};
+use crate::{
+    characters::billboard,
+    effects::{EffectKind, EffectPool},
+};
+use common::{defer, graph::NodeId, math, world::Material};
+
+const VERT: &[u32] = include_glsl!("shaders/effects.vert");
+const FRAG: &[u32] = include_glsl!("shaders/effects.frag");
+
+/// Number of particles in a burst
+const BURST_PARTICLES: u32 = 12;
+
+/// Translucent decals and particles attached to voxel faces, drawn over opaque geometry
+pub struct Effects {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl Effects {
+    pub fn new(gfx: &Base) -> Self {
+        let device = &*gfx.device;
+        unsafe {
+            // Construct the shader modules
+            let vert = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(VERT), None)
+                .unwrap();
+            // Note that these only need to live until the pipeline itself is constructed
+            let v_guard = defer(|| device.destroy_shader_module(vert, None));
+
+            let frag = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(FRAG), None)
+                .unwrap();
+            let f_guard = defer(|| device.destroy_shader_module(frag, None));
+
+            // Everything is supplied through push constants
+            let pipeline_layout = device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&[
+                        vk::PushConstantRange {
+                            stage_flags: vk::ShaderStageFlags::VERTEX
+                                | vk::ShaderStageFlags::FRAGMENT,
+                            offset: 0,
+                            size: mem::size_of::<PushConstants>() as u32,
+                        },
+                    ]),
+                    None,
+                )
+                .unwrap();
+
+            let entry_point = cstr!("main").as_ptr();
+            let mut pipelines = device
+                .create_graphics_pipelines(
+                    gfx.pipeline_cache,
+                    &[vk::GraphicsPipelineCreateInfo::builder()
+                        .stages(&[
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::VERTEX,
+                                module: vert,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::FRAGMENT,
+                                module: frag,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                        ])
+                        .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                        .input_assembly_state(
+                            &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                        )
+                        .viewport_state(
+                            &vk::PipelineViewportStateCreateInfo::builder()
+                                .scissor_count(1)
+                                .viewport_count(1),
+                        )
+                        .rasterization_state(
+                            &vk::PipelineRasterizationStateCreateInfo::builder()
+                                .cull_mode(vk::CullModeFlags::NONE)
+                                .polygon_mode(vk::PolygonMode::FILL)
+                                .line_width(1.0),
+                        )
+                        .multisample_state(
+                            &vk::PipelineMultisampleStateCreateInfo::builder()
+                                .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                        )
+                        // Occluded by, but not occluding, other geometry
+                        .depth_stencil_state(
+                            &vk::PipelineDepthStencilStateCreateInfo::builder()
+                                .depth_test_enable(true)
+                                .depth_write_enable(false)
+                                .depth_compare_op(vk::CompareOp::GREATER_OR_EQUAL),
+                        )
+                        // Premultiplied alpha, so decals can darken and particles can add light
+                        .color_blend_state(
+                            &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                                vk::PipelineColorBlendAttachmentState {
+                                    blend_enable: vk::TRUE,
+                                    src_color_blend_factor: vk::BlendFactor::ONE,
+                                    dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                                    color_blend_op: vk::BlendOp::ADD,
+                                    color_write_mask: vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B,
+                                    ..Default::default()
+                                },
+                            ]),
+                        )
+                        .dynamic_state(
+                            &vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&[
+                                vk::DynamicState::VIEWPORT,
+                                vk::DynamicState::SCISSOR,
+                            ]),
+                        )
+                        .layout(pipeline_layout)
+                        .render_pass(gfx.render_pass)
+                        .subpass(1)
+                        .build()],
+                    None,
+                )
+                .unwrap()
+                .into_iter();
+
+            let pipeline = pipelines.next().unwrap();
+            gfx.set_name(pipeline, cstr!("effects"));
+
+            v_guard.invoke();
+            f_guard.invoke();
+
+            Self {
+                pipeline_layout,
+                pipeline,
+            }
+        }
+    }
+
+    /// Draw the effects in `pool`
+    ///
+    /// `nodes` gives the transforms of nearby nodes relative to the view's node, and `local_to_view`
+    /// maps that node's space into view space.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn draw(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        pool: &EffectPool,
+        nodes: &[(NodeId, na::Matrix4<f32>)],
+        view_projection: &na::Matrix4<f32>,
+        local_to_view: &na::Matrix4<f32>,
+        frustum_planes: &FrustumPlanes,
+        now: Instant,
+    ) {
+        if pool.iter().len() == 0 {
+            return;
+        }
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        for effect in pool.iter() {
+            let Some(&(_, ref node_transform)) = nodes
+                .iter()
+                .find(|&&(node, _)| node == effect.anchor.chunk.node)
+            else {
+                continue;
+            };
+            let anchor_to_local = node_transform * effect.anchor.transform;
+            // Bursts spread a few voxels from their face
+            let radius = 4.0 * effect.anchor.half_extent;
+            if !frustum_planes.contain(&(local_to_view * anchor_to_local * math::origin()), radius)
+            {
+                continue;
+            }
+            let (kind, color, instances) = match effect.kind {
+                EffectKind::Crack => (0.0, [0.0, 0.0, 0.0, 0.7], 1),
+                EffectKind::Burst => (1.0, [1.0, 0.85, 0.6, 0.8], BURST_PARTICLES),
+                EffectKind::Obstructed => (2.0, [0.9, 0.1, 0.1, 0.4], 1),
+            };
+            let constants = PushConstants {
+                transform: view_projection * anchor_to_local,
+                color: color.into(),
+                params: na::Vector4::new(
+                    kind,
+                    effect.progress(now),
+                    effect.anchor.half_extent,
+                    effect.seed,
+                ),
+            };
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                super::as_bytes(&constants),
+            );
+            device.cmd_draw(cmd, 6, instances, 0, 0);
+        }
+    }
+
+    /// Draw thrown blocks in flight as squares facing the camera, tinted by their material
+    ///
+    /// `projectiles` gives each one's transform relative to the view's node, which `local_to_view`
+    /// maps into view space, and `radius` is their size in absolute units.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn draw_projectiles(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        projectiles: &[(na::Matrix4<f32>, Material)],
+        projection: &na::Matrix4<f32>,
+        local_to_view: &na::Matrix4<f32>,
+        frustum_planes: &FrustumPlanes,
+        radius: f32,
+    ) {
+        if projectiles.is_empty() {
+            return;
+        }
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        for &(ref transform, material) in projectiles {
+            let center = local_to_view * transform * math::origin();
+            if !frustum_planes.contain(&center, radius) {
+                continue;
+            }
+            let [r, g, b] = material_color(material);
+            let constants = PushConstants {
+                transform: projection * billboard(&center),
+                color: na::Vector4::new(r, g, b, 1.0),
+                // Drawn as an unfading tint the size of the projectile
+                params: na::Vector4::new(2.0, 0.0, radius.tanh(), 0.0),
+            };
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                super::as_bytes(&constants),
+            );
+            device.cmd_draw(cmd, 6, 1, 0, 0);
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PushConstants {
+    transform: na::Matrix4<f32>,
+    color: na::Vector4<f32>,
+    params: na::Vector4<f32>,
+}
@@ -102,7 +102,7 @@ impl Fog {
                         )
                         .layout(pipeline_layout)
                         .render_pass(gfx.render_pass)
-                        .subpass(1)
+                        .subpass(2)
                         .build()],
                     None,
                 )
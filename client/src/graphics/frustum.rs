@@ -1,4 +1,4 @@
-use common::Plane;
+use common::{math, Plane};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Frustum {
@@ -48,6 +48,25 @@ impl Frustum {
                       0.0,       0.0,      -1.0,       0.0))
     }
 
+    /// Compute the view-space ray through a point on the screen, given in normalized device
+    /// coordinates
+    ///
+    /// Points along the ray are exactly those that `projection` maps to `ndc`. Returns the
+    /// Lorentz-normalized position and direction of the ray, which starts at the viewpoint.
+    pub fn screen_to_ray(&self, ndc: na::Point2<f32>) -> (na::Vector4<f32>, na::Vector4<f32>) {
+        // Geodesics through the viewpoint are straight lines through the origin in the
+        // Beltrami-Klein model, so they're determined by where they cross the z = -1 plane. The
+        // depth convention doesn't affect x and y there.
+        let left = self.left.tan();
+        let right = self.right.tan();
+        let down = self.down.tan();
+        let up = self.up.tan();
+        let x = (ndc.x * (right - left) + right + left) * 0.5;
+        let y = (ndc.y * (down - up) + down + up) * 0.5;
+        let direction = na::Vector3::new(x, y, -1.0).normalize();
+        (math::origin(), direction.push(0.0))
+    }
+
     pub fn planes(&self) -> FrustumPlanes {
         FrustumPlanes {
             left: Plane::from(
@@ -66,6 +85,7 @@ impl Frustum {
                 na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), self.up)
                     * -na::Vector3::y_axis(),
             ),
+            turn: 0.0,
         }
     }
 }
@@ -76,12 +96,29 @@ pub struct FrustumPlanes {
     right: Plane<f32>,
     down: Plane<f32>,
     up: Plane<f32>,
+    /// Sine of the angle the view may turn through before the frame is drawn
+    turn: f32,
 }
 
 impl FrustumPlanes {
+    /// Widen the planes to also contain everything the view could see after turning through up to
+    /// `angle` radians about the viewpoint
+    pub fn turned(mut self, angle: f32) -> Self {
+        self.turn = angle.min(std::f32::consts::FRAC_PI_2).sin();
+        self
+    }
+
     pub fn contain(&self, point: &na::Vector4<f32>, radius: f32) -> bool {
+        // Turning moves a point at distance `d` from the viewpoint by up to `asinh(sinh(d) *
+        // sin(angle))` relative to each plane through it, which is largest at the sphere's far side
+        let slack = if self.turn > 0.0 {
+            let far = point.w.max(1.0).acosh() + radius;
+            (self.turn * far.sinh()).asinh()
+        } else {
+            0.0
+        };
         for &plane in &[&self.left, &self.right, &self.down, &self.up] {
-            if plane.distance_to(point) < -radius {
+            if plane.distance_to(point) < -radius - slack {
                 return false;
             }
         }
@@ -92,7 +129,9 @@ impl FrustumPlanes {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use common::math::{origin, translate_along};
+    use approx::*;
+    use common::math::{mip, origin, translate_along};
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
     use std::f32;
 
     #[test]
@@ -108,4 +147,68 @@ mod tests {
         assert!(!planes.contain(&(translate_along(&-na::Vector3::x()) * origin()), 0.0));
         assert!(!planes.contain(&(translate_along(&-na::Vector3::y()) * origin()), 0.0));
     }
+
+    #[test]
+    fn turned_planes_contain_turned_view() {
+        let mut rng = SmallRng::seed_from_u64(675);
+        for aspect_ratio in [0.5, 1.0, 1.8] {
+            let planes = Frustum::from_vfov(0.6, aspect_ratio).planes();
+            for angle in [0.01, 0.05, 0.3] {
+                let turned = planes.turned(angle);
+                for _ in 0..2000 {
+                    let direction = na::Vector3::new(
+                        rng.gen_range(-1.0..1.0),
+                        rng.gen_range(-1.0..1.0),
+                        rng.gen_range(-1.0..1.0),
+                    );
+                    let point = translate_along(&(direction * rng.gen_range(0.0..4.0))) * origin();
+                    let radius = rng.gen_range(0.0..1.0);
+                    let axis = na::Unit::new_normalize(na::Vector3::new(
+                        rng.gen_range(-1.0..1.0),
+                        rng.gen_range(-1.0..1.0),
+                        rng.gen_range(-1.0..1.0),
+                    ));
+                    let rotation =
+                        na::UnitQuaternion::from_axis_angle(&axis, rng.gen_range(-angle..angle))
+                            .to_homogeneous();
+                    // Seen by the view after turning, so must not be culled before
+                    if planes.contain(&(rotation.transpose() * point), radius) {
+                        assert!(turned.contain(&point, radius));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn screen_to_ray_inverts_projection() {
+        let frustum = Frustum::from_vfov(f32::consts::FRAC_PI_4, 1.6);
+        let projection = frustum.projection(1.0e-4);
+
+        let (position, direction) = frustum.screen_to_ray(na::Point2::origin());
+        assert_eq!(position, origin());
+        assert_abs_diff_eq!(direction, -na::Vector4::z(), epsilon = 1e-6);
+
+        for ndc in [
+            [0.0, 0.0],
+            [1.0, 1.0],
+            [-1.0, 1.0],
+            [0.3, -0.9],
+            [-0.7, -0.2],
+        ] {
+            let ndc = na::Point2::from(ndc);
+            let (position, direction) = frustum.screen_to_ray(ndc);
+            assert_abs_diff_eq!(mip(&position, &position), -1.0, epsilon = 1e-6);
+            assert_abs_diff_eq!(mip(&direction, &direction), 1.0, epsilon = 1e-6);
+            assert_abs_diff_eq!(mip(&position, &direction), 0.0, epsilon = 1e-6);
+            for tanh_distance in [0.1, 0.5, 0.99] {
+                let point = position + direction * tanh_distance;
+                let klein = na::Point3::from(point.xyz() / point.w);
+                let projected = projection.transform_point(&klein);
+                assert_abs_diff_eq!(projected.xy(), ndc, epsilon = 1e-5);
+                // In front of the viewer, within the depth range
+                assert!(projected.z > 0.0 && projected.z < 1.0);
+            }
+        }
+    }
 }
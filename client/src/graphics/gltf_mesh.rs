@@ -1,7 +1,5 @@
 use std::{
     borrow::Cow,
-    fs,
-    fs::File,
     mem,
     path::{Path, PathBuf},
     ptr,
@@ -30,19 +28,16 @@ impl Loadable for GlbFile {
 
 impl GlbFile {
     async fn load(self, ctx: &LoadCtx) -> Result<GltfScene> {
-        let path = ctx
-            .cfg
-            .find_asset(&self.path)
-            .ok_or_else(|| anyhow!("{} not found", self.path.display()))?;
-
-        let glb = gltf::Glb::from_reader(
-            File::open(&path).with_context(|| format!("opening {}", path.display()))?,
-        )
-        .with_context(|| format!("reading {}", path.display()))?;
-        let gltf = gltf::Document::from_json(
-            gltf::json::deserialize::from_slice(&glb.json).context("JSON parsing")?,
-        )
-        .context("GLTF parsing")?;
+        // Only a model that parses is taken from a pack, so a broken one falls back to the next
+        let (data, gltf) = ctx.sources().resolve(&self.path, |_, data| {
+            let glb = gltf::Glb::from_slice(&data)?;
+            let gltf = gltf::Document::from_json(
+                gltf::json::deserialize::from_slice(&glb.json).context("JSON parsing")?,
+            )
+            .context("GLTF parsing")?;
+            Ok((data, gltf))
+        })?;
+        let glb = gltf::Glb::from_slice(&data)?;
         let buffer = glb
             .bin
             .as_ref()
@@ -397,12 +392,12 @@ async fn load_material(
     };
     let color_data = match color.texture().source().source() {
         gltf::image::Source::Uri { uri, .. } => {
-            let path = ctx
-                .cfg
-                .find_asset(Path::new(uri))
-                .ok_or_else(|| anyhow!("texture {} not found", uri))?;
-            trace!(path = %path.display(), "reading texture");
-            Cow::Owned(fs::read(&path).context("reading texture")?)
+            trace!(uri, "reading texture");
+            Cow::Owned(
+                ctx.sources()
+                    .resolve(Path::new(uri), |_, data| Ok(data))
+                    .context("reading texture")?,
+            )
         }
         gltf::image::Source::View { view, .. } => {
             match view.buffer().source() {
@@ -0,0 +1,285 @@
+//! Recovery from running short of device memory by drawing less, rather than crashing
+//!
+//! An operation that can't find device memory is deferred rather than retried on the spot, since
+//! memory can only be freed once no frame in flight uses it. Between frames, it's retried after
+//! each stage of degradation in turn until it fits.
+
+use std::{collections::VecDeque, fmt};
+
+use super::base::OutOfDeviceMemory;
+
+/// A way of freeing device memory, in the order they're tried
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stage {
+    /// Draw the world to a shorter distance, freeing the surfaces of the farthest chunks
+    ShrinkViewDistance,
+    /// Render the world at a lower resolution
+    ReduceRenderScale,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 2] = [Stage::ShrinkViewDistance, Stage::ReduceRenderScale];
+}
+
+/// What deferred operations are retried against, and what gives up memory to make room for them
+pub trait Degrade {
+    type Op;
+
+    /// Try `op` again
+    fn retry(&mut self, op: &Self::Op) -> Result<(), OutOfDeviceMemory>;
+
+    /// Give up some memory in the way `stage` describes, returning false if there's nothing left
+    /// to give up that way
+    fn degrade(&mut self, stage: Stage) -> bool;
+}
+
+/// Operations waiting for device memory to be freed
+pub struct MemoryPressure<T> {
+    deferred: VecDeque<T>,
+}
+
+impl<T> MemoryPressure<T> {
+    /// Retry `op` when `relieve` is next called
+    pub fn defer(&mut self, op: T) {
+        self.deferred.push_back(op);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deferred.is_empty()
+    }
+
+    /// Retry every deferred operation in order, degrading `target` through each stage in turn
+    /// while one still fails, and starting over from the first stage after the last
+    ///
+    /// Fails with the first operation that can't be made to fit once every stage has nothing left
+    /// to give up, leaving any after it deferred. Whatever was given up stays given up.
+    pub fn relieve<D: Degrade<Op = T>>(&mut self, target: &mut D) -> Result<(), Exhausted<T>> {
+        while let Some(op) = self.deferred.pop_front() {
+            let mut result = target.retry(&op);
+            while let Err(error) = result {
+                let mut degraded = false;
+                for stage in Stage::ALL {
+                    if !target.degrade(stage) {
+                        continue;
+                    }
+                    degraded = true;
+                    result = target.retry(&op);
+                    if result.is_ok() {
+                        break;
+                    }
+                }
+                if !degraded {
+                    return Err(Exhausted { op, error });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> Default for MemoryPressure<T> {
+    fn default() -> Self {
+        Self {
+            deferred: VecDeque::new(),
+        }
+    }
+}
+
+/// An operation that couldn't be made to fit in device memory however little is drawn
+#[derive(Debug)]
+pub struct Exhausted<T> {
+    pub op: T,
+    /// How the last attempt failed
+    pub error: OutOfDeviceMemory,
+}
+
+impl<T: fmt::Debug> fmt::Display for Exhausted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} failed after freeing all that could be: {}",
+            self.op, self.error
+        )
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for Exhausted<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::mem_budget::{Pool, Reservation};
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    enum Event {
+        Retry(u32),
+        Degrade(Stage),
+    }
+
+    /// An allocator with a fixed budget, failing wherever allocations would exceed it or a failure
+    /// is scripted, with each stage freeing part of what it holds
+    struct Mock {
+        pool: &'static Pool,
+        budget: usize,
+        /// Whether each retry fails regardless of the budget, in order
+        injected: VecDeque<bool>,
+        /// What `ShrinkViewDistance` frees, a piece at a time
+        surfaces: Vec<Reservation>,
+        /// What `ReduceRenderScale` halves while it's bigger than `min_targets`
+        targets: Reservation,
+        min_targets: usize,
+        /// Bytes each operation needs
+        sizes: Vec<usize>,
+        /// Allocations made by operations that succeeded
+        allocated: Vec<Reservation>,
+        log: Vec<Event>,
+    }
+
+    impl Mock {
+        fn new(pool: &'static Pool, budget: usize, surfaces: &[usize], targets: usize) -> Self {
+            Self {
+                pool,
+                budget,
+                injected: VecDeque::new(),
+                surfaces: surfaces.iter().map(|&x| pool.reserve(x)).collect(),
+                targets: pool.reserve(targets),
+                min_targets: targets / 4,
+                sizes: Vec::new(),
+                allocated: Vec::new(),
+                log: Vec::new(),
+            }
+        }
+
+        /// Bytes held by every reservation this knows of
+        fn held(&self) -> usize {
+            self.surfaces.iter().map(Reservation::bytes).sum::<usize>()
+                + self.targets.bytes()
+                + self.allocated.iter().map(Reservation::bytes).sum::<usize>()
+        }
+    }
+
+    impl Degrade for Mock {
+        type Op = u32;
+
+        fn retry(&mut self, &op: &u32) -> Result<(), OutOfDeviceMemory> {
+            self.log.push(Event::Retry(op));
+            let size = self.sizes[op as usize];
+            let error = OutOfDeviceMemory {
+                requested: size as u64,
+            };
+            if self.injected.pop_front().unwrap_or(false) || self.pool.bytes() + size > self.budget
+            {
+                return Err(error);
+            }
+            self.allocated.push(self.pool.reserve(size));
+            Ok(())
+        }
+
+        fn degrade(&mut self, stage: Stage) -> bool {
+            let degraded = match stage {
+                Stage::ShrinkViewDistance => self.surfaces.pop().is_some(),
+                Stage::ReduceRenderScale => {
+                    let bytes = self.targets.bytes();
+                    if bytes > self.min_targets {
+                        self.targets.resize((bytes / 2).max(self.min_targets));
+                    }
+                    bytes > self.min_targets
+                }
+            };
+            if degraded {
+                self.log.push(Event::Degrade(stage));
+            }
+            degraded
+        }
+    }
+
+    #[test]
+    fn stages_run_in_order_until_op_fits() {
+        static POOL: Pool = Pool::new("test");
+        let mut mock = Mock::new(&POOL, 100, &[10, 20], 40);
+        // With 70 in use, the first fits only once the farthest surfaces and then some of the
+        // render targets are freed
+        mock.sizes = vec![55, 10];
+        let mut pressure = MemoryPressure::default();
+        pressure.defer(0);
+        pressure.relieve(&mut mock).unwrap();
+        assert_eq!(
+            mock.log,
+            [
+                Event::Retry(0),
+                Event::Degrade(Stage::ShrinkViewDistance),
+                Event::Retry(0),
+                Event::Degrade(Stage::ReduceRenderScale),
+                Event::Retry(0),
+            ]
+        );
+        assert!(pressure.is_empty());
+        // What was given up stays given up, and everything held is counted exactly once
+        assert_eq!(mock.surfaces.len(), 1);
+        assert_eq!(mock.targets.bytes(), 20);
+        assert_eq!(POOL.bytes(), 10 + 20 + 55);
+        assert_eq!(POOL.bytes(), mock.held());
+
+        // Operations that fit straight away degrade nothing further
+        mock.log.clear();
+        pressure.defer(1);
+        pressure.relieve(&mut mock).unwrap();
+        assert_eq!(mock.log, [Event::Retry(1)]);
+        assert_eq!(POOL.bytes(), mock.held());
+        drop(mock);
+        assert_eq!(POOL.bytes(), 0);
+    }
+
+    #[test]
+    fn injected_failures_continue_the_cascade() {
+        static POOL: Pool = Pool::new("test");
+        let mut mock = Mock::new(&POOL, 1000, &[10, 10], 40);
+        mock.sizes = vec![5];
+        // The device runs out despite the budget, until after the second stage
+        mock.injected = [true, true, true].into();
+        let mut pressure = MemoryPressure::default();
+        pressure.defer(0);
+        pressure.relieve(&mut mock).unwrap();
+        assert_eq!(
+            mock.log,
+            [
+                Event::Retry(0),
+                Event::Degrade(Stage::ShrinkViewDistance),
+                Event::Retry(0),
+                Event::Degrade(Stage::ReduceRenderScale),
+                Event::Retry(0),
+                Event::Degrade(Stage::ShrinkViewDistance),
+                Event::Retry(0),
+            ]
+        );
+        assert!(mock.surfaces.is_empty());
+        assert_eq!(mock.allocated.len(), 1);
+        assert_eq!(POOL.bytes(), 20 + 5);
+        assert_eq!(POOL.bytes(), mock.held());
+    }
+
+    #[test]
+    fn exhaustion_gives_up_cleanly() {
+        static POOL: Pool = Pool::new("test");
+        let mut mock = Mock::new(&POOL, 100, &[10], 80);
+        mock.sizes = vec![10, 95, 1];
+        let mut pressure = MemoryPressure::default();
+        for op in 0..3 {
+            pressure.defer(op);
+        }
+        let exhausted = pressure.relieve(&mut mock).unwrap_err();
+        assert_eq!(exhausted.op, 1);
+        assert_eq!(exhausted.error.requested, 95);
+        // Every stage was tried until it had nothing left
+        assert!(mock.surfaces.is_empty());
+        assert_eq!(mock.targets.bytes(), mock.min_targets);
+        // The first operation fit, and the one after the failure is still waiting
+        assert_eq!(mock.allocated.len(), 1);
+        assert!(!pressure.is_empty());
+        // Failed attempts leave nothing allocated
+        assert_eq!(POOL.bytes(), 20 + 10);
+        assert_eq!(POOL.bytes(), mock.held());
+        drop(mock);
+        assert_eq!(POOL.bytes(), 0);
+    }
+}
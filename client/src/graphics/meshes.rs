@@ -1,7 +1,7 @@
 use std::mem;
 
 use ash::{vk, Device};
-use lahar::{BufferRegionAlloc, DedicatedImage};
+use lahar::{BufferRegionAlloc, DedicatedImage, DedicatedMapping};
 use memoffset::offset_of;
 use vk_shader_macros::include_glsl;
 
@@ -37,12 +37,7 @@ impl Meshes {
             let pipeline_layout = device
                 .create_pipeline_layout(
                     &vk::PipelineLayoutCreateInfo::builder()
-                        .set_layouts(&[gfx.common_layout, ds_layout])
-                        .push_constant_ranges(&[vk::PushConstantRange {
-                            stage_flags: vk::ShaderStageFlags::VERTEX,
-                            offset: 0,
-                            size: 64,
-                        }]),
+                        .set_layouts(&[gfx.common_layout, ds_layout]),
                     None,
                 )
                 .unwrap();
@@ -68,11 +63,18 @@ impl Meshes {
                         ])
                         .vertex_input_state(
                             &vk::PipelineVertexInputStateCreateInfo::builder()
-                                .vertex_binding_descriptions(&[vk::VertexInputBindingDescription {
-                                    binding: 0,
-                                    stride: mem::size_of::<Vertex>() as u32,
-                                    input_rate: vk::VertexInputRate::VERTEX,
-                                }])
+                                .vertex_binding_descriptions(&[
+                                    vk::VertexInputBindingDescription {
+                                        binding: 0,
+                                        stride: mem::size_of::<Vertex>() as u32,
+                                        input_rate: vk::VertexInputRate::VERTEX,
+                                    },
+                                    vk::VertexInputBindingDescription {
+                                        binding: 1,
+                                        stride: mem::size_of::<na::Matrix4<f32>>() as u32,
+                                        input_rate: vk::VertexInputRate::INSTANCE,
+                                    },
+                                ])
                                 .vertex_attribute_descriptions(&[
                                     vk::VertexInputAttributeDescription {
                                         location: 0,
@@ -92,6 +94,31 @@ impl Meshes {
                                         format: vk::Format::R32G32B32_SFLOAT,
                                         offset: offset_of!(Vertex, normal) as u32,
                                     },
+                                    // One location per column of the instance's transform
+                                    vk::VertexInputAttributeDescription {
+                                        location: 3,
+                                        binding: 1,
+                                        format: vk::Format::R32G32B32A32_SFLOAT,
+                                        offset: 0,
+                                    },
+                                    vk::VertexInputAttributeDescription {
+                                        location: 4,
+                                        binding: 1,
+                                        format: vk::Format::R32G32B32A32_SFLOAT,
+                                        offset: 16,
+                                    },
+                                    vk::VertexInputAttributeDescription {
+                                        location: 5,
+                                        binding: 1,
+                                        format: vk::Format::R32G32B32A32_SFLOAT,
+                                        offset: 32,
+                                    },
+                                    vk::VertexInputAttributeDescription {
+                                        location: 6,
+                                        binding: 1,
+                                        format: vk::Format::R32G32B32A32_SFLOAT,
+                                        offset: 48,
+                                    },
                                 ]),
                         )
                         .input_assembly_state(
@@ -150,7 +177,7 @@ impl Meshes {
                 .into_iter();
 
             let pipeline = pipelines.next().unwrap();
-            gfx.set_name(pipeline, cstr!("sky"));
+            gfx.set_name(pipeline, cstr!("meshes"));
 
             // Clean up the shaders explicitly, so the defer guards don't hold onto references we're
             // moving into `Self` to be returned
@@ -164,14 +191,18 @@ impl Meshes {
         }
     }
 
+    /// Draw every instance in `instances` of `mesh` in a single call
     pub unsafe fn draw(
         &mut self,
         device: &Device,
         common_ds: vk::DescriptorSet,
         cmd: vk::CommandBuffer,
         mesh: &Mesh,
-        transform: &na::Matrix4<f32>,
+        instances: &Instances,
     ) {
+        if instances.len == 0 {
+            return;
+        }
         device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
         device.cmd_bind_descriptor_sets(
             cmd,
@@ -181,21 +212,19 @@ impl Meshes {
             &[common_ds, mesh.ds],
             &[],
         );
-        device.cmd_push_constants(
+        device.cmd_bind_vertex_buffers(
             cmd,
-            self.pipeline_layout,
-            vk::ShaderStageFlags::VERTEX,
             0,
-            &mem::transmute::<_, [u8; 64]>(*transform),
+            &[mesh.vertices.buffer, instances.transforms.buffer()],
+            &[mesh.vertices.offset, 0],
         );
-        device.cmd_bind_vertex_buffers(cmd, 0, &[mesh.vertices.buffer], &[mesh.vertices.offset]);
         device.cmd_bind_index_buffer(
             cmd,
             mesh.indices.buffer,
             mesh.indices.offset,
             vk::IndexType::UINT32,
         );
-        device.cmd_draw_indexed(cmd, mesh.index_count, 1, 0, 0, 0);
+        device.cmd_draw_indexed(cmd, mesh.index_count, instances.len, 0, 0, 0);
     }
 
     pub unsafe fn destroy(&mut self, device: &Device) {
@@ -204,6 +233,48 @@ impl Meshes {
     }
 }
 
+/// Maximum number of instances drawn per frame
+const MAX_INSTANCES: usize = 1024;
+
+/// Per-frame storage for the transforms of instanced meshes
+pub struct Instances {
+    transforms: DedicatedMapping<[na::Matrix4<f32>]>,
+    len: u32,
+}
+
+impl Instances {
+    pub fn new(gfx: &Base) -> Self {
+        unsafe {
+            let transforms = DedicatedMapping::zeroed_array(
+                &gfx.device,
+                &gfx.memory_properties,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                MAX_INSTANCES,
+            );
+            gfx.set_name(transforms.buffer(), cstr!("mesh instances"));
+            Self { transforms, len: 0 }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Add an instance drawn with `transform`, returning `false` if there's no room
+    pub fn push(&mut self, transform: &na::Matrix4<f32>) -> bool {
+        if self.len as usize == self.transforms.len() {
+            return false;
+        }
+        self.transforms[self.len as usize] = *transform;
+        self.len += 1;
+        true
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        self.transforms.destroy(device);
+    }
+}
+
 #[repr(C)]
 pub struct Vertex {
     pub position: na::Point3<f32>,
@@ -0,0 +1,323 @@
+use std::mem;
+use std::time::{Duration, Instant};
+
+use ash::{vk, Device};
+use vk_shader_macros::include_glsl;
+
+use super::Base;
+use crate::{
+    exploration::{Exploration, ExploredMapNode, NodeSummary},
+    Sim,
+};
+use common::{
+    defer,
+    map_projection::{LocalMap, MapFrame},
+    world::Material,
+};
+
+const VERT: &[u32] = include_glsl!("shaders/minimap.vert");
+const FRAG: &[u32] = include_glsl!("shaders/minimap.frag");
+
+/// How often the map contents are recomputed. Rotation and recentering only need to look right at
+/// a glance, so there's no need to walk the graph every frame.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Radius of the map, as a fraction of the window's height
+const MAP_RADIUS: f32 = 0.2;
+/// Radius of entity markers, as a fraction of the map's radius
+const MARKER_RADIUS: f32 = 0.03;
+/// Fraction of their color's saturation retained by explored nodes that are no longer loaded
+const EXPLORED_SATURATION: f32 = 0.35;
+
+/// Top-down overlay of the nodes and entities surrounding the viewpoint
+pub struct Minimap {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    map: Option<LocalMap>,
+    /// Nodes explored previously that are no longer loaded
+    explored: Vec<ExploredMapNode>,
+    /// Klein radius corresponding to the edge of the map
+    map_extent: f32,
+    /// Map coordinates of the waypoint, pinned to the edge of the map if it lies beyond
+    waypoint: Option<na::Point2<f32>>,
+    last_update: Option<Instant>,
+}
+
+impl Minimap {
+    pub fn new(gfx: &Base) -> Self {
+        let device = &*gfx.device;
+        unsafe {
+            // Construct the shader modules
+            let vert = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(VERT), None)
+                .unwrap();
+            // Note that these only need to live until the pipeline itself is constructed
+            let v_guard = defer(|| device.destroy_shader_module(vert, None));
+
+            let frag = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(FRAG), None)
+                .unwrap();
+            let f_guard = defer(|| device.destroy_shader_module(frag, None));
+
+            // Everything is supplied through push constants
+            let pipeline_layout = device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&[
+                        vk::PushConstantRange {
+                            stage_flags: vk::ShaderStageFlags::VERTEX
+                                | vk::ShaderStageFlags::FRAGMENT,
+                            offset: 0,
+                            size: mem::size_of::<PushConstants>() as u32,
+                        },
+                    ]),
+                    None,
+                )
+                .unwrap();
+
+            let entry_point = cstr!("main").as_ptr();
+            let mut pipelines = device
+                .create_graphics_pipelines(
+                    gfx.pipeline_cache,
+                    &[vk::GraphicsPipelineCreateInfo::builder()
+                        .stages(&[
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::VERTEX,
+                                module: vert,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::FRAGMENT,
+                                module: frag,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                        ])
+                        .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                        .input_assembly_state(
+                            &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                        )
+                        .viewport_state(
+                            &vk::PipelineViewportStateCreateInfo::builder()
+                                .scissor_count(1)
+                                .viewport_count(1),
+                        )
+                        .rasterization_state(
+                            &vk::PipelineRasterizationStateCreateInfo::builder()
+                                .cull_mode(vk::CullModeFlags::NONE)
+                                .polygon_mode(vk::PolygonMode::FILL)
+                                .line_width(1.0),
+                        )
+                        .multisample_state(
+                            &vk::PipelineMultisampleStateCreateInfo::builder()
+                                .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                        )
+                        .depth_stencil_state(
+                            &vk::PipelineDepthStencilStateCreateInfo::builder()
+                                .depth_test_enable(false)
+                                .depth_write_enable(false),
+                        )
+                        .color_blend_state(
+                            &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                                vk::PipelineColorBlendAttachmentState {
+                                    blend_enable: vk::TRUE,
+                                    src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+                                    dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                                    color_blend_op: vk::BlendOp::ADD,
+                                    color_write_mask: vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B,
+                                    ..Default::default()
+                                },
+                            ]),
+                        )
+                        .dynamic_state(
+                            &vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&[
+                                vk::DynamicState::VIEWPORT,
+                                vk::DynamicState::SCISSOR,
+                            ]),
+                        )
+                        .layout(pipeline_layout)
+                        .render_pass(gfx.render_pass)
+                        .subpass(2)
+                        .build()],
+                    None,
+                )
+                .unwrap()
+                .into_iter();
+
+            let pipeline = pipelines.next().unwrap();
+            gfx.set_name(pipeline, cstr!("minimap"));
+
+            v_guard.invoke();
+            f_guard.invoke();
+
+            Self {
+                pipeline_layout,
+                pipeline,
+                map: None,
+                explored: Vec::new(),
+                map_extent: 1.0,
+                waypoint: None,
+                last_update: None,
+            }
+        }
+    }
+
+    /// Recompute the map around the current view if it's stale
+    pub fn update(&mut self, sim: &Sim, exploration: Option<&Exploration>, distance: f32) {
+        let now = Instant::now();
+        if self
+            .last_update
+            .map_or(false, |t| now.duration_since(t) < UPDATE_INTERVAL)
+        {
+            return;
+        }
+        let Some(view) = sim.view().position() else {
+            return;
+        };
+        self.last_update = Some(now);
+        self.map = LocalMap::new(
+            &sim.graph,
+            &sim.graph_entities,
+            &sim.world,
+            &view,
+            f64::from(distance),
+        );
+        self.map_extent = distance.tanh();
+        self.explored = match (exploration, MapFrame::new(&sim.graph, &view)) {
+            (Some(exploration), Some(frame)) => {
+                exploration.map(&sim.graph, &view, &frame, self.map_extent)
+            }
+            _ => Vec::new(),
+        };
+        self.waypoint = sim.waypoint().and_then(|waypoint| {
+            let frame = MapFrame::new(&sim.graph, &view)?;
+            let point = waypoint.locate(&sim.graph, view.node);
+            let position = frame.project(&point);
+            if position.coords.norm() <= self.map_extent {
+                return Some(position);
+            }
+            // Too far to show, so point the way instead
+            let direction = frame.compass(&point)?;
+            Some(na::Point2::from(direction.into_inner() * self.map_extent))
+        });
+    }
+
+    pub unsafe fn draw(&mut self, device: &Device, cmd: vk::CommandBuffer, extent: vk::Extent2D) {
+        let Some(ref map) = self.map else {
+            return;
+        };
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+
+        // Anchor the map to the bottom-right corner of the screen, correcting for aspect ratio
+        let aspect = extent.height as f32 / extent.width as f32;
+        let radius = na::Vector2::new(MAP_RADIUS * aspect, MAP_RADIUS) * 2.0;
+        let center = na::Vector2::new(1.0, 1.0) - radius * 1.1;
+        // Map +Y is forward, which should point up the screen, whereas clip space +Y points down
+        let scale = na::Vector2::new(radius.x, -radius.y) / self.map_extent;
+
+        let draw_disk = |position: na::Vector2<f32>, size: f32, color: [f32; 4]| {
+            let constants = PushConstants {
+                disk: na::Vector4::new(
+                    center.x + position.x * scale.x,
+                    center.y + position.y * scale.y,
+                    size * radius.x,
+                    size * radius.y,
+                ),
+                color: color.into(),
+            };
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                super::as_bytes(&constants),
+            );
+            device.cmd_draw(cmd, 6, 1, 0, 0);
+        };
+
+        draw_disk(na::zero(), 1.0, [0.0, 0.0, 0.0, 0.5]);
+        for node in &self.explored {
+            draw_disk(
+                node.center.coords,
+                node.radius / self.map_extent,
+                explored_color(&node.summary),
+            );
+        }
+        for node in &map.nodes {
+            if node.center.coords.norm() > self.map_extent {
+                continue;
+            }
+            // Nodes whose centers lie above the terrain are drawn as sky, others as ground
+            let color = if node.elevation > 0.0 {
+                [0.5, 0.65, 0.9, 0.6]
+            } else {
+                [0.35, 0.55, 0.25, 0.6]
+            };
+            draw_disk(node.center.coords, node.radius / self.map_extent, color);
+        }
+        for marker in &map.markers {
+            if marker.position.coords.norm() > self.map_extent {
+                continue;
+            }
+            draw_disk(marker.position.coords, MARKER_RADIUS, [0.9, 0.2, 0.2, 1.0]);
+        }
+        if let Some(waypoint) = self.waypoint {
+            draw_disk(waypoint.coords, MARKER_RADIUS * 1.5, [1.0, 0.85, 0.1, 1.0]);
+        }
+        // The viewpoint itself, always at the center
+        draw_disk(na::zero(), MARKER_RADIUS, [1.0, 1.0, 1.0, 1.0]);
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}
+
+/// Muted color of an explored node that's no longer loaded
+fn explored_color(summary: &NodeSummary) -> [f32; 4] {
+    let [r, g, b] = match summary.material {
+        Some(material) => material_color(material),
+        None if summary.elevation > 0.0 => [0.5, 0.65, 0.9],
+        None => [0.35, 0.55, 0.25],
+    };
+    let gray = (r + g + b) / 3.0;
+    let desaturate = |x: f32| gray + (x - gray) * EXPLORED_SATURATION;
+    [desaturate(r), desaturate(g), desaturate(b), 0.45]
+}
+
+/// Rough color of a surface made of `material`, as seen from above
+pub(super) fn material_color(material: Material) -> [f32; 3] {
+    match material {
+        Material::Void => [0.5, 0.65, 0.9],
+        Material::Water => [0.2, 0.35, 0.8],
+        Material::Lava => [0.9, 0.35, 0.1],
+        Material::Ice | Material::IceSlush | Material::Snow => [0.9, 0.92, 0.95],
+        Material::Sand | Material::Sandstone => [0.85, 0.78, 0.5],
+        Material::RedSand | Material::RedSandstone => [0.8, 0.45, 0.25],
+        Material::Grass
+        | Material::LushGrass
+        | Material::CoarseGrass
+        | Material::CaveGrass
+        | Material::Leaves => [0.3, 0.6, 0.25],
+        Material::TanGrass => [0.6, 0.6, 0.3],
+        Material::MudGrass | Material::Mud | Material::Silt | Material::Clay => [0.45, 0.38, 0.28],
+        Material::Dirt | Material::SandyLoam | Material::SiltyLoam | Material::ClayLoam => {
+            [0.5, 0.4, 0.3]
+        }
+        Material::Wood | Material::WoodPlanks | Material::Sign | Material::Ladder => {
+            [0.55, 0.4, 0.25]
+        }
+        _ => [0.5, 0.5, 0.5],
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PushConstants {
+    disk: na::Vector4<f32>,
+    color: na::Vector4<f32>,
+}
@@ -2,12 +2,23 @@
 
 mod base;
 mod core;
+mod display;
 mod draw;
+mod effects;
 mod fog;
 mod frustum;
 mod gltf_mesh;
+mod memory_pressure;
 mod meshes;
+mod minimap;
+mod name_tags;
+mod pass_timer;
 mod png_array;
+mod post;
+mod sky;
+mod targets;
+mod timing_overlay;
+mod view_model;
 pub mod voxels;
 mod window;
 
@@ -17,12 +28,20 @@ mod tests;
 pub use self::{
     base::Base,
     core::Core,
+    display::{Display, DisplaySettings},
     draw::Draw,
+    effects::Effects,
     fog::Fog,
     frustum::Frustum,
     gltf_mesh::{GlbFile, GltfScene},
-    meshes::{Mesh, Meshes},
+    meshes::{Instances, Mesh, Meshes},
+    minimap::Minimap,
+    name_tags::NameTags,
+    pass_timer::PassTimer,
     png_array::PngArray,
+    post::Post,
+    timing_overlay::TimingOverlay,
+    view_model::HeldBlock,
     voxels::Voxels,
     window::{EarlyWindow, Window},
 };
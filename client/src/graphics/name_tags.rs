@@ -0,0 +1,391 @@
+use std::mem;
+
+use ash::{vk, Device};
+use vk_shader_macros::include_glsl;
+
+use super::Base;
+use crate::{
+    characters::{
+        billboard, name_tag_glyphs, name_tag_scale, VisibleCharacter, NAME_TAG_ELEVATION,
+        NAME_TAG_LETTER_HEIGHT, NAME_TAG_MAX_LEN,
+    },
+    signs::{line_offset, VisibleSign},
+    waypoints::Marker,
+    ConnectionQuality,
+};
+use common::{defer, math, waypoint::Waypoint};
+
+const VERT: &[u32] = include_glsl!("shaders/nametag.vert");
+const FRAG: &[u32] = include_glsl!("shaders/nametag.frag");
+
+/// Height of a letter in waypoint markers drawn flat on the screen, in normalized device coordinates
+const SCREEN_LETTER_HEIGHT: f32 = 0.035;
+/// Color of the names of characters whose players are away, dimmer than those of the rest
+const AFK_COLOR: [u8; 3] = [140, 140, 140];
+/// Color of the text of signs
+const SIGN_COLOR: [u8; 3] = [240, 225, 190];
+/// Distance of the connection warning from the edges of the screen, in normalized device
+/// coordinates
+const SCREEN_MARGIN: f32 = 0.03;
+
+/// Characters' names, drawn over their heads facing the camera, waypoints' markers, and the text
+/// of signs
+pub struct NameTags {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl NameTags {
+    pub fn new(gfx: &Base) -> Self {
+        let device = &*gfx.device;
+        unsafe {
+            // Construct the shader modules
+            let vert = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(VERT), None)
+                .unwrap();
+            // Note that these only need to live until the pipeline itself is constructed
+            let v_guard = defer(|| device.destroy_shader_module(vert, None));
+
+            let frag = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(FRAG), None)
+                .unwrap();
+            let f_guard = defer(|| device.destroy_shader_module(frag, None));
+
+            // Everything is supplied through push constants
+            let pipeline_layout = device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&[
+                        vk::PushConstantRange {
+                            stage_flags: vk::ShaderStageFlags::VERTEX,
+                            offset: 0,
+                            size: mem::size_of::<PushConstants>() as u32,
+                        },
+                    ]),
+                    None,
+                )
+                .unwrap();
+
+            let entry_point = cstr!("main").as_ptr();
+            let mut pipelines = device
+                .create_graphics_pipelines(
+                    gfx.pipeline_cache,
+                    &[vk::GraphicsPipelineCreateInfo::builder()
+                        .stages(&[
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::VERTEX,
+                                module: vert,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::FRAGMENT,
+                                module: frag,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                        ])
+                        .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                        .input_assembly_state(
+                            &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                        )
+                        .viewport_state(
+                            &vk::PipelineViewportStateCreateInfo::builder()
+                                .scissor_count(1)
+                                .viewport_count(1),
+                        )
+                        .rasterization_state(
+                            &vk::PipelineRasterizationStateCreateInfo::builder()
+                                .cull_mode(vk::CullModeFlags::NONE)
+                                .polygon_mode(vk::PolygonMode::FILL)
+                                .line_width(1.0),
+                        )
+                        .multisample_state(
+                            &vk::PipelineMultisampleStateCreateInfo::builder()
+                                .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                        )
+                        // Hidden behind terrain, but never hiding anything themselves
+                        .depth_stencil_state(
+                            &vk::PipelineDepthStencilStateCreateInfo::builder()
+                                .depth_test_enable(true)
+                                .depth_write_enable(false)
+                                .depth_compare_op(vk::CompareOp::GREATER_OR_EQUAL),
+                        )
+                        // Premultiplied alpha, for a translucent backing behind opaque text
+                        .color_blend_state(
+                            &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                                vk::PipelineColorBlendAttachmentState {
+                                    blend_enable: vk::TRUE,
+                                    src_color_blend_factor: vk::BlendFactor::ONE,
+                                    dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                                    color_blend_op: vk::BlendOp::ADD,
+                                    color_write_mask: vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B,
+                                    ..Default::default()
+                                },
+                            ]),
+                        )
+                        .dynamic_state(
+                            &vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&[
+                                vk::DynamicState::VIEWPORT,
+                                vk::DynamicState::SCISSOR,
+                            ]),
+                        )
+                        .layout(pipeline_layout)
+                        .render_pass(gfx.render_pass)
+                        .subpass(1)
+                        .build()],
+                    None,
+                )
+                .unwrap()
+                .into_iter();
+
+            let pipeline = pipelines.next().unwrap();
+            gfx.set_name(pipeline, cstr!("name tags"));
+
+            v_guard.invoke();
+            f_guard.invoke();
+
+            Self {
+                pipeline_layout,
+                pipeline,
+            }
+        }
+    }
+
+    /// Draw name tags over `characters`
+    ///
+    /// `local_to_view` maps the view's node into view space, and `projection` maps view space to
+    /// clip space.
+    pub unsafe fn draw(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        characters: &[VisibleCharacter],
+        projection: &na::Matrix4<f32>,
+        local_to_view: &na::Matrix4<f32>,
+        meters_to_absolute: f32,
+    ) {
+        if characters.is_empty() {
+            return;
+        }
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        let elevation =
+            math::translate_along(&(na::Vector3::y() * NAME_TAG_ELEVATION * meters_to_absolute));
+        for character in characters {
+            let center = local_to_view * character.body * elevation * math::origin();
+            let size = NAME_TAG_LETTER_HEIGHT
+                * meters_to_absolute
+                * name_tag_scale(character.distance, meters_to_absolute);
+            self.label(
+                device,
+                cmd,
+                &(projection * billboard(&center) * na::Matrix4::new_scaling(size)),
+                &character.name,
+                if character.afk { AFK_COLOR } else { [255; 3] },
+                1.0,
+            );
+        }
+    }
+
+    /// Draw the text of `signs` on the faces of their blocks
+    ///
+    /// `view_projection` maps the frame of the view's node to clip space.
+    pub unsafe fn draw_signs(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        signs: &[VisibleSign],
+        view_projection: &na::Matrix4<f32>,
+    ) {
+        if signs.is_empty() {
+            return;
+        }
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        for sign in signs {
+            let frame = view_projection * sign.frame;
+            for (i, line) in sign.lines.iter().enumerate() {
+                let transform = frame * shift(line_offset(i));
+                self.label(device, cmd, &transform, line, SIGN_COLOR, sign.opacity);
+            }
+        }
+    }
+
+    /// Draw a marker for each waypoint, labeled with its name and its distance in absolute units
+    ///
+    /// `projection` maps view space to clip space, and `aspect_ratio` is the width of the screen
+    /// over its height.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn draw_waypoints(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        waypoints: &[(Marker, &Waypoint, f32)],
+        projection: &na::Matrix4<f32>,
+        meters_to_absolute: f32,
+        aspect_ratio: f32,
+    ) {
+        if waypoints.is_empty() {
+            return;
+        }
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        for &(marker, waypoint, distance) in waypoints {
+            let text = format!("{} {:.0}m", waypoint.name, distance / meters_to_absolute);
+            let color = waypoint.color;
+            match marker {
+                Marker::InWorld(point) => {
+                    let size = NAME_TAG_LETTER_HEIGHT
+                        * meters_to_absolute
+                        * name_tag_scale(distance, meters_to_absolute);
+                    let frame = projection * billboard(&point) * na::Matrix4::new_scaling(size);
+                    // A pin with its tip at the waypoint, labeled above
+                    self.label(device, cmd, &(frame * shift(0.5)), "v", color, 1.0);
+                    self.label(device, cmd, &(frame * shift(1.7)), &text, color, 1.0);
+                }
+                Marker::Distant { position } => {
+                    let frame = on_screen(position.x, position.y, aspect_ratio);
+                    self.label(device, cmd, &(frame * shift(0.5)), "v", color, 1.0);
+                    self.label(device, cmd, &(frame * shift(1.7)), &text, color, 1.0);
+                }
+                Marker::Edge {
+                    position,
+                    direction,
+                } => {
+                    // An arrow pointing the way, beside the label
+                    let text = if direction.x.abs() >= direction.y.abs() {
+                        if direction.x > 0.0 {
+                            format!("{text} >")
+                        } else {
+                            format!("< {text}")
+                        }
+                    } else if direction.y > 0.0 {
+                        format!("v {text}")
+                    } else {
+                        format!("^ {text}")
+                    };
+                    // Keep the whole label on screen
+                    let half_width = 0.5 * text.len() as f32 * SCREEN_LETTER_HEIGHT / aspect_ratio;
+                    let x = position.x.clamp(-1.0 + half_width, 1.0 - half_width);
+                    let frame = on_screen(x, position.y, aspect_ratio);
+                    self.label(device, cmd, &frame, &text, color, 1.0);
+                }
+            }
+        }
+    }
+
+    /// Warn of an unreliable connection at the top of the screen: discreetly in the corner while
+    /// edits are held back, and prominently in the middle while prediction is frozen
+    ///
+    /// `aspect_ratio` is the width of the screen over its height.
+    pub unsafe fn draw_connection(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        quality: ConnectionQuality,
+        aspect_ratio: f32,
+    ) {
+        let (text, color, scale) = match quality {
+            ConnectionQuality::Healthy => return,
+            ConnectionQuality::Degraded => ("weak connection", [200, 170, 90], 1.0),
+            ConnectionQuality::Stalled => ("reconnecting...", [255, 90, 70], 2.0),
+        };
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        let height = scale * SCREEN_LETTER_HEIGHT;
+        let half_width = 0.5 * text.len() as f32 * height / aspect_ratio;
+        // The top of the screen is at -1
+        let (x, y) = if quality == ConnectionQuality::Stalled {
+            (0.0, -0.7)
+        } else {
+            (
+                1.0 - SCREEN_MARGIN - half_width,
+                -1.0 + SCREEN_MARGIN + 0.5 * height,
+            )
+        };
+        let frame = on_screen(x, y, aspect_ratio) * na::Matrix4::new_scaling(scale);
+        self.label(device, cmd, &frame, text, color, 1.0);
+    }
+
+    /// Tell the player of something that just happened, in the middle of the screen below where a
+    /// frozen connection is warned of
+    ///
+    /// `aspect_ratio` is the width of the screen over its height.
+    pub unsafe fn draw_notice(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        text: &str,
+        aspect_ratio: f32,
+    ) {
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        let frame = on_screen(0.0, -0.5, aspect_ratio) * na::Matrix4::new_scaling(1.5);
+        self.label(device, cmd, &frame, text, [230, 230, 230], 1.0);
+    }
+
+    /// Draw `text` in `color`, faded to `opacity`, in a frame mapped to clip space by `transform`
+    /// in which each glyph is a unit square and the text is centered on the origin
+    ///
+    /// The pipeline must already be bound.
+    unsafe fn label(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        transform: &na::Matrix4<f32>,
+        text: &str,
+        color: [u8; 3],
+        opacity: f32,
+    ) {
+        let (glyphs, len) = name_tag_glyphs(text);
+        if len == 0 {
+            return;
+        }
+        let [r, g, b] = color;
+        let constants = PushConstants {
+            transform: *transform,
+            glyphs,
+            len,
+            color: u32::from_le_bytes([r, g, b, (opacity.clamp(0.0, 1.0) * 255.0).round() as u8]),
+        };
+        device.cmd_push_constants(
+            cmd,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            super::as_bytes(&constants),
+        );
+        device.cmd_draw(cmd, 6 * len, 1, 0, 0);
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}
+
+/// Laid out as in `nametag.vert`
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PushConstants {
+    transform: na::Matrix4<f32>,
+    glyphs: [u32; NAME_TAG_MAX_LEN / 4],
+    len: u32,
+    color: u32,
+}
+
+/// Translation by `y` glyphs up a label's frame
+fn shift(y: f32) -> na::Matrix4<f32> {
+    na::Matrix4::new_translation(&na::Vector3::new(0.0, y, 0.0))
+}
+
+/// Transform from a label's frame to clip space placing it flat on the screen, centered at `x`, `y`
+/// in normalized device coordinates and in front of everything
+#[rustfmt::skip]
+fn on_screen(x: f32, y: f32, aspect_ratio: f32) -> na::Matrix4<f32> {
+    let height = SCREEN_LETTER_HEIGHT;
+    // Normalized device coordinates run downwards, and depth is reversed
+    na::Matrix4::new(
+        height / aspect_ratio,     0.0, 0.0,   x,
+                          0.0, -height, 0.0,   y,
+                          0.0,     0.0, 0.0, 1.0,
+                          0.0,     0.0, 0.0, 1.0)
+}
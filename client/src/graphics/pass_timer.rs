@@ -0,0 +1,405 @@
+//! GPU timestamps and CPU time around the passes of each frame
+//!
+//! Each frame in flight owns a range of timestamp queries, which are read back once the frame is
+//! known to have completed and before the range is reused. Devices without timestamp support still
+//! get CPU times.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use ash::{vk, Device};
+use tracing::{info, warn};
+
+use super::Base;
+use crate::metrics::{FrameTimings, Pass, PassHistory};
+
+/// Most scopes timed on the GPU in a single frame, which may time a pass in several parts
+const MAX_SCOPES: u32 = 16;
+const QUERIES_PER_FRAME: u32 = 2 * MAX_SCOPES;
+/// Number of frames whose timings are kept
+const HISTORY_LEN: usize = 90;
+
+pub struct PassTimer {
+    /// Absent where the device can't record timestamps
+    queries: Option<Queries>,
+    /// Frames submitted but not yet read back, oldest first
+    pending: VecDeque<Recording>,
+    /// Frame whose commands are being recorded
+    recording: Option<Recording>,
+    history: PassHistory,
+}
+
+struct Queries {
+    pool: vk::QueryPool,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    /// Number of meaningful low bits in each timestamp
+    valid_bits: u32,
+}
+
+struct Recording {
+    frame: u64,
+    /// Frame-in-flight state the frame was recorded with, which owns a range of queries
+    slot: u32,
+    /// Pass of each scope timed on the GPU, in the order of their queries
+    scopes: Vec<Pass>,
+    cpu: [Duration; Pass::COUNT],
+}
+
+impl PassTimer {
+    /// Time up to `slots` frames in flight
+    pub fn new(gfx: &Base, slots: u32) -> Self {
+        if gfx.timestamp_bits == 0 {
+            info!("timestamps unsupported; only CPU time will be recorded for each pass");
+            return Self::with_queries(None);
+        }
+        let pool = unsafe {
+            gfx.device.create_query_pool(
+                &vk::QueryPoolCreateInfo::builder()
+                    .query_type(vk::QueryType::TIMESTAMP)
+                    .query_count(QUERIES_PER_FRAME * slots),
+                None,
+            )
+        };
+        match pool {
+            Ok(pool) => {
+                gfx.set_name(pool, cstr!("timestamp pool"));
+                Self::with_queries(Some(Queries {
+                    pool,
+                    period: gfx.limits.timestamp_period,
+                    valid_bits: gfx.timestamp_bits,
+                }))
+            }
+            Err(e) => {
+                warn!("failed to create timestamp pool: {}", e);
+                Self::with_queries(None)
+            }
+        }
+    }
+
+    fn with_queries(queries: Option<Queries>) -> Self {
+        Self {
+            queries,
+            pending: VecDeque::new(),
+            recording: None,
+            history: PassHistory::new(HISTORY_LEN),
+        }
+    }
+
+    /// Timings of the latest frames read back
+    pub fn history(&self) -> &PassHistory {
+        &self.history
+    }
+
+    /// Read back the timings of every frame up to and including `completed`, which must be known
+    /// to have finished executing
+    pub unsafe fn resolve(&mut self, device: &Device, completed: u64) {
+        // Only called where timestamps are supported
+        let pool = self
+            .queries
+            .as_ref()
+            .map_or(vk::QueryPool::null(), |x| x.pool);
+        self.resolve_with(completed, |first, results| {
+            // Frames that completed have written every query they reset
+            device
+                .get_query_pool_results(
+                    pool,
+                    first,
+                    results.len() as u32,
+                    results,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+                .is_ok()
+        });
+    }
+
+    /// Read back completed frames through `read`, which fills a slice with the values of the
+    /// queries starting from an index and returns whether they were available
+    fn resolve_with(&mut self, completed: u64, mut read: impl FnMut(u32, &mut [u64]) -> bool) {
+        while self.pending.front().is_some_and(|x| x.frame <= completed) {
+            let recording = self.pending.pop_front().unwrap();
+            let gpu = self.queries.as_ref().and_then(|queries| {
+                let mut ticks = vec![0; 2 * recording.scopes.len()];
+                if !ticks.is_empty() && !read(recording.slot * QUERIES_PER_FRAME, &mut ticks) {
+                    warn!(frame = recording.frame, "timestamps unavailable");
+                    return None;
+                }
+                let mut gpu = [Duration::ZERO; Pass::COUNT];
+                for (&pass, ticks) in recording.scopes.iter().zip(ticks.chunks_exact(2)) {
+                    gpu[pass as usize] +=
+                        ticks_to_duration(ticks[0], ticks[1], queries.period, queries.valid_bits);
+                }
+                Some(gpu)
+            });
+            self.history.push(FrameTimings {
+                frame: recording.frame,
+                cpu: recording.cpu,
+                gpu,
+            });
+        }
+    }
+
+    /// Start timing `frame`, recorded with the frame-in-flight state `slot`, resetting its queries
+    /// on `cmd` before any are written
+    pub unsafe fn begin_frame(
+        &mut self,
+        device: &Device,
+        cmd: vk::CommandBuffer,
+        frame: u64,
+        slot: u32,
+    ) {
+        if let Some(first) = self.start_frame(frame, slot) {
+            let pool = self.queries.as_ref().unwrap().pool;
+            device.cmd_reset_query_pool(cmd, pool, first, QUERIES_PER_FRAME);
+        }
+    }
+
+    /// Begin recording `frame`, returning the first of the queries to reset for it if timestamps
+    /// are supported
+    fn start_frame(&mut self, frame: u64, slot: u32) -> Option<u32> {
+        // A frame never known to have completed can't be read once its queries are reset
+        self.pending.retain(|x| x.slot != slot);
+        self.recording = Some(Recording {
+            frame,
+            slot,
+            scopes: Vec::new(),
+            cpu: [Duration::ZERO; Pass::COUNT],
+        });
+        self.queries.as_ref()?;
+        Some(slot * QUERIES_PER_FRAME)
+    }
+
+    /// Finish recording the current frame, once its commands are submitted
+    pub fn end_frame(&mut self) {
+        let recording = self.recording.take().expect("no frame being recorded");
+        self.pending.push_back(recording);
+    }
+
+    /// Time the commands recorded on `cmd` as part of `pass` until the returned scope is dropped
+    pub unsafe fn pass_scope<'a>(
+        &'a mut self,
+        device: &'a Device,
+        cmd: vk::CommandBuffer,
+        pass: Pass,
+    ) -> PassScope<'a> {
+        let query = self.begin_scope(pass);
+        if let Some(query) = query {
+            let pool = self.queries.as_ref().unwrap().pool;
+            device.cmd_write_timestamp(cmd, vk::PipelineStageFlags::BOTTOM_OF_PIPE, pool, query);
+        }
+        PassScope {
+            timer: self,
+            device,
+            cmd,
+            pass,
+            query,
+            started: Instant::now(),
+        }
+    }
+
+    /// Allocate the pair of queries timing a scope of `pass` on the GPU, returning the first, if
+    /// any are available
+    fn begin_scope(&mut self, pass: Pass) -> Option<u32> {
+        let recording = self.recording.as_mut().expect("no frame being recorded");
+        self.queries.as_ref()?;
+        let index = recording.scopes.len() as u32;
+        if index == MAX_SCOPES {
+            return None;
+        }
+        recording.scopes.push(pass);
+        Some(recording.slot * QUERIES_PER_FRAME + 2 * index)
+    }
+
+    fn end_scope(&mut self, pass: Pass, cpu: Duration) {
+        let recording = self.recording.as_mut().expect("no frame being recorded");
+        recording.cpu[pass as usize] += cpu;
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        if let Some(queries) = self.queries.take() {
+            device.destroy_query_pool(queries.pool, None);
+        }
+    }
+}
+
+/// Commands being recorded as part of a pass, timed until dropped
+pub struct PassScope<'a> {
+    timer: &'a mut PassTimer,
+    device: &'a Device,
+    cmd: vk::CommandBuffer,
+    pass: Pass,
+    /// First of the pair of queries timing the scope on the GPU
+    query: Option<u32>,
+    started: Instant,
+}
+
+impl Drop for PassScope<'_> {
+    fn drop(&mut self) {
+        if let Some(query) = self.query {
+            let pool = self.timer.queries.as_ref().unwrap().pool;
+            unsafe {
+                self.device.cmd_write_timestamp(
+                    self.cmd,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    pool,
+                    query + 1,
+                );
+            }
+        }
+        self.timer.end_scope(self.pass, self.started.elapsed());
+    }
+}
+
+/// Time between two timestamps with `valid_bits` meaningful bits, taken `period` nanoseconds per
+/// tick apart
+fn ticks_to_duration(begin: u64, end: u64, period: f32, valid_bits: u32) -> Duration {
+    // The counter wraps around at its width
+    let mask = if valid_bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << valid_bits) - 1
+    };
+    let ticks = end.wrapping_sub(begin) & mask;
+    Duration::from_secs_f64(ticks as f64 * f64::from(period) * 1e-9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> PassTimer {
+        PassTimer::with_queries(Some(Queries {
+            pool: vk::QueryPool::null(),
+            period: 1.0,
+            valid_bits: 64,
+        }))
+    }
+
+    /// Record `frame` with `slot`, timing one scope of each of `passes`
+    fn record(timer: &mut PassTimer, frame: u64, slot: u32, passes: &[Pass]) {
+        timer.start_frame(frame, slot);
+        for &pass in passes {
+            timer.begin_scope(pass);
+            timer.end_scope(pass, Duration::from_micros(1));
+        }
+        timer.end_frame();
+    }
+
+    /// Reads each query as 100 ticks after the one before, noting which were read in `reads`
+    fn reader(reads: &mut Vec<(u32, usize)>) -> impl FnMut(u32, &mut [u64]) -> bool + '_ {
+        |first, results| {
+            reads.push((first, results.len()));
+            for (i, x) in results.iter_mut().enumerate() {
+                *x = 100 * (i as u64 + 1);
+            }
+            true
+        }
+    }
+
+    #[test]
+    fn only_completed_frames_are_read() {
+        let mut timer = enabled();
+        record(&mut timer, 1, 0, &[Pass::Voxels]);
+        record(&mut timer, 2, 1, &[Pass::Voxels, Pass::Post]);
+
+        // Neither fence has been observed
+        timer.resolve_with(0, |_, _| panic!("read an incomplete frame"));
+        assert_eq!(timer.history().iter().len(), 0);
+
+        let mut reads = Vec::new();
+        timer.resolve_with(1, reader(&mut reads));
+        timer.resolve_with(1, reader(&mut reads));
+        assert_eq!(reads, [(0, 2)], "each frame is read once");
+        let latest = timer.history().latest().unwrap();
+        assert_eq!(latest.frame, 1);
+        let gpu = latest.gpu.unwrap();
+        assert_eq!(gpu[Pass::Voxels as usize], Duration::from_nanos(100));
+        assert_eq!(gpu[Pass::Post as usize], Duration::ZERO);
+
+        // The first slot is reused once its frame is resolved
+        record(&mut timer, 3, 0, &[Pass::Transfer, Pass::Transfer]);
+        reads.clear();
+        timer.resolve_with(3, reader(&mut reads));
+        assert_eq!(reads, [(QUERIES_PER_FRAME, 4), (0, 4)]);
+        let latest = timer.history().latest().unwrap();
+        assert_eq!(latest.frame, 3);
+        // Scopes of the same pass accumulate
+        assert_eq!(
+            latest.gpu.unwrap()[Pass::Transfer as usize],
+            Duration::from_nanos(200)
+        );
+        assert_eq!(
+            latest.cpu[Pass::Transfer as usize],
+            Duration::from_micros(2)
+        );
+    }
+
+    #[test]
+    fn reused_slots_are_never_read_stale() {
+        let mut timer = enabled();
+        record(&mut timer, 1, 0, &[Pass::Voxels]);
+        // Recorded over before the first frame was known to complete
+        record(&mut timer, 2, 0, &[Pass::Meshes]);
+        let mut frames = 0;
+        timer.resolve_with(2, |_, results| {
+            frames += 1;
+            results.fill(7);
+            true
+        });
+        assert_eq!(frames, 1);
+        assert_eq!(timer.history().iter().len(), 1);
+        assert_eq!(timer.history().latest().unwrap().frame, 2);
+    }
+
+    #[test]
+    fn unavailable_results_are_omitted() {
+        let mut timer = enabled();
+        record(&mut timer, 1, 0, &[Pass::Voxels]);
+        timer.resolve_with(1, |_, _| false);
+        let latest = timer.history().latest().unwrap();
+        assert_eq!(latest.gpu, None);
+        assert_eq!(latest.cpu[Pass::Voxels as usize], Duration::from_micros(1));
+    }
+
+    #[test]
+    fn scopes_beyond_capacity_are_untimed() {
+        let mut timer = enabled();
+        timer.start_frame(1, 1);
+        for i in 0..MAX_SCOPES {
+            assert_eq!(
+                timer.begin_scope(Pass::Overlay),
+                Some(QUERIES_PER_FRAME + 2 * i)
+            );
+        }
+        assert_eq!(timer.begin_scope(Pass::Overlay), None);
+    }
+
+    #[test]
+    fn disabled_timer_records_only_cpu() {
+        let mut timer = PassTimer::with_queries(None);
+        assert_eq!(timer.start_frame(1, 0), None);
+        assert_eq!(timer.begin_scope(Pass::Voxels), None);
+        timer.end_scope(Pass::Voxels, Duration::from_millis(3));
+        timer.end_frame();
+        timer.resolve_with(1, |_, _| panic!("read queries of a disabled timer"));
+        let latest = timer.history().latest().unwrap();
+        assert_eq!(latest.gpu, None);
+        assert_eq!(latest.cpu[Pass::Voxels as usize], Duration::from_millis(3));
+    }
+
+    #[test]
+    fn ticks_convert_by_period() {
+        assert_eq!(
+            ticks_to_duration(100, 350, 1.0, 64),
+            Duration::from_nanos(250)
+        );
+        // A common period on integrated GPUs
+        let d = ticks_to_duration(0, 1_000_000, 52.08, 64);
+        assert!((d.as_secs_f64() - 0.05208).abs() < 1e-9);
+        // Counters narrower than 64 bits wrap around at their width
+        let top = (1 << 36) - 10;
+        assert_eq!(ticks_to_duration(top, 5, 1.0, 36), Duration::from_nanos(15));
+    }
+}
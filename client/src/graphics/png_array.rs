@@ -1,15 +1,20 @@
-use std::{fs, fs::File, path::PathBuf};
+use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, bail, Context};
+use anyhow::{anyhow, bail, Context, Result};
 use ash::vk;
 use lahar::DedicatedImage;
 use tracing::trace;
 
-use crate::loader::{LoadCtx, LoadFuture, Loadable};
+use crate::{
+    asset_pack::AssetSources,
+    loader::{LoadCtx, LoadFuture, Loadable},
+};
 
+/// An array image whose layers are PNG files in the directory `path`
 pub struct PngArray {
     pub path: PathBuf,
-    pub size: usize,
+    /// Prefixes of the names of the files holding each layer, in order
+    pub layers: Vec<String>,
 }
 
 impl Loadable for PngArray {
@@ -17,69 +22,16 @@ impl Loadable for PngArray {
 
     fn load(self, handle: &LoadCtx) -> LoadFuture<'_, Self::Output> {
         Box::pin(async move {
-            let full_path = handle
-                .cfg
-                .find_asset(&self.path)
-                .ok_or_else(|| anyhow!("{} not found", self.path.display()))?;
-            let mut paths = fs::read_dir(&full_path)
-                .with_context(|| format!("reading {}", full_path.display()))?
-                .map(|x| x.map(|x| x.path()))
-                .collect::<Result<Vec<_>, _>>()
-                .with_context(|| format!("reading {}", full_path.display()))?;
-            if paths.is_empty() {
-                bail!("{} is empty", full_path.display());
-            }
-            if paths.len() < self.size {
-                bail!(
-                    "{}: expected {} textures, found {}",
-                    full_path.display(),
-                    self.size,
-                    paths.len()
-                );
-            }
-            paths.sort();
-            paths.truncate(self.size);
-            let mut dims: Option<(u32, u32)> = None;
-            let mut mem = None;
-            for (i, path) in paths.iter().enumerate() {
-                trace!(layer=i, path=%path.display(), "loading");
-                let file =
-                    File::open(path).with_context(|| format!("reading {}", path.display()))?;
-                let decoder = png::Decoder::new(file);
-                let mut reader = decoder
-                    .read_info()
-                    .with_context(|| format!("decoding {}", path.display()))?;
-                let info = reader.info();
-                if let Some(dims) = dims {
-                    if dims != (info.width, info.height) {
-                        bail!(
-                            "inconsistent dimensions: expected {}x{}, got {}x{}",
-                            dims.0,
-                            dims.1,
-                            info.width,
-                            info.height
-                        );
-                    }
-                } else {
-                    dims = Some((info.width, info.height));
-                    mem = Some(
-                        handle
-                            .staging
-                            .alloc(info.width as usize * info.height as usize * 4 * self.size)
-                            .await
-                            .ok_or_else(|| {
-                                anyhow!("{}: image array too large", full_path.display())
-                            })?,
-                    );
-                }
-                let mem = mem.as_mut().unwrap();
-                let step_size = info.width as usize * info.height as usize * 4;
-                reader
-                    .next_frame(&mut mem[i * step_size..(i + 1) * step_size])
-                    .with_context(|| format!("decoding {}", path.display()))?;
-            }
-            let (width, height) = dims.unwrap();
-            let mem = mem.unwrap();
+            let ((width, height), pixels) =
+                read_layers(&handle.sources(), &self.path, &self.layers)
+                    .with_context(|| format!("loading {}", self.path.display()))?;
+            let layer_count = self.layers.len() as u32;
+            let mut mem = handle
+                .staging
+                .alloc(pixels.len())
+                .await
+                .ok_or_else(|| anyhow!("image array too large"))?;
+            mem.copy_from_slice(&pixels);
             unsafe {
                 let image = DedicatedImage::new(
                     &handle.gfx.device,
@@ -93,7 +45,7 @@ impl Loadable for PngArray {
                             depth: 1,
                         })
                         .mip_levels(1)
-                        .array_layers(self.size as u32)
+                        .array_layers(layer_count)
                         .samples(vk::SampleCountFlags::TYPE_1)
                         .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST),
                 );
@@ -103,7 +55,7 @@ impl Loadable for PngArray {
                     base_mip_level: 0,
                     level_count: 1,
                     base_array_layer: 0,
-                    layer_count: self.size as u32,
+                    layer_count,
                 };
                 let src = handle.staging.buffer();
                 let buffer_offset = mem.offset();
@@ -169,7 +121,7 @@ impl Loadable for PngArray {
                 trace!(
                     width = width,
                     height = height,
-                    path = %full_path.display(),
+                    layers = layer_count,
                     "loaded array"
                 );
                 Ok(image)
@@ -177,3 +129,136 @@ impl Loadable for PngArray {
         })
     }
 }
+
+/// Decode each of `layers` within `dir` from the first of `sources` with a usable copy, returning
+/// their dimensions and concatenated 8-bit RGBA pixels
+///
+/// The first layer decides the dimensions, and copies of later layers that don't match are passed
+/// over.
+fn read_layers(
+    sources: &AssetSources,
+    dir: &Path,
+    layers: &[String],
+) -> Result<((u32, u32), Vec<u8>)> {
+    let mut dims = None;
+    let mut pixels = Vec::new();
+    for (i, prefix) in layers.iter().enumerate() {
+        trace!(layer = i, prefix, "loading");
+        let (layer_dims, layer) = sources.resolve_prefixed(dir, prefix, |_, data| {
+            let (layer_dims, layer) = decode_rgba(&data)?;
+            if let Some((width, height)) = dims {
+                if layer_dims != (width, height) {
+                    bail!(
+                        "inconsistent dimensions: expected {}x{}, got {}x{}",
+                        width,
+                        height,
+                        layer_dims.0,
+                        layer_dims.1
+                    );
+                }
+            }
+            Ok((layer_dims, layer))
+        })?;
+        dims = Some(layer_dims);
+        pixels.extend_from_slice(&layer);
+    }
+    let dims = dims.ok_or_else(|| anyhow!("no layers in {}", dir.display()))?;
+    Ok((dims, pixels))
+}
+
+/// Decode an 8-bit RGBA PNG, returning its dimensions and pixels
+fn decode_rgba(data: &[u8]) -> Result<((u32, u32), Vec<u8>)> {
+    let mut reader = png::Decoder::new(data)
+        .read_info()
+        .context("decoding PNG header")?;
+    let info = reader.info();
+    if (info.color_type, info.bit_depth) != (png::ColorType::Rgba, png::BitDepth::Eight) {
+        bail!(
+            "expected 8-bit RGBA, got {}-bit {:?}",
+            info.bit_depth as u8,
+            info.color_type
+        );
+    }
+    let dims = (info.width, info.height);
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels).context("decoding PNG")?;
+    pixels.truncate(dims.0 as usize * dims.1 as usize * 4);
+    Ok((dims, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::asset_pack::PackSource;
+
+    fn write_png(path: &Path, (width, height): (u32, u32), value: u8) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut encoder = png::Encoder::new(fs::File::create(path).unwrap(), width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_image_data(&vec![value; width as usize * height as usize * 4])
+            .unwrap();
+    }
+
+    fn layers() -> Vec<String> {
+        vec!["00001_".into(), "00002_".into(), "00003_".into()]
+    }
+
+    #[test]
+    fn missing_layer_falls_back() {
+        let root = tempfile::tempdir().unwrap();
+        let pack = root.path().join("pack");
+        let builtin = root.path().join("builtin");
+        for (i, name) in ["00001_dirt.png", "00002_sand.png", "00003_stone.png"]
+            .into_iter()
+            .enumerate()
+        {
+            write_png(&builtin.join("materials").join(name), (2, 2), i as u8);
+        }
+        // The pack replaces only the first and last, under names of its own choosing
+        write_png(&pack.join("materials/00001_soil.png"), (2, 2), 10);
+        write_png(&pack.join("materials/00003_rock.png"), (2, 2), 12);
+        let sources =
+            AssetSources::from_sources(vec![PackSource::Dir(pack), PackSource::Dir(builtin)]);
+
+        let (dims, pixels) = read_layers(&sources, Path::new("materials"), &layers()).unwrap();
+        assert_eq!(dims, (2, 2));
+        assert_eq!(pixels.len(), 3 * 16);
+        assert!(pixels[..16].iter().all(|&x| x == 10));
+        assert!(pixels[16..32].iter().all(|&x| x == 1));
+        assert!(pixels[32..].iter().all(|&x| x == 12));
+    }
+
+    #[test]
+    fn mismatched_dimensions_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let pack = root.path().join("pack");
+        let builtin = root.path().join("builtin");
+        for (i, name) in ["00001_dirt.png", "00002_sand.png", "00003_stone.png"]
+            .into_iter()
+            .enumerate()
+        {
+            write_png(&builtin.join("materials").join(name), (2, 2), i as u8);
+        }
+        write_png(&pack.join("materials/00002_sand.png"), (4, 4), 11);
+        // Not a PNG at all
+        fs::write(pack.join("materials/00003_stone.png"), b"garbage").unwrap();
+        let sources = AssetSources::from_sources(vec![
+            PackSource::Dir(pack.clone()),
+            PackSource::Dir(builtin),
+        ]);
+
+        let (dims, pixels) = read_layers(&sources, Path::new("materials"), &layers()).unwrap();
+        assert_eq!(dims, (2, 2));
+        assert!(pixels[16..32].iter().all(|&x| x == 1));
+        assert!(pixels[32..].iter().all(|&x| x == 2));
+
+        // Without anything to fall back on, the array can't be made
+        let sources = AssetSources::from_sources(vec![PackSource::Dir(pack)]);
+        assert!(read_layers(&sources, Path::new("materials"), &layers()[1..]).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use std::mem;
+
+use ash::{vk, Device};
+use vk_shader_macros::include_glsl;
+
+use super::{display::PostConstants, Base};
+use common::defer;
+
+const VERT: &[u32] = include_glsl!("shaders/fullscreen.vert");
+const FRAG: &[u32] = include_glsl!("shaders/post.frag");
+
+/// Final pass, which scales the rendered image to the window and applies display settings
+pub struct Post {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl Post {
+    pub fn new(gfx: &Base) -> Self {
+        let device = &*gfx.device;
+        unsafe {
+            // Construct the shader modules
+            let vert = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(VERT), None)
+                .unwrap();
+            // Note that these only need to live until the pipeline itself is constructed
+            let v_guard = defer(|| device.destroy_shader_module(vert, None));
+
+            let frag = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(FRAG), None)
+                .unwrap();
+            let f_guard = defer(|| device.destroy_shader_module(frag, None));
+
+            // Define the outward-facing interface of the shaders, incl. uniforms, samplers, etc.
+            let pipeline_layout = device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&[gfx.post_layout])
+                        .push_constant_ranges(&[vk::PushConstantRange {
+                            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                            offset: 0,
+                            size: mem::size_of::<PostConstants>() as u32,
+                        }]),
+                    None,
+                )
+                .unwrap();
+
+            let entry_point = cstr!("main").as_ptr();
+            let mut pipelines = device
+                .create_graphics_pipelines(
+                    gfx.pipeline_cache,
+                    &[vk::GraphicsPipelineCreateInfo::builder()
+                        .stages(&[
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::VERTEX,
+                                module: vert,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::FRAGMENT,
+                                module: frag,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                        ])
+                        .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                        .input_assembly_state(
+                            &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                        )
+                        .viewport_state(
+                            &vk::PipelineViewportStateCreateInfo::builder()
+                                .scissor_count(1)
+                                .viewport_count(1),
+                        )
+                        .rasterization_state(
+                            &vk::PipelineRasterizationStateCreateInfo::builder()
+                                .cull_mode(vk::CullModeFlags::NONE)
+                                .polygon_mode(vk::PolygonMode::FILL)
+                                .line_width(1.0),
+                        )
+                        .multisample_state(
+                            &vk::PipelineMultisampleStateCreateInfo::builder()
+                                .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                        )
+                        .depth_stencil_state(
+                            &vk::PipelineDepthStencilStateCreateInfo::builder()
+                                .depth_test_enable(false)
+                                .depth_write_enable(false),
+                        )
+                        .color_blend_state(
+                            &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                                vk::PipelineColorBlendAttachmentState {
+                                    blend_enable: vk::FALSE,
+                                    color_write_mask: vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B
+                                        | vk::ColorComponentFlags::A,
+                                    ..Default::default()
+                                },
+                            ]),
+                        )
+                        .dynamic_state(
+                            &vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&[
+                                vk::DynamicState::VIEWPORT,
+                                vk::DynamicState::SCISSOR,
+                            ]),
+                        )
+                        .layout(pipeline_layout)
+                        .render_pass(gfx.post_render_pass)
+                        .subpass(0)
+                        .build()],
+                    None,
+                )
+                .unwrap()
+                .into_iter();
+
+            let pipeline = pipelines.next().unwrap();
+            gfx.set_name(pipeline, cstr!("post"));
+
+            // Clean up the shaders explicitly, so the defer guards don't hold onto references we're
+            // moving into `Self` to be returned
+            v_guard.invoke();
+            f_guard.invoke();
+
+            Self {
+                pipeline_layout,
+                pipeline,
+            }
+        }
+    }
+
+    /// Draw the image bound by `image_ds` over the entire framebuffer
+    pub unsafe fn draw(
+        &mut self,
+        device: &Device,
+        image_ds: vk::DescriptorSet,
+        cmd: vk::CommandBuffer,
+        constants: &PostConstants,
+    ) {
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[image_ds],
+            &[],
+        );
+        device.cmd_push_constants(
+            cmd,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            super::as_bytes(constants),
+        );
+        device.cmd_draw(cmd, 3, 1, 0, 0);
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}
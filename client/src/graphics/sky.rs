@@ -0,0 +1,89 @@
+//! Appearance of the sky over the course of the day/night cycle
+
+use std::f32::consts::TAU;
+
+/// Sky colors at points in the day, in increasing order of time. Colors are linearly interpolated
+/// between adjacent keyframes, wrapping around from the last to the first.
+const PALETTE: [(f32, [f32; 3]); 6] = [
+    // Dawn
+    (0.0, [0.9, 0.6, 0.45]),
+    // Noon
+    (0.08, [0.5, 0.65, 0.9]),
+    (0.42, [0.5, 0.65, 0.9]),
+    // Dusk
+    (0.5, [0.85, 0.45, 0.35]),
+    // Night
+    (0.58, [0.02, 0.03, 0.08]),
+    (0.92, [0.02, 0.03, 0.08]),
+];
+
+/// Color of the sky and fog at `time`, a fraction of the day as in `proto::StateDelta::world_time`
+pub fn color(time: f32) -> na::Vector3<f32> {
+    let time = time.rem_euclid(1.0);
+    let next = PALETTE.iter().position(|&(t, _)| t > time).unwrap_or(0);
+    let prev = (next + PALETTE.len() - 1) % PALETTE.len();
+    let (t0, c0) = PALETTE[prev];
+    let (t1, c1) = PALETTE[next];
+    let span = (t1 - t0).rem_euclid(1.0);
+    let s = (time - t0).rem_euclid(1.0) / span;
+    na::Vector3::from(c0).lerp(&na::Vector3::from(c1), s)
+}
+
+/// Direction towards the sun at `time`, given the local up direction
+///
+/// The sun rises along an arbitrary fixed horizontal axis, passes overhead at noon, and sets
+/// opposite where it rose. The w component holds the strength of direct sunlight, which fades out
+/// as the sun nears the horizon.
+pub fn sun(time: f32, up: &na::UnitVector3<f32>) -> na::Vector4<f32> {
+    // Any horizontal direction will do, so long as it's stable
+    let reference = if up.x.abs() < 0.9 {
+        na::Vector3::x()
+    } else {
+        na::Vector3::z()
+    };
+    let east = (reference - up.into_inner() * reference.dot(up)).normalize();
+    let angle = time * TAU;
+    let direction = east * angle.cos() + up.into_inner() * angle.sin();
+    direction.push((angle.sin() * 4.0).clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    #[test]
+    fn palette_keyframes() {
+        for &(t, c) in &PALETTE {
+            assert_abs_diff_eq!(color(t), na::Vector3::from(c), epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    fn palette_interpolation() {
+        // Halfway between dusk and night
+        let expected = na::Vector3::from(PALETTE[3].1).lerp(&na::Vector3::from(PALETTE[4].1), 0.5);
+        assert_abs_diff_eq!(color(0.54), expected, epsilon = 1e-5);
+
+        // Continuous across the end of the day
+        assert_abs_diff_eq!(color(0.9999), color(0.0), epsilon = 1e-3);
+        assert_abs_diff_eq!(color(1.0), color(0.0));
+        let expected = na::Vector3::from(PALETTE[5].1).lerp(&na::Vector3::from(PALETTE[0].1), 0.5);
+        assert_abs_diff_eq!(color(0.96), expected, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn sun_path() {
+        let up = na::Vector3::y_axis();
+        let noon = sun(0.25, &up);
+        assert_abs_diff_eq!(noon.xyz(), up.into_inner(), epsilon = 1e-6);
+        assert_eq!(noon.w, 1.0);
+        let midnight = sun(0.75, &up);
+        assert_abs_diff_eq!(midnight.xyz(), -up.into_inner(), epsilon = 1e-6);
+        assert_eq!(midnight.w, 0.0);
+        // Rises and sets on opposite horizons
+        let (dawn, dusk) = (sun(0.0, &up), sun(0.5, &up));
+        assert_abs_diff_eq!(dawn.xyz().dot(&up), 0.0, epsilon = 1e-6);
+        assert_abs_diff_eq!(dawn.xyz(), -dusk.xyz(), epsilon = 1e-6);
+    }
+}
@@ -0,0 +1,333 @@
+//! Images the world is rendered to before being scaled to fit the window
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use ash::{prelude::VkResult, vk};
+use lahar::DedicatedImage;
+
+use super::{
+    base::{OutOfDeviceMemory, COLOR_FORMAT},
+    Base,
+};
+use common::mem_budget::{self, Reservation};
+
+/// A set of render targets of a common size, one for each swapchain image
+pub struct RenderTargets {
+    gfx: Arc<Base>,
+    extent: vk::Extent2D,
+    /// Pool from which every target's `post_ds` is allocated
+    descriptor_pool: vk::DescriptorPool,
+    targets: Vec<RenderTarget>,
+    _memory: Reservation,
+}
+
+impl RenderTargets {
+    /// Allocate targets, unless device memory can't be found for them
+    pub unsafe fn new(
+        gfx: Arc<Base>,
+        extent: vk::Extent2D,
+        count: usize,
+    ) -> Result<Self, OutOfDeviceMemory> {
+        let device = &*gfx.device;
+        // A color and a depth image of four bytes per pixel for each target
+        let size = count as vk::DeviceSize
+            * vk::DeviceSize::from(extent.width)
+            * vk::DeviceSize::from(extent.height)
+            * 8;
+        let memory = gfx.try_reserve_memory(&mem_budget::GPU_TARGETS, size)?;
+        let descriptor_pool = OutOfDeviceMemory::check(
+            device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::builder()
+                    .max_sets(count as u32)
+                    .pool_sizes(&[vk::DescriptorPoolSize {
+                        ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                        descriptor_count: count as u32,
+                    }]),
+                None,
+            ),
+            size,
+        )?;
+        // Whatever's been created by the time anything fails is destroyed on drop
+        let mut result = Self {
+            gfx: gfx.clone(),
+            extent,
+            descriptor_pool,
+            targets: Vec::with_capacity(count),
+            _memory: memory,
+        };
+        let post_ds = OutOfDeviceMemory::check(
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&vec![gfx.post_layout; count]),
+            ),
+            size,
+        )?;
+        for post_ds in post_ds {
+            let color = create_image(
+                &gfx,
+                extent,
+                COLOR_FORMAT,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            );
+            gfx.set_name(color.handle, cstr!("rendered"));
+            let depth = create_image(
+                &gfx,
+                extent,
+                vk::Format::D32_SFLOAT,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                    | vk::ImageUsageFlags::INPUT_ATTACHMENT,
+            );
+            gfx.set_name(depth.handle, cstr!("depth"));
+            result.targets.push(RenderTarget {
+                extent,
+                color,
+                color_view: vk::ImageView::null(),
+                depth,
+                depth_view: vk::ImageView::null(),
+                buffer: vk::Framebuffer::null(),
+                post_ds,
+            });
+            let target = result.targets.last_mut().unwrap();
+            target.color_view = OutOfDeviceMemory::check(
+                create_view(
+                    &gfx,
+                    target.color.handle,
+                    COLOR_FORMAT,
+                    vk::ImageAspectFlags::COLOR,
+                ),
+                size,
+            )?;
+            gfx.set_name(target.color_view, cstr!("rendered"));
+            target.depth_view = OutOfDeviceMemory::check(
+                create_view(
+                    &gfx,
+                    target.depth.handle,
+                    vk::Format::D32_SFLOAT,
+                    vk::ImageAspectFlags::DEPTH,
+                ),
+                size,
+            )?;
+            gfx.set_name(target.depth_view, cstr!("depth"));
+            device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::builder()
+                    .dst_set(post_ds)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo {
+                        sampler: gfx.linear_sampler,
+                        image_view: target.color_view,
+                        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    }])
+                    .build()],
+                &[],
+            );
+            target.buffer = OutOfDeviceMemory::check(
+                device.create_framebuffer(
+                    &vk::FramebufferCreateInfo::builder()
+                        .render_pass(gfx.render_pass)
+                        .attachments(&[target.color_view, target.depth_view])
+                        .width(extent.width)
+                        .height(extent.height)
+                        .layers(1),
+                    None,
+                ),
+                size,
+            )?;
+        }
+        Ok(result)
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn get(&self, index: usize) -> &RenderTarget {
+        &self.targets[index]
+    }
+}
+
+impl Drop for RenderTargets {
+    fn drop(&mut self) {
+        let device = &*self.gfx.device;
+        // Views and framebuffers left null by a failure to create them are ignored
+        unsafe {
+            for target in &mut self.targets {
+                device.destroy_framebuffer(target.buffer, None);
+                device.destroy_image_view(target.color_view, None);
+                device.destroy_image_view(target.depth_view, None);
+                target.color.destroy(device);
+                target.depth.destroy(device);
+            }
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+    }
+}
+
+pub struct RenderTarget {
+    pub extent: vk::Extent2D,
+    /// Image the world is rendered to
+    color: DedicatedImage,
+    color_view: vk::ImageView,
+    depth: DedicatedImage,
+    pub depth_view: vk::ImageView,
+    /// Framebuffer for `Base::render_pass` referencing the color and depth images
+    pub buffer: vk::Framebuffer,
+    /// Descriptor set for `Base::post_layout` referencing the color image
+    pub post_ds: vk::DescriptorSet,
+}
+
+unsafe fn create_image(
+    gfx: &Base,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+) -> DedicatedImage {
+    DedicatedImage::new(
+        &gfx.device,
+        &gfx.memory_properties,
+        &vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(usage),
+    )
+}
+
+unsafe fn create_view(
+    gfx: &Base,
+    image: vk::Image,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+) -> VkResult<vk::ImageView> {
+    gfx.device.create_image_view(
+        &vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            }),
+        None,
+    )
+}
+
+/// Resources that new frames no longer use, held until the frames that did have completed
+///
+/// Frames are identified by the sequence numbers given by `Draw::submitted` and
+/// `Draw::completed`.
+pub struct Retired<T> {
+    /// Resources and the last frame that might use them, in order of retirement
+    items: VecDeque<(u64, T)>,
+}
+
+impl<T> Retired<T> {
+    /// Hold `item` until frame `last_use` completes
+    pub fn push(&mut self, last_use: u64, item: T) {
+        debug_assert!(self.items.back().map_or(true, |&(x, _)| x <= last_use));
+        self.items.push_back((last_use, item));
+    }
+
+    /// Drop everything used only by frames up to and including `completed`
+    pub fn collect(&mut self, completed: u64) {
+        while self.items.front().map_or(false, |&(x, _)| x <= completed) {
+            self.items.pop_front();
+        }
+    }
+}
+
+impl<T> Default for Retired<T> {
+    fn default() -> Self {
+        Self {
+            items: VecDeque::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Records when it's dropped
+    struct Resource {
+        id: u32,
+        last_use: u64,
+        log: Rc<RefCell<Vec<(u32, u64)>>>,
+        completed: Rc<RefCell<u64>>,
+    }
+
+    impl Drop for Resource {
+        fn drop(&mut self) {
+            let completed = *self.completed.borrow();
+            assert!(
+                completed >= self.last_use,
+                "{} dropped while in use",
+                self.id
+            );
+            self.log.borrow_mut().push((self.id, completed));
+        }
+    }
+
+    /// Simulates a renderer with two frames in flight whose render scale changes every frame, and
+    /// sometimes several times in one frame
+    #[test]
+    fn rapid_scale_changes() {
+        const PIPELINE_DEPTH: u64 = 2;
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let completed = Rc::new(RefCell::new(0));
+        let mut retired = Retired::default();
+        let mut next_id = 0;
+        let mut submitted = 0;
+        let mut current: Option<Resource> = None;
+        for frame in 0..100u64 {
+            // Waiting for the oldest frame in flight
+            *completed.borrow_mut() = submitted.saturating_sub(PIPELINE_DEPTH - 1);
+            for _ in 0..=(frame % 3) {
+                let new = Resource {
+                    id: next_id,
+                    last_use: submitted,
+                    log: log.clone(),
+                    completed: completed.clone(),
+                };
+                next_id += 1;
+                if let Some(old) = current.replace(new) {
+                    retired.push(submitted, old);
+                }
+            }
+            retired.collect(*completed.borrow());
+            // The current resources are used by the frame being submitted
+            current.as_mut().unwrap().last_use = submitted + 1;
+            submitted += 1;
+            // Nothing accumulates beyond the frames in flight
+            assert!(retired.items.len() <= 3 * PIPELINE_DEPTH as usize);
+        }
+        // Everything is freed once drawing stops
+        *completed.borrow_mut() = submitted;
+        retired.collect(submitted);
+        assert!(retired.items.is_empty());
+        drop(current);
+        let log = log.borrow();
+        assert_eq!(log.len(), next_id as usize);
+        let mut ids = log.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), next_id as usize);
+    }
+}
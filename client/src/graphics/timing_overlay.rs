@@ -0,0 +1,225 @@
+use std::mem;
+use std::time::Duration;
+
+use ash::{vk, Device};
+use vk_shader_macros::include_glsl;
+
+use super::Base;
+use crate::metrics::{Pass, PassHistory};
+use common::defer;
+
+const VERT: &[u32] = include_glsl!("shaders/rect.vert");
+const FRAG: &[u32] = include_glsl!("shaders/rect.frag");
+
+/// Frame time shown at the height of the graph
+const BUDGET: Duration = Duration::from_micros(16_667);
+/// Size of the graph in clip space, excluding bars over budget, which reach up to twice as high
+const WIDTH: f32 = 0.6;
+const HEIGHT: f32 = 0.3;
+/// Distance of the graph from the bottom-left corner of the screen, in clip space
+const MARGIN: f32 = 0.05;
+
+/// Stacked bars breaking down the time each recent frame spent in each pass, GPU time on the left
+/// of each frame's column and CPU time on the right
+pub struct TimingOverlay {
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    visible: bool,
+}
+
+impl TimingOverlay {
+    pub fn new(gfx: &Base) -> Self {
+        let device = &*gfx.device;
+        unsafe {
+            // Construct the shader modules
+            let vert = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(VERT), None)
+                .unwrap();
+            // Note that these only need to live until the pipeline itself is constructed
+            let v_guard = defer(|| device.destroy_shader_module(vert, None));
+
+            let frag = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(FRAG), None)
+                .unwrap();
+            let f_guard = defer(|| device.destroy_shader_module(frag, None));
+
+            // Everything is supplied through push constants
+            let pipeline_layout = device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&[
+                        vk::PushConstantRange {
+                            stage_flags: vk::ShaderStageFlags::VERTEX
+                                | vk::ShaderStageFlags::FRAGMENT,
+                            offset: 0,
+                            size: mem::size_of::<PushConstants>() as u32,
+                        },
+                    ]),
+                    None,
+                )
+                .unwrap();
+
+            let entry_point = cstr!("main").as_ptr();
+            let mut pipelines = device
+                .create_graphics_pipelines(
+                    gfx.pipeline_cache,
+                    &[vk::GraphicsPipelineCreateInfo::builder()
+                        .stages(&[
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::VERTEX,
+                                module: vert,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::FRAGMENT,
+                                module: frag,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                        ])
+                        .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                        .input_assembly_state(
+                            &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                        )
+                        .viewport_state(
+                            &vk::PipelineViewportStateCreateInfo::builder()
+                                .scissor_count(1)
+                                .viewport_count(1),
+                        )
+                        .rasterization_state(
+                            &vk::PipelineRasterizationStateCreateInfo::builder()
+                                .cull_mode(vk::CullModeFlags::NONE)
+                                .polygon_mode(vk::PolygonMode::FILL)
+                                .line_width(1.0),
+                        )
+                        .multisample_state(
+                            &vk::PipelineMultisampleStateCreateInfo::builder()
+                                .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                        )
+                        .depth_stencil_state(
+                            &vk::PipelineDepthStencilStateCreateInfo::builder()
+                                .depth_test_enable(false)
+                                .depth_write_enable(false),
+                        )
+                        .color_blend_state(
+                            &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                                vk::PipelineColorBlendAttachmentState {
+                                    blend_enable: vk::TRUE,
+                                    src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+                                    dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                                    color_blend_op: vk::BlendOp::ADD,
+                                    color_write_mask: vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B,
+                                    ..Default::default()
+                                },
+                            ]),
+                        )
+                        .dynamic_state(
+                            &vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&[
+                                vk::DynamicState::VIEWPORT,
+                                vk::DynamicState::SCISSOR,
+                            ]),
+                        )
+                        .layout(pipeline_layout)
+                        .render_pass(gfx.render_pass)
+                        .subpass(2)
+                        .build()],
+                    None,
+                )
+                .unwrap()
+                .into_iter();
+
+            let pipeline = pipelines.next().unwrap();
+            gfx.set_name(pipeline, cstr!("timing overlay"));
+
+            v_guard.invoke();
+            f_guard.invoke();
+
+            Self {
+                pipeline_layout,
+                pipeline,
+                visible: false,
+            }
+        }
+    }
+
+    /// Show the overlay if hidden, or hide it if shown
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub unsafe fn draw(&mut self, device: &Device, cmd: vk::CommandBuffer, history: &PassHistory) {
+        if !self.visible {
+            return;
+        }
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+
+        let draw_rect = |rect: [f32; 4], color: [f32; 4]| {
+            let constants = PushConstants {
+                rect: rect.into(),
+                color: color.into(),
+            };
+            device.cmd_push_constants(
+                cmd,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                super::as_bytes(&constants),
+            );
+            device.cmd_draw(cmd, 6, 1, 0, 0);
+        };
+
+        // Clip space +Y points down, so bars grow upward from the bottom of the graph
+        let left = -1.0 + MARGIN;
+        let bottom = 1.0 - MARGIN;
+        draw_rect(
+            [left, bottom - HEIGHT, left + WIDTH, bottom],
+            [0.0, 0.0, 0.0, 0.5],
+        );
+        let column = WIDTH / history.capacity() as f32;
+        // Stack a frame's passes in half a column starting at `x`
+        let draw_stack = |x: f32, times: &[Duration; Pass::COUNT], alpha: f32| {
+            let mut y = bottom;
+            for pass in Pass::ALL {
+                // Frames far over budget would otherwise cover the screen
+                let height = (times[pass as usize].as_secs_f32() / BUDGET.as_secs_f32() * HEIGHT)
+                    .min(2.0 * HEIGHT - (bottom - y));
+                let [r, g, b] = pass_color(pass);
+                draw_rect([x, y - height, x + column / 2.0, y], [r, g, b, alpha]);
+                y -= height;
+            }
+        };
+        for (i, frame) in history.iter().enumerate() {
+            let x = left + i as f32 * column;
+            if let Some(ref gpu) = frame.gpu {
+                draw_stack(x, gpu, 0.9);
+            }
+            draw_stack(x + column / 2.0, &frame.cpu, 0.6);
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}
+
+fn pass_color(pass: Pass) -> [f32; 3] {
+    match pass {
+        Pass::Transfer => [0.9, 0.55, 0.2],
+        Pass::Voxels => [0.3, 0.7, 0.3],
+        Pass::Meshes => [0.3, 0.5, 0.9],
+        Pass::Transparent => [0.4, 0.85, 0.9],
+        Pass::Post => [0.7, 0.4, 0.8],
+        Pass::Overlay => [0.9, 0.9, 0.4],
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PushConstants {
+    rect: na::Vector4<f32>,
+    color: na::Vector4<f32>,
+}
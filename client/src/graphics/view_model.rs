@@ -0,0 +1,266 @@
+use std::mem;
+
+use ash::{vk, Device};
+use vk_shader_macros::include_glsl;
+
+use super::Base;
+use common::defer;
+
+const VERT: &[u32] = include_glsl!("shaders/view_model.vert");
+const FRAG: &[u32] = include_glsl!("shaders/view_model.frag");
+
+/// Nearest distance in view space at which the held block is drawn, well short of the closest it
+/// comes to the camera
+pub const ZNEAR: f32 = 0.1;
+
+/// Least depth the held block is drawn at, above which nothing in the world comes short of
+/// pressing against the near plane, so the block is never cut by what the character stands beside
+pub const MIN_DEPTH: f32 = 0.999;
+
+/// Direction in view space the held block is lit from, over the character's shoulder
+const LIGHT: [f32; 3] = [-0.3, 1.0, 0.6];
+
+/// Draws the block held in the first-person view, textured like the material it is in the world
+pub struct HeldBlock {
+    ds_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    ds: vk::DescriptorSet,
+    /// Material textures `ds` refers to, if any yet
+    materials: vk::ImageView,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl HeldBlock {
+    pub fn new(gfx: &Base) -> Self {
+        let device = &*gfx.device;
+        unsafe {
+            // Construct the shader modules
+            let vert = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(VERT), None)
+                .unwrap();
+            // Note that these only need to live until the pipeline itself is constructed
+            let v_guard = defer(|| device.destroy_shader_module(vert, None));
+
+            let frag = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(FRAG), None)
+                .unwrap();
+            let f_guard = defer(|| device.destroy_shader_module(frag, None));
+
+            let ds_layout = device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[
+                        vk::DescriptorSetLayoutBinding {
+                            binding: 0,
+                            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                            descriptor_count: 1,
+                            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                            p_immutable_samplers: &gfx.linear_sampler,
+                        },
+                    ]),
+                    None,
+                )
+                .unwrap();
+            let descriptor_pool = device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::builder()
+                        .max_sets(1)
+                        .pool_sizes(&[vk::DescriptorPoolSize {
+                            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                            descriptor_count: 1,
+                        }]),
+                    None,
+                )
+                .unwrap();
+            let ds = device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::builder()
+                        .descriptor_pool(descriptor_pool)
+                        .set_layouts(&[ds_layout]),
+                )
+                .unwrap()[0];
+
+            // Common uniforms for the time of day, the material textures, and the rest through
+            // push constants
+            let pipeline_layout = device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&[gfx.common_layout, ds_layout])
+                        .push_constant_ranges(&[vk::PushConstantRange {
+                            stage_flags: vk::ShaderStageFlags::VERTEX
+                                | vk::ShaderStageFlags::FRAGMENT,
+                            offset: 0,
+                            size: mem::size_of::<PushConstants>() as u32,
+                        }]),
+                    None,
+                )
+                .unwrap();
+
+            let entry_point = cstr!("main").as_ptr();
+            let mut pipelines = device
+                .create_graphics_pipelines(
+                    gfx.pipeline_cache,
+                    &[vk::GraphicsPipelineCreateInfo::builder()
+                        .stages(&[
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::VERTEX,
+                                module: vert,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                            vk::PipelineShaderStageCreateInfo {
+                                stage: vk::ShaderStageFlags::FRAGMENT,
+                                module: frag,
+                                p_name: entry_point,
+                                ..Default::default()
+                            },
+                        ])
+                        .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                        .input_assembly_state(
+                            &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                        )
+                        .viewport_state(
+                            &vk::PipelineViewportStateCreateInfo::builder()
+                                .scissor_count(1)
+                                .viewport_count(1),
+                        )
+                        // The cube is convex, so depth testing alone hides its far faces
+                        .rasterization_state(
+                            &vk::PipelineRasterizationStateCreateInfo::builder()
+                                .cull_mode(vk::CullModeFlags::NONE)
+                                .polygon_mode(vk::PolygonMode::FILL)
+                                .line_width(1.0),
+                        )
+                        .multisample_state(
+                            &vk::PipelineMultisampleStateCreateInfo::builder()
+                                .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                        )
+                        .depth_stencil_state(
+                            &vk::PipelineDepthStencilStateCreateInfo::builder()
+                                .depth_test_enable(true)
+                                .depth_write_enable(true)
+                                .depth_compare_op(vk::CompareOp::GREATER),
+                        )
+                        .color_blend_state(
+                            &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                                vk::PipelineColorBlendAttachmentState {
+                                    blend_enable: vk::FALSE,
+                                    color_write_mask: vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B,
+                                    ..Default::default()
+                                },
+                            ]),
+                        )
+                        .dynamic_state(
+                            &vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&[
+                                vk::DynamicState::VIEWPORT,
+                                vk::DynamicState::SCISSOR,
+                            ]),
+                        )
+                        .layout(pipeline_layout)
+                        .render_pass(gfx.render_pass)
+                        .subpass(0)
+                        .build()],
+                    None,
+                )
+                .unwrap()
+                .into_iter();
+
+            let pipeline = pipelines.next().unwrap();
+            gfx.set_name(pipeline, cstr!("held block"));
+
+            v_guard.invoke();
+            f_guard.invoke();
+
+            Self {
+                ds_layout,
+                descriptor_pool,
+                ds,
+                materials: vk::ImageView::null(),
+                pipeline_layout,
+                pipeline,
+            }
+        }
+    }
+
+    /// Draw the unit cube, spanning -1 to 1 on each axis, mapped into view space by `model` and
+    /// textured with `layer` of `materials`
+    ///
+    /// The viewport must confine depth to `MIN_DEPTH` and above.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn draw(
+        &mut self,
+        device: &Device,
+        common_ds: vk::DescriptorSet,
+        cmd: vk::CommandBuffer,
+        materials: vk::ImageView,
+        projection: &na::Matrix4<f32>,
+        model: &na::Matrix4<f32>,
+        layer: u32,
+    ) {
+        if materials != self.materials {
+            if self.materials != vk::ImageView::null() {
+                // Frames still in flight may read the set, as when the textures are reloaded on
+                // connecting to another server
+                device.device_wait_idle().unwrap();
+            }
+            device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::builder()
+                    .dst_set(self.ds)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo {
+                        sampler: vk::Sampler::null(),
+                        image_view: materials,
+                        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    }])
+                    .build()],
+                &[],
+            );
+            self.materials = materials;
+        }
+        // Lighting is worked out in the cube's own space, where its normals are axes
+        let linear = model.fixed_view::<3, 3>(0, 0);
+        let light = (linear.transpose() * na::Vector3::from(LIGHT)).normalize();
+        let constants = PushConstants {
+            transform: projection * model,
+            params: na::Vector4::new(layer as f32, light.x, light.y, light.z),
+        };
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[common_ds, self.ds],
+            &[],
+        );
+        device.cmd_push_constants(
+            cmd,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            0,
+            super::as_bytes(&constants),
+        );
+        device.cmd_draw(cmd, 36, 1, 0, 0);
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_descriptor_set_layout(self.ds_layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PushConstants {
+    transform: na::Matrix4<f32>,
+    params: na::Vector4<f32>,
+}
+
+// Within the least push constant space any device offers
+const _: () = assert!(mem::size_of::<PushConstants>() <= 128);
@@ -1,33 +1,44 @@
 mod surface;
 pub mod surface_extraction;
+mod upload;
 
 #[cfg(test)]
 mod tests;
 
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use ash::{vk, Device};
-use metrics::histogram;
+use fxhash::FxHashSet;
+use metrics::{counter, gauge, histogram};
 use tracing::warn;
 
 use crate::{
-    graphics::{Base, Frustum},
-    loader::{Cleanup, LoadCtx, LoadFuture, Loadable, WorkQueue},
+    chunk_priority::{chunk_center, ChunkPriority, ChunkQueue},
+    graphics::{frustum::FrustumPlanes, Base},
+    loader::{Cleanup, Completion, Identified, LoadCtx, LoadFuture, Loadable, WorkQueue},
     Config, Loader, Sim,
 };
 use common::{
     dodeca,
     dodeca::Vertex,
-    graph::NodeId,
+    graph::{Graph, NodeId},
     lru_slab::SlotId,
     math,
+    mem_budget::{self, Counted},
     node::{Chunk, ChunkId, VoxelData},
-    traversal::nearby_nodes,
+    world::{Material, Shape},
+    worldgen::WorldgenPath,
+    worldgen_cache::{ChunkKey, WorldgenCache},
     LruSlab,
 };
 
+pub use surface::Pass;
 use surface::Surface;
 use surface_extraction::{DrawBuffer, ExtractTask, ScratchBuffer, SurfaceExtraction};
+use upload::{StagingRing, UploadQueue};
 
 pub struct Voxels {
     config: Arc<Config>,
@@ -38,6 +49,29 @@ pub struct Voxels {
     draw: Surface,
     max_chunks: u32,
     worldgen: WorkQueue<ChunkDesc>,
+    /// Fresh chunks in view, waiting for room in `worldgen`
+    generation: ChunkQueue<()>,
+    /// Scores ordering `generation` and `uploads` alike
+    priority: ChunkPriority,
+    /// When `prepare` last found a view, to tell how fast it's turning
+    last_view: Option<Instant>,
+    /// Generated chunks that couldn't be stored in the graph
+    worldgen_cache: WorldgenCache,
+    /// Space for a chunk's voxels and their margins, on their way to `extraction_scratch`
+    padded_materials: Counted<Material>,
+    padded_shapes: Counted<Shape>,
+    /// Chunks whose surfaces are wanted, waiting for their voxels to be uploaded for extraction
+    uploads: UploadQueue,
+    /// Space in `extraction_scratch`'s staging buffer, reused once the frame that read it completes
+    staging: StagingRing,
+    /// Most bytes of voxels uploaded in each frame
+    upload_budget: u64,
+    /// Identifies the next frame's use of `staging`
+    next_ticket: u64,
+    /// Distance from the view that chunks were last drawn out to
+    view_distance: f32,
+    /// Time the last `prepare` spent preparing chunk surfaces for extraction
+    upload_time: Duration,
 }
 
 impl Voxels {
@@ -48,8 +82,15 @@ impl Voxels {
         dimension: u32,
         frames: u32,
     ) -> Self {
-        let max_faces = 3 * (dimension.pow(3) + dimension.pow(2));
+        // Opaque and transparent faces each get their own space
+        let max_faces = 2 * surface_extraction::max_faces(dimension);
         let max_supported_chunks = gfx.limits.max_storage_buffer_range / (8 * max_faces);
+        let upload_size = surface_extraction::staging_size(dimension);
+        // Always room for at least one chunk, with none wasted on part of one
+        let upload_budget = config.upload_budget_bytes.max(upload_size);
+        let upload_budget = upload_budget - upload_budget % upload_size;
+        // Every frame in flight can use its whole budget
+        let staging_size = upload_budget * u64::from(frames);
         let max_chunks = if MAX_CHUNKS > max_supported_chunks {
             warn!(
                 "clamping max chunks to {} due to SSBO size limit",
@@ -66,86 +107,197 @@ impl Voxels {
             gfx,
             &surface_extraction,
             config.chunk_load_parallelism * frames,
+            staging_size,
             dimension,
         );
+        let mut states = LruSlab::with_capacity(max_chunks);
+        // Slots index into fixed-size GPU buffers
+        states.set_max_capacity(max_chunks);
+        let padded_len = (dimension as usize + 2).pow(3);
         Self {
-            worldgen: loader.make_queue(config.chunk_load_parallelism as usize),
+            worldgen: loader.make_queue(
+                config.chunk_load_parallelism as usize,
+                config.chunk_generation_timeout,
+            ),
+            generation: ChunkQueue::new(),
+            priority: ChunkPriority::new(),
+            last_view: None,
+            worldgen_cache: WorldgenCache::new(config.worldgen_cache_bytes),
             config,
             surface_extraction,
             extraction_scratch,
             surfaces,
-            states: LruSlab::with_capacity(max_chunks),
+            states,
             draw,
             max_chunks,
+            padded_materials: Counted::new(
+                &mem_budget::MESH_BUFFERS,
+                vec![Material::Void; padded_len],
+            ),
+            padded_shapes: Counted::new(&mem_budget::MESH_BUFFERS, vec![Shape::FULL; padded_len]),
+            uploads: UploadQueue::new(upload_size),
+            staging: StagingRing::new(staging_size),
+            upload_budget,
+            next_ticket: 0,
+            view_distance: f32::INFINITY,
+            upload_time: Duration::ZERO,
         }
     }
 
-    /// Determine what to render and stage chunk transforms
+    /// Texture array of every material but the void, by ID, once it's ready to be sampled
+    pub fn materials(&self) -> Option<vk::ImageView> {
+        self.draw.colors_view()
+    }
+
+    /// Time the last `prepare` spent preparing chunk surfaces for extraction
+    pub fn upload_time(&self) -> Duration {
+        self.upload_time
+    }
+
+    /// Fraction of the capacity for generating chunks that's in use
+    pub fn backlog(&self) -> f32 {
+        self.worldgen.fill()
+    }
+
+    /// Determine what to render out to `view_distance` and stage chunk transforms
     ///
     /// Surface extraction commands are written to `cmd`, and will be presumed complete for the next
-    /// (not current) frame.
+    /// (not current) frame. Chunks needing generation or surfaces are handled nearest and most
+    /// directly ahead first, as scored by `chunk_priority`, within the capacity for generation and
+    /// the per-frame upload budget, and the rest wait for later frames. When `view_distance`
+    /// shrinks, surfaces of chunks no longer in range are freed.
     pub unsafe fn prepare(
         &mut self,
         device: &Device,
         frame: &mut Frame,
         sim: &mut Sim,
         cmd: vk::CommandBuffer,
-        frustum: &Frustum,
+        frustum_planes: &FrustumPlanes,
+        view_distance: f32,
     ) {
         // Clean up after previous frame
         for i in frame.extracted.drain(..) {
             self.extraction_scratch.free(i);
         }
+        if let Some(ticket) = frame.staging_ticket.take() {
+            self.staging.release(ticket);
+        }
         for chunk in frame.drawn.drain(..) {
             self.states.peek_mut(chunk).refcount -= 1;
         }
-        while let Some(chunk) = self.worldgen.poll() {
-            let chunk_id = ChunkId::new(chunk.node, chunk.chunk);
-            sim.graph.populate_chunk(chunk_id, chunk.voxels, false);
-
-            // Now that the block is populated, we can apply any pending block updates the server
-            // provided that the client couldn't apply.
-            if let Some(block_updates) = sim.pending_modified_chunks.remove(&chunk_id) {
-                for block_update in block_updates {
-                    // The chunk was just populated, so a block update should always succeed.
-                    assert!(sim.graph.update_block(&block_update));
+        frame.transparent.clear();
+        while let Some(completion) = self.worldgen.poll() {
+            let mut chunk = match completion {
+                Completion::Loaded(chunk) => chunk,
+                Completion::Failed(chunk_id) => {
+                    // Leave it to be requested again, unless the node is gone or the data has
+                    // arrived some other way
+                    if let Some(Chunk::Generating) = sim.graph.get_chunk(chunk_id) {
+                        warn!(
+                            node = ?chunk_id.node,
+                            vertex = ?chunk_id.vertex,
+                            "retrying chunk generation"
+                        );
+                        counter!("worldgen.retry", 1);
+                        sim.graph[chunk_id] = Chunk::Fresh;
+                    }
+                    continue;
                 }
+            };
+            let chunk_id = ChunkId::new(chunk.node, chunk.chunk);
+            chunk.voxels.transfer(&mem_budget::DENSE_VOXELS);
+            match sim.graph.get_chunk(chunk_id) {
+                Some(Chunk::Generating) => sim.populate_chunk(chunk_id, chunk.voxels),
+                // The node is gone, but the data may be wanted again soon
+                None => self
+                    .worldgen_cache
+                    .insert(ChunkKey::new(&sim.graph, chunk_id), chunk.voxels),
+                // Populated in the meantime, e.g. by modified data from the server, which must not
+                // be clobbered
+                Some(_) => {}
             }
         }
 
         // Determine what to load/render
-        let view = sim.view();
+        // Nothing is loaded until there's somewhere to see it from
+        let Some(view) = sim.camera().position() else {
+            return;
+        };
         if !sim.graph.contains(view.node) {
             // Graph is temporarily out of sync with the server; we don't know where we are, so
             // there's no point trying to draw.
             return;
         }
+        let now = Instant::now();
+        let dt = self
+            .last_view
+            .replace(now)
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        self.priority.update(&view, dt);
+        if let Some(budget) = self.config.dense_voxel_budget_bytes {
+            // Chunks in view would only be generated again
+            let reclaimed =
+                mem_budget::reclaim_dense_voxels(&mut sim.graph, &view, view_distance, budget);
+            counter!("voxels.solidified", reclaimed.solidified as u64);
+            counter!("voxels.budget_evicted", reclaimed.evicted.len() as u64);
+        }
         let graph_traversal_started = Instant::now();
-        let mut nodes = nearby_nodes(
-            &sim.graph,
-            &view,
-            f64::from(self.config.local_simulation.view_distance),
-        );
+        let mut nodes = sim.nearby_nodes(f64::from(view_distance));
         histogram!(
             "frame.cpu.voxels.graph_traversal",
             graph_traversal_started.elapsed()
         );
-        // Sort nodes by distance to the view to prioritize loading closer data and improve early Z
-        // performance
+        if view_distance < self.view_distance {
+            let in_range = nodes
+                .iter()
+                .map(|&(node, _)| node)
+                .collect::<FxHashSet<_>>();
+            let graph = &mut sim.graph;
+            let before = self.states.len();
+            // Surfaces still referenced by frames in flight are freed once they're out of range
+            // and the distance shrinks again, or by the usual LRU replacement
+            self.states.retain(|slot, state| {
+                if state.refcount != 0 || in_range.contains(&state.node) {
+                    return true;
+                }
+                forget_surface(graph, slot, state);
+                false
+            });
+            counter!("voxels.evicted", u64::from(before - self.states.len()));
+        }
+        self.view_distance = view_distance;
+        // Sort nodes by distance to the view to improve early Z performance
         let view_pos = view.local * math::origin();
         nodes.sort_unstable_by(|&(_, ref xf_a), &(_, ref xf_b)| {
             math::distance(&view_pos, &(xf_a * math::origin()))
                 .partial_cmp(&math::distance(&view_pos, &(xf_b * math::origin())))
                 .unwrap_or(std::cmp::Ordering::Less)
         });
+        // Chunks the predicted character may reach come first, whether or not they're in view,
+        // since prediction is held back wherever they're missing
+        for node in sim.prefetch_nodes() {
+            if sim.graph.get(node).is_none() {
+                continue;
+            }
+            for vertex in Vertex::iter() {
+                let chunk = ChunkId::new(node, vertex);
+                if let Chunk::Fresh = sim.graph[chunk] {
+                    self.generate(sim, chunk);
+                }
+            }
+        }
         let node_scan_started = Instant::now();
-        let frustum_planes = frustum.planes();
         let local_to_view = math::mtranspose(&view.local);
-        let mut extractions = Vec::new();
+        self.generation.begin_frame();
+        self.uploads.begin_frame();
         for &(node, ref node_transform) in &nodes {
+            if sim.graph.get(node).is_none() {
+                // Still waiting to be populated
+                continue;
+            }
             let node_to_view = local_to_view * node_transform;
             let origin = node_to_view * math::origin();
-            if !frustum_planes.contain(&origin, dodeca::BOUNDING_SPHERE_RADIUS as f32) {
+            if !frustum_planes.contain(&origin, dodeca::BOUNDING_SPHERE_RADIUS_F32) {
                 // Don't bother generating or drawing chunks from nodes that are wholly outside the
                 // frustum.
                 continue;
@@ -155,95 +307,148 @@ impl Voxels {
             for vertex in Vertex::iter() {
                 let chunk = ChunkId::new(node, vertex);
                 // Fetch existing chunk, or extract surface of new chunk
-                match sim
-                    .graph
-                    .get_chunk_mut(chunk)
-                    .expect("all nodes must be populated before rendering")
-                {
+                let (surface, old_surface) = match sim.graph[chunk] {
                     Generating => continue,
                     Fresh => {
-                        // Generate voxel data
-                        if let Some(params) = common::worldgen::ChunkParams::new(
-                            self.surfaces.dimension() as u8,
-                            &sim.graph,
-                            chunk,
-                        ) {
-                            if self.worldgen.load(ChunkDesc { node, params }).is_ok() {
-                                sim.graph[chunk] = Generating;
-                            }
-                        }
+                        let score = self.priority.score(node, node_transform, vertex);
+                        self.generation.push(chunk, (), score);
                         continue;
                     }
                     Populated {
-                        ref mut surface,
+                        surface,
+                        old_surface,
+                        ..
+                    } => (surface, old_surface),
+                };
+                if let Some(slot) = surface.or(old_surface) {
+                    // Render an already-extracted surface
+                    let state = self.states.get_mut(slot);
+                    state.refcount += 1;
+                    frame.drawn.push(slot);
+                    if state.transparent {
+                        frame
+                            .transparent
+                            .push((slot, chunk_center(&node_to_view, vertex)));
+                    }
+                    // Transfer transform
+                    frame.surface.transforms_mut()[slot.0 as usize] =
+                        node_transform * vertex.chunk_to_node_f32();
+                }
+                if surface.is_some() {
+                    continue;
+                }
+                if !sim.graph.has_visible_faces(chunk) {
+                    // Neighbors may have been filled in since the old surface was extracted
+                    if let Populated {
                         ref mut old_surface,
-                        ref voxels,
                         ..
-                    } => {
-                        if let Some(slot) = surface.or(*old_surface) {
-                            // Render an already-extracted surface
-                            self.states.get_mut(slot).refcount += 1;
-                            frame.drawn.push(slot);
-                            // Transfer transform
-                            frame.surface.transforms_mut()[slot.0 as usize] =
-                                node_transform * vertex.chunk_to_node().map(|x| x as f32);
-                        }
-                        if let (None, &VoxelData::Dense(ref data)) = (&surface, voxels) {
-                            // Extract a surface so it can be drawn in future frames
-                            if frame.extracted.len() == self.config.chunk_load_parallelism as usize
-                            {
-                                continue;
-                            }
-                            let removed = if self.states.len() == self.max_chunks {
-                                let slot = self.states.lru().expect("full LRU table is nonempty");
-                                if self.states.peek(slot).refcount != 0 {
-                                    warn!("MAX_CHUNKS is too small");
-                                    break;
-                                }
-                                Some((slot, self.states.remove(slot)))
-                            } else {
-                                None
-                            };
-                            let scratch_slot = self.extraction_scratch.alloc().expect("there are at least chunks_loaded_per_frame scratch slots per frame");
-                            frame.extracted.push(scratch_slot);
-                            let slot = self.states.insert(SurfaceState {
-                                node,
-                                chunk: vertex,
-                                refcount: 0,
-                            });
-                            *surface = Some(slot);
-                            let storage = self.extraction_scratch.storage(scratch_slot);
-                            storage.copy_from_slice(&data[..]);
-                            if let Some((lru_slot, lru)) = removed {
-                                if let Populated {
-                                    ref mut surface,
-                                    ref mut old_surface,
-                                    ..
-                                } =
-                                    sim.graph.get_mut(lru.node).as_mut().unwrap().chunks[lru.chunk]
-                                {
-                                    // Remove references to released slot IDs
-                                    if surface.map_or(false, |slot| lru_slot == slot) {
-                                        *surface = None;
-                                    }
-                                    if old_surface.map_or(false, |slot| lru_slot == slot) {
-                                        *old_surface = None;
-                                    }
-                                }
-                            }
-                            let node_is_odd = sim.graph.length(node) & 1 != 0;
-                            extractions.push(ExtractTask {
-                                index: scratch_slot,
-                                indirect_offset: self.surfaces.indirect_offset(slot.0),
-                                face_offset: self.surfaces.face_offset(slot.0),
-                                draw_id: slot.0,
-                                reverse_winding: vertex.parity() ^ node_is_odd,
-                            });
-                        }
+                    } = sim.graph[chunk]
+                    {
+                        *old_surface = None;
                     }
+                    continue;
+                }
+                // Extract a surface so it can be drawn in future frames
+                let generation = sim
+                    .graph
+                    .chunk_generation(chunk)
+                    .expect("chunk is populated");
+                let score = self.priority.score(node, node_transform, vertex);
+                self.uploads.push(chunk, generation, score);
+            }
+        }
+
+        // Generate the most urgent chunks there's room for
+        counter!("worldgen.expired", self.generation.expire() as u64);
+        for chunk in self.generation.ordered() {
+            if !self.generate(sim, chunk) {
+                break;
+            }
+            self.generation.remove(chunk);
+        }
+        gauge!("worldgen.queue_depth", self.generation.len() as f64);
+
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        frame.staging_ticket = Some(ticket);
+        let stats_before = self.uploads.stats();
+        let graph = &sim.graph;
+        let uploads = self.uploads.schedule(
+            self.upload_budget,
+            self.config.chunk_load_parallelism as usize,
+            &mut self.staging,
+            ticket,
+            |chunk| graph.chunk_generation(chunk),
+        );
+        let mut extractions = Vec::with_capacity(uploads.len());
+        for upload in uploads {
+            let ChunkId { node, vertex } = upload.chunk;
+            let removed = if self.states.len() == self.max_chunks {
+                let slot = self.states.lru().expect("full LRU table is nonempty");
+                if self.states.peek(slot).refcount != 0 {
+                    warn!("MAX_CHUNKS is too small");
+                    break;
                 }
+                Some((slot, self.states.remove(slot)))
+            } else {
+                None
+            };
+            let scratch_slot = self
+                .extraction_scratch
+                .alloc()
+                .expect("there are at least chunks_loaded_per_frame scratch slots per frame");
+            frame.extracted.push(scratch_slot);
+            sim.graph.write_padded_voxels(
+                upload.chunk,
+                &mut self.padded_materials,
+                &mut self.padded_shapes,
+            );
+            let staging = self.extraction_scratch.staging(upload.offset);
+            for (out, (&material, &shape)) in staging
+                .iter_mut()
+                .zip(self.padded_materials.iter().zip(&self.padded_shapes))
+            {
+                *out = surface_extraction::pack_voxel(material, shape);
+            }
+            let slot = self.states.insert(SurfaceState {
+                node,
+                chunk: vertex,
+                refcount: 0,
+                transparent: self.padded_materials.iter().any(|x| x.is_transparent()),
+            });
+            if let Chunk::Populated {
+                ref mut surface, ..
+            } = sim.graph[upload.chunk]
+            {
+                *surface = Some(slot);
+            }
+            if let Some((lru_slot, lru)) = removed {
+                forget_surface(&mut sim.graph, lru_slot, &lru);
             }
+            let node_is_odd = sim.graph.length(node) & 1 != 0;
+            extractions.push(ExtractTask {
+                staging_offset: upload.offset,
+                index: scratch_slot,
+                indirect_offset: self.surfaces.indirect_offset(slot.0),
+                face_offset: self.surfaces.face_offset(slot.0),
+                draw_id: slot.0,
+                reverse_winding: vertex.parity() ^ node_is_odd,
+            });
         }
+        let stats = self.uploads.stats();
+        let bytes = stats.bytes - stats_before.bytes;
+        counter!("voxels.upload.chunks", extractions.len() as u64);
+        counter!("voxels.upload.bytes", bytes);
+        counter!(
+            "voxels.upload.superseded",
+            stats.superseded - stats_before.superseded
+        );
+        counter!(
+            "voxels.upload.expired",
+            stats.expired - stats_before.expired
+        );
+        gauge!("voxels.upload.frame_bytes", bytes as f64);
+        gauge!("voxels.upload.queue_depth", self.uploads.len() as f64);
         self.extraction_scratch.extract(
             device,
             &self.surface_extraction,
@@ -252,9 +457,45 @@ impl Voxels {
             cmd,
             &extractions,
         );
-        histogram!("frame.cpu.voxels.node_scan", node_scan_started.elapsed());
+        sort_back_to_front(&mut frame.transparent);
+        self.upload_time = node_scan_started.elapsed();
+        histogram!("frame.cpu.voxels.node_scan", self.upload_time);
     }
 
+    /// Populate the fresh `chunk` from the worldgen cache, or else start generating it, returning
+    /// false if there was no room to generate it
+    fn generate(&mut self, sim: &mut Sim, chunk: ChunkId) -> bool {
+        if let Some(voxels) = self.worldgen_cache.take(&ChunkKey::new(&sim.graph, chunk)) {
+            counter!("worldgen.cache.hit", 1);
+            sim.populate_chunk(chunk, voxels);
+            return true;
+        }
+        // Generate voxel data
+        if let Some(params) = common::worldgen::ChunkParams::new(
+            self.surfaces.dimension() as u8,
+            &sim.graph,
+            chunk,
+            &sim.cfg().terrain,
+        ) {
+            if self
+                .worldgen
+                .load(ChunkDesc {
+                    node: chunk.node,
+                    params,
+                })
+                .is_err()
+            {
+                return false;
+            }
+            counter!("worldgen.cache.miss", 1);
+            sim.graph[chunk] = Chunk::Generating;
+        }
+        true
+    }
+
+    /// Draw the faces selected by `pass` of the chunks chosen by `prepare`
+    ///
+    /// The transparent pass must follow all opaque geometry, including that drawn by other means.
     pub unsafe fn draw(
         &mut self,
         device: &Device,
@@ -262,22 +503,29 @@ impl Voxels {
         common_ds: vk::DescriptorSet,
         frame: &Frame,
         cmd: vk::CommandBuffer,
+        pass: Pass,
     ) {
         let started = Instant::now();
-        if !self.draw.bind(
-            device,
-            loader,
-            self.surfaces.dimension(),
-            common_ds,
-            &frame.surface,
-            cmd,
-        ) {
+        if !self
+            .draw
+            .bind(device, loader, common_ds, &frame.surface, cmd, pass)
+        {
             return;
         }
-        for chunk in &frame.drawn {
-            self.draw.draw(device, cmd, &self.surfaces, chunk.0);
+        match pass {
+            Pass::Opaque => {
+                for chunk in &frame.drawn {
+                    self.draw.draw(device, cmd, &self.surfaces, chunk.0, pass);
+                }
+                histogram!("frame.cpu.voxels.draw", started.elapsed());
+            }
+            Pass::Transparent => {
+                for &(chunk, _) in &frame.transparent {
+                    self.draw.draw(device, cmd, &self.surfaces, chunk.0, pass);
+                }
+                histogram!("frame.cpu.voxels.draw_transparent", started.elapsed());
+            }
         }
-        histogram!("frame.cpu.voxels.draw", started.elapsed());
     }
 
     pub unsafe fn destroy(&mut self, device: &Device) {
@@ -288,11 +536,48 @@ impl Voxels {
     }
 }
 
+/// Remove references to the released surface `slot` from the chunk it was extracted from
+fn forget_surface(graph: &mut Graph, slot: SlotId, state: &SurfaceState) {
+    if !graph.contains(state.node) {
+        return;
+    }
+    let Some(node) = graph.get_mut(state.node).as_mut() else {
+        return;
+    };
+    if let Chunk::Populated {
+        ref mut surface,
+        ref mut old_surface,
+        ..
+    } = node.chunks[state.chunk]
+    {
+        if *surface == Some(slot) {
+            *surface = None;
+        }
+        if *old_surface == Some(slot) {
+            *old_surface = None;
+        }
+    }
+}
+
+/// Order chunks, paired with their centers relative to the view, from farthest to nearest
+///
+/// Blending transparent faces requires drawing what's behind them first. Sorting whole chunks
+/// leaves faces out of order within a chunk, and where chunks' bounds interleave, but is cheap.
+fn sort_back_to_front(chunks: &mut [(SlotId, na::Vector4<f32>)]) {
+    // The view is at the origin, where the distance to a normalized point increases with its w
+    chunks.sort_unstable_by(|a, b| b.1.w.total_cmp(&a.1.w));
+}
+
 pub struct Frame {
     surface: surface::Frame,
     /// Scratch slots completed in this frame
     extracted: Vec<u32>,
+    /// Identifies the staging space read by this frame, to be released once it completes
+    staging_ticket: Option<u64>,
     drawn: Vec<SlotId>,
+    /// Drawn chunks that may have transparent faces, with their centers relative to the view,
+    /// farthest first
+    transparent: Vec<(SlotId, na::Vector4<f32>)>,
 }
 
 impl Frame {
@@ -306,7 +591,9 @@ impl Frame {
         Self {
             surface: surface::Frame::new(gfx, ctx.states.capacity()),
             extracted: Vec::new(),
+            staging_ticket: None,
             drawn: Vec::new(),
+            transparent: Vec::new(),
         }
     }
 }
@@ -318,6 +605,8 @@ struct SurfaceState {
     node: NodeId,
     chunk: common::dodeca::Vertex,
     refcount: u32,
+    /// Whether any voxel the surface was extracted from is transparent
+    transparent: bool,
 }
 
 struct ChunkDesc {
@@ -335,14 +624,30 @@ impl Cleanup for LoadedChunk {
     unsafe fn cleanup(self, _gfx: &Base) {}
 }
 
+impl Identified for ChunkDesc {
+    type Id = ChunkId;
+    fn id(&self) -> ChunkId {
+        ChunkId::new(self.node, self.params.chunk())
+    }
+}
+
 impl Loadable for ChunkDesc {
     type Output = LoadedChunk;
     fn load(self, _ctx: &LoadCtx) -> LoadFuture<'_, Self::Output> {
         Box::pin(async move {
+            let started = Instant::now();
+            let mut voxels = self.params.generate_voxels();
+            histogram!(
+                "worldgen.chunk",
+                started.elapsed(),
+                "path" => WorldgenPath::fastest().name()
+            );
+            // Counted as dense voxels once received
+            voxels.transfer(&mem_budget::CHUNK_LOADS);
             Ok(LoadedChunk {
                 node: self.node,
                 chunk: self.params.chunk(),
-                voxels: self.params.generate_voxels(),
+                voxels,
             })
         })
     }
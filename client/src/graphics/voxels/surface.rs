@@ -15,10 +15,13 @@ pub struct Surface {
     static_ds_layout: vk::DescriptorSetLayout,
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
+    transparent_pipeline: vk::Pipeline,
     descriptor_pool: vk::DescriptorPool,
     ds: vk::DescriptorSet,
     colors: Asset<DedicatedImage>,
     colors_view: vk::ImageView,
+    /// Number of voxels along a chunk edge
+    dimension: u32,
 }
 
 impl Surface {
@@ -112,108 +115,125 @@ impl Surface {
                 .unwrap();
 
             let entry_point = cstr!("main").as_ptr();
+            let stages = [
+                vk::PipelineShaderStageCreateInfo {
+                    stage: vk::ShaderStageFlags::VERTEX,
+                    module: vert,
+                    p_name: entry_point,
+                    ..Default::default()
+                },
+                vk::PipelineShaderStageCreateInfo {
+                    stage: vk::ShaderStageFlags::FRAGMENT,
+                    module: frag,
+                    p_name: entry_point,
+                    ..Default::default()
+                },
+            ];
+            let vertex_bindings = [vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: TRANSFORM_SIZE as u32,
+                input_rate: vk::VertexInputRate::INSTANCE,
+            }];
+            let vertex_attributes = [0, 1, 2, 3].map(|i| vk::VertexInputAttributeDescription {
+                location: i,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: 16 * i,
+            });
+            let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+                .vertex_binding_descriptions(&vertex_bindings)
+                .vertex_attribute_descriptions(&vertex_attributes);
+            let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+                .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+            let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+                .scissor_count(1)
+                .viewport_count(1);
+            let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+                .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+            let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+            let dynamic_state =
+                vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+            let color_write_mask = vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B;
+
+            let opaque_rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+                .cull_mode(vk::CullModeFlags::BACK)
+                .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                .polygon_mode(vk::PolygonMode::FILL)
+                .line_width(1.0);
+            let opaque_depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+                .depth_test_enable(true)
+                .depth_write_enable(true)
+                .depth_compare_op(vk::CompareOp::GREATER);
+            let opaque_blend_attachments = [vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::TRUE,
+                src_color_blend_factor: vk::BlendFactor::ONE,
+                dst_color_blend_factor: vk::BlendFactor::ZERO,
+                color_blend_op: vk::BlendOp::ADD,
+                color_write_mask,
+                ..Default::default()
+            }];
+            let opaque_color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+                .attachments(&opaque_blend_attachments);
+
+            // Transparent faces are visible from both sides, as when looking up from under water
+            let transparent_rasterization_state =
+                vk::PipelineRasterizationStateCreateInfo::builder()
+                    .cull_mode(vk::CullModeFlags::NONE)
+                    .polygon_mode(vk::PolygonMode::FILL)
+                    .line_width(1.0);
+            // Occluded by, but not occluding, other geometry, so that whatever's behind them
+            // remains visible regardless of draw order
+            let transparent_depth_stencil_state =
+                vk::PipelineDepthStencilStateCreateInfo::builder()
+                    .depth_test_enable(true)
+                    .depth_write_enable(false)
+                    .depth_compare_op(vk::CompareOp::GREATER);
+            let transparent_blend_attachments = [vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::TRUE,
+                src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+                dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                color_blend_op: vk::BlendOp::ADD,
+                color_write_mask,
+                ..Default::default()
+            }];
+            let transparent_color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+                .attachments(&transparent_blend_attachments);
+
             let mut pipelines = device
                 .create_graphics_pipelines(
                     gfx.pipeline_cache,
-                    &[vk::GraphicsPipelineCreateInfo::builder()
-                        .stages(&[
-                            vk::PipelineShaderStageCreateInfo {
-                                stage: vk::ShaderStageFlags::VERTEX,
-                                module: vert,
-                                p_name: entry_point,
-                                ..Default::default()
-                            },
-                            vk::PipelineShaderStageCreateInfo {
-                                stage: vk::ShaderStageFlags::FRAGMENT,
-                                module: frag,
-                                p_name: entry_point,
-                                ..Default::default()
-                            },
-                        ])
-                        .vertex_input_state(
-                            &vk::PipelineVertexInputStateCreateInfo::builder()
-                                .vertex_binding_descriptions(&[vk::VertexInputBindingDescription {
-                                    binding: 0,
-                                    stride: TRANSFORM_SIZE as u32,
-                                    input_rate: vk::VertexInputRate::INSTANCE,
-                                }])
-                                .vertex_attribute_descriptions(&[
-                                    vk::VertexInputAttributeDescription {
-                                        location: 0,
-                                        binding: 0,
-                                        format: vk::Format::R32G32B32A32_SFLOAT,
-                                        offset: 0,
-                                    },
-                                    vk::VertexInputAttributeDescription {
-                                        location: 1,
-                                        binding: 0,
-                                        format: vk::Format::R32G32B32A32_SFLOAT,
-                                        offset: 16,
-                                    },
-                                    vk::VertexInputAttributeDescription {
-                                        location: 2,
-                                        binding: 0,
-                                        format: vk::Format::R32G32B32A32_SFLOAT,
-                                        offset: 32,
-                                    },
-                                    vk::VertexInputAttributeDescription {
-                                        location: 3,
-                                        binding: 0,
-                                        format: vk::Format::R32G32B32A32_SFLOAT,
-                                        offset: 48,
-                                    },
-                                ]),
-                        )
-                        .input_assembly_state(
-                            &vk::PipelineInputAssemblyStateCreateInfo::builder()
-                                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
-                        )
-                        .viewport_state(
-                            &vk::PipelineViewportStateCreateInfo::builder()
-                                .scissor_count(1)
-                                .viewport_count(1),
-                        )
-                        .rasterization_state(
-                            &vk::PipelineRasterizationStateCreateInfo::builder()
-                                .cull_mode(vk::CullModeFlags::BACK)
-                                .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-                                .polygon_mode(vk::PolygonMode::FILL)
-                                .line_width(1.0),
-                        )
-                        .multisample_state(
-                            &vk::PipelineMultisampleStateCreateInfo::builder()
-                                .rasterization_samples(vk::SampleCountFlags::TYPE_1),
-                        )
-                        .depth_stencil_state(
-                            &vk::PipelineDepthStencilStateCreateInfo::builder()
-                                .depth_test_enable(true)
-                                .depth_write_enable(true)
-                                .depth_compare_op(vk::CompareOp::GREATER),
-                        )
-                        .color_blend_state(
-                            &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
-                                vk::PipelineColorBlendAttachmentState {
-                                    blend_enable: vk::TRUE,
-                                    src_color_blend_factor: vk::BlendFactor::ONE,
-                                    dst_color_blend_factor: vk::BlendFactor::ZERO,
-                                    color_blend_op: vk::BlendOp::ADD,
-                                    color_write_mask: vk::ColorComponentFlags::R
-                                        | vk::ColorComponentFlags::G
-                                        | vk::ColorComponentFlags::B,
-                                    ..Default::default()
-                                },
-                            ]),
-                        )
-                        .dynamic_state(
-                            &vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&[
-                                vk::DynamicState::VIEWPORT,
-                                vk::DynamicState::SCISSOR,
-                            ]),
-                        )
-                        .layout(pipeline_layout)
-                        .render_pass(gfx.render_pass)
-                        .subpass(0)
-                        .build()],
+                    &[
+                        vk::GraphicsPipelineCreateInfo::builder()
+                            .stages(&stages)
+                            .vertex_input_state(&vertex_input_state)
+                            .input_assembly_state(&input_assembly_state)
+                            .viewport_state(&viewport_state)
+                            .rasterization_state(&opaque_rasterization_state)
+                            .multisample_state(&multisample_state)
+                            .depth_stencil_state(&opaque_depth_stencil_state)
+                            .color_blend_state(&opaque_color_blend_state)
+                            .dynamic_state(&dynamic_state)
+                            .layout(pipeline_layout)
+                            .render_pass(gfx.render_pass)
+                            .subpass(0)
+                            .build(),
+                        vk::GraphicsPipelineCreateInfo::builder()
+                            .stages(&stages)
+                            .vertex_input_state(&vertex_input_state)
+                            .input_assembly_state(&input_assembly_state)
+                            .viewport_state(&viewport_state)
+                            .rasterization_state(&transparent_rasterization_state)
+                            .multisample_state(&multisample_state)
+                            .depth_stencil_state(&transparent_depth_stencil_state)
+                            .color_blend_state(&transparent_color_blend_state)
+                            .dynamic_state(&dynamic_state)
+                            .layout(pipeline_layout)
+                            .render_pass(gfx.render_pass)
+                            .subpass(0)
+                            .build(),
+                    ],
                     None,
                 )
                 .unwrap()
@@ -221,6 +241,8 @@ impl Surface {
 
             let pipeline = pipelines.next().unwrap();
             gfx.set_name(pipeline, cstr!("voxels"));
+            let transparent_pipeline = pipelines.next().unwrap();
+            gfx.set_name(transparent_pipeline, cstr!("transparent voxels"));
 
             // Clean up the shaders explicitly, so the defer guards don't hold onto references we're
             // moving into `Self` to be returned
@@ -231,7 +253,11 @@ impl Surface {
                 "voxel materials",
                 crate::graphics::PngArray {
                     path: "materials".into(),
-                    size: common::world::Material::COUNT - 1,
+                    // Every material but the void, by ID
+                    layers: common::world::Material::VALUES[1..]
+                        .iter()
+                        .map(|&material| format!("{:05}_", material.id()))
+                        .collect(),
                 },
             );
 
@@ -239,22 +265,29 @@ impl Surface {
                 static_ds_layout,
                 pipeline_layout,
                 pipeline,
+                transparent_pipeline,
                 descriptor_pool,
                 ds,
                 colors,
                 colors_view: vk::ImageView::null(),
+                dimension: buffer.dimension(),
             }
         }
     }
 
+    /// View of the material textures, once they've loaded and the first chunk has been drawn
+    pub fn colors_view(&self) -> Option<vk::ImageView> {
+        (self.colors_view != vk::ImageView::null()).then_some(self.colors_view)
+    }
+
     pub unsafe fn bind(
         &mut self,
         device: &Device,
         loader: &Loader,
-        dimension: u32,
         common_ds: vk::DescriptorSet,
         frame: &Frame,
         cmd: vk::CommandBuffer,
+        pass: Pass,
     ) -> bool {
         if self.colors_view == vk::ImageView::null() {
             if let Some(colors) = loader.get(self.colors) {
@@ -292,7 +325,11 @@ impl Surface {
             }
         }
 
-        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        let pipeline = match pass {
+            Pass::Opaque => self.pipeline,
+            Pass::Transparent => self.transparent_pipeline,
+        };
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
         device.cmd_bind_descriptor_sets(
             cmd,
             vk::PipelineBindPoint::GRAPHICS,
@@ -308,7 +345,7 @@ impl Surface {
             self.pipeline_layout,
             vk::ShaderStageFlags::VERTEX,
             0,
-            &dimension.to_ne_bytes(),
+            &self.dimension.to_ne_bytes(),
         );
 
         true
@@ -320,18 +357,18 @@ impl Surface {
         cmd: vk::CommandBuffer,
         buffer: &DrawBuffer,
         chunk: u32,
+        pass: Pass,
     ) {
-        device.cmd_draw_indirect(
-            cmd,
-            buffer.indirect_buffer(),
-            buffer.indirect_offset(chunk),
-            1,
-            16,
-        );
+        let offset = match pass {
+            Pass::Opaque => buffer.indirect_offset(chunk),
+            Pass::Transparent => buffer.transparent_indirect_offset(chunk),
+        };
+        device.cmd_draw_indirect(cmd, buffer.indirect_buffer(), offset, 1, 16);
     }
 
     pub unsafe fn destroy(&mut self, device: &Device) {
         device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline(self.transparent_pipeline, None);
         device.destroy_pipeline_layout(self.pipeline_layout, None);
         device.destroy_descriptor_set_layout(self.static_ds_layout, None);
         device.destroy_descriptor_pool(self.descriptor_pool, None);
@@ -341,6 +378,15 @@ impl Surface {
     }
 }
 
+/// Which of a chunk's faces are being drawn
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Pass {
+    /// Faces of opaque voxels, drawn first
+    Opaque,
+    /// Faces of transparent voxels, blended over everything else from back to front
+    Transparent,
+}
+
 pub struct Frame {
     transforms: DedicatedMapping<[na::Matrix4<f32>]>,
 }
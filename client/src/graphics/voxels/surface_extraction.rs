@@ -5,7 +5,11 @@ use lahar::{DedicatedBuffer, DedicatedMapping};
 use vk_shader_macros::include_glsl;
 
 use crate::graphics::{as_bytes, Base, VkDrawIndirectCommand};
-use common::{defer, world::Material};
+use common::{
+    defer,
+    mem_budget::{self, Reservation},
+    world::{Material, Shape},
+};
 
 const EXTRACT: &[u32] = include_glsl!("shaders/surface-extraction/extract.comp", target: vulkan1_1);
 
@@ -160,7 +164,8 @@ pub struct ScratchBuffer {
     voxel_buffer_unit: vk::DeviceSize,
     /// Size of a single entry in the state buffer
     state_buffer_unit: vk::DeviceSize,
-    voxels_staging: DedicatedMapping<[Material]>,
+    /// Host-visible space from which voxels are copied into `voxels`, allocated by the caller
+    voxels_staging: DedicatedMapping<[u16]>,
     voxels: DedicatedBuffer,
     state: DedicatedBuffer,
     descriptor_pool: vk::DescriptorPool,
@@ -168,20 +173,36 @@ pub struct ScratchBuffer {
     descriptor_sets: Vec<vk::DescriptorSet>,
     free_slots: Vec<u32>,
     concurrency: u32,
+    _memory: Reservation,
 }
 
 impl ScratchBuffer {
-    pub fn new(gfx: &Base, ctx: &SurfaceExtraction, concurrency: u32, dimension: u32) -> Self {
+    /// Scratch space for `concurrency` extractions at once, with `staging_size` bytes of staging
+    /// space for their voxels
+    pub fn new(
+        gfx: &Base,
+        ctx: &SurfaceExtraction,
+        concurrency: u32,
+        staging_size: vk::DeviceSize,
+        dimension: u32,
+    ) -> Self {
         let device = &*gfx.device;
         // Padded by 2 on each dimension so each voxel of interest has a full neighborhood
         let voxel_buffer_unit = round_up(
-            mem::size_of::<Material>() as vk::DeviceSize * (dimension as vk::DeviceSize + 2).pow(3),
+            mem::size_of::<u16>() as vk::DeviceSize * (dimension as vk::DeviceSize + 2).pow(3),
             // Pad at least to multiples of 4 so the shaders can safely read in 32 bit units
             gfx.limits.min_storage_buffer_offset_alignment.max(4),
         );
         let voxels_size = concurrency as vk::DeviceSize * voxel_buffer_unit;
 
-        let state_buffer_unit = round_up(4, gfx.limits.min_storage_buffer_offset_alignment);
+        // Opaque and transparent face counts
+        let state_buffer_unit = round_up(8, gfx.limits.min_storage_buffer_offset_alignment);
+        let state_size = state_buffer_unit * vk::DeviceSize::from(concurrency);
+        // Parameters, staged voxels, voxels, and state
+        let memory = gfx.reserve_memory(
+            &mem_budget::GPU,
+            mem::size_of::<Params>() as vk::DeviceSize + staging_size + voxels_size + state_size,
+        );
         unsafe {
             let params = DedicatedBuffer::new(
                 device,
@@ -200,7 +221,7 @@ impl ScratchBuffer {
                 device,
                 &gfx.memory_properties,
                 vk::BufferUsageFlags::TRANSFER_SRC,
-                (voxels_size / mem::size_of::<Material>() as vk::DeviceSize) as usize,
+                (staging_size / mem::size_of::<u16>() as vk::DeviceSize) as usize,
             );
             gfx.set_name(voxels_staging.buffer(), cstr!("voxels staging"));
 
@@ -221,7 +242,7 @@ impl ScratchBuffer {
                 device,
                 &gfx.memory_properties,
                 &vk::BufferCreateInfo::builder()
-                    .size(state_buffer_unit * vk::DeviceSize::from(concurrency))
+                    .size(state_size)
                     .usage(
                         vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
                     )
@@ -286,6 +307,7 @@ impl ScratchBuffer {
                 descriptor_sets,
                 free_slots: (0..concurrency).collect(),
                 concurrency,
+                _memory: memory,
             }
         }
     }
@@ -302,9 +324,12 @@ impl ScratchBuffer {
         self.free_slots.push(index);
     }
 
-    /// Includes a one-voxel margin around the entire volume
-    pub fn storage(&mut self, index: u32) -> &mut [Material] {
-        let start = index as usize * (self.voxel_buffer_unit as usize / mem::size_of::<Material>());
+    /// Staging space for a chunk's voxels at byte `offset`, which must be a multiple of 4
+    ///
+    /// Includes a one-voxel margin around the entire volume. Each voxel is written by `pack_voxel`.
+    pub fn staging(&mut self, offset: vk::DeviceSize) -> &mut [u16] {
+        debug_assert_eq!(offset % 4, 0, "misaligned voxel staging offset");
+        let start = offset as usize / mem::size_of::<u16>();
         let length = (self.dimension + 2).pow(3) as usize;
         &mut self.voxels_staging[start..start + length]
     }
@@ -325,14 +350,15 @@ impl ScratchBuffer {
             0,
             as_bytes(&Params {
                 dimension: self.dimension,
+                _padding: [0; 3],
+                transparent: transparency_mask(),
             }),
         );
         device.cmd_fill_buffer(cmd, self.state.handle, 0, vk::WHOLE_SIZE, 0);
 
         let voxel_count = (self.dimension + 2).pow(3) as usize;
-        let voxels_range =
-            voxel_count as vk::DeviceSize * mem::size_of::<Material>() as vk::DeviceSize;
-        let max_faces = 3 * (self.dimension.pow(3) + self.dimension.pow(2));
+        let voxels_range = voxel_count as vk::DeviceSize * mem::size_of::<u16>() as vk::DeviceSize;
+        let max_faces = max_faces(self.dimension);
         let dispatch = dispatch_sizes(self.dimension);
         device.cmd_bind_descriptor_sets(
             cmd,
@@ -389,7 +415,7 @@ impl ScratchBuffer {
                         .buffer_info(&[vk::DescriptorBufferInfo {
                             buffer: self.state.handle,
                             offset: self.state_buffer_unit * vk::DeviceSize::from(task.index),
-                            range: 4,
+                            range: 8,
                         }])
                         .build(),
                     vk::WriteDescriptorSet::builder()
@@ -399,7 +425,7 @@ impl ScratchBuffer {
                         .buffer_info(&[vk::DescriptorBufferInfo {
                             buffer: indirect_buffer,
                             offset: task.indirect_offset,
-                            range: INDIRECT_SIZE,
+                            range: 2 * INDIRECT_SIZE,
                         }])
                         .build(),
                     vk::WriteDescriptorSet::builder()
@@ -409,7 +435,7 @@ impl ScratchBuffer {
                         .buffer_info(&[vk::DescriptorBufferInfo {
                             buffer: face_buffer,
                             offset: task.face_offset,
-                            range: max_faces as vk::DeviceSize * FACE_SIZE,
+                            range: 2 * max_faces as vk::DeviceSize * FACE_SIZE,
                         }])
                         .build(),
                 ],
@@ -421,21 +447,24 @@ impl ScratchBuffer {
                 self.voxels_staging.buffer(),
                 self.voxels.handle,
                 &[vk::BufferCopy {
-                    src_offset: voxels_offset,
+                    src_offset: task.staging_offset,
                     dst_offset: voxels_offset,
                     size: voxels_range,
                 }],
             );
+            // Transparent faces are stored after the space reserved for opaque faces
+            let first_face = (task.face_offset / FACE_SIZE) as u32;
+            let draw = |first_face: u32| VkDrawIndirectCommand {
+                vertex_count: 0,
+                instance_count: 1,
+                first_vertex: first_face * 6,
+                first_instance: task.draw_id,
+            };
             device.cmd_update_buffer(
                 cmd,
                 indirect_buffer,
                 task.indirect_offset,
-                as_bytes(&VkDrawIndirectCommand {
-                    vertex_count: 0,
-                    instance_count: 1,
-                    first_vertex: (task.face_offset / FACE_SIZE) as u32 * 6,
-                    first_instance: task.draw_id,
-                }),
+                as_bytes(&[draw(first_face), draw(first_face + max_faces)]),
             )
         }
 
@@ -502,6 +531,8 @@ impl ScratchBuffer {
 /// Specifies a single chunk's worth of surface extraction work
 #[derive(Debug, Copy, Clone)]
 pub struct ExtractTask {
+    /// Offset of the chunk's voxels in the staging buffer
+    pub staging_offset: vk::DeviceSize,
     pub indirect_offset: vk::DeviceSize,
     pub face_offset: vk::DeviceSize,
     pub index: u32,
@@ -528,6 +559,58 @@ fn dispatch_sizes(dimension: u32) -> na::Vector3<u32> {
 #[derive(Copy, Clone)]
 struct Params {
     dimension: u32,
+    _padding: [u32; 3],
+    /// Bit `i` is set if the material with ID `i` is transparent
+    transparent: [u32; 4],
+}
+
+fn transparency_mask() -> [u32; 4] {
+    let mut mask = [0; 4];
+    for material in Material::VALUES {
+        if material.is_transparent() {
+            let i = usize::from(material.id());
+            mask[i / 32] |= 1 << (i % 32);
+        }
+    }
+    mask
+}
+
+/// Material ID given to the surface extraction shader in place of materials this build doesn't
+/// know, which `voxels.frag` draws as a placeholder pattern rather than a texture
+pub const PLACEHOLDER_MATERIAL: u16 = 127;
+
+// The placeholder must not be mistaken for a known material, and must fit the transparency mask
+const _: () = assert!(Material::COUNT <= PLACEHOLDER_MATERIAL as usize);
+
+/// A voxel as read by the surface extraction shader: its material in the low byte, and the octants
+/// its shape fills in the high byte
+pub fn pack_voxel(material: Material, shape: Shape) -> u16 {
+    let material = if material.is_known() {
+        material.id()
+    } else {
+        PLACEHOLDER_MATERIAL
+    };
+    material | u16::from(shape.octants()) << 8
+}
+
+/// Bytes of staging space taken by the voxels of a chunk having `dimension` voxels along each edge,
+/// including margins
+pub fn staging_size(dimension: u32) -> vk::DeviceSize {
+    round_up(
+        mem::size_of::<u16>() as vk::DeviceSize * (dimension as vk::DeviceSize + 2).pow(3),
+        4,
+    )
+}
+
+/// Number of faces that can be stored for a chunk having `dimension` voxels along each edge, in each
+/// of the opaque and transparent sections of its space in the face buffer
+///
+/// This is enough for a face between every pair of adjacent voxels, which is all there can be of
+/// opaque faces where every voxel is a full cube. Transparent faces can exceed it only where two
+/// different transparent materials are interleaved voxel by voxel, and faces of other shapes only
+/// where those are packed densely with gaps between them, in which case the excess is dropped.
+pub fn max_faces(dimension: u32) -> u32 {
+    3 * (dimension.pow(3) + dimension.pow(2))
 }
 
 /// Manages storage for ready-to-render voxels
@@ -537,6 +620,7 @@ pub struct DrawBuffer {
     dimension: u32,
     face_buffer_unit: vk::DeviceSize,
     count: u32,
+    _memory: Reservation,
 }
 
 impl DrawBuffer {
@@ -545,19 +629,23 @@ impl DrawBuffer {
     pub fn new(gfx: &Base, count: u32, dimension: u32) -> Self {
         let device = &*gfx.device;
 
-        let max_faces = 3 * (dimension.pow(3) + dimension.pow(2));
         let face_buffer_unit = round_up(
-            max_faces as vk::DeviceSize * FACE_SIZE,
+            2 * max_faces(dimension) as vk::DeviceSize * FACE_SIZE,
             gfx.limits.min_storage_buffer_offset_alignment,
         );
         let face_buffer_size = count as vk::DeviceSize * face_buffer_unit;
+        let indirect_buffer_size = 2 * count as vk::DeviceSize * INDIRECT_SIZE;
+        let memory = gfx.reserve_memory(
+            &mem_budget::GPU_SURFACES,
+            face_buffer_size + indirect_buffer_size,
+        );
 
         unsafe {
             let indirect = DedicatedBuffer::new(
                 device,
                 &gfx.memory_properties,
                 &vk::BufferCreateInfo::builder()
-                    .size(count as vk::DeviceSize * INDIRECT_SIZE)
+                    .size(indirect_buffer_size)
                     .usage(
                         vk::BufferUsageFlags::STORAGE_BUFFER
                             | vk::BufferUsageFlags::INDIRECT_BUFFER
@@ -585,6 +673,7 @@ impl DrawBuffer {
                 dimension,
                 face_buffer_unit,
                 count,
+                _memory: memory,
             }
         }
     }
@@ -605,10 +694,17 @@ impl DrawBuffer {
         vk::DeviceSize::from(chunk) * self.face_buffer_unit
     }
 
-    /// The offset into the indirect buffer at which a chunk's face data can be found
+    /// The offset into the indirect buffer at which the command to draw a chunk's opaque faces can
+    /// be found, immediately followed by the command for its transparent faces
     pub fn indirect_offset(&self, chunk: u32) -> vk::DeviceSize {
         assert!(chunk < self.count);
-        vk::DeviceSize::from(chunk) * INDIRECT_SIZE
+        vk::DeviceSize::from(chunk) * 2 * INDIRECT_SIZE
+    }
+
+    /// The offset into the indirect buffer at which the command to draw a chunk's transparent faces
+    /// can be found
+    pub fn transparent_indirect_offset(&self, chunk: u32) -> vk::DeviceSize {
+        self.indirect_offset(chunk) + INDIRECT_SIZE
     }
 
     /// Number of voxels along a chunk edge
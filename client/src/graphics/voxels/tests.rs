@@ -4,15 +4,29 @@ use ash::vk;
 use lahar::DedicatedMapping;
 use renderdoc::{RenderDoc, V110};
 
-use super::{surface_extraction, SurfaceExtraction};
+use super::{
+    chunk_center, sort_back_to_front,
+    surface_extraction::{self, pack_voxel, PLACEHOLDER_MATERIAL},
+    SurfaceExtraction,
+};
 use crate::graphics::{Base, VkDrawIndirectCommand};
-use common::world::Material;
+use common::{
+    dodeca::Vertex,
+    graph::{Graph, NodeId},
+    lru_slab::SlotId,
+    math,
+    node::{CoordAxis, CoordDirection, Coords, MarginCoords},
+    proto::Position,
+    traversal::{ensure_nearby, nearby_nodes},
+    world::{Material, Shape},
+};
 
 struct SurfaceExtractionTest {
     gfx: Arc<Base>,
     extract: SurfaceExtraction,
     scratch: surface_extraction::ScratchBuffer,
-    indirect: DedicatedMapping<VkDrawIndirectCommand>,
+    /// Draws of the opaque and transparent faces
+    indirect: DedicatedMapping<[VkDrawIndirectCommand]>,
     surfaces: DedicatedMapping<[Surface]>,
     cmd_pool: vk::CommandPool,
     cmd: vk::CommandBuffer,
@@ -23,22 +37,29 @@ impl SurfaceExtractionTest {
     pub fn new() -> Self {
         let gfx = Arc::new(Base::headless());
         let extract = SurfaceExtraction::new(&gfx);
-        let scratch = surface_extraction::ScratchBuffer::new(&gfx, &extract, 1, DIMENSION as u32);
+        let scratch = surface_extraction::ScratchBuffer::new(
+            &gfx,
+            &extract,
+            1,
+            surface_extraction::staging_size(DIMENSION as u32),
+            DIMENSION as u32,
+        );
 
         let device = &*gfx.device;
 
         unsafe {
-            let indirect = DedicatedMapping::<VkDrawIndirectCommand>::zeroed(
+            let indirect = DedicatedMapping::<[VkDrawIndirectCommand]>::zeroed_array(
                 device,
                 &gfx.memory_properties,
                 vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                2,
             );
 
             let surfaces = DedicatedMapping::<[Surface]>::zeroed_array(
                 device,
                 &gfx.memory_properties,
                 vk::BufferUsageFlags::STORAGE_BUFFER,
-                3 * (DIMENSION.pow(3) + DIMENSION.pow(2)),
+                2 * MAX_FACES,
             );
 
             let cmd_pool = device
@@ -94,6 +115,7 @@ impl SurfaceExtractionTest {
                 self.surfaces.buffer(),
                 self.cmd,
                 &[surface_extraction::ExtractTask {
+                    staging_offset: 0,
                     indirect_offset: 0,
                     face_offset: 0,
                     index: 0,
@@ -135,6 +157,7 @@ impl Drop for SurfaceExtractionTest {
 }
 
 const DIMENSION: usize = 2;
+const MAX_FACES: usize = 3 * (DIMENSION.pow(3) + DIMENSION.pow(2));
 
 #[repr(C)]
 #[derive(Debug, Eq, PartialEq)]
@@ -144,7 +167,7 @@ struct Surface {
     z: u8,
     axis: u8,
     mat: Material,
-    _padding: u8,
+    part: u8,
     occlusion: u8,
 }
 
@@ -156,44 +179,42 @@ fn surface_extraction() {
     let _guard = common::tracing_guard();
     let mut test = SurfaceExtractionTest::new();
 
-    for x in test.scratch.storage(0) {
-        *x = Material::Void;
+    for x in test.scratch.staging(0) {
+        *x = pack_voxel(Material::Void, Shape::FULL);
     }
 
     test.run();
 
     assert_eq!(
-        test.indirect.vertex_count, 0,
+        test.indirect[0].vertex_count, 0,
         "empty chunks have no surfaces"
     );
 
-    for x in test.scratch.storage(0) {
-        *x = Material::Dirt;
+    for x in test.scratch.staging(0) {
+        *x = pack_voxel(Material::Dirt, Shape::FULL);
     }
 
     test.run();
 
     assert_eq!(
-        test.indirect.vertex_count, 0,
+        test.indirect[0].vertex_count, 0,
         "solid chunks have no surfaces"
     );
 
-    let storage = test.scratch.storage(0);
+    let storage = test.scratch.staging(0);
     for x in &mut *storage {
-        *x = Material::Void;
+        *x = pack_voxel(Material::Void, Shape::FULL);
     }
-    for z in 0..((DIMENSION + 2) / 2) {
-        for y in 0..(DIMENSION + 2) {
-            for x in 0..(DIMENSION + 2) {
-                storage[x + y * (DIMENSION + 2) + z * (DIMENSION + 2).pow(2)] = Material::Dirt;
-            }
+    for coords in MarginCoords::all(DIMENSION as u8) {
+        if usize::from(coords[CoordAxis::Z]) < (DIMENSION + 2) / 2 {
+            storage[coords.to_index(DIMENSION as u8)] = pack_voxel(Material::Dirt, Shape::FULL);
         }
     }
 
     test.run();
 
     assert_eq!(
-        test.indirect.vertex_count,
+        test.indirect[0].vertex_count,
         6 * DIMENSION.pow(2) as u32,
         "half-solid chunks have n^2 surfaces"
     );
@@ -205,7 +226,7 @@ fn surface_extraction() {
             z: 1,
             axis: 5,
             mat: Material::Dirt,
-            _padding: 0,
+            part: 0,
             occlusion: 0xFF,
         },
         Surface {
@@ -214,7 +235,7 @@ fn surface_extraction() {
             z: 1,
             axis: 5,
             mat: Material::Dirt,
-            _padding: 0,
+            part: 0,
             occlusion: 0xFF,
         },
         Surface {
@@ -223,7 +244,7 @@ fn surface_extraction() {
             z: 1,
             axis: 5,
             mat: Material::Dirt,
-            _padding: 0,
+            part: 0,
             occlusion: 0xFF,
         },
         Surface {
@@ -232,10 +253,190 @@ fn surface_extraction() {
             z: 1,
             axis: 5,
             mat: Material::Dirt,
-            _padding: 0,
+            part: 0,
             occlusion: 0xFF,
         },
     ] {
         assert!(surfaces.contains(expected));
     }
 }
+
+#[test]
+#[ignore]
+fn transparent_surface_extraction() {
+    let _guard = common::tracing_guard();
+    let mut test = SurfaceExtractionTest::new();
+    let faces = DIMENSION.pow(2) as u32 * 6;
+
+    // Opaque and transparent vertex counts from a chunk of `lower` below `upper`
+    let mut layered = |lower, upper| {
+        let storage = test.scratch.staging(0);
+        for (coords, x) in MarginCoords::all(DIMENSION as u8).zip(storage.iter_mut()) {
+            let material = if usize::from(coords[CoordAxis::Z]) < (DIMENSION + 2) / 2 {
+                lower
+            } else {
+                upper
+            };
+            *x = pack_voxel(material, Shape::FULL);
+        }
+        test.run();
+        (test.indirect[0].vertex_count, test.indirect[1].vertex_count)
+    };
+
+    assert_eq!(layered(Material::Ice, Material::Void), (0, faces));
+    assert_eq!(
+        layered(Material::Dirt, Material::Ice),
+        (faces, 0),
+        "opaque voxels are seen through transparent ones"
+    );
+    assert_eq!(
+        layered(Material::Ice, Material::Ice),
+        (0, 0),
+        "like transparent voxels merge"
+    );
+    assert_eq!(
+        layered(Material::Ice, Material::Water),
+        (0, 2 * faces),
+        "unlike transparent voxels are seen through each other"
+    );
+    // Transparent faces are stored after the space for opaque faces
+    let transparent = &test.surfaces[MAX_FACES..][..2 * DIMENSION.pow(2)];
+    for material in [Material::Ice, Material::Water] {
+        assert_eq!(
+            transparent.iter().filter(|x| x.mat == material).count(),
+            DIMENSION.pow(2)
+        );
+    }
+}
+
+#[test]
+#[ignore]
+fn shaped_surface_extraction() {
+    use CoordAxis::*;
+    use CoordDirection::*;
+
+    let _guard = common::tracing_guard();
+    let mut test = SurfaceExtractionTest::new();
+    let bottom = Shape::slab(Y, Minus);
+    let top = Shape::slab(Y, Plus);
+
+    // Number of faces extracted from a chunk of void but for `voxels`
+    let mut faces = |voxels: &[([u8; 3], Shape)]| {
+        let storage = test.scratch.staging(0);
+        storage.fill(pack_voxel(Material::Void, Shape::FULL));
+        for &(coords, shape) in voxels {
+            storage[Coords(coords).to_index(DIMENSION as u8)] = pack_voxel(Material::Dirt, shape);
+        }
+        test.run();
+        test.indirect[0].vertex_count / 6
+    };
+
+    assert_eq!(faces(&[([0, 0, 0], Shape::FULL)]), 6);
+    // Top, bottom, and a half face on each side
+    assert_eq!(faces(&[([0, 0, 0], bottom)]), 6);
+    assert_eq!(
+        faces(&[([0, 0, 0], Shape::FULL), ([1, 0, 0], bottom)]),
+        11,
+        "a full cube shows the upper half of its face beside a bottom slab"
+    );
+    assert_eq!(
+        faces(&[([0, 0, 0], bottom), ([1, 0, 0], bottom)]),
+        10,
+        "slabs side by side hide the faces between them"
+    );
+    assert_eq!(
+        faces(&[([0, 0, 0], Shape::FULL), ([0, 1, 0], bottom)]),
+        10,
+        "a slab resting on a full cube hides the cube's top"
+    );
+    assert_eq!(
+        faces(&[([0, 0, 0], top), ([0, 1, 0], bottom)]),
+        10,
+        "a bottom slab resting on a top slab hides the faces between them"
+    );
+    // Both sides of stairs are L-shaped, taking two faces each
+    assert_eq!(
+        faces(&[([0, 0, 0], Shape::stairs((Y, Plus), (Z, Plus)))]),
+        10
+    );
+}
+
+#[test]
+#[ignore]
+fn unknown_surface_extraction() {
+    let _guard = common::tracing_guard();
+    let mut test = SurfaceExtractionTest::new();
+    // As sent by a newer server
+    let unknown = Material::from_id(Material::COUNT as u16 + 1);
+    let storage = test.scratch.staging(0);
+    for (coords, x) in MarginCoords::all(DIMENSION as u8).zip(storage.iter_mut()) {
+        let material = if usize::from(coords[CoordAxis::Z]) < (DIMENSION + 2) / 2 {
+            unknown
+        } else {
+            Material::Void
+        };
+        *x = pack_voxel(material, Shape::FULL);
+    }
+    test.run();
+
+    // Drawn as opaque placeholders
+    assert_eq!(test.indirect[0].vertex_count, 6 * DIMENSION.pow(2) as u32);
+    assert_eq!(test.indirect[1].vertex_count, 0);
+    assert!(test.surfaces[..DIMENSION.pow(2)]
+        .iter()
+        .all(|x| x.mat == Material::from_id(PLACEHOLDER_MATERIAL)));
+}
+
+#[test]
+fn unknown_materials_packed_as_placeholder() {
+    let stairs = Shape::stairs(
+        (CoordAxis::Y, CoordDirection::Plus),
+        (CoordAxis::X, CoordDirection::Minus),
+    );
+    for id in [Material::COUNT as u16, 0x1FF, u16::MAX] {
+        let packed = pack_voxel(Material::from_id(id), stairs);
+        assert_eq!(packed & 0xFF, PLACEHOLDER_MATERIAL);
+        assert_eq!(packed >> 8, u16::from(stairs.octants()));
+    }
+    assert_eq!(
+        pack_voxel(Material::Sign, Shape::FULL) & 0xFF,
+        Material::Sign.id()
+    );
+    assert!(!Material::from_id(PLACEHOLDER_MATERIAL).is_transparent());
+}
+
+#[test]
+fn chunks_sorted_back_to_front() {
+    let mut graph = Graph::new(DIMENSION as u8);
+    let view = Position {
+        node: NodeId::ROOT,
+        local: math::translate_along(&na::Vector3::new(0.3, -0.2, 0.1))
+            * na::Rotation3::from_euler_angles(0.3, 1.2, 0.0).to_homogeneous(),
+    };
+    ensure_nearby(&mut graph, &view, 2.0);
+    let local_to_view = math::mtranspose(&view.local);
+    let mut chunks = Vec::new();
+    // Centers relative to the node the view is in, indexed by slot
+    let mut absolute = Vec::new();
+    for (_, node_transform) in nearby_nodes(&graph, &view, 2.0) {
+        for vertex in Vertex::iter() {
+            let slot = SlotId(chunks.len() as u32);
+            chunks.push((
+                slot,
+                chunk_center(&(local_to_view * node_transform), vertex),
+            ));
+            absolute.push(chunk_center(&node_transform, vertex));
+        }
+    }
+    assert!(chunks.len() > 100);
+    sort_back_to_front(&mut chunks);
+
+    let eye = view.local * math::origin();
+    let distances = chunks
+        .iter()
+        .map(|(slot, _)| math::distance(&eye, &absolute[slot.0 as usize]))
+        .collect::<Vec<_>>();
+    for pair in distances.windows(2) {
+        assert!(pair[0] >= pair[1] - 1e-4, "{} before {}", pair[0], pair[1]);
+    }
+}
@@ -0,0 +1,354 @@
+//! Scheduling of chunk voxel uploads to the GPU for surface extraction
+//!
+//! Chunks awaiting a surface are queued in order of how soon they're likely to be seen, as scored
+//! by `chunk_priority`. Each frame,
+//! as many as fit within a byte budget are staged in a persistent ring buffer, whose space is
+//! reused once the frame that copied out of it is known to have completed.
+
+use std::collections::VecDeque;
+
+use crate::chunk_priority::ChunkQueue;
+use common::node::ChunkId;
+
+/// Alignment of regions allocated from a `StagingRing`, which suits copies and `u16` voxels alike
+const ALIGNMENT: u64 = 4;
+
+/// Allocator of space in a fixed-size buffer that's written by the host and read by the GPU
+///
+/// Each allocation is tagged with a ticket identifying the submission that reads it, and is reused
+/// only after that ticket is released. Tickets may be released in any order, but space is
+/// reclaimed strictly in the order it was allocated.
+pub struct StagingRing {
+    capacity: u64,
+    /// Offset at which the next allocation begins, if it fits before the end
+    head: u64,
+    /// Allocations not yet reclaimed, oldest first
+    regions: VecDeque<Region>,
+}
+
+struct Region {
+    /// Start of the space the region occupies, including any skipped to wrap around
+    start: u64,
+    ticket: u64,
+    released: bool,
+}
+
+impl StagingRing {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity: capacity - capacity % ALIGNMENT,
+            head: 0,
+            regions: VecDeque::new(),
+        }
+    }
+
+    /// Allocate `size` bytes to be read by the submission identified by `ticket`, returning their
+    /// offset, or `None` if there isn't room until earlier tickets are released
+    pub fn alloc(&mut self, size: u64, ticket: u64) -> Option<u64> {
+        let size = size.next_multiple_of(ALIGNMENT);
+        if size > self.capacity {
+            return None;
+        }
+        let (region_start, offset) = match self.regions.front() {
+            None => {
+                // Start over from the beginning, leaving the most contiguous space
+                self.head = 0;
+                (0, 0)
+            }
+            Some(oldest) if self.head > oldest.start => {
+                if self.capacity - self.head >= size {
+                    (self.head, self.head)
+                } else if oldest.start >= size {
+                    // Skip the space remaining at the end, which is reclaimed along with this region
+                    (self.head, 0)
+                } else {
+                    return None;
+                }
+            }
+            Some(oldest) if self.head < oldest.start && oldest.start - self.head >= size => {
+                (self.head, self.head)
+            }
+            // Wrapped around into space still in use, or full
+            Some(_) => return None,
+        };
+        self.regions.push_back(Region {
+            start: region_start,
+            ticket,
+            released: false,
+        });
+        self.head = offset + size;
+        if self.head == self.capacity {
+            self.head = 0;
+        }
+        Some(offset)
+    }
+
+    /// Note that the submission identified by `ticket` has completed, so that space it read from can
+    /// be reused
+    pub fn release(&mut self, ticket: u64) {
+        for region in self.regions.iter_mut().filter(|x| x.ticket == ticket) {
+            region.released = true;
+        }
+        while self.regions.front().is_some_and(|x| x.released) {
+            self.regions.pop_front();
+        }
+    }
+}
+
+/// Chunks awaiting upload, ordered by score
+///
+/// Surfaces are extracted from uploads in the order they're scheduled, so this orders meshing too.
+pub struct UploadQueue {
+    /// Bytes each upload occupies
+    size: u64,
+    /// Chunks with the generation of their voxels when queued
+    pending: ChunkQueue<u32>,
+    stats: UploadStats,
+}
+
+/// A chunk whose voxels should be staged at `offset` in the ring
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Upload {
+    pub chunk: ChunkId,
+    pub generation: u32,
+    pub offset: u64,
+}
+
+/// Running totals of what happened to queued uploads
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct UploadStats {
+    /// Bytes scheduled
+    pub bytes: u64,
+    /// Uploads dropped because the chunk's voxels changed or were discarded before upload
+    pub superseded: u64,
+    /// Uploads dropped because the chunk went out of view before upload
+    pub expired: u64,
+}
+
+impl UploadQueue {
+    /// Queue for uploads of `size` bytes each
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            pending: ChunkQueue::new(),
+            stats: UploadStats::default(),
+        }
+    }
+
+    /// Begin collecting the uploads wanted by a new frame
+    pub fn begin_frame(&mut self) {
+        self.pending.begin_frame();
+    }
+
+    /// Queue an upload of `chunk`'s voxels as of `generation` with `score`, or refresh its score
+    /// if already queued
+    ///
+    /// An upload of an older generation is dropped in favor of the new one.
+    pub fn push(&mut self, chunk: ChunkId, generation: u32, score: f32) {
+        if let Some(previous) = self.pending.push(chunk, generation, score) {
+            if previous != generation {
+                self.stats.superseded += 1;
+            }
+        }
+    }
+
+    /// Take the most urgent uploads wanted in the current frame, up to `max_count` totalling at
+    /// most `budget` bytes, allocating their staging space from `ring` under `ticket`
+    ///
+    /// Uploads not wanted since the last `begin_frame` are forgotten, and those whose chunk is no
+    /// longer at the queued generation according to `generation` are dropped. The rest are deferred
+    /// to a later frame.
+    pub fn schedule(
+        &mut self,
+        budget: u64,
+        max_count: usize,
+        ring: &mut StagingRing,
+        ticket: u64,
+        generation: impl Fn(ChunkId) -> Option<u32>,
+    ) -> Vec<Upload> {
+        self.stats.expired += self.pending.expire() as u64;
+
+        let mut uploads = Vec::new();
+        let mut bytes = 0;
+        for chunk in self.pending.ordered() {
+            if uploads.len() == max_count || bytes + self.size > budget {
+                break;
+            }
+            let queued = *self.pending.get(chunk).expect("ordered chunks are queued");
+            if generation(chunk) != Some(queued) {
+                self.pending.remove(chunk);
+                self.stats.superseded += 1;
+                continue;
+            }
+            let Some(offset) = ring.alloc(self.size, ticket) else {
+                break;
+            };
+            self.pending.remove(chunk);
+            bytes += self.size;
+            uploads.push(Upload {
+                chunk,
+                generation: queued,
+                offset,
+            });
+        }
+        self.stats.bytes += bytes;
+        uploads
+    }
+
+    /// Number of uploads waiting
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn stats(&self) -> UploadStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use super::*;
+    use common::{
+        dodeca::{Vertex, VERTEX_COUNT},
+        graph::NodeId,
+    };
+
+    fn chunk(i: u32) -> ChunkId {
+        ChunkId::new(
+            NodeId::ROOT,
+            Vertex::iter().nth(i as usize % VERTEX_COUNT).unwrap(),
+        )
+    }
+
+    fn index(chunk: ChunkId) -> usize {
+        Vertex::iter().position(|v| v == chunk.vertex).unwrap()
+    }
+
+    #[test]
+    fn ring_never_reuses_memory_in_flight() {
+        const CAPACITY: u64 = 1000;
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut ring = StagingRing::new(CAPACITY);
+        // Byte ranges allocated under each ticket not yet released
+        let mut in_flight = Vec::<(u64, Vec<(u64, u64)>)>::new();
+        for ticket in 0..2000 {
+            let mut ranges = Vec::new();
+            for _ in 0..rng.gen_range(0..6) {
+                let size = rng.gen_range(1..300);
+                let Some(offset) = ring.alloc(size, ticket) else {
+                    continue;
+                };
+                let end = offset + size;
+                assert_eq!(offset % ALIGNMENT, 0);
+                assert!(end <= CAPACITY, "{offset}..{end} out of bounds");
+                let live = in_flight.iter().flat_map(|(_, x)| x).chain(&ranges);
+                for &(start, other_end) in live {
+                    assert!(
+                        end <= start || offset >= other_end,
+                        "{offset}..{end} overlaps {start}..{other_end} in flight"
+                    );
+                }
+                ranges.push((offset, end));
+            }
+            in_flight.push((ticket, ranges));
+            // Fences complete in any order
+            in_flight.shuffle(&mut rng);
+            while in_flight.len() > rng.gen_range(1..5) {
+                let (ticket, _) = in_flight.pop().unwrap();
+                ring.release(ticket);
+            }
+        }
+        for (ticket, _) in in_flight.drain(..) {
+            ring.release(ticket);
+        }
+        assert_eq!(ring.alloc(CAPACITY, 0), Some(0));
+    }
+
+    #[test]
+    fn ring_wraps_around() {
+        let mut ring = StagingRing::new(100);
+        assert_eq!(ring.alloc(40, 0), Some(0));
+        assert_eq!(ring.alloc(40, 1), Some(40));
+        // Only 20 bytes left before the end, and the start is in use
+        assert_eq!(ring.alloc(40, 2), None);
+        ring.release(0);
+        assert_eq!(ring.alloc(40, 2), Some(0));
+        // Released out of order, so still held up by ticket 1
+        ring.release(2);
+        assert_eq!(ring.alloc(40, 3), None);
+        ring.release(1);
+        assert_eq!(ring.alloc(100, 3), Some(0));
+    }
+
+    #[test]
+    fn budget_respected() {
+        const SIZE: u64 = 100;
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut queue = UploadQueue::new(SIZE);
+        let mut ring = StagingRing::new(10_000);
+        for ticket in 0..100 {
+            queue.begin_frame();
+            for i in 0..VERTEX_COUNT as u32 {
+                queue.push(chunk(i), 0, rng.gen());
+            }
+            let budget = rng.gen_range(0..1000);
+            let uploads = queue.schedule(budget, usize::MAX, &mut ring, ticket, |_| Some(0));
+            let scheduled = uploads.len() as u64;
+            assert!(scheduled * SIZE <= budget);
+            // Nothing deferred that would have fit
+            assert_eq!(scheduled, (budget / SIZE).min(VERTEX_COUNT as u64));
+            ring.release(ticket);
+        }
+    }
+
+    #[test]
+    fn most_urgent_first() {
+        let mut queue = UploadQueue::new(1);
+        let mut ring = StagingRing::new(64);
+        queue.begin_frame();
+        queue.push(chunk(0), 0, 3.0);
+        queue.push(chunk(1), 0, 1.0);
+        queue.push(chunk(2), 0, 2.0);
+        let uploads = queue.schedule(8, 2, &mut ring, 0, |_| Some(0));
+        let chunks = uploads.iter().map(|x| x.chunk).collect::<Vec<_>>();
+        assert_eq!(chunks, [chunk(1), chunk(2)]);
+        assert_eq!(queue.len(), 1);
+
+        // Deferred uploads are forgotten if not wanted again
+        queue.begin_frame();
+        assert!(queue.schedule(8, 2, &mut ring, 1, |_| Some(0)).is_empty());
+        assert_eq!(queue.stats().expired, 1);
+    }
+
+    #[test]
+    fn superseded_never_uploaded() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut queue = UploadQueue::new(1);
+        let mut ring = StagingRing::new(1 << 16);
+        let mut generations = [0u32; VERTEX_COUNT];
+        let mut dropped = 0;
+        for ticket in 0..200 {
+            queue.begin_frame();
+            for (i, generation) in generations.iter_mut().enumerate() {
+                if rng.gen_bool(0.3) {
+                    // A newer generation is queued in place of any older one
+                    *generation += 1;
+                    dropped += u64::from(queue.pending.get(chunk(i as u32)).is_some());
+                }
+                queue.push(chunk(i as u32), *generation, rng.gen());
+            }
+            // Edited after being queued
+            let edited = rng.gen_range(0..VERTEX_COUNT);
+            generations[edited] += 1;
+            let budget = rng.gen_range(0..10);
+            let current = |chunk: ChunkId| Some(generations[index(chunk)]);
+            for upload in queue.schedule(budget, usize::MAX, &mut ring, ticket, current) {
+                assert_eq!(upload.generation, generations[index(upload.chunk)]);
+            }
+            ring.release(ticket);
+        }
+        assert!(queue.stats().superseded >= dropped);
+    }
+}
@@ -1,11 +1,13 @@
+use std::mem;
+use std::os::raw::c_char;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
-use std::{f32, os::raw::c_char};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use ash::{extensions::khr, vk};
-use lahar::DedicatedImage;
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 use winit::{
     dpi::PhysicalSize,
     event::{
@@ -15,9 +17,34 @@ use winit::{
     window::{CursorGrabMode, Window as WinitWindow, WindowBuilder},
 };
 
-use super::{Base, Core, Draw, Frustum};
+use super::{
+    base::OutOfDeviceMemory,
+    memory_pressure::{Degrade, Exhausted, MemoryPressure, Stage},
+    targets::{RenderTargets, Retired},
+    Base, Core, Display, Draw,
+};
 use crate::Net;
-use crate::{net, Config, Sim};
+use crate::{
+    asset_pack::{self, PackCache},
+    audio::{AudioOutput, Silence},
+    breadcrumbs::{Breadcrumb, Trail},
+    console::{Command, Console, RollbackTarget},
+    exploration::Exploration,
+    look::{LookDelta, LookInput},
+    net, templates,
+    view_distance::{FrameSample, ViewDistance},
+    waypoints::{self, PersonalWaypoints},
+    Config, Sim,
+};
+use common::{
+    logging::LogFilter,
+    node_path::NodePath,
+    protection::ProtectedRegion,
+    proto::{self, AssetPackOffer},
+    waypoint::{validate_name, Waypoint},
+    worldgen::{determinism::Golden, ChunkParams},
+    SimConfig,
+};
 
 /// OS window
 pub struct EarlyWindow {
@@ -53,9 +80,67 @@ pub struct Window {
     surface: vk::SurfaceKHR,
     swapchain: Option<SwapchainMgr>,
     swapchain_needs_update: bool,
+    /// Images rendered to before being fit to the swapchain, created on first use
+    targets: Option<RenderTargets>,
+    /// Render targets that frames in flight may still be using
+    retired_targets: Retired<RenderTargets>,
+    /// Work waiting for device memory to be freed between frames
+    memory_pressure: MemoryPressure<Deferred>,
+    display: Display,
     draw: Option<Draw>,
     sim: Option<Sim>,
     net: Net,
+    /// Positions recorded on the current server
+    trail: Option<Trail>,
+    /// Where the character was before this connection, if it's somewhere else
+    way_back: Option<Breadcrumb>,
+    /// Whether the local character has yet to appear since connecting
+    awaiting_spawn: bool,
+    /// Nodes explored on the current server
+    exploration: Option<Exploration>,
+    /// Waypoints made by the player on the current server
+    personal_waypoints: Option<PersonalWaypoints>,
+    console: Console,
+    /// Mouse movement yet to turn the view, taken before each step and again just before drawing
+    look: LookInput,
+    /// Adjusts what's logged at the console's request
+    log_filter: LogFilter,
+    /// Adapts how far the world is drawn to how long frames take
+    view_distance: ViewDistance,
+    audio: Box<dyn AudioOutput>,
+}
+
+/// Distance from a recorded position beyond which a returning player is offered the way back
+const WAY_BACK_DISTANCE: f32 = 0.5;
+
+/// Compare the chunks this build generates against those of the canonical build in the
+/// background, if they were recorded for the world `cfg` describes
+///
+/// A build that differs may see terrain the server doesn't, which is otherwise hard to explain.
+fn check_worldgen(cfg: &SimConfig) {
+    let golden = Golden::committed();
+    if !golden.applies_to(cfg.chunk_size, &cfg.terrain) {
+        return;
+    }
+    thread::spawn(move || {
+        let report = golden.verify(ChunkParams::generate_voxels);
+        if report.is_deterministic() {
+            debug!(
+                chunks = report.checked,
+                "world generation matches the canonical build"
+            );
+            return;
+        }
+        for divergence in &report.divergences {
+            warn!("{}", divergence);
+        }
+        warn!(
+            "{} of {} golden chunks generated differently than by the canonical build; terrain \
+             may not match the server's",
+            report.divergences.len(),
+            report.checked
+        );
+    });
 }
 
 impl Window {
@@ -66,6 +151,7 @@ impl Window {
         config: Arc<Config>,
         metrics: Arc<crate::metrics::Recorder>,
         net: Net,
+        log_filter: LogFilter,
     ) -> Self {
         let surface = unsafe {
             ash_window::create_surface(
@@ -78,20 +164,39 @@ impl Window {
             .unwrap()
         };
         let surface_fn = khr::Surface::new(&core.entry, &core.instance);
+        let refresh_interval = early
+            .window
+            .current_monitor()
+            .and_then(|monitor| monitor.refresh_rate_millihertz())
+            .map(|millihertz| Duration::from_secs_f64(1e3 / f64::from(millihertz)));
 
         Self {
             _core: core,
             window: early.window,
-            config,
             metrics,
             event_loop: Some(early.event_loop),
             surface,
             surface_fn,
             swapchain: None,
             swapchain_needs_update: false,
+            targets: None,
+            retired_targets: Retired::default(),
+            memory_pressure: MemoryPressure::default(),
+            display: Display::new(config.display),
             draw: None,
             sim: None,
             net,
+            trail: None,
+            way_back: None,
+            awaiting_spawn: false,
+            exploration: None,
+            personal_waypoints: None,
+            console: Console::spawn(),
+            look: LookInput::new(),
+            log_filter,
+            view_distance: ViewDistance::new(config.view_distance.clone(), refresh_interval),
+            audio: Box::new(Silence),
+            config,
         }
     }
 
@@ -124,12 +229,14 @@ impl Window {
         let mut clockwise = false;
         let mut anticlockwise = false;
         let mut last_frame = Instant::now();
+        let mut last_work_started = Instant::now();
         let mut mouse_captured = false;
         self.event_loop
             .take()
             .unwrap()
             .run(move |event, _, control_flow| match event {
                 Event::MainEventsCleared => {
+                    let work_started = Instant::now();
                     while let Ok(msg) = self.net.incoming.try_recv() {
                         self.handle_net(msg);
                     }
@@ -150,21 +257,42 @@ impl Window {
                             2.0 * (anticlockwise as u8 as f32 - clockwise as u8 as f32)
                                 * dt.as_secs_f32(),
                         );
+                        if let Some(delta) = self.look.drain() {
+                            sim.look(delta.yaw, delta.pitch, delta.roll);
+                        }
 
                         sim.step(dt, &mut self.net);
+                        for playback in sim.take_sounds() {
+                            self.audio.play(&playback);
+                        }
                         last_frame = this_frame;
+                        self.follow_trail(dt);
+                        self.explore(dt);
                     }
+                    while let Some(command) = self.console.poll() {
+                        self.run_command(command);
+                    }
+                    let sim_time = work_started.elapsed();
 
-                    self.draw();
+                    if let Err(e) = self.draw() {
+                        error!("exiting due to lack of GPU memory: {e}");
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+                    self.adapt_view_distance(work_started - last_work_started, sim_time);
+                    last_work_started = work_started;
                 }
                 Event::DeviceEvent { event, .. } => match event {
                     DeviceEvent::MouseMotion { delta } if mouse_captured => {
-                        if let Some(sim) = self.sim.as_mut() {
+                        if self.sim.is_some() {
                             const SENSITIVITY: f32 = 2e-3;
-                            sim.look(
-                                -delta.0 as f32 * SENSITIVITY,
-                                -delta.1 as f32 * SENSITIVITY,
-                                0.0,
+                            self.look.push(
+                                LookDelta {
+                                    yaw: -delta.0 as f32 * SENSITIVITY,
+                                    pitch: -delta.1 as f32 * SENSITIVITY,
+                                    roll: 0.0,
+                                },
+                                Instant::now(),
                             );
                         }
                     }
@@ -253,7 +381,82 @@ impl Window {
                         }
                         VirtualKeyCode::V if state == ElementState::Pressed => {
                             if let Some(sim) = self.sim.as_mut() {
-                                sim.toggle_no_clip();
+                                if !sim.toggle_no_clip() {
+                                    warn!("no-clip isn't allowed on this server");
+                                }
+                            }
+                        }
+                        VirtualKeyCode::C => {
+                            if let Some(sim) = self.sim.as_mut() {
+                                sim.set_fill_held(state == ElementState::Pressed);
+                            }
+                        }
+                        VirtualKeyCode::X if state == ElementState::Pressed => {
+                            if let Some(sim) = self.sim.as_mut() {
+                                sim.set_use_pressed_true();
+                            }
+                        }
+                        VirtualKeyCode::Z if state == ElementState::Pressed => {
+                            if let Some(sim) = self.sim.as_mut() {
+                                sim.set_throw_pressed_true();
+                            }
+                        }
+                        VirtualKeyCode::Tab if state == ElementState::Pressed => {
+                            if let Some(sim) = self.sim.as_mut() {
+                                sim.cycle_selected_material();
+                                info!(material = ?sim.selected_material(), "selected material");
+                            }
+                        }
+                        VirtualKeyCode::G if state == ElementState::Pressed => {
+                            if let Some(sim) = self.sim.as_mut() {
+                                sim.cycle_selected_shape();
+                                info!(shape = ?sim.selected_shape(), "selected shape");
+                            }
+                        }
+                        VirtualKeyCode::B if state == ElementState::Pressed => {
+                            if let Some(sim) = self.sim.as_mut() {
+                                if sim.waypoint().is_some() {
+                                    sim.set_waypoint(None);
+                                } else if let Some(ref way_back) = self.way_back {
+                                    info!("showing the way back on the minimap");
+                                    sim.set_waypoint(Some(way_back.clone()));
+                                }
+                            }
+                        }
+                        VirtualKeyCode::T if state == ElementState::Pressed => {
+                            if let Some(sim) = self.sim.as_ref() {
+                                // Skip ahead by an hour
+                                sim.set_world_time(sim.world_time() + 1.0 / 24.0, &mut self.net);
+                            }
+                        }
+                        VirtualKeyCode::F3 if state == ElementState::Pressed => {
+                            if let Some(draw) = self.draw.as_mut() {
+                                draw.toggle_timing_overlay();
+                            }
+                        }
+                        VirtualKeyCode::F6 if state == ElementState::Pressed => {
+                            let settings = self.config.reload_display();
+                            info!(?settings, "reloaded display settings");
+                            self.display.set(settings, Instant::now());
+                        }
+                        VirtualKeyCode::F7 if state == ElementState::Pressed => {
+                            if let Some(sim) = self.sim.as_mut() {
+                                toggle_observer(sim);
+                            }
+                        }
+                        VirtualKeyCode::F8 if state == ElementState::Pressed => {
+                            if let Some(sim) = self.sim.as_mut() {
+                                match sim.toggle_follow_lock() {
+                                    Some(true) => info!("camera turns with the character followed"),
+                                    Some(false) => info!("camera orbits the character followed"),
+                                    None => warn!("not following anyone"),
+                                }
+                            }
+                        }
+                        VirtualKeyCode::F5 if state == ElementState::Pressed => {
+                            if let Some(sim) = self.sim.as_ref() {
+                                info!("requesting save");
+                                sim.request_save(&mut self.net);
                             }
                         }
                         VirtualKeyCode::Escape => {
@@ -274,6 +477,11 @@ impl Window {
                 },
                 Event::LoopDestroyed => {
                     self.metrics.report();
+                    if let Some(exploration) = self.exploration.as_mut() {
+                        if let Err(e) = exploration.save() {
+                            warn!("failed to save explored nodes: {}", e);
+                        }
+                    }
                 }
                 _ => {}
             });
@@ -282,14 +490,43 @@ impl Window {
     fn handle_net(&mut self, msg: net::Message) {
         match msg {
             net::Message::ConnectionLost(e) => {
-                error!("connection lost: {}", e);
+                error!(error = %e, "connection lost");
+                if let Some(sim) = self.sim.as_mut() {
+                    sim.connection_lost();
+                }
             }
             net::Message::Hello(msg) => {
-                let sim = Sim::new(msg.sim_config, msg.character);
+                self.net
+                    .outgoing
+                    .set_capacity(net::outgoing_capacity(msg.sim_config.step_interval));
+                check_worldgen(&msg.sim_config);
+                if let Some(mismatch) = msg.materials.as_ref().and_then(|x| x.mismatch()) {
+                    warn!("{mismatch}");
+                }
+                let mut sim = Sim::new(msg.sim_config, self.config.camera.clone(), msg.character);
+                sim.set_capabilities(msg.header.capabilities);
+                sim.set_node_resync_steps(self.config.node_resync_steps);
+                let server_pack = self.server_pack(msg.asset_pack.as_ref());
                 if let Some(draw) = self.draw.as_mut() {
+                    draw.set_server_pack(server_pack);
                     draw.configure(sim.cfg());
                 }
                 self.sim = Some(sim);
+                self.trail = Some(Trail::open(
+                    &self.config.breadcrumb_dir,
+                    &self.config.server_identity,
+                    self.config.breadcrumb_interval,
+                ));
+                self.way_back = None;
+                self.awaiting_spawn = true;
+                self.exploration = Some(Exploration::open(
+                    &self.config.exploration_dir,
+                    &self.config.server_identity,
+                ));
+                self.personal_waypoints = Some(PersonalWaypoints::open(
+                    &self.config.waypoint_dir,
+                    &self.config.server_identity,
+                ));
             }
             msg => {
                 if let Some(sim) = self.sim.as_mut() {
@@ -301,8 +538,330 @@ impl Window {
         }
     }
 
-    /// Draw a new frame
-    fn draw(&mut self) {
+    /// Path of the asset pack `offer` suggested by the server, if it's allowed and already
+    /// downloaded
+    ///
+    /// Allowed packs not yet downloaded are fetched in the background for next time, since assets
+    /// already loaded aren't replaced.
+    fn server_pack(&self, offer: Option<&AssetPackOffer>) -> Option<PathBuf> {
+        let offer = offer?;
+        if !self.config.accept_server_asset_packs {
+            info!(
+                name = %offer.name,
+                "server suggests an asset pack; set accept_server_asset_packs to use it"
+            );
+            return None;
+        }
+        let cache = PackCache::new(
+            self.config.asset_pack_cache_dir.clone(),
+            self.config.asset_pack_cache_bytes,
+        );
+        if let Some(path) = cache.get(offer) {
+            info!(name = %offer.name, "using server asset pack");
+            return Some(path);
+        }
+        let offer = offer.clone();
+        thread::spawn(move || match asset_pack::download(&cache, &offer) {
+            Ok(_) => info!(
+                name = %offer.name,
+                "downloaded server asset pack, which will be used when next joining"
+            ),
+            Err(e) => warn!(name = %offer.name, "failed to download asset pack: {:#}", e),
+        });
+        None
+    }
+
+    /// Record the local character's position, and offer the way back to where it was on a previous
+    /// connection if the server put it somewhere else
+    fn follow_trail(&mut self, dt: Duration) {
+        let (Some(sim), Some(trail)) = (self.sim.as_ref(), self.trail.as_mut()) else {
+            return;
+        };
+        let Some(view) = sim.character_view() else {
+            return;
+        };
+        if mem::take(&mut self.awaiting_spawn) {
+            if let Some(last) = trail.last() {
+                if last.distance(&sim.graph, &view) > WAY_BACK_DISTANCE {
+                    info!("spawned away from where you left off; press B to show the way back");
+                    self.way_back = Some(last.clone());
+                }
+            }
+        }
+        if let Err(e) = trail.update(dt, &sim.graph, &view) {
+            warn!("failed to record position: {}", e);
+        }
+    }
+
+    /// Record the nodes around the local character as explored
+    fn explore(&mut self, dt: Duration) {
+        let (Some(sim), Some(exploration)) = (self.sim.as_ref(), self.exploration.as_mut()) else {
+            return;
+        };
+        let Some(view) = sim.character_view() else {
+            return;
+        };
+        if let Err(e) = exploration.update(dt, &sim.graph, view.node) {
+            warn!("failed to record explored nodes: {}", e);
+        }
+    }
+
+    /// Adjust how far the world is drawn according to what the latest frame cost, given the time
+    /// since the previous frame and the time spent on simulation
+    fn adapt_view_distance(&mut self, interval: Duration, sim_time: Duration) {
+        let Some(draw) = self.draw.as_mut() else {
+            return;
+        };
+        let target = self.view_distance.target();
+        self.view_distance.update(&FrameSample {
+            interval,
+            busy: (sim_time + draw.cpu_time()).max(draw.gpu_time()),
+            upload: draw.upload_time(),
+            backlog: draw.backlog(),
+        });
+        if self.view_distance.target() != target {
+            debug!(
+                meters =
+                    self.view_distance.target() / self.config.local_simulation.meters_to_absolute,
+                "adapted view distance"
+            );
+        }
+        draw.set_view_distance(
+            self.view_distance.radius(),
+            self.view_distance.fog_distance(),
+        );
+    }
+
+    /// Carry out a command entered at the console
+    fn run_command(&mut self, command: Command) {
+        if let Command::SetLogFilter { directives } = command {
+            let result = match directives {
+                Some(ref directives) => self.log_filter.set(directives),
+                None => self.log_filter.reset(),
+            };
+            if let Err(e) = result {
+                warn!(error = %e, "can't change what's logged");
+            }
+            return;
+        }
+        let (Some(sim), Some(personal)) = (self.sim.as_mut(), self.personal_waypoints.as_mut())
+        else {
+            warn!("not connected");
+            return;
+        };
+        let meters_to_absolute = sim.cfg().meters_to_absolute;
+        match command {
+            Command::ListWaypoints => {
+                let Some(view) = sim.character_view() else {
+                    warn!("can't list waypoints: no character");
+                    return;
+                };
+                let all = sim.shared_waypoints().chain(personal.iter());
+                let sorted = waypoints::by_distance(&sim.graph, &view, all);
+                if sorted.is_empty() {
+                    info!("no waypoints");
+                }
+                for (waypoint, distance) in sorted {
+                    let shared = sim.shared_waypoints().any(|x| std::ptr::eq(x, waypoint));
+                    info!(
+                        "{}: {:.0}m{}",
+                        waypoint.name,
+                        distance / meters_to_absolute,
+                        if shared {
+                            format!(", shared by {}", waypoint.owner)
+                        } else {
+                            String::new()
+                        }
+                    );
+                }
+            }
+            Command::AddWaypoint {
+                name,
+                shared,
+                color,
+            } => {
+                // The server checks the names of shared waypoints too, but can't say what's wrong
+                if let Err(e) = validate_name(&name) {
+                    warn!("can't add waypoint: {}", e);
+                    return;
+                }
+                let Some(view) = sim.character_view() else {
+                    warn!("can't add waypoint: no character");
+                    return;
+                };
+                let waypoint =
+                    Waypoint::new(&sim.graph, &view, name, color, self.config.name.to_string());
+                if shared {
+                    sim.share_waypoint(waypoint, &mut self.net);
+                } else {
+                    let name = waypoint.name.clone();
+                    match personal.insert(waypoint) {
+                        Ok(()) => info!("added waypoint {}", name),
+                        Err(e) => warn!("failed to save waypoint: {}", e),
+                    }
+                }
+            }
+            Command::RemoveWaypoint { name, shared } => {
+                if shared {
+                    sim.unshare_waypoint(name, &mut self.net);
+                    return;
+                }
+                match personal.remove(&name) {
+                    Ok(true) => info!("removed waypoint {}", name),
+                    Ok(false) => warn!("no waypoint named {:?}", name),
+                    Err(e) => warn!("failed to save waypoints: {}", e),
+                }
+            }
+            Command::SelectWaypoint { name } => {
+                // The player's own waypoints take precedence over shared ones of the same name
+                let Some(waypoint) = personal
+                    .get(&name)
+                    .or_else(|| sim.shared_waypoints().find(|x| x.name == name))
+                else {
+                    warn!("no waypoint named {:?}", name);
+                    return;
+                };
+                info!("showing the way to {} on the minimap", name);
+                let breadcrumb = Breadcrumb::from(waypoint);
+                sim.set_waypoint(Some(breadcrumb));
+            }
+            Command::SetViewDistance { meters } => {
+                self.view_distance
+                    .set_manual(meters.map(|x| x as f32 * meters_to_absolute));
+                match meters {
+                    Some(x) => info!("view distance fixed at {}m", x),
+                    None => info!("view distance adapting to the frame rate"),
+                }
+            }
+            Command::ProtectRegion {
+                name,
+                meters,
+                allowed,
+            } => {
+                let Some(view) = sim.character_view() else {
+                    warn!("can't protect region: no character");
+                    return;
+                };
+                let region = ProtectedRegion {
+                    name,
+                    center_path: NodePath::to(&sim.graph, view.node),
+                    radius: meters as f32,
+                    allowed: allowed.into_iter().collect(),
+                };
+                sim.protect_region(region, &mut self.net);
+            }
+            Command::UnprotectRegion { name } => sim.unprotect_region(name, &mut self.net),
+            Command::Rollback {
+                target,
+                seconds,
+                force,
+            } => {
+                let scope = match target {
+                    RollbackTarget::Player(name) => proto::RollbackScope::Player(name),
+                    RollbackTarget::Region {
+                        waypoint: Some(name),
+                        meters,
+                    } => {
+                        // As when selecting a waypoint, the player's own come first
+                        let Some(waypoint) = personal
+                            .get(&name)
+                            .or_else(|| sim.shared_waypoints().find(|x| x.name == name))
+                        else {
+                            warn!("no waypoint named {:?}", name);
+                            return;
+                        };
+                        proto::RollbackScope::Region {
+                            path: waypoint.path.clone(),
+                            local_translation: waypoint.local_translation,
+                            radius: meters as f32,
+                        }
+                    }
+                    RollbackTarget::Region {
+                        waypoint: None,
+                        meters,
+                    } => {
+                        let Some(view) = sim.character_view() else {
+                            warn!("can't roll back: no character");
+                            return;
+                        };
+                        let here =
+                            Waypoint::new(&sim.graph, &view, String::new(), [0; 3], String::new());
+                        proto::RollbackScope::Region {
+                            path: here.path,
+                            local_translation: here.local_translation,
+                            radius: meters as f32,
+                        }
+                    }
+                };
+                sim.rollback(
+                    proto::Rollback {
+                        scope,
+                        seconds,
+                        force,
+                    },
+                    &mut self.net,
+                );
+            }
+            Command::TraceCollisions { steps, character } => {
+                sim.trace_collisions(character, steps, &mut self.net)
+            }
+            Command::DumpCollisionTrace { path, character } => {
+                sim.dump_collision_trace(character, path, &mut self.net)
+            }
+            Command::Fill { extents, material } => sim.fill(extents, material),
+            Command::WriteSign { lines } => sim.write_sign(lines, &mut self.net),
+            Command::MarkTemplateCorner { corner } => {
+                if sim.mark_template_corner(corner) {
+                    info!("marked template corner {}", corner + 1);
+                } else {
+                    warn!("can't mark template corner: no block in reach");
+                }
+            }
+            Command::CopyTemplate { name } => {
+                let Some(template) = sim.copy_template() else {
+                    return;
+                };
+                match templates::save(&self.config.template_dir, &name, &template) {
+                    Ok(()) => info!("copied template {} of {} blocks", name, template.volume()),
+                    Err(e) => warn!("failed to save template: {}", e),
+                }
+            }
+            Command::PasteTemplate { name, orientation } => {
+                match templates::load(&self.config.template_dir, &name) {
+                    Ok(template) => sim.paste_template(template, orientation),
+                    Err(e) => warn!("can't paste template {:?}: {}", name, e),
+                }
+            }
+            Command::Observe => toggle_observer(sim),
+            Command::Follow { name, locked } => {
+                if sim.follow(name.clone(), locked) {
+                    info!(
+                        "following {}; F8 switches between orbiting and turning with them",
+                        name
+                    );
+                } else {
+                    warn!("can't follow {:?}: no such character in sight", name);
+                }
+            }
+            Command::Unfollow => {
+                if !sim.unfollow() {
+                    warn!("not following anyone");
+                }
+            }
+            Command::ReportMemory => {
+                for line in sim.debug_info().memory.to_string().lines() {
+                    info!("{}", line);
+                }
+            }
+            Command::SetLogFilter { .. } => {
+                unreachable!("handled before checking for a connection")
+            }
+        }
+    }
+
+    /// Draw a new frame, unless device memory can't be found for it however little is drawn
+    fn draw(&mut self) -> Result<(), Exhausted<Deferred>> {
+        self.relieve_memory_pressure()?;
         let swapchain = self.swapchain.as_mut().unwrap();
         let draw = self.draw.as_mut().unwrap();
         unsafe {
@@ -331,18 +890,43 @@ impl Window {
                     }
                 }
             };
+            if let Err(e) = self.replace_targets() {
+                warn!(error = %e, "deferring replacement of render targets");
+                self.memory_pressure.defer(Deferred::RenderTargets);
+                // The old targets make do until the next frame, if there are enough of them. If
+                // not, the swapchain was only just created, so no frame in flight uses any, and
+                // room can be made for new ones straight away.
+                if self
+                    .targets
+                    .as_ref()
+                    .map_or(true, |x| x.len() <= frame_id as usize)
+                {
+                    self.relieve_memory_pressure()?;
+                }
+            }
+            let swapchain = self.swapchain.as_mut().unwrap();
+            let draw = self.draw.as_mut().unwrap();
+            self.retired_targets.collect(draw.completed());
+            let target = self.targets.as_ref().unwrap().get(frame_id as usize);
+
             let aspect_ratio =
                 swapchain.state.extent.width as f32 / swapchain.state.extent.height as f32;
             let frame = &swapchain.state.frames[frame_id as usize];
-            let frustum = Frustum::from_vfov(f32::consts::FRAC_PI_4 * 1.2, aspect_ratio);
+            // Culling and rendering must agree on the field of view, so it's computed only once
+            let frustum = self.display.frustum(aspect_ratio, Instant::now());
             // Render the frame
             draw.draw(
                 self.sim.as_mut(),
+                self.exploration.as_ref(),
+                self.personal_waypoints.as_ref(),
+                target,
                 frame.buffer,
-                frame.depth_view,
                 swapchain.state.extent,
                 frame.present,
                 &frustum,
+                &self.display.view_model_frustum(aspect_ratio),
+                &self.display.post_constants(),
+                &self.look,
             );
             // Submit the frame to be presented on the window
             match swapchain.queue_present(frame_id) {
@@ -353,12 +937,117 @@ impl Window {
                 Err(e) => panic!("queue_present: {e}"),
             };
         }
+        Ok(())
+    }
+
+    /// Replace the render targets if the swapchain or the render scale has changed
+    fn replace_targets(&mut self) -> Result<(), OutOfDeviceMemory> {
+        let swapchain = self.swapchain.as_ref().unwrap();
+        let draw = self.draw.as_ref().unwrap();
+        let extent = self
+            .display
+            .settings()
+            .render_extent(swapchain.state.extent);
+        let count = swapchain.state.frames.len();
+        if self
+            .targets
+            .as_ref()
+            .map_or(false, |x| x.extent() == extent && x.len() == count)
+        {
+            return Ok(());
+        }
+        let targets = unsafe { RenderTargets::new(swapchain.state.gfx.clone(), extent, count)? };
+        if let Some(old) = self.targets.replace(targets) {
+            // Frames in flight may still be using the old targets
+            self.retired_targets.push(draw.submitted(), old);
+        }
+        Ok(())
+    }
+
+    /// Retry whatever was deferred for want of device memory, drawing less until it fits
+    fn relieve_memory_pressure(&mut self) -> Result<(), Exhausted<Deferred>> {
+        if self.memory_pressure.is_empty() {
+            return Ok(());
+        }
+        let mut pressure = mem::take(&mut self.memory_pressure);
+        let result = pressure.relieve(self);
+        self.memory_pressure = pressure;
+        result
+    }
+}
+
+/// Work put off until device memory is freed
+#[derive(Debug)]
+pub enum Deferred {
+    /// Replace the render targets to suit the swapchain and the render scale
+    RenderTargets,
+}
+
+impl Degrade for Window {
+    type Op = Deferred;
+
+    fn retry(&mut self, op: &Deferred) -> Result<(), OutOfDeviceMemory> {
+        match *op {
+            Deferred::RenderTargets => {
+                // Between frames, so once drawing is idle nothing uses the old targets, and they
+                // can make room for the new
+                self.draw.as_mut().unwrap().wait_idle();
+                self.targets.take();
+                self.retired_targets.collect(u64::MAX);
+                self.replace_targets()
+            }
+        }
+    }
+
+    fn degrade(&mut self, stage: Stage) -> bool {
+        match stage {
+            Stage::ShrinkViewDistance => {
+                if !self.view_distance.shrink_for_memory() {
+                    return false;
+                }
+                warn!(
+                    meters = self.view_distance.target()
+                        / self.config.local_simulation.meters_to_absolute,
+                    "shrinking view distance to free GPU memory"
+                );
+                // Surfaces out of range are freed as the next frame is prepared
+                if let Some(draw) = self.draw.as_mut() {
+                    draw.set_view_distance(
+                        self.view_distance.radius(),
+                        self.view_distance.fog_distance(),
+                    );
+                }
+                true
+            }
+            Stage::ReduceRenderScale => {
+                if !self.display.reduce_render_scale() {
+                    return false;
+                }
+                warn!(
+                    render_scale = self.display.settings().render_scale,
+                    "reducing render scale to free GPU memory"
+                );
+                true
+            }
+        }
+    }
+}
+
+/// Detach the camera from the local character or return it, saying which
+fn toggle_observer(sim: &mut Sim) {
+    match sim.toggle_observer() {
+        Some(true) => info!("observing; the character stays put until you return to it"),
+        Some(false) => info!("returned to the character"),
+        None => warn!("can't observe: no character"),
     }
 }
 
 impl Drop for Window {
     fn drop(&mut self) {
         self.draw.take();
+        // Drawing has finished, so nothing's using the render targets
+        self.targets.take();
+        self.retired_targets.collect(u64::MAX);
         self.swapchain.take();
         unsafe {
             self.surface_fn.destroy_surface(self.surface, None);
@@ -557,55 +1246,15 @@ impl SwapchainState {
                     )
                     .unwrap();
                 gfx.set_name(view, cstr!("swapchain"));
-                let depth = DedicatedImage::new(
-                    device,
-                    &gfx.memory_properties,
-                    &vk::ImageCreateInfo::builder()
-                        .image_type(vk::ImageType::TYPE_2D)
-                        .format(vk::Format::D32_SFLOAT)
-                        .extent(vk::Extent3D {
-                            width: extent.width,
-                            height: extent.height,
-                            depth: 1,
-                        })
-                        .mip_levels(1)
-                        .array_layers(1)
-                        .samples(vk::SampleCountFlags::TYPE_1)
-                        .usage(
-                            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
-                                | vk::ImageUsageFlags::INPUT_ATTACHMENT,
-                        ),
-                );
-                gfx.set_name(depth.handle, cstr!("depth"));
-                gfx.set_name(depth.memory, cstr!("depth"));
-                let depth_view = device
-                    .create_image_view(
-                        &vk::ImageViewCreateInfo::builder()
-                            .image(depth.handle)
-                            .view_type(vk::ImageViewType::TYPE_2D)
-                            .format(vk::Format::D32_SFLOAT)
-                            .subresource_range(vk::ImageSubresourceRange {
-                                aspect_mask: vk::ImageAspectFlags::DEPTH,
-                                base_mip_level: 0,
-                                level_count: 1,
-                                base_array_layer: 0,
-                                layer_count: 1,
-                            }),
-                        None,
-                    )
-                    .unwrap();
-                gfx.set_name(depth_view, cstr!("depth"));
                 let present = device.create_semaphore(&Default::default(), None).unwrap();
                 gfx.set_name(present, cstr!("present"));
                 Frame {
                     view,
-                    depth,
-                    depth_view,
                     buffer: device
                         .create_framebuffer(
                             &vk::FramebufferCreateInfo::builder()
-                                .render_pass(gfx.render_pass)
-                                .attachments(&[view, depth_view])
+                                .render_pass(gfx.post_render_pass)
+                                .attachments(&[view])
                                 .width(extent.width)
                                 .height(extent.height)
                                 .layers(1),
@@ -631,11 +1280,9 @@ impl Drop for SwapchainState {
     fn drop(&mut self) {
         let device = &*self.gfx.device;
         unsafe {
-            for frame in &mut self.frames {
+            for frame in &self.frames {
                 device.destroy_framebuffer(frame.buffer, None);
-                device.destroy_image_view(frame.depth_view, None);
                 device.destroy_image_view(frame.view, None);
-                frame.depth.destroy(device);
                 device.destroy_semaphore(frame.present, None);
             }
             self.swapchain_fn.destroy_swapchain(self.handle, None);
@@ -646,11 +1293,7 @@ impl Drop for SwapchainState {
 struct Frame {
     /// Image view for an entire swapchain image
     view: vk::ImageView,
-    /// Depth buffer to use when rendering to this image
-    depth: DedicatedImage,
-    /// View thereof
-    depth_view: vk::ImageView,
-    /// Framebuffer referencing `view` and `depth_view`
+    /// Post-processing framebuffer referencing `view`
     buffer: vk::Framebuffer,
     /// Semaphore used to ensure the frame isn't presented until rendering completes
     present: vk::Semaphore,
@@ -11,17 +11,43 @@ macro_rules! cstr {
 }
 
 extern crate nalgebra as na;
+mod asset_pack;
+mod audio;
+mod block_prediction;
+mod breadcrumbs;
+mod camera;
+mod characters;
+mod chunk_priority;
 mod config;
+mod connection_quality;
+mod console;
+mod deferred;
+mod effects;
+mod exploration;
+mod extrapolation;
+mod follow;
 pub mod graphics;
 mod lahar_deprecated;
 mod loader;
 mod local_character_controller;
+mod look;
 pub mod metrics;
 pub mod net;
+mod observer;
+mod pending_nodes;
 mod prediction;
+mod server_files;
+mod signs;
 pub mod sim;
+mod templates;
+mod view_distance;
+mod view_model;
+mod waypoints;
+mod world_clock;
 
-pub use config::Config;
+pub use camera::CameraConfig;
+pub use config::{Config, ConfigError, ConfigReport};
+pub use connection_quality::ConnectionQuality;
 pub use sim::Sim;
 
 use loader::{Asset, Loader};
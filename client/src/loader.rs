@@ -1,8 +1,13 @@
 use std::{
     any::{Any, TypeId},
+    collections::BTreeMap,
     convert::TryFrom,
+    fmt,
+    future::Future,
     marker::PhantomData,
-    sync::{Arc, Mutex},
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
@@ -11,9 +16,10 @@ use downcast_rs::{impl_downcast, Downcast};
 use fxhash::FxHashMap;
 use lahar::{BufferRegion, DedicatedImage};
 use tokio::sync::mpsc;
-use tracing::error;
+use tracing::{error, warn};
 
 use crate::{
+    asset_pack::AssetSources,
     graphics::Base,
     lahar_deprecated::{
         staging::StagingBuffer,
@@ -21,6 +27,7 @@ use crate::{
     },
     Config,
 };
+use common::mem_budget::{self, Reservation};
 
 pub trait Cleanup {
     unsafe fn cleanup(self, gfx: &Base);
@@ -37,6 +44,13 @@ pub trait Loadable: Send + 'static {
     fn load(self, ctx: &LoadCtx) -> LoadFuture<'_, Self::Output>;
 }
 
+/// An item whose loading can be tracked individually, so that a `WorkQueue` can say which of its
+/// items failed
+pub trait Identified {
+    type Id: Copy + fmt::Debug + Send + 'static;
+    fn id(&self) -> Self::Id;
+}
+
 pub type LoadFuture<'a, T> =
     std::pin::Pin<Box<dyn std::future::Future<Output = Result<T>> + 'a + Send>>;
 
@@ -53,6 +67,8 @@ impl Loader {
     pub fn new(cfg: Arc<Config>, gfx: Arc<Base>) -> Self {
         let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
         let (send, recv) = mpsc::unbounded_channel();
+        // The staging, vertex, and index buffers allocated below
+        let memory = gfx.reserve_memory(&mem_budget::GPU, (32 + 16 + 16) * 1024 * 1024);
         let staging =
             StagingBuffer::new(gfx.device.clone(), &gfx.memory_properties, 32 * 1024 * 1024);
         unsafe {
@@ -96,6 +112,7 @@ impl Loader {
         let shared = Arc::new(Shared {
             send,
             ctx: LoadCtx {
+                sources: RwLock::new(Arc::new(AssetSources::new(&cfg, None))),
                 cfg,
                 gfx,
                 staging,
@@ -103,6 +120,7 @@ impl Loader {
                 vertex_alloc: Mutex::new(vertex_alloc),
                 index_alloc: Mutex::new(index_alloc),
                 mesh_ds_layout,
+                _memory: memory,
             },
         });
         Self {
@@ -140,7 +158,7 @@ impl Loader {
                     });
                 }
                 Err(e) => {
-                    error!("{} load failed: {:#}", description, e);
+                    error!(%description, error = format_args!("{e:#}"), "load failed");
                 }
             }
         });
@@ -151,30 +169,33 @@ impl Loader {
         }
     }
 
-    pub fn make_queue<T: Loadable>(&mut self, capacity: usize) -> WorkQueue<T> {
-        let (input_send, mut input_recv) = mpsc::channel::<T>(capacity);
-        let (output_send, output_recv) = mpsc::channel::<T::Output>(capacity);
+    /// Create a queue loading up to `capacity` items at once, any of which is given up on if it
+    /// hasn't finished within `timeout`
+    pub fn make_queue<T: Loadable + Identified>(
+        &mut self,
+        capacity: usize,
+        timeout: Duration,
+    ) -> WorkQueue<T> {
+        let (input_send, mut input_recv) = mpsc::channel::<(u64, T)>(capacity);
+        let (output_send, output_recv) = mpsc::channel::<(u64, Option<T::Output>)>(capacity);
         let shared = self.shared.clone();
         self.runtime.spawn(async move {
-            while let Some(x) = input_recv.recv().await {
+            while let Some((ticket, x)) = input_recv.recv().await {
                 let shared = shared.clone();
                 let out = output_send.clone();
                 tokio::spawn(async move {
-                    match shared.ctx.load(x).await {
-                        Ok(x) => {
-                            if let Err(e) = out.send(x).await {
-                                unsafe {
-                                    e.0.cleanup(&shared.ctx.gfx);
-                                }
+                    let output = {
+                        let shared = shared.clone();
+                        supervise::<T, _>(async move { shared.ctx.load(x).await }).await
+                    };
+                    // Every item is answered, even if only to say it failed, so its capacity is
+                    // freed
+                    if let Err(e) = out.send((ticket, output)).await {
+                        if let (_, Some(x)) = e.0 {
+                            unsafe {
+                                x.cleanup(&shared.ctx.gfx);
                             }
                         }
-                        Err(e) => {
-                            error!(
-                                "streaming {} load failed: {:#}",
-                                std::any::type_name::<T>(),
-                                e
-                            );
-                        }
                     }
                 });
             }
@@ -183,8 +204,7 @@ impl Loader {
             shared: self.shared.clone(),
             send: input_send,
             recv: output_recv,
-            capacity,
-            fill: 0,
+            in_flight: InFlight::new(capacity, timeout),
         }
     }
 
@@ -207,6 +227,13 @@ impl Loader {
     pub fn ctx(&self) -> &LoadCtx {
         &self.shared.ctx
     }
+
+    /// Prefer assets from the pack at `path`, or stop preferring any server-suggested pack if
+    /// `None`, for assets loaded from now on
+    pub fn set_server_pack(&self, path: Option<PathBuf>) {
+        let ctx = &self.shared.ctx;
+        *ctx.sources.write().unwrap() = Arc::new(AssetSources::new(&ctx.cfg, path));
+    }
 }
 
 impl Drop for Loader {
@@ -230,15 +257,24 @@ struct Message {
 
 pub struct LoadCtx {
     pub cfg: Arc<Config>,
+    /// Where assets are read from, replaced when the server suggests a different pack
+    sources: RwLock<Arc<AssetSources>>,
     pub gfx: Arc<Base>,
     pub staging: StagingBuffer,
     pub transfer: TransferHandle,
     pub vertex_alloc: Mutex<BufferRegion>,
     pub index_alloc: Mutex<BufferRegion>,
     pub mesh_ds_layout: vk::DescriptorSetLayout,
+    /// Device memory of `staging`, `vertex_alloc`, and `index_alloc`
+    _memory: Reservation,
 }
 
 impl LoadCtx {
+    /// Where assets are currently read from
+    pub fn sources(&self) -> Arc<AssetSources> {
+        self.sources.read().unwrap().clone()
+    }
+
     async fn load<T: Loadable>(&self, x: T) -> Result<T::Output> {
         x.load(self).await
     }
@@ -307,55 +343,293 @@ impl<T: 'static> Clone for Asset<T> {
 
 impl<T: 'static> Copy for Asset<T> {}
 
+/// Run `load` as a task of its own, so that a panic is reported as a failure like any other
+async fn supervise<T, F>(load: F) -> Option<T::Output>
+where
+    T: Loadable,
+    F: Future<Output = Result<T::Output>> + Send + 'static,
+{
+    match tokio::spawn(load).await {
+        Ok(Ok(x)) => Some(x),
+        Ok(Err(e)) => {
+            error!(
+                "streaming {} load failed: {:#}",
+                std::any::type_name::<T>(),
+                e
+            );
+            None
+        }
+        Err(e) => {
+            error!(
+                "streaming {} load panicked: {}",
+                std::any::type_name::<T>(),
+                e
+            );
+            None
+        }
+    }
+}
+
 /// A bounded-capacity queue for streaming specific data (e.g. terrain chunks)
 ///
 /// Limiting capacity ensures predictable memory usage and helps focus computational resources on
 /// recent requests when the total number of requests that could be submitted is large. This is
 /// particularly useful for terrain, where recent requests are more likely to be close to the
 /// viewpoint.
-pub struct WorkQueue<T: Loadable> {
+pub struct WorkQueue<T: Loadable + Identified> {
     shared: Arc<Shared>,
-    send: mpsc::Sender<T>,
-    recv: mpsc::Receiver<T::Output>,
-    capacity: usize,
-    fill: usize,
+    send: mpsc::Sender<(u64, T)>,
+    recv: mpsc::Receiver<(u64, Option<T::Output>)>,
+    in_flight: InFlight<T::Id>,
 }
 
-impl<T: Loadable> WorkQueue<T> {
+/// What became of an item given to a `WorkQueue`
+pub enum Completion<T: Loadable + Identified> {
+    Loaded(T::Output),
+    /// The item failed, panicked, or took too long, and may be submitted again
+    Failed(T::Id),
+}
+
+impl<T: Loadable + Identified> WorkQueue<T> {
     /// Begin loading a single item, if capacity is available
     pub fn load(&mut self, x: T) -> Result<(), T> {
         use tokio::sync::mpsc::error::TrySendError::*;
-        if self.fill == self.capacity {
+        let Some(ticket) = self.in_flight.dispatch(x.id(), Instant::now()) else {
             return Err(x);
-        }
-        self.fill += 1;
-        self.send.try_send(x).map_err(|e| {
-            self.fill -= 1;
+        };
+        self.send.try_send((ticket, x)).map_err(|e| {
+            self.in_flight.complete(ticket);
             match e {
-                Full(x) => x,
-                Closed(x) => x,
+                Full((_, x)) => x,
+                Closed((_, x)) => x,
             }
         })
     }
 
-    /// Fetch a load result if one is ready, freeing capacity
-    pub fn poll(&mut self) -> Option<T::Output> {
-        let result = self.recv.try_recv().ok()?;
-        self.fill -= 1;
-        Some(result)
+    /// Fraction of the queue's capacity taken up by items still loading
+    pub fn fill(&self) -> f32 {
+        self.in_flight.fill() as f32 / self.in_flight.capacity as f32
+    }
+
+    /// Fetch the outcome of a load if one is known, freeing capacity
+    pub fn poll(&mut self) -> Option<Completion<T>> {
+        while let Ok((ticket, output)) = self.recv.try_recv() {
+            let Some(id) = self.in_flight.complete(ticket) else {
+                // Already given up on, and perhaps resubmitted
+                if let Some(x) = output {
+                    unsafe {
+                        x.cleanup(&self.shared.ctx.gfx);
+                    }
+                }
+                continue;
+            };
+            return Some(match output {
+                Some(x) => Completion::Loaded(x),
+                None => Completion::Failed(id),
+            });
+        }
+        let id = self.in_flight.expire(Instant::now())?;
+        warn!(
+            ?id,
+            "gave up on streaming {} load after {:?}",
+            std::any::type_name::<T>(),
+            self.in_flight.timeout
+        );
+        Some(Completion::Failed(id))
     }
 }
 
-impl<T: Loadable> Drop for WorkQueue<T> {
+impl<T: Loadable + Identified> Drop for WorkQueue<T> {
     fn drop(&mut self) {
         // Ensure any future completions will be cleaned up by the loader
         self.recv.close();
         // Gracefully drain already-completed tasks
-        while let Ok(x) = self.recv.try_recv() {
-            self.fill -= 1;
-            unsafe {
-                x.cleanup(&self.shared.ctx.gfx);
+        while let Ok((ticket, output)) = self.recv.try_recv() {
+            self.in_flight.complete(ticket);
+            if let Some(x) = output {
+                unsafe {
+                    x.cleanup(&self.shared.ctx.gfx);
+                }
             }
         }
     }
 }
+
+/// Bookkeeping for the items a `WorkQueue` is waiting on
+struct InFlight<Id> {
+    capacity: usize,
+    timeout: Duration,
+    next_ticket: u64,
+    /// Each outstanding item and when it was dispatched, by ticket
+    ///
+    /// Tickets are issued in order, so the first entry is the oldest.
+    items: BTreeMap<u64, (Id, Instant)>,
+}
+
+impl<Id> InFlight<Id> {
+    fn new(capacity: usize, timeout: Duration) -> Self {
+        Self {
+            capacity,
+            timeout,
+            next_ticket: 0,
+            items: BTreeMap::new(),
+        }
+    }
+
+    /// Number of outstanding items
+    fn fill(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Issue a ticket for `id` dispatched at `now`, if capacity is available
+    fn dispatch(&mut self, id: Id, now: Instant) -> Option<u64> {
+        if self.fill() == self.capacity {
+            return None;
+        }
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.items.insert(ticket, (id, now));
+        Some(ticket)
+    }
+
+    /// Retire `ticket`, unless it's already been retired or expired
+    fn complete(&mut self, ticket: u64) -> Option<Id> {
+        self.items.remove(&ticket).map(|(id, _)| id)
+    }
+
+    /// Retire the oldest item if it was dispatched more than `timeout` before `now`
+    fn expire(&mut self, now: Instant) -> Option<Id> {
+        let (_, &(_, dispatched)) = self.items.first_key_value()?;
+        if now.saturating_duration_since(dispatched) < self.timeout {
+            return None;
+        }
+        self.items.pop_first().map(|(_, (id, _))| id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+    use std::collections::HashSet;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    struct Job(u32);
+
+    struct Done(u32);
+
+    impl Cleanup for Done {
+        unsafe fn cleanup(self, _gfx: &Base) {}
+    }
+
+    impl Loadable for Job {
+        type Output = Done;
+        fn load(self, _ctx: &LoadCtx) -> LoadFuture<'_, Self::Output> {
+            unreachable!()
+        }
+    }
+
+    /// Drive `jobs` through a queue of `capacity` whose worker panics the first time it sees
+    /// `poisoned`, resubmitting failures as the chunk streamer does, until all are done
+    #[test]
+    fn panicking_load_is_retried() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let poisoned = 3;
+        let mut panicked = false;
+        let mut in_flight = InFlight::new(1, TIMEOUT);
+        let mut pending = (0..6).collect::<Vec<u32>>();
+        let mut done = HashSet::new();
+        let mut attempts = 0;
+        while !pending.is_empty() {
+            let key = pending.remove(0);
+            let ticket = in_flight.dispatch(key, Instant::now()).unwrap();
+            attempts += 1;
+            let panic = key == poisoned && !panicked;
+            panicked |= panic;
+            let output = runtime.block_on(supervise::<Job, _>(async move {
+                if panic {
+                    panic!("generation of {key} went wrong");
+                }
+                Ok(Done(key))
+            }));
+            let id = in_flight.complete(ticket).unwrap();
+            match output {
+                Some(Done(x)) => {
+                    assert_eq!(x, id);
+                    assert!(done.insert(x));
+                }
+                None => {
+                    assert_eq!(id, poisoned);
+                    pending.push(id);
+                }
+            }
+        }
+        assert!(panicked);
+        assert_eq!(done.len(), 6);
+        assert_eq!(attempts, 7);
+        assert_eq!(in_flight.fill(), 0);
+    }
+
+    #[test]
+    fn lost_result_expires() {
+        let start = Instant::now();
+        let mut in_flight = InFlight::new(4, TIMEOUT);
+        let lost = in_flight.dispatch("lost", start).unwrap();
+        let answered = in_flight.dispatch("answered", start + TIMEOUT / 2).unwrap();
+        assert_eq!(in_flight.expire(start + TIMEOUT / 2), None);
+        assert_eq!(in_flight.expire(start + TIMEOUT), Some("lost"));
+        assert_eq!(in_flight.fill(), 1);
+        // The younger item isn't due yet
+        assert_eq!(in_flight.expire(start + TIMEOUT), None);
+        assert_eq!(in_flight.complete(answered), Some("answered"));
+        // A result arriving after its item was given up on is discarded
+        assert_eq!(in_flight.complete(lost), None);
+        assert_eq!(in_flight.fill(), 0);
+    }
+
+    /// However items come and go, capacity is neither leaked nor exceeded
+    #[test]
+    fn fill_never_drifts() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let start = Instant::now();
+        let capacity = 8;
+        let mut in_flight = InFlight::new(capacity, TIMEOUT);
+        // Tickets whose results will eventually arrive, including late ones
+        let mut outstanding = Vec::new();
+        let mut live = HashSet::new();
+        let mut now = start;
+        for _ in 0..1000 {
+            now += Duration::from_millis(rng.gen_range(0..2000));
+            match rng.gen_range(0..4) {
+                0 => {
+                    if let Some(ticket) = in_flight.dispatch((), now) {
+                        outstanding.push(ticket);
+                        live.insert(ticket);
+                    } else {
+                        assert_eq!(live.len(), capacity);
+                    }
+                }
+                1 | 2 if !outstanding.is_empty() => {
+                    // Succeeded or failed; either way the result arrives
+                    let ticket = outstanding.swap_remove(rng.gen_range(0..outstanding.len()));
+                    assert_eq!(in_flight.complete(ticket).is_some(), live.remove(&ticket));
+                }
+                _ => {
+                    // Lost, to be found by the timeout
+                    while in_flight.expire(now).is_some() {}
+                    live.retain(|&ticket| in_flight.items.contains_key(&ticket));
+                }
+            }
+            assert_eq!(in_flight.fill(), live.len());
+            assert!(in_flight.fill() <= capacity);
+        }
+        for ticket in outstanding {
+            in_flight.complete(ticket);
+        }
+        while in_flight.expire(now + TIMEOUT).is_some() {}
+        assert_eq!(in_flight.fill(), 0);
+    }
+}
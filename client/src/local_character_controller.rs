@@ -33,6 +33,13 @@ impl LocalCharacterController {
         self.orientation
     }
 
+    /// Replaces the current orientation with a level `orientation` chosen by the server, as when the
+    /// character spawns. Up is taken to be the orientation's own up until the next position update.
+    pub fn adopt_orientation(&mut self, orientation: na::UnitQuaternion<f32>) {
+        self.orientation = orientation;
+        self.up = orientation * na::Vector3::y_axis();
+    }
+
     /// Updates the LocalCharacter based on outside information. Note that the `up` parameter is relative
     /// only to `position`, not the character's orientation.
     pub fn update_position(
@@ -225,6 +232,30 @@ mod tests {
         assert_yaw_and_pitch_correct(base_orientation, yaw, 0.0, subject.horizontal_orientation());
     }
 
+    #[test]
+    fn adopted_orientation_is_base_for_look() {
+        let mut subject = LocalCharacterController::new();
+        subject.look_level(0.3, 0.2);
+
+        let base_orientation = na::UnitQuaternion::new(na::Vector3::new(1.3, -2.1, 0.5));
+        subject.adopt_orientation(base_orientation);
+        assert_aligned_to_gravity(&subject);
+
+        // Updates with an unchanged up leave the adopted orientation alone
+        let up = subject.up;
+        subject.update_position(Position::origin(), up, true);
+
+        let deltas = [(0.5, -0.4), (-0.2, 0.3), (1.1, 0.05)];
+        let (mut yaw, mut pitch) = (0.0, 0.0);
+        for (delta_yaw, delta_pitch) in deltas {
+            subject.look_level(delta_yaw, delta_pitch);
+            yaw += delta_yaw;
+            pitch += delta_pitch;
+        }
+        assert_aligned_to_gravity(&subject);
+        assert_yaw_and_pitch_correct(base_orientation, yaw, pitch, subject.orientation);
+    }
+
     #[test]
     fn align_to_gravity_examples() {
         // Pick an arbitrary orientation
@@ -0,0 +1,160 @@
+//! Mouse-look input, taken both before each simulation step and again just before rendering
+//!
+//! Turning the view is where players notice latency most, so orientation is "late-latched": any
+//! deltas that arrive while a frame is prepared turn the view once more just before the frame's
+//! uniforms are written, rather than waiting for the next frame. Each delta is drained exactly
+//! once, by whichever point comes first, and turns the view the same way either way, so a delta
+//! drained late still reaches the next step's `CharacterInput`.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Turning of the view, in radians about the view's axes
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct LookDelta {
+    pub yaw: f32,
+    pub pitch: f32,
+    pub roll: f32,
+}
+
+/// Mouse-look deltas waiting to turn the view
+///
+/// Clones share the same deltas, so they may be pushed from any thread.
+#[derive(Clone, Default)]
+pub struct LookInput {
+    shared: Arc<Mutex<Pending>>,
+}
+
+#[derive(Default)]
+struct Pending {
+    /// Sum of the deltas pushed since the last drain
+    delta: LookDelta,
+    /// When the oldest delta not yet drained arrived
+    arrived: Option<Instant>,
+    /// When the oldest delta drained but not yet rendered arrived
+    unrendered: Option<Instant>,
+}
+
+impl LookInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `delta`, which arrived at `now`
+    pub fn push(&self, delta: LookDelta, now: Instant) {
+        let mut pending = self.shared.lock().unwrap();
+        pending.delta.yaw += delta.yaw;
+        pending.delta.pitch += delta.pitch;
+        pending.delta.roll += delta.roll;
+        pending.arrived.get_or_insert(now);
+    }
+
+    /// Take the sum of the deltas pushed since the last drain, if there were any
+    pub fn drain(&self) -> Option<LookDelta> {
+        let mut pending = self.shared.lock().unwrap();
+        let arrived = pending.arrived.take()?;
+        pending.unrendered.get_or_insert(arrived);
+        Some(std::mem::take(&mut pending.delta))
+    }
+
+    /// Note that everything drained so far is in the frame whose uniforms were written at `now`,
+    /// returning how long the oldest such delta waited for it
+    ///
+    /// Deltas arriving between the drain and `now` aren't counted, since they aren't in the frame.
+    pub fn rendered(&self, now: Instant) -> Option<Duration> {
+        let arrived = self.shared.lock().unwrap().unrendered.take()?;
+        Some(now.saturating_duration_since(arrived))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    #[test]
+    fn each_delta_drained_once() {
+        let mut rng = SmallRng::seed_from_u64(675);
+        let look = LookInput::new();
+        let start = Instant::now();
+        let mut pushed = [0.0; 3];
+        let mut drained = [0.0; 3];
+        let mut drain = |look: &LookInput| {
+            if let Some(x) = look.drain() {
+                drained[0] += x.yaw;
+                drained[1] += x.pitch;
+                drained[2] += x.roll;
+            }
+        };
+        for frame in 0..1000 {
+            let frame_start = start + Duration::from_millis(frame * 16);
+            // Deltas arrive before, between, and after the two drains of each frame at random
+            for i in 0..rng.gen_range(0..6) {
+                let delta = LookDelta {
+                    yaw: rng.gen_range(-0.05..0.05),
+                    pitch: rng.gen_range(-0.05..0.05),
+                    roll: rng.gen_range(-0.01..0.01),
+                };
+                pushed[0] += delta.yaw;
+                pushed[1] += delta.pitch;
+                pushed[2] += delta.roll;
+                look.push(delta, frame_start + Duration::from_millis(i));
+                match rng.gen_range(0..4) {
+                    0 => drain(&look),
+                    1 => {
+                        drain(&look);
+                        drain(&look);
+                    }
+                    _ => {}
+                }
+            }
+            if rng.gen() {
+                drain(&look);
+            }
+            look.rendered(frame_start + Duration::from_millis(10));
+        }
+        drain(&look);
+        assert!(look.drain().is_none());
+        for (pushed, drained) in pushed.iter().zip(&drained) {
+            assert_abs_diff_eq!(pushed, drained, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn late_drain_cuts_latency() {
+        // Each 16ms frame drains at its start, spends 12ms culling and recording, then writes
+        // uniforms, with a delta arriving every millisecond throughout
+        let mean_latency = |late_latch: bool| {
+            let look = LookInput::new();
+            let start = Instant::now();
+            let mut total = Duration::ZERO;
+            let mut frames = 0;
+            for frame in 0..100u32 {
+                let frame_start = start + Duration::from_millis(16 * u64::from(frame));
+                look.drain();
+                for i in 0..12 {
+                    look.push(LookDelta::default(), frame_start + Duration::from_millis(i));
+                }
+                if late_latch {
+                    look.drain();
+                }
+                if let Some(latency) = look.rendered(frame_start + Duration::from_millis(12)) {
+                    total += latency;
+                    frames += 1;
+                }
+                for i in 12..16 {
+                    look.push(LookDelta::default(), frame_start + Duration::from_millis(i));
+                }
+            }
+            total / frames
+        };
+        // Without late-latching, the oldest delta in each frame arrived at the start of the
+        // previous one, so it waits a whole frame on top of the time spent recording
+        assert_eq!(mean_latency(false), Duration::from_millis(28));
+        // With it, nothing waits longer than a frame
+        assert!(mean_latency(true) <= Duration::from_millis(16));
+    }
+}
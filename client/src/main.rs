@@ -3,18 +3,28 @@ use std::{
     sync::Arc,
 };
 
-use client::{graphics, metrics, net, Config};
-use save::Save;
+use client::{graphics, metrics, net, Config, ConfigError};
+use save::{Journal, Save};
 
 use ash::extensions::khr;
 use tracing::{error, error_span, info};
 
 fn main() {
     // Set up logging
-    common::init_tracing();
+    let log_filter = common::init_tracing();
+    let dirs = directories::ProjectDirs::from("", "", "hypermine").unwrap();
+    match std::env::args().nth(1).as_deref() {
+        None => {}
+        Some("--print-config") => std::process::exit(print_config(&dirs)),
+        Some("--check-config") => std::process::exit(check_config(&dirs)),
+        Some(other) => {
+            eprintln!("unrecognized argument {other:?}");
+            eprintln!("usage: client [--print-config | --check-config]");
+            std::process::exit(2);
+        }
+    }
     let metrics = crate::metrics::init();
 
-    let dirs = directories::ProjectDirs::from("", "", "hypermine").unwrap();
     let mut config = Config::load(&dirs);
 
     if config.server.is_none() {
@@ -27,14 +37,23 @@ fn main() {
         let key = cert.serialize_private_key_der();
         let cert = cert.serialize_der().unwrap();
         let sim_cfg = config.local_simulation.clone();
+        // Whoever runs the local server is in charge of it
+        let admins = vec![config.name.to_string()];
 
-        let save = dirs.data_local_dir().join("default.save");
-        info!("using save file {}", save.display());
-        std::fs::create_dir_all(save.parent().unwrap()).unwrap();
-        let save = match Save::open(&save, config.local_simulation.chunk_size) {
+        let save_path = dirs.data_local_dir().join("default.save");
+        info!("using save file {}", save_path.display());
+        std::fs::create_dir_all(save_path.parent().unwrap()).unwrap();
+        let mut save = match Save::open(&save_path, config.local_simulation.chunk_size) {
             Ok(x) => x,
             Err(e) => {
-                error!("couldn't open save: {}", e);
+                error!(error = %e, "couldn't open save");
+                return;
+            }
+        };
+        let journal = match Journal::open(&Journal::dir_for(&save_path), &mut save) {
+            Ok((journal, _)) => journal,
+            Err(e) => {
+                error!(error = %e, "couldn't recover interrupted writes");
                 return;
             }
         };
@@ -47,15 +66,37 @@ fn main() {
                     certificate_chain: vec![rustls::Certificate(cert)],
                     private_key: rustls::PrivateKey(key),
                     socket,
+                    status: None,
+                    admins,
+                    creative: Vec::new(),
+                    protected_regions: Vec::new(),
+                    asset_pack: None,
+                    graph_region_depth: server::DEFAULT_GRAPH_REGION_DEPTH,
+                    edit_history_records: server::DEFAULT_EDIT_HISTORY_RECORDS,
+                    edit_history_age: server::DEFAULT_EDIT_HISTORY_AGE,
+                    // Standard input is the client's own console
+                    console: false,
+                    console_socket: None,
+                    bulk_bytes_per_second: None,
+                    // Nothing to save by compressing over loopback
+                    compression: false,
+                    idle: server::IdlePolicy::default(),
                 },
                 sim_cfg,
-                save,
+                server::SaveParams {
+                    save,
+                    journal,
+                    autosave_interval: server::DEFAULT_AUTOSAVE_INTERVAL,
+                    // Interrupting the client shouldn't leave it running without its server
+                    save_on_interrupt: false,
+                },
             ) {
-                eprintln!("{e:#}");
+                error!(error = format_args!("{e:#}"), "local server failed");
                 std::process::exit(1);
             }
         });
     }
+    let gpu_memory_budget = config.gpu_memory_budget_bytes;
     let config = Arc::new(config);
 
     // Create the OS window
@@ -67,13 +108,14 @@ fn main() {
     let net = net::spawn(config.clone());
 
     // Finish creating the window, including the Vulkan resources used to render to it
-    let window = graphics::Window::new(window, core.clone(), config, metrics, net);
+    let window = graphics::Window::new(window, core.clone(), config, metrics, net, log_filter);
 
     // Initialize widely-shared graphics resources
     let gfx = Arc::new(
         graphics::Base::new(
             core,
             Some(dirs.cache_dir().join("pipeline_cache")),
+            gpu_memory_budget,
             &[khr::Swapchain::name()],
             |physical, queue_family| window.supports(physical, queue_family),
         )
@@ -83,3 +125,37 @@ fn main() {
     // Run the window's event loop
     window.run(gfx);
 }
+
+/// Print the config file as this client reads it, returning the exit status
+fn print_config(dirs: &directories::ProjectDirs) -> i32 {
+    match Config::print(dirs) {
+        Ok(text) => {
+            print!("{text}");
+            0
+        }
+        Err(e) => {
+            eprintln!("{}: {e}", Config::file(dirs).display());
+            1
+        }
+    }
+}
+
+/// Report any problems with the config file, returning the exit status
+fn check_config(dirs: &directories::ProjectDirs) -> i32 {
+    let path = Config::file(dirs);
+    match Config::check(dirs) {
+        Ok(report) => {
+            println!("{}", path.display());
+            print!("{report}");
+            i32::from(!report.is_valid())
+        }
+        Err(ConfigError::Io(ref e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!("{} not found; defaults apply", path.display());
+            0
+        }
+        Err(e) => {
+            eprintln!("{}: {e}", path.display());
+            1
+        }
+    }
+}
@@ -1,15 +1,22 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex, RwLock},
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
     time::Duration,
 };
 
 use hdrhistogram::Histogram;
+use metrics::histogram;
 use tracing::info;
 
 pub fn init() -> Arc<Recorder> {
     let recorder = Arc::new(Recorder {
         histograms: RwLock::new(HashMap::new()),
+        counters: Mutex::new(HashMap::new()),
+        gauges: Mutex::new(HashMap::new()),
     });
     metrics::set_boxed_recorder(Box::new(ArcRecorder(recorder.clone()))).unwrap();
     recorder
@@ -17,6 +24,9 @@ pub fn init() -> Arc<Recorder> {
 
 pub struct Recorder {
     histograms: RwLock<HashMap<metrics::Key, Mutex<Histogram<u64>>>>,
+    counters: Mutex<HashMap<metrics::Key, Arc<AtomicU64>>>,
+    /// Latest value of each gauge, as the bits of an `f64`
+    gauges: Mutex<HashMap<metrics::Key, Arc<AtomicU64>>>,
 }
 
 impl Recorder {
@@ -27,7 +37,7 @@ impl Recorder {
         for (key, histogram) in histograms {
             let histogram = histogram.lock().unwrap();
             info!(
-                key = %key.name(),
+                key = %describe(key),
                 percentile_25 = ?Duration::from_nanos(histogram.value_at_quantile(0.25)),
                 percentile_50 = ?Duration::from_nanos(histogram.value_at_quantile(0.50)),
                 percentile_75 = ?Duration::from_nanos(histogram.value_at_quantile(0.75)),
@@ -35,9 +45,136 @@ impl Recorder {
                 "metric"
             );
         }
+        #[allow(clippy::mutable_key_type)]
+        let counters = &*self.counters.lock().unwrap();
+        for (key, counter) in counters {
+            info!(
+                key = %describe(key),
+                total = counter.load(Ordering::Relaxed),
+                "metric"
+            );
+        }
+        #[allow(clippy::mutable_key_type)]
+        let gauges = &*self.gauges.lock().unwrap();
+        for (key, gauge) in gauges {
+            info!(
+                key = %describe(key),
+                latest = f64::from_bits(gauge.load(Ordering::Relaxed)),
+                "metric"
+            );
+        }
+    }
+}
+
+/// Parts of a frame's rendering that are timed separately
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pass {
+    /// Uniforms and chunk surfaces being uploaded and extracted
+    Transfer,
+    /// Opaque voxel surfaces
+    Voxels,
+    /// Characters and other meshes
+    Meshes,
+    /// Transparent voxel surfaces
+    Transparent,
+    /// Fog, and fitting the rendered image to the window
+    Post,
+    /// Decals, labels, the minimap, and other overlays
+    Overlay,
+}
+
+impl Pass {
+    pub const COUNT: usize = 6;
+    pub const ALL: [Pass; Self::COUNT] = [
+        Pass::Transfer,
+        Pass::Voxels,
+        Pass::Meshes,
+        Pass::Transparent,
+        Pass::Post,
+        Pass::Overlay,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Transfer => "transfer",
+            Pass::Voxels => "voxels",
+            Pass::Meshes => "meshes",
+            Pass::Transparent => "transparent",
+            Pass::Post => "post",
+            Pass::Overlay => "overlay",
+        }
+    }
+}
+
+/// Time each pass of a frame took
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameTimings {
+    /// Sequence number of the frame
+    pub frame: u64,
+    /// CPU time spent recording each pass's commands, indexed by `Pass`
+    pub cpu: [Duration; Pass::COUNT],
+    /// GPU time spent executing each pass's commands, indexed by `Pass`, unless the device can't
+    /// tell
+    pub gpu: Option<[Duration; Pass::COUNT]>,
+}
+
+impl FrameTimings {
+    /// Total GPU time spent on the frame, if known
+    pub fn gpu_total(&self) -> Option<Duration> {
+        self.gpu.map(|gpu| gpu.iter().sum())
     }
 }
 
+/// Timings of the latest frames, oldest first
+pub struct PassHistory {
+    frames: VecDeque<FrameTimings>,
+    capacity: usize,
+}
+
+impl PassHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Record a frame's timings, forgetting the oldest frame if full
+    pub fn push(&mut self, timings: FrameTimings) {
+        for pass in Pass::ALL {
+            histogram!("frame.cpu.pass", timings.cpu[pass as usize], "pass" => pass.name());
+            if let Some(ref gpu) = timings.gpu {
+                histogram!("frame.gpu.pass", gpu[pass as usize], "pass" => pass.name());
+            }
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(timings);
+    }
+
+    pub fn latest(&self) -> Option<&FrameTimings> {
+        self.frames.back()
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &FrameTimings> {
+        self.frames.iter()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Name of a metric followed by any labels distinguishing it from others of the same name
+fn describe(key: &metrics::Key) -> String {
+    let mut out = key.name().to_owned();
+    for label in key.labels() {
+        write!(out, " {}={}", label.key(), label.value()).unwrap();
+    }
+    out
+}
+
 struct ArcRecorder(Arc<Recorder>);
 
 impl metrics::Recorder for ArcRecorder {
@@ -68,12 +205,28 @@ impl metrics::Recorder for ArcRecorder {
         todo!()
     }
 
-    fn register_counter(&self, _key: &metrics::Key) -> metrics::Counter {
-        todo!()
+    fn register_counter(&self, key: &metrics::Key) -> metrics::Counter {
+        let counter = self
+            .0
+            .counters
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        metrics::Counter::from_arc(counter)
     }
 
-    fn register_gauge(&self, _key: &metrics::Key) -> metrics::Gauge {
-        todo!()
+    fn register_gauge(&self, key: &metrics::Key) -> metrics::Gauge {
+        let gauge = self
+            .0
+            .gauges
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        metrics::Gauge::from_arc(gauge)
     }
 
     fn register_histogram(&self, key: &metrics::Key) -> metrics::Histogram {
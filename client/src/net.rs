@@ -1,46 +1,457 @@
-use std::{sync::Arc, thread};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Error, Result};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Notify};
 
-use common::{codec, proto};
+use common::{
+    block_entity::BlockEntityChange,
+    codec::{self, BodyFormat, Sizes},
+    proto::{
+        self,
+        negotiation::{Protocol, IDLE_CLOSE_CODE, REFUSED_CLOSE_CODE},
+        Capabilities,
+    },
+    traffic::Traffic,
+    EntityId,
+};
 
 use crate::Config;
 
 pub struct Net {
     pub incoming: mpsc::UnboundedReceiver<Message>,
-    pub outgoing: mpsc::UnboundedSender<proto::Command>,
-    pub thread: thread::JoinHandle<()>,
+    pub outgoing: Outgoing,
+    /// Messages received from the server, counted by whatever reads them
+    pub received: Received,
+    /// Thread driving the connection, or `None` if the caller exchanges messages itself, as with
+    /// an in-process server
+    pub thread: Option<thread::JoinHandle<()>>,
 }
 
 pub fn spawn(cfg: Arc<Config>) -> Net {
     let (incoming_send, incoming_recv) = mpsc::unbounded_channel();
-    let (outgoing_send, outgoing_recv) = mpsc::unbounded_channel();
-    let thread = thread::spawn(move || {
-        if let Err(e) = run(cfg, incoming_send.clone(), outgoing_recv) {
-            let _ = incoming_send.send(Message::ConnectionLost(e));
+    let (outgoing_send, outgoing_recv) = outgoing(INITIAL_OUTGOING_CAPACITY, STALL_TIMEOUT);
+    let received = Received::default();
+    let thread = thread::spawn({
+        let received = received.clone();
+        move || {
+            if let Err(e) = run(cfg, incoming_send.clone(), outgoing_recv, received) {
+                let _ = incoming_send.send(Message::ConnectionLost(e));
+            }
         }
     });
     Net {
         incoming: incoming_recv,
         outgoing: outgoing_send,
-        thread,
+        received,
+        thread: Some(thread),
+    }
+}
+
+/// Length of time for which commands are kept waiting to be sent before the oldest are discarded
+const OUTGOING_BUFFER_TIME: Duration = Duration::from_secs(2);
+/// Capacity of the outgoing queue until the server's step rate is known
+const INITIAL_OUTGOING_CAPACITY: usize = 64;
+/// Length of time messages can wait without any being flushed before the connection is considered
+/// stalled
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(3);
+/// Period over which send and receive rates are measured
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Outgoing queue capacity holding `OUTGOING_BUFFER_TIME` worth of commands sent every
+/// `step_interval`
+pub fn outgoing_capacity(step_interval: Duration) -> usize {
+    (OUTGOING_BUFFER_TIME.as_secs_f64() / step_interval.as_secs_f64())
+        .ceil()
+        .max(1.0) as usize
+}
+
+/// Construct a queue of messages bound for the server holding at most `capacity` of them, which
+/// is considered stalled if nothing is flushed from it for `stall_timeout`
+pub fn outgoing(capacity: usize, stall_timeout: Duration) -> (Outgoing, OutgoingReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(QueueState {
+            messages: VecDeque::with_capacity(capacity),
+            capacity,
+            stall_timeout,
+            in_flight: 0,
+            closed: false,
+            progress: None,
+            dropped: 0,
+            messages_sent: 0,
+            bytes_sent: 0,
+            last_flushed: None,
+            recent: VecDeque::new(),
+        }),
+        ready: Notify::new(),
+    });
+    (
+        Outgoing {
+            shared: shared.clone(),
+        },
+        OutgoingReceiver { shared },
+    )
+}
+
+/// Sending half of the queue of messages bound for the server
+///
+/// Once the queue is full, the oldest messages are discarded to make room for new ones. A server
+/// that has fallen that far behind gains nothing from a backlog of stale input.
+#[derive(Clone)]
+pub struct Outgoing {
+    shared: Arc<Shared>,
+}
+
+impl Outgoing {
+    /// Queue `msg` to be sent
+    pub fn send(&self, msg: proto::ClientMessage) -> Result<Queued, Closed> {
+        self.send_at(msg, Instant::now())
+    }
+
+    fn send_at(&self, msg: proto::ClientMessage, now: Instant) -> Result<Queued, Closed> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return Err(Closed);
+        }
+        if state.unflushed() == 0 {
+            state.progress = Some(now);
+        }
+        let mut result = Queued::Appended;
+        if state.messages.len() >= state.capacity {
+            state.messages.pop_front();
+            state.dropped += 1;
+            result = Queued::DisplacedOldest;
+        }
+        state.messages.push_back(msg);
+        drop(state);
+        self.shared.ready.notify_one();
+        Ok(result)
+    }
+
+    /// Change the number of messages that can wait to be sent, discarding the oldest if there are
+    /// now too many
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.capacity = capacity.max(1);
+        while state.messages.len() > state.capacity {
+            state.messages.pop_front();
+            state.dropped += 1;
+        }
+    }
+
+    /// Give up on the connection, discarding any messages yet to be sent
+    ///
+    /// The transport closes the connection once it notices, which it reports as lost.
+    pub fn close(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        state.messages.clear();
+        drop(state);
+        self.shared.ready.notify_one();
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state_at(Instant::now())
+    }
+
+    fn state_at(&self, now: Instant) -> ConnectionState {
+        let state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return ConnectionState::Closed;
+        }
+        match state.progress {
+            Some(progress)
+                if state.unflushed() > 0
+                    && now.saturating_duration_since(progress) >= state.stall_timeout =>
+            {
+                ConnectionState::Stalled
+            }
+            _ => ConnectionState::Connected,
+        }
+    }
+
+    pub fn stats(&self) -> OutgoingStats {
+        self.stats_at(Instant::now())
+    }
+
+    fn stats_at(&self, now: Instant) -> OutgoingStats {
+        let mut state = self.shared.state.lock().unwrap();
+        state.forget_before(now);
+        OutgoingStats {
+            depth: state.unflushed(),
+            capacity: state.capacity,
+            dropped: state.dropped,
+            messages_sent: state.messages_sent,
+            bytes_sent: state.bytes_sent,
+            last_flushed: state.last_flushed,
+            messages_per_second: state.recent.len(),
+            bytes_per_second: state.recent.iter().map(|&(_, bytes)| bytes as u64).sum(),
+        }
+    }
+}
+
+impl Drop for Outgoing {
+    fn drop(&mut self) {
+        // Wake the receiver so it can notice if this was the last sender
+        self.shared.ready.notify_one();
+    }
+}
+
+/// Receiving half of the queue of messages bound for the server, drained by whatever delivers
+/// them
+pub struct OutgoingReceiver {
+    shared: Arc<Shared>,
+}
+
+impl OutgoingReceiver {
+    /// Wait for the next message to send, or return `None` once every `Outgoing` is gone or the
+    /// queue has been closed
+    pub async fn recv(&mut self) -> Option<proto::ClientMessage> {
+        loop {
+            if let Some(msg) = self.try_recv() {
+                return Some(msg);
+            }
+            if Arc::strong_count(&self.shared) == 1 || self.shared.state.lock().unwrap().closed {
+                return None;
+            }
+            self.shared.ready.notified().await;
+        }
+    }
+
+    /// Take the next message to send, if any
+    ///
+    /// The message is counted against the queue's depth until it's reported `flushed`.
+    pub fn try_recv(&mut self) -> Option<proto::ClientMessage> {
+        let mut state = self.shared.state.lock().unwrap();
+        let msg = state.messages.pop_front()?;
+        state.in_flight += 1;
+        Some(msg)
+    }
+
+    /// Record that a message taken from the queue, encoded as `bytes` bytes, was handed off to the
+    /// transport
+    pub fn flushed(&mut self, bytes: usize) {
+        self.flushed_at(bytes, Instant::now());
+    }
+
+    fn flushed_at(&mut self, bytes: usize, now: Instant) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.in_flight = state.in_flight.saturating_sub(1);
+        state.progress = Some(now);
+        state.messages_sent += 1;
+        state.bytes_sent += bytes as u64;
+        state.last_flushed = Some(now);
+        state.recent.push_back((now, bytes));
+        state.forget_before(now);
+    }
+}
+
+impl Drop for OutgoingReceiver {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+    }
+}
+
+struct Shared {
+    state: Mutex<QueueState>,
+    /// Signaled when a message is queued or an `Outgoing` is dropped
+    ready: Notify,
+}
+
+struct QueueState {
+    messages: VecDeque<proto::ClientMessage>,
+    capacity: usize,
+    stall_timeout: Duration,
+    /// Number of messages taken from `messages` but not yet flushed
+    in_flight: usize,
+    /// Whether the receiver is gone or the queue was closed
+    closed: bool,
+    /// When a message was last flushed, or began waiting while none were
+    progress: Option<Instant>,
+    dropped: u64,
+    messages_sent: u64,
+    bytes_sent: u64,
+    last_flushed: Option<Instant>,
+    /// Times and sizes of messages flushed within `RATE_WINDOW`
+    recent: VecDeque<(Instant, usize)>,
+}
+
+impl QueueState {
+    fn unflushed(&self) -> usize {
+        self.messages.len() + self.in_flight
+    }
+
+    fn forget_before(&mut self, now: Instant) {
+        while let Some(&(time, _)) = self.recent.front() {
+            if now.saturating_duration_since(time) < RATE_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+}
+
+/// How a message was added to the outgoing queue
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Queued {
+    Appended,
+    /// The queue was full, so the oldest message was discarded to make room
+    DisplacedOldest,
+}
+
+/// Error indicating that messages can no longer be sent, because the connection has shut down
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Closed;
+
+/// Health of the connection to the server, as judged by the outgoing queue
+///
+/// There's no reconnection logic yet, so a stalled connection either recovers by itself or is
+/// eventually closed by the transport.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Messages are being flushed, or there are none to flush
+    Connected,
+    /// Messages have waited for at least the stall timeout without any being flushed, though the
+    /// transport hasn't reported an error
+    Stalled,
+    /// The transport has shut down
+    Closed,
+}
+
+/// Snapshot of the outgoing queue's activity
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OutgoingStats {
+    /// Messages waiting to be flushed, including any being written
+    pub depth: usize,
+    pub capacity: usize,
+    /// Messages discarded to make room for newer ones
+    pub dropped: u64,
+    /// Messages flushed to the transport
+    pub messages_sent: u64,
+    /// Encoded size of the messages flushed to the transport
+    pub bytes_sent: u64,
+    /// When a message was last flushed to the transport, if ever
+    pub last_flushed: Option<Instant>,
+    /// Messages flushed within the last second
+    pub messages_per_second: usize,
+    /// Encoded size of the messages flushed within the last second
+    pub bytes_per_second: u64,
+}
+
+/// Accounting for the messages received from the server, shared between whatever receives them
+/// and whatever reports on them
+#[derive(Clone, Default)]
+pub struct Received {
+    state: Arc<Mutex<ReceivedState>>,
+}
+
+impl Received {
+    /// Count a message of `kind` received now
+    pub fn record(&self, kind: &'static str, sizes: Sizes) {
+        self.record_at(kind, sizes, Instant::now());
+    }
+
+    fn record_at(&self, kind: &'static str, sizes: Sizes, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.traffic.record(kind, sizes.wire, sizes.uncompressed);
+        state.recent.push_back((now, sizes.wire));
+        state.forget_before(now);
+    }
+
+    pub fn stats(&self) -> ReceivedStats {
+        self.stats_at(Instant::now())
+    }
+
+    fn stats_at(&self, now: Instant) -> ReceivedStats {
+        let mut state = self.state.lock().unwrap();
+        state.forget_before(now);
+        ReceivedStats {
+            traffic: state.traffic.clone(),
+            messages_per_second: state.recent.len(),
+            bytes_per_second: state.recent.iter().map(|&(_, bytes)| bytes as u64).sum(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ReceivedState {
+    traffic: Traffic,
+    /// Times and sizes on the wire of messages received within `RATE_WINDOW`
+    recent: VecDeque<(Instant, usize)>,
+}
+
+impl ReceivedState {
+    fn forget_before(&mut self, now: Instant) {
+        while let Some(&(time, _)) = self.recent.front() {
+            if now.saturating_duration_since(time) < RATE_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
     }
 }
 
+/// Snapshot of the messages received from the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedStats {
+    /// Every message received, by kind
+    pub traffic: Traffic,
+    /// Messages received within the last second
+    pub messages_per_second: usize,
+    /// Size on the wire of the messages received within the last second
+    pub bytes_per_second: u64,
+}
+
 #[derive(Debug)]
 pub enum Message {
     Hello(proto::ServerHello),
     Spawns(proto::Spawns),
+    Inventory(proto::InventoryUpdate),
+    BlockUpdateRejected(proto::BlockUpdateRejection),
+    MovementModes(proto::MovementModesUpdate),
+    Waypoints(proto::WaypointsUpdate),
+    CollisionTrace(proto::CollisionTraceReport),
+    Sounds(Vec<proto::SoundEvent>),
+    GraphRegions(Vec<proto::EncodedGraphRegion>),
+    EntityUpdates(proto::EntityUpdates),
+    GraphUpdates(proto::GraphUpdates),
+    WorldEdits(proto::WorldEdits),
+    Following(Option<EntityId>),
+    BlockEntities(Vec<BlockEntityChange>),
     StateDelta(proto::StateDelta),
     ConnectionLost(Error),
 }
 
+impl From<proto::ServerMessage> for Message {
+    fn from(msg: proto::ServerMessage) -> Self {
+        match msg {
+            proto::ServerMessage::Spawns(x) => Message::Spawns(x),
+            proto::ServerMessage::Inventory(x) => Message::Inventory(x),
+            proto::ServerMessage::BlockUpdateRejected(x) => Message::BlockUpdateRejected(x),
+            proto::ServerMessage::MovementModes(x) => Message::MovementModes(x),
+            proto::ServerMessage::Waypoints(x) => Message::Waypoints(x),
+            proto::ServerMessage::CollisionTrace(x) => Message::CollisionTrace(x),
+            proto::ServerMessage::Sounds(x) => Message::Sounds(x),
+            proto::ServerMessage::GraphRegions(x) => Message::GraphRegions(x),
+            proto::ServerMessage::EntityUpdates(x) => Message::EntityUpdates(x),
+            proto::ServerMessage::GraphUpdates(x) => Message::GraphUpdates(x),
+            proto::ServerMessage::WorldEdits(x) => Message::WorldEdits(x),
+            proto::ServerMessage::Following(x) => Message::Following(x),
+            proto::ServerMessage::BlockEntities(x) => Message::BlockEntities(x),
+        }
+    }
+}
+
 #[tokio::main(worker_threads = 1)]
 async fn run(
     cfg: Arc<Config>,
     incoming: mpsc::UnboundedSender<Message>,
-    outgoing: mpsc::UnboundedReceiver<proto::Command>,
+    outgoing: OutgoingReceiver,
+    received: Received,
 ) -> Result<()> {
     let mut endpoint = quinn::Endpoint::client("[::]:0".parse().unwrap())?;
     let crypto = rustls::ClientConfig::builder()
@@ -50,7 +461,7 @@ async fn run(
     let client_cfg = quinn::ClientConfig::new(Arc::new(crypto));
     endpoint.set_default_client_config(client_cfg);
 
-    let result = inner(cfg, incoming, outgoing, endpoint.clone()).await;
+    let result = inner(cfg, incoming, outgoing, received, endpoint.clone()).await;
     endpoint.wait_idle().await;
     result
 }
@@ -58,7 +469,8 @@ async fn run(
 async fn inner(
     cfg: Arc<Config>,
     incoming: mpsc::UnboundedSender<Message>,
-    outgoing: mpsc::UnboundedReceiver<proto::Command>,
+    outgoing: OutgoingReceiver,
+    received: Received,
     endpoint: quinn::Endpoint,
 ) -> Result<()> {
     let server = cfg.server.unwrap();
@@ -69,49 +481,137 @@ async fn inner(
     // Start sending commands asynchronously
     tokio::spawn(handle_outgoing(outgoing, connection.clone()));
     // Actually send the hello message
-    codec::send_whole(
-        clienthello_stream,
-        &proto::ClientHello {
-            name: (*cfg.name).into(),
-        },
-    )
-    .await?;
+    codec::send_whole(clienthello_stream, &proto::ClientHello::new(&*cfg.name)).await?;
 
-    let mut ordered = connection.accept_uni().await?;
-    // Handle unordered messages
-    tokio::spawn(handle_unordered(incoming.clone(), connection));
+    let mut ordered = connection.accept_uni().await.map_err(explain_close)?;
 
     // Receive the server's hello message
-    let hello = codec::recv::<proto::ServerHello>(&mut ordered)
+    let hello = codec::recv_bytes(&mut ordered)
         .await?
         .ok_or_else(|| anyhow!("ordered stream closed unexpectedly"))?;
+    received.record(
+        "ServerHello",
+        Sizes {
+            wire: hello.len(),
+            uncompressed: hello.len(),
+        },
+    );
+    let hello = proto::ServerHello::decode(&Protocol::CURRENT, &hello)?;
+    let capabilities = hello.header.capabilities;
+    let split = capabilities.contains(Capabilities::SPLIT_UPDATES);
+    // Everything after the hello is in whatever format was negotiated
+    let format = BodyFormat::negotiated(capabilities);
     // Forward it on
     incoming.send(Message::Hello(hello)).unwrap();
 
+    // The server opens its other ordered streams, if any, before its unordered ones
+    if split {
+        for _ in 0..2 {
+            let stream = connection.accept_uni().await?;
+            tokio::spawn(handle_ordered(
+                incoming.clone(),
+                stream,
+                format,
+                received.clone(),
+                connection.clone(),
+            ));
+        }
+    }
+    // Handle unordered messages
+    tokio::spawn(handle_unordered(
+        incoming.clone(),
+        format,
+        received.clone(),
+        connection.clone(),
+    ));
+
     // Receive ordered messages from the server
     loop {
-        let spawns = codec::recv::<proto::Spawns>(&mut ordered)
-            .await?
-            .ok_or_else(|| anyhow!("ordered stream closed unexpectedly"))?;
-        incoming.send(Message::Spawns(spawns)).unwrap();
+        let (msg, sizes) = match codec::recv_as::<proto::ServerMessage>(&mut ordered, format).await
+        {
+            Ok(x) => x.ok_or_else(|| anyhow!("ordered stream closed unexpectedly"))?,
+            // The server may have said why it closed the connection
+            Err(e) => return Err(connection.close_reason().map_or(e, explain_close)),
+        };
+        received.record(msg.kind(), sizes);
+        incoming.send(msg.into()).unwrap();
+    }
+}
+
+/// Explain `e` to the user, which the server gives a readable reason for if it refused the client
+/// or disconnected it for idleness
+fn explain_close(e: quinn::ConnectionError) -> Error {
+    match e {
+        quinn::ConnectionError::ApplicationClosed(ref close)
+            if close.error_code == quinn::VarInt::from_u32(REFUSED_CLOSE_CODE) =>
+        {
+            anyhow!(
+                "refused by server: {}",
+                String::from_utf8_lossy(&close.reason)
+            )
+        }
+        quinn::ConnectionError::ApplicationClosed(ref close)
+            if close.error_code == quinn::VarInt::from_u32(IDLE_CLOSE_CODE) =>
+        {
+            anyhow!(
+                "disconnected by server: {}",
+                String::from_utf8_lossy(&close.reason)
+            )
+        }
+        e => e.into(),
     }
 }
 
-/// Send commands to the server
+/// Send commands and other messages to the server
 async fn handle_outgoing(
-    mut outgoing: mpsc::UnboundedReceiver<proto::Command>,
+    mut outgoing: OutgoingReceiver,
     connection: quinn::Connection,
 ) -> Result<()> {
-    while let Some(cmd) = outgoing.recv().await {
+    while let Some(msg) = outgoing.recv().await {
         let stream = connection.open_uni().await?;
         // TODO: Don't silently die on parse errors
-        codec::send_whole(stream, &cmd).await?;
+        codec::send_whole(stream, &msg).await?;
+        outgoing.flushed(codec::encoded_len(&msg));
     }
+    // Nothing more will be sent, whether because the client is exiting or has given up
+    connection.close(0u32.into(), b"client closed");
     Ok(())
 }
 
+/// Receive messages from one of the server's ordered streams other than the first
+async fn handle_ordered(
+    incoming: mpsc::UnboundedSender<Message>,
+    mut stream: quinn::RecvStream,
+    format: BodyFormat,
+    received: Received,
+    connection: quinn::Connection,
+) {
+    loop {
+        match codec::recv_as::<proto::ServerMessage>(&mut stream, format).await {
+            Ok(Some((msg, sizes))) => {
+                received.record(msg.kind(), sizes);
+                if incoming.send(msg.into()).is_err() {
+                    return;
+                }
+            }
+            // The connection is closing, which is handled elsewhere
+            Ok(None) => return,
+            Err(e) => {
+                tracing::error!(error = %e, "Error when parsing ordered stream from server");
+                connection.close(1u32.into(), b"could not process stream");
+                return;
+            }
+        }
+    }
+}
+
 /// Receive unordered messages from the server
-async fn handle_unordered(incoming: mpsc::UnboundedSender<Message>, connection: quinn::Connection) {
+async fn handle_unordered(
+    incoming: mpsc::UnboundedSender<Message>,
+    format: BodyFormat,
+    received: Received,
+    connection: quinn::Connection,
+) {
     loop {
         let Ok(stream) = connection.accept_uni().await else {
             // accept_uni should only fail if the connection is closed, which is already handled elsewhere.
@@ -119,13 +619,15 @@ async fn handle_unordered(incoming: mpsc::UnboundedSender<Message>, connection:
         };
         let incoming = incoming.clone();
         let connection = connection.clone();
+        let received = received.clone();
         tokio::spawn(async move {
-            match codec::recv_whole::<proto::StateDelta>(2usize.pow(16), stream).await {
+            match codec::recv_whole_as::<proto::StateDelta>(2usize.pow(16), stream, format).await {
                 Err(e) => {
-                    tracing::error!("Error when parsing unordered stream from server: {e}");
+                    tracing::error!(error = %e, "Error when parsing unordered stream from server");
                     connection.close(1u32.into(), b"could not process stream");
                 }
-                Ok(msg) => {
+                Ok((msg, sizes)) => {
+                    received.record("StateDelta", sizes);
                     let _ = incoming.send(Message::StateDelta(msg));
                 }
             }
@@ -148,3 +650,211 @@ impl rustls::client::ServerCertVerifier for AcceptAnyCert {
         Ok(rustls::client::ServerCertVerified::assertion())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    fn msg(i: u32) -> proto::ClientMessage {
+        proto::ClientMessage::SetWorldTime(i as f32)
+    }
+
+    fn index(msg: proto::ClientMessage) -> u32 {
+        match msg {
+            proto::ClientMessage::SetWorldTime(x) => x as u32,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn capacity_covers_buffer_time() {
+        assert_eq!(outgoing_capacity(Duration::from_millis(50)), 40);
+        assert_eq!(outgoing_capacity(Duration::from_millis(30)), 67);
+        assert_eq!(outgoing_capacity(Duration::from_secs(5)), 1);
+    }
+
+    #[test]
+    fn full_queue_drops_oldest() {
+        let (send, mut recv) = outgoing(3, TIMEOUT);
+        let now = Instant::now();
+        for i in 0..3 {
+            assert_eq!(send.send_at(msg(i), now), Ok(Queued::Appended));
+        }
+        // The transport stops draining
+        for i in 3..5 {
+            assert_eq!(send.send_at(msg(i), now), Ok(Queued::DisplacedOldest));
+        }
+        let stats = send.stats_at(now);
+        assert_eq!(stats.depth, 3);
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(stats.messages_sent, 0);
+        let remaining = std::iter::from_fn(|| recv.try_recv())
+            .map(index)
+            .collect::<Vec<_>>();
+        assert_eq!(remaining, [2, 3, 4]);
+
+        // Shrinking discards the excess the same way
+        for i in 5..8 {
+            send.send_at(msg(i), now).unwrap();
+        }
+        send.set_capacity(1);
+        assert_eq!(send.stats_at(now).dropped, 4);
+        assert_eq!(recv.try_recv().map(index), Some(7));
+    }
+
+    #[test]
+    fn stalls_without_progress() {
+        let (send, mut recv) = outgoing(8, TIMEOUT);
+        let start = Instant::now();
+        // An idle connection isn't stalled, however long it's been
+        assert_eq!(
+            send.state_at(start + 10 * TIMEOUT),
+            ConnectionState::Connected
+        );
+
+        // A message is written promptly
+        send.send_at(msg(0), start).unwrap();
+        recv.try_recv().unwrap();
+        let flushed = start + Duration::from_millis(10);
+        recv.flushed_at(16, flushed);
+        assert_eq!(send.state_at(flushed + TIMEOUT), ConnectionState::Connected);
+
+        // The transport stops draining
+        let queued = start + Duration::from_secs(10);
+        send.send_at(msg(1), queued).unwrap();
+        send.send_at(msg(2), queued + TIMEOUT / 2).unwrap();
+        assert_eq!(
+            send.state_at(queued + TIMEOUT - Duration::from_millis(1)),
+            ConnectionState::Connected
+        );
+        assert_eq!(send.state_at(queued + TIMEOUT), ConnectionState::Stalled);
+
+        // A message taken but never finished is still unflushed
+        recv.try_recv().unwrap();
+        assert_eq!(
+            send.state_at(queued + 2 * TIMEOUT),
+            ConnectionState::Stalled
+        );
+
+        // Progress resumes
+        let resumed = queued + 2 * TIMEOUT;
+        recv.flushed_at(16, resumed);
+        assert_eq!(send.state_at(resumed), ConnectionState::Connected);
+        assert_eq!(send.state_at(resumed + TIMEOUT), ConnectionState::Stalled);
+        recv.try_recv().unwrap();
+        recv.flushed_at(16, resumed + TIMEOUT);
+        assert_eq!(
+            send.state_at(resumed + 10 * TIMEOUT),
+            ConnectionState::Connected
+        );
+    }
+
+    #[test]
+    fn counters_match_traffic() {
+        let (send, mut recv) = outgoing(64, TIMEOUT);
+        let start = Instant::now();
+        // Two seconds of 10 messages per second, each a little larger than the last
+        for i in 0..20 {
+            let now = start + Duration::from_millis(100 * i as u64);
+            send.send_at(msg(i), now).unwrap();
+            recv.try_recv().unwrap();
+            recv.flushed_at(10 + i as usize, now);
+        }
+        let last = start + Duration::from_millis(1900);
+        let stats = send.stats_at(last);
+        assert_eq!(stats.depth, 0);
+        assert_eq!(stats.dropped, 0);
+        assert_eq!(stats.messages_sent, 20);
+        assert_eq!(stats.bytes_sent, (10..30).sum::<u64>());
+        assert_eq!(stats.last_flushed, Some(last));
+        assert_eq!(stats.messages_per_second, 10);
+        assert_eq!(stats.bytes_per_second, (20..30).sum::<u64>());
+
+        // Rates fall off once traffic stops, but totals remain
+        let stats = send.stats_at(last + Duration::from_millis(500));
+        assert_eq!(stats.messages_per_second, 5);
+        assert_eq!(stats.bytes_per_second, (25..30).sum::<u64>());
+        let stats = send.stats_at(last + RATE_WINDOW);
+        assert_eq!(stats.messages_per_second, 0);
+        assert_eq!(stats.bytes_per_second, 0);
+        assert_eq!(stats.messages_sent, 20);
+    }
+
+    #[test]
+    fn received_counters_match_traffic() {
+        let received = Received::default();
+        let start = Instant::now();
+        // Two seconds of a state delta every 100ms, and a compressed graph update every 500ms
+        for i in 0..20 {
+            let now = start + Duration::from_millis(100 * i as u64);
+            let delta = Sizes {
+                wire: 100,
+                uncompressed: 100,
+            };
+            received.record_at("StateDelta", delta, now);
+            if i % 5 == 0 {
+                let update = Sizes {
+                    wire: 1000,
+                    uncompressed: 4000,
+                };
+                received.record_at("GraphUpdates", update, now);
+            }
+        }
+        let last = start + Duration::from_millis(1900);
+        let stats = received.stats_at(last);
+        assert_eq!(stats.traffic.get("StateDelta").messages, 20);
+        assert_eq!(stats.traffic.get("StateDelta").bytes, 2000);
+        assert_eq!(stats.traffic.get("GraphUpdates").messages, 4);
+        assert_eq!(stats.traffic.get("GraphUpdates").bytes, 4000);
+        assert_eq!(stats.traffic.get("GraphUpdates").uncompressed_bytes, 16000);
+        // The rates count the last second's messages as they were on the wire
+        assert_eq!(stats.messages_per_second, 12);
+        assert_eq!(stats.bytes_per_second, 10 * 100 + 2 * 1000);
+
+        let stats = received.stats_at(last + RATE_WINDOW);
+        assert_eq!(stats.messages_per_second, 0);
+        assert_eq!(stats.bytes_per_second, 0);
+        assert_eq!(stats.traffic.total().messages, 24);
+    }
+
+    #[test]
+    fn closed_when_receiver_dropped() {
+        let (send, recv) = outgoing(4, TIMEOUT);
+        send.send(msg(0)).unwrap();
+        drop(recv);
+        assert_eq!(send.send(msg(1)), Err(Closed));
+        assert_eq!(send.state(), ConnectionState::Closed);
+    }
+
+    #[test]
+    fn receiver_finishes_after_senders_dropped() {
+        let (send, mut recv) = outgoing(4, TIMEOUT);
+        send.send(msg(0)).unwrap();
+        drop(send);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            assert_eq!(recv.recv().await.map(index), Some(0));
+            assert!(recv.recv().await.is_none());
+        });
+    }
+
+    #[test]
+    fn receiver_finishes_after_close() {
+        let (send, mut recv) = outgoing(4, TIMEOUT);
+        send.send(msg(0)).unwrap();
+        send.close();
+        assert_eq!(send.state(), ConnectionState::Closed);
+        assert_eq!(send.send(msg(1)), Err(Closed));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        // Unsent messages are abandoned
+        runtime.block_on(async {
+            assert!(recv.recv().await.is_none());
+        });
+    }
+}
@@ -0,0 +1,81 @@
+//! A camera detached from the local character, flown freely through the world
+
+use common::{
+    character_controller,
+    graph::Graph,
+    proto::{CharacterInput, MovementInput, Position},
+    SimConfig,
+};
+
+pub struct Observer {
+    /// Where the camera is and which way it faces
+    view: Position,
+    /// Most recent movement input, relative to the view and to no-clip movement speed
+    movement: na::Vector3<f32>,
+    /// Time flown for too briefly to move the camera yet
+    unsimulated_seconds: f32,
+}
+
+impl Observer {
+    /// Start observing from `view`
+    pub fn new(view: Position) -> Self {
+        Self {
+            view,
+            movement: na::zero(),
+            unsimulated_seconds: 0.0,
+        }
+    }
+
+    pub fn view(&self) -> Position {
+        self.view
+    }
+
+    /// Move the camera to `view`, as when it follows a character
+    pub fn set_view(&mut self, view: Position) {
+        self.view = view;
+    }
+
+    /// Velocity relative to the view while flying
+    pub fn velocity(&self, cfg: &SimConfig) -> na::Vector3<f32> {
+        self.movement * cfg.character.no_clip_movement_speed
+    }
+
+    pub fn set_movement(&mut self, movement: na::Vector3<f32>) {
+        self.movement = movement;
+    }
+
+    /// Turn the view freely, with no regard for gravity
+    pub fn look(&mut self, delta_yaw: f32, delta_pitch: f32, delta_roll: f32) {
+        let rotation = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), delta_yaw)
+            * na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), delta_pitch)
+            * na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), delta_roll);
+        self.view.local *= rotation.to_homogeneous();
+    }
+
+    /// Fly for `dt` seconds as a no-clip character facing the view would
+    ///
+    /// The view stays put while its node waits to be populated.
+    pub fn fly(&mut self, cfg: &SimConfig, graph: &Graph, dt: f32) {
+        let input = CharacterInput {
+            movement: MovementInput::new(self.movement),
+            jump: false,
+            no_clip: true,
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        };
+        character_controller::run_character_step(
+            cfg,
+            graph,
+            &mut self.view,
+            &mut na::zero(),
+            &mut false,
+            &input,
+            &mut self.unsimulated_seconds,
+            dt,
+            None,
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Nodes heard of before the nodes they branch from
+//!
+//! The server sends every node after its parent within a message, but a node can still arrive
+//! ahead of its parent when the messages carrying them are lost, resent, or split up. Such nodes
+//! wait here, and are added to the graph as soon as their parents are. Should a parent never
+//! arrive, the server is asked for the way to it again.
+
+use fxhash::FxHashMap;
+use tracing::trace;
+
+use common::{
+    dodeca::Side,
+    graph::{Graph, NodeId},
+    proto::FreshNode,
+    Step,
+};
+
+/// Steps a node waits for its parent before the server is asked for it, unless configured otherwise
+pub const DEFAULT_RESYNC_STEPS: Step = 20;
+
+/// Nodes waiting for their parents to be added to the graph
+#[derive(Default)]
+pub struct PendingNodes {
+    /// Sides joining waiting nodes to each parent they wait on, and the latest step as of which
+    /// the parent was waited on without asking the server for it
+    waiting: FxHashMap<NodeId, (Vec<Side>, Step)>,
+}
+
+impl PendingNodes {
+    /// Add `nodes`, received as of `step`, to `graph`, holding back those whose parents it lacks
+    /// until they're added
+    pub fn insert(&mut self, graph: &mut Graph, nodes: &[FreshNode], step: Step) {
+        for node in nodes {
+            if graph.contains(node.parent) {
+                // The graph may already have been extended there around an observer
+                graph.ensure_neighbor(node.parent, node.side);
+            } else {
+                self.waiting
+                    .entry(node.parent)
+                    .or_insert_with(|| (Vec::new(), step))
+                    .0
+                    .push(node.side);
+            }
+        }
+
+        // Adding a node can supply the parents of others, including nodes other than itself that
+        // were added on the way to it
+        loop {
+            let ready = self
+                .waiting
+                .keys()
+                .copied()
+                .filter(|&parent| graph.contains(parent))
+                .collect::<Vec<_>>();
+            if ready.is_empty() {
+                break;
+            }
+            for parent in ready {
+                let (sides, _) = self.waiting.remove(&parent).unwrap();
+                trace!(count = sides.len(), "adding nodes whose parent arrived");
+                for side in sides {
+                    graph.ensure_neighbor(parent, side);
+                }
+            }
+        }
+    }
+
+    /// Number of nodes waiting for their parents
+    pub fn len(&self) -> usize {
+        self.waiting.values().map(|(sides, _)| sides.len()).sum()
+    }
+
+    /// Parents waited on for more than `timeout` steps as of `step`, which are then waited on
+    /// anew so that the server is only asked for each once per `timeout`
+    pub fn take_overdue(&mut self, step: Step, timeout: Step) -> Vec<NodeId> {
+        let mut overdue = Vec::new();
+        for (&parent, (_, since)) in &mut self.waiting {
+            if step.wrapping_sub(*since) > timeout {
+                overdue.push(parent);
+                *since = step;
+            }
+        }
+        overdue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{proto::Position, traversal::ensure_nearby};
+    use rand::{rngs::SmallRng, seq::SliceRandom, SeedableRng};
+
+    /// The nodes the server would send a client after extending `graph`, parents first
+    fn fresh_nodes(graph: &Graph) -> Vec<FreshNode> {
+        graph
+            .fresh()
+            .iter()
+            .filter_map(|&id| {
+                let side = graph.parent(id)?;
+                Some(FreshNode {
+                    side,
+                    parent: graph.neighbor(id, side).unwrap(),
+                })
+            })
+            .collect()
+    }
+
+    /// A graph of the nodes around the origin, and those nodes as the server would send them
+    fn server_graph() -> (Graph, Vec<FreshNode>) {
+        let mut graph = Graph::new(12);
+        ensure_nearby(&mut graph, &Position::origin(), 2.0);
+        let nodes = fresh_nodes(&graph);
+        (graph, nodes)
+    }
+
+    #[test]
+    fn shuffled_burst_reconstructed() {
+        let (_, nodes) = server_graph();
+        assert!(nodes.len() >= 50, "only {} nodes", nodes.len());
+        let burst = &nodes[..50];
+
+        let mut expected = Graph::new(12);
+        let mut pending = PendingNodes::default();
+        pending.insert(&mut expected, burst, 0);
+        assert_eq!(pending.len(), 0);
+
+        let mut shuffled = burst.to_vec();
+        shuffled.shuffle(&mut SmallRng::seed_from_u64(0));
+        let mut graph = Graph::new(12);
+        let mut pending = PendingNodes::default();
+        for (step, message) in shuffled.chunks(17).enumerate() {
+            pending.insert(&mut graph, message, step as Step);
+        }
+        assert_eq!(pending.len(), 0);
+        assert_eq!(graph.len(), expected.len());
+        assert_eq!(
+            graph.tree().collect::<Vec<_>>(),
+            expected.tree().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn cascade_resolves_chains() {
+        let (server, _) = server_graph();
+        // The way from the origin to the node farthest from it, parents first
+        let mut node = server
+            .tree()
+            .map(|(side, parent)| server.neighbor(parent, side).unwrap())
+            .max_by_key(|&x| server.length(x))
+            .unwrap();
+        let mut path = Vec::new();
+        while let Some(side) = server.parent(node) {
+            let parent = server.neighbor(node, side).unwrap();
+            path.push(FreshNode { side, parent });
+            node = parent;
+        }
+        path.reverse();
+        assert!(path.len() >= 3, "only {} levels", path.len());
+
+        // Everything but the first arrives, deepest first, and waits
+        let mut graph = Graph::new(12);
+        let mut pending = PendingNodes::default();
+        for node in path[1..].iter().rev() {
+            pending.insert(&mut graph, std::slice::from_ref(node), 0);
+        }
+        assert_eq!(pending.len(), path.len() - 1);
+        assert_eq!(graph.len(), 1);
+
+        // Until the first does
+        pending.insert(&mut graph, &path[..1], 1);
+        assert_eq!(pending.len(), 0);
+        for node in &path {
+            assert!(graph.contains(node.parent));
+        }
+        let last = path.last().unwrap();
+        assert!(graph.neighbor(last.parent, last.side).is_some());
+    }
+
+    #[test]
+    fn missing_parents_overdue() {
+        let (server, nodes) = server_graph();
+        // A node whose parent is never sent
+        let orphan = nodes.iter().find(|x| x.parent != NodeId::ROOT).unwrap();
+        assert!(server.contains(orphan.parent));
+        let mut graph = Graph::new(12);
+        let mut pending = PendingNodes::default();
+        pending.insert(&mut graph, std::slice::from_ref(orphan), 10);
+        assert_eq!(pending.len(), 1);
+
+        let timeout = 5;
+        assert!(pending.take_overdue(15, timeout).is_empty());
+        assert_eq!(pending.take_overdue(16, timeout), [orphan.parent]);
+        // Not asked for again straight away
+        assert!(pending.take_overdue(17, timeout).is_empty());
+        assert_eq!(pending.take_overdue(22, timeout), [orphan.parent]);
+        assert_eq!(pending.len(), 1);
+    }
+}
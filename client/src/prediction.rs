@@ -1,11 +1,19 @@
 use std::collections::VecDeque;
 
 use common::{
-    character_controller,
+    character_controller::{self, CollisionTrace, Tether},
     graph::Graph,
-    proto::{CharacterInput, Position},
+    proto::{CharacterInput, ExternalInfluence, MovementModes, Position},
     SimConfig,
 };
+use metrics::counter;
+use tracing::{info, warn};
+
+/// Largest number of logged inputs replayed in a single call to `advance_replay`
+///
+/// When the server acknowledges a large batch of input at once, bringing the prediction up to date
+/// is spread over several frames rather than stalling one.
+pub const REPLAY_BUDGET: usize = 16;
 
 /// Predicts the result of motion inputs in-flight to the server
 ///
@@ -13,13 +21,68 @@ use common::{
 /// inputs, and to obtaining a generation tag to send alongside the input. The server echos the
 /// highest tag it's received alongside every state update, which we then use in `reconcile` to
 /// determine which inputs have been integrated into the server's state and no longer need to be
-/// predicted.
+/// predicted. The corrected prediction is computed incrementally by `advance_replay`, which should
+/// be called once per frame.
+///
+/// At most `SimConfig::max_prediction_latency` worth of input is retained. If the server goes
+/// quiet for longer than that, the log is discarded and prediction is suspended until the server
+/// responds, at which point its state is adopted outright.
 pub struct PredictedMotion {
+    /// Inputs tagged with the generations following `generation - log.len()`, oldest first
     log: VecDeque<CharacterInput>,
     generation: u16,
+    sync: SyncState,
+    /// Prediction being rebuilt from the latest authoritative state, if any
+    replay: Option<Replay>,
     predicted_position: Position,
     predicted_velocity: na::Vector3<f32>,
     predicted_on_ground: bool,
+    /// Recent steps predicted by `push`, if they're being traced
+    trace: Option<CollisionTrace>,
+    /// What the server reports is pulling the character, applied to every step predicted
+    tether: Option<Tether>,
+    /// Whether any step predicted since the prediction was last rebuilt from the server's state
+    /// held the character back at the edge of the generated world
+    held_at_frontier: bool,
+    /// Steps predicted from fresh input that held the character back at the edge of the generated
+    /// world
+    frontier_holds: u64,
+    /// How the latest replay to be adopted corrected the prediction, until taken
+    correction: Option<Correction>,
+}
+
+/// Why a replay from the server's state corrected the prediction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Correction {
+    /// The prediction may have gone wrong anywhere, as when another character got in the way
+    Ordinary,
+    /// The prediction held the character back at the edge of the generated world, where the
+    /// server, having generated more of it, most likely let the character carry on
+    Frontier,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SyncState {
+    /// Every input since the latest acknowledged generation is in the log
+    Predicting,
+    /// Too much input went unacknowledged. Inputs up to and including `through` weren't logged,
+    /// and the prediction is frozen until the server responds.
+    Stalled { through: u16 },
+    /// The server has responded after a stall, but hasn't yet acknowledged `through`, so the
+    /// unlogged inputs it has yet to process can't be predicted
+    Resyncing { through: u16 },
+}
+
+struct Replay {
+    position: Position,
+    velocity: na::Vector3<f32>,
+    on_ground: bool,
+    /// Number of logged inputs already applied
+    applied: usize,
+    /// Whether any input applied so far held the character back at the edge of the generated world
+    held_at_frontier: bool,
+    /// Whether the prediction being replaced was held back there
+    correcting_frontier: bool,
 }
 
 impl PredictedMotion {
@@ -27,61 +90,297 @@ impl PredictedMotion {
         Self {
             log: VecDeque::new(),
             generation: 0,
+            sync: SyncState::Predicting,
+            replay: None,
             predicted_position: initial_position,
             predicted_velocity: na::Vector3::zeros(),
             predicted_on_ground: false,
+            trace: None,
+            tether: None,
+            held_at_frontier: false,
+            frontier_holds: 0,
+            correction: None,
         }
     }
 
+    /// Start predicting afresh from `position`, where the server has just spawned the character,
+    /// forgetting any input in flight but numbering input on from the last sent
+    pub fn place(&mut self, position: Position) {
+        *self = Self {
+            generation: self.generation,
+            trace: self.trace.take(),
+            frontier_holds: self.frontier_holds,
+            ..Self::new(position)
+        };
+    }
+
+    /// Record how collisions are handled over the latest `steps` steps predicted, discarding
+    /// anything recorded already, or stop recording if `steps` is 0
+    ///
+    /// Only steps predicted from fresh input are recorded, not replays of inputs after the server
+    /// corrects the prediction.
+    pub fn trace_collisions(&mut self, steps: usize) {
+        self.trace = (steps > 0).then(|| CollisionTrace::new(steps));
+    }
+
+    /// What's been recorded since `trace_collisions`, if anything is being traced
+    pub fn collision_trace(&self) -> Option<&CollisionTrace> {
+        self.trace.as_ref()
+    }
+
+    /// Adopt what the server reports is pulling the character, as it will pull it in every step it
+    /// has yet to process
+    ///
+    /// The prediction is corrected from the next `reconcile` onwards.
+    pub fn set_tether(&mut self, tether: Option<Tether>) {
+        self.tether = tether;
+    }
+
+    /// What the server will move the character by in a step from `position`, besides its input
+    ///
+    /// Impulses aren't predicted, since the server applies them without warning.
+    pub fn external(&self, graph: &Graph, position: &Position) -> ExternalInfluence {
+        pull(self.tether.as_ref(), graph, position)
+    }
+
     /// Update for input about to be sent to the server, returning the generation it should be
     /// tagged with
     pub fn push(&mut self, cfg: &SimConfig, graph: &Graph, input: &CharacterInput) -> u16 {
-        character_controller::run_character_step(
+        self.generation = self.generation.wrapping_add(1);
+        if let SyncState::Stalled { ref mut through } = self.sync {
+            *through = self.generation;
+            return self.generation;
+        }
+        if self.log.len() >= max_inputs(cfg) {
+            warn!(
+                inputs = self.log.len(),
+                "server unresponsive; suspending motion prediction"
+            );
+            self.log.clear();
+            self.replay = None;
+            self.sync = SyncState::Stalled {
+                through: self.generation,
+            };
+            return self.generation;
+        }
+        let step_input = CharacterInput {
+            external: self.external(graph, &self.predicted_position),
+            ..input.clone()
+        };
+        let output = character_controller::run_character_step(
             cfg,
             graph,
             &mut self.predicted_position,
             &mut self.predicted_velocity,
             &mut self.predicted_on_ground,
-            input,
+            &step_input,
+            &mut 0.0,
             cfg.step_interval.as_secs_f32(),
+            self.trace.as_mut(),
         );
+        if output.held_at_frontier {
+            counter!("prediction.frontier_holds", 1);
+            self.frontier_holds += 1;
+            self.held_at_frontier = true;
+        }
         self.log.push_back(input.clone());
-        self.generation = self.generation.wrapping_add(1);
         self.generation
     }
 
     /// Update with the latest state received from the server and the generation it was based on
     pub fn reconcile(
         &mut self,
-        cfg: &SimConfig,
-        graph: &Graph,
         generation: u16,
         position: Position,
         velocity: na::Vector3<f32>,
         on_ground: bool,
     ) {
+        let mut resumed = false;
+        match self.sync {
+            SyncState::Predicting => {}
+            SyncState::Stalled { through } => {
+                info!("server responded; resynchronizing motion prediction");
+                // Nothing was logged, so the server's state is the best available prediction
+                self.replay = None;
+                self.predicted_position = position;
+                self.predicted_velocity = velocity;
+                self.predicted_on_ground = on_ground;
+                self.held_at_frontier = false;
+                self.sync = if generation == through {
+                    SyncState::Predicting
+                } else {
+                    SyncState::Resyncing { through }
+                };
+                return;
+            }
+            SyncState::Resyncing { through } => {
+                if precedes(generation, through) {
+                    // Everything logged is still in flight, but some unlogged inputs are too
+                    self.start_replay(position, velocity, on_ground);
+                    return;
+                }
+                self.sync = SyncState::Predicting;
+                resumed = true;
+            }
+        }
+
         let first_gen = self.generation.wrapping_sub(self.log.len() as u16);
         let obsolete = usize::from(generation.wrapping_sub(first_gen));
-        if obsolete > self.log.len() || obsolete == 0 {
+        if obsolete > self.log.len() || (obsolete == 0 && !resumed) {
             // We've already processed a state incorporating equal or more recent input
             return;
         }
         self.log.drain(..obsolete);
-        self.predicted_position = position;
-        self.predicted_velocity = velocity;
-        self.predicted_on_ground = on_ground;
+        self.start_replay(position, velocity, on_ground);
+    }
 
-        for input in self.log.iter() {
-            character_controller::run_character_step(
+    /// Adopt a state the server moved the character to discontinuously, as by teleporting it, and
+    /// replay in-flight inputs from there at once rather than over several frames
+    ///
+    /// Unlike `reconcile`, this takes effect even if `generation` was already acknowledged, since
+    /// the server may move a character without processing any of its input.
+    pub fn teleport(
+        &mut self,
+        cfg: &SimConfig,
+        graph: &Graph,
+        generation: u16,
+        position: Position,
+        velocity: na::Vector3<f32>,
+        on_ground: bool,
+    ) {
+        let acknowledged = self.logged_through(generation);
+        self.log.drain(..acknowledged);
+        self.start_replay(position, velocity, on_ground);
+        while self.replay.is_some() {
+            self.advance_replay(cfg, graph);
+        }
+    }
+
+    fn start_replay(&mut self, position: Position, velocity: na::Vector3<f32>, on_ground: bool) {
+        self.replay = Some(Replay {
+            position,
+            velocity,
+            on_ground,
+            applied: 0,
+            held_at_frontier: false,
+            // Replays superseded before they're adopted never replaced what was predicted
+            correcting_frontier: self.held_at_frontier
+                || self
+                    .replay
+                    .as_ref()
+                    .is_some_and(|replay| replay.correcting_frontier),
+        });
+    }
+
+    /// Apply up to `REPLAY_BUDGET` in-flight inputs to the latest state received from the server,
+    /// adopting the result as the prediction once every input has been applied. Returns the number
+    /// of inputs applied.
+    pub fn advance_replay(&mut self, cfg: &SimConfig, graph: &Graph) -> usize {
+        let Some(ref mut replay) = self.replay else {
+            return 0;
+        };
+        let steps = (self.log.len() - replay.applied).min(REPLAY_BUDGET);
+        for input in self.log.range(replay.applied..replay.applied + steps) {
+            let input = CharacterInput {
+                external: pull(self.tether.as_ref(), graph, &replay.position),
+                ..input.clone()
+            };
+            let output = character_controller::run_character_step(
                 cfg,
                 graph,
-                &mut self.predicted_position,
-                &mut self.predicted_velocity,
-                &mut self.predicted_on_ground,
-                input,
+                &mut replay.position,
+                &mut replay.velocity,
+                &mut replay.on_ground,
+                &input,
+                &mut 0.0,
                 cfg.step_interval.as_secs_f32(),
+                None,
             );
+            replay.held_at_frontier |= output.held_at_frontier;
+        }
+        replay.applied += steps;
+        if replay.applied == self.log.len() {
+            let replay = self.replay.take().unwrap();
+            self.predicted_position = replay.position;
+            self.predicted_velocity = replay.velocity;
+            self.predicted_on_ground = replay.on_ground;
+            self.held_at_frontier = replay.held_at_frontier;
+            self.correction = Some(if replay.correcting_frontier {
+                Correction::Frontier
+            } else {
+                Correction::Ordinary
+            });
+        }
+        steps
+    }
+
+    /// In-flight inputs more recent than `generation`, oldest first
+    pub fn inputs_since(&self, generation: u16) -> impl Iterator<Item = &CharacterInput> {
+        self.log.iter().skip(self.logged_through(generation))
+    }
+
+    /// Restrict in-flight inputs more recent than `generation` to the movement modes in
+    /// `allowed`, as the server will when it applies them
+    ///
+    /// The prediction is corrected from the next `reconcile` onwards.
+    pub fn restrict_inputs_since(&mut self, generation: u16, allowed: MovementModes) {
+        let skip = self.logged_through(generation);
+        for input in self.log.iter_mut().skip(skip) {
+            input.restrict(allowed);
+        }
+    }
+
+    /// Number of logged inputs with generations up to and including `generation`
+    fn logged_through(&self, generation: u16) -> usize {
+        let first_gen = self.generation.wrapping_sub(self.log.len() as u16);
+        let skip = generation.wrapping_sub(first_gen);
+        // Generations from before the log are treated as predating everything in it
+        if usize::from(skip) > self.log.len() {
+            0
+        } else {
+            usize::from(skip)
+        }
+    }
+
+    /// Number of inputs the server has yet to acknowledge
+    pub fn unacknowledged(&self) -> usize {
+        self.log.len()
+    }
+
+    /// How the latest replay to be adopted corrected the prediction, if one has been adopted since
+    /// this was last called
+    ///
+    /// Corrections of a prediction held back at the edge of the generated world are expected to be
+    /// large, and are best adopted outright.
+    pub fn take_correction(&mut self) -> Option<Correction> {
+        self.correction.take()
+    }
+
+    /// Number of steps predicted from fresh input that held the character back at the edge of the
+    /// generated world
+    pub fn frontier_holds(&self) -> u64 {
+        self.frontier_holds
+    }
+
+    /// Stop predicting until the server responds, leaving the prediction where it stands, as when
+    /// it goes unresponsive for longer than `max_prediction_latency`
+    ///
+    /// Input pushed meanwhile is tagged but not logged, and the server's state is adopted outright
+    /// once it responds.
+    pub fn suspend(&mut self) {
+        if self.is_stalled() {
+            return;
         }
+        self.log.clear();
+        self.replay = None;
+        self.sync = SyncState::Stalled {
+            through: self.generation,
+        };
+    }
+
+    /// Whether prediction is suspended because the server hasn't acknowledged input for too long
+    pub fn is_stalled(&self) -> bool {
+        matches!(self.sync, SyncState::Stalled { .. })
     }
 
     /// Latest estimate of the server's state after receiving all `push`ed inputs.
@@ -98,6 +397,25 @@ impl PredictedMotion {
     }
 }
 
+/// What `tether`, if any, does to a character stepping from `position`
+fn pull(tether: Option<&Tether>, graph: &Graph, position: &Position) -> ExternalInfluence {
+    ExternalInfluence {
+        impulse: None,
+        constraint: tether.map(|tether| tether.constraint(graph, position)),
+    }
+}
+
+/// Largest number of unacknowledged inputs to retain
+fn max_inputs(cfg: &SimConfig) -> usize {
+    let steps = cfg.max_prediction_latency.as_secs_f64() / cfg.step_interval.as_secs_f64();
+    (steps.ceil() as usize).max(1)
+}
+
+/// Whether generation `a` was sent before generation `b`
+fn precedes(a: u16, b: u16) -> bool {
+    (b.wrapping_sub(a) as i16) > 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -116,10 +434,14 @@ mod tests {
         let mut mock_graph = Graph::new(1);
         common::node::populate_fresh_nodes(&mut mock_graph);
         let mock_character_input = CharacterInput {
-            movement: na::Vector3::x(),
+            movement: common::proto::MovementInput::new(na::Vector3::x()),
             jump: false,
             no_clip: true,
             block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
         };
 
         let mut pred = PredictedMotion::new(pos());
@@ -128,14 +450,7 @@ mod tests {
         let push =
             |pred: &mut PredictedMotion| pred.push(&mock_cfg, &mock_graph, &mock_character_input);
         let reconcile = |pred: &mut PredictedMotion, generation| {
-            pred.reconcile(
-                &mock_cfg,
-                &mock_graph,
-                generation,
-                pos(),
-                na::Vector3::zeros(),
-                false,
-            )
+            pred.reconcile(generation, pos(), na::Vector3::zeros(), false)
         };
 
         pred.generation = u16::max_value() - 1;
@@ -151,4 +466,305 @@ mod tests {
         reconcile(&mut pred, 0);
         assert_eq!(pred.log.len(), 0);
     }
+
+    #[test]
+    fn ack_gap() {
+        let cfg = SimConfig::from_raw(&common::SimConfigRaw {
+            rate: Some(60),
+            max_prediction_latency_ms: Some(1000),
+            ..Default::default()
+        });
+        let max = max_inputs(&cfg);
+        assert_eq!(max, 60);
+        let mut graph = Graph::new(1);
+        common::node::populate_fresh_nodes(&mut graph);
+        let input = CharacterInput {
+            movement: common::proto::MovementInput::new(na::Vector3::x() * 0.01),
+            jump: false,
+            no_clip: true,
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        };
+        let step = |position: &mut Position, velocity: &mut na::Vector3<f32>, on_ground| {
+            character_controller::run_character_step(
+                &cfg,
+                &graph,
+                position,
+                velocity,
+                on_ground,
+                &input,
+                &mut 0.0,
+                cfg.step_interval.as_secs_f32(),
+                None,
+            );
+        };
+
+        // Inputs take this many frames to be acknowledged
+        const LATENCY: usize = 6;
+        // Frames during which nothing is acknowledged; five seconds
+        let gap = 60..360;
+
+        let mut pred = PredictedMotion::new(pos());
+        let mut server = (pos(), na::Vector3::zeros(), false);
+        let mut in_flight = VecDeque::new();
+        let mut stalled = false;
+        for frame in 0..480 {
+            if frame < 420 {
+                in_flight.push_back((frame, pred.push(&cfg, &graph, &input)));
+            }
+            if !gap.contains(&frame) {
+                let mut latest = None;
+                while let Some(&(sent, generation)) = in_flight.front() {
+                    if frame < 420 && sent + LATENCY > frame {
+                        break;
+                    }
+                    in_flight.pop_front();
+                    step(&mut server.0, &mut server.1, &mut server.2);
+                    latest = Some(generation);
+                }
+                if let Some(generation) = latest {
+                    pred.reconcile(generation, server.0, server.1, server.2);
+                }
+            }
+            assert!(pred.advance_replay(&cfg, &graph) <= REPLAY_BUDGET);
+            assert!(pred.log.len() <= max);
+            stalled |= pred.is_stalled();
+        }
+
+        // Prediction was suspended during the gap, then resumed
+        assert!(stalled);
+        assert!(!pred.is_stalled());
+        assert!(pred.replay.is_none());
+        // Once everything is acknowledged, the prediction matches the server exactly
+        assert!(pred.log.is_empty());
+        assert_eq!(pred.predicted_position().local, server.0.local);
+    }
+
+    #[test]
+    fn suspended_until_server_responds() {
+        let cfg = SimConfig::from_raw(&common::SimConfigRaw::default());
+        let mut graph = Graph::new(1);
+        common::node::populate_fresh_nodes(&mut graph);
+        let input = CharacterInput {
+            movement: common::proto::MovementInput::new(na::Vector3::x()),
+            jump: false,
+            no_clip: true,
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        };
+        let mut pred = PredictedMotion::new(pos());
+        pred.push(&cfg, &graph, &input);
+        // Acknowledged inputs are replayed from the server's state, interrupted by the suspension
+        pred.push(&cfg, &graph, &input);
+        pred.reconcile(1, pos(), na::Vector3::zeros(), false);
+        let frozen = (
+            *pred.predicted_position(),
+            *pred.predicted_velocity(),
+            *pred.predicted_on_ground(),
+        );
+        pred.suspend();
+        assert!(pred.is_stalled());
+        assert_eq!(pred.advance_replay(&cfg, &graph), 0);
+        let mut generation = 0;
+        for _ in 0..5 {
+            generation = pred.push(&cfg, &graph, &input);
+        }
+        assert_eq!(pred.unacknowledged(), 0);
+        assert_eq!(pred.predicted_position().local, frozen.0.local);
+        assert_eq!(*pred.predicted_velocity(), frozen.1);
+        assert_eq!(*pred.predicted_on_ground(), frozen.2);
+
+        // Suspending again changes nothing
+        pred.suspend();
+        assert_eq!(pred.push(&cfg, &graph, &input), generation + 1);
+
+        // The server's state is adopted outright, and prediction resumes once it's caught up
+        let server = Position {
+            node: common::graph::NodeId::ROOT,
+            local: common::math::translate_along(&na::Vector3::new(0.1, 0.0, 0.0)),
+        };
+        pred.reconcile(generation, server, na::Vector3::zeros(), false);
+        assert!(!pred.is_stalled());
+        assert_eq!(pred.predicted_position().local, server.local);
+        pred.push(&cfg, &graph, &input);
+        pred.reconcile(generation + 1, server, na::Vector3::zeros(), false);
+        assert_eq!(pred.unacknowledged(), 1);
+    }
+
+    #[test]
+    fn replay_spread_across_frames() {
+        let cfg = SimConfig::from_raw(&common::SimConfigRaw {
+            rate: Some(60),
+            ..Default::default()
+        });
+        let mut graph = Graph::new(1);
+        common::node::populate_fresh_nodes(&mut graph);
+        let input = CharacterInput {
+            movement: common::proto::MovementInput::new(na::Vector3::x() * 0.01),
+            jump: false,
+            no_clip: true,
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        };
+        let mut pred = PredictedMotion::new(pos());
+        for _ in 0..3 * REPLAY_BUDGET {
+            pred.push(&cfg, &graph, &input);
+        }
+        let expected = *pred.predicted_position();
+
+        // Acknowledging nothing new replays everything, in budget-sized pieces
+        pred.reconcile(1, pos(), na::Vector3::zeros(), false);
+        assert_eq!(pred.advance_replay(&cfg, &graph), REPLAY_BUDGET);
+        // The previous prediction is shown until the replay completes
+        assert_eq!(pred.predicted_position().local, expected.local);
+        assert_eq!(pred.advance_replay(&cfg, &graph), REPLAY_BUDGET);
+        assert_eq!(pred.advance_replay(&cfg, &graph), REPLAY_BUDGET - 1);
+        assert_eq!(pred.advance_replay(&cfg, &graph), 0);
+        assert!(pred.replay.is_none());
+    }
+
+    #[test]
+    fn teleport_adopted_immediately() {
+        let cfg = SimConfig::from_raw(&common::SimConfigRaw {
+            rate: Some(60),
+            ..Default::default()
+        });
+        let mut graph = Graph::new(1);
+        common::node::populate_fresh_nodes(&mut graph);
+        let input = CharacterInput {
+            movement: common::proto::MovementInput::new(na::Vector3::x() * 0.01),
+            jump: false,
+            no_clip: true,
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        };
+        let mut pred = PredictedMotion::new(pos());
+        for _ in 0..2 * REPLAY_BUDGET {
+            pred.push(&cfg, &graph, &input);
+        }
+        pred.reconcile(1, pos(), na::Vector3::zeros(), false);
+        while pred.advance_replay(&cfg, &graph) > 0 {}
+
+        // Teleported without any further input being processed
+        let destination = Position {
+            node: common::graph::NodeId::ROOT,
+            local: common::math::translate_along(&na::Vector3::new(0.0, 0.3, -0.2)),
+        };
+        pred.teleport(&cfg, &graph, 1, destination, na::Vector3::zeros(), false);
+        assert!(pred.replay.is_none());
+        assert_eq!(pred.log.len(), 2 * REPLAY_BUDGET - 1);
+
+        // The unacknowledged inputs are applied from the destination
+        let mut expected = destination;
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        for _ in 0..pred.log.len() {
+            character_controller::run_character_step(
+                &cfg,
+                &graph,
+                &mut expected,
+                &mut velocity,
+                &mut on_ground,
+                &input,
+                &mut 0.0,
+                cfg.step_interval.as_secs_f32(),
+                None,
+            );
+        }
+        assert_eq!(pred.predicted_position().node, expected.node);
+        assert_eq!(pred.predicted_position().local, expected.local);
+    }
+
+    #[test]
+    fn corrections_classified_by_frontier() {
+        let cfg = SimConfig::from_raw(&common::SimConfigRaw::default());
+        // No chunks are ever generated, so walking is held back wherever it starts
+        let mut graph = Graph::new(1);
+        common::node::populate_fresh_nodes(&mut graph);
+        let walking = CharacterInput {
+            movement: common::proto::MovementInput::new(na::Vector3::x()),
+            jump: false,
+            no_clip: false,
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        };
+        let flying = CharacterInput {
+            no_clip: true,
+            ..walking.clone()
+        };
+        let mut pred = PredictedMotion::new(pos());
+        let reconcile = |pred: &mut PredictedMotion, generation, position| {
+            pred.reconcile(generation, position, na::Vector3::zeros(), false);
+            while pred.advance_replay(&cfg, &graph) > 0 {}
+            pred.take_correction()
+        };
+
+        // Flying touches no chunks
+        pred.push(&cfg, &graph, &flying);
+        pred.push(&cfg, &graph, &flying);
+        assert_eq!(reconcile(&mut pred, 1, pos()), Some(Correction::Ordinary));
+        assert_eq!(pred.take_correction(), None);
+        assert_eq!(pred.frontier_holds(), 0);
+
+        // The server, with chunks to walk on, let the character carry on
+        pred.push(&cfg, &graph, &walking);
+        assert_eq!(pred.frontier_holds(), 1);
+        let moved = Position {
+            node: common::graph::NodeId::ROOT,
+            local: common::math::translate_along(&na::Vector3::new(0.05, 0.0, 0.0)),
+        };
+        assert_eq!(reconcile(&mut pred, 3, moved), Some(Correction::Frontier));
+
+        // Nothing replayed from there was held back, so the next correction is ordinary again
+        pred.push(&cfg, &graph, &flying);
+        assert_eq!(reconcile(&mut pred, 4, moved), Some(Correction::Ordinary));
+        assert_eq!(pred.frontier_holds(), 1);
+    }
+
+    #[test]
+    fn traces_pushed_steps() {
+        let cfg = SimConfig::from_raw(&common::SimConfigRaw::default());
+        let mut graph = Graph::new(1);
+        common::node::populate_fresh_nodes(&mut graph);
+        let input = CharacterInput {
+            movement: common::proto::MovementInput::new(na::Vector3::x()),
+            jump: false,
+            no_clip: true,
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        };
+        let mut pred = PredictedMotion::new(pos());
+        pred.push(&cfg, &graph, &input);
+        assert!(pred.collision_trace().is_none());
+
+        pred.trace_collisions(2);
+        for _ in 0..3 {
+            pred.push(&cfg, &graph, &input);
+        }
+        let trace = pred.collision_trace().unwrap();
+        assert_eq!(trace.steps().len(), 2);
+        let latest = trace.steps().last().unwrap();
+        assert_eq!(latest.end.position.local, pred.predicted_position().local);
+
+        pred.trace_collisions(0);
+        assert!(pred.collision_trace().is_none());
+    }
 }
@@ -0,0 +1,32 @@
+//! Naming of the files the client keeps separately for each server
+
+/// Name of a file kept for `server`, with the given extension
+pub fn per_server_file_name(server: &str, extension: &str) -> String {
+    // Readable, but distinct for servers whose names differ only in characters that can't appear
+    // in file names
+    let readable = server
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    format!("{readable}-{:016x}.{extension}", fxhash::hash64(server))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_differ_where_sanitized_names_collide() {
+        let a = per_server_file_name("localhost:1234", "toml");
+        let b = per_server_file_name("localhost/1234", "toml");
+        assert!(a.starts_with("localhost_1234-"));
+        assert!(a.ends_with(".toml"));
+        assert_ne!(a, b);
+    }
+}
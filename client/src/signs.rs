@@ -0,0 +1,219 @@
+//! Placement of the text of signs on the faces of their blocks
+
+use common::{
+    block_entity::{Sign, MAX_SIGN_LINE_LEN, SIGN_LINES},
+    graph::Graph,
+    math,
+    node::{ChunkId, CoordAxis, CoordDirection, Coords},
+};
+
+use crate::sim::height_gradient;
+
+/// Height of a sign's letters as a fraction of the width of a voxel, leaving a margin beside the
+/// longest lines
+const SIGN_LETTER_HEIGHT: f32 = 0.9 / MAX_SIGN_LINE_LEN as f32;
+/// Distance between the baselines of a sign's lines, in letters
+const SIGN_LINE_SPACING: f32 = 1.25;
+/// Distance of a sign's text out from its face as a fraction of the width of a voxel, so that it
+/// isn't hidden by the face itself
+const SIGN_OFFSET: f32 = 0.01;
+/// Distance in meters beyond which signs aren't drawn
+pub const SIGN_DRAW_DISTANCE: f32 = 16.0;
+/// Distance in meters over which signs fade out on their way to `SIGN_DRAW_DISTANCE`
+const SIGN_FADE_DISTANCE: f32 = 4.0;
+
+/// A sign near enough to be drawn
+pub struct VisibleSign {
+    /// Transform from the sign's text frame, in which each letter is a unit square and the text is
+    /// centered on the origin, to the frame of the view's node
+    pub frame: na::Matrix4<f32>,
+    pub lines: [String; SIGN_LINES],
+    /// Opacity of the text, falling to nothing as the sign nears the draw distance
+    pub opacity: f32,
+}
+
+impl VisibleSign {
+    /// Where `sign`, on the voxel at `coords` in `chunk`, is drawn as seen from `eye`, if it can be
+    /// read from there
+    ///
+    /// `transform` maps the frame of `chunk`'s node into that of the view's node, in which `eye`
+    /// lies. Signs are drawn only from in front, and only out to `SIGN_DRAW_DISTANCE`.
+    pub fn new(
+        graph: &Graph,
+        chunk: ChunkId,
+        coords: Coords,
+        sign: &Sign,
+        transform: &na::Matrix4<f32>,
+        eye: &na::Vector4<f32>,
+        meters_to_absolute: f32,
+    ) -> Option<Self> {
+        if sign.lines.iter().all(String::is_empty) {
+            return None;
+        }
+        let frame = transform * face_frame(graph, chunk, coords, sign.face)?;
+        // Seen from behind, the text would be mirrored, and the block is in the way anyway
+        let in_text_frame = frame.try_inverse()? * eye;
+        if in_text_frame.z * in_text_frame.w <= 0.0 {
+            return None;
+        }
+        let distance = math::distance(eye, &math::lorentz_normalize(&(frame * math::origin())));
+        let opacity = opacity(distance / meters_to_absolute);
+        if opacity <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            frame,
+            lines: sign.lines.clone(),
+            opacity,
+        })
+    }
+}
+
+/// Vertical offset in letters of line `i` of a sign from the center of its text
+pub fn line_offset(i: usize) -> f32 {
+    (0.5 * (SIGN_LINES - 1) as f32 - i as f32) * SIGN_LINE_SPACING
+}
+
+/// Opacity of a sign `meters` away
+fn opacity(meters: f32) -> f32 {
+    ((SIGN_DRAW_DISTANCE - meters) / SIGN_FADE_DISTANCE).clamp(0.0, 1.0)
+}
+
+/// Transform from the text frame of a sign on `face` of the voxel at `coords` in `chunk` to the
+/// frame of its node, if the node is known
+///
+/// The text lies on the face just off the block, upright if the face is a wall and otherwise
+/// aligned with the chunk's grid, reading left to right when seen from outside.
+fn face_frame(
+    graph: &Graph,
+    chunk: ChunkId,
+    coords: Coords,
+    (axis, direction): (CoordAxis, CoordDirection),
+) -> Option<na::Matrix4<f32>> {
+    let gradient = height_gradient(graph, chunk, coords)?;
+    let [a, b] = axis.other_axes();
+    let up_axis = if gradient[b as usize].abs() > gradient[a as usize].abs() {
+        b
+    } else {
+        a
+    };
+    let mut up = na::Vector3::zeros();
+    up[up_axis as usize] = if gradient[up_axis as usize] < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    let mut normal = na::Vector3::zeros();
+    normal[axis as usize] = f32::from(direction as i8);
+    let mut right = up.cross(&normal);
+    if chunk.vertex.parity() {
+        // The chunk's grid is mirrored in its node, and would mirror the text too
+        right = -right;
+    }
+
+    let center =
+        na::Vector3::from(coords.0.map(|x| f32::from(x) + 0.5)) + normal * (0.5 + SIGN_OFFSET);
+    let text_to_grid = na::Matrix4::from_columns(&[
+        (right * SIGN_LETTER_HEIGHT).push(0.0),
+        (up * SIGN_LETTER_HEIGHT).push(0.0),
+        (normal * SIGN_LETTER_HEIGHT).push(0.0),
+        center.push(1.0),
+    ]);
+    let grid_to_dual = na::Matrix4::new_scaling(1.0 / graph.layout().dual_to_grid_factor());
+    Some(chunk.vertex.dual_to_node_f32() * grid_to_dual * text_to_grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{dodeca::Vertex, graph::NodeId, node::populate_fresh_nodes};
+
+    fn sign(face: (CoordAxis, CoordDirection)) -> Sign {
+        Sign {
+            lines: ["hello".into(), String::new(), String::new(), String::new()],
+            face,
+        }
+    }
+
+    /// A point `distance` voxels out from the middle of `face` of the voxel `coords`, in the frame
+    /// of its chunk's node
+    fn in_front(
+        graph: &Graph,
+        chunk: ChunkId,
+        coords: Coords,
+        (axis, direction): (CoordAxis, CoordDirection),
+        distance: f32,
+    ) -> na::Vector4<f32> {
+        let mut grid = na::Vector3::from(coords.0.map(|x| f32::from(x) + 0.5));
+        grid[axis as usize] += (0.5 + distance) * f32::from(direction as i8);
+        let dual = grid / graph.layout().dual_to_grid_factor();
+        math::lorentz_normalize(&(chunk.vertex.dual_to_node_f32() * dual.push(1.0)))
+    }
+
+    #[test]
+    fn text_reads_from_outside() {
+        let mut graph = Graph::new(12);
+        populate_fresh_nodes(&mut graph);
+        let coords = Coords([5, 6, 7]);
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(NodeId::ROOT, vertex);
+            for axis in CoordAxis::iter() {
+                for direction in CoordDirection::iter() {
+                    let face = (axis, direction);
+                    let frame = face_frame(&graph, chunk, coords, face).unwrap();
+                    // Not mirrored, whatever the parity of the chunk
+                    assert!(frame.determinant() > 0.0, "{vertex:?} {face:?}");
+
+                    let identity = na::Matrix4::identity();
+                    let front = in_front(&graph, chunk, coords, face, 2.0);
+                    let behind = in_front(&graph, chunk, coords, face, -3.0);
+                    let visible = |eye| {
+                        VisibleSign::new(&graph, chunk, coords, &sign(face), &identity, &eye, 1.0)
+                            .is_some()
+                    };
+                    assert!(visible(front), "{vertex:?} {face:?}");
+                    assert!(!visible(behind), "{vertex:?} {face:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blank_signs_skipped() {
+        let mut graph = Graph::new(12);
+        populate_fresh_nodes(&mut graph);
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let face = (CoordAxis::X, CoordDirection::Plus);
+        let blank = Sign {
+            lines: Default::default(),
+            face,
+        };
+        let eye = in_front(&graph, chunk, Coords([0; 3]), face, 2.0);
+        let sign = VisibleSign::new(
+            &graph,
+            chunk,
+            Coords([0; 3]),
+            &blank,
+            &na::Matrix4::identity(),
+            &eye,
+            1.0,
+        );
+        assert!(sign.is_none());
+    }
+
+    #[test]
+    fn fading() {
+        assert_eq!(opacity(0.0), 1.0);
+        assert_eq!(opacity(SIGN_DRAW_DISTANCE - SIGN_FADE_DISTANCE), 1.0);
+        assert!((opacity(SIGN_DRAW_DISTANCE - 0.5 * SIGN_FADE_DISTANCE) - 0.5).abs() < 1e-6);
+        assert_eq!(opacity(SIGN_DRAW_DISTANCE), 0.0);
+        assert_eq!(opacity(1e3), 0.0);
+    }
+
+    #[test]
+    fn lines_centered() {
+        let offsets = (0..SIGN_LINES).map(line_offset).collect::<Vec<_>>();
+        assert!(offsets.windows(2).all(|w| w[0] > w[1]), "first line on top");
+        assert!(offsets.iter().sum::<f32>().abs() < 1e-6);
+    }
+}
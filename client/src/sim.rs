@@ -1,38 +1,187 @@
-use std::time::Duration;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
 use hecs::Entity;
-use tracing::{debug, error, trace};
+use tracing::{debug, error, info, trace, warn};
 
 use crate::{
-    local_character_controller::LocalCharacterController, net, prediction::PredictedMotion, Net,
+    audio::{self, Playback, SoundQueue},
+    block_prediction::PredictedBlocks,
+    breadcrumbs::Breadcrumb,
+    camera::{self, Camera, CameraConfig},
+    characters::{body_orientation, VisibleCharacter},
+    connection_quality::{ConnectionQuality, QualityMonitor},
+    deferred::{DeferredUpdate, DeferredUpdates, EDIT_EXPIRY_STEPS},
+    extrapolation::{extrapolate, UpdateTiming},
+    follow::Follow,
+    graphics::Frustum,
+    local_character_controller::LocalCharacterController,
+    net::{self, ConnectionState, OutgoingStats, Queued, ReceivedStats},
+    observer::Observer,
+    pending_nodes::{PendingNodes, DEFAULT_RESYNC_STEPS},
+    prediction::{Correction, PredictedMotion},
+    signs::VisibleSign,
+    view_model::Gesture,
+    world_clock::WorldClock,
+    Net,
 };
 use common::{
-    character_controller,
+    block_entity::{validate_sign_text, BlockEntities, BlockEntity, SIGN_LINES},
+    character_controller::{self, TraceDump},
     collision_math::Ray,
+    dodeca,
     graph::{Graph, NodeId},
-    graph_ray_casting,
-    node::{populate_fresh_nodes, ChunkId, VoxelData},
+    graph_ray_casting::{self, GraphCastHit, OutOfBounds},
+    inventory::Inventory,
+    logging::{chunk_span, edit_span, entity_span, step_span, EditId},
+    math,
+    mem_budget::{self, MemReport},
+    node::{
+        populate_fresh_nodes, populate_with_dependencies, BlockUpdateOutcome, ChunkId, CoordAxis,
+        CoordDirection, Coords, PopulationQueue, VoxelData,
+    },
+    node_path::NodePath,
+    placement,
+    protection::ProtectedRegion,
     proto::{
-        self, BlockUpdate, Character, CharacterInput, CharacterState, Command, Component, Position,
+        self, BlockFill, BlockUpdate, Capabilities, Character, CharacterInput, CharacterState,
+        ChunkDiff, ClientMessage, Command, Component, FillContents, InteractTarget, MovementInput,
+        MovementModes, NodeInterestHint, Position, RejectionReason, SerializableVoxelData,
+        SoundKind, MAX_NODE_HINT_MARGIN,
     },
     sanitize_motion_input,
-    world::Material,
-    EntityId, GraphEntities, SimConfig, Step,
+    template::{Orientation, Template},
+    traversal::{
+        ensure_nearby, nearby_nodes, nearby_nodes_cached, nearest_missing_node, RayTraverser,
+        TransformCache,
+    },
+    waypoint::Waypoint,
+    world::{Material, Shape},
+    EntityId, GraphEntities, SimConfig, Step, ENTITY_ID_REUSE_DELAY,
 };
 
+/// Most recently despawned entities to remember, in case the server sends an unusual number of
+/// despawns at once
+const MAX_TOMBSTONES: usize = 4096;
+
+/// Number of messages from the server that can't be reconciled with what it sent before, after
+/// which the connection is abandoned rather than carrying on with a corrupt view of the world
+const MAX_PROTOCOL_ERRORS: u32 = 3;
+
+/// Distance around the local character within which new nodes are populated immediately rather
+/// than waiting their turn, covering every node it might move into before the next frame
+const URGENT_POPULATION_DISTANCE: f64 = 1.5 * dodeca::BOUNDING_SPHERE_RADIUS_F64;
+
+/// Shortest time between asks for the server to extend the graph ahead of the view
+const NODE_HINT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long the player is told that the character they followed was lost
+const FOLLOW_LOST_NOTICE: Duration = Duration::from_secs(5);
+
+/// Step at which the server reported an entity's spawn
+struct SpawnStep(Step);
+
+/// Material of a thrown block in flight
+struct Thrown(Material);
+
+/// The nearest thing under a point on the screen
+#[derive(Debug)]
+pub enum PickResult {
+    Block(GraphCastHit),
+    Entity { id: EntityId, tanh_distance: f32 },
+}
+
+/// How far along the client is in joining the world
+///
+/// A `Sim` is only made once the server has said hello, so before then the client is still
+/// connecting and has no session at all.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Session {
+    /// Waiting for the server to spawn the local character, so nobody knows where it is yet
+    AwaitingSpawn,
+    /// Controlling the local character
+    Playing { entity: Entity },
+    /// Watching through a camera flying free of the local character, which stands still
+    Spectating { entity: Entity },
+    /// The connection is gone, leaving the world as it was last seen, if the local character ever
+    /// appeared in it
+    Disconnected { entity: Option<Entity> },
+}
+
+impl Session {
+    /// The local character, if it exists
+    pub fn character(self) -> Option<Entity> {
+        match self {
+            Session::AwaitingSpawn => None,
+            Session::Playing { entity } | Session::Spectating { entity } => Some(entity),
+            Session::Disconnected { entity } => entity,
+        }
+    }
+
+    /// Whether input is predicted and sent to the server
+    fn sends_input(self) -> bool {
+        matches!(self, Session::Playing { .. } | Session::Spectating { .. })
+    }
+}
+
+/// Where the world is seen from
+#[derive(Debug, Copy, Clone)]
+pub enum ViewSource {
+    /// Nowhere yet, so something other than the world should be shown
+    LoadingScreen,
+    World(Position),
+}
+
+impl ViewSource {
+    /// The position the world is seen from, if it's seen at all
+    pub fn position(self) -> Option<Position> {
+        match self {
+            ViewSource::LoadingScreen => None,
+            ViewSource::World(position) => Some(position),
+        }
+    }
+}
+
 /// Game state
 pub struct Sim {
     // World state
     pub graph: Graph,
-    pub pending_modified_chunks: FxHashMap<ChunkId, Vec<BlockUpdate>>,
+    /// Nodes received from the server but not yet populated
+    population: PopulationQueue,
+    /// Nodes received from the server before their parents
+    pending_nodes: PendingNodes,
+    /// Steps a node may wait for its parent before the server is asked for it again
+    node_resync_steps: Step,
+    /// Time since the server was last asked to extend the graph ahead of the view
+    since_node_hint: Duration,
+    /// Transforms of nodes relative to the view's node
+    node_transforms: TransformCache,
+    /// Changes from the server to chunks that haven't been generated yet, applied once they are
+    pending_modified_chunks: FxHashMap<ChunkId, Vec<(Coords, Material, Shape)>>,
+    /// Spawns and edits received from the server before the nodes they're in
+    deferred: DeferredUpdates,
     pub graph_entities: GraphEntities,
     entity_ids: FxHashMap<EntityId, Entity>,
+    /// IDs of entities despawned within `ENTITY_ID_REUSE_DELAY`, with the step they were despawned
+    tombstones: FxHashMap<EntityId, Step>,
     pub world: hecs::World,
     pub cfg: SimConfig,
     pub local_character_id: EntityId,
-    pub local_character: Option<Entity>,
+    /// How far along joining the world the client is
+    session: Session,
+    /// Step of the latest `StateDelta`
     step: Option<Step>,
+    world_clock: WorldClock,
+    /// Latest inventory reported by the server
+    inventory: Inventory,
+    /// Highest input generation incorporated into `inventory`
+    inventory_generation: u16,
+    /// Movement modes the server allows the local character
+    allowed_movement_modes: MovementModes,
 
     // Input state
     since_input_sent: Duration,
@@ -45,6 +194,12 @@ pub struct Sim {
     ///
     /// Units are relative to movement speed.
     average_movement_input: na::Vector3<f32>,
+    /// Time for which `movement_input` has been held without being included in
+    /// `average_movement_input`
+    ///
+    /// Input is only folded into the average when it changes or a step ends, so that the inputs
+    /// sent don't depend on how the time they were held for was divided into frames.
+    movement_input_held: Duration,
     no_clip: bool,
     /// Whether no_clip will be toggled next step
     toggle_no_clip: bool,
@@ -58,46 +213,194 @@ pub struct Sim {
     place_block_pressed: bool,
     /// Whether the break-block button has been pressed since the last step
     break_block_pressed: bool,
+    /// Whether the use button has been pressed since the last step
+    use_pressed: bool,
+    /// Whether the throw button has been pressed since the last step
+    throw_pressed: bool,
+    /// Material to place when placing blocks
+    selected_material: Material,
+    /// Shape of the blocks to place
+    selected_shape: PlacementShape,
+    /// Fill to request with the next input
+    queued_block_fill: Option<BlockFill>,
+    /// Voxel a drag to fill started from, while the fill button is held
+    fill_drag: Option<(ChunkId, Coords)>,
+    /// Opposite corners of the box to copy as a template, each marked at a block looked at
+    template_corners: [Option<(ChunkId, Coords)>; 2],
+    /// Faces of blocks broken since the last call to `take_broken_faces`
+    broken_faces: Vec<GraphCastHit>,
+    /// Faces that blocks were to be placed against since the last call to `take_obstructed_faces`,
+    /// had a character not been in the way
+    obstructed_faces: Vec<GraphCastHit>,
+    /// What the local character did since the last call to `take_gestures`, for the view model to
+    /// react to
+    gestures: Vec<Gesture>,
+    /// Sounds yet to be played, and those played ahead of the server for the local character
+    sounds: SoundQueue,
+    /// Result of the last call to `target`
+    cached_target: Option<CachedTarget>,
     prediction: PredictedMotion,
+    /// Predicted position as of the step before the latest, which the view is interpolated from
+    previous_predicted_position: Position,
+    /// Whether the view is interpolated between predicted steps rather than extrapolated
+    interpolate_view: bool,
+    block_prediction: PredictedBlocks,
     local_character_controller: LocalCharacterController,
+    /// Camera flying free of the local character, which the view follows while set
+    observer: Option<Observer>,
+    /// Character the observer's camera trails, if any
+    follow: Option<Follow>,
+    /// Distance in absolute units the camera is held behind a character it follows
+    follow_distance: f32,
+    /// Name of the character last followed, if it was lost recently enough to tell the player, and
+    /// for how long they've been told
+    follow_lost: Option<(String, Duration)>,
+    /// Character the server was last asked to tell of the surroundings of in place of the local
+    /// character's
+    follow_requested: Option<String>,
+    /// Number of those asks the server has yet to answer
+    follow_unanswered: u32,
+    /// Where the world is rendered from, smoothly following the view position
+    camera: Camera,
+    /// View position the camera was last updated to follow
+    camera_followed: Option<Position>,
+    /// Position the player is being guided towards
+    waypoint: Option<Breadcrumb>,
+    /// Waypoints the server shares with everyone, by name
+    shared_waypoints: BTreeMap<String, Waypoint>,
+    /// Data the server has attached to individual blocks, such as the text of signs
+    block_entities: BlockEntities,
+    /// Files to write the collision traces requested from the server to, by character
+    pending_trace_dumps: FxHashMap<String, PathBuf>,
+    /// Optional protocol features the server agreed to use
+    capabilities: Capabilities,
+
+    // Connection state
+    connection: ConnectionState,
+    /// How far the server's updates can be trusted to keep the prediction honest
+    quality: QualityMonitor,
+    /// Activity of the outgoing queue as of the latest step
+    outgoing: Option<OutgoingStats>,
+    /// Messages received from the server as of the latest step
+    received: Option<ReceivedStats>,
+    /// Number of messages from the server that contradicted earlier ones, each indicating a bug
+    protocol_errors: u32,
+}
+
+/// Diagnostics describing the connection to the server and memory use
+#[derive(Debug, Clone)]
+pub struct DebugInfo {
+    pub connection: ConnectionState,
+    /// How far the server's updates can be trusted to keep the prediction honest
+    pub quality: ConnectionQuality,
+    /// Smoothed time for input to be acknowledged, once measured
+    pub round_trip: Option<Duration>,
+    /// Activity of the outgoing queue, if a step has run
+    pub outgoing: Option<OutgoingStats>,
+    /// Messages received from the server, if a step has run
+    pub received: Option<ReceivedStats>,
+    /// Whether prediction has stopped for lack of acknowledgements from the server
+    pub prediction_stalled: bool,
+    /// Number of messages from the server that contradicted earlier ones
+    pub protocol_errors: u32,
+    /// Nodes received from the server that are waiting for their parents
+    pub pending_nodes: usize,
+    /// Spawns and edits received from the server that are waiting for their nodes
+    pub deferred_updates: usize,
+    /// Memory held by each pool in the process, including any server running in it
+    pub memory: MemReport,
+    /// Predicted position of the local character
+    pub character: Position,
+    /// Position of the camera flying free of the character, if any
+    pub observer: Option<Position>,
 }
 
 impl Sim {
-    pub fn new(cfg: SimConfig, local_character_id: EntityId) -> Self {
+    pub fn new(cfg: SimConfig, camera: CameraConfig, local_character_id: EntityId) -> Self {
         let mut graph = Graph::new(cfg.chunk_size);
         populate_fresh_nodes(&mut graph);
         Self {
+            world_clock: WorldClock::new(cfg.day_length_seconds),
             graph,
+            population: PopulationQueue::new(),
+            pending_nodes: PendingNodes::default(),
+            node_resync_steps: DEFAULT_RESYNC_STEPS,
+            since_node_hint: Duration::ZERO,
+            node_transforms: TransformCache::new(NodeId::ROOT),
             pending_modified_chunks: FxHashMap::default(),
+            deferred: DeferredUpdates::default(),
             graph_entities: GraphEntities::new(),
             entity_ids: FxHashMap::default(),
+            tombstones: FxHashMap::default(),
             world: hecs::World::new(),
             cfg,
             local_character_id,
-            local_character: None,
+            session: Session::AwaitingSpawn,
             step: None,
+            inventory: Inventory::default(),
+            inventory_generation: 0,
+            allowed_movement_modes: cfg.default_movement_modes,
 
             since_input_sent: Duration::new(0, 0),
             movement_input: na::zero(),
             average_movement_input: na::zero(),
-            no_clip: true,
+            movement_input_held: Duration::ZERO,
+            no_clip: cfg.default_movement_modes.contains(MovementModes::NO_CLIP),
             toggle_no_clip: false,
             is_jumping: false,
             jump_pressed: false,
             jump_held: false,
             place_block_pressed: false,
             break_block_pressed: false,
+            use_pressed: false,
+            throw_pressed: false,
+            selected_material: Material::WoodPlanks,
+            selected_shape: PlacementShape::Full,
+            queued_block_fill: None,
+            fill_drag: None,
+            template_corners: [None; 2],
+            broken_faces: Vec::new(),
+            obstructed_faces: Vec::new(),
+            gestures: Vec::new(),
+            sounds: SoundQueue::new(),
+            cached_target: None,
             prediction: PredictedMotion::new(proto::Position {
                 node: NodeId::ROOT,
                 local: na::one(),
             }),
+            previous_predicted_position: Position::origin(),
+            interpolate_view: camera.interpolate_view,
+            block_prediction: PredictedBlocks::new(),
             local_character_controller: LocalCharacterController::new(),
+            observer: None,
+            follow: None,
+            follow_distance: camera.follow_distance,
+            follow_lost: None,
+            follow_requested: None,
+            follow_unanswered: 0,
+            camera: Camera::new(camera),
+            camera_followed: None,
+            waypoint: None,
+            shared_waypoints: BTreeMap::new(),
+            block_entities: BlockEntities::default(),
+            pending_trace_dumps: FxHashMap::default(),
+            capabilities: Capabilities::ALL,
+
+            connection: ConnectionState::Connected,
+            quality: QualityMonitor::new(&cfg),
+            outgoing: None,
+            received: None,
+            protocol_errors: 0,
         }
     }
 
     /// Rotates the camera's view in a context-dependent manner based on the desired yaw and pitch angles.
     pub fn look(&mut self, delta_yaw: f32, delta_pitch: f32, delta_roll: f32) {
-        if self.no_clip {
+        if let Some(ref mut follow) = self.follow {
+            follow.look(delta_yaw, delta_pitch, delta_roll);
+        } else if let Some(ref mut observer) = self.observer {
+            observer.look(delta_yaw, delta_pitch, delta_roll);
+        } else if self.no_clip {
             self.local_character_controller
                 .look_free(delta_yaw, delta_pitch, delta_roll);
         } else {
@@ -107,6 +410,18 @@ impl Sim {
     }
 
     pub fn set_movement_input(&mut self, mut raw_movement_input: na::Vector3<f32>) {
+        if let Some(ref mut observer) = self.observer {
+            if raw_movement_input.norm_squared() >= 1.0 {
+                raw_movement_input.normalize_mut();
+            }
+            // A camera following a character goes wherever it does
+            observer.set_movement(match self.follow {
+                Some(_) => na::zero(),
+                None => raw_movement_input,
+            });
+            // The character stands still while the camera moves
+            raw_movement_input = na::zero();
+        }
         if !self.no_clip {
             // Vertical movement keys shouldn't do anything unless no-clip is on.
             raw_movement_input.y = 0.0;
@@ -115,14 +430,161 @@ impl Sim {
             // Cap movement input at 1
             raw_movement_input.normalize_mut();
         }
-        self.movement_input = raw_movement_input;
+        if raw_movement_input != self.movement_input {
+            self.accumulate_movement_input();
+            self.movement_input = raw_movement_input;
+        }
+    }
+
+    /// Fold the time `movement_input` has been held for into `average_movement_input`
+    fn accumulate_movement_input(&mut self) {
+        self.average_movement_input += self.movement_input * self.movement_input_held.as_secs_f32()
+            / self.cfg.step_interval.as_secs_f32();
+        self.movement_input_held = Duration::ZERO;
     }
 
-    pub fn toggle_no_clip(&mut self) {
+    /// Toggle no_clip at the start of the next step, returning `false` if the server doesn't
+    /// allow it to be turned on
+    pub fn toggle_no_clip(&mut self) -> bool {
+        if !self.no_clip && !self.allowed_movement_modes.contains(MovementModes::NO_CLIP) {
+            return false;
+        }
         // We prepare to toggle no_clip after the next step instead of immediately, as otherwise,
         // there would be a discontinuity when predicting the player's position within a given step,
         // causing an undesirable jolt.
         self.toggle_no_clip = true;
+        true
+    }
+
+    /// Detach the view from the local character, which stays where it is, or return the view to
+    /// it, returning whether the view is now detached, or `None` if there's no character yet
+    ///
+    /// The character is simulated and predicted as usual all the while, just without movement
+    /// input.
+    pub fn toggle_observer(&mut self) -> Option<bool> {
+        let entity = self.local_character()?;
+        let view = self.view().position()?;
+        self.observer = match self.observer {
+            Some(_) => None,
+            None => Some(Observer::new(view)),
+        };
+        self.follow = None;
+        self.follow_lost = None;
+        if self.session.sends_input() {
+            self.session = match self.observer {
+                Some(_) => Session::Spectating { entity },
+                None => Session::Playing { entity },
+            };
+        }
+        // Smoothing would otherwise carry the camera between the character and the observer
+        self.camera.reset();
+        Some(self.observer.is_some())
+    }
+
+    /// Trail the character named `name` with the observer's camera, facing wherever it does if
+    /// `locked` or orbiting it freely otherwise, returning whether there's such a character
+    ///
+    /// A server offering `Capabilities::FOLLOW` is asked to tell of everything around the
+    /// character as it would around the local one, and the camera falls back to flying freely if it
+    /// refuses or the character is lost.
+    pub fn follow(&mut self, name: String, locked: bool) -> bool {
+        let local = self.local_character();
+        let Some((target, facing)) = self
+            .world
+            .query::<&Character>()
+            .iter()
+            .find(|&(entity, ch)| ch.name == name && Some(entity) != local)
+            .map(|(entity, ch)| (entity, ch.state.orientation))
+        else {
+            return false;
+        };
+        if self.observer.is_none() && self.toggle_observer() != Some(true) {
+            return false;
+        }
+        info!(%name, "following");
+        self.follow = Some(Follow::new(target, name, facing, locked));
+        self.follow_lost = None;
+        // Smoothing would otherwise carry the camera across the gap
+        self.camera.reset();
+        true
+    }
+
+    /// Stop trailing a character, leaving the observer's camera to fly freely from where it is,
+    /// returning whether one was followed
+    pub fn unfollow(&mut self) -> bool {
+        self.follow.take().is_some()
+    }
+
+    /// Switch between the camera following a character facing wherever it does and orbiting it
+    /// freely, returning whether it's now locked, or `None` if no character is followed
+    pub fn toggle_follow_lock(&mut self) -> Option<bool> {
+        let follow = self.follow.as_mut()?;
+        let facing = self
+            .world
+            .get::<&Character>(follow.target)
+            .ok()?
+            .state
+            .orientation;
+        Some(follow.toggle_lock(facing))
+    }
+
+    /// Name of the character the observer's camera trails, if any
+    pub fn followed(&self) -> Option<&str> {
+        self.follow.as_ref().map(|x| &x.name[..])
+    }
+
+    /// Name of the character last followed, if it was lost recently
+    pub fn follow_lost(&self) -> Option<&str> {
+        self.follow_lost.as_ref().map(|(name, _)| &name[..])
+    }
+
+    /// Fall back to flying freely from wherever the camera is, telling the player why
+    fn lose_follow(&mut self) {
+        let Some(follow) = self.follow.take() else {
+            return;
+        };
+        warn!(name = %follow.name, "lost the character followed");
+        self.follow_lost = Some((follow.name, Duration::ZERO));
+    }
+
+    /// Where to watch the character followed from, if it's where it can be seen
+    fn followed_view(&self) -> Option<Position> {
+        let follow = self.follow.as_ref()?;
+        let mut q = self
+            .world
+            .query_one::<(&Position, &Character, Option<&UpdateTiming>)>(follow.target)
+            .ok()?;
+        let (&position, ch, timing) = q.get()?;
+        let position = self.drawn_position(follow.target, position, &ch.state.velocity, timing);
+        Some(follow.view(
+            &self.graph,
+            &position,
+            &ch.state.orientation,
+            self.follow_distance,
+            self.cfg.character.character_radius,
+        ))
+    }
+
+    /// Ask the server to tell of the surroundings of the character followed, or of the local
+    /// character's again, unless it was last asked to already
+    fn request_follow(&mut self, net: &mut Net) {
+        if !self.capabilities.contains(Capabilities::FOLLOW) || !self.session.sends_input() {
+            return;
+        }
+        let name = self.follow.as_ref().map(|x| x.name.clone());
+        if name == self.follow_requested {
+            return;
+        }
+        if net
+            .outgoing
+            .send(ClientMessage::Follow(name.clone()))
+            .is_err()
+        {
+            warn!("can't follow: connection closed");
+            return;
+        }
+        self.follow_requested = name;
+        self.follow_unanswered += 1;
     }
 
     pub fn set_jump_held(&mut self, jump_held: bool) {
@@ -142,12 +604,529 @@ impl Sim {
         self.break_block_pressed = true;
     }
 
+    /// Ask the server to fill a box spanning `extents` voxels with `material`, from the block the
+    /// character is looking at, or from the space in front of it if `material` isn't void
+    pub fn fill(&mut self, extents: [u32; 3], material: Material) {
+        let Some((chunk_id, coords)) = self.fill_anchor(material) else {
+            warn!("can't fill: no block in reach");
+            return;
+        };
+        self.queue_fill(BlockFill {
+            chunk_id,
+            coords,
+            extents,
+            contents: FillContents::Material(material),
+            sequence: self.block_prediction.next_sequence(),
+        });
+    }
+
+    /// Start dragging out a box to fill with the selected material when `held` is set, or
+    /// request the fill the drag describes when it isn't
+    pub fn set_fill_held(&mut self, held: bool) {
+        if held {
+            if self.fill_drag.is_none() {
+                self.fill_drag = self.fill_anchor(self.selected_material);
+            }
+            return;
+        }
+        let preview = self.fill_preview();
+        self.fill_drag = None;
+        if let Some(block_fill) = preview {
+            self.queue_fill(block_fill);
+        }
+    }
+
+    /// The fill that releasing the fill button would request, spanning from where the drag
+    /// started to where the character is now looking
+    ///
+    /// Drags are confined to the chunk they started in, where which way each axis runs is plain.
+    pub fn fill_preview(&mut self) -> Option<BlockFill> {
+        let (chunk_id, start) = self.fill_drag?;
+        let (chunk, end) = self.fill_anchor(self.selected_material)?;
+        if chunk != chunk_id {
+            return None;
+        }
+        Some(BlockFill {
+            chunk_id,
+            coords: Coords([0, 1, 2].map(|i| start.0[i].min(end.0[i]))),
+            extents: [0, 1, 2].map(|i| u32::from(start.0[i].abs_diff(end.0[i])) + 1),
+            contents: FillContents::Material(self.selected_material),
+            sequence: self.block_prediction.next_sequence(),
+        })
+    }
+
+    /// The voxel a fill of `material` starts from: the one looked at when breaking, or the space
+    /// in front of it when placing
+    fn fill_anchor(&mut self, material: Material) -> Option<(ChunkId, Coords)> {
+        if self.observer.is_some() {
+            // The camera can fly well beyond the character's reach
+            return None;
+        }
+        let hit = self.target().ok().flatten()?;
+        if material == Material::Void {
+            return Some((hit.chunk, hit.voxel_coords));
+        }
+        self.graph.get_block_neighbor(
+            hit.chunk,
+            hit.voxel_coords,
+            hit.face_axis,
+            hit.face_direction,
+        )
+    }
+
+    /// Mark the block looked at as corner `index`, 0 or 1, of the box to copy as a template,
+    /// returning whether there was one in reach
+    pub fn mark_template_corner(&mut self, index: usize) -> bool {
+        let corner = self.fill_anchor(Material::Void);
+        self.template_corners[index] = corner;
+        corner.is_some()
+    }
+
+    /// Copy the box between the marked corners as a template, with up taken from the terrain at
+    /// its anchor
+    ///
+    /// As with drags to fill, both corners must be in the same chunk.
+    pub fn copy_template(&self) -> Option<Template> {
+        let [Some((chunk, a)), Some((other, b))] = self.template_corners else {
+            warn!("can't copy template: mark both corners first");
+            return None;
+        };
+        if chunk != other {
+            warn!("can't copy template: corners must be in the same chunk");
+            return None;
+        }
+        let anchor = Coords([0, 1, 2].map(|i| a.0[i].min(b.0[i])));
+        let extents = [0, 1, 2].map(|i| u32::from(a.0[i].abs_diff(b.0[i])) + 1);
+        let Some(up) = voxel_up(&self.graph, chunk, anchor) else {
+            warn!("can't copy template: its node is no longer known");
+            return None;
+        };
+        match self
+            .graph
+            .copy_box((chunk, anchor), extents, up, Template::MAX_VOLUME)
+        {
+            Ok(template) => Some(template),
+            Err(e) => {
+                warn!("can't copy template: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Ask the server to paste `template` into the space in front of the block looked at, turned
+    /// as `orientation` says
+    pub fn paste_template(&mut self, template: Template, orientation: PasteOrientation) {
+        let Some((chunk_id, coords)) = self.fill_anchor(Material::Dirt) else {
+            warn!("can't paste template: no block in reach");
+            return;
+        };
+        let orientation = match orientation {
+            PasteOrientation::Upright { quarter_turns } => {
+                let Some(up) = voxel_up(&self.graph, chunk_id, coords) else {
+                    warn!("can't paste template: no block in reach");
+                    return;
+                };
+                Orientation::upright(template.up(), up, quarter_turns)
+            }
+            PasteOrientation::Exact(orientation) => orientation,
+        };
+        self.queue_fill(BlockFill {
+            chunk_id,
+            coords,
+            extents: template.pasted_extents(orientation),
+            contents: FillContents::Template {
+                template: Box::new(template),
+                orientation,
+            },
+            sequence: self.block_prediction.next_sequence(),
+        });
+    }
+
+    /// Send `block_fill`, as numbered by `fill_preview`, `fill`, or `paste_template`, with the next
+    /// input, unless the connection is too unreliable for edits
+    fn queue_fill(&mut self, block_fill: BlockFill) {
+        if !self.quality.get().allows_edits() {
+            warn!("can't fill: connection degraded");
+            return;
+        }
+        let sequence = self.block_prediction.skip_sequence();
+        debug_assert_eq!(block_fill.sequence, sequence);
+        let edit = EditId {
+            author: self.local_character_id,
+            sequence,
+        };
+        debug!(%edit, extents = ?block_fill.extents, "requesting block fill");
+        self.queued_block_fill = Some(block_fill);
+    }
+
+    pub fn set_use_pressed_true(&mut self) {
+        self.use_pressed = true;
+    }
+
+    pub fn set_throw_pressed_true(&mut self) {
+        self.throw_pressed = true;
+    }
+
+    /// Select the next material in the inventory to place, in material order
+    pub fn cycle_selected_material(&mut self) {
+        let inventory = self.predicted_inventory();
+        let next = inventory
+            .iter()
+            .find(|&(material, _)| material > self.selected_material)
+            .or_else(|| inventory.iter().next());
+        if let Some((material, _)) = next {
+            self.selected_material = material;
+        }
+    }
+
+    pub fn selected_material(&self) -> Material {
+        self.selected_material
+    }
+
+    /// Select the next shape of block to place
+    pub fn cycle_selected_shape(&mut self) {
+        self.selected_shape = match self.selected_shape {
+            PlacementShape::Full => PlacementShape::Slab,
+            PlacementShape::Slab => PlacementShape::Stairs,
+            PlacementShape::Stairs => PlacementShape::Full,
+        };
+    }
+
+    pub fn selected_shape(&self) -> PlacementShape {
+        self.selected_shape
+    }
+
+    pub fn waypoint(&self) -> Option<&Breadcrumb> {
+        self.waypoint.as_ref()
+    }
+
+    pub fn set_waypoint(&mut self, waypoint: Option<Breadcrumb>) {
+        self.waypoint = waypoint;
+    }
+
+    /// The server's latest inventory, less materials to be spent by placements and throws it
+    /// hasn't processed yet
+    ///
+    /// Placements and throws the server rejects stop being subtracted once it reports having
+    /// processed them, so that the prediction rolls back.
+    pub fn predicted_inventory(&self) -> Inventory {
+        let mut inventory = self.inventory.clone();
+        for input in self.prediction.inputs_since(self.inventory_generation) {
+            if let Some(ref block_update) = input.block_update {
+                if block_update.new_material != Material::Void {
+                    inventory.try_remove(block_update.new_material);
+                }
+            }
+            if let Some(material) = input.throw {
+                inventory.try_remove(material);
+            }
+        }
+        inventory
+    }
+
+    /// Whether the server has stopped acknowledging input for long enough that local motion is no
+    /// longer predicted
+    pub fn connection_problem(&self) -> bool {
+        self.quality.get() == ConnectionQuality::Stalled
+            || self.prediction.is_stalled()
+            || self.connection != ConnectionState::Connected
+    }
+
+    /// How far the server's updates can be trusted to keep the prediction honest, as of the latest
+    /// step
+    pub fn connection_quality(&self) -> ConnectionQuality {
+        self.quality.get()
+    }
+
+    pub fn debug_info(&self) -> DebugInfo {
+        DebugInfo {
+            connection: self.connection,
+            quality: self.quality.get(),
+            round_trip: self.quality.round_trip(),
+            outgoing: self.outgoing,
+            received: self.received.clone(),
+            prediction_stalled: self.prediction.is_stalled(),
+            protocol_errors: self.protocol_errors,
+            pending_nodes: self.pending_nodes.len(),
+            deferred_updates: self.deferred.len(),
+            memory: mem_budget::report(),
+            character: *self.prediction.predicted_position(),
+            observer: self.observer.as_ref().map(Observer::view),
+        }
+    }
+
     pub fn cfg(&self) -> &SimConfig {
         &self.cfg
     }
 
+    pub fn session(&self) -> Session {
+        self.session
+    }
+
+    /// The local character, once the server has spawned it
+    pub fn local_character(&self) -> Option<Entity> {
+        self.session.character()
+    }
+
+    /// Give up on the connection, which the caller has found to be lost
+    pub fn connection_lost(&mut self) {
+        self.update_connection_state(ConnectionState::Closed);
+    }
+
+    /// Limit requests to the optional protocol features negotiated with the server, which are
+    /// taken to be all of them until this is called
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Wait `steps` steps for the parent of a node before asking the server for it again
+    pub fn set_node_resync_steps(&mut self, steps: Step) {
+        self.node_resync_steps = steps;
+    }
+
+    /// Fraction of the day/night cycle elapsed, smoothed between updates from the server
+    pub fn world_time(&self) -> f32 {
+        self.world_clock.get()
+    }
+
+    /// Ask the server to jump to a point in the day/night cycle
+    pub fn set_world_time(&self, fraction: f32, net: &mut Net) {
+        let msg = ClientMessage::SetWorldTime(fraction.rem_euclid(1.0));
+        if net.outgoing.send(msg).is_err() {
+            warn!("can't set world time: connection closed");
+        }
+    }
+
+    /// Waypoints the server shares with everyone, in order of name
+    pub fn shared_waypoints(&self) -> impl Iterator<Item = &Waypoint> {
+        self.shared_waypoints.values()
+    }
+
+    /// Ask the server to share `waypoint` with everyone, replacing any of the same name
+    pub fn share_waypoint(&self, waypoint: Waypoint, net: &mut Net) {
+        if !self.capabilities.contains(Capabilities::SHARED_WAYPOINTS) {
+            warn!("can't share waypoint: the server doesn't support shared waypoints");
+            return;
+        }
+        if net
+            .outgoing
+            .send(ClientMessage::SetWaypoint(waypoint))
+            .is_err()
+        {
+            warn!("can't share waypoint: connection closed");
+        }
+    }
+
+    /// Ask the server to stop sharing the waypoint called `name`
+    pub fn unshare_waypoint(&self, name: String, net: &mut Net) {
+        if !self.capabilities.contains(Capabilities::SHARED_WAYPOINTS) {
+            warn!("can't remove waypoint: the server doesn't support shared waypoints");
+            return;
+        }
+        if net
+            .outgoing
+            .send(ClientMessage::RemoveWaypoint(name))
+            .is_err()
+        {
+            warn!("can't remove waypoint: connection closed");
+        }
+    }
+
+    /// The block entity the server has attached to the voxel at `coords` in `chunk`, if any
+    pub fn block_entity(&self, chunk: ChunkId, coords: Coords) -> Option<&BlockEntity> {
+        self.block_entities.get(chunk, coords)
+    }
+
+    /// Ask the server to write `lines` on the sign the character is looking at
+    pub fn write_sign(&mut self, lines: [String; SIGN_LINES], net: &mut Net) {
+        if self.observer.is_some() {
+            warn!("can't write on a sign while observing");
+            return;
+        }
+        let Some(hit) = self.target().ok().flatten() else {
+            warn!("can't write on a sign: no block in reach");
+            return;
+        };
+        let (chunk, coords) = (hit.chunk, hit.voxel_coords);
+        if self.graph.get_block(chunk, coords) != Some(Material::Sign) {
+            warn!("can't write on a sign: not looking at one");
+            return;
+        }
+        // The server checks the text too, but can't say what's wrong
+        if let Err(e) = validate_sign_text(&lines) {
+            warn!("can't write on the sign: {}", e);
+            return;
+        }
+        let msg = ClientMessage::SetSignText {
+            chunk,
+            coords,
+            lines,
+        };
+        if net.outgoing.send(msg).is_err() {
+            warn!("can't write on the sign: connection closed");
+        }
+    }
+
+    /// Ask the server to protect `region`, replacing any of the same name
+    pub fn protect_region(&self, region: ProtectedRegion, net: &mut Net) {
+        if net
+            .outgoing
+            .send(ClientMessage::SetProtectedRegion(region))
+            .is_err()
+        {
+            warn!("can't protect region: connection closed");
+        }
+    }
+
+    /// Ask the server to lift the protection of the region called `name`
+    pub fn unprotect_region(&self, name: String, net: &mut Net) {
+        if net
+            .outgoing
+            .send(ClientMessage::RemoveProtectedRegion(name))
+            .is_err()
+        {
+            warn!("can't lift protection: connection closed");
+        }
+    }
+
+    /// Ask the server to undo the block changes `rollback` selects
+    pub fn rollback(&self, rollback: proto::Rollback, net: &mut Net) {
+        if net
+            .outgoing
+            .send(ClientMessage::Rollback(rollback))
+            .is_err()
+        {
+            warn!("can't roll back: connection closed");
+        }
+    }
+
+    /// Record how collisions are handled over the latest `steps` steps, or stop recording if `steps`
+    /// is 0, for the local character's predicted movement if `character` is `None`, and otherwise
+    /// by asking the server to record the named character's
+    pub fn trace_collisions(&mut self, character: Option<String>, steps: u32, net: &mut Net) {
+        let Some(character) = character else {
+            self.prediction.trace_collisions(steps as usize);
+            match steps {
+                0 => info!("stopped tracing collisions"),
+                _ => info!("tracing collisions over the latest {} steps", steps),
+            }
+            return;
+        };
+        if net
+            .outgoing
+            .send(ClientMessage::TraceCollisions { character, steps })
+            .is_err()
+        {
+            warn!("can't trace collisions: connection closed");
+        }
+    }
+
+    /// Write the collisions recorded since `trace_collisions` to `path`, for the local character's
+    /// predicted movement if `character` is `None`, and otherwise once the server sends what it's
+    /// recorded of the named character
+    pub fn dump_collision_trace(
+        &mut self,
+        character: Option<String>,
+        path: PathBuf,
+        net: &mut Net,
+    ) {
+        let Some(character) = character else {
+            match self.prediction.collision_trace() {
+                Some(trace) => save_collision_trace(&trace.dump(), &path),
+                None => warn!("collisions aren't being traced"),
+            }
+            return;
+        };
+        if net
+            .outgoing
+            .send(ClientMessage::DumpCollisionTrace(character.clone()))
+            .is_err()
+        {
+            warn!("can't get collision trace: connection closed");
+            return;
+        }
+        self.pending_trace_dumps.insert(character, path);
+    }
+
+    /// Ask the server for the way to `nodes` again, which other nodes have waited too long for
+    fn resync_nodes(&self, nodes: Vec<NodeId>, net: &mut Net) {
+        if !self.capabilities.contains(Capabilities::NODE_RESYNC) {
+            warn!(
+                count = nodes.len(),
+                "missing nodes, which the server doesn't support resending"
+            );
+            return;
+        }
+        warn!(
+            count = nodes.len(),
+            "asking the server to resend missing nodes"
+        );
+        if net
+            .outgoing
+            .send(ClientMessage::ResyncNodes(nodes))
+            .is_err()
+        {
+            warn!("can't request missing nodes: connection closed");
+        }
+    }
+
+    /// Ask the server to write the world to its save now
+    pub fn request_save(&self, net: &mut Net) {
+        if net.outgoing.send(ClientMessage::Save).is_err() {
+            warn!("can't request save: connection closed");
+        }
+    }
+
     pub fn step(&mut self, dt: Duration, net: &mut Net) {
+        let _span = self.step.map(|step| step_span(step).entered());
+        // Until the server says where the local character is, anything populated would as likely as
+        // not be in the wrong part of the world
+        let placed = self.local_character().is_some();
+        if placed {
+            let mut urgent = self.prefetch_nodes();
+            if let Some(ref observer) = self.observer {
+                // The server only extends the graph around characters, so the graph is extended
+                // here around the camera, as far as the server would around a character
+                let view = observer.view();
+                ensure_nearby(&mut self.graph, &view, f64::from(self.cfg.view_distance));
+                self.population.enqueue_fresh(&mut self.graph);
+                urgent.extend(self.nodes_around(&view));
+            }
+            self.population
+                .run(&mut self.graph, urgent, self.cfg.node_population_budget);
+        }
         self.local_character_controller.renormalize_orientation();
+        self.world_clock.advance(dt);
+        if self.protocol_errors >= MAX_PROTOCOL_ERRORS && self.connection != ConnectionState::Closed
+        {
+            error!(
+                count = self.protocol_errors,
+                "disconnecting after too many protocol errors"
+            );
+            net.outgoing.close();
+        }
+        self.update_connection_state(net.outgoing.state());
+        self.outgoing = Some(net.outgoing.stats());
+        self.received = Some(net.received.stats());
+        self.quality.advance(dt);
+        if self.session.sends_input()
+            && self.quality.update(self.prediction.is_stalled()) == ConnectionQuality::Stalled
+        {
+            // Frozen where it stands until the server responds, whereupon its state is adopted.
+            // Prediction stalls on its own once it's retained too much input regardless.
+            self.prediction.suspend();
+        }
+        if let Some(step) = self.step {
+            let overdue = self
+                .pending_nodes
+                .take_overdue(step, self.node_resync_steps);
+            if !overdue.is_empty() {
+                self.resync_nodes(overdue, net);
+            }
+            if self.capabilities.contains(Capabilities::GRAPH_REGIONS) {
+                self.deferred.expire_edits(step, EDIT_EXPIRY_STEPS);
+            }
+        }
 
         let step_interval = self.cfg.step_interval;
         self.since_input_sent += dt;
@@ -158,13 +1137,17 @@ impl Sim {
             // Update average movement input for the time between the last input sample and the end of
             // the previous step. dt > overflow because we check whether a step has elapsed
             // after each increment.
-            self.average_movement_input +=
-                self.movement_input * (dt - overflow).as_secs_f32() / step_interval.as_secs_f32();
+            self.movement_input_held += dt - overflow;
+            self.accumulate_movement_input();
 
-            // Send fresh input
-            self.send_input(net);
+            // Send fresh input, once there's a character for it to move
+            if self.session.sends_input() {
+                self.send_input(net);
+            }
             self.place_block_pressed = false;
             self.break_block_pressed = false;
+            self.use_pressed = false;
+            self.throw_pressed = false;
 
             // Toggle no clip at the start of a new step
             if self.toggle_no_clip {
@@ -172,7 +1155,7 @@ impl Sim {
                 self.toggle_no_clip = false;
             }
 
-            self.is_jumping = self.jump_held || self.jump_pressed;
+            self.is_jumping = (self.jump_held || self.jump_pressed) && self.observer.is_none();
             self.jump_pressed = false;
 
             // Reset state for the next step
@@ -182,140 +1165,680 @@ impl Sim {
                 self.average_movement_input = na::zero();
                 self.since_input_sent = Duration::new(0, 0);
             } else {
-                self.average_movement_input =
-                    self.movement_input * overflow.as_secs_f32() / step_interval.as_secs_f32();
+                self.average_movement_input = na::zero();
+                self.movement_input_held = overflow;
                 // Send the next input a little sooner if necessary to stay in sync
                 self.since_input_sent = overflow;
             }
         } else {
             // Update average movement input for the time within the current step
-            self.average_movement_input +=
-                self.movement_input * dt.as_secs_f32() / step_interval.as_secs_f32();
+            self.movement_input_held += dt;
         }
-        self.update_view_position();
+        if !placed {
+            return;
+        }
+        self.prediction.advance_replay(&self.cfg, &self.graph);
+        if self.prediction.take_correction() == Some(Correction::Frontier) {
+            // The prediction was held back at the edge of the generated world while the server's
+            // character carried on, so easing the view across the gap would only prolong the
+            // stutter
+            trace!("prediction caught up past the generated frontier");
+            self.camera.reset();
+            self.previous_predicted_position = *self.prediction.predicted_position();
+        }
+        let on_ground = self.update_view_position();
         if !self.no_clip {
             self.local_character_controller.align_to_gravity();
         }
+        if let Some((_, ref mut shown)) = self.follow_lost {
+            *shown += dt;
+            if *shown >= FOLLOW_LOST_NOTICE {
+                self.follow_lost = None;
+            }
+        }
+        if self
+            .follow
+            .as_ref()
+            .is_some_and(|x| !self.world.contains(x.target))
+        {
+            // Despawned, as by leaving or moving out of sight
+            self.lose_follow();
+        }
+        if let Some(view) = self.followed_view() {
+            if let Some(ref mut observer) = self.observer {
+                observer.set_view(view);
+            }
+        } else if let Some(ref mut observer) = self.observer {
+            observer.fly(&self.cfg, &self.graph, dt.as_secs_f32());
+        }
+        // Only once prediction is done, so that smoothing never affects the simulation
+        if let ViewSource::World(view) = self.view() {
+            self.camera.update(
+                &self.graph,
+                &view,
+                on_ground && self.observer.is_none(),
+                dt.as_secs_f32(),
+            );
+            self.camera_followed = Some(view);
+        }
+        self.request_follow(net);
+        self.hint_nodes(dt, net);
     }
 
-    pub fn handle_net(&mut self, msg: net::Message) {
-        use net::Message::*;
-        match msg {
-            ConnectionLost(_) | Hello(_) => {
-                unreachable!("Case already handled by caller");
+    /// Ask the server to extend the graph ahead of the view if it's headed for the edge, since the
+    /// server would otherwise only do so once the character has caught up
+    fn hint_nodes(&mut self, dt: Duration, net: &mut Net) {
+        if !self.capabilities.contains(Capabilities::NODE_HINTS) {
+            return;
+        }
+        self.since_node_hint += dt;
+        if self.since_node_hint < NODE_HINT_INTERVAL {
+            return;
+        }
+        let Some(hint) = self.node_hint() else {
+            return;
+        };
+        self.since_node_hint = Duration::ZERO;
+        trace!(radius = hint.radius, "extending graph ahead of view");
+        if net
+            .outgoing
+            .send(ClientMessage::NodeInterestHint(hint))
+            .is_err()
+        {
+            warn!("can't ask for nodes ahead: connection closed");
+        }
+    }
+
+    /// A hint for the graph to reach the view distance from wherever the view will be by the time
+    /// the server could answer, if it doesn't already
+    fn node_hint(&self) -> Option<NodeInterestHint> {
+        let (position, velocity) = match self.observer {
+            Some(ref observer) => (observer.view(), observer.velocity(&self.cfg)),
+            None => {
+                self.local_character()?;
+                (
+                    *self.prediction.predicted_position(),
+                    *self.prediction.predicted_velocity(),
+                )
+            }
+        };
+        // The next hint may be a while yet
+        let lookahead = self.round_trip() + NODE_HINT_INTERVAL;
+        let ahead = extrapolate_within(&self.graph, &position, &velocity, lookahead.as_secs_f32());
+        nearest_missing_node(&self.graph, &ahead, f64::from(self.cfg.view_distance))?;
+        // Reaching as much farther as the point ahead is from its node's origin covers everything
+        // within the view distance of it
+        let offset = (ahead.local * math::origin()).w.max(1.0).acosh();
+        Some(NodeInterestHint {
+            center_path: NodePath::to(&self.graph, ahead.node),
+            radius: self.cfg.view_distance + offset.min(MAX_NODE_HINT_MARGIN),
+        })
+    }
+
+    /// Roughly how long the server takes to answer input, judged by how much input it has yet to
+    /// acknowledge
+    fn round_trip(&self) -> Duration {
+        self.cfg.step_interval * self.prediction.unacknowledged() as u32
+    }
+
+    /// Nodes the local character's prediction may reach before the server's corrections do,
+    /// roughly nearest first
+    ///
+    /// Prediction is held back wherever chunks haven't been generated, even though the server's
+    /// character carries on, so chunks in these nodes are worth generating before anything merely
+    /// in view. They cover the predicted position and wherever its velocity carries it over a
+    /// round trip.
+    pub fn prefetch_nodes(&self) -> Vec<NodeId> {
+        if self.local_character().is_none() {
+            return Vec::new();
+        }
+        let position = self.prediction.predicted_position();
+        if !self.graph.contains(position.node) {
+            return Vec::new();
+        }
+        let velocity = self.prediction.predicted_velocity();
+        let speed = velocity.norm();
+        // Never further ahead than anything is drawn
+        let horizon = self
+            .round_trip()
+            .as_secs_f32()
+            .min(self.cfg.view_distance / speed.max(f32::EPSILON));
+        // Samples half a node apart leave every point of the way within a quarter of a node of
+        // one, and around each is everything a step from that near could touch
+        let spacing = 0.5 * dodeca::BOUNDING_SPHERE_RADIUS_F32;
+        let samples = (speed * horizon / spacing).ceil() as u32;
+        let distance =
+            URGENT_POPULATION_DISTANCE + f64::from(speed * self.cfg.step_interval.as_secs_f32());
+        let mut seen = FxHashSet::default();
+        let mut nodes = Vec::new();
+        for i in 0..=samples {
+            let dt = horizon * i as f32 / samples.max(1) as f32;
+            let point = extrapolate_within(&self.graph, position, velocity, dt);
+            for (node, _) in nearby_nodes(&self.graph, &point, distance) {
+                if seen.insert(node) {
+                    nodes.push(node);
+                }
+            }
+        }
+        nodes
+    }
+
+    /// Number of steps predicted from fresh input in which the local character was held back at
+    /// the edge of the generated world
+    pub fn frontier_holds(&self) -> u64 {
+        self.prediction.frontier_holds()
+    }
+
+    pub fn handle_net(&mut self, msg: net::Message) {
+        use net::Message::*;
+        let _span = self.step.map(|step| step_span(step).entered());
+        match msg {
+            ConnectionLost(_) | Hello(_) => {
+                unreachable!("Case already handled by caller");
             }
             Spawns(msg) => self.handle_spawns(msg),
+            net::Message::Inventory(msg) => {
+                debug!(inventory = ?msg.inventory, "inventory changed");
+                self.inventory = msg.inventory;
+                self.inventory_generation = msg.latest_input;
+            }
+            BlockUpdateRejected(rejection) => {
+                match rejection.reason {
+                    RejectionReason::Refused => {
+                        debug!(sequence = rejection.sequence, "block update rejected")
+                    }
+                    RejectionReason::Protected(ref region) => {
+                        warn!("can't change blocks in {}, which is protected", region)
+                    }
+                }
+                self.block_prediction
+                    .reject(&mut self.graph, rejection.sequence);
+            }
+            net::Message::MovementModes(msg) => self.handle_movement_modes(msg),
+            Waypoints(msg) => {
+                for name in msg.removed {
+                    self.shared_waypoints.remove(&name);
+                }
+                for waypoint in msg.set {
+                    debug!(name = %waypoint.name, owner = %waypoint.owner, "waypoint shared");
+                    self.shared_waypoints
+                        .insert(waypoint.name.clone(), waypoint);
+                }
+            }
+            CollisionTrace(report) => {
+                let Some(path) = self.pending_trace_dumps.remove(&report.character) else {
+                    debug!(character = %report.character, "ignoring unrequested collision trace");
+                    return;
+                };
+                match report.trace {
+                    Some(trace) => save_collision_trace(&trace, &path),
+                    None => warn!("collisions of {} aren't being traced", report.character),
+                }
+            }
+            Sounds(events) => {
+                for event in events {
+                    self.sounds.receive(event);
+                }
+            }
+            GraphRegions(regions) => self.handle_graph_regions(regions),
+            EntityUpdates(msg) => self.handle_entity_updates(msg),
+            GraphUpdates(msg) => self.handle_graph_updates(msg),
+            WorldEdits(msg) => self.handle_world_edits(msg),
+            BlockEntities(changes) => {
+                for change in changes {
+                    self.block_entities.apply(change);
+                }
+            }
+            Following(followed) => {
+                self.follow_unanswered = self.follow_unanswered.saturating_sub(1);
+                // Answers to asks since superseded are ignored
+                if followed.is_none()
+                    && self.follow_unanswered == 0
+                    && self.follow_requested.take().is_some()
+                {
+                    // Refused, or the server lost track of the character
+                    self.lose_follow();
+                }
+            }
             StateDelta(msg) => {
                 // Discard out-of-order messages, taking care to account for step counter wrapping.
                 if self.step.map_or(false, |x| x.wrapping_sub(msg.step) >= 0) {
                     return;
                 }
                 self.step = Some(msg.step);
+                self.quality.received(msg.latest_input);
+                self.world_clock.observe(msg.world_time);
+                let teleported = self.local_character_teleported(&msg.character_states);
                 for &(id, ref new_pos) in &msg.positions {
-                    self.update_position(id, new_pos);
+                    self.update_position(id, msg.step, new_pos);
                 }
                 for &(id, ref new_state) in &msg.character_states {
-                    self.update_character_state(id, new_state);
+                    self.update_character_state(id, msg.step, new_state);
                 }
-                self.reconcile_prediction(msg.latest_input);
+                self.prediction.set_tether(msg.tether);
+                self.reconcile_prediction(msg.latest_input, teleported);
             }
         }
     }
 
-    fn update_position(&mut self, id: EntityId, new_pos: &Position) {
-        match self.entity_ids.get(&id) {
-            None => debug!(%id, "position update for unknown entity"),
-            Some(&entity) => match self.world.get::<&mut Position>(entity) {
-                Ok(mut pos) => {
-                    if pos.node != new_pos.node {
-                        self.graph_entities.remove(pos.node, entity);
-                        self.graph_entities.insert(new_pos.node, entity);
-                    }
-                    *pos = *new_pos;
+    fn update_position(&mut self, id: EntityId, step: Step, new_pos: &Position) {
+        let Some(entity) = self.updated_entity(id, step, "position") else {
+            return;
+        };
+        if let Ok(mut timing) = self.world.get::<&mut UpdateTiming>(entity) {
+            timing.observe(step);
+        }
+        match self.world.get::<&mut Position>(entity) {
+            Ok(mut pos) => {
+                if pos.node != new_pos.node {
+                    self.graph_entities.remove(pos.node, entity);
+                    self.graph_entities.insert(new_pos.node, entity);
                 }
-                Err(e) => error!(%id, "position update error: {}", e),
-            },
+                *pos = *new_pos;
+            }
+            Err(e) => error!(entity = %id, error = %e, "position update error"),
         }
     }
 
-    fn update_character_state(&mut self, id: EntityId, new_character_state: &CharacterState) {
-        match self.entity_ids.get(&id) {
-            None => debug!(%id, "character state update for unknown entity"),
-            Some(&entity) => match self.world.get::<&mut Character>(entity) {
-                Ok(mut ch) => {
-                    ch.state = new_character_state.clone();
-                }
-                Err(e) => {
-                    error!(%id, "character state update error: {}", e)
-                }
-            },
+    fn update_character_state(
+        &mut self,
+        id: EntityId,
+        step: Step,
+        new_character_state: &CharacterState,
+    ) {
+        let Some(entity) = self.updated_entity(id, step, "character state") else {
+            return;
+        };
+        match self.world.get::<&mut Character>(entity) {
+            Ok(mut ch) => {
+                ch.state = new_character_state.clone();
+            }
+            Err(e) => {
+                error!(entity = %id, error = %e, "character state update error")
+            }
+        }
+    }
+
+    /// The entity that a `what` update for `id` as of `step` applies to, if any
+    ///
+    /// State updates travel separately from spawns and despawns, so they may describe an entity
+    /// that's already been despawned, or an earlier entity with the same ID.
+    fn updated_entity(&self, id: EntityId, step: Step, what: &str) -> Option<Entity> {
+        if let Some(&entity) = self.entity_ids.get(&id) {
+            let spawned = self.world.get::<&SpawnStep>(entity).ok()?.0;
+            if step.wrapping_sub(spawned) < 0 {
+                trace!(entity = %id, step, spawned, "discarding {} update from before spawn", what);
+                return None;
+            }
+            return Some(entity);
+        }
+        match self.tombstones.get(&id) {
+            Some(&despawned) if step.wrapping_sub(despawned) < 0 => {
+                trace!(
+                    entity = %id,
+                    step,
+                    despawned,
+                    "discarding {} update for despawned entity",
+                    what
+                );
+            }
+            _ => debug!(entity = %id, "{} update for unknown entity", what),
+        }
+        None
+    }
+
+    fn handle_movement_modes(&mut self, msg: proto::MovementModesUpdate) {
+        debug!(allowed = ?msg.allowed, "movement modes changed");
+        self.allowed_movement_modes = msg.allowed;
+        self.prediction
+            .restrict_inputs_since(msg.latest_input, msg.allowed);
+        if !msg.allowed.contains(MovementModes::NO_CLIP) {
+            if self.no_clip {
+                warn!("no-clip revoked by the server");
+            }
+            self.no_clip = false;
+            self.toggle_no_clip = false;
         }
     }
 
-    fn reconcile_prediction(&mut self, latest_input: u16) {
+    /// Whether `states` shows the local character to have been teleported since its state was last
+    /// updated
+    fn local_character_teleported(&self, states: &[(EntityId, CharacterState)]) -> bool {
+        let Some(entity) = self.local_character() else {
+            return false;
+        };
+        let Ok(ch) = self.world.get::<&Character>(entity) else {
+            return false;
+        };
+        states.iter().any(|(id, state)| {
+            *id == self.local_character_id && state.teleports != ch.state.teleports
+        })
+    }
+
+    /// Correct the prediction with the latest state of the local character, adopting it outright
+    /// if the character was `teleported`
+    fn reconcile_prediction(&mut self, latest_input: u16, teleported: bool) {
         let id = self.local_character_id;
         let Some(&entity) = self.entity_ids.get(&id) else {
-            debug!(%id, "reconciliation attempted for unknown entity");
+            debug!(entity = %id, "reconciliation attempted for unknown entity");
             return;
         };
         let pos = match self.world.get::<&Position>(entity) {
-            Ok(pos) => pos,
+            Ok(pos) => *pos,
             Err(e) => {
-                error!(%id, "reconciliation error: {}", e);
+                error!(entity = %id, error = %e, "reconciliation error");
                 return;
             }
         };
-        let ch = match self.world.get::<&Character>(entity) {
-            Ok(ch) => ch,
+        let _span = entity_span(id, pos.node).entered();
+        let (velocity, on_ground, orientation) = match self.world.get::<&Character>(entity) {
+            Ok(ch) => (ch.state.velocity, ch.state.on_ground, ch.state.orientation),
             Err(e) => {
-                error!(%id, "reconciliation error: {}", e);
+                error!(entity = %id, error = %e, "reconciliation error");
                 return;
             }
         };
-        self.prediction.reconcile(
-            &self.cfg,
-            &self.graph,
-            latest_input,
-            *pos,
-            ch.state.velocity,
-            ch.state.on_ground,
-        );
+        // Replaying unacknowledged input from here needs the surrounding nodes
+        let urgent = self.nodes_around(&pos);
+        self.population.run(&mut self.graph, urgent, 0);
+        if teleported {
+            debug!("teleported by the server");
+            self.prediction.teleport(
+                &self.cfg,
+                &self.graph,
+                latest_input,
+                pos,
+                velocity,
+                on_ground,
+            );
+            // Smoothing the camera's way across the jump would only be disorienting
+            self.camera.reset();
+            // Where the server chose for a respawned character, or otherwise the orientation last
+            // sent to it
+            self.local_character_controller
+                .adopt_orientation(orientation);
+            self.previous_predicted_position = *self.prediction.predicted_position();
+        } else {
+            self.prediction
+                .reconcile(latest_input, pos, velocity, on_ground);
+        }
+    }
+
+    /// Nodes that must be populated to simulate a character at `position`
+    fn nodes_around(&self, position: &Position) -> Vec<NodeId> {
+        if !self.graph.contains(position.node) {
+            return Vec::new();
+        }
+        nearby_nodes(&self.graph, position, URGENT_POPULATION_DISTANCE)
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect()
     }
 
+    /// Handle the combined message sent by servers without `Capabilities::SPLIT_UPDATES` as its
+    /// parts, nodes first so that nothing in it waits for them
     fn handle_spawns(&mut self, msg: proto::Spawns) {
-        self.step = self.step.max(Some(msg.step));
-        let mut builder = hecs::EntityBuilder::new();
-        for (id, components) in msg.spawns {
-            self.spawn(&mut builder, id, components);
+        let (entities, graph, edits) = msg.split(usize::MAX);
+        for msg in graph {
+            self.handle_graph_updates(msg);
+        }
+        for msg in entities {
+            self.handle_entity_updates(msg);
+        }
+        for msg in edits {
+            self.handle_world_edits(msg);
         }
+    }
+
+    fn handle_entity_updates(&mut self, msg: proto::EntityUpdates) {
+        // Despawn first, so that an ID can be despawned and reused within one message
         for &id in &msg.despawns {
             match self.entity_ids.get(&id) {
                 Some(&entity) => self.destroy(entity),
-                None => error!(%id, "despawned unknown entity"),
+                None if self.deferred.cancel_spawn(id) => {
+                    trace!(entity = %id, "despawned entity before its node arrived")
+                }
+                None => error!(entity = %id, "despawned unknown entity"),
+            }
+            self.tombstones.insert(id, msg.step);
+        }
+        self.prune_tombstones(msg.step);
+        let mut builder = hecs::EntityBuilder::new();
+        for (id, components) in msg.spawns {
+            if self.tombstones.remove(&id).is_some() && !msg.despawns.contains(&id) {
+                debug!(entity = %id, "recently despawned entity ID reused");
+            }
+            let node = components.iter().find_map(|component| match *component {
+                Component::Position(ref position) => Some(position.node),
+                _ => None,
+            });
+            match node {
+                Some(node) if !self.graph.contains(node) => {
+                    trace!(entity = %id, "deferring spawn until its node arrives");
+                    self.deferred
+                        .defer(node, msg.step, DeferredUpdate::Spawn(id, components));
+                }
+                _ => self.spawn(&mut builder, id, msg.step, components),
             }
         }
+    }
+
+    fn handle_graph_updates(&mut self, msg: proto::GraphUpdates) {
         if !msg.nodes.is_empty() {
             trace!(count = msg.nodes.len(), "adding nodes");
         }
-        for node in &msg.nodes {
-            self.graph.insert_child(node.parent, node.side);
+        self.pending_nodes
+            .insert(&mut self.graph, &msg.nodes, msg.step);
+        // Populated a few at a time over the following frames, so a burst of new nodes can't stall
+        // one frame
+        self.population.enqueue_fresh(&mut self.graph);
+        self.apply_deferred();
+    }
+
+    fn handle_world_edits(&mut self, msg: proto::WorldEdits) {
+        for (author, block_update) in msg.block_updates {
+            let node = block_update.chunk_id.node;
+            if self.graph.contains(node) {
+                self.apply_block_update(author, &block_update);
+            } else {
+                let update = DeferredUpdate::BlockUpdate(author, block_update);
+                self.deferred.defer(node, msg.step, update);
+            }
+        }
+        for (author, block_fill) in msg.block_fills {
+            let node = block_fill.chunk_id.node;
+            if self.graph.contains(node) {
+                self.apply_block_fill(author, &block_fill);
+            } else {
+                let update = DeferredUpdate::BlockFill(author, block_fill);
+                self.deferred.defer(node, msg.step, update);
+            }
+        }
+        for diff in msg.chunk_diffs {
+            let node = diff.chunk.node;
+            if self.graph.contains(node) {
+                self.apply_chunk_diff(diff);
+            } else {
+                self.deferred
+                    .defer(node, msg.step, DeferredUpdate::ChunkDiff(diff));
+            }
+        }
+        for (chunk, voxels) in msg.modified_chunks {
+            if self.graph.contains(chunk.node) {
+                self.apply_modified_chunk(chunk, voxels);
+            } else {
+                let update = DeferredUpdate::ModifiedChunk(chunk, voxels);
+                self.deferred.defer(chunk.node, msg.step, update);
+            }
+        }
+    }
+
+    /// Apply the spawns and edits that were waiting for nodes that have since been added
+    fn apply_deferred(&mut self) {
+        let mut builder = hecs::EntityBuilder::new();
+        for (step, update) in self.deferred.take_ready(&self.graph) {
+            match update {
+                DeferredUpdate::Spawn(id, components) => {
+                    self.spawn(&mut builder, id, step, components)
+                }
+                DeferredUpdate::BlockUpdate(author, block_update) => {
+                    self.apply_block_update(author, &block_update)
+                }
+                DeferredUpdate::BlockFill(author, block_fill) => {
+                    self.apply_block_fill(author, &block_fill)
+                }
+                DeferredUpdate::ChunkDiff(diff) => self.apply_chunk_diff(diff),
+                DeferredUpdate::ModifiedChunk(chunk, voxels) => {
+                    self.apply_modified_chunk(chunk, voxels)
+                }
+            }
+        }
+    }
+
+    /// Apply a block update made at the request of `author`, holding it until its chunk is
+    /// generated if it hasn't been yet
+    fn apply_block_update(&mut self, author: EntityId, block_update: &BlockUpdate) {
+        let _span = edit_span(EditId::new(author, block_update)).entered();
+        debug!("applying block update");
+        let local = author == self.local_character_id;
+        if self
+            .block_prediction
+            .accept(&mut self.graph, block_update, local)
+            == BlockUpdateOutcome::ChunkMissing
+        {
+            self.pending_modified_chunks
+                .entry(block_update.chunk_id)
+                .or_default()
+                .push((
+                    block_update.coords,
+                    block_update.new_material,
+                    block_update.new_shape,
+                ));
+        }
+    }
+
+    /// Apply a block fill made at the request of `author`, holding the parts in chunks that haven't
+    /// been generated yet until they are
+    ///
+    /// Parts in nodes not yet known are left to the changes sent with those nodes.
+    fn apply_block_fill(&mut self, author: EntityId, block_fill: &BlockFill) {
+        let _span = edit_span(EditId {
+            author,
+            sequence: block_fill.sequence,
+        })
+        .entered();
+        debug!(volume = block_fill.volume(), "applying block fill");
+        // The server has already held the fill to its limit
+        let edit = block_fill.apply(&mut self.graph);
+        for (chunk, coords, material, shape) in edit.missing {
+            self.pending_modified_chunks
+                .entry(chunk)
+                .or_default()
+                .push((coords, material, shape));
+        }
+    }
+
+    /// Add regions of the graph, which may arrive in any order and overlap nodes already known
+    fn handle_graph_regions(&mut self, regions: Vec<proto::EncodedGraphRegion>) {
+        for region in regions {
+            let region = match region.decode() {
+                Ok(x) => x,
+                Err(e) => {
+                    error!(error = %e, "malformed graph region");
+                    self.protocol_errors += 1;
+                    continue;
+                }
+            };
+            trace!(
+                depth = region.root.0.len(),
+                count = region.nodes.len(),
+                "adding graph region"
+            );
+            region.root.ensure(&mut self.graph);
+            self.pending_nodes.insert(
+                &mut self.graph,
+                &region.nodes,
+                self.step.unwrap_or_default(),
+            );
+            self.population.enqueue_fresh(&mut self.graph);
+            self.apply_modified_chunks(region.chunk_diffs, region.modified_chunks);
+            for (chunk, coords, entity) in region.block_entities {
+                self.block_entities.insert(chunk, coords, entity);
+            }
+        }
+        self.apply_deferred();
+    }
+
+    /// Apply the changes the server has made to chunks, holding those for chunks not yet generated
+    /// until they are
+    fn apply_modified_chunks(
+        &mut self,
+        chunk_diffs: Vec<ChunkDiff>,
+        modified_chunks: Vec<(ChunkId, SerializableVoxelData)>,
+    ) {
+        for diff in chunk_diffs {
+            self.apply_chunk_diff(diff);
+        }
+        for (chunk_id, voxel_data) in modified_chunks {
+            self.apply_modified_chunk(chunk_id, voxel_data);
         }
-        populate_fresh_nodes(&mut self.graph);
-        for block_update in msg.block_updates.into_iter() {
-            if !self.graph.update_block(&block_update) {
+    }
+
+    fn apply_chunk_diff(&mut self, diff: ChunkDiff) {
+        for (coords, material, shape) in diff.changes {
+            if self.graph.set_block(diff.chunk, coords, material, shape)
+                == BlockUpdateOutcome::ChunkMissing
+            {
                 self.pending_modified_chunks
-                    .entry(block_update.chunk_id)
+                    .entry(diff.chunk)
                     .or_default()
-                    .push(block_update);
+                    .push((coords, material, shape));
             }
         }
-        for (chunk_id, voxel_data) in msg.modified_chunks {
-            let Some(voxel_data) = VoxelData::from_serializable(&voxel_data, self.cfg.chunk_size)
-            else {
-                tracing::error!("Voxel data received from server is of incorrect dimension");
-                continue;
-            };
-            self.graph.populate_chunk(chunk_id, voxel_data, true);
+    }
+
+    fn apply_modified_chunk(&mut self, chunk_id: ChunkId, voxel_data: SerializableVoxelData) {
+        let Some(voxel_data) = VoxelData::from_serializable(&voxel_data, self.cfg.chunk_size)
+        else {
+            tracing::error!("Voxel data received from server is of incorrect dimension");
+            return;
+        };
+        populate_with_dependencies(&mut self.graph, chunk_id.node);
+        self.graph.populate_chunk(chunk_id, voxel_data, true);
+    }
+
+    /// Store freshly generated voxel data for `chunk`, applying any changes the server sent for it
+    /// in the meantime
+    pub fn populate_chunk(&mut self, chunk: ChunkId, voxels: VoxelData) {
+        let _span = chunk_span(chunk).entered();
+        self.graph.populate_chunk(chunk, voxels, false);
+        for (coords, material, shape) in self
+            .pending_modified_chunks
+            .remove(&chunk)
+            .unwrap_or_default()
+        {
+            // The chunk was just populated, so this should always succeed
+            assert_ne!(
+                self.graph.set_block(chunk, coords, material, shape),
+                BlockUpdateOutcome::ChunkMissing
+            );
+        }
+    }
+
+    /// Forget despawned entities that the server may now reuse the IDs of
+    fn prune_tombstones(&mut self, step: Step) {
+        self.tombstones
+            .retain(|_, &mut despawned| step.wrapping_sub(despawned) < ENTITY_ID_REUSE_DELAY);
+        if self.tombstones.len() > MAX_TOMBSTONES {
+            let mut oldest = self
+                .tombstones
+                .iter()
+                .map(|(&id, &despawned)| (step.wrapping_sub(despawned), id))
+                .collect::<Vec<_>>();
+            oldest.sort_unstable_by_key(|&(age, _)| std::cmp::Reverse(age));
+            for &(_, id) in &oldest[..oldest.len() - MAX_TOMBSTONES] {
+                self.tombstones.remove(&id);
+            }
         }
     }
 
@@ -323,61 +1846,220 @@ impl Sim {
         &mut self,
         builder: &mut hecs::EntityBuilder,
         id: EntityId,
+        step: Step,
         components: Vec<Component>,
     ) {
-        trace!(%id, "spawning entity");
+        trace!(entity = %id, "spawning entity");
         builder.add(id);
-        let mut node = None;
+        builder.add(SpawnStep(step));
+        builder.add(UpdateTiming::new(step));
+        let mut position = None;
+        let mut orientation = None;
         for component in components {
             use common::proto::Component::*;
             match component {
                 Character(x) => {
+                    orientation = Some(x.state.orientation);
                     builder.add(x);
                 }
                 Position(x) => {
-                    node = Some(x.node);
+                    position = Some(x);
+                    builder.add(x);
+                }
+                Interactable(x) => {
                     builder.add(x);
                 }
+                Projectile(x) => {
+                    builder.add(Thrown(x));
+                }
             };
         }
         let entity = self.world.spawn(builder.build());
-        if let Some(node) = node {
-            self.graph_entities.insert(node, entity);
+        if let Some(position) = position {
+            self.graph_entities.insert(position.node, entity);
         }
         if id == self.local_character_id {
-            self.local_character = Some(entity);
+            self.adopt_local_character(entity, position);
+            // The server picks which way the character first faces
+            if let Some(orientation) = orientation {
+                self.local_character_controller
+                    .adopt_orientation(orientation);
+            }
         }
         if let Some(x) = self.entity_ids.insert(id, entity) {
+            // The server never reuses an ID while it might still be known, so this is a bug
             self.destroy_idless(x);
-            error!(%id, "id collision");
+            self.protocol_errors += 1;
+            error!(entity = %id, "id collision");
+        }
+    }
+
+    /// Take `entity`, just spawned by the server at `position`, as the local character
+    fn adopt_local_character(&mut self, entity: Entity, position: Option<Position>) {
+        self.session = match self.session {
+            Session::AwaitingSpawn => {
+                let Some(position) = position else {
+                    error!("local character spawned without a position");
+                    self.protocol_errors += 1;
+                    return;
+                };
+                // Nothing has been predicted since the character last existed, if it ever did, so
+                // prediction starts afresh from where the server put it
+                self.prediction.place(position);
+                self.previous_predicted_position = position;
+                let urgent = self.nodes_around(&position);
+                self.population.run(&mut self.graph, urgent, 0);
+                Session::Playing { entity }
+            }
+            Session::Playing { .. } => Session::Playing { entity },
+            Session::Spectating { .. } => Session::Spectating { entity },
+            Session::Disconnected { .. } => Session::Disconnected {
+                entity: Some(entity),
+            },
+        };
+        if !self.session.sends_input() {
+            return;
         }
+        self.update_view_position();
     }
 
     fn send_input(&mut self, net: &mut Net) {
+        let _span = entity_span(
+            self.local_character_id,
+            self.prediction.predicted_position().node,
+        )
+        .entered();
         let orientation = if self.no_clip {
             self.local_character_controller.orientation()
         } else {
             self.local_character_controller.horizontal_orientation()
         };
-        let character_input = CharacterInput {
-            movement: sanitize_motion_input(orientation * self.average_movement_input),
+        // Input is sent once a whole step has been accumulated
+        let step_interval = self.cfg.step_interval;
+        let mut character_input = CharacterInput {
+            movement: sanitize_motion_input(MovementInput::from_accumulated(
+                orientation * self.average_movement_input,
+                step_interval,
+                step_interval,
+            )),
             jump: self.is_jumping,
             no_clip: self.no_clip,
-            block_update: self.get_local_character_block_update(),
+            block_update: None,
+            block_fill: None,
+            use_target: self.use_target(),
+            throw: self.throw(),
+            external: Default::default(),
         };
+        if self.break_block_pressed && !self.place_block_pressed && self.observer.is_none() {
+            // Swung whether or not there's anything in reach to break
+            self.gestures.push(Gesture::Swing);
+        }
+        let mut block_update = self.get_local_character_block_update();
+        if block_update.is_some() && !self.quality.get().allows_edits() {
+            // The server would likely reject or lose it, so it isn't predicted even briefly
+            debug!("block update held back: connection degraded");
+            block_update = None;
+        }
+        if block_update
+            .as_ref()
+            .is_some_and(|x| self.placement_obstructed(x, &character_input))
+        {
+            // The server would refuse it, so it isn't predicted even briefly
+            trace!("placement obstructed by a character");
+            self.obstructed_faces.extend(self.target().ok().flatten());
+            block_update = None;
+        }
+        if let Some(ref block_update) = block_update {
+            debug!(
+                edit = %EditId::new(self.local_character_id, block_update),
+                "requesting block update"
+            );
+            if block_update.new_material == Material::Void {
+                self.broken_faces.extend(self.target().ok().flatten());
+            }
+            let _ = self.block_prediction.predict(&mut self.graph, block_update);
+            if block_update.new_material != Material::Void {
+                self.gestures.push(Gesture::Push);
+            }
+            if let Some(step) = self.step {
+                self.sounds.predict(
+                    SoundKind::of_block_update(block_update),
+                    block_update.chunk_id,
+                    block_update.coords,
+                    step,
+                );
+            }
+        }
+        character_input.block_update = block_update;
+        character_input.block_fill = self.queued_block_fill.take();
+        self.previous_predicted_position = *self.prediction.predicted_position();
         let generation = self
             .prediction
             .push(&self.cfg, &self.graph, &character_input);
+        self.quality.sent(generation);
 
-        // Any failure here will be better handled in handle_net's ConnectionLost case
-        let _ = net.outgoing.send(Command {
+        match net.outgoing.send(ClientMessage::Command(Command {
             generation,
             character_input,
             orientation: self.local_character_controller.orientation(),
-        });
+        })) {
+            Ok(Queued::Appended) => {}
+            Ok(Queued::DisplacedOldest) => {
+                // Counted in the outgoing stats; the server will see a gap in our input
+                trace!("outgoing queue full, discarded oldest command");
+            }
+            // The cause is reported by handle_net's ConnectionLost case
+            Err(net::Closed) => self.update_connection_state(ConnectionState::Closed),
+        }
+    }
+
+    fn update_connection_state(&mut self, state: ConnectionState) {
+        // A closed connection never comes back, whatever the transport says afterwards
+        if state == self.connection || self.connection == ConnectionState::Closed {
+            return;
+        }
+        match state {
+            ConnectionState::Connected => info!("connection recovered"),
+            ConnectionState::Stalled => warn!("nothing sent to the server recently"),
+            ConnectionState::Closed => {
+                warn!("connection closed");
+                self.session = Session::Disconnected {
+                    entity: self.local_character(),
+                };
+            }
+        }
+        self.connection = state;
+    }
+
+    /// Derive the view position from the latest prediction, returning whether it's on the ground
+    fn update_view_position(&mut self) -> bool {
+        let (mut view_position, view_on_ground) = if self.prediction.is_stalled() {
+            // Held exactly where prediction stopped, rather than edging along with each frame
+            (
+                *self.prediction.predicted_position(),
+                *self.prediction.predicted_on_ground(),
+            )
+        } else if self.interpolate_view {
+            self.interpolated_view_position()
+        } else {
+            self.extrapolated_view_position()
+        };
+        if !self.no_clip && !self.prediction.is_stalled() {
+            view_position = self.separate_from_remote_characters(view_position);
+        }
+
+        // Nodes around the character are populated before prediction runs, but stay safe if it
+        // somehow got ahead of them
+        let Some(up) = self.graph.get_relative_up(&view_position) else {
+            return view_on_ground;
+        };
+        self.local_character_controller
+            .update_position(view_position, up, !self.no_clip);
+        view_on_ground
     }
 
-    fn update_view_position(&mut self) {
+    /// The latest prediction advanced by the input accumulated in the current step
+    fn extrapolated_view_position(&mut self) -> (Position, bool) {
         let mut view_position = *self.prediction.predicted_position();
         let mut view_velocity = *self.prediction.predicted_velocity();
         let mut view_on_ground = *self.prediction.predicted_on_ground();
@@ -387,16 +2069,23 @@ impl Sim {
             self.local_character_controller.horizontal_orientation()
         };
         // Apply input that hasn't been sent yet
+        let average_movement_input = self.average_movement_input
+            + self.movement_input * self.movement_input_held.as_secs_f32()
+                / self.cfg.step_interval.as_secs_f32();
         let predicted_input = CharacterInput {
-            // We divide by how far we are through the timestep because self.average_movement_input
-            // is always over the entire timestep, filling in zeroes for the future, and we
-            // want to use the average over what we have so far. Dividing by zero is handled
-            // by the character_controller sanitizing this input.
-            movement: orientation * self.average_movement_input
-                / (self.since_input_sent.as_secs_f32() / self.cfg.step_interval.as_secs_f32()),
+            // Only part of the step has elapsed, and the prediction only covers that part
+            movement: MovementInput::from_accumulated(
+                orientation * average_movement_input,
+                self.since_input_sent,
+                self.cfg.step_interval,
+            ),
             jump: self.is_jumping,
             no_clip: self.no_clip,
             block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: self.prediction.external(&self.graph, &view_position),
         };
         character_controller::run_character_step(
             &self.cfg,
@@ -405,66 +2094,500 @@ impl Sim {
             &mut view_velocity,
             &mut view_on_ground,
             &predicted_input,
+            &mut 0.0,
             self.since_input_sent.as_secs_f32(),
+            None,
         );
+        (view_position, view_on_ground)
+    }
 
-        self.local_character_controller.update_position(
-            view_position,
-            self.graph.get_relative_up(&view_position).unwrap(),
-            !self.no_clip,
-        )
+    /// The point between the two latest predicted steps reached by the time elapsed since the
+    /// latter
+    ///
+    /// This trails the prediction by up to a step, but never simulates anything itself.
+    fn interpolated_view_position(&self) -> (Position, bool) {
+        let progress = self.since_input_sent.as_secs_f32() / self.cfg.step_interval.as_secs_f32();
+        let position = interpolate(
+            &self.graph,
+            &self.previous_predicted_position,
+            self.prediction.predicted_position(),
+            progress.min(1.0),
+        );
+        (position, *self.prediction.predicted_on_ground())
     }
 
-    pub fn view(&self) -> Position {
-        self.local_character_controller.oriented_position()
+    /// Whether placing `block_update` would overlap the local character where `input` leaves it at
+    /// the end of the step, or a remote character where it was last seen
+    ///
+    /// The server judges placements once characters have moved, so the local character is checked
+    /// where it will be when the server sees the placement rather than where it is now. Remote
+    /// characters may have moved on, and their movement modes aren't known, so the server has the
+    /// final word on them.
+    fn placement_obstructed(&self, block_update: &BlockUpdate, input: &CharacterInput) -> bool {
+        let radius = self.cfg.character.character_radius;
+        if !input.no_clip {
+            let mut position = *self.prediction.predicted_position();
+            let mut velocity = *self.prediction.predicted_velocity();
+            let mut on_ground = *self.prediction.predicted_on_ground();
+            character_controller::run_character_step(
+                &self.cfg,
+                &self.graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                input,
+                &mut 0.0,
+                self.cfg.step_interval.as_secs_f32(),
+                None,
+            );
+            if placement::obstructs(&self.graph, block_update, &position, radius) {
+                return true;
+            }
+        }
+        self.world
+            .query::<(&Position, &Character)>()
+            .iter()
+            .filter(|&(entity, _)| self.local_character() != Some(entity))
+            .any(|(_, (position, _))| {
+                placement::obstructs(&self.graph, block_update, position, radius)
+            })
     }
 
-    /// Destroy all aspects of an entity
-    fn destroy(&mut self, entity: Entity) {
-        let id = *self
-            .world
-            .get::<&EntityId>(entity)
-            .expect("destroyed nonexistent entity");
-        self.entity_ids.remove(&id);
-        self.destroy_idless(entity);
+    /// Apply the server's character separation to the local character only, treating remote
+    /// characters as fixed at their latest known positions
+    fn separate_from_remote_characters(&self, local: Position) -> Position {
+        let mut positions = vec![local];
+        positions.extend(
+            self.world
+                .query::<(&Position, &Character)>()
+                .iter()
+                .filter(|&(entity, _)| self.local_character() != Some(entity))
+                .map(|(_, (&position, _))| position),
+        );
+        character_controller::separate_characters(
+            &self.cfg,
+            &self.graph,
+            &mut positions,
+            self.since_input_sent.as_secs_f32(),
+        );
+        positions[0]
     }
 
-    /// Destroy an entity without an EntityId mapped
-    fn destroy_idless(&mut self, entity: Entity) {
-        if let Ok(position) = self.world.get::<&Position>(entity) {
-            self.graph_entities.remove(position.node, entity);
+    /// Where the world is seen from: the local character's eyes, or the observer's camera while
+    /// there is one, or nowhere before the character is spawned
+    pub fn view(&self) -> ViewSource {
+        match self.observer {
+            Some(ref observer) => ViewSource::World(observer.view()),
+            None => self
+                .character_view()
+                .map_or(ViewSource::LoadingScreen, ViewSource::World),
         }
-        self.world
-            .despawn(entity)
-            .expect("destroyed nonexistent entity");
     }
 
-    /// Provides the logic for the player to be able to place and break blocks at will
-    fn get_local_character_block_update(&self) -> Option<BlockUpdate> {
-        let placing = if self.place_block_pressed {
-            true
-        } else if self.break_block_pressed {
-            false
-        } else {
-            return None;
-        };
+    /// The local character's view, whether or not the world is seen from it, if the character
+    /// exists
+    pub fn character_view(&self) -> Option<Position> {
+        self.local_character()?;
+        Some(self.local_character_controller.oriented_position())
+    }
 
-        let view_position = self.view();
-        let ray_casing_result = graph_ray_casting::ray_cast(
-            &self.graph,
-            &view_position,
-            &Ray::new(na::Vector4::w(), -na::Vector4::z()),
-            self.cfg.character.block_reach,
-        );
+    /// Where to render the world from, which lags slightly behind `view`
+    pub fn camera(&self) -> ViewSource {
+        match self.view() {
+            ViewSource::World(view) => ViewSource::World(self.camera.view().unwrap_or(view)),
+            ViewSource::LoadingScreen => ViewSource::LoadingScreen,
+        }
+    }
 
-        let Ok(ray_casting_result) = ray_casing_result else {
-            tracing::warn!("Tried to run a raycast beyond generated terrain.");
-            return None;
+    /// Like `camera`, but turned however the view has turned since the camera last followed it,
+    /// for the latest orientation to be rendered without waiting for the next step
+    pub fn latched_camera(&self) -> ViewSource {
+        let ViewSource::World(view) = self.view() else {
+            return ViewSource::LoadingScreen;
+        };
+        let Some(camera) = self.camera.view() else {
+            return ViewSource::World(view);
         };
+        match self.camera_followed {
+            Some(followed) if followed.node == view.node => ViewSource::World(Position {
+                node: camera.node,
+                local: camera.local * math::mtranspose(&followed.local) * view.local,
+            }),
+            _ => ViewSource::World(camera),
+        }
+    }
 
-        let hit = ray_casting_result?;
+    /// Find the nearest block or entity within `max_distance` under `ndc`, a point on the screen in
+    /// normalized device coordinates, given the camera's `frustum`
+    ///
+    /// Entities are treated as spheres of the character radius. The local character is never
+    /// picked.
+    pub fn pick(
+        &mut self,
+        frustum: &Frustum,
+        ndc: na::Point2<f32>,
+        max_distance: f32,
+    ) -> Option<PickResult> {
+        let (position, direction) = frustum.screen_to_ray(ndc);
+        self.pick_along(&Ray::new(position, direction), max_distance)
+    }
 
-        let block_pos = if placing {
+    /// Like `pick`, for a ray in the view's coordinates
+    fn pick_along(&mut self, ray: &Ray, max_distance: f32) -> Option<PickResult> {
+        let view = self.view().position()?;
+        let mut tanh_distance = max_distance.tanh();
+        let mut result = match graph_ray_casting::ray_cast(&self.graph, &view, ray, tanh_distance) {
+            Ok(hit) => hit.map(|hit| {
+                tanh_distance = hit.tanh_distance;
+                PickResult::Block(hit)
+            }),
+            Err(_) => {
+                tracing::warn!("Tried to run a raycast beyond generated terrain.");
+                None
+            }
+        };
+
+        let radius = self.cfg.character.character_radius;
+        let view_inverse = math::mtranspose(&view.local);
+        let nodes = self
+            .nearby_nodes(f64::from(max_distance + radius) + dodeca::BOUNDING_SPHERE_RADIUS_F64);
+        for (node, transform) in nodes {
+            for &entity in self.graph_entities.get(node) {
+                if self.local_character() == Some(entity) {
+                    continue;
+                }
+                let mut q = self
+                    .world
+                    .query_one::<(&EntityId, &Position)>(entity)
+                    .unwrap();
+                let Some((&id, position)) = q.get() else {
+                    continue;
+                };
+                let center = math::lorentz_normalize(
+                    &(view_inverse * transform * position.local * math::origin()),
+                );
+                // The ray hits the sphere where it's within `radius` of all three planes through
+                // the center
+                let to_center = math::translate(&math::origin(), &center);
+                let Some(hit) = ray.solve_sphere_point_intersection(
+                    &(to_center * na::Vector4::x()),
+                    &(to_center * na::Vector4::y()),
+                    &(to_center * na::Vector4::z()),
+                    radius.sinh(),
+                ) else {
+                    continue;
+                };
+                if hit < tanh_distance {
+                    tanh_distance = hit;
+                    result = Some(PickResult::Entity {
+                        id,
+                        tanh_distance: hit,
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    /// Characters other than our own within `max_distance` of `eye`, upright on their nodes'
+    /// terrain
+    ///
+    /// `nodes` gives the transforms of nearby nodes into the frame of the view's node, in which
+    /// `eye` lies. The local character is omitted, since the camera is inside it, unless observing.
+    /// Others are carried along their last known velocity since the server last reported them.
+    pub fn visible_characters(
+        &self,
+        nodes: &[(NodeId, na::Matrix4<f32>)],
+        eye: &na::Vector4<f32>,
+        max_distance: f32,
+    ) -> Vec<VisibleCharacter> {
+        let mut result = Vec::new();
+        for &(node, ref transform) in nodes {
+            for &entity in self.graph_entities.get(node) {
+                if self.local_character() == Some(entity) && self.observer.is_none() {
+                    continue;
+                }
+                let mut q = self
+                    .world
+                    .query_one::<(&Position, &Character, Option<&UpdateTiming>)>(entity)
+                    .unwrap();
+                let Some((&position, ch, timing)) = q.get() else {
+                    continue;
+                };
+                let position = self.drawn_position(entity, position, &ch.state.velocity, timing);
+                let local = transform * position.local;
+                let distance = math::distance(eye, &(local * math::origin()));
+                if distance > max_distance {
+                    continue;
+                }
+                let orientation = ch.state.orientation;
+                let up = self
+                    .graph
+                    .get_relative_up(&position)
+                    .unwrap_or_else(|| orientation * na::Vector3::y_axis());
+                result.push(VisibleCharacter {
+                    entity,
+                    body: local * body_orientation(&up, &orientation).to_homogeneous(),
+                    distance,
+                    name: ch.name.clone(),
+                    afk: ch.state.afk,
+                });
+            }
+        }
+        result
+    }
+
+    /// Where `entity`, last reported at `position` moving at `velocity`, is drawn: carried along
+    /// since it was reported, unless it's the local character
+    fn drawn_position(
+        &self,
+        entity: Entity,
+        position: Position,
+        velocity: &na::Vector3<f32>,
+        timing: Option<&UpdateTiming>,
+    ) -> Position {
+        match (timing, self.step) {
+            (Some(timing), Some(latest)) if self.local_character() != Some(entity) => {
+                let dt = timing.elapsed(latest, self.cfg.step_interval);
+                extrapolate(&position, velocity, dt)
+            }
+            _ => position,
+        }
+    }
+
+    /// Signs that can be read from `eye`
+    ///
+    /// `nodes` gives the transforms of nearby nodes into the frame of the view's node, in which
+    /// `eye` lies.
+    pub fn visible_signs(
+        &self,
+        nodes: &[(NodeId, na::Matrix4<f32>)],
+        eye: &na::Vector4<f32>,
+    ) -> Vec<VisibleSign> {
+        let mut result = Vec::new();
+        for &(node, ref transform) in nodes {
+            for vertex in dodeca::Vertex::iter() {
+                let chunk = ChunkId::new(node, vertex);
+                for (coords, entity) in self.block_entities.in_chunk(chunk) {
+                    let BlockEntity::Sign(sign) = entity;
+                    result.extend(VisibleSign::new(
+                        &self.graph,
+                        chunk,
+                        coords,
+                        sign,
+                        transform,
+                        eye,
+                        self.cfg.meters_to_absolute,
+                    ));
+                }
+            }
+        }
+        result
+    }
+
+    /// Thrown blocks within `max_distance` of `eye`, as transforms into the frame of the view's
+    /// node with their materials
+    ///
+    /// `nodes` gives the transforms of nearby nodes into the frame of the view's node, in which
+    /// `eye` lies.
+    pub fn visible_projectiles(
+        &self,
+        nodes: &[(NodeId, na::Matrix4<f32>)],
+        eye: &na::Vector4<f32>,
+        max_distance: f32,
+    ) -> Vec<(na::Matrix4<f32>, Material)> {
+        let mut result = Vec::new();
+        for &(node, ref transform) in nodes {
+            for &entity in self.graph_entities.get(node) {
+                let mut q = self
+                    .world
+                    .query_one::<(&Position, &Thrown)>(entity)
+                    .unwrap();
+                let Some((position, thrown)) = q.get() else {
+                    continue;
+                };
+                let local = transform * position.local;
+                if math::distance(eye, &(local * math::origin())) > max_distance {
+                    continue;
+                }
+                result.push((local, thrown.0));
+            }
+        }
+        result
+    }
+
+    /// Destroy all aspects of an entity
+    fn destroy(&mut self, entity: Entity) {
+        let id = *self
+            .world
+            .get::<&EntityId>(entity)
+            .expect("destroyed nonexistent entity");
+        self.entity_ids.remove(&id);
+        self.destroy_idless(entity);
+    }
+
+    /// Destroy an entity without an EntityId mapped
+    fn destroy_idless(&mut self, entity: Entity) {
+        if self.local_character() == Some(entity) {
+            // Until the server spawns it again, if it ever does
+            self.session = match self.session {
+                Session::Disconnected { .. } => Session::Disconnected { entity: None },
+                _ => Session::AwaitingSpawn,
+            };
+            self.observer = None;
+            self.follow = None;
+        }
+        if let Ok(position) = self.world.get::<&Position>(entity) {
+            self.graph_entities.remove(position.node, entity);
+        }
+        self.world
+            .despawn(entity)
+            .expect("destroyed nonexistent entity");
+    }
+
+    /// Transforms relative to the view's node of all nodes whose origins lie within `distance` of
+    /// the view
+    ///
+    /// Transforms are cached between calls, so this is cheap while the view stays in or near the
+    /// same node. There are none until there's a view.
+    pub fn nearby_nodes(&mut self, distance: f64) -> Vec<(NodeId, na::Matrix4<f32>)> {
+        let ViewSource::World(view) = self.view() else {
+            return Vec::new();
+        };
+        nearby_nodes_cached(&self.graph, &mut self.node_transforms, &view, distance)
+    }
+
+    /// The block face under the crosshair, if it's within reach
+    ///
+    /// The result is reused until the view moves or a chunk the crosshair passes through changes,
+    /// so this is cheap to call every frame.
+    pub fn target(&mut self) -> Result<Option<GraphCastHit>, OutOfBounds> {
+        let ViewSource::World(view) = self.view() else {
+            return Ok(None);
+        };
+        if let Some(ref cached) = self.cached_target {
+            if cached.is_valid(&self.graph, &view) {
+                return Ok(cached.hit.clone());
+            }
+        }
+        let ray = Ray::new(na::Vector4::w(), -na::Vector4::z());
+        let reach = self.cfg.character.block_reach;
+        let hit = graph_ray_casting::ray_cast(&self.graph, &view, &ray, reach)?;
+        let tanh_distance = hit.as_ref().map_or(reach, |hit| hit.tanh_distance);
+        self.cached_target =
+            chunk_generations(&self.graph, &view, &ray, tanh_distance).map(|chunks| CachedTarget {
+                view,
+                hit: hit.clone(),
+                chunks,
+            });
+        Ok(hit)
+    }
+
+    /// Faces of the blocks broken by the local character since the last call
+    pub fn take_broken_faces(&mut self) -> Vec<GraphCastHit> {
+        std::mem::take(&mut self.broken_faces)
+    }
+
+    /// Faces that blocks would have been placed against since the last call, had a character not
+    /// been in the way
+    pub fn take_obstructed_faces(&mut self) -> Vec<GraphCastHit> {
+        std::mem::take(&mut self.obstructed_faces)
+    }
+
+    /// What the local character did since the last call, for the view model to react to
+    pub fn take_gestures(&mut self) -> Vec<Gesture> {
+        std::mem::take(&mut self.gestures)
+    }
+
+    /// What the local character holds up in view: the selected material while any of it is held,
+    /// or `Void` if none is, or `None` if the world isn't seen through the character's eyes
+    pub fn held_in_view(&self) -> Option<Material> {
+        if self.observer.is_some() {
+            return None;
+        }
+        self.local_character()?;
+        if self.predicted_inventory().count(self.selected_material) == 0 {
+            return Some(Material::Void);
+        }
+        Some(self.selected_material)
+    }
+
+    /// Velocity of the camera in meters per second, relative to the camera, and the fraction of
+    /// the current footstep walked
+    pub fn camera_motion(&self) -> (na::Vector3<f32>, f32) {
+        (
+            self.camera.velocity() / self.cfg.meters_to_absolute,
+            self.camera.footstep_phase(),
+        )
+    }
+
+    /// Sounds to play since the last call, as heard from the camera
+    pub fn take_sounds(&mut self) -> Vec<Playback> {
+        let Some(step) = self.step else {
+            return Vec::new();
+        };
+        let Some(listener) = self.camera().position() else {
+            return Vec::new();
+        };
+        self.sounds
+            .drain(step)
+            .iter()
+            .filter_map(|event| audio::spatialize(&self.cfg, &self.graph, &listener, event))
+            .collect()
+    }
+
+    /// What the local character uses this step, if the use key was pressed: whatever is under the
+    /// crosshair within reach
+    fn use_target(&mut self) -> Option<InteractTarget> {
+        if !self.use_pressed || self.observer.is_some() {
+            return None;
+        }
+        let ray = Ray::new(na::Vector4::w(), -na::Vector4::z());
+        match self.pick_along(&ray, self.cfg.character.block_reach)? {
+            PickResult::Block(hit) => Some(InteractTarget::Block(hit.chunk, hit.voxel_coords)),
+            PickResult::Entity { id, .. } => Some(InteractTarget::Entity(id)),
+        }
+    }
+
+    /// What the local character throws this step, if the throw key was pressed: a unit of the
+    /// selected material, if any is held once everything else in flight has been spent
+    fn throw(&self) -> Option<Material> {
+        if !self.throw_pressed || self.observer.is_some() {
+            return None;
+        }
+        if self.predicted_inventory().count(self.selected_material) == 0 {
+            trace!(material = ?self.selected_material, "can't throw unheld material");
+            return None;
+        }
+        Some(self.selected_material)
+    }
+
+    /// Provides the logic for the player to be able to place and break blocks at will
+    fn get_local_character_block_update(&mut self) -> Option<BlockUpdate> {
+        if self.observer.is_some() {
+            // The camera can fly well beyond the character's reach
+            return None;
+        }
+        let placing = if self.place_block_pressed {
+            true
+        } else if self.break_block_pressed {
+            false
+        } else {
+            return None;
+        };
+        if placing && self.predicted_inventory().count(self.selected_material) == 0 {
+            trace!(material = ?self.selected_material, "can't place unheld material");
+            return None;
+        }
+
+        let Ok(ray_casting_result) = self.target() else {
+            tracing::warn!("Tried to run a raycast beyond generated terrain.");
+            return None;
+        };
+
+        let hit = ray_casting_result?;
+
+        let block_pos = if placing {
             self.graph.get_block_neighbor(
                 hit.chunk,
                 hit.voxel_coords,
@@ -474,17 +2597,2102 @@ impl Sim {
         } else {
             (hit.chunk, hit.voxel_coords)
         };
+        let shape = if placing {
+            let shape = placement_shape(
+                &self.graph,
+                &self.character_view()?,
+                &hit,
+                self.selected_shape,
+            );
+            self.graph.shape_in_block_neighbor(
+                hit.chunk,
+                hit.voxel_coords,
+                hit.face_axis,
+                hit.face_direction,
+                shape,
+            )
+        } else {
+            Shape::FULL
+        };
 
         let material = if placing {
-            Material::WoodPlanks
+            self.selected_material
         } else {
             Material::Void
         };
+        if material == Material::Sign {
+            info!("write on the sign with: sign <line>|<line>|...");
+        }
 
         Some(BlockUpdate {
             chunk_id: block_pos.0,
             coords: block_pos.1,
             new_material: material,
+            new_shape: shape,
+            sequence: self.block_prediction.next_sequence(),
         })
     }
 }
+
+/// Shapes of block a player can choose between placing
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlacementShape {
+    Full,
+    /// Half a block, against the bottom of the space it's placed in unless aimed at the top half
+    /// of a block's side or at the underside of a block
+    Slab,
+    /// Stairs climbing away from the viewer
+    Stairs,
+}
+
+/// How `Sim::paste_template` turns a template
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PasteOrientation {
+    /// Stood upright where it's pasted, then turned about up by this many quarter turns
+    Upright { quarter_turns: u32 },
+    /// Turned exactly so, in the axes of the chunk it's pasted into
+    Exact(Orientation),
+}
+
+/// The shape, in the coordinates of `hit.chunk`, of a block of the `selected` kind placed against
+/// the face `hit` found from `view`
+fn placement_shape(
+    graph: &Graph,
+    view: &Position,
+    hit: &GraphCastHit,
+    selected: PlacementShape,
+) -> Shape {
+    if selected == PlacementShape::Full {
+        return Shape::FULL;
+    }
+    let (Some(transform), Some((up_axis, up))) = (
+        camera::transition(graph, view.node, hit.chunk.node),
+        voxel_up(graph, hit.chunk, hit.voxel_coords),
+    ) else {
+        return Shape::FULL;
+    };
+    let vertex = hit.chunk.vertex;
+    let dual_to_grid = graph.layout().dual_to_grid_factor();
+    let to_grid = |point: na::Vector4<f32>| {
+        let dual = vertex.node_to_dual_f32() * transform * view.local * point;
+        dual.xyz() / dual.w * dual_to_grid
+    };
+    let center = na::Vector3::from(hit.voxel_coords.0.map(|x| f32::from(x) + 0.5));
+    let sign = |positive: bool| {
+        if positive {
+            CoordDirection::Plus
+        } else {
+            CoordDirection::Minus
+        }
+    };
+    let down = sign(up == CoordDirection::Minus);
+
+    match selected {
+        PlacementShape::Full => Shape::FULL,
+        PlacementShape::Slab => {
+            let top = if hit.face_axis == up_axis {
+                hit.face_direction == down
+            } else {
+                let point = to_grid(na::Vector4::w() - na::Vector4::z() * hit.tanh_distance);
+                let offset = point[up_axis as usize] - center[up_axis as usize];
+                (offset > 0.0) == (up == CoordDirection::Plus)
+            };
+            Shape::slab(up_axis, if top { up } else { down })
+        }
+        PlacementShape::Stairs => {
+            let forward = to_grid(na::Vector4::w() - na::Vector4::z() * hit.tanh_distance)
+                - to_grid(na::Vector4::w());
+            let back_axis = up_axis
+                .other_axes()
+                .into_iter()
+                .max_by(|&a, &b| {
+                    forward[a as usize]
+                        .abs()
+                        .total_cmp(&forward[b as usize].abs())
+                })
+                .unwrap();
+            Shape::stairs(
+                (up_axis, up),
+                (back_axis, sign(forward[back_axis as usize] > 0.0)),
+            )
+        }
+    }
+}
+
+/// The axis and direction of `chunk` nearest to up at the voxel `coords`, if its node is known
+fn voxel_up(graph: &Graph, chunk: ChunkId, coords: Coords) -> Option<(CoordAxis, CoordDirection)> {
+    // Terrain is rarely aligned with the voxel grid, so take up to be the nearest axis
+    let gradient = height_gradient(graph, chunk, coords)?;
+    let up_axis = CoordAxis::iter()
+        .max_by(|&a, &b| {
+            gradient[a as usize]
+                .abs()
+                .total_cmp(&gradient[b as usize].abs())
+        })
+        .unwrap();
+    let up = if gradient[up_axis as usize] > 0.0 {
+        CoordDirection::Plus
+    } else {
+        CoordDirection::Minus
+    };
+    Some((up_axis, up))
+}
+
+/// How much higher above the terrain of its node each face of the voxel `coords` in `chunk` is
+/// than the opposite face, along each axis of `chunk`, if its node is known
+pub(crate) fn height_gradient(
+    graph: &Graph,
+    chunk: ChunkId,
+    coords: Coords,
+) -> Option<na::Vector3<f32>> {
+    let node = graph.get(chunk.node).as_ref()?;
+    let vertex = chunk.vertex;
+    let dual_to_grid = graph.layout().dual_to_grid_factor();
+    let up_direction = node.state.up_direction();
+    let height = |grid: na::Vector3<f32>| {
+        let point = vertex.dual_to_node_f32() * (grid / dual_to_grid).push(1.0);
+        math::mip(&up_direction, &math::lorentz_normalize(&point))
+    };
+
+    let center = na::Vector3::from(coords.0.map(|x| f32::from(x) + 0.5));
+    Some(na::Vector3::from_fn(|axis, _| {
+        let mut above = center;
+        above[axis] += 0.5;
+        let mut below = center;
+        below[axis] -= 0.5;
+        height(above) - height(below)
+    }))
+}
+
+/// The outcome of a targeting ray cast, which holds until the view moves or the voxels the ray
+/// passed through change
+struct CachedTarget {
+    view: Position,
+    hit: Option<GraphCastHit>,
+    /// Generations of the chunks the ray passed through before it stopped
+    chunks: Vec<(ChunkId, u32)>,
+}
+
+impl CachedTarget {
+    fn is_valid(&self, graph: &Graph, view: &Position) -> bool {
+        self.view.node == view.node
+            && self.view.local == view.local
+            && self
+                .chunks
+                .iter()
+                .all(|&(chunk, generation)| graph.chunk_generation(chunk) == Some(generation))
+    }
+}
+
+/// The position a fraction `t` of the way along the geodesic from `from` to `to`, or `to` if
+/// they're too far apart in the graph to be related
+fn interpolate(graph: &Graph, from: &Position, to: &Position, t: f32) -> Position {
+    let Some(transform) = camera::transition(graph, from.node, to.node) else {
+        return *to;
+    };
+    // Measured back from `to`, so that its frame carries over
+    let back =
+        camera::tangent(&(math::mtranspose(&to.local) * transform * from.local * math::origin()));
+    Position {
+        node: to.node,
+        local: to.local * math::translate_along(&(back * (1.0 - t))),
+    }
+}
+
+/// Where something at `position`, moving at `velocity` in its own frame, is after `dt` seconds,
+/// relative to the node it's then in
+fn extrapolate_within(
+    graph: &Graph,
+    position: &Position,
+    velocity: &na::Vector3<f32>,
+    dt: f32,
+) -> Position {
+    let local = extrapolate(position, velocity, dt).local;
+    let (node, transform) = graph.normalize_transform(position.node, &local);
+    Position {
+        node,
+        local: transform * local,
+    }
+}
+
+/// Generations of the chunks `ray` passes through within `tanh_distance`, or `None` if any are
+/// unpopulated
+fn chunk_generations(
+    graph: &Graph,
+    position: &Position,
+    ray: &Ray,
+    tanh_distance: f32,
+) -> Option<Vec<(ChunkId, u32)>> {
+    let mut traverser = RayTraverser::new(graph, *position, ray, 0.0);
+    let mut result = Vec::new();
+    while let Some((chunk, _)) = traverser.next(tanh_distance) {
+        let chunk = chunk?;
+        result.push((chunk, graph.chunk_generation(chunk)?));
+    }
+    Some(result)
+}
+
+fn save_collision_trace(trace: &TraceDump, path: &Path) {
+    match trace.save(path) {
+        Ok(()) => info!(
+            "wrote {} traced steps to {}",
+            trace.steps.len(),
+            path.display()
+        ),
+        Err(e) => warn!("failed to save collision trace: {:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{mem, time::Instant};
+
+    use super::*;
+    use crate::effects::{EffectKind, EffectPool};
+    use crate::look::{LookDelta, LookInput};
+    use approx::*;
+    use common::{
+        coords::{locate_voxel, voxel_center_position},
+        dodeca::Vertex,
+        proto::{EncodedGraphRegion, FreshNode},
+        traversal::{ensure_nearby, nearby_nodes},
+        SimConfigRaw,
+    };
+    use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+    /// Follow the view position exactly
+    fn camera_cfg() -> CameraConfig {
+        CameraConfig {
+            half_life: 0.0,
+            rise_half_life: 0.0,
+            snap_distance: 0.0,
+            bob_depth: 0.0,
+            stride: 1.0,
+            interpolate_view: false,
+            follow_distance: 0.5,
+        }
+    }
+
+    /// A connection whose messages go nowhere but the returned receiver
+    fn loose_net() -> (Net, net::OutgoingReceiver) {
+        let (_, incoming) = tokio::sync::mpsc::unbounded_channel();
+        let (outgoing, sent) = net::outgoing(1024, net::STALL_TIMEOUT);
+        let net = Net {
+            incoming,
+            outgoing,
+            received: Default::default(),
+            thread: None,
+        };
+        (net, sent)
+    }
+
+    fn interpolating_sim() -> Sim {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        Sim::new(
+            cfg,
+            CameraConfig {
+                interpolate_view: true,
+                ..camera_cfg()
+            },
+            EntityId::from_bits(1),
+        )
+    }
+
+    /// Distance between the locations of `a` and `b`, which must be in the same node
+    fn separation(a: &Position, b: &Position) -> f32 {
+        assert_eq!(a.node, b.node);
+        camera::tangent(&(math::mtranspose(&a.local) * b.local * math::origin())).norm()
+    }
+
+    fn spawn_character(sim: &mut Sim, id: EntityId, position: Position) {
+        sim.handle_net(spawns(0, vec![(id, character(position))], vec![]));
+    }
+
+    fn character(position: Position) -> Vec<Component> {
+        vec![
+            Component::Position(position),
+            Component::Character(Character {
+                name: "test".into(),
+                state: CharacterState {
+                    velocity: na::zero(),
+                    on_ground: false,
+                    orientation: na::one(),
+                    teleports: 0,
+                    health: 100.0,
+                    afk: false,
+                },
+            }),
+        ]
+    }
+
+    fn spawns(
+        step: Step,
+        spawns: Vec<(EntityId, Vec<Component>)>,
+        despawns: Vec<EntityId>,
+    ) -> net::Message {
+        net::Message::Spawns(proto::Spawns {
+            step,
+            spawns,
+            despawns,
+            nodes: vec![],
+            block_updates: vec![],
+            block_fills: vec![],
+            modified_chunks: vec![],
+            chunk_diffs: vec![],
+        })
+    }
+
+    fn placement() -> CharacterInput {
+        CharacterInput {
+            movement: MovementInput::zero(),
+            jump: false,
+            no_clip: true,
+            block_update: Some(BlockUpdate {
+                chunk_id: ChunkId::new(NodeId::ROOT, Vertex::A),
+                coords: Coords([0, 0, 0]),
+                new_material: Material::Dirt,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            }),
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        }
+    }
+
+    #[test]
+    fn partial_step_view_prediction() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut sim = Sim::new(cfg, camera_cfg(), EntityId::from_bits(1));
+        let id = sim.local_character_id;
+        spawn_character(&mut sim, id, Position::origin());
+        let step_interval = sim.cfg.step_interval;
+        let input = na::Vector3::new(0.6, 0.0, -0.8);
+        sim.set_movement_input(input);
+
+        // Hold the input for a third of a step, sampled as `step` would
+        let elapsed = step_interval / 3;
+        for _ in 0..4 {
+            let dt = elapsed / 4;
+            sim.since_input_sent += dt;
+            sim.average_movement_input +=
+                sim.movement_input * dt.as_secs_f32() / step_interval.as_secs_f32();
+        }
+        sim.update_view_position();
+
+        // The view should have moved as far as the server would in that time at full rate
+        let mut position = Position {
+            node: NodeId::ROOT,
+            local: na::one(),
+        };
+        character_controller::run_character_step(
+            &sim.cfg,
+            &sim.graph,
+            &mut position,
+            &mut na::zero(),
+            &mut false,
+            &CharacterInput {
+                movement: MovementInput::new(sim.local_character_controller.orientation() * input),
+                jump: false,
+                no_clip: true,
+                block_update: None,
+                block_fill: None,
+                use_target: None,
+                throw: None,
+                external: Default::default(),
+            },
+            &mut 0.0,
+            elapsed.as_secs_f32(),
+            None,
+        );
+        let moved = math::distance(&(position.local * math::origin()), &math::origin());
+        assert!(moved > 0.0);
+        let view = sim.view().position().unwrap();
+        assert_eq!(view.node, position.node);
+        assert_abs_diff_eq!(
+            view.local * math::origin(),
+            position.local * math::origin(),
+            epsilon = 1e-3 * moved
+        );
+    }
+
+    #[test]
+    fn prediction_independent_of_frame_rate() {
+        let inputs = [
+            na::Vector3::new(1.0, 0.0, 0.0),
+            na::Vector3::new(0.0, 0.0, -1.0),
+            na::Vector3::new(0.3, 0.6, -0.2),
+            na::zero(),
+        ];
+        // The predicted position after each step, with input changing every 48ms
+        let predict = |frame: Duration| {
+            let mut sim = interpolating_sim();
+            let id = sim.local_character_id;
+            spawn_character(&mut sim, id, Position::origin());
+            let (mut net, mut sent) = loose_net();
+            let input_interval = Duration::from_millis(48);
+            let mut elapsed = Duration::ZERO;
+            let mut steps = Vec::new();
+            while elapsed < Duration::from_millis(960) {
+                let input = (elapsed.as_millis() / input_interval.as_millis()) as usize;
+                sim.set_movement_input(inputs[input % inputs.len()]);
+                sim.step(frame, &mut net);
+                elapsed += frame;
+                while let Some(msg) = sent.try_recv() {
+                    if let ClientMessage::Command(_) = msg {
+                        steps.push(*sim.prediction.predicted_position());
+                    }
+                }
+            }
+            steps
+        };
+
+        let fast = predict(Duration::from_millis(4));
+        let slow = predict(Duration::from_millis(16));
+        assert_eq!(fast.len(), 9);
+        assert_eq!(fast.len(), slow.len());
+        for (fast, slow) in fast.iter().zip(&slow) {
+            assert_eq!(fast.node, slow.node);
+            assert_eq!(fast.local, slow.local);
+        }
+        assert!(separation(&Position::origin(), fast.last().unwrap()) > 0.0);
+    }
+
+    #[test]
+    fn view_interpolated_between_steps() {
+        let mut sim = interpolating_sim();
+        let id = sim.local_character_id;
+        spawn_character(&mut sim, id, Position::origin());
+        let (mut net, _sent) = loose_net();
+        let step_interval = sim.cfg.step_interval;
+        sim.set_movement_input(na::Vector3::new(0.6, 0.0, -0.8));
+        sim.step(step_interval, &mut net);
+        let start = Position::origin();
+        let end = *sim.prediction.predicted_position();
+        let moved = separation(&start, &end);
+        assert!(moved > 0.0);
+        // Nothing new is predicted mid-step, so the view only catches up with the last step
+        sim.set_movement_input(na::zero());
+        assert_abs_diff_eq!(
+            sim.view().position().unwrap().local * math::origin(),
+            start.local * math::origin(),
+            epsilon = 1e-6
+        );
+        sim.step(step_interval / 2, &mut net);
+        let view = sim.view().position().unwrap();
+        assert_relative_eq!(separation(&start, &view), moved / 2.0, max_relative = 1e-3);
+        assert_relative_eq!(separation(&view, &end), moved / 2.0, max_relative = 1e-3);
+        sim.step(step_interval / 2 - Duration::from_nanos(1), &mut net);
+        assert_relative_eq!(
+            separation(&sim.view().position().unwrap(), &end),
+            0.0,
+            epsilon = 1e-3 * moved
+        );
+    }
+
+    #[test]
+    fn id_collisions_disconnect() {
+        let mut sim = interpolating_sim();
+        let (mut net, _sent) = loose_net();
+        let step_interval = sim.cfg.step_interval;
+        let id = EntityId::from_bits(2);
+        spawn_character(&mut sim, id, Position::origin());
+        for i in 1..=MAX_PROTOCOL_ERRORS {
+            // Tolerated until there have been too many
+            sim.step(step_interval, &mut net);
+            assert_eq!(sim.debug_info().connection, ConnectionState::Connected);
+            spawn_character(&mut sim, id, Position::origin());
+            assert_eq!(sim.debug_info().protocol_errors, i);
+            // The latest spawn replaces the earlier entity
+            assert_eq!(sim.world.query::<&EntityId>().iter().count(), 1);
+        }
+        sim.step(step_interval, &mut net);
+        assert_eq!(sim.debug_info().connection, ConnectionState::Closed);
+        assert_eq!(net.outgoing.state(), ConnectionState::Closed);
+    }
+
+    #[test]
+    fn closed_connection_reported() {
+        let mut sim = interpolating_sim();
+        let (mut net, sent) = loose_net();
+        let step_interval = sim.cfg.step_interval;
+        sim.step(step_interval, &mut net);
+        let info = sim.debug_info();
+        assert_eq!(info.connection, ConnectionState::Connected);
+        assert_eq!(info.outgoing.unwrap().depth, 0);
+        assert!(!sim.connection_problem());
+
+        // The transport shuts down, and the next command can't be queued
+        drop(sent);
+        sim.step(step_interval, &mut net);
+        assert_eq!(sim.debug_info().connection, ConnectionState::Closed);
+        assert!(sim.connection_problem());
+    }
+
+    /// Whether any commands were sent since this was last called
+    fn commands_sent(sent: &mut net::OutgoingReceiver) -> bool {
+        let mut any = false;
+        while let Some(msg) = sent.try_recv() {
+            any |= matches!(msg, ClientMessage::Command(_));
+        }
+        any
+    }
+
+    #[test]
+    fn idle_until_local_character_spawns() {
+        let mut sim = interpolating_sim();
+        let (mut net, mut sent) = loose_net();
+        let step_interval = sim.cfg.step_interval;
+        let net::Message::Spawns(mut msg) = spawns(0, vec![], vec![]) else {
+            unreachable!()
+        };
+        msg.nodes.push(FreshNode {
+            side: dodeca::Side::A,
+            parent: NodeId::ROOT,
+        });
+        sim.handle_net(net::Message::Spawns(msg));
+        let node = sim.graph.neighbor(NodeId::ROOT, dodeca::Side::A).unwrap();
+
+        // Input goes nowhere, and nothing is generated or drawn, for as long as the spawn takes
+        sim.set_movement_input(-na::Vector3::z());
+        for _ in 0..5 {
+            sim.step(step_interval, &mut net);
+        }
+        assert_eq!(sim.session(), Session::AwaitingSpawn);
+        assert!(!commands_sent(&mut sent));
+        assert!(sim.view().position().is_none());
+        assert!(sim.nearby_nodes(10.0).is_empty());
+        assert!(sim.target().unwrap().is_none());
+        assert!(sim.graph.get(node).is_none());
+        assert_eq!(sim.toggle_observer(), None);
+        assert_eq!(sim.debug_info().protocol_errors, 0);
+
+        let id = sim.local_character_id;
+        let position = Position {
+            node,
+            local: na::one(),
+        };
+        spawn_character(&mut sim, id, position);
+        let entity = sim.entity_ids[&id];
+        assert_eq!(sim.session(), Session::Playing { entity });
+        assert!(sim.graph.get(node).is_some());
+        let view = sim.view().position().unwrap();
+        assert_eq!(view.node, node);
+        assert_abs_diff_eq!(separation(&view, &position), 0.0, epsilon = 1e-5);
+        sim.step(step_interval, &mut net);
+        assert!(commands_sent(&mut sent));
+        assert_eq!(sim.debug_info().protocol_errors, 0);
+    }
+
+    #[test]
+    fn spectating_keeps_sending_input() {
+        let (mut sim, _) = picking_sim();
+        let (mut net, mut sent) = loose_net();
+        let entity = sim.local_character().unwrap();
+        assert_eq!(sim.toggle_observer(), Some(true));
+        assert_eq!(sim.session(), Session::Spectating { entity });
+        sim.step(sim.cfg.step_interval, &mut net);
+        assert!(commands_sent(&mut sent));
+        assert_eq!(sim.toggle_observer(), Some(false));
+        assert_eq!(sim.session(), Session::Playing { entity });
+    }
+
+    #[test]
+    fn connection_lost_in_any_session() {
+        let step_interval = SimConfig::from_raw(&SimConfigRaw::default()).step_interval;
+        // Waiting for the spawn, noticed by the transport
+        let mut sim = interpolating_sim();
+        let (mut net, sent) = loose_net();
+        drop(sent);
+        sim.step(step_interval, &mut net);
+        assert_eq!(sim.session(), Session::Disconnected { entity: None });
+        assert!(sim.view().position().is_none());
+
+        // Playing or spectating, noticed by the caller, after which the world stays as it was but
+        // no more input is sent
+        for spectate in [false, true] {
+            let (mut sim, _) = picking_sim();
+            let (mut net, mut sent) = loose_net();
+            let entity = sim.local_character().unwrap();
+            if spectate {
+                assert_eq!(sim.toggle_observer(), Some(true));
+            }
+            sim.connection_lost();
+            assert_eq!(
+                sim.session(),
+                Session::Disconnected {
+                    entity: Some(entity)
+                }
+            );
+            sim.step(step_interval, &mut net);
+            assert_eq!(sim.debug_info().connection, ConnectionState::Closed);
+            assert!(!commands_sent(&mut sent));
+            assert!(sim.view().position().is_some());
+        }
+    }
+
+    fn state_delta(step: Step, latest_input: u16, id: EntityId) -> proto::StateDelta {
+        proto::StateDelta {
+            step,
+            latest_input,
+            positions: vec![(id, Position::origin())],
+            character_states: vec![(
+                id,
+                CharacterState {
+                    velocity: na::zero(),
+                    on_ground: false,
+                    orientation: na::one(),
+                    teleports: 0,
+                    health: 100.0,
+                    afk: false,
+                },
+            )],
+            world_time: 0.0,
+            tether: None,
+        }
+    }
+
+    /// A `StateDelta` moving `id` to `x` meters along the x axis
+    fn move_to(sim: &Sim, step: Step, id: EntityId, x: f32) -> net::Message {
+        let mut delta = state_delta(step, 0, id);
+        delta.positions[0].1 = along_x(sim, x);
+        net::Message::StateDelta(delta)
+    }
+
+    fn along_x(sim: &Sim, x: f32) -> Position {
+        Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&(na::Vector3::x() * x * sim.cfg.meters_to_absolute)),
+        }
+    }
+
+    /// Assert that `id` is the only entity, and is at `position`
+    fn assert_only_entity(sim: &Sim, id: EntityId, position: &Position) {
+        assert_eq!(sim.world.len(), 1);
+        let entity = sim.entity_ids[&id];
+        assert_eq!(*sim.world.get::<&EntityId>(entity).unwrap(), id);
+        assert_eq!(
+            sim.world.get::<&Position>(entity).unwrap().local,
+            position.local
+        );
+        assert_eq!(sim.graph_entities.get(NodeId::ROOT), [entity]);
+    }
+
+    #[test]
+    fn late_delta_for_despawned_entity() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut sim = Sim::new(cfg, camera_cfg(), EntityId::from_bits(1));
+        let id = EntityId::from_bits(2);
+        sim.handle_net(spawns(0, vec![(id, character(along_x(&sim, 0.0)))], vec![]));
+        sim.handle_net(move_to(&sim, 1, id, 1.0));
+        sim.handle_net(spawns(3, vec![], vec![id]));
+
+        // Sent before the despawn, but arriving after
+        sim.handle_net(move_to(&sim, 2, id, 2.0));
+        assert!(sim.world.is_empty());
+        assert!(sim.entity_ids.is_empty());
+        assert!(sim.graph_entities.get(NodeId::ROOT).is_empty());
+    }
+
+    #[test]
+    fn missing_parent_requested_again() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut sim = Sim::new(cfg, camera_cfg(), EntityId::from_bits(1));
+        sim.set_node_resync_steps(5);
+        let (mut net, mut sent) = loose_net();
+
+        // A node branching from one that never arrives
+        let mut server = Graph::new(sim.cfg.chunk_size);
+        ensure_nearby(&mut server, &Position::origin(), 2.0);
+        let orphan = server
+            .fresh()
+            .iter()
+            .filter_map(|&id| {
+                let side = server.parent(id)?;
+                Some(proto::FreshNode {
+                    side,
+                    parent: server.neighbor(id, side).unwrap(),
+                })
+            })
+            .find(|x| x.parent != NodeId::ROOT)
+            .unwrap();
+        let id = EntityId::from_bits(2);
+        let net::Message::Spawns(mut msg) =
+            spawns(0, vec![(id, character(along_x(&sim, 0.0)))], vec![])
+        else {
+            unreachable!()
+        };
+        msg.nodes.push(orphan);
+        sim.handle_net(net::Message::Spawns(msg));
+        assert_eq!(sim.debug_info().pending_nodes, 1);
+
+        let mut requested = Vec::new();
+        for step in 1..=6 {
+            sim.handle_net(move_to(&sim, step, id, 0.0));
+            sim.step(Duration::from_millis(1), &mut net);
+            while let Some(msg) = sent.try_recv() {
+                if let ClientMessage::ResyncNodes(nodes) = msg {
+                    requested.push((step, nodes));
+                }
+            }
+        }
+        assert_eq!(requested, [(6, vec![orphan.parent])]);
+    }
+
+    #[test]
+    fn nodes_hinted_near_frontier() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let id = EntityId::from_bits(1);
+        let mut sim = Sim::new(cfg, camera_cfg(), id);
+        spawn_character(&mut sim, id, Position::origin());
+        ensure_nearby(&mut sim.graph, &Position::origin(), 2.0);
+        let frontier = nearest_missing_node(&sim.graph, &Position::origin(), 10.0).unwrap() as f32;
+
+        // Standing still, the hint is wanted once the view reaches the nearest missing node
+        sim.cfg.view_distance = frontier - 1e-3;
+        assert!(sim.node_hint().is_none());
+        sim.cfg.view_distance = frontier + 1e-3;
+        let hint = sim.node_hint().unwrap();
+        assert_eq!(hint.center_path, NodePath::default());
+        assert_abs_diff_eq!(hint.radius, sim.cfg.view_distance, epsilon = 1e-3);
+
+        // Sent no more often than the interval allows, and only to servers that understand it
+        let (mut net, mut sent) = loose_net();
+        let mut count_hints = |sim: &mut Sim| {
+            let mut hints = 0;
+            for _ in 0..10 {
+                sim.step(Duration::from_millis(100), &mut net);
+                while let Some(msg) = sent.try_recv() {
+                    hints += usize::from(matches!(msg, ClientMessage::NodeInterestHint(_)));
+                }
+            }
+            hints
+        };
+        assert_eq!(count_hints(&mut sim), 3);
+        sim.set_capabilities(Capabilities::NONE);
+        assert_eq!(count_hints(&mut sim), 0);
+    }
+
+    #[test]
+    fn graph_regions_applied_in_any_order() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut sim = Sim::new(cfg, camera_cfg(), EntityId::from_bits(1));
+        let mut server = Graph::new(sim.cfg.chunk_size);
+        ensure_nearby(&mut server, &Position::origin(), 3.0);
+        let nodes = server
+            .tree()
+            .map(|(side, parent)| {
+                let node = server.neighbor(parent, side).unwrap();
+                (node, FreshNode { side, parent })
+            })
+            .collect::<Vec<_>>();
+
+        // Regions rooted at the second generation, each holding its root's children
+        let mut regions = nodes
+            .iter()
+            .filter(|&&(node, _)| server.length(node) == 2)
+            .map(|&(root, _)| proto::GraphRegion {
+                root: NodePath::to(&server, root),
+                nodes: nodes
+                    .iter()
+                    .filter(|&&(_, x)| x.parent == root)
+                    .map(|&(_, x)| x)
+                    .collect(),
+                modified_chunks: vec![],
+                chunk_diffs: vec![],
+                block_entities: vec![],
+            })
+            .collect::<Vec<_>>();
+        // A change to a chunk the client hasn't generated
+        let i = regions.iter().position(|x| !x.nodes.is_empty()).unwrap();
+        let first = regions[i].nodes[0];
+        let chunk = ChunkId::new(
+            server.neighbor(first.parent, first.side).unwrap(),
+            Vertex::A,
+        );
+        let coords = Coords([1, 2, 3]);
+        regions[i].chunk_diffs.push(ChunkDiff {
+            chunk,
+            changes: vec![(coords, Material::Dirt, Shape::FULL)],
+        });
+
+        // The generation beyond arrives first, and waits for its parents
+        let beyond = nodes
+            .iter()
+            .filter(|&&(node, _)| server.length(node) == 4)
+            .map(|&(_, x)| x)
+            .collect::<Vec<_>>();
+        assert!(!beyond.is_empty());
+        let net::Message::Spawns(mut msg) = spawns(0, vec![], vec![]) else {
+            unreachable!()
+        };
+        msg.nodes = beyond.clone();
+        sim.handle_net(net::Message::Spawns(msg));
+        assert_eq!(sim.debug_info().pending_nodes, beyond.len());
+
+        regions.shuffle(&mut SmallRng::seed_from_u64(0));
+        sim.handle_net(net::Message::GraphRegions(
+            regions.iter().map(EncodedGraphRegion::encode).collect(),
+        ));
+        assert_eq!(sim.debug_info().pending_nodes, 0);
+        for &(node, _) in &nodes {
+            if server.length(node) <= 4 {
+                assert!(sim.graph.contains(node));
+            }
+        }
+        sim.populate_chunk(chunk, VoxelData::Solid(Material::Void));
+        assert_eq!(sim.graph.get_block(chunk, coords), Some(Material::Dirt));
+    }
+
+    #[test]
+    fn spawn_orientation_adopted() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut sim = Sim::new(cfg, camera_cfg(), EntityId::from_bits(1));
+        sim.no_clip = false;
+        let id = sim.local_character_id;
+        let orientation = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 2.0);
+        let mut components = character(Position::origin());
+        let Component::Character(ref mut ch) = components[1] else {
+            unreachable!()
+        };
+        ch.state.orientation = orientation;
+        sim.handle_net(spawns(0, vec![(id, components)], vec![]));
+        assert_eq!(sim.local_character_controller.orientation(), orientation);
+
+        // Mouse movement turns the character from there
+        sim.look(0.4, 0.0, 0.0);
+        sim.look(-0.1, 0.0, 0.0);
+        assert_abs_diff_eq!(
+            sim.local_character_controller.orientation(),
+            orientation * na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), 0.3),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn late_latched_look() {
+        let spawned = || {
+            let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+            let id = EntityId::from_bits(1);
+            let mut sim = Sim::new(cfg, camera_cfg(), id);
+            sim.no_clip = false;
+            ensure_nearby(&mut sim.graph, &Position::origin(), 2.0);
+            spawn_character(&mut sim, id, Position::origin());
+            sim
+        };
+        let mut rng = SmallRng::seed_from_u64(675);
+        let mut delta = || LookDelta {
+            yaw: rng.gen_range(-0.1..0.1),
+            pitch: rng.gen_range(-0.02..0.02),
+            roll: 0.0,
+        };
+        let drain = |look: &LookInput, sim: &mut Sim| {
+            if let Some(x) = look.drain() {
+                sim.look(x.yaw, x.pitch, x.roll);
+            }
+        };
+        let orientations = |sent: &mut net::OutgoingReceiver| {
+            let mut orientations = Vec::new();
+            while let Some(msg) = sent.try_recv() {
+                if let ClientMessage::Command(command) = msg {
+                    orientations.push(command.orientation);
+                }
+            }
+            orientations
+        };
+
+        // Turned as each delta arrives, as if every frame were drawn the moment it's stepped
+        let mut reference = spawned();
+        let (mut reference_net, mut reference_sent) = loose_net();
+        // Turned at the early and late drains of each frame
+        let mut latched = spawned();
+        let (mut latched_net, mut latched_sent) = loose_net();
+        let look = LookInput::new();
+        let dt = latched.cfg.step_interval;
+        for _ in 0..50 {
+            let early = delta();
+            reference.look(early.yaw, early.pitch, early.roll);
+            look.push(early, Instant::now());
+            drain(&look, &mut latched);
+            reference.step(dt, &mut reference_net);
+            latched.step(dt, &mut latched_net);
+
+            // Arrives while the frame is prepared, so only the late drain picks it up
+            let late = delta();
+            reference.look(late.yaw, late.pitch, late.roll);
+            look.push(late, Instant::now());
+            drain(&look, &mut latched);
+            assert!(look.rendered(Instant::now()).is_some());
+
+            // Rendered straight away, though the camera only follows the view at the next step
+            let ViewSource::World(view) = latched.view() else {
+                panic!("character not spawned");
+            };
+            let ViewSource::World(camera) = latched.latched_camera() else {
+                panic!("character not spawned");
+            };
+            assert_eq!(camera.node, view.node);
+            assert_abs_diff_eq!(camera.local, view.local, epsilon = 1e-4);
+        }
+
+        // Each delta turned the character exactly once, by the time of the next command
+        let expected = orientations(&mut reference_sent);
+        let actual = orientations(&mut latched_sent);
+        assert!(!expected.is_empty());
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(&expected) {
+            assert_abs_diff_eq!(actual, expected, epsilon = 1e-5);
+        }
+        assert_abs_diff_eq!(
+            latched.local_character_controller.orientation(),
+            reference.local_character_controller.orientation(),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn stale_delta_for_respawned_id() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut sim = Sim::new(cfg, camera_cfg(), EntityId::from_bits(1));
+        let id = EntityId::from_bits(2);
+        sim.handle_net(spawns(0, vec![(id, character(along_x(&sim, 0.0)))], vec![]));
+        sim.handle_net(move_to(&sim, 1, id, 1.0));
+        sim.handle_net(spawns(5, vec![], vec![id]));
+        let respawned = along_x(&sim, 5.0);
+        sim.handle_net(spawns(6, vec![(id, character(respawned))], vec![]));
+
+        // Describes the previous entity with the same ID
+        sim.handle_net(move_to(&sim, 4, id, 4.0));
+        assert_only_entity(&sim, id, &respawned);
+
+        sim.handle_net(move_to(&sim, 7, id, 7.0));
+        assert_only_entity(&sim, id, &along_x(&sim, 7.0));
+    }
+
+    #[test]
+    fn despawn_and_respawn_in_one_message() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut sim = Sim::new(cfg, camera_cfg(), EntityId::from_bits(1));
+        let id = EntityId::from_bits(2);
+        sim.handle_net(spawns(0, vec![(id, character(along_x(&sim, 0.0)))], vec![]));
+        let respawned = along_x(&sim, 5.0);
+        sim.handle_net(spawns(5, vec![(id, character(respawned))], vec![id]));
+        assert_only_entity(&sim, id, &respawned);
+
+        sim.handle_net(move_to(&sim, 3, id, 3.0));
+        assert_only_entity(&sim, id, &respawned);
+
+        sim.handle_net(move_to(&sim, 6, id, 6.0));
+        assert_only_entity(&sim, id, &along_x(&sim, 6.0));
+
+        // The ID is no longer considered despawned
+        sim.handle_net(spawns(7, vec![], vec![id]));
+        assert!(sim.world.is_empty());
+    }
+
+    #[test]
+    fn rejected_placement_is_rolled_back() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let id = EntityId::from_bits(1);
+        let mut sim = Sim::new(cfg, camera_cfg(), id);
+        spawn_character(&mut sim, id, Position::origin());
+        let mut held = Inventory::default();
+        assert!(held.try_add(Material::Dirt, 1));
+        sim.handle_net(net::Message::Inventory(proto::InventoryUpdate {
+            latest_input: 0,
+            inventory: held,
+        }));
+        let dirt = |sim: &Sim| sim.predicted_inventory().count(Material::Dirt);
+        assert_eq!(dirt(&sim), 1);
+
+        // The server processes the placement without changing the inventory
+        let generation = sim.prediction.push(&sim.cfg, &sim.graph, &placement());
+        assert_eq!(dirt(&sim), 0);
+        sim.handle_net(net::Message::StateDelta(state_delta(1, generation, id)));
+        assert_eq!(dirt(&sim), 1);
+
+        // The server accepts the placement, reporting the new inventory before the acknowledgement
+        let generation = sim.prediction.push(&sim.cfg, &sim.graph, &placement());
+        assert_eq!(dirt(&sim), 0);
+        sim.handle_net(net::Message::Inventory(proto::InventoryUpdate {
+            latest_input: generation,
+            inventory: Inventory::default(),
+        }));
+        assert_eq!(dirt(&sim), 0);
+        sim.handle_net(net::Message::StateDelta(state_delta(2, generation, id)));
+        assert_eq!(dirt(&sim), 0);
+    }
+
+    #[test]
+    fn throws_are_predicted() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let id = EntityId::from_bits(1);
+        let mut sim = Sim::new(cfg, camera_cfg(), id);
+        spawn_character(&mut sim, id, Position::origin());
+        let material = sim.selected_material();
+        sim.set_throw_pressed_true();
+        assert_eq!(sim.throw(), None, "nothing held");
+
+        let mut held = Inventory::default();
+        assert!(held.try_add(material, 1));
+        sim.handle_net(net::Message::Inventory(proto::InventoryUpdate {
+            latest_input: 0,
+            inventory: held,
+        }));
+        assert_eq!(sim.throw(), Some(material));
+        let throw = CharacterInput {
+            block_update: None,
+            block_fill: None,
+            throw: Some(material),
+            ..placement()
+        };
+        let generation = sim.prediction.push(&sim.cfg, &sim.graph, &throw);
+        assert_eq!(sim.predicted_inventory().count(material), 0);
+        assert_eq!(sim.throw(), None, "only unit already thrown");
+
+        // The server refuses the throw, leaving the inventory unchanged
+        sim.handle_net(net::Message::StateDelta(state_delta(1, generation, id)));
+        assert_eq!(sim.predicted_inventory().count(material), 1);
+        assert_eq!(sim.throw(), Some(material));
+    }
+
+    /// A sim whose local character is at the origin, surrounded by empty space, and a camera
+    /// frustum
+    fn picking_sim() -> (Sim, Frustum) {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut sim = Sim::new(cfg, camera_cfg(), EntityId::from_bits(1));
+        ensure_nearby(&mut sim.graph, &Position::origin(), 3.0);
+        populate_fresh_nodes(&mut sim.graph);
+        for (node, _) in nearby_nodes(&sim.graph, &Position::origin(), 3.0) {
+            for vertex in Vertex::iter() {
+                sim.graph.populate_chunk(
+                    ChunkId::new(node, vertex),
+                    VoxelData::Solid(Material::Void),
+                    false,
+                );
+            }
+        }
+        let id = sim.local_character_id;
+        spawn_character(&mut sim, id, Position::origin());
+        (sim, Frustum::from_vfov(std::f32::consts::FRAC_PI_4, 1.6))
+    }
+
+    #[test]
+    fn revoked_no_clip_is_predicted() {
+        let (mut sim, _) = picking_sim();
+        let id = sim.local_character_id;
+        let flying = CharacterInput {
+            movement: MovementInput::new(na::Vector3::x()),
+            jump: false,
+            no_clip: true,
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        };
+        let generations = (0..6)
+            .map(|_| sim.prediction.push(&sim.cfg, &sim.graph, &flying))
+            .collect::<Vec<_>>();
+        let unrestricted = *sim.prediction.predicted_position();
+
+        // The server had applied the first two inputs when no-clip was revoked, and restricts the
+        // rest
+        sim.handle_net(net::Message::MovementModes(proto::MovementModesUpdate {
+            latest_input: generations[1],
+            allowed: MovementModes::NONE,
+        }));
+        assert!(!sim.no_clip);
+        let mut server = (Position::origin(), na::Vector3::zeros(), false);
+        let mut acknowledged = None;
+        for i in 0..generations.len() {
+            let mut input = flying.clone();
+            assert_eq!(input.restrict(MovementModes::NONE), i > 1);
+            character_controller::run_character_step(
+                &sim.cfg,
+                &sim.graph,
+                &mut server.0,
+                &mut server.1,
+                &mut server.2,
+                &input,
+                &mut 0.0,
+                sim.cfg.step_interval.as_secs_f32(),
+                None,
+            );
+            if i == 2 {
+                acknowledged = Some(server);
+            }
+        }
+        let acknowledged = acknowledged.unwrap();
+        let mut delta = state_delta(1, generations[2], id);
+        delta.positions[0].1 = acknowledged.0;
+        delta.character_states[0].1.velocity = acknowledged.1;
+        delta.character_states[0].1.on_ground = acknowledged.2;
+        sim.handle_net(net::Message::StateDelta(delta));
+        while sim.prediction.advance_replay(&sim.cfg, &sim.graph) > 0 {}
+
+        // The prediction replays the inputs still in flight as the server will apply them
+        assert_ne!(unrestricted.local, server.0.local);
+        assert_eq!(sim.prediction.predicted_position().node, server.0.node);
+        assert_eq!(sim.prediction.predicted_position().local, server.0.local);
+    }
+
+    #[test]
+    fn placement_judged_where_the_step_ends() {
+        let (mut sim, _) = picking_sim();
+        let id = sim.local_character_id;
+        // Falling a few voxels per step
+        let up = sim.graph.get_relative_up(&Position::origin()).unwrap();
+        let mut delta = state_delta(1, 0, id);
+        delta.character_states[0].1.velocity = -up.into_inner() * 20.0 * sim.cfg.meters_to_absolute;
+        sim.handle_net(net::Message::StateDelta(delta));
+        while sim.prediction.advance_replay(&sim.cfg, &sim.graph) > 0 {}
+        let falling = CharacterInput {
+            movement: MovementInput::zero(),
+            jump: false,
+            no_clip: false,
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        };
+
+        // Placing a block where the character will be by the time the server sees it
+        let mut end = *sim.prediction.predicted_position();
+        character_controller::run_character_step(
+            &sim.cfg,
+            &sim.graph,
+            &mut end,
+            &mut sim.prediction.predicted_velocity().clone(),
+            &mut false,
+            &falling,
+            &mut 0.0,
+            sim.cfg.step_interval.as_secs_f32(),
+            None,
+        );
+        let (chunk_id, coords, _) = locate_voxel(&sim.graph, sim.graph.layout(), &end).unwrap();
+        let update = BlockUpdate {
+            chunk_id,
+            coords,
+            new_material: Material::Dirt,
+            new_shape: Shape::FULL,
+            sequence: 0,
+        };
+        let radius = sim.cfg.character.character_radius;
+        assert!(!placement::obstructs(
+            &sim.graph,
+            &update,
+            sim.prediction.predicted_position(),
+            radius
+        ));
+        assert!(sim.placement_obstructed(&update, &falling));
+
+        // A character that passes through blocks is never in the way
+        let flying = CharacterInput {
+            no_clip: true,
+            ..falling
+        };
+        assert!(!sim.placement_obstructed(&update, &flying));
+    }
+
+    #[test]
+    fn observer_round_trip_leaves_character_alone() {
+        let (mut sim, _) = picking_sim();
+        let (mut net, _sent) = loose_net();
+        sim.no_clip = true;
+        let dt = sim.cfg.step_interval / 3;
+        sim.step(dt, &mut net);
+        let character = |sim: &Sim| {
+            let position = *sim.prediction.predicted_position();
+            (
+                position.node,
+                position.local,
+                *sim.prediction.predicted_velocity(),
+                *sim.prediction.predicted_on_ground(),
+                sim.character_view().unwrap().local,
+            )
+        };
+        let before = character(&sim);
+        let view = sim.view().position().unwrap();
+
+        // The camera flies and turns, while the character is sent no movement
+        assert_eq!(sim.toggle_observer(), Some(true));
+        for _ in 0..12 {
+            sim.set_movement_input(-na::Vector3::z());
+            sim.look(0.01, 0.0, 0.0);
+            sim.step(dt, &mut net);
+        }
+        let observed = sim.view().position().unwrap();
+        assert_ne!(observed.local, view.local);
+        assert_eq!(sim.debug_info().observer.unwrap().local, observed.local);
+        assert_eq!(character(&sim), before);
+
+        // Returning finds the character as it was left
+        assert_eq!(sim.toggle_observer(), Some(false));
+        let returned = sim.view().position().unwrap();
+        assert_eq!(returned.node, view.node);
+        assert_eq!(returned.local, view.local);
+        assert!(sim.debug_info().observer.is_none());
+    }
+
+    #[test]
+    fn graph_extended_around_observer() {
+        let mut raw = SimConfigRaw {
+            view_distance: Some(20.0),
+            ..Default::default()
+        };
+        raw.character.no_clip_movement_speed = Some(50.0);
+        let mut sim = Sim::new(
+            SimConfig::from_raw(&raw),
+            camera_cfg(),
+            EntityId::from_bits(1),
+        );
+        let (mut net, _sent) = loose_net();
+        let id = sim.local_character_id;
+        spawn_character(&mut sim, id, Position::origin());
+        // As the server would build it around the character
+        let view_distance = f64::from(sim.cfg.view_distance);
+        ensure_nearby(&mut sim.graph, &Position::origin(), view_distance);
+        sim.population.enqueue_fresh(&mut sim.graph);
+        let served = sim.graph.len();
+        let dt = Duration::from_millis(50);
+        // The character alone never builds anything
+        for _ in 0..5 {
+            sim.set_movement_input(-na::Vector3::z());
+            sim.step(dt, &mut net);
+        }
+        assert_eq!(sim.graph.len(), served);
+        assert_eq!(sim.toggle_observer(), Some(true));
+        for _ in 0..40 {
+            sim.set_movement_input(-na::Vector3::z());
+            sim.step(dt, &mut net);
+        }
+        assert!(sim.graph.len() > served);
+
+        // The camera's surroundings are generated and drawn, and the character's no longer are
+        let camera = sim.view().position().unwrap();
+        let character = sim.character_view().unwrap();
+        assert_ne!(camera.node, character.node);
+        for (node, _) in nearby_nodes(&sim.graph, &camera, URGENT_POPULATION_DISTANCE) {
+            assert!(sim.graph.get(node).is_some());
+        }
+        let drawn = sim
+            .nearby_nodes(view_distance)
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect::<Vec<_>>();
+        assert!(drawn.contains(&camera.node));
+        assert!(!drawn.contains(&character.node));
+    }
+
+    fn named_character(position: Position, name: &str) -> Vec<Component> {
+        let mut components = character(position);
+        for component in &mut components {
+            if let Component::Character(ref mut ch) = *component {
+                ch.name = name.into();
+            }
+        }
+        components
+    }
+
+    /// The characters `ClientMessage::Follow` asked the server to tell of the surroundings of
+    fn follows_sent(sent: &mut net::OutgoingReceiver) -> Vec<Option<String>> {
+        let mut follows = Vec::new();
+        while let Some(msg) = sent.try_recv() {
+            if let ClientMessage::Follow(name) = msg {
+                follows.push(name);
+            }
+        }
+        follows
+    }
+
+    #[test]
+    fn lost_follow_target_leaves_camera_in_place() {
+        let (mut sim, _) = picking_sim();
+        let (mut net, mut sent) = loose_net();
+        sim.follow_distance = 0.5 * sim.cfg.meters_to_absolute;
+        let dt = sim.cfg.step_interval;
+        let local = sim.local_character().unwrap();
+        let id = EntityId::from_bits(2);
+        let bob = named_character(along_x(&sim, 1.0), "bob");
+        sim.handle_net(spawns(0, vec![(id, bob)], vec![]));
+        assert!(!sim.follow("alice".into(), false));
+        assert!(sim.follow("bob".into(), false));
+        assert_eq!(sim.session(), Session::Spectating { entity: local });
+
+        // The camera trails the target wherever it goes, and the server is asked to tell of its
+        // surroundings
+        let mut last = None;
+        for (step, x) in [(1, 1.0), (2, 1.5)] {
+            sim.handle_net(move_to(&sim, step, id, x));
+            sim.step(dt, &mut net);
+            let target = along_x(&sim, x).local * math::origin();
+            let view = sim.view().position().unwrap();
+            assert_eq!(view.node, NodeId::ROOT);
+            let distance = math::distance(&(view.local * math::origin()), &target);
+            assert_abs_diff_eq!(distance, sim.follow_distance, epsilon = 1e-4);
+            last = Some(view);
+        }
+        assert_eq!(follows_sent(&mut sent), [Some(String::from("bob"))]);
+
+        // Once it's gone, the camera flies freely from where it was, and the player is told
+        sim.handle_net(spawns(3, vec![], vec![id]));
+        sim.step(dt, &mut net);
+        let last = last.unwrap();
+        let view = sim.view().position().unwrap();
+        assert_eq!(view.node, last.node);
+        assert_abs_diff_eq!(view.local, last.local, epsilon = 1e-5);
+        assert_eq!(sim.followed(), None);
+        assert_eq!(sim.follow_lost(), Some("bob"));
+        assert_eq!(sim.session(), Session::Spectating { entity: local });
+        assert_eq!(follows_sent(&mut sent), [None]);
+        for _ in 0..(FOLLOW_LOST_NOTICE.as_millis() / dt.as_millis()) {
+            sim.step(dt, &mut net);
+        }
+        assert_eq!(sim.follow_lost(), None);
+    }
+
+    #[test]
+    fn refused_follow_is_lost() {
+        let (mut sim, _) = picking_sim();
+        let (mut net, mut sent) = loose_net();
+        let dt = sim.cfg.step_interval;
+        let id = EntityId::from_bits(2);
+        let bob = named_character(along_x(&sim, 3.0), "bob");
+        sim.handle_net(spawns(0, vec![(id, bob)], vec![]));
+        assert!(sim.follow("bob".into(), true));
+        sim.step(dt, &mut net);
+        assert_eq!(follows_sent(&mut sent), [Some(String::from("bob"))]);
+
+        // An answer to an earlier ask is no refusal
+        sim.follow_unanswered += 1;
+        sim.handle_net(net::Message::Following(None));
+        assert_eq!(sim.followed(), Some("bob"));
+
+        sim.handle_net(net::Message::Following(None));
+        assert_eq!(sim.followed(), None);
+        assert_eq!(sim.follow_lost(), Some("bob"));
+        // Nothing more to ask of a server that already gave up on it
+        sim.step(dt, &mut net);
+        assert!(follows_sent(&mut sent).is_empty());
+    }
+
+    #[test]
+    fn denied_no_clip_toggle() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw {
+            allow_no_clip: Some(false),
+            ..Default::default()
+        });
+        let mut sim = Sim::new(cfg, camera_cfg(), EntityId::from_bits(1));
+        assert!(!sim.no_clip);
+        assert!(!sim.toggle_no_clip());
+        assert!(!sim.toggle_no_clip);
+
+        sim.handle_net(net::Message::MovementModes(proto::MovementModesUpdate {
+            latest_input: 0,
+            allowed: MovementModes::NO_CLIP,
+        }));
+        assert!(sim.toggle_no_clip());
+        assert!(sim.toggle_no_clip);
+    }
+
+    /// Every voxel in the root node, with its center in the root node's coordinates
+    fn root_voxels(sim: &Sim) -> Vec<(ChunkId, Coords, na::Vector4<f32>)> {
+        let dimension = sim.cfg.chunk_size;
+        let mut result = Vec::new();
+        for vertex in Vertex::iter() {
+            for z in 0..dimension {
+                for y in 0..dimension {
+                    for x in 0..dimension {
+                        let chunk = ChunkId::new(NodeId::ROOT, vertex);
+                        let coords = Coords([x, y, z]);
+                        let center = voxel_center_position(sim.graph.layout(), chunk, coords);
+                        result.push((chunk, coords, center.local * math::origin()));
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Where a point in front of the viewer at the origin appears on the screen
+    fn project(frustum: &Frustum, point: &na::Vector4<f32>) -> Option<na::Point2<f32>> {
+        if point.z >= 0.0 {
+            return None;
+        }
+        let klein = na::Point3::from(point.xyz() / point.w);
+        Some(frustum.projection(1.0e-4).transform_point(&klein).xy())
+    }
+
+    fn fill(sim: &mut Sim, chunk_id: ChunkId, coords: Coords) {
+        assert_eq!(
+            sim.graph.update_block(&BlockUpdate {
+                chunk_id,
+                coords,
+                new_material: Material::Dirt,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            }),
+            BlockUpdateOutcome::Applied {
+                previous: Material::Void,
+                previous_shape: Shape::FULL,
+            }
+        );
+    }
+
+    #[test]
+    fn center_pick_matches_crosshair() {
+        let (mut sim, frustum) = picking_sim();
+        for (chunk, coords, center) in root_voxels(&sim) {
+            if center.z < 0.0 && math::distance(&center, &math::origin()) > 0.3 {
+                fill(&mut sim, chunk, coords);
+            }
+        }
+
+        let Some(PickResult::Block(picked)) = sim.pick(&frustum, na::Point2::origin(), 1.0) else {
+            panic!("center of the screen should be obstructed");
+        };
+        // As in `get_local_character_block_update`
+        let crosshair = graph_ray_casting::ray_cast(
+            &sim.graph,
+            &sim.view().position().unwrap(),
+            &Ray::new(na::Vector4::w(), -na::Vector4::z()),
+            1.0f32.tanh(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(picked.chunk, crosshair.chunk);
+        assert_eq!(picked.voxel_coords, crosshair.voxel_coords);
+        assert_abs_diff_eq!(
+            picked.tanh_distance,
+            crosshair.tanh_distance,
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    fn corner_pick_hits_voxel_under_it() {
+        let (mut sim, frustum) = picking_sim();
+        let (chunk, coords, ndc) = root_voxels(&sim)
+            .into_iter()
+            .find_map(|(chunk, coords, center)| {
+                let ndc = project(&frustum, &center)?;
+                let distance = math::distance(&center, &math::origin());
+                (ndc.x > 0.8 && ndc.y > 0.8 && (0.1..0.5).contains(&distance))
+                    .then_some((chunk, coords, ndc))
+            })
+            .unwrap();
+        assert!(sim.pick(&frustum, ndc, 1.0).is_none());
+        fill(&mut sim, chunk, coords);
+
+        let Some(PickResult::Block(hit)) = sim.pick(&frustum, ndc, 1.0) else {
+            panic!("voxel wasn't picked");
+        };
+        assert_eq!(hit.chunk, chunk);
+        assert_eq!(hit.voxel_coords, coords);
+    }
+
+    #[test]
+    fn visible_characters() {
+        let (mut sim, _) = picking_sim();
+        let other = EntityId::from_bits(2);
+        let position = along_x(&sim, 2.0);
+        spawn_character(&mut sim, other, position);
+        // Looking down, as if at the ground
+        let pitched = na::UnitQuaternion::from_euler_angles(-1.2, 0.4, 0.0);
+        sim.world
+            .get::<&mut Character>(sim.entity_ids[&other])
+            .unwrap()
+            .state
+            .orientation = pitched;
+
+        let m = sim.cfg.meters_to_absolute;
+        let nodes = sim.nearby_nodes(3.0);
+        let visible = sim.visible_characters(&nodes, &math::origin(), 10.0 * m);
+        // The local character is never drawn
+        assert_eq!(visible.len(), 1);
+        assert_eq!(visible[0].entity, sim.entity_ids[&other]);
+        assert_relative_eq!(visible[0].distance, 2.0 * m, max_relative = 1e-3);
+        // Standing upright, though looking down
+        let up = position.local * sim.graph.get_relative_up(&position).unwrap().push(0.0);
+        assert_abs_diff_eq!(visible[0].body * na::Vector4::y(), up, epsilon = 1e-4);
+
+        // Characters beyond the interest radius are culled
+        assert!(sim
+            .visible_characters(&nodes, &math::origin(), 1.0 * m)
+            .is_empty());
+
+        // Despawned characters are gone by the next frame
+        sim.handle_net(spawns(1, vec![], vec![other]));
+        assert!(sim
+            .visible_characters(&nodes, &math::origin(), 10.0 * m)
+            .is_empty());
+    }
+
+    #[test]
+    fn nearest_of_entity_and_block_is_picked() {
+        let (mut sim, frustum) = picking_sim();
+        // A voxel roughly half a unit away, and the exact point on the screen it's centered on
+        let target = na::Point2::new(0.3, -0.2);
+        let (chunk, coords, ndc) = root_voxels(&sim)
+            .into_iter()
+            .filter(|(_, _, center)| {
+                (0.45..0.55).contains(&math::distance(center, &math::origin()))
+            })
+            .filter_map(|(chunk, coords, center)| {
+                Some((chunk, coords, project(&frustum, &center)?))
+            })
+            .min_by(|a, b| (a.2 - target).norm().total_cmp(&(b.2 - target).norm()))
+            .unwrap();
+        fill(&mut sim, chunk, coords);
+        let (_, direction) = frustum.screen_to_ray(ndc);
+        let along_ray = |distance: f32| Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&(direction.xyz() * distance)),
+        };
+
+        let id = EntityId::from_bits(2);
+        spawn_character(&mut sim, id, along_ray(0.2));
+        let Some(PickResult::Entity {
+            id: picked,
+            tanh_distance,
+        }) = sim.pick(&frustum, ndc, 1.0)
+        else {
+            panic!("nearer entity wasn't picked");
+        };
+        assert_eq!(picked, id);
+        let radius = sim.cfg.character.character_radius;
+        assert_abs_diff_eq!(tanh_distance, (0.2 - radius).tanh(), epsilon = 1e-4);
+
+        let entity = sim.entity_ids[&id];
+        *sim.world.get::<&mut Position>(entity).unwrap() = along_ray(0.8);
+        let Some(PickResult::Block(hit)) = sim.pick(&frustum, ndc, 1.0) else {
+            panic!("nearer block wasn't picked");
+        };
+        assert_eq!((hit.chunk, hit.voxel_coords), (chunk, coords));
+
+        // Out of range entirely
+        assert!(sim.pick(&frustum, ndc, 0.3).is_none());
+    }
+
+    /// The root node voxel containing the point `distance` straight ahead of the origin
+    fn voxel_ahead(sim: &Sim, distance: f32) -> (ChunkId, Coords) {
+        let position = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&(-na::Vector3::z() * distance)),
+        };
+        let (chunk, coords, _) = locate_voxel(&sim.graph, sim.graph.layout(), &position).unwrap();
+        (chunk, coords)
+    }
+
+    #[test]
+    fn remote_edit_retargets_crosshair() {
+        let (mut sim, _) = picking_sim();
+        let voxel = sim.cfg.meters_to_absolute;
+        let front = voxel_ahead(&sim, 3.0 * voxel);
+        let behind = voxel_ahead(&sim, 6.0 * voxel);
+        assert_ne!(front, behind);
+        fill(&mut sim, front.0, front.1);
+        fill(&mut sim, behind.0, behind.1);
+
+        let start = Instant::now();
+        let mut effects = EffectPool::new();
+        let target = sim.target().unwrap().unwrap();
+        assert_eq!((target.chunk, target.voxel_coords), front);
+        effects.set_target(&sim.graph, Some(&target), start);
+
+        // Someone else breaks the targeted block between frames
+        sim.handle_net(net::Message::Spawns(proto::Spawns {
+            step: 0,
+            spawns: vec![],
+            despawns: vec![],
+            nodes: vec![],
+            block_updates: vec![(
+                EntityId::from_bits(2),
+                BlockUpdate {
+                    chunk_id: front.0,
+                    coords: front.1,
+                    new_material: Material::Void,
+                    new_shape: Shape::FULL,
+                    sequence: 0,
+                },
+            )],
+            block_fills: vec![],
+            modified_chunks: vec![],
+            chunk_diffs: vec![],
+        }));
+
+        // By the next frame, the cracks have moved to the block that was behind it, starting over,
+        // though the view hasn't moved
+        let next_frame = start + Duration::from_millis(16);
+        effects.update(&sim.graph, next_frame);
+        let target = sim.target().unwrap().unwrap();
+        assert_eq!((target.chunk, target.voxel_coords), behind);
+        effects.set_target(&sim.graph, Some(&target), next_frame);
+        let cracks = effects
+            .iter()
+            .filter(|x| x.kind == EffectKind::Crack)
+            .collect::<Vec<_>>();
+        assert_eq!(cracks.len(), 1);
+        assert_eq!((cracks[0].anchor.chunk, cracks[0].anchor.coords), behind);
+        assert_eq!(cracks[0].progress(next_frame), 0.0);
+    }
+
+    #[test]
+    fn dragged_fill_sends_its_preview() {
+        let (mut sim, _) = picking_sim();
+        let front = voxel_ahead(&sim, 3.0 * sim.cfg.meters_to_absolute);
+        fill(&mut sim, front.0, front.1);
+        assert_eq!(sim.fill_preview(), None);
+
+        sim.set_fill_held(true);
+        let target = sim.target().unwrap().unwrap();
+        let space = sim
+            .graph
+            .get_block_neighbor(
+                target.chunk,
+                target.voxel_coords,
+                target.face_axis,
+                target.face_direction,
+            )
+            .unwrap();
+        let preview = sim.fill_preview().unwrap();
+        assert_eq!(
+            preview,
+            BlockFill {
+                chunk_id: space.0,
+                coords: space.1,
+                extents: [1, 1, 1],
+                contents: FillContents::Material(sim.selected_material()),
+                sequence: 0,
+            }
+        );
+        // Key repeat doesn't restart the drag
+        sim.set_fill_held(true);
+        sim.set_fill_held(false);
+        assert_eq!(sim.queued_block_fill, Some(preview));
+        assert_eq!(sim.fill_preview(), None);
+        assert_eq!(sim.block_prediction.next_sequence(), 1);
+
+        // Fills are applied as the server accepts them
+        sim.handle_net(net::Message::Spawns(proto::Spawns {
+            step: 0,
+            spawns: vec![],
+            despawns: vec![],
+            nodes: vec![],
+            block_updates: vec![],
+            block_fills: vec![(
+                EntityId::from_bits(2),
+                BlockFill {
+                    chunk_id: front.0,
+                    coords: front.1,
+                    extents: [1, 1, 1],
+                    contents: FillContents::Material(Material::Void),
+                    sequence: 0,
+                },
+            )],
+            modified_chunks: vec![],
+            chunk_diffs: vec![],
+        }));
+        assert_eq!(sim.graph.get_block(front.0, front.1), Some(Material::Void));
+    }
+
+    /// Commands sent since this was last called
+    fn take_commands(sent: &mut net::OutgoingReceiver) -> Vec<Command> {
+        let mut commands = Vec::new();
+        while let Some(msg) = sent.try_recv() {
+            if let ClientMessage::Command(command) = msg {
+                commands.push(command);
+            }
+        }
+        commands
+    }
+
+    #[test]
+    fn edits_held_back_while_degraded() {
+        let (mut sim, _) = picking_sim();
+        let (mut net, mut sent) = loose_net();
+        sim.no_clip = true;
+        let id = sim.local_character_id;
+        let step_interval = sim.cfg.step_interval;
+        let front = voxel_ahead(&sim, 3.0 * sim.cfg.meters_to_absolute);
+        fill(&mut sim, front.0, front.1);
+
+        // The server answers once, then goes quiet for a few steps
+        sim.step(step_interval, &mut net);
+        let generation = take_commands(&mut sent).last().unwrap().generation;
+        sim.handle_net(net::Message::StateDelta(state_delta(1, generation, id)));
+        let mut quiet = 0;
+        while sim.connection_quality() == ConnectionQuality::Healthy {
+            sim.step(step_interval, &mut net);
+            quiet += 1;
+            assert!(quiet < 10, "never degraded");
+        }
+        assert!(quiet > 3);
+        assert_eq!(sim.connection_quality(), ConnectionQuality::Degraded);
+
+        // Neither breaking nor filling is sent, or even predicted
+        take_commands(&mut sent);
+        sim.set_break_block_pressed_true();
+        sim.fill([1, 1, 1], Material::Void);
+        assert_eq!(sim.queued_block_fill, None);
+        sim.step(step_interval, &mut net);
+        let commands = take_commands(&mut sent);
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0].character_input.block_update.is_none());
+        assert!(commands[0].character_input.block_fill.is_none());
+        assert_eq!(sim.graph.get_block(front.0, front.1), Some(Material::Dirt));
+        assert!(sim.take_broken_faces().is_empty());
+
+        // Edits go through again once the server has answered on time for a while
+        let mut generation = commands[0].generation;
+        let mut answered: Step = 0;
+        while sim.connection_quality() != ConnectionQuality::Healthy {
+            answered += 1;
+            assert!(answered < 30, "never recovered");
+            sim.handle_net(net::Message::StateDelta(state_delta(
+                1 + answered,
+                generation,
+                id,
+            )));
+            sim.step(step_interval, &mut net);
+            generation = take_commands(&mut sent).last().unwrap().generation;
+        }
+        assert!(answered >= 10);
+        sim.set_break_block_pressed_true();
+        sim.step(step_interval, &mut net);
+        assert!(take_commands(&mut sent)[0]
+            .character_input
+            .block_update
+            .is_some());
+        assert_eq!(sim.graph.get_block(front.0, front.1), Some(Material::Void));
+    }
+
+    #[test]
+    fn gestures_follow_edits() {
+        let (mut sim, _) = picking_sim();
+        let (mut net, _sent) = loose_net();
+        sim.no_clip = true;
+        let step_interval = sim.cfg.step_interval;
+        let front = voxel_ahead(&sim, 3.0 * sim.cfg.meters_to_absolute);
+        fill(&mut sim, front.0, front.1);
+        let material = sim.selected_material();
+        let mut held = Inventory::default();
+        assert!(held.try_add(material, 1));
+        sim.handle_net(net::Message::Inventory(proto::InventoryUpdate {
+            latest_input: 0,
+            inventory: held,
+        }));
+        assert_eq!(sim.held_in_view(), Some(material));
+
+        // Breaking swings, as does swinging at nothing
+        for _ in 0..2 {
+            sim.set_break_block_pressed_true();
+            sim.step(step_interval, &mut net);
+            assert_eq!(sim.take_gestures(), [Gesture::Swing]);
+        }
+        assert_eq!(sim.graph.get_block(front.0, front.1), Some(Material::Void));
+
+        // Placing pushes, but only once something is placed
+        fill(&mut sim, front.0, front.1);
+        sim.set_place_block_pressed_true();
+        sim.step(step_interval, &mut net);
+        assert_eq!(sim.take_gestures(), [Gesture::Push]);
+        assert_eq!(sim.held_in_view(), Some(Material::Void));
+        sim.set_place_block_pressed_true();
+        sim.step(step_interval, &mut net);
+        assert!(sim.take_gestures().is_empty());
+
+        // Nothing is held up to an observer's view
+        assert_eq!(sim.toggle_observer(), Some(true));
+        assert_eq!(sim.held_in_view(), None);
+        sim.set_break_block_pressed_true();
+        sim.step(step_interval, &mut net);
+        assert!(sim.take_gestures().is_empty());
+    }
+
+    #[test]
+    fn prediction_frozen_while_stalled() {
+        let (mut sim, _) = picking_sim();
+        let (mut net, mut sent) = loose_net();
+        sim.no_clip = true;
+        let id = sim.local_character_id;
+        let m = sim.cfg.meters_to_absolute;
+        let step_interval = sim.cfg.step_interval;
+        // Someone else walking by, reported every other step
+        let other = EntityId::from_bits(2);
+        spawn_character(&mut sim, other, along_x(&sim, 2.0));
+        let walking = |sim: &Sim, step: Step, generation: u16| {
+            let mut delta = state_delta(step, generation, id);
+            let state = CharacterState {
+                velocity: -na::Vector3::z() * m,
+                ..delta.character_states[0].1.clone()
+            };
+            delta.positions.push((other, along_x(sim, 2.0)));
+            delta.character_states.push((other, state));
+            delta
+        };
+        sim.set_movement_input(na::Vector3::x() * 0.05);
+        for step in 1..=4 {
+            sim.step(step_interval, &mut net);
+            let generation = take_commands(&mut sent).last().unwrap().generation;
+            let delta = if step % 2 == 1 {
+                walking(&sim, step, generation)
+            } else {
+                state_delta(step, generation, id)
+            };
+            sim.handle_net(net::Message::StateDelta(delta));
+        }
+        let drawn = |sim: &mut Sim| {
+            let nodes = sim.nearby_nodes(3.0);
+            let visible = sim.visible_characters(&nodes, &math::origin(), 10.0 * m);
+            assert_eq!(visible.len(), 1);
+            visible[0].body
+        };
+        // Carried a step past where it was last reported, as far as its updates' spacing allows
+        let carried = drawn(&mut sim);
+        let reported = along_x(&sim, 2.0).local * math::origin();
+        assert!(
+            math::distance(&(carried * math::origin()), &reported)
+                > 0.5 * m * step_interval.as_secs_f32()
+        );
+
+        // The server goes quiet. Remote characters hold where extrapolation left them, while the
+        // local character carries on until prediction is suspended.
+        let frame = step_interval / 4;
+        let mut frames = 0;
+        while sim.connection_quality() != ConnectionQuality::Stalled {
+            sim.step(frame, &mut net);
+            frames += 1;
+            assert!(frames < 100, "never stalled");
+            assert_eq!(drawn(&mut sim), carried);
+        }
+        assert!(sim.prediction.is_stalled());
+        assert!(sim.connection_problem());
+
+        // Frozen exactly, though input keeps coming and the view still turns
+        let checksum = |sim: &Sim| {
+            let ViewSource::World(view) = sim.view() else {
+                panic!("character not spawned");
+            };
+            let prediction = &sim.prediction;
+            prediction
+                .predicted_position()
+                .local
+                .iter()
+                .chain(prediction.predicted_velocity().iter())
+                .chain((view.local * math::origin()).iter())
+                .fold(u64::from(*prediction.predicted_on_ground()), |acc, x| {
+                    acc.rotate_left(5) ^ u64::from(x.to_bits())
+                })
+        };
+        let frozen = checksum(&sim);
+        let orientation = sim.local_character_controller.orientation();
+        for _ in 0..20 {
+            sim.look(0.01, 0.0, 0.0);
+            sim.step(frame, &mut net);
+            assert_eq!(checksum(&sim), frozen);
+            assert_eq!(drawn(&mut sim), carried);
+        }
+        assert_ne!(sim.local_character_controller.orientation(), orientation);
+
+        // Picked up again from the server's word as soon as it speaks
+        let generation = take_commands(&mut sent).last().unwrap().generation;
+        sim.handle_net(net::Message::StateDelta(state_delta(5, generation, id)));
+        sim.step(frame, &mut net);
+        assert!(!sim.prediction.is_stalled());
+        assert_eq!(sim.connection_quality(), ConnectionQuality::Degraded);
+    }
+
+    #[test]
+    fn chunk_diffs_wait_for_generation() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut sim = Sim::new(cfg, camera_cfg(), EntityId::from_bits(1));
+        populate_fresh_nodes(&mut sim.graph);
+        let pending = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let ready = ChunkId::new(NodeId::ROOT, Vertex::B);
+        sim.graph
+            .populate_chunk(ready, VoxelData::Solid(Material::Void), false);
+        let slab = Shape::slab(CoordAxis::X, CoordDirection::Plus);
+        let changes = vec![
+            (Coords([0, 0, 0]), Material::Dirt, Shape::FULL),
+            (Coords([1, 2, 3]), Material::Sand, slab),
+        ];
+        sim.handle_net(net::Message::Spawns(proto::Spawns {
+            step: 0,
+            spawns: vec![],
+            despawns: vec![],
+            nodes: vec![],
+            block_updates: vec![],
+            block_fills: vec![],
+            modified_chunks: vec![],
+            chunk_diffs: [pending, ready]
+                .map(|chunk| ChunkDiff {
+                    chunk,
+                    changes: changes.clone(),
+                })
+                .into(),
+        }));
+
+        // Generated chunks are changed right away
+        for &(coords, material, shape) in &changes {
+            assert_eq!(sim.graph.get_block(ready, coords), Some(material));
+            assert_eq!(sim.graph.get_shape(ready, coords), Some(shape));
+        }
+        // Others once they're generated
+        assert_eq!(sim.graph.get_block(pending, Coords([0, 0, 0])), None);
+        sim.populate_chunk(pending, VoxelData::Solid(Material::Void));
+        for &(coords, material, shape) in &changes {
+            assert_eq!(sim.graph.get_block(pending, coords), Some(material));
+            assert_eq!(sim.graph.get_shape(pending, coords), Some(shape));
+        }
+        assert_eq!(
+            sim.graph.get_block(pending, Coords([1, 0, 0])),
+            Some(Material::Void)
+        );
+        assert!(sim.pending_modified_chunks.is_empty());
+    }
+
+    /// A step's updates introducing a node, an entity in it, and changes to its chunks, and the
+    /// node
+    fn node_updates(cfg: &SimConfig) -> (proto::Spawns, NodeId) {
+        let mut server = Graph::new(cfg.chunk_size);
+        let node = server.ensure_neighbor(NodeId::ROOT, dodeca::Side::A);
+        let spawns = proto::Spawns {
+            step: 1,
+            spawns: vec![(
+                EntityId::from_bits(2),
+                character(Position {
+                    node,
+                    local: na::one(),
+                }),
+            )],
+            despawns: vec![],
+            nodes: vec![FreshNode {
+                side: dodeca::Side::A,
+                parent: NodeId::ROOT,
+            }],
+            block_updates: vec![(
+                EntityId::from_bits(2),
+                BlockUpdate {
+                    chunk_id: ChunkId::new(node, Vertex::A),
+                    coords: Coords([0, 0, 0]),
+                    new_material: Material::Dirt,
+                    new_shape: Shape::FULL,
+                    sequence: 0,
+                },
+            )],
+            block_fills: vec![],
+            modified_chunks: vec![(
+                ChunkId::new(node, Vertex::C),
+                SerializableVoxelData {
+                    voxels: vec![Material::Sand; usize::from(cfg.chunk_size).pow(3)],
+                    shapes: Vec::new(),
+                },
+            )],
+            chunk_diffs: vec![ChunkDiff {
+                chunk: ChunkId::new(node, Vertex::B),
+                changes: vec![(Coords([1, 1, 1]), Material::Sand, Shape::FULL)],
+            }],
+        };
+        (spawns, node)
+    }
+
+    /// The parts of `spawns` as messages, in the order the server would send them on each stream
+    fn split_messages(spawns: proto::Spawns) -> [Vec<net::Message>; 3] {
+        let (entities, graph, edits) = spawns.split(proto::MAX_UPDATE_BYTES);
+        [
+            entities
+                .into_iter()
+                .map(net::Message::EntityUpdates)
+                .collect(),
+            graph.into_iter().map(net::Message::GraphUpdates).collect(),
+            edits.into_iter().map(net::Message::WorldEdits).collect(),
+        ]
+    }
+
+    #[test]
+    fn split_updates_converge_in_any_order() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let (spawns, node) = node_updates(&cfg);
+        let mut whole = Sim::new(cfg.clone(), camera_cfg(), EntityId::from_bits(1));
+        whole.handle_net(net::Message::Spawns(spawns));
+        let entity = whole.entity_ids[&EntityId::from_bits(2)];
+        assert_eq!(whole.world.get::<&Position>(entity).unwrap().node, node);
+        assert_eq!(whole.pending_modified_chunks.len(), 2);
+        let modified = ChunkId::new(node, Vertex::C);
+        assert_eq!(
+            whole.graph.get_block(modified, Coords([0, 0, 0])),
+            Some(Material::Sand)
+        );
+
+        for order in [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ] {
+            let mut sim = Sim::new(cfg.clone(), camera_cfg(), EntityId::from_bits(1));
+            let mut streams = split_messages(node_updates(&cfg).0);
+            for stream in order {
+                for msg in mem::take(&mut streams[stream]) {
+                    sim.handle_net(msg);
+                }
+            }
+            assert_eq!(sim.debug_info().deferred_updates, 0, "{order:?}");
+            assert_eq!(sim.graph.len(), whole.graph.len(), "{order:?}");
+            let entity = sim.entity_ids[&EntityId::from_bits(2)];
+            assert_eq!(sim.world.get::<&Position>(entity).unwrap().node, node);
+            assert_eq!(
+                sim.pending_modified_chunks, whole.pending_modified_chunks,
+                "{order:?}"
+            );
+            assert_eq!(
+                sim.graph.get_block(modified, Coords([0, 0, 0])),
+                Some(Material::Sand)
+            );
+        }
+    }
+
+    #[test]
+    fn updates_wait_for_their_nodes() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let id = EntityId::from_bits(2);
+        let mut sim = Sim::new(cfg.clone(), camera_cfg(), EntityId::from_bits(1));
+        let [entities, graph, edits] = split_messages(node_updates(&cfg).0);
+        for msg in entities.into_iter().chain(edits) {
+            sim.handle_net(msg);
+        }
+        // The spawn, block update, diff, and modified chunk
+        assert_eq!(sim.debug_info().deferred_updates, 4);
+        assert!(sim.entity_ids.is_empty());
+        assert!(sim.pending_modified_chunks.is_empty());
+        for msg in graph {
+            sim.handle_net(msg);
+        }
+        assert_eq!(sim.debug_info().deferred_updates, 0);
+        assert!(sim.entity_ids.contains_key(&id));
+        assert_eq!(sim.pending_modified_chunks.len(), 2);
+
+        // An entity despawned before its node arrives is never spawned
+        let mut sim = Sim::new(cfg.clone(), camera_cfg(), EntityId::from_bits(1));
+        let [entities, graph, _] = split_messages(node_updates(&cfg).0);
+        for msg in entities {
+            sim.handle_net(msg);
+        }
+        sim.handle_net(net::Message::EntityUpdates(proto::EntityUpdates {
+            step: 2,
+            spawns: vec![],
+            despawns: vec![id],
+        }));
+        assert_eq!(sim.debug_info().deferred_updates, 0);
+        for msg in graph {
+            sim.handle_net(msg);
+        }
+        assert!(sim.entity_ids.is_empty());
+    }
+}
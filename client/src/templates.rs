@@ -0,0 +1,108 @@
+//! Templates copied by the player, kept as files by name
+//!
+//! Templates don't refer to any node of the graph, so unlike waypoints they're shared between
+//! servers and can be pasted anywhere.
+
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use common::{codec, template::Template};
+
+/// Write `template` to `dir` under `name`, replacing any of the same name
+pub fn save(dir: &Path, name: &str, template: &Template) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(file_name(name));
+    // Replaced atomically, so a crash mid-write leaves any previous template intact
+    let temp = path.with_extension("template.tmp");
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(&codec::encode(template))?;
+    file.sync_all()?;
+    fs::rename(&temp, &path)
+}
+
+/// Read the template saved in `dir` under `name`
+pub fn load(dir: &Path, name: &str) -> Result<Template, LoadError> {
+    let data = fs::read(dir.join(file_name(name))).map_err(LoadError::Io)?;
+    codec::decode(&data).map_err(LoadError::Malformed)
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Malformed(anyhow::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                f.pad("no such template")
+            }
+            LoadError::Io(ref e) => write!(f, "couldn't read template: {e}"),
+            LoadError::Malformed(ref e) => write!(f, "malformed template: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Name of the file recording the template called `name`
+fn file_name(name: &str) -> String {
+    // As for waypoints, readable but distinct for names differing only in characters that can't
+    // appear in file names
+    let readable = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    format!("{readable}-{:016x}.template", fxhash::hash64(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        node::{CoordAxis, CoordDirection},
+        world::{Material, Shape},
+    };
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = Template::new(
+            [1, 2, 1],
+            (CoordAxis::Y, CoordDirection::Plus),
+            false,
+            vec![
+                (Material::Dirt, Shape::FULL),
+                (
+                    Material::WoodPlanks,
+                    Shape::slab(CoordAxis::Y, CoordDirection::Minus),
+                ),
+            ],
+        )
+        .unwrap();
+        save(dir.path(), "tiny hut", &template).unwrap();
+        assert_eq!(load(dir.path(), "tiny hut").unwrap(), template);
+        assert!(matches!(
+            load(dir.path(), "tiny_hut"),
+            Err(LoadError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+
+        fs::write(dir.path().join(file_name("broken")), [0xff; 3]).unwrap();
+        assert!(matches!(
+            load(dir.path(), "broken"),
+            Err(LoadError::Malformed(_))
+        ));
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
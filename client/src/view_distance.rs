@@ -0,0 +1,340 @@
+//! Automatic adjustment of how far the world is drawn, keeping frames within their time budget
+
+use std::time::Duration;
+
+/// Parameters for `ViewDistance`, in absolute units
+#[derive(Debug, Clone)]
+pub struct ViewDistanceConfig {
+    /// Nearest the world is ever drawn to while adapting
+    pub min: f32,
+    /// Farthest the world is ever drawn to
+    pub max: f32,
+    /// Distance past where the fog becomes opaque out to which chunks are kept, so that the world
+    /// grows and shrinks out of sight
+    pub fog_margin: f32,
+    /// Longest each frame should take, if shorter than the display's refresh interval
+    pub frame_time_cap: Option<Duration>,
+}
+
+/// What drawing a frame cost
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FrameSample {
+    /// Time since the previous frame began
+    pub interval: Duration,
+    /// Time spent working on the frame, excluding waits for the display to be ready for it
+    ///
+    /// Frames synchronized to the display's refresh never take less than the refresh interval, so
+    /// this tells whether there's room to draw more.
+    pub busy: Duration,
+    /// Part of `busy` spent preparing chunk surfaces for the GPU
+    pub upload: Duration,
+    /// Fraction of the capacity for generating chunks in the background that's in use
+    pub backlog: f32,
+}
+
+/// Feedback controller choosing how far the world is drawn
+///
+/// Shrinks the distance quickly while frames take longer than the budget, and grows it slowly
+/// while they leave plenty to spare. Frames that are neither leave it alone, so it settles rather
+/// than oscillating around the budget.
+pub struct ViewDistance {
+    cfg: ViewDistanceConfig,
+    /// Longest a frame should take, in seconds
+    budget: f32,
+    /// Distance the fog is moving towards
+    target: f32,
+    /// Distance at which the fog becomes opaque
+    fog: f32,
+    /// Farthest `target` may be, lowered to free the memory held for distant chunks
+    ceiling: f32,
+    /// Whether `target` was fixed from the console, disabling adaptation
+    manual: bool,
+    /// Exponential moving average of `FrameSample::busy`, in seconds
+    busy: f32,
+    /// Exponential moving average of `FrameSample::upload`, in seconds
+    upload: f32,
+    /// Seconds spent in frames over budget since the average was last back within it
+    overloaded: f32,
+    /// Seconds since the average last left room to spare
+    relaxed: f32,
+}
+
+impl ViewDistance {
+    /// Begin at the greatest distance, with frames budgeted to fit the display's refresh interval,
+    /// if known
+    pub fn new(cfg: ViewDistanceConfig, refresh_interval: Option<Duration>) -> Self {
+        let budget = match (cfg.frame_time_cap, refresh_interval) {
+            (Some(cap), Some(refresh)) => cap.min(refresh),
+            (Some(x), None) | (None, Some(x)) => x,
+            (None, None) => DEFAULT_BUDGET,
+        }
+        .as_secs_f32();
+        Self {
+            budget,
+            target: cfg.max,
+            fog: cfg.max,
+            ceiling: cfg.max,
+            manual: false,
+            busy: 0.0,
+            upload: 0.0,
+            overloaded: 0.0,
+            relaxed: 0.0,
+            cfg,
+        }
+    }
+
+    /// Account for a frame, adjusting the distance to suit
+    pub fn update(&mut self, sample: &FrameSample) {
+        let dt = sample.interval.as_secs_f32();
+        let weight = 1.0 - 0.5f32.powf(dt / SMOOTHING_HALF_LIFE);
+        self.busy += (sample.busy.as_secs_f32() - self.busy) * weight;
+        self.upload += (sample.upload.as_secs_f32() - self.upload) * weight;
+        self.fog = self.target + (self.fog - self.target) * 0.5f32.powf(dt / FOG_HALF_LIFE);
+        if self.manual {
+            return;
+        }
+
+        // Only slow frames count towards shrinking, so a lone hitch doesn't, however long it is
+        let limit = self.budget * OVERLOAD_RATIO;
+        if sample.interval.as_secs_f32() > limit && sample.busy.as_secs_f32() > limit {
+            self.overloaded += dt;
+        } else if self.busy <= limit {
+            self.overloaded = 0.0;
+        }
+        if self.overloaded >= SHRINK_DELAY {
+            self.target = (self.target * SHRINK_FACTOR).max(self.cfg.min);
+            self.overloaded = 0.0;
+            self.relaxed = 0.0;
+            return;
+        }
+
+        // Uploads compete with drawing, and a full backlog means more is already wanted than can
+        // be generated, so neither leaves room to grow
+        if self.busy + self.upload < self.budget * RELAXED_RATIO && sample.backlog < MAX_BACKLOG {
+            self.relaxed += dt;
+        } else {
+            self.relaxed = 0.0;
+        }
+        if self.relaxed >= GROW_DELAY {
+            self.target =
+                (self.target + (self.cfg.max - self.cfg.min) * GROW_STEP).min(self.ceiling);
+            self.relaxed = 0.0;
+        }
+    }
+
+    /// Fix the distance, or with `None` go back to adapting it
+    pub fn set_manual(&mut self, distance: Option<f32>) {
+        self.manual = distance.is_some();
+        if let Some(distance) = distance {
+            self.target = distance.clamp(0.0, self.ceiling);
+        }
+        self.overloaded = 0.0;
+        self.relaxed = 0.0;
+    }
+
+    /// Shrink the distance at once and keep it from growing back, so that the memory held for the
+    /// farthest chunks can be freed, returning false if it's already as near as it goes
+    pub fn shrink_for_memory(&mut self) -> bool {
+        if self.target <= self.cfg.min {
+            return false;
+        }
+        self.ceiling = (self.target * SHRINK_FACTOR).max(self.cfg.min);
+        self.target = self.ceiling;
+        // Chunks beyond the fog are freed only once it's moved in
+        self.fog = self.fog.min(self.target);
+        self.overloaded = 0.0;
+        self.relaxed = 0.0;
+        true
+    }
+
+    /// Distance the fog is moving towards
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    /// Distance at which the fog becomes opaque
+    pub fn fog_distance(&self) -> f32 {
+        self.fog
+    }
+
+    /// Distance out to which chunks are generated, drawn, and kept
+    pub fn radius(&self) -> f32 {
+        (self.fog + self.cfg.fog_margin).min(self.cfg.max)
+    }
+}
+
+/// Frame budget when neither the display nor the config gives one
+const DEFAULT_BUDGET: Duration = Duration::from_micros(16_667);
+/// Time in seconds over which frame costs are averaged
+const SMOOTHING_HALF_LIFE: f32 = 0.1;
+/// Time in seconds for the fog to cover half the way to a new distance
+const FOG_HALF_LIFE: f32 = 0.5;
+/// Fraction of the budget beyond which a frame is over budget
+const OVERLOAD_RATIO: f32 = 1.15;
+/// Fraction of the budget below which frames have room to spare
+const RELAXED_RATIO: f32 = 0.7;
+/// Time in seconds spent in frames over budget that shrinks the distance
+const SHRINK_DELAY: f32 = 1.0;
+/// Factor by which each shrink scales the distance
+const SHRINK_FACTOR: f32 = 0.8;
+/// Time in seconds with room to spare that grows the distance
+const GROW_DELAY: f32 = 4.0;
+/// Fraction of the adjustable range added by each growth
+const GROW_STEP: f32 = 0.1;
+/// Fraction of the chunk generation capacity in use beyond which the distance doesn't grow
+const MAX_BACKLOG: f32 = 0.5;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: Duration = Duration::from_millis(16);
+
+    fn controller() -> ViewDistance {
+        ViewDistance::new(
+            ViewDistanceConfig {
+                min: 20.0,
+                max: 100.0,
+                fog_margin: 5.0,
+                frame_time_cap: None,
+            },
+            Some(BUDGET),
+        )
+    }
+
+    /// Frames taking `busy` each, unsynchronized to the display
+    fn frames(busy: Duration, seconds: f32) -> impl Iterator<Item = FrameSample> {
+        let count = (seconds / busy.as_secs_f32()).round() as usize;
+        std::iter::repeat(FrameSample {
+            interval: busy,
+            busy,
+            upload: Duration::ZERO,
+            backlog: 0.0,
+        })
+        .take(count)
+    }
+
+    /// Feed `samples` to `controller`, returning the targets it passes through in order
+    fn run(
+        controller: &mut ViewDistance,
+        samples: impl IntoIterator<Item = FrameSample>,
+    ) -> Vec<f32> {
+        let mut targets = vec![controller.target()];
+        for sample in samples {
+            controller.update(&sample);
+            if controller.target() != *targets.last().unwrap() {
+                targets.push(controller.target());
+            }
+            // Whatever happens, the fog hides the edge of the world
+            assert!(controller.radius() >= controller.fog_distance());
+            assert!(controller.radius() - controller.fog_distance() <= 5.0 + 1e-3);
+            assert!(controller.radius() <= 100.0);
+        }
+        targets
+    }
+
+    #[test]
+    fn spike_is_ignored() {
+        let mut controller = controller();
+        let samples = frames(Duration::from_millis(10), 2.0)
+            .chain(frames(Duration::from_millis(500), 0.5))
+            .chain(frames(Duration::from_millis(10), 2.0));
+        assert_eq!(run(&mut controller, samples), [100.0]);
+    }
+
+    #[test]
+    fn sustained_load_shrinks_then_recovery_grows() {
+        let mut controller = controller();
+        let targets = run(&mut controller, frames(Duration::from_millis(30), 3.5));
+        assert_eq!(targets.len(), 4);
+        for pair in targets.windows(2) {
+            approx::assert_relative_eq!(pair[1], pair[0] * SHRINK_FACTOR);
+        }
+        // Shrinking stops at the minimum
+        run(&mut controller, frames(Duration::from_millis(30), 10.0));
+        assert_eq!(controller.target(), 20.0);
+        approx::assert_relative_eq!(controller.fog_distance(), 20.0, epsilon = 0.1);
+
+        // Growing back is much slower
+        let targets = run(&mut controller, frames(Duration::from_millis(5), 9.0));
+        assert_eq!(targets.len(), 3);
+        for pair in targets.windows(2) {
+            approx::assert_relative_eq!(pair[1] - pair[0], 8.0, epsilon = 1e-3);
+        }
+        run(&mut controller, frames(Duration::from_millis(5), 60.0));
+        assert_eq!(controller.target(), 100.0);
+    }
+
+    #[test]
+    fn near_budget_holds_steady() {
+        let mut controller = controller();
+        run(&mut controller, frames(Duration::from_millis(30), 1.5));
+        let settled = controller.target();
+        assert!(settled < 100.0);
+        // Neither over budget nor leaving room to grow, whether slightly under or over
+        let samples = frames(Duration::from_millis(14), 10.0)
+            .chain(frames(Duration::from_millis(18), 10.0))
+            .chain(frames(Duration::from_millis(12), 10.0));
+        assert_eq!(run(&mut controller, samples), [settled]);
+    }
+
+    #[test]
+    fn waiting_for_display_leaves_room() {
+        let mut controller = controller();
+        run(&mut controller, frames(Duration::from_millis(30), 1.5));
+        let settled = controller.target();
+        // Synchronized to the display, with little work to do
+        let synchronized = FrameSample {
+            interval: BUDGET,
+            busy: Duration::from_millis(4),
+            upload: Duration::ZERO,
+            backlog: 0.0,
+        };
+        run(&mut controller, std::iter::repeat(synchronized).take(300));
+        assert!(controller.target() > settled);
+
+        // But not while generation is backed up
+        let target = controller.target();
+        let backed_up = FrameSample {
+            backlog: 1.0,
+            ..synchronized
+        };
+        run(&mut controller, std::iter::repeat(backed_up).take(1000));
+        assert_eq!(controller.target(), target);
+    }
+
+    #[test]
+    fn manual_override() {
+        let mut controller = controller();
+        controller.set_manual(Some(40.0));
+        let samples =
+            frames(Duration::from_millis(30), 5.0).chain(frames(Duration::from_millis(5), 20.0));
+        assert_eq!(run(&mut controller, samples), [40.0]);
+        approx::assert_relative_eq!(controller.fog_distance(), 40.0, epsilon = 0.1);
+
+        controller.set_manual(None);
+        run(&mut controller, frames(Duration::from_millis(30), 1.5));
+        assert!(controller.target() < 40.0);
+    }
+
+    #[test]
+    fn memory_pressure_caps_growth() {
+        let mut controller = controller();
+        assert!(controller.shrink_for_memory());
+        assert_eq!(controller.target(), 80.0);
+        // The chunks to be freed are out of range straight away
+        assert_eq!(controller.fog_distance(), 80.0);
+        assert_eq!(controller.radius(), 85.0);
+
+        // Plenty of room to spare doesn't bring them back
+        run(&mut controller, frames(Duration::from_millis(5), 60.0));
+        assert_eq!(controller.target(), 80.0);
+        controller.set_manual(Some(100.0));
+        assert_eq!(controller.target(), 80.0);
+
+        // Down to the minimum, and no further
+        controller.set_manual(None);
+        while controller.shrink_for_memory() {}
+        assert_eq!(controller.target(), 20.0);
+    }
+}
@@ -0,0 +1,450 @@
+//! Procedural animation of the block held in the first-person view
+//!
+//! The view model is drawn in view space, apart from the world, so none of this is hyperbolic:
+//! offsets are euclidean, in units of the view model's own projection.
+
+use common::world::Material;
+
+use crate::graphics::voxels::surface_extraction::PLACEHOLDER_MATERIAL;
+
+/// Seconds a swing takes from start to finish
+const SWING_DURATION: f32 = 0.25;
+/// Seconds a push takes from start to finish
+const PUSH_DURATION: f32 = 0.15;
+/// Seconds taken to lower the held block out of view, and again to raise the next one
+const SWAP_HALF_DURATION: f32 = 0.12;
+/// Time in seconds for the sway to close half its distance to where motion carries it
+const SWAY_HALF_LIFE: f32 = 0.1;
+
+/// Where the block rests in view space, below and to the right of the crosshair
+const REST: [f32; 3] = [0.28, -0.26, -0.5];
+/// Distance from the block's center to the middle of its faces
+const HALF_EXTENT: f32 = 0.1;
+/// Distance the block drops out of view while swapping
+const SWAP_DROP: f32 = 0.4;
+/// Distance the block is thrust forward by a push
+const PUSH_REACH: f32 = 0.08;
+/// Angle in radians the block pitches down through at the height of a swing
+const SWING_ANGLE: f32 = 1.0;
+/// Distance the block is displaced per meter per second of motion
+const SWAY_PER_SPEED: f32 = 0.006;
+/// Furthest the block is displaced by motion
+const MAX_SWAY: f32 = 0.04;
+/// Amplitude of the sideways rocking with each footstep
+const FOOTSTEP_SWAY: f32 = 0.012;
+
+/// Something the local character did that the view model reacts to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Gesture {
+    /// The break input was used, whether or not anything was broken
+    Swing,
+    /// A block was placed, as predicted ahead of the server
+    Push,
+}
+
+/// An animation in progress, with the seconds elapsed since it began
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Animation {
+    Idle,
+    Swing(f32),
+    Push(f32),
+    /// Lowering the shown block, then raising the held one once it's out of view
+    Swap(f32),
+}
+
+/// What the first-person view shows of the local character
+pub struct ViewModel {
+    /// Material most recently selected
+    held: Material,
+    /// Material on display, which lags `held` while the old block is lowered out of view
+    shown: Material,
+    animation: Animation,
+    /// Displacement due to the character's motion, eased towards where the latest motion carries
+    /// it
+    sway: na::Vector3<f32>,
+    /// Fraction of the current footstep walked, in [0, 1)
+    footstep_phase: f32,
+}
+
+impl ViewModel {
+    pub fn new(held: Material) -> Self {
+        Self {
+            held,
+            shown: held,
+            animation: Animation::Idle,
+            sway: na::zero(),
+            footstep_phase: 0.0,
+        }
+    }
+
+    /// Texture layer of the shown material in the voxel material array, or `None` if there's
+    /// nothing to draw
+    pub fn layer(&self) -> Option<u32> {
+        texture_layer(self.shown)
+    }
+
+    /// React to `gesture`
+    ///
+    /// Swings take priority over pushes: a swing cuts a push short, while a push during a swing is
+    /// dropped. Either finishes a swap immediately, so the block acted with is the one shown.
+    pub fn gesture(&mut self, gesture: Gesture) {
+        if let Animation::Swap(_) = self.animation {
+            self.shown = self.held;
+        }
+        self.animation = match (gesture, self.animation) {
+            (_, Animation::Swing(elapsed)) => Animation::Swing(elapsed),
+            (Gesture::Swing, _) => Animation::Swing(0.0),
+            (Gesture::Push, _) => Animation::Push(0.0),
+        };
+    }
+
+    /// Hold `material`, swapping it into view once any animation in progress is done
+    pub fn select(&mut self, material: Material) {
+        if material == self.held {
+            return;
+        }
+        self.held = material;
+        match self.animation {
+            Animation::Idle => self.animation = Animation::Swap(0.0),
+            // Raising a block that's no longer wanted, or lowering the one that's wanted again, so
+            // turn around from the same height rather than jumping
+            Animation::Swap(elapsed)
+                if (elapsed > SWAP_HALF_DURATION) != (material == self.shown) =>
+            {
+                self.animation = Animation::Swap(2.0 * SWAP_HALF_DURATION - elapsed);
+            }
+            // Already on the way down, or picked up once the current animation finishes
+            _ => {}
+        }
+    }
+
+    /// Advance by `dt` seconds, given the character's `velocity` in meters per second in view
+    /// space and how far through a footstep it is
+    pub fn update(&mut self, dt: f32, velocity: &na::Vector3<f32>, footstep_phase: f32) {
+        let target = -velocity * SWAY_PER_SPEED;
+        let target = target * (MAX_SWAY / target.norm().max(MAX_SWAY));
+        let decay = 0.5f32.powf(dt / SWAY_HALF_LIFE);
+        self.sway = target + (self.sway - target) * decay;
+        self.footstep_phase = footstep_phase;
+
+        self.animation = match self.animation {
+            Animation::Idle => Animation::Idle,
+            Animation::Swing(elapsed) if elapsed + dt < SWING_DURATION => {
+                Animation::Swing(elapsed + dt)
+            }
+            Animation::Push(elapsed) if elapsed + dt < PUSH_DURATION => {
+                Animation::Push(elapsed + dt)
+            }
+            Animation::Swap(elapsed) if elapsed + dt < 2.0 * SWAP_HALF_DURATION => {
+                if elapsed + dt >= SWAP_HALF_DURATION {
+                    // Out of view, so the next block can be brought up
+                    self.shown = self.held;
+                }
+                Animation::Swap(elapsed + dt)
+            }
+            Animation::Swap(_) => {
+                self.shown = self.held;
+                Animation::Idle
+            }
+            Animation::Swing(_) | Animation::Push(_) => Animation::Idle,
+        };
+        if self.animation == Animation::Idle && self.shown != self.held {
+            // Selected while something else was going on
+            self.animation = Animation::Swap(0.0);
+        }
+    }
+
+    /// Transform from the unit cube's space, spanning -1 to 1 on each axis, to view space
+    pub fn transform(&self) -> na::Matrix4<f32> {
+        let mut offset = na::Vector3::from(REST) + self.sway;
+        let angle = std::f32::consts::TAU * self.footstep_phase;
+        offset += na::Vector3::new(angle.sin(), -angle.cos().abs(), 0.0) * FOOTSTEP_SWAY;
+
+        let mut pitch = 0.0;
+        match self.animation {
+            Animation::Idle => {}
+            Animation::Swing(elapsed) => {
+                let height = (std::f32::consts::PI * elapsed / SWING_DURATION).sin();
+                pitch = -SWING_ANGLE * height;
+                offset += na::Vector3::new(-0.05, -0.05, -0.1) * height;
+            }
+            Animation::Push(elapsed) => {
+                let height = (std::f32::consts::PI * elapsed / PUSH_DURATION).sin();
+                offset.z -= PUSH_REACH * height;
+            }
+            Animation::Swap(elapsed) => {
+                let t = 1.0 - (elapsed / SWAP_HALF_DURATION - 1.0).abs();
+                // Smoothstep, so the block eases out of view and back
+                offset.y -= SWAP_DROP * t * t * (3.0 - 2.0 * t);
+            }
+        }
+
+        // Turned slightly, so more than one face shows
+        let rotation = na::Rotation3::from_axis_angle(&na::Vector3::x_axis(), pitch)
+            * na::Rotation3::from_axis_angle(&na::Vector3::y_axis(), -0.5)
+            * na::Rotation3::from_axis_angle(&na::Vector3::x_axis(), 0.2);
+        na::Translation3::from(offset).to_homogeneous()
+            * rotation.to_homogeneous()
+            * na::Matrix4::new_scaling(HALF_EXTENT)
+    }
+}
+
+/// Layer of `material` in the voxel material texture array, or `None` for `Void`, which has none
+///
+/// Materials this build doesn't know get the placeholder's layer, as they do in the world.
+pub fn texture_layer(material: Material) -> Option<u32> {
+    if material == Material::Void {
+        return None;
+    }
+    let id = if material.is_known() {
+        material.id()
+    } else {
+        PLACEHOLDER_MATERIAL
+    };
+    Some(u32::from(id) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn still(view_model: &mut ViewModel, seconds: f32) {
+        for _ in 0..(seconds / DT).ceil() as usize {
+            view_model.update(DT, &na::zero(), 0.0);
+        }
+    }
+
+    fn is_swing(animation: Animation) -> bool {
+        matches!(animation, Animation::Swing(_))
+    }
+
+    #[test]
+    fn gestures_run_their_course() {
+        let mut view_model = ViewModel::new(Material::Dirt);
+        let rest = view_model.transform();
+        for gesture in [Gesture::Swing, Gesture::Push] {
+            view_model.gesture(gesture);
+            still(&mut view_model, 0.05);
+            assert_ne!(view_model.animation, Animation::Idle);
+            assert_ne!(view_model.transform(), rest);
+            still(&mut view_model, 1.0);
+            assert_eq!(view_model.animation, Animation::Idle);
+            assert_eq!(view_model.transform(), rest);
+        }
+    }
+
+    #[test]
+    fn swing_takes_priority() {
+        // A push during a swing is dropped
+        let mut view_model = ViewModel::new(Material::Dirt);
+        view_model.gesture(Gesture::Swing);
+        still(&mut view_model, 0.1);
+        view_model.gesture(Gesture::Push);
+        assert!(is_swing(view_model.animation));
+        // Nor does another swing start over
+        let before = view_model.animation;
+        view_model.gesture(Gesture::Swing);
+        assert_eq!(view_model.animation, before);
+
+        // A swing cuts a push short
+        let mut view_model = ViewModel::new(Material::Dirt);
+        view_model.gesture(Gesture::Push);
+        still(&mut view_model, 0.05);
+        view_model.gesture(Gesture::Swing);
+        assert_eq!(view_model.animation, Animation::Swing(0.0));
+
+        // Both in the same step, either way around, swing
+        for order in [
+            [Gesture::Swing, Gesture::Push],
+            [Gesture::Push, Gesture::Swing],
+        ] {
+            let mut view_model = ViewModel::new(Material::Dirt);
+            for gesture in order {
+                view_model.gesture(gesture);
+            }
+            assert_eq!(view_model.animation, Animation::Swing(0.0), "{order:?}");
+        }
+
+        // A push restarts a push
+        let mut view_model = ViewModel::new(Material::Dirt);
+        view_model.gesture(Gesture::Push);
+        still(&mut view_model, 0.05);
+        view_model.gesture(Gesture::Push);
+        assert_eq!(view_model.animation, Animation::Push(0.0));
+    }
+
+    #[test]
+    fn gesture_finishes_swap() {
+        let mut view_model = ViewModel::new(Material::Dirt);
+        view_model.select(Material::Stone);
+        still(&mut view_model, 0.05);
+        assert_eq!(view_model.shown, Material::Dirt);
+        view_model.gesture(Gesture::Push);
+        // The block placed is the one shown placing it
+        assert_eq!(view_model.shown, Material::Stone);
+        assert_eq!(view_model.animation, Animation::Push(0.0));
+        still(&mut view_model, 1.0);
+        assert_eq!(view_model.animation, Animation::Idle);
+    }
+
+    #[test]
+    fn swap_waits_for_gesture() {
+        let mut view_model = ViewModel::new(Material::Dirt);
+        view_model.gesture(Gesture::Swing);
+        view_model.select(Material::Stone);
+        still(&mut view_model, 0.1);
+        assert!(is_swing(view_model.animation));
+        assert_eq!(view_model.shown, Material::Dirt);
+        still(&mut view_model, SWING_DURATION);
+        assert!(matches!(view_model.animation, Animation::Swap(_)));
+        still(&mut view_model, 1.0);
+        assert_eq!(view_model.animation, Animation::Idle);
+        assert_eq!(view_model.shown, Material::Stone);
+    }
+
+    #[test]
+    fn swap_switches_out_of_view() {
+        let mut view_model = ViewModel::new(Material::Dirt);
+        let rest = view_model.transform();
+        view_model.select(Material::Stone);
+        let mut lowest = f32::INFINITY;
+        let mut shown_at = None;
+        for i in 0..60 {
+            view_model.update(DT, &na::zero(), 0.0);
+            let height = view_model.transform()[(1, 3)];
+            if shown_at.is_none() && view_model.shown == Material::Stone {
+                shown_at = Some(height);
+            }
+            lowest = lowest.min(height);
+            if i > 30 {
+                assert_eq!(view_model.animation, Animation::Idle);
+            }
+        }
+        // Switched at the bottom, where it's hidden
+        assert!((shown_at.unwrap() - lowest).abs() < 0.05);
+        assert!(lowest < rest[(1, 3)] - SWAP_DROP * 0.9);
+        assert_eq!(view_model.transform(), rest);
+    }
+
+    #[test]
+    fn reselecting_while_raising_lowers_again() {
+        let mut view_model = ViewModel::new(Material::Dirt);
+        view_model.select(Material::Stone);
+        still(&mut view_model, SWAP_HALF_DURATION * 1.5);
+        assert_eq!(view_model.shown, Material::Stone);
+        let height = view_model.transform()[(1, 3)];
+        view_model.select(Material::Sand);
+        // Carries on from the same height, now headed down
+        assert!((view_model.transform()[(1, 3)] - height).abs() < 1e-5);
+        view_model.update(DT, &na::zero(), 0.0);
+        assert!(view_model.transform()[(1, 3)] < height);
+        still(&mut view_model, 1.0);
+        assert_eq!(view_model.shown, Material::Sand);
+        assert_eq!(view_model.animation, Animation::Idle);
+    }
+
+    /// However inputs and selections interleave, the view model settles showing the held material
+    #[test]
+    fn never_stuck() {
+        let materials = [
+            Material::Dirt,
+            Material::Stone,
+            Material::Void,
+            Material::Sand,
+        ];
+        let mut seed = 0x2545_f491u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        for _ in 0..50 {
+            let mut view_model = ViewModel::new(Material::Dirt);
+            let mut held = Material::Dirt;
+            for _ in 0..100 {
+                match next() % 4 {
+                    0 => view_model.gesture(Gesture::Swing),
+                    1 => view_model.gesture(Gesture::Push),
+                    2 => {
+                        held = materials[next() as usize % materials.len()];
+                        view_model.select(held);
+                    }
+                    _ => {}
+                }
+                let dt = (next() % 10) as f32 * 0.005;
+                view_model.update(dt, &na::zero(), 0.0);
+            }
+            still(&mut view_model, 1.0);
+            assert_eq!(view_model.animation, Animation::Idle);
+            assert_eq!(view_model.shown, held);
+        }
+    }
+
+    #[test]
+    fn rapid_selection_layers() {
+        let mut view_model = ViewModel::new(Material::Dirt);
+        assert_eq!(view_model.layer(), Some(u32::from(Material::Dirt.id()) - 1));
+        // Flicking through materials within a frame shows only where the selection ended up
+        for material in [Material::Stone, Material::Sand, Material::WoodPlanks] {
+            view_model.select(material);
+            view_model.update(0.001, &na::zero(), 0.0);
+        }
+        assert_eq!(view_model.shown, Material::Dirt);
+        still(&mut view_model, 1.0);
+        assert_eq!(
+            view_model.layer(),
+            Some(u32::from(Material::WoodPlanks.id()) - 1)
+        );
+
+        // Flicking back to the shown material brings it straight back up
+        view_model.select(Material::Stone);
+        view_model.update(DT, &na::zero(), 0.0);
+        view_model.select(Material::WoodPlanks);
+        assert!(
+            matches!(view_model.animation, Animation::Swap(elapsed) if elapsed > SWAP_HALF_DURATION)
+        );
+        still(&mut view_model, 1.0);
+        assert_eq!(view_model.shown, Material::WoodPlanks);
+        assert_eq!(view_model.animation, Animation::Idle);
+
+        // Nothing held draws nothing
+        view_model.select(Material::Void);
+        still(&mut view_model, 1.0);
+        assert_eq!(view_model.layer(), None);
+
+        // Unknown materials look as they do in the world
+        let unknown = Material::from_id(Material::COUNT as u16 + 3);
+        assert!(!unknown.is_known());
+        assert_eq!(texture_layer(unknown), Some(126));
+        for &material in &Material::VALUES[1..] {
+            assert_eq!(texture_layer(material), Some(u32::from(material.id()) - 1));
+        }
+    }
+
+    #[test]
+    fn sways_with_motion() {
+        let mut view_model = ViewModel::new(Material::Dirt);
+        let rest = view_model.transform();
+        for _ in 0..60 {
+            view_model.update(DT, &na::Vector3::new(0.0, 0.0, -5.0), 0.0);
+        }
+        // Lags behind the direction of travel, within bounds
+        let lag = view_model.transform()[(2, 3)] - rest[(2, 3)];
+        assert!(lag > 0.0 && lag <= MAX_SWAY + 1e-6, "{lag}");
+        for _ in 0..60 {
+            view_model.update(DT, &na::Vector3::new(0.0, 0.0, -1000.0), 0.0);
+        }
+        let lag = view_model.transform()[(2, 3)] - rest[(2, 3)];
+        assert!((lag - MAX_SWAY).abs() < 1e-4, "{lag}");
+
+        // And rocks with each footstep
+        let mut view_model = ViewModel::new(Material::Dirt);
+        view_model.update(DT, &na::zero(), 0.25);
+        assert!(view_model.transform()[(0, 3)] > rest[(0, 3)]);
+        view_model.update(DT, &na::zero(), 0.75);
+        assert!(view_model.transform()[(0, 3)] < rest[(0, 3)]);
+    }
+}
@@ -0,0 +1,315 @@
+//! Waypoints kept by the player, and how waypoints are marked on screen
+//!
+//! Shared waypoints are kept by the server and arrive through `Sim`. Personal waypoints are kept
+//! by each client per server, so they can be made without the permission to share them.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use common::{graph::Graph, proto::Position, waypoint::Waypoint};
+
+use crate::{breadcrumbs::Breadcrumb, server_files::per_server_file_name};
+
+/// Farthest from the center of the screen, in normalized device coordinates, that markers for
+/// waypoints out of view are placed
+pub const EDGE_INSET: f32 = 0.9;
+
+/// Waypoints made by the player on one server, by name
+pub struct PersonalWaypoints {
+    file: PathBuf,
+    waypoints: BTreeMap<String, Waypoint>,
+}
+
+impl PersonalWaypoints {
+    /// Load the waypoints made on the server identified by `server` from `dir`
+    pub fn open(dir: &Path, server: &str) -> Self {
+        let file = dir.join(per_server_file_name(server, "toml"));
+        let waypoints = match fs::read_to_string(&file) {
+            Ok(data) => match toml::from_str::<WaypointsFile>(&data) {
+                Ok(x) => x
+                    .waypoints
+                    .into_iter()
+                    .map(|waypoint| (waypoint.name.clone(), waypoint))
+                    .collect(),
+                Err(e) => {
+                    warn!("ignoring malformed waypoints in {}: {}", file.display(), e);
+                    BTreeMap::new()
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                warn!("couldn't read waypoints from {}: {}", file.display(), e);
+                BTreeMap::new()
+            }
+        };
+        Self { file, waypoints }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Waypoint> {
+        self.waypoints.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Waypoint> {
+        self.waypoints.values()
+    }
+
+    /// Add `waypoint`, replacing any of the same name
+    pub fn insert(&mut self, waypoint: Waypoint) -> io::Result<()> {
+        self.waypoints.insert(waypoint.name.clone(), waypoint);
+        self.save()
+    }
+
+    /// Remove the waypoint named `name`, returning whether there was one
+    pub fn remove(&mut self, name: &str) -> io::Result<bool> {
+        if self.waypoints.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Replace the file atomically, so a crash mid-write leaves the previous waypoints intact
+    fn save(&self) -> io::Result<()> {
+        let data = toml::to_string(&WaypointsFile {
+            waypoints: self.waypoints.values().cloned().collect(),
+        })
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = self.file.with_extension("toml.tmp");
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.file)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WaypointsFile {
+    waypoints: Vec<Waypoint>,
+}
+
+impl From<&Waypoint> for Breadcrumb {
+    fn from(waypoint: &Waypoint) -> Self {
+        Self {
+            path: waypoint.path.clone(),
+            point: waypoint.point().into(),
+        }
+    }
+}
+
+/// `waypoints` paired with their distances from `view` in absolute units, nearest first
+pub fn by_distance<'a>(
+    graph: &Graph,
+    view: &Position,
+    waypoints: impl IntoIterator<Item = &'a Waypoint>,
+) -> Vec<(&'a Waypoint, f32)> {
+    let mut result = waypoints
+        .into_iter()
+        .map(|waypoint| (waypoint, Breadcrumb::from(waypoint).distance(graph, view)))
+        .collect::<Vec<_>>();
+    result.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.name.cmp(&b.0.name)));
+    result
+}
+
+/// Where a waypoint's marker is drawn
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Marker {
+    /// At the waypoint itself, given in view space
+    InWorld(na::Vector4<f32>),
+    /// On screen at `position` in normalized device coordinates, for a waypoint in view but too
+    /// far away to be rendered
+    Distant { position: na::Point2<f32> },
+    /// At `position` in normalized device coordinates near the edge of the screen, for a waypoint
+    /// out of view, which lies towards `direction`
+    Edge {
+        position: na::Point2<f32>,
+        direction: na::UnitVector2<f32>,
+    },
+}
+
+/// Choose how to mark a waypoint at `point` in view space, which `projection` maps to clip space
+///
+/// `rendered` is whether the waypoint's node is near enough to be drawn. Otherwise, `point` may be
+/// scaled arbitrarily, as from `Breadcrumb::locate`, since only its direction is used.
+pub fn marker(projection: &na::Matrix4<f32>, point: &na::Vector4<f32>, rendered: bool) -> Marker {
+    let clip = projection * point;
+    // Behind the camera, a point's projection is mirrored through the center of the screen
+    let in_front = clip.w > 0.0;
+    if in_front {
+        let ndc = clip.xy() / clip.w;
+        if ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0 {
+            return if rendered {
+                Marker::InWorld(*point)
+            } else {
+                Marker::Distant {
+                    position: ndc.into(),
+                }
+            };
+        }
+    }
+    // Whether ahead of or behind the camera, the sign of the clip-space x and y says which way to
+    // turn. Directly behind, either way will do.
+    let direction = na::UnitVector2::try_new(clip.xy(), 1e-6).unwrap_or_else(na::Vector2::y_axis);
+    let position = direction.into_inner() * (EDGE_INSET / direction.x.abs().max(direction.y.abs()));
+    Marker::Edge {
+        position: position.into(),
+        direction,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::Frustum;
+    use approx::*;
+    use common::{dodeca::Side, graph::NodeId, math};
+
+    fn waypoint(graph: &Graph, name: &str, offset: na::Vector3<f32>) -> Waypoint {
+        let position = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&offset),
+        };
+        Waypoint::new(graph, &position, name.into(), [0, 128, 255], "me".into())
+    }
+
+    #[test]
+    fn personal_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut graph = Graph::new(4);
+        let node = graph.ensure_neighbor(NodeId::ROOT, Side::B);
+        let far = Waypoint::new(
+            &graph,
+            &Position {
+                node,
+                local: math::translate_along(&na::Vector3::new(0.1, 0.0, -0.2)),
+            },
+            "far".into(),
+            [255, 0, 0],
+            "me".into(),
+        );
+        let near = waypoint(&graph, "near", na::Vector3::new(0.0, 0.05, 0.0));
+
+        let mut waypoints = PersonalWaypoints::open(dir.path(), "example.com:1234");
+        assert_eq!(waypoints.iter().count(), 0);
+        waypoints.insert(far.clone()).unwrap();
+        waypoints.insert(near.clone()).unwrap();
+        assert!(waypoints.remove("near").unwrap());
+        assert!(!waypoints.remove("near").unwrap());
+
+        let reopened = PersonalWaypoints::open(dir.path(), "example.com:1234");
+        assert_eq!(reopened.iter().collect::<Vec<_>>(), [&far]);
+        assert_eq!(
+            reopened.get("far").unwrap().path.resolve(&graph),
+            Some(node)
+        );
+        assert_eq!(
+            PersonalWaypoints::open(dir.path(), "elsewhere")
+                .iter()
+                .count(),
+            0
+        );
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn nearest_first() {
+        let graph = Graph::new(4);
+        let a = waypoint(&graph, "a", na::Vector3::new(0.3, 0.0, 0.0));
+        let b = waypoint(&graph, "b", na::Vector3::new(0.0, 0.0, -0.1));
+        let view = Position::origin();
+        let sorted = by_distance(&graph, &view, [&a, &b]);
+        assert_eq!(sorted[0].0.name, "b");
+        assert_abs_diff_eq!(sorted[0].1, 0.1, epsilon = 1e-4);
+        assert_eq!(sorted[1].0.name, "a");
+        assert_abs_diff_eq!(sorted[1].1, 0.3, epsilon = 1e-4);
+    }
+
+    /// The marker for a waypoint at `point` in world space, seen from a camera transformed by
+    /// `camera`
+    fn mark(camera: &na::Matrix4<f32>, point: na::Vector3<f32>, rendered: bool) -> Marker {
+        let projection = Frustum::from_vfov(0.5, 1.5).projection(1e-4);
+        let point = math::mtranspose(camera) * math::translate_along(&point) * math::origin();
+        marker(projection.matrix(), &point, rendered)
+    }
+
+    fn yaw(angle: f32) -> na::Matrix4<f32> {
+        na::Rotation3::from_axis_angle(&na::Vector3::y_axis(), angle).to_homogeneous()
+    }
+
+    fn assert_on_edge(marker: Marker) -> (na::Point2<f32>, na::UnitVector2<f32>) {
+        match marker {
+            Marker::Edge {
+                position,
+                direction,
+            } => {
+                assert_abs_diff_eq!(position.x.abs().max(position.y.abs()), EDGE_INSET);
+                (position, direction)
+            }
+            _ => panic!("expected an edge marker, got {marker:?}"),
+        }
+    }
+
+    #[test]
+    fn ahead_in_world() {
+        let ahead = na::Vector3::new(0.0, 0.0, -0.3);
+        match mark(&na::Matrix4::identity(), ahead, true) {
+            Marker::InWorld(point) => assert_abs_diff_eq!(
+                point,
+                math::translate_along(&ahead) * math::origin(),
+                epsilon = 1e-5
+            ),
+            marker => panic!("expected an in-world marker, got {marker:?}"),
+        }
+        // Too far away to be drawn, but still in view
+        match mark(&na::Matrix4::identity(), ahead, false) {
+            Marker::Distant { position } => {
+                assert_abs_diff_eq!(position, na::Point2::origin(), epsilon = 1e-5)
+            }
+            marker => panic!("expected a distant marker, got {marker:?}"),
+        }
+    }
+
+    #[test]
+    fn out_of_view_on_edge() {
+        // Ahead and slightly to the left, but the camera has turned well to the right
+        let point = na::Vector3::new(-0.05, 0.0, -0.3);
+        let camera = yaw(-1.2);
+        let (position, direction) = assert_on_edge(mark(&camera, point, true));
+        assert_abs_diff_eq!(position.x, -EDGE_INSET);
+        assert!(direction.x < 0.0);
+
+        // Turned around, so the point is behind and to the right
+        let camera = yaw(std::f32::consts::PI);
+        let (position, direction) = assert_on_edge(mark(&camera, point, true));
+        assert_abs_diff_eq!(position.x, EDGE_INSET);
+        assert!(direction.x > 0.0);
+
+        // Moved past the point, leaving it behind, overhead
+        let camera = math::translate_along(&na::Vector3::new(0.0, -0.1, -0.6));
+        let (position, direction) =
+            assert_on_edge(mark(&camera, na::Vector3::new(0.0, 0.0, -0.3), false));
+        // Up is towards negative y in normalized device coordinates
+        assert_abs_diff_eq!(position.y, -EDGE_INSET);
+        assert!(direction.y < 0.0);
+    }
+
+    #[test]
+    fn directly_behind_still_marked() {
+        let (_, direction) = assert_on_edge(mark(
+            &yaw(std::f32::consts::PI),
+            na::Vector3::new(0.0, 0.0, -0.3),
+            true,
+        ));
+        assert!(direction.into_inner().iter().all(|x| x.is_finite()));
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+/// Differences from the server's clock larger than this, as a fraction of a day, are taken to be
+/// deliberate jumps rather than drift
+const SNAP_THRESHOLD: f32 = 0.05;
+
+/// Local estimate of the server's day/night cycle
+///
+/// Advances at the configured rate between `StateDelta`s. When the server reports a slightly
+/// different time, the estimate runs at up to double speed or pauses until it catches up, so that
+/// it never visibly runs backwards.
+pub struct WorldClock {
+    /// Fraction of the day elapsed, or `None` if the server hasn't reported a time yet
+    time: Option<f32>,
+    /// Outstanding correction towards the server's time, in fractions of a day
+    error: f32,
+    /// Fractions of a day per second
+    rate: f32,
+}
+
+impl WorldClock {
+    pub fn new(day_length_seconds: f32) -> Self {
+        Self {
+            time: None,
+            error: 0.0,
+            rate: if day_length_seconds > 0.0 {
+                day_length_seconds.recip()
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Incorporate a time reported by the server
+    pub fn observe(&mut self, server_time: f32) {
+        let Some(time) = self.time else {
+            self.time = Some(server_time);
+            return;
+        };
+        // Signed difference in [-0.5, 0.5), taking the shorter way around the cycle
+        let difference = (server_time - time + 0.5).rem_euclid(1.0) - 0.5;
+        if difference.abs() > SNAP_THRESHOLD {
+            self.time = Some(server_time);
+            self.error = 0.0;
+        } else {
+            self.error = difference;
+        }
+    }
+
+    pub fn advance(&mut self, dt: Duration) {
+        let Some(ref mut time) = self.time else {
+            return;
+        };
+        let nominal = self.rate * dt.as_secs_f32();
+        let correction = self.error.clamp(-nominal, nominal);
+        self.error -= correction;
+        *time = (*time + nominal + correction).rem_euclid(1.0);
+    }
+
+    /// Fraction of the day elapsed, as in `proto::StateDelta::world_time`
+    pub fn get(&self) -> f32 {
+        self.time.unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Forward distance from `a` to `b` around the cycle
+    fn forward(a: f32, b: f32) -> f32 {
+        (b - a).rem_euclid(1.0)
+    }
+
+    #[test]
+    fn monotonic_across_wrap() {
+        // A ten-second day observed at 10Hz, rendered at 60Hz
+        let mut clock = WorldClock::new(10.0);
+        let frame = Duration::from_secs(1) / 60;
+        let mut server_time = 0.9;
+        let mut since_delta = Duration::ZERO;
+        let mut previous = None;
+        let mut wrapped = false;
+        let mut deltas = 0;
+        clock.observe(server_time);
+        for _ in 0..600 {
+            if since_delta >= Duration::from_millis(100) {
+                since_delta -= Duration::from_millis(100);
+                // Simulate jittery delivery that alternately leads and lags
+                let jitter = if deltas % 2 == 0 { 0.002 } else { -0.002 };
+                clock.observe((server_time + jitter).rem_euclid(1.0));
+                deltas += 1;
+            }
+            clock.advance(frame);
+            since_delta += frame;
+            server_time = (server_time + frame.as_secs_f32() / 10.0) % 1.0;
+
+            let time = clock.get();
+            assert!((0.0..1.0).contains(&time));
+            if let Some(previous) = previous {
+                let step = forward(previous, time);
+                // Never runs backwards, and never more than double speed
+                assert!(
+                    step < 2.1 * frame.as_secs_f32() / 10.0,
+                    "{previous} -> {time}"
+                );
+                wrapped |= time < previous;
+            }
+            previous = Some(time);
+        }
+        assert!(wrapped);
+        assert!(forward(clock.get(), server_time).min(forward(server_time, clock.get())) < 0.005);
+    }
+
+    #[test]
+    fn snaps_to_distant_time() {
+        let mut clock = WorldClock::new(10.0);
+        clock.observe(0.1);
+        clock.advance(Duration::from_millis(100));
+        clock.observe(0.6);
+        assert_eq!(clock.get(), 0.6);
+    }
+}
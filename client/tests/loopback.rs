@@ -0,0 +1,1871 @@
+//! A real server and several headless clients exchanging messages in-process
+//!
+//! Time advances one step interval at a time for the server and every client alike, and each
+//! client's messages can be held back to simulate latency, so these tests run identically every
+//! time.
+
+use std::{collections::VecDeque, f32::consts::TAU, time::Duration};
+
+use fxhash::FxHashSet;
+use nalgebra as na;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use tokio::sync::mpsc;
+
+use client::{
+    net::{self, Message, Net},
+    CameraConfig, Sim,
+};
+use common::{
+    block_entity::BlockEntity,
+    character_controller::Tether,
+    codec,
+    coords::voxel_center_position,
+    dodeca::{self, Vertex},
+    graph::Graph,
+    logging::{self, EditId},
+    math,
+    node::{Chunk, ChunkId, Coords},
+    node_path::NodePath,
+    protection::ProtectedRegion,
+    proto::{
+        self,
+        negotiation::{Refusal, MIN_PROTOCOL_VERSION},
+        BlockUpdate, Capabilities, ClientHello, Position, SoundEvent, SoundKind, SoundSource,
+    },
+    traffic::Traffic,
+    traversal::{nearby_nodes, nearest_missing_node},
+    waypoint::Waypoint,
+    world::{Material, Shape},
+    worldgen::{ChunkParams, TerrainPassKind},
+    worldgen_cache::{ChunkKey, WorldgenCache},
+    EntityId, SimConfig, SimConfigRaw, MAX_UPDATE_STRIDE,
+};
+use server::{
+    AdminCommand, AdminError, BlockChange, ConnectionStats, DeparturePolicy, IdlePolicy,
+    LocalClientId, LocalMessage, LocalServer, Place, SaveParams, TaskKind, CONSOLE_ACTOR,
+};
+
+/// Name of the only client permitted to send administrative commands
+const ADMIN: &str = "admin";
+
+#[test]
+fn block_updates_reach_other_clients() {
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(a) && h.ready(b));
+
+    // Break the block underfoot
+    harness.sim(a).look(0.0, -1.5, 0.0);
+    harness.run_until(20, |h| matches!(h.sim(a).target(), Ok(Some(_))));
+    harness.sim(a).set_break_block_pressed_true();
+    harness.run_until(5, |h| h.clients[a].block_updates.len() == 1);
+    let broken = harness.clients[a].block_updates[0].clone();
+    assert_eq!(broken.new_material, Material::Void);
+    harness.run_until(20, |h| {
+        h.sim(b).graph.get_block(broken.chunk_id, broken.coords) == Some(Material::Void)
+    });
+
+    // Put it back with what was gained by breaking it
+    harness.run_until(20, |h| {
+        h.sim(a).predicted_inventory().iter().next().is_some()
+    });
+    harness.sim(a).cycle_selected_material();
+    let material = harness.sim(a).selected_material();
+    assert_ne!(material, Material::Void);
+    harness.sim(a).set_place_block_pressed_true();
+    harness.run_until(5, |h| h.clients[a].block_updates.len() == 2);
+    let placed = harness.clients[a].block_updates[1].clone();
+    assert_eq!(placed.new_material, material);
+    harness.run_until(20, |h| {
+        h.sim(b).graph.get_block(placed.chunk_id, placed.coords) == Some(material)
+    });
+}
+
+#[test]
+fn block_updates_traceable_through_logs() {
+    let (_guard, logs) = logging::capture("info,client=debug,server=debug");
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(a) && h.ready(b));
+
+    harness.sim(a).look(0.0, -1.5, 0.0);
+    harness.run_until(20, |h| matches!(h.sim(a).target(), Ok(Some(_))));
+    harness.sim(a).set_break_block_pressed_true();
+    harness.run_until(5, |h| h.clients[a].block_updates.len() == 1);
+    let broken = harness.clients[a].block_updates[0].clone();
+    harness.run_until(20, |h| {
+        h.sim(b).graph.get_block(broken.chunk_id, broken.coords) == Some(Material::Void)
+    });
+
+    // Everything logged about the update is found by its ID alone
+    let edit = EditId::new(harness.clients[a].character.unwrap(), &broken);
+    let field = format!("edit={edit}");
+    let traced = logs
+        .lines()
+        .into_iter()
+        .filter(|line| {
+            line.split(|c: char| c.is_whitespace() || c == '{' || c == '}')
+                .any(|x| x == field)
+        })
+        .collect::<Vec<_>>();
+    let count = |stage: &str| traced.iter().filter(|x| x.contains(stage)).count();
+    assert_eq!(count("requesting block update"), 1, "{traced:#?}");
+    assert_eq!(count("applied block update"), 1, "{traced:#?}");
+    assert_eq!(count("broadcasting block update"), 1, "{traced:#?}");
+    // By the client that made it and by the other
+    assert_eq!(count("applying block update"), 2, "{traced:#?}");
+}
+
+#[test]
+fn edits_survive_eviction_races() {
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    // Evicts chunks as edits to them arrive
+    let b = harness.connect("b");
+    // Never evicts anything
+    let control = harness.connect("control");
+    harness.run_until(100, |h| [a, b, control].iter().all(|&i| h.ready(i)));
+    let mut rng = SmallRng::seed_from_u64(664);
+    let mut cache = WorldgenCache::new(1 << 24);
+    let mut edited = FxHashSet::default();
+    let mut evictions = 0;
+
+    // Break blocks on the ground all around, while b evicts each edited chunk at some point
+    // before, during, or after the edit's arrival, and regenerates it either from the cache at once
+    // or by world generation whenever the harness gets to it
+    harness.sim(a).look(0.0, -0.8, 0.0);
+    for _ in 0..40 {
+        harness.clients[b].latency = rng.gen_range(0..4);
+        harness.sim(a).look(rng.gen_range(0.0..TAU), 0.0, 0.0);
+        harness.run_until(20, |h| matches!(h.sim(a).target(), Ok(Some(_))));
+        let sent = harness.clients[a].block_updates.len();
+        harness.sim(a).set_break_block_pressed_true();
+        harness.run_until(5, |h| h.clients[a].block_updates.len() == sent + 1);
+        let chunk = harness.clients[a].block_updates[sent].chunk_id;
+        edited.insert(chunk);
+        harness.run(rng.gen_range(0..4));
+        let sim = harness.sim(b);
+        if let Some(Chunk::Populated {
+            modified: false, ..
+        }) = sim.graph.get_chunk(chunk)
+        {
+            let key = ChunkKey::new(&sim.graph, chunk);
+            cache.evict(key, sim.graph.evict_chunk(chunk));
+            evictions += 1;
+            if rng.gen() {
+                sim.populate_chunk(chunk, cache.take(&key).unwrap());
+            }
+        }
+    }
+    harness.clients[b].latency = 0;
+    harness.run(10);
+    assert!(evictions > 0);
+
+    // Every edit landed exactly as it did where nothing was evicted
+    let dimension = harness.server.cfg().chunk_size;
+    for &chunk in &edited {
+        for i in [b, control] {
+            if let Some(Chunk::Fresh) = harness.sim(i).graph.get_chunk(chunk) {
+                generate_chunk(harness.sim(i), chunk);
+            }
+        }
+        let voxels = |h: &mut Harness, i| {
+            let graph = &h.sim(i).graph;
+            let Some(Chunk::Populated { voxels, .. }) = graph.get_chunk(chunk) else {
+                panic!("{chunk:?} never populated");
+            };
+            let voxels = voxels.view(dimension);
+            (0..dimension)
+                .flat_map(|x| {
+                    (0..dimension).flat_map(move |y| (0..dimension).map(move |z| [x, y, z]))
+                })
+                .map(|coords| (voxels.get(Coords(coords)), voxels.shape(Coords(coords))))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(voxels(&mut harness, b), voxels(&mut harness, control));
+    }
+    for update in harness.clients[a].block_updates.clone() {
+        assert_eq!(
+            harness
+                .sim(b)
+                .graph
+                .get_block(update.chunk_id, update.coords),
+            Some(Material::Void)
+        );
+    }
+}
+
+#[test]
+fn predictions_converge_after_latency_spike() {
+    let mut harness = Harness::new();
+    let clients = [harness.connect("a"), harness.connect("b")];
+    harness.run_until(100, |h| clients.iter().all(|&i| h.ready(i)));
+    let m = harness.server.cfg().meters_to_absolute;
+    let starts = clients.map(|i| harness.server.position(harness.clients[i].id).unwrap());
+
+    for (&i, direction) in clients.iter().zip([-na::Vector3::z(), na::Vector3::x()]) {
+        harness.sim(i).set_movement_input(direction * 0.2);
+    }
+    harness.run(10);
+    // Hold every message for half a second in each direction, while the clients keep moving
+    for &i in &clients {
+        harness.clients[i].latency = 5;
+    }
+    harness.run(20);
+    for &i in &clients {
+        harness.clients[i].latency = 0;
+        harness.sim(i).set_movement_input(na::Vector3::zeros());
+    }
+    harness.run(50);
+
+    for (&i, start) in clients.iter().zip(&starts) {
+        let id = harness.clients[i].id;
+        let authoritative = harness.server.position(id).unwrap();
+        let sim = harness.sim(i);
+        let moved = separation(&sim.graph, &authoritative, start);
+        assert!(moved > m, "client {i} only moved {moved}");
+        let error = separation(&sim.graph, &sim.view().position().unwrap(), &authoritative);
+        assert!(error < 0.01 * m, "client {i} predicted {error} away");
+    }
+}
+
+#[test]
+fn disconnected_characters_despawn() {
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(a) && h.ready(b));
+    let character = harness.clients[a].character.unwrap();
+    assert!(knows_of(harness.sim(b), character));
+
+    harness.disconnect(a);
+    harness.run_until(5, |h| !knows_of(h.sim(b), character));
+    // The departed client doesn't come back
+    harness.run(5);
+    assert!(!knows_of(harness.sim(b), character));
+}
+
+#[test]
+fn entity_ids_never_collide() {
+    let mut harness = Harness::new();
+    let watcher = harness.connect("watcher");
+    harness.run_until(100, |h| h.ready(watcher));
+
+    // Characters come and go, some within a single step, without their clients ever stepping
+    let mut guests = VecDeque::new();
+    for i in 0..10_000 {
+        guests.push_back(harness.server.connect(&format!("guest {i}")));
+        while guests.len() > i % 7 {
+            harness.server.disconnect(guests.pop_front().unwrap());
+        }
+        if i % 10 == 0 {
+            harness.step();
+        }
+    }
+    for guest in guests {
+        harness.server.disconnect(guest);
+    }
+    harness.run(2);
+
+    let sim = harness.sim(watcher);
+    assert_eq!(sim.debug_info().protocol_errors, 0);
+    // Only the watcher itself remains
+    let ids = sim
+        .world
+        .query::<&EntityId>()
+        .iter()
+        .map(|(_, &id)| id)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, [harness.clients[watcher].character.unwrap()]);
+}
+
+#[test]
+fn teleports_snap_prediction() {
+    let mut harness = Harness::new();
+    let admin = harness.connect(ADMIN);
+    harness.run_until(100, |h| h.ready(admin));
+    let m = harness.server.cfg().meters_to_absolute;
+    let id = harness.clients[admin].id;
+    let before = harness.server.position(id).unwrap();
+
+    // Straight up, into open air
+    let sim = harness.sim(admin);
+    let up = sim.graph.get_relative_up(&before).unwrap();
+    harness.clients[admin]
+        .net
+        .outgoing
+        .send(proto::ClientMessage::Teleport {
+            character: ADMIN.into(),
+            destination: proto::TeleportDestination::Relative {
+                translation: up.into_inner() * 5.0 * m,
+            },
+        })
+        .unwrap();
+    harness.run_until(5, |h| {
+        let sim = h.sim(admin);
+        separation(&sim.graph, &sim.view().position().unwrap(), &before) > 4.0 * m
+    });
+
+    // Both the prediction and the camera jump straight to the destination
+    let after = harness.server.position(id).unwrap();
+    let sim = harness.sim(admin);
+    let error = separation(&sim.graph, &sim.view().position().unwrap(), &after);
+    assert!(error < 0.01 * m, "predicted {error} away");
+    let camera = sim.camera().position().unwrap();
+    let view = sim.view().position().unwrap();
+    assert_eq!(camera.node, view.node);
+    assert_eq!(camera.local, view.local);
+}
+
+#[test]
+fn knockback_predicted_like_authority() {
+    let mut harness = Harness::new();
+    let client = harness.connect("player");
+    harness.run_until(100, |h| h.ready(client));
+    harness.run(20);
+    let m = harness.server.cfg().meters_to_absolute;
+    let id = harness.clients[client].id;
+    let start = harness.server.position(id).unwrap();
+
+    // Below and to one side, so the character is thrown up and across the ground
+    let up = harness.sim(client).graph.get_relative_up(&start).unwrap();
+    let side = up.cross(&na::Vector3::x()).normalize();
+    let center = Position {
+        node: start.node,
+        local: start.local * math::translate_along(&((side - up.into_inner()) * m)),
+    };
+    harness.server.blast(&center, 3.0 * m, 10.0 * m);
+
+    let trajectories = record_trajectories(&mut harness, client, 30);
+    let end = harness.server.position(id).unwrap();
+    let sim = harness.sim(client);
+    assert!(
+        separation(&sim.graph, &start, &end) > m,
+        "blast had no effect"
+    );
+    assert_trajectories_match(&sim.graph, &trajectories, m);
+}
+
+#[test]
+fn tether_predicted_like_authority() {
+    let mut harness = Harness::new();
+    let client = harness.connect("player");
+    harness.run_until(100, |h| h.ready(client));
+    harness.run(20);
+    let m = harness.server.cfg().meters_to_absolute;
+    let id = harness.clients[client].id;
+    let start = harness.server.position(id).unwrap();
+
+    // Above and to one side, lifting the character off the ground
+    let up = harness.sim(client).graph.get_relative_up(&start).unwrap();
+    let side = up.cross(&na::Vector3::x()).normalize();
+    let anchor = Position {
+        node: start.node,
+        local: start.local * math::translate_along(&((side * 3.0 + up.into_inner()) * m)),
+    };
+    harness.server.set_tether(
+        id,
+        Some(Tether {
+            anchor,
+            stiffness: 20.0,
+            damping: 8.0,
+            max_acceleration: 50.0 * m,
+        }),
+    );
+
+    let trajectories = record_trajectories(&mut harness, client, 30);
+    let end = harness.server.position(id).unwrap();
+    let sim = harness.sim(client);
+    assert!(
+        separation(&sim.graph, &start, &end) > m,
+        "tether had no effect"
+    );
+    assert_trajectories_match(&sim.graph, &trajectories, m);
+}
+
+#[test]
+fn waypoints_sync_on_join_and_change() {
+    let mut harness = Harness::new();
+    let admin = harness.connect(ADMIN);
+    harness.run_until(100, |h| h.ready(admin));
+    let spawn = harness.sim(admin).view().position().unwrap();
+    let camp = Waypoint::new(
+        &harness.sim(admin).graph,
+        &spawn,
+        "camp".into(),
+        [255, 0, 0],
+        String::new(),
+    );
+    harness.send(admin, proto::ClientMessage::SetWaypoint(camp.clone()));
+    // Even the client that shared it waits to hear from the server
+    harness.run_until(5, |h| shared_names(h.sim(admin)) == ["camp"]);
+
+    // Clients joining later are told of every waypoint
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(b));
+    let received = harness
+        .sim(b)
+        .shared_waypoints()
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        received,
+        [Waypoint {
+            owner: ADMIN.into(),
+            ..camp.clone()
+        }]
+    );
+
+    // Only administrators can change them
+    let mine = Waypoint {
+        name: "mine".into(),
+        ..camp.clone()
+    };
+    harness.send(b, proto::ClientMessage::SetWaypoint(mine));
+    harness.send(b, proto::ClientMessage::RemoveWaypoint("camp".into()));
+    harness.run(5);
+    assert_eq!(shared_names(harness.sim(b)), ["camp"]);
+
+    // Changes reach everyone as they happen
+    let lake = Waypoint {
+        name: "lake".into(),
+        color: [0, 0, 255],
+        ..camp
+    };
+    harness.send(admin, proto::ClientMessage::SetWaypoint(lake));
+    harness.send(admin, proto::ClientMessage::RemoveWaypoint("camp".into()));
+    harness.run_until(5, |h| {
+        [admin, b]
+            .iter()
+            .all(|&i| shared_names(h.sim(i)) == ["lake"])
+    });
+    assert_eq!(
+        harness.sim(b).shared_waypoints().next().unwrap().color,
+        [0, 0, 255]
+    );
+}
+
+#[test]
+fn protected_blocks_roll_back() {
+    let mut harness = Harness::new();
+    let admin = harness.connect(ADMIN);
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(admin) && h.ready(b));
+    let m = harness.server.cfg().meters_to_absolute;
+
+    // Protect everything within the view distance of b's node, from everyone
+    let center = Position {
+        node: harness.sim(b).view().position().unwrap().node,
+        local: na::Matrix4::identity(),
+    };
+    let spawn = ProtectedRegion {
+        name: "spawn".into(),
+        center_path: NodePath::to(&harness.sim(b).graph, center.node),
+        radius: 20.0,
+        allowed: Default::default(),
+    };
+    harness.send(admin, proto::ClientMessage::SetProtectedRegion(spawn));
+    harness.run(2);
+
+    // Breaking a block is predicted, then undone when the server refuses it
+    harness.clients[b].latency = 2;
+    harness.sim(b).look(0.0, -1.5, 0.0);
+    harness.run_until(20, |h| matches!(h.sim(b).target(), Ok(Some(_))));
+    harness.sim(b).set_break_block_pressed_true();
+    harness.run_until(5, |h| h.clients[b].block_updates.len() == 1);
+    let broken = harness.clients[b].block_updates[0].clone();
+    let voxel = voxel_center_position(
+        harness.sim(b).graph.layout(),
+        broken.chunk_id,
+        broken.coords,
+    );
+    assert!(separation(&harness.sim(b).graph, &center, &voxel) < 20.0 * m);
+    let block = |h: &mut Harness, client| {
+        h.sim(client)
+            .graph
+            .get_block(broken.chunk_id, broken.coords)
+    };
+    assert_eq!(block(&mut harness, b), Some(Material::Void));
+    harness.run_until(10, |h| block(h, b) == Some(Material::Dirt));
+    assert_eq!(block(&mut harness, admin), Some(Material::Dirt));
+
+    // Lifting the protection needs no restart
+    harness.clients[b].latency = 0;
+    harness.send(
+        admin,
+        proto::ClientMessage::RemoveProtectedRegion("spawn".into()),
+    );
+    harness.run(2);
+    harness.sim(b).set_break_block_pressed_true();
+    harness.run_until(5, |h| h.clients[b].block_updates.len() == 2);
+    harness.run_until(20, |h| {
+        [admin, b]
+            .iter()
+            .all(|&i| block(h, i) == Some(Material::Void))
+    });
+}
+
+#[test]
+fn admin_commands_honor_actors() {
+    let mut harness = Harness::new();
+    let admin = harness.connect(ADMIN);
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(admin) && h.ready(b));
+    let m = harness.server.cfg().meters_to_absolute;
+    let (admin_id, b_id) = (harness.clients[admin].id, harness.clients[b].id);
+    let summon = |place| AdminCommand::Summon {
+        character: "b".into(),
+        place,
+    };
+
+    // Only administrators and the console may run commands, even saving or setting the time
+    for actor in [Some(admin_id), None] {
+        let result = match actor {
+            Some(id) => harness.server.admin_as(id, AdminCommand::Save),
+            None => harness.server.admin(AdminCommand::Save),
+        };
+        assert!(result.is_ok(), "{result:?}");
+    }
+    assert_eq!(
+        harness.server.admin_as(b_id, AdminCommand::Save),
+        Err(AdminError::Forbidden)
+    );
+    assert_eq!(
+        harness
+            .server
+            .admin_as(b_id, AdminCommand::SetWorldTime(0.5)),
+        Err(AdminError::Forbidden)
+    );
+    for x in [f32::NAN, f32::INFINITY] {
+        assert!(matches!(
+            harness
+                .server
+                .admin_as(admin_id, AdminCommand::SetWorldTime(x)),
+            Err(AdminError::Failed(_))
+        ));
+    }
+    let players = harness.server.admin(AdminCommand::ListPlayers).unwrap();
+    assert!(
+        players.contains(ADMIN) && players.contains('b'),
+        "{players}"
+    );
+    assert_eq!(
+        harness.server.admin_as(b_id, AdminCommand::ListPlayers),
+        Err(AdminError::Forbidden)
+    );
+    assert_eq!(
+        harness.server.admin_as(b_id, summon(Place::Here)),
+        Err(AdminError::Forbidden)
+    );
+
+    // Waypoints are recorded as shared by whoever shared them, the console included
+    let camp = Waypoint::new(
+        &harness.sim(admin).graph,
+        &harness.server.position(admin_id).unwrap(),
+        "camp".into(),
+        [255, 0, 0],
+        String::new(),
+    );
+    harness
+        .server
+        .admin_as(admin_id, AdminCommand::SetWaypoint(camp.clone()))
+        .unwrap();
+    let lake = Waypoint {
+        name: "lake".into(),
+        ..camp.clone()
+    };
+    harness
+        .server
+        .admin(AdminCommand::SetWaypoint(lake))
+        .unwrap();
+    harness.run_until(5, |h| shared_names(h.sim(b)).len() == 2);
+    let owners = harness
+        .sim(b)
+        .shared_waypoints()
+        .map(|x| (x.name.clone(), x.owner.clone()))
+        .collect::<Vec<_>>();
+    assert!(
+        owners.contains(&("camp".into(), ADMIN.into())),
+        "{owners:?}"
+    );
+    assert!(
+        owners.contains(&("lake".into(), CONSOLE_ACTOR.into())),
+        "{owners:?}"
+    );
+
+    // The console names where to bring a character, since it's nowhere itself
+    harness
+        .server
+        .admin(summon(Place::Waypoint("camp".into())))
+        .unwrap();
+    harness.run(2);
+    let separated = |h: &mut Harness| {
+        let a = h.server.position(admin_id).unwrap();
+        let b = h.server.position(b_id).unwrap();
+        separation(&h.sim(admin).graph, &a, &b)
+    };
+    assert!(separated(&mut harness) < 0.1 * m);
+    assert_eq!(
+        harness
+            .server
+            .admin(summon(Place::Waypoint("nowhere".into()))),
+        Err(AdminError::NoSuchWaypoint("nowhere".into()))
+    );
+
+    // Removals go through the same checks
+    assert_eq!(
+        harness
+            .server
+            .admin_as(b_id, AdminCommand::RemoveWaypoint("camp".into())),
+        Err(AdminError::Forbidden)
+    );
+    harness
+        .server
+        .admin(AdminCommand::RemoveWaypoint("camp".into()))
+        .unwrap();
+    assert_eq!(
+        harness
+            .server
+            .admin_as(admin_id, AdminCommand::RemoveWaypoint("camp".into())),
+        Err(AdminError::NoSuchWaypoint("camp".into()))
+    );
+    harness.run_until(5, |h| shared_names(h.sim(b)) == ["lake"]);
+}
+
+#[test]
+fn console_commands_need_named_places() {
+    let mut harness = Harness::new();
+    let admin = harness.connect(ADMIN);
+    harness.run_until(100, |h| h.ready(admin));
+    let admin_id = harness.clients[admin].id;
+
+    // Whatever acts "here" explains how to name a waypoint instead when run from the console
+    for line in [
+        "bring admin",
+        "teleport admin here",
+        "region add 10 spawn",
+        "rollback region here 10 5m",
+    ] {
+        let command = server::parse_command(line).unwrap().unwrap();
+        match harness.server.admin(command.clone()) {
+            Err(e @ AdminError::Nowhere(usage)) => {
+                assert!(usage.contains("<waypoint>"), "{line}: {usage}");
+                assert!(e.to_string().contains(usage), "{line}: {e}");
+            }
+            x => panic!("{line}: {x:?}"),
+        }
+        // Players have somewhere to be
+        assert!(harness.server.admin_as(admin_id, command).is_ok(), "{line}");
+    }
+    assert_eq!(
+        harness
+            .server
+            .admin(AdminCommand::RemoveProtectedRegion("spawn".into())),
+        Ok("lifted protection of region spawn".into())
+    );
+}
+
+#[test]
+fn stopping_saves_before_disconnecting() {
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    harness.run_until(100, |h| h.ready(a));
+
+    // A change the server has made but, an hour from the next autosave, not yet written
+    harness.sim(a).look(0.0, -1.5, 0.0);
+    harness.run_until(20, |h| matches!(h.sim(a).target(), Ok(Some(_))));
+    harness.sim(a).set_break_block_pressed_true();
+    harness.run_until(5, |h| h.clients[a].block_updates.len() == 1);
+    harness.run(5);
+    let broken = harness.clients[a].block_updates[0].clone();
+    let node = harness.sim(a).graph.hash_of(broken.chunk_id.node);
+
+    assert_eq!(
+        harness.server.admin(AdminCommand::Stop),
+        Ok("stopping".into())
+    );
+    assert!(harness.server.stopping());
+    let Harness {
+        server, _dir: dir, ..
+    } = harness;
+    server.shutdown();
+
+    // Both the change and the character that made it were saved
+    let save = save::Save::open(&dir.path().join("world.save"), 12).unwrap();
+    let reader = save.read().unwrap();
+    let mut reader = reader.get().unwrap();
+    let voxels = reader
+        .get_voxel_node(node)
+        .unwrap()
+        .expect("edited node saved");
+    assert!(voxels
+        .chunks
+        .iter()
+        .any(|x| x.vertex == broken.chunk_id.vertex as u32));
+    assert!(reader.get_character("a").unwrap().is_some());
+}
+
+#[test]
+fn pickups_taken_by_use() {
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    harness.run_until(100, |h| h.ready(a));
+    // Let the character settle on the ground
+    harness.run(20);
+    let m = harness.server.cfg().meters_to_absolute;
+
+    // Something to pick up, floating just ahead of the crosshair
+    let view = harness.sim(a).view().position().unwrap();
+    let pickup = harness.server.spawn(
+        Position {
+            node: view.node,
+            local: view.local * math::translate_along(&(-na::Vector3::z() * 2.0 * m)),
+        },
+        vec![proto::Component::Interactable(proto::Interactable::Pickup(
+            Material::Sand,
+        ))],
+    );
+    harness.run_until(10, |h| knows_of(h.sim(a), pickup));
+
+    harness.sim(a).set_use_pressed_true();
+    harness.run_until(10, |h| {
+        h.sim(a).predicted_inventory().count(Material::Sand) == 1
+    });
+    harness.run_until(10, |h| !knows_of(h.sim(a), pickup));
+}
+
+#[test]
+fn thrown_blocks_land_alike_everywhere() {
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(a) && h.ready(b));
+
+    // Dig up something to throw
+    harness.sim(a).look(0.0, -1.5, 0.0);
+    harness.run_until(20, |h| matches!(h.sim(a).target(), Ok(Some(_))));
+    harness.sim(a).set_break_block_pressed_true();
+    harness.run_until(20, |h| {
+        h.sim(a).predicted_inventory().iter().next().is_some()
+    });
+    harness.sim(a).cycle_selected_material();
+    let material = harness.sim(a).selected_material();
+    assert_ne!(material, Material::Void);
+
+    // Lob it well away from everyone
+    harness.sim(a).look(0.0, 2.0, 0.0);
+    harness.sim(a).set_throw_pressed_true();
+    harness.run_until(10, |h| h.sim(a).predicted_inventory().count(material) == 0);
+    harness.run_until(300, |h| !h.clients[b].server_edits.is_empty());
+    let landed = harness.clients[b].server_edits[0].clone();
+    assert_eq!(landed.new_material, material);
+    harness.run_until(20, |h| {
+        [a, b]
+            .iter()
+            .all(|&i| h.sim(i).graph.get_block(landed.chunk_id, landed.coords) == Some(material))
+    });
+    assert_eq!(harness.sim(a).predicted_inventory().count(material), 0);
+}
+
+#[test]
+fn sounds_reach_only_nearby_clients() {
+    let mut harness = Harness::new();
+    let admin = harness.connect(ADMIN);
+    let near = harness.connect("near");
+    let far = harness.connect("far");
+    harness.run_until(100, |h| [admin, near, far].iter().all(|&i| h.ready(i)));
+    let m = harness.server.cfg().meters_to_absolute;
+
+    // Send one client well beyond the view distance of the others, to hear itself arrive
+    harness.send(
+        admin,
+        proto::ClientMessage::Teleport {
+            character: "far".into(),
+            destination: proto::TeleportDestination::Relative {
+                translation: na::Vector3::x() * 100.0 * m,
+            },
+        },
+    );
+    harness.run_until(5, |h| {
+        h.clients[far]
+            .sounds
+            .iter()
+            .any(|x| x.kind == SoundKind::Teleport)
+    });
+    harness.run(2);
+    harness.sim(admin).take_sounds();
+
+    // Break the block beneath the admin, playing its sound straight away
+    harness.sim(admin).look(0.0, -1.5, 0.0);
+    harness.run_until(20, |h| matches!(h.sim(admin).target(), Ok(Some(_))));
+    harness.sim(admin).set_break_block_pressed_true();
+    let mut played = Vec::new();
+    for _ in 0..10 {
+        harness.step();
+        played.extend(harness.sim(admin).take_sounds());
+    }
+    let broken = harness.clients[admin].block_updates[0].clone();
+    let of_broken = |x: &SoundEvent| {
+        x.kind == SoundKind::BlockBroken
+            && matches!(x.source, SoundSource::Voxel(chunk, coords)
+                if chunk == broken.chunk_id && coords == broken.coords)
+    };
+
+    // Everyone nearby is told, the admin included, though it doesn't play the sound again
+    for i in [admin, near] {
+        assert_eq!(
+            harness.clients[i]
+                .sounds
+                .iter()
+                .filter(|x| of_broken(x))
+                .count(),
+            1
+        );
+    }
+    assert!(!harness.clients[far].sounds.iter().any(of_broken));
+    let broken_played = played
+        .iter()
+        .filter(|x| x.kind == SoundKind::BlockBroken)
+        .count();
+    assert_eq!(broken_played, 1);
+}
+
+#[test]
+fn followers_told_of_the_followed_surroundings() {
+    let mut harness = Harness::new();
+    let admin = harness.connect(ADMIN);
+    let watcher = harness.connect("watcher");
+    let far = harness.connect("far");
+    harness.run_until(100, |h| [admin, watcher, far].iter().all(|&i| h.ready(i)));
+    let m = harness.server.cfg().meters_to_absolute;
+    let far_id = harness.clients[far].character.unwrap();
+
+    // Send one client well beyond the view distance of the others
+    harness.send(
+        admin,
+        proto::ClientMessage::Teleport {
+            character: "far".into(),
+            destination: proto::TeleportDestination::Relative {
+                translation: na::Vector3::x() * 100.0 * m,
+            },
+        },
+    );
+    harness.run_until(5, |h| {
+        h.clients[far]
+            .sounds
+            .iter()
+            .any(|x| x.kind == SoundKind::Teleport)
+    });
+    let stride = MAX_UPDATE_STRIDE as usize;
+    harness.run(stride);
+    // Of the latest `stride` state deltas, the number that told `i` where the far character is
+    let told = |h: &Harness, i: usize| {
+        h.clients[i]
+            .delta_positions
+            .iter()
+            .rev()
+            .take(stride)
+            .filter(|x| x.contains(&far_id))
+            .count()
+    };
+    assert!(told(&harness, admin) < stride);
+
+    // Only administrators may be told of the surroundings of another
+    let regions = harness.clients[admin].graph_regions;
+    assert!(harness.sim(admin).follow("far".into(), false));
+    assert!(harness.sim(watcher).follow("far".into(), false));
+    harness.run_until(10, |h| {
+        [admin, watcher]
+            .iter()
+            .all(|&i| !h.clients[i].following.is_empty())
+    });
+    assert_eq!(harness.clients[admin].following, [Some(far_id)]);
+    assert_eq!(harness.clients[watcher].following, [None]);
+    assert_eq!(harness.sim(watcher).followed(), None);
+    assert_eq!(harness.sim(watcher).follow_lost(), Some("far"));
+    assert_eq!(harness.sim(admin).followed(), Some("far"));
+
+    // The admin is told of the far character as often as of its own, and of the graph around it
+    harness.run(stride);
+    assert_eq!(told(&harness, admin), stride);
+    assert!(harness.clients[admin].graph_regions > regions);
+    let followed = harness.server.position(harness.clients[far].id).unwrap();
+    let sim = harness.sim(admin);
+    let view = sim.view().position().unwrap();
+    assert!(separation(&sim.graph, &view, &followed) <= camera_cfg().follow_distance + 1e-3);
+
+    // And hears what happens there, unlike everyone else nearby
+    harness.sim(far).look(0.0, -1.5, 0.0);
+    harness.run_until(20, |h| matches!(h.sim(far).target(), Ok(Some(_))));
+    harness.sim(far).set_break_block_pressed_true();
+    let broken = |h: &Harness, i: usize| {
+        h.clients[i]
+            .sounds
+            .iter()
+            .any(|x| x.kind == SoundKind::BlockBroken)
+    };
+    harness.run_until(10, |h| broken(h, admin));
+    assert!(!broken(&harness, watcher));
+
+    // Losing the far character leaves the admin's camera to fly freely
+    harness.disconnect(far);
+    harness.run_until(5, |h| h.clients[admin].following.len() == 2);
+    harness.run(5);
+    assert_eq!(harness.clients[admin].following, [Some(far_id), None]);
+    let sim = harness.sim(admin);
+    assert_eq!(sim.followed(), None);
+    assert_eq!(sim.follow_lost(), Some("far"));
+    assert!(sim.debug_info().observer.is_some());
+}
+
+#[test]
+fn optional_features_follow_negotiation() {
+    for capabilities in [
+        Capabilities::NONE,
+        Capabilities::CHUNK_DIFFS,
+        Capabilities::SHARED_WAYPOINTS,
+        Capabilities::GRAPH_REGIONS,
+        Capabilities::SPLIT_UPDATES,
+        Capabilities::ALL,
+    ] {
+        let mut harness = Harness::new();
+        let admin = harness.connect(ADMIN);
+        harness.run_until(100, |h| h.ready(admin));
+
+        // Break the block beneath the admin and share a waypoint, so that a client joining later
+        // must be sent both
+        harness.sim(admin).look(0.0, -1.5, 0.0);
+        harness.run_until(20, |h| matches!(h.sim(admin).target(), Ok(Some(_))));
+        harness.sim(admin).set_break_block_pressed_true();
+        harness.run_until(5, |h| h.clients[admin].block_updates.len() == 1);
+        let broken = harness.clients[admin].block_updates[0].clone();
+        let camp = Waypoint::new(
+            &harness.sim(admin).graph,
+            &harness.sim(admin).view().position().unwrap(),
+            "camp".into(),
+            [255, 0, 0],
+            String::new(),
+        );
+        harness.send(admin, proto::ClientMessage::SetWaypoint(camp));
+        harness.run_until(5, |h| shared_names(h.sim(admin)) == ["camp"]);
+
+        let b = harness.connect_with(ClientHello {
+            capabilities,
+            ..ClientHello::new("b")
+        });
+        harness.run_until(100, |h| h.ready(b));
+        // The broken block arrives one way or another
+        harness.run_until(20, |h| {
+            h.sim(b).graph.get_block(broken.chunk_id, broken.coords) == Some(Material::Void)
+        });
+        assert_eq!(
+            harness.clients[b].chunk_diffs > 0,
+            capabilities.contains(Capabilities::CHUNK_DIFFS),
+            "{capabilities:?}"
+        );
+        // Waypoints just aren't sent to clients that don't know of them
+        let expected: &[&str] = if capabilities.contains(Capabilities::SHARED_WAYPOINTS) {
+            &["camp"]
+        } else {
+            &[]
+        };
+        assert_eq!(shared_names(harness.sim(b)), expected, "{capabilities:?}");
+    }
+}
+
+#[test]
+fn late_joiners_share_graph_regions() {
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    harness.run_until(100, |h| h.ready(a));
+
+    // Two clients joining at once are sent the same regions, encoded only for the first
+    let sent = |h: &mut Harness| {
+        let stats = h.server.stats().graph_regions;
+        (stats.encoded, stats.reused)
+    };
+    let before = sent(&mut harness);
+    let b = harness.connect("b");
+    let between = sent(&mut harness);
+    let c = harness.connect("c");
+    let after = sent(&mut harness);
+    let regions = (between.0 + between.1) - (before.0 + before.1);
+    assert!(regions > 0);
+    assert_eq!(after.0, between.0);
+    assert_eq!(after.1 - between.1, regions);
+
+    harness.run_until(100, |h| h.ready(b) && h.ready(c));
+    let node = harness.server.position(harness.clients[a].id).unwrap().node;
+    assert!(harness.sim(b).graph.contains(node));
+    assert!(harness.sim(c).graph.contains(node));
+}
+
+#[test]
+fn signs_reach_late_joiners() {
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    harness.run_until(100, |h| h.ready(a));
+
+    // Turn the block underfoot into a sign, as a scheduled edit might
+    harness.sim(a).look(0.0, -1.5, 0.0);
+    harness.run_until(20, |h| matches!(h.sim(a).target(), Ok(Some(_))));
+    let hit = harness.sim(a).target().unwrap().unwrap();
+    let (chunk, coords) = (hit.chunk, hit.voxel_coords);
+    let change = BlockChange {
+        path: NodePath::to(&harness.sim(a).graph, chunk.node),
+        vertex: chunk.vertex,
+        coords,
+        material: Material::Sign,
+        shape: Shape::FULL,
+    };
+    harness
+        .server
+        .schedule(0, None, TaskKind::SetBlocks(vec![change]));
+    harness.run_until(20, |h| {
+        h.sim(a).graph.get_block(chunk, coords) == Some(Material::Sign)
+    });
+
+    let lines = [
+        "Welcome".into(),
+        "to the".into(),
+        "void".into(),
+        String::new(),
+    ];
+    let client = &mut harness.clients[a];
+    client
+        .sim
+        .as_mut()
+        .unwrap()
+        .write_sign(lines.clone(), &mut client.net);
+    let written = |sim: &Sim| match sim.block_entity(chunk, coords) {
+        Some(BlockEntity::Sign(sign)) => sign.lines == lines,
+        None => false,
+    };
+    harness.run_until(20, |h| written(h.sim(a)));
+
+    // Clients joining later are told of it along with the block
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(b));
+    harness.run_until(20, |h| {
+        h.sim(b).graph.get_block(chunk, coords) == Some(Material::Sign)
+    });
+    assert!(written(harness.sim(b)));
+
+    // And of its removal as it happens
+    harness.server.schedule(
+        0,
+        None,
+        TaskKind::SetBlocks(vec![BlockChange {
+            path: NodePath::to(&harness.sim(a).graph, chunk.node),
+            vertex: chunk.vertex,
+            coords,
+            material: Material::Void,
+            shape: Shape::FULL,
+        }]),
+    );
+    harness.run_until(20, |h| {
+        [a, b]
+            .iter()
+            .all(|&i| h.sim(i).block_entity(chunk, coords).is_none())
+    });
+}
+
+#[test]
+fn unknown_materials_sync_unchanged() {
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    harness.run_until(100, |h| h.ready(a));
+    let m = harness.server.cfg().meters_to_absolute;
+
+    // The ground underfoot becomes a material the client doesn't know, as if the server were newer
+    let unknown = Material::from_id(Material::COUNT as u16 + 4);
+    harness.sim(a).look(0.0, -1.5, 0.0);
+    harness.run_until(20, |h| matches!(h.sim(a).target(), Ok(Some(_))));
+    let hit = harness.sim(a).target().unwrap().unwrap();
+    let (chunk, coords) = (hit.chunk, hit.voxel_coords);
+    harness.server.schedule(
+        0,
+        None,
+        TaskKind::SetBlocks(vec![BlockChange {
+            path: NodePath::to(&harness.sim(a).graph, chunk.node),
+            vertex: chunk.vertex,
+            coords,
+            material: unknown,
+            shape: Shape::FULL,
+        }]),
+    );
+    harness.run_until(20, |h| {
+        h.sim(a).graph.get_block(chunk, coords) == Some(unknown)
+    });
+
+    // Clients joining later are sent it unchanged with the rest of its chunk
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(b));
+    harness.run_until(20, |h| {
+        h.sim(b).graph.get_block(chunk, coords) == Some(unknown)
+    });
+
+    // It bears a character like any other ground, alike on the client and the server
+    let trajectories = record_trajectories(&mut harness, a, 30);
+    assert_trajectories_match(&harness.sim(a).graph, &trajectories, m);
+    let (start, end) = (&trajectories.1[0], trajectories.1.last().unwrap());
+    let fallen = separation(&harness.sim(a).graph, start, end);
+    assert!(fallen < 0.01 * m, "sank {fallen} into unknown ground");
+}
+
+#[test]
+fn hints_keep_graph_ahead_of_fast_travel() {
+    // How far beyond the view distance the nearest missing node stays from the view of a client
+    // flying at full speed over a slow connection
+    let nearest_missing = |capabilities| {
+        let mut harness = Harness::new();
+        let a = harness.connect_with(ClientHello {
+            capabilities,
+            ..ClientHello::new("a")
+        });
+        harness.run_until(100, |h| h.ready(a));
+        harness.clients[a].latency = 8;
+        harness.sim(a).set_movement_input(-na::Vector3::z());
+        // Until the first nodes asked for could have arrived
+        harness.run(40);
+        let view_distance = f64::from(harness.server.cfg().view_distance);
+        let distance = view_distance + 2.0 * dodeca::BOUNDING_SPHERE_RADIUS_F64;
+        let mut nearest = f64::INFINITY;
+        for _ in 0..200 {
+            harness.step();
+            let sim = harness.sim(a);
+            let view = sim.view().position().unwrap();
+            if let Some(x) = nearest_missing_node(&sim.graph, &view, distance) {
+                nearest = nearest.min(x);
+            }
+        }
+        nearest - view_distance
+    };
+    let hinted = nearest_missing(Capabilities::ALL);
+    let unhinted = nearest_missing(
+        Capabilities::CHUNK_DIFFS
+            | Capabilities::SHARED_WAYPOINTS
+            | Capabilities::SOUNDS
+            | Capabilities::NODE_RESYNC
+            | Capabilities::GRAPH_REGIONS
+            | Capabilities::SPLIT_UPDATES,
+    );
+    assert!(
+        hinted > 0.0,
+        "missing node {hinted} within the view distance"
+    );
+    assert!(hinted > unhinted, "{hinted} is no better than {unhinted}");
+}
+
+#[test]
+fn prefetch_keeps_prediction_off_the_frontier() {
+    // Steps in which a client walking over a 200ms round trip predicts its character held back at
+    // the edge of what it has generated
+    let holds = |prefetch| {
+        let mut harness = Harness::new();
+        let a = harness.connect("a");
+        // Only the chunks right around the view, as a renderer facing elsewhere might leave it
+        harness.clients[a].chunk_reach = Some(dodeca::BOUNDING_SPHERE_RADIUS_F64);
+        harness.clients[a].prefetch = prefetch;
+        harness.run_until(100, |h| h.ready(a));
+        assert!(harness.sim(a).toggle_no_clip());
+        // Settle onto the ground
+        harness.run(20);
+        harness.clients[a].latency = 1;
+        let before = harness.sim(a).frontier_holds();
+        harness.sim(a).set_movement_input(-na::Vector3::z());
+        harness.run(300);
+        harness.sim(a).frontier_holds() - before
+    };
+    let prefetched = holds(true);
+    let unprefetched = holds(false);
+    assert!(prefetched <= 2, "held back in {prefetched} steps");
+    assert!(
+        unprefetched >= 10,
+        "only held back in {unprefetched} steps without prefetching"
+    );
+}
+
+#[test]
+fn traffic_accounted_by_kind() {
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    harness.run_until(100, |h| h.ready(a));
+    harness.sim(a).set_movement_input(-na::Vector3::z());
+    harness.run(50);
+
+    let stats = harness.server.stats();
+    let connection = &stats.connections[0];
+    let sent = &connection.traffic_sent;
+    let received = &harness.clients[a].received;
+    let kinds = |traffic: &Traffic| traffic.iter().map(|(kind, _)| kind).collect::<Vec<_>>();
+    assert_eq!(kinds(sent), kinds(received));
+    // Everything the server counted arrived, and came to the same size once decompressed
+    for (kind, count) in received.iter() {
+        let sent = sent.get(kind);
+        assert_eq!(sent.messages, count.messages, "{kind}");
+        assert_eq!(sent.uncompressed_bytes, count.bytes, "{kind}");
+    }
+    // The hello is sent as is, and everything after it behind a byte saying whether it's
+    // compressed, which state deltas never are
+    assert_eq!(sent.get("ServerHello"), received.get("ServerHello"));
+    let deltas = sent.get("StateDelta");
+    assert_eq!(deltas.bytes, deltas.uncompressed_bytes + deltas.messages);
+    assert_eq!(
+        deltas.messages,
+        harness.clients[a].deltas.iter().sum::<usize>() as u64
+    );
+    // The regions sent on joining are large and repetitive enough to be worth compressing
+    let regions = sent.get("GraphRegions");
+    assert!(
+        regions.bytes < regions.uncompressed_bytes,
+        "{regions:?} not compressed"
+    );
+
+    // Commands are counted as they arrive, never compressed
+    let commands = connection.traffic_received.get("Command");
+    assert!(commands.messages >= 50, "{commands:?}");
+    assert_eq!(commands.bytes, commands.uncompressed_bytes);
+}
+
+#[test]
+fn graph_backlog_paced_without_delaying_deltas() {
+    // Everything the client is told of the graph on joining, sent as fast as it can be
+    let mut harness = Harness::new();
+    let a = harness.connect("a");
+    harness.run(100);
+    let regions = harness.clients[a].graph_regions;
+    let total = harness.clients[a]
+        .graph_messages
+        .iter()
+        .map(|&(_, bytes)| bytes)
+        .sum::<usize>();
+    let first_delta = harness.clients[a].deltas.iter().position(|&n| n > 0);
+
+    // Paced to take about five seconds
+    let rate = total as u64 / 5;
+    let mut harness = Harness::new();
+    harness.server.set_bulk_bytes_per_second(Some(rate));
+    let a = harness.connect("a");
+    let per_second = (1.0 / harness.server.cfg().step_interval.as_secs_f64()).round() as usize;
+    harness.run_until(20 * per_second, |h| h.clients[a].graph_regions >= regions);
+    let client = &harness.clients[a];
+
+    // Over any second, no more than the rate plus the overshoot of a single message
+    let mut per_step = vec![0; harness.step as usize + 1];
+    for &(step, bytes) in &client.graph_messages {
+        per_step[step as usize] += bytes;
+    }
+    let largest = client.graph_messages.iter().map(|&(_, x)| x).max().unwrap();
+    for window in per_step.windows(per_second) {
+        let sent = window.iter().sum::<usize>();
+        assert!(
+            sent <= rate as usize + largest,
+            "{sent} bytes in a second at {rate} bytes per second"
+        );
+    }
+    // Yet held back only as long as the rate demands
+    let seconds = harness.step as f64 / per_second as f64;
+    assert!((3.0..10.0).contains(&seconds), "took {seconds}s");
+
+    // Meanwhile, state deltas arrived from the first step they would have unpaced, one every step
+    let deltas = &client.deltas;
+    assert_eq!(deltas.iter().position(|&n| n > 0), first_delta);
+    assert!(
+        deltas[first_delta.unwrap()..].iter().all(|&n| n == 1),
+        "{deltas:?}"
+    );
+}
+
+#[test]
+fn idle_players_marked_away_then_disconnected() {
+    let mut harness = Harness::new();
+    harness
+        .server
+        .set_idle_policy(short_idle_policy(DeparturePolicy::Despawn));
+    let second = steps_per_second(&harness);
+    let a = harness.connect("a");
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(a) && h.ready(b));
+    let character = harness.clients[a].character.unwrap();
+
+    // Never away while playing, however long that lasts
+    harness.run(3 * second);
+    assert!(!seen_away(harness.sim(b), character));
+
+    // Away once no commands have come for long enough
+    harness.clients[a].paused = true;
+    harness.run(second);
+    assert!(!seen_away(harness.sim(b), character));
+    harness.run_until(2 * second, |h| seen_away(h.sim(b), character));
+    assert!(connection_stats(&mut harness, "a").afk);
+
+    // Back as soon as they come again
+    harness.clients[a].paused = false;
+    harness.run_until(5, |h| !seen_away(h.sim(b), character));
+    assert!(!connection_stats(&mut harness, "a").afk);
+
+    // Disconnected once none have come for longer, timed from the latest, taking the character
+    // along
+    harness.clients[a].paused = true;
+    harness.run(4 * second);
+    assert!(harness.clients[a].connected);
+    harness.run_until(2 * second, |h| !h.clients[a].connected);
+    harness.run_until(5, |h| !knows_of(h.sim(b), character));
+}
+
+#[test]
+fn keepalives_dont_count_as_playing() {
+    let mut harness = Harness::new();
+    harness
+        .server
+        .set_idle_policy(short_idle_policy(DeparturePolicy::Despawn));
+    let second = steps_per_second(&harness);
+    let a = harness.connect("a");
+    harness.run_until(100, |h| h.ready(a));
+
+    harness.clients[a].paused = true;
+    for _ in 0..3 * second {
+        harness.send(a, proto::ClientMessage::Keepalive);
+        harness.step();
+    }
+    // Heard from all along, but away regardless
+    let stats = connection_stats(&mut harness, "a");
+    assert!(stats.silent_ms < 200.0, "silent for {} ms", stats.silent_ms);
+    assert!(stats.idle_ms >= 2000.0, "idle for {} ms", stats.idle_ms);
+    assert!(stats.afk);
+    // and disconnected on time
+    harness.run_until(3 * second, |h| {
+        h.send(a, proto::ClientMessage::Keepalive);
+        !h.clients[a].connected
+    });
+}
+
+#[test]
+fn away_players_leave_regions_to_cool() {
+    let mut harness = Harness::new();
+    harness
+        .server
+        .set_idle_policy(short_idle_policy(DeparturePolicy::Despawn));
+    let second = steps_per_second(&harness);
+    let a = harness.connect("a");
+    harness.run_until(100, |h| h.ready(a));
+    assert!(harness.server.stats().regions.active > 0);
+
+    harness.clients[a].paused = true;
+    harness.run_until(3 * second, |h| connection_stats(h, "a").afk);
+    let regions = harness.server.stats().regions;
+    assert_eq!(regions.active, 0);
+    assert!(regions.cooling > 0);
+
+    // Woken again by the player's return
+    harness.clients[a].paused = false;
+    harness.run_until(5, |h| h.server.stats().regions.active > 0);
+}
+
+#[test]
+fn departed_characters_kept_as_configured() {
+    let disconnect = |h: &mut Harness, client: usize| h.disconnect(client);
+    // Started afresh at a spawn point by default
+    let separation = rejoined_separation(DeparturePolicy::Despawn, disconnect);
+    assert!(separation > 3.0, "rejoined {separation} m away");
+    // or returned to where they left, if so configured
+    let separation = rejoined_separation(DeparturePolicy::PersistAndDespawn, disconnect);
+    assert!(separation < 0.5, "rejoined {separation} m away");
+    // however they came to leave
+    let separation = rejoined_separation(DeparturePolicy::PersistAndDespawn, |h, client| {
+        h.clients[client].paused = true;
+        h.run_until(10 * steps_per_second(h), |h| !h.clients[client].connected);
+    });
+    assert!(separation < 0.5, "rejoined {separation} m away");
+}
+
+#[test]
+fn outdated_clients_are_refused() {
+    let mut harness = Harness::new();
+    let hello = ClientHello {
+        protocol_version: MIN_PROTOCOL_VERSION - 1,
+        ..ClientHello::new("old")
+    };
+    assert_eq!(
+        harness.server.connect_with(hello),
+        Err(Refusal::OutdatedClient {
+            version: MIN_PROTOCOL_VERSION - 1,
+            minimum: MIN_PROTOCOL_VERSION,
+        })
+    );
+}
+
+/// A server and the clients connected to it
+struct Harness {
+    server: LocalServer,
+    clients: Vec<TestClient>,
+    /// Number of steps run so far
+    step: u64,
+    _dir: tempfile::TempDir,
+}
+
+/// A client without graphics or a network connection
+struct TestClient {
+    id: LocalClientId,
+    connected: bool,
+    /// Created on receipt of the server's hello
+    sim: Option<Sim>,
+    net: Net,
+    /// Messages `sim` has sent to `net`
+    sent: net::OutgoingReceiver,
+    /// Number of steps each message spends in transit, in either direction
+    latency: u64,
+    /// Messages in transit to the server, and the steps at which they arrive
+    upstream: VecDeque<(u64, proto::ClientMessage)>,
+    /// Messages in transit from the server, and the steps at which they arrive
+    downstream: VecDeque<(u64, LocalMessage)>,
+    /// Every block update sent, in order
+    block_updates: Vec<BlockUpdate>,
+    /// Every block update the server reported making on nobody's behalf, in order
+    server_edits: Vec<BlockUpdate>,
+    /// Entity the server assigned to the client's character
+    character: Option<EntityId>,
+    /// Number of modified chunks received as diffs
+    chunk_diffs: usize,
+    /// Every sound the server reported
+    sounds: Vec<SoundEvent>,
+    /// Entities whose positions each state delta carried, in order
+    delta_positions: Vec<Vec<EntityId>>,
+    /// Number of graph regions received
+    graph_regions: usize,
+    /// Every message received, by kind, counted as encoded without compression
+    received: Traffic,
+    /// Steps at which graph regions and updates arrived, and their encoded sizes, in order
+    graph_messages: Vec<(u64, usize)>,
+    /// Number of state deltas that arrived in each step
+    deltas: Vec<usize>,
+    /// Every character the server said it tells the client of the surroundings of, in order
+    following: Vec<Option<EntityId>>,
+    /// Distance around its view within which `sim` has chunks generated, if other than the reach
+    /// of a block-placing character
+    chunk_reach: Option<f64>,
+    /// Whether `sim` has the chunks its prediction may reach generated too, as the real client does
+    prefetch: bool,
+    /// Whether `sim` is left unstepped, as by its player having walked away from a hung client, so
+    /// that it sends no commands but still reads what arrives
+    paused: bool,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("world.save");
+        let mut save = save::Save::open(&path, 12).unwrap();
+        let (journal, _) = save::Journal::open(&save::Journal::dir_for(&path), &mut save).unwrap();
+        let cfg = SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(20.0),
+            // Keep everyone in view of each other
+            spawn_distance: Some(0),
+            terrain: Some(vec![TerrainPassKind::Flat {
+                height: 0.0,
+                material: Material::Dirt,
+            }]),
+            ..Default::default()
+        });
+        Self {
+            server: LocalServer::new(
+                cfg,
+                SaveParams {
+                    save,
+                    journal,
+                    autosave_interval: Duration::from_secs(3600),
+                    save_on_interrupt: false,
+                },
+                vec![ADMIN.into()],
+            ),
+            clients: Vec::new(),
+            step: 0,
+            _dir: dir,
+        }
+    }
+
+    /// Connect a new client, returning its index
+    fn connect(&mut self, name: &str) -> usize {
+        self.connect_with(ClientHello::new(name))
+    }
+
+    /// Connect a new client that introduces itself with `hello`, returning its index
+    fn connect_with(&mut self, hello: ClientHello) -> usize {
+        let (outgoing, sent) = net::outgoing(1024, net::STALL_TIMEOUT);
+        // Messages are handed to the sim directly instead
+        let (_, incoming) = mpsc::unbounded_channel();
+        self.clients.push(TestClient {
+            id: self.server.connect_with(hello).unwrap(),
+            connected: true,
+            sim: None,
+            net: Net {
+                incoming,
+                outgoing,
+                received: Default::default(),
+                thread: None,
+            },
+            sent,
+            latency: 0,
+            upstream: VecDeque::new(),
+            downstream: VecDeque::new(),
+            block_updates: Vec::new(),
+            server_edits: Vec::new(),
+            character: None,
+            chunk_diffs: 0,
+            sounds: Vec::new(),
+            delta_positions: Vec::new(),
+            graph_regions: 0,
+            received: Traffic::default(),
+            graph_messages: Vec::new(),
+            deltas: Vec::new(),
+            following: Vec::new(),
+            chunk_reach: None,
+            prefetch: true,
+            paused: false,
+        });
+        self.clients.len() - 1
+    }
+
+    /// Send `msg` from `client` to the server, as if from its sim
+    fn send(&mut self, client: usize, msg: proto::ClientMessage) {
+        self.clients[client].net.outgoing.send(msg).unwrap();
+    }
+
+    fn disconnect(&mut self, client: usize) {
+        self.server.disconnect(self.clients[client].id);
+        self.clients[client].connected = false;
+    }
+
+    fn sim(&mut self, client: usize) -> &mut Sim {
+        self.clients[client]
+            .sim
+            .as_mut()
+            .expect("client hasn't been greeted yet")
+    }
+
+    /// Whether `client` has heard from the server and knows where its character is
+    fn ready(&self, client: usize) -> bool {
+        self.clients[client]
+            .sim
+            .as_ref()
+            .map_or(false, |sim| sim.local_character().is_some())
+    }
+
+    /// Advance the server and every connected client by one step interval
+    fn step(&mut self) {
+        let dt = self.server.cfg().step_interval;
+        for client in self.clients.iter_mut().filter(|x| x.connected) {
+            while let Some(msg) = client.sent.try_recv() {
+                client.sent.flushed(codec::encoded_len(&msg));
+                if let proto::ClientMessage::Command(ref cmd) = msg {
+                    client
+                        .block_updates
+                        .extend(cmd.character_input.block_update.clone());
+                }
+                client.upstream.push_back((self.step + client.latency, msg));
+            }
+            while let Some((_, msg)) = pop_arrived(&mut client.upstream, self.step) {
+                self.server.send(client.id, &msg).unwrap();
+            }
+        }
+
+        self.server.step();
+        self.step += 1;
+
+        for client in self.clients.iter_mut().filter(|x| x.connected) {
+            let Ok(received) = self.server.recv(client.id) else {
+                // Disconnected by the server
+                client.connected = false;
+                continue;
+            };
+            for msg in received {
+                client
+                    .downstream
+                    .push_back((self.step + client.latency, msg));
+            }
+            let mut deltas = 0;
+            while let Some((_, msg)) = pop_arrived(&mut client.downstream, self.step) {
+                match msg {
+                    LocalMessage::Hello(hello) => {
+                        let bytes = codec::encoded_len(&hello);
+                        client.received.record("ServerHello", bytes, bytes);
+                        client.character = Some(hello.character);
+                        let mut sim = Sim::new(hello.sim_config, camera_cfg(), hello.character);
+                        sim.set_capabilities(hello.header.capabilities);
+                        client.sim = Some(sim);
+                    }
+                    LocalMessage::Ordered(msg) => {
+                        let bytes = codec::encoded_len(&msg);
+                        client.received.record(msg.kind(), bytes, bytes);
+                        match msg {
+                            proto::ServerMessage::Spawns(ref spawns) => {
+                                client.chunk_diffs += spawns.chunk_diffs.len();
+                                client
+                                    .server_edits
+                                    .extend(server_edits(&spawns.block_updates));
+                            }
+                            proto::ServerMessage::WorldEdits(ref edits) => {
+                                client.chunk_diffs += edits.chunk_diffs.len();
+                                client
+                                    .server_edits
+                                    .extend(server_edits(&edits.block_updates));
+                            }
+                            proto::ServerMessage::GraphUpdates(_) => {
+                                client.graph_messages.push((self.step, bytes));
+                            }
+                            proto::ServerMessage::GraphRegions(ref regions) => {
+                                client.graph_messages.push((self.step, bytes));
+                                client.graph_regions += regions.len();
+                                client.chunk_diffs += regions
+                                    .iter()
+                                    .map(|x| x.decode().unwrap().chunk_diffs.len())
+                                    .sum::<usize>();
+                            }
+                            proto::ServerMessage::Sounds(ref sounds) => {
+                                client.sounds.extend(sounds.iter().cloned());
+                            }
+                            proto::ServerMessage::Following(followed) => {
+                                client.following.push(followed);
+                            }
+                            _ => {}
+                        }
+                        client.sim.as_mut().unwrap().handle_net(msg.into())
+                    }
+                    LocalMessage::Unordered(msg) => {
+                        let bytes = codec::encoded_len(&msg);
+                        client.received.record("StateDelta", bytes, bytes);
+                        deltas += 1;
+                        client
+                            .delta_positions
+                            .push(msg.positions.iter().map(|&(id, _)| id).collect());
+                        client
+                            .sim
+                            .as_mut()
+                            .unwrap()
+                            .handle_net(Message::StateDelta(msg))
+                    }
+                }
+            }
+            client.deltas.push(deltas);
+            if client.paused {
+                continue;
+            }
+            if let Some(ref mut sim) = client.sim {
+                generate_chunks(sim, client.chunk_reach, client.prefetch);
+                sim.step(dt, &mut client.net);
+            }
+        }
+    }
+
+    fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+
+    /// Step until `done`, failing if that takes longer than `max_steps`
+    fn run_until(&mut self, max_steps: usize, mut done: impl FnMut(&mut Self) -> bool) {
+        for _ in 0..max_steps {
+            if done(self) {
+                return;
+            }
+            self.step();
+        }
+        assert!(done(self), "not done after {max_steps} steps");
+    }
+}
+
+/// Where `client` predicts its character is and where the server has it, after each of `steps`
+/// steps
+fn record_trajectories(
+    harness: &mut Harness,
+    client: usize,
+    steps: usize,
+) -> (Vec<Position>, Vec<Position>) {
+    let id = harness.clients[client].id;
+    let mut predicted = Vec::new();
+    let mut authoritative = Vec::new();
+    for _ in 0..steps {
+        harness.step();
+        predicted.push(harness.sim(client).view().position().unwrap());
+        authoritative.push(harness.server.position(id).unwrap());
+    }
+    (predicted, authoritative)
+}
+
+/// Check that once the server's first word on a sudden change has reached the client, the
+/// predicted trajectory follows the authoritative one exactly, a step or two ahead of it
+fn assert_trajectories_match(
+    graph: &Graph,
+    (predicted, authoritative): &(Vec<Position>, Vec<Position>),
+    m: f32,
+) {
+    const SETTLE: usize = 3;
+    let error = |lead: usize| {
+        (SETTLE..predicted.len() - lead)
+            .map(|i| separation(graph, &predicted[i], &authoritative[i + lead]))
+            .fold(0.0, f32::max)
+    };
+    let error = (0..=2).map(error).fold(f32::INFINITY, f32::min);
+    assert!(error < 0.01 * m, "predicted up to {error} away");
+}
+
+/// The block updates among `updates` that the server made on nobody's behalf
+fn server_edits(updates: &[(EntityId, BlockUpdate)]) -> impl Iterator<Item = BlockUpdate> + '_ {
+    updates
+        .iter()
+        .filter(|&&(author, _)| author == EntityId::NOBODY)
+        .map(|(_, update)| update.clone())
+}
+
+/// Take the next message off `queue` if it has arrived by `step`
+fn pop_arrived<T>(queue: &mut VecDeque<(u64, T)>, step: u64) -> Option<(u64, T)> {
+    if queue.front()?.0 > step {
+        return None;
+    }
+    queue.pop_front()
+}
+
+/// Generate the chunks within `reach` of `sim`'s view, or within reach of a block-placing character
+/// by default, and those its prediction may reach if `prefetch`, which the real client does in the
+/// background
+fn generate_chunks(sim: &mut Sim, reach: Option<f64>, prefetch: bool) {
+    let distance = reach
+        .unwrap_or(f64::from(sim.cfg().character.block_reach) + dodeca::BOUNDING_SPHERE_RADIUS_F64);
+    let mut nodes = sim
+        .nearby_nodes(distance)
+        .into_iter()
+        .map(|(node, _)| node)
+        .collect::<Vec<_>>();
+    if prefetch {
+        nodes.extend(sim.prefetch_nodes());
+    }
+    for node in nodes {
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(node, vertex);
+            if matches!(sim.graph.get_chunk(chunk), Some(Chunk::Fresh)) {
+                generate_chunk(sim, chunk);
+            }
+        }
+    }
+}
+
+/// Generate `chunk` for `sim`, applying whatever edits were waiting for it
+fn generate_chunk(sim: &mut Sim, chunk: ChunkId) {
+    if let Some(params) =
+        ChunkParams::new(sim.cfg().chunk_size, &sim.graph, chunk, &sim.cfg().terrain)
+    {
+        let voxels = params.generate_voxels();
+        sim.populate_chunk(chunk, voxels);
+    }
+}
+
+/// Smoothing that takes a few steps to catch up with any sudden motion
+fn camera_cfg() -> CameraConfig {
+    CameraConfig {
+        half_life: 0.1,
+        rise_half_life: 0.1,
+        snap_distance: 1.0,
+        bob_depth: 0.0,
+        stride: 1.0,
+        interpolate_view: false,
+        follow_distance: 0.1,
+    }
+}
+
+/// Names of the waypoints `sim` has been told are shared
+fn shared_names(sim: &Sim) -> Vec<&str> {
+    sim.shared_waypoints().map(|x| &*x.name).collect()
+}
+
+/// Whether `sim` has an entity for `id`
+fn knows_of(sim: &Sim, id: EntityId) -> bool {
+    sim.world.query::<&EntityId>().iter().any(|(_, &x)| x == id)
+}
+
+/// Distance between `a` and `b` in absolute units, according to `graph`
+fn separation(graph: &Graph, a: &Position, b: &Position) -> f32 {
+    let (_, b_to_a) = nearby_nodes(graph, a, 2.0 * dodeca::BOUNDING_SPHERE_RADIUS_F64)
+        .into_iter()
+        .find(|&(node, _)| node == b.node)
+        .expect("positions are too far apart to compare");
+    let a = a.local * math::origin();
+    let b = b_to_a * b.local * math::origin();
+    // Distances this small are lost to rounding in single precision
+    math::distance(&a.cast::<f64>(), &b.cast::<f64>()) as f32
+}
+
+/// Meters between where a player's character was when they left by way of `leave` and where it is
+/// on their return, with the server applying `departure`
+fn rejoined_separation(departure: DeparturePolicy, leave: impl FnOnce(&mut Harness, usize)) -> f32 {
+    let mut harness = Harness::new();
+    harness.server.set_idle_policy(short_idle_policy(departure));
+    let m = harness.server.cfg().meters_to_absolute;
+    let a = harness.connect("a");
+    let b = harness.connect("b");
+    harness.run_until(100, |h| h.ready(a) && h.ready(b));
+    let character = harness.clients[a].character.unwrap();
+
+    // Well away from the spawn point
+    let spawned = harness.server.position(harness.clients[a].id).unwrap();
+    let up = harness.sim(b).graph.get_relative_up(&spawned).unwrap();
+    let across = (na::Vector3::x() - up.into_inner() * up.x).normalize();
+    harness
+        .server
+        .admin(AdminCommand::Teleport {
+            character: "a".into(),
+            destination: proto::TeleportDestination::Relative {
+                translation: across * 6.0 * m,
+            },
+        })
+        .unwrap();
+    harness.run(10);
+
+    let left = harness.server.position(harness.clients[a].id).unwrap();
+    leave(&mut harness, a);
+    // Never left behind
+    harness.run_until(5, |h| !knows_of(h.sim(b), character));
+
+    let rejoined = harness.connect("a");
+    harness.run_until(100, |h| h.ready(rejoined));
+    let returned = harness
+        .server
+        .position(harness.clients[rejoined].id)
+        .unwrap();
+    separation(&harness.sim(b).graph, &left, &returned) / m
+}
+
+/// Idle thresholds short enough to reach in a test
+fn short_idle_policy(departure: DeparturePolicy) -> IdlePolicy {
+    IdlePolicy {
+        afk_after: Some(Duration::from_secs(2)),
+        kick_after: Some(Duration::from_secs(5)),
+        departure,
+    }
+}
+
+fn steps_per_second(harness: &Harness) -> usize {
+    (1.0 / harness.server.cfg().step_interval.as_secs_f32()).round() as usize
+}
+
+/// What the server reports of the connection of the player called `name`
+fn connection_stats(harness: &mut Harness, name: &str) -> ConnectionStats {
+    harness
+        .server
+        .stats()
+        .connections
+        .into_iter()
+        .find(|x| x.name.as_deref() == Some(name))
+        .expect("no such player connected")
+}
+
+/// Whether `sim` has been told that the character `id`'s player is away
+fn seen_away(sim: &Sim, id: EntityId) -> bool {
+    sim.world
+        .query::<(&EntityId, &proto::Character)>()
+        .iter()
+        .any(|(_, (&x, ch))| x == id && ch.state.afk)
+}
@@ -1,13 +1,22 @@
 use criterion::{criterion_group, criterion_main, Criterion};
+use nalgebra as na;
+use rayon::prelude::*;
 
 use common::{
+    character_controller::run_character_step,
+    chunk_collision::chunk_sphere_cast,
+    collision_math::Ray,
     dodeca::{Side, Vertex},
     graph::{Graph, NodeId},
+    logging::{entity_span, step_span},
+    math,
     node::Chunk,
-    node::{populate_fresh_nodes, ChunkId},
-    proto::Position,
-    traversal::ensure_nearby,
-    worldgen::ChunkParams,
+    node::{populate_fresh_nodes, ChunkId, ChunkLayout, VoxelData},
+    proto::{CharacterInput, MovementInput, Position},
+    traversal::{ensure_nearby, nearby_nodes, nearby_nodes_cached, TransformCache},
+    world::Material,
+    worldgen::{ChunkParams, TerrainPassKind, WorldgenPath},
+    EntityId, Occupancy, SimConfig, SimConfigRaw,
 };
 
 fn build_graph(c: &mut Criterion) {
@@ -46,12 +55,16 @@ fn build_graph(c: &mut Criterion) {
             for node in fresh {
                 for vertex in Vertex::iter() {
                     let chunk = ChunkId::new(node, vertex);
-                    if let Some(params) = ChunkParams::new(12, &graph, chunk) {
+                    if let Some(params) =
+                        ChunkParams::new(12, &graph, chunk, &TerrainPassKind::DEFAULT)
+                    {
                         graph[chunk] = Chunk::Populated {
                             voxels: params.generate_voxels(),
                             modified: false,
+                            generation: 0,
                             surface: None,
                             old_surface: None,
+                            occupancy: Default::default(),
                         };
                         n += 1;
                     }
@@ -62,5 +75,215 @@ fn build_graph(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, build_graph);
+fn worldgen_paths(c: &mut Criterion) {
+    let mut graph = Graph::new(12);
+    ensure_nearby(&mut graph, &Position::origin(), 3.0);
+    populate_fresh_nodes(&mut graph);
+    // Only chunks crossing the terrain surface are evaluated voxel by voxel
+    let chunks = nearby_nodes(&graph, &Position::origin(), 2.0)
+        .into_iter()
+        .flat_map(|(node, _)| Vertex::iter().map(move |vertex| ChunkId::new(node, vertex)))
+        .filter_map(|chunk| ChunkParams::new(12, &graph, chunk, &TerrainPassKind::DEFAULT))
+        .filter(|params| {
+            matches!(
+                params.generate_voxels_on(WorldgenPath::Scalar),
+                VoxelData::Dense(_)
+            )
+        })
+        .take(100)
+        .collect::<Vec<_>>();
+    assert!(chunks.len() >= 50, "only {} surface chunks", chunks.len());
+
+    for path in [WorldgenPath::Scalar, WorldgenPath::Wide] {
+        c.bench_function(&format!("worldgen surface {}", path.name()), |b| {
+            b.iter(|| {
+                for params in &chunks {
+                    params.generate_voxels_on(path);
+                }
+            })
+        });
+    }
+}
+
+fn collision(c: &mut Criterion) {
+    let mut graph = Graph::new(12);
+    ensure_nearby(&mut graph, &Position::origin(), 2.0);
+    populate_fresh_nodes(&mut graph);
+    let voxels = nearby_nodes(&graph, &Position::origin(), 1.0)
+        .into_iter()
+        .flat_map(|(node, _)| Vertex::iter().map(move |vertex| ChunkId::new(node, vertex)))
+        .filter_map(|chunk| ChunkParams::new(12, &graph, chunk, &TerrainPassKind::DEFAULT))
+        .map(|params| params.generate_voxels())
+        .find(|voxels| matches!(voxels, VoxelData::Dense(_)))
+        .expect("no surface chunk near the origin");
+    let layout = ChunkLayout::new(12);
+    // Rays fanning out across the chunk from its corner at the node's center
+    let rays = (0..64)
+        .map(|i| {
+            let angle = i as f32 / 64.0 * std::f32::consts::FRAC_PI_2;
+            let direction = na::Vector3::new(angle.cos(), 0.5, angle.sin()).normalize();
+            Ray::new(math::origin(), direction.push(0.0))
+        })
+        .collect::<Vec<_>>();
+
+    // Occupancy is found by the first cast and kept, as it is for a chunk in the graph
+    let occupancy = Occupancy::default();
+    c.bench_function("chunk_sphere_cast 64", |b| {
+        b.iter(|| {
+            rays.iter()
+                .filter_map(|ray| chunk_sphere_cast(0.02, &voxels, &occupancy, &layout, ray, 0.5))
+                .count()
+        })
+    });
+    // Casts just after the chunk is populated, which have to find its occupancy first
+    c.bench_function("chunk_sphere_cast 64 finding occupancy", |b| {
+        b.iter(|| {
+            let occupancy = Occupancy::default();
+            rays.iter()
+                .filter_map(|ray| chunk_sphere_cast(0.02, &voxels, &occupancy, &layout, ray, 0.5))
+                .count()
+        })
+    });
+}
+
+/// Steps of a crowd of characters spread across increasing numbers of threads, as the server takes
+/// them, up to as many as the machine has
+fn character_steps(c: &mut Criterion) {
+    let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+    let m = cfg.meters_to_absolute;
+    let mut graph = Graph::new(cfg.chunk_size);
+    ensure_nearby(&mut graph, &Position::origin(), 2.0);
+    populate_fresh_nodes(&mut graph);
+    let passes = [TerrainPassKind::Flat {
+        height: 0.0,
+        material: Material::Dirt,
+    }];
+    for (node, _) in nearby_nodes(&graph, &Position::origin(), 1.0) {
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(node, vertex);
+            if let Some(params) = ChunkParams::new(cfg.chunk_size, &graph, chunk, &passes) {
+                graph.populate_chunk(chunk, params.generate_voxels(), false);
+            }
+        }
+    }
+
+    // Two hundred characters a meter apart on the ground, walking every which way
+    let state = &graph.get(NodeId::ROOT).as_ref().unwrap().state;
+    let up = state.up_direction().xyz().normalize();
+    let ground = up * (cfg.character.character_radius - state.elevation());
+    let across = up.cross(&na::Vector3::x()).normalize();
+    let along = up.cross(&across);
+    let characters = (0..200)
+        .map(|i| {
+            let (row, column) = ((i / 20) as f32, (i % 20) as f32);
+            let offset = (across * (column - 9.5) + along * (row - 4.5)) * m;
+            let angle = i as f32 * 2.4;
+            let position = Position {
+                node: NodeId::ROOT,
+                local: math::translate_along(&(ground + offset)),
+            };
+            let input = CharacterInput {
+                movement: MovementInput::new(across * angle.cos() + along * angle.sin()),
+                jump: false,
+                no_clip: false,
+                block_update: None,
+                block_fill: None,
+                use_target: None,
+                throw: None,
+                external: Default::default(),
+            };
+            (position, input)
+        })
+        .collect::<Vec<_>>();
+    let dt = cfg.step_interval.as_secs_f32();
+    let step = |&(mut position, ref input): &(Position, CharacterInput)| {
+        let (mut velocity, mut on_ground) = (na::Vector3::zeros(), true);
+        run_character_step(
+            &cfg,
+            &graph,
+            &mut position,
+            &mut velocity,
+            &mut on_ground,
+            input,
+            &mut 0.0,
+            dt,
+            None,
+        );
+        position
+    };
+
+    let cores = std::thread::available_parallelism().map_or(1, |x| x.get());
+    for threads in [1, 2, 4, 8, 16].into_iter().filter(|&x| x <= cores) {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        c.bench_function(&format!("character_step 200 x{threads}"), |b| {
+            b.iter(|| pool.install(|| characters.par_iter().map(step).collect::<Vec<_>>()))
+        });
+    }
+
+    // The spans the server enters around each step and each character in it, which should cost
+    // next to nothing beside the steps themselves when logging as usual
+    let _guard = common::tracing_guard();
+    c.bench_function("character_step 200 serial", |b| {
+        b.iter(|| characters.iter().map(step).collect::<Vec<_>>())
+    });
+    c.bench_function("character_step 200 serial with spans", |b| {
+        b.iter(|| {
+            let _span = step_span(0).entered();
+            characters
+                .iter()
+                .enumerate()
+                .map(|(i, character)| {
+                    let _span =
+                        entity_span(EntityId::from_bits(i as u64), character.0.node).entered();
+                    step(character)
+                })
+                .collect::<Vec<_>>()
+        })
+    });
+}
+
+fn traversal(c: &mut Criterion) {
+    // Roughly the number of nodes visible at a long view distance
+    const RADIUS: f64 = 4.5;
+    let mut graph = Graph::new(12);
+    ensure_nearby(&mut graph, &Position::origin(), RADIUS + 1.0);
+    let visible = nearby_nodes(&graph, &Position::origin(), RADIUS).len();
+    assert!(visible >= 2000, "only {visible} nodes visible");
+
+    c.bench_function("nearby_nodes 2k", |b| {
+        b.iter(|| nearby_nodes(&graph, &Position::origin(), RADIUS))
+    });
+
+    let mut cache = TransformCache::new(NodeId::ROOT);
+    nearby_nodes_cached(&graph, &mut cache, &Position::origin(), RADIUS);
+    c.bench_function("nearby_nodes_cached 2k", |b| {
+        b.iter(|| nearby_nodes_cached(&graph, &mut cache, &Position::origin(), RADIUS))
+    });
+
+    // Moving to a neighboring node rebases rather than recomputes
+    let neighbor = graph.neighbor(NodeId::ROOT, Side::A).unwrap();
+    c.bench_function("nearby_nodes_cached 2k moving", |b| {
+        let mut position = Position::origin();
+        b.iter(|| {
+            position.node = if position.node == NodeId::ROOT {
+                neighbor
+            } else {
+                NodeId::ROOT
+            };
+            nearby_nodes_cached(&graph, &mut cache, &position, RADIUS)
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    build_graph,
+    worldgen_paths,
+    collision,
+    character_steps,
+    traversal
+);
 criterion_main!(benches);
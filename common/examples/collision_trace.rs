@@ -0,0 +1,193 @@
+//! Draws the constraint solving recorded in a collision trace, for inspecting how a character's
+//! movement was resolved
+//!
+//! ```text
+//! cargo run -p common --example collision_trace -- trace.json > trace.svg
+//! ```
+//!
+//! Each bound added during a step gets a panel looking down along the character's up direction,
+//! showing the plane of the bound in gray with its normal solid and its projection direction
+//! dashed, and the displacement in blue and velocity in red, light before the bound was applied
+//! and dark after.
+
+use std::{env, fmt::Write, path::Path, process};
+
+use common::character_controller::{TraceDump, TraceEvent, TracedBound};
+use nalgebra as na;
+
+/// Side length of each panel, in pixels
+const PANEL: f32 = 240.0;
+const COLUMNS: usize = 4;
+
+fn main() {
+    let Some(path) = env::args_os().nth(1) else {
+        eprintln!("usage: collision_trace <trace.json>");
+        process::exit(2);
+    };
+    let dump = match TraceDump::load(Path::new(&path)) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{e:#}");
+            process::exit(1);
+        }
+    };
+    print!("{}", render(&dump));
+}
+
+/// A bound being applied, viewed from above
+struct Panel {
+    caption: String,
+    /// Basis of the horizontal plane the vectors are projected onto
+    basis: [na::Vector3<f32>; 2],
+    bound: TracedBound,
+    displacement: [na::Vector3<f32>; 2],
+    velocity: Option<[na::Vector3<f32>; 2]>,
+}
+
+fn render(dump: &TraceDump) -> String {
+    let mut panels = Vec::new();
+    for (step_index, step) in dump.steps.iter().enumerate() {
+        let mut basis = horizontal_basis(&na::Vector3::y());
+        let mut substep = 0;
+        for event in &step.events {
+            match *event {
+                TraceEvent::Substep { up, .. } => {
+                    basis = horizontal_basis(&up);
+                    substep += 1;
+                }
+                TraceEvent::Bound {
+                    bound,
+                    temporary,
+                    displacement_before,
+                    displacement_after,
+                    velocity_before,
+                    velocity_after,
+                } => panels.push(Panel {
+                    caption: format!(
+                        "step {step_index}.{substep}: {}{} bound",
+                        if temporary { "temporary " } else { "" },
+                        if bound.front_facing { "front" } else { "back" },
+                    ),
+                    basis,
+                    bound,
+                    displacement: [displacement_before, displacement_after],
+                    velocity: velocity_before.zip(velocity_after).map(|(a, b)| [a, b]),
+                }),
+                _ => {}
+            }
+        }
+    }
+
+    let rows = panels.len().div_ceil(COLUMNS).max(1);
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" {}>"#,
+        PANEL * COLUMNS as f32,
+        PANEL * rows as f32,
+        r#"font-family="sans-serif" font-size="11""#
+    )
+    .unwrap();
+    if panels.is_empty() {
+        writeln!(svg, r#"<text x="8" y="16">no bounds in trace</text>"#).unwrap();
+    }
+    for (i, panel) in panels.iter().enumerate() {
+        let x = PANEL * (i % COLUMNS) as f32;
+        let y = PANEL * (i / COLUMNS) as f32;
+        draw_panel(&mut svg, x, y, panel);
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn draw_panel(svg: &mut String, x: f32, y: f32, panel: &Panel) {
+    let center = na::Vector2::new(x + PANEL * 0.5, y + PANEL * 0.5);
+    let project =
+        |v: &na::Vector3<f32>| na::Vector2::new(v.dot(&panel.basis[0]), v.dot(&panel.basis[1]));
+    let vectors = panel
+        .displacement
+        .iter()
+        .chain(panel.velocity.iter().flatten())
+        .map(project)
+        .collect::<Vec<_>>();
+    // Fit the longest vector in the panel, with room for the caption
+    let longest = vectors.iter().map(|v| v.norm()).fold(0.0, f32::max);
+    let scale = if longest > 0.0 {
+        PANEL * 0.4 / longest
+    } else {
+        1.0
+    };
+    // SVG's y axis points down
+    let to_screen = |v: na::Vector2<f32>| center + na::Vector2::new(v.x, -v.y);
+
+    writeln!(
+        svg,
+        r##"<rect x="{x}" y="{y}" width="{PANEL}" height="{PANEL}" fill="#fff" stroke="#ccc"/>"##
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<text x="{}" y="{}">{}</text>"#,
+        x + 6.0,
+        y + 14.0,
+        panel.caption
+    )
+    .unwrap();
+
+    // The plane of the bound, seen edge on, unless it's nearly level
+    let normal = project(&panel.bound.normal);
+    if normal.norm() > 1e-3 {
+        let along = na::Vector2::new(-normal.y, normal.x).normalize() * PANEL * 0.45;
+        line(svg, to_screen(along), to_screen(-along), "#888", false);
+        let tip = normal.normalize() * PANEL * 0.1;
+        line(svg, center, to_screen(tip), "#888", false);
+    } else {
+        writeln!(
+            svg,
+            r##"<text x="{}" y="{}" fill="#888">level plane</text>"##,
+            x + 6.0,
+            y + PANEL - 8.0
+        )
+        .unwrap();
+    }
+    let projection = project(&panel.bound.projection_direction);
+    if projection.norm() > 1e-3 {
+        let tip = projection.normalize() * PANEL * 0.1;
+        line(svg, center, to_screen(tip), "#888", true);
+    }
+
+    let colors = [("#9cf", "#06c"), ("#f99", "#c00")];
+    for (pair, (before, after)) in vectors.chunks(2).zip(colors) {
+        line(svg, center, to_screen(pair[0] * scale), before, false);
+        line(svg, center, to_screen(pair[1] * scale), after, false);
+    }
+}
+
+fn line(svg: &mut String, from: na::Vector2<f32>, to: na::Vector2<f32>, color: &str, dashed: bool) {
+    writeln!(
+        svg,
+        r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{color}" stroke-width="2"{}/>"#,
+        from.x,
+        from.y,
+        to.x,
+        to.y,
+        if dashed {
+            r#" stroke-dasharray="4 3""#
+        } else {
+            ""
+        }
+    )
+    .unwrap();
+}
+
+/// Two orthonormal vectors perpendicular to `up`
+fn horizontal_basis(up: &na::Vector3<f32>) -> [na::Vector3<f32>; 2] {
+    let up = up.normalize();
+    let reference = if up.x.abs() < 0.9 {
+        na::Vector3::x()
+    } else {
+        na::Vector3::z()
+    };
+    let across = (reference - up * up.dot(&reference)).normalize();
+    [across, up.cross(&across)]
+}
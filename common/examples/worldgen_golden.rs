@@ -0,0 +1,20 @@
+//! Records the golden chunks that builds are checked against for deterministic world generation
+//!
+//! ```text
+//! cargo run -p common --release --example worldgen_golden > common/src/worldgen/golden.json
+//! ```
+//!
+//! Run only from a canonical build, and only when world generation is meant to have changed.
+
+use std::io;
+
+use common::worldgen::{determinism::Golden, TerrainPassKind};
+
+/// Size of the chunks generated, as used by default
+const DIMENSION: u8 = 12;
+
+fn main() {
+    let golden = Golden::record(DIMENSION, &TerrainPassKind::DEFAULT);
+    serde_json::to_writer_pretty(io::stdout().lock(), &golden).unwrap();
+    println!();
+}
@@ -0,0 +1,43 @@
+//! Summarizes the chunks generated near the origin by biome, as JSON to diff between commits when
+//! tuning world generation
+//!
+//! ```text
+//! cargo run -p common --release --example worldgen_stats -- [radius] [passes] > stats.json
+//! ```
+//!
+//! `radius` defaults to 2. `passes` is a JSON list of terrain passes such as `'["terrain", "road"]'`,
+//! with heights in absolute units rather than meters, defaulting to those generating the usual
+//! world.
+
+use std::{env, io, process};
+
+use common::worldgen::{stats::Survey, TerrainPassKind};
+
+/// Size of the chunks generated, as used by default
+const DIMENSION: u8 = 12;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let radius = match args.next().map(|x| x.parse::<f64>()) {
+        None => 2.0,
+        Some(Ok(x)) => x,
+        Some(Err(e)) => fail(&format!("invalid radius: {e}")),
+    };
+    let passes = match args
+        .next()
+        .map(|x| serde_json::from_str::<Vec<TerrainPassKind>>(&x))
+    {
+        None => TerrainPassKind::DEFAULT.to_vec(),
+        Some(Ok(x)) => x,
+        Some(Err(e)) => fail(&format!("invalid passes: {e}")),
+    };
+    let survey = Survey::run(DIMENSION, radius, &passes);
+    serde_json::to_writer_pretty(io::stdout().lock(), &survey).unwrap();
+    println!();
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    eprintln!("usage: worldgen_stats [radius] [passes]");
+    process::exit(2);
+}
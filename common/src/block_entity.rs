@@ -0,0 +1,278 @@
+//! Data attached to individual blocks beyond their material and shape
+//!
+//! A block entity belongs to one voxel, and lives exactly as long as the voxel holds the material
+//! that calls for it: it's created when such a block is placed, and removed when the block is
+//! broken or replaced, however that happens.
+
+use std::fmt;
+
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    graph::Graph,
+    math,
+    node::{ChunkId, CoordAxis, CoordDirection, Coords},
+    node_path::NodePath,
+    proto::Position,
+    world::Material,
+};
+
+/// Number of lines of text on a sign
+pub const SIGN_LINES: usize = 4;
+
+/// Longest line a sign may carry, in characters
+pub const MAX_SIGN_LINE_LEN: usize = 16;
+
+/// Data attached to a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockEntity {
+    Sign(Sign),
+}
+
+impl BlockEntity {
+    /// Whether a block of `material` may carry this block entity
+    pub fn suits(&self, material: Material) -> bool {
+        match *self {
+            BlockEntity::Sign(_) => material == Material::Sign,
+        }
+    }
+}
+
+/// Text written on one face of a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sign {
+    pub lines: [String; SIGN_LINES],
+    /// The face of the block the text is written on, in the coordinates of its chunk, chosen when
+    /// the sign is placed
+    pub face: (CoordAxis, CoordDirection),
+}
+
+impl Sign {
+    /// A blank sign on the face of the voxel at `coords` in `chunk` that most nearly faces
+    /// `viewer`, as the character placing it would want to read it
+    pub fn facing(graph: &Graph, chunk: ChunkId, coords: Coords, viewer: &Position) -> Self {
+        Self {
+            lines: Default::default(),
+            face: face_toward(graph, chunk, coords, viewer),
+        }
+    }
+}
+
+/// The face of the voxel at `coords` in `chunk` whose outward direction points most nearly toward
+/// `viewer`
+fn face_toward(
+    graph: &Graph,
+    chunk: ChunkId,
+    coords: Coords,
+    viewer: &Position,
+) -> (CoordAxis, CoordDirection) {
+    // Where the viewer lies in the grid coordinates of the chunk
+    let point = NodePath::to(graph, chunk.node).transform_from(&NodePath::to(graph, viewer.node))
+        * viewer.local.cast::<f64>()
+        * math::origin();
+    let dual = chunk.vertex.node_to_dual_f64() * point;
+    let grid = dual.xyz() / dual.w * f64::from(graph.layout().dual_to_grid_factor());
+    let offset = grid - na::Vector3::from(coords.0.map(|x| f64::from(x) + 0.5));
+    let axis = offset.iamax();
+    let direction = if offset[axis] >= 0.0 {
+        CoordDirection::Plus
+    } else {
+        CoordDirection::Minus
+    };
+    (CoordAxis::try_from(axis).unwrap(), direction)
+}
+
+/// Check that `lines` are fit to write on a sign
+///
+/// Each line must be at most `MAX_SIGN_LINE_LEN` printable ASCII characters, the only ones signs
+/// can draw.
+pub fn validate_sign_text(lines: &[String; SIGN_LINES]) -> Result<(), InvalidSignText> {
+    for (line, text) in lines.iter().enumerate() {
+        if text.chars().count() > MAX_SIGN_LINE_LEN {
+            return Err(InvalidSignText::TooLong { line });
+        }
+        if let Some(c) = text.chars().find(|&c| !(' '..='~').contains(&c)) {
+            return Err(InvalidSignText::BadCharacter { line, character: c });
+        }
+    }
+    Ok(())
+}
+
+/// Why text can't be written on a sign
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvalidSignText {
+    TooLong { line: usize },
+    BadCharacter { line: usize, character: char },
+}
+
+impl fmt::Display for InvalidSignText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            InvalidSignText::TooLong { line } => write!(
+                f,
+                "line {} is longer than {MAX_SIGN_LINE_LEN} characters",
+                line + 1
+            ),
+            InvalidSignText::BadCharacter { line, character } => {
+                write!(f, "line {} contains {character:?}", line + 1)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidSignText {}
+
+/// A change to the block entity of one voxel: its new value, or `None` if it was removed
+pub type BlockEntityChange = (ChunkId, Coords, Option<BlockEntity>);
+
+/// The block entities of a world, by the voxel each is attached to
+#[derive(Debug, Default, Clone)]
+pub struct BlockEntities {
+    entities: FxHashMap<ChunkId, FxHashMap<Coords, BlockEntity>>,
+}
+
+impl BlockEntities {
+    pub fn get(&self, chunk: ChunkId, coords: Coords) -> Option<&BlockEntity> {
+        self.entities.get(&chunk)?.get(&coords)
+    }
+
+    /// Attach `entity` to a voxel, returning what was attached before
+    pub fn insert(
+        &mut self,
+        chunk: ChunkId,
+        coords: Coords,
+        entity: BlockEntity,
+    ) -> Option<BlockEntity> {
+        self.entities
+            .entry(chunk)
+            .or_default()
+            .insert(coords, entity)
+    }
+
+    pub fn remove(&mut self, chunk: ChunkId, coords: Coords) -> Option<BlockEntity> {
+        let entities = self.entities.get_mut(&chunk)?;
+        let removed = entities.remove(&coords);
+        if entities.is_empty() {
+            self.entities.remove(&chunk);
+        }
+        removed
+    }
+
+    /// Apply a change as sent by the server
+    pub fn apply(&mut self, (chunk, coords, entity): BlockEntityChange) {
+        match entity {
+            Some(entity) => {
+                self.insert(chunk, coords, entity);
+            }
+            None => {
+                self.remove(chunk, coords);
+            }
+        }
+    }
+
+    /// The block entities in `chunk`, in no particular order
+    pub fn in_chunk(&self, chunk: ChunkId) -> impl Iterator<Item = (Coords, &BlockEntity)> {
+        self.entities
+            .get(&chunk)
+            .into_iter()
+            .flat_map(|entities| entities.iter().map(|(&coords, entity)| (coords, entity)))
+    }
+
+    /// Every block entity, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (ChunkId, Coords, &BlockEntity)> {
+        self.entities.iter().flat_map(|(&chunk, entities)| {
+            entities
+                .iter()
+                .map(move |(&coords, entity)| (chunk, coords, entity))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.values().map(|x| x.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        coords::voxel_point_position, dodeca::Vertex, graph::NodeId, traversal::ensure_nearby,
+    };
+
+    fn sign(text: &str) -> BlockEntity {
+        BlockEntity::Sign(Sign {
+            lines: [text.into(), String::new(), String::new(), String::new()],
+            face: (CoordAxis::X, CoordDirection::Plus),
+        })
+    }
+
+    #[test]
+    fn text_validation() {
+        let lines = |first: &str| [first.into(), "b".into(), String::new(), "~!".into()];
+        assert_eq!(validate_sign_text(&lines("Home, sweet home")), Ok(()));
+        assert_eq!(
+            validate_sign_text(&lines(&"x".repeat(MAX_SIGN_LINE_LEN + 1))),
+            Err(InvalidSignText::TooLong { line: 0 })
+        );
+        assert_eq!(
+            validate_sign_text(&lines("café")),
+            Err(InvalidSignText::BadCharacter {
+                line: 0,
+                character: 'é'
+            })
+        );
+        assert_eq!(
+            validate_sign_text(&lines("tab\there")),
+            Err(InvalidSignText::BadCharacter {
+                line: 0,
+                character: '\t'
+            })
+        );
+    }
+
+    #[test]
+    fn entities_by_voxel() {
+        let mut entities = BlockEntities::default();
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let other = ChunkId::new(NodeId::ROOT, Vertex::B);
+        assert!(entities
+            .insert(chunk, Coords([1, 2, 3]), sign("a"))
+            .is_none());
+        entities.insert(other, Coords([1, 2, 3]), sign("b"));
+        assert_eq!(
+            entities.insert(chunk, Coords([1, 2, 3]), sign("c")),
+            Some(sign("a"))
+        );
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities.in_chunk(chunk).count(), 1);
+
+        entities.apply((chunk, Coords([1, 2, 3]), None));
+        assert!(entities.get(chunk, Coords([1, 2, 3])).is_none());
+        assert_eq!(entities.get(other, Coords([1, 2, 3])), Some(&sign("b")));
+        entities.remove(other, Coords([1, 2, 3]));
+        assert!(entities.is_empty());
+    }
+
+    #[test]
+    fn signs_face_their_placer() {
+        let mut graph = Graph::new(12);
+        ensure_nearby(&mut graph, &Position::origin(), 2.0);
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let coords = Coords([3, 3, 3]);
+        for axis in CoordAxis::iter() {
+            for direction in CoordDirection::iter() {
+                // A viewer a few voxels off along the axis
+                let mut offset = na::Vector3::repeat(3.5);
+                offset[axis as usize] += 3.0 * f64::from(direction as i8);
+                let viewer = voxel_point_position(graph.layout(), chunk, Coords([0; 3]), &offset);
+                let sign = Sign::facing(&graph, chunk, coords, &viewer);
+                assert_eq!(sign.face, (axis, direction));
+            }
+        }
+    }
+}
@@ -2,7 +2,10 @@
 
 use tracing::error;
 
-use crate::{collision_math::Ray, graph::Graph, graph_collision, math, proto::Position};
+use crate::{
+    collision_math::Ray, graph::Graph, graph_collision, graph_ray_casting, math, node::ChunkId,
+    proto::Position, world::Material,
+};
 
 /// Checks for collisions when a character moves with a character-relative displacement vector of `relative_displacement`.
 pub fn check_collision(
@@ -36,8 +39,11 @@ pub fn check_collision(
     let cast_hit = match cast_hit {
         Ok(r) => r,
         Err(e) => {
-            error!("Collision checking returned {:?}", e);
-            return CollisionCheckingResult::stationary();
+            error!(error = ?e, "collision checking failed");
+            return CollisionCheckingResult {
+                out_of_bounds: true,
+                ..CollisionCheckingResult::stationary()
+            };
         }
     };
 
@@ -52,18 +58,47 @@ pub fn check_collision(
     CollisionCheckingResult {
         displacement_vector,
         displacement_transform,
-        collision: cast_hit.map(|hit| Collision {
+        out_of_bounds: false,
+        collision: cast_hit.map(|hit| {
             // `CastEndpoint` has its `normal` given relative to the character's original position,
             // but we want the normal relative to the character after the character moves to meet the wall.
             // This normal now represents a contact point at the origin, so we omit the w-coordinate
             // to ensure that it's orthogonal to the origin.
-            normal: na::UnitVector3::new_normalize(
+            let normal = na::UnitVector3::new_normalize(
                 (math::mtranspose(&displacement_transform) * hit.normal).xyz(),
-            ),
+            );
+            let contact = Position {
+                node: position.node,
+                local: position.local * displacement_transform,
+            };
+            Collision {
+                normal,
+                chunk: hit.chunk,
+                material: surface_material(collision_context, &contact, &normal),
+            }
         }),
     }
 }
 
+/// Material of the surface touched by a character at `position` where the surface has the given
+/// character-relative normal, if it can be found
+///
+/// The point of contact lies one radius away, against the normal. A contact on the very edge or
+/// corner of a voxel may be missed, in which case the material is unknown.
+fn surface_material(
+    collision_context: &CollisionContext,
+    position: &Position,
+    normal: &na::UnitVector3<f32>,
+) -> Option<Material> {
+    let ray = Ray::new(math::origin(), -normal.into_inner().to_homogeneous());
+    let reach = collision_context.radius * 1.5;
+    let hit = graph_ray_casting::ray_cast(collision_context.graph, position, &ray, reach.tanh())
+        .ok()??;
+    collision_context
+        .graph
+        .get_block(hit.chunk, hit.voxel_coords)
+}
+
 /// Contains information about the character and the world that is only relevant for collision checking
 pub struct CollisionContext<'a> {
     pub graph: &'a Graph,
@@ -79,6 +114,10 @@ pub struct CollisionCheckingResult {
     /// displacement until it hits the wall.
     pub displacement_transform: na::Matrix4<f32>,
 
+    /// Whether the cast reached chunks that haven't been generated yet, in which case the character
+    /// is held in place rather than risk moving it into terrain that isn't known
+    pub out_of_bounds: bool,
+
     pub collision: Option<Collision>,
 }
 
@@ -89,6 +128,7 @@ impl CollisionCheckingResult {
         CollisionCheckingResult {
             displacement_vector: na::Vector3::zeros(),
             displacement_transform: na::Matrix4::identity(),
+            out_of_bounds: false,
             collision: None,
         }
     }
@@ -99,4 +139,10 @@ pub struct Collision {
     /// _after_ it is transformed by `allowed_displacement`. The 4th coordinate of this normal vector is assumed to be
     /// 0.0 and is therefore omitted.
     pub normal: na::UnitVector3<f32>,
+
+    /// The chunk containing the surface hit
+    pub chunk: ChunkId,
+
+    /// The material of the surface hit, if known
+    pub material: Option<Material>,
 }
@@ -0,0 +1,68 @@
+//! Motion imposed on characters from outside their own input
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    graph::Graph,
+    math,
+    node_path::NodePath,
+    proto::{ExternalImpulse, ImpulseMode, Position, PositionConstraint},
+};
+
+/// A spring pulling a character toward a fixed point until it's removed, as by a grappling hook
+///
+/// The server applies it to every step of the character, and its client to every step it predicts,
+/// each working out the pull from where the character is at the time.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tether {
+    pub anchor: Position,
+    /// As in `PositionConstraint`
+    pub stiffness: f32,
+    pub damping: f32,
+    pub max_acceleration: f32,
+}
+
+impl Tether {
+    /// The pull on a character at `position`
+    pub fn constraint(&self, graph: &Graph, position: &Position) -> PositionConstraint {
+        PositionConstraint {
+            offset: relative_offset(graph, position, &self.anchor),
+            stiffness: self.stiffness,
+            damping: self.damping,
+            max_acceleration: self.max_acceleration,
+        }
+    }
+}
+
+/// Displacement from `from` to `to`, relative to `from`, in absolute units
+///
+/// The direction is that of the geodesic between them, and the length its length.
+pub fn relative_offset(graph: &Graph, from: &Position, to: &Position) -> na::Vector3<f32> {
+    let target = math::mtranspose(&from.local.cast::<f64>())
+        * NodePath::to(graph, from.node).transform_from(&NodePath::to(graph, to.node))
+        * to.local.cast::<f64>()
+        * math::origin();
+    let target = math::lorentz_normalize(&target.cast::<f32>());
+    let distance = target.w.max(1.0).acosh();
+    target
+        .xyz()
+        .try_normalize(1e-6)
+        .map_or_else(na::Vector3::zeros, |direction| direction * distance)
+}
+
+pub(super) fn apply_impulse(velocity: &mut na::Vector3<f32>, impulse: &ExternalImpulse) {
+    match impulse.mode {
+        ImpulseMode::Add => *velocity += impulse.delta_velocity,
+        ImpulseMode::Override => *velocity = impulse.delta_velocity,
+    }
+}
+
+/// Accelerate a character as `constraint` pulls it over `dt_seconds`
+pub(super) fn apply_constraint(
+    velocity: &mut na::Vector3<f32>,
+    constraint: &PositionConstraint,
+    dt_seconds: f32,
+) {
+    let acceleration = constraint.offset * constraint.stiffness - *velocity * constraint.damping;
+    *velocity += acceleration.cap_magnitude(constraint.max_acceleration) * dt_seconds;
+}
@@ -1,24 +1,94 @@
 mod collision;
+mod external;
+mod separation;
+mod trace;
+mod unembed;
 mod vector_bounds;
 
-use std::mem::replace;
+pub use external::{relative_offset, Tether};
+pub use separation::{separate_characters, SeparationStats};
+pub use trace::{
+    CastHit, CastPurpose, CollisionTrace, StepTrace, TraceDump, TraceEvent, TracedBound,
+    TracedState,
+};
+pub use unembed::nearest_free_position;
+
+use std::{
+    cell::{Cell, RefCell},
+    mem::replace,
+};
 
 use tracing::warn;
 
 use crate::{
     character_controller::{
-        collision::{check_collision, Collision, CollisionContext},
+        collision::{check_collision, Collision, CollisionCheckingResult, CollisionContext},
+        external::{apply_constraint, apply_impulse},
+        trace::Tracer,
+        unembed::{is_clear, push_out},
         vector_bounds::{BoundedVectors, VectorBound},
     },
     graph::Graph,
     math,
-    proto::{CharacterInput, Position},
+    proto::{CharacterInput, ExternalImpulse, ExternalInfluence, Position, PositionConstraint},
     sanitize_motion_input,
     sim_config::CharacterConfig,
+    world::Material,
     SimConfig,
 };
 
+/// Information about a character step beyond the updated character state
+#[derive(Debug, Clone, Default)]
+pub struct StepOutput {
+    /// If the character ended the step in a different node than it started in, the transform from
+    /// the starting node's coordinates to the final node's coordinates
+    ///
+    /// `Position::local` has already been rebased by this transform. Because the rebasing
+    /// multiplies on the left, anything expressed relative to the character, such as a view
+    /// orientation applied on the right of `Position::local`, stays valid without adjustment. This
+    /// is only needed for data expressed relative to the character's node.
+    pub node_transition: Option<na::Matrix4<f32>>,
+    /// The first time the character touched down on the ground during the step, if any
+    ///
+    /// Later touchdowns within the same step are bounces off the ground just landed on.
+    pub landing: Option<Landing>,
+    /// Whether the character was held back during the step by parts of the graph that haven't been
+    /// generated yet
+    ///
+    /// This happens at the edge of what a client has generated when its prediction outruns it, in
+    /// which case the server, having generated more, most likely moved the character further.
+    pub held_at_frontier: bool,
+}
+
+/// A character coming to rest on the ground after being airborne
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Landing {
+    /// Speed in absolute units per second at which the character struck the ground, along the
+    /// ground's normal
+    pub impact_speed: f32,
+    /// Normal of the ground landed on, relative to the character
+    pub normal: na::UnitVector3<f32>,
+    /// Time in seconds the character spent airborne during the step before landing
+    ///
+    /// Only whole substeps are counted, and time airborne in earlier steps isn't included.
+    pub airborne_seconds: f32,
+}
+
 /// Runs a single step of character movement
+///
+/// Steps longer than `sim_config.max_substep_seconds` are split into equal substeps so that large
+/// time deltas don't overwhelm collision handling. Both this and the handling of short steps
+/// depend only on the time simulated and the config, so the client's prediction and the server
+/// agree exactly.
+///
+/// `carried_seconds` is time from earlier steps that was too short to simulate. It's simulated
+/// along with `dt_seconds`, unless their sum is still shorter than `sim_config.min_step_seconds`,
+/// in which case the character is left untouched and the sum is carried on to the next step.
+/// Callers that always step by at least the minimum can pass a fresh zero.
+///
+/// If `trace` is supplied, what happened during the step is recorded in it. Skipped steps aren't
+/// recorded.
+#[allow(clippy::too_many_arguments)]
 pub fn run_character_step(
     sim_config: &SimConfig,
     graph: &Graph,
@@ -26,58 +96,222 @@ pub fn run_character_step(
     velocity: &mut na::Vector3<f32>,
     on_ground: &mut bool,
     input: &CharacterInput,
+    carried_seconds: &mut f32,
     dt_seconds: f32,
-) {
+    trace: Option<&mut CollisionTrace>,
+) -> StepOutput {
+    let mut output = StepOutput::default();
+    let dt_seconds = *carried_seconds + dt_seconds;
+    if dt_seconds < sim_config.min_step_seconds {
+        *carried_seconds = dt_seconds;
+        return output;
+    }
+    *carried_seconds = 0.0;
+
+    let traced_state =
+        |position: &Position, velocity: &na::Vector3<f32>, on_ground: &bool| TracedState {
+            position: *position,
+            velocity: *velocity,
+            on_ground: *on_ground,
+        };
+    let start = trace
+        .is_some()
+        .then(|| traced_state(position, velocity, on_ground));
+    let events = trace.is_some().then(|| RefCell::new(Vec::new()));
+    let tracer = Tracer::new(events.as_ref());
+
+    let substeps = (dt_seconds / sim_config.max_substep_seconds)
+        .ceil()
+        .clamp(1.0, f32::from(sim_config.max_substeps.max(1))) as u32;
+    let substep_seconds = dt_seconds / substeps as f32;
+    let mut airborne_seconds = 0.0;
+    let mut external = input.external;
+    for _ in 0..substeps {
+        let was_on_ground = *on_ground;
+        let start = position.local;
+        let substep = run_character_substep(
+            sim_config,
+            graph,
+            position,
+            velocity,
+            on_ground,
+            input,
+            &external,
+            substep_seconds,
+            tracer,
+        );
+        // An impulse is applied once however the step is divided, while a constraint keeps pulling
+        // toward the same point as the character moves
+        external.impulse = None;
+        if let Some(ref mut constraint) = external.constraint {
+            let start = substep
+                .node_transition
+                .map_or(start, |transition| transition * start);
+            constraint.offset -= (math::mtranspose(&start) * position.local * math::origin()).xyz();
+        }
+        if let Some(transition) = substep.node_transition {
+            output.node_transition = Some(
+                output
+                    .node_transition
+                    .map_or(transition, |previous| transition * previous),
+            );
+        }
+        if !was_on_ground {
+            airborne_seconds += substep_seconds;
+        }
+        if let Some(landing) = substep.landing {
+            output.landing.get_or_insert(Landing {
+                airborne_seconds,
+                ..landing
+            });
+        }
+        output.held_at_frontier |= substep.held_at_frontier;
+    }
+
+    if let (Some(trace), Some(start), Some(events)) = (trace, start, events) {
+        trace.push(StepTrace {
+            dt_seconds,
+            input: input.clone(),
+            start,
+            events: events.into_inner(),
+            end: traced_state(position, velocity, on_ground),
+        });
+    }
+    output
+}
+
+/// Effects of a substep beyond the updated character state
+#[derive(Default)]
+struct SubstepOutput {
+    /// Transform from the old node's coordinates to the new node's, if the character changed nodes
+    node_transition: Option<na::Matrix4<f32>>,
+    /// How the character landed, if it did, not counting time airborne
+    landing: Option<Landing>,
+    /// Whether a cast reached parts of the graph that haven't been generated yet
+    held_at_frontier: bool,
+}
+
+/// Runs character movement over an interval short enough to be integrated in one go
+#[allow(clippy::too_many_arguments)]
+fn run_character_substep(
+    sim_config: &SimConfig,
+    graph: &Graph,
+    position: &mut Position,
+    velocity: &mut na::Vector3<f32>,
+    on_ground: &mut bool,
+    input: &CharacterInput,
+    external: &ExternalInfluence,
+    dt_seconds: f32,
+    tracer: Tracer,
+) -> SubstepOutput {
+    // A node whose state isn't known yet, as when it's still waiting to be populated, has no
+    // direction for gravity, so the character holds still until it's ready
+    let Some(up) = graph.get_relative_up(position) else {
+        return SubstepOutput {
+            held_at_frontier: true,
+            ..Default::default()
+        };
+    };
     let ctx = CharacterControllerContext {
         cfg: &sim_config.character,
         collision_context: CollisionContext {
             graph,
             radius: sim_config.character.character_radius,
         },
-        up: graph.get_relative_up(position).unwrap(),
+        up,
         dt_seconds,
-        movement_input: sanitize_motion_input(input.movement),
+        movement_input: *sanitize_motion_input(input.movement).vector(),
         jump_input: input.jump,
+        impulse: external.impulse,
+        constraint: external.constraint,
+        tracer,
+        held_at_frontier: Cell::new(false),
     };
+    tracer.record(|| TraceEvent::Substep {
+        dt_seconds,
+        up: up.into_inner(),
+    });
 
-    if input.no_clip {
+    let landing = if input.no_clip {
         run_no_clip_character_step(&ctx, position, velocity, on_ground);
+        None
     } else {
-        run_standard_character_step(&ctx, position, velocity, on_ground);
+        run_standard_character_step(&ctx, position, velocity, on_ground)
+    };
+
+    SubstepOutput {
+        node_transition: renormalize_position(graph, position),
+        landing,
+        held_at_frontier: ctx.held_at_frontier.get(),
     }
+}
 
-    // Renormalize
+/// Correct accumulated error in a character's transform and move it to the node it now lies in,
+/// returning the transform from the old node's coordinates to the new node's if it changed
+fn renormalize_position(graph: &Graph, position: &mut Position) -> Option<na::Matrix4<f32>> {
     position.local = math::renormalize_isometry(&position.local);
     let (next_node, transition_xf) = graph.normalize_transform(position.node, &position.local);
-    if next_node != position.node {
-        position.node = next_node;
-        position.local = transition_xf * position.local;
+    if next_node == position.node {
+        return None;
     }
+    position.node = next_node;
+    position.local = transition_xf * position.local;
+    Some(transition_xf)
 }
 
+/// Runs a step of ordinary movement, returning how the character landed if it did
 fn run_standard_character_step(
     ctx: &CharacterControllerContext,
     position: &mut Position,
     velocity: &mut na::Vector3<f32>,
     on_ground: &mut bool,
-) {
+) -> Option<Landing> {
+    // Sphere casts that start inside a solid voxel can miss it entirely, as when no-clip has just
+    // been turned off inside terrain, so get clear of it first
+    if let Some(direction) = push_out(
+        ctx.collision_context.graph,
+        position,
+        ctx.collision_context.radius,
+        &ctx.up,
+        ctx.collision_context.radius * MAX_PUSH_OUT_RADII,
+    ) {
+        ctx.tracer.record(|| TraceEvent::PushOut {
+            direction: direction.into_inner(),
+        });
+        remove_approach(velocity, &direction);
+    }
+
+    let was_on_ground = *on_ground;
     let mut ground_normal = None;
+    let mut ground_material = None;
     if *on_ground {
-        ground_normal = get_ground_normal(ctx, position);
+        if let Some((normal, material)) = get_ground_normal(ctx, position) {
+            ground_normal = Some(normal);
+            ground_material = material;
+        }
     }
 
-    // Handle jumping
+    let mut wall = get_climbable_wall(ctx, position, velocity, ground_normal.is_some());
+
+    // Handle jumping, which pushes away from a wall being climbed
     if ctx.jump_input && ground_normal.is_some() {
         let horizontal_velocity = *velocity - *ctx.up * ctx.up.dot(velocity);
         *velocity = horizontal_velocity + *ctx.up * ctx.cfg.jump_speed;
         ground_normal = None;
+        wall = None;
+    } else if let Some(normal) = wall.filter(|_| ctx.jump_input) {
+        *velocity = (normal.into_inner() + *ctx.up) * ctx.cfg.jump_speed;
+        wall = None;
     }
 
     let old_velocity = *velocity;
 
     // Update velocity
-    if let Some(ground_normal) = ground_normal {
-        apply_ground_controls(ctx, &ground_normal, velocity);
+    if let Some(ref normal) = wall {
+        apply_climbing_controls(ctx, normal, velocity);
+        ground_normal = None;
+    } else if let Some(ground_normal) = ground_normal {
+        apply_ground_controls(ctx, &ground_normal, ground_material, velocity);
     } else {
         apply_air_controls(ctx, velocity);
 
@@ -85,8 +319,18 @@ fn run_standard_character_step(
         *velocity *= (-ctx.cfg.air_resistance * ctx.dt_seconds).exp();
     }
 
-    // Apply gravity
-    *velocity -= *ctx.up * ctx.cfg.gravity_acceleration * ctx.dt_seconds;
+    // Apply gravity, which characters holding onto a wall resist
+    if wall.is_none() {
+        *velocity -= *ctx.up * ctx.cfg.gravity_acceleration * ctx.dt_seconds;
+    }
+
+    // Apply external influences, which are then subject to the same limits as the rest of the motion
+    if let Some(ref impulse) = ctx.impulse {
+        apply_impulse(velocity, impulse);
+    }
+    if let Some(ref constraint) = ctx.constraint {
+        apply_constraint(velocity, constraint, ctx.dt_seconds);
+    }
 
     // Apply speed cap
     *velocity = velocity.cap_magnitude(ctx.cfg.speed_cap);
@@ -101,15 +345,274 @@ fn run_standard_character_step(
     let average_velocity = (*velocity + old_velocity) * 0.5;
 
     // Handle actual movement
-    apply_velocity(
+    let approach_velocity = *velocity;
+    let start = *position;
+    let walking = ground_normal.is_some();
+    let collisions = apply_velocity(
         ctx,
         average_velocity * ctx.dt_seconds,
         position,
         velocity,
         &mut ground_normal,
+        MAX_COLLISION_ITERATIONS,
     );
 
+    // Climb ledges low enough to step onto, if doing so gets the character further, as at the top
+    // of a wall being climbed
+    let mut stepped_up = false;
+    if walking || wall.is_some() {
+        if let Some((stepped, stepped_velocity, normal)) = step_up(
+            ctx,
+            &start,
+            position,
+            average_velocity * ctx.dt_seconds,
+            approach_velocity,
+        ) {
+            *position = stepped;
+            *velocity = stepped_velocity;
+            ground_normal = Some(normal);
+            stepped_up = true;
+        }
+    }
+
+    // Follow ground that falls away beneath a walking character, as down slopes and stairs, rather
+    // than letting it drop through the air for a moment. Characters rising fast enough are left
+    // to fly off, and the snap shares the collision budget with the rest of the movement.
+    if walking
+        && !stepped_up
+        && ctx.cfg.ground_snap_distance > 0.0
+        && ctx.up.dot(velocity) <= ctx.cfg.ground_snap_max_rise_speed
+        && collisions < MAX_COLLISION_ITERATIONS
+    {
+        ground_normal = snap_to_ground(
+            ctx,
+            position,
+            velocity,
+            MAX_COLLISION_ITERATIONS - collisions,
+        );
+    }
+
+    // Keep heading into a wall still being climbed, so that it's found again next step even if
+    // the character's input no longer leads there
+    if let (Some(normal), None) = (wall, ground_normal) {
+        *velocity -= normal.into_inner() * CLIMB_GRIP * ctx.cfg.climb_speed;
+    }
+
     *on_ground = ground_normal.is_some();
+    if was_on_ground {
+        return None;
+    }
+    let normal = ground_normal?;
+    Some(Landing {
+        impact_speed: impact_speed(&approach_velocity, &normal),
+        normal,
+        airborne_seconds: 0.0,
+    })
+}
+
+/// Outward normal of a climbable wall the character is holding onto, if any, at right angles to up
+///
+/// Walls are looked for by casting a short way along the character's horizontal movement input,
+/// and while off the ground against it too, so that backing away from a wall climbs down it. With
+/// no input, the cast follows the character's horizontal velocity instead, which is left heading
+/// into a wall being climbed. Walls merely brushed in passing aren't held, since the character must
+/// be heading into them. Only a couple of casts are made, and nothing is remembered between steps,
+/// so prediction agrees with the server as it does for the rest of the movement.
+fn get_climbable_wall(
+    ctx: &CharacterControllerContext,
+    position: &Position,
+    velocity: &na::Vector3<f32>,
+    on_ground: bool,
+) -> Option<na::UnitVector3<f32>> {
+    let horizontal = |v: &na::Vector3<f32>| na::Unit::try_new(v - *ctx.up * ctx.up.dot(v), 1e-5);
+    let directions = match horizontal(&ctx.movement_input) {
+        Some(input) => [Some(input), (!on_ground).then_some(-input)],
+        None if on_ground => return None,
+        None => [horizontal(velocity), None],
+    };
+    directions.into_iter().flatten().find_map(|direction| {
+        let hit = cast(
+            ctx,
+            CastPurpose::Climb,
+            position,
+            &(direction.into_inner() * ctx.cfg.ground_distance_tolerance),
+        )
+        .collision?;
+        // Floors and ceilings can't be climbed, even of climbable materials
+        if !hit.material?.is_climbable() || hit.normal.dot(&ctx.up).abs() > MAX_CLIMB_TILT {
+            return None;
+        }
+        let normal = horizontal(&hit.normal)?;
+        // Characters that just jumped off a wall don't take hold of it again on their way
+        let leaving = velocity.dot(&normal) > ctx.cfg.climb_speed;
+        (-normal.dot(&direction) > MIN_CLIMB_APPROACH && !leaving).then_some(normal)
+    })
+}
+
+/// Sine of the steepest angle from vertical at which a surface can still be climbed as a wall
+const MAX_CLIMB_TILT: f32 = 0.5;
+
+/// Cosine of the widest angle from a wall's normal at which a character heading into it takes
+/// hold, so that running along a wall doesn't
+const MIN_CLIMB_APPROACH: f32 = 0.7;
+
+/// Speed at which a character climbing a wall is kept heading into it, as a fraction of
+/// `climb_speed`
+const CLIMB_GRIP: f32 = 0.01;
+
+/// Speed at which a character climbing a wall moves along it, as a fraction of `climb_speed`
+const CLIMB_SIDEWAYS_SPEED: f32 = 0.5;
+
+/// Sets the velocity of a character holding onto a wall with the given outward normal from its
+/// input
+///
+/// Heading into the wall climbs up it and heading away climbs down, while heading along it moves
+/// sideways, more slowly. Climbing up also presses into the wall, so that the character steps onto
+/// the ledge at its top.
+fn apply_climbing_controls(
+    ctx: &CharacterControllerContext,
+    normal: &na::UnitVector3<f32>,
+    velocity: &mut na::Vector3<f32>,
+) {
+    let into = -ctx.movement_input.dot(normal);
+    let sideways =
+        ctx.movement_input + normal.into_inner() * into - *ctx.up * ctx.up.dot(&ctx.movement_input);
+    *velocity = (*ctx.up * into - normal.into_inner() * into.max(0.0)
+        + sideways * CLIMB_SIDEWAYS_SPEED)
+        * ctx.cfg.climb_speed;
+}
+
+/// Retry a walk from `start` that ended at `walked` by rising up to `max_step_height`, moving
+/// horizontally, and settling back onto the ground, returning the resulting position, velocity, and
+/// ground normal if that gets the character further
+fn step_up(
+    ctx: &CharacterControllerContext,
+    start: &Position,
+    walked: &Position,
+    displacement: na::Vector3<f32>,
+    velocity: na::Vector3<f32>,
+) -> Option<(Position, na::Vector3<f32>, na::UnitVector3<f32>)> {
+    let horizontal = displacement - *ctx.up * ctx.up.dot(&displacement);
+    let length = horizontal.norm();
+    if ctx.cfg.max_step_height <= 0.0 || length < 1e-6 {
+        return None;
+    }
+    let direction = horizontal / length;
+    let progress = |position: &Position| {
+        (math::mtranspose(&start.local) * position.local * math::origin())
+            .xyz()
+            .dot(&direction)
+    };
+    let walked_progress = progress(walked);
+    // Nothing held the character back
+    if walked_progress >= length.sinh() * 0.99 {
+        return None;
+    }
+
+    let climbed = try_step_up(ctx, start, horizontal, velocity, walked_progress, progress);
+    ctx.tracer.record(|| TraceEvent::StepUp {
+        climbed: climbed.is_some(),
+    });
+    climbed
+}
+
+/// The part of `step_up` that moves the character, once it's known to have been held back
+fn try_step_up(
+    ctx: &CharacterControllerContext,
+    start: &Position,
+    horizontal: na::Vector3<f32>,
+    velocity: na::Vector3<f32>,
+    walked_progress: f32,
+    progress: impl Fn(&Position) -> f32,
+) -> Option<(Position, na::Vector3<f32>, na::UnitVector3<f32>)> {
+    let mut stepped = *start;
+    let rise = cast(
+        ctx,
+        CastPurpose::StepUp,
+        &stepped,
+        &(*ctx.up * ctx.cfg.max_step_height),
+    );
+    stepped.local *= rise.displacement_transform;
+    let mut velocity = velocity - *ctx.up * ctx.up.dot(&velocity);
+    let mut ground_normal = None;
+    apply_velocity(
+        ctx,
+        horizontal,
+        &mut stepped,
+        &mut velocity,
+        &mut ground_normal,
+        MAX_COLLISION_ITERATIONS,
+    );
+    let fall = cast(
+        ctx,
+        CastPurpose::StepUp,
+        &stepped,
+        &(-*ctx.up * (rise.displacement_vector.norm() + ctx.cfg.ground_distance_tolerance)),
+    );
+    let ground = fall.collision?;
+    if !is_ground(ctx, &ground.normal, ground.material) {
+        return None;
+    }
+    let normal = ground.normal;
+    stepped.local *= fall.displacement_transform;
+    if progress(&stepped) <= walked_progress
+        || !is_clear(
+            ctx.collision_context.graph,
+            &stepped,
+            ctx.collision_context.radius,
+        )
+    {
+        return None;
+    }
+    remove_approach(&mut velocity, &normal);
+    Some((stepped, velocity, normal))
+}
+
+/// Move a character down onto ground within `ground_snap_distance` below it, sliding past up to
+/// `max_collisions` walls and corners on the way, and return the ground's normal, or leave the
+/// character where it is and return `None` if there's no such ground
+fn snap_to_ground(
+    ctx: &CharacterControllerContext,
+    position: &mut Position,
+    velocity: &mut na::Vector3<f32>,
+    max_collisions: u32,
+) -> Option<na::UnitVector3<f32>> {
+    let mut allowed_displacement =
+        BoundedVectors::new(-ctx.up.into_inner() * ctx.cfg.ground_snap_distance, None);
+    let mut probe = *position;
+    for _ in 0..max_collisions {
+        let collision_result = cast(
+            ctx,
+            CastPurpose::GroundSnap,
+            &probe,
+            allowed_displacement.displacement(),
+        );
+        probe.local *= collision_result.displacement_transform;
+        let collision = collision_result.collision?;
+        if is_ground(ctx, &collision.normal, collision.material) {
+            *position = probe;
+            remove_approach(velocity, &collision.normal);
+            return Some(collision.normal);
+        }
+        allowed_displacement.scale_displacement(
+            1.0 - collision_result.displacement_vector.magnitude()
+                / allowed_displacement.displacement().magnitude(),
+        );
+        add_bound(
+            ctx,
+            &mut allowed_displacement,
+            VectorBound::new(collision.normal, collision.normal, true),
+            false,
+        );
+    }
+    None
+}
+
+/// Speed at which a character moving at `velocity` strikes ground with the given normal
+///
+/// Only motion into the ground counts, so grazing a slope hurts less than falling flat onto it.
+fn impact_speed(velocity: &na::Vector3<f32>, ground_normal: &na::UnitVector3<f32>) -> f32 {
+    (-velocity.dot(ground_normal)).max(0.0)
 }
 
 fn run_no_clip_character_step(
@@ -123,12 +626,12 @@ fn run_no_clip_character_step(
     position.local *= math::translate_along(&(*velocity * ctx.dt_seconds));
 }
 
-/// Returns the normal corresponding to the ground below the character, up to the `allowed_distance`. If
-/// no such ground exists, returns `None`.
+/// Returns the normal corresponding to the ground below the character, up to the `allowed_distance`,
+/// and the ground's material if known. If no such ground exists, returns `None`.
 fn get_ground_normal(
     ctx: &CharacterControllerContext,
     position: &Position,
-) -> Option<na::UnitVector3<f32>> {
+) -> Option<(na::UnitVector3<f32>, Option<Material>)> {
     // Since the character can be at a corner between a slanted wall and the ground, the first collision
     // directly below the character is not guaranteed to be part of the ground regardless of whether the
     // character is on the ground. To handle this, we repeatedly redirect the direction we search to be
@@ -140,21 +643,23 @@ fn get_ground_normal(
     );
 
     for _ in 0..MAX_COLLISION_ITERATIONS {
-        let collision_result = check_collision(
-            &ctx.collision_context,
+        let collision_result = cast(
+            ctx,
+            CastPurpose::Ground,
             position,
             allowed_displacement.displacement(),
         );
         if let Some(collision) = collision_result.collision.as_ref() {
-            if is_ground(ctx, &collision.normal) {
+            if is_ground(ctx, &collision.normal, collision.material) {
                 // We found the ground, so return its normal.
-                return Some(collision.normal);
+                return Some((collision.normal, collision.material));
             }
-            allowed_displacement.add_bound(VectorBound::new(
-                collision.normal,
-                collision.normal,
-                true,
-            ));
+            add_bound(
+                ctx,
+                &mut allowed_displacement,
+                VectorBound::new(collision.normal, collision.normal, true),
+                false,
+            );
         } else {
             // Return `None` if we travel the whole `allowed_displacement` and don't find the ground.
             return None;
@@ -164,16 +669,50 @@ fn get_ground_normal(
     None
 }
 
-/// Checks whether the given normal is flat enough to be considered part of the ground
-fn is_ground(ctx: &CharacterControllerContext, normal: &na::UnitVector3<f32>) -> bool {
-    let min_slope_up_component = 1.0 / (ctx.cfg.max_ground_slope.powi(2) + 1.0).sqrt();
-    normal.dot(&ctx.up) > min_slope_up_component
+/// Checks whether the given normal of a surface of `material`, if known, is flat enough to be
+/// considered part of the ground
+fn is_ground(
+    ctx: &CharacterControllerContext,
+    normal: &na::UnitVector3<f32>,
+    material: Option<Material>,
+) -> bool {
+    let max_slope = material
+        .and_then(Material::max_ground_slope)
+        .unwrap_or(ctx.cfg.max_ground_slope);
+    normal.dot(&ctx.up) > slope_up_component(max_slope)
+}
+
+/// Component along the up direction of the normal of ground with the given slope, as rise over run
+fn slope_up_component(slope: f32) -> f32 {
+    1.0 / (slope.powi(2) + 1.0).sqrt()
 }
 
-/// Updates the velocity based on user input assuming the character is on the ground
+/// Acceleration pulling a character down ground with the given normal, of `material` if known, if
+/// it's slippery and steep enough to slide on
+///
+/// This is gravity projected onto the ground, so it lies along the ground and grows with its
+/// steepness.
+fn slide_acceleration(
+    ctx: &CharacterControllerContext,
+    ground_normal: &na::UnitVector3<f32>,
+    material: Option<Material>,
+) -> Option<na::Vector3<f32>> {
+    let up_component = ground_normal.dot(&ctx.up);
+    if up_component >= slope_up_component(material?.slide_slope()?) {
+        return None;
+    }
+    Some(
+        (ground_normal.into_inner() * up_component - ctx.up.into_inner())
+            * ctx.cfg.gravity_acceleration,
+    )
+}
+
+/// Updates the velocity based on user input assuming the character is on the ground of `material`,
+/// if known
 fn apply_ground_controls(
     ctx: &CharacterControllerContext,
     ground_normal: &na::UnitVector3<f32>,
+    material: Option<Material>,
     velocity: &mut na::Vector3<f32>,
 ) {
     // Set `target_ground_velocity` to have a consistent magnitude regardless
@@ -199,11 +738,26 @@ fn apply_ground_controls(
     // target velocity.
     let current_to_target_velocity = target_ground_velocity - ground_velocity;
     let max_delta_velocity = ctx.cfg.ground_acceleration * ctx.dt_seconds;
-    if current_to_target_velocity.norm_squared() > max_delta_velocity.powi(2) {
-        *velocity += current_to_target_velocity.normalize() * max_delta_velocity;
-    } else {
-        *velocity += current_to_target_velocity;
+    let mut delta_velocity =
+        if current_to_target_velocity.norm_squared() > max_delta_velocity.powi(2) {
+            current_to_target_velocity.normalize() * max_delta_velocity
+        } else {
+            current_to_target_velocity
+        };
+
+    // On slippery slopes, walking can hold back only part of the pull downhill, so the character
+    // always drifts down
+    if let Some(slide) = slide_acceleration(ctx, ground_normal, material) {
+        let pull = slide.norm();
+        let downhill = slide / pull;
+        let max_resistance = ctx.cfg.slide_resistance * pull * ctx.dt_seconds;
+        let resistance = -delta_velocity.dot(&downhill);
+        if resistance > max_resistance {
+            delta_velocity += downhill * (resistance - max_resistance);
+        }
+        delta_velocity += slide * ctx.dt_seconds;
     }
+    *velocity += delta_velocity;
 }
 
 /// Updates the velocity based on user input assuming the character is in the air
@@ -211,35 +765,62 @@ fn apply_air_controls(ctx: &CharacterControllerContext, velocity: &mut na::Vecto
     *velocity += ctx.movement_input * ctx.cfg.air_acceleration * ctx.dt_seconds;
 }
 
-/// Updates the character's position based on the given average velocity while handling collisions.
-/// Also updates the velocity and ground normal based on collisions that occur.
+/// Remove any component of `velocity` heading into a surface with the given normal
+fn remove_approach(velocity: &mut na::Vector3<f32>, normal: &na::UnitVector3<f32>) {
+    *velocity -= normal.into_inner() * velocity.dot(normal).min(0.0);
+}
+
+/// Number of collisions processed in a single step before movement is cut short
+///
+/// This bounds the runtime of a step. If the character encounters excessively complex geometry, it is
+/// possible to hit this limit, in which case further movement processing is delayed until the next
+/// time step.
+const MAX_COLLISION_ITERATIONS: u32 = 6;
+
+/// Furthest a character overlapping terrain at the start of a step is pushed to free it, in multiples
+/// of its radius
+const MAX_PUSH_OUT_RADII: f32 = 6.0;
+
+/// Updates the character's position based on the given average velocity while handling up to
+/// `max_collisions` collisions. Also updates the velocity and ground normal based on collisions that
+/// occur. Returns the number of collisions handled.
 fn apply_velocity(
     ctx: &CharacterControllerContext,
     expected_displacement: na::Vector3<f32>,
     position: &mut Position,
     velocity: &mut na::Vector3<f32>,
     ground_normal: &mut Option<na::UnitVector3<f32>>,
-) {
-    // To prevent an unbounded runtime, we only allow a limited number of collisions to be processed in
-    // a single step. If the character encounters excessively complex geometry, it is possible to hit this limit,
-    // in which case further movement processing is delayed until the next time step.
-    const MAX_COLLISION_ITERATIONS: u32 = 6;
-
+    max_collisions: u32,
+) -> u32 {
     let mut bounded_vectors = BoundedVectors::new(expected_displacement, Some(*velocity));
     let mut bounded_vectors_without_collisions = bounded_vectors.clone();
 
     let mut ground_collision_handled = false;
 
     let mut all_collisions_resolved = false;
-    for _ in 0..MAX_COLLISION_ITERATIONS {
-        let collision_result = check_collision(
-            &ctx.collision_context,
+    let mut collisions = 0;
+    // Where the character can safely be left if collision resolution is cut short
+    let mut last_clear = *position;
+    let mut last_normal = None;
+    for _ in 0..max_collisions {
+        let collision_result = cast(
+            ctx,
+            CastPurpose::Movement,
             position,
             bounded_vectors.displacement(),
         );
         position.local *= collision_result.displacement_transform;
+        if is_clear(
+            ctx.collision_context.graph,
+            position,
+            ctx.collision_context.radius,
+        ) {
+            last_clear = *position;
+        }
 
         if let Some(collision) = collision_result.collision {
+            collisions += 1;
+            last_normal = Some(collision.normal);
             // Update the expected displacement to represent a reduction in the remaining dt
             let displacement_reduction_factor = 1.0
                 - collision_result.displacement_vector.magnitude()
@@ -261,11 +842,25 @@ fn apply_velocity(
         }
     }
 
+    *velocity = *bounded_vectors.velocity().unwrap();
+
     if !all_collisions_resolved {
         warn!("A character entity processed too many collisions and collision resolution was cut short.");
+        ctx.tracer.record(|| TraceEvent::CutShort);
+        // The remaining displacement is dropped, so make sure what's left isn't inside the surface
+        // last struck or heading further into it
+        if !is_clear(
+            ctx.collision_context.graph,
+            position,
+            ctx.collision_context.radius,
+        ) {
+            *position = last_clear;
+        }
+        if let Some(normal) = last_normal {
+            remove_approach(velocity, &normal);
+        }
     }
-
-    *velocity = *bounded_vectors.velocity().unwrap();
+    collisions
 }
 
 /// Updates character information based on the results of a single collision
@@ -282,7 +877,7 @@ fn handle_collision(
     // push the character away from the wall in a perpendicular direction. If the character is on the ground,
     // we have extra logic: Using a temporary bound to ensure that slanted wall collisions do not lift the
     // character off the ground.
-    if is_ground(ctx, &collision.normal) {
+    if is_ground(ctx, &collision.normal, collision.material) {
         if !*ground_collision_handled {
             // Wall collisions can turn vertical momentum into unwanted horizontal momentum. This can
             // occur if the character jumps at the corner between the ground and a slanted wall. If the wall
@@ -295,30 +890,124 @@ fn handle_collision(
             // afterwards, there is no more unexpected vertical momentum.
             let old_bounded_vectors =
                 replace(bounded_vectors, bounded_vectors_without_collisions.clone());
-            bounded_vectors.add_temp_bound(VectorBound::new(collision.normal, ctx.up, false));
-            bounded_vectors.add_bound(VectorBound::new(collision.normal, ctx.up, true));
+            ctx.tracer.record(|| TraceEvent::Rewind {
+                displacement: *bounded_vectors.displacement(),
+                velocity: bounded_vectors.velocity().copied(),
+            });
+            add_bound(
+                ctx,
+                bounded_vectors,
+                VectorBound::new(collision.normal, ctx.up, false),
+                true,
+            );
+            add_bound(
+                ctx,
+                bounded_vectors,
+                VectorBound::new(collision.normal, ctx.up, true),
+                false,
+            );
             for bound in old_bounded_vectors.bounds() {
-                bounded_vectors.add_bound(bound.clone());
+                add_bound(ctx, bounded_vectors, bound.clone(), false);
             }
-            bounded_vectors.clear_temp_bounds();
+            clear_temp_bounds(ctx, bounded_vectors);
 
             *ground_collision_handled = true;
         } else {
-            bounded_vectors.add_temp_bound(VectorBound::new(collision.normal, ctx.up, false));
-            bounded_vectors.add_bound(VectorBound::new(collision.normal, ctx.up, true));
-            bounded_vectors.clear_temp_bounds();
+            add_bound(
+                ctx,
+                bounded_vectors,
+                VectorBound::new(collision.normal, ctx.up, false),
+                true,
+            );
+            add_bound(
+                ctx,
+                bounded_vectors,
+                VectorBound::new(collision.normal, ctx.up, true),
+                false,
+            );
+            clear_temp_bounds(ctx, bounded_vectors);
         }
 
         *ground_normal = Some(collision.normal);
     } else {
         if let Some(ground_normal) = ground_normal {
-            bounded_vectors.add_temp_bound(VectorBound::new(*ground_normal, ctx.up, false));
+            add_bound(
+                ctx,
+                bounded_vectors,
+                VectorBound::new(*ground_normal, ctx.up, false),
+                true,
+            );
         }
-        bounded_vectors.add_bound(VectorBound::new(collision.normal, collision.normal, true));
-        bounded_vectors.clear_temp_bounds();
+        add_bound(
+            ctx,
+            bounded_vectors,
+            VectorBound::new(collision.normal, collision.normal, true),
+            false,
+        );
+        clear_temp_bounds(ctx, bounded_vectors);
     }
 }
 
+/// Checks for collisions as the character moves by `displacement`, recording the result
+fn cast(
+    ctx: &CharacterControllerContext,
+    purpose: CastPurpose,
+    position: &Position,
+    displacement: &na::Vector3<f32>,
+) -> CollisionCheckingResult {
+    let result = check_collision(&ctx.collision_context, position, displacement);
+    if result.out_of_bounds {
+        ctx.held_at_frontier.set(true);
+    }
+    ctx.tracer.record(|| TraceEvent::Cast {
+        purpose,
+        displacement: *displacement,
+        distance: result.displacement_vector.norm(),
+        hit: result.collision.as_ref().map(|collision| CastHit {
+            normal: collision.normal.into_inner(),
+            chunk: collision.chunk,
+        }),
+    });
+    result
+}
+
+/// Constrains `bounded_vectors` with `bound`, until temporary bounds are next cleared if
+/// `temporary`, recording the effect
+fn add_bound(
+    ctx: &CharacterControllerContext,
+    bounded_vectors: &mut BoundedVectors,
+    bound: VectorBound,
+    temporary: bool,
+) {
+    let before = ctx.tracer.is_enabled().then(|| {
+        (
+            bound.traced(),
+            *bounded_vectors.displacement(),
+            bounded_vectors.velocity().copied(),
+        )
+    });
+    if temporary {
+        bounded_vectors.add_temp_bound(bound);
+    } else {
+        bounded_vectors.add_bound(bound);
+    }
+    if let Some((bound, displacement_before, velocity_before)) = before {
+        ctx.tracer.record(|| TraceEvent::Bound {
+            bound,
+            temporary,
+            displacement_before,
+            displacement_after: *bounded_vectors.displacement(),
+            velocity_before,
+            velocity_after: bounded_vectors.velocity().copied(),
+        });
+    }
+}
+
+fn clear_temp_bounds(ctx: &CharacterControllerContext, bounded_vectors: &mut BoundedVectors) {
+    bounded_vectors.clear_temp_bounds();
+    ctx.tracer.record(|| TraceEvent::TempBoundsCleared);
+}
+
 /// Contains all information about a character that the character controller doesn't change during
 /// one of its simulation steps
 struct CharacterControllerContext<'a> {
@@ -328,4 +1017,1559 @@ struct CharacterControllerContext<'a> {
     dt_seconds: f32,
     movement_input: na::Vector3<f32>,
     jump_input: bool,
+    /// Applied in this substep only
+    impulse: Option<ExternalImpulse>,
+    constraint: Option<PositionConstraint>,
+    tracer: Tracer<'a>,
+    /// Set once any cast reaches parts of the graph that haven't been generated yet
+    held_at_frontier: Cell<bool>,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        coords::{locate_voxel, voxel_center_position},
+        dodeca::Vertex,
+        graph::NodeId,
+        node::{
+            populate_fresh_nodes, Chunk, ChunkId, CoordAxis, CoordDirection, Coords, VoxelData,
+        },
+        proto::{ImpulseMode, MovementInput},
+        sim_config::CharacterConfigRaw,
+        test_graphs::{elevation, empty_graph, graph_with_floor, graph_with_terrain, GRAPH_RADIUS},
+        traversal::{ensure_nearby, nearby_nodes},
+        world::{Material, Shape},
+        SimConfigRaw,
+    };
+    use approx::*;
+
+    /// Transform from a node's coordinates to the root node's
+    fn root_transform(graph: &Graph, node: NodeId) -> na::Matrix4<f32> {
+        // Test graphs are small, so every node can be visited
+        let (_, transform) = nearby_nodes(graph, &Position::origin(), f64::INFINITY)
+            .into_iter()
+            .find(|&(x, _)| x == node)
+            .unwrap();
+        transform
+    }
+
+    /// A character's location in the root node's coordinates
+    fn root_relative(graph: &Graph, position: &Position) -> na::Vector4<f32> {
+        root_transform(graph, position.node) * position.local * math::origin()
+    }
+
+    fn character_elevation(graph: &Graph, position: &Position) -> f32 {
+        elevation(graph, &root_relative(graph, position))
+    }
+
+    fn walking_input() -> CharacterInput {
+        CharacterInput {
+            movement: MovementInput::new(na::Vector3::new(0.6, 0.0, -0.3)),
+            jump: false,
+            no_clip: false,
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        }
+    }
+
+    #[test]
+    fn split_step_matches_whole_step() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw {
+            max_substep_seconds: Some(0.05),
+            ..Default::default()
+        });
+        let m = cfg.meters_to_absolute;
+        let graph = graph_with_floor(&cfg, -3.0 * m..-1.0 * m);
+        let input = walking_input();
+        let dt = 0.1;
+
+        let mut whole = (Position::origin(), na::Vector3::zeros(), false);
+        let mut split = whole;
+        for _ in 0..10 {
+            run_character_step(
+                &cfg,
+                &graph,
+                &mut whole.0,
+                &mut whole.1,
+                &mut whole.2,
+                &input,
+                &mut 0.0,
+                dt,
+                None,
+            );
+            for _ in 0..2 {
+                run_character_step(
+                    &cfg,
+                    &graph,
+                    &mut split.0,
+                    &mut split.1,
+                    &mut split.2,
+                    &input,
+                    &mut 0.0,
+                    dt / 2.0,
+                    None,
+                );
+            }
+            assert_eq!(whole.0.node, split.0.node);
+            assert_eq!(whole.0.local, split.0.local);
+            assert_eq!(whole.1, split.1);
+            assert_eq!(whole.2, split.2);
+        }
+        // Make sure the scenario actually exercised the floor
+        assert!(whole.2);
+    }
+
+    #[test]
+    fn large_step_lands_on_thin_floor() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let floor = -2.5 * m..-1.0 * m;
+        let graph = graph_with_floor(&cfg, floor.clone());
+        let input = walking_input();
+
+        let mut position = Position::origin();
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        for _ in 0..2 {
+            run_character_step(
+                &cfg,
+                &graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &input,
+                &mut 0.0,
+                1.0,
+                None,
+            );
+            assert!(character_elevation(&graph, &position) > floor.end);
+        }
+        assert!(on_ground);
+    }
+
+    #[test]
+    fn view_is_continuous_across_node_transition() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let graph = graph_with_floor(&cfg, 0.0..0.0);
+        // An arbitrary view orientation, held fixed relative to the character
+        let orientation = na::UnitQuaternion::from_euler_angles(0.1, 0.7, 0.0);
+        let look = orientation * -na::Vector3::z();
+        let input = CharacterInput {
+            movement: MovementInput::new(look),
+            no_clip: true,
+            ..idle_input()
+        };
+        let dt = 0.1;
+        let step_length = cfg.character.no_clip_movement_speed * dt;
+
+        let mut position = Position::origin();
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        let mut transitions = 0;
+        for _ in 0..30 {
+            let view = |position: &Position| {
+                root_transform(&graph, position.node)
+                    * position.local
+                    * orientation.to_homogeneous()
+            };
+            let previous = position;
+            let output = run_character_step(
+                &cfg,
+                &graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &input,
+                &mut 0.0,
+                dt,
+                None,
+            );
+            assert_eq!(
+                output.node_transition.is_some(),
+                position.node != previous.node
+            );
+            transitions += usize::from(output.node_transition.is_some());
+
+            // Relative to the previous view, the new view should be translated straight ahead
+            // without turning, whether or not the character changed nodes
+            let relative = math::mtranspose(&view(&previous)) * view(&position);
+            let expected = math::translate_along(&(-na::Vector3::z() * step_length));
+            assert!(
+                (relative - expected).abs().max() < 1e-4,
+                "{relative} != {expected}"
+            );
+        }
+        assert!(transitions > 0);
+    }
+
+    #[test]
+    fn ungenerated_chunks_hold_character_at_frontier() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let input = walking_input();
+        let step = |graph: &Graph| {
+            let mut position = Position::origin();
+            let mut velocity = na::Vector3::zeros();
+            let mut on_ground = false;
+            let output = run_character_step(
+                &cfg,
+                graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &input,
+                &mut 0.0,
+                0.1,
+                None,
+            );
+            (output.held_at_frontier, position)
+        };
+
+        // Nodes whose chunks were never generated can't be moved through
+        let mut graph = Graph::new(cfg.chunk_size);
+        ensure_nearby(&mut graph, &Position::origin(), GRAPH_RADIUS);
+        populate_fresh_nodes(&mut graph);
+        let (held, position) = step(&graph);
+        assert!(held);
+        assert_eq!(position.local, Position::origin().local);
+
+        let graph = graph_with_floor(
+            &cfg,
+            -3.0 * cfg.meters_to_absolute..-1.0 * cfg.meters_to_absolute,
+        );
+        assert!(!step(&graph).0);
+    }
+
+    #[test]
+    fn tiny_step_is_skipped() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let graph = graph_with_floor(&cfg, 0.0..0.0);
+        let mut position = Position::origin();
+        let mut velocity = na::Vector3::new(0.1, 0.0, 0.0);
+        let mut on_ground = false;
+        let mut carried = 0.0;
+        run_character_step(
+            &cfg,
+            &graph,
+            &mut position,
+            &mut velocity,
+            &mut on_ground,
+            &walking_input(),
+            &mut carried,
+            cfg.min_step_seconds * 0.5,
+            None,
+        );
+        assert_eq!(position.local, na::Matrix4::identity());
+        assert_eq!(velocity, na::Vector3::new(0.1, 0.0, 0.0));
+        assert_eq!(carried, cfg.min_step_seconds * 0.5);
+    }
+
+    #[test]
+    fn tiny_steps_add_up_to_whole_step() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw {
+            min_step_seconds: Some(0.1),
+            ..Default::default()
+        });
+        let m = cfg.meters_to_absolute;
+        let graph = graph_with_floor(&cfg, -3.0 * m..-1.0 * m);
+        let input = walking_input();
+        // Powers of two, so that the pieces sum exactly
+        let dt = 0.125;
+
+        let mut whole = (Position::origin(), na::Vector3::zeros(), false);
+        run_character_step(
+            &cfg,
+            &graph,
+            &mut whole.0,
+            &mut whole.1,
+            &mut whole.2,
+            &input,
+            &mut 0.0,
+            dt,
+            None,
+        );
+
+        let mut split = (Position::origin(), na::Vector3::zeros(), false);
+        let mut carried = 0.0;
+        for _ in 0..4 {
+            run_character_step(
+                &cfg,
+                &graph,
+                &mut split.0,
+                &mut split.1,
+                &mut split.2,
+                &input,
+                &mut carried,
+                dt / 4.0,
+                None,
+            );
+        }
+        assert_eq!(carried, 0.0);
+        assert_ne!(split.0.local, Position::origin().local);
+        assert_eq!(whole.0.node, split.0.node);
+        assert_eq!(whole.0.local, split.0.local);
+        assert_eq!(whole.1, split.1);
+        assert_eq!(whole.2, split.2);
+    }
+
+    #[test]
+    fn landing_reports_impact_into_ground() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let graph = graph_with_floor(&cfg, -3.0 * m..-1.0 * m);
+        let up = graph.get_relative_up(&Position::origin()).unwrap();
+        let across = (na::Vector3::x() - up.into_inner() * up.x).normalize();
+        let dt = 0.1;
+
+        let mut position = Position::origin();
+        // Thrown sideways, which mustn't count towards the impact
+        let mut velocity = across * 5.0 * m;
+        let mut on_ground = false;
+        let mut landings = 0;
+        for _ in 0..20 {
+            // Velocity at the end of the step, had the character not hit anything
+            let up = graph.get_relative_up(&position).unwrap();
+            let approach = velocity * (-cfg.character.air_resistance * dt).exp()
+                - up.into_inner() * cfg.character.gravity_acceleration * dt;
+            let output = run_character_step(
+                &cfg,
+                &graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &idle_input(),
+                &mut 0.0,
+                dt,
+                None,
+            );
+            let Some(landing) = output.landing else {
+                continue;
+            };
+            landings += 1;
+            assert!(on_ground);
+            assert!(landing.normal.dot(&up) > 0.0);
+            approx::assert_relative_eq!(
+                landing.impact_speed,
+                -approach.dot(&landing.normal),
+                max_relative = 1e-4
+            );
+            assert!(landing.impact_speed < approach.norm());
+            assert_eq!(landing.airborne_seconds, dt);
+        }
+        // Standing on the ground afterwards isn't landing again
+        assert_eq!(landings, 1);
+    }
+
+    #[test]
+    fn slopes_soften_impact() {
+        let falling = -na::Vector3::y() * 10.0;
+        let flat = na::Vector3::y_axis();
+        assert_eq!(impact_speed(&falling, &flat), 10.0);
+        // Landing on a 30 degree slope
+        let slope = na::UnitVector3::new_normalize(na::Vector3::new(
+            0.0,
+            30f32.to_radians().cos(),
+            30f32.to_radians().sin(),
+        ));
+        approx::assert_relative_eq!(
+            impact_speed(&falling, &slope),
+            10.0 * 30f32.to_radians().cos()
+        );
+        // Sliding along it, or moving away from it, isn't an impact at all
+        let along = slope.cross(&na::Vector3::x()) * 10.0;
+        assert_eq!(impact_speed(&along, &slope), 0.0);
+        assert_eq!(impact_speed(&-falling, &slope), 0.0);
+    }
+
+    fn idle_input() -> CharacterInput {
+        CharacterInput {
+            movement: MovementInput::zero(),
+            ..walking_input()
+        }
+    }
+
+    #[test]
+    fn characters_walking_into_each_other_separate() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let floor = -3.0 * m..-1.0 * m;
+        let graph = graph_with_floor(&cfg, floor.clone());
+        let up = graph.get_relative_up(&Position::origin()).unwrap();
+        let across = (na::Vector3::x() - up.into_inner() * up.x).normalize();
+
+        let mut positions = [
+            Position {
+                node: NodeId::ROOT,
+                local: math::translate_along(&(-across * m)),
+            },
+            Position {
+                node: NodeId::ROOT,
+                local: math::translate_along(&(across * m)),
+            },
+        ];
+        let mut states = [(na::Vector3::zeros(), false); 2];
+        let mut overlapped = false;
+        for step in 0..30 {
+            for (i, (position, (velocity, on_ground))) in
+                positions.iter_mut().zip(&mut states).enumerate()
+            {
+                // Walk towards each other, then stand still
+                let input = if step < 15 {
+                    CharacterInput {
+                        movement: MovementInput::new(across * if i == 0 { 1.0 } else { -1.0 }),
+                        ..idle_input()
+                    }
+                } else {
+                    idle_input()
+                };
+                run_character_step(
+                    &cfg, &graph, position, velocity, on_ground, &input, &mut 0.0, 0.1, None,
+                );
+            }
+            let stats = separate_characters(&cfg, &graph, &mut positions, 0.1);
+            assert_eq!(stats.pairs_tested, 1);
+            overlapped |= stats.pairs_overlapping > 0;
+        }
+        assert!(overlapped);
+
+        let distance = math::distance(
+            &root_relative(&graph, &positions[0]),
+            &root_relative(&graph, &positions[1]),
+        );
+        assert!(distance >= 2.0 * cfg.character.character_radius * 0.999);
+        for position in &positions {
+            assert!(character_elevation(&graph, position) > floor.end);
+        }
+    }
+
+    #[test]
+    fn separation_never_pushes_into_floor() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let radius = cfg.character.character_radius;
+        let graph = graph_with_floor(&cfg, -3.0 * m..-1.0 * m);
+
+        // Let a character come to rest on the floor
+        let mut below = Position::origin();
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        for _ in 0..20 {
+            run_character_step(
+                &cfg,
+                &graph,
+                &mut below,
+                &mut velocity,
+                &mut on_ground,
+                &idle_input(),
+                &mut 0.0,
+                0.1,
+                None,
+            );
+        }
+        assert!(on_ground);
+        let resting_elevation = character_elevation(&graph, &below);
+
+        // Drop another character halfway into it from above, so that separation pushes the first
+        // character straight down
+        let up = graph.get_relative_up(&below).unwrap();
+        let above = Position {
+            node: below.node,
+            local: below.local * math::translate_along(&(up.into_inner() * radius)),
+        };
+        let above_elevation = character_elevation(&graph, &above);
+        let mut positions = [below, above];
+        let stats = separate_characters(&cfg, &graph, &mut positions, 0.1);
+        assert_eq!(stats.pairs_overlapping, 1);
+        assert!(character_elevation(&graph, &positions[0]) > resting_elevation - 1e-3 * m);
+        let raised = character_elevation(&graph, &positions[1]) - above_elevation;
+        assert!((raised - 0.5 * radius).abs() < 1e-2 * m);
+    }
+
+    #[test]
+    fn distant_characters_are_not_compared() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut graph = Graph::new(cfg.chunk_size);
+        ensure_nearby(&mut graph, &Position::origin(), 4.5);
+        populate_fresh_nodes(&mut graph);
+        let (far_node, _) = nearby_nodes(&graph, &Position::origin(), 4.5)
+            .into_iter()
+            .find(|(_, transform)| {
+                math::distance(&math::origin(), &(transform * math::origin())) > 4.0
+            })
+            .unwrap();
+
+        let mut positions = [
+            Position::origin(),
+            Position {
+                node: far_node,
+                local: na::Matrix4::identity(),
+            },
+        ];
+        let stats = separate_characters(&cfg, &graph, &mut positions, 0.1);
+        assert_eq!(stats, SeparationStats::default());
+        assert_eq!(positions[0].local, na::Matrix4::identity());
+    }
+
+    #[test]
+    fn clipping_inside_floor_pushes_out() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let radius = cfg.character.character_radius;
+        let floor = -1.0 * m..0.5 * m;
+        let graph = graph_with_floor(&cfg, floor.clone());
+
+        // As if no-clip had just been turned off with the character inside the floor
+        let mut position = Position::origin();
+        assert!(!is_clear(&graph, &position, radius));
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        for _ in 0..10 {
+            run_character_step(
+                &cfg,
+                &graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &idle_input(),
+                &mut 0.0,
+                0.1,
+                None,
+            );
+            assert!(is_clear(&graph, &position, radius));
+            // Out the nearer side, not through to the far one
+            assert!(character_elevation(&graph, &position) > floor.end);
+        }
+        assert!(on_ground);
+    }
+
+    #[test]
+    fn collision_limit_never_leaves_character_embedded() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let floor = -3.0 * m..-1.0 * m;
+        let graph = graph_with_floor(&cfg, floor.clone());
+        let mut position = Position::origin();
+        let up = graph.get_relative_up(&position).unwrap();
+        let ctx = CharacterControllerContext {
+            cfg: &cfg.character,
+            collision_context: CollisionContext {
+                graph: &graph,
+                radius: cfg.character.character_radius,
+            },
+            up,
+            dt_seconds: 1.0,
+            movement_input: na::Vector3::zeros(),
+            jump_input: false,
+            impulse: None,
+            constraint: None,
+            tracer: Tracer::new(None),
+            held_at_frontier: Cell::new(false),
+        };
+
+        // Diving steeply at the floor at the speed cap, so there's plenty of sliding left after
+        // the only collision allowed
+        let horizontal = up.cross(&na::Vector3::x()).normalize();
+        let mut velocity =
+            (horizontal - up.into_inner() * 3.0).normalize() * cfg.character.speed_cap;
+        let mut ground_normal = None;
+        apply_velocity(
+            &ctx,
+            velocity * ctx.dt_seconds,
+            &mut position,
+            &mut velocity,
+            &mut ground_normal,
+            1,
+        );
+        assert!(ground_normal.is_some());
+        assert!(is_clear(&graph, &position, ctx.collision_context.radius));
+        assert!(character_elevation(&graph, &position) > floor.end);
+        assert!(velocity.dot(&ground_normal.unwrap()) > -1e-3 * m);
+    }
+
+    /// The chunk of the root node and the axis within it that most nearly points up, and which way
+    /// along the axis is up
+    fn most_vertical_axis(graph: &Graph) -> (Vertex, CoordAxis, CoordDirection) {
+        let dimension = graph.layout().dimension();
+        let dual_to_grid = f64::from(graph.layout().dual_to_grid_factor());
+        let to_root = |vertex: Vertex, grid: na::Vector3<f64>| {
+            math::lorentz_normalize(&(vertex.dual_to_node_f64() * (grid / dual_to_grid).push(1.0)))
+                .cast::<f32>()
+        };
+        let center = na::Vector3::repeat(f64::from(dimension) * 0.5);
+        let (vertex, up_axis, rise) = Vertex::iter()
+            .flat_map(|vertex| CoordAxis::iter().map(move |axis| (vertex, axis)))
+            .map(|(vertex, axis)| {
+                let mut above = center;
+                above[axis as usize] += 1.0;
+                let rise = elevation(graph, &to_root(vertex, above))
+                    - elevation(graph, &to_root(vertex, center));
+                (vertex, axis, rise)
+            })
+            .max_by(|a, b| a.2.abs().total_cmp(&b.2.abs()))
+            .unwrap();
+        let up = if rise > 0.0 {
+            CoordDirection::Plus
+        } else {
+            CoordDirection::Minus
+        };
+        (vertex, up_axis, up)
+    }
+
+    /// Walk a character towards a ledge of bottom slabs within a single chunk, whose voxel layers
+    /// are nearly level, and return the coordinates along the walking axis of the voxel it ends up
+    /// in, and whether it ended up in the upper half of the ground layer
+    fn walk_towards_slabs(cfg: &SimConfig) -> (u8, bool) {
+        let dimension = cfg.chunk_size;
+        let mut graph = empty_graph(cfg);
+        let (vertex, up_axis, up) = most_vertical_axis(&graph);
+        let down = match up {
+            CoordDirection::Plus => CoordDirection::Minus,
+            CoordDirection::Minus => CoordDirection::Plus,
+        };
+        let height = |coords: Coords| match up {
+            CoordDirection::Plus => coords[up_axis],
+            CoordDirection::Minus => dimension - 1 - coords[up_axis],
+        };
+        let forward_axis = up_axis.other_axes()[0];
+
+        // Solid ground up to layer 4, and a ledge of slabs on layer 4 partway along
+        let chunk = ChunkId::new(NodeId::ROOT, vertex);
+        let mut voxels = VoxelData::Solid(Material::Void);
+        for z in 0..dimension {
+            for y in 0..dimension {
+                for x in 0..dimension {
+                    let coords = Coords([x, y, z]);
+                    let index = coords.to_index(dimension);
+                    if height(coords) < 4 {
+                        voxels.data_mut(dimension)[index] = Material::Dirt;
+                    } else if height(coords) == 4 && coords[forward_axis] >= 7 {
+                        voxels.data_mut(dimension)[index] = Material::Dirt;
+                        voxels.set_shape(dimension, index, Shape::slab(up_axis, down));
+                    }
+                }
+            }
+        }
+        graph[chunk] = Chunk::Populated {
+            voxels,
+            modified: false,
+            generation: 0,
+            surface: None,
+            old_surface: None,
+            occupancy: Default::default(),
+        };
+
+        let mut start = Coords([dimension / 2; 3]);
+        start[up_axis] = match up {
+            CoordDirection::Plus => 4,
+            CoordDirection::Minus => dimension - 5,
+        };
+        start[forward_axis] = 2;
+        let mut ahead = start;
+        ahead[forward_axis] += 1;
+        let mut position = voxel_center_position(graph.layout(), chunk, start);
+        let forward = (math::mtranspose(&position.local)
+            * voxel_center_position(graph.layout(), chunk, ahead).local
+            * math::origin())
+        .xyz();
+        let up_direction = graph.get_relative_up(&position).unwrap();
+        let input = CharacterInput {
+            movement: MovementInput::new(
+                (forward - up_direction.into_inner() * up_direction.dot(&forward)).normalize(),
+            ),
+            ..idle_input()
+        };
+
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        for _ in 0..20 {
+            run_character_step(
+                cfg,
+                &graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &input,
+                &mut 0.0,
+                0.1,
+                None,
+            );
+        }
+        assert!(on_ground);
+
+        let (located, coords, offset) = locate_voxel(&graph, graph.layout(), &position).unwrap();
+        assert_eq!(located, chunk);
+        let raised = height(coords) == 4
+            && match up {
+                CoordDirection::Plus => offset[up_axis as usize] > 0.5,
+                CoordDirection::Minus => offset[up_axis as usize] < 0.5,
+            };
+        (coords[forward_axis], raised)
+    }
+
+    #[test]
+    fn walking_climbs_onto_slabs() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let (forward, raised) = walk_towards_slabs(&cfg);
+        assert!(forward >= 7);
+        assert!(raised);
+
+        // Without the assist, the ledge stops the character
+        let cfg = SimConfig::from_raw(&SimConfigRaw {
+            character: CharacterConfigRaw {
+                max_step_height: Some(0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let (forward, raised) = walk_towards_slabs(&cfg);
+        assert!(forward < 7);
+        assert!(!raised);
+    }
+
+    /// Voxel along the forward axis of a `LadderScene` at which its wall of ladder begins
+    const LADDER_WALL: u8 = 7;
+    /// Highest layer of a `LadderScene`'s wall of ladder, whose ground is below layer 3
+    const LADDER_TOP: u8 = 8;
+
+    /// A chunk of the root node whose voxel layers are nearly level, with solid ground below layer
+    /// 3 and a block of ladder from `LADDER_WALL` on along the forward axis rising to `LADDER_TOP`,
+    /// so that the block's near face is a wall of ladder
+    struct LadderScene {
+        graph: Graph,
+        chunk: ChunkId,
+        dimension: u8,
+        up_axis: CoordAxis,
+        up: CoordDirection,
+        forward_axis: CoordAxis,
+    }
+
+    impl LadderScene {
+        fn new(cfg: &SimConfig) -> Self {
+            let graph = empty_graph(cfg);
+            let (vertex, up_axis, up) = most_vertical_axis(&graph);
+            let mut scene = Self {
+                graph,
+                chunk: ChunkId::new(NodeId::ROOT, vertex),
+                dimension: cfg.chunk_size,
+                up_axis,
+                up,
+                forward_axis: up_axis.other_axes()[0],
+            };
+            let dimension = scene.dimension;
+            let mut voxels = VoxelData::Solid(Material::Void);
+            for z in 0..dimension {
+                for y in 0..dimension {
+                    for x in 0..dimension {
+                        let coords = Coords([x, y, z]);
+                        let height = scene.height(coords);
+                        voxels.data_mut(dimension)[coords.to_index(dimension)] = if height < 3 {
+                            Material::Dirt
+                        } else if height <= LADDER_TOP && coords[scene.forward_axis] >= LADDER_WALL
+                        {
+                            Material::Ladder
+                        } else {
+                            Material::Void
+                        };
+                    }
+                }
+            }
+            scene.graph[scene.chunk] = Chunk::Populated {
+                voxels,
+                modified: false,
+                generation: 0,
+                surface: None,
+                old_surface: None,
+                occupancy: Default::default(),
+            };
+            scene
+        }
+
+        /// Layer of the voxel at `coords`, counting up from the bottom of the chunk
+        fn height(&self, coords: Coords) -> u8 {
+            match self.up {
+                CoordDirection::Plus => coords[self.up_axis],
+                CoordDirection::Minus => self.dimension - 1 - coords[self.up_axis],
+            }
+        }
+
+        /// The center of the voxel `forward` along the forward axis on layer `height`, midway
+        /// across the chunk
+        fn position(&self, forward: u8, height: u8) -> Position {
+            let mut coords = Coords([self.dimension / 2; 3]);
+            coords[self.forward_axis] = forward;
+            coords[self.up_axis] = match self.up {
+                CoordDirection::Plus => height,
+                CoordDirection::Minus => self.dimension - 1 - height,
+            };
+            voxel_center_position(self.graph.layout(), self.chunk, coords)
+        }
+
+        /// Where along the forward axis, and on which layer, a character at `position` is
+        fn locate(&self, position: &Position) -> (u8, u8) {
+            let (chunk, coords, _) =
+                locate_voxel(&self.graph, self.graph.layout(), position).unwrap();
+            assert_eq!(chunk, self.chunk);
+            (coords[self.forward_axis], self.height(coords))
+        }
+
+        /// Horizontal direction from a character at `position` straight towards the wall
+        fn toward_wall(&self, position: &Position) -> na::Vector3<f32> {
+            let (_, mut coords, _) =
+                locate_voxel(&self.graph, self.graph.layout(), position).unwrap();
+            coords[self.forward_axis] = LADDER_WALL;
+            let ahead = (math::mtranspose(&position.local)
+                * voxel_center_position(self.graph.layout(), self.chunk, coords).local
+                * math::origin())
+            .xyz();
+            let up = self.graph.get_relative_up(position).unwrap();
+            (ahead - up.into_inner() * up.dot(&ahead)).normalize()
+        }
+
+        fn step(
+            &self,
+            cfg: &SimConfig,
+            position: &mut Position,
+            velocity: &mut na::Vector3<f32>,
+            on_ground: &mut bool,
+            input: &CharacterInput,
+        ) {
+            run_character_step(
+                cfg,
+                &self.graph,
+                position,
+                velocity,
+                on_ground,
+                input,
+                &mut 0.0,
+                LADDER_DT,
+                None,
+            );
+        }
+    }
+
+    /// Length of each step taken in a `LadderScene`, short enough to measure climbing speeds by
+    const LADDER_DT: f32 = 0.05;
+
+    fn heading(direction: na::Vector3<f32>) -> CharacterInput {
+        CharacterInput {
+            movement: MovementInput::new(direction),
+            ..idle_input()
+        }
+    }
+
+    #[test]
+    fn ladders_climbed_onto_ledge() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let scene = LadderScene::new(&cfg);
+        let mut position = scene.position(LADDER_WALL - 3, 3);
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        settle(
+            &cfg,
+            &scene.graph,
+            &mut position,
+            &mut velocity,
+            &mut on_ground,
+        );
+        let start = character_elevation(&scene.graph, &position);
+
+        let mut elevations = vec![start];
+        for _ in 0..100 {
+            let input = heading(scene.toward_wall(&position));
+            scene.step(&cfg, &mut position, &mut velocity, &mut on_ground, &input);
+            elevations.push(character_elevation(&scene.graph, &position));
+            if on_ground && scene.locate(&position).1 > LADDER_TOP {
+                break;
+            }
+        }
+
+        // Up the wall at the configured speed
+        let rates = elevations
+            .windows(2)
+            .filter(|w| {
+                w.iter()
+                    .all(|&e| (start + 1.0 * m..start + 4.0 * m).contains(&e))
+            })
+            .map(|w| (w[1] - w[0]) / LADDER_DT)
+            .collect::<Vec<_>>();
+        assert!(rates.len() >= 10, "{rates:?}");
+        for rate in rates {
+            assert_relative_eq!(rate, cfg.character.climb_speed, max_relative = 0.05);
+        }
+
+        // And over the top onto the ledge
+        assert!(on_ground);
+        let (forward, height) = scene.locate(&position);
+        assert!(forward >= LADDER_WALL);
+        assert_eq!(height, LADDER_TOP + 1);
+    }
+
+    #[test]
+    fn ladders_climbed_down_and_held() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let scene = LadderScene::new(&cfg);
+        let ground = {
+            let mut position = scene.position(LADDER_WALL - 1, 3);
+            let mut velocity = na::Vector3::zeros();
+            let mut on_ground = false;
+            settle(
+                &cfg,
+                &scene.graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+            );
+            character_elevation(&scene.graph, &position)
+        };
+        let mut position = scene.position(LADDER_WALL - 1, LADDER_TOP);
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        let top = character_elevation(&scene.graph, &position);
+
+        // Backing away from the wall climbs down it
+        let mut elevations = vec![top];
+        while *elevations.last().unwrap() > ground + 2.5 * m {
+            let input = heading(-scene.toward_wall(&position));
+            scene.step(&cfg, &mut position, &mut velocity, &mut on_ground, &input);
+            elevations.push(character_elevation(&scene.graph, &position));
+            assert!(elevations.len() < 100, "never climbed down");
+        }
+        let rates = elevations
+            .windows(2)
+            .skip(1)
+            .map(|w| (w[1] - w[0]) / LADDER_DT)
+            .collect::<Vec<_>>();
+        assert!(rates.len() >= 10, "{rates:?}");
+        for rate in rates {
+            assert_relative_eq!(rate, -cfg.character.climb_speed, max_relative = 0.05);
+        }
+
+        // Letting go of the controls holds on where the character is
+        scene.step(
+            &cfg,
+            &mut position,
+            &mut velocity,
+            &mut on_ground,
+            &idle_input(),
+        );
+        let held = character_elevation(&scene.graph, &position);
+        for _ in 0..20 {
+            scene.step(
+                &cfg,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &idle_input(),
+            );
+        }
+        assert!(!on_ground);
+        assert_abs_diff_eq!(
+            character_elevation(&scene.graph, &position),
+            held,
+            epsilon = 0.01 * m
+        );
+        assert_eq!(scene.locate(&position).0, LADDER_WALL - 1);
+
+        // Until reaching the ground
+        for _ in 0..40 {
+            let input = heading(-scene.toward_wall(&position));
+            scene.step(&cfg, &mut position, &mut velocity, &mut on_ground, &input);
+            if on_ground {
+                break;
+            }
+        }
+        assert!(on_ground);
+        assert_eq!(scene.locate(&position).1, 3);
+    }
+
+    #[test]
+    fn jumping_off_ladders() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let scene = LadderScene::new(&cfg);
+        let mut position = scene.position(LADDER_WALL - 1, 6);
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        for _ in 0..5 {
+            let input = heading(scene.toward_wall(&position));
+            scene.step(&cfg, &mut position, &mut velocity, &mut on_ground, &input);
+        }
+
+        // Away from the wall, and up
+        let away = -scene.toward_wall(&position);
+        let up = scene.graph.get_relative_up(&position).unwrap();
+        let jump = CharacterInput {
+            jump: true,
+            ..idle_input()
+        };
+        scene.step(&cfg, &mut position, &mut velocity, &mut on_ground, &jump);
+        let jump_speed = cfg.character.jump_speed;
+        assert!(velocity.dot(&away) > 0.95 * jump_speed, "{velocity}");
+        assert!(up.dot(&velocity) > 0.8 * jump_speed, "{velocity}");
+
+        // Without taking hold of the wall again
+        for _ in 0..10 {
+            scene.step(
+                &cfg,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &idle_input(),
+            );
+        }
+        assert!(scene.locate(&position).0 <= LADDER_WALL - 3);
+    }
+
+    #[test]
+    fn running_past_ladders() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let scene = LadderScene::new(&cfg);
+        let mut position = scene.position(LADDER_WALL - 1, 3);
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        settle(
+            &cfg,
+            &scene.graph,
+            &mut position,
+            &mut velocity,
+            &mut on_ground,
+        );
+        let start = character_elevation(&scene.graph, &position);
+
+        // Glancing off the wall at 30 degrees, too shallow to take hold
+        for i in 0..15 {
+            let toward = scene.toward_wall(&position);
+            let up = scene.graph.get_relative_up(&position).unwrap();
+            let along = up.cross(&toward);
+            let angle = 30f32.to_radians();
+            let input = heading(along * angle.cos() + toward * angle.sin());
+            scene.step(&cfg, &mut position, &mut velocity, &mut on_ground, &input);
+            assert!(on_ground, "left the ground on step {i}");
+            assert!(character_elevation(&scene.graph, &position) < start + 0.05 * m);
+        }
+    }
+
+    #[test]
+    fn trace_records_corner_collisions() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw {
+            character: CharacterConfigRaw {
+                gravity_acceleration: Some(0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let m = cfg.meters_to_absolute;
+        let dimension = cfg.chunk_size;
+        let mut graph = empty_graph(&cfg);
+        let (vertex, up_axis, _) = most_vertical_axis(&graph);
+        let [near_axis, far_axis] = up_axis.other_axes();
+
+        // Two walls meeting in a corner, one barely beyond the character and the other further
+        let chunk = ChunkId::new(NodeId::ROOT, vertex);
+        let mut voxels = VoxelData::Solid(Material::Void);
+        for z in 0..dimension {
+            for y in 0..dimension {
+                for x in 0..dimension {
+                    let coords = Coords([x, y, z]);
+                    if coords[near_axis] >= 8 || coords[far_axis] >= 8 {
+                        voxels.data_mut(dimension)[coords.to_index(dimension)] = Material::Dirt;
+                    }
+                }
+            }
+        }
+        graph[chunk] = Chunk::Populated {
+            voxels,
+            modified: false,
+            generation: 0,
+            surface: None,
+            old_surface: None,
+            occupancy: Default::default(),
+        };
+        let mut start = Coords([dimension / 2; 3]);
+        start[near_axis] = 7;
+        start[far_axis] = 6;
+        let mut position = voxel_center_position(graph.layout(), chunk, start);
+        let towards = |axis: CoordAxis| {
+            let mut ahead = start;
+            ahead[axis] += 1;
+            (math::mtranspose(&position.local)
+                * voxel_center_position(graph.layout(), chunk, ahead).local
+                * math::origin())
+            .xyz()
+            .normalize()
+        };
+        let (near, far) = (towards(near_axis), towards(far_axis));
+
+        // Thrown mostly towards the further wall, sliding along the nearer one to reach it
+        let mut velocity = (near * 10.0 + far * 20.0) * m;
+        let mut on_ground = false;
+        let mut trace = CollisionTrace::new(4);
+        run_character_step(
+            &cfg,
+            &graph,
+            &mut position,
+            &mut velocity,
+            &mut on_ground,
+            &idle_input(),
+            &mut 0.0,
+            0.1,
+            Some(&mut trace),
+        );
+        assert!(velocity.norm() < 1e-3 * m);
+
+        assert_eq!(trace.steps().len(), 1);
+        let step = trace.steps().next().unwrap();
+        assert_eq!(step.start.velocity, (near * 10.0 + far * 20.0) * m);
+        assert_eq!(step.end.velocity, velocity);
+        assert_eq!(step.end.position.local, position.local);
+        let hits = step
+            .events
+            .iter()
+            .filter_map(|event| match *event {
+                TraceEvent::Cast {
+                    purpose: CastPurpose::Movement,
+                    hit,
+                    ..
+                } => Some(hit),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(hits.len(), 3);
+        assert!(hits[..2].iter().all(|hit| hit.unwrap().chunk == chunk));
+        assert!(hits[2].is_none());
+        let bounds = step
+            .events
+            .iter()
+            .filter_map(|event| match *event {
+                TraceEvent::Bound {
+                    bound,
+                    temporary,
+                    velocity_after,
+                    ..
+                } => Some((bound, temporary, velocity_after)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(bounds.len(), 2);
+        // First the nearer wall, then the further one, each pushing straight back from the wall
+        for ((bound, temporary, _), wall) in bounds.iter().zip([near, far]) {
+            assert!(!temporary);
+            assert!(bound.front_facing);
+            assert_eq!(bound.normal, bound.projection_direction);
+            assert!(bound.normal.dot(&wall) < -0.9, "{}", bound.normal);
+        }
+        assert_eq!(bounds[1].2, Some(velocity));
+
+        // Traces survive being written out and read back
+        let json = serde_json::to_string(&trace.dump()).unwrap();
+        let dump = serde_json::from_str::<TraceDump>(&json).unwrap();
+        assert_eq!(serde_json::to_string(&dump).unwrap(), json);
+    }
+
+    /// A horizontal direction at the origin
+    fn across(graph: &Graph) -> na::Vector3<f32> {
+        let up = graph.get_relative_up(&Position::origin()).unwrap();
+        (na::Vector3::x() - up.into_inner() * up.x).normalize()
+    }
+
+    /// Roughly how far along `across` a point given in the root node's coordinates lies
+    fn along(graph: &Graph, point: &na::Vector4<f32>) -> f32 {
+        (point.xyz() / point.w).dot(&across(graph))
+    }
+
+    /// Run idle steps until the character stands on the ground
+    fn settle(
+        cfg: &SimConfig,
+        graph: &Graph,
+        position: &mut Position,
+        velocity: &mut na::Vector3<f32>,
+        on_ground: &mut bool,
+    ) {
+        for _ in 0..20 {
+            run_character_step(
+                cfg,
+                graph,
+                position,
+                velocity,
+                on_ground,
+                &idle_input(),
+                &mut 0.0,
+                0.1,
+                None,
+            );
+            if *on_ground {
+                return;
+            }
+        }
+        panic!("character never landed");
+    }
+
+    #[test]
+    fn walking_down_ramp_stays_on_ground() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        // Level behind the origin, then falling away at 30 degrees
+        let slope = 30f32.to_radians().tan();
+        let graph = graph_with_terrain(&cfg, -12.0 * m..0.0, |graph, point| {
+            let surface = -1.0 * m - slope * along(graph, point).max(0.0);
+            (surface - 3.0 * m..surface).contains(&elevation(graph, point))
+        });
+        let across = across(&graph);
+
+        let mut position = Position::origin();
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        settle(&cfg, &graph, &mut position, &mut velocity, &mut on_ground);
+        let start = character_elevation(&graph, &position);
+
+        let input = CharacterInput {
+            movement: MovementInput::new(across),
+            ..idle_input()
+        };
+        for i in 0..20 {
+            run_character_step(
+                &cfg,
+                &graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &input,
+                &mut 0.0,
+                0.1,
+                None,
+            );
+            assert!(on_ground, "left the ramp on step {i}");
+        }
+        assert!(start - character_elevation(&graph, &position) > 2.0 * m);
+    }
+
+    #[test]
+    fn walking_off_tall_ledge_falls() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        // The lower floor is further down than snapping reaches
+        let drop = 3.0 * m;
+        assert!(drop > cfg.character.ground_snap_distance + cfg.character.character_radius);
+        let graph = graph_with_terrain(&cfg, -7.0 * m..-1.0 * m, |graph, point| {
+            let surface = if along(graph, point) < 1.0 * m {
+                -1.0 * m
+            } else {
+                -1.0 * m - drop
+            };
+            (surface - 3.0 * m..surface).contains(&elevation(graph, point))
+        });
+        let across = across(&graph);
+
+        let mut position = Position::origin();
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        settle(&cfg, &graph, &mut position, &mut velocity, &mut on_ground);
+        let start = character_elevation(&graph, &position);
+
+        let input = CharacterInput {
+            movement: MovementInput::new(across),
+            ..idle_input()
+        };
+        let mut takeoffs = 0;
+        for _ in 0..20 {
+            let was_on_ground = on_ground;
+            run_character_step(
+                &cfg,
+                &graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &input,
+                &mut 0.0,
+                0.1,
+                None,
+            );
+            if was_on_ground && !on_ground {
+                takeoffs += 1;
+            }
+        }
+        assert_eq!(takeoffs, 1);
+        assert!(on_ground);
+        assert!(start - character_elevation(&graph, &position) > drop * 0.9);
+    }
+
+    #[test]
+    fn jumps_are_not_snapped_down() {
+        let mut raw = SimConfigRaw::default();
+        // Slow enough that the ground is still within snapping distance after the first step
+        raw.character.jump_speed = Some(2.0);
+        let cfg = SimConfig::from_raw(&raw);
+        let m = cfg.meters_to_absolute;
+        let graph = graph_with_floor(&cfg, -3.0 * m..-1.0 * m);
+
+        let mut position = Position::origin();
+        let mut velocity = na::Vector3::zeros();
+        let mut on_ground = false;
+        settle(&cfg, &graph, &mut position, &mut velocity, &mut on_ground);
+        let start = character_elevation(&graph, &position);
+
+        let input = CharacterInput {
+            jump: true,
+            ..walking_input()
+        };
+        run_character_step(
+            &cfg,
+            &graph,
+            &mut position,
+            &mut velocity,
+            &mut on_ground,
+            &input,
+            &mut 0.0,
+            0.1,
+            None,
+        );
+        assert!(!on_ground);
+        let risen = character_elevation(&graph, &position) - start;
+        assert!(risen > 0.0);
+        assert!(risen < cfg.character.ground_snap_distance);
+        let up = graph.get_relative_up(&position).unwrap();
+        assert!(up.dot(&velocity) > 0.0);
+    }
+
+    /// Normal of ground sloping down towards +x at `degrees` from horizontal, where up is +y
+    fn slope_normal(degrees: f32) -> na::UnitVector3<f32> {
+        let angle = degrees.to_radians();
+        na::UnitVector3::new_normalize(na::Vector3::new(angle.sin(), angle.cos(), 0.0))
+    }
+
+    /// A context for a character standing still on no particular terrain, where up is +y
+    fn slope_ctx<'a>(cfg: &'a SimConfig, graph: &'a Graph) -> CharacterControllerContext<'a> {
+        CharacterControllerContext {
+            cfg: &cfg.character,
+            collision_context: CollisionContext {
+                graph,
+                radius: cfg.character.character_radius,
+            },
+            up: na::Vector3::y_axis(),
+            dt_seconds: 0.1,
+            movement_input: na::Vector3::zeros(),
+            jump_input: false,
+            impulse: None,
+            constraint: None,
+            tracer: Tracer::new(None),
+            held_at_frontier: Cell::new(false),
+        }
+    }
+
+    #[test]
+    fn ground_slope_limits_per_material() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let graph = Graph::new(cfg.chunk_size);
+        let ctx = slope_ctx(&cfg, &graph);
+        let global = cfg.character.max_ground_slope;
+        for (material, max_slope) in [
+            (None, global),
+            (Some(Material::Dirt), global),
+            (Some(Material::Granite), global),
+            (Some(Material::Ice), 0.47),
+            (Some(Material::IceSlush), 0.58),
+            (Some(Material::Sand), 0.7),
+            (Some(Material::Gravel), 0.84),
+        ] {
+            let threshold = max_slope.atan().to_degrees();
+            let margin = 0.05;
+            assert!(
+                is_ground(&ctx, &slope_normal(threshold - margin), material),
+                "{material:?} below {threshold} degrees"
+            );
+            assert!(
+                !is_ground(&ctx, &slope_normal(threshold + margin), material),
+                "{material:?} above {threshold} degrees"
+            );
+        }
+    }
+
+    #[test]
+    fn sliding_starts_at_material_slide_slope() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let graph = Graph::new(cfg.chunk_size);
+        let ctx = slope_ctx(&cfg, &graph);
+        for material in Material::VALUES {
+            let Some(slide_slope) = material.slide_slope() else {
+                // Rock and the like never slide, however steep
+                assert!(slide_acceleration(&ctx, &slope_normal(59.0), Some(material)).is_none());
+                continue;
+            };
+            let max_slope = material
+                .max_ground_slope()
+                .unwrap_or(cfg.character.max_ground_slope);
+            assert!(slide_slope < max_slope, "{material:?} slides only on walls");
+            let threshold = slide_slope.atan().to_degrees();
+            let margin = 0.05;
+            assert!(
+                slide_acceleration(&ctx, &slope_normal(threshold - margin), Some(material))
+                    .is_none(),
+                "{material:?} below {threshold} degrees"
+            );
+            assert!(
+                slide_acceleration(&ctx, &slope_normal(threshold + margin), Some(material))
+                    .is_some(),
+                "{material:?} above {threshold} degrees"
+            );
+        }
+        // Without knowing the material, nothing slides
+        assert!(slide_acceleration(&ctx, &slope_normal(45.0), None).is_none());
+    }
+
+    #[test]
+    fn slide_acceleration_runs_downhill_along_ground() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let graph = Graph::new(cfg.chunk_size);
+        let ctx = slope_ctx(&cfg, &graph);
+        for degrees in [5.0, 15.0, 24.0] {
+            let normal = slope_normal(degrees);
+            let slide = slide_acceleration(&ctx, &normal, Some(Material::Ice)).unwrap();
+            let gravity = cfg.character.gravity_acceleration;
+            assert_abs_diff_eq!(slide.dot(&normal), 0.0, epsilon = 1e-4 * gravity);
+            assert_abs_diff_eq!(
+                slide.norm(),
+                gravity * degrees.to_radians().sin(),
+                epsilon = 1e-4 * gravity
+            );
+            // Down, towards where the ground falls away
+            assert!(slide.y < 0.0);
+            assert!(slide.x > 0.0);
+            assert_abs_diff_eq!(slide.z, 0.0);
+        }
+    }
+
+    #[test]
+    fn walking_up_slippery_slope_drifts_down() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let graph = Graph::new(cfg.chunk_size);
+        let degrees = 15.0;
+        let normal = slope_normal(degrees);
+        let ctx = CharacterControllerContext {
+            // Straight uphill
+            movement_input: -na::Vector3::x(),
+            ..slope_ctx(&cfg, &graph)
+        };
+        // Walking is strong enough to hold the character still, if it weren't held back
+        let pull = cfg.character.gravity_acceleration * f32::to_radians(degrees).sin();
+        assert!(cfg.character.ground_acceleration > pull);
+
+        let mut velocity = na::Vector3::zeros();
+        let steps = 10;
+        for _ in 0..steps {
+            apply_ground_controls(&ctx, &normal, Some(Material::Ice), &mut velocity);
+        }
+        let elapsed = steps as f32 * ctx.dt_seconds;
+        let downhill = na::Vector3::new(normal.y, -normal.x, 0.0);
+        let expected = downhill * pull * (1.0 - cfg.character.slide_resistance) * elapsed;
+        assert!(
+            (velocity - expected).norm() < 1e-4 * pull,
+            "{velocity} != {expected}"
+        );
+    }
+
+    #[test]
+    fn override_impulse_sets_velocity() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let graph = graph_with_floor(&cfg, 0.0..0.0);
+        let delta_velocity = na::Vector3::new(1.0, 2.0, -0.5) * m;
+        let input = CharacterInput {
+            external: ExternalInfluence {
+                impulse: Some(ExternalImpulse {
+                    delta_velocity,
+                    mode: ImpulseMode::Override,
+                }),
+                constraint: None,
+            },
+            ..walking_input()
+        };
+        for (prior, on_ground) in [
+            (na::Vector3::zeros(), false),
+            (na::Vector3::new(-20.0, 3.0, 7.0) * m, false),
+            (na::Vector3::new(0.0, -1.0, 0.0) * m, true),
+        ] {
+            let mut position = Position::origin();
+            let mut velocity = prior;
+            let mut on_ground = on_ground;
+            run_character_step(
+                &cfg,
+                &graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &input,
+                &mut 0.0,
+                cfg.max_substep_seconds,
+                None,
+            );
+            assert_eq!(velocity, delta_velocity);
+        }
+    }
+
+    #[test]
+    fn constraint_converges() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw {
+            character: CharacterConfigRaw {
+                gravity_acceleration: Some(0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let m = cfg.meters_to_absolute;
+        let graph = graph_with_floor(&cfg, 0.0..0.0);
+        let dt = cfg.max_substep_seconds;
+        let anchor = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&(na::Vector3::x() * 2.0 * m)),
+        };
+        // Heavily damped, critically damped, lightly damped, and close to the documented limits
+        for (stiffness, damping) in [(4.0, 10.0), (16.0, 8.0), (20.0, 1.5), (200.0, 19.0)] {
+            assert!(damping * dt < 2.0 && stiffness * dt < 2.0 * damping);
+            let tether = Tether {
+                anchor,
+                stiffness,
+                damping,
+                max_acceleration: f32::INFINITY,
+            };
+            let mut position = Position::origin();
+            let mut velocity = na::Vector3::zeros();
+            let mut on_ground = false;
+            let initial = relative_offset(&graph, &position, &anchor).norm();
+            for _ in 0..300 {
+                let input = CharacterInput {
+                    external: ExternalInfluence {
+                        impulse: None,
+                        constraint: Some(tether.constraint(&graph, &position)),
+                    },
+                    ..idle_input()
+                };
+                run_character_step(
+                    &cfg,
+                    &graph,
+                    &mut position,
+                    &mut velocity,
+                    &mut on_ground,
+                    &input,
+                    &mut 0.0,
+                    dt,
+                    None,
+                );
+                let distance = relative_offset(&graph, &position, &anchor).norm();
+                assert!(
+                    distance <= initial * 1.01,
+                    "stiffness {stiffness}, damping {damping}: {distance} from anchor"
+                );
+            }
+            let distance = relative_offset(&graph, &position, &anchor).norm();
+            assert!(
+                distance < 0.01 * m,
+                "stiffness {stiffness}, damping {damping}: settled {distance} from anchor"
+            );
+            assert!(velocity.norm() < 0.01 * m);
+        }
+    }
 }
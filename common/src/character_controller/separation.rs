@@ -0,0 +1,129 @@
+//! Soft resolution of overlaps between characters
+
+use fxhash::FxHashMap;
+
+use super::{
+    collision::{check_collision, CollisionContext},
+    renormalize_position,
+};
+use crate::{
+    dodeca,
+    graph::{Graph, NodeId},
+    math,
+    proto::Position,
+    traversal::nearby_nodes,
+    SimConfig,
+};
+
+/// Work done by `separate_characters`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SeparationStats {
+    /// Pairs of characters in nearby nodes whose distance was measured
+    pub pairs_tested: usize,
+    /// Pairs of characters found to be overlapping
+    pub pairs_overlapping: usize,
+}
+
+/// Push overlapping characters apart
+///
+/// Both characters in an overlapping pair are moved away from each other along the geodesic
+/// connecting them by half of the overlap. The total movement of each character is limited to what
+/// `max_separation_speed` allows over `dt_seconds` and is collision-checked against voxels, so a
+/// character can be pushed against a wall but never into it.
+///
+/// Pushes are summed in order of the characters' indices in `positions`, so that the result
+/// depends only on that order and never on how the characters are grouped internally.
+pub fn separate_characters(
+    sim_config: &SimConfig,
+    graph: &Graph,
+    positions: &mut [Position],
+    dt_seconds: f32,
+) -> SeparationStats {
+    let radius = sim_config.character.character_radius;
+    let min_distance = 2.0 * radius;
+    let mut stats = SeparationStats::default();
+
+    let mut occupants = FxHashMap::<NodeId, Vec<usize>>::default();
+    for (i, position) in positions.iter().enumerate() {
+        occupants.entry(position.node).or_default().push(i);
+    }
+
+    // Characters lie within their node's bounding sphere, so characters can only overlap if their
+    // nodes' centers are within this distance of each other
+    let search_distance = 2.0 * dodeca::BOUNDING_SPHERE_RADIUS_F64 + f64::from(min_distance);
+    // Pushes on each pair of characters (i, j), with i < j
+    let mut pushes = Vec::new();
+    for (&node, here) in &occupants {
+        let center = Position {
+            node,
+            local: na::Matrix4::identity(),
+        };
+        for (other_node, transform) in nearby_nodes(graph, &center, search_distance) {
+            let Some(there) = occupants.get(&other_node) else {
+                continue;
+            };
+            for &i in here {
+                for &j in there {
+                    // Each pair is encountered from both sides, so only handle one of them
+                    if j <= i {
+                        continue;
+                    }
+                    stats.pairs_tested += 1;
+                    let j_from_i = math::mtranspose(&positions[i].local)
+                        * transform
+                        * positions[j].local
+                        * math::origin();
+                    let distance = math::distance(&math::origin(), &j_from_i);
+                    if distance >= min_distance {
+                        continue;
+                    }
+                    stats.pairs_overlapping += 1;
+                    let push = 0.5 * (min_distance - distance);
+                    let i_from_j = math::mtranspose(&positions[j].local)
+                        * math::mtranspose(&transform)
+                        * positions[i].local
+                        * math::origin();
+                    // Characters at exactly the same point are split along an arbitrary axis
+                    pushes.push((
+                        i,
+                        j,
+                        -j_from_i
+                            .xyz()
+                            .try_normalize(1e-6)
+                            .unwrap_or_else(na::Vector3::x)
+                            * push,
+                        -i_from_j
+                            .xyz()
+                            .try_normalize(1e-6)
+                            .unwrap_or_else(|| -na::Vector3::x())
+                            * push,
+                    ));
+                }
+            }
+        }
+    }
+
+    // Floating-point addition isn't associative, so the order pushes are summed in is fixed
+    pushes.sort_unstable_by_key(|&(i, j, _, _)| (i, j));
+    let mut displacements = vec![na::Vector3::<f32>::zeros(); positions.len()];
+    for (i, j, push_i, push_j) in pushes {
+        displacements[i] += push_i;
+        displacements[j] += push_j;
+    }
+
+    let max_push = sim_config.character.max_separation_speed * dt_seconds;
+    let collision_context = CollisionContext { graph, radius };
+    for (position, displacement) in positions.iter_mut().zip(displacements) {
+        if displacement.norm_squared() == 0.0 {
+            continue;
+        }
+        let result = check_collision(
+            &collision_context,
+            position,
+            &displacement.cap_magnitude(max_push),
+        );
+        position.local *= result.displacement_transform;
+        renormalize_position(graph, position);
+    }
+    stats
+}
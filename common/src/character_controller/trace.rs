@@ -0,0 +1,263 @@
+//! Records of how the character controller handled collisions, for diagnosing odd movement
+//!
+//! Tracing is opt-in per character. Untraced steps record nothing and allocate nothing, so tracing
+//! costs only a branch wherever an event could be recorded.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    node::ChunkId,
+    proto::{CharacterInput, Position},
+};
+
+/// The most recent steps of a character's movement
+#[derive(Debug, Clone)]
+pub struct CollisionTrace {
+    /// Most steps kept, beyond which the oldest are discarded
+    capacity: usize,
+    steps: VecDeque<StepTrace>,
+}
+
+impl CollisionTrace {
+    /// Keep up to `capacity` steps, which is raised to 1 if smaller
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            steps: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Recorded steps, oldest first
+    pub fn steps(&self) -> impl ExactSizeIterator<Item = &StepTrace> {
+        self.steps.iter()
+    }
+
+    pub fn push(&mut self, step: StepTrace) {
+        if self.steps.len() == self.capacity {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(step);
+    }
+
+    /// The recorded steps in the form written to files
+    pub fn dump(&self) -> TraceDump {
+        TraceDump {
+            version: TraceDump::VERSION,
+            steps: self.steps.iter().cloned().collect(),
+        }
+    }
+}
+
+/// A trace as written to files, to be attached to bug reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceDump {
+    /// Format of the trace, incremented on every incompatible change
+    pub version: u32,
+    /// Oldest first
+    pub steps: Vec<StepTrace>,
+}
+
+impl TraceDump {
+    pub const VERSION: u32 = 1;
+
+    /// Write as JSON to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file =
+            fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .with_context(|| format!("writing {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Read a trace written by `save`
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let dump: Self = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("reading {}", path.display()))?;
+        if dump.version != Self::VERSION {
+            bail!(
+                "{} has trace format version {}, but only {} is supported",
+                path.display(),
+                dump.version,
+                Self::VERSION
+            );
+        }
+        Ok(dump)
+    }
+}
+
+/// Everything that happened during one call to `run_character_step`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepTrace {
+    pub dt_seconds: f32,
+    pub input: CharacterInput,
+    pub start: TracedState,
+    /// In the order they happened
+    pub events: Vec<TraceEvent>,
+    pub end: TracedState,
+}
+
+/// The state of a character that the character controller updates
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct TracedState {
+    pub position: Position,
+    pub velocity: na::Vector3<f32>,
+    pub on_ground: bool,
+}
+
+/// Something the character controller did during a step
+///
+/// Vectors are relative to the character at the time of the event. The character only moves
+/// between collisions without turning, so vectors from one substep can be compared directly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TraceEvent {
+    /// A substep began
+    Substep {
+        dt_seconds: f32,
+        up: na::Vector3<f32>,
+    },
+    /// The character started inside terrain and was pushed out in `direction`
+    PushOut { direction: na::Vector3<f32> },
+    /// The character's sphere was swept along `displacement` to see what it would hit
+    Cast {
+        purpose: CastPurpose,
+        displacement: na::Vector3<f32>,
+        /// How far the character could move along `displacement`
+        distance: f32,
+        hit: Option<CastHit>,
+    },
+    /// A constraint was applied to the displacement and velocity being resolved
+    Bound {
+        bound: TracedBound,
+        /// Whether the bound only applies until temporary bounds are next cleared
+        temporary: bool,
+        displacement_before: na::Vector3<f32>,
+        displacement_after: na::Vector3<f32>,
+        velocity_before: Option<na::Vector3<f32>>,
+        velocity_after: Option<na::Vector3<f32>>,
+    },
+    /// Temporary bounds were discarded
+    TempBoundsCleared,
+    /// The bounds applied so far were set aside to handle a collision with the ground as if it
+    /// had come first, then are added again after it, leaving these vectors to be constrained
+    Rewind {
+        displacement: na::Vector3<f32>,
+        velocity: Option<na::Vector3<f32>>,
+    },
+    /// Collision handling was cut short after too many collisions
+    CutShort,
+    /// Climbing a ledge was tried, succeeding if `climbed`
+    StepUp { climbed: bool },
+}
+
+/// What a cast was for
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CastPurpose {
+    /// Finding the ground beneath the character
+    Ground,
+    /// Moving the character
+    Movement,
+    /// Rising or settling while climbing a ledge
+    StepUp,
+    /// Following the ground down after walking off it
+    GroundSnap,
+    /// Finding a wall to climb
+    Climb,
+}
+
+/// Where a cast stopped
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct CastHit {
+    /// Facing away from the surface hit, relative to the character after moving to meet it
+    pub normal: na::Vector3<f32>,
+    pub chunk: ChunkId,
+}
+
+/// Parameters of a constraint on the vectors being resolved
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct TracedBound {
+    /// Normal of the plane vectors are held to one side of
+    pub normal: na::Vector3<f32>,
+    /// Direction vectors are pushed in to satisfy the bound
+    pub projection_direction: na::Vector3<f32>,
+    /// Whether vectors are held in front of the plane rather than behind it
+    pub front_facing: bool,
+}
+
+/// Where the events of a step are recorded, if it's traced
+#[derive(Copy, Clone)]
+pub(super) struct Tracer<'a>(Option<&'a RefCell<Vec<TraceEvent>>>);
+
+impl<'a> Tracer<'a> {
+    pub(super) fn new(events: Option<&'a RefCell<Vec<TraceEvent>>>) -> Self {
+        Self(events)
+    }
+
+    pub(super) fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Record the event made by `event`, which is only called if the step is traced
+    pub(super) fn record(&self, event: impl FnOnce() -> TraceEvent) {
+        if let Some(events) = self.0 {
+            events.borrow_mut().push(event());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(dt_seconds: f32) -> StepTrace {
+        let state = TracedState {
+            position: Position::origin(),
+            velocity: na::Vector3::zeros(),
+            on_ground: false,
+        };
+        StepTrace {
+            dt_seconds,
+            input: CharacterInput {
+                movement: crate::proto::MovementInput::zero(),
+                jump: false,
+                no_clip: false,
+                block_update: None,
+                block_fill: None,
+                use_target: None,
+                throw: None,
+                external: Default::default(),
+            },
+            start: state,
+            events: Vec::new(),
+            end: state,
+        }
+    }
+
+    #[test]
+    fn oldest_steps_are_discarded() {
+        let mut trace = CollisionTrace::new(3);
+        for i in 0..5 {
+            trace.push(step(i as f32));
+        }
+        assert_eq!(
+            trace.steps().map(|x| x.dt_seconds).collect::<Vec<_>>(),
+            [2.0, 3.0, 4.0]
+        );
+        let dump = trace.dump();
+        assert_eq!(dump.version, TraceDump::VERSION);
+        assert_eq!(dump.steps.len(), 3);
+    }
+}
@@ -0,0 +1,178 @@
+//! Recovery of characters left inside terrain, as when no-clip is taken away mid-flight
+
+use super::renormalize_position;
+use crate::{
+    coords::locate_voxel, graph::Graph, math, proto::Position, world::Material, SimConfig,
+};
+
+/// Find the position nearest to `position`, within `max_distance`, at which a character overlaps
+/// no solid voxels
+///
+/// Candidates are spaced at half the character radius, so the result may be up to that much
+/// further away than the true nearest free position. Returns `None` if every candidate is
+/// obstructed or lies in an ungenerated chunk.
+pub fn nearest_free_position(
+    sim_config: &SimConfig,
+    graph: &Graph,
+    position: &Position,
+    max_distance: f32,
+) -> Option<Position> {
+    let radius = sim_config.character.character_radius;
+    let spacing = radius * 0.5;
+    let steps = (max_distance / spacing).ceil() as i32;
+    let mut offsets = Vec::new();
+    for x in -steps..=steps {
+        for y in -steps..=steps {
+            for z in -steps..=steps {
+                let offset = na::Vector3::new(x, y, z).cast::<f32>() * spacing;
+                if offset.norm() <= max_distance {
+                    offsets.push(offset);
+                }
+            }
+        }
+    }
+    offsets.sort_by(|a, b| a.norm_squared().total_cmp(&b.norm_squared()));
+    offsets.into_iter().find_map(|offset| {
+        let mut candidate = Position {
+            node: position.node,
+            local: position.local * math::translate_along(&offset),
+        };
+        renormalize_position(graph, &mut candidate);
+        is_clear(graph, &candidate, radius).then_some(candidate)
+    })
+}
+
+/// Move a character of `radius` that overlaps solid voxels by the shortest distance, along one of
+/// six directions, that leaves it clear, returning the direction it was moved in
+///
+/// The directions are `up`, down, and both ways along two horizontal axes, tried in that order at
+/// increasing distances of a quarter of the radius, so ties favor climbing out on top and the
+/// result depends only on the inputs. Returns `None`, leaving `position` untouched, if the character
+/// is already clear or no direction frees it within `max_distance`.
+pub(super) fn push_out(
+    graph: &Graph,
+    position: &mut Position,
+    radius: f32,
+    up: &na::UnitVector3<f32>,
+    max_distance: f32,
+) -> Option<na::UnitVector3<f32>> {
+    if is_clear(graph, position, radius) {
+        return None;
+    }
+    // Whichever local axis is furthest from vertical gives the most precise horizontal
+    let across = [na::Vector3::x(), na::Vector3::y(), na::Vector3::z()]
+        .into_iter()
+        .min_by(|a, b| a.dot(up).abs().total_cmp(&b.dot(up).abs()))
+        .unwrap();
+    let across = na::UnitVector3::new_normalize(across - up.into_inner() * across.dot(up));
+    let beside = na::UnitVector3::new_normalize(up.cross(&across));
+    let directions = [up, &across, &beside]
+        .into_iter()
+        .flat_map(|&direction| [direction, -direction]);
+
+    let spacing = radius * 0.25;
+    let steps = (max_distance / spacing).floor() as u32;
+    (1..=steps)
+        .flat_map(|step| {
+            directions
+                .clone()
+                .map(move |direction| (direction, direction.into_inner() * spacing * step as f32))
+        })
+        .find_map(|(direction, offset)| {
+            let candidate = Position {
+                node: position.node,
+                local: position.local * math::translate_along(&offset),
+            };
+            is_clear(graph, &candidate, radius).then(|| {
+                *position = candidate;
+                direction
+            })
+        })
+}
+
+/// Whether a character of `radius` at `position` is clear of solid voxels, judged by the voxels
+/// containing its center and fourteen points evenly spread over its surface
+///
+/// Voxels are larger than characters, so no solid voxel can fit between the samples, though the
+/// thinner parts of slabs and stairs might.
+pub(super) fn is_clear(graph: &Graph, position: &Position, radius: f32) -> bool {
+    let axes = [na::Vector3::x(), na::Vector3::y(), na::Vector3::z()]
+        .into_iter()
+        .flat_map(|axis| [axis, -axis]);
+    let diagonals = (0..8).map(|i| {
+        let sign = |bit: u32| if i & (1 << bit) == 0 { 1.0 } else { -1.0 };
+        na::Vector3::new(sign(0), sign(1), sign(2)).normalize()
+    });
+    std::iter::once(na::Vector3::zeros())
+        .chain(axes.chain(diagonals).map(|direction| direction * radius))
+        .all(|offset| {
+            let sample = Position {
+                node: position.node,
+                local: position.local * math::translate_along(&offset),
+            };
+            let Some((chunk, coords, within)) = locate_voxel(graph, graph.layout(), &sample) else {
+                return false;
+            };
+            match (
+                graph.get_block(chunk, coords),
+                graph.get_shape(chunk, coords),
+            ) {
+                (Some(Material::Void), _) => true,
+                (Some(_), Some(shape)) => !shape.contains_point(&within),
+                _ => false,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{graph::NodeId, test_graphs::graph_with_voxels, SimConfigRaw};
+
+    /// A graph of solid dirt, except for a ball of air of `pocket_radius` centered on `pocket`
+    fn graph_with_pocket(cfg: &SimConfig, pocket: &Position, pocket_radius: f32) -> Graph {
+        let pocket = pocket.local * math::origin();
+        graph_with_voxels(
+            cfg,
+            1.0,
+            |_, _| true,
+            |_, center| {
+                if math::distance(center, &pocket) < pocket_radius {
+                    Material::Void
+                } else {
+                    Material::Dirt
+                }
+            },
+        )
+    }
+
+    #[test]
+    fn finds_nearby_pocket() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let m = cfg.meters_to_absolute;
+        let pocket = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&na::Vector3::new(3.5 * m, 0.5 * m, 0.0)),
+        };
+        let graph = graph_with_pocket(&cfg, &pocket, 1.5 * m);
+
+        // Embedded in the dirt beside the pocket
+        let embedded = Position::origin();
+        assert!(!is_clear(&graph, &embedded, cfg.character.character_radius));
+        let free = nearest_free_position(&cfg, &graph, &embedded, 4.0 * m).unwrap();
+        assert!(is_clear(&graph, &free, cfg.character.character_radius));
+        assert_eq!(free.node, NodeId::ROOT);
+        let moved = math::distance(&(free.local * math::origin()), &math::origin());
+        let to_pocket = math::distance(&(pocket.local * math::origin()), &math::origin());
+        // Pushed towards the pocket, and only as far in as needed
+        assert!(moved < to_pocket, "moved {moved}, pocket at {to_pocket}");
+
+        // A character already in the open stays put
+        let open = nearest_free_position(&cfg, &graph, &pocket, 4.0 * m).unwrap();
+        assert_eq!(open.node, pocket.node);
+        assert!((open.local - pocket.local).abs().max() < 1e-4);
+
+        // Nothing is found out of reach
+        assert!(nearest_free_position(&cfg, &graph, &embedded, 0.5 * m).is_none());
+    }
+}
@@ -3,7 +3,7 @@
 use rand_distr::num_traits::Zero;
 use tracing::warn;
 
-use crate::math;
+use crate::{character_controller::TracedBound, math};
 
 /// Encapsulates all the information needed to constrain a vector (displacement) based on a set of `VectorBound`s and apply those
 /// same constraints to a secondary vector (velocity).
@@ -165,6 +165,15 @@ impl VectorBound {
         }
     }
 
+    /// The parameters of this bound, for recording in a trace
+    pub fn traced(&self) -> TracedBound {
+        TracedBound {
+            normal: self.normal.into_inner(),
+            projection_direction: self.projection_direction.into_inner(),
+            front_facing: self.front_facing,
+        }
+    }
+
     /// Updates `subject` with a projection transformation based on the constraint given by `self`.
     /// This function does not check whether such a constraint is needed.
     fn constrain_vector(&self, subject: &mut na::Vector3<f32>, error_margin: f32) {
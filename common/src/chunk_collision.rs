@@ -2,7 +2,9 @@ use crate::{
     collision_math::Ray,
     math,
     node::{ChunkLayout, Coords, VoxelAABB, VoxelData},
+    occupancy::NearCells,
     world::Material,
+    Occupancy,
 };
 
 pub struct ChunkCastHit {
@@ -19,9 +21,37 @@ pub struct ChunkCastHit {
 /// The `ray` parameter is given and any resulting hit normals are given in the chunk's dual coordinate system.
 ///
 /// The `tanh_distance` is the hyperbolic tangent of the distance along the ray to check for hits.
+///
+/// The `occupancy` must be that of the same chunk, and is used to skip over its empty parts without
+/// affecting the result.
 pub fn chunk_sphere_cast(
     collider_radius: f32,
     voxel_data: &VoxelData,
+    occupancy: &Occupancy,
+    layout: &ChunkLayout,
+    ray: &Ray,
+    tanh_distance: f32,
+) -> Option<ChunkCastHit> {
+    let cells = occupancy.cells(voxel_data, layout.dimension());
+    if !cells.any() {
+        return None;
+    }
+    let near = cells.near(layout, ray, tanh_distance, collider_radius);
+    sphere_cast_near(
+        collider_radius,
+        voxel_data,
+        &near,
+        layout,
+        ray,
+        tanh_distance,
+    )
+}
+
+/// Like `chunk_sphere_cast`, considering only voxels in the `near` cells
+fn sphere_cast_near(
+    collider_radius: f32,
+    voxel_data: &VoxelData,
+    near: &NearCells,
     layout: &ChunkLayout,
     ray: &Ray,
     tanh_distance: f32,
@@ -38,6 +68,7 @@ pub fn chunk_sphere_cast(
         hit = find_face_collision(
             collider_radius,
             voxel_data,
+            near,
             layout,
             &bounding_box,
             t_axis,
@@ -51,6 +82,7 @@ pub fn chunk_sphere_cast(
         hit = find_edge_collision(
             collider_radius,
             voxel_data,
+            near,
             layout,
             &bounding_box,
             t_axis,
@@ -63,6 +95,18 @@ pub fn chunk_sphere_cast(
     hit = find_vertex_collision(
         collider_radius,
         voxel_data,
+        near,
+        layout,
+        &bounding_box,
+        ray,
+        hit.as_ref().map_or(tanh_distance, |hit| hit.tanh_distance),
+    )
+    .or(hit);
+
+    hit = find_shape_collision(
+        collider_radius,
+        voxel_data,
+        near,
         layout,
         &bounding_box,
         ray,
@@ -77,6 +121,7 @@ pub fn chunk_sphere_cast(
 fn find_face_collision(
     collider_radius: f32,
     voxel_data: &VoxelData,
+    near: &NearCells,
     layout: &ChunkLayout,
     bounding_box: &VoxelAABB,
     t_axis: usize,
@@ -90,6 +135,15 @@ fn find_face_collision(
 
     // Loop through all grid planes overlapping the bounding box
     for t in bounding_box.grid_planes(t_axis) {
+        // Only voxels on either side of the plane can be hit through it
+        let voxels = 0..layout.dimension();
+        if !near.any(math::tuv_to_xyz(
+            t_axis,
+            [layout.neighboring_voxels(t), voxels.clone(), voxels],
+        )) {
+            continue;
+        }
+
         // Find a normal to the grid plane. Note that (t, 0, 0, x) is a normal of the plane whose closest point
         // to the origin is (x, 0, 0, t), and we use that fact here.
         let normal = math::lorentz_normalize(&math::tuv_to_xyz(
@@ -157,6 +211,7 @@ fn find_face_collision(
 fn find_edge_collision(
     collider_radius: f32,
     voxel_data: &VoxelData,
+    near: &NearCells,
     layout: &ChunkLayout,
     bounding_box: &VoxelAABB,
     t_axis: usize,
@@ -170,6 +225,18 @@ fn find_edge_collision(
 
     // Loop through all grid lines overlapping the bounding box
     for (u, v) in bounding_box.grid_lines(u_axis, v_axis) {
+        // Only voxels around the line can be hit through it
+        if !near.any(math::tuv_to_xyz(
+            t_axis,
+            [
+                0..layout.dimension(),
+                layout.neighboring_voxels(u),
+                layout.neighboring_voxels(v),
+            ],
+        )) {
+            continue;
+        }
+
         // Compute vectors Lorentz-orthogonal to the edge and to each other
         let edge_normal0 = math::lorentz_normalize(&math::tuv_to_xyz(
             t_axis,
@@ -234,6 +301,7 @@ fn find_edge_collision(
 fn find_vertex_collision(
     collider_radius: f32,
     voxel_data: &VoxelData,
+    near: &NearCells,
     layout: &ChunkLayout,
     bounding_box: &VoxelAABB,
     ray: &Ray,
@@ -243,6 +311,10 @@ fn find_vertex_collision(
 
     // Loop through all grid points contained in the bounding box
     for (x, y, z) in bounding_box.grid_points(0, 1, 2) {
+        if !near.any([x, y, z].map(|grid| layout.neighboring_voxels(grid))) {
+            continue;
+        }
+
         // Skip vertices that have no solid voxels adjacent to them
         if layout.neighboring_voxels(x).all(|voxel_x| {
             layout.neighboring_voxels(y).all(|voxel_y| {
@@ -303,17 +375,211 @@ fn find_vertex_collision(
     hit
 }
 
-/// Checks whether a voxel can be collided with. Any non-void voxel falls under this category.
+/// Detect collisions where a sphere contacts a voxel that isn't a full cube
+///
+/// Such voxels are made up of one or two boxes, each of which is tested separately.
+fn find_shape_collision(
+    collider_radius: f32,
+    voxel_data: &VoxelData,
+    near: &NearCells,
+    layout: &ChunkLayout,
+    bounding_box: &VoxelAABB,
+    ray: &Ray,
+    tanh_distance: f32,
+) -> Option<ChunkCastHit> {
+    if !matches!(*voxel_data, VoxelData::Shaped(..)) {
+        return None;
+    }
+    let mut hit: Option<ChunkCastHit> = None;
+
+    let view = voxel_data.view(layout.dimension());
+    for coords in bounding_box.voxels(layout.dimension()) {
+        if !near.any(coords.map(|x| x..x + 1)) {
+            continue;
+        }
+
+        let shape = view.shape(Coords(coords));
+        if view.get(Coords(coords)) == Material::Void || shape.is_full() {
+            continue;
+        }
+        for [min, max] in shape.boxes() {
+            // Boxes are given in half-voxel units
+            let to_grid = |axis: usize, half: u8| f32::from(coords[axis]) + f32::from(half) * 0.5;
+            let bounds = [0, 1, 2].map(|axis| [to_grid(axis, min[axis]), to_grid(axis, max[axis])]);
+            hit = find_box_collision(
+                collider_radius,
+                layout,
+                bounds,
+                ray,
+                hit.as_ref().map_or(tanh_distance, |hit| hit.tanh_distance),
+            )
+            .or(hit);
+        }
+    }
+
+    hit
+}
+
+/// Detect collisions where a sphere contacts the front side of a face, an edge, or a vertex of the
+/// box spanning `bounds`, given as the minimum and maximum grid coordinates along each axis
+fn find_box_collision(
+    collider_radius: f32,
+    layout: &ChunkLayout,
+    bounds: [[f32; 2]; 3],
+    ray: &Ray,
+    tanh_distance: f32,
+) -> Option<ChunkCastHit> {
+    let mut hit: Option<ChunkCastHit> = None;
+    let grid_to_dual = |grid: f32| grid / layout.dual_to_grid_factor();
+    let contains = |axis: usize, point: &na::Vector4<f32>| {
+        let grid = point[axis] / point.w * layout.dual_to_grid_factor();
+        (bounds[axis][0]..=bounds[axis][1]).contains(&grid)
+    };
+
+    for t_axis in 0..3 {
+        let u_axis = (t_axis + 1) % 3;
+        let v_axis = (t_axis + 2) % 3;
+
+        // Faces
+        for (side, &t) in bounds[t_axis].iter().enumerate() {
+            let normal = math::lorentz_normalize(&math::tuv_to_xyz(
+                t_axis,
+                na::Vector4::new(1.0, 0.0, 0.0, grid_to_dual(t)),
+            ));
+            // Only the outside of each face can be hit
+            let approaching_max = math::mip(&ray.direction, &normal) < 0.0;
+            if approaching_max != (side == 1) {
+                continue;
+            }
+            let normal = if approaching_max { normal } else { -normal };
+
+            let Some(new_tanh_distance) =
+                ray.solve_sphere_plane_intersection(&normal, collider_radius.sinh())
+            else {
+                continue;
+            };
+            if new_tanh_distance >= hit.as_ref().map_or(tanh_distance, |hit| hit.tanh_distance) {
+                continue;
+            }
+
+            let ray_endpoint = ray.ray_point(new_tanh_distance);
+            let contact_point = ray_endpoint - normal * math::mip(&ray_endpoint, &normal);
+            if !contains(u_axis, &contact_point) || !contains(v_axis, &contact_point) {
+                continue;
+            }
+
+            hit = Some(ChunkCastHit {
+                tanh_distance: new_tanh_distance,
+                normal,
+            });
+        }
+
+        // Edges parallel to the t-axis
+        for &u in &bounds[u_axis] {
+            for &v in &bounds[v_axis] {
+                let edge_normal0 = math::lorentz_normalize(&math::tuv_to_xyz(
+                    t_axis,
+                    na::Vector4::new(0.0, 1.0, 0.0, grid_to_dual(u)),
+                ));
+                let edge_normal1 =
+                    math::tuv_to_xyz(t_axis, na::Vector4::new(0.0, 0.0, 1.0, grid_to_dual(v)));
+                let edge_normal1 = math::lorentz_normalize(
+                    &(edge_normal1 - edge_normal0 * math::mip(&edge_normal0, &edge_normal1)),
+                );
+
+                let Some(new_tanh_distance) = ray.solve_sphere_line_intersection(
+                    &edge_normal0,
+                    &edge_normal1,
+                    collider_radius.sinh(),
+                ) else {
+                    continue;
+                };
+                if new_tanh_distance >= hit.as_ref().map_or(tanh_distance, |hit| hit.tanh_distance)
+                {
+                    continue;
+                }
+
+                let ray_endpoint = ray.ray_point(new_tanh_distance);
+                let contact_point = ray_endpoint
+                    - edge_normal0 * math::mip(&ray_endpoint, &edge_normal0)
+                    - edge_normal1 * math::mip(&ray_endpoint, &edge_normal1);
+                if !contains(t_axis, &contact_point) {
+                    continue;
+                }
+
+                hit = Some(ChunkCastHit {
+                    tanh_distance: new_tanh_distance,
+                    normal: ray_endpoint - contact_point,
+                });
+            }
+        }
+    }
+
+    // Vertices
+    for &x in &bounds[0] {
+        for &y in &bounds[1] {
+            for &z in &bounds[2] {
+                let vertex_normal0 =
+                    math::lorentz_normalize(&na::Vector4::new(1.0, 0.0, 0.0, grid_to_dual(x)));
+
+                let vertex_normal1 = na::Vector4::new(0.0, 1.0, 0.0, grid_to_dual(y));
+                let vertex_normal1 = math::lorentz_normalize(
+                    &(vertex_normal1
+                        - vertex_normal0 * math::mip(&vertex_normal0, &vertex_normal1)),
+                );
+
+                let vertex_normal2 = na::Vector4::new(0.0, 0.0, 1.0, grid_to_dual(z));
+                let vertex_normal2 = math::lorentz_normalize(
+                    &(vertex_normal2
+                        - vertex_normal0 * math::mip(&vertex_normal0, &vertex_normal2)
+                        - vertex_normal1 * math::mip(&vertex_normal1, &vertex_normal2)),
+                );
+
+                let Some(new_tanh_distance) = ray.solve_sphere_point_intersection(
+                    &vertex_normal0,
+                    &vertex_normal1,
+                    &vertex_normal2,
+                    collider_radius.sinh(),
+                ) else {
+                    continue;
+                };
+                if new_tanh_distance >= hit.as_ref().map_or(tanh_distance, |hit| hit.tanh_distance)
+                {
+                    continue;
+                }
+
+                let vertex_position = math::lorentz_normalize(&na::Vector4::new(
+                    grid_to_dual(x),
+                    grid_to_dual(y),
+                    grid_to_dual(z),
+                    1.0,
+                ));
+                let ray_endpoint = ray.ray_point(new_tanh_distance);
+                hit = Some(ChunkCastHit {
+                    tanh_distance: new_tanh_distance,
+                    normal: ray_endpoint - vertex_position,
+                });
+            }
+        }
+    }
+
+    hit
+}
+
+/// Checks whether a voxel can be collided with as a full cube. Any non-void voxel that isn't
+/// shaped otherwise falls under this category.
 fn voxel_is_solid(voxel_data: &VoxelData, layout: &ChunkLayout, coords: [u8; 3]) -> bool {
-    debug_assert!(coords[0] < layout.dimension());
-    debug_assert!(coords[1] < layout.dimension());
-    debug_assert!(coords[2] < layout.dimension());
-    voxel_data.get(Coords(coords).to_index(layout.dimension())) != Material::Void
+    let view = voxel_data.view(layout.dimension());
+    view.get(Coords(coords)) != Material::Void && view.shape(Coords(coords)).is_full()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::node::VoxelData;
+    use crate::node::{CoordAxis, CoordDirection, VoxelData};
+    use crate::world::Shape;
+    use approx::assert_abs_diff_eq;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg64Mcg;
 
     use super::*;
 
@@ -348,6 +614,14 @@ mod tests {
             self.voxel_data.data_mut(self.layout.dimension())
                 [Coords(coords).to_index(self.layout.dimension())] = material;
         }
+
+        fn set_shape(&mut self, coords: [u8; 3], shape: Shape) {
+            self.voxel_data.set_shape(
+                self.layout.dimension(),
+                Coords(coords).to_index(self.layout.dimension()),
+                shape,
+            );
+        }
     }
 
     /// Helper method to set up common parameters that are used
@@ -393,6 +667,7 @@ mod tests {
         chunk_sphere_cast(
             ctx.collider_radius,
             &ctx.voxel_data,
+            &Occupancy::default(),
             &ctx.layout,
             ray,
             tanh_distance,
@@ -408,6 +683,7 @@ mod tests {
         find_face_collision(
             ctx.collider_radius,
             &ctx.voxel_data,
+            &NearCells::all(ctx.layout.dimension()),
             &ctx.layout,
             &VoxelAABB::from_ray_segment_and_radius(
                 &ctx.layout,
@@ -431,6 +707,7 @@ mod tests {
         find_edge_collision(
             ctx.collider_radius,
             &ctx.voxel_data,
+            &NearCells::all(ctx.layout.dimension()),
             &ctx.layout,
             &VoxelAABB::from_ray_segment_and_radius(
                 &ctx.layout,
@@ -453,6 +730,7 @@ mod tests {
         find_vertex_collision(
             ctx.collider_radius,
             &ctx.voxel_data,
+            &NearCells::all(ctx.layout.dimension()),
             &ctx.layout,
             &VoxelAABB::from_ray_segment_and_radius(
                 &ctx.layout,
@@ -647,4 +925,129 @@ mod tests {
             },
         )
     }
+
+    /// Tests that a sphere comes to rest on top of a slab, halfway up its voxel, and that only the
+    /// filled half of the voxel can be collided with.
+    #[test]
+    fn slab_collisions() {
+        let collider_radius = 0.005;
+        let mut ctx = TestSphereCastContext::new(collider_radius);
+        ctx.set_shape([1, 1, 1], Shape::slab(CoordAxis::Y, CoordDirection::Minus));
+
+        // Falling onto the slab
+        cast_with_test_ray(
+            &ctx,
+            [1.5, 3.0, 1.5],
+            [1.5, 0.5, 1.5],
+            |ray, tanh_distance| {
+                let hit = chunk_sphere_cast_wrapper(&ctx, ray, tanh_distance)
+                    .expect("collision expected");
+                let top = math::lorentz_normalize(&na::Vector4::new(
+                    0.0,
+                    1.0,
+                    0.0,
+                    1.5 / ctx.layout.dual_to_grid_factor(),
+                ));
+                let center = math::lorentz_normalize(&ray.ray_point(hit.tanh_distance));
+                assert_abs_diff_eq!(
+                    math::mip(&center, &top),
+                    collider_radius.sinh(),
+                    epsilon = 1e-5
+                );
+                sanity_check_normal(ray, &hit);
+            },
+        );
+
+        // Hitting the side of the slab
+        cast_with_test_ray(
+            &ctx,
+            [0.0, 1.25, 1.5],
+            [1.5, 1.25, 1.5],
+            |ray, tanh_distance| {
+                let hit = chunk_sphere_cast_wrapper(&ctx, ray, tanh_distance)
+                    .expect("collision expected");
+                sanity_check_normal(ray, &hit);
+            },
+        );
+
+        // No collision: Passing through the empty upper half of the voxel
+        cast_with_test_ray(
+            &ctx,
+            [0.0, 1.8, 1.5],
+            [3.0, 1.8, 1.5],
+            |ray, tanh_distance| {
+                assert!(chunk_sphere_cast_wrapper(&ctx, ray, tanh_distance).is_none());
+            },
+        );
+
+        // No collision: Rising out of the slab's voxel through its empty half
+        cast_with_test_ray(
+            &ctx,
+            [1.5, 1.8, 1.5],
+            [1.5, 3.0, 1.5],
+            |ray, tanh_distance| {
+                assert!(chunk_sphere_cast_wrapper(&ctx, ray, tanh_distance).is_none());
+            },
+        );
+    }
+
+    /// Tests that materials from newer builds, which this one doesn't know, are solid
+    #[test]
+    fn unknown_materials_collide() {
+        let collider_radius = 0.02;
+        let mut ctx = TestSphereCastContext::new(collider_radius);
+        ctx.set_voxel([1, 1, 1], Material::from_id(Material::COUNT as u16));
+
+        cast_with_test_ray(
+            &ctx,
+            [1.5, 3.0, 1.5],
+            [1.5, 1.5, 1.5],
+            |ray, tanh_distance| {
+                test_face_collision(&ctx, ray, 1, tanh_distance);
+            },
+        );
+    }
+
+    /// Tests that skipping the empty parts of a chunk never changes what a cast hits
+    #[test]
+    fn occupancy_preserves_hits() {
+        let mut rng = Pcg64Mcg::seed_from_u64(0);
+        let mut ctx = TestSphereCastContext::new(0.0);
+        let dimension = ctx.layout.dimension();
+        // A few scattered voxels, leaving most cells empty
+        for _ in 0..6 {
+            let coords = [(); 3].map(|()| rng.gen_range(0..dimension));
+            ctx.set_voxel(coords, Material::Dirt);
+            if rng.gen_bool(0.5) {
+                ctx.set_shape(coords, Shape::slab(CoordAxis::Y, CoordDirection::Minus));
+            }
+        }
+
+        let mut hits = 0;
+        for _ in 0..500 {
+            ctx.collider_radius = rng.gen_range(0.005..0.05);
+            // Rays may start and end outside the chunk
+            let mut grid_point =
+                || [(); 3].map(|()| rng.gen_range(-2.0..f32::from(dimension) + 2.0));
+            let (start, end) = (grid_point(), grid_point());
+            cast_with_test_ray(&ctx, start, end, |ray, tanh_distance| {
+                let accelerated = chunk_sphere_cast_wrapper(&ctx, ray, tanh_distance);
+                let reference = sphere_cast_near(
+                    ctx.collider_radius,
+                    &ctx.voxel_data,
+                    &NearCells::all(dimension),
+                    &ctx.layout,
+                    ray,
+                    tanh_distance,
+                );
+                let summarize =
+                    |hit: Option<ChunkCastHit>| hit.map(|x| (x.tanh_distance, x.normal));
+                let reference = summarize(reference);
+                hits += usize::from(reference.is_some());
+                assert_eq!(summarize(accelerated), reference, "{start:?} to {end:?}");
+            });
+        }
+        // Make sure the comparison covered casts that hit something
+        assert!(hits > 0);
+    }
 }
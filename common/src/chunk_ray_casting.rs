@@ -29,6 +29,19 @@ pub fn chunk_ray_cast(
     layout: &ChunkLayout,
     ray: &Ray,
     tanh_distance: f32,
+) -> Option<ChunkCastHit> {
+    chunk_ray_cast_filtered(voxel_data, layout, ray, tanh_distance, |material| {
+        material != Material::Void
+    })
+}
+
+/// Like `chunk_ray_cast`, but only voxels whose material satisfies `blocks` can be hit
+pub fn chunk_ray_cast_filtered(
+    voxel_data: &VoxelData,
+    layout: &ChunkLayout,
+    ray: &Ray,
+    tanh_distance: f32,
+    blocks: impl Fn(Material) -> bool,
 ) -> Option<ChunkCastHit> {
     let mut hit: Option<ChunkCastHit> = None;
 
@@ -46,10 +59,21 @@ pub fn chunk_ray_cast(
             t_axis,
             ray,
             hit.as_ref().map_or(tanh_distance, |hit| hit.tanh_distance),
+            &blocks,
         )
         .or(hit);
     }
 
+    hit = find_shape_collision(
+        voxel_data,
+        layout,
+        &bounding_box,
+        ray,
+        hit.as_ref().map_or(tanh_distance, |hit| hit.tanh_distance),
+        &blocks,
+    )
+    .or(hit);
+
     hit
 }
 
@@ -61,6 +85,7 @@ fn find_face_collision(
     t_axis: usize,
     ray: &Ray,
     tanh_distance: f32,
+    blocks: &impl Fn(Material) -> bool,
 ) -> Option<ChunkCastHit> {
     let mut hit: Option<ChunkCastHit> = None;
 
@@ -111,12 +136,14 @@ fn find_face_collision(
             continue;
         };
 
-        // Ensure that the relevant voxel is solid
-        if !voxel_is_solid(
-            voxel_data,
-            layout,
-            math::tuv_to_xyz(t_axis, [voxel_t, voxel_u, voxel_v]),
-        ) {
+        // Ensure that the relevant voxel blocks the ray
+        let coords = math::tuv_to_xyz(t_axis, [voxel_t, voxel_u, voxel_v]);
+        if !blocks(voxel_material(voxel_data, layout, coords))
+            || !voxel_data
+                .view(layout.dimension())
+                .shape(Coords(coords))
+                .is_full()
+        {
             continue;
         }
 
@@ -132,17 +159,89 @@ fn find_face_collision(
     hit
 }
 
-/// Checks whether a voxel can be collided with. Any non-void voxel falls under this category.
-fn voxel_is_solid(voxel_data: &VoxelData, layout: &ChunkLayout, coords: [u8; 3]) -> bool {
-    debug_assert!(coords[0] < layout.dimension());
-    debug_assert!(coords[1] < layout.dimension());
-    debug_assert!(coords[2] < layout.dimension());
-    voxel_data.get(Coords(coords).to_index(layout.dimension())) != Material::Void
+/// Detect intersections between a ray and the front side of a face of a voxel that isn't a full cube
+fn find_shape_collision(
+    voxel_data: &VoxelData,
+    layout: &ChunkLayout,
+    bounding_box: &VoxelAABB,
+    ray: &Ray,
+    tanh_distance: f32,
+    blocks: &impl Fn(Material) -> bool,
+) -> Option<ChunkCastHit> {
+    if !matches!(*voxel_data, VoxelData::Shaped(..)) {
+        return None;
+    }
+    let mut hit: Option<ChunkCastHit> = None;
+
+    let view = voxel_data.view(layout.dimension());
+    for coords in bounding_box.voxels(layout.dimension()) {
+        let shape = view.shape(Coords(coords));
+        if shape.is_full() || !blocks(view.get(Coords(coords))) {
+            continue;
+        }
+        for [min, max] in shape.boxes() {
+            // Boxes are given in half-voxel units
+            let to_grid = |axis: usize, half: u8| f32::from(coords[axis]) + f32::from(half) * 0.5;
+            let bounds = [0, 1, 2].map(|axis| [to_grid(axis, min[axis]), to_grid(axis, max[axis])]);
+            let contains = |axis: usize, point: &na::Vector4<f32>| {
+                let grid = point[axis] / point.w * layout.dual_to_grid_factor();
+                (bounds[axis][0]..=bounds[axis][1]).contains(&grid)
+            };
+            for t_axis in 0..3 {
+                for (face_direction, t) in [
+                    (CoordDirection::Minus, bounds[t_axis][0]),
+                    (CoordDirection::Plus, bounds[t_axis][1]),
+                ] {
+                    let normal = math::lorentz_normalize(&math::tuv_to_xyz(
+                        t_axis,
+                        na::Vector4::new(1.0, 0.0, 0.0, t / layout.dual_to_grid_factor()),
+                    ));
+                    // Only the outside of each face can be hit
+                    let approaching_plus = math::mip(&ray.direction, &normal) < 0.0;
+                    if approaching_plus != (face_direction == CoordDirection::Plus) {
+                        continue;
+                    }
+
+                    let Some(new_tanh_distance) = ray.solve_point_plane_intersection(&normal)
+                    else {
+                        continue;
+                    };
+                    if new_tanh_distance
+                        >= hit.as_ref().map_or(tanh_distance, |hit| hit.tanh_distance)
+                    {
+                        continue;
+                    }
+
+                    let ray_endpoint = ray.ray_point(new_tanh_distance);
+                    let contact_point = ray_endpoint - normal * math::mip(&ray_endpoint, &normal);
+                    if !contains((t_axis + 1) % 3, &contact_point)
+                        || !contains((t_axis + 2) % 3, &contact_point)
+                    {
+                        continue;
+                    }
+
+                    hit = Some(ChunkCastHit {
+                        tanh_distance: new_tanh_distance,
+                        voxel_coords: Coords(coords),
+                        face_axis: CoordAxis::try_from(t_axis).unwrap(),
+                        face_direction,
+                    });
+                }
+            }
+        }
+    }
+
+    hit
+}
+
+fn voxel_material(voxel_data: &VoxelData, layout: &ChunkLayout, coords: [u8; 3]) -> Material {
+    voxel_data.view(layout.dimension()).get(Coords(coords))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::node::VoxelData;
+    use crate::world::Shape;
 
     use super::*;
 
@@ -315,4 +414,58 @@ mod tests {
             },
         )
     }
+
+    /// Tests that only the filled part of a voxel that isn't a full cube can be hit
+    #[test]
+    fn shaped_voxels() {
+        let mut ctx = TestRayCastContext::new();
+        // An upper slab, leaving the lower half of the voxel empty
+        ctx.voxel_data.set_shape(
+            ctx.layout.dimension(),
+            Coords([1, 1, 1]).to_index(ctx.layout.dimension()),
+            Shape::slab(CoordAxis::Y, CoordDirection::Plus),
+        );
+
+        // Hitting the slab's underside from within the voxel
+        cast_with_test_ray(
+            &ctx,
+            [1.5, 1.2, 1.5],
+            [1.5, 1.8, 1.5],
+            |ray, tanh_distance| {
+                test_face_collision(
+                    &ctx,
+                    ray,
+                    tanh_distance,
+                    CoordAxis::Y,
+                    CoordDirection::Minus,
+                );
+            },
+        );
+
+        // Hitting the slab's side
+        cast_with_test_ray(
+            &ctx,
+            [0.0, 1.75, 1.5],
+            [1.5, 1.75, 1.5],
+            |ray, tanh_distance| {
+                test_face_collision(
+                    &ctx,
+                    ray,
+                    tanh_distance,
+                    CoordAxis::X,
+                    CoordDirection::Minus,
+                );
+            },
+        );
+
+        // No collision: Passing beneath the slab
+        cast_with_test_ray(
+            &ctx,
+            [0.0, 1.25, 1.5],
+            [3.0, 1.25, 1.5],
+            |ray, tanh_distance| {
+                assert!(chunk_ray_cast_wrapper(&ctx, ray, tanh_distance).is_none());
+            },
+        );
+    }
 }
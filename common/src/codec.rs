@@ -1,21 +1,167 @@
+use std::borrow::Cow;
+
 use anyhow::{bail, Result};
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::proto::Capabilities;
+
+/// Length of the largest message `send` can frame, which is also the most a compressed message on
+/// an ordered stream may inflate to
+pub const MAX_FRAMED_LEN: usize = 1 << 24;
+
+/// Smallest encoded message worth compressing, below which the savings rarely pay for the header
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Leading byte of a `BodyFormat::Tagged` body sent as is
+const PLAIN_TAG: u8 = 0;
+/// Leading byte of a `BodyFormat::Tagged` body compressed with LZ4, followed by its uncompressed
+/// length as a little-endian `u32`
+const LZ4_TAG: u8 = 1;
+
+/// How the bodies of the messages on a connection are encoded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BodyFormat {
+    /// Bare bincode
+    Plain,
+    /// Bincode behind a byte saying whether it was then compressed, which it is when the sender
+    /// allows it and it comes to at least `COMPRESSION_THRESHOLD` bytes. Compressed bodies state
+    /// their uncompressed length, so that receivers can refuse to inflate more than they'd accept
+    /// uncompressed.
+    Tagged,
+}
+
+impl BodyFormat {
+    /// The format of the messages from a server that agreed to `capabilities`
+    pub fn negotiated(capabilities: Capabilities) -> Self {
+        if capabilities.contains(Capabilities::COMPRESSION) {
+            Self::Tagged
+        } else {
+            Self::Plain
+        }
+    }
+}
+
+/// Numbers of bytes a message occupied
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sizes {
+    /// On the wire, after any compression and not counting framing
+    pub wire: usize,
+    /// Encoded, before compression
+    pub uncompressed: usize,
+}
+
+/// Turn `encoded` into a body in `format`, compressing it if `compressible` and worthwhile
+pub fn compress(format: BodyFormat, encoded: Vec<u8>, compressible: bool) -> Vec<u8> {
+    if format == BodyFormat::Plain {
+        return encoded;
+    }
+    if compressible && encoded.len() >= COMPRESSION_THRESHOLD {
+        let compressed = lz4_flex::block::compress(&encoded);
+        if compressed.len() + 5 < encoded.len() {
+            let mut body = Vec::with_capacity(compressed.len() + 5);
+            body.push(LZ4_TAG);
+            body.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+            body.extend_from_slice(&compressed);
+            return body;
+        }
+    }
+    let mut body = Vec::with_capacity(encoded.len() + 1);
+    body.push(PLAIN_TAG);
+    body.extend_from_slice(&encoded);
+    body
+}
+
+/// Recover the encoded message from a body in `format`, refusing to inflate it beyond `limit`
+/// bytes
+pub fn decompress(format: BodyFormat, body: &[u8], limit: usize) -> Result<Cow<'_, [u8]>> {
+    if format == BodyFormat::Plain {
+        return Ok(Cow::Borrowed(body));
+    }
+    match body.split_first() {
+        Some((&PLAIN_TAG, rest)) => Ok(Cow::Borrowed(rest)),
+        Some((&LZ4_TAG, rest)) if rest.len() >= 4 => {
+            let (len, compressed) = rest.split_at(4);
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            if len > limit {
+                bail!("compressed message claims {len} bytes, more than the {limit} allowed");
+            }
+            let encoded = lz4_flex::block::decompress(compressed, len)?;
+            if encoded.len() != len {
+                bail!(
+                    "compressed message claims {len} bytes but holds {}",
+                    encoded.len()
+                );
+            }
+            Ok(Cow::Owned(encoded))
+        }
+        Some((&tag, _)) => bail!("malformed message body with tag {tag}"),
+        None => bail!("empty message body"),
+    }
+}
+
 pub async fn send<T: Serialize + ?Sized>(stream: &mut quinn::SendStream, msg: &T) -> Result<()> {
-    let mut buf = Vec::new();
-    let len = bincode::serialized_size(msg).unwrap();
-    if len >= 2u64.pow(24) {
+    send_as(stream, BodyFormat::Plain, msg, false).await?;
+    Ok(())
+}
+
+/// Send `msg` as `send` does with its body in `format`, compressing it if `compressible`,
+/// returning the bytes it occupied
+pub async fn send_as<T: Serialize + ?Sized>(
+    stream: &mut quinn::SendStream,
+    format: BodyFormat,
+    msg: &T,
+    compressible: bool,
+) -> Result<Sizes> {
+    let encoded = bincode::serialize(msg).unwrap();
+    let uncompressed = encoded.len();
+    if uncompressed >= MAX_FRAMED_LEN {
+        bail!(
+            "{} byte ordered message exceeds maximum length",
+            uncompressed
+        );
+    }
+    let body = compress(format, encoded, compressible);
+    let len = body.len();
+    if len >= MAX_FRAMED_LEN {
         bail!("{} byte ordered message exceeds maximum length", len);
     }
-    let tag = (len as u32).to_le_bytes();
-    buf.extend_from_slice(&tag[0..3]);
-    bincode::serialize_into(&mut buf, msg).unwrap();
+    let mut buf = Vec::with_capacity(len + 3);
+    buf.extend_from_slice(&(len as u32).to_le_bytes()[0..3]);
+    buf.extend_from_slice(&body);
     stream.write_all(&buf).await?;
-    Ok(())
+    Ok(Sizes {
+        wire: len,
+        uncompressed,
+    })
 }
 
 /// Returns `None` on end of stream
 pub async fn recv<T: DeserializeOwned>(stream: &mut quinn::RecvStream) -> Result<Option<T>> {
+    let Some(buf) = recv_bytes(stream).await? else {
+        return Ok(None);
+    };
+    Ok(Some(bincode::deserialize(&buf)?))
+}
+
+/// Receive a message sent with `send_as` in `format`, along with the bytes it occupied, returning
+/// `None` on end of stream
+pub async fn recv_as<T: DeserializeOwned>(
+    stream: &mut quinn::RecvStream,
+    format: BodyFormat,
+) -> Result<Option<(T, Sizes)>> {
+    let Some(body) = recv_bytes(stream).await? else {
+        return Ok(None);
+    };
+    let encoded = decompress(format, &body, MAX_FRAMED_LEN)?;
+    let sizes = Sizes {
+        wire: body.len(),
+        uncompressed: encoded.len(),
+    };
+    Ok(Some((bincode::deserialize(&encoded)?, sizes)))
+}
+
+/// Receive a message sent with `send` without decoding it, returning `None` on end of stream
+pub async fn recv_bytes(stream: &mut quinn::RecvStream) -> Result<Option<Vec<u8>>> {
     let mut tag = [0; 4];
     match stream.read_exact(&mut tag[0..3]).await {
         Err(quinn::ReadExactError::FinishedEarly) => return Ok(None),
@@ -30,7 +176,7 @@ pub async fn recv<T: DeserializeOwned>(stream: &mut quinn::RecvStream) -> Result
         Err(quinn::ReadExactError::ReadError(e)) => return Err(e.into()),
         Ok(()) => {}
     }
-    Ok(Some(bincode::deserialize(&buf)?))
+    Ok(Some(buf))
 }
 
 /// Send a message as the entirety of `stream`
@@ -44,6 +190,30 @@ pub async fn send_whole<T: Serialize + ?Sized>(
     Ok(())
 }
 
+/// Send a message as the entirety of `stream` with its body in `format`, compressing it if
+/// `compressible`, returning the bytes it occupied
+pub async fn send_whole_as<T: Serialize + ?Sized>(
+    mut stream: quinn::SendStream,
+    format: BodyFormat,
+    msg: &T,
+    compressible: bool,
+) -> std::result::Result<Sizes, quinn::WriteError> {
+    let encoded = bincode::serialize(msg).unwrap();
+    let uncompressed = encoded.len();
+    let body = compress(format, encoded, compressible);
+    stream.write_all(&body).await?;
+    stream.finish().await?;
+    Ok(Sizes {
+        wire: body.len(),
+        uncompressed,
+    })
+}
+
+/// Number of bytes `msg` occupies when sent with `send_whole`
+pub fn encoded_len<T: Serialize + ?Sized>(msg: &T) -> usize {
+    bincode::serialized_size(msg).unwrap() as usize
+}
+
 /// Receive the entirety of `stream` as a `T`
 pub async fn recv_whole<T: DeserializeOwned>(
     size_limit: usize,
@@ -52,3 +222,124 @@ pub async fn recv_whole<T: DeserializeOwned>(
     let buf = stream.read_to_end(size_limit).await?;
     Ok(bincode::deserialize(&buf)?)
 }
+
+/// Receive the entirety of `stream` as a `T` sent with `send_whole_as` in `format`, along with the
+/// bytes it occupied, refusing any more than `size_limit` bytes whether compressed or not
+pub async fn recv_whole_as<T: DeserializeOwned>(
+    size_limit: usize,
+    mut stream: quinn::RecvStream,
+    format: BodyFormat,
+) -> Result<(T, Sizes)> {
+    let body = stream.read_to_end(size_limit).await?;
+    let encoded = decompress(format, &body, size_limit)?;
+    let sizes = Sizes {
+        wire: body.len(),
+        uncompressed: encoded.len(),
+    };
+    Ok((bincode::deserialize(&encoded)?, sizes))
+}
+
+/// Encode `msg` as it would be sent, for decoding later with `decode`
+pub fn encode<T: Serialize + ?Sized>(msg: &T) -> Vec<u8> {
+    bincode::serialize(msg).unwrap()
+}
+
+/// Decode a message encoded with `encode`
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    Ok(bincode::deserialize(bytes)?)
+}
+
+/// Encode `msg` and decode the result as a `U`, exactly as if it had been sent and received
+pub fn reencode<T: Serialize + ?Sized, U: DeserializeOwned>(msg: &T) -> Result<U> {
+    Ok(bincode::deserialize(&bincode::serialize(msg)?)?)
+}
+
+/// As `reencode`, with the message's body in `format` and compressed if `compressible`, also
+/// returning the bytes it would have occupied
+pub fn reencode_as<T: Serialize + ?Sized, U: DeserializeOwned>(
+    format: BodyFormat,
+    msg: &T,
+    compressible: bool,
+) -> Result<(U, Sizes)> {
+    let encoded = bincode::serialize(msg)?;
+    let uncompressed = encoded.len();
+    let body = compress(format, encoded, compressible);
+    let msg = bincode::deserialize(&decompress(format, &body, MAX_FRAMED_LEN)?)?;
+    Ok((
+        msg,
+        Sizes {
+            wire: body.len(),
+            uncompressed,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Something large and repetitive, as chunk data tends to be
+    fn compressible_msg() -> Vec<u16> {
+        (0..4096).map(|i| (i / 64) as u16).collect()
+    }
+
+    #[test]
+    fn compression_round_trip() {
+        let msg = compressible_msg();
+        let encoded = encode(&msg);
+        let body = compress(BodyFormat::Tagged, encoded.clone(), true);
+        assert_eq!(body[0], LZ4_TAG);
+        assert!(body.len() < encoded.len() / 4);
+        let decoded = decompress(BodyFormat::Tagged, &body, encoded.len()).unwrap();
+        assert_eq!(decoded, &encoded[..]);
+        let (msg2, sizes) = reencode_as::<_, Vec<u16>>(BodyFormat::Tagged, &msg, true).unwrap();
+        assert_eq!(msg2, msg);
+        assert_eq!(
+            sizes,
+            Sizes {
+                wire: body.len(),
+                uncompressed: encoded.len(),
+            }
+        );
+
+        // Left alone if not allowed, too small, or untagged
+        let body = compress(BodyFormat::Tagged, encoded.clone(), false);
+        assert_eq!((body[0], &body[1..]), (PLAIN_TAG, &encoded[..]));
+        let small = encode(&[7u16; 16]);
+        let body = compress(BodyFormat::Tagged, small.clone(), true);
+        assert_eq!((body[0], &body[1..]), (PLAIN_TAG, &small[..]));
+        assert_eq!(
+            decompress(BodyFormat::Tagged, &body, 0).unwrap(),
+            &small[..]
+        );
+        assert_eq!(compress(BodyFormat::Plain, encoded.clone(), true), encoded);
+        assert_eq!(
+            decompress(BodyFormat::Plain, &encoded, 0).unwrap(),
+            &encoded[..]
+        );
+    }
+
+    #[test]
+    fn compression_bombs_rejected() {
+        let encoded = encode(&compressible_msg());
+        let body = compress(BodyFormat::Tagged, encoded.clone(), true);
+        // Inflating to more than the receiver accepts
+        assert!(decompress(BodyFormat::Tagged, &body, encoded.len() - 1).is_err());
+
+        // Understating the uncompressed length to get past the limit
+        let mut understated = body.clone();
+        understated[1..5].copy_from_slice(&1024u32.to_le_bytes());
+        assert!(decompress(BodyFormat::Tagged, &understated, 1024).is_err());
+
+        // Overstating it
+        let mut overstated = body.clone();
+        overstated[1..5].copy_from_slice(&(encoded.len() as u32 + 1).to_le_bytes());
+        assert!(decompress(BodyFormat::Tagged, &overstated, MAX_FRAMED_LEN).is_err());
+
+        // Truncated, or otherwise malformed
+        assert!(decompress(BodyFormat::Tagged, &body[..body.len() / 2], MAX_FRAMED_LEN).is_err());
+        assert!(decompress(BodyFormat::Tagged, &body[..3], MAX_FRAMED_LEN).is_err());
+        assert!(decompress(BodyFormat::Tagged, &[], MAX_FRAMED_LEN).is_err());
+        assert!(decompress(BodyFormat::Tagged, &[2, 0, 0], MAX_FRAMED_LEN).is_err());
+    }
+}
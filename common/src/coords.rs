@@ -0,0 +1,177 @@
+//! Conversions between positions in the world and the voxels containing them
+
+use crate::{
+    dodeca::Vertex,
+    graph::{Graph, NodeId},
+    math,
+    node::{ChunkId, ChunkLayout, Coords},
+    proto::Position,
+};
+
+/// Find the voxel containing `position`, returning its chunk, its coordinates, and the position's
+/// offset within it along each axis, in [0, 1)
+///
+/// Positions whose local coordinates lie outside their node, as they may before being normalized,
+/// are located in the neighboring node they've moved into. Returns `None` if that node doesn't
+/// exist.
+pub fn locate_voxel(
+    graph: &Graph,
+    layout: &ChunkLayout,
+    position: &Position,
+) -> Option<(ChunkId, Coords, na::Vector3<f32>)> {
+    let point = position.local * math::origin();
+    if let Some(x) = locate_in_node(layout, position.node, &point) {
+        return Some(x);
+    }
+    let (node, transform) = graph.normalize_transform(position.node, &position.local);
+    locate_in_node(layout, node, &(transform * point))
+}
+
+/// The position at the center of a voxel
+pub fn voxel_center_position(layout: &ChunkLayout, chunk: ChunkId, coords: Coords) -> Position {
+    voxel_point_position(layout, chunk, coords, &na::Vector3::repeat(0.5))
+}
+
+/// The position `offset` voxels along the grid from the corner of a voxel with the least grid
+/// coordinates, which may lie beyond the voxel
+pub fn voxel_point_position(
+    layout: &ChunkLayout,
+    chunk: ChunkId,
+    coords: Coords,
+    offset: &na::Vector3<f64>,
+) -> Position {
+    let grid = na::Vector3::from(coords.0.map(f64::from)) + offset;
+    let dual = (grid / f64::from(layout.dual_to_grid_factor())).push(1.0);
+    let point = math::lorentz_normalize(&(chunk.vertex.dual_to_node_f64() * dual));
+    Position {
+        node: chunk.node,
+        local: math::translate(&math::origin(), &point).cast(),
+    }
+}
+
+/// Like `locate_voxel`, for a point in the space of `node` which must lie within it
+fn locate_in_node(
+    layout: &ChunkLayout,
+    node: NodeId,
+    point: &na::Vector4<f32>,
+) -> Option<(ChunkId, Coords, na::Vector3<f32>)> {
+    Vertex::iter().find_map(|vertex| {
+        let dual = vertex.node_to_dual_f32() * point;
+        let dual = dual.xyz() / dual.w;
+        let coords = Coords([
+            layout.dual_to_voxel(dual.x)?,
+            layout.dual_to_voxel(dual.y)?,
+            layout.dual_to_voxel(dual.z)?,
+        ]);
+        let offset =
+            dual * layout.dual_to_grid_factor() - na::Vector3::from(coords.0.map(f32::from));
+        Some((ChunkId::new(node, vertex), coords, offset))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dodeca::Side, node::populate_fresh_nodes};
+    use approx::*;
+
+    const DIMENSION: u8 = 12;
+
+    fn graph() -> Graph {
+        let mut graph = Graph::new(DIMENSION);
+        for side in Side::iter() {
+            graph.ensure_neighbor(NodeId::ROOT, side);
+        }
+        populate_fresh_nodes(&mut graph);
+        graph
+    }
+
+    fn all_coords() -> impl Iterator<Item = Coords> {
+        (0..DIMENSION).flat_map(|x| {
+            (0..DIMENSION).flat_map(move |y| (0..DIMENSION).map(move |z| Coords([x, y, z])))
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        let graph = graph();
+        let layout = graph.layout();
+        let neighbor = graph.neighbor(NodeId::ROOT, Side::C).unwrap();
+        for chunk in [
+            ChunkId::new(NodeId::ROOT, Vertex::A),
+            ChunkId::new(NodeId::ROOT, Vertex::K),
+            ChunkId::new(NodeId::ROOT, Vertex::T),
+            ChunkId::new(neighbor, Vertex::F),
+        ] {
+            for coords in all_coords() {
+                let center = voxel_center_position(layout, chunk, coords);
+                let (located_chunk, located_coords, offset) =
+                    locate_voxel(&graph, layout, &center).unwrap();
+                assert_eq!((located_chunk, located_coords), (chunk, coords));
+                assert_abs_diff_eq!(offset, na::Vector3::repeat(0.5), epsilon = 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn agrees_with_dual_to_voxel() {
+        let graph = graph();
+        let layout = graph.layout();
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::D);
+        // Points scattered unevenly through the chunk, including close to its faces
+        for i in 0..1000u32 {
+            let chunk_coords = na::Vector3::new(i * 7919, i * 104_729, i * 1_299_709)
+                .map(|x| f64::from(x % 10_007) / 10_007.0);
+            let node_point = math::lorentz_normalize(
+                &(chunk.vertex.chunk_to_node_f64() * chunk_coords.push(1.0)),
+            );
+            let position = Position {
+                node: NodeId::ROOT,
+                local: math::translate(&math::origin(), &node_point).cast(),
+            };
+            let Some((located_chunk, coords, offset)) = locate_voxel(&graph, layout, &position)
+            else {
+                panic!("{chunk_coords} wasn't located");
+            };
+            if located_chunk != chunk {
+                // Rounding can tip points on a face into the adjacent chunk
+                assert!(chunk_coords.iter().any(|&x| x < 1e-3 || x > 1.0 - 1e-3));
+                continue;
+            }
+            let dual = chunk.vertex.node_to_dual_f32() * position.local * math::origin();
+            for axis in 0..3 {
+                assert_eq!(
+                    Some(coords.0[axis]),
+                    layout.dual_to_voxel(dual[axis] / dual.w)
+                );
+                assert!((0.0..1.0).contains(&offset[axis]));
+            }
+        }
+    }
+
+    #[test]
+    fn unnormalized_position() {
+        let graph = graph();
+        let layout = graph.layout();
+        let neighbor = graph.neighbor(NodeId::ROOT, Side::H).unwrap();
+        let chunk = ChunkId::new(neighbor, Vertex::B);
+        let coords = Coords([0, DIMENSION / 2, DIMENSION - 1]);
+        let center = voxel_center_position(layout, chunk, coords);
+
+        // The same point, expressed relative to the root node, which doesn't contain it
+        let outside = Position {
+            node: NodeId::ROOT,
+            local: Side::H.reflection_f32() * center.local,
+        };
+        assert!(Side::H.is_facing(&(outside.local * math::origin())));
+        let (located_chunk, located_coords, _) = locate_voxel(&graph, layout, &outside).unwrap();
+        assert_eq!((located_chunk, located_coords), (chunk, coords));
+
+        // Nodes that don't exist yet can't be searched
+        let far = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&(na::Vector3::x() * 10.0)),
+        };
+        assert!(locate_voxel(&graph, layout, &far).is_none());
+    }
+}
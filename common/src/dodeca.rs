@@ -1,4 +1,8 @@
 //! Tools for processing the geometry of a right dodecahedron
+//!
+//! Every table is computed once in f64. Accessors suffixed `_f32` return values rounded from those,
+//! also computed once, so that callers working in f32 neither pay for conversion on each use nor
+//! accumulate error from deriving the tables at lower precision.
 
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
@@ -45,20 +49,32 @@ impl Side {
 
     /// Outward normal vector of this side
     #[inline]
-    pub fn normal(self) -> &'static na::Vector4<f64> {
+    pub fn normal_f64(self) -> &'static na::Vector4<f64> {
         &SIDE_NORMALS[self as usize]
     }
 
+    /// Outward normal vector of this side
+    #[inline]
+    pub fn normal_f32(self) -> &'static na::Vector4<f32> {
+        &SIDE_NORMALS_F32[self as usize]
+    }
+
     /// Reflection across this side
     #[inline]
-    pub fn reflection(self) -> &'static na::Matrix4<f64> {
+    pub fn reflection_f64(self) -> &'static na::Matrix4<f64> {
         &REFLECTIONS[self as usize]
     }
 
+    /// Reflection across this side
+    #[inline]
+    pub fn reflection_f32(self) -> &'static na::Matrix4<f32> {
+        &REFLECTIONS_F32[self as usize]
+    }
+
     /// Whether `p` is opposite the dodecahedron across the plane containing `self`
     #[inline]
     pub fn is_facing<N: na::RealField + Copy>(self, p: &na::Vector4<N>) -> bool {
-        let r = na::convert::<_, na::RowVector4<N>>(self.reflection().row(3).clone_owned());
+        let r = na::convert::<_, na::RowVector4<N>>(self.reflection_f64().row(3).clone_owned());
         (r * p).x < p.w
     }
 }
@@ -138,40 +154,70 @@ impl Vertex {
     }
 
     /// Transform from euclidean chunk coordinates to hyperbolic node space
-    pub fn chunk_to_node(self) -> na::Matrix4<f64> {
-        self.dual_to_node() * na::Matrix4::new_scaling(1.0 / Self::dual_to_chunk_factor())
+    pub fn chunk_to_node_f64(self) -> na::Matrix4<f64> {
+        CHUNK_TO_NODE[self as usize]
+    }
+
+    /// Transform from euclidean chunk coordinates to hyperbolic node space
+    pub fn chunk_to_node_f32(self) -> na::Matrix4<f32> {
+        CHUNK_TO_NODE_F32[self as usize]
     }
 
     /// Transform from hyperbolic node space to euclidean chunk coordinates
-    pub fn node_to_chunk(self) -> na::Matrix4<f64> {
-        na::Matrix4::new_scaling(Self::dual_to_chunk_factor()) * self.node_to_dual()
+    pub fn node_to_chunk_f64(self) -> na::Matrix4<f64> {
+        NODE_TO_CHUNK[self as usize]
+    }
+
+    /// Transform from hyperbolic node space to euclidean chunk coordinates
+    pub fn node_to_chunk_f32(self) -> na::Matrix4<f32> {
+        NODE_TO_CHUNK_F32[self as usize]
     }
 
     /// Transform from cube-centric coordinates to dodeca-centric coordinates
-    pub fn dual_to_node(self) -> &'static na::Matrix4<f64> {
+    pub fn dual_to_node_f64(self) -> &'static na::Matrix4<f64> {
         &DUAL_TO_NODE[self as usize]
     }
 
+    /// Transform from cube-centric coordinates to dodeca-centric coordinates
+    pub fn dual_to_node_f32(self) -> &'static na::Matrix4<f32> {
+        &DUAL_TO_NODE_F32[self as usize]
+    }
+
     /// Transform from dodeca-centric coordinates to cube-centric coordinates
-    pub fn node_to_dual(self) -> &'static na::Matrix4<f64> {
+    pub fn node_to_dual_f64(self) -> &'static na::Matrix4<f64> {
         &NODE_TO_DUAL[self as usize]
     }
 
+    /// Transform from dodeca-centric coordinates to cube-centric coordinates
+    pub fn node_to_dual_f32(self) -> &'static na::Matrix4<f32> {
+        &NODE_TO_DUAL_F32[self as usize]
+    }
+
     /// Scale factor used in conversion from cube-centric coordinates to euclidean chunk coordinates.
     /// Scaling the x, y, and z components of a vector in cube-centric coordinates by this value
     /// and dividing them by the w coordinate will yield euclidean chunk coordinates.
-    pub fn dual_to_chunk_factor() -> f64 {
+    pub fn dual_to_chunk_factor_f64() -> f64 {
         *DUAL_TO_CHUNK_FACTOR
     }
 
+    /// `dual_to_chunk_factor_f64`, rounded
+    pub fn dual_to_chunk_factor_f32() -> f32 {
+        *DUAL_TO_CHUNK_FACTOR as f32
+    }
+
     /// Scale factor used in conversion from euclidean chunk coordinates to cube-centric coordinates.
     /// Scaling the x, y, and z components of a vector in homogeneous euclidean chunk coordinates by this value
     /// and lorentz-normalizing the result will yield cube-centric coordinates.
-    pub fn chunk_to_dual_factor() -> f64 {
+    pub fn chunk_to_dual_factor_f64() -> f64 {
         *CHUNK_TO_DUAL_FACTOR
     }
 
-    /// Convenience method for `self.chunk_to_node().determinant() < 0`.
+    /// `chunk_to_dual_factor_f64`, rounded
+    pub fn chunk_to_dual_factor_f32() -> f32 {
+        *CHUNK_TO_DUAL_FACTOR as f32
+    }
+
+    /// Convenience method for `self.chunk_to_node_f64().determinant() < 0`.
     pub fn parity(self) -> bool {
         CHUNK_TO_NODE_PARITY[self as usize]
     }
@@ -179,7 +225,10 @@ impl Vertex {
 
 pub const VERTEX_COUNT: usize = 20;
 pub const SIDE_COUNT: usize = 12;
-pub const BOUNDING_SPHERE_RADIUS: f64 = 1.2264568712514068;
+/// Distance from the center of a dodecahedron to its vertices
+pub const BOUNDING_SPHERE_RADIUS_F64: f64 = 1.2264568712514068;
+/// `BOUNDING_SPHERE_RADIUS_F64`, rounded
+pub const BOUNDING_SPHERE_RADIUS_F32: f32 = BOUNDING_SPHERE_RADIUS_F64 as f32;
 
 lazy_static! {
     /// Whether two sides share an edge
@@ -218,11 +267,15 @@ lazy_static! {
         result
     };
 
+    static ref SIDE_NORMALS_F32: [na::Vector4<f32>; SIDE_COUNT] = SIDE_NORMALS.map(|n| n.cast());
+
     /// Transform that moves from a neighbor to a reference node, for each side
     static ref REFLECTIONS: [na::Matrix4<f64>; SIDE_COUNT] = {
         SIDE_NORMALS.map(|r| math::reflect(&r))
     };
 
+    static ref REFLECTIONS_F32: [na::Matrix4<f32>; SIDE_COUNT] = REFLECTIONS.map(|m| m.cast());
+
     /// Sides incident to a vertex, in canonical order
     static ref VERTEX_SIDES: [[Side; 3]; VERTEX_COUNT] = {
         let mut result = [[Side::A; 3]; VERTEX_COUNT];
@@ -273,9 +326,9 @@ lazy_static! {
         for i in 0..VERTEX_COUNT {
             let [a, b, c] = VERTEX_SIDES[i];
             let vertex_position = math::lorentz_normalize(
-                &(math::origin() - (a.normal() + b.normal() + c.normal()) * mip_origin_normal),
+                &(math::origin() - (a.normal_f64() + b.normal_f64() + c.normal_f64()) * mip_origin_normal),
             );
-            result[i] = na::Matrix4::from_columns(&[-a.normal(), -b.normal(), -c.normal(), vertex_position]);
+            result[i] = na::Matrix4::from_columns(&[-a.normal_f64(), -b.normal_f64(), -c.normal_f64(), vertex_position]);
         }
         result
     };
@@ -285,9 +338,25 @@ lazy_static! {
         DUAL_TO_NODE.map(|m| math::mtranspose(&m))
     };
 
+    static ref DUAL_TO_NODE_F32: [na::Matrix4<f32>; VERTEX_COUNT] = DUAL_TO_NODE.map(|m| m.cast());
+    static ref NODE_TO_DUAL_F32: [na::Matrix4<f32>; VERTEX_COUNT] = NODE_TO_DUAL.map(|m| m.cast());
+
     static ref DUAL_TO_CHUNK_FACTOR: f64 = (2.0 + 5.0f64.sqrt()).sqrt();
     static ref CHUNK_TO_DUAL_FACTOR: f64 = 1.0 / *DUAL_TO_CHUNK_FACTOR;
 
+    /// Transform from euclidean chunk coordinates to hyperbolic node space
+    static ref CHUNK_TO_NODE: [na::Matrix4<f64>; VERTEX_COUNT] = {
+        DUAL_TO_NODE.map(|m| m * na::Matrix4::new_scaling(*CHUNK_TO_DUAL_FACTOR))
+    };
+
+    /// Transform from hyperbolic node space to euclidean chunk coordinates
+    static ref NODE_TO_CHUNK: [na::Matrix4<f64>; VERTEX_COUNT] = {
+        NODE_TO_DUAL.map(|m| na::Matrix4::new_scaling(*DUAL_TO_CHUNK_FACTOR) * m)
+    };
+
+    static ref CHUNK_TO_NODE_F32: [na::Matrix4<f32>; VERTEX_COUNT] = CHUNK_TO_NODE.map(|m| m.cast());
+    static ref NODE_TO_CHUNK_F32: [na::Matrix4<f32>; VERTEX_COUNT] = NODE_TO_CHUNK.map(|m| m.cast());
+
     /// Vertex shared by 3 sides
     static ref SIDES_TO_VERTEX: [[[Option<Vertex>; SIDE_COUNT]; SIDE_COUNT]; SIDE_COUNT] = {
         let mut result = [[[None; SIDE_COUNT]; SIDE_COUNT]; SIDE_COUNT];
@@ -321,7 +390,7 @@ lazy_static! {
         let mut result = [false; VERTEX_COUNT];
 
         for v in Vertex::iter() {
-            result[v as usize] = math::parity(&v.chunk_to_node());
+            result[v as usize] = math::parity(&v.chunk_to_node_f64());
         }
 
         result
@@ -365,21 +434,21 @@ mod tests {
     fn side_is_facing() {
         for side in Side::iter() {
             assert!(!side.is_facing::<f32>(&math::origin()));
-            assert!(side.is_facing(&(side.reflection() * math::origin())));
+            assert!(side.is_facing(&(side.reflection_f64() * math::origin())));
         }
     }
 
     #[test]
     fn radius() {
-        let corner = Vertex::A.chunk_to_node() * math::origin();
+        let corner = Vertex::A.chunk_to_node_f64() * math::origin();
         assert_abs_diff_eq!(
-            BOUNDING_SPHERE_RADIUS,
+            BOUNDING_SPHERE_RADIUS_F64,
             math::distance(&corner, &math::origin()),
             epsilon = 1e-10
         );
         let phi = (1.0 + 5.0f64.sqrt()) / 2.0; // Golden ratio
         assert_abs_diff_eq!(
-            BOUNDING_SPHERE_RADIUS,
+            BOUNDING_SPHERE_RADIUS_F64,
             (1.5 * phi).sqrt().asinh(),
             epsilon = 1e-10
         );
@@ -389,7 +458,7 @@ mod tests {
     fn chunk_to_node() {
         // Chunk coordinates of (1, 1, 1) should be at the center of a dodecahedron.
         let mut chunk_corner_in_node_coordinates =
-            Vertex::A.chunk_to_node() * na::Vector4::new(1.0, 1.0, 1.0, 1.0);
+            Vertex::A.chunk_to_node_f64() * na::Vector4::new(1.0, 1.0, 1.0, 1.0);
         chunk_corner_in_node_coordinates /= chunk_corner_in_node_coordinates.w;
         assert_abs_diff_eq!(
             chunk_corner_in_node_coordinates,
@@ -401,9 +470,113 @@ mod tests {
     #[test]
     fn node_to_chunk() {
         assert_abs_diff_eq!(
-            Vertex::A.chunk_to_node().try_inverse().unwrap(),
-            Vertex::A.node_to_chunk(),
+            Vertex::A.chunk_to_node_f64().try_inverse().unwrap(),
+            Vertex::A.node_to_chunk_f64(),
             epsilon = 1e-10
         );
     }
+
+    /// Tolerance for identities checked at full precision
+    const EPSILON: f64 = 1e-12;
+
+    /// Position of a vertex in node coordinates
+    fn vertex_position(v: Vertex) -> na::Vector4<f64> {
+        v.dual_to_node_f64() * math::origin()
+    }
+
+    #[test]
+    fn reflections_are_involutions() {
+        for side in Side::iter() {
+            let r = side.reflection_f64();
+            assert_abs_diff_eq!(
+                r * math::mtranspose(r),
+                na::Matrix4::identity(),
+                epsilon = EPSILON
+            );
+            assert_abs_diff_eq!(r * r, na::Matrix4::identity(), epsilon = EPSILON);
+        }
+    }
+
+    #[test]
+    fn adjacent_reflections_commute() {
+        // Sides of a right dodecahedron meet at right angles, so reflecting across two adjacent
+        // sides is a half turn about their shared edge
+        for a in Side::iter() {
+            for b in Side::iter() {
+                let product = a.reflection_f64() * b.reflection_f64();
+                let half_turn = relative_eq!(
+                    product * product,
+                    na::Matrix4::identity(),
+                    epsilon = EPSILON
+                );
+                assert_eq!(half_turn, a.adjacent_to(b) || a == b, "{a:?}, {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn dual_inverts_node() {
+        for v in Vertex::iter() {
+            assert_abs_diff_eq!(
+                v.dual_to_node_f64() * v.node_to_dual_f64(),
+                na::Matrix4::identity(),
+                epsilon = EPSILON
+            );
+            assert_abs_diff_eq!(
+                v.chunk_to_node_f64() * v.node_to_chunk_f64(),
+                na::Matrix4::identity(),
+                epsilon = EPSILON
+            );
+        }
+    }
+
+    #[test]
+    fn vertices_lie_on_their_sides() {
+        for v in Vertex::iter() {
+            let p = vertex_position(v);
+            assert_abs_diff_eq!(math::mip(&p, &p), -1.0, epsilon = EPSILON);
+            for side in Side::iter() {
+                let mip = math::mip(&p, side.normal_f64());
+                if v.canonical_sides().contains(&side) {
+                    assert_abs_diff_eq!(mip, 0.0, epsilon = EPSILON);
+                } else {
+                    assert!(mip < -EPSILON, "{v:?} outside {side:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bounding_sphere_bounds_vertices() {
+        for v in Vertex::iter() {
+            let distance = math::distance(&vertex_position(v), &math::origin());
+            assert_abs_diff_eq!(distance, BOUNDING_SPHERE_RADIUS_F64, epsilon = EPSILON);
+        }
+    }
+
+    #[test]
+    fn f32_is_rounded_f64() {
+        assert_eq!(
+            BOUNDING_SPHERE_RADIUS_F32,
+            BOUNDING_SPHERE_RADIUS_F64 as f32
+        );
+        assert_eq!(
+            Vertex::dual_to_chunk_factor_f32(),
+            Vertex::dual_to_chunk_factor_f64() as f32
+        );
+        assert_eq!(
+            Vertex::chunk_to_dual_factor_f32(),
+            Vertex::chunk_to_dual_factor_f64() as f32
+        );
+        for side in Side::iter() {
+            assert_eq!(*side.normal_f32(), side.normal_f64().cast::<f32>());
+            assert_eq!(*side.reflection_f32(), side.reflection_f64().cast::<f32>());
+        }
+        for v in Vertex::iter() {
+            assert_eq!(*v.dual_to_node_f32(), v.dual_to_node_f64().cast::<f32>());
+            assert_eq!(*v.node_to_dual_f32(), v.node_to_dual_f64().cast::<f32>());
+            assert_eq!(v.chunk_to_node_f32(), v.chunk_to_node_f64().cast::<f32>());
+            assert_eq!(v.node_to_chunk_f32(), v.node_to_chunk_f64().cast::<f32>());
+        }
+    }
 }
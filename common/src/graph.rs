@@ -9,6 +9,7 @@ use serde::{Deserialize, Serialize};
 use crate::{
     dodeca::{Side, SIDE_COUNT},
     math,
+    mem_budget::{self, Reservation},
     node::{ChunkId, ChunkLayout, Node},
 };
 
@@ -19,6 +20,21 @@ pub struct Graph {
     /// order
     fresh: Vec<NodeId>,
     layout: ChunkLayout,
+    chunk_counts: ChunkCounts,
+    /// Most recent generation assigned to a chunk
+    chunk_generation: u32,
+}
+
+/// Numbers of chunks in a graph in particular states
+///
+/// Maintained as chunks are populated, edited, and evicted through `Graph`'s methods, so they can be
+/// inspected without visiting every node. Chunks modified through direct access to a node aren't
+/// reflected.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ChunkCounts {
+    pub populated: usize,
+    /// Populated chunks that differ from what world generation would produce
+    pub modified: usize,
 }
 
 impl Graph {
@@ -29,6 +45,8 @@ impl Graph {
             nodes,
             fresh: vec![NodeId::ROOT],
             layout: ChunkLayout::new(dimension),
+            chunk_counts: ChunkCounts::default(),
+            chunk_generation: 0,
         }
     }
 
@@ -42,11 +60,32 @@ impl Graph {
         self.nodes.len() as u32
     }
 
+    #[inline]
+    pub fn chunk_counts(&self) -> ChunkCounts {
+        self.chunk_counts
+    }
+
+    #[inline]
+    pub(crate) fn chunk_counts_mut(&mut self) -> &mut ChunkCounts {
+        &mut self.chunk_counts
+    }
+
+    /// A generation distinct from any recently assigned to a chunk in this graph
+    pub(crate) fn next_chunk_generation(&mut self) -> u32 {
+        self.chunk_generation = self.chunk_generation.wrapping_add(1);
+        self.chunk_generation
+    }
+
     #[inline]
     pub fn contains(&self, node: NodeId) -> bool {
         self.nodes.contains_key(&node)
     }
 
+    /// Every node in the graph, in no particular order
+    pub fn nodes(&self) -> impl ExactSizeIterator<Item = NodeId> + '_ {
+        self.nodes.keys().copied()
+    }
+
     /// Nodes created since the last call to `clear_fresh`
     #[inline]
     pub fn fresh(&self) -> &[NodeId] {
@@ -136,7 +175,7 @@ impl Graph {
                     None => continue,
                     Some(x) => x,
                 };
-                let mat = na::convert::<_, na::Matrix4<T>>(*side.reflection());
+                let mat = na::convert::<_, na::Matrix4<T>>(*side.reflection_f64());
                 location = mat * location;
                 transform = mat * transform;
                 continue 'outer;
@@ -226,6 +265,12 @@ impl Graph {
         node.0
     }
 
+    /// The node whose hash is `hash`, if it's in the graph
+    pub fn node_with_hash(&self, hash: u128) -> Option<NodeId> {
+        let node = NodeId(hash);
+        self.contains(node).then_some(node)
+    }
+
     /// Ensure all shorter neighbors of a not-yet-created child node exist and return them, excluding the given parent node
     fn populate_shorter_neighbors_of_child(
         &mut self,
@@ -273,6 +318,9 @@ struct NodeContainer {
     /// Distance to origin via parents
     length: u32,
     neighbors: [Option<NodeId>; SIDE_COUNT],
+    /// Size of the node's entry in `Graph::nodes`, which holds its `Node` once populated, not
+    /// counting the dense voxel data of its chunks
+    _memory: Reservation,
 }
 
 impl NodeContainer {
@@ -282,6 +330,7 @@ impl NodeContainer {
             parent_side,
             length,
             neighbors: [None; SIDE_COUNT],
+            _memory: mem_budget::GRAPH.reserve(std::mem::size_of::<(NodeId, NodeContainer)>()),
         }
     }
 
@@ -402,9 +451,9 @@ mod tests {
             assert_abs_diff_eq!(xf, na::Matrix4::identity(), epsilon = 1e-5);
         }
         {
-            let (node, xf) = graph.normalize_transform(NodeId::ROOT, Side::A.reflection());
+            let (node, xf) = graph.normalize_transform(NodeId::ROOT, Side::A.reflection_f64());
             assert_eq!(node, a);
-            assert_abs_diff_eq!(xf, Side::A.reflection(), epsilon = 1e-5);
+            assert_abs_diff_eq!(xf, Side::A.reflection_f64(), epsilon = 1e-5);
         }
     }
 
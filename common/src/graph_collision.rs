@@ -1,11 +1,14 @@
 use crate::{
     chunk_collision::chunk_sphere_cast,
+    chunk_ray_casting::chunk_ray_cast_filtered,
     collision_math::Ray,
     graph::Graph,
     math,
-    node::{Chunk, ChunkId},
+    node::{Chunk, ChunkId, ChunkLayout, Coords},
+    node_path::NodePath,
     proto::Position,
     traversal::RayTraverser,
+    world::Material,
 };
 
 /// Performs sphere casting (swept collision query) against the voxels in the `Graph`
@@ -16,7 +19,7 @@ use crate::{
 ///
 /// This function may return a `Err(OutOfBounds)` if not enough chunks are generated, even if the ray never reaches an
 /// ungenerated chunk. To prevent these errors, make sure that the distance between the ray's start point and the center of
-/// the closest node with ungenerated chunks is greater than `cast_distance + collider_radius + dodeca::BOUNDING_SPHERE_RADIUS`
+/// the closest node with ungenerated chunks is greater than `cast_distance + collider_radius + dodeca::BOUNDING_SPHERE_RADIUS_F64`
 pub fn sphere_cast(
     collider_radius: f32,
     graph: &Graph,
@@ -36,6 +39,7 @@ pub fn sphere_cast(
         };
         let Chunk::Populated {
             voxels: ref voxel_data,
+            ref occupancy,
             ..
         } = graph[chunk]
         else {
@@ -47,6 +51,7 @@ pub fn sphere_cast(
         hit = chunk_sphere_cast(
             collider_radius,
             voxel_data,
+            occupancy,
             graph.layout(),
             &(transform * ray),
             tanh_distance,
@@ -67,6 +72,79 @@ pub fn sphere_cast(
 #[derive(Debug)]
 pub struct OutOfBounds;
 
+/// Whether anything opaque lies on the geodesic between `from` and `to`
+///
+/// Transparent materials, such as water and leaves, don't obstruct the view. Unlike a cast, which
+/// must find the nearest hit, any obstruction settles the question, so the search stops at the
+/// first one found.
+pub fn line_of_sight(
+    graph: &Graph,
+    layout: &ChunkLayout,
+    from: &Position,
+    to: &Position,
+    max_distance: f32,
+) -> LosResult {
+    // Where `to` lies in the coordinates of `from`
+    let target = math::mtranspose(&from.local.cast::<f64>())
+        * NodePath::to(graph, from.node).transform_from(&NodePath::to(graph, to.node))
+        * to.local.cast::<f64>()
+        * math::origin();
+    let target = math::lorentz_normalize(&target.cast::<f32>());
+    let distance = target.w.max(1.0).acosh();
+    if distance > max_distance {
+        return LosResult::TooFar;
+    }
+    let Some(direction) = target.xyz().try_normalize(1e-6) else {
+        // Nothing can lie between a point and itself
+        return LosResult::Clear;
+    };
+    let ray = Ray::new(math::origin(), direction.push(0.0));
+    let tanh_distance = distance.tanh();
+
+    let mut indeterminate = false;
+    let mut traverser = RayTraverser::new(graph, *from, &ray, 0.0);
+    while let Some((chunk, transform)) = traverser.next(tanh_distance) {
+        // An obstruction further along could still settle the question
+        let Some(chunk) = chunk else {
+            indeterminate = true;
+            continue;
+        };
+        let Some(Chunk::Populated { ref voxels, .. }) = graph.get_chunk(chunk) else {
+            indeterminate = true;
+            continue;
+        };
+        if let Some(hit) =
+            chunk_ray_cast_filtered(voxels, layout, &(transform * &ray), tanh_distance, |x| {
+                x != Material::Void && !x.is_transparent()
+            })
+        {
+            return LosResult::Blocked {
+                chunk,
+                coords: hit.voxel_coords,
+            };
+        }
+    }
+    if indeterminate {
+        LosResult::Indeterminate
+    } else {
+        LosResult::Clear
+    }
+}
+
+/// Outcome of `line_of_sight`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LosResult {
+    /// Nothing opaque lies between the two positions
+    Clear,
+    /// The view is obstructed by at least this voxel, which need not be the nearest obstruction
+    Blocked { chunk: ChunkId, coords: Coords },
+    /// No obstruction was found, but the path crosses chunks that aren't populated, so one might
+    /// be there
+    Indeterminate,
+    /// The positions are further apart than the distance considered
+    TooFar,
+}
+
 /// Information about the intersection at the end of a ray segment.
 #[derive(Debug)]
 pub struct GraphCastHit {
@@ -86,6 +164,7 @@ pub struct GraphCastHit {
 mod tests {
     use crate::{
         collision_math::Ray,
+        coords::locate_voxel,
         dodeca::{self, Side, Vertex},
         graph::{Graph, NodeId},
         node::{populate_fresh_nodes, Coords, VoxelData},
@@ -156,8 +235,10 @@ mod tests {
                     graph[ChunkId::new(node, vertex)] = Chunk::Populated {
                         voxels: VoxelData::Solid(Material::Void),
                         modified: false,
+                        generation: 0,
                         surface: None,
                         old_surface: None,
+                        occupancy: Default::default(),
                     };
                 }
             }
@@ -172,8 +253,8 @@ mod tests {
             let chosen_chunk_transform: na::Matrix4<f32> =
                 self.chosen_voxel.node_path.iter().fold(
                     na::Matrix4::identity(),
-                    |transform: na::Matrix4<f32>, side| transform * side.reflection().cast::<f32>(),
-                ) * self.chosen_voxel.vertex.dual_to_node().cast();
+                    |transform: na::Matrix4<f32>, side| transform * *side.reflection_f32(),
+                ) * self.chosen_voxel.vertex.dual_to_node_f32();
 
             let dual_to_grid_factor = graph.layout().dual_to_grid_factor();
             let ray_target = chosen_chunk_transform
@@ -184,7 +265,7 @@ mod tests {
                     1.0,
                 ));
 
-            let ray_position = Vertex::A.dual_to_node().cast()
+            let ray_position = Vertex::A.dual_to_node_f32()
                 * math::lorentz_normalize(&na::Vector4::new(
                     self.start_chunk_relative_grid_ray_start[0] / dual_to_grid_factor,
                     self.start_chunk_relative_grid_ray_start[1] / dual_to_grid_factor,
@@ -428,14 +509,16 @@ mod tests {
                 graph[ChunkId::new(node, vertex)] = Chunk::Populated {
                     voxels: VoxelData::Solid(Material::Void),
                     modified: false,
+                    generation: 0,
                     surface: None,
                     old_surface: None,
+                    occupancy: Default::default(),
                 };
             }
         }
 
         // The node coordinates of the corner of the missing node
-        let vertex_pos = Vertex::A.dual_to_node().cast::<f32>() * math::origin();
+        let vertex_pos = Vertex::A.dual_to_node_f32() * math::origin();
 
         // Use a ray starting from the origin. The direction vector is vertex_pos with the w coordinate
         // set to 0 and normalized
@@ -458,4 +541,83 @@ mod tests {
 
         assert!(hit.is_ok());
     }
+
+    /// Lines of sight between the root node and its neighbor, obstructed by whatever's put in the
+    /// voxel three quarters of the way along
+    #[test]
+    fn line_of_sight_across_node_boundary() {
+        let dimension: u8 = 12;
+        let mut graph = Graph::new(dimension);
+        let graph_radius = 3.0;
+        ensure_nearby(&mut graph, &Position::origin(), graph_radius);
+        populate_fresh_nodes(&mut graph);
+        for (node, _) in nearby_nodes(&graph, &Position::origin(), graph_radius) {
+            for vertex in dodeca::Vertex::iter() {
+                graph[ChunkId::new(node, vertex)] = Chunk::Populated {
+                    voxels: VoxelData::Solid(Material::Void),
+                    modified: false,
+                    generation: 0,
+                    surface: None,
+                    old_surface: None,
+                    occupancy: Default::default(),
+                };
+            }
+        }
+
+        let from = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&na::Vector3::new(0.1, 0.05, 0.02)),
+        };
+        let to = Position {
+            node: graph.neighbor(NodeId::ROOT, Side::A).unwrap(),
+            local: math::translate_along(&na::Vector3::new(-0.05, 0.1, 0.03)),
+        };
+        let target =
+            math::mtranspose(&from.local) * Side::A.reflection_f32() * to.local * math::origin();
+        let distance = target.w.acosh();
+        let blocker = Position {
+            node: NodeId::ROOT,
+            local: from.local
+                * math::translate_along(&(target.xyz().normalize() * distance * 0.75)),
+        };
+        let (chunk, coords, _) = locate_voxel(&graph, graph.layout(), &blocker).unwrap();
+        assert_ne!(
+            chunk.node,
+            NodeId::ROOT,
+            "blocker should lie beyond the node boundary"
+        );
+        let check = |graph: &Graph| line_of_sight(graph, graph.layout(), &from, &to, 10.0);
+        let set_blocker = |graph: &mut Graph, material| {
+            let Chunk::Populated { ref mut voxels, .. } = graph[chunk] else {
+                unreachable!()
+            };
+            voxels.data_mut(dimension)[coords.to_index(dimension)] = material;
+        };
+
+        assert_eq!(check(&graph), LosResult::Clear);
+        // Symmetric
+        assert_eq!(
+            line_of_sight(&graph, graph.layout(), &to, &from, 10.0),
+            LosResult::Clear
+        );
+        assert_eq!(
+            line_of_sight(&graph, graph.layout(), &from, &to, distance * 0.5),
+            LosResult::TooFar
+        );
+
+        set_blocker(&mut graph, Material::Dirt);
+        assert_eq!(check(&graph), LosResult::Blocked { chunk, coords });
+        assert!(matches!(
+            line_of_sight(&graph, graph.layout(), &to, &from, 10.0),
+            LosResult::Blocked { .. }
+        ));
+
+        // Transparent materials don't obstruct the view
+        set_blocker(&mut graph, Material::Water);
+        assert_eq!(check(&graph), LosResult::Clear);
+
+        // A chunk along the way that's yet to be generated could hold anything
+        graph[chunk] = Chunk::Fresh;
+        assert_eq!(check(&graph), LosResult::Indeterminate);
+    }
 }
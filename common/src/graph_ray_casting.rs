@@ -15,7 +15,7 @@ use crate::{
 ///
 /// This function may return an `Err(OutOfBounds)` if not enough chunks are generated, even if the ray never reaches an
 /// ungenerated chunk. To prevent these errors, make sure that the distance between the ray's start point and the center of
-/// the closest node with ungenerated chunks is greater than `cast_distance + dodeca::BOUNDING_SPHERE_RADIUS`
+/// the closest node with ungenerated chunks is greater than `cast_distance + dodeca::BOUNDING_SPHERE_RADIUS_F64`
 pub fn ray_cast(
     graph: &Graph,
     position: &Position,
@@ -66,7 +66,7 @@ pub fn ray_cast(
 pub struct OutOfBounds;
 
 /// Information about the intersection at the end of a ray segment.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GraphCastHit {
     /// The tanh of the distance traveled along the ray to result in this hit.
     pub tanh_distance: f32,
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{world::Material, SimConfig};
+
+/// Materials carried by a character, collected by breaking blocks and spent by placing them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inventory {
+    /// Number of units of each material held. Materials with no units have no entry.
+    counts: BTreeMap<Material, u32>,
+}
+
+impl Inventory {
+    pub fn count(&self, material: Material) -> u32 {
+        self.counts.get(&material).copied().unwrap_or(0)
+    }
+
+    /// Add a unit of `material`, unless `capacity` units are already held. Returns whether the unit
+    /// was added.
+    pub fn try_add(&mut self, material: Material, capacity: u32) -> bool {
+        let count = self.counts.entry(material).or_insert(0);
+        if *count >= capacity {
+            if *count == 0 {
+                self.counts.remove(&material);
+            }
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// Remove a unit of `material`, if any are held. Returns whether a unit was removed.
+    pub fn try_remove(&mut self, material: Material) -> bool {
+        let Some(count) = self.counts.get_mut(&material) else {
+            return false;
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.counts.remove(&material);
+        }
+        true
+    }
+
+    /// Held materials and their counts, in material order
+    pub fn iter(&self) -> impl Iterator<Item = (Material, u32)> + '_ {
+        self.counts
+            .iter()
+            .map(|(&material, &count)| (material, count))
+    }
+
+    /// Settle the cost of replacing a voxel of material `old` with `new`, returning whether the
+    /// change is allowed. The inventory is unchanged if it isn't.
+    ///
+    /// Placing a material consumes a unit of it, and breaking a block collects a unit of its
+    /// material. If there's no room for the collected material, the change is refused unless
+    /// `cfg.discard_when_inventory_full` is set, in which case the material is lost.
+    pub fn exchange_block(&mut self, cfg: &SimConfig, old: Material, new: Material) -> bool {
+        if old == new {
+            // Nothing to do, e.g. because someone else already broke the block
+            return false;
+        }
+        if new != Material::Void && self.count(new) == 0 {
+            return false;
+        }
+        if old != Material::Void
+            && !self.try_add(old, cfg.inventory_capacity)
+            && !cfg.discard_when_inventory_full
+        {
+            return false;
+        }
+        if new != Material::Void {
+            self.try_remove(new);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimConfigRaw;
+
+    fn config(capacity: u32, discard_when_inventory_full: bool) -> SimConfig {
+        SimConfig::from_raw(&SimConfigRaw {
+            inventory_capacity: Some(capacity),
+            discard_when_inventory_full: Some(discard_when_inventory_full),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn consume_and_refund() {
+        let cfg = config(2, false);
+        let mut inventory = Inventory::default();
+        assert!(!inventory.exchange_block(&cfg, Material::Void, Material::Dirt));
+        assert_eq!(inventory, Inventory::default());
+
+        assert!(inventory.exchange_block(&cfg, Material::Dirt, Material::Void));
+        assert_eq!(inventory.count(Material::Dirt), 1);
+        assert!(inventory.exchange_block(&cfg, Material::Void, Material::Dirt));
+        assert_eq!(inventory.count(Material::Dirt), 0);
+        // Empty entries don't linger
+        assert_eq!(inventory, Inventory::default());
+
+        // Breaking an already-broken block collects nothing
+        assert!(!inventory.exchange_block(&cfg, Material::Void, Material::Void));
+        assert_eq!(inventory, Inventory::default());
+    }
+
+    #[test]
+    fn capacity() {
+        let mut inventory = Inventory::default();
+        let cfg = config(2, false);
+        assert!(inventory.exchange_block(&cfg, Material::Dirt, Material::Void));
+        assert!(inventory.exchange_block(&cfg, Material::Dirt, Material::Void));
+        assert!(!inventory.exchange_block(&cfg, Material::Dirt, Material::Void));
+        assert_eq!(inventory.count(Material::Dirt), 2);
+        // Capacity is per material
+        assert!(inventory.exchange_block(&cfg, Material::Sand, Material::Void));
+
+        let cfg = config(2, true);
+        assert!(inventory.exchange_block(&cfg, Material::Dirt, Material::Void));
+        assert_eq!(inventory.count(Material::Dirt), 2);
+
+        let mut inventory = Inventory::default();
+        assert!(!inventory.try_add(Material::Dirt, 0));
+        assert_eq!(inventory, Inventory::default());
+        assert_eq!(inventory.iter().count(), 0);
+    }
+}
@@ -1,60 +1,92 @@
 #![allow(clippy::needless_borrowed_reference)]
 
-use rand::{
-    distributions::{Distribution, Standard},
-    Rng,
-};
-
 #[macro_use]
 mod id;
 
 extern crate nalgebra as na;
+pub mod block_entity;
 pub mod character_controller;
 pub mod chunk_collision;
 mod chunk_ray_casting;
 mod chunks;
 pub mod codec;
 pub mod collision_math;
+pub mod coords;
 pub mod cursor;
 pub mod dodeca;
 pub mod graph;
 pub mod graph_collision;
 mod graph_entities;
 pub mod graph_ray_casting;
+pub mod inventory;
+pub mod logging;
 pub mod lru_slab;
+pub mod map_projection;
 pub mod math;
+pub mod mem_budget;
 pub mod node;
+pub mod node_path;
+mod occupancy;
+pub mod placement;
 mod plane;
+pub mod projectile;
+pub mod protection;
 pub mod proto;
+pub mod reach;
+pub mod region;
+pub mod rng;
 mod sim_config;
+pub mod template;
 pub mod terraingen;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_graphs;
+pub mod traffic;
 pub mod traversal;
+pub mod waypoint;
 pub mod world;
 pub mod worldgen;
+pub mod worldgen_cache;
 
 pub use chunks::Chunks;
 pub use graph_entities::GraphEntities;
+pub use logging::{init_tracing, tracing_guard};
 pub use lru_slab::LruSlab;
+pub use occupancy::Occupancy;
 pub use plane::Plane;
-pub use sim_config::{SimConfig, SimConfigRaw};
+pub use sim_config::{CharacterConfigRaw, SimConfig, SimConfigRaw};
 
-// Stable IDs made of 8 random bytes for easy persistent references
+// Stable IDs issued in order by the server for easy persistent references
 mkid!(EntityId: u64);
 
-impl std::fmt::Display for EntityId {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:016x}", self.0)
+impl EntityId {
+    /// Bit set in the IDs of transient entities, like projectiles, which are never saved and whose
+    /// IDs may be reused once they're forgotten
+    pub const TRANSIENT_BIT: u64 = 1 << 63;
+
+    /// Never issued to an entity, standing in for the server where an entity is expected, as the
+    /// author of changes no character requested directly
+    pub const NOBODY: Self = Self(0);
+
+    pub fn is_transient(self) -> bool {
+        self.0 & Self::TRANSIENT_BIT != 0
     }
 }
 
-impl Distribution<EntityId> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> EntityId {
-        EntityId(rng.gen())
+impl std::fmt::Display for EntityId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
     }
 }
 
 pub type Step = i32;
 
+/// Number of steps after an entity is despawned during which its `EntityId` isn't reused, so that
+/// clients can recognize late state updates for it
+pub const ENTITY_ID_REUSE_DELAY: Step = 600;
+
+/// Most steps between the state updates a client receives of any entity, however distant
+pub const MAX_UPDATE_STRIDE: Step = 8;
+
 pub fn defer<F: FnOnce()>(f: F) -> Defer<F> {
     Defer::new(f)
 }
@@ -82,34 +114,10 @@ impl<F: FnOnce()> Drop for Defer<F> {
 }
 
 /// Clamp speed to to 1.0 and graceful NaN handling
-pub fn sanitize_motion_input(v: na::Vector3<f32>) -> na::Vector3<f32> {
+pub fn sanitize_motion_input(input: proto::MovementInput) -> proto::MovementInput {
+    let v = input.vector();
     if !v.iter().all(|x| x.is_finite()) {
-        return na::Vector3::zeros();
+        return proto::MovementInput::zero();
     }
-    v / v.norm().max(1.0)
-}
-
-pub fn tracing_guard() -> tracing::dispatcher::DefaultGuard {
-    use tracing_subscriber::util::SubscriberInitExt;
-    tracing_subscriber().set_default()
-}
-
-pub fn init_tracing() {
-    use tracing_subscriber::util::SubscriberInitExt;
-    tracing_subscriber().init();
-}
-
-fn tracing_subscriber() -> impl tracing::Subscriber {
-    use tracing_subscriber::{filter, fmt, layer::SubscriberExt, registry};
-
-    registry()
-        .with(
-            fmt::layer()
-                .with_target(false)
-                .with_ansi(cfg!(not(windows))),
-        )
-        .with(
-            filter::EnvFilter::from_default_env()
-                .add_directive(tracing_subscriber::filter::LevelFilter::INFO.into()),
-        )
+    proto::MovementInput::new(v / v.norm().max(1.0))
 }
@@ -0,0 +1,209 @@
+//! Conventions for the context attached to log messages
+//!
+//! Client and server log the same things under the same field names, so that the logs of
+//! different machines can be searched alike:
+//!
+//! - `step`: the server step being simulated, or the latest one the client has heard of
+//! - `entity`: the `EntityId` an event concerns
+//! - `node`: the `NodeId` of the node concerned, a hash that's the same on every machine
+//! - `vertex`: the vertex of a node identifying a chunk, alongside `node`
+//! - `client`: the server's identifier for a connection
+//! - `edit`: an `EditId`, following a block update from the client that made it through the
+//!   server to every other client
+//!
+//! Context that applies to everything logged during some work, like the entity being moved, goes
+//! in a span entered around that work using the helpers here. These spans are at the error level,
+//! like the others in this codebase, so that they frame warnings and errors however quiet the
+//! filter is; they're made once per entity or chunk per step at most, which costs little beside the
+//! work they frame. Anything logged more often than that is at the debug or trace level, where the
+//! default filter rejects it before its fields are so much as formatted.
+
+use std::{
+    fmt, io,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{error_span, Span};
+use tracing_subscriber::{
+    filter::{Directive, EnvFilter, LevelFilter},
+    fmt as tracing_fmt,
+    layer::SubscriberExt,
+    registry, reload,
+    util::SubscriberInitExt,
+    Registry,
+};
+
+use crate::{graph::NodeId, node::ChunkId, proto::BlockUpdate, EntityId, Step};
+
+/// Context for everything done while simulating `step`
+pub fn step_span(step: Step) -> Span {
+    error_span!("step", step)
+}
+
+/// Context for everything done on behalf of the entity `id`, which is in `node`
+pub fn entity_span(id: EntityId, node: NodeId) -> Span {
+    error_span!("entity", entity = %id, node = ?node)
+}
+
+/// Context for everything done to populate or modify `chunk`
+pub fn chunk_span(chunk: ChunkId) -> Span {
+    error_span!("chunk", node = ?chunk.node, vertex = ?chunk.vertex)
+}
+
+/// Context for everything done to apply the block update `edit`
+pub fn edit_span(edit: EditId) -> Span {
+    error_span!("edit", edit = %edit)
+}
+
+/// Identifies a block update wherever it's logged
+///
+/// Each character numbers its block updates, so the author and sequence number together are
+/// unique, and every machine that handles the update knows both.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EditId {
+    pub author: EntityId,
+    pub sequence: u32,
+}
+
+impl EditId {
+    /// Identify `update`, made at the request of `author`
+    pub fn new(author: EntityId, update: &BlockUpdate) -> Self {
+        Self {
+            author,
+            sequence: update.sequence,
+        }
+    }
+}
+
+impl fmt::Display for EditId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.author, self.sequence)
+    }
+}
+
+/// Controls which messages are logged while running
+#[derive(Clone)]
+pub struct LogFilter(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilter {
+    /// Log what `directives` permit on top of the default, taking directives in the syntax of
+    /// `RUST_LOG`, like `server=debug,common::character_controller=trace`
+    pub fn set(&self, directives: &str) -> anyhow::Result<()> {
+        let mut filter = default_filter();
+        for directive in directives.split(',').filter(|x| !x.trim().is_empty()) {
+            filter = filter.add_directive(directive.trim().parse::<Directive>()?);
+        }
+        self.0.reload(filter)?;
+        Ok(())
+    }
+
+    /// Go back to logging what was logged at startup
+    pub fn reset(&self) -> anyhow::Result<()> {
+        self.0.reload(default_filter())?;
+        Ok(())
+    }
+}
+
+/// Log to the terminal for the rest of the process, returning a handle to adjust what's logged
+pub fn init_tracing() -> LogFilter {
+    let (subscriber, filter) = tracing_subscriber();
+    subscriber.init();
+    filter
+}
+
+/// Log to the terminal from the current thread until the guard is dropped
+pub fn tracing_guard() -> tracing::dispatcher::DefaultGuard {
+    tracing_subscriber().0.set_default()
+}
+
+/// Collect what `directives` permit to be logged from the current thread in memory, until the
+/// guard is dropped
+pub fn capture(directives: &str) -> (tracing::dispatcher::DefaultGuard, Captured) {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let guard = registry()
+        .with(EnvFilter::new(directives))
+        .with(
+            tracing_fmt::layer()
+                .with_ansi(false)
+                .with_writer(move || writer.clone()),
+        )
+        .set_default();
+    (guard, captured)
+}
+
+/// Log messages collected by `capture`
+#[derive(Clone, Default)]
+pub struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Captured {
+    /// Every line logged so far
+    pub fn lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.0.lock().unwrap())
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
+
+impl io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn tracing_subscriber() -> (impl tracing::Subscriber, LogFilter) {
+    let (filter, handle) = reload::Layer::new(default_filter());
+    let subscriber = registry().with(filter).with(
+        tracing_fmt::layer()
+            .with_target(false)
+            .with_ansi(cfg!(not(windows))),
+    );
+    (subscriber, LogFilter(handle))
+}
+
+fn default_filter() -> EnvFilter {
+    EnvFilter::from_default_env().add_directive(LevelFilter::INFO.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{debug, error, info};
+
+    #[test]
+    fn events_inherit_context() {
+        let (_guard, captured) = capture("debug");
+        let id = EntityId::from_bits(0xab);
+        let _step = step_span(7).entered();
+        let _entity = entity_span(id, NodeId::ROOT).entered();
+        error!("something went wrong");
+        let lines = captured.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("step=7"), "{}", lines[0]);
+        assert!(lines[0].contains("entity=00000000000000ab"), "{}", lines[0]);
+        assert!(lines[0].contains("node="), "{}", lines[0]);
+    }
+
+    #[test]
+    fn filter_limits_capture() {
+        let (_guard, captured) = capture("info");
+        debug!("hidden");
+        info!("shown");
+        assert_eq!(captured.lines().len(), 1);
+    }
+
+    #[test]
+    fn edit_ids_name_author_and_sequence() {
+        let id = EditId {
+            author: EntityId::from_bits(1),
+            sequence: 42,
+        };
+        assert_eq!(id.to_string(), "0000000000000001/42");
+    }
+}
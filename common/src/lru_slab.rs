@@ -1,4 +1,12 @@
+use std::fmt;
+
 /// A random-access table that maintains an LRU list in constant time
+///
+/// Values are addressed by the `SlotId` returned when they're inserted, which remains valid and
+/// refers to the same value until that value is removed, regardless of any other insertions,
+/// removals, or accesses. Once removed, a slot may be reused by a later insertion, the most
+/// recently freed slot being reused first. Slots are numbered densely from zero, so a `SlotId` can
+/// index parallel storage of `capacity()` elements, e.g. in a GPU buffer.
 pub struct LruSlab<T> {
     slots: Box<[Slot<T>]>,
     /// Most recently used
@@ -9,6 +17,10 @@ pub struct LruSlab<T> {
     free: SlotId,
     /// Number of occupied slots
     len: u32,
+    /// Largest value `len` has taken
+    peak_len: u32,
+    /// Number of slots beyond which the table will not grow
+    max_capacity: u32,
 }
 
 impl<T> LruSlab<T> {
@@ -39,9 +51,21 @@ impl<T> LruSlab<T> {
                 SlotId(0)
             },
             len: 0,
+            peak_len: 0,
+            max_capacity: u32::max_value() - 1,
         }
     }
 
+    /// Limit the number of slots to `max_capacity`, so that inserting into a full table fails
+    /// rather than allocating more slots
+    ///
+    /// Useful when slots correspond to a fixed-size resource. Has no effect on slots that have
+    /// already been allocated.
+    pub fn set_max_capacity(&mut self, max_capacity: u32) {
+        assert!(max_capacity != u32::max_value(), "capacity too large");
+        self.max_capacity = max_capacity;
+    }
+
     pub fn is_empty(&self) -> bool {
         self.len == 0
     }
@@ -50,19 +74,45 @@ impl<T> LruSlab<T> {
         self.len
     }
 
+    /// Number of allocated slots, occupied or not
     pub fn capacity(&self) -> u32 {
         self.slots.len() as u32
     }
 
+    /// Largest number of slots that have been simultaneously occupied
+    pub fn peak_len(&self) -> u32 {
+        self.peak_len
+    }
+
     /// Inserts a value, returning the slot it was stored in
     ///
-    /// The returned slot is marked as the most recently used.
+    /// The returned slot is marked as the most recently used. The number of slots doubles when
+    /// they're all occupied.
+    ///
+    /// # Panics
+    ///
+    /// If the table is full and has reached its maximum capacity. Use `try_insert` to handle this
+    /// case.
     pub fn insert(&mut self, value: T) -> SlotId {
+        match self.try_insert(value) {
+            Ok(id) => id,
+            Err(_) => panic!("LruSlab is at maximum capacity"),
+        }
+    }
+
+    /// Inserts a value, returning the slot it was stored in, or returns the value if the table is
+    /// full and has reached its maximum capacity
+    ///
+    /// The returned slot is marked as the most recently used.
+    pub fn try_insert(&mut self, value: T) -> Result<SlotId, Full<T>> {
         let id = match self.alloc() {
             Some(id) => id,
             None => {
                 let len = self.capacity();
-                let cap = 2 * len.max(2);
+                if len >= self.max_capacity {
+                    return Err(Full(value));
+                }
+                let cap = (2 * len.max(2)).min(self.max_capacity);
                 self.slots = self
                     .slots
                     .iter_mut()
@@ -82,7 +132,11 @@ impl<T> LruSlab<T> {
                     }))
                     .collect::<Vec<_>>()
                     .into_boxed_slice();
-                self.free = SlotId(len + 1);
+                self.free = if len + 1 == cap {
+                    SlotId::NONE
+                } else {
+                    SlotId(len + 1)
+                };
                 SlotId(len)
             }
         };
@@ -92,11 +146,12 @@ impl<T> LruSlab<T> {
         self.slots[idx].value = Some(value);
         self.link_at_head(id);
         self.len += 1;
+        self.peak_len = self.peak_len.max(self.len);
 
-        id
+        Ok(id)
     }
 
-    /// Get the least recently used slot, if any
+    /// Get the least recently used slot, if any, without marking it as used
     pub fn lru(&self) -> Option<SlotId> {
         if self.tail == SlotId::NONE {
             debug_assert_eq!(self.head, SlotId::NONE);
@@ -106,6 +161,7 @@ impl<T> LruSlab<T> {
         }
     }
 
+    /// Remove the value stored in `slot`, freeing the slot for reuse
     pub fn remove(&mut self, slot: SlotId) -> T {
         self.unlink(slot);
         self.slots[slot.0 as usize].next = self.free;
@@ -134,7 +190,7 @@ impl<T> LruSlab<T> {
         self.slots[slot.0 as usize].value.as_mut().unwrap()
     }
 
-    /// Walks the container from most to least recently used
+    /// Walks the container from most to least recently used, without marking anything as used
     pub fn iter(&self) -> Iter<'_, T> {
         Iter {
             slots: &self.slots[..],
@@ -144,6 +200,29 @@ impl<T> LruSlab<T> {
         }
     }
 
+    /// Access every value uniquely, in order of slot, without marking anything as used
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (SlotId, &mut T)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, slot)| Some((SlotId(i as u32), slot.value.as_mut()?)))
+    }
+
+    /// Remove every value for which `f` returns false, in order of slot
+    ///
+    /// The relative recency of the remaining values is unchanged.
+    pub fn retain(&mut self, mut f: impl FnMut(SlotId, &mut T) -> bool) {
+        for i in 0..self.capacity() {
+            let slot = SlotId(i);
+            let Some(ref mut value) = self.slots[i as usize].value else {
+                continue;
+            };
+            if !f(slot, value) {
+                self.remove(slot);
+            }
+        }
+    }
+
     /// Remove a slot from the freelist
     fn alloc(&mut self) -> Option<SlotId> {
         if self.free == SlotId::NONE {
@@ -212,6 +291,7 @@ struct Slot<T> {
     prev: SlotId,
 }
 
+/// Identifies a slot of an `LruSlab`
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct SlotId(pub u32);
 
@@ -219,6 +299,18 @@ impl SlotId {
     const NONE: Self = SlotId(u32::max_value());
 }
 
+/// Error returned by `LruSlab::try_insert`, containing the value that couldn't be inserted
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Full<T>(pub T);
+
+impl<T> fmt::Display for Full<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LruSlab is at maximum capacity")
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for Full<T> {}
+
 pub struct Iter<'a, T> {
     slots: &'a [Slot<T>],
     head: SlotId,
@@ -227,16 +319,19 @@ pub struct Iter<'a, T> {
 }
 
 impl<'a, T> Iterator for Iter<'a, T> {
-    type Item = &'a T;
-    fn next(&mut self) -> Option<&'a T> {
+    type Item = (SlotId, &'a T);
+    fn next(&mut self) -> Option<(SlotId, &'a T)> {
         if self.len == 0 {
             return None;
         }
-        let idx = self.head.0 as usize;
-        let result = self.slots[idx].value.as_ref().expect("corrupt LRU list");
-        self.head = self.slots[idx].next;
+        let slot = self.head;
+        let result = self.slots[slot.0 as usize]
+            .value
+            .as_ref()
+            .expect("corrupt LRU list");
+        self.head = self.slots[slot.0 as usize].next;
         self.len -= 1;
-        Some(result)
+        Some((slot, result))
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -245,15 +340,18 @@ impl<'a, T> Iterator for Iter<'a, T> {
 }
 
 impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
-    fn next_back(&mut self) -> Option<&'a T> {
+    fn next_back(&mut self) -> Option<(SlotId, &'a T)> {
         if self.len == 0 {
             return None;
         }
-        let idx = self.tail.0 as usize;
-        let result = self.slots[idx].value.as_ref().expect("corrupt LRU list");
-        self.tail = self.slots[idx].prev;
+        let slot = self.tail;
+        let result = self.slots[slot.0 as usize]
+            .value
+            .as_ref()
+            .expect("corrupt LRU list");
+        self.tail = self.slots[slot.0 as usize].prev;
         self.len -= 1;
-        Some(result)
+        Some((slot, result))
     }
 }
 
@@ -266,20 +364,26 @@ impl<T> ExactSizeIterator for Iter<'_, T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{Rng, SeedableRng};
+
+    /// Values from most to least recently used
+    fn contents(cache: &LruSlab<char>) -> String {
+        cache.iter().map(|(_, &c)| c).collect()
+    }
 
     #[test]
     fn lru_order() {
         let mut cache = LruSlab::new();
         let b = cache.insert('b');
-        assert_eq!(cache.iter().collect::<String>(), "b");
+        assert_eq!(contents(&cache), "b");
         let _a = cache.insert('a');
-        assert_eq!(cache.iter().collect::<String>(), "ab");
+        assert_eq!(contents(&cache), "ab");
         let d = cache.insert('d');
-        assert_eq!(cache.iter().collect::<String>(), "dab");
+        assert_eq!(contents(&cache), "dab");
         let c = cache.insert('c');
-        assert_eq!(cache.iter().collect::<String>(), "cdab");
+        assert_eq!(contents(&cache), "cdab");
         let e = cache.insert('e');
-        assert_eq!(cache.iter().collect::<String>(), "ecdab");
+        assert_eq!(contents(&cache), "ecdab");
 
         cache.get_mut(b);
         cache.get_mut(c);
@@ -303,4 +407,180 @@ mod tests {
         assert_eq!(a, a_prime);
         assert_eq!(cache.len(), 1);
     }
+
+    #[test]
+    fn slot_stability() {
+        let mut cache = LruSlab::new();
+        let slots = "abcdefgh"
+            .chars()
+            .map(|c| cache.insert(c))
+            .collect::<Vec<_>>();
+        // Removing other values, including those in between, and growing doesn't disturb slots
+        cache.remove(slots[1]);
+        cache.remove(slots[5]);
+        for c in "ijklmn".chars() {
+            cache.insert(c);
+        }
+        for (&slot, c) in slots.iter().zip("abcdefgh".chars()) {
+            if slot != slots[1] && slot != slots[5] {
+                assert_eq!(*cache.peek(slot), c);
+            }
+        }
+        // Iteration reports the slot each value lives in
+        for (slot, &c) in cache.iter() {
+            assert_eq!(*cache.peek(slot), c);
+        }
+    }
+
+    #[test]
+    fn max_capacity() {
+        let mut cache = LruSlab::with_capacity(1);
+        cache.set_max_capacity(3);
+        let a = cache.insert('a');
+        cache.insert('b');
+        cache.insert('c');
+        assert_eq!(cache.capacity(), 3);
+        assert_eq!(cache.try_insert('d'), Err(Full('d')));
+        assert_eq!(cache.len(), 3);
+        cache.remove(a);
+        assert_eq!(cache.try_insert('d'), Ok(a));
+        assert_eq!(contents(&cache), "dcb");
+        assert_eq!(cache.peak_len(), 3);
+    }
+
+    #[test]
+    fn retain_and_iter_mut() {
+        let mut cache = LruSlab::new();
+        for c in "abcdef".chars() {
+            cache.insert(c);
+        }
+        cache.retain(|_, &mut c| c != 'b' && c != 'e');
+        assert_eq!(contents(&cache), "fdca");
+        for (_, c) in cache.iter_mut() {
+            *c = c.to_ascii_uppercase();
+        }
+        // Neither affects recency
+        assert_eq!(contents(&cache), "FDCA");
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.peak_len(), 6);
+    }
+
+    /// Straightforward implementation of the semantics `LruSlab` promises
+    #[derive(Default)]
+    struct Model {
+        /// Occupied slots and their values, from most to least recently used
+        entries: Vec<(SlotId, u32)>,
+        /// Free slots, the next to be used last
+        free: Vec<SlotId>,
+        capacity: u32,
+        max_capacity: u32,
+        peak_len: u32,
+    }
+
+    impl Model {
+        fn insert(&mut self, value: u32) -> Option<SlotId> {
+            if self.free.is_empty() {
+                if self.capacity >= self.max_capacity {
+                    return None;
+                }
+                let cap = (2 * self.capacity.max(2)).min(self.max_capacity);
+                self.free.extend((self.capacity..cap).rev().map(SlotId));
+                self.capacity = cap;
+            }
+            let slot = self.free.pop().unwrap();
+            self.entries.insert(0, (slot, value));
+            self.peak_len = self.peak_len.max(self.entries.len() as u32);
+            Some(slot)
+        }
+
+        fn touch(&mut self, index: usize) -> (SlotId, u32) {
+            let entry = self.entries.remove(index);
+            self.entries.insert(0, entry);
+            entry
+        }
+
+        fn remove(&mut self, index: usize) -> (SlotId, u32) {
+            let entry = self.entries.remove(index);
+            self.free.push(entry.0);
+            entry
+        }
+    }
+
+    #[test]
+    fn matches_model() {
+        for seed in 0..32 {
+            let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(seed);
+            let max_capacity = if seed % 2 == 0 {
+                u32::max_value() - 1
+            } else {
+                5
+            };
+            let mut cache = LruSlab::new();
+            cache.set_max_capacity(max_capacity);
+            let mut model = Model {
+                max_capacity,
+                ..Default::default()
+            };
+            for value in 0..500 {
+                let len = model.entries.len();
+                match rng.gen_range(0..6) {
+                    0 | 1 => {
+                        let expected = model.insert(value);
+                        assert_eq!(cache.try_insert(value).ok(), expected);
+                    }
+                    2 if len > 0 => {
+                        let (slot, value) = model.touch(rng.gen_range(0..len));
+                        assert_eq!(*cache.get_mut(slot), value);
+                    }
+                    3 if len > 0 => {
+                        let (slot, value) = model.remove(rng.gen_range(0..len));
+                        assert_eq!(cache.remove(slot), value);
+                    }
+                    4 if len > 0 => {
+                        let (slot, value) = model.remove(len - 1);
+                        assert_eq!(cache.lru(), Some(slot));
+                        assert_eq!(cache.remove(slot), value);
+                    }
+                    5 => {
+                        let modulus = rng.gen_range(2..5);
+                        // Removals occur in order of slot
+                        let mut removed = model
+                            .entries
+                            .iter()
+                            .filter(|&&(_, value)| value % modulus == 0)
+                            .map(|&(slot, _)| slot)
+                            .collect::<Vec<_>>();
+                        removed.sort_unstable_by_key(|slot| slot.0);
+                        for slot in removed {
+                            let index = model.entries.iter().position(|x| x.0 == slot).unwrap();
+                            model.remove(index);
+                        }
+                        cache.retain(|_, &mut value| value % modulus != 0);
+                    }
+                    _ => {}
+                }
+
+                assert_eq!(cache.len() as usize, model.entries.len());
+                assert_eq!(cache.is_empty(), model.entries.is_empty());
+                assert_eq!(cache.capacity(), model.capacity);
+                assert_eq!(cache.peak_len(), model.peak_len);
+                assert_eq!(cache.lru(), model.entries.last().map(|x| x.0));
+                assert!(cache
+                    .iter()
+                    .map(|(slot, &value)| (slot, value))
+                    .eq(model.entries.iter().copied()));
+                assert!(cache
+                    .iter()
+                    .rev()
+                    .map(|(slot, &value)| (slot, value))
+                    .eq(model.entries.iter().rev().copied()));
+                let mut by_slot = model.entries.clone();
+                by_slot.sort_unstable_by_key(|x| x.0 .0);
+                assert!(cache
+                    .iter_mut()
+                    .map(|(slot, &mut value)| (slot, value))
+                    .eq(by_slot));
+            }
+        }
+    }
 }
@@ -0,0 +1,208 @@
+//! Flattening of the space around a viewpoint into a top-down map in the Klein disk
+
+use hecs::Entity;
+
+use crate::{
+    dodeca::Side,
+    graph::{Graph, NodeId},
+    math,
+    proto::Position,
+    traversal::nearby_nodes,
+    GraphEntities,
+};
+
+/// Orientation of a top-down map centered on a viewpoint
+///
+/// The map plane is perpendicular to the viewpoint's relative up direction. The map's +Y axis
+/// follows the horizontal component of the view direction, so the map rotates with yaw and the
+/// viewpoint always lies at the map's origin.
+#[derive(Debug, Clone)]
+pub struct MapFrame {
+    /// Maps the viewpoint node's coordinates to view space
+    inverse_view: na::Matrix4<f32>,
+    right: na::Vector3<f32>,
+    forward: na::Vector3<f32>,
+}
+
+impl MapFrame {
+    /// Construct a frame for a viewpoint whose local -Z axis is the view direction, as returned by
+    /// the client's `Sim::view`. Returns `None` if the viewpoint's node isn't populated.
+    pub fn new(graph: &Graph, view: &Position) -> Option<Self> {
+        Some(Self::from_up(view, graph.get_relative_up(view)?))
+    }
+
+    /// Construct a frame given the up direction in the viewpoint's local coordinates
+    pub fn from_up(view: &Position, up: na::UnitVector3<f32>) -> Self {
+        let look = -na::Vector3::z();
+        let mut forward = look - up.into_inner() * look.dot(&up);
+        if forward.norm_squared() < 1e-6 {
+            // Looking straight up or down, so the view direction says nothing about yaw. The top of
+            // the screen points forward when looking down, and backward when looking up.
+            let screen_up = na::Vector3::y() * up.z.signum();
+            forward = screen_up - up.into_inner() * screen_up.dot(&up);
+        }
+        let forward = forward.normalize();
+        Self {
+            inverse_view: math::mtranspose(&view.local),
+            right: forward.cross(&up),
+            forward,
+        }
+    }
+
+    /// Map coordinates of a point given in the viewpoint node's coordinates
+    pub fn project(&self, point: &na::Vector4<f32>) -> na::Point2<f32> {
+        let p = self.inverse_view * point;
+        let klein = p.xyz() / p.w;
+        na::Point2::new(klein.dot(&self.right), klein.dot(&self.forward))
+    }
+
+    /// Direction on the map towards a point given in the viewpoint node's coordinates, however far
+    /// beyond the map it lies, or `None` if it's directly above or below the viewpoint
+    pub fn compass(&self, point: &na::Vector4<f32>) -> Option<na::UnitVector2<f32>> {
+        na::Unit::try_new(self.project(point).coords, 1e-6)
+    }
+
+    /// Map coordinates of the origin of a node with the given viewpoint-node-relative transform
+    pub fn project_node(&self, node_transform: &na::Matrix4<f32>) -> na::Point2<f32> {
+        self.project(&(node_transform * math::origin()))
+    }
+}
+
+/// Approximate radius on a map of the inscribed sphere of a node whose center is at `center`
+pub fn node_radius(center: &na::Point2<f32>) -> f32 {
+    let inradius = Side::A.normal_f64().w.abs().asinh() as f32;
+    // Tangential shrinkage of hyperbolic lengths in the Klein model
+    inradius.tanh() * (1.0 - center.coords.norm_squared()).max(0.0).sqrt()
+}
+
+/// A node drawn on the map
+#[derive(Debug, Clone)]
+pub struct MapNode {
+    pub id: NodeId,
+    /// Position of the node's center on the map
+    pub center: na::Point2<f32>,
+    /// Approximate radius on the map of the node's inscribed sphere
+    pub radius: f32,
+    /// Signed distance from the node's center to its terrain surface, useful for color coding
+    pub elevation: f32,
+}
+
+/// An entity drawn on the map
+#[derive(Debug, Clone)]
+pub struct MapMarker {
+    pub entity: Entity,
+    pub position: na::Point2<f32>,
+}
+
+/// Everything within some distance of a viewpoint, projected onto a top-down map
+#[derive(Debug, Clone)]
+pub struct LocalMap {
+    pub nodes: Vec<MapNode>,
+    pub markers: Vec<MapMarker>,
+}
+
+impl LocalMap {
+    /// Project all populated nodes whose centers lie within `distance` of `view`, along with the
+    /// entities they contain. Returns `None` if the viewpoint's node isn't populated.
+    pub fn new(
+        graph: &Graph,
+        graph_entities: &GraphEntities,
+        world: &hecs::World,
+        view: &Position,
+        distance: f64,
+    ) -> Option<Self> {
+        let frame = MapFrame::new(graph, view)?;
+        let mut nodes = Vec::new();
+        let mut markers = Vec::new();
+        for (id, transform) in nearby_nodes(graph, view, distance) {
+            let Some(node) = graph.get(id).as_ref() else {
+                continue;
+            };
+            let center = frame.project_node(&transform);
+            nodes.push(MapNode {
+                id,
+                center,
+                radius: node_radius(&center),
+                elevation: node.state.elevation(),
+            });
+            for &entity in graph_entities.get(id) {
+                let Ok(pos) = world.get::<&Position>(entity) else {
+                    continue;
+                };
+                markers.push(MapMarker {
+                    entity,
+                    position: frame.project(&(transform * pos.local * math::origin())),
+                });
+            }
+        }
+        Some(Self { nodes, markers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node::populate_fresh_nodes, traversal::ensure_nearby};
+    use approx::*;
+
+    #[test]
+    fn adjacent_node_radius() {
+        let frame = MapFrame::from_up(&Position::origin(), na::Vector3::y_axis());
+        for side in Side::iter() {
+            let projected = frame.project_node(side.reflection_f32());
+            // Adjacent node centers are twice the inradius apart
+            let expected = (2.0 * side.normal_f64().w.abs().asinh()).tanh() as f32;
+            assert_abs_diff_eq!(projected.coords.norm(), expected, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn rotates_with_yaw() {
+        let up = na::Vector3::y_axis();
+        let ahead =
+            math::translate_along(&na::Vector3::<f32>::new(0.0, 0.0, -0.5)) * math::origin();
+        let straight = MapFrame::from_up(&Position::origin(), up);
+        assert_abs_diff_eq!(straight.project(&ahead).x, 0.0, epsilon = 1e-6);
+        assert!(straight.project(&ahead).y > 0.0);
+
+        // After turning left, the same point should appear to the right
+        let turned = Position {
+            node: NodeId::ROOT,
+            local: na::Rotation3::from_axis_angle(&up, std::f32::consts::FRAC_PI_2)
+                .to_homogeneous(),
+        };
+        let turned = MapFrame::from_up(&turned, up);
+        assert!(turned.project(&ahead).x > 0.0);
+        assert_abs_diff_eq!(turned.project(&ahead).y, 0.0, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn markers_match_relative_transforms() {
+        let mut graph = Graph::new(12);
+        let view = Position::origin();
+        ensure_nearby(&mut graph, &view, 3.0);
+        populate_fresh_nodes(&mut graph);
+
+        let mut world = hecs::World::new();
+        let mut graph_entities = GraphEntities::new();
+        let node = graph.neighbor(NodeId::ROOT, Side::C).unwrap();
+        let pos = Position {
+            node,
+            local: math::translate_along(&na::Vector3::new(0.2, 0.0, 0.1)),
+        };
+        let entity = world.spawn((pos,));
+        graph_entities.insert(node, entity);
+
+        let map = LocalMap::new(&graph, &graph_entities, &world, &view, 3.0).unwrap();
+        let marker = map.markers.iter().find(|m| m.entity == entity).unwrap();
+
+        let frame = MapFrame::new(&graph, &view).unwrap();
+        let (_, transform) = nearby_nodes(&graph, &view, 3.0)
+            .into_iter()
+            .find(|&(id, _)| id == node)
+            .unwrap();
+        let expected = frame.project(&(transform * pos.local * math::origin()));
+        assert_abs_diff_eq!(marker.position, expected, epsilon = 1e-5);
+        assert!(map.nodes.iter().any(|n| n.id == node));
+    }
+}
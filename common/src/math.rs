@@ -169,13 +169,13 @@ pub fn rotation_between_axis<N: RealField + Copy>(
 /// Converts from t-u-v coordinates to x-y-z coordinates. t-u-v coordinates are a permuted version of x-y-z coordinates.
 /// `t_axis` determines which of the three x-y-z coordinates corresponds to the t-coordinate. This function works with
 /// any indexable entity with at least three entries. Any entry after the third entry is ignored.
-pub fn tuv_to_xyz<T: std::ops::IndexMut<usize, Output = N>, N: Copy>(t_axis: usize, tuv: T) -> T {
+pub fn tuv_to_xyz<T: std::ops::IndexMut<usize, Output = N>, N: Clone>(t_axis: usize, tuv: T) -> T {
     let mut result = tuv;
     (
         result[t_axis],
         result[(t_axis + 1) % 3],
         result[(t_axis + 2) % 3],
-    ) = (result[0], result[1], result[2]);
+    ) = (result[0].clone(), result[1].clone(), result[2].clone());
     result
 }
 
@@ -0,0 +1,360 @@
+//! Accounting of the memory held by each major pool of data, for telling where a process's memory
+//! goes
+//!
+//! Each pool counts the bytes allocated for it. Counts are raised where memory is allocated and
+//! lowered where it's freed through `Reservation`s, which give their bytes back when dropped, so
+//! that no path freeing the memory can be missed. Sizes are those of the data itself, leaving out
+//! allocator overhead, and are estimates only where noted.
+
+use std::{
+    fmt, mem,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde::Serialize;
+
+use crate::{
+    dodeca::Vertex,
+    graph::Graph,
+    math,
+    node::{Chunk, ChunkId},
+    proto::Position,
+    traversal::nearby_nodes,
+};
+
+/// Voxels of chunks that aren't a single material, wherever they're held
+pub static DENSE_VOXELS: Pool = Pool::new("dense voxels");
+/// Nodes of graphs, estimated from the size of their fixed parts
+pub static GRAPH: Pool = Pool::new("graph");
+/// Voxels of chunks being generated or loaded, not yet handed over to a graph
+pub static CHUNK_LOADS: Pool = Pool::new("chunk loads");
+/// Data on its way into meshes, held in main memory
+pub static MESH_BUFFERS: Pool = Pool::new("mesh buffers");
+/// Device memory not counted by a more specific pool, as requested when buffers are created
+pub static GPU: Pool = Pool::new("gpu");
+/// Device memory holding the surfaces of chunks ready to be drawn
+pub static GPU_SURFACES: Pool = Pool::new("gpu surfaces");
+/// Device memory holding the images the world is rendered to, estimated from their formats
+pub static GPU_TARGETS: Pool = Pool::new("gpu targets");
+/// Regions of the graph encoded for sending to clients and kept for reuse
+pub static GRAPH_REGIONS: Pool = Pool::new("graph regions");
+
+/// Every pool, in the order they're reported
+pub static POOLS: [&Pool; 8] = [
+    &DENSE_VOXELS,
+    &GRAPH,
+    &CHUNK_LOADS,
+    &MESH_BUFFERS,
+    &GPU,
+    &GPU_SURFACES,
+    &GPU_TARGETS,
+    &GRAPH_REGIONS,
+];
+
+/// Every pool of device memory
+pub static GPU_POOLS: [&Pool; 3] = [&GPU, &GPU_SURFACES, &GPU_TARGETS];
+
+/// A counter of the bytes allocated for some purpose
+#[derive(Debug)]
+pub struct Pool {
+    name: &'static str,
+    bytes: AtomicUsize,
+}
+
+impl Pool {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Bytes currently reserved
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Count `bytes` towards this pool until the result is dropped
+    pub fn reserve(&'static self, bytes: usize) -> Reservation {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        Reservation { pool: self, bytes }
+    }
+}
+
+/// Bytes counted towards a pool for as long as this lives
+#[derive(Debug)]
+#[must_use]
+pub struct Reservation {
+    pool: &'static Pool,
+    bytes: usize,
+}
+
+impl Reservation {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Change the number of bytes counted
+    pub fn resize(&mut self, bytes: usize) {
+        self.pool.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.pool.bytes.fetch_sub(self.bytes, Ordering::Relaxed);
+        self.bytes = bytes;
+    }
+
+    /// Count the same bytes towards `pool` instead
+    pub fn transfer(&mut self, pool: &'static Pool) {
+        if std::ptr::eq(pool, self.pool) {
+            return;
+        }
+        pool.bytes.fetch_add(self.bytes, Ordering::Relaxed);
+        self.pool.bytes.fetch_sub(self.bytes, Ordering::Relaxed);
+        self.pool = pool;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.pool.bytes.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// A boxed slice counted towards a pool
+pub struct Counted<T> {
+    data: Box<[T]>,
+    reservation: Reservation,
+}
+
+impl<T> Counted<T> {
+    pub fn new(pool: &'static Pool, data: impl Into<Box<[T]>>) -> Self {
+        let data = data.into();
+        let reservation = pool.reserve(mem::size_of_val(&data[..]));
+        Self { data, reservation }
+    }
+
+    /// Count the data towards `pool` instead
+    pub fn transfer(&mut self, pool: &'static Pool) {
+        self.reservation.transfer(pool);
+    }
+}
+
+impl<T> Deref for Counted<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.data
+    }
+}
+
+impl<T> DerefMut for Counted<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+}
+
+impl<T: PartialEq> PartialEq for Counted<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Counted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.data.fmt(f)
+    }
+}
+
+/// Bytes held by each pool at some moment
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MemReport {
+    pub pools: Vec<PoolUsage>,
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct PoolUsage {
+    pub name: &'static str,
+    pub bytes: usize,
+}
+
+impl MemReport {
+    pub fn total(&self) -> usize {
+        self.pools.iter().map(|x| x.bytes).sum()
+    }
+}
+
+impl fmt::Display for MemReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.pools.iter().map(|x| x.name.len()).max().unwrap_or(0);
+        for pool in &self.pools {
+            writeln!(f, "{:<width$}  {:>10}", pool.name, Bytes(pool.bytes))?;
+        }
+        write!(f, "{:<width$}  {:>10}", "total", Bytes(self.total()))
+    }
+}
+
+/// A size formatted in the largest binary unit it amounts to at least one of
+struct Bytes(usize);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return f.pad(&format!("{} B", self.0));
+        }
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit + 1 < UNITS.len() {
+            value /= 1024.0;
+            unit += 1;
+        }
+        f.pad(&format!("{value:.1} {}", UNITS[unit]))
+    }
+}
+
+/// Bytes of device memory counted by every pool of it
+pub fn gpu_bytes() -> usize {
+    GPU_POOLS.iter().map(|pool| pool.bytes()).sum()
+}
+
+/// Snapshot of every pool
+pub fn report() -> MemReport {
+    MemReport {
+        pools: POOLS
+            .iter()
+            .map(|pool| PoolUsage {
+                name: pool.name(),
+                bytes: pool.bytes(),
+            })
+            .collect(),
+    }
+}
+
+/// What `reclaim_dense_voxels` did
+#[derive(Debug, Clone, Default)]
+pub struct Reclaimed {
+    /// Number of chunks found to be a single material and stored as such
+    pub solidified: usize,
+    /// Chunks evicted, farthest first
+    pub evicted: Vec<ChunkId>,
+}
+
+/// If dense voxels take up more than `budget` bytes, free some in `graph`, first by storing
+/// chunks that turn out to be a single material as such, then by evicting the unmodified chunks
+/// farthest from `center` until back within budget
+///
+/// Chunks within `keep_within` of `center` are never evicted, so that chunks which would only be
+/// generated again straight away are left alone. The budget is soft: voxels held outside `graph`
+/// count towards it, but can't be freed here.
+pub fn reclaim_dense_voxels(
+    graph: &mut Graph,
+    center: &Position,
+    keep_within: f32,
+    budget: usize,
+) -> Reclaimed {
+    let mut result = Reclaimed::default();
+    if DENSE_VOXELS.bytes() <= budget {
+        return result;
+    }
+    let dimension = graph.layout().dimension();
+    let center_to_local = math::mtranspose(&center.local);
+    let mut candidates = Vec::new();
+    for (node, transform) in nearby_nodes(graph, center, f64::INFINITY) {
+        let Some(ref mut node_data) = *graph.get_mut(node) else {
+            continue;
+        };
+        for vertex in Vertex::iter() {
+            let Chunk::Populated {
+                ref mut voxels,
+                modified,
+                ..
+            } = node_data.chunks[vertex]
+            else {
+                continue;
+            };
+            if voxels.solidify(dimension) {
+                result.solidified += 1;
+            } else if !modified && !voxels.is_solid() {
+                let chunk_center = math::lorentz_normalize(
+                    &(center_to_local
+                        * transform
+                        * vertex.chunk_to_node_f32()
+                        * na::Vector4::new(0.5, 0.5, 0.5, 1.0)),
+                );
+                let distance = math::distance(&math::origin(), &chunk_center);
+                if distance > keep_within {
+                    candidates.push((distance, ChunkId::new(node, vertex)));
+                }
+            }
+        }
+    }
+    candidates.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+    for (_, chunk) in candidates {
+        if DENSE_VOXELS.bytes() <= budget {
+            break;
+        }
+        graph.evict_chunk(chunk);
+        result.evicted.push(chunk);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use super::*;
+
+    #[test]
+    fn reservations_are_released_on_unwind() {
+        static POOL: Pool = Pool::new("test");
+        let result = panic::catch_unwind(|| {
+            let _outer = POOL.reserve(100);
+            let mut inner = Counted::new(&POOL, vec![0u32; 10]);
+            inner[3] = 1;
+            assert_eq!(POOL.bytes(), 140);
+            panic!("allocation site failed");
+        });
+        assert!(result.is_err());
+        assert_eq!(POOL.bytes(), 0);
+    }
+
+    #[test]
+    fn transfer_and_resize() {
+        static A: Pool = Pool::new("a");
+        static B: Pool = Pool::new("b");
+        let mut data = Counted::new(&A, vec![0u16; 8]);
+        assert_eq!((A.bytes(), B.bytes()), (16, 0));
+        data.transfer(&B);
+        assert_eq!((A.bytes(), B.bytes()), (0, 16));
+        let mut reservation = A.reserve(5);
+        reservation.resize(2);
+        assert_eq!(A.bytes(), 2);
+        drop(data);
+        drop(reservation);
+        assert_eq!((A.bytes(), B.bytes()), (0, 0));
+    }
+
+    #[test]
+    fn report_formatting() {
+        let report = MemReport {
+            pools: vec![
+                PoolUsage {
+                    name: "dense voxels",
+                    bytes: 3 * 1024 * 1024 / 2,
+                },
+                PoolUsage {
+                    name: "gpu",
+                    bytes: 12,
+                },
+            ],
+        };
+        assert_eq!(
+            report.to_string(),
+            "dense voxels     1.5 MiB\ngpu                 12 B\ntotal            1.5 MiB"
+        );
+    }
+}
@@ -1,6 +1,7 @@
 /*the name of this module is pretty arbitrary at the moment*/
 
-use std::ops::{Index, IndexMut};
+use std::collections::VecDeque;
+use std::ops::{Index, IndexMut, Range};
 
 use serde::{Deserialize, Serialize};
 
@@ -8,10 +9,11 @@ use crate::collision_math::Ray;
 use crate::dodeca::Vertex;
 use crate::graph::{Graph, NodeId};
 use crate::lru_slab::SlotId;
+use crate::mem_budget::{self, Counted, Pool};
 use crate::proto::{BlockUpdate, Position, SerializableVoxelData};
-use crate::world::Material;
+use crate::world::{Material, Shape};
 use crate::worldgen::NodeState;
-use crate::{math, Chunks};
+use crate::{math, Chunks, Occupancy};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ChunkId {
@@ -74,26 +76,7 @@ impl Graph {
         if coords[coord_axis] == self.layout().dimension - 1
             && coord_direction == CoordDirection::Plus
         {
-            let new_vertex = chunk.vertex.adjacent_vertices()[coord_axis as usize];
-            // Permute coordinates based on differences in the canonical orders between the old
-            // and new vertex
-            let [coord_plane0, coord_plane1] = coord_axis.other_axes();
-            let mut new_coords = Coords([0; 3]);
-            for current_axis in CoordAxis::iter() {
-                if new_vertex.canonical_sides()[current_axis as usize]
-                    == chunk.vertex.canonical_sides()[coord_plane0 as usize]
-                {
-                    new_coords[current_axis] = coords[coord_plane0];
-                } else if new_vertex.canonical_sides()[current_axis as usize]
-                    == chunk.vertex.canonical_sides()[coord_plane1 as usize]
-                {
-                    new_coords[current_axis] = coords[coord_plane1];
-                } else {
-                    new_coords[current_axis] = coords[coord_axis];
-                }
-            }
-            coords = new_coords;
-            chunk.vertex = new_vertex;
+            (chunk.vertex, coords) = adjacent_vertex_coords(chunk.vertex, coords, coord_axis);
         } else if coords[coord_axis] == 0 && coord_direction == CoordDirection::Minus {
             chunk.node = self.neighbor(
                 chunk.node,
@@ -106,110 +89,468 @@ impl Graph {
         Some((chunk, coords))
     }
 
-    /// Populates a chunk with the given voxel data and ensures that margins are correctly cleared if necessary.
-    pub fn populate_chunk(&mut self, chunk: ChunkId, mut new_data: VoxelData, modified: bool) {
-        // New solid chunks should have their margin cleared if they are adjacent to any modified chunks.
-        // See the function description of VoxelData::clear_margin for why this is necessary.
-        if new_data.is_solid() {
-            // Loop through all six potential chunk neighbors. If any are modified, the `new_data` should have
-            // its margin cleared.
-            'outer: for coord_axis in CoordAxis::iter() {
-                for coord_direction in CoordDirection::iter() {
-                    if let Some(chunk_id) =
-                        self.get_chunk_neighbor(chunk, coord_axis, coord_direction)
-                    {
-                        if let Chunk::Populated { modified: true, .. } = self[chunk_id] {
-                            new_data.clear_margin(self.layout().dimension);
-                            break 'outer;
-                        }
-                    }
+    /// Express `shape`, given in the coordinates of `chunk`, in those of the chunk containing the
+    /// block `get_block_neighbor` finds from `coords`
+    pub fn shape_in_block_neighbor(
+        &self,
+        chunk: ChunkId,
+        coords: Coords,
+        coord_axis: CoordAxis,
+        coord_direction: CoordDirection,
+        shape: Shape,
+    ) -> Shape {
+        let boundary = match coord_direction {
+            CoordDirection::Plus => self.layout().dimension - 1,
+            CoordDirection::Minus => 0,
+        };
+        if coords[coord_axis] != boundary {
+            return shape;
+        }
+        // Each octant of the new chunk's shape is filled if its counterpart in ours is
+        let counterpart =
+            |octant| neighbor_octant(chunk.vertex, coord_axis, coord_direction, octant);
+        Shape::from_fn(|octant| {
+            Shape::OCTANTS
+                .into_iter()
+                .any(|ours| counterpart(ours) == octant && shape.contains(ours))
+        })
+        .expect("shapes are preserved by symmetries of the cube")
+    }
+
+    /// Populates a chunk with the given voxel data
+    pub fn populate_chunk(&mut self, chunk: ChunkId, new_data: VoxelData, modified: bool) {
+        // Surfaces of neighboring chunks were extracted assuming this chunk was empty
+        let stale_neighbors = !matches!(new_data, VoxelData::Solid(Material::Void));
+
+        let generation = self.next_chunk_generation();
+        let old = std::mem::replace(
+            self.get_chunk_mut(chunk).unwrap(),
+            Chunk::Populated {
+                voxels: new_data,
+                modified,
+                generation,
+                surface: None,
+                old_surface: None,
+                occupancy: Occupancy::default(),
+            },
+        );
+        self.uncount_chunk(&old);
+        let counts = self.chunk_counts_mut();
+        counts.populated += 1;
+        counts.modified += usize::from(modified);
+
+        if stale_neighbors {
+            self.invalidate_neighbor_surfaces(chunk);
+        }
+    }
+
+    /// Discard the data of a chunk, returning it to the `Fresh` state, and return its old state
+    ///
+    /// Only unmodified chunks may be evicted, since world generation can reproduce nothing else.
+    /// Modified chunks stay put until whatever persists their edits has them, as the server does
+    /// by never evicting chunks at all.
+    pub fn evict_chunk(&mut self, chunk: ChunkId) -> Chunk {
+        let slot = self.get_chunk_mut(chunk).unwrap();
+        assert!(
+            !matches!(*slot, Chunk::Populated { modified: true, .. }),
+            "modified chunk evicted"
+        );
+        let old = std::mem::take(slot);
+        self.uncount_chunk(&old);
+        if let Chunk::Populated { ref voxels, .. } = old {
+            if !matches!(*voxels, VoxelData::Solid(Material::Void)) {
+                // Neighbors must now assume this chunk is empty
+                self.invalidate_neighbor_surfaces(chunk);
+            }
+        }
+        old
+    }
+
+    /// Update `chunk_counts` for the removal of `chunk` from the graph
+    fn uncount_chunk(&mut self, chunk: &Chunk) {
+        if let Chunk::Populated { modified, .. } = *chunk {
+            let counts = self.chunk_counts_mut();
+            counts.populated -= 1;
+            counts.modified -= usize::from(modified);
+        }
+    }
+
+    fn invalidate_neighbor_surfaces(&mut self, chunk: ChunkId) {
+        for coord_axis in CoordAxis::iter() {
+            for coord_direction in CoordDirection::iter() {
+                if let Some(neighbor) = self.get_chunk_neighbor(chunk, coord_axis, coord_direction)
+                {
+                    self.invalidate_surface(neighbor);
                 }
             }
         }
+    }
 
-        // Existing adjacent solid chunks should have their margins cleared if the chunk we're populating is modified.
-        // See the function description of VoxelData::clear_margin for why this is necessary.
-        if modified {
-            self.clear_adjacent_solid_chunk_margins(chunk);
+    /// Generation of a populated chunk, which changes whenever its voxels are populated or edited
+    /// through the graph, or `None` if it isn't populated
+    ///
+    /// Anything derived from a chunk's voxels can record the generation it saw to later tell
+    /// whether it's stale.
+    pub fn chunk_generation(&self, chunk: ChunkId) -> Option<u32> {
+        match *self.get_chunk(chunk)? {
+            Chunk::Populated { generation, .. } => Some(generation),
+            _ => None,
         }
+    }
 
-        // After clearing any margins we needed to clear, we can now insert the data into the graph
-        *self.get_chunk_mut(chunk).unwrap() = Chunk::Populated {
-            voxels: new_data,
-            modified,
-            surface: None,
-            old_surface: None,
+    /// Material of a voxel, or `None` if its chunk isn't populated
+    pub fn get_block(&self, chunk: ChunkId, coords: Coords) -> Option<Material> {
+        let Some(Chunk::Populated { voxels, .. }) = self.get_chunk(chunk) else {
+            return None;
+        };
+        Some(voxels.view(self.layout().dimension).get(coords))
+    }
+
+    /// Shape of a voxel, or `None` if its chunk isn't populated
+    pub fn get_shape(&self, chunk: ChunkId, coords: Coords) -> Option<Shape> {
+        let Some(Chunk::Populated { voxels, .. }) = self.get_chunk(chunk) else {
+            return None;
         };
+        Some(voxels.view(self.layout().dimension).shape(coords))
     }
 
-    /// Tries to update the block at the given position to the given material.
-    /// Fails and returns false if the chunk is not populated yet.
+    /// Tries to update the block at the given position to the given material and shape.
+    ///
+    /// Updates that wouldn't change the block have no effect, so applying the same update twice
+    /// is harmless.
     #[must_use]
-    pub fn update_block(&mut self, block_update: &BlockUpdate) -> bool {
+    pub fn update_block(&mut self, block_update: &BlockUpdate) -> BlockUpdateOutcome {
+        self.set_block(
+            block_update.chunk_id,
+            block_update.coords,
+            block_update.new_material,
+            block_update.new_shape,
+        )
+    }
+
+    /// Like `update_block`, for a change not requested by any particular character
+    ///
+    /// Empty voxels are always full cubes, whatever `shape` is given.
+    #[must_use]
+    pub fn set_block(
+        &mut self,
+        chunk: ChunkId,
+        coords: Coords,
+        material: Material,
+        shape: Shape,
+    ) -> BlockUpdateOutcome {
         let dimension = self.layout().dimension;
+        let shape = if material == Material::Void {
+            Shape::FULL
+        } else {
+            shape
+        };
 
         // Update the block
+        let generation = self.next_chunk_generation();
         let Some(Chunk::Populated {
             voxels,
             modified,
+            generation: chunk_generation,
             surface,
             old_surface,
-        }) = self.get_chunk_mut(block_update.chunk_id)
+            occupancy,
+        }) = self.get_chunk_mut(chunk)
         else {
-            return false;
+            return BlockUpdateOutcome::ChunkMissing;
         };
-        if voxels.is_solid() {
-            voxels.clear_margin(dimension);
+        let view = voxels.view(dimension);
+        let (previous, previous_shape) = (view.get(coords), view.shape(coords));
+        if previous == material && previous_shape == shape {
+            return BlockUpdateOutcome::NoChange;
         }
+        let index = coords.to_index(dimension);
         let voxel = voxels
             .data_mut(dimension)
-            .get_mut(block_update.coords.to_index(dimension))
+            .get_mut(index)
             .expect("coords are in-bounds");
 
-        *voxel = block_update.new_material;
-        *modified = true;
+        *voxel = material;
+        voxels.set_shape(dimension, index, shape);
+        occupancy.update(voxels, dimension, coords);
+        *chunk_generation = generation;
+        let newly_modified = !std::mem::replace(modified, true);
         *old_surface = surface.take().or(*old_surface);
+        self.chunk_counts_mut().modified += usize::from(newly_modified);
 
-        self.clear_adjacent_solid_chunk_margins(block_update.chunk_id);
-        true
-    }
-
-    /// Clears margins from any populated and solid adjacent chunks. When a chunk is modified, this function should
-    /// be called on that chunk to ensure that adjacent chunks are rendered, since they can no longer be assumed to be
-    /// hidden by world generation.
-    fn clear_adjacent_solid_chunk_margins(&mut self, chunk: ChunkId) {
+        // Chunks sharing a face with the voxel see it through their margins
         for coord_axis in CoordAxis::iter() {
             for coord_direction in CoordDirection::iter() {
-                if let Some(chunk_id) = self.get_chunk_neighbor(chunk, coord_axis, coord_direction)
+                let boundary = match coord_direction {
+                    CoordDirection::Plus => dimension - 1,
+                    CoordDirection::Minus => 0,
+                };
+                if coords[coord_axis] != boundary {
+                    continue;
+                }
+                if let Some(neighbor) = self.get_chunk_neighbor(chunk, coord_axis, coord_direction)
                 {
-                    // We only need to clear margins from populated chunks.
-                    let _ = self.clear_solid_chunk_margin(chunk_id);
+                    self.invalidate_surface(neighbor);
                 }
             }
         }
+        BlockUpdateOutcome::Applied {
+            previous,
+            previous_shape,
+        }
     }
 
-    /// Tries to clear the margins of the given chunk. Fails and returns false if the
-    /// chunk is not populated yet. Succeeds and returns true if the chunk is not Solid, as the
-    /// chunk is assumed to have empty margins already.
-    #[must_use]
-    fn clear_solid_chunk_margin(&mut self, chunk: ChunkId) -> bool {
+    /// Materials and shapes of the voxels in the chunk adjacent to `chunk` that share a face with
+    /// `chunk`'s voxels on its `coord_direction` side along `coord_axis`, or `None` if that chunk
+    /// isn't populated
+    pub fn get_boundary_layer(
+        &self,
+        chunk: ChunkId,
+        coord_axis: CoordAxis,
+        coord_direction: CoordDirection,
+    ) -> Option<BoundaryLayer> {
+        let neighbor = self.get_chunk_neighbor(chunk, coord_axis, coord_direction)?;
+        let Chunk::Populated { ref voxels, .. } = *self.get_chunk(neighbor)? else {
+            return None;
+        };
         let dimension = self.layout().dimension;
-        let Some(Chunk::Populated {
-            voxels,
+        if let VoxelData::Solid(material) = *voxels {
+            return Some(BoundaryLayer::Solid(material));
+        }
+
+        // The neighbor's boundary voxels have the same coordinate along the shared axis as ours,
+        // but the other axes are permuted when the neighbor belongs to a different vertex.
+        let [u_axis, v_axis] = coord_axis.other_axes();
+        let mut layer = Vec::with_capacity(usize::from(dimension).pow(2));
+        let mut shapes = matches!(*voxels, VoxelData::Shaped(..))
+            .then(|| Vec::with_capacity(usize::from(dimension).pow(2)));
+        let view = voxels.view(dimension);
+        for v in 0..dimension {
+            for u in 0..dimension {
+                let mut coords = Coords([0; 3]);
+                coords[u_axis] = u;
+                coords[v_axis] = v;
+                coords = match coord_direction {
+                    CoordDirection::Plus => {
+                        coords[coord_axis] = dimension - 1;
+                        adjacent_vertex_coords(chunk.vertex, coords, coord_axis).1
+                    }
+                    CoordDirection::Minus => coords,
+                };
+                layer.push(view.get(coords));
+                if let Some(ref mut shapes) = shapes {
+                    // Shapes are likewise mirrored and permuted
+                    let shape = view.shape(coords);
+                    shapes.push(
+                        Shape::from_fn(|octant| {
+                            shape.contains(neighbor_octant(
+                                chunk.vertex,
+                                coord_axis,
+                                coord_direction,
+                                octant,
+                            ))
+                        })
+                        .expect("shapes are preserved by symmetries of the cube"),
+                    );
+                }
+            }
+        }
+        Some(BoundaryLayer::Dense {
+            materials: layer.into(),
+            shapes: shapes.map(Vec::into_boxed_slice),
+        })
+    }
+
+    /// Whether any face of a voxel in `chunk` is visible, as judged by `Material::shows_face_to`,
+    /// considering the margins that `write_padded_voxels` would produce
+    pub fn has_visible_faces(&self, chunk: ChunkId) -> bool {
+        let Some(Chunk::Populated { ref voxels, .. }) = self.get_chunk(chunk) else {
+            return false;
+        };
+        let material = match *voxels {
+            VoxelData::Dense(_) | VoxelData::Shaped(..) => return true,
+            VoxelData::Solid(Material::Void) => return false,
+            VoxelData::Solid(material) => material,
+        };
+        let visible = |neighbor: Material| {
+            material.shows_face_to(neighbor) || neighbor.shows_face_to(material)
+        };
+        // A solid chunk's faces are all on its boundary
+        CoordAxis::iter().any(|coord_axis| {
+            CoordDirection::iter().any(|coord_direction| {
+                // Our faces show wherever a neighbor leaves the face it shares with us uncovered
+                let facing = match coord_direction {
+                    CoordDirection::Plus => CoordDirection::Minus,
+                    CoordDirection::Minus => CoordDirection::Plus,
+                };
+                match self.get_boundary_layer(chunk, coord_axis, coord_direction) {
+                    None => true,
+                    Some(BoundaryLayer::Solid(neighbor)) => visible(neighbor),
+                    Some(BoundaryLayer::Dense {
+                        ref materials,
+                        ref shapes,
+                    }) => materials.iter().enumerate().any(|(i, &x)| {
+                        visible(x)
+                            || shapes
+                                .as_ref()
+                                .is_some_and(|shapes| !shapes[i].covers_face(coord_axis, facing))
+                    }),
+                }
+            })
+        })
+    }
+
+    /// Write the materials and shapes of the voxels of `chunk`, including a one-voxel margin on each
+    /// side, into `materials` and `shapes`, returning false if the chunk isn't populated
+    ///
+    /// Margins on the faces of the chunk are taken from the adjacent chunks, or treated as void where
+    /// those aren't populated. Margins along the edges and corners of the chunk, which only affect
+    /// ambient occlusion, are left as world generation produced them.
+    pub fn write_padded_voxels(
+        &self,
+        chunk: ChunkId,
+        materials: &mut [Material],
+        shapes: &mut [Shape],
+    ) -> bool {
+        let Some(Chunk::Populated { ref voxels, .. }) = self.get_chunk(chunk) else {
+            return false;
+        };
+        match *voxels {
+            VoxelData::Dense(ref data) => {
+                materials.copy_from_slice(data);
+                shapes.fill(Shape::FULL);
+            }
+            VoxelData::Shaped(ref data, ref data_shapes) => {
+                materials.copy_from_slice(data);
+                shapes.copy_from_slice(data_shapes);
+            }
+            VoxelData::Solid(material) => {
+                materials.fill(material);
+                shapes.fill(Shape::FULL);
+            }
+        }
+
+        let dimension = self.layout().dimension;
+        for coord_axis in CoordAxis::iter() {
+            let [u_axis, v_axis] = coord_axis.other_axes();
+            for coord_direction in CoordDirection::iter() {
+                let layer = self.get_boundary_layer(chunk, coord_axis, coord_direction);
+                let mut coords = MarginCoords([0; 3]);
+                coords[coord_axis] = match coord_direction {
+                    CoordDirection::Plus => dimension + 1,
+                    CoordDirection::Minus => 0,
+                };
+                for v in 0..dimension {
+                    for u in 0..dimension {
+                        coords[u_axis] = u + 1;
+                        coords[v_axis] = v + 1;
+                        let i = coords.to_index(dimension);
+                        (materials[i], shapes[i]) = layer
+                            .as_ref()
+                            .map_or((Material::Void, Shape::FULL), |layer| {
+                                (layer.get(dimension, u, v), layer.shape(dimension, u, v))
+                            });
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Discard the surface extracted for `chunk`, if any, so that it's extracted again. The old
+    /// surface continues to be drawn in the meantime.
+    fn invalidate_surface(&mut self, chunk: ChunkId) {
+        if let Some(Chunk::Populated {
             surface,
             old_surface,
             ..
         }) = self.get_chunk_mut(chunk)
-        else {
-            return false;
-        };
-
-        if voxels.is_solid() {
-            voxels.clear_margin(dimension);
+        {
             *old_surface = surface.take().or(*old_surface);
         }
-        true
+    }
+}
+
+/// Find the chunk adjacent to `vertex`'s chunk in the positive direction along `coord_axis`, and the
+/// coordinates in that chunk corresponding to `coords`, which lie on the shared boundary
+///
+/// The coordinate along `coord_axis` is preserved, with the others permuted based on differences
+/// in the canonical orders between the old and new vertex.
+fn adjacent_vertex_coords(
+    vertex: Vertex,
+    coords: Coords,
+    coord_axis: CoordAxis,
+) -> (Vertex, Coords) {
+    let new_vertex = vertex.adjacent_vertices()[coord_axis as usize];
+    let [coord_plane0, coord_plane1] = coord_axis.other_axes();
+    let mut new_coords = Coords([0; 3]);
+    for current_axis in CoordAxis::iter() {
+        if new_vertex.canonical_sides()[current_axis as usize]
+            == vertex.canonical_sides()[coord_plane0 as usize]
+        {
+            new_coords[current_axis] = coords[coord_plane0];
+        } else if new_vertex.canonical_sides()[current_axis as usize]
+            == vertex.canonical_sides()[coord_plane1 as usize]
+        {
+            new_coords[current_axis] = coords[coord_plane1];
+        } else {
+            new_coords[current_axis] = coords[coord_axis];
+        }
+    }
+    (new_vertex, new_coords)
+}
+
+/// The octant of a voxel in the chunk adjacent to `vertex`'s chunk along `coord_axis` in
+/// `coord_direction` that coincides with `octant` of a voxel in `vertex`'s chunk, when the two
+/// voxels share a face on the chunks' boundary
+///
+/// Coordinates along `coord_axis` increase towards the boundary in one chunk and away from it in the
+/// other, so octants are mirrored along it as well as being permuted like `adjacent_vertex_coords`.
+fn neighbor_octant(
+    vertex: Vertex,
+    coord_axis: CoordAxis,
+    coord_direction: CoordDirection,
+    octant: [u8; 3],
+) -> [u8; 3] {
+    let mut mirrored = Coords(octant);
+    mirrored[coord_axis] ^= 1;
+    match coord_direction {
+        CoordDirection::Plus => adjacent_vertex_coords(vertex, mirrored, coord_axis).1 .0,
+        CoordDirection::Minus => mirrored.0,
+    }
+}
+
+/// Materials and shapes of the voxels just beyond one face of a chunk
+pub enum BoundaryLayer {
+    Solid(Material),
+    Dense {
+        /// `dimension * dimension` materials, indexed by `u + v * dimension`, where `u` and `v`
+        /// are coordinates along the face's `CoordAxis::other_axes` in the chunk the face belongs to
+        materials: Box<[Material]>,
+        /// Shapes indexed like `materials` and given in the coordinates of the chunk the face
+        /// belongs to, or `None` if they're all full cubes
+        shapes: Option<Box<[Shape]>>,
+    },
+}
+
+impl BoundaryLayer {
+    pub fn get(&self, dimension: u8, u: u8, v: u8) -> Material {
+        match *self {
+            BoundaryLayer::Solid(material) => material,
+            BoundaryLayer::Dense { ref materials, .. } => {
+                materials[usize::from(u) + usize::from(v) * usize::from(dimension)]
+            }
+        }
+    }
+
+    pub fn shape(&self, dimension: u8, u: u8, v: u8) -> Shape {
+        match *self {
+            BoundaryLayer::Dense {
+                shapes: Some(ref shapes),
+                ..
+            } => shapes[usize::from(u) + usize::from(v) * usize::from(dimension)],
+            _ => Shape::FULL,
+        }
     }
 }
 
@@ -228,16 +569,25 @@ impl IndexMut<ChunkId> for Graph {
 }
 
 /// Coordinates for a discrete voxel within a chunk, not including margins
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Coords(pub [u8; 3]);
 
 impl Coords {
     /// Returns the array index in `VoxelData` corresponding to these coordinates
     pub fn to_index(&self, chunk_size: u8) -> usize {
-        let chunk_size_with_margin = chunk_size as usize + 2;
-        (self.0[0] as usize + 1)
-            + (self.0[1] as usize + 1) * chunk_size_with_margin
-            + (self.0[2] as usize + 1) * chunk_size_with_margin.pow(2)
+        debug_assert!(
+            self.0.iter().all(|&x| x < chunk_size),
+            "{self:?} out of bounds of a chunk of size {chunk_size}"
+        );
+        MarginCoords::from(*self).to_index(chunk_size)
+    }
+
+    /// Every voxel of a chunk of size `chunk_size`, in the order they're stored: x varying fastest,
+    /// then y, then z
+    pub fn all(chunk_size: u8) -> impl Iterator<Item = Coords> {
+        (0..chunk_size).flat_map(move |z| {
+            (0..chunk_size).flat_map(move |y| (0..chunk_size).map(move |x| Coords([x, y, z])))
+        })
     }
 }
 
@@ -255,6 +605,70 @@ impl IndexMut<CoordAxis> for Coords {
     }
 }
 
+/// Coordinates for a discrete voxel within a chunk's `VoxelData`, including margins
+///
+/// Each coordinate ranges from 0 to `chunk_size + 1` inclusive, 0 and `chunk_size + 1` lying in the
+/// margins. The voxel at `Coords([0, 0, 0])` is at `MarginCoords([1, 1, 1])`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MarginCoords(pub [u8; 3]);
+
+impl MarginCoords {
+    /// Returns the array index in `VoxelData` corresponding to these coordinates
+    ///
+    /// Coordinates beyond the margins are caught in debug builds, and may otherwise index another
+    /// voxel.
+    pub fn to_index(&self, chunk_size: u8) -> usize {
+        let chunk_size_with_margin = usize::from(chunk_size) + 2;
+        debug_assert!(
+            self.0
+                .iter()
+                .all(|&x| usize::from(x) < chunk_size_with_margin),
+            "{self:?} out of bounds of a chunk of size {chunk_size} with margins"
+        );
+        usize::from(self.0[0])
+            + usize::from(self.0[1]) * chunk_size_with_margin
+            + usize::from(self.0[2]) * chunk_size_with_margin.pow(2)
+    }
+
+    /// The voxel of the chunk proper at these coordinates, or `None` if they're in the margins
+    pub fn to_coords(self, chunk_size: u8) -> Option<Coords> {
+        self.0
+            .iter()
+            .all(|&x| (1..=chunk_size).contains(&x))
+            .then(|| Coords(self.0.map(|x| x - 1)))
+    }
+
+    /// Every voxel of a chunk of size `chunk_size`, margins included, in the order they're stored
+    pub fn all(chunk_size: u8) -> impl Iterator<Item = MarginCoords> {
+        let chunk_size_with_margin = chunk_size + 2;
+        (0..chunk_size_with_margin).flat_map(move |z| {
+            (0..chunk_size_with_margin).flat_map(move |y| {
+                (0..chunk_size_with_margin).map(move |x| MarginCoords([x, y, z]))
+            })
+        })
+    }
+}
+
+impl From<Coords> for MarginCoords {
+    fn from(coords: Coords) -> Self {
+        MarginCoords(coords.0.map(|x| x + 1))
+    }
+}
+
+impl Index<CoordAxis> for MarginCoords {
+    type Output = u8;
+
+    fn index(&self, coord_axis: CoordAxis) -> &u8 {
+        self.0.index(coord_axis as usize)
+    }
+}
+
+impl IndexMut<CoordAxis> for MarginCoords {
+    fn index_mut(&mut self, coord_axis: CoordAxis) -> &mut u8 {
+        self.0.index_mut(coord_axis as usize)
+    }
+}
+
 pub struct Node {
     pub state: NodeState,
     /// We can only populate chunks which lie within a cube of populated nodes, so nodes on the edge
@@ -262,6 +676,20 @@ pub struct Node {
     pub chunks: Chunks<Chunk>,
 }
 
+/// Result of `Graph::update_block`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockUpdateOutcome {
+    /// The block was changed from `previous`, shaped as `previous_shape`
+    Applied {
+        previous: Material,
+        previous_shape: Shape,
+    },
+    /// The block already had the requested material and shape, so nothing was done
+    NoChange,
+    /// The chunk isn't populated yet
+    ChunkMissing,
+}
+
 #[derive(Default)]
 pub enum Chunk {
     #[default]
@@ -270,20 +698,43 @@ pub enum Chunk {
     Populated {
         voxels: VoxelData,
         modified: bool,
+        /// Changes whenever `voxels` are replaced or edited through the `Graph`, and differs from
+        /// any generation the chunk had while previously populated
+        generation: u32,
         surface: Option<SlotId>,
         old_surface: Option<SlotId>,
+        /// Which parts of `voxels` are empty, for collision checks
+        occupancy: Occupancy,
     },
 }
 
+/// Voxels of a chunk, with a one-voxel margin on each side unless `Solid`
+///
+/// Dense data is counted towards `mem_budget::DENSE_VOXELS` when built from a `Vec`.
 pub enum VoxelData {
     Solid(Material),
-    Dense(Box<[Material]>),
+    Dense(Counted<Material>),
+    /// Like `Dense`, along with the shape of each voxel, for chunks where not every voxel is a full
+    /// cube. Void voxels are always full cubes.
+    Shaped(Counted<Material>, Counted<Shape>),
+}
+
+impl From<Vec<Material>> for Counted<Material> {
+    fn from(x: Vec<Material>) -> Self {
+        Counted::new(&mem_budget::DENSE_VOXELS, x)
+    }
+}
+
+impl From<Vec<Shape>> for Counted<Shape> {
+    fn from(x: Vec<Shape>) -> Self {
+        Counted::new(&mem_budget::DENSE_VOXELS, x)
+    }
 }
 
 impl VoxelData {
     pub fn data_mut(&mut self, dimension: u8) -> &mut [Material] {
         match *self {
-            VoxelData::Dense(ref mut d) => d,
+            VoxelData::Dense(ref mut d) | VoxelData::Shaped(ref mut d, _) => &mut d[..],
             VoxelData::Solid(mat) => {
                 *self = VoxelData::Dense(vec![mat; (usize::from(dimension) + 2).pow(3)].into());
                 self.data_mut(dimension)
@@ -291,84 +742,196 @@ impl VoxelData {
         }
     }
 
-    pub fn get(&self, index: usize) -> Material {
+    /// Voxels of a chunk of size `dimension`, addressed by their coordinates
+    pub fn view(&self, dimension: u8) -> VoxelView<'_> {
+        VoxelView {
+            data: self,
+            dimension,
+        }
+    }
+
+    fn get(&self, index: usize) -> Material {
         match *self {
-            VoxelData::Dense(ref d) => d[index],
+            VoxelData::Dense(ref d) | VoxelData::Shaped(ref d, _) => d[index],
             VoxelData::Solid(mat) => mat,
         }
     }
 
-    /// Replaces all voxels in the margin of this chunk with the "Void" material. This function is a coarse
-    /// way to ensure that chunks are fully rendered when they need to be, avoiding a rendering bug caused
-    /// by a voxel's surface failing to render because of a margin being solid.
-    /// Until margins are fully implemented, any solid chunk produced by world generation should have its
-    /// margins cleared if it, or any chunk adjacent to it, is edited, since otherwise, the margins could
-    /// be inaccurate.
-    pub fn clear_margin(&mut self, dimension: u8) {
-        let data = self.data_mut(dimension);
-        let lwm = usize::from(dimension) + 2;
-        for z in 0..lwm {
-            for y in 0..lwm {
-                for x in 0..lwm {
-                    if x == 0 || x == lwm - 1 || y == 0 || y == lwm - 1 || z == 0 || z == lwm - 1 {
-                        // The current coordinates correspond to a margin point. Set it to void.
-                        data[x + y * lwm + z * lwm.pow(2)] = Material::Void;
-                    }
-                }
-            }
+    fn shape(&self, index: usize) -> Shape {
+        match *self {
+            VoxelData::Shaped(_, ref shapes) => shapes[index],
+            VoxelData::Dense(_) | VoxelData::Solid(_) => Shape::FULL,
         }
     }
 
+    /// Change the shape of the voxel at `index`, keeping track of shapes only once a voxel isn't a
+    /// full cube
+    pub fn set_shape(&mut self, dimension: u8, index: usize, shape: Shape) {
+        if let VoxelData::Shaped(_, ref mut shapes) = *self {
+            shapes[index] = shape;
+            return;
+        }
+        if shape.is_full() {
+            return;
+        }
+        self.data_mut(dimension);
+        let VoxelData::Dense(materials) = std::mem::replace(self, VoxelData::Solid(Material::Void))
+        else {
+            unreachable!("data_mut makes data dense");
+        };
+        let mut shapes = vec![Shape::FULL; materials.len()];
+        shapes[index] = shape;
+        *self = VoxelData::Shaped(materials, shapes.into());
+    }
+
     pub fn is_solid(&self) -> bool {
         match *self {
-            VoxelData::Dense(_) => false,
+            VoxelData::Dense(_) | VoxelData::Shaped(..) => false,
             VoxelData::Solid(_) => true,
         }
     }
 
+    /// Store dense data as `Solid` if every voxel is a full cube of the same material, returning
+    /// whether it was. Margins are disregarded, as when world generation makes a chunk solid.
+    pub fn solidify(&mut self, dimension: u8) -> bool {
+        let (VoxelData::Dense(ref data) | VoxelData::Shaped(ref data, _)) = *self else {
+            return false;
+        };
+        let material = data[Coords([0, 0, 0]).to_index(dimension)];
+        let view = self.view(dimension);
+        if !Coords::all(dimension)
+            .all(|coords| view.get(coords) == material && view.shape(coords).is_full())
+        {
+            return false;
+        }
+        *self = VoxelData::Solid(material);
+        true
+    }
+
+    /// Count dense data towards `pool` rather than wherever it's counted now
+    pub fn transfer(&mut self, pool: &'static Pool) {
+        match *self {
+            VoxelData::Solid(_) => {}
+            VoxelData::Dense(ref mut data) => data.transfer(pool),
+            VoxelData::Shaped(ref mut data, ref mut shapes) => {
+                data.transfer(pool);
+                shapes.transfer(pool);
+            }
+        }
+    }
+
     /// Returns a `VoxelData` with void margins based on the given `SerializableVoxelData`, or `None` if
     /// the `SerializableVoxelData` came from a `VoxelData` with the wrong dimension.
     pub fn from_serializable(serializable: &SerializableVoxelData, dimension: u8) -> Option<Self> {
-        if serializable.voxels.len() != usize::from(dimension).pow(3) {
+        let len = usize::from(dimension).pow(3);
+        if serializable.voxels.len() != len
+            || !(serializable.shapes.is_empty() || serializable.shapes.len() == len)
+        {
             return None;
         }
 
         let mut data = vec![Material::Void; (usize::from(dimension) + 2).pow(3)];
-        let mut input_index = 0;
-        for z in 0..dimension {
-            for y in 0..dimension {
-                for x in 0..dimension {
-                    // We cannot use a linear copy here because `data` has margins, while `serializable.voxels` does not.
-                    data[Coords([x, y, z]).to_index(dimension)] = serializable.voxels[input_index];
-                    input_index += 1;
+        let mut shapes = vec![Shape::FULL; data.len()];
+        // We cannot use a linear copy here because `data` has margins, while `serializable.voxels` does not.
+        for (input_index, coords) in Coords::all(dimension).enumerate() {
+            let index = coords.to_index(dimension);
+            data[index] = serializable.voxels[input_index];
+            if let Some(&shape) = serializable.shapes.get(input_index) {
+                if data[index] != Material::Void {
+                    shapes[index] = shape;
                 }
             }
         }
-        Some(VoxelData::Dense(data.into_boxed_slice()))
+        if shapes.iter().all(|shape| shape.is_full()) {
+            Some(VoxelData::Dense(data.into()))
+        } else {
+            Some(VoxelData::Shaped(data.into(), shapes.into()))
+        }
     }
 
-    /// Returns a `SerializableVoxelData` corresponding to `self`. Assumes that`self` is `Dense` and
-    /// has the right dimension, as it will panic or return incorrect data otherwise.
+    /// Returns a `SerializableVoxelData` corresponding to `self`. Assumes that`self` is `Dense` or
+    /// `Shaped` and has the right dimension, as it will panic or return incorrect data otherwise.
     pub fn to_serializable(&self, dimension: u8) -> SerializableVoxelData {
-        let VoxelData::Dense(data) = self else {
-            panic!("Only dense chunks can be serialized.");
+        let (data, shapes) = match self {
+            VoxelData::Dense(data) => (data, None),
+            VoxelData::Shaped(data, shapes) => (data, Some(shapes)),
+            VoxelData::Solid(_) => panic!("Only dense chunks can be serialized."),
         };
 
-        let mut serializable: Vec<Material> = Vec::with_capacity(usize::from(dimension).pow(3));
-        for z in 0..dimension {
-            for y in 0..dimension {
-                for x in 0..dimension {
-                    // We cannot use a linear copy here because `data` has margins, while `serializable.voxels` does not.
-                    serializable.push(data[Coords([x, y, z]).to_index(dimension)]);
-                }
+        let len = usize::from(dimension).pow(3);
+        let mut serializable: Vec<Material> = Vec::with_capacity(len);
+        let mut serializable_shapes = Vec::with_capacity(if shapes.is_some() { len } else { 0 });
+        // We cannot use a linear copy here because `data` has margins, while `serializable.voxels` does not.
+        for coords in Coords::all(dimension) {
+            let index = coords.to_index(dimension);
+            serializable.push(data[index]);
+            if let Some(shapes) = shapes {
+                serializable_shapes.push(shapes[index]);
             }
         }
         SerializableVoxelData {
             voxels: serializable,
+            shapes: serializable_shapes,
         }
     }
 }
 
+/// Read access to the voxels of a chunk, addressed by coordinates rather than by index into the
+/// underlying data
+#[derive(Copy, Clone)]
+pub struct VoxelView<'a> {
+    data: &'a VoxelData,
+    dimension: u8,
+}
+
+impl<'a> VoxelView<'a> {
+    pub fn dimension(&self) -> u8 {
+        self.dimension
+    }
+
+    /// Material of the voxel at `coords`, which may lie in the margins if given as `MarginCoords`
+    ///
+    /// Coordinates out of bounds are caught in debug builds, even for `Solid` data.
+    #[inline]
+    pub fn get(&self, coords: impl Into<MarginCoords>) -> Material {
+        self.data.get(coords.into().to_index(self.dimension))
+    }
+
+    /// Shape of the voxel at `coords`, as in `get`
+    #[inline]
+    pub fn shape(&self, coords: impl Into<MarginCoords>) -> Shape {
+        self.data.shape(coords.into().to_index(self.dimension))
+    }
+
+    /// Like `get`, without bounds checks outside of debug builds
+    ///
+    /// # Safety
+    ///
+    /// Each of `coords` must be at most `dimension + 1`.
+    #[inline]
+    pub unsafe fn get_unchecked(&self, coords: impl Into<MarginCoords>) -> Material {
+        let index = coords.into().to_index(self.dimension);
+        match *self.data {
+            VoxelData::Dense(ref d) | VoxelData::Shaped(ref d, _) => *d.get_unchecked(index),
+            VoxelData::Solid(mat) => mat,
+        }
+    }
+
+    /// Materials of every voxel including margins, in the order `MarginCoords::all` visits them,
+    /// or `None` if the data is `Solid`
+    pub fn as_slice(&self) -> Option<&'a [Material]> {
+        match *self.data {
+            VoxelData::Dense(ref d) | VoxelData::Shaped(ref d, _) => Some(&d[..]),
+            VoxelData::Solid(_) => None,
+        }
+    }
+
+    /// Every voxel of the chunk proper and its material, in the order they're stored
+    pub fn iter(self) -> impl Iterator<Item = (Coords, Material)> + 'a {
+        Coords::all(self.dimension).map(move |coords| (coords, self.get(coords)))
+    }
+}
+
 /// Contains the context needed to know the locations of individual cubes within a chunk in the chunk's coordinate
 /// system. A given `ChunkLayout` is uniquely determined by its dimension.
 pub struct ChunkLayout {
@@ -380,7 +943,7 @@ impl ChunkLayout {
     pub fn new(dimension: u8) -> Self {
         ChunkLayout {
             dimension,
-            dual_to_grid_factor: Vertex::dual_to_chunk_factor() as f32 * dimension as f32,
+            dual_to_grid_factor: Vertex::dual_to_chunk_factor_f32() * dimension as f32,
         }
     }
 
@@ -418,7 +981,7 @@ impl ChunkLayout {
 
     /// Takes in a single grid coordinate and returns a range containing all voxel coordinates surrounding it.
     #[inline]
-    pub fn neighboring_voxels(&self, grid_coord: u8) -> impl Iterator<Item = u8> {
+    pub fn neighboring_voxels(&self, grid_coord: u8) -> Range<u8> {
         grid_coord.saturating_sub(1)..grid_coord.saturating_add(1).min(self.dimension())
     }
 }
@@ -429,11 +992,96 @@ pub fn populate_fresh_nodes(graph: &mut Graph) {
     let fresh = graph.fresh().to_vec();
     graph.clear_fresh();
     for &node in &fresh {
-        populate_node(graph, node);
+        populate_with_dependencies(graph, node);
+    }
+}
+
+/// Fresh nodes awaiting population, for spreading the work of populating large numbers of new nodes
+/// over several frames or steps
+///
+/// A node's state is derived from those of its shorter neighbors, so nodes are populated in the
+/// order they were created, and any node populated out of turn has its dependencies populated
+/// first.
+#[derive(Debug, Default)]
+pub struct PopulationQueue {
+    pending: VecDeque<NodeId>,
+}
+
+impl PopulationQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take over population of the nodes created since the last call to `Graph::clear_fresh`
+    pub fn enqueue_fresh(&mut self, graph: &mut Graph) {
+        self.pending.extend(graph.fresh());
+        graph.clear_fresh();
+    }
+
+    /// Number of nodes that may still need populating
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Populate every node in `urgent`, then queued nodes in order until a total of `budget` nodes
+    /// have been populated, returning the number populated
+    ///
+    /// Urgent nodes are populated even if that exceeds `budget`.
+    pub fn run(
+        &mut self,
+        graph: &mut Graph,
+        urgent: impl IntoIterator<Item = NodeId>,
+        budget: usize,
+    ) -> usize {
+        let mut count = 0;
+        for node in urgent {
+            count += populate_with_dependencies(graph, node);
+        }
+        while count < budget {
+            let Some(node) = self.pending.pop_front() else {
+                break;
+            };
+            count += populate_with_dependencies(graph, node);
+        }
+        count
+    }
+}
+
+/// Populate `node` if it isn't already, along with any unpopulated nodes its state is derived from,
+/// returning the number of nodes populated
+pub fn populate_with_dependencies(graph: &mut Graph, node: NodeId) -> usize {
+    let mut count = 0;
+    let mut stack = vec![node];
+    while let Some(&next) = stack.last() {
+        if graph.get(next).is_some() {
+            stack.pop();
+            continue;
+        }
+        let depth = stack.len();
+        stack.extend(
+            graph
+                .descenders(next)
+                .map(|(_, x)| x)
+                .filter(|&x| graph.get(x).is_none()),
+        );
+        if stack.len() == depth {
+            populate_node(graph, next);
+            count += 1;
+            stack.pop();
+        }
     }
+    count
 }
 
 fn populate_node(graph: &mut Graph, node: NodeId) {
+    debug_assert!(
+        graph.descenders(node).all(|(_, x)| graph.get(x).is_some()),
+        "node populated before the nodes its state derives from"
+    );
     *graph.get_mut(node) = Some(Node {
         state: graph
             .parent(node)
@@ -447,7 +1095,7 @@ fn populate_node(graph: &mut Graph, node: NodeId) {
 }
 
 /// Represents a particular axis in a voxel grid.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CoordAxis {
     X = 0,
     Y = 1,
@@ -491,7 +1139,7 @@ impl TryFrom<usize> for CoordAxis {
 /// Represents a direction in a particular axis. This struct is meant to be used with a coordinate axis,
 /// so when paired with the X-axis, it represents the postitive X-direction when set to Plus and the
 /// negative X-direction when set to Minus.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CoordDirection {
     Plus = 1,
     Minus = -1,
@@ -576,19 +1224,784 @@ impl VoxelAABB {
     pub fn grid_planes(&self, axis: usize) -> impl Iterator<Item = u8> {
         self.bounds[axis][0]..self.bounds[axis][1]
     }
+
+    /// Iterator over the coordinates of voxels of a chunk with the given dimension that overlap the region
+    pub fn voxels(&self, dimension: u8) -> impl Iterator<Item = [u8; 3]> {
+        let range = |axis: usize| {
+            self.bounds[axis][0].saturating_sub(1)..self.bounds[axis][1].min(dimension)
+        };
+        let (xs, ys, zs) = (range(0), range(1), range(2));
+        zs.flat_map(move |z| {
+            let xs = xs.clone();
+            ys.clone()
+                .flat_map(move |y| xs.clone().map(move |x| [x, y, z]))
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
 
-    use crate::math;
+    use crate::{
+        dodeca::{self, Side},
+        math,
+        traversal::{ensure_nearby, nearby_nodes},
+    };
 
     use super::*;
 
+    const DIMENSION: u8 = 4;
+
+    /// Materials and shapes of a chunk's voxels, including margins
+    type Padded = (Vec<Material>, Vec<Shape>);
+
+    /// Number of faces the surface extraction shader would produce from `padded` voxels
+    fn count_faces(padded: &Padded) -> usize {
+        let (opaque, transparent) = count_surface_faces(padded);
+        opaque + transparent
+    }
+
+    /// Numbers of opaque and transparent faces the surface extraction shader would produce from
+    /// `padded` voxels
+    fn count_surface_faces((materials, shapes): &Padded) -> (usize, usize) {
+        let dimension = i32::from(DIMENSION);
+        let get = |c: [i32; 3]| {
+            let index = MarginCoords(c.map(|x| (x + 1) as u8)).to_index(DIMENSION);
+            (materials[index], shapes[index])
+        };
+        // Quarters of the layer of octants in the `half` of `voxel` along `axis` that are filled
+        let layer = |(material, shape): (Material, Shape), axis: usize, half: u8| {
+            let mut quarters = 0u8;
+            for j in 0..2 {
+                for i in 0..2 {
+                    let mut octant = [0; 3];
+                    octant[axis] = half;
+                    octant[(axis + 1) % 3] = i;
+                    octant[(axis + 2) % 3] = j;
+                    if material != Material::Void && shape.contains(octant) {
+                        quarters |= 1 << (i + 2 * j);
+                    }
+                }
+            }
+            quarters
+        };
+        // Number of rectangles the shader divides `quarters` into
+        let count_parts = |mut quarters: u8| {
+            if quarters == 0xF {
+                return 1;
+            }
+            let mut count = 0;
+            for half in [0x5, 0xA, 0x3, 0xC] {
+                if quarters & half == half {
+                    count += 1;
+                    quarters &= !half;
+                }
+            }
+            (count + quarters.count_ones() as usize).min(2)
+        };
+        let in_bounds = |c: [i32; 3]| c.iter().all(|&x| (0..dimension).contains(&x));
+        let mut faces = (0, 0);
+        let mut add = |material: Material, quarters: u8| {
+            let count = count_parts(quarters);
+            if material.is_transparent() {
+                faces.1 += count;
+            } else {
+                faces.0 += count;
+            }
+        };
+        for z in 0..=dimension {
+            for y in 0..=dimension {
+                for x in 0..=dimension {
+                    let voxel = [x, y, z];
+                    for axis in 0..3 {
+                        let mut neighbor = voxel;
+                        neighbor[axis] -= 1;
+                        let (ours, theirs) = (get(voxel), get(neighbor));
+                        if in_bounds(voxel) {
+                            let (near, far) = (layer(ours, axis, 0), layer(ours, axis, 1));
+                            add(ours.0, far & !near);
+                            add(ours.0, near & !far);
+                        }
+                        if voxel.iter().any(|&c| c >= dimension)
+                            && neighbor.iter().any(|&c| c >= dimension)
+                        {
+                            continue;
+                        }
+                        for (solid, other, (filled, other_filled)) in [
+                            (
+                                voxel,
+                                neighbor,
+                                (layer(ours, axis, 0), layer(theirs, axis, 1)),
+                            ),
+                            (
+                                neighbor,
+                                voxel,
+                                (layer(theirs, axis, 1), layer(ours, axis, 0)),
+                            ),
+                        ] {
+                            let material = get(solid).0;
+                            if material.is_transparent() && !in_bounds(solid) {
+                                continue;
+                            }
+                            if material.shows_face_to(get(other).0) {
+                                add(material, filled);
+                            } else {
+                                add(material, filled & !other_filled);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        faces
+    }
+
+    fn padded(graph: &Graph, chunk: ChunkId) -> Padded {
+        let len = (usize::from(DIMENSION) + 2).pow(3);
+        let mut out = (vec![Material::Void; len], vec![Shape::FULL; len]);
+        assert!(graph.write_padded_voxels(chunk, &mut out.0, &mut out.1));
+        out
+    }
+
+    /// A graph around the origin whose chunks are all solid `material`
+    fn solid_graph(material: Material) -> Graph {
+        let radius = 2.0 * dodeca::BOUNDING_SPHERE_RADIUS_F64;
+        let mut graph = Graph::new(DIMENSION);
+        ensure_nearby(&mut graph, &Position::origin(), radius);
+        populate_fresh_nodes(&mut graph);
+        for (node, _) in nearby_nodes(&graph, &Position::origin(), radius) {
+            for vertex in Vertex::iter() {
+                graph.populate_chunk(
+                    ChunkId::new(node, vertex),
+                    VoxelData::Solid(material),
+                    false,
+                );
+            }
+        }
+        graph
+    }
+
+    fn set_surface(graph: &mut Graph, chunk: ChunkId, slot: u32) {
+        let Chunk::Populated {
+            ref mut surface, ..
+        } = graph[chunk]
+        else {
+            panic!("chunk not populated");
+        };
+        *surface = Some(SlotId(slot));
+    }
+
+    fn surface(graph: &Graph, chunk: ChunkId) -> Option<SlotId> {
+        let Chunk::Populated { surface, .. } = graph[chunk] else {
+            panic!("chunk not populated");
+        };
+        surface
+    }
+
+    #[test]
+    fn adjacent_solid_chunks_hide_shared_faces() {
+        let mut graph = solid_graph(Material::Dirt);
+        let a = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let b = graph
+            .get_chunk_neighbor(a, CoordAxis::X, CoordDirection::Plus)
+            .unwrap();
+        graph.populate_chunk(a, VoxelData::Solid(Material::Sand), false);
+        for chunk in [a, b] {
+            assert!(!graph.has_visible_faces(chunk));
+            assert_eq!(count_faces(&padded(&graph, chunk)), 0);
+        }
+
+        // Unpopulated neighbors are assumed to be empty
+        graph[b] = Chunk::Fresh;
+        assert!(graph.has_visible_faces(a));
+        assert_eq!(
+            count_faces(&padded(&graph, a)),
+            usize::from(DIMENSION).pow(2)
+        );
+
+        // Populating the neighbor calls for a new surface, without changing the old one's chunk
+        set_surface(&mut graph, a, 0);
+        graph.populate_chunk(b, VoxelData::Solid(Material::Dirt), false);
+        assert_eq!(surface(&graph, a), None);
+        assert!(!graph.has_visible_faces(a));
+        assert!(matches!(
+            graph[a],
+            Chunk::Populated {
+                voxels: VoxelData::Solid(Material::Sand),
+                old_surface: Some(SlotId(0)),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn transparent_faces() {
+        let layer = usize::from(DIMENSION).pow(2);
+        // Padded voxels of `lower` in the bottom half of the chunk and `upper` in the top half
+        let layered = |lower, upper| {
+            let materials = MarginCoords::all(DIMENSION)
+                .map(|coords| {
+                    if coords[CoordAxis::Z] < (DIMENSION + 2) / 2 {
+                        lower
+                    } else {
+                        upper
+                    }
+                })
+                .collect::<Vec<_>>();
+            let shapes = vec![Shape::FULL; materials.len()];
+            (materials, shapes)
+        };
+        assert_eq!(
+            count_surface_faces(&layered(Material::Dirt, Material::Void)),
+            (layer, 0)
+        );
+        assert_eq!(
+            count_surface_faces(&layered(Material::Ice, Material::Void)),
+            (0, layer)
+        );
+        // Opaque voxels are seen through transparent ones, which hide their own faces
+        assert_eq!(
+            count_surface_faces(&layered(Material::Dirt, Material::Ice)),
+            (layer, 0)
+        );
+        assert_eq!(
+            count_surface_faces(&layered(Material::Ice, Material::Dirt)),
+            (layer, 0)
+        );
+        // Like transparent voxels merge
+        assert_eq!(
+            count_surface_faces(&layered(Material::Ice, Material::Ice)),
+            (0, 0)
+        );
+        // Unlike transparent voxels are each seen through the other
+        assert_eq!(
+            count_surface_faces(&layered(Material::Ice, Material::Water)),
+            (0, 2 * layer)
+        );
+
+        // Solid chunks are visible through transparent neighbors
+        let mut graph = solid_graph(Material::Ice);
+        let a = ChunkId::new(NodeId::ROOT, Vertex::A);
+        assert!(!graph.has_visible_faces(a));
+        graph.populate_chunk(a, VoxelData::Solid(Material::Dirt), false);
+        assert!(graph.has_visible_faces(a));
+        assert_eq!(count_surface_faces(&padded(&graph, a)).0, 6 * layer);
+    }
+
+    #[test]
+    fn edit_exposes_faces_on_both_sides() {
+        let mut graph = solid_graph(Material::Dirt);
+        let a = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let b = graph
+            .get_chunk_neighbor(a, CoordAxis::X, CoordDirection::Plus)
+            .unwrap();
+        let c = graph
+            .get_chunk_neighbor(a, CoordAxis::Y, CoordDirection::Plus)
+            .unwrap();
+        for (slot, chunk) in [a, b, c].into_iter().enumerate() {
+            set_surface(&mut graph, chunk, slot as u32);
+        }
+
+        // Dig out a voxel on the boundary shared with `b`
+        assert_eq!(
+            graph.update_block(&BlockUpdate {
+                chunk_id: a,
+                coords: Coords([DIMENSION - 1, 1, 2]),
+                new_material: Material::Void,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            }),
+            BlockUpdateOutcome::Applied {
+                previous: Material::Dirt,
+                previous_shape: Shape::FULL,
+            }
+        );
+        // Five faces of the hole belong to `a` and one to `b`, each of which extracts the face
+        // between them
+        assert_eq!(count_faces(&padded(&graph, a)), 6);
+        assert_eq!(count_faces(&padded(&graph, b)), 1);
+        assert!(graph.has_visible_faces(b));
+        assert_eq!(count_faces(&padded(&graph, c)), 0);
+        assert!(!graph.has_visible_faces(c));
+
+        // Only chunks sharing a face with the edit need new surfaces
+        assert_eq!(surface(&graph, a), None);
+        assert_eq!(surface(&graph, b), None);
+        assert_eq!(surface(&graph, c), Some(SlotId(2)));
+        // Neighbors aren't densified
+        assert!(matches!(
+            graph[b],
+            Chunk::Populated {
+                voxels: VoxelData::Solid(Material::Dirt),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn repeated_update_is_applied_once() {
+        let mut graph = solid_graph(Material::Dirt);
+        let a = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let b = graph
+            .get_chunk_neighbor(a, CoordAxis::X, CoordDirection::Plus)
+            .unwrap();
+        let update = BlockUpdate {
+            chunk_id: a,
+            coords: Coords([DIMENSION - 1, 1, 2]),
+            new_material: Material::Void,
+            new_shape: Shape::FULL,
+            sequence: 0,
+        };
+        assert_eq!(
+            graph.update_block(&update),
+            BlockUpdateOutcome::Applied {
+                previous: Material::Dirt,
+                previous_shape: Shape::FULL,
+            }
+        );
+        assert_eq!(graph.chunk_counts().modified, 1);
+
+        // Once the new surfaces are extracted, applying the update again invalidates nothing
+        set_surface(&mut graph, a, 0);
+        set_surface(&mut graph, b, 1);
+        assert_eq!(graph.update_block(&update), BlockUpdateOutcome::NoChange);
+        assert_eq!(surface(&graph, a), Some(SlotId(0)));
+        assert_eq!(surface(&graph, b), Some(SlotId(1)));
+        assert_eq!(graph.chunk_counts().modified, 1);
+
+        // Nor does setting an unmodified block to what it already is
+        let c = ChunkId::new(NodeId::ROOT, Vertex::B);
+        set_surface(&mut graph, c, 2);
+        let update = BlockUpdate {
+            chunk_id: c,
+            new_material: Material::Dirt,
+            ..update
+        };
+        assert_eq!(graph.update_block(&update), BlockUpdateOutcome::NoChange);
+        assert_eq!(surface(&graph, c), Some(SlotId(2)));
+        assert!(matches!(
+            graph[c],
+            Chunk::Populated {
+                voxels: VoxelData::Solid(Material::Dirt),
+                modified: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn chunk_generations() {
+        let mut graph = solid_graph(Material::Dirt);
+        let a = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let b = ChunkId::new(NodeId::ROOT, Vertex::B);
+        let first = graph.chunk_generation(a).unwrap();
+        assert_ne!(graph.chunk_generation(b), Some(first));
+
+        // Only effective edits advance the generation, and only of the edited chunk
+        let edit = |graph: &mut Graph, new_material| {
+            graph.update_block(&BlockUpdate {
+                chunk_id: a,
+                coords: Coords([1, 1, 1]),
+                new_material,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            })
+        };
+        let unrelated = graph.chunk_generation(b);
+        assert_eq!(
+            edit(&mut graph, Material::Dirt),
+            BlockUpdateOutcome::NoChange
+        );
+        assert_eq!(graph.chunk_generation(a), Some(first));
+        assert_eq!(
+            edit(&mut graph, Material::Void),
+            BlockUpdateOutcome::Applied {
+                previous: Material::Dirt,
+                previous_shape: Shape::FULL,
+            }
+        );
+        let edited = graph.chunk_generation(a).unwrap();
+        assert_ne!(edited, first);
+        assert_eq!(graph.chunk_generation(b), unrelated);
+
+        // Repopulating, even after eviction, never restores an earlier generation
+        graph.populate_chunk(a, VoxelData::Solid(Material::Dirt), false);
+        let repopulated = graph.chunk_generation(a).unwrap();
+        assert!(![first, edited].contains(&repopulated));
+        graph.evict_chunk(a);
+        assert_eq!(graph.chunk_generation(a), None);
+        graph.populate_chunk(a, VoxelData::Solid(Material::Dirt), false);
+        assert!(![first, edited, repopulated].contains(&graph.chunk_generation(a).unwrap()));
+    }
+
+    #[test]
+    fn chunk_counts() {
+        let mut graph = solid_graph(Material::Dirt);
+        let populated = graph.chunk_counts().populated;
+        assert!(populated > dodeca::VERTEX_COUNT);
+        let count = |graph: &Graph| {
+            let counts = graph.chunk_counts();
+            (counts.populated, counts.modified)
+        };
+        assert_eq!(count(&graph), (populated, 0));
+
+        let a = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let b = ChunkId::new(NodeId::ROOT, Vertex::B);
+        // Replacing a chunk's data doesn't count it twice
+        graph.populate_chunk(a, VoxelData::Solid(Material::Sand), false);
+        assert_eq!(count(&graph), (populated, 0));
+
+        // Only the first edit to a chunk marks it modified
+        let edit = |graph: &mut Graph, chunk, x| {
+            assert!(matches!(
+                graph.update_block(&BlockUpdate {
+                    chunk_id: chunk,
+                    coords: Coords([x, 0, 0]),
+                    new_material: Material::Void,
+                    new_shape: Shape::FULL,
+                    sequence: 0,
+                }),
+                BlockUpdateOutcome::Applied { .. }
+            ));
+        };
+        edit(&mut graph, a, 0);
+        edit(&mut graph, a, 1);
+        assert_eq!(count(&graph), (populated, 1));
+        graph.populate_chunk(b, VoxelData::Solid(Material::Sand), true);
+        assert_eq!(count(&graph), (populated, 2));
+        edit(&mut graph, b, 0);
+        assert_eq!(count(&graph), (populated, 2));
+
+        // Eviction forgets an unmodified chunk
+        let c = ChunkId::new(NodeId::ROOT, Vertex::C);
+        assert!(matches!(
+            graph.evict_chunk(c),
+            Chunk::Populated {
+                modified: false,
+                ..
+            }
+        ));
+        assert!(matches!(graph[c], Chunk::Fresh));
+        assert_eq!(count(&graph), (populated - 1, 2));
+        assert!(matches!(graph.evict_chunk(c), Chunk::Fresh));
+        assert_eq!(count(&graph), (populated - 1, 2));
+        // Failed edits change nothing
+        assert_eq!(
+            graph.update_block(&BlockUpdate {
+                chunk_id: c,
+                coords: Coords([0, 0, 0]),
+                new_material: Material::Void,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            }),
+            BlockUpdateOutcome::ChunkMissing
+        );
+        graph.populate_chunk(c, VoxelData::Solid(Material::Dirt), false);
+        assert_eq!(count(&graph), (populated, 2));
+        // Overwriting modified data with unmodified data
+        graph.populate_chunk(a, VoxelData::Solid(Material::Dirt), false);
+        graph.populate_chunk(b, VoxelData::Solid(Material::Dirt), false);
+        assert_eq!(count(&graph), (populated, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "modified chunk evicted")]
+    fn modified_chunks_never_evicted() {
+        let mut graph = solid_graph(Material::Dirt);
+        let a = ChunkId::new(NodeId::ROOT, Vertex::A);
+        graph.populate_chunk(a, VoxelData::Solid(Material::Sand), true);
+        graph.evict_chunk(a);
+    }
+
+    #[test]
+    fn permuted_boundary_layer() {
+        let mut graph = Graph::new(DIMENSION);
+        populate_fresh_nodes(&mut graph);
+        let mut permuted = false;
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(NodeId::ROOT, vertex);
+            for axis in CoordAxis::iter() {
+                let [u_axis, v_axis] = axis.other_axes();
+                for (u, v) in [(0, 1), (2, 3), (3, 0)] {
+                    let mut coords = Coords([0; 3]);
+                    coords[axis] = DIMENSION - 1;
+                    coords[u_axis] = u;
+                    coords[v_axis] = v;
+                    let (neighbor, neighbor_coords) = graph
+                        .get_block_neighbor(chunk, coords, axis, CoordDirection::Plus)
+                        .unwrap();
+                    permuted |= neighbor_coords != coords;
+
+                    // Mark the single neighboring voxel that shares a face with `coords`
+                    let mut voxels = VoxelData::Solid(Material::Void);
+                    voxels.data_mut(DIMENSION)[neighbor_coords.to_index(DIMENSION)] =
+                        Material::Dirt;
+                    for other in Vertex::iter() {
+                        graph.populate_chunk(
+                            ChunkId::new(NodeId::ROOT, other),
+                            VoxelData::Solid(Material::Void),
+                            false,
+                        );
+                    }
+                    graph.populate_chunk(neighbor, voxels, false);
+
+                    let layer = graph
+                        .get_boundary_layer(chunk, axis, CoordDirection::Plus)
+                        .unwrap();
+                    for layer_v in 0..DIMENSION {
+                        for layer_u in 0..DIMENSION {
+                            assert_eq!(
+                                layer.get(DIMENSION, layer_u, layer_v) == Material::Dirt,
+                                (layer_u, layer_v) == (u, v),
+                                "vertex {vertex:?} axis {axis:?} at ({layer_u}, {layer_v})"
+                            );
+                        }
+                    }
+
+                    // The voxel lands in the margin just beyond `coords`
+                    let padded = padded(&graph, chunk);
+                    let mut margin = MarginCoords::from(coords);
+                    margin[axis] = DIMENSION + 1;
+                    assert_eq!(padded.0[margin.to_index(DIMENSION)], Material::Dirt);
+                    assert_eq!(count_faces(&padded), 1);
+                }
+            }
+        }
+        // Some vertex pairs order their axes differently
+        assert!(permuted);
+    }
+
+    #[test]
+    fn shaped_faces() {
+        let len = (usize::from(DIMENSION) + 2).pow(3);
+        let at = |coords| MarginCoords(coords).to_index(DIMENSION);
+        let center = at([2, 2, 2]);
+        let single = |shape| {
+            let mut padded = (vec![Material::Void; len], vec![Shape::FULL; len]);
+            padded.0[center] = Material::Dirt;
+            padded.1[center] = shape;
+            padded
+        };
+        let slab = Shape::slab(CoordAxis::Y, CoordDirection::Minus);
+        let stairs = Shape::stairs(
+            (CoordAxis::Y, CoordDirection::Plus),
+            (CoordAxis::Z, CoordDirection::Plus),
+        );
+        assert_eq!(count_surface_faces(&single(Shape::FULL)), (6, 0));
+        // The top of a slab lies halfway through its voxel
+        assert_eq!(count_surface_faces(&single(slab)), (6, 0));
+        // Stairs have two faces on each of their L-shaped sides and the step's riser and tread
+        assert_eq!(count_surface_faces(&single(stairs)), (10, 0));
+
+        // A slab on a full cube hides the full cube's top
+        let mut stacked = single(slab);
+        stacked.0[at([2, 1, 2])] = Material::Dirt;
+        assert_eq!(count_surface_faces(&stacked), (10, 0));
+        // A full cube beside a slab shows the upper half of the face between them
+        let mut beside = single(slab);
+        beside.0[at([3, 2, 2])] = Material::Dirt;
+        assert_eq!(count_surface_faces(&beside), (11, 0));
+        // Slabs side by side hide their shared faces entirely
+        beside.1[at([3, 2, 2])] = slab;
+        assert_eq!(count_surface_faces(&beside), (10, 0));
+    }
+
+    #[test]
+    fn shaped_boundary_layer() {
+        let mut graph = Graph::new(DIMENSION);
+        populate_fresh_nodes(&mut graph);
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(NodeId::ROOT, vertex);
+            for axis in CoordAxis::iter() {
+                let [u_axis, v_axis] = axis.other_axes();
+                let mut coords = Coords([0; 3]);
+                coords[axis] = DIMENSION - 1;
+                coords[u_axis] = 1;
+                coords[v_axis] = 2;
+                let (neighbor, neighbor_coords) = graph
+                    .get_block_neighbor(chunk, coords, axis, CoordDirection::Plus)
+                    .unwrap();
+                for other in Vertex::iter() {
+                    graph.populate_chunk(
+                        ChunkId::new(NodeId::ROOT, other),
+                        VoxelData::Solid(Material::Void),
+                        false,
+                    );
+                }
+
+                // The same half of each of two voxels sharing a face across the boundary
+                let slab = Shape::slab(u_axis, CoordDirection::Minus);
+                let neighbor_slab =
+                    graph.shape_in_block_neighbor(chunk, coords, axis, CoordDirection::Plus, slab);
+                assert_eq!(
+                    graph.set_block(neighbor, neighbor_coords, Material::Dirt, neighbor_slab),
+                    BlockUpdateOutcome::Applied {
+                        previous: Material::Void,
+                        previous_shape: Shape::FULL,
+                    }
+                );
+                let layer = graph
+                    .get_boundary_layer(chunk, axis, CoordDirection::Plus)
+                    .unwrap();
+                assert_eq!(
+                    layer.shape(DIMENSION, 1, 2),
+                    slab,
+                    "vertex {vertex:?} axis {axis:?}"
+                );
+                // Only the neighbor's half of the face between them shows
+                assert_eq!(count_faces(&padded(&graph, chunk)), 1);
+
+                // Filling the same half on our side hides the boundary entirely, leaving five of
+                // our slab's faces
+                assert_eq!(
+                    graph.set_block(chunk, coords, Material::Dirt, slab),
+                    BlockUpdateOutcome::Applied {
+                        previous: Material::Void,
+                        previous_shape: Shape::FULL,
+                    }
+                );
+                assert_eq!(count_faces(&padded(&graph, chunk)), 5);
+            }
+        }
+    }
+
+    #[test]
+    fn serialize_shapes() {
+        let slab = Shape::slab(CoordAxis::Z, CoordDirection::Plus);
+        let mut voxels = VoxelData::Solid(Material::Void);
+        voxels.data_mut(DIMENSION)[Coords([0, 1, 2]).to_index(DIMENSION)] = Material::Dirt;
+        let serializable = voxels.to_serializable(DIMENSION);
+        assert!(serializable.shapes.is_empty());
+        assert!(matches!(
+            VoxelData::from_serializable(&serializable, DIMENSION),
+            Some(VoxelData::Dense(_))
+        ));
+
+        voxels.set_shape(DIMENSION, Coords([0, 1, 2]).to_index(DIMENSION), slab);
+        let serializable = voxels.to_serializable(DIMENSION);
+        assert_eq!(serializable.shapes.len(), usize::from(DIMENSION).pow(3));
+        let restored = VoxelData::from_serializable(&serializable, DIMENSION).unwrap();
+        assert!(matches!(restored, VoxelData::Shaped(..)));
+        let (restored, voxels) = (restored.view(DIMENSION), voxels.view(DIMENSION));
+        for coords in Coords::all(DIMENSION) {
+            assert_eq!(restored.get(coords), voxels.get(coords));
+            assert_eq!(restored.shape(coords), voxels.shape(coords));
+        }
+    }
+
     /// Any voxel AABB should at least cover a capsule-shaped region consisting of all points
     /// `radius` units away from the ray's line segment. This region consists of two spheres
     /// and a cylinder. We only test planes because covered lines and points are a strict subset.
+    /// A burst of new nodes, as announced to a client that's just joined, is populated over several
+    /// frames, never populating a node before those it derives from
+    #[test]
+    fn margin_coords() {
+        let coords = Coords([0, 1, DIMENSION - 1]);
+        let margin = MarginCoords::from(coords);
+        assert_eq!(margin, MarginCoords([1, 2, DIMENSION]));
+        assert_eq!(margin.to_index(DIMENSION), coords.to_index(DIMENSION));
+        assert_eq!(margin.to_coords(DIMENSION), Some(coords));
+        assert_eq!(MarginCoords([0, 1, 1]).to_coords(DIMENSION), None);
+        assert_eq!(
+            MarginCoords([1, 1, DIMENSION + 1]).to_coords(DIMENSION),
+            None
+        );
+
+        // Both kinds of coordinates are visited in the order they're stored
+        assert!(MarginCoords::all(DIMENSION)
+            .enumerate()
+            .all(|(i, coords)| coords.to_index(DIMENSION) == i));
+        let indices = Coords::all(DIMENSION)
+            .map(|coords| coords.to_index(DIMENSION))
+            .collect::<Vec<_>>();
+        assert_eq!(indices.len(), usize::from(DIMENSION).pow(3));
+        assert!(indices.windows(2).all(|x| x[0] < x[1]));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "out of bounds")]
+    fn margin_overrun_caught() {
+        // Solid data has nothing to index, so only the bounds assertion can catch this
+        let voxels = VoxelData::Solid(Material::Dirt);
+        voxels
+            .view(DIMENSION)
+            .get(MarginCoords([DIMENSION + 2, 0, 0]));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "out of bounds")]
+    fn margin_as_coords_caught() {
+        // Off by one into the margin, which would otherwise read the next row's first voxel
+        let voxels =
+            VoxelData::Dense(vec![Material::Void; (usize::from(DIMENSION) + 2).pow(3)].into());
+        voxels.view(DIMENSION).get(Coords([DIMENSION, 0, 0]));
+    }
+
+    #[test]
+    fn budgeted_population() {
+        const BUDGET: usize = 64;
+        let mut graph = Graph::new(DIMENSION);
+        populate_fresh_nodes(&mut graph);
+        let mut radius = 0.0;
+        while graph.len() < 500 {
+            radius += 0.5;
+            ensure_nearby(&mut graph, &Position::origin(), radius);
+        }
+        let burst = graph.fresh().to_vec();
+        let mut queue = PopulationQueue::new();
+        queue.enqueue_fresh(&mut graph);
+        assert!(graph.fresh().is_empty());
+        assert_eq!(queue.len(), burst.len());
+
+        // A character a couple of nodes from the root needs its surroundings right away
+        let character = Position {
+            node: graph
+                .neighbor(graph.neighbor(NodeId::ROOT, Side::A).unwrap(), Side::B)
+                .unwrap(),
+            local: na::Matrix4::identity(),
+        };
+        let surroundings = nearby_nodes(&graph, &character, dodeca::BOUNDING_SPHERE_RADIUS_F64)
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect::<Vec<_>>();
+
+        let mut frames = 0;
+        while !queue.is_empty() {
+            let populated = queue.run(&mut graph, surroundings.iter().copied(), BUDGET);
+            assert!(
+                populated <= BUDGET,
+                "populated {populated} nodes in one frame"
+            );
+            if frames == 0 {
+                assert!(surroundings.iter().all(|&node| graph.get(node).is_some()));
+            }
+            for &node in &burst {
+                if graph.get(node).is_some() {
+                    assert!(graph.descenders(node).all(|(_, x)| graph.get(x).is_some()));
+                }
+            }
+            frames += 1;
+        }
+        assert!(frames > 1);
+        assert!(burst.iter().all(|&node| graph.get(node).is_some()));
+
+        // Population out of turn derives the same states as population in order
+        let mut reference = Graph::new(DIMENSION);
+        for &node in &burst {
+            let side = graph.parent(node).unwrap();
+            reference.insert_child(graph.neighbor(node, side).unwrap(), side);
+        }
+        populate_fresh_nodes(&mut reference);
+        for &node in &burst {
+            let expected = &reference.get(node).as_ref().unwrap().state;
+            let actual = &graph.get(node).as_ref().unwrap().state;
+            assert_eq!(actual.elevation(), expected.elevation());
+        }
+    }
+
     #[test]
     fn voxel_aabb_coverage() {
         let dimension = 12;
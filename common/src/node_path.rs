@@ -0,0 +1,117 @@
+//! Routes from the root to nodes of the graph
+//!
+//! Unlike a `NodeId`, a route can be followed geometrically without the graph having been built out
+//! to its destination, so it remains meaningful across connections and graph resets.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dodeca::Side,
+    graph::{Graph, NodeId},
+};
+
+/// Sides crossed, in order, to reach a node from the root
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NodePath(pub Vec<Side>);
+
+impl NodePath {
+    /// The route from the root to `node` by way of each node's parent
+    pub fn to(graph: &Graph, node: NodeId) -> Self {
+        let mut sides = Vec::with_capacity(graph.length(node) as usize);
+        let mut current = node;
+        while let Some(side) = graph.parent(current) {
+            sides.push(side);
+            current = graph
+                .neighbor(current, side)
+                .expect("parents are always present");
+        }
+        sides.reverse();
+        Self(sides)
+    }
+
+    /// The node at the end of the route, if `graph` extends that far
+    pub fn resolve(&self, graph: &Graph) -> Option<NodeId> {
+        self.0
+            .iter()
+            .try_fold(NodeId::ROOT, |node, &side| graph.neighbor(node, side))
+    }
+
+    /// The node at the end of the route, extending `graph` as needed to reach it
+    pub fn ensure(&self, graph: &mut Graph) -> NodeId {
+        self.0.iter().fold(NodeId::ROOT, |node, &side| {
+            graph.ensure_neighbor(node, side)
+        })
+    }
+
+    /// Transform from the coordinates of the node at the end of `other` to those of the node at
+    /// the end of `self`
+    pub fn transform_from(&self, other: &NodePath) -> na::Matrix4<f64> {
+        // Any common prefix cancels out, which also keeps distant routes to nearby nodes precise
+        let shared = self
+            .0
+            .iter()
+            .zip(&other.0)
+            .take_while(|(a, b)| a == b)
+            .count();
+        self.0[shared..]
+            .iter()
+            .rev()
+            .chain(&other.0[shared..])
+            .fold(na::Matrix4::identity(), |transform, side| {
+                transform * side.reflection_f64()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{math, proto::Position, test_graphs::walk, traversal::nearby_nodes};
+    use approx::*;
+
+    #[test]
+    fn resolves_to_node() {
+        let mut graph = Graph::new(4);
+        let a = walk(&mut graph, &[Side::A, Side::B, Side::C, Side::D]);
+        let path = NodePath::to(&graph, a);
+        assert_eq!(path.0.len(), graph.length(a) as usize);
+        assert_eq!(path.resolve(&graph), Some(a));
+        assert_eq!(NodePath::to(&graph, NodeId::ROOT), NodePath::default());
+
+        // Routes are followed by geometry rather than identity, so another graph reaches the same
+        // place the same way
+        let mut other = Graph::new(4);
+        assert_eq!(path.resolve(&other), None);
+        let reached = path.ensure(&mut other);
+        assert_eq!(other.hash_of(reached), graph.hash_of(a));
+        assert_eq!(path.resolve(&other), Some(reached));
+        // Extending it again changes nothing
+        let len = other.len();
+        assert_eq!(path.ensure(&mut other), reached);
+        assert_eq!(other.len(), len);
+    }
+
+    #[test]
+    fn transforms_match_traversal() {
+        let mut graph = Graph::new(4);
+        let a = walk(&mut graph, &[Side::A, Side::B, Side::C]);
+        let b = walk(&mut graph, &[Side::A, Side::E, Side::F]);
+        let start = Position {
+            node: a,
+            local: na::Matrix4::identity(),
+        };
+        let transforms = nearby_nodes(&graph, &start, 10.0);
+        let b_to_a = transforms.iter().find(|&&(node, _)| node == b).unwrap().1;
+        let path = NodePath::to(&graph, a).transform_from(&NodePath::to(&graph, b));
+        assert_abs_diff_eq!(
+            path.cast::<f32>() * math::origin(),
+            b_to_a * math::origin(),
+            epsilon = 1e-3
+        );
+        assert_abs_diff_eq!(
+            NodePath::to(&graph, a).transform_from(&NodePath::to(&graph, a)),
+            na::Matrix4::identity()
+        );
+    }
+}
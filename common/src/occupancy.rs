@@ -0,0 +1,306 @@
+use std::{ops::Range, sync::OnceLock};
+
+use crate::{
+    collision_math::Ray,
+    node::{ChunkLayout, Coords, VoxelData},
+    world::Material,
+};
+
+/// Voxels along each axis of a cell of an `Occupancy`
+const CELL_SIZE: u8 = 8;
+
+/// Which cells of `CELL_SIZE` voxels along each axis of a chunk contain anything that isn't void,
+/// letting collision checks pass over the empty parts of the chunk
+///
+/// Found from the chunk's voxels when first needed, and kept up to date with changes to single
+/// voxels after that.
+#[derive(Default)]
+pub struct Occupancy(OnceLock<Cells>);
+
+impl Occupancy {
+    /// Cells of the chunk with `voxels`, found now if they haven't been
+    pub(crate) fn cells(&self, voxels: &VoxelData, dimension: u8) -> &Cells {
+        self.0.get_or_init(|| Cells::new(voxels, dimension))
+    }
+
+    /// Account for the voxel at `coords` of `voxels` having changed
+    pub(crate) fn update(&mut self, voxels: &VoxelData, dimension: u8, coords: Coords) {
+        let Some(cells) = self.0.get_mut() else {
+            return;
+        };
+        let cell = coords.0.map(|x| x / CELL_SIZE);
+        // Emptying a voxel only empties its cell if every other voxel there is empty too
+        let occupied = voxels.view(dimension).get(coords) != Material::Void
+            || cells.scan(voxels, dimension, cell);
+        cells.set(cell, occupied);
+    }
+}
+
+pub(crate) struct Cells {
+    /// Number of cells along each axis
+    count: u8,
+    /// Whether each cell is occupied, indexed like voxels
+    occupied: Vec<bool>,
+    /// Number of occupied cells
+    occupied_count: usize,
+}
+
+impl Cells {
+    fn new(voxels: &VoxelData, dimension: u8) -> Self {
+        let count = dimension.div_ceil(CELL_SIZE);
+        let len = usize::from(count).pow(3);
+        if let VoxelData::Solid(material) = *voxels {
+            let occupied = material != Material::Void;
+            return Self {
+                count,
+                occupied: vec![occupied; len],
+                occupied_count: if occupied { len } else { 0 },
+            };
+        }
+        let mut cells = Self {
+            count,
+            occupied: vec![false; len],
+            occupied_count: 0,
+        };
+        let view = voxels.view(dimension);
+        for coords in Coords::all(dimension) {
+            if view.get(coords) != Material::Void {
+                cells.set(coords.0.map(|x| x / CELL_SIZE), true);
+            }
+        }
+        cells
+    }
+
+    /// Whether any voxel of the chunk isn't void
+    pub(crate) fn any(&self) -> bool {
+        self.occupied_count > 0
+    }
+
+    /// The occupied cells that a sphere of `radius` could touch while traveling the given distance
+    /// along `ray`
+    ///
+    /// Found conservatively, like `VoxelAABB::from_ray_segment_and_radius`, in grid coordinates,
+    /// in which the ray is a straight line and the sphere never reaches further than `radius`
+    /// scaled to grid units. Cells are inflated by a voxel beyond that, so that rounding can never
+    /// matter.
+    pub(crate) fn near(
+        &self,
+        layout: &ChunkLayout,
+        ray: &Ray,
+        tanh_distance: f32,
+        radius: f32,
+    ) -> NearCells {
+        let start =
+            na::Point3::from_homogeneous(ray.position).unwrap() * layout.dual_to_grid_factor();
+        let end = na::Point3::from_homogeneous(ray.ray_point(tanh_distance)).unwrap()
+            * layout.dual_to_grid_factor();
+        let margin = radius * layout.dual_to_grid_factor() + 1.0;
+        let dimension = f32::from(layout.dimension());
+        let mut near = vec![false; self.occupied.len()];
+        for (index, near) in near.iter_mut().enumerate() {
+            if !self.occupied[index] {
+                continue;
+            }
+            let cell = self.cell_of(index);
+            let bounds = cell.map(|x| {
+                let min = f32::from(x * CELL_SIZE);
+                [
+                    min - margin,
+                    (min + f32::from(CELL_SIZE)).min(dimension) + margin,
+                ]
+            });
+            *near = segment_meets_box(&start, &end, bounds);
+        }
+        NearCells {
+            count: self.count,
+            near,
+        }
+    }
+
+    /// Whether any voxel of `cell` isn't void
+    fn scan(&self, voxels: &VoxelData, dimension: u8, cell: [u8; 3]) -> bool {
+        let view = voxels.view(dimension);
+        let [xs, ys, zs] = cell.map(|x| x * CELL_SIZE..((x + 1) * CELL_SIZE).min(dimension));
+        zs.flat_map(|z| {
+            let xs = xs.clone();
+            ys.clone()
+                .flat_map(move |y| xs.clone().map(move |x| Coords([x, y, z])))
+        })
+        .any(|coords| view.get(coords) != Material::Void)
+    }
+
+    fn set(&mut self, cell: [u8; 3], occupied: bool) {
+        let index = self.index(cell);
+        if self.occupied[index] != occupied {
+            self.occupied[index] = occupied;
+            if occupied {
+                self.occupied_count += 1;
+            } else {
+                self.occupied_count -= 1;
+            }
+        }
+    }
+
+    fn index(&self, [x, y, z]: [u8; 3]) -> usize {
+        let count = usize::from(self.count);
+        usize::from(x) + count * (usize::from(y) + count * usize::from(z))
+    }
+
+    fn cell_of(&self, index: usize) -> [u8; 3] {
+        let count = usize::from(self.count);
+        [index % count, index / count % count, index / count / count].map(|x| x as u8)
+    }
+}
+
+/// Cells of a chunk a sphere cast could hit something in
+pub(crate) struct NearCells {
+    /// Number of cells along each axis
+    count: u8,
+    /// Whether each cell is near, indexed like `Cells::occupied`
+    near: Vec<bool>,
+}
+
+impl NearCells {
+    /// Every cell of a chunk of size `dimension`, for checking casts that pass over nothing
+    #[cfg(test)]
+    pub(crate) fn all(dimension: u8) -> Self {
+        let count = dimension.div_ceil(CELL_SIZE);
+        Self {
+            count,
+            near: vec![true; usize::from(count).pow(3)],
+        }
+    }
+
+    /// Whether any of the voxels within `ranges` along each axis is in a near cell
+    pub(crate) fn any(&self, ranges: [Range<u8>; 3]) -> bool {
+        if ranges.iter().any(|x| x.is_empty()) {
+            return false;
+        }
+        let [xs, ys, mut zs] = ranges.map(|x| x.start / CELL_SIZE..(x.end - 1) / CELL_SIZE + 1);
+        let count = usize::from(self.count);
+        zs.any(|z| {
+            ys.clone().any(|y| {
+                xs.clone().any(|x| {
+                    self.near[usize::from(x) + count * (usize::from(y) + count * usize::from(z))]
+                })
+            })
+        })
+    }
+}
+
+/// Whether the line segment from `start` to `end` passes through the box spanning `bounds`, given
+/// as the minimum and maximum along each axis
+fn segment_meets_box(
+    start: &na::Point3<f32>,
+    end: &na::Point3<f32>,
+    bounds: [[f32; 2]; 3],
+) -> bool {
+    // The portion of the segment within every slab so far, as fractions of its length
+    let (mut enter, mut exit) = (0.0f32, 1.0f32);
+    for (axis, [min, max]) in bounds.into_iter().enumerate() {
+        let delta = end[axis] - start[axis];
+        if delta == 0.0 {
+            if start[axis] < min || start[axis] > max {
+                return false;
+            }
+            continue;
+        }
+        let (a, b) = ((min - start[axis]) / delta, (max - start[axis]) / delta);
+        enter = enter.max(a.min(b));
+        exit = exit.min(a.max(b));
+        if enter > exit {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg64Mcg;
+
+    use super::*;
+
+    const DIMENSION: u8 = 20;
+
+    fn random_voxels(rng: &mut Pcg64Mcg, density: f64) -> VoxelData {
+        let mut voxels = VoxelData::Solid(Material::Void);
+        let data = voxels.data_mut(DIMENSION);
+        for coords in Coords::all(DIMENSION) {
+            if rng.gen_bool(density) {
+                data[coords.to_index(DIMENSION)] = Material::Dirt;
+            }
+        }
+        voxels
+    }
+
+    #[test]
+    fn updates_match_rescans() {
+        let mut rng = Pcg64Mcg::seed_from_u64(0);
+        let mut voxels = random_voxels(&mut rng, 0.01);
+        let mut occupancy = Occupancy::default();
+        occupancy.cells(&voxels, DIMENSION);
+        for _ in 0..2000 {
+            let coords = Coords([(); 3].map(|()| rng.gen_range(0..DIMENSION)));
+            // Mostly clearing, so that cells empty out again
+            let material = if rng.gen_bool(0.3) {
+                Material::Dirt
+            } else {
+                Material::Void
+            };
+            voxels.data_mut(DIMENSION)[coords.to_index(DIMENSION)] = material;
+            occupancy.update(&voxels, DIMENSION, coords);
+            let updated = occupancy.cells(&voxels, DIMENSION);
+            let rescanned = Cells::new(&voxels, DIMENSION);
+            assert_eq!(updated.occupied, rescanned.occupied);
+            assert_eq!(updated.occupied_count, rescanned.occupied_count);
+        }
+    }
+
+    #[test]
+    fn solid_chunks() {
+        let void = Cells::new(&VoxelData::Solid(Material::Void), DIMENSION);
+        assert!(!void.any());
+        let dirt = Cells::new(&VoxelData::Solid(Material::Dirt), DIMENSION);
+        assert_eq!(dirt.occupied_count, 27);
+    }
+
+    #[test]
+    fn near_ranges() {
+        let mut near = NearCells::all(DIMENSION);
+        near.near.iter_mut().for_each(|x| *x = false);
+        near.near[1] = true;
+        assert!(near.any([8..9, 0..1, 0..1]));
+        assert!(near.any([7..9, 0..20, 0..20]));
+        assert!(!near.any([0..8, 0..20, 0..20]));
+        assert!(!near.any([8..9, 8..20, 0..20]));
+        assert!(!near.any([8..8, 0..20, 0..20]));
+    }
+
+    #[test]
+    fn segments_meet_boxes() {
+        let bounds = [[0.0, 1.0]; 3];
+        let point = |x: f32, y: f32, z: f32| na::Point3::new(x, y, z);
+        assert!(segment_meets_box(
+            &point(-1.0, 0.5, 0.5),
+            &point(2.0, 0.5, 0.5),
+            bounds
+        ));
+        assert!(!segment_meets_box(
+            &point(-1.0, 0.5, 0.5),
+            &point(-0.5, 0.5, 0.5),
+            bounds
+        ));
+        assert!(!segment_meets_box(
+            &point(-1.0, 2.0, 0.5),
+            &point(2.0, 1.5, 0.5),
+            bounds
+        ));
+        assert!(segment_meets_box(
+            &point(0.5, 0.5, 0.5),
+            &point(0.5, 0.5, 0.5),
+            bounds
+        ));
+    }
+}
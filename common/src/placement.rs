@@ -0,0 +1,229 @@
+//! Whether blocks may be placed where characters stand
+//!
+//! Servers refuse to place blocks overlapping characters, who would otherwise be trapped inside
+//! them. Clients judge their own placements with the same functions before requesting them, so that
+//! they never predict a placement the server would refuse for overlapping them.
+
+use crate::{
+    dodeca,
+    graph::Graph,
+    math,
+    node::{ChunkId, Coords},
+    proto::{BlockUpdate, Position},
+    traversal::nearby_nodes,
+    world::Material,
+};
+
+/// Slack in Beltrami-Klein coordinates when deciding whether a point lies on a voxel's boundary
+const EPSILON: f64 = 1e-9;
+
+/// Whether `update` would place a block overlapping a character of `radius` at `position`
+///
+/// Removing blocks is never obstructed.
+pub fn obstructs(graph: &Graph, update: &BlockUpdate, position: &Position, radius: f32) -> bool {
+    update.new_material != Material::Void
+        && sphere_overlaps_voxel(graph, position, radius, update.chunk_id, update.coords)
+}
+
+/// Whether any point of the voxel at `coords` in `chunk` lies within `radius` of `center`
+///
+/// The whole voxel is considered, whatever the shape of its block. Voxels in nodes too far from
+/// `center` to reach, or not connected to its node in `graph`, never overlap.
+pub fn sphere_overlaps_voxel(
+    graph: &Graph,
+    center: &Position,
+    radius: f32,
+    chunk: ChunkId,
+    coords: Coords,
+) -> bool {
+    // Every voxel lies within the bounding sphere of its node
+    let reach = f64::from(radius) + dodeca::BOUNDING_SPHERE_RADIUS_F64;
+    let Some((_, transform)) = nearby_nodes(graph, center, reach)
+        .into_iter()
+        .find(|&(node, _)| node == chunk.node)
+    else {
+        return false;
+    };
+    // In the chunk's dual coordinates, the voxel is an axis-aligned cube
+    let point = chunk.vertex.node_to_dual_f32()
+        * math::mtranspose(&transform)
+        * center.local
+        * math::origin();
+    let dual_to_grid = f64::from(graph.layout().dual_to_grid_factor());
+    let bounds = coords.0.map(|x| {
+        [
+            f64::from(x) / dual_to_grid,
+            (f64::from(x) + 1.0) / dual_to_grid,
+        ]
+    });
+    distance_to_box(&point.cast(), &bounds) < f64::from(radius)
+}
+
+/// Distance from `point` to the box spanning `bounds` along each axis in Beltrami-Klein coordinates,
+/// or zero if the box contains it
+fn distance_to_box(point: &na::Vector4<f64>, bounds: &[[f64; 2]; 3]) -> f64 {
+    let contains = |p: &na::Vector4<f64>| {
+        (0..3).all(|axis| {
+            let x = p[axis] / p.w;
+            bounds[axis][0] - EPSILON <= x && x <= bounds[axis][1] + EPSILON
+        })
+    };
+    if contains(point) {
+        return 0.0;
+    }
+    // Otherwise the nearest point of the box is the nearest point of one of its faces, edges, or
+    // vertices, which is the projection of `point` onto the planes meeting there, if that lies on
+    // the box. The planes are far from orthogonal, so the projections are found together.
+    let candidates = (1..8u32).flat_map(|axes| {
+        (0..8u32)
+            .filter(move |sides| sides & !axes == 0)
+            .map(move |sides| (axes, sides))
+    });
+    candidates
+        .filter_map(|(axes, sides)| {
+            let normals = (0..3)
+                .filter(|axis| axes & (1 << axis) != 0)
+                .map(|axis| {
+                    // Orthogonal to the points whose coordinate along `axis` is the bound
+                    let mut normal = na::Vector4::zeros();
+                    normal[axis] = 1.0;
+                    normal.w = bounds[axis][(sides >> axis & 1) as usize];
+                    normal
+                })
+                .collect::<Vec<_>>();
+            // Solve for the multiples of the normals whose removal leaves `point` on every plane
+            let mut gram = na::Matrix3::identity();
+            let mut offsets = na::Vector3::zeros();
+            for (i, a) in normals.iter().enumerate() {
+                offsets[i] = math::mip(point, a);
+                for (j, b) in normals.iter().enumerate() {
+                    gram[(i, j)] = math::mip(a, b);
+                }
+            }
+            let weights = gram.lu().solve(&offsets)?;
+            let projection = normals
+                .iter()
+                .zip(weights.iter())
+                .fold(*point, |acc, (normal, weight)| acc - normal * *weight);
+            contains(&projection).then_some(projection)
+        })
+        .map(|projection| {
+            // Rounding can push nearly coincident points out of the domain of `acosh`
+            let cosh = -math::mip(point, &projection)
+                / (math::mip(point, point) * math::mip(&projection, &projection)).sqrt();
+            cosh.max(1.0).acosh()
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dodeca::Vertex,
+        graph::NodeId,
+        node::{populate_fresh_nodes, ChunkLayout},
+        traversal::ensure_nearby,
+        world::Shape,
+    };
+
+    const DIMENSION: u8 = 12;
+
+    /// Point in a chunk's dual coordinates at the given grid coordinates
+    fn grid_to_dual(grid: na::Vector3<f64>) -> na::Vector4<f64> {
+        let dual_to_grid = f64::from(ChunkLayout::new(DIMENSION).dual_to_grid_factor());
+        math::lorentz_normalize(&(grid / dual_to_grid).push(1.0))
+    }
+
+    #[test]
+    fn distance_matches_sampling() {
+        let lo = na::Vector3::new(3.0, 5.0, 7.0);
+        let dual_to_grid = f64::from(ChunkLayout::new(DIMENSION).dual_to_grid_factor());
+        let bounds =
+            [0, 1, 2].map(|axis| [lo[axis] / dual_to_grid, (lo[axis] + 1.0) / dual_to_grid]);
+        // The box's surface, finely sampled
+        const STEPS: u32 = 24;
+        let mut surface = Vec::new();
+        for axis in 0..3 {
+            for side in [0.0, 1.0] {
+                for i in 0..=STEPS {
+                    for j in 0..=STEPS {
+                        let mut grid = lo;
+                        grid[axis] += side;
+                        grid[(axis + 1) % 3] += f64::from(i) / f64::from(STEPS);
+                        grid[(axis + 2) % 3] += f64::from(j) / f64::from(STEPS);
+                        surface.push(grid_to_dual(grid));
+                    }
+                }
+            }
+        }
+        // No point of the surface is further than this from a sample
+        let spacing = math::distance(
+            &grid_to_dual(lo),
+            &grid_to_dual(lo + na::Vector3::new(1.0, 1.0, 0.0) / f64::from(STEPS)),
+        );
+        for offset in [
+            na::Vector3::new(-0.5, 0.5, 0.5),
+            na::Vector3::new(1.5, 0.5, 0.5),
+            na::Vector3::new(-0.3, -0.4, 0.5),
+            na::Vector3::new(1.2, 1.7, 0.9),
+            na::Vector3::new(-0.5, -0.5, -0.5),
+            na::Vector3::new(2.0, 2.0, 2.0),
+            na::Vector3::new(0.5, 0.5, 3.0),
+        ] {
+            let point = grid_to_dual(lo + offset);
+            let sampled = surface
+                .iter()
+                .map(|x| math::distance(&point, x))
+                .fold(f64::INFINITY, f64::min);
+            let exact = distance_to_box(&point, &bounds);
+            assert!(
+                exact <= sampled + 1e-9 && exact >= sampled - spacing,
+                "at {offset}: exact {exact}, sampled {sampled}"
+            );
+        }
+        assert_eq!(
+            distance_to_box(&grid_to_dual(lo + na::Vector3::repeat(0.5)), &bounds),
+            0.0
+        );
+    }
+
+    #[test]
+    fn placements_obstructed_within_radius() {
+        let mut graph = Graph::new(DIMENSION);
+        ensure_nearby(&mut graph, &Position::origin(), 1.0);
+        populate_fresh_nodes(&mut graph);
+        let dual_to_grid = f64::from(graph.layout().dual_to_grid_factor());
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let coords = Coords([2, 3, 4]);
+        let mut update = BlockUpdate {
+            chunk_id: chunk,
+            coords,
+            new_material: Material::Dirt,
+            new_shape: Shape::FULL,
+            sequence: 0,
+        };
+        let at_grid = |grid: na::Vector3<f64>| Position {
+            node: NodeId::ROOT,
+            local: math::translate(
+                &math::origin(),
+                &(chunk.vertex.dual_to_node_f64() * grid_to_dual(grid)),
+            )
+            .cast(),
+        };
+        // Centered in the neighboring voxel, half a voxel from the face they share
+        let beside = grid_to_dual(na::Vector3::new(3.5, 3.5, 4.5));
+        let face = na::Vector4::new(1.0, 0.0, 0.0, 3.0 / dual_to_grid);
+        let foot = beside - face * (math::mip(&beside, &face) / math::mip(&face, &face));
+        let gap = math::distance(&beside, &foot) as f32;
+        let beside = at_grid(na::Vector3::new(3.5, 3.5, 4.5));
+        assert!(obstructs(&graph, &update, &beside, gap * 1.01));
+        assert!(!obstructs(&graph, &update, &beside, gap * 0.99));
+        let inside = at_grid(na::Vector3::new(2.5, 3.5, 4.5));
+        assert!(obstructs(&graph, &update, &inside, 1e-3));
+
+        // Breaking is never obstructed
+        update.new_material = Material::Void;
+        assert!(!obstructs(&graph, &update, &inside, 1e-3));
+    }
+}
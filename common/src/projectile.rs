@@ -0,0 +1,290 @@
+//! Motion of thrown blocks, which fall, bounce off terrain, and come to rest
+
+use tracing::trace;
+
+use crate::{
+    collision_math::Ray, graph::Graph, graph_collision, math, proto::Position, world::Material,
+    SimConfig,
+};
+
+/// Speed in m/s below which a projectile counts as still, and below which it stops bouncing
+pub const REST_SPEED: f32 = 0.5;
+
+/// Number of consecutive steps a projectile must end still to be at rest, so that one caught at the
+/// top of a bounce isn't mistaken for one that has settled
+pub const REST_STEPS: u32 = 3;
+
+/// Ratio of the friction slowing a projectile sliding along a surface to how hard it meets the
+/// surface
+const FRICTION: f32 = 0.6;
+
+/// A thrown block in flight
+#[derive(Debug, Clone)]
+pub struct Projectile {
+    pub material: Material,
+    /// Relative to the projectile's position, in absolute units per second
+    pub velocity: na::Vector3<f32>,
+    /// Number of consecutive steps that have ended with the projectile still
+    pub still_steps: u32,
+}
+
+impl Projectile {
+    pub fn new(material: Material, velocity: na::Vector3<f32>) -> Self {
+        Self {
+            material,
+            velocity,
+            still_steps: 0,
+        }
+    }
+
+    /// Whether the projectile has been still for long enough to have settled
+    pub fn at_rest(&self) -> bool {
+        self.still_steps >= REST_STEPS
+    }
+
+    /// Move the projectile at `position` through `dt` seconds of flight, under gravity and
+    /// bouncing off terrain
+    ///
+    /// A projectile in a node whose state isn't known yet, or headed into chunks that haven't been
+    /// generated, holds still until they're ready, without that counting towards its rest.
+    pub fn step(&mut self, cfg: &SimConfig, graph: &Graph, position: &mut Position, dt: f32) {
+        let substeps = (dt / cfg.max_substep_seconds)
+            .ceil()
+            .clamp(1.0, f32::from(cfg.max_substeps.max(1))) as u32;
+        let substep_seconds = dt / substeps as f32;
+        for _ in 0..substeps {
+            if !self.substep(cfg, graph, position, substep_seconds) {
+                return;
+            }
+        }
+        if self.velocity.norm() < REST_SPEED * cfg.meters_to_absolute {
+            self.still_steps += 1;
+        } else {
+            self.still_steps = 0;
+        }
+    }
+
+    /// Move the projectile over an interval short enough to be integrated in one go, returning
+    /// whether it could move at all
+    fn substep(
+        &mut self,
+        cfg: &SimConfig,
+        graph: &Graph,
+        position: &mut Position,
+        dt: f32,
+    ) -> bool {
+        let Some(up) = graph.get_relative_up(position) else {
+            return false;
+        };
+        let velocity = self.velocity - up.into_inner() * cfg.character.gravity_acceleration * dt;
+        let distance = velocity.norm() * dt;
+        if distance < 1e-8 {
+            self.velocity = velocity;
+            return true;
+        }
+        let direction = velocity.normalize();
+        let ray = Ray::new(math::origin(), direction.to_homogeneous());
+        let hit = match graph_collision::sphere_cast(
+            cfg.projectile_radius,
+            graph,
+            position,
+            &ray,
+            distance.tanh(),
+        ) {
+            Ok(hit) => hit,
+            Err(e) => {
+                trace!("projectile held at the edge of the world: {:?}", e);
+                return false;
+            }
+        };
+        self.velocity = velocity;
+        let moved = hit
+            .as_ref()
+            .map_or(distance, |hit| hit.tanh_distance.atanh());
+        let displacement = math::translate_along(&(direction * moved));
+        position.local *= displacement;
+        if let Some(hit) = hit {
+            // Relative to the projectile where it meets the surface, as in the character controller
+            let normal = na::UnitVector3::new_normalize(
+                (math::mtranspose(&displacement) * hit.normal).xyz(),
+            );
+            self.bounce(cfg, &normal);
+        }
+
+        position.local = math::renormalize_isometry(&position.local);
+        let (node, transition) = graph.normalize_transform(position.node, &position.local);
+        if node != position.node {
+            position.node = node;
+            position.local = transition * position.local;
+        }
+        true
+    }
+
+    /// Rebound off a surface with the given outward `normal`, losing speed to restitution and
+    /// friction
+    fn bounce(&mut self, cfg: &SimConfig, normal: &na::UnitVector3<f32>) {
+        let approach = self.velocity.dot(normal);
+        if approach >= 0.0 {
+            // Already moving away
+            return;
+        }
+        let mut sliding = self.velocity - normal.into_inner() * approach;
+        let impulse = -approach * (1.0 + cfg.projectile_restitution);
+        let speed = sliding.norm();
+        if speed > 0.0 {
+            sliding *= (speed - FRICTION * impulse).max(0.0) / speed;
+        }
+        let rebound = -approach * cfg.projectile_restitution;
+        self.velocity = if rebound < REST_SPEED * cfg.meters_to_absolute {
+            sliding
+        } else {
+            sliding + normal.into_inner() * rebound
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        graph::NodeId,
+        test_graphs::{elevation, graph_with_floor, GRAPH_RADIUS},
+        traversal::ensure_nearby,
+        SimConfigRaw,
+    };
+
+    /// Elevations in meters between which voxel centers are filled, the floor's surface lying
+    /// within a voxel above the top
+    const FLOOR: std::ops::Range<f32> = -3.0..-1.0;
+
+    fn config() -> SimConfig {
+        SimConfig::from_raw(&SimConfigRaw::default())
+    }
+
+    /// Empty space around the origin, above a floor of dirt filling the voxels whose centers lie
+    /// within `FLOOR`
+    fn floor_graph(cfg: &SimConfig) -> Graph {
+        let m = cfg.meters_to_absolute;
+        graph_with_floor(cfg, FLOOR.start * m..FLOOR.end * m)
+    }
+
+    /// Step `projectile` until it comes to rest, returning the number of steps that took
+    fn settle(
+        cfg: &SimConfig,
+        graph: &Graph,
+        projectile: &mut Projectile,
+        position: &mut Position,
+    ) -> u32 {
+        let dt = cfg.step_interval.as_secs_f32();
+        for steps in 1..=100 {
+            projectile.step(cfg, graph, position, dt);
+            if projectile.at_rest() {
+                return steps;
+            }
+        }
+        panic!("projectile never came to rest");
+    }
+
+    /// Whether a projectile at `position` lies on top of the floor, neither sunk into it nor
+    /// hovering above it
+    fn on_floor(cfg: &SimConfig, graph: &Graph, position: &Position) -> bool {
+        // Elevations are found in the root node's coordinates
+        assert_eq!(position.node, NodeId::ROOT);
+        let m = cfg.meters_to_absolute;
+        let height = elevation(graph, &(position.local * math::origin()));
+        height > FLOOR.end * m && height < (FLOOR.end + 1.0) * m + cfg.projectile_radius
+    }
+
+    #[test]
+    fn dropped_projectile_settles_on_floor() {
+        let cfg = config();
+        let m = cfg.meters_to_absolute;
+        let graph = floor_graph(&cfg);
+        let mut projectile = Projectile::new(Material::Dirt, na::Vector3::zeros());
+        let mut position = Position::origin();
+        let steps = settle(&cfg, &graph, &mut projectile, &mut position);
+        assert!(steps > REST_STEPS, "came to rest before reaching the floor");
+
+        assert!(on_floor(&cfg, &graph, &position));
+
+        // And staying there
+        let rested = position;
+        projectile.step(&cfg, &graph, &mut position, cfg.step_interval.as_secs_f32());
+        assert!(projectile.at_rest());
+        assert!(
+            math::distance(
+                &(rested.local * math::origin()),
+                &(position.local * math::origin())
+            ) < 0.01 * m
+        );
+    }
+
+    #[test]
+    fn thrown_projectile_slides_to_a_stop() {
+        let cfg = config();
+        let m = cfg.meters_to_absolute;
+        let graph = floor_graph(&cfg);
+        let up = graph.get_relative_up(&Position::origin()).unwrap();
+        let across = (na::Vector3::x() - up.into_inner() * up.x).normalize();
+        let mut projectile = Projectile::new(Material::Dirt, across * 3.0 * m);
+        let mut position = Position::origin();
+        settle(&cfg, &graph, &mut projectile, &mut position);
+        assert!(on_floor(&cfg, &graph, &position));
+    }
+
+    #[test]
+    fn motion_resets_rest() {
+        let cfg = config();
+        let m = cfg.meters_to_absolute;
+        let graph = floor_graph(&cfg);
+        let mut projectile = Projectile::new(Material::Dirt, na::Vector3::zeros());
+        let mut position = Position::origin();
+        settle(&cfg, &graph, &mut projectile, &mut position);
+
+        let up = graph.get_relative_up(&position).unwrap();
+        projectile.velocity = up.into_inner() * 5.0 * m;
+        projectile.step(&cfg, &graph, &mut position, cfg.step_interval.as_secs_f32());
+        assert_eq!(projectile.still_steps, 0);
+        assert!(!projectile.at_rest());
+    }
+
+    #[test]
+    fn bounces_lose_speed() {
+        let cfg = config();
+        let m = cfg.meters_to_absolute;
+        let normal = na::Vector3::y_axis();
+
+        // A hard hit rebounds at a fraction of its speed, and slides less far
+        let mut projectile = Projectile::new(Material::Dirt, na::Vector3::new(2.0, -10.0, 0.0) * m);
+        projectile.bounce(&cfg, &normal);
+        let rebound = 10.0 * m * cfg.projectile_restitution;
+        assert!((projectile.velocity.y - rebound).abs() < 1e-4);
+        assert!(projectile.velocity.x >= 0.0 && projectile.velocity.x < 2.0 * m);
+
+        // A gentle one doesn't rebound at all
+        let mut projectile = Projectile::new(Material::Dirt, na::Vector3::new(0.0, -1.0, 0.0) * m);
+        projectile.bounce(&cfg, &normal);
+        assert_eq!(projectile.velocity, na::Vector3::zeros());
+
+        // Nor does anything moving away already
+        let velocity = na::Vector3::new(1.0, 1.0, 0.0) * m;
+        let mut projectile = Projectile::new(Material::Dirt, velocity);
+        projectile.bounce(&cfg, &normal);
+        assert_eq!(projectile.velocity, velocity);
+    }
+
+    #[test]
+    fn waits_for_unpopulated_nodes() {
+        let cfg = config();
+        let mut graph = Graph::new(cfg.chunk_size);
+        ensure_nearby(&mut graph, &Position::origin(), GRAPH_RADIUS);
+        let mut projectile = Projectile::new(Material::Dirt, na::Vector3::x());
+        let mut position = Position::origin();
+        for _ in 0..2 * REST_STEPS {
+            projectile.step(&cfg, &graph, &mut position, cfg.step_interval.as_secs_f32());
+        }
+        assert_eq!(position.local, Position::origin().local);
+        assert_eq!(projectile.velocity, na::Vector3::x());
+        assert!(!projectile.at_rest());
+    }
+}
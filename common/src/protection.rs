@@ -0,0 +1,21 @@
+//! Areas where only some players may change blocks
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::node_path::NodePath;
+
+/// A ball around the origin of a node within which only the listed players may change blocks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProtectedRegion {
+    pub name: String,
+    /// Route to the node at whose origin the region is centered
+    pub center_path: NodePath,
+    /// Distance from the center within which blocks are protected, in meters
+    pub radius: f32,
+    /// Names of the characters permitted to change blocks within the region
+    #[serde(default)]
+    pub allowed: BTreeSet<String>,
+}
@@ -0,0 +1,1033 @@
+pub mod negotiation;
+
+use std::{mem, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block_entity::{BlockEntity, BlockEntityChange, SIGN_LINES},
+    character_controller::{Tether, TraceDump},
+    codec, dodeca,
+    graph::{Graph, NodeId},
+    inventory::Inventory,
+    node::{ChunkId, Coords},
+    node_path::NodePath,
+    protection::ProtectedRegion,
+    region::{self, BoxEdit, RegionCoverage},
+    template::{Orientation, Template},
+    waypoint::Waypoint,
+    world::{Material, Shape},
+    EntityId, Step,
+};
+
+pub use negotiation::{
+    AssetPackOffer, Capabilities, ClientHello, MaterialMismatch, MaterialRegistry, PackHash,
+    ServerHello, ServerHelloHeader,
+};
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct Position {
+    pub node: NodeId,
+    pub local: na::Matrix4<f32>,
+}
+
+impl Position {
+    pub fn origin() -> Self {
+        Self {
+            node: NodeId::ROOT,
+            local: na::Matrix4::identity(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateDelta {
+    pub step: Step,
+    /// Highest input generation received prior to `step`
+    pub latest_input: u16,
+    pub positions: Vec<(EntityId, Position)>,
+    pub character_states: Vec<(EntityId, CharacterState)>,
+    /// Fraction of the day/night cycle elapsed as of `step`, in [0, 1). Sunrise is at 0, noon at
+    /// 0.25, sunset at 0.5, and midnight at 0.75.
+    pub world_time: f32,
+    /// Tether on the receiving client's character as of `step`, which its prediction applies to
+    /// the inputs the server has yet to process. Filled in for each client, like `latest_input`.
+    ///
+    /// Impulses need no echo, being part of the state they were applied to by the time it's sent.
+    pub tether: Option<Tether>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CharacterState {
+    pub velocity: na::Vector3<f32>,
+    pub on_ground: bool,
+    pub orientation: na::UnitQuaternion<f32>,
+    /// Number of times the character has been teleported, wrapping. Clients adopt the
+    /// authoritative state outright when this changes, rather than reconciling their prediction
+    /// with it.
+    pub teleports: u16,
+    /// Remaining health, between zero and `CharacterConfig::max_health`. Only ever set by the
+    /// server.
+    pub health: f32,
+    /// Whether the character's player has sent no commands for long enough to be taken as away.
+    /// Only ever set by the server.
+    pub afk: bool,
+}
+
+/// Most bytes of entities, nodes, or edits carried by one `EntityUpdates`, `GraphUpdates`, or
+/// `WorldEdits`, beyond which the rest follow in further messages for the same step
+pub const MAX_UPDATE_BYTES: usize = 64 << 10;
+
+/// Everything that changed during a step besides the states of entities, as sent whole to clients
+/// without `Capabilities::SPLIT_UPDATES`
+///
+/// Other clients are sent its parts separately, on streams of their own, so that a large batch of
+/// one kind can't hold up the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spawns {
+    pub step: Step,
+    pub spawns: Vec<(EntityId, Vec<Component>)>,
+    pub despawns: Vec<EntityId>,
+    pub nodes: Vec<FreshNode>,
+    /// Accepted block updates, each with the character that requested it, or `EntityId::NOBODY`
+    /// for those made by the server, like blocks changed by being used
+    pub block_updates: Vec<(EntityId, BlockUpdate)>,
+    /// Accepted block fills, each with the character that requested it, applied after
+    /// `block_updates`
+    pub block_fills: Vec<(EntityId, BlockFill)>,
+    pub modified_chunks: Vec<(ChunkId, SerializableVoxelData)>,
+    /// Modified chunks that differ from world generation in few enough voxels to send only those
+    pub chunk_diffs: Vec<ChunkDiff>,
+}
+
+impl Spawns {
+    pub fn is_empty(&self) -> bool {
+        self.spawns.is_empty()
+            && self.despawns.is_empty()
+            && self.nodes.is_empty()
+            && self.block_updates.is_empty()
+            && self.block_fills.is_empty()
+            && self.modified_chunks.is_empty()
+            && self.chunk_diffs.is_empty()
+    }
+
+    /// Divide into the messages sent in its place to clients with `Capabilities::SPLIT_UPDATES`,
+    /// each carrying at most `max_bytes` of entities, nodes, or edits unless a single one is
+    /// larger
+    ///
+    /// Messages that would be empty are left out. Despawns all go in the first `EntityUpdates`.
+    pub fn split(
+        self,
+        max_bytes: usize,
+    ) -> (Vec<EntityUpdates>, Vec<GraphUpdates>, Vec<WorldEdits>) {
+        let step = self.step;
+        let mut entities = batches(self.spawns, max_bytes)
+            .into_iter()
+            .map(|spawns| EntityUpdates {
+                step,
+                spawns,
+                despawns: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        if !self.despawns.is_empty() {
+            match entities.first_mut() {
+                Some(first) => first.despawns = self.despawns,
+                None => entities.push(EntityUpdates {
+                    step,
+                    spawns: Vec::new(),
+                    despawns: self.despawns,
+                }),
+            }
+        }
+        let graph = batches(self.nodes, max_bytes)
+            .into_iter()
+            .map(|nodes| GraphUpdates { step, nodes })
+            .collect();
+        let edits = WorldEdits {
+            step,
+            block_updates: Vec::new(),
+            block_fills: Vec::new(),
+            modified_chunks: Vec::new(),
+            chunk_diffs: Vec::new(),
+        };
+        let edits =
+            batches(self.block_updates, max_bytes)
+                .into_iter()
+                .map(|block_updates| WorldEdits {
+                    block_updates,
+                    ..edits.clone()
+                })
+                .chain(
+                    batches(self.block_fills, max_bytes)
+                        .into_iter()
+                        .map(|block_fills| WorldEdits {
+                            block_fills,
+                            ..edits.clone()
+                        }),
+                )
+                .chain(
+                    batches(self.chunk_diffs, max_bytes)
+                        .into_iter()
+                        .map(|chunk_diffs| WorldEdits {
+                            chunk_diffs,
+                            ..edits.clone()
+                        }),
+                )
+                .chain(batches(self.modified_chunks, max_bytes).into_iter().map(
+                    |modified_chunks| WorldEdits {
+                        modified_chunks,
+                        ..edits.clone()
+                    },
+                ))
+                .collect();
+        (entities, graph, edits)
+    }
+}
+
+/// `items` in order, divided into runs of at most `max_bytes` encoded, except where a single item
+/// is larger
+fn batches<T: Serialize>(items: Vec<T>, max_bytes: usize) -> Vec<Vec<T>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut bytes = 0;
+    for item in items {
+        let len = codec::encoded_len(&item);
+        if !batch.is_empty() && bytes + len > max_bytes {
+            batches.push(mem::take(&mut batch));
+            bytes = 0;
+        }
+        bytes += len;
+        batch.push(item);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// Entities that appeared and disappeared during a step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntityUpdates {
+    pub step: Step,
+    pub spawns: Vec<(EntityId, Vec<Component>)>,
+    /// Applied before `spawns`, so that an ID can be despawned and reused within one step
+    pub despawns: Vec<EntityId>,
+}
+
+/// Nodes added to the graph during a step, each after its parent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphUpdates {
+    pub step: Step,
+    pub nodes: Vec<FreshNode>,
+}
+
+/// Changes made to blocks during a step, applied in the order of the fields
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldEdits {
+    pub step: Step,
+    /// As in `Spawns`
+    pub block_updates: Vec<(EntityId, BlockUpdate)>,
+    /// As in `Spawns`
+    pub block_fills: Vec<(EntityId, BlockFill)>,
+    pub chunk_diffs: Vec<ChunkDiff>,
+    pub modified_chunks: Vec<(ChunkId, SerializableVoxelData)>,
+}
+
+/// Messages sent on the server's ordered stream after `ServerHello`
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Spawns(Spawns),
+    Inventory(InventoryUpdate),
+    BlockUpdateRejected(BlockUpdateRejection),
+    MovementModes(MovementModesUpdate),
+    Waypoints(WaypointsUpdate),
+    CollisionTrace(CollisionTraceReport),
+    /// Sounds made near the client's character during a step
+    Sounds(Vec<SoundEvent>),
+    /// Regions of the graph the client's character has come near, sent in place of the nodes and
+    /// modified chunks of the first `Spawns` and of the ways sent in answer to
+    /// `ClientMessage::ResyncNodes`
+    GraphRegions(Vec<EncodedGraphRegion>),
+    /// The parts of `Spawns`, sent in its place to clients with `Capabilities::SPLIT_UPDATES`.
+    /// Graph updates and regions travel on the second ordered stream, and world edits and block
+    /// update rejections on the third.
+    EntityUpdates(EntityUpdates),
+    GraphUpdates(GraphUpdates),
+    WorldEdits(WorldEdits),
+    /// The character the client is told of the surroundings of in place of its own, if any, in
+    /// answer to `ClientMessage::Follow` or once the character followed is lost
+    Following(Option<EntityId>),
+    /// Block entities created, changed, or removed during a step, sent after the step's edits.
+    /// Clients not sent graph regions are sent every block entity this way when they join.
+    BlockEntities(Vec<BlockEntityChange>),
+}
+
+impl ServerMessage {
+    /// Name of the message's variant, by which traffic is accounted
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Self::Spawns(_) => "Spawns",
+            Self::Inventory(_) => "Inventory",
+            Self::BlockUpdateRejected(_) => "BlockUpdateRejected",
+            Self::MovementModes(_) => "MovementModes",
+            Self::Waypoints(_) => "Waypoints",
+            Self::CollisionTrace(_) => "CollisionTrace",
+            Self::Sounds(_) => "Sounds",
+            Self::GraphRegions(_) => "GraphRegions",
+            Self::EntityUpdates(_) => "EntityUpdates",
+            Self::GraphUpdates(_) => "GraphUpdates",
+            Self::WorldEdits(_) => "WorldEdits",
+            Self::Following(_) => "Following",
+            Self::BlockEntities(_) => "BlockEntities",
+        }
+    }
+}
+
+/// Part of the graph sent as a unit: the descendants of one node down to a fixed number of
+/// generations, with the changes made to their chunks
+///
+/// A region carries the way to its root, so it can be added to a graph on its own, whatever other
+/// regions have or haven't arrived.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GraphRegion {
+    /// The way from the origin to the region's root
+    pub root: NodePath,
+    /// Nodes of the region other than its root, each after its parent
+    pub nodes: Vec<FreshNode>,
+    pub modified_chunks: Vec<(ChunkId, SerializableVoxelData)>,
+    pub chunk_diffs: Vec<ChunkDiff>,
+    /// Block entities attached to voxels of the region's chunks
+    pub block_entities: Vec<(ChunkId, Coords, BlockEntity)>,
+}
+
+/// A `GraphRegion` encoded once, to be sent to any number of clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EncodedGraphRegion(pub Vec<u8>);
+
+impl EncodedGraphRegion {
+    pub fn encode(region: &GraphRegion) -> Self {
+        Self(codec::encode(region))
+    }
+
+    pub fn decode(&self) -> anyhow::Result<GraphRegion> {
+        codec::decode(&self.0)
+    }
+}
+
+/// Something that happened loudly enough for nearby clients to play a sound
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SoundEvent {
+    pub kind: SoundKind,
+    pub source: SoundSource,
+    /// Loudness relative to that of a block being broken or placed
+    pub intensity: f32,
+    /// Step during which the sound was made
+    pub step: Step,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SoundKind {
+    BlockBroken,
+    BlockPlaced,
+    Teleport,
+}
+
+impl SoundKind {
+    /// The sound `update` makes when it's applied
+    pub fn of_block_update(update: &BlockUpdate) -> Self {
+        if update.new_material == Material::Void {
+            SoundKind::BlockBroken
+        } else {
+            SoundKind::BlockPlaced
+        }
+    }
+}
+
+/// Where a sound comes from
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum SoundSource {
+    Position(Position),
+    /// The center of a voxel, which clients can match against the blocks they changed themselves
+    Voxel(ChunkId, Coords),
+}
+
+impl SoundSource {
+    /// The node the sound is made in
+    pub fn node(&self) -> NodeId {
+        match *self {
+            SoundSource::Position(ref position) => position.node,
+            SoundSource::Voxel(chunk, _) => chunk.node,
+        }
+    }
+}
+
+/// Notice that a `BlockUpdate` requested by the client won't be applied
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockUpdateRejection {
+    /// `BlockUpdate::sequence` of the rejected update
+    pub sequence: u32,
+    pub reason: RejectionReason,
+}
+
+/// Why a `BlockUpdate` was rejected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectionReason {
+    /// The block couldn't be changed as requested, such as for want of the material, because it
+    /// had already changed, or because a character stood where it was to be placed
+    Refused,
+    /// The block lies within the named protected region, which the character isn't permitted to
+    /// change
+    Protected(String),
+}
+
+/// Answer to `ClientMessage::DumpCollisionTrace`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollisionTraceReport {
+    pub character: String,
+    /// The steps recorded, or `None` if the character isn't being traced
+    pub trace: Option<TraceDump>,
+}
+
+/// Changes to the waypoints shared by everyone on the server
+///
+/// Sent with every waypoint when a client joins, then with only those that change.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WaypointsUpdate {
+    /// Waypoints added or replaced
+    pub set: Vec<Waypoint>,
+    /// Names of waypoints removed
+    pub removed: Vec<String>,
+}
+
+/// The authoritative contents of a client's character's inventory, sent when it changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InventoryUpdate {
+    /// Highest input generation incorporated into `inventory`
+    pub latest_input: u16,
+    pub inventory: Inventory,
+}
+
+/// The movement modes a client's character may use, sent when they change from
+/// `SimConfig::default_movement_modes`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovementModesUpdate {
+    /// Highest input generation processed before the change. Later inputs are restricted to
+    /// `allowed`.
+    pub latest_input: u16,
+    pub allowed: MovementModes,
+}
+
+/// Messages sent by clients after `ClientHello`, each on its own stream
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ClientMessage {
+    Command(Command),
+    /// Jump the day/night cycle to the given fraction, as in `StateDelta::world_time`. Only
+    /// honored from clients the server lists as administrators, and only if finite.
+    SetWorldTime(f32),
+    /// Write the world to the save now, rather than at the next autosave. Only honored from
+    /// clients the server lists as administrators.
+    Save,
+    /// Change the movement modes the named client's character may use. Only honored from clients
+    /// the server lists as administrators.
+    SetMovementModes {
+        character: String,
+        allowed: MovementModes,
+    },
+    /// Move the named client's character to `destination`. Only honored from clients the server
+    /// lists as administrators.
+    Teleport {
+        character: String,
+        destination: TeleportDestination,
+    },
+    /// Share a waypoint with everyone on the server, replacing any of the same name. The server
+    /// fills in `Waypoint::owner`. Only honored from clients the server lists as administrators.
+    SetWaypoint(Waypoint),
+    /// Remove the shared waypoint with this name. Only honored from clients the server lists as
+    /// administrators.
+    RemoveWaypoint(String),
+    /// Protect a region, replacing any of the same name. Only honored from clients the server
+    /// lists as administrators.
+    SetProtectedRegion(ProtectedRegion),
+    /// Lift the protection of the region with this name. Only honored from clients the server
+    /// lists as administrators.
+    RemoveProtectedRegion(String),
+    /// Record how collisions are handled for the named client's character over its latest `steps`
+    /// steps, or stop recording if `steps` is 0. Only honored from clients the server lists as
+    /// administrators.
+    TraceCollisions {
+        character: String,
+        steps: u32,
+    },
+    /// Ask for what's been recorded of the named client's character since `TraceCollisions`,
+    /// answered with `ServerMessage::CollisionTrace`. Only honored from clients the server lists
+    /// as administrators.
+    DumpCollisionTrace(String),
+    /// Ask for the way from the origin to each of these nodes again, having received nodes that
+    /// branch from them but not the nodes themselves, answered with `ServerMessage::Spawns`, or with
+    /// the regions containing them under `Capabilities::GRAPH_REGIONS`. Only sent to servers
+    /// offering `Capabilities::NODE_RESYNC`.
+    ResyncNodes(Vec<NodeId>),
+    /// Undo blocks changed recently by a player or in a region. Only honored from clients the
+    /// server lists as administrators.
+    Rollback(Rollback),
+    /// Extend the graph ahead of the view, which is headed for the edge of what the client knows
+    /// of it. Only sent to servers offering `Capabilities::NODE_HINTS`.
+    NodeInterestHint(NodeInterestHint),
+    /// Tell the client of everything around the named character as if it were the client's own,
+    /// or of what's around its own character again if `None`, answered with
+    /// `ServerMessage::Following`. Only honored from clients the server lists as administrators,
+    /// and only sent to servers offering `Capabilities::FOLLOW`.
+    Follow(Option<String>),
+    /// Write `lines` on the sign at `coords` in `chunk`, which the client's character must be able
+    /// to reach and permitted to change
+    SetSignText {
+        chunk: ChunkId,
+        coords: Coords,
+        lines: [String; SIGN_LINES],
+    },
+    /// Nothing but a sign that the client is still there, which doesn't count as its player being
+    /// active
+    Keepalive,
+}
+
+impl ClientMessage {
+    /// Name of the message's variant, by which traffic is accounted
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Self::Command(_) => "Command",
+            Self::SetWorldTime(_) => "SetWorldTime",
+            Self::Save => "Save",
+            Self::SetMovementModes { .. } => "SetMovementModes",
+            Self::Teleport { .. } => "Teleport",
+            Self::SetWaypoint(_) => "SetWaypoint",
+            Self::RemoveWaypoint(_) => "RemoveWaypoint",
+            Self::SetProtectedRegion(_) => "SetProtectedRegion",
+            Self::RemoveProtectedRegion(_) => "RemoveProtectedRegion",
+            Self::TraceCollisions { .. } => "TraceCollisions",
+            Self::DumpCollisionTrace(_) => "DumpCollisionTrace",
+            Self::ResyncNodes(_) => "ResyncNodes",
+            Self::Rollback(_) => "Rollback",
+            Self::NodeInterestHint(_) => "NodeInterestHint",
+            Self::Follow(_) => "Follow",
+            Self::SetSignText { .. } => "SetSignText",
+            Self::Keepalive => "Keepalive",
+        }
+    }
+}
+
+/// Where to teleport a character to
+///
+/// There are no absolute coordinates to name, so destinations are given relative to things that
+/// can be: other characters, routes through the graph, and the character itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TeleportDestination {
+    /// The named character's position, raised by `offset_along_up` absolute units
+    Character { name: String, offset_along_up: f32 },
+    /// `local`, in the coordinates of the node at the end of `path`
+    Path {
+        path: NodePath,
+        local: na::Matrix4<f32>,
+    },
+    /// `translation` in absolute units, in the character's own frame
+    Relative { translation: na::Vector3<f32> },
+}
+
+/// Blocks to return to what they were before they were changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rollback {
+    pub scope: RollbackScope,
+    /// How far back to undo changes
+    pub seconds: u32,
+    /// Whether to undo changes to blocks that have been changed again since, overwriting what
+    /// they've become
+    pub force: bool,
+}
+
+/// Nodes a client expects to need before the server would otherwise announce them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeInterestHint {
+    /// The node around which nodes are wanted
+    pub center_path: NodePath,
+    /// Distance in absolute units from the center's origin within which every node is wanted, at
+    /// most `MAX_NODE_HINT_MARGIN` beyond the view distance
+    pub radius: f32,
+}
+
+/// Farthest beyond the view distance that a `NodeInterestHint` may reach, in absolute units
+///
+/// Enough for a hint centered on the node nearest a point to take in everything within the view
+/// distance of that point.
+pub const MAX_NODE_HINT_MARGIN: f32 = dodeca::BOUNDING_SPHERE_RADIUS_F32;
+
+/// Whose changes `Rollback` undoes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RollbackScope {
+    /// Those made by the named character
+    Player(String),
+    /// Those made by anyone within `radius` meters of the point `local_translation` absolute units
+    /// from the origin of the node at the end of `path`
+    Region {
+        path: NodePath,
+        local_translation: na::Vector3<f32>,
+        radius: f32,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Command {
+    pub generation: u16,
+    pub character_input: CharacterInput,
+    pub orientation: na::UnitQuaternion<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CharacterInput {
+    /// Relative to the character's current position, excluding orientation
+    pub movement: MovementInput,
+    pub jump: bool,
+    pub no_clip: bool,
+    pub block_update: Option<BlockUpdate>,
+    /// Blocks to fill at once, numbered along with `block_update`
+    pub block_fill: Option<BlockFill>,
+    /// What the character is pointing at to use, if its player pressed the use key during the step
+    pub use_target: Option<InteractTarget>,
+    /// Material the character throws a unit of, if its player pressed the throw key during the step
+    pub throw: Option<Material>,
+    /// Set by the server for the step it applies the input in, replacing whatever a client sent
+    pub external: ExternalInfluence,
+}
+
+impl CharacterInput {
+    /// Fall back to ordinary movement where the input asks for a mode not in `allowed`, returning
+    /// whether anything changed
+    ///
+    /// The server does this to every input it receives, and clients to the inputs they predict.
+    pub fn restrict(&mut self, allowed: MovementModes) -> bool {
+        if self.no_clip && !allowed.contains(MovementModes::NO_CLIP) {
+            self.no_clip = false;
+            return true;
+        }
+        false
+    }
+}
+
+/// What moves a character during a step besides its own input, like knockback or a grappling hook
+///
+/// Applied after the character's own acceleration and before its speed is capped and collisions are
+/// handled, so it can't push a character through terrain. Characters in no-clip mode are unaffected.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExternalInfluence {
+    pub impulse: Option<ExternalImpulse>,
+    pub constraint: Option<PositionConstraint>,
+}
+
+/// A sudden change in a character's velocity, applied once at the start of a step
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternalImpulse {
+    /// Relative to the character, in absolute units per second
+    pub delta_velocity: na::Vector3<f32>,
+    pub mode: ImpulseMode,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImpulseMode {
+    /// `ExternalImpulse::delta_velocity` is added to the character's velocity
+    Add,
+    /// `ExternalImpulse::delta_velocity` replaces the character's velocity
+    Override,
+}
+
+/// A damped spring pulling a character toward a point
+///
+/// Converges without oscillating out of control as long as `damping * dt < 2` and
+/// `stiffness * dt < 2 * damping`, where `dt` is the length in seconds of a substep.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PositionConstraint {
+    /// Displacement from the character to the point as of the start of the step, relative to the
+    /// character, in absolute units
+    pub offset: na::Vector3<f32>,
+    /// Acceleration toward the point per unit of distance from it, per second squared
+    pub stiffness: f32,
+    /// Deceleration per unit of velocity, per second
+    pub damping: f32,
+    /// Greatest acceleration the spring applies, in absolute units per second squared
+    pub max_acceleration: f32,
+}
+
+/// A set of movement modes beyond ordinary walking, which is always allowed
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MovementModes(u8);
+
+impl MovementModes {
+    pub const NONE: Self = Self(0);
+    /// Flying through terrain, as requested by `CharacterInput::no_clip`
+    pub const NO_CLIP: Self = Self(1);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for MovementModes {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Desired movement over a step, as a fraction of the character's maximum speed in each direction
+///
+/// Clients sample movement input many times per step, and all conversions from those samples to
+/// the per-step average sent to the server go through `from_accumulated`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MovementInput(na::Vector3<f32>);
+
+impl MovementInput {
+    /// Movement input held steadily for a whole step
+    pub fn new(fraction_of_max_speed: na::Vector3<f32>) -> Self {
+        Self(fraction_of_max_speed)
+    }
+
+    pub fn zero() -> Self {
+        Self(na::Vector3::zeros())
+    }
+
+    /// Average movement input over the first `elapsed` of a step
+    ///
+    /// `accumulated` is the sum of each input sample weighted by the fraction of `step_interval`
+    /// it was held for, so that it's the average over the whole step if the remainder of the step
+    /// is assumed to have no input.
+    pub fn from_accumulated(
+        accumulated: na::Vector3<f32>,
+        elapsed: Duration,
+        step_interval: Duration,
+    ) -> Self {
+        let fraction_elapsed = elapsed.as_secs_f32() / step_interval.as_secs_f32();
+        if fraction_elapsed <= 0.0 {
+            return Self::zero();
+        }
+        Self(accumulated / fraction_elapsed)
+    }
+
+    pub fn vector(&self) -> &na::Vector3<f32> {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockUpdate {
+    pub chunk_id: ChunkId,
+    pub coords: Coords,
+    pub new_material: Material,
+    /// Ignored when `new_material` is `Material::Void`
+    pub new_shape: Shape,
+    /// Assigned by the requesting client, counting up from zero on each connection, and echoed
+    /// back by the server so the client can tell which of its requests was resolved
+    pub sequence: u32,
+}
+
+/// A box of voxels to fill with one material, as a wall, a floor, or a line of blocks, or with a
+/// template pasted from elsewhere
+///
+/// The box is defined as in `Graph::for_each_block_in_box`, so that it covers the same voxels
+/// wherever it's applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockFill {
+    /// Chunk of the corner voxel
+    pub chunk_id: ChunkId,
+    /// The corner voxel, from which the box extends along the positive axes of `chunk_id`
+    pub coords: Coords,
+    /// Number of voxels spanned along each axis
+    pub extents: [u32; 3],
+    pub contents: FillContents,
+    /// As in `BlockUpdate`, from the same count
+    pub sequence: u32,
+}
+
+impl BlockFill {
+    /// Number of voxels in the box
+    pub fn volume(&self) -> u64 {
+        region::box_volume(self.extents)
+    }
+
+    /// Whether the box's extents are those of what it's filled with
+    pub fn is_consistent(&self) -> bool {
+        match self.contents {
+            FillContents::Material(_) => true,
+            FillContents::Template {
+                ref template,
+                orientation,
+            } => template.pasted_extents(orientation) == self.extents,
+        }
+    }
+
+    /// Fill the box in `graph`, whatever its size, as `Graph::edit_box_with` would
+    pub fn apply(&self, graph: &mut Graph) -> BoxEdit {
+        let contents = self.contents.voxels(graph.chunk_parity(self.chunk_id));
+        graph
+            .edit_box_with(self.anchor(), self.extents, contents, u64::MAX)
+            .unwrap()
+    }
+
+    /// Work out what `apply` would do without doing it, as `Graph::plan_box_edit` does
+    pub fn plan(
+        &self,
+        graph: &Graph,
+        f: impl FnMut(ChunkId, Coords, Option<(Material, Shape)>, (Material, Shape)),
+    ) -> RegionCoverage {
+        let contents = self.contents.voxels(graph.chunk_parity(self.chunk_id));
+        graph.plan_box_edit(self.anchor(), self.extents, contents, f)
+    }
+
+    fn anchor(&self) -> (ChunkId, Coords) {
+        (self.chunk_id, self.coords)
+    }
+}
+
+/// What a `BlockFill` fills its box with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FillContents {
+    /// Full blocks of one material
+    Material(Material),
+    /// A template turned by `orientation`, whose pasted extents are the box's
+    Template {
+        template: Box<Template>,
+        orientation: Orientation,
+    },
+}
+
+impl FillContents {
+    /// Whether the fill only clears the box
+    pub fn is_void(&self) -> bool {
+        *self == FillContents::Material(Material::Void)
+    }
+
+    /// What the voxel at each offset of the box is to hold, with its shape in the axes of the box,
+    /// for a box anchored in a chunk of parity `parity`
+    fn voxels(&self, parity: bool) -> impl Fn([u32; 3]) -> (Material, Shape) + '_ {
+        move |offset| match *self {
+            FillContents::Material(material) => (material, Shape::FULL),
+            FillContents::Template {
+                ref template,
+                orientation,
+            } => template.pasted(orientation, parity)(offset),
+        }
+    }
+}
+
+/// Something a character can use by pointing at it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InteractTarget {
+    Entity(EntityId),
+    /// A voxel, identified as in `BlockUpdate`
+    Block(ChunkId, Coords),
+}
+
+/// What happens to an entity when a character uses it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interactable {
+    /// Taken into the user's inventory as a unit of this material, leaving the world
+    Pickup(Material),
+    /// Nothing yet, beyond the use being noted
+    Prop,
+}
+
+/// The voxels of a chunk that differ from what world generation produces for it
+///
+/// Clients generate the chunk themselves and apply the changes on top.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkDiff {
+    pub chunk: ChunkId,
+    pub changes: Vec<(Coords, Material, Shape)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerializableVoxelData {
+    pub voxels: Vec<Material>,
+    /// Shapes of `voxels`, or empty if they're all full cubes
+    pub shapes: Vec<Shape>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Component {
+    Character(Character),
+    Position(Position),
+    Interactable(Interactable),
+    /// A thrown block of the given material, in flight
+    Projectile(Material),
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct FreshNode {
+    /// The side joining the new node to `parent`
+    pub side: dodeca::Side,
+    pub parent: NodeId,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Character {
+    pub name: String,
+    pub state: CharacterState,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dodeca::Vertex;
+    use crate::node::{CoordAxis, CoordDirection};
+
+    #[test]
+    fn small_diffs_are_small() {
+        let dimension = 12;
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let diff = ChunkDiff {
+            chunk,
+            changes: vec![
+                (Coords([0, 0, 0]), Material::Dirt, Shape::FULL),
+                (Coords([5, 6, 7]), Material::Void, Shape::FULL),
+                (
+                    Coords([11, 11, 11]),
+                    Material::Sand,
+                    Shape::slab(CoordAxis::Y, CoordDirection::Minus),
+                ),
+            ],
+        };
+        let diff_size = bincode::serialized_size(&diff).unwrap();
+        assert!(diff_size < 100, "{diff_size} bytes");
+        let full = (
+            chunk,
+            SerializableVoxelData {
+                voxels: vec![Material::Dirt; usize::from(dimension).pow(3)],
+                shapes: Vec::new(),
+            },
+        );
+        assert!(bincode::serialized_size(&full).unwrap() > 50 * diff_size);
+    }
+
+    /// Encoded size of `items`, not counting the length of the sequence
+    fn items_len<T: Serialize>(items: &[T]) -> usize {
+        items.iter().map(|x| codec::encoded_len(x)).sum()
+    }
+
+    #[test]
+    fn split_spawns_reassemble() {
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let spawns = Spawns {
+            step: 7,
+            spawns: (0..40)
+                .map(|i| {
+                    (
+                        EntityId::from_bits(i),
+                        vec![Component::Position(Position::origin())],
+                    )
+                })
+                .collect(),
+            despawns: vec![EntityId::from_bits(3)],
+            nodes: dodeca::Side::iter()
+                .map(|side| FreshNode {
+                    side,
+                    parent: NodeId::ROOT,
+                })
+                .collect(),
+            block_updates: (0..30)
+                .map(|i| {
+                    (
+                        EntityId::NOBODY,
+                        BlockUpdate {
+                            chunk_id: chunk,
+                            coords: Coords([i, 0, 0]),
+                            new_material: Material::Dirt,
+                            new_shape: Shape::FULL,
+                            sequence: u32::from(i),
+                        },
+                    )
+                })
+                .collect(),
+            block_fills: (0..10)
+                .map(|i| {
+                    (
+                        EntityId::from_bits(u64::from(i) + 1),
+                        BlockFill {
+                            chunk_id: chunk,
+                            coords: Coords([0, i, 0]),
+                            extents: [4, 1, 4],
+                            contents: FillContents::Material(Material::Sand),
+                            sequence: u32::from(i),
+                        },
+                    )
+                })
+                .collect(),
+            modified_chunks: vec![(
+                chunk,
+                SerializableVoxelData {
+                    voxels: vec![Material::Dirt; 12usize.pow(3)],
+                    shapes: Vec::new(),
+                },
+            )],
+            chunk_diffs: vec![ChunkDiff {
+                chunk,
+                changes: vec![(Coords([0, 0, 0]), Material::Void, Shape::FULL)],
+            }],
+        };
+        let max_bytes = 128;
+        let (entities, graph, edits) = spawns.clone().split(max_bytes);
+        assert!(entities.len() > 1 && graph.len() > 1 && edits.len() > 3);
+        assert!(entities.iter().all(|x| x.step == spawns.step));
+        assert!(graph.iter().all(|x| x.step == spawns.step));
+        assert!(edits.iter().all(|x| x.step == spawns.step));
+        assert_eq!(entities[0].despawns, spawns.despawns);
+        assert!(entities[1..].iter().all(|x| x.despawns.is_empty()));
+        for part in &entities {
+            assert!(items_len(&part.spawns) <= max_bytes);
+        }
+        for part in &graph {
+            assert!(items_len(&part.nodes) <= max_bytes);
+        }
+        // A modified chunk is larger than the limit, so travels alone
+        for part in &edits {
+            assert!(items_len(&part.block_updates) <= max_bytes);
+            assert!(items_len(&part.block_fills) <= max_bytes);
+            assert!(items_len(&part.chunk_diffs) <= max_bytes);
+            assert!(part.modified_chunks.len() <= 1);
+        }
+
+        let reassembled = Spawns {
+            step: spawns.step,
+            spawns: entities.into_iter().flat_map(|x| x.spawns).collect(),
+            despawns: spawns.despawns.clone(),
+            nodes: graph.into_iter().flat_map(|x| x.nodes).collect(),
+            block_updates: edits
+                .iter()
+                .flat_map(|x| x.block_updates.iter().cloned())
+                .collect(),
+            block_fills: edits
+                .iter()
+                .flat_map(|x| x.block_fills.iter().cloned())
+                .collect(),
+            modified_chunks: edits
+                .iter()
+                .flat_map(|x| x.modified_chunks.iter().cloned())
+                .collect(),
+            chunk_diffs: edits.into_iter().flat_map(|x| x.chunk_diffs).collect(),
+        };
+        assert_eq!(codec::encode(&reassembled), codec::encode(&spawns));
+
+        // Nothing to send, nothing sent
+        let empty = Spawns {
+            step: 8,
+            spawns: Vec::new(),
+            despawns: Vec::new(),
+            nodes: Vec::new(),
+            block_updates: Vec::new(),
+            block_fills: Vec::new(),
+            modified_chunks: Vec::new(),
+            chunk_diffs: Vec::new(),
+        };
+        let (entities, graph, edits) = empty.split(max_bytes);
+        assert!(entities.is_empty() && graph.is_empty() && edits.is_empty());
+    }
+}
@@ -0,0 +1,680 @@
+//! Agreeing on a protocol version and optional features when a client connects
+//!
+//! The hello messages are the only ones every version must be able to decode, so their encodings
+//! are frozen: `ClientHello` may only ever gain fields at its end, which older servers ignore, and
+//! `ServerHello` leads with a header that never changes, so that a client can read the server's
+//! version before deciding whether it understands the rest. Everything sent after the hellos may
+//! change freely between versions, as long as the version is raised to match.
+//!
+//! Features that a version may or may not support are gated by `Capabilities` instead, so that
+//! they can be rolled out without refusing anyone. Each side offers what it supports, and the
+//! server only uses what both do.
+
+use std::{fmt, ops, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    world::{material_registry_hash, Material},
+    EntityId, SimConfig,
+};
+
+/// Version of the protocol spoken by this build, raised on every incompatible change
+pub const PROTOCOL_VERSION: u32 = 7;
+
+/// Oldest version of the protocol this build can talk to
+///
+/// Clients that predate versioning send no version at all, and are taken to speak version 0.
+pub const MIN_PROTOCOL_VERSION: u32 = 7;
+
+/// QUIC application error code with which servers close connections they refuse, the reason being
+/// the refusal as text
+pub const REFUSED_CLOSE_CODE: u32 = 3;
+
+/// QUIC application error code with which servers close every connection when an administrator
+/// stops them, after saving
+pub const STOPPING_CLOSE_CODE: u32 = 4;
+
+/// QUIC application error code with which servers close the connections of clients that have sent
+/// no commands for too long, the reason being given as text
+pub const IDLE_CLOSE_CODE: u32 = 5;
+
+/// A set of optional protocol features
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    /// Modified chunks may be sent as `ChunkDiff`s rather than in full
+    pub const CHUNK_DIFFS: Self = Self(1);
+    /// `ServerMessage::Waypoints` may be sent, and `ClientMessage::SetWaypoint` is understood
+    pub const SHARED_WAYPOINTS: Self = Self(2);
+    /// `ServerMessage::Sounds` may be sent
+    pub const SOUNDS: Self = Self(4);
+    /// `ClientMessage::ResyncNodes` is understood
+    pub const NODE_RESYNC: Self = Self(8);
+    /// `ServerHello` ends with `asset_pack`
+    pub const ASSET_PACKS: Self = Self(16);
+    /// `ServerMessage::GraphRegions` may be sent in place of the whole graph. Regions carry
+    /// modified chunks as diffs, so this is only used alongside `CHUNK_DIFFS`.
+    pub const GRAPH_REGIONS: Self = Self(32);
+    /// `ServerMessage::EntityUpdates`, `GraphUpdates`, and `WorldEdits` are sent in place of
+    /// `Spawns`, and the server opens two more ordered streams right after the first
+    pub const SPLIT_UPDATES: Self = Self(64);
+    /// `ClientMessage::NodeInterestHint` is understood
+    pub const NODE_HINTS: Self = Self(128);
+    /// `ClientMessage::Follow` is understood, and `ServerMessage::Following` may be sent
+    pub const FOLLOW: Self = Self(256);
+    /// `ServerHello` ends with `materials`, after `asset_pack`, so this is only used alongside
+    /// `ASSET_PACKS`
+    pub const MATERIAL_REGISTRY: Self = Self(512);
+    /// Messages from the server after its hello lead with a byte saying whether they're
+    /// compressed, as `codec::BodyFormat::Tagged` describes
+    pub const COMPRESSION: Self = Self(1024);
+    /// Every feature this build supports
+    pub const ALL: Self = Self(
+        Self::CHUNK_DIFFS.0
+            | Self::SHARED_WAYPOINTS.0
+            | Self::SOUNDS.0
+            | Self::NODE_RESYNC.0
+            | Self::ASSET_PACKS.0
+            | Self::GRAPH_REGIONS.0
+            | Self::SPLIT_UPDATES.0
+            | Self::NODE_HINTS.0
+            | Self::FOLLOW.0
+            | Self::MATERIAL_REGISTRY.0
+            | Self::COMPRESSION.0,
+    );
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// These capabilities less those of `other`
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl ops::BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl ops::BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// What one side of a connection can speak
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Protocol {
+    pub version: u32,
+    /// Oldest version of the other side that can be talked to
+    pub minimum: u32,
+    pub capabilities: Capabilities,
+}
+
+impl Protocol {
+    /// The protocol spoken by this build
+    pub const CURRENT: Self = Self {
+        version: PROTOCOL_VERSION,
+        minimum: MIN_PROTOCOL_VERSION,
+        capabilities: Capabilities::ALL,
+    };
+
+    /// As a server, decide how to talk to the client that sent `hello`, returning the capabilities
+    /// both sides support
+    pub fn accept_client(&self, hello: &ClientHello) -> Result<Capabilities, Refusal> {
+        if hello.protocol_version < self.minimum {
+            return Err(Refusal::OutdatedClient {
+                version: hello.protocol_version,
+                minimum: self.minimum,
+            });
+        }
+        Ok(self.capabilities & hello.capabilities)
+    }
+
+    /// As a client, decide whether the server that sent `header` can be talked to, returning the
+    /// capabilities in use
+    pub fn accept_server(&self, header: &ServerHelloHeader) -> Result<Capabilities, Refusal> {
+        if header.protocol_version < self.minimum {
+            return Err(Refusal::OutdatedServer {
+                version: header.protocol_version,
+                minimum: self.minimum,
+            });
+        }
+        // The server should never use what wasn't offered, but don't trust it
+        Ok(self.capabilities & header.capabilities)
+    }
+}
+
+/// Why one side won't talk to the other
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refusal {
+    OutdatedClient { version: u32, minimum: u32 },
+    OutdatedServer { version: u32, minimum: u32 },
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Refusal::OutdatedClient { version, minimum } => write!(
+                f,
+                "client speaks protocol version {version}, but the server needs at least \
+                 {minimum}; update the client to join"
+            ),
+            Refusal::OutdatedServer { version, minimum } => write!(
+                f,
+                "server speaks protocol version {version}, but the client needs at least \
+                 {minimum}; the server must be updated"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+/// The first message a client sends
+///
+/// Fields may only be added at the end. Servers take any that are missing to be those of a client
+/// from before they were added.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientHello {
+    /// Name of the character to play as. Clients from before versioning sent only this.
+    pub name: String,
+    pub protocol_version: u32,
+    /// Optional features the client supports
+    pub capabilities: Capabilities,
+}
+
+impl ClientHello {
+    /// A hello from a client of this build
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::ALL,
+        }
+    }
+
+    /// Decode a hello sent by a client of any version
+    pub fn decode(mut bytes: &[u8]) -> bincode::Result<Self> {
+        let name = bincode::deserialize_from(&mut bytes)?;
+        if bytes.is_empty() {
+            return Ok(Self {
+                name,
+                protocol_version: 0,
+                capabilities: Capabilities::NONE,
+            });
+        }
+        Ok(Self {
+            name,
+            protocol_version: bincode::deserialize_from(&mut bytes)?,
+            capabilities: bincode::deserialize_from(&mut bytes)?,
+        })
+    }
+}
+
+/// The part of `ServerHello` every version of the client can decode
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerHelloHeader {
+    pub protocol_version: u32,
+    /// Optional features in use, supported by both the server and the client
+    pub capabilities: Capabilities,
+}
+
+/// The server's answer to a `ClientHello` it accepts, first on its ordered stream
+///
+/// Servers close the connection with `REFUSED_CLOSE_CODE` instead if they refuse the client.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerHello {
+    /// Never changes, so must stay first
+    pub header: ServerHelloHeader,
+    pub character: EntityId,
+    pub sim_config: SimConfig,
+    /// Assets the server suggests drawing its world with, in place of the client's own
+    ///
+    /// Only present in the encoding when `header.capabilities` includes
+    /// `Capabilities::ASSET_PACKS`, although servers may send it to any client, since trailing
+    /// bytes are ignored.
+    pub asset_pack: Option<AssetPackOffer>,
+    /// The materials the server knows, so that clients can warn of any they can't draw
+    ///
+    /// Only present in the encoding when `header.capabilities` includes
+    /// `Capabilities::MATERIAL_REGISTRY`.
+    pub materials: Option<MaterialRegistry>,
+}
+
+impl ServerHello {
+    /// Decode a hello sent by a server of any version this build can talk to
+    pub fn decode(protocol: &Protocol, bytes: &[u8]) -> Result<Self, HelloError> {
+        // Trailing bytes are ignored, so the header can be read alone
+        let header = bincode::deserialize::<ServerHelloHeader>(bytes)?;
+        let capabilities = protocol.accept_server(&header)?;
+        let mut hello = if header
+            .capabilities
+            .contains(Capabilities::MATERIAL_REGISTRY)
+        {
+            bincode::deserialize::<Self>(bytes)?
+        } else if header.capabilities.contains(Capabilities::ASSET_PACKS) {
+            // Servers that can't describe their materials end their hello after the offer
+            let (header, character, sim_config, asset_pack) = bincode::deserialize::<(
+                ServerHelloHeader,
+                EntityId,
+                SimConfig,
+                Option<AssetPackOffer>,
+            )>(bytes)?;
+            Self {
+                header,
+                character,
+                sim_config,
+                asset_pack,
+                materials: None,
+            }
+        } else {
+            // Servers that can't offer asset packs end their hello before the offer
+            let (header, character, sim_config) =
+                bincode::deserialize::<(ServerHelloHeader, EntityId, SimConfig)>(bytes)?;
+            Self {
+                header,
+                character,
+                sim_config,
+                asset_pack: None,
+                materials: None,
+            }
+        };
+        hello.header.capabilities = capabilities;
+        Ok(hello)
+    }
+}
+
+/// Which materials one side of a connection knows
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaterialRegistry {
+    /// Number of materials known, whose IDs are those below it
+    pub count: u16,
+    /// `material_registry_hash` of those materials
+    pub hash: u64,
+}
+
+impl MaterialRegistry {
+    /// The materials this build knows
+    pub fn current() -> Self {
+        Self {
+            count: Material::COUNT as u16,
+            hash: material_registry_hash(Material::COUNT),
+        }
+    }
+
+    /// How materials sent by a peer with this registry will look to this build, if not as intended
+    pub fn mismatch(&self) -> Option<MaterialMismatch> {
+        if usize::from(self.count) > Material::COUNT {
+            return Some(MaterialMismatch::Unknown {
+                count: self.count - Material::COUNT as u16,
+            });
+        }
+        (material_registry_hash(self.count.into()) != self.hash)
+            .then_some(MaterialMismatch::Conflicting)
+    }
+}
+
+/// Why a peer's materials won't all be drawn as intended
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MaterialMismatch {
+    /// The peer knows `count` materials this build doesn't, which are drawn as placeholders
+    Unknown { count: u16 },
+    /// The peer gives some IDs to different materials than this build does
+    Conflicting,
+}
+
+impl fmt::Display for MaterialMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MaterialMismatch::Unknown { count } => write!(
+                f,
+                "the server knows {count} materials this client doesn't, which will be drawn as \
+                 placeholders"
+            ),
+            MaterialMismatch::Conflicting => f.write_str(
+                "the server's materials don't match this client's, so some may be drawn as others",
+            ),
+        }
+    }
+}
+
+/// An asset pack a server suggests, to be downloaded by clients that consent to it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetPackOffer {
+    /// Name to show players
+    pub name: String,
+    /// Hash of the pack's zip archive, which must match for the pack to be used
+    pub hash: PackHash,
+    /// Where to download the zip archive from
+    pub url: String,
+}
+
+/// BLAKE3 hash of an asset pack, written as 64 hexadecimal digits
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PackHash(pub [u8; 32]);
+
+impl PackHash {
+    /// The hash of `data`
+    pub fn of(data: &[u8]) -> Self {
+        Self(*blake3::hash(data).as_bytes())
+    }
+}
+
+impl fmt::Display for PackHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PackHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for PackHash {
+    type Err = InvalidPackHash;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 64 || !s.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(InvalidPackHash);
+        }
+        let mut hash = [0; 32];
+        for (byte, digits) in hash.iter_mut().zip(s.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).unwrap();
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| InvalidPackHash)?;
+        }
+        Ok(Self(hash))
+    }
+}
+
+/// Text that isn't a `PackHash`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidPackHash;
+
+impl fmt::Display for InvalidPackHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected 64 hexadecimal digits")
+    }
+}
+
+impl std::error::Error for InvalidPackHash {}
+
+/// Why a server's hello couldn't be accepted
+#[derive(Debug)]
+pub enum HelloError {
+    Refused(Refusal),
+    Malformed(bincode::Error),
+}
+
+impl From<Refusal> for HelloError {
+    fn from(x: Refusal) -> Self {
+        HelloError::Refused(x)
+    }
+}
+
+impl From<bincode::Error> for HelloError {
+    fn from(x: bincode::Error) -> Self {
+        HelloError::Malformed(x)
+    }
+}
+
+impl fmt::Display for HelloError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            HelloError::Refused(ref e) => e.fmt(f),
+            HelloError::Malformed(ref e) => write!(f, "malformed server hello: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for HelloError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server or client of an older version that knew only of chunk diffs
+    const OLD: Protocol = Protocol {
+        version: 1,
+        minimum: 1,
+        capabilities: Capabilities::CHUNK_DIFFS,
+    };
+
+    /// A newer version that supports everything, but has given up on version 1
+    const NEW: Protocol = Protocol {
+        version: 3,
+        minimum: 2,
+        capabilities: Capabilities::ALL,
+    };
+
+    fn hello_from(protocol: &Protocol) -> ClientHello {
+        ClientHello {
+            name: "a".into(),
+            protocol_version: protocol.version,
+            capabilities: protocol.capabilities,
+        }
+    }
+
+    /// The header `server` would answer `client` with, if it accepts it
+    fn handshake(server: &Protocol, client: &Protocol) -> Result<Capabilities, Refusal> {
+        let hello = ClientHello::decode(&bincode::serialize(&hello_from(client)).unwrap()).unwrap();
+        let capabilities = server.accept_client(&hello)?;
+        let header = ServerHelloHeader {
+            protocol_version: server.version,
+            capabilities,
+        };
+        client.accept_server(&header)
+    }
+
+    #[test]
+    fn old_client_new_server() {
+        assert_eq!(
+            handshake(&NEW, &OLD),
+            Err(Refusal::OutdatedClient {
+                version: 1,
+                minimum: 2
+            })
+        );
+        let lenient = Protocol { minimum: 1, ..NEW };
+        assert_eq!(handshake(&lenient, &OLD), Ok(Capabilities::CHUNK_DIFFS));
+    }
+
+    #[test]
+    fn new_client_old_server() {
+        assert_eq!(
+            handshake(&OLD, &NEW),
+            Err(Refusal::OutdatedServer {
+                version: 1,
+                minimum: 2
+            })
+        );
+        let lenient = Protocol { minimum: 1, ..NEW };
+        assert_eq!(handshake(&OLD, &lenient), Ok(Capabilities::CHUNK_DIFFS));
+        assert_eq!(handshake(&NEW, &lenient), Ok(Capabilities::ALL));
+    }
+
+    #[test]
+    fn unversioned_client() {
+        // Before versioning, clients sent a hello holding only their name
+        #[derive(Serialize)]
+        struct LegacyHello {
+            name: String,
+        }
+        let legacy = bincode::serialize(&LegacyHello { name: "a".into() }).unwrap();
+        let hello = ClientHello::decode(&legacy).unwrap();
+        assert_eq!(hello.name, "a");
+        assert_eq!(hello.protocol_version, 0);
+        assert_eq!(
+            Protocol::CURRENT.accept_client(&hello),
+            Err(Refusal::OutdatedClient {
+                version: 0,
+                minimum: MIN_PROTOCOL_VERSION
+            })
+        );
+
+        // Unknown trailing fields from later clients are ignored
+        let mut later = bincode::serialize(&ClientHello::new("b")).unwrap();
+        later.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(ClientHello::decode(&later).unwrap(), ClientHello::new("b"));
+    }
+
+    #[test]
+    fn server_hello_header_is_readable_alone() {
+        let hello = ServerHello {
+            header: ServerHelloHeader {
+                protocol_version: OLD.version,
+                capabilities: Capabilities::CHUNK_DIFFS,
+            },
+            character: EntityId::from_bits(7),
+            sim_config: SimConfig::from_raw(&Default::default()),
+            asset_pack: None,
+            materials: None,
+        };
+        let bytes = bincode::serialize(&hello).unwrap();
+        assert!(matches!(
+            ServerHello::decode(&NEW, &bytes),
+            Err(HelloError::Refused(Refusal::OutdatedServer {
+                version: 1,
+                ..
+            }))
+        ));
+        // Even if the rest is beyond understanding
+        assert!(matches!(
+            ServerHello::decode(&NEW, &bytes[..12]),
+            Err(HelloError::Refused(_))
+        ));
+        let decoded = ServerHello::decode(&OLD, &bytes).unwrap();
+        assert_eq!(decoded.header, hello.header);
+        assert_eq!(decoded.character, hello.character);
+    }
+
+    fn pack_offer() -> AssetPackOffer {
+        AssetPackOffer {
+            name: "autumn".into(),
+            hash: PackHash::of(b"autumn"),
+            url: "https://example.com/autumn.zip".into(),
+        }
+    }
+
+    #[test]
+    fn asset_pack_offered_only_with_capability() {
+        let hello = |capabilities| ServerHello {
+            header: ServerHelloHeader {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities,
+            },
+            character: EntityId::from_bits(7),
+            sim_config: SimConfig::from_raw(&Default::default()),
+            asset_pack: Some(pack_offer()),
+            materials: None,
+        };
+        let bytes = bincode::serialize(&hello(Capabilities::ALL)).unwrap();
+        let decoded = ServerHello::decode(&Protocol::CURRENT, &bytes).unwrap();
+        assert_eq!(decoded.asset_pack, Some(pack_offer()));
+
+        // A client that didn't offer the capability ignores the offer
+        let bytes = bincode::serialize(&hello(Capabilities::CHUNK_DIFFS)).unwrap();
+        assert_eq!(ServerHello::decode(&OLD, &bytes).unwrap().asset_pack, None);
+
+        // A server that predates asset packs sends no offer at all
+        #[derive(Serialize)]
+        struct LegacyHello {
+            header: ServerHelloHeader,
+            character: EntityId,
+            sim_config: SimConfig,
+        }
+        let legacy = bincode::serialize(&LegacyHello {
+            header: ServerHelloHeader {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: Capabilities::CHUNK_DIFFS,
+            },
+            character: EntityId::from_bits(7),
+            sim_config: SimConfig::from_raw(&Default::default()),
+        })
+        .unwrap();
+        let decoded = ServerHello::decode(&Protocol::CURRENT, &legacy).unwrap();
+        assert_eq!(decoded.character, EntityId::from_bits(7));
+        assert_eq!(decoded.asset_pack, None);
+    }
+
+    #[test]
+    fn material_registry_sent_only_with_capability() {
+        let hello = |capabilities| ServerHello {
+            header: ServerHelloHeader {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities,
+            },
+            character: EntityId::from_bits(7),
+            sim_config: SimConfig::from_raw(&Default::default()),
+            asset_pack: Some(pack_offer()),
+            materials: Some(MaterialRegistry::current()),
+        };
+        let bytes = bincode::serialize(&hello(Capabilities::ALL)).unwrap();
+        let decoded = ServerHello::decode(&Protocol::CURRENT, &bytes).unwrap();
+        assert_eq!(decoded.materials, Some(MaterialRegistry::current()));
+        assert_eq!(decoded.asset_pack, Some(pack_offer()));
+
+        // Servers that predate the registry end their hello with the asset pack offer
+        let mut earlier = hello(Capabilities::ASSET_PACKS);
+        earlier.materials = None;
+        let mut bytes = bincode::serialize(&earlier).unwrap();
+        bytes.pop();
+        let decoded = ServerHello::decode(&Protocol::CURRENT, &bytes).unwrap();
+        assert_eq!(decoded.asset_pack, Some(pack_offer()));
+        assert_eq!(decoded.materials, None);
+    }
+
+    #[test]
+    fn material_mismatches() {
+        assert_eq!(MaterialRegistry::current().mismatch(), None);
+        // An older peer knowing only some of this build's materials
+        let older = MaterialRegistry {
+            count: 3,
+            hash: material_registry_hash(3),
+        };
+        assert_eq!(older.mismatch(), None);
+        let newer = MaterialRegistry {
+            count: Material::COUNT as u16 + 2,
+            hash: 0,
+        };
+        assert_eq!(
+            newer.mismatch(),
+            Some(MaterialMismatch::Unknown { count: 2 })
+        );
+        let conflicting = MaterialRegistry {
+            count: 3,
+            hash: MaterialRegistry::current().hash,
+        };
+        assert_eq!(conflicting.mismatch(), Some(MaterialMismatch::Conflicting));
+    }
+
+    #[test]
+    fn pack_hash_hex_round_trip() {
+        let hash = PackHash::of(b"autumn");
+        let hex = hash.to_string();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex.parse::<PackHash>(), Ok(hash));
+        assert_eq!(hex.to_uppercase().parse::<PackHash>(), Ok(hash));
+        assert_eq!(hex[..62].parse::<PackHash>(), Err(InvalidPackHash));
+        assert_eq!(
+            format!("{}zz", &hex[..62]).parse::<PackHash>(),
+            Err(InvalidPackHash)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Whether a character can reach what it's pointing at
+//!
+//! Clients pick what a character uses by casting a ray from its view, but the server must check
+//! that the pick was honest: that the target is near enough, and that nothing opaque stands in the
+//! way.
+
+use crate::{
+    coords::voxel_point_position,
+    graph::Graph,
+    graph_collision::{line_of_sight, LosResult},
+    math,
+    node::{ChunkId, Coords},
+    proto::Position,
+    SimConfig,
+};
+
+/// Fraction of a voxel beyond each face at which its visibility is judged, so that the line of
+/// sight ends in the open rather than on the face itself
+const FACE_CLEARANCE: f64 = 0.05;
+
+/// Why a character can't use something
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Unreachable {
+    TooFar,
+    /// Something opaque, or terrain not yet generated, lies in the way
+    OutOfSight,
+}
+
+/// Whether a character at `user` can reach an entity at `target`
+///
+/// Entities are picked as spheres of the character radius, so their centers may lie that much
+/// further off than the reach.
+pub fn entity_in_reach(
+    cfg: &SimConfig,
+    graph: &Graph,
+    user: &Position,
+    target: &Position,
+) -> Result<(), Unreachable> {
+    let max_distance = cfg.character.block_reach + cfg.character.character_radius;
+    judge(line_of_sight(
+        graph,
+        graph.layout(),
+        user,
+        target,
+        max_distance,
+    ))
+}
+
+/// Whether a character at `user` can reach the voxel at `coords` in `chunk`
+///
+/// The voxel is reachable if the center of any of its faces is, looking from just outside it. A
+/// face hidden behind a solid neighbor is never seen, just as a ray cast never hits it.
+pub fn block_in_reach(
+    cfg: &SimConfig,
+    graph: &Graph,
+    user: &Position,
+    chunk: ChunkId,
+    coords: Coords,
+) -> Result<(), Unreachable> {
+    let layout = graph.layout();
+    let point = |offset: na::Vector3<f64>| voxel_point_position(layout, chunk, coords, &offset);
+    // A ray may hit the voxel anywhere on a face, which can be nearer than its center
+    let diagonal = math::distance(
+        &(point(na::Vector3::zeros()).local * math::origin()),
+        &(point(na::Vector3::repeat(1.0)).local * math::origin()),
+    );
+    let max_distance = cfg.character.block_reach + diagonal;
+
+    let mut result = Err(Unreachable::TooFar);
+    for axis in 0..3 {
+        for side in [-FACE_CLEARANCE, 1.0 + FACE_CLEARANCE] {
+            let mut offset = na::Vector3::repeat(0.5);
+            offset[axis] = side;
+            match judge(line_of_sight(
+                graph,
+                layout,
+                user,
+                &point(offset),
+                max_distance,
+            )) {
+                Ok(()) => return Ok(()),
+                // The more informative reason when faces differ
+                Err(Unreachable::OutOfSight) => result = Err(Unreachable::OutOfSight),
+                Err(Unreachable::TooFar) => {}
+            }
+        }
+    }
+    result
+}
+
+fn judge(los: LosResult) -> Result<(), Unreachable> {
+    match los {
+        LosResult::Clear => Ok(()),
+        LosResult::TooFar => Err(Unreachable::TooFar),
+        LosResult::Blocked { .. } | LosResult::Indeterminate => Err(Unreachable::OutOfSight),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        coords::{locate_voxel, voxel_center_position},
+        dodeca::Vertex,
+        graph::NodeId,
+        node::Chunk,
+        test_graphs::empty_graph,
+        world::Material,
+        SimConfigRaw,
+    };
+
+    fn set_block(graph: &mut Graph, chunk: ChunkId, coords: Coords, material: Material) {
+        let dimension = graph.layout().dimension();
+        let Chunk::Populated { ref mut voxels, .. } = graph[chunk] else {
+            unreachable!()
+        };
+        voxels.data_mut(dimension)[coords.to_index(dimension)] = material;
+    }
+
+    /// The position `meters` from the origin along the x axis
+    fn ahead(cfg: &SimConfig, meters: f32) -> Position {
+        Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&(na::Vector3::x() * meters * cfg.meters_to_absolute)),
+        }
+    }
+
+    /// The voxel `meters` from the origin along the x axis
+    fn voxel_ahead(cfg: &SimConfig, graph: &Graph, meters: f32) -> (ChunkId, Coords) {
+        let (chunk, coords, _) = locate_voxel(graph, graph.layout(), &ahead(cfg, meters)).unwrap();
+        (chunk, coords)
+    }
+
+    /// Fill every voxel of the root node whose center lies between `inner` and `outer` meters from
+    /// the origin, walling it in
+    fn enclose_origin(cfg: &SimConfig, graph: &mut Graph, inner: f32, outer: f32) {
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(NodeId::ROOT, vertex);
+            let dimension = cfg.chunk_size;
+            let all_coords = (0..dimension).flat_map(|x| {
+                (0..dimension).flat_map(move |y| (0..dimension).map(move |z| Coords([x, y, z])))
+            });
+            for coords in all_coords {
+                let center = voxel_center_position(graph.layout(), chunk, coords);
+                let meters = math::distance(&(center.local * math::origin()), &math::origin())
+                    / cfg.meters_to_absolute;
+                if (inner..outer).contains(&meters) {
+                    set_block(graph, chunk, coords, Material::Dirt);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn entities() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut graph = empty_graph(&cfg);
+        let user = Position::origin();
+        let reach =
+            |graph: &Graph, meters| entity_in_reach(&cfg, graph, &user, &ahead(&cfg, meters));
+
+        assert_eq!(reach(&graph, 5.0), Ok(()));
+        assert_eq!(reach(&graph, 20.0), Err(Unreachable::TooFar));
+        enclose_origin(&cfg, &mut graph, 1.0, 3.5);
+        assert_eq!(reach(&graph, 5.0), Err(Unreachable::OutOfSight));
+    }
+
+    #[test]
+    fn blocks() {
+        let cfg = SimConfig::from_raw(&SimConfigRaw::default());
+        let mut graph = empty_graph(&cfg);
+        let user = Position::origin();
+        let reach =
+            |graph: &Graph, (chunk, coords)| block_in_reach(&cfg, graph, &user, chunk, coords);
+
+        let far = voxel_ahead(&cfg, &graph, 20.0);
+        set_block(&mut graph, far.0, far.1, Material::Dirt);
+        assert_eq!(reach(&graph, far), Err(Unreachable::TooFar));
+
+        let near = voxel_ahead(&cfg, &graph, 5.0);
+        set_block(&mut graph, near.0, near.1, Material::Dirt);
+        assert_eq!(reach(&graph, near), Ok(()));
+
+        enclose_origin(&cfg, &mut graph, 1.0, 3.5);
+        assert_eq!(reach(&graph, near), Err(Unreachable::OutOfSight));
+    }
+}
@@ -0,0 +1,977 @@
+//! Queries and edits over boxes of voxels that may span many chunks
+
+use std::{fmt, ops::Range};
+
+use fxhash::{FxHashMap, FxHashSet};
+
+use crate::{
+    graph::Graph,
+    node::{BlockUpdateOutcome, Chunk, ChunkId, CoordAxis, CoordDirection, Coords},
+    template::{Orientation, Template},
+    world::{Material, Shape},
+};
+
+/// Whether a region query was able to visit every voxel in its region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionCoverage {
+    Complete,
+    /// Some voxels were skipped because their chunks are unpopulated or lie outside the graph
+    Partial,
+}
+
+impl Graph {
+    /// Calls `f` on every voxel in a box with the corner `anchor` extending `extents` voxels along
+    /// the anchor chunk's positive axes
+    ///
+    /// Boxes may span chunk and node boundaries. Because more than four chunks can meet at an edge,
+    /// a box isn't uniquely defined by its extents alone, so the voxel at box offset `(i, j, k)` is
+    /// defined to be the one reached from the anchor by taking `i` steps along X, then `j` steps
+    /// along Y, then `k` steps along Z with `get_block_neighbor`, with each axis following any
+    /// reorientation introduced by chunk boundaries crossed along the way.
+    ///
+    /// Each chunk overlapping the box is visited once, with its voxels visited in index order.
+    pub fn for_each_block_in_box(
+        &self,
+        anchor: (ChunkId, Coords),
+        extents: [u32; 3],
+        mut f: impl FnMut(ChunkId, Coords, Material),
+    ) -> RegionCoverage {
+        let mut populated = true;
+        let coverage = self.visit_box(anchor, extents, |voxel| match voxel.contents {
+            Some((material, _)) => f(voxel.chunk, voxel.coords, material),
+            None => populated = false,
+        });
+        if populated {
+            coverage
+        } else {
+            RegionCoverage::Partial
+        }
+    }
+
+    /// Fills a box, as defined by `for_each_block_in_box`, with full blocks of `material`
+    ///
+    /// Voxels in chunks that aren't populated yet are left alone, and listed in the result so that
+    /// the change can be applied once they are. Boxes of more than `max_volume` voxels are refused.
+    pub fn edit_box(
+        &mut self,
+        anchor: (ChunkId, Coords),
+        extents: [u32; 3],
+        material: Material,
+        max_volume: u64,
+    ) -> Result<BoxEdit, EditError> {
+        self.edit_box_with(anchor, extents, |_| (material, Shape::FULL), max_volume)
+    }
+
+    /// Fills each voxel of a box with what `contents` gives for its offset in the box, as
+    /// `edit_box` would, with shapes given in the axes of the box
+    pub fn edit_box_with(
+        &mut self,
+        anchor: (ChunkId, Coords),
+        extents: [u32; 3],
+        contents: impl Fn([u32; 3]) -> (Material, Shape),
+        max_volume: u64,
+    ) -> Result<BoxEdit, EditError> {
+        check_volume(extents, max_volume)?;
+        let mut voxels = Vec::new();
+        let coverage = self.plan_box_edit(anchor, extents, contents, |chunk, coords, old, new| {
+            voxels.push((chunk, coords, old.is_some(), new));
+        });
+        let mut edit = BoxEdit {
+            changed: Vec::new(),
+            missing: Vec::new(),
+            coverage,
+        };
+        for (chunk, coords, populated, (material, shape)) in voxels {
+            if !populated {
+                edit.missing.push((chunk, coords, material, shape));
+                edit.coverage = RegionCoverage::Partial;
+                continue;
+            }
+            if let BlockUpdateOutcome::Applied { .. } =
+                self.set_block(chunk, coords, material, shape)
+            {
+                if !edit.changed.contains(&chunk) {
+                    edit.changed.push(chunk);
+                }
+            }
+        }
+        Ok(edit)
+    }
+
+    /// Works out what `edit_box_with` would do without doing it, calling `f` once on each distinct
+    /// voxel of the box with what it holds now, or `None` if its chunk isn't populated, and what it
+    /// would hold after, with shapes given in the axes of the voxel's chunk
+    ///
+    /// Voxels near the corners of nodes may be reached by more than one offset, and take the
+    /// contents of the first. The coverage is partial only if the box reaches beyond the graph.
+    pub fn plan_box_edit(
+        &self,
+        anchor: (ChunkId, Coords),
+        extents: [u32; 3],
+        contents: impl Fn([u32; 3]) -> (Material, Shape),
+        mut f: impl FnMut(ChunkId, Coords, Option<(Material, Shape)>, (Material, Shape)),
+    ) -> RegionCoverage {
+        let mut seen = FxHashSet::default();
+        self.visit_box(anchor, extents, |voxel| {
+            if !seen.insert((voxel.chunk, voxel.coords)) {
+                return;
+            }
+            let (material, shape) = contents(voxel.offset);
+            // As `set_block` would have it
+            let shape = if material == Material::Void {
+                Shape::FULL
+            } else {
+                shape_in_chunk(&voxel.frame, shape)
+            };
+            f(voxel.chunk, voxel.coords, voxel.contents, (material, shape));
+        })
+    }
+
+    /// Copies the voxels of a box, as defined by `for_each_block_in_box`, into a template whose
+    /// axes are those of the box
+    ///
+    /// `up` is the direction that's up at the anchor, in the axes of its chunk. Boxes of more than
+    /// `max_volume` voxels are refused, as are those reaching unpopulated chunks or beyond the
+    /// graph.
+    pub fn copy_box(
+        &self,
+        anchor: (ChunkId, Coords),
+        extents: [u32; 3],
+        up: (CoordAxis, CoordDirection),
+        max_volume: u64,
+    ) -> Result<Template, EditError> {
+        let volume = check_volume(extents, max_volume)?;
+        let [ex, ey, _] = extents.map(|x| x as usize);
+        let mut voxels = vec![(Material::Void, Shape::FULL); volume as usize];
+        let mut populated = true;
+        let coverage = self.visit_box(anchor, extents, |voxel| {
+            let Some((material, shape)) = voxel.contents else {
+                populated = false;
+                return;
+            };
+            let [x, y, z] = voxel.offset.map(|x| x as usize);
+            voxels[x + ex * (y + ey * z)] = (material, shape_in_box(&voxel.frame, shape));
+        });
+        if coverage == RegionCoverage::Partial || !populated {
+            return Err(EditError::Incomplete);
+        }
+        let parity = self.chunk_parity(anchor.0);
+        Ok(Template::new(extents, up, parity, voxels).expect("volume already checked"))
+    }
+
+    /// Pastes `template` turned by `orientation` as a box with the corner `anchor`, as
+    /// `edit_box_with` would
+    pub fn paste(
+        &mut self,
+        anchor: (ChunkId, Coords),
+        template: &Template,
+        orientation: Orientation,
+        max_volume: u64,
+    ) -> Result<BoxEdit, EditError> {
+        let contents = template.pasted(orientation, self.chunk_parity(anchor.0));
+        let extents = template.pasted_extents(orientation);
+        self.edit_box_with(anchor, extents, contents, max_volume)
+    }
+
+    /// Whether the coordinates of `chunk` have the opposite handedness to those of the root node's
+    /// chunks of parity `false`
+    ///
+    /// Templates pasted into chunks whose handedness differs from where they were copied must be
+    /// mirrored to come out the same.
+    pub fn chunk_parity(&self, chunk: ChunkId) -> bool {
+        chunk.vertex.parity() ^ (self.length(chunk.node) & 1 != 0)
+    }
+
+    /// Fills `length` voxels with `material`, starting from `from` and going along the positive
+    /// direction of `axis`, as `edit_box` would
+    pub fn edit_line(
+        &mut self,
+        from: (ChunkId, Coords),
+        axis: CoordAxis,
+        length: u32,
+        material: Material,
+        max_volume: u64,
+    ) -> Result<BoxEdit, EditError> {
+        self.edit_box(from, line_extents(axis, length), material, max_volume)
+    }
+
+    /// Fills a layer one voxel thick across `normal` with `material`, spanning `extents` voxels
+    /// along the other two axes in order, as `edit_box` would
+    pub fn edit_plane(
+        &mut self,
+        anchor: (ChunkId, Coords),
+        normal: CoordAxis,
+        extents: [u32; 2],
+        material: Material,
+        max_volume: u64,
+    ) -> Result<BoxEdit, EditError> {
+        self.edit_box(anchor, plane_extents(normal, extents), material, max_volume)
+    }
+
+    /// Calls `f` on every voxel of a box as in `for_each_block_in_box`, including those of chunks
+    /// that aren't populated
+    ///
+    /// The coverage is partial only if the box reaches beyond the graph.
+    fn visit_box(
+        &self,
+        anchor: (ChunkId, Coords),
+        extents: [u32; 3],
+        mut f: impl FnMut(BoxVoxel),
+    ) -> RegionCoverage {
+        let dimension = self.layout().dimension();
+        let mut coverage = RegionCoverage::Complete;
+        let (chunk, coords) = anchor;
+        let frame = [0, 1, 2].map(|i| AxisMap {
+            chunk_axis: CoordAxis::try_from(i).unwrap(),
+            reversed: false,
+            offset: i32::from(coords.0[i]),
+        });
+
+        for (chunk, frame, x_range) in self.box_segments(chunk, frame, 0, extents[0], &mut coverage)
+        {
+            for (chunk, frame, y_range) in
+                self.box_segments(chunk, frame, 1, extents[1], &mut coverage)
+            {
+                for (chunk, frame, z_range) in
+                    self.box_segments(chunk, frame, 2, extents[2], &mut coverage)
+                {
+                    let view = match self.get_chunk(chunk) {
+                        Some(Chunk::Populated { ref voxels, .. }) => Some(voxels.view(dimension)),
+                        _ => None,
+                    };
+
+                    // Convert the box-relative ranges into ranges along each chunk axis
+                    let mut chunk_ranges = [0..0, 0..0, 0..0];
+                    for (map, range) in frame.iter().zip([x_range, y_range, z_range]) {
+                        chunk_ranges[map.chunk_axis as usize] = map.chunk_range(range);
+                    }
+                    for z in chunk_ranges[2].clone() {
+                        for y in chunk_ranges[1].clone() {
+                            for x in chunk_ranges[0].clone() {
+                                let coords = Coords([x, y, z]);
+                                f(BoxVoxel {
+                                    chunk,
+                                    coords,
+                                    offset: frame.map(|map| map.box_offset(coords[map.chunk_axis])),
+                                    frame,
+                                    contents: view
+                                        .as_ref()
+                                        .map(|view| (view.get(coords), view.shape(coords))),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        coverage
+    }
+
+    /// Tallies the materials of all voxels in a box, as defined by `for_each_block_in_box`
+    pub fn count_materials_in_box(
+        &self,
+        anchor: (ChunkId, Coords),
+        extents: [u32; 3],
+    ) -> (FxHashMap<Material, usize>, RegionCoverage) {
+        let mut counts = FxHashMap::<Material, usize>::default();
+        let coverage = self.for_each_block_in_box(anchor, extents, |_, _, material| {
+            *counts.entry(material).or_default() += 1;
+        });
+        (counts, coverage)
+    }
+
+    /// Splits the part of a box along `box_axis` into per-chunk segments, starting from `chunk`
+    fn box_segments(
+        &self,
+        mut chunk: ChunkId,
+        mut frame: [AxisMap; 3],
+        box_axis: usize,
+        extent: u32,
+        coverage: &mut RegionCoverage,
+    ) -> Vec<(ChunkId, [AxisMap; 3], Range<u32>)> {
+        let dimension = i32::from(self.layout().dimension());
+        let mut segments = Vec::new();
+        let mut start = 0;
+        while start < extent {
+            let map = frame[box_axis];
+            // Distance to the first box offset beyond the edge of this chunk
+            let limit = if map.reversed {
+                map.offset + 1
+            } else {
+                dimension - map.offset
+            };
+            let end = (limit.max(0) as u32).min(extent);
+            segments.push((chunk, frame, start..end));
+            start = end;
+            if start == extent {
+                break;
+            }
+
+            let direction = if map.reversed {
+                CoordDirection::Minus
+            } else {
+                CoordDirection::Plus
+            };
+            let Some(next) = self.get_chunk_neighbor(chunk, map.chunk_axis, direction) else {
+                *coverage = RegionCoverage::Partial;
+                break;
+            };
+            frame = frame.map(|m| m.cross(chunk, next, map.chunk_axis, direction, dimension));
+            chunk = next;
+        }
+        segments
+    }
+}
+
+/// The volume of a box with `extents`, unless it's more than `max_volume`
+fn check_volume(extents: [u32; 3], max_volume: u64) -> Result<u64, EditError> {
+    let volume = box_volume(extents);
+    if volume > max_volume {
+        return Err(EditError::TooLarge {
+            volume,
+            max: max_volume,
+        });
+    }
+    Ok(volume)
+}
+
+/// Number of voxels in a box with `extents`, saturating rather than overflowing
+pub fn box_volume(extents: [u32; 3]) -> u64 {
+    extents
+        .iter()
+        .fold(1u64, |volume, &x| volume.saturating_mul(u64::from(x)))
+}
+
+/// Extents of the box covering `length` voxels along `axis`
+pub fn line_extents(axis: CoordAxis, length: u32) -> [u32; 3] {
+    let mut extents = [1; 3];
+    extents[axis as usize] = length;
+    extents
+}
+
+/// Extents of the box one voxel thick across `normal` and spanning `extents` along the other two
+/// axes, in order
+pub fn plane_extents(normal: CoordAxis, extents: [u32; 2]) -> [u32; 3] {
+    let mut result = [1; 3];
+    for (axis, extent) in normal.other_axes().into_iter().zip(extents) {
+        result[axis as usize] = extent;
+    }
+    result
+}
+
+/// What `Graph::edit_box` changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoxEdit {
+    /// Chunks whose voxels changed, in the order they were first changed
+    pub changed: Vec<ChunkId>,
+    /// Voxels that couldn't be changed because their chunks aren't populated, with what they're
+    /// to hold, their shapes given in the axes of their chunks
+    pub missing: Vec<(ChunkId, Coords, Material, Shape)>,
+    /// Whether every voxel of the box was changed, or already held the material
+    pub coverage: RegionCoverage,
+}
+
+/// Why a box edit or copy was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditError {
+    /// The box has more than `max` voxels
+    TooLarge { volume: u64, max: u64 },
+    /// Some voxels to be copied lie in unpopulated chunks or beyond the graph
+    Incomplete,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            EditError::TooLarge { volume, max } => {
+                write!(f, "{volume} voxels is more than the limit of {max}")
+            }
+            EditError::Incomplete => f.pad("some voxels haven't been generated"),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// A voxel of a box, as visited by `Graph::visit_box`
+struct BoxVoxel {
+    chunk: ChunkId,
+    coords: Coords,
+    /// Offset from the anchor along each axis of the box
+    offset: [u32; 3],
+    /// How each axis of the box runs through the voxel's chunk
+    frame: [AxisMap; 3],
+    /// Material and shape, in the axes of the chunk, or `None` if the chunk isn't populated
+    contents: Option<(Material, Shape)>,
+}
+
+/// `shape`, given in the axes of a box, in the axes of the chunk whose relationship to the box is
+/// `frame`
+fn shape_in_chunk(frame: &[AxisMap; 3], shape: Shape) -> Shape {
+    Shape::from_fn(|octant| shape.contains(frame.map(|map| map.box_half(octant))))
+        .expect("shapes are preserved by symmetries of the cube")
+}
+
+/// `shape`, given in the axes of a chunk, in the axes of the box whose relationship to the chunk
+/// is `frame`
+fn shape_in_box(frame: &[AxisMap; 3], shape: Shape) -> Shape {
+    Shape::from_fn(|octant| {
+        let mut chunk_octant = [0; 3];
+        for (map, half) in frame.iter().zip(octant) {
+            chunk_octant[map.chunk_axis as usize] = if map.reversed { 1 - half } else { half };
+        }
+        shape.contains(chunk_octant)
+    })
+    .expect("shapes are preserved by symmetries of the cube")
+}
+
+/// Relationship between one axis of a box and the coordinates of a particular chunk
+#[derive(Debug, Clone, Copy)]
+struct AxisMap {
+    /// The chunk axis parallel to the box axis
+    chunk_axis: CoordAxis,
+    /// Whether chunk coordinates decrease as box offsets increase
+    reversed: bool,
+    /// Chunk coordinate corresponding to box offset 0, which may lie outside the chunk
+    offset: i32,
+}
+
+impl AxisMap {
+    /// Box offset corresponding to the chunk coordinate `x`
+    fn box_offset(&self, x: u8) -> u32 {
+        let x = i32::from(x);
+        (if self.reversed {
+            self.offset - x
+        } else {
+            x - self.offset
+        }) as u32
+    }
+
+    /// Which half of a voxel along the box axis the octant `octant`, in chunk axes, lies in
+    fn box_half(&self, octant: [u8; 3]) -> u8 {
+        let half = octant[self.chunk_axis as usize];
+        if self.reversed {
+            1 - half
+        } else {
+            half
+        }
+    }
+
+    /// Chunk coordinates covered by a range of box offsets that lies within the chunk
+    fn chunk_range(&self, range: Range<u32>) -> Range<u8> {
+        let (start, end) = (range.start as i32, range.end as i32);
+        if self.reversed {
+            (self.offset - end + 1) as u8..(self.offset - start + 1) as u8
+        } else {
+            (self.offset + start) as u8..(self.offset + end) as u8
+        }
+    }
+
+    /// Express this map in terms of the chunk reached by leaving `old` in the given direction along
+    /// `crossed_axis`, mirroring the coordinate conventions of `Graph::get_block_neighbor`
+    fn cross(
+        self,
+        old: ChunkId,
+        new: ChunkId,
+        crossed_axis: CoordAxis,
+        direction: CoordDirection,
+        dimension: i32,
+    ) -> Self {
+        let chunk_axis = match direction {
+            // Neighboring nodes share chunk orientation
+            CoordDirection::Minus => self.chunk_axis,
+            // Chunks within a node are related by a permutation of axes that keeps the coordinate
+            // of the shared face
+            CoordDirection::Plus if self.chunk_axis == crossed_axis => {
+                let [a, b] = crossed_axis.other_axes();
+                CoordAxis::iter()
+                    .find(|&axis| {
+                        let side = new.vertex.canonical_sides()[axis as usize];
+                        side != old.vertex.canonical_sides()[a as usize]
+                            && side != old.vertex.canonical_sides()[b as usize]
+                    })
+                    .unwrap()
+            }
+            CoordDirection::Plus => CoordAxis::iter()
+                .find(|&axis| {
+                    new.vertex.canonical_sides()[axis as usize]
+                        == old.vertex.canonical_sides()[self.chunk_axis as usize]
+                })
+                .unwrap(),
+        };
+        if self.chunk_axis != crossed_axis {
+            return Self { chunk_axis, ..self };
+        }
+        // Crossing reflects the crossed coordinate: the last voxel on one side and the first voxel
+        // on the other side share the boundary's coordinate
+        let mirror = match direction {
+            CoordDirection::Plus => 2 * dimension - 1,
+            CoordDirection::Minus => -1,
+        };
+        Self {
+            chunk_axis,
+            reversed: !self.reversed,
+            offset: mirror - self.offset,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dodeca::Vertex,
+        node::{populate_fresh_nodes, VoxelData},
+        proto::Position,
+        traversal::{ensure_nearby, nearby_nodes},
+    };
+
+    const MATERIALS: [Material; 5] = [
+        Material::Void,
+        Material::Dirt,
+        Material::Sand,
+        Material::Silt,
+        Material::Clay,
+    ];
+
+    /// A graph whose voxels have a variety of materials, so that mismatches are likely to be caught
+    fn patterned_graph(dimension: u8) -> Graph {
+        let mut graph = Graph::new(dimension);
+        ensure_nearby(&mut graph, &Position::origin(), 2.0);
+        populate_fresh_nodes(&mut graph);
+        for (i, (node, _)) in nearby_nodes(&graph, &Position::origin(), 2.0)
+            .into_iter()
+            .enumerate()
+        {
+            for vertex in Vertex::iter() {
+                let mut voxels = VoxelData::Solid(Material::Void);
+                let data = voxels.data_mut(dimension);
+                for (j, voxel) in data.iter_mut().enumerate() {
+                    *voxel = MATERIALS[(i * 7 + vertex as usize * 3 + j) % MATERIALS.len()];
+                }
+                graph.populate_chunk(ChunkId::new(node, vertex), voxels, false);
+            }
+        }
+        graph
+    }
+
+    type Block = (ChunkId, Coords);
+    type Direction = (CoordAxis, CoordDirection);
+
+    fn step(graph: &Graph, block: Block, (axis, direction): Direction) -> Option<Block> {
+        graph.get_block_neighbor(block.0, block.1, axis, direction)
+    }
+
+    fn flip((axis, direction): Direction) -> Direction {
+        match direction {
+            CoordDirection::Plus => (axis, CoordDirection::Minus),
+            CoordDirection::Minus => (axis, CoordDirection::Plus),
+        }
+    }
+
+    fn all_directions() -> impl Iterator<Item = Direction> {
+        CoordAxis::iter().flat_map(|a| CoordDirection::iter().map(move |d| (a, d)))
+    }
+
+    /// Reference implementation stepping through the box one block at a time, working out how
+    /// each axis is reoriented at chunk boundaries purely from `get_block_neighbor`
+    fn brute_force(
+        graph: &Graph,
+        anchor: Block,
+        extents: [u32; 3],
+    ) -> FxHashMap<(ChunkId, [u8; 3]), usize> {
+        let mut result = FxHashMap::default();
+        for i in 0..extents[0] {
+            for j in 0..extents[1] {
+                for k in 0..extents[2] {
+                    let mut block = anchor;
+                    let mut axes = [CoordAxis::X, CoordAxis::Y, CoordAxis::Z]
+                        .map(|a| (a, CoordDirection::Plus));
+                    for (box_axis, steps) in [i, j, k].into_iter().enumerate() {
+                        for _ in 0..steps {
+                            let crossed = axes[box_axis];
+                            let next = step(graph, block, crossed).unwrap();
+                            if next.0 != block.0 {
+                                axes = axes.map(|dir| reorient(graph, block, next, crossed, dir));
+                            }
+                            block = next;
+                        }
+                    }
+                    *result.entry((block.0, block.1 .0)).or_default() += 1;
+                }
+            }
+        }
+        result
+    }
+
+    /// Find the direction at `next` corresponding to `dir` at `block`, where `next` is reached from
+    /// `block` by stepping in direction `crossed` across a chunk boundary
+    fn reorient(
+        graph: &Graph,
+        block: Block,
+        next: Block,
+        crossed: Direction,
+        dir: Direction,
+    ) -> Direction {
+        if dir.0 == crossed.0 {
+            // Keep moving away from where we came from
+            let back = all_directions()
+                .find(|&d| step(graph, next, d) == Some(block))
+                .unwrap();
+            return if dir == crossed { flip(back) } else { back };
+        }
+        // Probe a neighbor along `dir` on the same chunk face and find the direction at `next`
+        // that reaches the probe's counterpart across the boundary
+        let (probe_dir, flipped) = if step(graph, block, dir).unwrap().0 == block.0 {
+            (dir, false)
+        } else {
+            (flip(dir), true)
+        };
+        let probe = step(graph, block, probe_dir).unwrap();
+        let target = step(graph, probe, crossed).unwrap();
+        let found = all_directions()
+            .find(|&d| step(graph, next, d) == Some(target))
+            .unwrap();
+        if flipped {
+            flip(found)
+        } else {
+            found
+        }
+    }
+
+    fn material_at(graph: &Graph, chunk: ChunkId, coords: Coords) -> Material {
+        let Chunk::Populated { ref voxels, .. } = graph[chunk] else {
+            panic!("unpopulated chunk");
+        };
+        voxels.view(graph.layout().dimension()).get(coords)
+    }
+
+    #[test]
+    fn box_across_node_corner_matches_brute_force() {
+        let dimension = 4;
+        let graph = patterned_graph(dimension);
+        let anchor = (
+            ChunkId::new(crate::graph::NodeId::ROOT, Vertex::A),
+            Coords([dimension - 2, dimension - 2, 1]),
+        );
+        // Long enough to pass through the neighboring chunk in the same node and into the chunk
+        // of the next node over, along both X and Y
+        let extents = [u32::from(dimension) + 4, u32::from(dimension) + 4, 2];
+
+        let mut visited = FxHashMap::<(ChunkId, [u8; 3]), usize>::default();
+        let coverage = graph.for_each_block_in_box(anchor, extents, |chunk, coords, material| {
+            assert_eq!(material, material_at(&graph, chunk, coords));
+            *visited.entry((chunk, coords.0)).or_default() += 1;
+        });
+        assert_eq!(coverage, RegionCoverage::Complete);
+        assert_eq!(visited, brute_force(&graph, anchor, extents));
+
+        let nodes: std::collections::HashSet<_> = visited.keys().map(|(c, _)| c.node).collect();
+        assert!(nodes.len() >= 3);
+
+        let (counts, coverage) = graph.count_materials_in_box(anchor, extents);
+        assert_eq!(coverage, RegionCoverage::Complete);
+        assert_eq!(
+            counts.values().sum::<usize>(),
+            extents.iter().product::<u32>() as usize
+        );
+    }
+
+    #[test]
+    fn unpopulated_chunk_is_partial() {
+        let dimension = 4;
+        let mut graph = patterned_graph(dimension);
+        let anchor = (
+            ChunkId::new(crate::graph::NodeId::ROOT, Vertex::A),
+            Coords([0, 0, 0]),
+        );
+        let extents = [u32::from(dimension) + 1, 1, 1];
+        assert_eq!(
+            graph.count_materials_in_box(anchor, extents).1,
+            RegionCoverage::Complete
+        );
+
+        let neighbor = graph
+            .get_chunk_neighbor(anchor.0, CoordAxis::X, CoordDirection::Plus)
+            .unwrap();
+        graph[neighbor] = Chunk::Fresh;
+        let (counts, coverage) = graph.count_materials_in_box(anchor, extents);
+        assert_eq!(coverage, RegionCoverage::Partial);
+        assert_eq!(counts.values().sum::<usize>(), usize::from(dimension));
+    }
+
+    type Edit = fn(&mut Graph, Block, u32) -> Result<BoxEdit, EditError>;
+
+    #[test]
+    fn edits_across_node_corner_match_brute_force() {
+        let dimension = 4;
+        let anchor = (
+            ChunkId::new(crate::graph::NodeId::ROOT, Vertex::A),
+            Coords([dimension - 2, dimension - 2, 1]),
+        );
+        let length = u32::from(dimension) + 4;
+        let edits: [(Edit, [u32; 3]); 3] = [
+            (
+                |graph, anchor, length| {
+                    graph.edit_box(anchor, [length, length, 2], Material::Gravel, u64::MAX)
+                },
+                [length, length, 2],
+            ),
+            (
+                |graph, anchor, length| {
+                    graph.edit_line(anchor, CoordAxis::Y, length, Material::Gravel, u64::MAX)
+                },
+                line_extents(CoordAxis::Y, length),
+            ),
+            (
+                |graph, anchor, length| {
+                    let extents = [length, length];
+                    graph.edit_plane(anchor, CoordAxis::Z, extents, Material::Gravel, u64::MAX)
+                },
+                plane_extents(CoordAxis::Z, [length, length]),
+            ),
+        ];
+
+        let original = patterned_graph(dimension);
+        for (edit, extents) in edits {
+            let mut graph = patterned_graph(dimension);
+            let expected = brute_force(&graph, anchor, extents);
+            let result = edit(&mut graph, anchor, length).unwrap();
+            assert_eq!(result.coverage, RegionCoverage::Complete);
+            assert!(result.missing.is_empty());
+            let changed = result.changed.iter().copied().collect::<FxHashSet<_>>();
+            assert_eq!(changed.len(), result.changed.len());
+            assert_eq!(
+                changed,
+                expected
+                    .keys()
+                    .map(|&(chunk, _)| chunk)
+                    .collect::<FxHashSet<_>>()
+            );
+
+            // Exactly the voxels of the box change
+            for (node, _) in nearby_nodes(&graph, &Position::origin(), 2.0) {
+                for vertex in Vertex::iter() {
+                    let chunk = ChunkId::new(node, vertex);
+                    for coords in Coords::all(dimension) {
+                        let material = if expected.contains_key(&(chunk, coords.0)) {
+                            Material::Gravel
+                        } else {
+                            material_at(&original, chunk, coords)
+                        };
+                        assert_eq!(material_at(&graph, chunk, coords), material);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_edit_is_refused() {
+        let dimension = 4;
+        let mut graph = patterned_graph(dimension);
+        let anchor = (
+            ChunkId::new(crate::graph::NodeId::ROOT, Vertex::A),
+            Coords([0, 0, 0]),
+        );
+        assert_eq!(
+            graph.edit_box(anchor, [3, 3, 1], Material::Gravel, 8),
+            Err(EditError::TooLarge { volume: 9, max: 8 })
+        );
+        assert_eq!(
+            graph
+                .count_materials_in_box(anchor, [3, 3, 1])
+                .0
+                .get(&Material::Gravel),
+            None
+        );
+        assert!(graph
+            .edit_line(anchor, CoordAxis::X, 8, Material::Gravel, 8)
+            .is_ok());
+    }
+
+    #[test]
+    fn unpopulated_voxels_are_left_for_later() {
+        let dimension = 4;
+        let mut graph = patterned_graph(dimension);
+        let anchor = (
+            ChunkId::new(crate::graph::NodeId::ROOT, Vertex::A),
+            Coords([0, 0, 0]),
+        );
+        let neighbor = graph
+            .get_chunk_neighbor(anchor.0, CoordAxis::X, CoordDirection::Plus)
+            .unwrap();
+        graph[neighbor] = Chunk::Fresh;
+        let edit = graph
+            .edit_line(
+                anchor,
+                CoordAxis::X,
+                u32::from(dimension) + 1,
+                Material::Gravel,
+                64,
+            )
+            .unwrap();
+        assert_eq!(edit.coverage, RegionCoverage::Partial);
+        assert_eq!(edit.changed, vec![anchor.0]);
+        assert_eq!(edit.missing.len(), 1);
+        assert_eq!(edit.missing[0].0, neighbor);
+    }
+
+    /// Give a variety of shapes to some of the voxels of a box, in the axes of their chunks
+    fn shape_box(graph: &mut Graph, anchor: Block, extents: [u32; 3]) {
+        use CoordAxis::*;
+        use CoordDirection::*;
+        let shapes = [
+            Shape::stairs((Y, Plus), (Z, Minus)),
+            Shape::slab(X, Plus),
+            Shape::stairs((Z, Minus), (X, Plus)),
+        ];
+        let mut voxels = Vec::new();
+        graph.for_each_block_in_box(anchor, extents, |chunk, coords, material| {
+            if material != Material::Void {
+                voxels.push((chunk, coords, material));
+            }
+        });
+        for (i, (chunk, coords, material)) in voxels.into_iter().enumerate().step_by(2) {
+            let _ = graph.set_block(chunk, coords, material, shapes[i % shapes.len()]);
+        }
+    }
+
+    /// A template with no symmetries, with slabs and stairs in it
+    fn asymmetric_template() -> Template {
+        use CoordAxis::*;
+        use CoordDirection::*;
+        let voxels = (0..3 * 2 * 5)
+            .map(|i| match i % 4 {
+                0 => (Material::Void, Shape::FULL),
+                1 => (Material::Dirt, Shape::stairs((Y, Plus), (X, Minus))),
+                2 => (MATERIALS[i % MATERIALS.len()], Shape::FULL),
+                _ => (Material::Clay, Shape::slab(Z, Plus)),
+            })
+            .collect();
+        Template::new([3, 2, 5], (Y, Plus), false, voxels).unwrap()
+    }
+
+    #[test]
+    fn copy_paste_round_trip() {
+        let dimension = 4;
+        let anchor = (
+            ChunkId::new(crate::graph::NodeId::ROOT, Vertex::A),
+            Coords([dimension - 2, dimension - 2, 1]),
+        );
+        let extents = [u32::from(dimension) + 4, u32::from(dimension) + 4, 2];
+        let mut source = patterned_graph(dimension);
+        shape_box(&mut source, anchor, extents);
+        let up = (CoordAxis::Y, CoordDirection::Plus);
+        let template = source.copy_box(anchor, extents, up, u64::MAX).unwrap();
+        assert_eq!(template.extents(), extents);
+        assert!((0..template.volume()).any(|i| {
+            let [ex, ey, _] = extents.map(u64::from);
+            let offset = [i % ex, i / ex % ey, i / ex / ey].map(|x| x as u32);
+            !template.get(offset).1.is_full()
+        }));
+
+        // Pasted and copied again unturned, anywhere, the voxels are the same, though they're
+        // mirrored where the handedness of the chunks' coordinates differs
+        for vertex in Vertex::iter() {
+            let target = (ChunkId::new(crate::graph::NodeId::ROOT, vertex), anchor.1);
+            let mut graph = patterned_graph(dimension);
+            let edit = graph
+                .paste(target, &template, Orientation::IDENTITY, u64::MAX)
+                .unwrap();
+            assert_eq!(edit.coverage, RegionCoverage::Complete);
+            let copy = graph.copy_box(target, extents, up, u64::MAX).unwrap();
+            let same_parity = graph.chunk_parity(target.0) == source.chunk_parity(anchor.0);
+            if same_parity {
+                assert_eq!(copy, template);
+            }
+            let unmirrored = copy.pasted(Orientation::IDENTITY, source.chunk_parity(anchor.0));
+            for z in 0..extents[2] {
+                for y in 0..extents[1] {
+                    for x in 0..extents[0] {
+                        assert_eq!(unmirrored([x, y, z]), template.get([x, y, z]));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pastes_match_their_templates_in_every_orientation() {
+        let dimension = 4;
+        let anchor = (
+            ChunkId::new(crate::graph::NodeId::ROOT, Vertex::A),
+            Coords([dimension - 2, dimension - 2, dimension - 2]),
+        );
+        let template = asymmetric_template();
+        let mut graph = patterned_graph(dimension);
+        let parity = graph.chunk_parity(anchor.0);
+        for orientation in Orientation::all() {
+            let extents = template.pasted_extents(orientation);
+            let edit = graph.paste(anchor, &template, orientation, 30).unwrap();
+            assert_eq!(edit.coverage, RegionCoverage::Complete);
+            let up = orientation.direction(template.up());
+            let copy = graph.copy_box(anchor, extents, up, 30).unwrap();
+            let expected = template.pasted(orientation, parity);
+            for z in 0..extents[2] {
+                for y in 0..extents[1] {
+                    for x in 0..extents[0] {
+                        assert_eq!(copy.get([x, y, z]), expected([x, y, z]));
+                    }
+                }
+            }
+        }
+        assert_eq!(
+            graph.paste(anchor, &template, Orientation::IDENTITY, 29),
+            Err(EditError::TooLarge {
+                volume: 30,
+                max: 29
+            })
+        );
+    }
+
+    #[test]
+    fn pastes_into_unpopulated_chunks_finish_later() {
+        let dimension = 4;
+        let anchor = (
+            ChunkId::new(crate::graph::NodeId::ROOT, Vertex::A),
+            Coords([dimension - 2, 0, 0]),
+        );
+        let template = asymmetric_template();
+        let orientation = Orientation::quarter_turn(CoordAxis::Y, CoordDirection::Plus);
+        let extents = template.pasted_extents(orientation);
+        let mut graph = patterned_graph(dimension);
+        let neighbor = graph
+            .get_chunk_neighbor(anchor.0, CoordAxis::X, CoordDirection::Plus)
+            .unwrap();
+        let saved = std::mem::replace(&mut graph[neighbor], Chunk::Fresh);
+        let up = (CoordAxis::Y, CoordDirection::Plus);
+        assert_eq!(
+            graph.copy_box(anchor, extents, up, u64::MAX),
+            Err(EditError::Incomplete)
+        );
+
+        let edit = graph
+            .paste(anchor, &template, orientation, u64::MAX)
+            .unwrap();
+        assert_eq!(edit.coverage, RegionCoverage::Partial);
+        assert_eq!(edit.changed, vec![anchor.0]);
+        assert!(!edit.missing.is_empty());
+        assert!(edit.missing.iter().all(|&(chunk, ..)| chunk == neighbor));
+
+        // The rest lands once the chunk is generated
+        graph[neighbor] = saved;
+        for (chunk, coords, material, shape) in edit.missing {
+            let _ = graph.set_block(chunk, coords, material, shape);
+        }
+        let copy = graph.copy_box(anchor, extents, up, u64::MAX).unwrap();
+        let expected = template.pasted(orientation, graph.chunk_parity(anchor.0));
+        for z in 0..extents[2] {
+            for y in 0..extents[1] {
+                for x in 0..extents[0] {
+                    assert_eq!(copy.get([x, y, z]), expected([x, y, z]));
+                }
+            }
+        }
+    }
+}
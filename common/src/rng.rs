@@ -0,0 +1,286 @@
+//! Deterministic random numbers for anything that must come out the same everywhere
+//!
+//! Worldgen and gameplay both need randomness that every machine agrees on: clients and servers
+//! generate terrain independently, and a client predicting the outcome of a step must draw the
+//! same numbers the server does. Each stream is therefore seeded only from where and when it's
+//! used, never from a thread-local source, and is tagged with an `RngPurpose` so that two systems
+//! keyed on the same place or step don't unwittingly share numbers.
+//!
+//! The generator is PCG's 128-bit multiplicative congruential variant with an XSL-RR output
+//! function, implemented here rather than taken from a crate so that a dependency upgrade can
+//! never change the world. It is bit-for-bit identical to `rand_pcg::Pcg64Mcg` seeded with
+//! `SeedableRng::seed_from_u64`, which worldgen used before, so terrain is unchanged. Seeds are
+//! mixed with a fixed multiply-rotate hash rather than `DefaultHasher`, whose output may differ
+//! between platforms and releases.
+
+use std::ops::Range;
+
+use crate::{dodeca::Vertex, Step};
+
+/// What a stream of random numbers is used for, so that each system draws from its own stream
+///
+/// Add a variant for every new system rather than reusing an existing one.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum RngPurpose {
+    /// Natural terrain and everything else generated in a chunk, in pass order
+    Terrain,
+    /// Environmental factors of a node, varied from those of its parent
+    Environment,
+    /// Walks out from the origin in search of spawn points
+    Spawn,
+    /// Outcomes of blocks changing on their own, such as which way sand slides
+    BlockTick,
+}
+
+impl RngPurpose {
+    /// Value mixed into the seed of each stream, fixed forever once published
+    ///
+    /// Worldgen's purposes predate this module and are left unmixed so that terrain is unchanged.
+    fn tag(self) -> Option<u64> {
+        use RngPurpose::*;
+        match self {
+            Terrain | Environment => None,
+            Spawn => Some(1),
+            BlockTick => Some(2),
+        }
+    }
+}
+
+/// A deterministic pseudorandom number generator
+#[derive(Debug, Clone)]
+pub struct Pcg {
+    state: u128,
+}
+
+impl Pcg {
+    const MULTIPLIER: u128 = 0x2360_ed05_1fc6_5da4_4385_df64_9fcc_f645;
+
+    /// Stream for something happening in the chunk at `vertex` of the node whose hash is
+    /// `node_hash`
+    pub fn for_chunk(world_seed: u64, node_hash: u64, vertex: Vertex, purpose: RngPurpose) -> Self {
+        Self::purposed(hash(node_hash ^ world_seed, vertex as u64), purpose)
+    }
+
+    /// Stream for something happening to a whole node
+    pub fn for_node(world_seed: u64, node_hash: u64, purpose: RngPurpose) -> Self {
+        Self::purposed(node_hash ^ world_seed, purpose)
+    }
+
+    /// Stream for something happening to `entity`, given by its bits, during `step`
+    pub fn for_step(world_seed: u64, step: Step, entity: u64, purpose: RngPurpose) -> Self {
+        Self::purposed(hash(hash(world_seed, step as u64), entity), purpose)
+    }
+
+    fn purposed(seed: u64, purpose: RngPurpose) -> Self {
+        match purpose.tag() {
+            None => Self::from_u64(seed),
+            Some(tag) => Self::from_u64(hash(seed, tag)),
+        }
+    }
+
+    /// Expand `seed` into a full state the way `SeedableRng::seed_from_u64` does, by drawing from
+    /// a 64-bit PCG
+    fn from_u64(mut seed: u64) -> Self {
+        let mut state = 0;
+        for i in 0..4 {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(11_634_580_027_462_621_573);
+            let xorshifted = (((seed >> 18) ^ seed) >> 27) as u32;
+            let word = xorshifted.rotate_right((seed >> 59) as u32);
+            state |= u128::from(word) << (32 * i);
+        }
+        Self::from_state(state)
+    }
+
+    fn from_state(state: u128) -> Self {
+        // A multiplicative generator's state must be odd
+        Self { state: state | 3 }
+    }
+
+    fn step(&mut self) -> u64 {
+        self.state = self.state.wrapping_mul(Self::MULTIPLIER);
+        let rotation = (self.state >> 122) as u32;
+        (((self.state >> 64) as u64) ^ (self.state as u64)).rotate_right(rotation)
+    }
+
+    /// Uniformly distributed in [0, 1)
+    pub fn unit(&mut self) -> f32 {
+        (self.step() >> 40) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+
+    /// Uniformly distributed in `range`
+    pub fn range(&mut self, range: Range<f32>) -> f32 {
+        range.start + self.unit() * (range.end - range.start)
+    }
+
+    /// An index into a collection of `len` elements, which must be nonzero
+    pub fn index(&mut self, len: usize) -> usize {
+        debug_assert!(len > 0, "no elements to choose from");
+        ((u128::from(self.step()) * len as u128) >> 64) as usize
+    }
+
+    /// True with probability `p`
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.unit() < p
+    }
+
+    /// Uniformly distributed direction perpendicular to `normal`
+    pub fn unit_in_plane(&mut self, normal: &na::UnitVector3<f32>) -> na::UnitVector3<f32> {
+        // Any axis not nearly parallel to the normal yields a basis for the plane
+        let axis = if normal.x.abs() < 0.5 {
+            na::Vector3::x()
+        } else {
+            na::Vector3::y()
+        };
+        let u = normal.cross(&axis).normalize();
+        let v = normal.cross(&u);
+        let angle = self.range(0.0..std::f32::consts::TAU);
+        na::UnitVector3::new_unchecked(u * angle.cos() + v * angle.sin())
+    }
+}
+
+impl rand::RngCore for Pcg {
+    fn next_u32(&mut self) -> u32 {
+        self.step() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.step()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            chunk.copy_from_slice(&self.step().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Mix `b` into `a`, identically on every platform
+pub fn hash(a: u64, b: u64) -> u64 {
+    use std::ops::BitXor;
+    a.rotate_left(5)
+        .bitxor(b)
+        .wrapping_mul(0x517c_c1b7_2722_0a95)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngCore, SeedableRng};
+
+    fn draws(rng: &mut Pcg) -> [u64; 2] {
+        [(); 2].map(|()| rng.next_u64())
+    }
+
+    #[test]
+    fn matches_reference_generator() {
+        // From the PCG reference implementation's test suite
+        let mut rng = Pcg::from_state(42);
+        assert_eq!(
+            [(); 3].map(|()| rng.next_u64()),
+            [
+                0x63b4_a3a8_13ce_700a,
+                0x3829_5420_0617_ab24,
+                0xa7fd_85ae_3fe9_50ce
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_former_worldgen_generator() {
+        for seed in [0, 1, 0x5eed, u64::MAX] {
+            let mut ours = Pcg::from_u64(seed);
+            let mut theirs = rand_pcg::Pcg64Mcg::seed_from_u64(seed);
+            for _ in 0..16 {
+                assert_eq!(ours.next_u64(), theirs.next_u64(), "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn seeding_is_stable() {
+        use RngPurpose::*;
+        assert_eq!(
+            draws(&mut Pcg::for_chunk(0, 0x1234, Vertex::C, Terrain)),
+            [0x4c93_73d7_e983_4d90, 0xd545_2866_96a0_a2b8]
+        );
+        assert_eq!(
+            draws(&mut Pcg::for_chunk(0x5eed, 0x1234, Vertex::C, Terrain)),
+            [0x1cb2_ec82_63fe_2ec4, 0x7f5b_66dc_0eba_50da]
+        );
+        assert_eq!(
+            draws(&mut Pcg::for_node(0, 0x1234, Environment)),
+            [0xe67f_effe_2939_36e4, 0x9011_4eef_98d1_2ffd]
+        );
+        assert_eq!(
+            draws(&mut Pcg::for_step(7, 100, 3, BlockTick)),
+            [0x0523_b600_2856_ec46, 0xedfa_7d5e_1d55_2719]
+        );
+    }
+
+    #[test]
+    fn purposes_are_independent() {
+        use RngPurpose::*;
+        const SAMPLES: usize = 4096;
+        let purposes = [Terrain, Spawn, BlockTick];
+        for (i, &a) in purposes.iter().enumerate() {
+            for &b in &purposes[i + 1..] {
+                // Matching bits between independent streams are a fair coin flip
+                let mut matching = 0;
+                for key in 0..SAMPLES as u64 {
+                    let x = Pcg::for_chunk(0, key, Vertex::A, a).next_u64();
+                    let y = Pcg::for_chunk(0, key, Vertex::A, b).next_u64();
+                    matching += (!(x ^ y)).count_ones();
+                }
+                let fraction = matching as f64 / (SAMPLES * 64) as f64;
+                assert!((fraction - 0.5).abs() < 0.01, "{a:?} and {b:?}: {fraction}");
+            }
+        }
+    }
+
+    #[test]
+    fn helpers_stay_in_bounds() {
+        let mut rng = Pcg::for_step(0, 0, 0, RngPurpose::BlockTick);
+        let normal = na::UnitVector3::new_normalize(na::Vector3::new(1.0, 2.0, -0.5));
+        for _ in 0..1000 {
+            assert!((-2.0..3.0).contains(&rng.range(-2.0..3.0)));
+            assert!(rng.index(7) < 7);
+            assert!(!rng.chance(0.0));
+            assert!(rng.chance(1.0));
+            let v = rng.unit_in_plane(&normal);
+            assert!(v.dot(&normal).abs() < 1e-5);
+            assert!((v.norm() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    /// A grain of sand falling down a column, sliding left or right at random whenever it lands
+    /// on another, as a block tick would move it
+    fn slide_sand(world_seed: u64, grain: u64) -> Vec<i32> {
+        let mut x = 0;
+        let mut path = Vec::new();
+        for step in 0..32 {
+            let mut rng = Pcg::for_step(world_seed, step, grain, RngPurpose::BlockTick);
+            x += if rng.chance(0.5) { 1 } else { -1 };
+            path.push(x);
+        }
+        path
+    }
+
+    #[test]
+    fn block_ticks_replay_identically() {
+        // Two machines replaying the same steps agree on every slide
+        for grain in 0..8 {
+            let server = slide_sand(0x5eed, grain);
+            let client = slide_sand(0x5eed, grain);
+            assert_eq!(server, client);
+        }
+        // Different grains don't all slide alike
+        assert_ne!(slide_sand(0x5eed, 0), slide_sand(0x5eed, 1));
+    }
+}
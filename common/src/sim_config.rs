@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{dodeca, math};
+use crate::{dodeca, math, proto::MovementModes, worldgen::TerrainPassKind};
 
 /// Manually specified simulation config parameters
 #[derive(Serialize, Deserialize, Default)]
@@ -13,6 +13,9 @@ pub struct SimConfigRaw {
     /// Maximum distance at which anything can be seen in meters
     pub view_distance: Option<f32>,
     pub input_queue_size_ms: Option<u16>,
+    /// Longest time in milliseconds that clients predict motion ahead of the latest state
+    /// acknowledged by the server. Beyond this, prediction is suspended until the server responds.
+    pub max_prediction_latency_ms: Option<u16>,
     /// Number of voxels along the edge of a chunk
     pub chunk_size: Option<u8>,
     /// Approximate length of the edge of a voxel in meters
@@ -24,6 +27,41 @@ pub struct SimConfigRaw {
     /// Note that exact voxel size varies within each chunk. We reference the mean width of the voxels
     /// along the X axis through the center of a chunk.
     pub voxel_size: Option<f32>,
+    /// Longest interval in seconds that character physics integrates at once. Longer steps are
+    /// split into equal substeps.
+    pub max_substep_seconds: Option<f32>,
+    /// Maximum number of substeps a single character step can be split into
+    pub max_substeps: Option<u16>,
+    /// Character steps shorter than this many seconds are skipped, their time carried into the next
+    /// step
+    pub min_step_seconds: Option<f32>,
+    /// Length of a full day/night cycle in seconds. Zero or less stops the clock.
+    pub day_length_seconds: Option<f32>,
+    /// Maximum number of units of each material a character can carry
+    pub inventory_capacity: Option<u32>,
+    /// Whether breaking a block whose material a character has no room for destroys the material
+    /// rather than leaving the block in place
+    pub discard_when_inventory_full: Option<bool>,
+    /// Most voxels a character can fill at once
+    pub max_fill_volume: Option<u64>,
+    /// Most voxels an administrator can fill at once
+    pub admin_max_fill_volume: Option<u64>,
+    /// Number of nodes between the origin and the points new characters are spread across. Zero
+    /// places every new character at the origin.
+    pub spawn_distance: Option<u32>,
+    /// Whether characters may fly through terrain unless an administrator says otherwise
+    pub allow_no_clip: Option<bool>,
+    /// Passes applied, in order, to generate each chunk. Defaults to the usual world.
+    pub terrain: Option<Vec<TerrainPassKind>>,
+    /// Most new nodes to compute the state of per server step or client frame, beyond those needed
+    /// immediately around characters
+    pub node_population_budget: Option<u32>,
+    /// Speed in m/s at which characters throw blocks, on top of their own velocity
+    pub throw_speed: Option<f32>,
+    /// Radius of a thrown block in meters, as it collides with terrain
+    pub projectile_radius: Option<f32>,
+    /// Fraction of a thrown block's speed into a surface that it bounces back with, from 0 to 1
+    pub projectile_restitution: Option<f32>,
     /// Static configuration information relevant to character physics
     #[serde(default)]
     pub character: CharacterConfigRaw,
@@ -36,7 +74,24 @@ pub struct SimConfig {
     pub step_interval: Duration,
     pub view_distance: f32,
     pub input_queue_size: Duration,
+    pub max_prediction_latency: Duration,
     pub chunk_size: u8,
+    pub max_substep_seconds: f32,
+    pub max_substeps: u16,
+    pub min_step_seconds: f32,
+    pub day_length_seconds: f32,
+    pub inventory_capacity: u32,
+    pub discard_when_inventory_full: bool,
+    pub max_fill_volume: u64,
+    pub admin_max_fill_volume: u64,
+    pub spawn_distance: u32,
+    /// Movement modes characters may use when they first join
+    pub default_movement_modes: MovementModes,
+    pub terrain: Vec<TerrainPassKind>,
+    pub node_population_budget: usize,
+    pub throw_speed: f32,
+    pub projectile_radius: f32,
+    pub projectile_restitution: f32,
     pub character: CharacterConfig,
     /// Scaling factor converting meters to absolute units
     pub meters_to_absolute: f32,
@@ -51,7 +106,41 @@ impl SimConfig {
             step_interval: Duration::from_secs(1) / x.rate.unwrap_or(10) as u32,
             view_distance: x.view_distance.unwrap_or(90.0) * meters_to_absolute,
             input_queue_size: Duration::from_millis(x.input_queue_size_ms.unwrap_or(50).into()),
+            max_prediction_latency: Duration::from_millis(
+                x.max_prediction_latency_ms.unwrap_or(2000).into(),
+            ),
             chunk_size,
+            max_substep_seconds: x.max_substep_seconds.unwrap_or(0.1),
+            max_substeps: x.max_substeps.unwrap_or(8),
+            min_step_seconds: x.min_step_seconds.unwrap_or(1e-4),
+            day_length_seconds: x.day_length_seconds.unwrap_or(20.0 * 60.0),
+            inventory_capacity: x.inventory_capacity.unwrap_or(64),
+            discard_when_inventory_full: x.discard_when_inventory_full.unwrap_or(false),
+            max_fill_volume: x.max_fill_volume.unwrap_or(64),
+            admin_max_fill_volume: x.admin_max_fill_volume.unwrap_or(4096),
+            spawn_distance: x.spawn_distance.unwrap_or(2),
+            default_movement_modes: if x.allow_no_clip.unwrap_or(true) {
+                MovementModes::NO_CLIP
+            } else {
+                MovementModes::NONE
+            },
+            terrain: match x.terrain {
+                None => TerrainPassKind::DEFAULT.to_vec(),
+                Some(ref passes) => passes
+                    .iter()
+                    .map(|&pass| match pass {
+                        TerrainPassKind::Flat { height, material } => TerrainPassKind::Flat {
+                            height: height * meters_to_absolute,
+                            material,
+                        },
+                        _ => pass,
+                    })
+                    .collect(),
+            },
+            node_population_budget: x.node_population_budget.unwrap_or(64) as usize,
+            throw_speed: x.throw_speed.unwrap_or(12.0) * meters_to_absolute,
+            projectile_radius: x.projectile_radius.unwrap_or(0.2) * meters_to_absolute,
+            projectile_restitution: x.projectile_restitution.unwrap_or(0.2).clamp(0.0, 1.0),
             character: CharacterConfig::from_raw(&x.character, meters_to_absolute),
             meters_to_absolute,
         }
@@ -61,8 +150,8 @@ impl SimConfig {
 /// Compute the scaling factor from meters to absolute units, given the number of voxels in a chunk
 /// and the approximate size of a voxel in meters.
 fn meters_to_absolute(chunk_size: u8, voxel_size: f32) -> f32 {
-    let a = dodeca::Vertex::A.chunk_to_node() * na::Vector4::new(1.0, 0.5, 0.5, 1.0);
-    let b = dodeca::Vertex::A.chunk_to_node() * na::Vector4::new(0.0, 0.5, 0.5, 1.0);
+    let a = dodeca::Vertex::A.chunk_to_node_f64() * na::Vector4::new(1.0, 0.5, 0.5, 1.0);
+    let b = dodeca::Vertex::A.chunk_to_node_f64() * na::Vector4::new(0.0, 0.5, 0.5, 1.0);
     let minimum_chunk_face_separation = math::distance(&a, &b);
     let absolute_voxel_size = minimum_chunk_face_separation / f64::from(chunk_size);
     absolute_voxel_size as f32 / voxel_size
@@ -77,8 +166,10 @@ pub struct CharacterConfigRaw {
     pub max_ground_speed: Option<f32>,
     /// Character artificial speed cap to avoid overloading the server in m/s
     pub speed_cap: Option<f32>,
-    /// Maximum ground slope (0=horizontal, 1=45 degrees)
+    /// Maximum ground slope (0=horizontal, 1=45 degrees), for materials that don't set their own
     pub max_ground_slope: Option<f32>,
+    /// Fraction of the pull down a slippery slope that walking up it can counteract, from 0 to 1
+    pub slide_resistance: Option<f32>,
     /// Character acceleration while on the ground in m/s^2
     pub ground_acceleration: Option<f32>,
     /// Character acceleration while in the air in m/s^2
@@ -95,6 +186,27 @@ pub struct CharacterConfigRaw {
     pub character_radius: Option<f32>,
     /// How far a character can reach when placing blocks
     pub block_reach: Option<f32>,
+    /// Fastest speed in m/s at which overlapping characters are pushed apart
+    pub max_separation_speed: Option<f32>,
+    /// Health of an unharmed character
+    pub max_health: Option<f32>,
+    /// Fastest speed in m/s at which a character can hit the ground unharmed
+    pub safe_fall_speed: Option<f32>,
+    /// Health lost per m/s by which a character's impact speed exceeds `safe_fall_speed`
+    pub fall_damage: Option<f32>,
+    /// Shortest time in seconds a character must be airborne for landing to cause damage
+    pub min_fall_seconds: Option<f32>,
+    /// Tallest ledge in meters, such as a slab or a stair, that a character walking on the ground
+    /// climbs without jumping
+    pub max_step_height: Option<f32>,
+    /// Farthest drop in meters, such as down a slope or a stair, onto which a character walking
+    /// off the ground is pulled rather than left to fall
+    pub ground_snap_distance: Option<f32>,
+    /// Fastest speed in m/s at which a character walking off the ground can be rising for it to be
+    /// pulled down, so that running up a ramp can launch it off the top
+    pub ground_snap_max_rise_speed: Option<f32>,
+    /// Speed in m/s at which a character climbs up or down a climbable wall
+    pub climb_speed: Option<f32>,
 }
 
 /// Static configuration information relevant to character physics
@@ -104,6 +216,7 @@ pub struct CharacterConfig {
     pub max_ground_speed: f32,
     pub speed_cap: f32,
     pub max_ground_slope: f32,
+    pub slide_resistance: f32,
     pub ground_acceleration: f32,
     pub air_acceleration: f32,
     pub gravity_acceleration: f32,
@@ -112,6 +225,16 @@ pub struct CharacterConfig {
     pub ground_distance_tolerance: f32,
     pub character_radius: f32,
     pub block_reach: f32,
+    pub max_separation_speed: f32,
+    pub max_health: f32,
+    pub safe_fall_speed: f32,
+    /// Health lost per absolute unit per second of impact speed beyond `safe_fall_speed`
+    pub fall_damage: f32,
+    pub min_fall_seconds: f32,
+    pub max_step_height: f32,
+    pub ground_snap_distance: f32,
+    pub ground_snap_max_rise_speed: f32,
+    pub climb_speed: f32,
 }
 
 impl CharacterConfig {
@@ -121,6 +244,7 @@ impl CharacterConfig {
             max_ground_speed: x.max_ground_speed.unwrap_or(4.0) * meters_to_absolute,
             speed_cap: x.speed_cap.unwrap_or(30.0) * meters_to_absolute,
             max_ground_slope: x.max_ground_slope.unwrap_or(1.73), // 60 degrees
+            slide_resistance: x.slide_resistance.unwrap_or(0.5).clamp(0.0, 1.0),
             ground_acceleration: x.ground_acceleration.unwrap_or(20.0) * meters_to_absolute,
             air_acceleration: x.air_acceleration.unwrap_or(2.0) * meters_to_absolute,
             gravity_acceleration: x.gravity_acceleration.unwrap_or(20.0) * meters_to_absolute,
@@ -130,6 +254,16 @@ impl CharacterConfig {
                 * meters_to_absolute,
             character_radius: x.character_radius.unwrap_or(0.4) * meters_to_absolute,
             block_reach: x.block_reach.unwrap_or(10.0) * meters_to_absolute,
+            max_separation_speed: x.max_separation_speed.unwrap_or(4.0) * meters_to_absolute,
+            max_health: x.max_health.unwrap_or(100.0),
+            safe_fall_speed: x.safe_fall_speed.unwrap_or(12.0) * meters_to_absolute,
+            fall_damage: x.fall_damage.unwrap_or(10.0) / meters_to_absolute,
+            min_fall_seconds: x.min_fall_seconds.unwrap_or(0.25),
+            max_step_height: x.max_step_height.unwrap_or(0.7) * meters_to_absolute,
+            ground_snap_distance: x.ground_snap_distance.unwrap_or(1.5) * meters_to_absolute,
+            ground_snap_max_rise_speed: x.ground_snap_max_rise_speed.unwrap_or(1.0)
+                * meters_to_absolute,
+            climb_speed: x.climb_speed.unwrap_or(3.0) * meters_to_absolute,
         }
     }
 }
@@ -0,0 +1,627 @@
+//! Copies of boxes of voxels that can be pasted elsewhere in any of the box's orientations
+//!
+//! Templates are copied and pasted as boxes, as defined by `Graph::for_each_block_in_box`. Their
+//! voxels are kept in the axes of the box they were copied from, which are those of its anchor's
+//! chunk, along with which way was up there and the handedness of that chunk's coordinates, so
+//! that a paste can be turned to suit wherever it lands.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    node::{CoordAxis, CoordDirection},
+    region::box_volume,
+    world::{Material, Shape},
+};
+
+/// One of the 24 ways of turning a box that keep it aligned with the voxel grid
+///
+/// Maps the axes of a template to those of the box it's pasted as, each of which the template's
+/// axis runs along either forwards or backwards. Encoded as its position in `all`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct Orientation {
+    /// Axis of the pasted box along which each axis of the template runs
+    axes: [CoordAxis; 3],
+    /// Whether each axis of the template runs backwards along its counterpart
+    reversed: [bool; 3],
+}
+
+impl Orientation {
+    pub const IDENTITY: Self = Self {
+        axes: [CoordAxis::X, CoordAxis::Y, CoordAxis::Z],
+        reversed: [false; 3],
+    };
+
+    /// Every orientation, starting with `IDENTITY`, in the order of their indices
+    pub fn all() -> impl Iterator<Item = Self> {
+        // Even permutations of the axes first, which are turns only when an even number of axes
+        // are reversed
+        const PERMUTATIONS: [[usize; 3]; 6] = [
+            [0, 1, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [0, 2, 1],
+            [2, 1, 0],
+            [1, 0, 2],
+        ];
+        PERMUTATIONS
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, permutation)| (0..8u8).map(move |bits| (i >= 3, permutation, bits)))
+            .filter(|&(odd, _, bits)| odd == (bits.count_ones() % 2 == 1))
+            .map(|(_, permutation, bits)| Self {
+                axes: permutation.map(|axis| CoordAxis::try_from(axis).unwrap()),
+                reversed: [0, 1, 2].map(|i| bits & (1 << i) != 0),
+            })
+    }
+
+    /// Position of this orientation in `all`
+    pub fn index(self) -> u8 {
+        Self::all().position(|x| x == self).unwrap() as u8
+    }
+
+    /// A quarter turn about `axis`, counterclockwise when looking back along `direction`
+    pub fn quarter_turn(axis: CoordAxis, direction: CoordDirection) -> Self {
+        let a = axis as usize;
+        let (b, c) = ((a + 1) % 3, (a + 2) % 3);
+        let mut result = Self::IDENTITY;
+        result.axes[b] = CoordAxis::try_from(c).unwrap();
+        result.axes[c] = CoordAxis::try_from(b).unwrap();
+        result.reversed[b] = direction == CoordDirection::Minus;
+        result.reversed[c] = direction == CoordDirection::Plus;
+        result
+    }
+
+    /// The first orientation, in the order of `all`, that turns direction `from` to `to`
+    pub fn aligning(from: (CoordAxis, CoordDirection), to: (CoordAxis, CoordDirection)) -> Self {
+        Self::all().find(|x| x.direction(from) == to).unwrap()
+    }
+
+    /// The orientation turning a template whose up is `from` upright where up is `to`, then
+    /// `turns` quarter turns about `to`
+    pub fn upright(
+        from: (CoordAxis, CoordDirection),
+        to: (CoordAxis, CoordDirection),
+        turns: u32,
+    ) -> Self {
+        let turn = Self::quarter_turn(to.0, to.1);
+        (0..turns % 4).fold(Self::aligning(from, to), |x, _| x.then(turn))
+    }
+
+    /// Reverses `axis`, which no turn can do, for undoing a difference in handedness
+    fn mirror(axis: CoordAxis) -> Self {
+        let mut result = Self::IDENTITY;
+        result.reversed[axis as usize] = true;
+        result
+    }
+
+    /// This orientation followed by `next`
+    pub fn then(self, next: Self) -> Self {
+        Self {
+            axes: self.axes.map(|axis| next.axes[axis as usize]),
+            reversed: [0, 1, 2].map(|i| self.reversed[i] != next.reversed[self.axes[i] as usize]),
+        }
+    }
+
+    /// The orientation undoing this one
+    pub fn inverse(self) -> Self {
+        let mut result = Self::IDENTITY;
+        for (i, &axis) in self.axes.iter().enumerate() {
+            result.axes[axis as usize] = CoordAxis::try_from(i).unwrap();
+            result.reversed[axis as usize] = self.reversed[i];
+        }
+        result
+    }
+
+    /// The direction a template's `direction` faces once pasted
+    pub fn direction(
+        self,
+        (axis, direction): (CoordAxis, CoordDirection),
+    ) -> (CoordAxis, CoordDirection) {
+        let i = axis as usize;
+        let direction = match (self.reversed[i], direction) {
+            (false, x) => x,
+            (true, CoordDirection::Plus) => CoordDirection::Minus,
+            (true, CoordDirection::Minus) => CoordDirection::Plus,
+        };
+        (self.axes[i], direction)
+    }
+
+    /// Extents of the box a template spanning `extents` is pasted as
+    pub fn extents(self, extents: [u32; 3]) -> [u32; 3] {
+        let mut result = [0; 3];
+        for (i, &axis) in self.axes.iter().enumerate() {
+            result[axis as usize] = extents[i];
+        }
+        result
+    }
+
+    /// Where the voxel at `offset` in a template spanning `extents` lands in the pasted box
+    pub fn offset(self, extents: [u32; 3], offset: [u32; 3]) -> [u32; 3] {
+        let mut result = [0; 3];
+        for (i, &axis) in self.axes.iter().enumerate() {
+            result[axis as usize] = if self.reversed[i] {
+                extents[i] - 1 - offset[i]
+            } else {
+                offset[i]
+            };
+        }
+        result
+    }
+
+    /// The shape a voxel of a template takes once pasted
+    pub fn shape(self, shape: Shape) -> Shape {
+        Shape::from_fn(|octant| {
+            shape.contains([0, 1, 2].map(|i| {
+                let half = octant[self.axes[i] as usize];
+                if self.reversed[i] {
+                    1 - half
+                } else {
+                    half
+                }
+            }))
+        })
+        .expect("shapes are preserved by symmetries of the cube")
+    }
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl TryFrom<u8> for Orientation {
+    type Error = InvalidOrientation;
+
+    fn try_from(index: u8) -> Result<Self, Self::Error> {
+        Self::all()
+            .nth(usize::from(index))
+            .ok_or(InvalidOrientation(index))
+    }
+}
+
+impl From<Orientation> for u8 {
+    fn from(orientation: Orientation) -> Self {
+        orientation.index()
+    }
+}
+
+/// An orientation index of 24 or more
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidOrientation(pub u8);
+
+impl fmt::Display for InvalidOrientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not an orientation from 0 to 23", self.0)
+    }
+}
+
+impl std::error::Error for InvalidOrientation {}
+
+/// The contents of a box of voxels, for pasting elsewhere
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawTemplate")]
+pub struct Template {
+    extents: [u32; 3],
+    #[serde(with = "direction")]
+    up: (CoordAxis, CoordDirection),
+    parity: bool,
+    /// Materials and shapes of each voxel, with offsets along X varying fastest, then Y
+    voxels: Vec<(Material, Shape)>,
+}
+
+impl Template {
+    /// Most voxels a template may hold, well beyond what anyone may paste at once
+    pub const MAX_VOLUME: u64 = 1 << 20;
+
+    /// A template spanning `extents`, with the voxels at each offset listed with X varying
+    /// fastest, then Y, and their shapes given in the template's axes
+    ///
+    /// `up` is the direction that was up where the template was copied, and `parity` the parity of
+    /// the chunk it was copied from, as given by `Graph::chunk_parity`.
+    pub fn new(
+        extents: [u32; 3],
+        up: (CoordAxis, CoordDirection),
+        parity: bool,
+        voxels: Vec<(Material, Shape)>,
+    ) -> Result<Self, InvalidTemplate> {
+        let volume = box_volume(extents);
+        if volume == 0 || volume > Self::MAX_VOLUME {
+            return Err(InvalidTemplate::Extents(extents));
+        }
+        if voxels.len() as u64 != volume {
+            return Err(InvalidTemplate::Voxels {
+                expected: volume,
+                actual: voxels.len(),
+            });
+        }
+        Ok(Self {
+            extents,
+            up,
+            parity,
+            voxels,
+        })
+    }
+
+    /// Number of voxels spanned along each axis
+    pub fn extents(&self) -> [u32; 3] {
+        self.extents
+    }
+
+    /// The direction that was up where the template was copied, in its own axes
+    pub fn up(&self) -> (CoordAxis, CoordDirection) {
+        self.up
+    }
+
+    pub fn volume(&self) -> u64 {
+        self.voxels.len() as u64
+    }
+
+    /// Material and shape of the voxel at `offset`
+    pub fn get(&self, offset: [u32; 3]) -> (Material, Shape) {
+        let [x, y, z] = offset.map(|x| x as usize);
+        let [ex, ey, _] = self.extents.map(|x| x as usize);
+        self.voxels[x + ex * (y + ey * z)]
+    }
+
+    /// Extents of the box the template is pasted as with `orientation`
+    pub fn pasted_extents(&self, orientation: Orientation) -> [u32; 3] {
+        orientation.extents(self.extents)
+    }
+
+    /// The contents of each voxel of the box the template is pasted as with `orientation`, given
+    /// its offset, into a box anchored in a chunk of parity `parity`
+    ///
+    /// Shapes are given in the axes of that box.
+    pub fn pasted(
+        &self,
+        orientation: Orientation,
+        parity: bool,
+    ) -> impl Fn([u32; 3]) -> (Material, Shape) + '_ {
+        let placement = self.placement(orientation, parity);
+        let inverse = placement.inverse();
+        let extents = placement.extents(self.extents);
+        move |offset| {
+            let (material, shape) = self.get(inverse.offset(extents, offset));
+            (material, placement.shape(shape))
+        }
+    }
+
+    /// How the voxels are rearranged when pasted with `orientation` into a chunk of parity
+    /// `parity`
+    fn placement(&self, orientation: Orientation, parity: bool) -> Orientation {
+        if parity == self.parity {
+            return orientation;
+        }
+        // The two chunks' coordinates have opposite handedness, so the same orientation would paste
+        // a mirror image. Reversing an axis other than up first keeps up where the orientation
+        // puts it.
+        Orientation::mirror(self.up.0.other_axes()[0]).then(orientation)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawTemplate {
+    extents: [u32; 3],
+    #[serde(with = "direction")]
+    up: (CoordAxis, CoordDirection),
+    parity: bool,
+    voxels: Vec<(Material, Shape)>,
+}
+
+impl TryFrom<RawTemplate> for Template {
+    type Error = InvalidTemplate;
+
+    fn try_from(raw: RawTemplate) -> Result<Self, Self::Error> {
+        Self::new(raw.extents, raw.up, raw.parity, raw.voxels)
+    }
+}
+
+/// Why a template couldn't be made
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidTemplate {
+    /// The box is empty, or holds more than `Template::MAX_VOLUME` voxels
+    Extents([u32; 3]),
+    /// The number of voxels given doesn't match the extents
+    Voxels { expected: u64, actual: usize },
+}
+
+impl fmt::Display for InvalidTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            InvalidTemplate::Extents(extents) => write!(f, "invalid template extents {extents:?}"),
+            InvalidTemplate::Voxels { expected, actual } => {
+                write!(
+                    f,
+                    "template has {actual} voxels where {expected} were expected"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidTemplate {}
+
+/// Directions encoded as single numbers, the axis times two, plus one if negative
+mod direction {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use crate::node::{CoordAxis, CoordDirection};
+
+    pub fn serialize<S: Serializer>(
+        &(axis, direction): &(CoordAxis, CoordDirection),
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(axis as u8 * 2 + u8::from(direction == CoordDirection::Minus))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<(CoordAxis, CoordDirection), D::Error> {
+        let x = u8::deserialize(deserializer)?;
+        let axis = CoordAxis::try_from(usize::from(x / 2))
+            .map_err(|_| de::Error::custom(format!("{x} is not a direction")))?;
+        let direction = if x % 2 == 0 {
+            CoordDirection::Plus
+        } else {
+            CoordDirection::Minus
+        };
+        Ok((axis, direction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec;
+
+    const MATERIALS: [Material; 6] = [
+        Material::Dirt,
+        Material::Sand,
+        Material::Silt,
+        Material::Clay,
+        Material::Granite,
+        Material::Void,
+    ];
+
+    fn all_directions() -> impl Iterator<Item = (CoordAxis, CoordDirection)> {
+        CoordAxis::iter().flat_map(|a| CoordDirection::iter().map(move |d| (a, d)))
+    }
+
+    /// A template with no symmetries, in either its extents or its contents, some of whose voxels
+    /// are stairs or slabs facing every which way
+    fn asymmetric() -> Template {
+        use CoordAxis::*;
+        use CoordDirection::*;
+        let shapes = [
+            Shape::FULL,
+            Shape::stairs((Y, Plus), (Z, Plus)),
+            Shape::slab(X, Minus),
+            Shape::stairs((Z, Minus), (X, Plus)),
+            Shape::slab(Y, Plus),
+            Shape::stairs((X, Plus), (Y, Minus)),
+            Shape::FULL,
+        ];
+        let extents = [2, 3, 4];
+        let voxels = (0..24)
+            .map(|i| {
+                let material = MATERIALS[i % MATERIALS.len()];
+                let shape = if material == Material::Void {
+                    Shape::FULL
+                } else {
+                    shapes[i % shapes.len()]
+                };
+                (material, shape)
+            })
+            .collect();
+        Template::new(extents, (Y, Plus), false, voxels).unwrap()
+    }
+
+    /// The signed permutation matrix of `orientation`, taking template axes to pasted ones
+    fn matrix(orientation: Orientation) -> [[i32; 3]; 3] {
+        let mut result = [[0; 3]; 3];
+        for i in 0..3 {
+            let (axis, direction) =
+                orientation.direction((CoordAxis::try_from(i).unwrap(), CoordDirection::Plus));
+            result[axis as usize][i] = direction as i32;
+        }
+        result
+    }
+
+    fn determinant(m: [[i32; 3]; 3]) -> i32 {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    #[test]
+    fn orientations_are_the_24_turns() {
+        let all = Orientation::all().collect::<Vec<_>>();
+        assert_eq!(all.len(), 24);
+        assert_eq!(all[0], Orientation::IDENTITY);
+        for (i, &orientation) in all.iter().enumerate() {
+            assert_eq!(determinant(matrix(orientation)), 1);
+            assert!(all[..i].iter().all(|&x| x != orientation));
+            assert_eq!(usize::from(orientation.index()), i);
+            assert_eq!(Orientation::try_from(i as u8), Ok(orientation));
+            let encoded = codec::encode(&orientation);
+            assert_eq!(codec::decode::<Orientation>(&encoded).unwrap(), orientation);
+            assert_eq!(
+                orientation.then(orientation.inverse()),
+                Orientation::IDENTITY
+            );
+            assert_eq!(
+                orientation.inverse().then(orientation),
+                Orientation::IDENTITY
+            );
+            for &other in &all {
+                assert!(all.contains(&orientation.then(other)));
+            }
+        }
+        assert_eq!(Orientation::try_from(24), Err(InvalidOrientation(24)));
+        assert!(codec::decode::<Orientation>(&codec::encode(&24u8)).is_err());
+    }
+
+    #[test]
+    fn turns_compose() {
+        use CoordAxis::*;
+        use CoordDirection::*;
+        let turn = Orientation::quarter_turn(Z, Plus);
+        assert_eq!(turn.direction((X, Plus)), (Y, Plus));
+        assert_eq!(turn.direction((Y, Plus)), (X, Minus));
+        assert_eq!(turn.direction((Z, Minus)), (Z, Minus));
+        assert_eq!(Orientation::quarter_turn(Z, Minus), turn.inverse());
+        for (axis, direction) in all_directions() {
+            let turn = Orientation::quarter_turn(axis, direction);
+            let full = (0..4).fold(Orientation::IDENTITY, |x, _| x.then(turn));
+            assert_eq!(full, Orientation::IDENTITY);
+            assert_ne!(turn.then(turn), Orientation::IDENTITY);
+        }
+        for from in all_directions() {
+            for to in all_directions() {
+                let upright = (0..4)
+                    .map(|turns| Orientation::upright(from, to, turns))
+                    .collect::<Vec<_>>();
+                for (i, &x) in upright.iter().enumerate() {
+                    assert_eq!(x.direction(from), to);
+                    assert!(upright[..i].iter().all(|&y| y != x));
+                }
+                assert_eq!(Orientation::upright(from, to, 5), upright[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn every_orientation_moves_voxels_rigidly() {
+        let template = asymmetric();
+        let extents = template.extents();
+        for orientation in Orientation::all() {
+            let m = matrix(orientation);
+            let pasted_extents = template.pasted_extents(orientation);
+            assert_eq!(box_volume(pasted_extents), template.volume());
+            let pasted = template.pasted(orientation, false);
+            let mut filled = 0;
+            // Follow the center of each filled octant, in quarters of a voxel, through a turn
+            // about the center of the box
+            for z in 0..extents[2] {
+                for y in 0..extents[1] {
+                    for x in 0..extents[0] {
+                        let offset = [x, y, z];
+                        let (material, shape) = template.get(offset);
+                        for octant in Shape::OCTANTS {
+                            if !shape.contains(octant) {
+                                continue;
+                            }
+                            filled += 1;
+                            let point = [0, 1, 2].map(|i| {
+                                4 * offset[i] as i32 + 2 * i32::from(octant[i]) + 1
+                                    - 2 * extents[i] as i32
+                            });
+                            let moved = [0, 1, 2].map(|i| {
+                                (0..3).map(|j| m[i][j] * point[j]).sum::<i32>()
+                                    + 2 * pasted_extents[i] as i32
+                            });
+                            let target = moved.map(|x| (x / 4) as u32);
+                            let target_octant = moved.map(|x| ((x % 4) / 2) as u8);
+                            assert_eq!(orientation.offset(extents, offset), target);
+                            let (pasted_material, pasted_shape) = pasted(target);
+                            assert_eq!(pasted_material, material);
+                            assert!(
+                                pasted_shape.contains(target_octant),
+                                "{orientation:?} misplaced an octant of {offset:?}"
+                            );
+                        }
+                    }
+                }
+            }
+            // Nothing filled out of nowhere
+            let mut pasted_filled = 0;
+            for z in 0..pasted_extents[2] {
+                for y in 0..pasted_extents[1] {
+                    for x in 0..pasted_extents[0] {
+                        pasted_filled += pasted([x, y, z]).1.octants().count_ones();
+                    }
+                }
+            }
+            assert_eq!(pasted_filled, filled);
+        }
+    }
+
+    #[test]
+    fn stairs_and_slabs_turn_with_the_box() {
+        for orientation in Orientation::all() {
+            for up in all_directions() {
+                assert_eq!(
+                    orientation.shape(Shape::slab(up.0, up.1)),
+                    Shape::slab(orientation.direction(up).0, orientation.direction(up).1)
+                );
+                for back in all_directions().filter(|back| back.0 != up.0) {
+                    assert_eq!(
+                        orientation.shape(Shape::stairs(up, back)),
+                        Shape::stairs(orientation.direction(up), orientation.direction(back))
+                    );
+                }
+            }
+            assert_eq!(orientation.shape(Shape::FULL), Shape::FULL);
+        }
+    }
+
+    #[test]
+    fn opposite_parity_is_mirrored_upright() {
+        let template = asymmetric();
+        let same = template.pasted(Orientation::IDENTITY, false);
+        let mirrored = template.pasted(Orientation::IDENTITY, true);
+        let [ex, ey, ez] = template.extents();
+        for z in 0..ez {
+            for y in 0..ey {
+                for x in 0..ex {
+                    assert_eq!(same([x, y, z]), template.get([x, y, z]));
+                    // Up is Y, so Z is the axis reversed
+                    let (material, shape) = template.get([x, y, ez - 1 - z]);
+                    let mirror = Orientation::mirror(CoordAxis::Z);
+                    assert_eq!(mirrored([x, y, z]), (material, mirror.shape(shape)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn templates_validated_when_decoded() {
+        let template = asymmetric();
+        let encoded = codec::encode(&template);
+        assert_eq!(codec::decode::<Template>(&encoded).unwrap(), template);
+
+        let mut voxels = template.voxels.clone();
+        voxels.pop();
+        assert_eq!(
+            Template::new(template.extents(), template.up(), false, voxels.clone()),
+            Err(InvalidTemplate::Voxels {
+                expected: 24,
+                actual: 23
+            })
+        );
+        let truncated = RawTemplateForTest {
+            extents: template.extents(),
+            up: 2,
+            parity: false,
+            voxels,
+        };
+        assert!(codec::decode::<Template>(&codec::encode(&truncated)).is_err());
+        assert_eq!(
+            Template::new([0, 1, 1], template.up(), false, Vec::new()),
+            Err(InvalidTemplate::Extents([0, 1, 1]))
+        );
+    }
+
+    /// The encoding of a template, unchecked
+    #[derive(Serialize)]
+    struct RawTemplateForTest {
+        extents: [u32; 3],
+        up: u8,
+        parity: bool,
+        voxels: Vec<(Material, Shape)>,
+    }
+}
@@ -0,0 +1,147 @@
+//! Graphs of simple terrain around the origin, shared by tests of movement and collision
+//!
+//! Also built for other crates' tests under the `test-util` feature.
+
+use std::ops::Range;
+
+use crate::{
+    coords::voxel_center_position,
+    dodeca::{Side, Vertex},
+    graph::{Graph, NodeId},
+    math,
+    node::{populate_fresh_nodes, Chunk, ChunkId, Coords, VoxelData},
+    proto::Position,
+    traversal::{ensure_nearby, nearby_nodes},
+    world::Material,
+    SimConfig,
+};
+
+/// Distance from the origin within which the graphs built here have every chunk populated
+pub const GRAPH_RADIUS: f64 = 2.0;
+
+/// Elevation of a point given in the root node's coordinates, relative to the origin
+pub fn elevation(graph: &Graph, point: &na::Vector4<f32>) -> f32 {
+    let up = graph
+        .get(NodeId::ROOT)
+        .as_ref()
+        .unwrap()
+        .state
+        .up_direction();
+    math::mip(&up, point).asinh() - math::mip(&up, &math::origin()).asinh()
+}
+
+/// Elevation of `position` above the reference surface of the terrain of its node, from which
+/// the height of flat terrain is measured
+pub fn surface_elevation(graph: &Graph, position: &Position) -> f32 {
+    let up = graph
+        .get(position.node)
+        .as_ref()
+        .unwrap()
+        .state
+        .up_direction();
+    math::mip(&up, &(position.local * math::origin())).asinh()
+}
+
+/// Extend `graph` from the root along `sides`, returning the node reached
+pub fn walk(graph: &mut Graph, sides: &[Side]) -> NodeId {
+    sides.iter().fold(NodeId::ROOT, |node, &side| {
+        graph.ensure_neighbor(node, side)
+    })
+}
+
+/// Build a graph of every node within `radius` of the origin, with each voxel made of the
+/// `material` found at its center, given in the root node's coordinates
+///
+/// `material` is only consulted in chunks whose centers `near` accepts. Every other chunk is left
+/// empty.
+pub fn graph_with_voxels(
+    cfg: &SimConfig,
+    radius: f64,
+    near: impl Fn(&Graph, &na::Vector4<f32>) -> bool,
+    material: impl Fn(&Graph, &na::Vector4<f32>) -> Material,
+) -> Graph {
+    let dimension = cfg.chunk_size;
+    let mut graph = Graph::new(dimension);
+    ensure_nearby(&mut graph, &Position::origin(), radius);
+    populate_fresh_nodes(&mut graph);
+    for (node, transform) in nearby_nodes(&graph, &Position::origin(), radius) {
+        for vertex in Vertex::iter() {
+            let chunk = ChunkId::new(node, vertex);
+            let center = transform
+                * math::lorentz_normalize(
+                    &(vertex.chunk_to_node_f64() * na::Vector4::new(0.5, 0.5, 0.5, 1.0)),
+                )
+                .cast::<f32>();
+            let voxels = if near(&graph, &center) {
+                let mut materials = Vec::with_capacity(usize::from(dimension).pow(3));
+                for z in 0..dimension {
+                    for y in 0..dimension {
+                        for x in 0..dimension {
+                            let center =
+                                voxel_center_position(graph.layout(), chunk, Coords([x, y, z]));
+                            materials.push(material(
+                                &graph,
+                                &(transform * center.local * math::origin()),
+                            ));
+                        }
+                    }
+                }
+                if materials.iter().all(|&x| x == materials[0]) {
+                    VoxelData::Solid(materials[0])
+                } else {
+                    VoxelData::Dense(materials.into())
+                }
+            } else {
+                VoxelData::Solid(Material::Void)
+            };
+            graph[chunk] = Chunk::Populated {
+                voxels,
+                modified: false,
+                generation: 0,
+                surface: None,
+                old_surface: None,
+                occupancy: Default::default(),
+            };
+        }
+    }
+    graph
+}
+
+/// Build a graph around the origin with every chunk populated and empty
+pub fn empty_graph(cfg: &SimConfig) -> Graph {
+    graph_with_voxels(cfg, GRAPH_RADIUS, |_, _| false, |_, _| Material::Void)
+}
+
+/// Build a graph around the origin that is empty except for a horizontal slab of dirt spanning
+/// the given range of elevations
+pub fn graph_with_floor(cfg: &SimConfig, floor: Range<f32>) -> Graph {
+    graph_with_terrain(cfg, floor.clone(), |graph, point| {
+        floor.contains(&elevation(graph, point))
+    })
+}
+
+/// Build a graph around the origin that is empty except for dirt filling the voxels whose
+/// centers, in the root node's coordinates, are `solid`, all of which lie within the given range
+/// of elevations
+pub fn graph_with_terrain(
+    cfg: &SimConfig,
+    elevations: Range<f32>,
+    solid: impl Fn(&Graph, &na::Vector4<f32>) -> bool,
+) -> Graph {
+    graph_with_voxels(
+        cfg,
+        GRAPH_RADIUS,
+        // Chunks are much smaller than this, so distant ones can be skipped
+        |graph, center| {
+            let center = elevation(graph, center);
+            center >= elevations.start - 1.0 && center <= elevations.end + 1.0
+        },
+        |graph, point| {
+            if solid(graph, point) {
+                Material::Dirt
+            } else {
+                Material::Void
+            }
+        },
+    )
+}
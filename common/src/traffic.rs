@@ -0,0 +1,106 @@
+//! Accounting for the messages crossing a connection, by kind
+
+use std::{collections::BTreeMap, ops};
+
+use serde::Serialize;
+
+/// Number and size of some messages
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TrafficCount {
+    pub messages: u64,
+    /// Bytes the messages occupied on the wire, after any compression
+    pub bytes: u64,
+    /// Bytes the messages were encoded as before compression
+    pub uncompressed_bytes: u64,
+}
+
+impl ops::AddAssign for TrafficCount {
+    fn add_assign(&mut self, rhs: Self) {
+        self.messages += rhs.messages;
+        self.bytes += rhs.bytes;
+        self.uncompressed_bytes += rhs.uncompressed_bytes;
+    }
+}
+
+/// Messages sent or received one way across a connection, counted by kind
+///
+/// Kinds are the names of message variants, as given by `ServerMessage::kind` and
+/// `ClientMessage::kind`, with the hellos and state deltas named after their types.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Traffic(BTreeMap<&'static str, TrafficCount>);
+
+impl Traffic {
+    /// Count a message of `kind` that occupied `bytes` on the wire, having been `uncompressed_bytes`
+    /// before compression
+    pub fn record(&mut self, kind: &'static str, bytes: usize, uncompressed_bytes: usize) {
+        *self.0.entry(kind).or_default() += TrafficCount {
+            messages: 1,
+            bytes: bytes as u64,
+            uncompressed_bytes: uncompressed_bytes as u64,
+        };
+    }
+
+    /// The messages of `kind` counted
+    pub fn get(&self, kind: &str) -> TrafficCount {
+        self.0.get(kind).copied().unwrap_or_default()
+    }
+
+    /// Every message counted, of whatever kind
+    pub fn total(&self) -> TrafficCount {
+        let mut total = TrafficCount::default();
+        for &count in self.0.values() {
+            total += count;
+        }
+        total
+    }
+
+    /// Each kind of message counted, in order of name
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, TrafficCount)> + '_ {
+        self.0.iter().map(|(&kind, &count)| (kind, count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_by_kind() {
+        let mut traffic = Traffic::default();
+        // A second of state deltas alongside a few large compressed messages
+        for i in 0..10 {
+            traffic.record("StateDelta", 100 + i, 100 + i);
+        }
+        for _ in 0..3 {
+            traffic.record("GraphRegions", 2000, 8000);
+        }
+        assert_eq!(
+            traffic.get("StateDelta"),
+            TrafficCount {
+                messages: 10,
+                bytes: (100..110).sum(),
+                uncompressed_bytes: (100..110).sum(),
+            }
+        );
+        assert_eq!(
+            traffic.get("GraphRegions"),
+            TrafficCount {
+                messages: 3,
+                bytes: 6000,
+                uncompressed_bytes: 24000,
+            }
+        );
+        assert_eq!(traffic.get("Spawns"), TrafficCount::default());
+        assert_eq!(
+            traffic.total(),
+            TrafficCount {
+                messages: 13,
+                bytes: 6000 + (100..110).sum::<u64>(),
+                uncompressed_bytes: 24000 + (100..110).sum::<u64>(),
+            }
+        );
+        let kinds = traffic.iter().map(|(kind, _)| kind).collect::<Vec<_>>();
+        assert_eq!(kinds, ["GraphRegions", "StateDelta"]);
+    }
+}
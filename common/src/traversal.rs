@@ -1,6 +1,6 @@
 use std::collections::VecDeque;
 
-use fxhash::FxHashSet;
+use fxhash::{FxHashMap, FxHashSet};
 
 use crate::{
     collision_math::Ray,
@@ -27,7 +27,7 @@ pub fn ensure_nearby(graph: &mut Graph, start: &Position, distance: f64) {
                 continue;
             }
             visited.insert(neighbor);
-            let neighbor_transform = current_transform * side.reflection();
+            let neighbor_transform = current_transform * side.reflection_f64();
             let neighbor_p = neighbor_transform * math::origin();
             if math::distance(&start_p, &neighbor_p) > distance {
                 continue;
@@ -77,7 +77,7 @@ pub fn nearby_nodes(
             }
             pending.push(PendingNode {
                 id: neighbor,
-                transform: current.transform * side.reflection(),
+                transform: current.transform * side.reflection_f64(),
             });
             visited.insert(neighbor);
         }
@@ -86,6 +86,259 @@ pub fn nearby_nodes(
     result
 }
 
+/// Distance from `start` to the origin of the nearest node within `distance` of it that's missing
+/// from `graph` but neighbors a node that isn't, if any
+pub fn nearest_missing_node(graph: &Graph, start: &Position, distance: f64) -> Option<f64> {
+    let start_p = start.local.map(|x| x as f64) * math::origin();
+    // Neighboring origins are never more than twice the bounding sphere radius apart
+    nearby_nodes(
+        graph,
+        start,
+        distance + 2.0 * dodeca::BOUNDING_SPHERE_RADIUS_F64,
+    )
+    .into_iter()
+    .flat_map(|(node, transform)| {
+        let transform = transform.cast::<f64>();
+        Side::iter()
+            .filter(move |&side| graph.neighbor(node, side).is_none())
+            .map(move |side| {
+                math::distance(
+                    &start_p,
+                    &(transform * side.reflection_f64() * math::origin()),
+                )
+            })
+    })
+    .filter(|&x| x <= distance)
+    .min_by(f64::total_cmp)
+}
+
+/// Compute `start.node`-relative transforms of all nodes whose origins lie within `distance` of
+/// `start`, as `nearby_nodes` does, but reusing transforms stored in `cache`
+///
+/// `cache` is rebased onto `start.node` first if necessary.
+pub fn nearby_nodes_cached(
+    graph: &Graph,
+    cache: &mut TransformCache,
+    start: &Position,
+    distance: f64,
+) -> Vec<(NodeId, na::Matrix4<f32>)> {
+    cache.set_reference(start.node);
+
+    let mut result = Vec::new();
+    if !graph.contains(start.node) {
+        return result;
+    }
+    let mut pending = vec![start.node];
+    let mut visited = FxHashSet::<NodeId>::default();
+    visited.insert(start.node);
+    let start_p = start.local.map(|x| x as f64) * math::origin();
+
+    while let Some(current) = pending.pop() {
+        let Some(entry) = cache.entry(graph, current) else {
+            continue;
+        };
+        if math::distance(&start_p, &entry.origin()) > distance {
+            continue;
+        }
+        result.push((current, entry.transform_f32));
+
+        for side in Side::iter() {
+            let Some(neighbor) = graph.neighbor(current, side) else {
+                continue;
+            };
+            if visited.insert(neighbor) {
+                pending.push(neighbor);
+            }
+        }
+    }
+
+    result
+}
+
+/// Transforms from the coordinates of arbitrary nodes into those of a reference node, computed on
+/// demand and retained while they remain within `RETAIN_DISTANCE` of the reference
+///
+/// Each transform is the product of the reflections along a short path from the reference to the
+/// node, found by breadth-first search or by extending the path to an already-known neighbor. When
+/// the reference changes to a node whose transform is known, such as a neighbor of the previous
+/// reference, every entry is rebased with a single matrix product rather than being recomputed.
+///
+/// Every product adds rounding error, so each entry tracks how many products it has accumulated
+/// since it was last exact or renormalized. Past `RENORMALIZE_DEPTH`, the entry is projected back
+/// onto the nearest isometry with `math::renormalize_isometry` and the count restarts. This keeps
+/// transforms of nodes far from the reference, and those that survived many rebases, as accurate
+/// as freshly computed ones.
+pub struct TransformCache {
+    reference: NodeId,
+    entries: FxHashMap<NodeId, CachedTransform>,
+    /// Cached nodes whose neighbors might not be cached yet, in the order they were cached
+    frontier: VecDeque<NodeId>,
+}
+
+struct CachedTransform {
+    transform: na::Matrix4<f64>,
+    transform_f32: na::Matrix4<f32>,
+    /// Number of matrix products accumulated into `transform` since it was last renormalized
+    depth: u32,
+}
+
+impl CachedTransform {
+    fn new(mut transform: na::Matrix4<f64>, mut depth: u32) -> Self {
+        if depth > TransformCache::RENORMALIZE_DEPTH {
+            transform = math::renormalize_isometry(&transform);
+            depth = 0;
+        }
+        Self {
+            transform,
+            transform_f32: na::convert(transform),
+            depth,
+        }
+    }
+
+    /// The node's origin in the reference node's coordinates
+    fn origin(&self) -> na::Vector4<f64> {
+        self.transform.column(3).into_owned()
+    }
+}
+
+impl TransformCache {
+    /// Number of accumulated products after which an entry is renormalized
+    pub const RENORMALIZE_DEPTH: u32 = 16;
+    /// Distance from the reference beyond which entries are discarded when the reference changes
+    pub const RETAIN_DISTANCE: f64 = 8.0;
+
+    pub fn new(reference: NodeId) -> Self {
+        let mut result = Self {
+            reference,
+            entries: FxHashMap::default(),
+            frontier: VecDeque::new(),
+        };
+        result.reset();
+        result
+    }
+
+    /// The node whose coordinates transforms map into
+    pub fn reference(&self) -> NodeId {
+        self.reference
+    }
+
+    /// Number of nodes with cached transforms
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Map into the coordinates of `reference` from now on
+    pub fn set_reference(&mut self, reference: NodeId) {
+        if reference == self.reference {
+            return;
+        }
+        self.reference = reference;
+        let Some(new_reference) = self.entries.get(&reference) else {
+            // Too far to be worth rebasing
+            self.reset();
+            return;
+        };
+        let rebase = math::mtranspose(&new_reference.transform);
+        let rebase_depth = new_reference.depth + 1;
+        for entry in self.entries.values_mut() {
+            *entry = CachedTransform::new(rebase * entry.transform, entry.depth + rebase_depth);
+        }
+        // Exactly, rather than approximately, the identity
+        self.entries
+            .insert(reference, CachedTransform::new(na::Matrix4::identity(), 0));
+
+        // Forget nodes left far behind, which would otherwise accumulate without bound
+        let max_cosh_distance = Self::RETAIN_DISTANCE.cosh();
+        self.entries
+            .retain(|_, entry| entry.origin().w <= max_cosh_distance);
+        let entries = &self.entries;
+        self.frontier.retain(|node| entries.contains_key(node));
+    }
+
+    /// Transform from `target`'s coordinates into the reference node's, or `None` if `target`
+    /// isn't in `graph`
+    pub fn transform_to(&mut self, graph: &Graph, target: NodeId) -> Option<&na::Matrix4<f32>> {
+        Some(&self.entry(graph, target)?.transform_f32)
+    }
+
+    fn entry(&mut self, graph: &Graph, target: NodeId) -> Option<&CachedTransform> {
+        if !self.entries.contains_key(&target) {
+            if !graph.contains(target) {
+                return None;
+            }
+            if !self.extend_from_neighbor(graph, target) && !self.search(graph, target) {
+                // Nodes added to the graph since their neighbors were explored can leave gaps
+                // that the search can't cross, so start over
+                self.reset();
+                if !self.search(graph, target) {
+                    return None;
+                }
+            }
+        }
+        self.entries.get(&target)
+    }
+
+    /// Compute the transform of `target` from a cached neighbor, if there is one
+    fn extend_from_neighbor(&mut self, graph: &Graph, target: NodeId) -> bool {
+        let Some((side, neighbor)) = Side::iter().find_map(|side| {
+            let neighbor = self.entries.get(&graph.neighbor(target, side)?)?;
+            Some((side, neighbor))
+        }) else {
+            return false;
+        };
+        let entry = CachedTransform::new(
+            neighbor.transform * side.reflection_f64(),
+            neighbor.depth + 1,
+        );
+        self.entries.insert(target, entry);
+        self.frontier.push_back(target);
+        true
+    }
+
+    /// Breadth-first search outwards from the cached nodes until `target` is cached
+    fn search(&mut self, graph: &Graph, target: NodeId) -> bool {
+        while let Some(&node) = self.frontier.front() {
+            let (transform, depth) = {
+                let entry = &self.entries[&node];
+                (entry.transform, entry.depth)
+            };
+            for side in Side::iter() {
+                let Some(neighbor) = graph.neighbor(node, side) else {
+                    continue;
+                };
+                if self.entries.contains_key(&neighbor) {
+                    continue;
+                }
+                self.entries.insert(
+                    neighbor,
+                    CachedTransform::new(transform * side.reflection_f64(), depth + 1),
+                );
+                self.frontier.push_back(neighbor);
+            }
+            self.frontier.pop_front();
+            if self.entries.contains_key(&target) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Forget everything but the reference
+    fn reset(&mut self) {
+        self.entries.clear();
+        self.frontier.clear();
+        self.entries.insert(
+            self.reference,
+            CachedTransform::new(na::Matrix4::identity(), 0),
+        );
+        self.frontier.push_back(self.reference);
+    }
+}
+
 pub struct RayTraverser<'a> {
     graph: &'a Graph,
     ray: &'a Ray,
@@ -107,7 +360,7 @@ impl<'a> RayTraverser<'a> {
         let mut closest_vertex_cosh_distance = f32::INFINITY;
         for vertex in Vertex::iter() {
             let vertex_cosh_distance =
-                (vertex.node_to_dual().cast::<f32>() * position.local * math::origin()).w;
+                (vertex.node_to_dual_f32() * position.local * math::origin()).w;
             if vertex_cosh_distance < closest_vertex_cosh_distance {
                 closest_vertex = vertex;
                 closest_vertex_cosh_distance = vertex_cosh_distance;
@@ -123,8 +376,7 @@ impl<'a> RayTraverser<'a> {
         // Precalculate the chunk boundaries for collision purposes. If the collider goes outside these bounds,
         // the corresponding neighboring chunk will also be used for collision checking.
         let klein_lower_boundary = radius.tanh();
-        let klein_upper_boundary =
-            ((Vertex::chunk_to_dual_factor() as f32).atanh() - radius).tanh();
+        let klein_upper_boundary = (Vertex::chunk_to_dual_factor_f32().atanh() - radius).tanh();
 
         Self {
             graph,
@@ -146,7 +398,7 @@ impl<'a> RayTraverser<'a> {
                 // Combine node and vertex, and convert node transform to chunk transform
                 return Some((
                     node.map(|node| ChunkId::new(node, vertex)),
-                    vertex.node_to_dual().cast::<f32>() * node_transform,
+                    vertex.node_to_dual_f32() * node_transform,
                 ));
             }
 
@@ -157,7 +409,7 @@ impl<'a> RayTraverser<'a> {
                 continue;
             };
 
-            let local_ray = vertex.node_to_dual().cast::<f32>() * node_transform * self.ray;
+            let local_ray = vertex.node_to_dual_f32() * node_transform * self.ray;
 
             // Compute the Klein-Beltrami coordinates of the ray segment's endpoints. To check whether neighboring chunks
             // are needed, we need to check whether the endpoints of the line segments lie outside the boundaries of the square
@@ -173,7 +425,7 @@ impl<'a> RayTraverser<'a> {
                     || klein_ray_end[axis] <= self.klein_lower_boundary
                 {
                     let side = vertex.canonical_sides()[axis];
-                    let next_node_transform = side.reflection().cast::<f32>() * node_transform;
+                    let next_node_transform = *side.reflection_f32() * node_transform;
                     // Crude check to ensure that the neighboring chunk's node can be in the path of the ray. For simplicity, this
                     // check treats each node as a sphere and assumes the ray is pointed directly towards its center. The check is
                     // needed because chunk generation uses this approximation, and this check is not guaranteed to pass near corners
@@ -181,7 +433,7 @@ impl<'a> RayTraverser<'a> {
                     let ray_node_distance = (next_node_transform * self.ray.position).w.acosh();
                     let ray_length = tanh_distance.atanh();
                     if ray_node_distance - ray_length - self.radius
-                        > dodeca::BOUNDING_SPHERE_RADIUS as f32
+                        > dodeca::BOUNDING_SPHERE_RADIUS_F32
                     {
                         // Ray cannot intersect node
                         continue;
@@ -216,3 +468,170 @@ impl<'a> RayTraverser<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::*;
+
+    fn graph(radius: f64) -> Graph {
+        let mut graph = Graph::new(1);
+        ensure_nearby(&mut graph, &Position::origin(), radius);
+        graph
+    }
+
+    /// Product of reflections along the path through the tree from `from` to `to`
+    fn path_product(graph: &Graph, from: NodeId, to: NodeId) -> na::Matrix4<f64> {
+        let to_root = |mut node| {
+            let mut transform = na::Matrix4::<f64>::identity();
+            while let Some(side) = graph.parent(node) {
+                transform = side.reflection_f64() * transform;
+                node = graph.neighbor(node, side).unwrap();
+            }
+            transform
+        };
+        math::mtranspose(&to_root(from)) * to_root(to)
+    }
+
+    #[test]
+    fn cached_matches_path_product() {
+        let graph = graph(3.0);
+        let mut cache = TransformCache::new(NodeId::ROOT);
+        let nodes = nearby_nodes(&graph, &Position::origin(), 3.0);
+        // In an order unrelated to the search
+        for &(node, _) in nodes.iter().rev() {
+            let expected = path_product(&graph, NodeId::ROOT, node).cast::<f32>();
+            assert_abs_diff_eq!(
+                *cache.transform_to(&graph, node).unwrap(),
+                expected,
+                epsilon = 1e-4
+            );
+        }
+        assert!(cache.len() >= nodes.len());
+
+        // Repeated queries see the same result
+        let (node, transform) = nodes[nodes.len() / 2];
+        assert_abs_diff_eq!(
+            *cache.transform_to(&graph, node).unwrap(),
+            transform,
+            epsilon = 1e-4
+        );
+
+        // Equivalent to the uncached traversal
+        let cached = nearby_nodes_cached(&graph, &mut cache, &Position::origin(), 3.0);
+        assert_eq!(cached.len(), nodes.len());
+        for (node, transform) in cached {
+            let (_, expected) = nodes.iter().find(|&&(x, _)| x == node).unwrap();
+            assert_abs_diff_eq!(transform, expected, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn rebase_matches_recomputation() {
+        let graph = graph(4.0);
+        let nodes = nearby_nodes(&graph, &Position::origin(), 3.0);
+        let mut cache = TransformCache::new(NodeId::ROOT);
+        for &(node, _) in &nodes {
+            cache.transform_to(&graph, node).unwrap();
+        }
+
+        // Wander away from the root, one neighbor at a time
+        let mut reference = NodeId::ROOT;
+        for side in [Side::A, Side::C, Side::F] {
+            reference = graph.neighbor(reference, side).unwrap();
+            cache.set_reference(reference);
+            let mut fresh = TransformCache::new(reference);
+            for &(node, _) in &nodes {
+                assert_abs_diff_eq!(
+                    *cache.transform_to(&graph, node).unwrap(),
+                    *fresh.transform_to(&graph, node).unwrap(),
+                    epsilon = 1e-3
+                );
+            }
+        }
+        assert_eq!(cache.reference(), reference);
+        assert_ne!(reference, NodeId::ROOT);
+    }
+
+    #[test]
+    fn entries_stay_isometries() {
+        let graph = graph(4.0);
+        let mut cache = TransformCache::new(NodeId::ROOT);
+        let far = nearby_nodes(&graph, &Position::origin(), 4.0)
+            .into_iter()
+            .max_by_key(|&(node, _)| graph.length(node))
+            .unwrap()
+            .0;
+        // Bounce the reference back and forth to accumulate products
+        let neighbor = graph.neighbor(NodeId::ROOT, Side::A).unwrap();
+        for _ in 0..100 {
+            cache.transform_to(&graph, far).unwrap();
+            cache.transform_to(&graph, neighbor).unwrap();
+            cache.set_reference(neighbor);
+            cache.transform_to(&graph, NodeId::ROOT).unwrap();
+            cache.set_reference(NodeId::ROOT);
+        }
+        let transform = cache.transform_to(&graph, far).unwrap().cast::<f64>();
+        assert_abs_diff_eq!(
+            math::mtranspose(&transform) * transform,
+            na::Matrix4::identity(),
+            epsilon = 1e-2
+        );
+        assert_abs_diff_eq!(
+            transform,
+            path_product(&graph, NodeId::ROOT, far),
+            epsilon = 1e-4 * transform.abs().max()
+        );
+    }
+
+    #[test]
+    fn nearest_missing() {
+        let graph = graph(2.0);
+        let start = Position::origin();
+        // Every node within the distance ensured is present
+        assert_eq!(nearest_missing_node(&graph, &start, 2.0), None);
+        let nearest = nearest_missing_node(&graph, &start, 10.0).unwrap();
+        assert!(nearest > 2.0);
+        assert_abs_diff_eq!(
+            nearest_missing_node(&graph, &start, nearest + 1e-3).unwrap(),
+            nearest,
+            epsilon = 1e-4
+        );
+        assert_eq!(nearest_missing_node(&graph, &start, nearest - 1e-3), None);
+
+        // Found the same from anywhere nearby
+        let side = Side::iter().next().unwrap();
+        let neighbor = Position {
+            node: graph.neighbor(NodeId::ROOT, side).unwrap(),
+            local: side.reflection_f32().into_owned(),
+        };
+        assert_abs_diff_eq!(
+            nearest_missing_node(&graph, &neighbor, 10.0).unwrap(),
+            nearest,
+            epsilon = 1e-3
+        );
+    }
+
+    #[test]
+    fn missing_nodes() {
+        let mut graph = graph(1.0);
+        let mut cache = TransformCache::new(NodeId::ROOT);
+        let nodes = nearby_nodes(&graph, &Position::origin(), 1.0);
+        for &(node, _) in &nodes {
+            cache.transform_to(&graph, node).unwrap();
+        }
+
+        // Nodes added later are found, even when not adjacent to any cached node
+        let mut node = NodeId::ROOT;
+        for _ in 0..4 {
+            node = graph.ensure_neighbor(node, Side::B);
+            node = graph.ensure_neighbor(node, Side::D);
+        }
+        let expected = path_product(&graph, NodeId::ROOT, node).cast::<f32>();
+        assert_abs_diff_eq!(
+            *cache.transform_to(&graph, node).unwrap(),
+            expected,
+            epsilon = 1e-3 * expected.abs().max()
+        );
+    }
+}
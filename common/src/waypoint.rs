@@ -0,0 +1,141 @@
+//! Named places players can find their way back to
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{graph::Graph, math, node_path::NodePath, proto::Position};
+
+/// Longest name a waypoint may have, in characters
+pub const MAX_NAME_LEN: usize = 32;
+
+/// A named place, shared with everyone on a server or kept by a single player
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Waypoint {
+    pub name: String,
+    /// Route to the node the waypoint lies in
+    pub path: NodePath,
+    /// Translation from the origin of that node to the waypoint, in absolute units
+    pub local_translation: na::Vector3<f32>,
+    /// sRGB color of the waypoint's marker
+    pub color: [u8; 3],
+    /// Name of the character that created the waypoint
+    pub owner: String,
+}
+
+impl Waypoint {
+    /// A waypoint at `position`
+    pub fn new(
+        graph: &Graph,
+        position: &Position,
+        name: String,
+        color: [u8; 3],
+        owner: String,
+    ) -> Self {
+        let point = math::lorentz_normalize(&(position.local * math::origin()));
+        let distance = math::distance(&math::origin(), &point);
+        let local_translation = point
+            .xyz()
+            .try_normalize(1e-16)
+            .map_or_else(na::Vector3::zeros, |direction| direction * distance);
+        Self {
+            name,
+            path: NodePath::to(graph, position.node),
+            local_translation,
+            color,
+            owner,
+        }
+    }
+
+    /// The waypoint in the coordinates of the node at the end of `path`
+    pub fn point(&self) -> na::Vector4<f32> {
+        math::translate_along(&self.local_translation) * math::origin()
+    }
+}
+
+/// Check that `name` is fit to name a waypoint
+///
+/// Names must be nonempty and at most `MAX_NAME_LEN` characters of ASCII letters, digits, spaces,
+/// hyphens, and underscores, so that they're legible in markers and unambiguous in commands.
+pub fn validate_name(name: &str) -> Result<(), InvalidName> {
+    if name.trim().is_empty() {
+        return Err(InvalidName::Empty);
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(InvalidName::TooLong);
+    }
+    if name.trim() != name {
+        return Err(InvalidName::Padded);
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|&c| !(c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_'))
+    {
+        return Err(InvalidName::BadCharacter(c));
+    }
+    Ok(())
+}
+
+/// Why a name can't be used for a waypoint
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvalidName {
+    Empty,
+    TooLong,
+    /// Starts or ends with a space
+    Padded,
+    BadCharacter(char),
+}
+
+impl fmt::Display for InvalidName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            InvalidName::Empty => f.pad("name is empty"),
+            InvalidName::TooLong => write!(f, "name is longer than {MAX_NAME_LEN} characters"),
+            InvalidName::Padded => f.pad("name starts or ends with a space"),
+            InvalidName::BadCharacter(c) => write!(f, "name contains {c:?}"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidName {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dodeca::Side, graph::NodeId};
+    use approx::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(validate_name("big cave"), Ok(()));
+        assert_eq!(validate_name("Base_2-east"), Ok(()));
+        assert_eq!(validate_name(""), Err(InvalidName::Empty));
+        assert_eq!(validate_name("   "), Err(InvalidName::Empty));
+        assert_eq!(validate_name(" base"), Err(InvalidName::Padded));
+        assert_eq!(
+            validate_name(&"x".repeat(MAX_NAME_LEN + 1)),
+            Err(InvalidName::TooLong)
+        );
+        assert_eq!(validate_name(&"x".repeat(MAX_NAME_LEN)), Ok(()));
+        assert_eq!(validate_name("café"), Err(InvalidName::BadCharacter('é')));
+        assert_eq!(validate_name("a\nb"), Err(InvalidName::BadCharacter('\n')));
+    }
+
+    #[test]
+    fn point_matches_position() {
+        let mut graph = Graph::new(4);
+        let node = graph.ensure_neighbor(NodeId::ROOT, Side::C);
+        let position = Position {
+            node,
+            local: math::translate_along(&na::Vector3::new(0.3, -0.1, 0.2))
+                * na::Rotation3::from_euler_angles(0.4, 0.0, 1.0).to_homogeneous(),
+        };
+        let waypoint = Waypoint::new(&graph, &position, "here".into(), [255, 0, 0], "a".into());
+        assert_eq!(waypoint.path.resolve(&graph), Some(node));
+        assert_abs_diff_eq!(
+            waypoint.point(),
+            position.local * math::origin(),
+            epsilon = 1e-5
+        );
+    }
+}
@@ -1,53 +1,698 @@
-use serde::{Deserialize, Serialize};
-
-#[derive(
-    Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
-)]
-#[repr(u16)]
-pub enum Material {
-    #[default]
-    Void = 0,
-    Dirt = 1,
-    Sand = 2,
-    Silt = 3,
-    Clay = 4,
-    Mud = 5,
-    SandyLoam = 6,
-    SiltyLoam = 7,
-    ClayLoam = 8,
-    RedSand = 9,
-    Limestone = 10,
-    Shale = 11,
-    Dolomite = 12,
-    Sandstone = 13,
-    RedSandstone = 14,
-    Marble = 15,
-    Slate = 16,
-    Granite = 17,
-    Diorite = 18,
-    Andesite = 19,
-    Gabbro = 20,
-    Basalt = 21,
-    Olivine = 22,
-    Water = 23,
-    Lava = 24,
-    Wood = 25,
-    Leaves = 26,
-    WoodPlanks = 27,
-    GreyBrick = 28,
-    WhiteBrick = 29,
-    Ice = 30,
-    IceSlush = 31,
-    Gravel = 32,
-    Snow = 33,
-    CoarseGrass = 34,
-    TanGrass = 35,
-    LushGrass = 36,
-    MudGrass = 37,
-    Grass = 38,
-    CaveGrass = 39,
+use std::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::node::{CoordAxis, CoordDirection};
+
+/// What a voxel is made of
+///
+/// Each material is identified by a number that's fixed once assigned, since it's how the material
+/// is stored in saves and sent over the network. Assignments are recorded in `materials.txt` beside
+/// this file, which tests hold the definitions below to. A newer server or save may use IDs this
+/// build doesn't know. Those are kept as they are rather than being rejected or rewritten, so they
+/// survive being passed along, and are drawn as a placeholder and treated as solid rock.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[repr(transparent)]
+pub struct Material(u16);
+
+#[allow(non_upper_case_globals)]
+impl Material {
+    pub const Void: Self = Self(0);
+    pub const Dirt: Self = Self(1);
+    pub const Sand: Self = Self(2);
+    pub const Silt: Self = Self(3);
+    pub const Clay: Self = Self(4);
+    pub const Mud: Self = Self(5);
+    pub const SandyLoam: Self = Self(6);
+    pub const SiltyLoam: Self = Self(7);
+    pub const ClayLoam: Self = Self(8);
+    pub const RedSand: Self = Self(9);
+    pub const Limestone: Self = Self(10);
+    pub const Shale: Self = Self(11);
+    pub const Dolomite: Self = Self(12);
+    pub const Sandstone: Self = Self(13);
+    pub const RedSandstone: Self = Self(14);
+    pub const Marble: Self = Self(15);
+    pub const Slate: Self = Self(16);
+    pub const Granite: Self = Self(17);
+    pub const Diorite: Self = Self(18);
+    pub const Andesite: Self = Self(19);
+    pub const Gabbro: Self = Self(20);
+    pub const Basalt: Self = Self(21);
+    pub const Olivine: Self = Self(22);
+    pub const Water: Self = Self(23);
+    pub const Lava: Self = Self(24);
+    pub const Wood: Self = Self(25);
+    pub const Leaves: Self = Self(26);
+    pub const WoodPlanks: Self = Self(27);
+    pub const GreyBrick: Self = Self(28);
+    pub const WhiteBrick: Self = Self(29);
+    pub const Ice: Self = Self(30);
+    pub const IceSlush: Self = Self(31);
+    pub const Gravel: Self = Self(32);
+    pub const Snow: Self = Self(33);
+    pub const CoarseGrass: Self = Self(34);
+    pub const TanGrass: Self = Self(35);
+    pub const LushGrass: Self = Self(36);
+    pub const MudGrass: Self = Self(37);
+    pub const Grass: Self = Self(38);
+    pub const CaveGrass: Self = Self(39);
+    pub const Sign: Self = Self(40);
+    pub const Ladder: Self = Self(41);
+    pub const SwitchOff: Self = Self(42);
+    pub const SwitchOn: Self = Self(43);
 }
 
 impl Material {
-    pub const COUNT: usize = 40;
+    /// Number of materials this build knows, whose IDs are those below it
+    pub const COUNT: usize = 44;
+
+    /// Every known material, indexed by its ID
+    pub const VALUES: [Self; Self::COUNT] = [
+        Material::Void,
+        Material::Dirt,
+        Material::Sand,
+        Material::Silt,
+        Material::Clay,
+        Material::Mud,
+        Material::SandyLoam,
+        Material::SiltyLoam,
+        Material::ClayLoam,
+        Material::RedSand,
+        Material::Limestone,
+        Material::Shale,
+        Material::Dolomite,
+        Material::Sandstone,
+        Material::RedSandstone,
+        Material::Marble,
+        Material::Slate,
+        Material::Granite,
+        Material::Diorite,
+        Material::Andesite,
+        Material::Gabbro,
+        Material::Basalt,
+        Material::Olivine,
+        Material::Water,
+        Material::Lava,
+        Material::Wood,
+        Material::Leaves,
+        Material::WoodPlanks,
+        Material::GreyBrick,
+        Material::WhiteBrick,
+        Material::Ice,
+        Material::IceSlush,
+        Material::Gravel,
+        Material::Snow,
+        Material::CoarseGrass,
+        Material::TanGrass,
+        Material::LushGrass,
+        Material::MudGrass,
+        Material::Grass,
+        Material::CaveGrass,
+        Material::Sign,
+        Material::Ladder,
+        Material::SwitchOff,
+        Material::SwitchOn,
+    ];
+
+    /// Name of every known material, indexed by its ID
+    const NAMES: [&'static str; Self::COUNT] = [
+        "Void",
+        "Dirt",
+        "Sand",
+        "Silt",
+        "Clay",
+        "Mud",
+        "SandyLoam",
+        "SiltyLoam",
+        "ClayLoam",
+        "RedSand",
+        "Limestone",
+        "Shale",
+        "Dolomite",
+        "Sandstone",
+        "RedSandstone",
+        "Marble",
+        "Slate",
+        "Granite",
+        "Diorite",
+        "Andesite",
+        "Gabbro",
+        "Basalt",
+        "Olivine",
+        "Water",
+        "Lava",
+        "Wood",
+        "Leaves",
+        "WoodPlanks",
+        "GreyBrick",
+        "WhiteBrick",
+        "Ice",
+        "IceSlush",
+        "Gravel",
+        "Snow",
+        "CoarseGrass",
+        "TanGrass",
+        "LushGrass",
+        "MudGrass",
+        "Grass",
+        "CaveGrass",
+        "Sign",
+        "Ladder",
+        "SwitchOff",
+        "SwitchOn",
+    ];
+
+    /// The material with ID `id`, whether or not it's known
+    pub const fn from_id(id: u16) -> Self {
+        Self(id)
+    }
+
+    /// The number identifying this material in saves and on the wire
+    pub const fn id(self) -> u16 {
+        self.0
+    }
+
+    /// Whether this build knows what the material is, rather than only carrying its ID
+    pub const fn is_known(self) -> bool {
+        (self.0 as usize) < Self::COUNT
+    }
+
+    /// Name of the material, if it's known
+    pub fn name(self) -> Option<&'static str> {
+        Self::NAMES.get(usize::from(self.0)).copied()
+    }
+
+    /// The known material called `name`, if any
+    pub fn from_name(name: &str) -> Option<Self> {
+        let id = Self::NAMES.iter().position(|&x| x == name)?;
+        Some(Self::VALUES[id])
+    }
+
+    /// Whether what lies behind this material can be seen through it
+    ///
+    /// Transparent materials are drawn after everything else, blended over what's behind them.
+    pub const fn is_transparent(self) -> bool {
+        matches!(self, Material::Water | Material::Leaves | Material::Ice)
+    }
+
+    /// Whether the face a voxel of this material shares with a voxel of `neighbor` should be drawn
+    ///
+    /// Faces hidden behind opaque voxels, or between voxels of the same material, are omitted.
+    pub fn shows_face_to(self, neighbor: Material) -> bool {
+        self != Material::Void
+            && self != neighbor
+            && (neighbor == Material::Void || neighbor.is_transparent())
+    }
+
+    /// Whether characters can climb walls of this material
+    pub const fn is_climbable(self) -> bool {
+        matches!(self, Material::Ladder)
+    }
+
+    /// Fraction of the usual fall damage dealt to a character landing on this material
+    ///
+    /// Soft materials cushion a fall, and water absorbs it entirely.
+    pub const fn landing_damage_factor(self) -> f32 {
+        match self {
+            Material::Water => 0.0,
+            Material::Snow | Material::Leaves | Material::Mud | Material::IceSlush => 0.5,
+            Material::Sand | Material::RedSand | Material::Silt | Material::MudGrass => 0.75,
+            _ => 1.0,
+        }
+    }
+
+    /// Steepest slope of this material a character can stand on, as rise over run, if it differs
+    /// from the configured `max_ground_slope`
+    ///
+    /// Slippery and loose materials give way sooner than rock.
+    pub const fn max_ground_slope(self) -> Option<f32> {
+        match self {
+            Material::Ice => Some(0.47),                      // 25 degrees
+            Material::IceSlush | Material::Mud => Some(0.58), // 30 degrees
+            Material::Sand | Material::RedSand => Some(0.7),  // 35 degrees
+            Material::Gravel | Material::Snow => Some(0.84),  // 40 degrees
+            _ => None,
+        }
+    }
+
+    /// Slope of this material, as rise over run, beyond which characters standing on it slide
+    /// down, if any
+    ///
+    /// Should be less than `max_ground_slope`, past which the material is a wall rather than ground.
+    pub const fn slide_slope(self) -> Option<f32> {
+        match self {
+            Material::Ice => Some(0.05),      // 3 degrees
+            Material::IceSlush => Some(0.18), // 10 degrees
+            Material::Mud => Some(0.27),      // 15 degrees
+            Material::Sand | Material::RedSand | Material::Snow => Some(0.47), // 25 degrees
+            Material::Gravel => Some(0.58),   // 30 degrees
+            _ => None,
+        }
+    }
+
+    /// What a block of this material becomes when a character uses it, if it responds to use
+    pub const fn toggled(self) -> Option<Material> {
+        match self {
+            Material::SwitchOff => Some(Material::SwitchOn),
+            Material::SwitchOn => Some(Material::SwitchOff),
+            _ => None,
+        }
+    }
+}
+
+// Known materials are exactly those whose IDs are below `COUNT`
+const _: () = {
+    let mut id = 0;
+    while id < Material::COUNT {
+        assert!(Material::VALUES[id].0 as usize == id);
+        id += 1;
+    }
+};
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::Void
+    }
+}
+
+impl fmt::Debug for Material {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "Unknown({})", self.0),
+        }
+    }
+}
+
+/// Hash of the IDs and names of the first `count` known materials
+///
+/// Equal hashes mean two builds agree on what those IDs stand for.
+pub fn material_registry_hash(count: usize) -> u64 {
+    // FNV-1a, which unlike the standard library's hashers is fixed across builds
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for (id, name) in Material::NAMES.iter().take(count).enumerate() {
+        for &byte in format!("{id} {name}\n").as_bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+// Materials are encoded as the variants of an enum would be, by ID in compact formats and by name
+// in readable ones, so that IDs this build doesn't know are carried through unchanged.
+impl Serialize for Material {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.name() {
+            Some(name) if serializer.is_human_readable() => serializer.serialize_str(name),
+            None if serializer.is_human_readable() => serializer.serialize_u16(self.0),
+            name => serializer.serialize_unit_variant(
+                "Material",
+                self.0.into(),
+                name.unwrap_or("Unknown"),
+            ),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Material {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(MaterialVisitor)
+        } else {
+            deserializer.deserialize_enum("Material", &Material::NAMES, MaterialVisitor)
+        }
+    }
+}
+
+/// Accepts a material's name or ID, alone or as the variant of an enum
+struct MaterialVisitor;
+
+impl<'de> de::Visitor<'de> for MaterialVisitor {
+    type Value = Material;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a material name or ID")
+    }
+
+    fn visit_u64<E: de::Error>(self, id: u64) -> Result<Material, E> {
+        u16::try_from(id)
+            .map(Material)
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(id), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<Material, E> {
+        // Unknown materials used as map keys are written as their IDs in strings
+        Material::from_name(name)
+            .or_else(|| name.parse().ok().map(Material))
+            .ok_or_else(|| E::unknown_variant(name, &Material::NAMES))
+    }
+
+    fn visit_enum<A: de::EnumAccess<'de>>(self, data: A) -> Result<Material, A::Error> {
+        let (material, variant) = data.variant_seed(self)?;
+        de::VariantAccess::unit_variant(variant)?;
+        Ok(material)
+    }
+}
+
+impl<'de> de::DeserializeSeed<'de> for MaterialVisitor {
+    type Value = Material;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Material, D::Error> {
+        deserializer.deserialize_identifier(self)
+    }
+}
+
+/// The part of a voxel its material fills, as a set of the eight octants the voxel divides into
+///
+/// Bit `x | y << 1 | z << 2` is set if the octant in the lower (0) or upper (1) half of the voxel
+/// along each axis is filled. Every shape is a full cube, a slab filling the half of the voxel
+/// against one face, or stairs, which fill a slab along with one of the two quarters of the voxel
+/// above it. Shapes are given in the coordinates of the voxel's chunk, since chunks have no fixed
+/// up direction.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct Shape(u8);
+
+impl Shape {
+    pub const FULL: Self = Shape(0xFF);
+
+    /// Every octant of a voxel, in order of their bits
+    pub const OCTANTS: [[u8; 3]; 8] = [
+        [0, 0, 0],
+        [1, 0, 0],
+        [0, 1, 0],
+        [1, 1, 0],
+        [0, 0, 1],
+        [1, 0, 1],
+        [0, 1, 1],
+        [1, 1, 1],
+    ];
+
+    /// The half of a voxel against its face on the `direction` side of `axis`
+    pub fn slab(axis: CoordAxis, direction: CoordDirection) -> Self {
+        let half = u8::from(direction == CoordDirection::Plus);
+        Self::from_fn(|octant| octant[axis as usize] == half).unwrap()
+    }
+
+    /// Stairs climbing towards `back`, filling the half of a voxel against its face opposite `up`
+    /// and the quarter above that against its face on the `back` side
+    pub fn stairs(up: (CoordAxis, CoordDirection), back: (CoordAxis, CoordDirection)) -> Self {
+        assert_ne!(up.0, back.0, "stairs must climb across their up axis");
+        let bottom = Self::slab(up.0, opposite(up.1)).0;
+        let back = Self::slab(back.0, back.1).0;
+        Shape(bottom | back)
+    }
+
+    /// The shape filling the octants for which `filled` is true, if that's a valid shape
+    pub(crate) fn from_fn(filled: impl Fn([u8; 3]) -> bool) -> Option<Self> {
+        let mut bits = 0;
+        for (i, &octant) in Self::OCTANTS.iter().enumerate() {
+            if filled(octant) {
+                bits |= 1 << i;
+            }
+        }
+        Self::try_from(bits).ok()
+    }
+
+    /// Bit set of the octants filled, as described for `Shape`
+    pub fn octants(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_full(self) -> bool {
+        self == Self::FULL
+    }
+
+    /// Whether the octant with coordinates `octant`, each 0 or 1, is filled
+    pub fn contains(self, octant: [u8; 3]) -> bool {
+        self.0 & (1 << (octant[0] | octant[1] << 1 | octant[2] << 2)) != 0
+    }
+
+    /// Whether the point at `offset` within the voxel, in [0, 1) along each axis, is filled
+    pub fn contains_point(self, offset: &na::Vector3<f32>) -> bool {
+        self.contains([0, 1, 2].map(|axis| u8::from(offset[axis] >= 0.5)))
+    }
+
+    /// Whether the voxel's face on the `direction` side of `axis` is entirely filled
+    pub fn covers_face(self, axis: CoordAxis, direction: CoordDirection) -> bool {
+        let side = Self::slab(axis, direction).0;
+        self.0 & side == side
+    }
+
+    /// Divide the shape into at most two disjoint boxes, each given by its minimum and maximum
+    /// corners in units of half a voxel
+    pub fn boxes(self) -> impl Iterator<Item = [[u8; 3]; 2]> {
+        // Full cubes and slabs are boxes already. Stairs are a slab and the quarter above it.
+        let slab = if self.0.count_ones() == 6 {
+            (0..3)
+                .flat_map(|axis| [(axis, 0), (axis, 1)])
+                .map(|(axis, half)| Self::from_fn(|octant| octant[axis] == half).unwrap().0)
+                .find(|&slab| self.0 & slab == slab)
+                .unwrap()
+        } else {
+            self.0
+        };
+        [slab, self.0 & !slab]
+            .into_iter()
+            .filter(|&part| part != 0)
+            .map(bounds)
+    }
+}
+
+fn opposite(direction: CoordDirection) -> CoordDirection {
+    match direction {
+        CoordDirection::Plus => CoordDirection::Minus,
+        CoordDirection::Minus => CoordDirection::Plus,
+    }
+}
+
+/// Corners of the box enclosing the octants in `bits`, in units of half a voxel
+fn bounds(bits: u8) -> [[u8; 3]; 2] {
+    let mut result = [[2; 3], [0; 3]];
+    for (i, octant) in Shape::OCTANTS.iter().enumerate() {
+        if bits & (1 << i) == 0 {
+            continue;
+        }
+        for axis in 0..3 {
+            result[0][axis] = result[0][axis].min(octant[axis]);
+            result[1][axis] = result[1][axis].max(octant[axis] + 1);
+        }
+    }
+    result
+}
+
+impl Default for Shape {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+impl TryFrom<u8> for Shape {
+    type Error = InvalidShape;
+
+    fn try_from(bits: u8) -> Result<Self, Self::Error> {
+        let missing = !bits;
+        let missing_octants = || {
+            Self::OCTANTS
+                .iter()
+                .enumerate()
+                .filter(move |&(i, _)| missing & (1 << i) != 0)
+                .map(|(_, &octant)| octant)
+        };
+        // Missing octants all lie in one half for slabs, and in one quarter for stairs
+        let shared_axes = (0..3)
+            .filter(|&axis| {
+                let mut halves = missing_octants().map(|octant| octant[axis]);
+                let first = halves.next();
+                halves.all(|half| Some(half) == first)
+            })
+            .count();
+        let valid = match missing.count_ones() {
+            0 => true,
+            4 => shared_axes == 1,
+            2 => shared_axes == 2,
+            _ => false,
+        };
+        if valid {
+            Ok(Shape(bits))
+        } else {
+            Err(InvalidShape(bits))
+        }
+    }
+}
+
+impl From<Shape> for u8 {
+    fn from(shape: Shape) -> Self {
+        shape.0
+    }
+}
+
+/// A set of octants that isn't a full cube, slab, or stairs
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct InvalidShape(pub u8);
+
+impl fmt::Display for InvalidShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010b} is not a valid voxel shape", self.0)
+    }
+}
+
+impl std::error::Error for InvalidShape {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A material from a newer build
+    const UNKNOWN: Material = Material::from_id(Material::COUNT as u16 + 7);
+
+    #[test]
+    fn ids_match_registry() {
+        let mut ids = std::collections::HashSet::new();
+        let mut names = std::collections::HashSet::new();
+        for line in include_str!("materials.txt").lines() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (id, name) = line.split_once(' ').unwrap();
+            let id = id.parse::<u16>().unwrap();
+            assert!(ids.insert(id), "ID {id} registered twice");
+            assert!(names.insert(name), "{name} registered twice");
+            // Registered IDs must keep their meaning
+            assert_eq!(
+                Material::from_id(id).name(),
+                Some(name),
+                "material {id} was registered as {name}"
+            );
+        }
+        for material in Material::VALUES {
+            assert!(
+                ids.contains(&material.id()),
+                "{material:?} is missing from materials.txt"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_materials_kept() {
+        assert!(!UNKNOWN.is_known());
+        assert_eq!(UNKNOWN.name(), None);
+        assert_eq!(
+            format!("{UNKNOWN:?}"),
+            format!("Unknown({})", Material::COUNT + 7)
+        );
+        assert_eq!(format!("{:?}", Material::Dirt), "Dirt");
+        assert!(!UNKNOWN.is_transparent());
+        assert!(UNKNOWN.shows_face_to(Material::Void));
+        assert_eq!(UNKNOWN.landing_damage_factor(), 1.0);
+        assert_eq!(UNKNOWN.max_ground_slope(), None);
+
+        for material in [Material::Void, Material::Sign, UNKNOWN] {
+            let encoded = bincode::serialize(&material).unwrap();
+            assert_eq!(
+                bincode::deserialize::<Material>(&encoded).unwrap(),
+                material
+            );
+            let json = serde_json::to_string(&material).unwrap();
+            assert_eq!(serde_json::from_str::<Material>(&json).unwrap(), material);
+        }
+        assert_eq!(serde_json::to_string(&Material::Dirt).unwrap(), "\"Dirt\"");
+        let counts = std::collections::BTreeMap::from([(Material::Dirt, 2), (UNKNOWN, 3)]);
+        let json = serde_json::to_string(&counts).unwrap();
+        assert_eq!(
+            serde_json::from_str::<std::collections::BTreeMap<_, _>>(&json).unwrap(),
+            counts
+        );
+        assert!(serde_json::from_str::<Material>("\"Mithril\"").is_err());
+    }
+
+    #[test]
+    fn encoded_like_enum() {
+        // How materials were encoded when `Material` was a plain enum, which saves and older peers
+        // still expect
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum Old {
+            Void,
+            Dirt,
+            Sand,
+        }
+        for (old, new) in [(Old::Dirt, Material::Dirt), (Old::Sand, Material::Sand)] {
+            let encoded = bincode::serialize(&old).unwrap();
+            assert_eq!(bincode::serialize(&new).unwrap(), encoded);
+            assert_eq!(bincode::deserialize::<Material>(&encoded).unwrap(), new);
+            let json = serde_json::to_string(&old).unwrap();
+            assert_eq!(serde_json::to_string(&new).unwrap(), json);
+            assert_eq!(serde_json::from_str::<Old>(&json).unwrap(), old);
+        }
+        // Older builds reject what they don't know, rather than misreading it
+        let encoded = bincode::serialize(&UNKNOWN).unwrap();
+        assert!(bincode::deserialize::<Old>(&encoded).is_err());
+    }
+
+    #[test]
+    fn registry_hash() {
+        let all = material_registry_hash(Material::COUNT);
+        assert_eq!(all, material_registry_hash(Material::COUNT));
+        assert_ne!(all, material_registry_hash(Material::COUNT - 1));
+        assert_eq!(material_registry_hash(Material::COUNT + 1), all);
+    }
+
+    #[test]
+    fn face_visibility() {
+        let (void, dirt, sand) = (Material::Void, Material::Dirt, Material::Sand);
+        let (ice, water) = (Material::Ice, Material::Water);
+        assert!(!void.shows_face_to(dirt));
+        assert!(dirt.shows_face_to(void));
+        assert!(!dirt.shows_face_to(sand));
+        // Opaque materials are seen through transparent ones, but not the reverse
+        assert!(dirt.shows_face_to(ice));
+        assert!(!ice.shows_face_to(dirt));
+        // Transparent materials merge with themselves and are seen through each other
+        assert!(!ice.shows_face_to(ice));
+        assert!(ice.shows_face_to(water));
+        assert!(water.shows_face_to(ice));
+    }
+
+    #[test]
+    fn shapes() {
+        use CoordAxis::*;
+        use CoordDirection::*;
+        let valid = (0..=u8::MAX)
+            .filter(|&bits| Shape::try_from(bits).is_ok())
+            .count();
+        // A full cube, six slabs, and stairs along each of the twelve edges of the cube
+        assert_eq!(valid, 1 + 6 + 12);
+
+        let bottom = Shape::slab(Y, Minus);
+        assert_eq!(bottom.octants(), 0b0011_0011);
+        assert!(bottom.covers_face(Y, Minus));
+        assert!(!bottom.covers_face(Y, Plus));
+        assert!(!bottom.covers_face(X, Plus));
+        assert!(bottom.contains_point(&na::Vector3::new(0.9, 0.2, 0.5)));
+        assert!(!bottom.contains_point(&na::Vector3::new(0.9, 0.7, 0.5)));
+        assert_eq!(bottom.boxes().collect::<Vec<_>>(), [[[0, 0, 0], [2, 1, 2]]]);
+
+        let stairs = Shape::stairs((Y, Plus), (Z, Plus));
+        assert_eq!(stairs.octants().count_ones(), 6);
+        assert!(stairs.covers_face(Z, Plus));
+        assert!(!stairs.covers_face(Z, Minus));
+        assert!(stairs.contains([0, 1, 1]));
+        assert!(!stairs.contains([0, 1, 0]));
+        // Climbing towards +Z is the same shape as climbing towards -Y with +Z as up
+        assert_eq!(stairs, Shape::stairs((Z, Minus), (Y, Minus)));
+        assert_eq!(
+            stairs.boxes().collect::<Vec<_>>(),
+            [[[0, 0, 0], [2, 1, 2]], [[0, 1, 1], [2, 2, 2]]]
+        );
+        assert_eq!(
+            Shape::FULL.boxes().collect::<Vec<_>>(),
+            [[[0, 0, 0], [2, 2, 2]]]
+        );
+
+        assert_eq!(u8::from(stairs), stairs.octants());
+        assert_eq!(Shape::try_from(0b0101_0110), Err(InvalidShape(0b0101_0110)));
+        assert_eq!(Shape::try_from(0), Err(InvalidShape(0)));
+    }
 }
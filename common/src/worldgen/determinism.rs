@@ -0,0 +1,368 @@
+//! Checking that this build generates chunks exactly as a canonical build does
+//!
+//! Clients and servers generate chunks independently, so builds whose floating-point arithmetic
+//! differs, such as debug and release builds, can disagree about the terrain without either
+//! noticing. A sample of chunks generated by a canonical build is committed as `golden.json`, with
+//! a fingerprint and the materials of each. Regenerating them and comparing reveals any
+//! disagreement, and where a chunk differs, the quantities computed on the way to the first
+//! differing voxel show which arithmetic is to blame.
+//!
+//! The golden chunks are recorded with:
+//!
+//! ```text
+//! cargo run -p common --release --example worldgen_golden > common/src/worldgen/golden.json
+//! ```
+//!
+//! The world has no seed, so the golden chunks apply to any world generated with the same chunk
+//! dimension and passes.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ChunkParams, TerrainIntermediates, TerrainPassKind, VoxelCoords};
+use crate::{
+    dodeca::Vertex,
+    graph::Graph,
+    node::{populate_fresh_nodes, ChunkId, Coords, VoxelData},
+    node_path::NodePath,
+    proto::Position,
+    traversal::{ensure_nearby, nearby_nodes},
+    world::Material,
+};
+
+/// Most chunks recorded in a sample
+pub const SAMPLE_SIZE: usize = 32;
+
+/// Distance from the origin within which sampled chunks' nodes lie
+const SAMPLE_RADIUS: f64 = 1.5;
+
+/// Chunks generated by a canonical build, to compare this one against
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Golden {
+    /// Number of voxels along an edge of each chunk
+    pub dimension: u8,
+    /// Passes the chunks were generated with
+    pub passes: Vec<TerrainPassKind>,
+    pub chunks: Vec<GoldenChunk>,
+}
+
+/// A chunk generated by a canonical build
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoldenChunk {
+    pub path: NodePath,
+    pub vertex: Vertex,
+    pub fingerprint: u64,
+    /// Runs of each material, in the order `ChunkGenContext::voxels` visits them
+    pub materials: Vec<(Material, u32)>,
+}
+
+impl Golden {
+    /// The golden chunks committed alongside this module
+    pub fn committed() -> Self {
+        serde_json::from_str(include_str!("golden.json")).expect("malformed golden.json")
+    }
+
+    /// Generate a representative sample of up to `SAMPLE_SIZE` chunks near the origin, favoring
+    /// those that aren't all one material
+    pub fn record(dimension: u8, passes: &[TerrainPassKind]) -> Self {
+        let mut graph = Graph::new(dimension);
+        ensure_nearby(&mut graph, &Position::origin(), SAMPLE_RADIUS + 1.5);
+        populate_fresh_nodes(&mut graph);
+        let mut chunks = nearby_nodes(&graph, &Position::origin(), SAMPLE_RADIUS)
+            .into_iter()
+            .flat_map(|(node, _)| Vertex::iter().map(move |vertex| ChunkId::new(node, vertex)))
+            .filter_map(|chunk| {
+                let params = ChunkParams::new(dimension, &graph, chunk, passes)?;
+                let voxels = params.generate_voxels();
+                Some(GoldenChunk {
+                    path: NodePath::to(&graph, chunk.node),
+                    vertex: chunk.vertex,
+                    fingerprint: fingerprint(&voxels, dimension),
+                    materials: runs(&voxels, dimension),
+                })
+            })
+            .collect::<Vec<_>>();
+        chunks.sort_by(|a, b| (&a.path, a.vertex as u8).cmp(&(&b.path, b.vertex as u8)));
+        // Uniform chunks are cheap to generate and exercise little of the arithmetic
+        let (mut sample, uniform): (Vec<_>, Vec<_>) =
+            chunks.into_iter().partition(|x| x.materials.len() > 1);
+        sample = spread(sample, SAMPLE_SIZE - SAMPLE_SIZE / 8);
+        sample.extend(spread(uniform, SAMPLE_SIZE - sample.len()));
+        Self {
+            dimension,
+            passes: passes.to_vec(),
+            chunks: sample,
+        }
+    }
+
+    /// Whether these chunks are what a world generated with `passes` in chunks of `dimension`
+    /// would contain
+    pub fn applies_to(&self, dimension: u8, passes: &[TerrainPassKind]) -> bool {
+        self.dimension == dimension && self.passes == passes
+    }
+
+    /// Regenerate each chunk with `generate`, reporting those that differ
+    pub fn verify(&self, generate: impl Fn(&ChunkParams) -> VoxelData) -> DeterminismReport {
+        let mut graph = Graph::new(self.dimension);
+        let mut divergences = Vec::new();
+        for golden in &self.chunks {
+            let node = golden.path.ensure(&mut graph);
+            // Chunks are generated from the states of the nodes around them
+            for (_, path) in golden.vertex.dual_vertices() {
+                path.fold(node, |node, side| graph.ensure_neighbor(node, side));
+            }
+            populate_fresh_nodes(&mut graph);
+            let params = ChunkParams::new(
+                self.dimension,
+                &graph,
+                ChunkId::new(node, golden.vertex),
+                &self.passes,
+            )
+            .expect("surrounding nodes are populated");
+            let voxels = generate(&params);
+            let actual = fingerprint(&voxels, self.dimension);
+            if actual == golden.fingerprint {
+                continue;
+            }
+            let first = first_difference(&golden.materials, &voxels, self.dimension).map(
+                |(coords, expected, actual)| VoxelDivergence {
+                    coords,
+                    expected,
+                    actual,
+                    intermediates: params.terrain_intermediates(coords),
+                },
+            );
+            divergences.push(Divergence {
+                path: golden.path.clone(),
+                vertex: golden.vertex,
+                expected: golden.fingerprint,
+                actual,
+                first,
+            });
+        }
+        DeterminismReport {
+            checked: self.chunks.len(),
+            divergences,
+        }
+    }
+}
+
+/// Regenerate the committed golden chunks as this build does, reporting those that differ
+pub fn verify_worldgen_determinism() -> DeterminismReport {
+    Golden::committed().verify(ChunkParams::generate_voxels)
+}
+
+/// The outcome of comparing chunks generated by this build against golden chunks
+#[derive(Debug, Clone)]
+pub struct DeterminismReport {
+    /// Number of chunks compared
+    pub checked: usize,
+    /// Chunks that differ, in the order they were compared
+    pub divergences: Vec<Divergence>,
+}
+
+impl DeterminismReport {
+    /// Whether every chunk compared was generated exactly as the canonical build did
+    pub fn is_deterministic(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// A chunk generated differently than by the canonical build
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub path: NodePath,
+    pub vertex: Vertex,
+    /// Fingerprint of the chunk as generated by the canonical build
+    pub expected: u64,
+    /// Fingerprint of the chunk as generated by this build
+    pub actual: u64,
+    /// The first voxel that differs, or `None` if the golden chunk's materials don't match its
+    /// own fingerprint
+    pub first: Option<VoxelDivergence>,
+}
+
+/// A voxel generated differently than by the canonical build
+#[derive(Debug, Clone)]
+pub struct VoxelDivergence {
+    pub coords: Coords,
+    pub expected: Material,
+    pub actual: Material,
+    /// The quantities this build computes on the way to the voxel's natural terrain
+    pub intermediates: TerrainIntermediates,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "chunk {:?} of node {:?} has fingerprint {:016x} rather than {:016x}",
+            self.vertex, self.path.0, self.actual, self.expected
+        )?;
+        match self.first {
+            None => write!(f, ", though its golden materials match"),
+            Some(ref x) => write!(
+                f,
+                "; voxel {:?} is {:?} rather than {:?}, from {:?}",
+                x.coords.0, x.actual, x.expected, x.intermediates
+            ),
+        }
+    }
+}
+
+/// Hash of the materials of `voxels`, however they're represented
+pub fn fingerprint(voxels: &VoxelData, dimension: u8) -> u64 {
+    let mut hasher = blake3::Hasher::new();
+    for (material, count) in runs(voxels, dimension) {
+        for _ in 0..count {
+            hasher.update(&material.id().to_le_bytes());
+        }
+    }
+    u64::from_le_bytes(hasher.finalize().as_bytes()[..8].try_into().unwrap())
+}
+
+/// The materials of `voxels` in the order `ChunkGenContext::voxels` visits them, as runs of each
+fn runs(voxels: &VoxelData, dimension: u8) -> Vec<(Material, u32)> {
+    let view = voxels.view(dimension);
+    let mut runs = Vec::<(Material, u32)>::new();
+    for (x, y, z) in VoxelCoords::new(dimension) {
+        let material = view.get(Coords([x, y, z]));
+        match runs.last_mut() {
+            Some((last, count)) if *last == material => *count += 1,
+            _ => runs.push((material, 1)),
+        }
+    }
+    runs
+}
+
+/// The first voxel of `voxels` whose material differs from `golden`, with the golden material
+/// and the actual one
+fn first_difference(
+    golden: &[(Material, u32)],
+    voxels: &VoxelData,
+    dimension: u8,
+) -> Option<(Coords, Material, Material)> {
+    let view = voxels.view(dimension);
+    let expected = golden
+        .iter()
+        .flat_map(|&(material, count)| (0..count).map(move |_| material));
+    VoxelCoords::new(dimension)
+        .map(|(x, y, z)| Coords([x, y, z]))
+        .zip(expected)
+        .map(|(coords, expected)| (coords, expected, view.get(coords)))
+        .find(|&(_, expected, actual)| expected != actual)
+}
+
+/// Up to `count` of `items`, evenly spaced
+fn spread<T>(items: Vec<T>, count: usize) -> Vec<T> {
+    let len = items.len();
+    if len <= count {
+        return items;
+    }
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|&(i, _)| i * count / len != (i + 1) * count / len)
+        .map(|(_, item)| item)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worldgen::WorldgenPath;
+
+    const DIMENSION: u8 = 12;
+
+    fn canonical() -> Golden {
+        Golden::record(DIMENSION, &TerrainPassKind::DEFAULT)
+    }
+
+    #[test]
+    fn committed_golden_chunks_match() {
+        // An empty sample would check nothing
+        assert_eq!(
+            Golden::committed().chunks.len(),
+            SAMPLE_SIZE,
+            "golden.json should hold a full sample, as recorded by the worldgen_golden example"
+        );
+        let report = verify_worldgen_determinism();
+        assert_eq!(report.checked, SAMPLE_SIZE);
+        for divergence in &report.divergences {
+            eprintln!("{divergence}");
+        }
+        assert!(report.is_deterministic());
+    }
+
+    #[test]
+    fn recorded_chunks_match() {
+        let golden = canonical();
+        assert_eq!(golden.chunks.len(), SAMPLE_SIZE);
+        assert!(golden.chunks.iter().any(|x| x.materials.len() > 1));
+        // As committed
+        let golden: Golden =
+            serde_json::from_str(&serde_json::to_string(&golden).unwrap()).unwrap();
+        let report = golden.verify(ChunkParams::generate_voxels);
+        assert_eq!(report.checked, SAMPLE_SIZE);
+        assert!(report.is_deterministic());
+
+        // Every evaluation path agrees with the golden chunks too
+        for path in [WorldgenPath::Scalar, WorldgenPath::Wide] {
+            assert!(golden
+                .verify(|params| params.generate_voxels_on(path))
+                .is_deterministic());
+        }
+    }
+
+    #[test]
+    fn perturbation_located() {
+        let golden = canonical();
+        let target = golden
+            .chunks
+            .iter()
+            .find(|x| x.materials.len() > 1)
+            .unwrap();
+        let (path, vertex) = (target.path.clone(), target.vertex);
+        // Two voxels changed, so the earlier must be reported
+        let early = Coords([2, 7, 5]);
+        let late = Coords([9, 1, 3]);
+        // Every chunk against the same vertex is perturbed alike, including the target
+        let report = golden.verify(|params| {
+            let mut voxels = params.generate_voxels();
+            if params.chunk() == vertex {
+                for coords in [late, early] {
+                    let data = voxels.data_mut(DIMENSION);
+                    let i = coords.to_index(DIMENSION);
+                    data[i] = if data[i] == Material::Void {
+                        Material::Gravel
+                    } else {
+                        Material::Void
+                    };
+                }
+            }
+            voxels
+        });
+        let divergence = report
+            .divergences
+            .iter()
+            .find(|x| x.path == path && x.vertex == vertex)
+            .unwrap();
+        let golden_material = target
+            .materials
+            .iter()
+            .flat_map(|&(material, count)| (0..count).map(move |_| material))
+            .nth(
+                VoxelCoords::new(DIMENSION)
+                    .position(|(x, y, z)| Coords([x, y, z]) == early)
+                    .unwrap(),
+            )
+            .unwrap();
+        let first = divergence.first.as_ref().unwrap();
+        assert_eq!(first.coords, early);
+        assert_eq!(first.expected, golden_material);
+        assert_ne!(first.actual, golden_material);
+        assert_eq!(divergence.expected, target.fingerprint);
+        assert_ne!(divergence.actual, target.fingerprint);
+    }
+}
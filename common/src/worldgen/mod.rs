@@ -0,0 +1,1320 @@
+pub mod determinism;
+pub mod stats;
+mod wide;
+
+pub use wide::WorldgenPath;
+
+use std::cell::{RefCell, RefMut};
+
+use rand::{distributions::Uniform, Rng};
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dodeca::{Side, Vertex},
+    graph::{Graph, NodeId},
+    math,
+    node::{ChunkId, Coords, VoxelData},
+    rng::{Pcg, RngPurpose},
+    terraingen::VoronoiInfo,
+    world::Material,
+    Plane,
+};
+use wide::TerrainBuffers;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum NodeStateKind {
+    Sky,
+    DeepSky,
+    Land,
+    DeepLand,
+}
+use NodeStateKind::*;
+
+impl NodeStateKind {
+    const ROOT: Self = Land;
+
+    /// What state comes after this state, from a given side?
+    fn child(self, side: Side) -> Self {
+        match (self, side) {
+            (Sky, Side::A) => Land,
+            (Land, Side::A) => Sky,
+            (Sky, _) if !side.adjacent_to(Side::A) => DeepSky,
+            (Land, _) if !side.adjacent_to(Side::A) => DeepLand,
+            _ => self,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum NodeStateRoad {
+    East,
+    DeepEast,
+    West,
+    DeepWest,
+}
+use NodeStateRoad::*;
+
+impl NodeStateRoad {
+    const ROOT: Self = West;
+
+    /// What state comes after this state, from a given side?
+    fn child(self, side: Side) -> Self {
+        match (self, side) {
+            (East, Side::B) => West,
+            (West, Side::B) => East,
+            (East, _) if !side.adjacent_to(Side::B) => DeepEast,
+            (West, _) if !side.adjacent_to(Side::B) => DeepWest,
+            _ => self,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NodeState {
+    kind: NodeStateKind,
+    surface: Plane<f64>,
+    road_state: NodeStateRoad,
+    enviro: EnviroFactors,
+}
+impl NodeState {
+    pub fn root() -> Self {
+        Self {
+            kind: NodeStateKind::ROOT,
+            surface: Plane::from(Side::A),
+            road_state: NodeStateRoad::ROOT,
+            enviro: EnviroFactors {
+                max_elevation: 0.0,
+                temperature: 0.0,
+                rainfall: 0.0,
+                blockiness: 0.0,
+            },
+        }
+    }
+
+    pub fn child(&self, graph: &Graph, node: NodeId, side: Side) -> Self {
+        let mut d = graph
+            .descenders(node)
+            .map(|(s, n)| (s, &graph.get(n).as_ref().unwrap().state));
+        let enviro = match (d.next(), d.next()) {
+            (Some(_), None) => {
+                let parent_side = graph.parent(node).unwrap();
+                let parent_node = graph.neighbor(node, parent_side).unwrap();
+                let parent_state = &graph.get(parent_node).as_ref().unwrap().state;
+                let spice = graph.hash_of(node) as u64;
+                EnviroFactors::varied_from(parent_state.enviro, spice)
+            }
+            (Some((a_side, a_state)), Some((b_side, b_state))) => {
+                let ab_node = graph
+                    .neighbor(graph.neighbor(node, a_side).unwrap(), b_side)
+                    .unwrap();
+                let ab_state = &graph.get(ab_node).as_ref().unwrap().state;
+                EnviroFactors::continue_from(a_state.enviro, b_state.enviro, ab_state.enviro)
+            }
+            _ => unreachable!(),
+        };
+
+        let child_kind = self.kind.child(side);
+        let child_road = self.road_state.child(side);
+
+        Self {
+            kind: child_kind,
+            surface: match child_kind {
+                Land => Plane::from(Side::A),
+                Sky => -Plane::from(Side::A),
+                _ => side * self.surface,
+            },
+            road_state: child_road,
+            enviro,
+        }
+    }
+
+    pub fn up_direction(&self) -> na::Vector4<f32> {
+        self.surface.normal().cast()
+    }
+
+    /// Signed distance from the node's center to its terrain surface
+    pub fn elevation(&self) -> f32 {
+        self.surface.distance_to(&math::origin()) as f32
+    }
+}
+
+struct VoxelCoords {
+    counter: u32,
+    dimension: u8,
+}
+
+impl VoxelCoords {
+    fn new(dimension: u8) -> Self {
+        VoxelCoords {
+            counter: 0,
+            dimension,
+        }
+    }
+}
+
+impl Iterator for VoxelCoords {
+    type Item = (u8, u8, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let dim = u32::from(self.dimension);
+
+        if self.counter == dim.pow(3) {
+            return None;
+        }
+
+        let result = (
+            (self.counter / dim.pow(2)) as u8,
+            ((self.counter / dim) % dim) as u8,
+            (self.counter % dim) as u8,
+        );
+
+        self.counter += 1;
+        Some(result)
+    }
+}
+
+/// A stage of terrain generation
+///
+/// A chunk is generated by applying a sequence of passes to initially empty voxels. Passes must be
+/// deterministic, as clients and servers generate chunks independently and must agree on the
+/// result.
+pub trait TerrainPass {
+    fn apply(&self, ctx: &ChunkGenContext<'_>, voxels: &mut VoxelData);
+}
+
+/// Built-in terrain passes, from which the passes used by a simulation are chosen
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TerrainPassKind {
+    /// Rolling hills of natural materials
+    Terrain,
+    /// The road and the supports beneath it
+    Road,
+    /// Trees planted on dirt and grass
+    Trees,
+    /// A featureless plane of `material`, filling everything below `height`
+    ///
+    /// `height` is measured from the terrain's reference surface, in meters in `SimConfigRaw` and
+    /// in absolute units in `SimConfig`.
+    Flat { height: f32, material: Material },
+    /// Empty space, discarding anything generated by earlier passes
+    Void,
+}
+
+impl TerrainPassKind {
+    /// Passes generating the usual world
+    pub const DEFAULT: [Self; 3] = [Self::Terrain, Self::Road, Self::Trees];
+}
+
+impl TerrainPass for TerrainPassKind {
+    fn apply(&self, ctx: &ChunkGenContext<'_>, voxels: &mut VoxelData) {
+        match *self {
+            Self::Terrain => ctx.generate_terrain(voxels),
+            Self::Road => ctx.generate_roads(voxels),
+            Self::Trees => ctx.generate_trees(voxels),
+            Self::Flat { height, material } => ctx.generate_flat(voxels, height, material),
+            Self::Void => *voxels = VoxelData::Solid(Material::Void),
+        }
+    }
+}
+
+/// Data needed to generate a chunk
+pub struct ChunkParams {
+    /// Number of voxels along an edge
+    dimension: u8,
+    /// Which vertex of the containing node this chunk lies against
+    chunk: Vertex,
+    /// Random quantities stored at the eight adjacent nodes, used for terrain generation
+    env: ChunkIncidentEnviroFactors,
+    /// State of the containing node
+    state: NodeState,
+    /// Whether this chunk contains a segment of the road
+    is_road: bool,
+    /// Whether this chunk contains a section of the road's supports
+    is_road_support: bool,
+    /// Random quantity used to seed terrain gen
+    node_spice: u64,
+    /// Passes to apply, in order
+    passes: Vec<TerrainPassKind>,
+}
+
+impl ChunkParams {
+    /// Extract data necessary to generate a chunk with `passes`
+    ///
+    /// Returns `None` if an unpopulated node is needed.
+    pub fn new(
+        dimension: u8,
+        graph: &Graph,
+        chunk: ChunkId,
+        passes: &[TerrainPassKind],
+    ) -> Option<Self> {
+        let state = &graph.get(chunk.node).as_ref()?.state;
+        Some(Self {
+            dimension,
+            chunk: chunk.vertex,
+            env: chunk_incident_enviro_factors(graph, chunk)?,
+            state: state.clone(),
+            is_road: state.kind == Sky
+                && ((state.road_state == East) || (state.road_state == West)),
+            is_road_support: ((state.kind == Land) || (state.kind == DeepLand))
+                && ((state.road_state == East) || (state.road_state == West)),
+            node_spice: graph.hash_of(chunk.node) as u64,
+            passes: passes.to_vec(),
+        })
+    }
+
+    pub fn chunk(&self) -> Vertex {
+        self.chunk
+    }
+
+    /// The quantities computed on the way to the natural terrain of the voxel at `coords`, as
+    /// evaluated one voxel at a time
+    pub fn terrain_intermediates(&self, coords: Coords) -> TerrainIntermediates {
+        self.context().terrain_intermediates(coords)
+    }
+
+    /// Generate voxels making up the chunk
+    pub fn generate_voxels(&self) -> VoxelData {
+        self.generate_voxels_on(WorldgenPath::fastest())
+    }
+
+    /// Generate voxels making up the chunk, evaluating its terrain along `path`
+    pub fn generate_voxels_on(&self, path: WorldgenPath) -> VoxelData {
+        self.generate(path, self.passes.iter().map(|x| x as &dyn TerrainPass))
+    }
+
+    /// Generate voxels making up the chunk using `passes` rather than those it was created with
+    pub fn generate_with<'a>(
+        &self,
+        passes: impl IntoIterator<Item = &'a dyn TerrainPass>,
+    ) -> VoxelData {
+        self.generate(WorldgenPath::fastest(), passes)
+    }
+
+    fn generate<'a>(
+        &self,
+        path: WorldgenPath,
+        passes: impl IntoIterator<Item = &'a dyn TerrainPass>,
+    ) -> VoxelData {
+        let ctx = self.context_on(path);
+        let mut voxels = VoxelData::Solid(Material::Void);
+        for pass in passes {
+            pass.apply(&ctx, &mut voxels);
+        }
+        voxels
+    }
+
+    fn context(&self) -> ChunkGenContext<'_> {
+        self.context_on(WorldgenPath::fastest())
+    }
+
+    fn context_on(&self, path: WorldgenPath) -> ChunkGenContext<'_> {
+        ChunkGenContext {
+            params: self,
+            rng: RefCell::new(self.rng()),
+            path,
+        }
+    }
+
+    /// Random number generator unique to this chunk
+    fn rng(&self) -> Pcg {
+        Pcg::for_chunk(WORLD_SEED, self.node_spice, self.chunk, RngPurpose::Terrain)
+    }
+}
+
+/// Everything a `TerrainPass` may base its output on
+pub struct ChunkGenContext<'a> {
+    params: &'a ChunkParams,
+    rng: RefCell<Pcg>,
+    /// How to evaluate the terrain
+    path: WorldgenPath,
+}
+
+impl ChunkGenContext<'_> {
+    /// Number of voxels along an edge
+    pub fn dimension(&self) -> u8 {
+        self.params.dimension
+    }
+
+    /// Which vertex of the containing node this chunk lies against
+    pub fn vertex(&self) -> Vertex {
+        self.params.chunk
+    }
+
+    /// State of the containing node
+    pub fn node_state(&self) -> &NodeState {
+        &self.params.state
+    }
+
+    /// Transform from chunk coordinates, which range from 0 to 1 within the chunk, to the space of
+    /// the containing node
+    pub fn chunk_to_node(&self) -> na::Matrix4<f64> {
+        self.params.chunk.chunk_to_node_f64()
+    }
+
+    /// Random number generator unique to this chunk, shared by all passes in order
+    pub fn rng(&self) -> RefMut<'_, Pcg> {
+        self.rng.borrow_mut()
+    }
+
+    /// Environmental factors interpolated from the surrounding nodes at `chunk_coords`
+    pub fn biome(&self, chunk_coords: &na::Vector3<f64>) -> Biome {
+        let env = &self.params.env;
+        let t = chunk_coords.map(|x| (1.0 - x) * 0.5);
+        Biome {
+            max_elevation: trilerp(&env.max_elevations, t),
+            temperature: trilerp(&env.temperatures, t),
+            rainfall: trilerp(&env.rainfalls, t),
+            blockiness: trilerp(&env.blockinesses, t),
+        }
+    }
+
+    /// Signed distance from the terrain's reference surface to `chunk_coords`, positive above it
+    pub fn elevation(&self, chunk_coords: &na::Vector3<f64>) -> f64 {
+        self.params
+            .state
+            .surface
+            .distance_to_chunk(self.params.chunk, chunk_coords)
+    }
+
+    /// Chunk coordinates of the center of the voxel at `coords`
+    pub fn voxel_center(&self, coords: Coords) -> na::Vector3<f64> {
+        voxel_center(self.dimension(), na::Vector3::from(coords.0))
+    }
+
+    /// Coordinates of every voxel in the chunk
+    pub fn voxels(&self) -> impl Iterator<Item = Coords> {
+        VoxelCoords::new(self.dimension()).map(|(x, y, z)| Coords([x, y, z]))
+    }
+
+    /// Whether the terrain surface certainly lies below the whole chunk
+    fn above_terrain(&self) -> bool {
+        let (center_elevation, _, me_max) = self.terrain_bounds();
+        center_elevation - ELEVATION_MARGIN > me_max / TERRAIN_SMOOTHNESS
+    }
+
+    /// Whether the terrain surface certainly lies above the whole chunk
+    fn below_terrain(&self) -> bool {
+        let (center_elevation, me_min, _) = self.terrain_bounds();
+        center_elevation + ELEVATION_MARGIN < me_min / TERRAIN_SMOOTHNESS
+    }
+
+    /// The elevation of the chunk's center, and the least and greatest max elevations of the
+    /// surrounding nodes
+    fn terrain_bounds(&self) -> (f64, f64, f64) {
+        let max_elevations = &self.params.env.max_elevations;
+        let mut me_min = max_elevations[0];
+        let mut me_max = max_elevations[0];
+        for &me in &max_elevations[1..] {
+            me_min = me_min.min(me);
+            me_max = me_max.max(me);
+        }
+        let center_elevation = self.elevation(&na::Vector3::repeat(0.5));
+        (center_elevation, me_min, me_max)
+    }
+
+    /// Generates the natural terrain, skipping the detailed work for chunks entirely above or
+    /// below its surface
+    fn generate_terrain(&self, voxels: &mut VoxelData) {
+        let params = self.params;
+        // Chunks containing the road or its supports are generated in full regardless, so that the
+        // random numbers left for later passes are unaffected
+        if self.above_terrain() && !(params.is_road || params.is_road_support) {
+            // The whole chunk is above ground
+            return;
+        }
+        if self.below_terrain() && !params.is_road {
+            // The whole chunk is underground
+            // TODO: More accurate VoxelData
+            *voxels = VoxelData::Solid(Material::Dirt);
+            return;
+        }
+        self.generate_terrain_voxels(voxels);
+    }
+
+    /// Performs all terrain generation that can be done one voxel at a time and with
+    /// only the containing chunk's surrounding nodes' envirofactors.
+    fn generate_terrain_voxels(&self, voxels: &mut VoxelData) {
+        match self.path {
+            WorldgenPath::Scalar => self.generate_terrain_voxels_scalar(voxels),
+            WorldgenPath::Wide => self.generate_terrain_voxels_wide(voxels),
+        }
+    }
+
+    /// Like `generate_terrain_voxels_scalar`, but with the arithmetic done for several voxels at
+    /// once by `TerrainBuffers`
+    fn generate_terrain_voxels_wide(&self, voxels: &mut VoxelData) {
+        let normal = Normal::new(0.0, TERRAIN_NOISE).unwrap();
+        let dimension = self.dimension();
+        let mut buffers = TerrainBuffers::new(usize::from(dimension).pow(3));
+        {
+            let mut rng = self.rng();
+            for (i, (x, y, z)) in VoxelCoords::new(dimension).enumerate() {
+                let center = voxel_center(dimension, na::Vector3::new(x, y, z));
+                buffers.x[i] = center.x;
+                buffers.y[i] = center.y;
+                buffers.z[i] = center.z;
+                buffers.elevation[i] = self.elevation(&center);
+                // Drawn in the same order as by the scalar path
+                buffers.rain_noise[i] = rng.sample(normal);
+                buffers.temp_noise[i] = rng.sample(normal);
+                buffers.elev_noise[i] = rng.sample(normal);
+            }
+        }
+        buffers.evaluate(&self.params.env);
+        for (i, (x, y, z)) in VoxelCoords::new(dimension).enumerate() {
+            let dist = buffers.dist[i];
+            if dist >= 0.0 {
+                let voxel_mat = VoronoiInfo::terraingen_voronoi(
+                    buffers.elev[i],
+                    buffers.rain[i],
+                    buffers.temp[i],
+                    dist,
+                );
+                voxels.data_mut(dimension)[index(dimension, na::Vector3::new(x, y, z))] = voxel_mat;
+            }
+        }
+    }
+
+    /// Evaluates the terrain one voxel at a time, the reference for every other `WorldgenPath`
+    fn generate_terrain_voxels_scalar(&self, voxels: &mut VoxelData) {
+        let normal = Normal::new(0.0, TERRAIN_NOISE).unwrap();
+        let dimension = self.dimension();
+        let mut rng = self.rng();
+
+        for (x, y, z) in VoxelCoords::new(dimension) {
+            let coords = na::Vector3::new(x, y, z);
+            let noise = [(); 3].map(|()| rng.sample(normal));
+            let voxel = self.terrain_voxel(&voxel_center(dimension, coords), noise);
+            if let Some(material) = voxel.material {
+                voxels.data_mut(dimension)[index(dimension, coords)] = material;
+            }
+        }
+    }
+
+    /// Evaluates the terrain at the voxel at `coords` alone, as the scalar path would, replaying
+    /// the random numbers drawn for the voxels before it
+    ///
+    /// The terrain pass must be the first to draw random numbers for this to match.
+    fn terrain_intermediates(&self, coords: Coords) -> TerrainIntermediates {
+        let normal = Normal::new(0.0, TERRAIN_NOISE).unwrap();
+        let dimension = self.dimension();
+        let mut rng = self.params.rng();
+        let target = (coords.0[0], coords.0[1], coords.0[2]);
+        for voxel in VoxelCoords::new(dimension) {
+            let noise = [(); 3].map(|()| rng.sample(normal));
+            if voxel == target {
+                return self.terrain_voxel(&self.voxel_center(coords), noise);
+            }
+        }
+        panic!("{coords:?} lies outside the chunk");
+    }
+
+    /// Evaluates the terrain at the voxel centered at `center`, given the noise added to its
+    /// rainfall, temperature, and elevation in that order
+    fn terrain_voxel(&self, center: &na::Vector3<f64>, noise: [f64; 3]) -> TerrainIntermediates {
+        let biome = self.biome(center);
+        let [rain_noise, temp_noise, elev_noise] = noise;
+
+        let rain = biome.rainfall + rain_noise;
+        let temp = biome.temperature + temp_noise;
+
+        // elev is calculated in multiple steps. The initial value elev_pre_terracing
+        // is used to calculate elev_pre_noise which is used to calculate elev.
+        let elev_pre_terracing = biome.max_elevation;
+        let block = biome.blockiness;
+        let voxel_elevation = self.elevation(center);
+        let strength = 0.4 / (1.0 + math::sqr(voxel_elevation));
+        let terracing_small = terracing_diff(elev_pre_terracing, block, 5.0, strength, 2.0);
+        let terracing_big = terracing_diff(elev_pre_terracing, block, 15.0, strength, -1.0);
+        // Small and big terracing effects must not sum to more than 1,
+        // otherwise the terracing fails to be (nonstrictly) monotonic
+        // and the terrain gets trenches ringing around its cliffs.
+        let elev_pre_noise = elev_pre_terracing + 0.6 * terracing_small + 0.4 * terracing_big;
+
+        // initial value dist_pre_noise is the difference between the voxel's distance
+        // from the guiding plane and the voxel's calculated elev value. It represents
+        // how far from the terrain surface a voxel is.
+        let dist_pre_noise = elev_pre_noise / TERRAIN_SMOOTHNESS - voxel_elevation;
+
+        // adding noise allows interfaces between strata to be rough
+        let elev = elev_pre_noise + TERRAIN_SMOOTHNESS * elev_noise;
+
+        // Final value of dist is calculated in this roundabout way for greater control
+        // over how noise in elev affects dist.
+        let dist = if dist_pre_noise > 0.0 {
+            // The .max(0.0) keeps the top of the ground smooth
+            // while still allowing the surface/general terrain interface to be rough
+            (elev / TERRAIN_SMOOTHNESS - voxel_elevation).max(0.0)
+        } else {
+            // Distance not updated for updated elevation if distance was originally
+            // negative. This ensures that no voxels that would have otherwise
+            // been void are changed to a material---so no floating dirt blocks.
+            dist_pre_noise
+        };
+
+        TerrainIntermediates {
+            biome,
+            rain_noise,
+            temp_noise,
+            elev_noise,
+            rain,
+            temp,
+            voxel_elevation,
+            terracing_small,
+            terracing_big,
+            elev_pre_noise,
+            dist_pre_noise,
+            elev,
+            dist,
+            material: (dist >= 0.0)
+                .then(|| VoronoiInfo::terraingen_voronoi(elev, rain, temp, dist)),
+        }
+    }
+
+    /// Places the road or its supports, if they pass through this chunk
+    fn generate_roads(&self, voxels: &mut VoxelData) {
+        if self.params.is_road {
+            self.generate_road(voxels);
+        } else if self.params.is_road_support && !self.below_terrain() {
+            self.generate_road_support(voxels);
+        }
+    }
+
+    /// Places a road along the guiding plane.
+    fn generate_road(&self, voxels: &mut VoxelData) {
+        let plane = -Plane::from(Side::B);
+        let dimension = self.dimension();
+
+        for (x, y, z) in VoxelCoords::new(dimension) {
+            let coords = na::Vector3::new(x, y, z);
+            let center = voxel_center(dimension, coords);
+            let horizontal_distance = plane.distance_to_chunk(self.params.chunk, &center);
+            let elevation = self.elevation(&center);
+
+            if horizontal_distance > 0.3 || elevation > 0.9 {
+                continue;
+            }
+
+            let mut mat: Material = Material::Void;
+
+            if elevation < 0.075 {
+                if horizontal_distance < 0.15 {
+                    // Inner
+                    mat = Material::WhiteBrick;
+                } else {
+                    // Outer
+                    mat = Material::GreyBrick;
+                }
+            }
+
+            voxels.data_mut(dimension)[index(dimension, coords)] = mat;
+        }
+    }
+
+    /// Fills the half-plane below the road with wooden supports.
+    fn generate_road_support(&self, voxels: &mut VoxelData) {
+        let plane = -Plane::from(Side::B);
+        let dimension = self.dimension();
+
+        for (x, y, z) in VoxelCoords::new(dimension) {
+            let coords = na::Vector3::new(x, y, z);
+            let center = voxel_center(dimension, coords);
+            let horizontal_distance = plane.distance_to_chunk(self.params.chunk, &center);
+
+            if horizontal_distance > 0.3 {
+                continue;
+            }
+
+            let mat = if self.trussing_at(coords) {
+                Material::WoodPlanks
+            } else {
+                Material::Void
+            };
+
+            if mat != Material::Void {
+                voxels.data_mut(dimension)[index(dimension, coords)] = mat;
+            }
+        }
+    }
+
+    /// Make a truss-shaped template
+    fn trussing_at(&self, coords: na::Vector3<u8>) -> bool {
+        // Generates planar diagonals, but corner is offset
+        let mut criteria_met = 0_u32;
+        let x = coords[0];
+        let y = coords[1];
+        let z = coords[2];
+        let offset = self.dimension() / 3;
+
+        // straight lines.
+        criteria_met += u32::from(x == offset);
+        criteria_met += u32::from(y == offset);
+        criteria_met += u32::from(z == offset);
+
+        // main diagonal
+        criteria_met += u32::from(x == y);
+        criteria_met += u32::from(y == z);
+        criteria_met += u32::from(x == z);
+
+        criteria_met >= 2
+    }
+
+    /// Plants trees on dirt and grass. Trees consist of a block of wood
+    /// and a block of leaves. The leaf block is on the opposite face of the
+    /// wood block as the ground block.
+    fn generate_trees(&self, voxels: &mut VoxelData) {
+        let dimension = self.dimension();
+        // TODO: Don't generate detailed data for solid chunks with no neighboring voids
+        if dimension <= 4 || !matches!(voxels, VoxelData::Dense(_)) {
+            return;
+        }
+        let mut rng = self.rng();
+        // margins are added to keep voxels outside the chunk from being read/written
+        let random_position = Uniform::new(1, dimension - 1);
+
+        let rain = self.params.env.rainfalls[0];
+        let tree_candidate_count =
+            (u32::from(dimension - 2).pow(3) as f64 * (rain / 100.0).clamp(0.0, 0.5)) as usize;
+        for _ in 0..tree_candidate_count {
+            let loc = na::Vector3::from_distribution(&random_position, &mut *rng);
+            let voxel_of_interest_index = index(dimension, loc);
+            let neighbor_data = self.voxel_neighbors(loc, voxels);
+
+            let num_void_neighbors = neighbor_data
+                .iter()
+                .filter(|n| n.material == Material::Void)
+                .count();
+
+            // Only plant a tree if there is exactly one adjacent block of dirt or grass
+            if num_void_neighbors == 5 {
+                for i in neighbor_data.iter() {
+                    if (i.material == Material::Dirt)
+                        || (i.material == Material::Grass)
+                        || (i.material == Material::MudGrass)
+                        || (i.material == Material::LushGrass)
+                        || (i.material == Material::TanGrass)
+                        || (i.material == Material::CoarseGrass)
+                    {
+                        voxels.data_mut(dimension)[voxel_of_interest_index] = Material::Wood;
+                        let leaf_location = index(dimension, i.coords_opposing);
+                        voxels.data_mut(dimension)[leaf_location] = Material::Leaves;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fills every voxel whose center lies below `height` with `material`
+    fn generate_flat(&self, voxels: &mut VoxelData, height: f32, material: Material) {
+        let dimension = self.dimension();
+        let mut filled = 0;
+        for (x, y, z) in VoxelCoords::new(dimension) {
+            let coords = na::Vector3::new(x, y, z);
+            if self.elevation(&voxel_center(dimension, coords)) < f64::from(height) {
+                voxels.data_mut(dimension)[index(dimension, coords)] = material;
+                filled += 1;
+            }
+        }
+        if filled == u32::from(dimension).pow(3) {
+            *voxels = VoxelData::Solid(material);
+        }
+    }
+
+    /// Provides information on the type of material in a voxel's six neighbours
+    fn voxel_neighbors(&self, coords: na::Vector3<u8>, voxels: &VoxelData) -> [NeighborData; 6] {
+        [
+            self.neighbor(coords, -1, 0, 0, voxels),
+            self.neighbor(coords, 1, 0, 0, voxels),
+            self.neighbor(coords, 0, -1, 0, voxels),
+            self.neighbor(coords, 0, 1, 0, voxels),
+            self.neighbor(coords, 0, 0, -1, voxels),
+            self.neighbor(coords, 0, 0, 1, voxels),
+        ]
+    }
+
+    fn neighbor(
+        &self,
+        w: na::Vector3<u8>,
+        x: i8,
+        y: i8,
+        z: i8,
+        voxels: &VoxelData,
+    ) -> NeighborData {
+        let coords = na::Vector3::new(
+            (w.x as i8 + x) as u8,
+            (w.y as i8 + y) as u8,
+            (w.z as i8 + z) as u8,
+        );
+        let coords_opposing = na::Vector3::new(
+            (w.x as i8 - x) as u8,
+            (w.y as i8 - y) as u8,
+            (w.z as i8 - z) as u8,
+        );
+        let material = voxels.view(self.dimension()).get(Coords(coords.into()));
+
+        NeighborData {
+            coords_opposing,
+            material,
+        }
+    }
+}
+
+/// Environmental factors at a point, interpolated from those of the surrounding nodes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Biome {
+    pub max_elevation: f64,
+    pub temperature: f64,
+    pub rainfall: f64,
+    pub blockiness: f64,
+}
+
+/// Every quantity computed on the way to a voxel's natural terrain, for finding where two builds
+/// that generate it differently part ways
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TerrainIntermediates {
+    pub biome: Biome,
+    /// Random noise added to the rainfall, temperature, and elevation
+    pub rain_noise: f64,
+    pub temp_noise: f64,
+    pub elev_noise: f64,
+    pub rain: f64,
+    pub temp: f64,
+    /// Signed distance from the terrain's reference surface to the voxel's center
+    pub voxel_elevation: f64,
+    pub terracing_small: f64,
+    pub terracing_big: f64,
+    pub elev_pre_noise: f64,
+    pub dist_pre_noise: f64,
+    pub elev: f64,
+    /// How far below the terrain surface the voxel lies, negative above it
+    pub dist: f64,
+    /// The terrain's material, or `None` if the voxel is left empty
+    pub material: Option<Material>,
+}
+
+/// Seed of every world, none having one of its own yet
+const WORLD_SEED: u64 = 0;
+
+const TERRAIN_SMOOTHNESS: f64 = 10.0;
+
+/// Standard deviation of the noise added to each voxel's rainfall, temperature, and elevation
+const TERRAIN_NOISE: f64 = 0.03;
+
+/// Maximum difference between elevations at the center of a chunk and any other point in the chunk
+// TODO: Compute what this actually is, current value is a guess! Real one must be > 0.6
+// empirically.
+const ELEVATION_MARGIN: f64 = 0.7;
+
+/// Greatest distance terracing moves the terrain's surface from where the biome's max elevation
+/// puts it, given the scales and weights of terracing in `generate_terrain_voxels`
+const TERRACING_REACH: f64 = (0.6 * 5.0 + 0.4 * 15.0) / TERRAIN_SMOOTHNESS;
+
+struct NeighborData {
+    coords_opposing: na::Vector3<u8>,
+    material: Material,
+}
+
+#[derive(Copy, Clone)]
+struct EnviroFactors {
+    max_elevation: f64,
+    temperature: f64,
+    rainfall: f64,
+    blockiness: f64,
+}
+impl EnviroFactors {
+    fn varied_from(parent: Self, spice: u64) -> Self {
+        let mut rng = Pcg::for_node(WORLD_SEED, spice, RngPurpose::Environment);
+        let unif = Uniform::new_inclusive(-1.0, 1.0);
+        let max_elevation = parent.max_elevation + rng.sample(Normal::new(0.0, 4.0).unwrap());
+
+        Self {
+            max_elevation,
+            temperature: parent.temperature + rng.sample(unif),
+            rainfall: parent.rainfall + rng.sample(unif),
+            blockiness: parent.blockiness + rng.sample(unif),
+        }
+    }
+    fn continue_from(a: Self, b: Self, ab: Self) -> Self {
+        Self {
+            max_elevation: a.max_elevation + (b.max_elevation - ab.max_elevation),
+            temperature: a.temperature + (b.temperature - ab.temperature),
+            rainfall: a.rainfall + (b.rainfall - ab.rainfall),
+            blockiness: a.blockiness + (b.blockiness - ab.blockiness),
+        }
+    }
+}
+impl From<EnviroFactors> for (f64, f64, f64, f64) {
+    fn from(envirofactors: EnviroFactors) -> Self {
+        (
+            envirofactors.max_elevation,
+            envirofactors.temperature,
+            envirofactors.rainfall,
+            envirofactors.blockiness,
+        )
+    }
+}
+struct ChunkIncidentEnviroFactors {
+    max_elevations: [f64; 8],
+    temperatures: [f64; 8],
+    rainfalls: [f64; 8],
+    blockinesses: [f64; 8],
+}
+
+/// Returns the max_elevation values for the nodes that are incident to this chunk,
+/// sorted and converted to f64 for use in functions like trilerp.
+///
+/// Returns `None` if not all incident nodes are populated.
+fn chunk_incident_enviro_factors(
+    graph: &Graph,
+    chunk: ChunkId,
+) -> Option<ChunkIncidentEnviroFactors> {
+    let mut i = chunk
+        .vertex
+        .dual_vertices()
+        .map(|(_, mut path)| path.try_fold(chunk.node, |node, side| graph.neighbor(node, side)))
+        .filter_map(|node| Some(graph.get(node?).as_ref()?.state.enviro));
+
+    // this is a bit cursed, but I don't want to collect into a vec because perf,
+    // and I can't just return an iterator because then something still references graph.
+    let (e1, t1, r1, b1) = i.next()?.into();
+    let (e2, t2, r2, b2) = i.next()?.into();
+    let (e3, t3, r3, b3) = i.next()?.into();
+    let (e4, t4, r4, b4) = i.next()?.into();
+    let (e5, t5, r5, b5) = i.next()?.into();
+    let (e6, t6, r6, b6) = i.next()?.into();
+    let (e7, t7, r7, b7) = i.next()?.into();
+    let (e8, t8, r8, b8) = i.next()?.into();
+
+    Some(ChunkIncidentEnviroFactors {
+        max_elevations: [e1, e2, e3, e4, e5, e6, e7, e8],
+        temperatures: [t1, t2, t3, t4, t5, t6, t7, t8],
+        rainfalls: [r1, r2, r3, r4, r5, r6, r7, r8],
+        blockinesses: [b1, b2, b3, b4, b5, b6, b7, b8],
+    })
+}
+
+/// Linearly interpolate at interior and boundary of a cube given values at the eight corners.
+fn trilerp<N: na::RealField + Copy>(
+    &[v000, v001, v010, v011, v100, v101, v110, v111]: &[N; 8],
+    t: na::Vector3<N>,
+) -> N {
+    fn lerp<N: na::RealField + Copy>(v0: N, v1: N, t: N) -> N {
+        v0 * (N::one() - t) + v1 * t
+    }
+    fn bilerp<N: na::RealField + Copy>(v00: N, v01: N, v10: N, v11: N, t: na::Vector2<N>) -> N {
+        lerp(lerp(v00, v01, t.x), lerp(v10, v11, t.x), t.y)
+    }
+
+    lerp(
+        bilerp(v000, v100, v010, v110, t.xy()),
+        bilerp(v001, v101, v011, v111, t.xy()),
+        t.z,
+    )
+}
+
+/// serp interpolates between two values v0 and v1 over the interval [0, 1] by yielding
+/// v0 for [0, threshold], v1 for [1-threshold, 1], and linear interpolation in between
+/// such that the overall shape is an S-shaped piecewise function.
+/// threshold should be between 0 and 0.5.
+fn serp<N: na::RealField + Copy>(v0: N, v1: N, t: N, threshold: N) -> N {
+    if t < threshold {
+        v0
+    } else if t < (N::one() - threshold) {
+        let s = (t - threshold) / ((N::one() - threshold) - threshold);
+        v0 * (N::one() - s) + v1 * s
+    } else {
+        v1
+    }
+}
+
+/// Intended to produce a number that is added to elev_raw.
+/// block is a real number, threshold is in (0, strength) via a logistic function
+/// scale controls wavelength and amplitude. It is not 1:1 to the number of blocks in a period.
+/// strength represents extremity of terracing effect. Sensible values are in (0, 0.5).
+/// The greater the value of limiter, the stronger the bias of threshold towards 0.
+fn terracing_diff(elev_raw: f64, block: f64, scale: f64, strength: f64, limiter: f64) -> f64 {
+    let threshold: f64 = strength / (1.0 + libm::pow(2.0, limiter - block));
+    let elev_floor = libm::floor(elev_raw / scale);
+    let elev_rem = elev_raw / scale - elev_floor;
+    scale * elev_floor + serp(0.0, scale, elev_rem, threshold) - elev_raw
+}
+
+/// Location of the center of a voxel in a unit chunk
+fn voxel_center(dimension: u8, voxel: na::Vector3<u8>) -> na::Vector3<f64> {
+    voxel.map(|x| f64::from(x) + 0.5) / f64::from(dimension)
+}
+
+fn index(dimension: u8, v: na::Vector3<u8>) -> usize {
+    Coords(v.into()).to_index(dimension)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::node::{populate_fresh_nodes, MarginCoords, Node};
+    use crate::proto::Position;
+    use crate::rng::hash;
+    use crate::traversal::{ensure_nearby, nearby_nodes};
+    use crate::Chunks;
+    use approx::*;
+
+    const CHUNK_SIZE: u8 = 12;
+
+    /// Chunks of every node near the origin, for which generation is possible
+    fn chunks_near_origin(passes: &[TerrainPassKind]) -> Vec<(ChunkId, ChunkParams)> {
+        let mut graph = Graph::new(CHUNK_SIZE);
+        ensure_nearby(&mut graph, &Position::origin(), 3.0);
+        populate_fresh_nodes(&mut graph);
+        nearby_nodes(&graph, &Position::origin(), 1.5)
+            .into_iter()
+            .flat_map(|(node, _)| Vertex::iter().map(move |vertex| ChunkId::new(node, vertex)))
+            .filter_map(|chunk| Some((chunk, ChunkParams::new(CHUNK_SIZE, &graph, chunk, passes)?)))
+            .collect()
+    }
+
+    /// How chunks were generated before being divided into passes
+    fn generate_monolithic(params: &ChunkParams) -> VoxelData {
+        let ctx = params.context();
+        if ctx.above_terrain() && !(params.is_road || params.is_road_support) {
+            return VoxelData::Solid(Material::Void);
+        }
+        if ctx.below_terrain() && !params.is_road {
+            return VoxelData::Solid(Material::Dirt);
+        }
+        let mut voxels = VoxelData::Solid(Material::Void);
+        ctx.generate_terrain_voxels(&mut voxels);
+        if params.is_road {
+            ctx.generate_road(&mut voxels);
+        } else if params.is_road_support {
+            ctx.generate_road_support(&mut voxels);
+        }
+        if params.dimension > 4 && matches!(voxels, VoxelData::Dense(_)) {
+            ctx.generate_trees(&mut voxels);
+        }
+        voxels
+    }
+
+    fn same_voxels(a: &VoxelData, b: &VoxelData) -> bool {
+        match (a, b) {
+            (VoxelData::Solid(a), VoxelData::Solid(b)) => a == b,
+            (VoxelData::Dense(a), VoxelData::Dense(b)) => a == b,
+            (VoxelData::Shaped(a, a_shapes), VoxelData::Shaped(b, b_shapes)) => {
+                a == b && a_shapes == b_shapes
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn default_passes_match_monolithic_generation() {
+        let chunks = chunks_near_origin(&TerrainPassKind::DEFAULT);
+        assert!(chunks.iter().any(|(_, params)| params.is_road));
+        assert!(chunks.iter().any(|(_, params)| params.is_road_support));
+        let mut dense = 0;
+        for (chunk, params) in &chunks {
+            let voxels = params.generate_voxels();
+            assert!(
+                same_voxels(&voxels, &generate_monolithic(params)),
+                "{chunk:?} differs"
+            );
+            dense += usize::from(matches!(voxels, VoxelData::Dense(_)));
+        }
+        assert!(dense > 0);
+    }
+
+    #[test]
+    fn wide_path_matches_scalar() {
+        let mut chunks = chunks_near_origin(&TerrainPassKind::DEFAULT);
+        assert!(chunks.len() > 200);
+        let mut dense = 0;
+        // Vary the random quantities behind each chunk as different worlds would
+        for seed in [0, 1, 0x5eed] {
+            for (chunk, params) in &mut chunks {
+                params.node_spice = hash(params.node_spice, seed);
+                let scalar = params.generate_voxels_on(WorldgenPath::Scalar);
+                let wide = params.generate_voxels_on(WorldgenPath::Wide);
+                assert!(
+                    same_voxels(&scalar, &wide),
+                    "{chunk:?} differs with seed {seed}"
+                );
+                dense += usize::from(matches!(wide, VoxelData::Dense(_)));
+            }
+        }
+        assert!(dense > 0);
+    }
+
+    #[test]
+    fn terrain_intermediates_match_generation() {
+        let chunks = chunks_near_origin(&[TerrainPassKind::Terrain]);
+        let (params, voxels) = chunks
+            .iter()
+            .map(|(_, params)| (params, params.generate_voxels_on(WorldgenPath::Scalar)))
+            .find(|(_, voxels)| matches!(voxels, VoxelData::Dense(_)))
+            .unwrap();
+        let ctx = params.context();
+        let normal = Normal::new(0.0, TERRAIN_NOISE).unwrap();
+        let mut rng = params.rng();
+        for (i, coords) in ctx.voxels().enumerate() {
+            let noise = [(); 3].map(|()| rng.sample(normal));
+            // Each evaluation replays the noise of every voxel before it
+            if i % 37 != 0 {
+                continue;
+            }
+            let center = ctx.voxel_center(coords);
+            let x = params.terrain_intermediates(coords);
+            assert_eq!([x.rain_noise, x.temp_noise, x.elev_noise], noise);
+            assert_eq!(x.biome, ctx.biome(&center));
+            assert_eq!(x.voxel_elevation, ctx.elevation(&center));
+            assert_eq!(x.rain, x.biome.rainfall + noise[0]);
+            assert_eq!(x.temp, x.biome.temperature + noise[1]);
+            assert_eq!(x.material.is_some(), x.dist >= 0.0);
+            assert_eq!(
+                x.material.unwrap_or(Material::Void),
+                voxels.view(CHUNK_SIZE).get(coords),
+                "{coords:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn flat_passes() {
+        let material = Material::Sand;
+        let passes = [TerrainPassKind::Flat {
+            height: 0.0,
+            material,
+        }];
+        for (chunk, params) in chunks_near_origin(&passes) {
+            let voxels = params.generate_voxels();
+            let ctx = params.context();
+            for coords in ctx.voxels() {
+                let expected = if ctx.elevation(&ctx.voxel_center(coords)) < 0.0 {
+                    material
+                } else {
+                    Material::Void
+                };
+                assert_eq!(
+                    voxels.view(CHUNK_SIZE).get(coords),
+                    expected,
+                    "{chunk:?} {coords:?}"
+                );
+            }
+        }
+
+        // Later passes build on earlier ones, and can discard them
+        let (_, params) = chunks_near_origin(&[]).pop().unwrap();
+        let flat = passes[0];
+        let voxels = params.generate_with([&flat as &dyn TerrainPass, &TerrainPassKind::Void]);
+        assert!(same_voxels(&voxels, &VoxelData::Solid(Material::Void)));
+    }
+
+    #[test]
+    fn chunk_indexing_origin() {
+        // (0, 0, 0) in localized coords, just inside the margins
+        let origin_index = MarginCoords([1; 3]).to_index(CHUNK_SIZE);
+
+        // simple sanity check
+        assert_eq!(index(CHUNK_SIZE, na::Vector3::repeat(0)), origin_index);
+    }
+
+    #[test]
+    fn chunk_indexing_absolute() {
+        let origin_index = MarginCoords([1; 3]).to_index(CHUNK_SIZE);
+        // (0.5, 0.5, 0.5) in localized coords
+        let center_index = index(CHUNK_SIZE, na::Vector3::repeat(CHUNK_SIZE / 2));
+        // the point farthest from the origin, (1, 1, 1) in localized coords, in the margin
+        let anti_index = MarginCoords([CHUNK_SIZE + 1; 3]).to_index(CHUNK_SIZE);
+
+        assert_eq!(index(CHUNK_SIZE, na::Vector3::new(0, 0, 0)), origin_index);
+
+        // biggest possible index in subchunk closest to origin still isn't the center
+        assert!(
+            index(
+                CHUNK_SIZE,
+                na::Vector3::new(CHUNK_SIZE / 2 - 1, CHUNK_SIZE / 2 - 1, CHUNK_SIZE / 2 - 1,)
+            ) < center_index
+        );
+        // but the first chunk in the subchunk across from that is
+        assert_eq!(
+            index(
+                CHUNK_SIZE,
+                na::Vector3::new(CHUNK_SIZE / 2, CHUNK_SIZE / 2, CHUNK_SIZE / 2)
+            ),
+            center_index
+        );
+
+        // biggest possible index in subchunk closest to anti_origin is still not quite
+        // the anti_origin
+        assert!(
+            index(
+                CHUNK_SIZE,
+                na::Vector3::new(CHUNK_SIZE - 1, CHUNK_SIZE - 1, CHUNK_SIZE - 1,)
+            ) < anti_index
+        );
+
+        // one is added in the chunk indexing so this works out fine, the
+        // domain is still CHUNK_SIZE because 0 is included.
+        assert_eq!(
+            index(
+                CHUNK_SIZE,
+                na::Vector3::new(CHUNK_SIZE - 1, CHUNK_SIZE - 1, CHUNK_SIZE - 1,)
+            ),
+            index(CHUNK_SIZE, na::Vector3::repeat(CHUNK_SIZE - 1))
+        );
+    }
+
+    #[test]
+    fn check_chunk_incident_max_elevations() {
+        let mut g = Graph::new(1);
+        for (i, path) in Vertex::A.dual_vertices().map(|(_, p)| p).enumerate() {
+            let new_node = path.fold(NodeId::ROOT, |node, side| g.ensure_neighbor(node, side));
+
+            // assigning state
+            *g.get_mut(new_node) = Some(Node {
+                state: {
+                    let mut state = NodeState::root();
+                    state.enviro.max_elevation = i as f64 + 1.0;
+                    state
+                },
+                chunks: Chunks::default(),
+            });
+        }
+
+        let enviros =
+            chunk_incident_enviro_factors(&g, ChunkId::new(NodeId::ROOT, Vertex::A)).unwrap();
+        for (i, max_elevation) in enviros.max_elevations.iter().cloned().enumerate() {
+            println!("{i}, {max_elevation}");
+            assert_abs_diff_eq!(max_elevation, (i + 1) as f64, epsilon = 1e-8);
+        }
+
+        // see corresponding test for trilerp
+        let center_max_elevation = trilerp(&enviros.max_elevations, na::Vector3::repeat(0.5));
+        assert_abs_diff_eq!(center_max_elevation, 4.5, epsilon = 1e-8);
+
+        let mut checked_center = false;
+        let center = na::Vector3::repeat(CHUNK_SIZE / 2);
+        'top: for z in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let a = na::Vector3::new(x, y, z);
+                    if a == center {
+                        checked_center = true;
+                        let c = center.map(|x| x as f64) / CHUNK_SIZE as f64;
+                        let center_max_elevation = trilerp(&enviros.max_elevations, c);
+                        assert_abs_diff_eq!(center_max_elevation, 4.5, epsilon = 1e-8);
+                        break 'top;
+                    }
+                }
+            }
+        }
+
+        if !checked_center {
+            panic!("Never checked trilerping center max_elevation!");
+        }
+    }
+
+    #[test]
+    fn check_trilerp() {
+        assert_abs_diff_eq!(
+            1.0,
+            trilerp(
+                &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                na::Vector3::new(0.0, 0.0, 0.0),
+            ),
+            epsilon = 1e-8,
+        );
+        assert_abs_diff_eq!(
+            1.0,
+            trilerp(
+                &[0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+                na::Vector3::new(1.0, 0.0, 0.0),
+            ),
+            epsilon = 1e-8,
+        );
+        assert_abs_diff_eq!(
+            1.0,
+            trilerp(
+                &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                na::Vector3::new(0.0, 1.0, 0.0),
+            ),
+            epsilon = 1e-8,
+        );
+        assert_abs_diff_eq!(
+            1.0,
+            trilerp(
+                &[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+                na::Vector3::new(1.0, 1.0, 0.0),
+            ),
+            epsilon = 1e-8,
+        );
+        assert_abs_diff_eq!(
+            1.0,
+            trilerp(
+                &[0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                na::Vector3::new(0.0, 0.0, 1.0),
+            ),
+            epsilon = 1e-8,
+        );
+        assert_abs_diff_eq!(
+            1.0,
+            trilerp(
+                &[0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+                na::Vector3::new(1.0, 0.0, 1.0),
+            ),
+            epsilon = 1e-8,
+        );
+        assert_abs_diff_eq!(
+            1.0,
+            trilerp(
+                &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+                na::Vector3::new(0.0, 1.0, 1.0),
+            ),
+            epsilon = 1e-8,
+        );
+        assert_abs_diff_eq!(
+            1.0,
+            trilerp(
+                &[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+                na::Vector3::new(1.0, 1.0, 1.0),
+            ),
+            epsilon = 1e-8,
+        );
+
+        assert_abs_diff_eq!(
+            0.5,
+            trilerp(
+                &[0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0],
+                na::Vector3::new(0.5, 0.5, 0.5),
+            ),
+            epsilon = 1e-8,
+        );
+        assert_abs_diff_eq!(
+            0.5,
+            trilerp(
+                &[0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0],
+                na::Vector3::new(0.5, 0.5, 0.5),
+            ),
+            epsilon = 1e-8,
+        );
+
+        assert_abs_diff_eq!(
+            4.5,
+            trilerp(
+                &[1.0, 5.0, 3.0, 7.0, 2.0, 6.0, 4.0, 8.0],
+                na::Vector3::new(0.5, 0.5, 0.5),
+            ),
+            epsilon = 1e-8,
+        );
+    }
+
+    #[test]
+    fn check_voxel_iterable() {
+        let dimension = 12;
+
+        for (counter, (x, y, z)) in (VoxelCoords::new(dimension as u8)).enumerate() {
+            let index = z as usize + y as usize * dimension + x as usize * dimension.pow(2);
+            assert!(counter == index);
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//! Statistics of generated chunks, for tuning world generation and noticing when it changes
+//!
+//! `ChunkStats` summarizes a single chunk. `Survey` generates every chunk near the origin and sums
+//! their statistics by biome, in a report meant to be saved as JSON and diffed between commits:
+//!
+//! ```text
+//! cargo run -p common --release --example worldgen_stats > stats.json
+//! ```
+//!
+//! The world has no seed: what's generated is determined by the graph alone, so a survey depends
+//! only on its radius, chunk dimension, and passes.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::{ChunkParams, TerrainPassKind, TERRACING_REACH, TERRAIN_SMOOTHNESS};
+use crate::{
+    dodeca::{self, Vertex},
+    graph::Graph,
+    node::{populate_fresh_nodes, ChunkId, CoordAxis, Coords, MarginCoords, VoxelData},
+    proto::Position,
+    traversal::{ensure_nearby, nearby_nodes},
+    world::Material,
+};
+
+/// Range of temperatures and of rainfalls covered by each bucket of a `Survey`
+pub const BUCKET_WIDTH: f64 = 3.0;
+
+/// Depth below the nominal terrain surface past which voxels count as deep
+///
+/// Terracing can't carve the surface down this far, so deep void is a cave or tunnel.
+pub const DEEP: f64 = TERRACING_REACH;
+
+/// Where a voxel lies relative to the terrain
+#[derive(Debug, Copy, Clone)]
+pub struct Placement {
+    /// Signed distance from the terrain's reference surface, positive above it
+    pub elevation: f64,
+    /// Distance below the nominal terrain surface, where the biome's max elevation puts it
+    pub depth: f64,
+}
+
+/// Summary of the voxels of one chunk
+#[derive(Debug, Clone)]
+pub struct ChunkStats {
+    /// Number of voxels of each material, indexed by discriminant
+    pub materials: [u32; Material::COUNT],
+    /// Number of voxels that aren't void
+    pub solid: u32,
+    /// Faces between a void voxel and one that isn't, as an estimate of exposed surface area
+    ///
+    /// Faces on the chunk's boundary are left out, as what lies beyond them isn't known.
+    pub exposed_faces: u32,
+    /// Elevations of the voxels that aren't void, if any
+    pub solid_elevation: Option<Span>,
+    /// Number of voxels deeper than `DEEP`
+    pub deep: u32,
+    /// Number of voxels deeper than `DEEP` that are void
+    pub deep_void: u32,
+}
+
+impl ChunkStats {
+    /// Summarize `voxels`, with `placement` giving where the voxel at each coordinate lies
+    pub fn from_voxels(
+        voxels: &VoxelData,
+        dimension: u8,
+        placement: impl Fn(Coords) -> Placement,
+    ) -> Self {
+        let view = voxels.view(dimension);
+        match view.as_slice() {
+            None => {
+                let material = view.get(Coords([0; 3]));
+                Self::scan(dimension, |_| material, placement)
+            }
+            Some(data) => Self::scan(
+                dimension,
+                |coords| data[coords.to_index(dimension)],
+                placement,
+            ),
+        }
+    }
+
+    /// Generate the chunk described by `params` and summarize it
+    pub fn generate(params: &ChunkParams) -> Self {
+        let voxels = params.generate_voxels();
+        let ctx = params.context();
+        Self::from_voxels(&voxels, params.dimension, |coords| {
+            let center = ctx.voxel_center(coords);
+            let elevation = ctx.elevation(&center);
+            Placement {
+                elevation,
+                depth: ctx.biome(&center).max_elevation / TERRAIN_SMOOTHNESS - elevation,
+            }
+        })
+    }
+
+    /// Summarize the voxels `material` looks up in padded voxel data, monomorphized so that dense
+    /// data is read straight from its slice
+    fn scan(
+        dimension: u8,
+        material: impl Fn(MarginCoords) -> Material,
+        placement: impl Fn(Coords) -> Placement,
+    ) -> Self {
+        let mut stats = Self {
+            materials: [0; Material::COUNT],
+            solid: 0,
+            exposed_faces: 0,
+            solid_elevation: None,
+            deep: 0,
+            deep_void: 0,
+        };
+        let mut elevations = SpanSum::default();
+        for coords in Coords::all(dimension) {
+            let here = material(coords.into());
+            let void = here == Material::Void;
+            stats.materials[usize::from(here.id())] += 1;
+            // Each face within the chunk is counted from the voxel on its negative side
+            for axis in CoordAxis::iter() {
+                let mut next = MarginCoords::from(coords);
+                next[axis] += 1;
+                if coords[axis] + 1 < dimension && void != (material(next) == Material::Void) {
+                    stats.exposed_faces += 1;
+                }
+            }
+            let placement = placement(coords);
+            if !void {
+                stats.solid += 1;
+                elevations.add(placement.elevation);
+            }
+            if placement.depth > DEEP {
+                stats.deep += 1;
+                stats.deep_void += u32::from(void);
+            }
+        }
+        stats.solid_elevation = elevations.finish();
+        stats
+    }
+}
+
+/// Least, greatest, and mean of some values
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct Span {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Span {
+    /// The span of the values of both `self` and `other`, of which there are `weight` and
+    /// `other_weight`
+    fn merge(self, weight: u64, other: Self, other_weight: u64) -> Self {
+        let total = (weight + other_weight) as f64;
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            mean: (self.mean * weight as f64 + other.mean * other_weight as f64) / total,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SpanSum {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u32,
+}
+
+impl SpanSum {
+    fn add(&mut self, value: f64) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        }
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn finish(self) -> Option<Span> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(Span {
+            min: self.min,
+            max: self.max,
+            mean: self.sum / f64::from(self.count),
+        })
+    }
+}
+
+/// Statistics of every chunk near the origin, summed by biome
+#[derive(Debug, Clone, Serialize)]
+pub struct Survey {
+    pub dimension: u8,
+    /// Distance from the origin within which the chunks of every node were generated
+    pub radius: f64,
+    pub passes: Vec<TerrainPassKind>,
+    /// Number of chunks generated
+    pub chunks: u32,
+    /// Ordered by temperature, then rainfall
+    pub buckets: Vec<BucketStats>,
+}
+
+impl Survey {
+    /// Generate the chunks of every node whose center lies within `radius` of the origin using
+    /// `passes`, and sum their statistics by the temperature and rainfall of their nodes
+    pub fn run(dimension: u8, radius: f64, passes: &[TerrainPassKind]) -> Self {
+        let mut buckets = BTreeMap::new();
+        let chunks = measure(dimension, radius, passes);
+        for (_, params, stats) in &chunks {
+            let (temperature, rainfall) = bucket(params);
+            buckets
+                .entry((temperature, rainfall))
+                .or_insert_with(|| BucketStats::new(temperature, rainfall))
+                .add(stats);
+        }
+        Self {
+            dimension,
+            radius,
+            passes: passes.to_vec(),
+            chunks: chunks.len() as u32,
+            buckets: buckets.into_values().collect(),
+        }
+    }
+}
+
+/// Statistics summed over the chunks of nodes with similar climates
+#[derive(Debug, Clone, Serialize)]
+pub struct BucketStats {
+    /// Least temperature of the nodes whose chunks are summed, up to `BUCKET_WIDTH` below the
+    /// greatest
+    pub temperature: f64,
+    /// Least rainfall of the nodes whose chunks are summed, up to `BUCKET_WIDTH` below the
+    /// greatest
+    pub rainfall: f64,
+    pub chunks: u32,
+    /// Number of voxels of each material present
+    pub materials: BTreeMap<Material, u64>,
+    pub solid: u64,
+    pub exposed_faces: u64,
+    pub solid_elevation: Option<Span>,
+    pub deep: u64,
+    pub deep_void: u64,
+}
+
+impl BucketStats {
+    fn new(temperature: i32, rainfall: i32) -> Self {
+        Self {
+            temperature: f64::from(temperature) * BUCKET_WIDTH,
+            rainfall: f64::from(rainfall) * BUCKET_WIDTH,
+            chunks: 0,
+            materials: BTreeMap::new(),
+            solid: 0,
+            exposed_faces: 0,
+            solid_elevation: None,
+            deep: 0,
+            deep_void: 0,
+        }
+    }
+
+    fn add(&mut self, stats: &ChunkStats) {
+        self.chunks += 1;
+        for (&material, &count) in Material::VALUES.iter().zip(&stats.materials) {
+            if count > 0 {
+                *self.materials.entry(material).or_default() += u64::from(count);
+            }
+        }
+        self.solid_elevation = match (self.solid_elevation, stats.solid_elevation) {
+            (Some(a), Some(b)) => Some(a.merge(self.solid, b, u64::from(stats.solid))),
+            (a, b) => a.or(b),
+        };
+        self.solid += u64::from(stats.solid);
+        self.exposed_faces += u64::from(stats.exposed_faces);
+        self.deep += u64::from(stats.deep);
+        self.deep_void += u64::from(stats.deep_void);
+    }
+
+    /// Fraction of deep voxels that are void, if any are deep
+    pub fn deep_void_fraction(&self) -> Option<f64> {
+        if self.deep == 0 {
+            return None;
+        }
+        Some(self.deep_void as f64 / self.deep as f64)
+    }
+}
+
+/// The bucket of the node containing the chunk generated from `params`, in multiples of
+/// `BUCKET_WIDTH`
+fn bucket(params: &ChunkParams) -> (i32, i32) {
+    let enviro = &params.state.enviro;
+    (
+        (enviro.temperature / BUCKET_WIDTH).floor() as i32,
+        (enviro.rainfall / BUCKET_WIDTH).floor() as i32,
+    )
+}
+
+/// Generate and summarize every chunk of the nodes within `radius` of the origin
+fn measure(
+    dimension: u8,
+    radius: f64,
+    passes: &[TerrainPassKind],
+) -> Vec<(ChunkId, ChunkParams, ChunkStats)> {
+    let mut graph = Graph::new(dimension);
+    // Chunks on the edge of the region depend on nodes beyond it
+    ensure_nearby(
+        &mut graph,
+        &Position::origin(),
+        radius + 2.0 * dodeca::BOUNDING_SPHERE_RADIUS_F64,
+    );
+    populate_fresh_nodes(&mut graph);
+    nearby_nodes(&graph, &Position::origin(), radius)
+        .into_iter()
+        .flat_map(|(node, _)| Vertex::iter().map(move |vertex| ChunkId::new(node, vertex)))
+        .filter_map(|chunk| {
+            let params = ChunkParams::new(dimension, &graph, chunk, passes)?;
+            let stats = ChunkStats::generate(&params);
+            Some((chunk, params, stats))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK_SIZE: u8 = 12;
+    const RADIUS: f64 = 1.5;
+
+    /// Placement by the `y` coordinate, with voxels of the lowest layer deep
+    fn by_layer(coords: Coords) -> Placement {
+        let elevation = f64::from(coords.0[1]);
+        Placement {
+            elevation,
+            depth: DEEP + 0.5 - elevation,
+        }
+    }
+
+    #[test]
+    fn dense_voxels() {
+        let mut voxels = VoxelData::Solid(Material::Void);
+        voxels.data_mut(2)[Coords([0, 0, 0]).to_index(2)] = Material::Dirt;
+        voxels.data_mut(2)[Coords([1, 1, 0]).to_index(2)] = Material::Sand;
+        let stats = ChunkStats::from_voxels(&voxels, 2, by_layer);
+        assert_eq!(stats.materials[usize::from(Material::Void.id())], 6);
+        assert_eq!(stats.materials[usize::from(Material::Dirt.id())], 1);
+        assert_eq!(stats.materials[usize::from(Material::Sand.id())], 1);
+        assert_eq!(stats.solid, 2);
+        // Each solid voxel has three faces towards void within the chunk
+        assert_eq!(stats.exposed_faces, 6);
+        assert_eq!(
+            stats.solid_elevation,
+            Some(Span {
+                min: 0.0,
+                max: 1.0,
+                mean: 0.5
+            })
+        );
+        assert_eq!((stats.deep, stats.deep_void), (4, 3));
+    }
+
+    #[test]
+    fn solid_voxels() {
+        let stats = ChunkStats::from_voxels(&VoxelData::Solid(Material::Dirt), 2, by_layer);
+        assert_eq!(stats.materials[usize::from(Material::Dirt.id())], 8);
+        assert_eq!(stats.solid, 8);
+        assert_eq!(stats.exposed_faces, 0);
+        assert_eq!((stats.deep, stats.deep_void), (4, 0));
+
+        let stats = ChunkStats::from_voxels(&VoxelData::Solid(Material::Void), 2, by_layer);
+        assert_eq!(stats.solid, 0);
+        assert_eq!(stats.solid_elevation, None);
+        assert_eq!((stats.deep, stats.deep_void), (4, 4));
+    }
+
+    #[test]
+    fn survey_is_deterministic() {
+        let a = Survey::run(CHUNK_SIZE, RADIUS, &TerrainPassKind::DEFAULT);
+        let b = Survey::run(CHUNK_SIZE, RADIUS, &TerrainPassKind::DEFAULT);
+        assert!(a.chunks > 0);
+        assert_eq!(
+            a.buckets.iter().map(|x| x.chunks).sum::<u32>(),
+            a.chunks,
+            "every chunk is in a bucket"
+        );
+        assert_eq!(
+            serde_json::to_string(&a).unwrap(),
+            serde_json::to_string(&b).unwrap()
+        );
+    }
+
+    #[test]
+    fn surface_stays_within_terracing_reach() {
+        // Terracing moves the surface at most `TERRACING_REACH` from where the highest max
+        // elevation of the surrounding nodes puts it
+        let mut surfaces = 0;
+        for (chunk, params, stats) in measure(CHUNK_SIZE, RADIUS, &[TerrainPassKind::Terrain]) {
+            let Some(elevation) = stats.solid_elevation else {
+                continue;
+            };
+            let highest = params
+                .env
+                .max_elevations
+                .iter()
+                .copied()
+                .fold(f64::NEG_INFINITY, f64::max);
+            assert!(
+                elevation.max <= highest / TERRAIN_SMOOTHNESS + TERRACING_REACH + 1e-9,
+                "{chunk:?} has ground at {} beyond the bound of {}",
+                elevation.max,
+                highest / TERRAIN_SMOOTHNESS + TERRACING_REACH,
+            );
+            surfaces += usize::from(stats.solid < u32::from(CHUNK_SIZE).pow(3));
+        }
+        assert!(surfaces > 0, "no chunk contains the surface");
+    }
+
+    #[test]
+    fn world_is_not_hollow() {
+        // There are no caves yet, so nothing deep is void. The lower bound of this range should
+        // be raised once there are.
+        let survey = Survey::run(CHUNK_SIZE, RADIUS, &[TerrainPassKind::Terrain]);
+        let deep = survey.buckets.iter().map(|x| x.deep).sum::<u64>();
+        let deep_void = survey.buckets.iter().map(|x| x.deep_void).sum::<u64>();
+        assert!(deep > 0);
+        let fraction = deep_void as f64 / deep as f64;
+        assert!((0.0..=0.0).contains(&fraction), "{fraction} of depths void");
+        for bucket in &survey.buckets {
+            assert!(bucket.deep_void_fraction().map_or(true, |x| x == 0.0));
+        }
+    }
+
+    #[test]
+    fn only_the_road_tunnels() {
+        for (chunk, params, stats) in measure(CHUNK_SIZE, RADIUS, &TerrainPassKind::DEFAULT) {
+            if stats.deep_void > 0 {
+                assert!(params.is_road, "{chunk:?} is hollow without a road");
+            }
+        }
+    }
+
+    #[test]
+    fn road_materials_only_appear_with_the_road() {
+        let mut road = 0;
+        for (chunk, params, stats) in measure(CHUNK_SIZE, RADIUS, &TerrainPassKind::DEFAULT) {
+            let bricks = stats.materials[usize::from(Material::WhiteBrick.id())]
+                + stats.materials[usize::from(Material::GreyBrick.id())];
+            let planks = stats.materials[usize::from(Material::WoodPlanks.id())];
+            assert!(bricks == 0 || params.is_road, "{chunk:?} has stray bricks");
+            assert!(
+                planks == 0 || params.is_road_support,
+                "{chunk:?} has stray planks"
+            );
+            road += bricks;
+        }
+        assert!(road > 0, "no road was generated");
+    }
+
+    #[test]
+    fn trees_only_grow_where_it_rains() {
+        let survey = Survey::run(CHUNK_SIZE, RADIUS, &TerrainPassKind::DEFAULT);
+        for bucket in &survey.buckets {
+            if bucket.rainfall < 0.0 {
+                assert!(
+                    !bucket.materials.contains_key(&Material::Wood)
+                        && !bucket.materials.contains_key(&Material::Leaves),
+                    "trees grew with rainfall below {}",
+                    bucket.rainfall + BUCKET_WIDTH
+                );
+            }
+        }
+    }
+}
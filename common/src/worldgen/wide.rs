@@ -0,0 +1,304 @@
+//! Terrain evaluated for several voxels at once
+//!
+//! The per-voxel terrain math is written once over `Lanes`, fixed-size arrays whose arithmetic
+//! the compiler turns into vector instructions. Each lane performs exactly the operations the
+//! scalar path does, in the same order, so both produce the same bits. In particular, Rust never
+//! contracts a multiplication and an addition into a fused multiply-add unless `mul_add` is
+//! called explicitly, which neither path does, so enabling wider instruction sets can't change
+//! rounding. Functions of libm are applied lane by lane for the same reason.
+
+use std::{
+    array,
+    ops::{Add, Div, Mul, Sub},
+};
+
+use super::{ChunkIncidentEnviroFactors, TERRAIN_SMOOTHNESS};
+
+/// Voxels evaluated together along the wide path, enough to fill an AVX2 register
+const WIDE_LANES: usize = 4;
+
+/// How the terrain of each chunk is evaluated
+///
+/// Every path generates identical voxels; they differ only in speed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WorldgenPath {
+    /// One voxel at a time, the reference the others are held to
+    Scalar,
+    /// Several voxels at a time, using AVX2 where the CPU supports it
+    Wide,
+}
+
+impl WorldgenPath {
+    /// The fastest path on this machine
+    pub fn fastest() -> Self {
+        Self::Wide
+    }
+
+    /// Short name of the path, including the instruction set it runs on
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Scalar => "scalar",
+            Self::Wide if avx2() => "avx2",
+            Self::Wide => "wide",
+        }
+    }
+}
+
+fn avx2() -> bool {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        is_x86_feature_detected!("avx2")
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        false
+    }
+}
+
+/// Per-voxel quantities of a chunk's terrain, each in its own buffer so that consecutive voxels
+/// load into lanes together
+///
+/// Buffers are padded to a whole number of lanes. Whatever is computed for the padding is ignored.
+pub(super) struct TerrainBuffers {
+    // Inputs
+    /// Chunk coordinates of each voxel's center
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+    pub z: Vec<f64>,
+    /// Signed distance from the terrain's reference surface to each voxel's center
+    pub elevation: Vec<f64>,
+    /// Noise added to rainfall, temperature, and elevation, in the order it was drawn
+    pub rain_noise: Vec<f64>,
+    pub temp_noise: Vec<f64>,
+    pub elev_noise: Vec<f64>,
+
+    // Outputs
+    pub elev: Vec<f64>,
+    pub rain: Vec<f64>,
+    pub temp: Vec<f64>,
+    /// Distance below the terrain surface, negative above it
+    pub dist: Vec<f64>,
+}
+
+impl TerrainBuffers {
+    /// Buffers for `voxels` voxels, zeroed
+    pub fn new(voxels: usize) -> Self {
+        let len = voxels.next_multiple_of(WIDE_LANES);
+        let buffer = || vec![0.0; len];
+        Self {
+            x: buffer(),
+            y: buffer(),
+            z: buffer(),
+            elevation: buffer(),
+            rain_noise: buffer(),
+            temp_noise: buffer(),
+            elev_noise: buffer(),
+            elev: buffer(),
+            rain: buffer(),
+            temp: buffer(),
+            dist: buffer(),
+        }
+    }
+
+    /// Compute the outputs of every voxel from its inputs and the chunk's environmental factors
+    pub fn evaluate(&mut self, env: &ChunkIncidentEnviroFactors) {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        if avx2() {
+            // SAFETY: the CPU was just found to support AVX2
+            unsafe { self.evaluate_avx2(env) };
+            return;
+        }
+        self.evaluate_lanes(env);
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[target_feature(enable = "avx2")]
+    unsafe fn evaluate_avx2(&mut self, env: &ChunkIncidentEnviroFactors) {
+        self.evaluate_lanes(env);
+    }
+
+    #[inline(always)]
+    fn evaluate_lanes(&mut self, env: &ChunkIncidentEnviroFactors) {
+        for start in (0..self.x.len()).step_by(WIDE_LANES) {
+            self.evaluate_at::<WIDE_LANES>(env, start);
+        }
+    }
+
+    /// Mirrors `ChunkGenContext::generate_terrain_voxels_scalar` for the voxels from `start`
+    #[inline(always)]
+    fn evaluate_at<const N: usize>(&mut self, env: &ChunkIncidentEnviroFactors, start: usize) {
+        let load = |buffer: &[f64]| Lanes::<N>::load(buffer, start);
+        let one = Lanes::splat(1.0);
+
+        // As in `ChunkGenContext::biome`
+        let half = Lanes::splat(0.5);
+        let t = [load(&self.x), load(&self.y), load(&self.z)].map(|x| (one - x) * half);
+        let max_elevation = trilerp(&env.max_elevations, t);
+        let temperature = trilerp(&env.temperatures, t);
+        let rainfall = trilerp(&env.rainfalls, t);
+        let blockiness = trilerp(&env.blockinesses, t);
+
+        let rain = rainfall + load(&self.rain_noise);
+        let temp = temperature + load(&self.temp_noise);
+
+        let voxel_elevation = load(&self.elevation);
+        let strength = Lanes::splat(0.4) / (one + voxel_elevation * voxel_elevation);
+        let terracing_small = terracing_diff(max_elevation, blockiness, 5.0, strength, 2.0);
+        let terracing_big = terracing_diff(max_elevation, blockiness, 15.0, strength, -1.0);
+        let elev_pre_noise =
+            max_elevation + Lanes::splat(0.6) * terracing_small + Lanes::splat(0.4) * terracing_big;
+
+        let smoothness = Lanes::splat(TERRAIN_SMOOTHNESS);
+        let dist_pre_noise = elev_pre_noise / smoothness - voxel_elevation;
+        let elev = elev_pre_noise + smoothness * load(&self.elev_noise);
+        let dist = Lanes::select(
+            dist_pre_noise.gt(Lanes::splat(0.0)),
+            (elev / smoothness - voxel_elevation).map(|x| x.max(0.0)),
+            dist_pre_noise,
+        );
+
+        elev.store(&mut self.elev, start);
+        rain.store(&mut self.rain, start);
+        temp.store(&mut self.temp, start);
+        dist.store(&mut self.dist, start);
+    }
+}
+
+/// `super::trilerp` across lanes
+#[inline(always)]
+fn trilerp<const N: usize>(
+    &[v000, v001, v010, v011, v100, v101, v110, v111]: &[f64; 8],
+    [tx, ty, tz]: [Lanes<N>; 3],
+) -> Lanes<N> {
+    #[inline(always)]
+    fn lerp<const N: usize>(v0: f64, v1: f64, t: Lanes<N>) -> Lanes<N> {
+        lerp_lanes(Lanes::splat(v0), Lanes::splat(v1), t)
+    }
+    #[inline(always)]
+    fn lerp_lanes<const N: usize>(v0: Lanes<N>, v1: Lanes<N>, t: Lanes<N>) -> Lanes<N> {
+        v0 * (Lanes::splat(1.0) - t) + v1 * t
+    }
+    #[inline(always)]
+    fn bilerp<const N: usize>(
+        v00: f64,
+        v01: f64,
+        v10: f64,
+        v11: f64,
+        tx: Lanes<N>,
+        ty: Lanes<N>,
+    ) -> Lanes<N> {
+        lerp_lanes(lerp(v00, v01, tx), lerp(v10, v11, tx), ty)
+    }
+
+    lerp_lanes(
+        bilerp(v000, v100, v010, v110, tx, ty),
+        bilerp(v001, v101, v011, v111, tx, ty),
+        tz,
+    )
+}
+
+/// `super::serp` across lanes
+#[inline(always)]
+fn serp<const N: usize>(v0: Lanes<N>, v1: Lanes<N>, t: Lanes<N>, threshold: Lanes<N>) -> Lanes<N> {
+    let one = Lanes::splat(1.0);
+    let upper = one - threshold;
+    let s = (t - threshold) / (upper - threshold);
+    let between = v0 * (one - s) + v1 * s;
+    Lanes::select(t.lt(threshold), v0, Lanes::select(t.lt(upper), between, v1))
+}
+
+/// `super::terracing_diff` across lanes
+#[inline(always)]
+fn terracing_diff<const N: usize>(
+    elev_raw: Lanes<N>,
+    block: Lanes<N>,
+    scale: f64,
+    strength: Lanes<N>,
+    limiter: f64,
+) -> Lanes<N> {
+    let scale = Lanes::splat(scale);
+    let threshold =
+        strength / (Lanes::splat(1.0) + (Lanes::splat(limiter) - block).map(|x| libm::pow(2.0, x)));
+    let elev_floor = (elev_raw / scale).map(libm::floor);
+    let elev_rem = elev_raw / scale - elev_floor;
+    scale * elev_floor + serp(Lanes::splat(0.0), scale, elev_rem, threshold) - elev_raw
+}
+
+/// A value for each of `N` voxels
+#[derive(Copy, Clone)]
+struct Lanes<const N: usize>([f64; N]);
+
+impl<const N: usize> Lanes<N> {
+    #[inline(always)]
+    fn splat(x: f64) -> Self {
+        Self([x; N])
+    }
+
+    #[inline(always)]
+    fn load(buffer: &[f64], start: usize) -> Self {
+        Self(buffer[start..start + N].try_into().unwrap())
+    }
+
+    #[inline(always)]
+    fn store(self, buffer: &mut [f64], start: usize) {
+        buffer[start..start + N].copy_from_slice(&self.0);
+    }
+
+    #[inline(always)]
+    fn map(self, f: impl Fn(f64) -> f64) -> Self {
+        Self(self.0.map(f))
+    }
+
+    #[inline(always)]
+    fn zip(self, other: Self, f: impl Fn(f64, f64) -> f64) -> Self {
+        Self(array::from_fn(|i| f(self.0[i], other.0[i])))
+    }
+
+    #[inline(always)]
+    fn lt(self, other: Self) -> [bool; N] {
+        array::from_fn(|i| self.0[i] < other.0[i])
+    }
+
+    #[inline(always)]
+    fn gt(self, other: Self) -> [bool; N] {
+        array::from_fn(|i| self.0[i] > other.0[i])
+    }
+
+    /// `a` in lanes where `mask` holds, otherwise `b`
+    #[inline(always)]
+    fn select(mask: [bool; N], a: Self, b: Self) -> Self {
+        Self(array::from_fn(|i| if mask[i] { a.0[i] } else { b.0[i] }))
+    }
+}
+
+impl<const N: usize> Add for Lanes<N> {
+    type Output = Self;
+    #[inline(always)]
+    fn add(self, rhs: Self) -> Self {
+        self.zip(rhs, |a, b| a + b)
+    }
+}
+
+impl<const N: usize> Sub for Lanes<N> {
+    type Output = Self;
+    #[inline(always)]
+    fn sub(self, rhs: Self) -> Self {
+        self.zip(rhs, |a, b| a - b)
+    }
+}
+
+impl<const N: usize> Mul for Lanes<N> {
+    type Output = Self;
+    #[inline(always)]
+    fn mul(self, rhs: Self) -> Self {
+        self.zip(rhs, |a, b| a * b)
+    }
+}
+
+impl<const N: usize> Div for Lanes<N> {
+    type Output = Self;
+    #[inline(always)]
+    fn div(self, rhs: Self) -> Self {
+        self.zip(rhs, |a, b| a / b)
+    }
+}
@@ -0,0 +1,260 @@
+//! Recently generated chunk data that isn't currently part of a graph
+
+use fxhash::FxHashMap;
+
+use crate::{
+    dodeca::Vertex,
+    graph::Graph,
+    lru_slab::SlotId,
+    node::{Chunk, ChunkId, VoxelData},
+    LruSlab,
+};
+
+/// Identifies the output of world generation for a chunk, independent of any particular `Graph`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    /// Hash of the path to the containing node
+    pub node: u128,
+    pub vertex: Vertex,
+    /// Number of voxels along an edge
+    pub dimension: u8,
+}
+
+impl ChunkKey {
+    pub fn new(graph: &Graph, chunk: ChunkId) -> Self {
+        Self {
+            node: graph.hash_of(chunk.node),
+            vertex: chunk.vertex,
+            dimension: graph.layout().dimension(),
+        }
+    }
+}
+
+/// Bounded store of generated voxel data, so that chunks which are dropped and then requested
+/// again shortly afterwards needn't be regenerated
+///
+/// Only unmodified chunks are stored, since anything else can't be reproduced by world generation
+/// and must be persisted instead. When the total size of stored voxel data would exceed the
+/// configured limit, the least recently stored entries are discarded.
+pub struct WorldgenCache {
+    entries: LruSlab<Entry>,
+    index: FxHashMap<ChunkKey, SlotId>,
+    /// Total size of stored voxel data
+    bytes: usize,
+    max_bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+struct Entry {
+    key: ChunkKey,
+    voxels: VoxelData,
+}
+
+impl WorldgenCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: LruSlab::new(),
+            index: FxHashMap::default(),
+            bytes: 0,
+            max_bytes,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Remove and return the voxel data stored for `key`, if any
+    pub fn take(&mut self, key: &ChunkKey) -> Option<VoxelData> {
+        let Some(slot) = self.index.remove(key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        let entry = self.entries.remove(slot);
+        self.bytes -= voxel_bytes(&entry.voxels);
+        Some(entry.voxels)
+    }
+
+    /// Store freshly generated voxel data for `key`
+    pub fn insert(&mut self, key: ChunkKey, voxels: VoxelData) {
+        if let Some(slot) = self.index.remove(&key) {
+            let old = self.entries.remove(slot);
+            self.bytes -= voxel_bytes(&old.voxels);
+        }
+        let size = voxel_bytes(&voxels);
+        if size > self.max_bytes {
+            return;
+        }
+        while self.bytes + size > self.max_bytes {
+            let lru = self.entries.lru().expect("nonzero size implies an entry");
+            let old = self.entries.remove(lru);
+            self.index.remove(&old.key);
+            self.bytes -= voxel_bytes(&old.voxels);
+        }
+        self.bytes += size;
+        let slot = self.entries.insert(Entry { key, voxels });
+        self.index.insert(key, slot);
+    }
+
+    /// Store the data of a chunk removed from a graph, if it had finished generating
+    ///
+    /// The chunk must be unmodified, lest its edits be handed out again in place of freshly
+    /// generated data after newer edits were made elsewhere.
+    pub fn evict(&mut self, key: ChunkKey, chunk: Chunk) {
+        if let Chunk::Populated {
+            voxels, modified, ..
+        } = chunk
+        {
+            assert!(!modified, "modified chunk offered to the worldgen cache");
+            self.insert(key, voxels);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Total size of stored voxel data
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Number of calls to `take` that found data
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Number of calls to `take` that found nothing
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+/// Approximate memory occupied by `voxels`
+fn voxel_bytes(voxels: &VoxelData) -> usize {
+    std::mem::size_of::<VoxelData>()
+        + match *voxels {
+            VoxelData::Solid(_) => 0,
+            VoxelData::Dense(ref data) => std::mem::size_of_val(&data[..]),
+            VoxelData::Shaped(ref data, ref shapes) => {
+                std::mem::size_of_val(&data[..]) + std::mem::size_of_val(&shapes[..])
+            }
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dodeca,
+        graph::NodeId,
+        node::populate_fresh_nodes,
+        proto::Position,
+        traversal::ensure_nearby,
+        world::Material,
+        worldgen::{ChunkParams, TerrainPassKind},
+    };
+
+    const DIMENSION: u8 = 4;
+
+    fn dense(material: Material) -> VoxelData {
+        VoxelData::Dense(vec![material; (usize::from(DIMENSION) + 2).pow(3)].into())
+    }
+
+    fn key(vertex: Vertex) -> ChunkKey {
+        ChunkKey {
+            node: 0,
+            vertex,
+            dimension: DIMENSION,
+        }
+    }
+
+    #[test]
+    fn request_evict_request() {
+        let mut graph = Graph::new(DIMENSION);
+        ensure_nearby(
+            &mut graph,
+            &Position::origin(),
+            2.0 * dodeca::BOUNDING_SPHERE_RADIUS_F64,
+        );
+        populate_fresh_nodes(&mut graph);
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let key = ChunkKey::new(&graph, chunk);
+        let mut cache = WorldgenCache::new(1 << 20);
+
+        // First request misses and generates
+        assert!(cache.take(&key).is_none());
+        let params = ChunkParams::new(DIMENSION, &graph, chunk, &TerrainPassKind::DEFAULT).unwrap();
+        graph.populate_chunk(chunk, params.generate_voxels(), false);
+
+        // Eviction retains the data
+        let evicted = graph.evict_chunk(chunk);
+        cache.evict(key, evicted);
+        assert_eq!(cache.len(), 1);
+
+        // Second request hits
+        let voxels = cache.take(&key).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert!(cache.is_empty());
+        assert_eq!(cache.bytes(), 0);
+        let expected = params.generate_voxels();
+        let (voxels, expected) = (voxels.view(DIMENSION), expected.view(DIMENSION));
+        assert!(MarginCoords::all(DIMENSION).all(|x| voxels.get(x) == expected.get(x)));
+    }
+
+    #[test]
+    fn unpopulated_chunks_are_not_cached() {
+        let mut cache = WorldgenCache::new(1 << 20);
+        cache.evict(key(Vertex::B), Chunk::Generating);
+        cache.evict(key(Vertex::C), Chunk::Fresh);
+        assert!(cache.is_empty());
+        assert!(cache.take(&key(Vertex::B)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "modified chunk offered to the worldgen cache")]
+    fn modified_chunks_are_not_cached() {
+        let mut cache = WorldgenCache::new(1 << 20);
+        cache.evict(
+            key(Vertex::A),
+            Chunk::Populated {
+                voxels: dense(Material::Dirt),
+                modified: true,
+                generation: 0,
+                surface: None,
+                old_surface: None,
+                occupancy: Default::default(),
+            },
+        );
+    }
+
+    #[test]
+    fn byte_bound() {
+        let entry_size = voxel_bytes(&dense(Material::Void));
+        let mut cache = WorldgenCache::new(2 * entry_size);
+        cache.insert(key(Vertex::A), dense(Material::Dirt));
+        cache.insert(key(Vertex::B), dense(Material::Sand));
+        assert_eq!(cache.bytes(), 2 * entry_size);
+        cache.insert(key(Vertex::C), dense(Material::Wood));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.bytes() <= 2 * entry_size);
+        // Least recently stored entry was discarded
+        assert!(cache.take(&key(Vertex::A)).is_none());
+        assert!(cache.take(&key(Vertex::B)).is_some());
+        assert!(cache.take(&key(Vertex::C)).is_some());
+
+        // Replacing an entry doesn't double-count it
+        cache.insert(key(Vertex::A), dense(Material::Dirt));
+        cache.insert(key(Vertex::A), dense(Material::Sand));
+        assert_eq!(cache.bytes(), entry_size);
+
+        // Entries that could never fit are dropped
+        let mut cache = WorldgenCache::new(entry_size - 1);
+        cache.insert(key(Vertex::A), dense(Material::Dirt));
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+//! Memory accounting is process-wide, so these tests run in a process of their own, one at a time,
+//! where nothing else allocates voxels behind their backs
+
+use std::{collections::HashSet, sync::Mutex};
+
+use common::{
+    dodeca::Vertex,
+    graph::{Graph, NodeId},
+    math,
+    mem_budget::{self, DENSE_VOXELS, GRAPH},
+    node::{populate_fresh_nodes, Chunk, ChunkId, CoordAxis, CoordDirection, Coords, VoxelData},
+    proto::Position,
+    traversal::{ensure_nearby, nearby_nodes},
+    world::{Material, Shape},
+};
+
+const DIMENSION: u8 = 4;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn padded_len() -> usize {
+    (usize::from(DIMENSION) + 2).pow(3)
+}
+
+fn graph() -> Graph {
+    let mut graph = Graph::new(DIMENSION);
+    ensure_nearby(&mut graph, &Position::origin(), 2.0);
+    populate_fresh_nodes(&mut graph);
+    graph
+}
+
+/// Dense data that isn't a single material
+fn varied() -> VoxelData {
+    VoxelData::Dense(
+        (0..padded_len())
+            .map(|i| {
+                if i % 2 == 0 {
+                    Material::Dirt
+                } else {
+                    Material::Void
+                }
+            })
+            .collect::<Vec<_>>()
+            .into(),
+    )
+}
+
+/// Dense data that could have been `Solid`
+fn uniform() -> VoxelData {
+    VoxelData::Dense(vec![Material::Sand; padded_len()].into())
+}
+
+#[test]
+fn counters_return_to_baseline() {
+    let _guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dense = padded_len() * std::mem::size_of::<Material>();
+    let shapes = padded_len() * std::mem::size_of::<Shape>();
+    let (dense_baseline, graph_baseline) = (DENSE_VOXELS.bytes(), GRAPH.bytes());
+
+    let mut graph = graph();
+    assert!(GRAPH.bytes() > graph_baseline);
+    let a = ChunkId::new(NodeId::ROOT, Vertex::A);
+
+    // Editing a solid chunk makes it dense, and giving a voxel a shape adds shapes
+    graph.populate_chunk(a, VoxelData::Solid(Material::Dirt), false);
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline);
+    graph.set_block(a, Coords([1, 2, 3]), Material::Sand, Shape::FULL);
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline + dense);
+    let slab = Shape::slab(CoordAxis::Y, CoordDirection::Minus);
+    graph.set_block(a, Coords([1, 2, 3]), Material::Sand, slab);
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline + dense + shapes);
+
+    // Undoing the edits leaves the data dense until it's solidified
+    graph.set_block(a, Coords([1, 2, 3]), Material::Dirt, Shape::FULL);
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline + dense + shapes);
+    let Chunk::Populated { ref mut voxels, .. } = graph[a] else {
+        unreachable!();
+    };
+    assert!(voxels.solidify(DIMENSION));
+    assert!(!voxels.solidify(DIMENSION));
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline);
+
+    // Replacing and evicting chunks frees their data once it's dropped
+    graph.populate_chunk(a, varied(), false);
+    graph.populate_chunk(a, varied(), false);
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline + dense);
+    let evicted = graph.evict_chunk(a);
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline + dense);
+    drop(evicted);
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline);
+
+    // Data being loaded is counted apart until it's handed over
+    let loads_baseline = mem_budget::CHUNK_LOADS.bytes();
+    let mut loading = varied();
+    loading.transfer(&mem_budget::CHUNK_LOADS);
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline);
+    assert_eq!(mem_budget::CHUNK_LOADS.bytes(), loads_baseline + dense);
+    loading.transfer(&DENSE_VOXELS);
+    graph.populate_chunk(a, loading, false);
+    assert_eq!(mem_budget::CHUNK_LOADS.bytes(), loads_baseline);
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline + dense);
+
+    drop(graph);
+    assert_eq!(DENSE_VOXELS.bytes(), dense_baseline);
+    assert_eq!(GRAPH.bytes(), graph_baseline);
+}
+
+#[test]
+fn soft_budget_solidifies_before_evicting() {
+    let _guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let dense = padded_len() * std::mem::size_of::<Material>();
+    let baseline = DENSE_VOXELS.bytes();
+
+    let mut graph = graph();
+    let center = Position::origin();
+    let mut chunks = nearby_nodes(&graph, &center, 2.0)
+        .into_iter()
+        .flat_map(|(node, transform)| {
+            Vertex::iter().map(move |vertex| {
+                let chunk_center = math::lorentz_normalize(
+                    &(transform
+                        * vertex.chunk_to_node_f32()
+                        * nalgebra::Vector4::new(0.5, 0.5, 0.5, 1.0)),
+                );
+                (
+                    ChunkId::new(node, vertex),
+                    math::distance(&math::origin(), &chunk_center),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    chunks.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+    // Varied chunks both nearest and farthest, so that it's clear which go first
+    let (uniform_chunks, modified_chunks) = (&chunks[3..7], &chunks[7..9]);
+    let (near_chunks, far_chunks) = (&chunks[..3], &chunks[chunks.len() - 3..]);
+    assert!(near_chunks[2].1 < far_chunks[0].1);
+    for &(chunk, _) in uniform_chunks {
+        graph.populate_chunk(chunk, uniform(), false);
+    }
+    for &(chunk, _) in modified_chunks {
+        graph.populate_chunk(chunk, varied(), true);
+    }
+    for &(chunk, _) in near_chunks.iter().chain(far_chunks) {
+        graph.populate_chunk(chunk, varied(), false);
+    }
+    assert_eq!(DENSE_VOXELS.bytes(), baseline + 12 * dense);
+
+    // Within budget, nothing is done
+    let reclaimed =
+        mem_budget::reclaim_dense_voxels(&mut graph, &center, 0.0, baseline + 12 * dense);
+    assert_eq!(reclaimed.solidified, 0);
+    assert!(reclaimed.evicted.is_empty());
+
+    // Solidifying is enough to get back within budget, so nothing is evicted
+    let reclaimed =
+        mem_budget::reclaim_dense_voxels(&mut graph, &center, 0.0, baseline + 8 * dense);
+    assert_eq!(reclaimed.solidified, uniform_chunks.len());
+    assert!(reclaimed.evicted.is_empty());
+    assert_eq!(DENSE_VOXELS.bytes(), baseline + 8 * dense);
+    for &(chunk, _) in uniform_chunks {
+        assert!(matches!(
+            graph[chunk],
+            Chunk::Populated {
+                voxels: VoxelData::Solid(Material::Sand),
+                ..
+            }
+        ));
+    }
+
+    // Otherwise the farthest unmodified chunks go
+    let reclaimed =
+        mem_budget::reclaim_dense_voxels(&mut graph, &center, 0.0, baseline + 5 * dense);
+    assert_eq!(reclaimed.solidified, 0);
+    assert_eq!(reclaimed.evicted.len(), 3);
+    assert_eq!(DENSE_VOXELS.bytes(), baseline + 5 * dense);
+    assert_eq!(
+        reclaimed.evicted.iter().collect::<HashSet<_>>(),
+        far_chunks.iter().map(|x| &x.0).collect::<HashSet<_>>()
+    );
+    for &(chunk, _) in far_chunks {
+        assert!(matches!(graph[chunk], Chunk::Fresh));
+    }
+
+    // Modified chunks can't be regenerated, so they stay however far over budget
+    let reclaimed = mem_budget::reclaim_dense_voxels(&mut graph, &center, 0.0, baseline);
+    assert_eq!(reclaimed.evicted.len(), near_chunks.len());
+    for &(chunk, _) in modified_chunks {
+        assert!(matches!(graph[chunk], Chunk::Populated { .. }));
+    }
+    assert_eq!(DENSE_VOXELS.bytes(), baseline + 2 * dense);
+}
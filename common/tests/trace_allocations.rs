@@ -0,0 +1,107 @@
+//! Collision tracing must cost nothing when it's disabled, so characters that aren't traced must not
+//! allocate on its account
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use common::{
+    character_controller::{run_character_step, CollisionTrace},
+    dodeca::Vertex,
+    graph::Graph,
+    node::{populate_fresh_nodes, Chunk, ChunkId, VoxelData},
+    proto::{CharacterInput, MovementInput, Position},
+    traversal::{ensure_nearby, nearby_nodes},
+    world::Material,
+    SimConfig, SimConfigRaw,
+};
+
+/// Counts the allocations made by each thread, so that tests running in parallel don't disturb
+/// each other
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Allocations made while the thread is being torn down needn't be counted
+        let _ = ALLOCATIONS.try_with(|x| x.set(x.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Number of allocations made by `f` on this thread
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn disabled_tracing_allocates_nothing() {
+    // Without gravity, a character at rest in open space moves through every stage of a step
+    // without sweeping its sphere through the world, which would allocate for reasons of its own
+    let mut raw = SimConfigRaw::default();
+    raw.character.gravity_acceleration = Some(0.0);
+    let cfg = SimConfig::from_raw(&raw);
+    let mut graph = Graph::new(cfg.chunk_size);
+    ensure_nearby(&mut graph, &Position::origin(), 2.0);
+    populate_fresh_nodes(&mut graph);
+    for (node, _) in nearby_nodes(&graph, &Position::origin(), 2.0) {
+        for vertex in Vertex::iter() {
+            graph[ChunkId::new(node, vertex)] = Chunk::Populated {
+                voxels: VoxelData::Solid(Material::Void),
+                modified: false,
+                generation: 0,
+                surface: None,
+                old_surface: None,
+                occupancy: Default::default(),
+            };
+        }
+    }
+    let input = CharacterInput {
+        movement: MovementInput::zero(),
+        jump: false,
+        no_clip: false,
+        block_update: None,
+        block_fill: None,
+        use_target: None,
+        throw: None,
+        external: Default::default(),
+    };
+
+    let mut position = Position::origin();
+    let mut velocity = nalgebra::Vector3::zeros();
+    let mut on_ground = false;
+    let mut step = |trace: Option<&mut CollisionTrace>| {
+        allocations(|| {
+            run_character_step(
+                &cfg,
+                &graph,
+                &mut position,
+                &mut velocity,
+                &mut on_ground,
+                &input,
+                &mut 0.0,
+                cfg.step_interval.as_secs_f32(),
+                trace,
+            );
+        })
+    };
+    assert_eq!(step(None), 0);
+
+    // The same step traced does allocate, so the count isn't vacuous
+    let mut trace = CollisionTrace::new(1);
+    assert!(step(Some(&mut trace)) > 0);
+    assert_eq!(trace.steps().len(), 1);
+}
@@ -11,6 +11,8 @@ fn save(c: &mut Criterion) {
         chunks: vec![save::Chunk {
             vertex: 0,
             voxels: vec![0; 12 * 12 * 12 * 2],
+            edit_generation: 0,
+            block_entities: Vec::new(),
         }],
     };
     let mut rng = SmallRng::from_entropy();
@@ -49,7 +51,7 @@ fn save(c: &mut Criterion) {
             b.iter_batched(
                 || {
                     let file = tempfile::NamedTempFile::new().unwrap();
-                    let mut save = Save::open(file.path(), 12).unwrap();
+                    let save = Save::open(file.path(), 12).unwrap();
                     let node_ids = (&mut rng)
                         .sample_iter(rand::distributions::Standard)
                         .take(count as usize)
@@ -0,0 +1,349 @@
+//! Write-ahead log protecting the save from interrupted writes
+//!
+//! Each batch of changes is first written to its own segment file and made durable, then applied
+//! to the save. Segments are numbered consecutively, and an index file records the range of
+//! segments which were completely written but may not yet have been applied. The index is
+//! replaced atomically, so however abruptly the process stops, opening the journal again brings
+//! the save up to date by applying the segments it lists.
+//!
+//! A segment consists of the little-endian `u64` length of its payload, the little-endian CRC-32
+//! of the payload, and the payload itself, which is a compressed `Segment` message. A segment
+//! whose contents don't match its length or checksum was torn mid-write, so it and any later
+//! segments are discarded.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use prost::Message;
+use thiserror::Error;
+
+use crate::{
+    cctx, dctx, decompress, prepare, Batch, GetError, IdentifiedEntity, IdentifiedTask,
+    NamedCharacter, NamedProtectedRegion, NamedWaypoint, NodeChunk, NodeEntities, Save, Segment,
+    SequencedEdit,
+};
+
+const INDEX: &str = "index";
+const INDEX_TEMP: &str = "index.tmp";
+const SEGMENT_EXTENSION: &str = "segment";
+/// Size of a segment's length and checksum
+const HEADER_SIZE: usize = 12;
+
+pub struct Journal {
+    dir: PathBuf,
+    /// First segment that may not have been applied to the save
+    checkpoint: u64,
+    /// Number of the next segment to be written
+    head: u64,
+}
+
+impl Journal {
+    /// Directory conventionally used for the journal of the save at `save_path`
+    pub fn dir_for(save_path: &Path) -> PathBuf {
+        let mut dir = save_path.as_os_str().to_owned();
+        dir.push(".journal");
+        dir.into()
+    }
+
+    /// Open or create the journal in `dir`, applying any intact segments it holds to `save`
+    pub fn open(dir: &Path, save: &mut Save) -> Result<(Self, Recovery), JournalError> {
+        fs::create_dir_all(dir)?;
+        let (checkpoint, head) = read_index(&dir.join(INDEX))?;
+        let mut journal = Self {
+            dir: dir.into(),
+            checkpoint,
+            head,
+        };
+
+        let mut next = checkpoint;
+        while next < head {
+            let Some(batch) = journal.read_segment(next)? else {
+                break;
+            };
+            save.apply(&batch)?;
+            if let Some(meta) = batch.meta {
+                save.meta = meta;
+            }
+            next += 1;
+        }
+        let recovery = Recovery {
+            replayed: next - checkpoint,
+            discarded: head - next,
+        };
+
+        journal.checkpoint = next;
+        journal.head = next;
+        journal.write_index()?;
+        journal.remove_stray_segments()?;
+        Ok((journal, recovery))
+    }
+
+    /// Durably record `batch` in a new segment, to be applied to the save by `checkpoint`
+    pub fn append(&mut self, batch: &Batch) -> Result<(), JournalError> {
+        let mut plain = Vec::new();
+        let mut compressed = Vec::new();
+        prepare(&mut cctx(), &mut plain, &mut compressed, &to_segment(batch));
+
+        let mut file = fs::File::create(self.segment_path(self.head))?;
+        file.write_all(&(compressed.len() as u64).to_le_bytes())?;
+        file.write_all(&crc32fast::hash(&compressed).to_le_bytes())?;
+        file.write_all(&compressed)?;
+        file.sync_all()?;
+
+        self.head += 1;
+        if let Err(e) = self.write_index() {
+            self.head -= 1;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Apply every recorded segment to `save`, then discard them
+    pub fn checkpoint(&mut self, save: &Save) -> Result<(), JournalError> {
+        while self.checkpoint < self.head {
+            let segment = self.checkpoint;
+            let batch = self
+                .read_segment(segment)?
+                .ok_or(JournalError::Corrupt(segment))?;
+            save.apply(&batch)?;
+            self.checkpoint += 1;
+            // The segment must not be listed once it's gone, or it'd look torn
+            self.write_index()?;
+            fs::remove_file(self.segment_path(segment))?;
+        }
+        Ok(())
+    }
+
+    /// Number of segments written but not yet applied
+    pub fn pending(&self) -> u64 {
+        self.head - self.checkpoint
+    }
+
+    fn segment_path(&self, segment: u64) -> PathBuf {
+        self.dir
+            .join(format!("{segment:016x}"))
+            .with_extension(SEGMENT_EXTENSION)
+    }
+
+    /// Read a segment, or `None` if it's missing or torn
+    fn read_segment(&self, segment: u64) -> Result<Option<Batch>, JournalError> {
+        let data = match fs::read(self.segment_path(segment)) {
+            Ok(x) => x,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if data.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        let (header, payload) = data.split_at(HEADER_SIZE);
+        let len = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let checksum = u32::from_le_bytes(header[8..].try_into().unwrap());
+        if len != payload.len() as u64 || checksum != crc32fast::hash(payload) {
+            return Ok(None);
+        }
+
+        let mut plain = Vec::new();
+        decompress(&mut dctx(), payload, &mut plain).map_err(|_| JournalError::Corrupt(segment))?;
+        let decoded = Segment::decode(&*plain).map_err(|_| JournalError::Corrupt(segment))?;
+        from_segment(decoded)
+            .map(Some)
+            .ok_or(JournalError::Corrupt(segment))
+    }
+
+    /// Replace the index, such that it's either entirely old or entirely new if interrupted
+    fn write_index(&self) -> io::Result<()> {
+        let temp = self.dir.join(INDEX_TEMP);
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(&self.checkpoint.to_le_bytes())?;
+        file.write_all(&self.head.to_le_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, self.dir.join(INDEX))?;
+        sync_dir(&self.dir)
+    }
+
+    /// Remove segments outside the range listed by the index, left by interrupted writes
+    fn remove_stray_segments(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|x| x.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let number = path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .and_then(|x| u64::from_str_radix(x, 16).ok());
+            if number.map_or(true, |x| x < self.checkpoint || x >= self.head) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What was found when opening a journal
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Recovery {
+    /// Number of segments applied to the save
+    pub replayed: u64,
+    /// Number of segments discarded as torn
+    pub discarded: u64,
+}
+
+/// Read the checkpoint and head recorded in an index, which is empty if absent
+fn read_index(path: &Path) -> Result<(u64, u64), JournalError> {
+    let data = match fs::read(path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e.into()),
+    };
+    let data: [u8; 16] = data.try_into().map_err(|_| JournalError::MalformedIndex)?;
+    let checkpoint = u64::from_le_bytes(data[..8].try_into().unwrap());
+    let head = u64::from_le_bytes(data[8..].try_into().unwrap());
+    if checkpoint > head {
+        return Err(JournalError::MalformedIndex);
+    }
+    Ok((checkpoint, head))
+}
+
+/// Make renames within `dir` durable, where the platform allows it
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+fn to_segment(batch: &Batch) -> Segment {
+    Segment {
+        meta: batch.meta.clone(),
+        chunks: batch
+            .chunks
+            .iter()
+            .map(|(&(node_id, _), chunk)| NodeChunk {
+                node_high: (node_id >> 64) as u64,
+                node_low: node_id as u64,
+                chunk: Some(chunk.clone()),
+            })
+            .collect(),
+        entity_nodes: batch
+            .entity_nodes
+            .iter()
+            .map(|(&node_id, state)| NodeEntities {
+                node_high: (node_id >> 64) as u64,
+                node_low: node_id as u64,
+                entities: Some(state.clone()),
+            })
+            .collect(),
+        characters: batch
+            .characters
+            .iter()
+            .map(|(name, character)| NamedCharacter {
+                name: name.clone(),
+                character: Some(character.clone()),
+            })
+            .collect(),
+        waypoints: batch
+            .waypoints
+            .iter()
+            .map(|(name, waypoint)| NamedWaypoint {
+                name: name.clone(),
+                waypoint: waypoint.clone(),
+            })
+            .collect(),
+        protected_regions: batch
+            .protected_regions
+            .iter()
+            .map(|(name, region)| NamedProtectedRegion {
+                name: name.clone(),
+                region: region.clone(),
+            })
+            .collect(),
+        entities: batch
+            .entities
+            .iter()
+            .map(|(&id, entity)| IdentifiedEntity {
+                id,
+                entity: entity.clone(),
+            })
+            .collect(),
+        edits: batch
+            .edits
+            .iter()
+            .map(|(&sequence, edit)| SequencedEdit {
+                sequence,
+                edit: edit.clone(),
+            })
+            .collect(),
+        tasks: batch
+            .tasks
+            .iter()
+            .map(|(&id, task)| IdentifiedTask {
+                id,
+                task: task.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// Inverse of `to_segment`. Returns `None` if records are incomplete.
+fn from_segment(segment: Segment) -> Option<Batch> {
+    let node_id = |high: u64, low: u64| (u128::from(high) << 64) | u128::from(low);
+    let mut batch = Batch::new();
+    batch.meta = segment.meta;
+    for x in segment.chunks {
+        batch.put_chunk(node_id(x.node_high, x.node_low), x.chunk?);
+    }
+    for x in segment.entity_nodes {
+        batch.put_entity_node(node_id(x.node_high, x.node_low), x.entities?);
+    }
+    for x in segment.characters {
+        batch.put_character(x.name, x.character?);
+    }
+    for x in segment.waypoints {
+        match x.waypoint {
+            Some(waypoint) => batch.put_waypoint(x.name, waypoint),
+            None => batch.remove_waypoint(x.name),
+        }
+    }
+    for x in segment.protected_regions {
+        match x.region {
+            Some(region) => batch.put_protected_region(x.name, region),
+            None => batch.remove_protected_region(x.name),
+        }
+    }
+    for x in segment.entities {
+        match x.entity {
+            Some(entity) => batch.put_entity(x.id, entity),
+            None => batch.remove_entity(x.id),
+        }
+    }
+    for x in segment.edits {
+        match x.edit {
+            Some(edit) => batch.put_edit(x.sequence, edit),
+            None => batch.remove_edit(x.sequence),
+        }
+    }
+    for x in segment.tasks {
+        match x.task {
+            Some(task) => batch.put_task(x.id, task),
+            None => batch.remove_task(x.id),
+        }
+    }
+    Some(batch)
+}
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Save(#[from] GetError),
+    #[error("malformed journal index")]
+    MalformedIndex,
+    #[error("journal segment {0} is corrupt")]
+    Corrupt(u64),
+}
@@ -1,11 +1,13 @@
+mod journal;
 mod protos;
 
-use std::path::Path;
+use std::{collections::BTreeMap, path::Path};
 
 use prost::Message;
 use redb::{Database, ReadableTable, TableDefinition};
 use thiserror::Error;
 
+pub use journal::{Journal, JournalError, Recovery};
 pub use protos::*;
 
 pub struct Save {
@@ -34,6 +36,11 @@ impl Save {
                     // Must be an empty save file. Initialize the meta record and create the other tables.
                     let defaults = Meta {
                         chunk_size: default_chunk_size.into(),
+                        world_time: 0.0,
+                        next_entity_id: 0,
+                        entity_format: 0,
+                        steps: 0,
+                        task_format: 0,
                     };
                     init_meta_table(&db, &defaults)?;
                     defaults
@@ -45,6 +52,7 @@ impl Save {
         Ok(Self { meta, db })
     }
 
+    /// Metadata as of when the save was opened
     #[inline]
     pub fn meta(&self) -> &Meta {
         &self.meta
@@ -55,10 +63,18 @@ impl Save {
         Ok(ReaderGuard { tx })
     }
 
-    pub fn write(&mut self) -> Result<WriterGuard<'_>, DbError> {
+    pub fn write(&self) -> Result<WriterGuard<'_>, DbError> {
         let tx = self.db.begin_write().map_err(redb::Error::from)?;
         Ok(WriterGuard { tx })
     }
+
+    /// Write every record in `batch` in a single transaction
+    pub fn apply(&self, batch: &Batch) -> Result<(), GetError> {
+        let mut tx = self.write()?;
+        tx.get()?.apply(batch)?;
+        tx.commit()?;
+        Ok(())
+    }
 }
 
 fn init_meta_table(db: &Database, value: &Meta) -> Result<(), redb::Error> {
@@ -74,6 +90,11 @@ fn init_meta_table(db: &Database, value: &Meta) -> Result<(), redb::Error> {
     tx.open_table(VOXEL_NODE_TABLE)?;
     tx.open_table(ENTITY_NODE_TABLE)?;
     tx.open_table(CHARACTERS_BY_NAME_TABLE)?;
+    tx.open_table(WAYPOINTS_BY_NAME_TABLE)?;
+    tx.open_table(PROTECTED_REGIONS_BY_NAME_TABLE)?;
+    tx.open_table(ENTITIES_BY_ID_TABLE)?;
+    tx.open_table(EDITS_BY_SEQUENCE_TABLE)?;
+    tx.open_table(TASKS_BY_ID_TABLE)?;
     tx.commit()?;
     Ok(())
 }
@@ -88,6 +109,36 @@ impl ReaderGuard<'_> {
             voxel_nodes: self.tx.open_table(VOXEL_NODE_TABLE)?,
             entity_nodes: self.tx.open_table(ENTITY_NODE_TABLE)?,
             characters: self.tx.open_table(CHARACTERS_BY_NAME_TABLE)?,
+            // Saves from before waypoints existed lack the table until first written to
+            waypoints: match self.tx.open_table(WAYPOINTS_BY_NAME_TABLE) {
+                Ok(x) => Some(x),
+                Err(redb::TableError::TableDoesNotExist(_)) => None,
+                Err(e) => return Err(e.into()),
+            },
+            // Likewise for protected regions
+            protected_regions: match self.tx.open_table(PROTECTED_REGIONS_BY_NAME_TABLE) {
+                Ok(x) => Some(x),
+                Err(redb::TableError::TableDoesNotExist(_)) => None,
+                Err(e) => return Err(e.into()),
+            },
+            // And for entities other than characters
+            entities: match self.tx.open_table(ENTITIES_BY_ID_TABLE) {
+                Ok(x) => Some(x),
+                Err(redb::TableError::TableDoesNotExist(_)) => None,
+                Err(e) => return Err(e.into()),
+            },
+            // And for the edit history
+            edits: match self.tx.open_table(EDITS_BY_SEQUENCE_TABLE) {
+                Ok(x) => Some(x),
+                Err(redb::TableError::TableDoesNotExist(_)) => None,
+                Err(e) => return Err(e.into()),
+            },
+            // And for scheduled tasks
+            tasks: match self.tx.open_table(TASKS_BY_ID_TABLE) {
+                Ok(x) => Some(x),
+                Err(redb::TableError::TableDoesNotExist(_)) => None,
+                Err(e) => return Err(e.into()),
+            },
             dctx: dctx(),
             accum: Vec::new(),
         })
@@ -105,6 +156,11 @@ pub struct Reader<'a> {
     voxel_nodes: redb::ReadOnlyTable<'a, u128, &'static [u8]>,
     entity_nodes: redb::ReadOnlyTable<'a, u128, &'static [u8]>,
     characters: redb::ReadOnlyTable<'a, &'static str, &'static [u8]>,
+    waypoints: Option<redb::ReadOnlyTable<'a, &'static str, &'static [u8]>>,
+    protected_regions: Option<redb::ReadOnlyTable<'a, &'static str, &'static [u8]>>,
+    entities: Option<redb::ReadOnlyTable<'a, u64, &'static [u8]>>,
+    edits: Option<redb::ReadOnlyTable<'a, u64, &'static [u8]>>,
+    tasks: Option<redb::ReadOnlyTable<'a, u64, &'static [u8]>>,
     dctx: zstd::DCtx<'static>,
     accum: Vec<u8>,
 }
@@ -120,6 +176,20 @@ impl Reader<'_> {
         Ok(Some(VoxelNode::decode(&*self.accum)?))
     }
 
+    /// Whether voxels are saved for the node `node_id`, without reading them
+    pub fn has_voxel_node(&self, node_id: u128) -> Result<bool, DbError> {
+        Ok(self.voxel_nodes.get(&node_id)?.is_some())
+    }
+
+    /// IDs of every node with saved voxels, without reading the voxels themselves
+    pub fn voxel_node_ids(&self) -> Result<Vec<u128>, GetError> {
+        let mut result = Vec::new();
+        for entry in self.voxel_nodes.iter()? {
+            result.push(entry?.0.value());
+        }
+        Ok(result)
+    }
+
     pub fn get_entity_node(&mut self, node_id: u128) -> Result<Option<EntityNode>, GetError> {
         let Some(node) = self.entity_nodes.get(&node_id)? else {
             return Ok(None);
@@ -139,6 +209,89 @@ impl Reader<'_> {
             .map_err(GetError::DecompressionFailed)?;
         Ok(Some(Character::decode(&*self.accum)?))
     }
+
+    /// Every waypoint, with its name
+    pub fn get_waypoints(&mut self) -> Result<Vec<(String, Waypoint)>, GetError> {
+        let Some(ref waypoints) = self.waypoints else {
+            return Ok(Vec::new());
+        };
+        let mut result = Vec::new();
+        for entry in waypoints.iter()? {
+            let (name, waypoint) = entry?;
+            self.accum.clear();
+            decompress(&mut self.dctx, waypoint.value(), &mut self.accum)
+                .map_err(GetError::DecompressionFailed)?;
+            result.push((name.value().to_owned(), Waypoint::decode(&*self.accum)?));
+        }
+        Ok(result)
+    }
+
+    /// Every protected region, with its name
+    pub fn get_protected_regions(&mut self) -> Result<Vec<(String, ProtectedRegion)>, GetError> {
+        let Some(ref regions) = self.protected_regions else {
+            return Ok(Vec::new());
+        };
+        let mut result = Vec::new();
+        for entry in regions.iter()? {
+            let (name, region) = entry?;
+            self.accum.clear();
+            decompress(&mut self.dctx, region.value(), &mut self.accum)
+                .map_err(GetError::DecompressionFailed)?;
+            result.push((
+                name.value().to_owned(),
+                ProtectedRegion::decode(&*self.accum)?,
+            ));
+        }
+        Ok(result)
+    }
+
+    /// Every entity other than a character, with its ID
+    pub fn get_entities(&mut self) -> Result<Vec<(u64, Entity)>, GetError> {
+        let Some(ref entities) = self.entities else {
+            return Ok(Vec::new());
+        };
+        let mut result = Vec::new();
+        for entry in entities.iter()? {
+            let (id, entity) = entry?;
+            self.accum.clear();
+            decompress(&mut self.dctx, entity.value(), &mut self.accum)
+                .map_err(GetError::DecompressionFailed)?;
+            result.push((id.value(), Entity::decode(&*self.accum)?));
+        }
+        Ok(result)
+    }
+
+    /// Every edit remembered, with its sequence number, in the order they were made
+    pub fn get_edits(&mut self) -> Result<Vec<(u64, Edit)>, GetError> {
+        let Some(ref edits) = self.edits else {
+            return Ok(Vec::new());
+        };
+        let mut result = Vec::new();
+        for entry in edits.iter()? {
+            let (sequence, edit) = entry?;
+            self.accum.clear();
+            decompress(&mut self.dctx, edit.value(), &mut self.accum)
+                .map_err(GetError::DecompressionFailed)?;
+            result.push((sequence.value(), Edit::decode(&*self.accum)?));
+        }
+        Ok(result)
+    }
+
+    /// Every task yet to be done, with its ID
+    pub fn get_tasks(&mut self) -> Result<Vec<(u64, Task)>, GetError> {
+        let Some(ref tasks) = self.tasks else {
+            return Ok(Vec::new());
+        };
+        let mut result = Vec::new();
+        for entry in tasks.iter()? {
+            let (id, task) = entry?;
+            self.accum.clear();
+            decompress(&mut self.dctx, task.value(), &mut self.accum)
+                .map_err(GetError::DecompressionFailed)?;
+            result.push((id.value(), Task::decode(&*self.accum)?));
+        }
+        Ok(result)
+    }
 }
 
 fn decompress(
@@ -170,6 +323,7 @@ pub struct WriterGuard<'a> {
 impl<'a> WriterGuard<'a> {
     pub fn get(&mut self) -> Result<Writer<'a, '_>, DbError> {
         Ok(Writer {
+            meta: self.tx.open_table(META_TABLE).map_err(redb::Error::from)?,
             voxel_nodes: self
                 .tx
                 .open_table(VOXEL_NODE_TABLE)
@@ -182,9 +336,31 @@ impl<'a> WriterGuard<'a> {
                 .tx
                 .open_table(CHARACTERS_BY_NAME_TABLE)
                 .map_err(redb::Error::from)?,
+            waypoints: self
+                .tx
+                .open_table(WAYPOINTS_BY_NAME_TABLE)
+                .map_err(redb::Error::from)?,
+            protected_regions: self
+                .tx
+                .open_table(PROTECTED_REGIONS_BY_NAME_TABLE)
+                .map_err(redb::Error::from)?,
+            entities: self
+                .tx
+                .open_table(ENTITIES_BY_ID_TABLE)
+                .map_err(redb::Error::from)?,
+            edits: self
+                .tx
+                .open_table(EDITS_BY_SEQUENCE_TABLE)
+                .map_err(redb::Error::from)?,
+            tasks: self
+                .tx
+                .open_table(TASKS_BY_ID_TABLE)
+                .map_err(redb::Error::from)?,
             cctx: cctx(),
             plain: Vec::new(),
             compressed: Vec::new(),
+            dctx: dctx(),
+            accum: Vec::new(),
         })
     }
 
@@ -204,15 +380,30 @@ fn cctx() -> zstd::CCtx<'static> {
 }
 
 pub struct Writer<'save, 'guard> {
+    meta: redb::Table<'save, 'guard, &'static [u8], &'static [u8]>,
     voxel_nodes: redb::Table<'save, 'guard, u128, &'static [u8]>,
     entity_nodes: redb::Table<'save, 'guard, u128, &'static [u8]>,
     characters: redb::Table<'save, 'guard, &'static str, &'static [u8]>,
+    waypoints: redb::Table<'save, 'guard, &'static str, &'static [u8]>,
+    protected_regions: redb::Table<'save, 'guard, &'static str, &'static [u8]>,
+    entities: redb::Table<'save, 'guard, u64, &'static [u8]>,
+    edits: redb::Table<'save, 'guard, u64, &'static [u8]>,
+    tasks: redb::Table<'save, 'guard, u64, &'static [u8]>,
     cctx: zstd::CCtx<'static>,
     plain: Vec<u8>,
     compressed: Vec<u8>,
+    dctx: zstd::DCtx<'static>,
+    accum: Vec<u8>,
 }
 
 impl Writer<'_, '_> {
+    /// Replace the save's metadata. Takes effect for `Save::meta` when the save is next opened.
+    pub fn put_meta(&mut self, meta: &Meta) -> Result<(), DbError> {
+        prepare(&mut self.cctx, &mut self.plain, &mut self.compressed, meta);
+        self.meta.insert(&[][..], &*self.compressed)?;
+        Ok(())
+    }
+
     pub fn put_voxel_node(&mut self, node_id: u128, state: &VoxelNode) -> Result<(), DbError> {
         prepare(&mut self.cctx, &mut self.plain, &mut self.compressed, state);
         self.voxel_nodes.insert(node_id, &*self.compressed)?;
@@ -235,6 +426,319 @@ impl Writer<'_, '_> {
         self.characters.insert(name, &*self.compressed)?;
         Ok(())
     }
+
+    pub fn put_waypoint(&mut self, name: &str, waypoint: &Waypoint) -> Result<(), DbError> {
+        prepare(
+            &mut self.cctx,
+            &mut self.plain,
+            &mut self.compressed,
+            waypoint,
+        );
+        self.waypoints.insert(name, &*self.compressed)?;
+        Ok(())
+    }
+
+    pub fn remove_waypoint(&mut self, name: &str) -> Result<(), DbError> {
+        self.waypoints.remove(name)?;
+        Ok(())
+    }
+
+    pub fn put_protected_region(
+        &mut self,
+        name: &str,
+        region: &ProtectedRegion,
+    ) -> Result<(), DbError> {
+        prepare(
+            &mut self.cctx,
+            &mut self.plain,
+            &mut self.compressed,
+            region,
+        );
+        self.protected_regions.insert(name, &*self.compressed)?;
+        Ok(())
+    }
+
+    pub fn remove_protected_region(&mut self, name: &str) -> Result<(), DbError> {
+        self.protected_regions.remove(name)?;
+        Ok(())
+    }
+
+    pub fn put_entity(&mut self, id: u64, entity: &Entity) -> Result<(), DbError> {
+        prepare(
+            &mut self.cctx,
+            &mut self.plain,
+            &mut self.compressed,
+            entity,
+        );
+        self.entities.insert(id, &*self.compressed)?;
+        Ok(())
+    }
+
+    pub fn remove_entity(&mut self, id: u64) -> Result<(), DbError> {
+        self.entities.remove(id)?;
+        Ok(())
+    }
+
+    pub fn put_edit(&mut self, sequence: u64, edit: &Edit) -> Result<(), DbError> {
+        prepare(&mut self.cctx, &mut self.plain, &mut self.compressed, edit);
+        self.edits.insert(sequence, &*self.compressed)?;
+        Ok(())
+    }
+
+    pub fn remove_edit(&mut self, sequence: u64) -> Result<(), DbError> {
+        self.edits.remove(sequence)?;
+        Ok(())
+    }
+
+    pub fn put_task(&mut self, id: u64, task: &Task) -> Result<(), DbError> {
+        prepare(&mut self.cctx, &mut self.plain, &mut self.compressed, task);
+        self.tasks.insert(id, &*self.compressed)?;
+        Ok(())
+    }
+
+    pub fn remove_task(&mut self, id: u64) -> Result<(), DbError> {
+        self.tasks.remove(id)?;
+        Ok(())
+    }
+
+    /// Write every record in `batch`
+    ///
+    /// Chunks are merged into any voxels already saved for their nodes.
+    pub fn apply(&mut self, batch: &Batch) -> Result<(), GetError> {
+        if let Some(ref meta) = batch.meta {
+            self.put_meta(meta)?;
+        }
+        let mut chunks = batch.chunks.iter().peekable();
+        while let Some(&(&(node_id, _), _)) = chunks.peek() {
+            let mut record = self.get_voxel_node(node_id)?.unwrap_or_default();
+            while let Some((&(_, vertex), chunk)) = chunks.next_if(|&(&(x, _), _)| x == node_id) {
+                match record.chunks.iter_mut().find(|x| x.vertex == vertex) {
+                    Some(existing) => existing.clone_from(chunk),
+                    None => record.chunks.push(chunk.clone()),
+                }
+            }
+            self.put_voxel_node(node_id, &record)?;
+        }
+        for (&node_id, state) in &batch.entity_nodes {
+            self.put_entity_node(node_id, state)?;
+        }
+        for (name, character) in &batch.characters {
+            self.put_character(name, character)?;
+        }
+        for (name, waypoint) in &batch.waypoints {
+            match waypoint {
+                Some(waypoint) => self.put_waypoint(name, waypoint)?,
+                None => self.remove_waypoint(name)?,
+            }
+        }
+        for (name, region) in &batch.protected_regions {
+            match region {
+                Some(region) => self.put_protected_region(name, region)?,
+                None => self.remove_protected_region(name)?,
+            }
+        }
+        for (&id, entity) in &batch.entities {
+            match entity {
+                Some(entity) => self.put_entity(id, entity)?,
+                None => self.remove_entity(id)?,
+            }
+        }
+        for (&sequence, edit) in &batch.edits {
+            match edit {
+                Some(edit) => self.put_edit(sequence, edit)?,
+                None => self.remove_edit(sequence)?,
+            }
+        }
+        for (&id, task) in &batch.tasks {
+            match task {
+                Some(task) => self.put_task(id, task)?,
+                None => self.remove_task(id)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn get_voxel_node(&mut self, node_id: u128) -> Result<Option<VoxelNode>, GetError> {
+        let Some(node) = self.voxel_nodes.get(&node_id)? else {
+            return Ok(None);
+        };
+        self.accum.clear();
+        decompress(&mut self.dctx, node.value(), &mut self.accum)
+            .map_err(GetError::DecompressionFailed)?;
+        Ok(Some(VoxelNode::decode(&*self.accum)?))
+    }
+}
+
+/// Changes to be written to a save together
+///
+/// Records are identified by their keys, and writing a record that's already present replaces it,
+/// so a batch holds only the latest version of each record however often it's written.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Batch {
+    meta: Option<Meta>,
+    /// Individual chunks, by node ID and vertex
+    chunks: BTreeMap<(u128, u32), Chunk>,
+    entity_nodes: BTreeMap<u128, EntityNode>,
+    characters: BTreeMap<String, Character>,
+    /// Waypoints by name, or `None` for those removed
+    waypoints: BTreeMap<String, Option<Waypoint>>,
+    /// Protected regions by name, or `None` for those removed
+    protected_regions: BTreeMap<String, Option<ProtectedRegion>>,
+    /// Entities other than characters by ID, or `None` for those despawned
+    entities: BTreeMap<u64, Option<Entity>>,
+    /// Edits by sequence number, or `None` for those forgotten
+    edits: BTreeMap<u64, Option<Edit>>,
+    /// Tasks by ID, or `None` for those done or cancelled
+    tasks: BTreeMap<u64, Option<Task>>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put_meta(&mut self, meta: Meta) {
+        self.meta = Some(meta);
+    }
+
+    /// Replace a single chunk of a node's voxels, leaving any others saved for that node intact
+    pub fn put_chunk(&mut self, node_id: u128, chunk: Chunk) {
+        self.chunks.insert((node_id, chunk.vertex), chunk);
+    }
+
+    pub fn put_entity_node(&mut self, node_id: u128, state: EntityNode) {
+        self.entity_nodes.insert(node_id, state);
+    }
+
+    pub fn put_character(&mut self, name: String, character: Character) {
+        self.characters.insert(name, character);
+    }
+
+    pub fn put_waypoint(&mut self, name: String, waypoint: Waypoint) {
+        self.waypoints.insert(name, Some(waypoint));
+    }
+
+    pub fn remove_waypoint(&mut self, name: String) {
+        self.waypoints.insert(name, None);
+    }
+
+    pub fn put_protected_region(&mut self, name: String, region: ProtectedRegion) {
+        self.protected_regions.insert(name, Some(region));
+    }
+
+    pub fn remove_protected_region(&mut self, name: String) {
+        self.protected_regions.insert(name, None);
+    }
+
+    pub fn put_entity(&mut self, id: u64, entity: Entity) {
+        self.entities.insert(id, Some(entity));
+    }
+
+    pub fn remove_entity(&mut self, id: u64) {
+        self.entities.insert(id, None);
+    }
+
+    pub fn put_edit(&mut self, sequence: u64, edit: Edit) {
+        self.edits.insert(sequence, Some(edit));
+    }
+
+    pub fn remove_edit(&mut self, sequence: u64) {
+        self.edits.insert(sequence, None);
+    }
+
+    pub fn put_task(&mut self, id: u64, task: Task) {
+        self.tasks.insert(id, Some(task));
+    }
+
+    pub fn remove_task(&mut self, id: u64) {
+        self.tasks.insert(id, None);
+    }
+
+    /// Add the records of `later`, replacing any with the same keys
+    pub fn append(&mut self, later: Batch) {
+        if later.meta.is_some() {
+            self.meta = later.meta;
+        }
+        self.chunks.extend(later.chunks);
+        self.entity_nodes.extend(later.entity_nodes);
+        self.characters.extend(later.characters);
+        self.waypoints.extend(later.waypoints);
+        self.protected_regions.extend(later.protected_regions);
+        self.entities.extend(later.entities);
+        self.edits.extend(later.edits);
+        self.tasks.extend(later.tasks);
+    }
+
+    pub fn meta(&self) -> Option<&Meta> {
+        self.meta.as_ref()
+    }
+
+    /// Encoded voxels of a chunk, as in `Chunk::voxels`
+    pub fn chunk(&self, node_id: u128, vertex: u32) -> Option<&[u8]> {
+        self.chunks.get(&(node_id, vertex)).map(|x| &x.voxels[..])
+    }
+
+    /// Edits ever made to a chunk, as in `Chunk::edit_generation`
+    pub fn edit_generation(&self, node_id: u128, vertex: u32) -> Option<u32> {
+        self.chunks
+            .get(&(node_id, vertex))
+            .map(|x| x.edit_generation)
+    }
+
+    pub fn entity_node(&self, node_id: u128) -> Option<&EntityNode> {
+        self.entity_nodes.get(&node_id)
+    }
+
+    pub fn character(&self, name: &str) -> Option<&Character> {
+        self.characters.get(name)
+    }
+
+    /// The waypoint called `name`, which is `Some(None)` if it's to be removed
+    pub fn waypoint(&self, name: &str) -> Option<Option<&Waypoint>> {
+        self.waypoints.get(name).map(Option::as_ref)
+    }
+
+    /// The protected region called `name`, which is `Some(None)` if it's to be removed
+    pub fn protected_region(&self, name: &str) -> Option<Option<&ProtectedRegion>> {
+        self.protected_regions.get(name).map(Option::as_ref)
+    }
+
+    /// The entity with ID `id`, which is `Some(None)` if it's to be removed
+    pub fn entity(&self, id: u64) -> Option<Option<&Entity>> {
+        self.entities.get(&id).map(Option::as_ref)
+    }
+
+    /// The edit with sequence number `sequence`, which is `Some(None)` if it's to be forgotten
+    pub fn edit(&self, sequence: u64) -> Option<Option<&Edit>> {
+        self.edits.get(&sequence).map(Option::as_ref)
+    }
+
+    /// The task with ID `id`, which is `Some(None)` if it's to be removed
+    pub fn task(&self, id: u64) -> Option<Option<&Task>> {
+        self.tasks.get(&id).map(Option::as_ref)
+    }
+
+    /// Number of chunks in the batch
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Number of records in the batch
+    pub fn len(&self) -> usize {
+        usize::from(self.meta.is_some())
+            + self.chunks.len()
+            + self.entity_nodes.len()
+            + self.characters.len()
+            + self.waypoints.len()
+            + self.protected_regions.len()
+            + self.entities.len()
+            + self.edits.len()
+            + self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 /// Buffer the compressed, encoded form of `msg` in `compressed`
@@ -258,6 +762,14 @@ const VOXEL_NODE_TABLE: TableDefinition<u128, &[u8]> = TableDefinition::new("vox
 const ENTITY_NODE_TABLE: TableDefinition<u128, &[u8]> = TableDefinition::new("entity nodes");
 const CHARACTERS_BY_NAME_TABLE: TableDefinition<&str, &[u8]> =
     TableDefinition::new("characters by name");
+const WAYPOINTS_BY_NAME_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("waypoints by name");
+const PROTECTED_REGIONS_BY_NAME_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("protected regions by name");
+const ENTITIES_BY_ID_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("entities by id");
+const EDITS_BY_SEQUENCE_TABLE: TableDefinition<u64, &[u8]> =
+    TableDefinition::new("edits by sequence");
+const TASKS_BY_ID_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("tasks by id");
 
 #[derive(Debug, Error)]
 pub enum OpenError {
@@ -4,6 +4,24 @@ pub struct Meta {
     /// Number of voxels along the edge of a chunk
     #[prost(uint32, tag = "1")]
     pub chunk_size: u32,
+    /// Fraction of the day/night cycle elapsed, in \[0, 1)
+    #[prost(float, tag = "2")]
+    pub world_time: f32,
+    /// Lowest entity ID never issued, which is issued next. Zero in saves from before IDs were
+    /// issued in order.
+    #[prost(uint64, tag = "3")]
+    pub next_entity_id: u64,
+    /// Version of the encoding of `Entity.components`. Zero in saves from before entities other
+    /// than characters were saved.
+    #[prost(uint32, tag = "4")]
+    pub entity_format: u32,
+    /// Steps the server has run, over every run using this save. Zero in saves from before steps
+    /// were counted.
+    #[prost(uint64, tag = "5")]
+    pub steps: u64,
+    /// Version of the encoding of `Task.kind`. Zero in saves from before tasks were saved.
+    #[prost(uint32, tag = "6")]
+    pub task_format: u32,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -11,6 +29,25 @@ pub struct Character {
     /// Graph edges to traverse from the origin to find the node containing the character's entity
     #[prost(uint32, repeated, tag = "1")]
     pub path: ::prost::alloc::vec::Vec<u32>,
+    /// Transform from the character's frame to that of its node, as 16 floats in column-major
+    /// order, if its player is to return there. Empty for characters saved while their players
+    /// were present, or that are to start afresh.
+    #[prost(float, repeated, tag = "2")]
+    pub local: ::prost::alloc::vec::Vec<f32>,
+}
+/// An entity other than a character, saved by itself rather than with its node
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Entity {
+    /// Graph edges to traverse from the origin to find the node containing the entity
+    #[prost(uint32, repeated, tag = "1")]
+    pub path: ::prost::alloc::vec::Vec<u32>,
+    /// Transform from the entity's frame to that of its node, as 16 floats in column-major order
+    #[prost(float, repeated, tag = "2")]
+    pub local: ::prost::alloc::vec::Vec<f32>,
+    /// Components other than position, encoded as described by `Meta.entity_format`
+    #[prost(bytes = "vec", tag = "3")]
+    pub components: ::prost::alloc::vec::Vec<u8>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -47,9 +84,194 @@ pub struct Chunk {
     /// Which dodecahedron vertex is associated with this chunk
     #[prost(uint32, tag = "1")]
     pub vertex: u32,
-    /// Dense 3D array of 16-bit material tags for all voxels in this chunk
+    /// Dense 3D array of 16-bit material tags for all voxels in this chunk, or a single tag if all
+    /// voxels are the same
     #[prost(bytes = "vec", tag = "2")]
     pub voxels: ::prost::alloc::vec::Vec<u8>,
+    /// Number of edits ever made to this chunk, counting on from the saved value whenever it's
+    /// loaded again. Zero in saves from before edits were counted.
+    #[prost(uint32, tag = "3")]
+    pub edit_generation: u32,
+    /// Block entities attached to voxels of this chunk, as a postcard-encoded list of coordinates
+    /// and entities. Empty if there are none, as in saves from before block entities existed.
+    #[prost(bytes = "vec", tag = "4")]
+    pub block_entities: ::prost::alloc::vec::Vec<u8>,
+}
+/// Changes to the save recorded together in one segment of its journal
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Segment {
+    /// Replacement metadata, if any
+    #[prost(message, optional, tag = "1")]
+    pub meta: ::core::option::Option<Meta>,
+    #[prost(message, repeated, tag = "2")]
+    pub chunks: ::prost::alloc::vec::Vec<NodeChunk>,
+    #[prost(message, repeated, tag = "3")]
+    pub entity_nodes: ::prost::alloc::vec::Vec<NodeEntities>,
+    #[prost(message, repeated, tag = "4")]
+    pub characters: ::prost::alloc::vec::Vec<NamedCharacter>,
+    #[prost(message, repeated, tag = "5")]
+    pub waypoints: ::prost::alloc::vec::Vec<NamedWaypoint>,
+    #[prost(message, repeated, tag = "6")]
+    pub protected_regions: ::prost::alloc::vec::Vec<NamedProtectedRegion>,
+    #[prost(message, repeated, tag = "7")]
+    pub entities: ::prost::alloc::vec::Vec<IdentifiedEntity>,
+    #[prost(message, repeated, tag = "8")]
+    pub edits: ::prost::alloc::vec::Vec<SequencedEdit>,
+    #[prost(message, repeated, tag = "9")]
+    pub tasks: ::prost::alloc::vec::Vec<IdentifiedTask>,
+}
+/// A single chunk of a node's voxels
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NodeChunk {
+    /// Upper and lower halves of the node's ID
+    #[prost(fixed64, tag = "1")]
+    pub node_high: u64,
+    #[prost(fixed64, tag = "2")]
+    pub node_low: u64,
+    #[prost(message, optional, tag = "3")]
+    pub chunk: ::core::option::Option<Chunk>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NodeEntities {
+    /// Upper and lower halves of the node's ID
+    #[prost(fixed64, tag = "1")]
+    pub node_high: u64,
+    #[prost(fixed64, tag = "2")]
+    pub node_low: u64,
+    #[prost(message, optional, tag = "3")]
+    pub entities: ::core::option::Option<EntityNode>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NamedCharacter {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub character: ::core::option::Option<Character>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Waypoint {
+    /// Graph edges to traverse from the origin to find the node containing the waypoint
+    #[prost(uint32, repeated, tag = "1")]
+    pub path: ::prost::alloc::vec::Vec<u32>,
+    /// Translation from the origin of that node to the waypoint, in absolute units
+    #[prost(float, repeated, tag = "2")]
+    pub translation: ::prost::alloc::vec::Vec<f32>,
+    /// sRGB color of the waypoint's marker, as 0xRRGGBB
+    #[prost(uint32, tag = "3")]
+    pub color: u32,
+    /// Name of the character that created the waypoint
+    #[prost(string, tag = "4")]
+    pub owner: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NamedWaypoint {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    /// Absent if the waypoint was removed
+    #[prost(message, optional, tag = "2")]
+    pub waypoint: ::core::option::Option<Waypoint>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ProtectedRegion {
+    /// Graph edges to traverse from the origin to find the node at whose origin the region is
+    /// centered
+    #[prost(uint32, repeated, tag = "1")]
+    pub path: ::prost::alloc::vec::Vec<u32>,
+    /// Distance from the center within which blocks are protected, in meters
+    #[prost(float, tag = "2")]
+    pub radius: f32,
+    /// Names of the characters permitted to change blocks within the region
+    #[prost(string, repeated, tag = "3")]
+    pub allowed: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NamedProtectedRegion {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    /// Absent if the region was removed
+    #[prost(message, optional, tag = "2")]
+    pub region: ::core::option::Option<ProtectedRegion>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IdentifiedEntity {
+    #[prost(fixed64, tag = "1")]
+    pub id: u64,
+    /// Absent if the entity was despawned
+    #[prost(message, optional, tag = "2")]
+    pub entity: ::core::option::Option<Entity>,
+}
+/// A block changed by a player, kept so that administrators can undo it
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Edit {
+    /// Steps the server had run, over every run using this save, when the block was changed
+    #[prost(uint64, tag = "1")]
+    pub step: u64,
+    /// Name of the character that changed the block
+    #[prost(string, tag = "2")]
+    pub player: ::prost::alloc::string::String,
+    /// Upper and lower halves of the ID of the node containing the block
+    #[prost(fixed64, tag = "3")]
+    pub node_high: u64,
+    #[prost(fixed64, tag = "4")]
+    pub node_low: u64,
+    /// Which dodecahedron vertex is associated with the chunk containing the block
+    #[prost(uint32, tag = "5")]
+    pub vertex: u32,
+    /// Coordinates of the block within its chunk, as x | y << 8 | z << 16
+    #[prost(uint32, tag = "6")]
+    pub coords: u32,
+    /// Material tags and shapes of the block before and after the change
+    #[prost(uint32, tag = "7")]
+    pub old_material: u32,
+    #[prost(uint32, tag = "8")]
+    pub old_shape: u32,
+    #[prost(uint32, tag = "9")]
+    pub new_material: u32,
+    #[prost(uint32, tag = "10")]
+    pub new_shape: u32,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SequencedEdit {
+    /// Position of the edit in the order edits were made
+    #[prost(fixed64, tag = "1")]
+    pub sequence: u64,
+    /// Absent if the edit was forgotten
+    #[prost(message, optional, tag = "2")]
+    pub edit: ::core::option::Option<Edit>,
+}
+/// Something the server is to do once it has run a certain number of steps
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Task {
+    /// Steps the server will have run, over every run using this save, when the task is due
+    #[prost(uint64, tag = "1")]
+    pub fire_step: u64,
+    /// Steps between repetitions of the task, or zero if it's done once
+    #[prost(uint32, tag = "2")]
+    pub repeat: u32,
+    /// What the task does, encoded as described by `Meta.task_format`
+    #[prost(bytes = "vec", tag = "3")]
+    pub kind: ::prost::alloc::vec::Vec<u8>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IdentifiedTask {
+    #[prost(fixed64, tag = "1")]
+    pub id: u64,
+    /// Absent if the task was done or cancelled
+    #[prost(message, optional, tag = "2")]
+    pub task: ::core::option::Option<Task>,
 }
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
 #[repr(i32)]
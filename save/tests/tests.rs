@@ -1,6 +1,8 @@
+use std::fs;
+
 use rand::{rngs::SmallRng, Rng, SeedableRng};
 
-use save::Save;
+use save::{Batch, Journal, Recovery, Save};
 
 #[test]
 fn persist_meta() {
@@ -12,14 +14,44 @@ fn persist_meta() {
     assert_eq!(save.meta().chunk_size, 12);
 }
 
+#[test]
+fn update_meta() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let save = Save::open(file.path(), 12).unwrap();
+    assert_eq!(save.meta().world_time, 0.0);
+    let mut writer_guard = save.write().unwrap();
+    writer_guard
+        .get()
+        .unwrap()
+        .put_meta(&save::Meta {
+            chunk_size: 12,
+            world_time: 0.6,
+            next_entity_id: 42,
+            entity_format: 1,
+            steps: 1000,
+            task_format: 1,
+        })
+        .unwrap();
+    writer_guard.commit().unwrap();
+    drop(save);
+    let save = Save::open(file.path(), 8).unwrap();
+    assert_eq!(save.meta().chunk_size, 12);
+    assert_eq!(save.meta().world_time, 0.6);
+    assert_eq!(save.meta().next_entity_id, 42);
+    assert_eq!(save.meta().entity_format, 1);
+    assert_eq!(save.meta().steps, 1000);
+}
+
 #[test]
 fn persist_node() {
     let file = tempfile::NamedTempFile::new().unwrap();
-    let mut save = Save::open(file.path(), 12).unwrap();
+    let save = Save::open(file.path(), 12).unwrap();
     let node = save::VoxelNode {
         chunks: vec![save::Chunk {
             vertex: 0,
             voxels: vec![0; 12 * 12 * 12 * 2],
+            edit_generation: 3,
+            block_entities: Vec::new(),
         }],
     };
     let mut writer_guard = save.write().unwrap();
@@ -41,10 +73,38 @@ fn persist_node() {
     );
 }
 
+#[test]
+fn voxel_nodes_indexed() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let save = Save::open(file.path(), 12).unwrap();
+    assert!(save
+        .read()
+        .unwrap()
+        .get()
+        .unwrap()
+        .voxel_node_ids()
+        .unwrap()
+        .is_empty());
+    let mut batch = Batch::new();
+    for node_id in [7, 3, 1 << 100] {
+        batch.put_chunk(node_id, chunk(0, 1));
+    }
+    save.apply(&batch).unwrap();
+    let mut ids = save
+        .read()
+        .unwrap()
+        .get()
+        .unwrap()
+        .voxel_node_ids()
+        .unwrap();
+    ids.sort_unstable();
+    assert_eq!(ids, [3, 7, 1 << 100]);
+}
+
 #[test]
 fn persist_character() {
     let file = tempfile::NamedTempFile::new().unwrap();
-    let mut save = Save::open(file.path(), 12).unwrap();
+    let save = Save::open(file.path(), 12).unwrap();
     let mut writer_guard = save.write().unwrap();
     let mut writer = writer_guard.get().unwrap();
     let mut rng = SmallRng::from_entropy();
@@ -52,7 +112,10 @@ fn persist_character() {
     for _ in 0..17000 {
         path.push(rng.gen_range(0..12));
     }
-    let ch = save::Character { path };
+    let ch = save::Character {
+        path,
+        local: Vec::new(),
+    };
     writer.put_character("asdf", &ch).unwrap();
     drop(writer);
     writer_guard.commit().unwrap();
@@ -70,3 +133,381 @@ fn persist_character() {
             .unwrap()
     );
 }
+
+fn chunk(vertex: u32, material: u8) -> save::Chunk {
+    save::Chunk {
+        vertex,
+        voxels: vec![material, 0],
+        edit_generation: 0,
+        block_entities: Vec::new(),
+    }
+}
+
+#[test]
+fn batches_keep_latest_records() {
+    let mut batch = Batch::new();
+    for material in 1..=5 {
+        batch.put_chunk(7, chunk(0, material));
+    }
+    batch.put_character(
+        "a".into(),
+        save::Character {
+            path: vec![1],
+            local: Vec::new(),
+        },
+    );
+    assert_eq!(batch.chunk_count(), 1);
+    assert_eq!(batch.chunk(7, 0), Some(&[5, 0][..]));
+
+    let mut later = Batch::new();
+    later.put_chunk(7, chunk(0, 6));
+    later.put_chunk(7, chunk(1, 6));
+    later.put_character(
+        "a".into(),
+        save::Character {
+            path: vec![2],
+            local: Vec::new(),
+        },
+    );
+    batch.append(later);
+    assert_eq!(batch.len(), 3);
+    assert_eq!(batch.chunk(7, 0), Some(&[6, 0][..]));
+    assert_eq!(batch.character("a").unwrap().path, [2]);
+}
+
+#[test]
+fn chunks_merge_into_saved_nodes() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let save = Save::open(file.path(), 12).unwrap();
+    let mut batch = Batch::new();
+    batch.put_chunk(7, chunk(0, 1));
+    batch.put_chunk(7, chunk(1, 1));
+    save.apply(&batch).unwrap();
+
+    let mut batch = Batch::new();
+    batch.put_chunk(7, chunk(1, 2));
+    batch.put_chunk(7, chunk(2, 2));
+    save.apply(&batch).unwrap();
+
+    let mut node = save
+        .read()
+        .unwrap()
+        .get()
+        .unwrap()
+        .get_voxel_node(7)
+        .unwrap()
+        .unwrap();
+    node.chunks.sort_by_key(|x| x.vertex);
+    assert_eq!(node.chunks, [chunk(0, 1), chunk(1, 2), chunk(2, 2)]);
+}
+
+#[test]
+fn edit_generations_persist() {
+    let dir = tempfile::tempdir().unwrap();
+    let save_path = dir.path().join("test.save");
+    let journal_path = Journal::dir_for(&save_path);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    let (mut journal, _) = Journal::open(&journal_path, &mut save).unwrap();
+    let edited = |vertex, edit_generation| save::Chunk {
+        edit_generation,
+        ..chunk(vertex, 1)
+    };
+
+    // Checkpointed, then advanced by a batch only in the journal
+    let mut batch = Batch::new();
+    batch.put_chunk(7, edited(0, 4));
+    batch.put_chunk(7, edited(1, 9));
+    journal.append(&batch).unwrap();
+    journal.checkpoint(&save).unwrap();
+    let mut batch = Batch::new();
+    batch.put_chunk(7, edited(0, 5));
+    assert_eq!(batch.edit_generation(7, 0), Some(5));
+    assert_eq!(batch.edit_generation(7, 1), None);
+    journal.append(&batch).unwrap();
+
+    // Recovered from the journal, as after a crash
+    drop(journal);
+    drop(save);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    Journal::open(&journal_path, &mut save).unwrap();
+    drop(save);
+
+    let save = Save::open(&save_path, 12).unwrap();
+    let mut node = save
+        .read()
+        .unwrap()
+        .get()
+        .unwrap()
+        .get_voxel_node(7)
+        .unwrap()
+        .unwrap();
+    node.chunks.sort_by_key(|x| x.vertex);
+    assert_eq!(node.chunks, [edited(0, 5), edited(1, 9)]);
+}
+
+fn waypoint(path: Vec<u32>, owner: &str) -> save::Waypoint {
+    save::Waypoint {
+        path,
+        translation: vec![0.1, -0.2, 0.3],
+        color: 0xff8000,
+        owner: owner.into(),
+    }
+}
+
+#[test]
+fn persist_waypoints() {
+    let dir = tempfile::tempdir().unwrap();
+    let save_path = dir.path().join("test.save");
+    let journal_path = Journal::dir_for(&save_path);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    let (mut journal, _) = Journal::open(&journal_path, &mut save).unwrap();
+
+    let mut batch = Batch::new();
+    batch.put_waypoint(String::from("base"), waypoint(vec![1, 2], "a"));
+    batch.put_waypoint(String::from("big cave"), waypoint(vec![3], "b"));
+    batch.put_waypoint("gone".into(), waypoint(vec![], "a"));
+    journal.append(&batch).unwrap();
+    let mut batch = Batch::new();
+    batch.remove_waypoint("gone".into());
+    assert_eq!(batch.waypoint("gone"), Some(None));
+    journal.append(&batch).unwrap();
+
+    // Recovered from the journal, as after a crash
+    drop(journal);
+    drop(save);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    Journal::open(&journal_path, &mut save).unwrap();
+    drop(save);
+
+    let save = Save::open(&save_path, 12).unwrap();
+    let waypoints = save.read().unwrap().get().unwrap().get_waypoints().unwrap();
+    assert_eq!(
+        waypoints,
+        [
+            (String::from("base"), waypoint(vec![1, 2], "a")),
+            (String::from("big cave"), waypoint(vec![3], "b")),
+        ]
+    );
+}
+
+fn entity(path: Vec<u32>) -> save::Entity {
+    save::Entity {
+        path,
+        local: vec![0.0; 16],
+        components: vec![1, 2, 3],
+    }
+}
+
+#[test]
+fn persist_entities() {
+    let dir = tempfile::tempdir().unwrap();
+    let save_path = dir.path().join("test.save");
+    let journal_path = Journal::dir_for(&save_path);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    let (mut journal, _) = Journal::open(&journal_path, &mut save).unwrap();
+
+    let mut batch = Batch::new();
+    batch.put_entity(1, entity(vec![1, 2]));
+    batch.put_entity(2, entity(vec![]));
+    batch.put_entity(3, entity(vec![3]));
+    journal.append(&batch).unwrap();
+    journal.checkpoint(&save).unwrap();
+    let mut batch = Batch::new();
+    batch.remove_entity(2);
+    batch.remove_entity(4);
+    assert_eq!(batch.entity(2), Some(None));
+    journal.append(&batch).unwrap();
+
+    // Recovered from the journal, as after a crash
+    drop(journal);
+    drop(save);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    Journal::open(&journal_path, &mut save).unwrap();
+    drop(save);
+
+    let save = Save::open(&save_path, 12).unwrap();
+    let entities = save.read().unwrap().get().unwrap().get_entities().unwrap();
+    assert_eq!(entities, [(1, entity(vec![1, 2])), (3, entity(vec![3]))]);
+}
+
+fn edit(step: u64, player: &str) -> save::Edit {
+    save::Edit {
+        step,
+        player: player.into(),
+        node_high: 1,
+        node_low: 2,
+        vertex: 3,
+        coords: 4 | 5 << 8 | 6 << 16,
+        old_material: 0,
+        old_shape: 0,
+        new_material: 1,
+        new_shape: 2,
+    }
+}
+
+#[test]
+fn persist_edits() {
+    let dir = tempfile::tempdir().unwrap();
+    let save_path = dir.path().join("test.save");
+    let journal_path = Journal::dir_for(&save_path);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    let (mut journal, _) = Journal::open(&journal_path, &mut save).unwrap();
+
+    let mut batch = Batch::new();
+    batch.put_edit(0, edit(10, "a"));
+    batch.put_edit(1, edit(11, "b"));
+    batch.put_edit(2, edit(12, "a"));
+    journal.append(&batch).unwrap();
+    journal.checkpoint(&save).unwrap();
+    let mut batch = Batch::new();
+    batch.remove_edit(0);
+    assert_eq!(batch.edit(0), Some(None));
+    journal.append(&batch).unwrap();
+
+    // Recovered from the journal, as after a crash
+    drop(journal);
+    drop(save);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    Journal::open(&journal_path, &mut save).unwrap();
+    drop(save);
+
+    let save = Save::open(&save_path, 12).unwrap();
+    let edits = save.read().unwrap().get().unwrap().get_edits().unwrap();
+    assert_eq!(edits, [(1, edit(11, "b")), (2, edit(12, "a"))]);
+}
+
+#[test]
+fn persist_tasks() {
+    let dir = tempfile::tempdir().unwrap();
+    let save_path = dir.path().join("test.save");
+    let journal_path = Journal::dir_for(&save_path);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    let (mut journal, _) = Journal::open(&journal_path, &mut save).unwrap();
+    let task = |fire_step, repeat| save::Task {
+        fire_step,
+        repeat,
+        kind: vec![1, 2, 3],
+    };
+
+    let mut batch = Batch::new();
+    batch.put_task(0, task(100, 0));
+    batch.put_task(1, task(200, 50));
+    journal.append(&batch).unwrap();
+    journal.checkpoint(&save).unwrap();
+    let mut batch = Batch::new();
+    batch.remove_task(0);
+    batch.put_task(1, task(250, 50));
+    assert_eq!(batch.task(0), Some(None));
+    journal.append(&batch).unwrap();
+
+    // Recovered from the journal, as after a crash
+    drop(journal);
+    drop(save);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    Journal::open(&journal_path, &mut save).unwrap();
+    drop(save);
+
+    let save = Save::open(&save_path, 12).unwrap();
+    let tasks = save.read().unwrap().get().unwrap().get_tasks().unwrap();
+    assert_eq!(tasks, [(1, task(250, 50))]);
+}
+
+#[test]
+fn journal_discards_torn_segment() {
+    let dir = tempfile::tempdir().unwrap();
+    let save_path = dir.path().join("test.save");
+    let journal_path = Journal::dir_for(&save_path);
+    let mut save = Save::open(&save_path, 12).unwrap();
+    let (mut journal, recovery) = Journal::open(&journal_path, &mut save).unwrap();
+    assert_eq!(recovery, Recovery::default());
+
+    let mut first = Batch::new();
+    first.put_chunk(1, chunk(0, 1));
+    first.put_character(
+        "a".into(),
+        save::Character {
+            path: vec![1],
+            local: Vec::new(),
+        },
+    );
+    first.put_meta(save::Meta {
+        chunk_size: 12,
+        world_time: 0.5,
+        next_entity_id: 0,
+        entity_format: 0,
+        steps: 0,
+        task_format: 0,
+    });
+    journal.append(&first).unwrap();
+    let mut second = Batch::new();
+    second.put_chunk(2, chunk(0, 2));
+    second.put_character(
+        "b".into(),
+        save::Character {
+            path: vec![2],
+            local: Vec::new(),
+        },
+    );
+    journal.append(&second).unwrap();
+    assert_eq!(journal.pending(), 2);
+
+    // The process dies while writing the second segment, before anything reaches the save
+    drop(journal);
+    drop(save);
+    let mut segments = fs::read_dir(&journal_path)
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().is_some_and(|x| x == "segment"))
+        .collect::<Vec<_>>();
+    segments.sort();
+    assert_eq!(segments.len(), 2);
+    let torn = fs::OpenOptions::new()
+        .write(true)
+        .open(&segments[1])
+        .unwrap();
+    torn.set_len(torn.metadata().unwrap().len() / 2).unwrap();
+    drop(torn);
+
+    // Only the torn segment is lost
+    let mut save = Save::open(&save_path, 12).unwrap();
+    let (mut journal, recovery) = Journal::open(&journal_path, &mut save).unwrap();
+    assert_eq!(
+        recovery,
+        Recovery {
+            replayed: 1,
+            discarded: 1
+        }
+    );
+    assert_eq!(journal.pending(), 0);
+    assert_eq!(save.meta().world_time, 0.5);
+    {
+        let guard = save.read().unwrap();
+        let mut reader = guard.get().unwrap();
+        assert_eq!(
+            reader.get_voxel_node(1).unwrap().unwrap().chunks,
+            [chunk(0, 1)]
+        );
+        assert!(reader.get_character("a").unwrap().is_some());
+        assert!(reader.get_voxel_node(2).unwrap().is_none());
+        assert!(reader.get_character("b").unwrap().is_none());
+    }
+
+    // Writing continues normally, and checkpointed segments aren't replayed
+    journal.append(&second).unwrap();
+    journal.checkpoint(&save).unwrap();
+    assert_eq!(journal.pending(), 0);
+    assert_eq!(fs::read_dir(&journal_path).unwrap().count(), 1);
+    drop(journal);
+    assert_eq!(
+        Journal::open(&journal_path, &mut save).unwrap().1,
+        Recovery::default()
+    );
+    assert!(save
+        .read()
+        .unwrap()
+        .get()
+        .unwrap()
+        .get_character("b")
+        .unwrap()
+        .is_some());
+}
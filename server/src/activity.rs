@@ -0,0 +1,246 @@
+//! Which parts of the world are near enough to players to be worth simulating
+//!
+//! Activity is tracked for each region of the graph, as divided for sending to clients. A region is
+//! active while a character is within the wake distance of it, which reaches further than clients
+//! are told of, so anything frozen there resumes before it can be seen. Once no character is that
+//! near, a region cools for a while in case one comes back, and then hibernates until one does.
+//! Only characters changing nodes, or the graph growing around them, costs anything to track.
+
+use std::time::Duration;
+
+use fxhash::{FxHashMap, FxHashSet};
+use hecs::Entity;
+
+use common::{
+    dodeca,
+    graph::{Graph, NodeId},
+    proto::Position,
+    traversal::nearby_nodes,
+    Step,
+};
+
+use crate::{graph_regions::GraphRegions, stats::RegionActivityStats};
+
+/// Distance beyond a client's view within which regions are woken, in absolute units
+pub const WAKE_MARGIN: f64 = 2.0 * dodeca::BOUNDING_SPHERE_RADIUS_F64;
+
+/// How long a region stays cooling after the last character leaves it before it hibernates
+const COOLING_TIME: Duration = Duration::from_secs(30);
+
+/// How much simulation a region of the graph gets
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegionState {
+    /// Within the wake distance of a character
+    Active,
+    /// Active recently enough that a character is likely to return
+    Cooling,
+    /// Left alone until a character approaches
+    Hibernating,
+}
+
+pub struct RegionActivity {
+    /// Distance from the center of a character's node within which regions are active
+    wake_distance: f64,
+    /// Steps a region cools for before hibernating
+    cooling_steps: Step,
+    /// Node each character was in when the regions it keeps active were found, and those regions
+    characters: FxHashMap<Entity, (NodeId, Vec<NodeId>)>,
+    /// Number of characters keeping each active region active, by root
+    active: FxHashMap<NodeId, u32>,
+    /// Step each cooling region was last active, by root
+    cooling: FxHashMap<NodeId, Step>,
+    /// Number of nodes in the graph when the regions near characters were last found
+    graph_len: u32,
+}
+
+impl RegionActivity {
+    /// Track activity for clients that see `view_distance` around them, in steps of
+    /// `step_interval`
+    pub fn new(view_distance: f32, step_interval: Duration) -> Self {
+        Self {
+            // A character may be anywhere in its node
+            wake_distance: f64::from(view_distance)
+                + WAKE_MARGIN
+                + dodeca::BOUNDING_SPHERE_RADIUS_F64,
+            cooling_steps: (COOLING_TIME.as_secs_f64() / step_interval.as_secs_f64()).ceil()
+                as Step,
+            characters: FxHashMap::default(),
+            active: FxHashMap::default(),
+            cooling: FxHashMap::default(),
+            graph_len: 0,
+        }
+    }
+
+    /// Account for `characters` being in the given nodes as of `step`, forgetting any characters
+    /// not among them
+    pub fn update(
+        &mut self,
+        graph: &Graph,
+        regions: &GraphRegions,
+        step: Step,
+        characters: impl IntoIterator<Item = (Entity, NodeId)>,
+    ) {
+        // Nodes created since the last update may belong to regions no character has woken yet
+        let grown = graph.len() != self.graph_len;
+        self.graph_len = graph.len();
+        let mut present = FxHashSet::default();
+        for (entity, node) in characters {
+            present.insert(entity);
+            if !grown
+                && self
+                    .characters
+                    .get(&entity)
+                    .is_some_and(|&(x, _)| x == node)
+            {
+                continue;
+            }
+            let roots = nearby_nodes(
+                graph,
+                &Position {
+                    node,
+                    local: na::one(),
+                },
+                self.wake_distance,
+            )
+            .into_iter()
+            .map(|(node, _)| regions.root_of(graph, node))
+            .collect::<FxHashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+            // Woken first, so regions kept by both don't cool in between
+            for &root in &roots {
+                self.wake(root);
+            }
+            if let Some((_, old)) = self.characters.insert(entity, (node, roots)) {
+                for root in old {
+                    self.release(root, step);
+                }
+            }
+        }
+        let departed = self
+            .characters
+            .keys()
+            .copied()
+            .filter(|entity| !present.contains(entity))
+            .collect::<Vec<_>>();
+        for entity in departed {
+            let (_, roots) = self.characters.remove(&entity).unwrap();
+            for root in roots {
+                self.release(root, step);
+            }
+        }
+        let cooling_steps = self.cooling_steps;
+        self.cooling
+            .retain(|_, &mut since| step.wrapping_sub(since) < cooling_steps);
+    }
+
+    fn wake(&mut self, root: NodeId) {
+        *self.active.entry(root).or_default() += 1;
+        self.cooling.remove(&root);
+    }
+
+    fn release(&mut self, root: NodeId, step: Step) {
+        let count = self.active.get_mut(&root).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.active.remove(&root);
+            self.cooling.insert(root, step);
+        }
+    }
+
+    /// State of the region rooted at `root`
+    pub fn state(&self, root: NodeId) -> RegionState {
+        if self.active.contains_key(&root) {
+            RegionState::Active
+        } else if self.cooling.contains_key(&root) {
+            RegionState::Cooling
+        } else {
+            RegionState::Hibernating
+        }
+    }
+
+    /// Number of regions in each state, out of the `total` in the graph
+    pub fn stats(&self, total: usize) -> RegionActivityStats {
+        RegionActivityStats {
+            active: self.active.len(),
+            cooling: self.cooling.len(),
+            hibernating: total - self.active.len() - self.cooling.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{node_path::NodePath, traversal::ensure_nearby};
+
+    const STEP_INTERVAL: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn regions_cool_then_hibernate() {
+        let mut graph = Graph::new(12);
+        ensure_nearby(&mut graph, &Position::origin(), 4.0);
+        // Alternating between two non-adjacent sides leads straight away from the origin
+        let side = dodeca::Side::iter()
+            .find(|&side| side != dodeca::Side::A && !dodeca::Side::A.adjacent_to(side))
+            .unwrap();
+        let far = NodePath([dodeca::Side::A, side].repeat(4)).ensure(&mut graph);
+        ensure_nearby(
+            &mut graph,
+            &Position {
+                node: far,
+                local: na::one(),
+            },
+            4.0,
+        );
+        let regions = GraphRegions::new(2);
+        let origin = regions.root_of(&graph, NodeId::ROOT);
+        let distant = regions.root_of(&graph, far);
+        let entity = hecs::World::new().spawn(());
+        let mut activity = RegionActivity::new(1.0, STEP_INTERVAL);
+        activity.update(&graph, &regions, 0, [(entity, NodeId::ROOT)]);
+        assert_eq!(activity.state(origin), RegionState::Active);
+        assert_eq!(activity.state(distant), RegionState::Hibernating);
+
+        activity.update(&graph, &regions, 1, [(entity, far)]);
+        assert_eq!(activity.state(origin), RegionState::Cooling);
+        assert_eq!(activity.state(distant), RegionState::Active);
+        let cooling_steps = activity.cooling_steps;
+        activity.update(&graph, &regions, cooling_steps, [(entity, far)]);
+        assert_eq!(activity.state(origin), RegionState::Cooling);
+        activity.update(&graph, &regions, cooling_steps + 1, [(entity, far)]);
+        assert_eq!(activity.state(origin), RegionState::Hibernating);
+
+        // Returning wakes the region at once, and leaving altogether lets everything cool
+        activity.update(
+            &graph,
+            &regions,
+            cooling_steps + 2,
+            [(entity, NodeId::ROOT)],
+        );
+        assert_eq!(activity.state(origin), RegionState::Active);
+        assert_eq!(activity.state(distant), RegionState::Cooling);
+        activity.update(&graph, &regions, cooling_steps + 3, []);
+        assert_eq!(activity.state(origin), RegionState::Cooling);
+        let stats = activity.stats(1000);
+        assert_eq!(stats.active, 0);
+        assert!(stats.cooling >= 2);
+        assert_eq!(stats.cooling + stats.hibernating, 1000);
+    }
+
+    #[test]
+    fn shared_regions_stay_active() {
+        let mut graph = Graph::new(12);
+        ensure_nearby(&mut graph, &Position::origin(), 4.0);
+        let regions = GraphRegions::new(2);
+        let origin = regions.root_of(&graph, NodeId::ROOT);
+        let mut world = hecs::World::new();
+        let (a, b) = (world.spawn(()), world.spawn(()));
+        let mut activity = RegionActivity::new(1.0, STEP_INTERVAL);
+        activity.update(&graph, &regions, 0, [(a, NodeId::ROOT), (b, NodeId::ROOT)]);
+        activity.update(&graph, &regions, 1, [(b, NodeId::ROOT)]);
+        assert_eq!(activity.state(origin), RegionState::Active);
+        activity.update(&graph, &regions, 2, []);
+        assert_eq!(activity.state(origin), RegionState::Cooling);
+    }
+}
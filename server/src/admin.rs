@@ -0,0 +1,427 @@
+//! Administrative commands, whether sent by players' clients or typed at the server's console
+//!
+//! Every command is carried out by `Server::run_admin` on behalf of an `Actor`, which decides
+//! whether it's permitted and where "here" is for commands that act on a place.
+
+use std::{collections::BTreeSet, fmt};
+
+use hecs::Entity;
+use tracing::{error_span, info, warn};
+
+use common::{math, protection::ProtectedRegion, proto, waypoint::Waypoint};
+
+use crate::Server;
+
+/// Name under which commands typed at the server's console are recorded
+pub const CONSOLE_ACTOR: &str = "console";
+
+/// Who an administrative command is run for
+#[derive(Debug, Clone)]
+pub enum Actor {
+    /// The server's own console, which may do anything but isn't anywhere in the world
+    Console,
+    /// A connected player, acting through their character
+    Player {
+        name: String,
+        character: Entity,
+        /// Whether the player is listed as an administrator
+        admin: bool,
+    },
+}
+
+impl Actor {
+    /// Name recorded as the author of whatever the actor does
+    pub fn name(&self) -> &str {
+        match *self {
+            Actor::Console => CONSOLE_ACTOR,
+            Actor::Player { ref name, .. } => name,
+        }
+    }
+
+    /// Whether the actor may run commands reserved for administrators
+    pub fn is_admin(&self) -> bool {
+        match *self {
+            Actor::Console => true,
+            Actor::Player { admin, .. } => admin,
+        }
+    }
+
+    /// The character standing in for the actor in the world, if any
+    pub fn character(&self) -> Option<Entity> {
+        match *self {
+            Actor::Console => None,
+            Actor::Player { character, .. } => Some(character),
+        }
+    }
+}
+
+impl fmt::Display for Actor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// Something an administrator can have the server do
+#[derive(Debug, Clone)]
+pub enum AdminCommand {
+    /// Jump the day/night cycle to the given fraction of a day
+    SetWorldTime(f32),
+    /// Write the world to the save now, rather than at the next autosave
+    Save,
+    /// Save, disconnect every client, and exit
+    Stop,
+    /// Name every connected player
+    ListPlayers,
+    SetMovementModes {
+        character: String,
+        allowed: proto::MovementModes,
+    },
+    Teleport {
+        character: String,
+        destination: proto::TeleportDestination,
+    },
+    /// Teleport `character` to `place`
+    Summon {
+        character: String,
+        place: Place,
+    },
+    /// Share a waypoint, which is recorded as the actor's
+    SetWaypoint(Waypoint),
+    RemoveWaypoint(String),
+    SetProtectedRegion(ProtectedRegion),
+    /// Protect the region within `radius` meters of the origin of the node `center` lies in
+    ProtectRegion {
+        name: String,
+        radius: f32,
+        allowed: BTreeSet<String>,
+        center: Place,
+    },
+    RemoveProtectedRegion(String),
+    /// Record collisions over the latest `steps` steps of the named character, or stop if 0
+    TraceCollisions {
+        character: String,
+        steps: u32,
+    },
+    Rollback(proto::Rollback),
+    /// Undo changes made within the latest `seconds` by anyone within `radius` meters of `center`
+    RollbackRegion {
+        center: Place,
+        radius: f32,
+        seconds: u32,
+        force: bool,
+    },
+    /// Tell the actor's client of everything around the named character in place of its own
+    /// character's surroundings, or of its own character's again if `None`
+    Follow(Option<String>),
+}
+
+impl AdminCommand {
+    /// A short name for the command, for the record of who did what
+    pub fn name(&self) -> &'static str {
+        use AdminCommand::*;
+        match *self {
+            SetWorldTime(_) => "time",
+            Save => "save",
+            Stop => "stop",
+            ListPlayers => "players",
+            SetMovementModes { .. } => "movement modes",
+            Teleport { .. } | Summon { .. } => "teleport",
+            SetWaypoint(_) => "share waypoint",
+            RemoveWaypoint(_) => "remove waypoint",
+            SetProtectedRegion(_) | ProtectRegion { .. } => "protect region",
+            RemoveProtectedRegion(_) => "lift protection",
+            TraceCollisions { .. } => "trace collisions",
+            Rollback(_) | RollbackRegion { .. } => "rollback",
+            Follow(_) => "follow",
+        }
+    }
+}
+
+/// Where a command that acts on a place takes effect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Place {
+    /// Where the actor's character is
+    Here,
+    /// At the shared waypoint of this name
+    Waypoint(String),
+}
+
+/// Why an administrative command wasn't carried out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminError {
+    Forbidden,
+    /// The command acts where the actor is, but the actor isn't anywhere, as the console isn't.
+    /// Holds how to name the place instead.
+    Nowhere(&'static str),
+    NoSuchCharacter(String),
+    NoSuchWaypoint(String),
+    NoSuchRegion(String),
+    /// The command was understood, but couldn't be carried out for this reason
+    Failed(String),
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            AdminError::Forbidden => f.pad("only administrators may do that"),
+            AdminError::Nowhere(usage) => {
+                write!(f, "there's no \"here\" without a character; use {usage}")
+            }
+            AdminError::NoSuchCharacter(ref x) => write!(f, "no character named {x:?}"),
+            AdminError::NoSuchWaypoint(ref x) => write!(f, "no shared waypoint named {x:?}"),
+            AdminError::NoSuchRegion(ref x) => write!(f, "no protected region named {x:?}"),
+            AdminError::Failed(ref x) => f.pad(x),
+        }
+    }
+}
+
+impl std::error::Error for AdminError {}
+
+impl Server {
+    /// Carry out `command` for `actor`, logging what became of it under the actor's name, and
+    /// return a description of what was done
+    pub(crate) fn run_admin(
+        &mut self,
+        actor: &Actor,
+        command: AdminCommand,
+    ) -> Result<String, AdminError> {
+        let span = error_span!("admin", %actor, command = command.name());
+        let _guard = span.enter();
+        let result = self.dispatch_admin(actor, command);
+        match result {
+            Ok(ref done) => info!("{}", done),
+            Err(ref e) => warn!("refused: {}", e),
+        }
+        result
+    }
+
+    fn dispatch_admin(
+        &mut self,
+        actor: &Actor,
+        command: AdminCommand,
+    ) -> Result<String, AdminError> {
+        if !actor.is_admin() {
+            return Err(AdminError::Forbidden);
+        }
+        use AdminCommand::*;
+        match command {
+            SetWorldTime(fraction) => {
+                if !fraction.is_finite() {
+                    return Err(AdminError::Failed(format!(
+                        "{fraction} isn't a time of day"
+                    )));
+                }
+                self.sim.set_world_time(fraction);
+                Ok(format!("set the time of day to {fraction}"))
+            }
+            Save => {
+                self.flush();
+                Ok("saving".into())
+            }
+            Stop => {
+                self.stopping = true;
+                Ok("stopping".into())
+            }
+            ListPlayers => {
+                let names = self
+                    .clients
+                    .values()
+                    .filter(|client| client.handles.is_some())
+                    .filter_map(|client| client.name.as_deref())
+                    .collect::<Vec<_>>();
+                if names.is_empty() {
+                    return Ok("no players".into());
+                }
+                Ok(format!("{} players: {}", names.len(), names.join(", ")))
+            }
+            SetMovementModes { character, allowed } => {
+                let target = self
+                    .client_named(&character)
+                    .ok_or_else(|| AdminError::NoSuchCharacter(character.clone()))?;
+                self.set_movement_modes(target, allowed);
+                Ok(format!(
+                    "changed movement modes of {character} to {allowed:?}"
+                ))
+            }
+            Teleport {
+                character,
+                destination,
+            } => {
+                let subject = self
+                    .character_entity(&character)
+                    .ok_or_else(|| AdminError::NoSuchCharacter(character.clone()))?;
+                self.teleport(subject, destination)
+                    .map_err(|e| AdminError::Failed(format!("couldn't teleport: {e}")))?;
+                Ok(format!("teleported {character}"))
+            }
+            Summon { character, place } => {
+                let anchor =
+                    self.resolve_place(actor, &place, "teleport <character> to <waypoint>")?;
+                self.dispatch_admin(
+                    actor,
+                    Teleport {
+                        character,
+                        destination: proto::TeleportDestination::Path {
+                            path: anchor.path,
+                            local: math::translate_along(&anchor.local_translation),
+                        },
+                    },
+                )
+            }
+            SetWaypoint(mut waypoint) => {
+                waypoint.owner = actor.name().into();
+                let name = waypoint.name.clone();
+                self.sim
+                    .set_waypoint(waypoint.clone())
+                    .map_err(|e| AdminError::Failed(format!("can't share waypoint: {e}")))?;
+                self.broadcast_waypoints(proto::WaypointsUpdate {
+                    set: vec![waypoint],
+                    removed: Vec::new(),
+                });
+                Ok(format!("shared waypoint {name}"))
+            }
+            RemoveWaypoint(name) => {
+                if !self.sim.remove_waypoint(&name) {
+                    return Err(AdminError::NoSuchWaypoint(name));
+                }
+                self.broadcast_waypoints(proto::WaypointsUpdate {
+                    set: Vec::new(),
+                    removed: vec![name.clone()],
+                });
+                Ok(format!("removed waypoint {name}"))
+            }
+            SetProtectedRegion(region) => {
+                let name = region.name.clone();
+                let radius = region.radius;
+                self.sim
+                    .set_protected_region(region)
+                    .map_err(|e| AdminError::Failed(format!("can't protect region: {e}")))?;
+                Ok(format!("protected region {name} within {radius}m"))
+            }
+            ProtectRegion {
+                name,
+                radius,
+                allowed,
+                center,
+            } => {
+                let anchor = self.resolve_place(
+                    actor,
+                    &center,
+                    "region add <meters> --at <waypoint> <name>",
+                )?;
+                self.dispatch_admin(
+                    actor,
+                    SetProtectedRegion(ProtectedRegion {
+                        name,
+                        center_path: anchor.path,
+                        radius,
+                        allowed,
+                    }),
+                )
+            }
+            RemoveProtectedRegion(name) => {
+                if !self.sim.remove_protected_region(&name) {
+                    return Err(AdminError::NoSuchRegion(name));
+                }
+                Ok(format!("lifted protection of region {name}"))
+            }
+            TraceCollisions { character, steps } => {
+                let subject = self
+                    .character_entity(&character)
+                    .ok_or_else(|| AdminError::NoSuchCharacter(character.clone()))?;
+                self.sim
+                    .trace_collisions(subject, steps)
+                    .map_err(|_| AdminError::NoSuchCharacter(character.clone()))?;
+                Ok(format!(
+                    "tracing collisions of {character} over {steps} steps"
+                ))
+            }
+            Rollback(rollback) => {
+                let count = self
+                    .sim
+                    .rollback(actor.name(), &rollback)
+                    .map_err(|e| AdminError::Failed(format!("can't roll back: {e}")))?;
+                Ok(format!(
+                    "rolled back {count} edits from the latest {}s",
+                    rollback.seconds
+                ))
+            }
+            RollbackRegion {
+                center,
+                radius,
+                seconds,
+                force,
+            } => {
+                let anchor = self.resolve_place(
+                    actor,
+                    &center,
+                    "rollback region <waypoint> <meters> <duration>",
+                )?;
+                self.dispatch_admin(
+                    actor,
+                    Rollback(proto::Rollback {
+                        scope: proto::RollbackScope::Region {
+                            path: anchor.path,
+                            local_translation: anchor.local_translation,
+                            radius,
+                        },
+                        seconds,
+                        force,
+                    }),
+                )
+            }
+            Follow(character) => {
+                let own = actor
+                    .character()
+                    .ok_or_else(|| AdminError::Failed("only players can follow others".into()))?;
+                let client_id = self
+                    .client_of(own)
+                    .ok_or_else(|| AdminError::Failed("only players can follow others".into()))?;
+                let Some(character) = character else {
+                    self.set_followed(client_id, None);
+                    return Ok("stopped following".into());
+                };
+                let subject = self
+                    .character_entity(&character)
+                    .ok_or_else(|| AdminError::NoSuchCharacter(character.clone()))?;
+                if subject == own {
+                    self.set_followed(client_id, None);
+                    return Ok("stopped following".into());
+                }
+                self.set_followed(client_id, Some(subject));
+                Ok(format!("following {character}"))
+            }
+        }
+    }
+
+    /// Where `place` is for `actor`, as a waypoint there would record it, with `usage` suggesting
+    /// how to name a place instead if `actor` isn't anywhere
+    fn resolve_place(
+        &self,
+        actor: &Actor,
+        place: &Place,
+        usage: &'static str,
+    ) -> Result<Waypoint, AdminError> {
+        match *place {
+            Place::Here => {
+                let position = actor
+                    .character()
+                    .and_then(|character| self.sim.position(character))
+                    .ok_or(AdminError::Nowhere(usage))?;
+                Ok(Waypoint::new(
+                    self.sim.graph(),
+                    &position,
+                    String::new(),
+                    [0; 3],
+                    String::new(),
+                ))
+            }
+            Place::Waypoint(ref name) => self
+                .sim
+                .waypoints()
+                .find(|x| x.name == *name)
+                .cloned()
+                .ok_or_else(|| AdminError::NoSuchWaypoint(name.clone())),
+        }
+    }
+}
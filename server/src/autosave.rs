@@ -0,0 +1,92 @@
+use std::{
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{error, trace};
+
+use save::{Batch, Journal, Save};
+
+/// Periodically hands changes to a background thread, which journals them and writes them to the
+/// save, so that the simulation never waits on the disk
+pub struct Autosave {
+    interval: Duration,
+    last_flush: Instant,
+    send: mpsc::Sender<Batch>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Autosave {
+    pub fn new(interval: Duration, save: Arc<Save>, journal: Journal) -> Self {
+        let (send, recv) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("autosave".into())
+            .spawn(move || write_batches(recv, &save, journal))
+            .unwrap();
+        Self {
+            interval,
+            last_flush: Instant::now(),
+            send,
+            thread,
+        }
+    }
+
+    /// Whether it's time to call `flush` again
+    pub fn due(&self) -> bool {
+        self.last_flush.elapsed() >= self.interval
+    }
+
+    /// Queue `batch` to be written
+    pub fn flush(&mut self, batch: Batch) {
+        self.last_flush = Instant::now();
+        if batch.is_empty() {
+            return;
+        }
+        // The thread only stops when we drop the sender
+        self.send.send(batch).unwrap();
+    }
+
+    /// Wait for every queued batch to be written
+    pub fn finish(self) {
+        drop(self.send);
+        if self.thread.join().is_err() {
+            error!("autosave thread panicked");
+        }
+    }
+}
+
+fn write_batches(recv: mpsc::Receiver<Batch>, save: &Save, mut journal: Journal) {
+    // Changes that couldn't be journaled, to be retried with the next batch
+    let mut unsaved: Option<Batch> = None;
+    while let Ok(mut batch) = recv.recv() {
+        // Catch up if we've fallen behind, keeping only the latest version of each record
+        while let Ok(later) = recv.try_recv() {
+            batch.append(later);
+        }
+        if let Some(mut earlier) = unsaved.take() {
+            earlier.append(batch);
+            batch = earlier;
+        }
+        unsaved = write(&mut journal, save, batch);
+    }
+    // Last chance for anything that failed before
+    if let Some(batch) = unsaved.and_then(|batch| write(&mut journal, save, batch)) {
+        error!(records = batch.len(), "discarding unsaved changes");
+    }
+}
+
+/// Journal `batch` and apply it to `save`, returning it if it couldn't be journaled
+fn write(journal: &mut Journal, save: &Save, batch: Batch) -> Option<Batch> {
+    let start = Instant::now();
+    if let Err(e) = journal.append(&batch) {
+        error!(error = %e, "couldn't journal changes");
+        return Some(batch);
+    }
+    // If this fails, the changes remain in the journal to be applied later
+    if let Err(e) = journal.checkpoint(save) {
+        error!(pending = journal.pending(), "couldn't save: {}", e);
+    }
+    trace!(records = batch.len(), elapsed = ?start.elapsed(), "saved");
+    None
+}
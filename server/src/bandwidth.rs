@@ -0,0 +1,162 @@
+use std::{collections::VecDeque, time::Duration};
+
+use common::traffic::Traffic;
+
+/// Messages sent to and received from a single client, as counted by whatever writes and reads
+/// its streams
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionTraffic {
+    pub sent: Traffic,
+    pub received: Traffic,
+}
+
+/// Holds back a client's bulk messages, releasing them no faster than a budget of bytes per second
+///
+/// The budget is replenished by one step's worth each step and never saved up beyond that, so a
+/// client that's been idle can't be sent a burst. A message larger than what's left of the budget
+/// is still released if any is left, leaving the budget in debt to be paid off by later steps, so
+/// that no message is too large to ever be sent.
+#[derive(Debug)]
+pub struct BulkPacer<T> {
+    /// Bytes released per second
+    rate: u64,
+    /// Bytes that may yet be released, negative after releasing more than was left
+    allowance: f64,
+    /// Messages waiting, and the numbers of bytes they're counted as
+    waiting: VecDeque<(T, usize)>,
+    /// Sum of the bytes of `waiting`
+    waiting_bytes: usize,
+}
+
+impl<T> BulkPacer<T> {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            allowance: 0.0,
+            waiting: VecDeque::new(),
+            waiting_bytes: 0,
+        }
+    }
+
+    /// Hold back `msg`, counted as `bytes`, until it can be released after those already waiting
+    pub fn push(&mut self, msg: T, bytes: usize) {
+        self.waiting_bytes += bytes;
+        self.waiting.push_back((msg, bytes));
+    }
+
+    /// Replenish the budget for a step of length `dt`, then hand `send` the messages it now
+    /// allows, in order, until it refuses one, which is kept to be offered again next time
+    pub fn release(&mut self, dt: Duration, mut send: impl FnMut(T) -> Result<(), T>) {
+        let step_budget = self.rate as f64 * dt.as_secs_f64();
+        self.allowance = (self.allowance + step_budget).min(step_budget);
+        while self.allowance > 0.0 {
+            let Some((msg, bytes)) = self.waiting.pop_front() else {
+                break;
+            };
+            match send(msg) {
+                Ok(()) => {
+                    self.allowance -= bytes as f64;
+                    self.waiting_bytes -= bytes;
+                }
+                Err(msg) => {
+                    self.waiting.push_front((msg, bytes));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Number of bytes waiting to be released
+    pub fn waiting_bytes(&self) -> usize {
+        self.waiting_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn backlog_paced_under_rate() {
+        let rate = 10_000;
+        let mut pacer = BulkPacer::new(rate);
+        // Half a minute's worth, in messages of varying size up to several steps' worth
+        let sizes = (0..200)
+            .map(|i| 100 + (i * 397) % 2900)
+            .collect::<Vec<usize>>();
+        let total = sizes.iter().sum::<usize>();
+        for (i, &bytes) in sizes.iter().enumerate() {
+            pacer.push(i, bytes);
+        }
+        assert_eq!(pacer.waiting_bytes(), total);
+
+        let mut released = Vec::new();
+        let mut per_step = Vec::new();
+        while pacer.waiting_bytes() > 0 {
+            let mut bytes = 0;
+            pacer.release(DT, |i| {
+                released.push(i);
+                bytes += sizes[i];
+                Ok(())
+            });
+            per_step.push(bytes);
+            assert!(per_step.len() < 1000, "backlog never drained");
+        }
+        assert_eq!(released, (0..200).collect::<Vec<_>>());
+
+        // Over any second, no more than the rate plus the overshoot of a single message
+        let largest = *sizes.iter().max().unwrap();
+        for window in per_step.windows(10) {
+            let sent = window.iter().sum::<usize>();
+            assert!(sent <= rate as usize + largest, "{sent} bytes in a second");
+        }
+        // And not much slower than the rate allows
+        let seconds = per_step.len() as f64 * DT.as_secs_f64();
+        assert!(seconds <= total as f64 / rate as f64 + 1.0, "{seconds}s");
+    }
+
+    #[test]
+    fn idle_budget_not_saved_up() {
+        let mut pacer = BulkPacer::new(10_000);
+        for _ in 0..100 {
+            pacer.release(DT, |()| Ok(()));
+        }
+        for _ in 0..10 {
+            pacer.push((), 600);
+        }
+        let mut sent = 0;
+        pacer.release(DT, |()| {
+            sent += 1;
+            Ok(())
+        });
+        // One step's worth, the second message overshooting it
+        assert_eq!(sent, 2);
+        assert_eq!(pacer.waiting_bytes(), 4800);
+    }
+
+    #[test]
+    fn refused_messages_retried() {
+        let mut pacer = BulkPacer::new(1_000_000);
+        for i in 0..3 {
+            pacer.push(i, 10);
+        }
+        let mut sent = Vec::new();
+        pacer.release(DT, |i| {
+            if i == 1 {
+                return Err(i);
+            }
+            sent.push(i);
+            Ok(())
+        });
+        assert_eq!(sent, [0]);
+        assert_eq!(pacer.waiting_bytes(), 20);
+        pacer.release(DT, |i| {
+            sent.push(i);
+            Ok(())
+        });
+        assert_eq!(sent, [0, 1, 2]);
+        assert_eq!(pacer.waiting_bytes(), 0);
+    }
+}
@@ -7,7 +7,8 @@ use std::{
 use anyhow::{Context, Result};
 use serde::Deserialize;
 
-use common::SimConfigRaw;
+use common::{protection::ProtectedRegion, SimConfigRaw};
+use server::DeparturePolicy;
 
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -17,10 +18,86 @@ pub struct Config {
     pub private_key: Option<PathBuf>,
     pub save: Option<PathBuf>,
     pub listen: SocketAddr,
+    /// Address to serve server statistics on over HTTP. Requires the "status" feature.
+    pub status_listen: Option<SocketAddr>,
+    /// Whether to take administrative commands typed into the terminal the server runs in
+    pub console: Option<bool>,
+    /// Unix socket to take administrative commands from, as for a server run by a service
+    /// manager. Requires the "console-socket" feature.
+    pub console_socket: Option<PathBuf>,
+    /// Seconds between writes of the world to the save
+    pub autosave_interval_seconds: Option<f32>,
+    /// Names of players permitted to use administrative commands
+    #[serde(default)]
+    pub admins: Vec<String>,
+    /// Names of players who place blocks, including pasted templates, without paying for them
+    /// from their inventories
+    #[serde(default)]
+    pub creative: Vec<String>,
+    /// Regions where only the players listed in each may change blocks, set on startup. Regions
+    /// protected at runtime by administrators are kept in the save.
+    #[serde(default)]
+    pub protected_regions: Vec<ProtectedRegion>,
+    /// Asset pack suggested to clients, which only download it if their players allow it
+    pub asset_pack: Option<AssetPackConfig>,
+    /// Generations of nodes in each region of the graph sent to clients as a unit. Deeper regions
+    /// mean fewer, larger messages, each re-encoded more often as the world changes.
+    pub graph_region_depth: Option<u32>,
+    /// Most block edits remembered for administrators to roll back, oldest forgotten first
+    pub edit_history_records: Option<usize>,
+    /// Hours block edits are remembered for administrators to roll back, counting only time the
+    /// server is running
+    pub edit_history_hours: Option<f32>,
+    /// Bytes per second each client is sent graph updates at most, beyond which they're held back
+    /// so that realtime updates aren't delayed behind them. Unlimited if unset.
+    pub bulk_bytes_per_second: Option<u64>,
+    /// Whether to compress large messages to clients that can decompress them
+    pub compression: Option<bool>,
+    /// Seconds a player may send no commands before their character is marked away, which stops
+    /// it keeping the world around it awake. Never if 0.
+    pub afk_soft_seconds: Option<f32>,
+    /// Seconds a player may send no commands before being disconnected. Never if 0 or unset.
+    pub afk_kick_seconds: Option<f32>,
+    /// What becomes of a player's character when they disconnect
+    #[serde(default)]
+    pub departure: DeparturePolicy,
+    /// What to do on startup if this build generates the world differently than the canonical
+    /// build recorded
+    #[serde(default)]
+    pub worldgen_check: WorldgenCheck,
+    /// What to log besides what's logged by default, in the syntax of `RUST_LOG`, like
+    /// `server=debug`
+    pub log_filter: Option<String>,
+    /// File of further directives like `log_filter`'s, one per line, checked for changes while
+    /// running so that what's logged can be adjusted without a restart
+    pub log_filter_file: Option<PathBuf>,
     #[serde(default)]
     pub simulation: SimConfigRaw,
 }
 
+/// Response to this build generating the world differently than the canonical build
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorldgenCheck {
+    /// Don't check
+    Off,
+    /// Log the differences and carry on
+    #[default]
+    Warn,
+    /// Log the differences and refuse to start
+    Refuse,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssetPackConfig {
+    pub name: String,
+    /// Where clients download the pack's zip archive from
+    pub url: String,
+    /// BLAKE3 hash of the zip archive, as 64 hexadecimal digits
+    pub blake3: String,
+}
+
 impl Config {
     pub fn load(path: &Path) -> Result<Self> {
         toml::from_str(
@@ -39,6 +116,25 @@ impl Default for Config {
             private_key: None,
             save: None,
             listen: SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 1234),
+            status_listen: None,
+            console: None,
+            console_socket: None,
+            autosave_interval_seconds: None,
+            admins: Vec::new(),
+            creative: Vec::new(),
+            protected_regions: Vec::new(),
+            asset_pack: None,
+            graph_region_depth: None,
+            edit_history_records: None,
+            edit_history_hours: None,
+            bulk_bytes_per_second: None,
+            compression: None,
+            afk_soft_seconds: None,
+            afk_kick_seconds: None,
+            departure: DeparturePolicy::default(),
+            worldgen_check: WorldgenCheck::default(),
+            log_filter: None,
+            log_filter_file: None,
             simulation: SimConfigRaw::default(),
         }
     }
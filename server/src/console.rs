@@ -0,0 +1,408 @@
+//! Administrative commands typed at the server's terminal, or sent over a local socket
+//!
+//! Lines are parsed here and carried out by the server as `Actor::Console`, which may do anything
+//! but has no character, so commands acting "here" must name a waypoint instead.
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, BufRead},
+    thread,
+};
+
+use common::proto;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+use crate::admin::{AdminCommand, Place};
+
+/// A line typed at the console, and where to send the answer to it
+pub struct ConsoleRequest {
+    pub line: String,
+    /// Dropped unanswered if the line was blank
+    pub reply: oneshot::Sender<String>,
+}
+
+/// Read lines from standard input in the background, printing the answer to each
+pub fn read_stdin(send: mpsc::Sender<ConsoleRequest>) {
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("stopped reading console commands: {}", e);
+                    return;
+                }
+            };
+            let (reply, answer) = oneshot::channel();
+            if send.blocking_send(ConsoleRequest { line, reply }).is_err() {
+                // The server has stopped
+                return;
+            }
+            if let Ok(answer) = answer.blocking_recv() {
+                println!("{answer}");
+            }
+        }
+    });
+}
+
+/// Take lines from connections to a Unix socket at `path`, answering each on the same connection
+#[cfg(all(feature = "console-socket", unix))]
+pub fn listen(path: &std::path::Path, send: mpsc::Sender<ConsoleRequest>) -> anyhow::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    use anyhow::Context;
+    use tokio::net::UnixListener;
+    use tracing::{debug, info};
+
+    // A socket left behind by an earlier run would keep us from binding, but anything else at
+    // `path` is left alone
+    if std::fs::symlink_metadata(path).is_ok_and(|x| x.file_type().is_socket()) {
+        std::fs::remove_file(path).context("removing stale console socket")?;
+    }
+    let listener = UnixListener::bind(path).context("binding console socket")?;
+    info!(path = %path.display(), "taking commands on console socket");
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("couldn't accept console connection: {}", e);
+                    continue;
+                }
+            };
+            let send = send.clone();
+            tokio::spawn(async move {
+                if let Err(e) = answer(stream, send).await {
+                    debug!("console connection failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(all(feature = "console-socket", unix))]
+async fn answer(
+    stream: tokio::net::UnixStream,
+    send: mpsc::Sender<ConsoleRequest>,
+) -> io::Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        let (reply, answer) = oneshot::channel();
+        if send.send(ConsoleRequest { line, reply }).await.is_err() {
+            break;
+        }
+        if let Ok(answer) = answer.await {
+            write.write_all(answer.as_bytes()).await?;
+            write.write_all(b"\n").await?;
+        }
+    }
+    write.shutdown().await
+}
+
+#[cfg(not(all(feature = "console-socket", unix)))]
+pub fn listen(_: &std::path::Path, _: mpsc::Sender<ConsoleRequest>) -> anyhow::Result<()> {
+    warn!(
+        "console socket configured, but this server was built without the \"console-socket\" \
+         feature or for a platform without Unix sockets"
+    );
+    Ok(())
+}
+
+/// Interpret a line typed at the console, if it isn't blank
+///
+/// ```text
+/// players
+/// time <fraction of a day>
+/// save
+/// stop
+/// noclip <character> on | off
+/// teleport <character> to <waypoint>
+/// teleport <character> here
+/// bring <character>
+/// waypoint remove <name>
+/// region add <meters> [--allow <player>,...] [--at <waypoint>] <name>
+/// region remove <name>
+/// rollback player <name> <duration> [--force]
+/// rollback region <waypoint> | here <meters> <duration> [--force]
+/// trace <steps> | off <character>
+/// ```
+///
+/// As in the client's console, names take up the rest of the line, or all of it between the
+/// command and its trailing arguments, so they may contain spaces; the waypoint after `--at` is
+/// the exception, being a single word. Durations are seconds, or a number followed by `s`, `m`,
+/// or `h`. "Here" is where the player running the command is, so the console itself can't use
+/// it, but is understood for parity with players' commands.
+pub fn parse_command(line: &str) -> Result<Option<AdminCommand>, ParseError> {
+    let mut words = line.split_whitespace().peekable();
+    let Some(command) = words.next() else {
+        return Ok(None);
+    };
+    match command {
+        "players" | "save" | "stop" => match words.next() {
+            None => Ok(Some(match command {
+                "players" => AdminCommand::ListPlayers,
+                "save" => AdminCommand::Save,
+                _ => AdminCommand::Stop,
+            })),
+            Some(x) => Err(ParseError::Unexpected(x.into())),
+        },
+        "time" => {
+            let x = words.next().ok_or(ParseError::Usage)?;
+            let fraction = x
+                .parse::<f32>()
+                .ok()
+                .filter(|x| (0.0..=1.0).contains(x))
+                .ok_or_else(|| ParseError::Unexpected(x.into()))?;
+            match words.next() {
+                None => Ok(Some(AdminCommand::SetWorldTime(fraction))),
+                Some(x) => Err(ParseError::Unexpected(x.into())),
+            }
+        }
+        "noclip" => {
+            let mut rest = words.collect::<Vec<_>>();
+            let allowed = match rest.pop().ok_or(ParseError::Usage)? {
+                "on" => proto::MovementModes::NO_CLIP,
+                "off" => proto::MovementModes::NONE,
+                x => return Err(ParseError::Unexpected(x.into())),
+            };
+            Ok(Some(AdminCommand::SetMovementModes {
+                character: name(&rest)?,
+                allowed,
+            }))
+        }
+        "teleport" => {
+            let rest = words.collect::<Vec<_>>();
+            let (character, place) = match rest.iter().position(|&x| x == "to") {
+                Some(i) => (&rest[..i], Place::Waypoint(name(&rest[i + 1..])?)),
+                None => match rest.split_last() {
+                    Some((&"here", character)) => (character, Place::Here),
+                    _ => return Err(ParseError::Usage),
+                },
+            };
+            Ok(Some(AdminCommand::Summon {
+                character: name(character)?,
+                place,
+            }))
+        }
+        "bring" => Ok(Some(AdminCommand::Summon {
+            character: name(&words.collect::<Vec<_>>())?,
+            place: Place::Here,
+        })),
+        "waypoint" => match words.next().ok_or(ParseError::Usage)? {
+            "remove" => Ok(Some(AdminCommand::RemoveWaypoint(name(
+                &words.collect::<Vec<_>>(),
+            )?))),
+            x => Err(ParseError::Unexpected(x.into())),
+        },
+        "region" => match words.next().ok_or(ParseError::Usage)? {
+            "add" => {
+                let radius = parse_meters(words.next().ok_or(ParseError::Usage)?)?;
+                let mut allowed = BTreeSet::new();
+                let mut center = Place::Here;
+                while let Some(flag) = words.next_if(|x| x.starts_with("--")) {
+                    let value = words.next().ok_or(ParseError::Usage)?;
+                    match flag {
+                        "--allow" => allowed
+                            .extend(value.split(',').filter(|x| !x.is_empty()).map(String::from)),
+                        "--at" => center = Place::Waypoint(value.into()),
+                        _ => return Err(ParseError::Unexpected(flag.into())),
+                    }
+                }
+                Ok(Some(AdminCommand::ProtectRegion {
+                    name: name(&words.collect::<Vec<_>>())?,
+                    radius,
+                    allowed,
+                    center,
+                }))
+            }
+            "remove" => Ok(Some(AdminCommand::RemoveProtectedRegion(name(
+                &words.collect::<Vec<_>>(),
+            )?))),
+            x => Err(ParseError::Unexpected(x.into())),
+        },
+        "rollback" => {
+            let action = words.next().ok_or(ParseError::Usage)?;
+            let mut rest = words.collect::<Vec<_>>();
+            let force = rest.last() == Some(&"--force");
+            if force {
+                rest.pop();
+            }
+            let seconds = parse_duration(rest.pop().ok_or(ParseError::Usage)?)?;
+            match action {
+                "player" => Ok(Some(AdminCommand::Rollback(proto::Rollback {
+                    scope: proto::RollbackScope::Player(name(&rest)?),
+                    seconds,
+                    force,
+                }))),
+                "region" => {
+                    let radius = parse_meters(rest.pop().ok_or(ParseError::Usage)?)?;
+                    let center = match &*rest {
+                        ["here"] => Place::Here,
+                        _ => Place::Waypoint(name(&rest)?),
+                    };
+                    Ok(Some(AdminCommand::RollbackRegion {
+                        center,
+                        radius,
+                        seconds,
+                        force,
+                    }))
+                }
+                _ => Err(ParseError::Unexpected(action.into())),
+            }
+        }
+        "trace" => {
+            let steps = match words.next().ok_or(ParseError::Usage)? {
+                "off" => 0,
+                x => x.parse().map_err(|_| ParseError::Unexpected(x.into()))?,
+            };
+            Ok(Some(AdminCommand::TraceCollisions {
+                character: name(&words.collect::<Vec<_>>())?,
+                steps,
+            }))
+        }
+        _ => Err(ParseError::Unexpected(command.into())),
+    }
+}
+
+/// The name spelled by `words`, which mustn't be empty
+fn name(words: &[&str]) -> Result<String, ParseError> {
+    if words.is_empty() {
+        return Err(ParseError::MissingName);
+    }
+    Ok(words.join(" "))
+}
+
+fn parse_meters(x: &str) -> Result<f32, ParseError> {
+    x.parse::<u32>()
+        .map(|x| x as f32)
+        .map_err(|_| ParseError::Unexpected(x.into()))
+}
+
+fn parse_duration(x: &str) -> Result<u32, ParseError> {
+    let (digits, scale) = match x.as_bytes().last() {
+        Some(b's') => (&x[..x.len() - 1], 1),
+        Some(b'm') => (&x[..x.len() - 1], 60),
+        Some(b'h') => (&x[..x.len() - 1], 3600),
+        _ => (x, 1),
+    };
+    digits
+        .parse::<u32>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(|| ParseError::BadDuration(x.into()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Unexpected(String),
+    MissingName,
+    BadDuration(String),
+    Usage,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ParseError::Unexpected(ref x) => write!(f, "unexpected {x:?}"),
+            ParseError::MissingName => f.pad("missing name"),
+            ParseError::BadDuration(ref x) => write!(f, "{x:?} is not a duration like 10m"),
+            ParseError::Usage => f.pad(
+                "usage: players | time <fraction of a day> | save | stop \
+                 | noclip <character> on|off | teleport <character> to <waypoint> \
+                 | waypoint remove <name> \
+                 | region add <meters> [--allow <player>,...] --at <waypoint> <name> \
+                 | region remove <name> | rollback player <name> <duration> [--force] \
+                 | rollback region <waypoint> <meters> <duration> [--force] \
+                 | trace <steps>|off <character>",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse_command(" "), Ok(None));
+        assert!(matches!(
+            parse_command("players"),
+            Ok(Some(AdminCommand::ListPlayers))
+        ));
+        assert!(matches!(
+            parse_command("stop"),
+            Ok(Some(AdminCommand::Stop))
+        ));
+        assert_eq!(
+            parse_command("stop now").unwrap_err(),
+            ParseError::Unexpected("now".into())
+        );
+        assert!(matches!(
+            parse_command("time 0.25"),
+            Ok(Some(AdminCommand::SetWorldTime(x))) if x == 0.25
+        ));
+        assert_eq!(
+            parse_command("time 2").unwrap_err(),
+            ParseError::Unexpected("2".into())
+        );
+        assert!(matches!(
+            parse_command("noclip big bob on"),
+            Ok(Some(AdminCommand::SetMovementModes { character, allowed }))
+                if character == "big bob" && allowed == proto::MovementModes::NO_CLIP
+        ));
+        assert!(matches!(
+            parse_command("teleport bob to town square"),
+            Ok(Some(AdminCommand::Summon { character, place }))
+                if character == "bob" && place == Place::Waypoint("town square".into())
+        ));
+        assert!(matches!(
+            parse_command("bring bob"),
+            Ok(Some(AdminCommand::Summon { character, place: Place::Here })) if character == "bob"
+        ));
+        assert_eq!(
+            parse_command("teleport bob").unwrap_err(),
+            ParseError::Usage
+        );
+        assert_eq!(
+            parse_command("teleport to spawn").unwrap_err(),
+            ParseError::MissingName
+        );
+        assert!(matches!(
+            parse_command("region add 20 --allow alice,bob --at spawn town hall"),
+            Ok(Some(AdminCommand::ProtectRegion { name, radius, allowed, center }))
+                if name == "town hall"
+                    && radius == 20.0
+                    && allowed.len() == 2
+                    && center == Place::Waypoint("spawn".into())
+        ));
+        assert!(matches!(
+            parse_command("rollback player bob 10m --force"),
+            Ok(Some(AdminCommand::Rollback(proto::Rollback {
+                scope: proto::RollbackScope::Player(name),
+                seconds: 600,
+                force: true,
+            }))) if name == "bob"
+        ));
+        assert!(matches!(
+            parse_command("rollback region town square 30 1h"),
+            Ok(Some(AdminCommand::RollbackRegion { center, seconds: 3600, force: false, .. }))
+                if center == Place::Waypoint("town square".into())
+        ));
+        assert!(matches!(
+            parse_command("trace off bob"),
+            Ok(Some(AdminCommand::TraceCollisions { steps: 0, .. }))
+        ));
+        assert_eq!(
+            parse_command("fly").unwrap_err(),
+            ParseError::Unexpected("fly".into())
+        );
+    }
+}
@@ -0,0 +1,514 @@
+//! Blocks changed by players, remembered so that administrators can undo them
+//!
+//! Edits are kept in the order they were made, up to a number and an age, after which the oldest
+//! are forgotten. Each is numbered in that order, and indexed by the chunk it changed so that
+//! undoing what happened in one place needn't look at edits made anywhere else. Ages are counted in
+//! steps over every run using the same save, treating the time the server was stopped as though
+//! none passed, so a restart neither forgets recent edits nor brings back old ones.
+
+use std::{collections::VecDeque, time::Duration};
+
+use fxhash::FxHashMap;
+use tracing::warn;
+
+use common::{
+    dodeca::Vertex,
+    node::Coords,
+    world::{Material, Shape},
+    Step,
+};
+
+/// Most edits remembered unless configured otherwise
+pub const DEFAULT_MAX_RECORDS: usize = 100_000;
+
+/// Longest edits are remembered unless configured otherwise
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A voxel's material and shape
+pub type Voxel = (Material, Shape);
+
+/// A chunk, identified by the hash of its node so that it's the same from one run to the next
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct EditedChunk {
+    pub node: u128,
+    pub vertex: Vertex,
+}
+
+/// A block changed by a player
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// When the block was changed, counting steps over every run
+    pub step: u64,
+    /// Name of the character that changed the block
+    pub player: String,
+    pub chunk: EditedChunk,
+    pub coords: Coords,
+    pub old: Voxel,
+    pub new: Voxel,
+}
+
+/// Which edits to undo
+#[derive(Debug, Copy, Clone)]
+pub enum Selection<'a> {
+    /// Those made by the named player
+    Player(&'a str),
+    /// Those made to any of the given distinct chunks
+    Chunks(&'a [EditedChunk]),
+}
+
+/// A change to a voxel undoing edits to it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Revert {
+    pub chunk: EditedChunk,
+    pub coords: Coords,
+    /// What the voxel was before the first of the edits undone
+    pub voxel: Voxel,
+}
+
+pub struct EditHistory {
+    max_records: usize,
+    /// Age in steps beyond which edits are forgotten
+    max_age: u64,
+    /// Steps run before this run started, over every run using the same save
+    steps_before: u64,
+    /// Sequence number of the first edit in `edits`
+    first: u64,
+    edits: VecDeque<Edit>,
+    /// Sequence numbers of the edits to each chunk, oldest first
+    by_chunk: FxHashMap<EditedChunk, VecDeque<u64>>,
+    /// Sequence number of the first edit not yet returned by `take_changes`
+    unsaved: u64,
+    /// Edits that were saved and have since been forgotten, not yet returned by `take_changes`
+    forgotten: Vec<u64>,
+}
+
+impl EditHistory {
+    /// Remember at most `max_records` edits, for at most `max_age` steps
+    pub fn new(max_records: usize, max_age: u64) -> Self {
+        Self {
+            max_records,
+            max_age,
+            steps_before: 0,
+            first: 0,
+            edits: VecDeque::new(),
+            by_chunk: FxHashMap::default(),
+            unsaved: 0,
+            forgotten: Vec::new(),
+        }
+    }
+
+    /// Restore edits read from the save, in order of sequence number, as though this run began
+    /// just after the latest of them
+    pub fn load(&mut self, saved: Vec<(u64, Edit)>) {
+        for (sequence, edit) in saved {
+            if !self.edits.is_empty() && sequence != self.next() {
+                // Edits are only forgotten oldest first, so anything before a gap is stale
+                warn!(sequence, "forgetting saved edits preceding a gap");
+                while !self.edits.is_empty() {
+                    self.forget_oldest();
+                }
+            }
+            if self.edits.is_empty() {
+                self.first = sequence;
+            }
+            self.steps_before = self.steps_before.max(edit.step + 1);
+            self.insert(edit);
+            self.unsaved = self.next();
+        }
+        self.forget_excess(0);
+    }
+
+    /// Change the limits on what's remembered, forgetting whatever lies beyond them as of `step`
+    pub fn set_limits(&mut self, max_records: usize, max_age: u64, step: Step) {
+        self.max_records = max_records;
+        self.max_age = max_age;
+        self.forget_excess(step);
+    }
+
+    /// Remember that `player` changed the voxel at `coords` in `chunk` from `old` to `new` during
+    /// `step`
+    pub fn record(
+        &mut self,
+        step: Step,
+        player: &str,
+        chunk: EditedChunk,
+        coords: Coords,
+        old: Voxel,
+        new: Voxel,
+    ) {
+        self.insert(Edit {
+            step: self.elapsed(step),
+            player: player.into(),
+            chunk,
+            coords,
+            old,
+            new,
+        });
+        self.forget_excess(step);
+    }
+
+    /// Changes undoing the `selection` of edits made within `duration` steps before `step`
+    ///
+    /// Each voxel is returned to what it was before the first of those edits to it. Unless `force`
+    /// is set, voxels that have changed since the last of them, or whose chunks `current` can't
+    /// find, are left alone.
+    pub fn reverts(
+        &self,
+        step: Step,
+        duration: u64,
+        selection: Selection<'_>,
+        force: bool,
+        mut current: impl FnMut(EditedChunk, Coords) -> Option<Voxel>,
+    ) -> Vec<Revert> {
+        let since = self.elapsed(step).saturating_sub(duration);
+        // Latest value and earliest previous value of each voxel among the selected edits
+        let mut net = FxHashMap::<(EditedChunk, Coords), (Voxel, Voxel)>::default();
+        let mut visit = |edit: &Edit| {
+            net.entry((edit.chunk, edit.coords))
+                .and_modify(|(old, _)| *old = edit.old)
+                .or_insert((edit.old, edit.new));
+        };
+        match selection {
+            Selection::Player(player) => {
+                for edit in self
+                    .edits
+                    .iter()
+                    .rev()
+                    .take_while(|edit| edit.step >= since)
+                    .filter(|edit| edit.player == player)
+                {
+                    visit(edit);
+                }
+            }
+            Selection::Chunks(chunks) => {
+                for chunk in chunks {
+                    let Some(sequences) = self.by_chunk.get(chunk) else {
+                        continue;
+                    };
+                    for edit in sequences
+                        .iter()
+                        .rev()
+                        .map(|&sequence| &self.edits[(sequence - self.first) as usize])
+                        .take_while(|edit| edit.step >= since)
+                    {
+                        visit(edit);
+                    }
+                }
+            }
+        }
+        let mut reverts = net
+            .into_iter()
+            .filter_map(|((chunk, coords), (old, new))| {
+                let now = current(chunk, coords)?;
+                if now == old || (!force && now != new) {
+                    return None;
+                }
+                Some(Revert {
+                    chunk,
+                    coords,
+                    voxel: old,
+                })
+            })
+            .collect::<Vec<_>>();
+        // Applied in a consistent order, for the sake of reproducibility
+        reverts.sort_unstable_by_key(|x| (x.chunk.node, x.chunk.vertex as u8, x.coords.0));
+        reverts
+    }
+
+    /// Record the edits remembered or forgotten since the last call in `batch`
+    pub fn take_changes(&mut self, step: Step, batch: &mut save::Batch) {
+        self.forget_excess(step);
+        for sequence in self.forgotten.drain(..) {
+            batch.remove_edit(sequence);
+        }
+        for sequence in self.unsaved.max(self.first)..self.next() {
+            batch.put_edit(
+                sequence,
+                encode_edit(&self.edits[(sequence - self.first) as usize]),
+            );
+        }
+        self.unsaved = self.next();
+    }
+
+    /// Steps elapsed over every run as of `step` of this one
+    fn elapsed(&self, step: Step) -> u64 {
+        self.steps_before + u64::try_from(step).unwrap()
+    }
+
+    /// Sequence number of the next edit to be recorded
+    fn next(&self) -> u64 {
+        self.first + self.edits.len() as u64
+    }
+
+    fn insert(&mut self, edit: Edit) {
+        self.by_chunk
+            .entry(edit.chunk)
+            .or_default()
+            .push_back(self.next());
+        self.edits.push_back(edit);
+    }
+
+    fn forget_excess(&mut self, step: Step) {
+        let since = self.elapsed(step).saturating_sub(self.max_age);
+        while self
+            .edits
+            .front()
+            .is_some_and(|edit| self.edits.len() > self.max_records || edit.step < since)
+        {
+            self.forget_oldest();
+        }
+    }
+
+    fn forget_oldest(&mut self) {
+        let edit = self.edits.pop_front().unwrap();
+        let sequence = self.first;
+        self.first += 1;
+        let sequences = self.by_chunk.get_mut(&edit.chunk).unwrap();
+        // Every edit before this one has already been forgotten
+        debug_assert_eq!(sequences.front(), Some(&sequence));
+        sequences.pop_front();
+        if sequences.is_empty() {
+            self.by_chunk.remove(&edit.chunk);
+        }
+        if sequence < self.unsaved {
+            self.forgotten.push(sequence);
+        }
+    }
+}
+
+pub fn encode_edit(edit: &Edit) -> save::Edit {
+    let Coords([x, y, z]) = edit.coords;
+    save::Edit {
+        step: edit.step,
+        player: edit.player.clone(),
+        node_high: (edit.chunk.node >> 64) as u64,
+        node_low: edit.chunk.node as u64,
+        vertex: edit.chunk.vertex as u32,
+        coords: u32::from(x) | u32::from(y) << 8 | u32::from(z) << 16,
+        old_material: edit.old.0.id().into(),
+        old_shape: u8::from(edit.old.1).into(),
+        new_material: edit.new.0.id().into(),
+        new_shape: u8::from(edit.new.1).into(),
+    }
+}
+
+/// Interpret an edit read from the save, unless it's malformed
+pub fn decode_edit(stored: save::Edit) -> Option<Edit> {
+    fn voxel(material: u32, shape: u32) -> Option<Voxel> {
+        Some((
+            Material::from_id(u16::try_from(material).ok()?),
+            Shape::try_from(u8::try_from(shape).ok()?).ok()?,
+        ))
+    }
+
+    let [x, y, z, rest] = stored.coords.to_le_bytes();
+    if rest != 0 {
+        return None;
+    }
+    Some(Edit {
+        step: stored.step,
+        chunk: EditedChunk {
+            node: u128::from(stored.node_high) << 64 | u128::from(stored.node_low),
+            vertex: Vertex::iter().nth(stored.vertex as usize)?,
+        },
+        coords: Coords([x, y, z]),
+        old: voxel(stored.old_material, stored.old_shape)?,
+        new: voxel(stored.new_material, stored.new_shape)?,
+        player: stored.player,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIRT: Voxel = (Material::Dirt, Shape::FULL);
+    const STONE: Voxel = (Material::Limestone, Shape::FULL);
+    const WOOD: Voxel = (Material::WoodPlanks, Shape::FULL);
+    const VOID: Voxel = (Material::Void, Shape::FULL);
+
+    fn chunk(node: u128) -> EditedChunk {
+        EditedChunk {
+            node,
+            vertex: Vertex::A,
+        }
+    }
+
+    /// A world of dirt, changed only by the edits recorded
+    struct World {
+        history: EditHistory,
+        voxels: FxHashMap<(EditedChunk, Coords), Voxel>,
+    }
+
+    impl World {
+        fn new(max_records: usize) -> Self {
+            Self {
+                history: EditHistory::new(max_records, 1000),
+                voxels: FxHashMap::default(),
+            }
+        }
+
+        fn get(&self, chunk: EditedChunk, coords: Coords) -> Voxel {
+            self.voxels.get(&(chunk, coords)).copied().unwrap_or(DIRT)
+        }
+
+        fn edit(&mut self, step: Step, player: &str, chunk: EditedChunk, x: u8, new: Voxel) {
+            let coords = Coords([x, 0, 0]);
+            let old = self.get(chunk, coords);
+            self.history.record(step, player, chunk, coords, old, new);
+            self.voxels.insert((chunk, coords), new);
+        }
+
+        fn rollback(&mut self, step: Step, selection: Selection<'_>, force: bool) -> usize {
+            let reverts = self
+                .history
+                .reverts(step, 1000, selection, force, |chunk, coords| {
+                    Some(self.get(chunk, coords))
+                });
+            for revert in &reverts {
+                self.voxels
+                    .insert((revert.chunk, revert.coords), revert.voxel);
+            }
+            reverts.len()
+        }
+    }
+
+    #[test]
+    fn rollback_by_player() {
+        let mut world = World::new(100);
+        world.edit(0, "a", chunk(1), 0, VOID);
+        world.edit(1, "b", chunk(1), 1, STONE);
+        world.edit(2, "a", chunk(1), 0, WOOD);
+        world.edit(3, "b", chunk(2), 0, VOID);
+        world.edit(4, "a", chunk(2), 1, STONE);
+        world.edit(5, "a", chunk(2), 1, VOID);
+
+        assert_eq!(world.rollback(6, Selection::Player("a"), false), 2);
+        // Each voxel is back to what it was before a's first edit to it
+        assert_eq!(world.get(chunk(1), Coords([0, 0, 0])), DIRT);
+        assert_eq!(world.get(chunk(2), Coords([1, 0, 0])), DIRT);
+        // And b's are untouched
+        assert_eq!(world.get(chunk(1), Coords([1, 0, 0])), STONE);
+        assert_eq!(world.get(chunk(2), Coords([0, 0, 0])), VOID);
+
+        // Only edits within the duration are undone
+        let reverts =
+            world
+                .history
+                .reverts(6, 3, Selection::Player("b"), false, |chunk, coords| {
+                    Some(world.get(chunk, coords))
+                });
+        assert_eq!(
+            reverts,
+            [Revert {
+                chunk: chunk(2),
+                coords: Coords([0, 0, 0]),
+                voxel: DIRT,
+            }]
+        );
+    }
+
+    #[test]
+    fn rollback_by_chunk() {
+        let mut world = World::new(100);
+        world.edit(0, "a", chunk(1), 0, VOID);
+        world.edit(1, "b", chunk(2), 0, VOID);
+        world.edit(2, "a", chunk(3), 0, VOID);
+        assert_eq!(
+            world.rollback(3, Selection::Chunks(&[chunk(1), chunk(2)]), false),
+            2
+        );
+        assert_eq!(world.get(chunk(1), Coords([0, 0, 0])), DIRT);
+        assert_eq!(world.get(chunk(2), Coords([0, 0, 0])), DIRT);
+        assert_eq!(world.get(chunk(3), Coords([0, 0, 0])), VOID);
+    }
+
+    #[test]
+    fn later_edits_guard_voxels() {
+        let mut world = World::new(100);
+        world.edit(0, "a", chunk(1), 0, VOID);
+        world.edit(1, "a", chunk(1), 1, VOID);
+        world.edit(2, "b", chunk(1), 0, STONE);
+        assert_eq!(world.rollback(3, Selection::Player("a"), false), 1);
+        assert_eq!(world.get(chunk(1), Coords([0, 0, 0])), STONE);
+        assert_eq!(world.get(chunk(1), Coords([1, 0, 0])), DIRT);
+
+        // Unless forced
+        assert_eq!(world.rollback(4, Selection::Player("a"), true), 1);
+        assert_eq!(world.get(chunk(1), Coords([0, 0, 0])), DIRT);
+    }
+
+    #[test]
+    fn oldest_forgotten_first() {
+        let mut world = World::new(3);
+        for x in 0..5 {
+            world.edit(x.into(), "a", chunk(u128::from(x % 2)), x, VOID);
+        }
+        assert_eq!(world.history.edits.len(), 3);
+        let history = &world.history;
+        assert_eq!(history.first, 2);
+        // The index holds exactly the edits remembered
+        let mut indexed = history
+            .by_chunk
+            .iter()
+            .flat_map(|(&chunk, sequences)| sequences.iter().map(move |&x| (chunk, x)))
+            .collect::<Vec<_>>();
+        indexed.sort_unstable_by_key(|&(_, sequence)| sequence);
+        assert_eq!(indexed, [(chunk(0), 2), (chunk(1), 3), (chunk(0), 4)]);
+
+        // Forgotten edits can't be undone
+        assert_eq!(
+            world.rollback(5, Selection::Chunks(&[chunk(0), chunk(1)]), false),
+            3
+        );
+        assert_eq!(world.get(chunk(0), Coords([0, 0, 0])), VOID);
+        assert_eq!(world.get(chunk(1), Coords([1, 0, 0])), VOID);
+        assert_eq!(world.get(chunk(0), Coords([2, 0, 0])), DIRT);
+    }
+
+    #[test]
+    fn old_edits_forgotten() {
+        let mut history = EditHistory::new(100, 10);
+        history.record(0, "a", chunk(0), Coords([0, 0, 0]), DIRT, VOID);
+        history.record(5, "a", chunk(0), Coords([1, 0, 0]), DIRT, VOID);
+        let mut batch = save::Batch::new();
+        history.take_changes(12, &mut batch);
+        assert_eq!(history.edits.len(), 1);
+        // Never saved, so there's nothing to remove
+        assert_eq!(batch.edit(0), None);
+        assert!(batch.edit(1).is_some_and(|x| x.is_some()));
+
+        history.take_changes(20, &mut batch);
+        assert_eq!(history.edits.len(), 0);
+        assert_eq!(batch.edit(1), Some(None));
+    }
+
+    #[test]
+    fn ages_carry_across_runs() {
+        let mut history = EditHistory::new(100, 10);
+        history.record(0, "a", chunk(0), Coords([0, 0, 0]), DIRT, VOID);
+        history.record(8, "a", chunk(0), Coords([1, 0, 0]), DIRT, VOID);
+        let saved = (0..)
+            .zip(
+                history
+                    .edits
+                    .iter()
+                    .map(|x| decode_edit(encode_edit(x)).unwrap()),
+            )
+            .collect::<Vec<_>>();
+        assert_eq!(saved[1].1, history.edits[1]);
+
+        let mut restarted = EditHistory::new(100, 10);
+        restarted.load(saved);
+        assert_eq!(restarted.edits.len(), 2);
+        let mut batch = save::Batch::new();
+        // Step 5 of the new run is 14 steps after the first edit
+        restarted.take_changes(5, &mut batch);
+        assert_eq!(restarted.edits.len(), 1);
+        assert_eq!(batch.edit(0), Some(None));
+        assert_eq!(batch.edit(1), None);
+        restarted.record(6, "a", chunk(0), Coords([2, 0, 0]), DIRT, VOID);
+        restarted.take_changes(6, &mut batch);
+        assert!(batch.edit(2).is_some_and(|x| x.is_some()));
+    }
+}
@@ -0,0 +1,105 @@
+//! Issuing `EntityId`s, which must never be reused while anyone might still remember them
+
+#[cfg(debug_assertions)]
+use fxhash::FxHashSet;
+
+use common::EntityId;
+
+/// Source of IDs for new entities
+///
+/// Entities that may be saved are numbered in order by a counter that's saved along with them, so
+/// an ID is never issued twice in the life of a world, even across restarts. Transient entities,
+/// like projectiles, are numbered separately in the range marked by `EntityId::TRANSIENT_BIT`. That
+/// counter isn't saved and may wrap around, so those IDs are only unique among entities alive or
+/// recently despawned, which the caller must rule out.
+pub struct EntityIdAllocator {
+    /// Lowest persistent ID not yet issued
+    next_persistent: u64,
+    /// Transient ID to try next, without `EntityId::TRANSIENT_BIT`
+    next_transient: u64,
+    /// Persistent IDs issued since the allocator was created
+    #[cfg(debug_assertions)]
+    issued: FxHashSet<EntityId>,
+}
+
+impl EntityIdAllocator {
+    /// Resume issuing persistent IDs from `next_persistent`, as previously returned by
+    /// `next_persistent`
+    pub fn new(next_persistent: u64) -> Self {
+        Self {
+            // Zero is left unused, so that it's never mistaken for a real entity
+            next_persistent: next_persistent.max(1),
+            next_transient: 0,
+            #[cfg(debug_assertions)]
+            issued: FxHashSet::default(),
+        }
+    }
+
+    /// Lowest persistent ID not yet issued, to be saved along with every entity issued one
+    pub fn next_persistent(&self) -> u64 {
+        self.next_persistent
+    }
+
+    /// An ID for an entity that may be saved
+    pub fn persistent(&mut self) -> EntityId {
+        let bits = self.next_persistent;
+        assert_eq!(
+            bits & EntityId::TRANSIENT_BIT,
+            0,
+            "persistent entity IDs exhausted"
+        );
+        self.next_persistent += 1;
+        let id = EntityId::from_bits(bits);
+        #[cfg(debug_assertions)]
+        assert!(self.issued.insert(id), "entity ID {id} issued twice");
+        id
+    }
+
+    /// An ID for an entity that's never saved, other than one for which `in_use` is true
+    pub fn transient(&mut self, mut in_use: impl FnMut(EntityId) -> bool) -> EntityId {
+        loop {
+            let id = EntityId::from_bits(EntityId::TRANSIENT_BIT | self.next_transient);
+            self.next_transient = self.next_transient.wrapping_add(1) & !EntityId::TRANSIENT_BIT;
+            if !in_use(id) {
+                return id;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persistent_ids_survive_restarts() {
+        let mut allocator = EntityIdAllocator::new(0);
+        let first = (0..3).map(|_| allocator.persistent()).collect::<Vec<_>>();
+        assert_eq!(first, [1, 2, 3].map(EntityId::from_bits));
+        assert!(first.iter().all(|&id| !id.is_transient()));
+        // Transient IDs don't disturb the count
+        allocator.transient(|_| false);
+
+        // As if the counter were saved and the server restarted
+        let mut restarted = EntityIdAllocator::new(allocator.next_persistent());
+        let second = (0..3).map(|_| restarted.persistent()).collect::<Vec<_>>();
+        assert_eq!(second, [4, 5, 6].map(EntityId::from_bits));
+    }
+
+    #[test]
+    fn transient_ids_wrap_around_ids_in_use() {
+        let mut allocator = EntityIdAllocator::new(0);
+        let first = allocator.transient(|_| false);
+        assert!(first.is_transient());
+
+        // Near the end of the range, with the first ID still in use
+        allocator.next_transient = !EntityId::TRANSIENT_BIT - 1;
+        let last = allocator.transient(|_| false);
+        assert_eq!(last.to_bits(), u64::MAX - 1);
+        assert_eq!(allocator.transient(|_| false).to_bits(), u64::MAX);
+        let wrapped = allocator.transient(|id| id == first);
+        assert_eq!(wrapped.to_bits(), EntityId::TRANSIENT_BIT | 1);
+        // Persistent IDs are never mistaken for transient ones
+        assert!(!allocator.persistent().is_transient());
+    }
+}
@@ -0,0 +1,245 @@
+//! The graph divided into regions that are sent to clients as units
+//!
+//! A region is rooted at each node whose depth, its distance from the origin through the tree of
+//! parents, is a multiple of the region depth, and holds that node's descendants down to the next
+//! such depth. Regions are encoded when first sent and kept until something in them changes, so
+//! clients joining or passing through the same place share one encoding.
+
+use std::sync::Arc;
+
+use fxhash::FxHashMap;
+
+use common::{
+    dodeca::Side,
+    graph::{Graph, NodeId},
+    lru_slab::{LruSlab, SlotId},
+    mem_budget::{self, Reservation},
+    proto::{EncodedGraphRegion, GraphRegion},
+};
+
+use crate::stats::GraphRegionStats;
+
+/// Generations of nodes in each region, unless configured otherwise
+pub const DEFAULT_REGION_DEPTH: u32 = 3;
+
+/// Most bytes of encoded regions kept, beyond which those sent least recently are forgotten
+const CACHE_BYTES: usize = 64 << 20;
+
+/// Encoded regions of a graph, kept for reuse until they change
+pub struct GraphRegions {
+    /// Generations of nodes in each region
+    depth: u32,
+    /// Most bytes of encoded regions kept
+    budget: usize,
+    cache: LruSlab<CachedRegion>,
+    /// Slot of each cached region in `cache`, by root
+    slots: FxHashMap<NodeId, SlotId>,
+    /// Bytes of encoded regions in `cache`
+    bytes: usize,
+    encoded: u64,
+    reused: u64,
+}
+
+struct CachedRegion {
+    root: NodeId,
+    region: Arc<EncodedGraphRegion>,
+    _memory: Reservation,
+}
+
+impl GraphRegions {
+    /// Divide a graph into regions `depth` generations deep
+    pub fn new(depth: u32) -> Self {
+        Self::with_budget(depth, CACHE_BYTES)
+    }
+
+    fn with_budget(depth: u32, budget: usize) -> Self {
+        assert!(depth > 0, "regions must hold at least one generation");
+        Self {
+            depth,
+            budget,
+            cache: LruSlab::new(),
+            slots: FxHashMap::default(),
+            bytes: 0,
+            encoded: 0,
+            reused: 0,
+        }
+    }
+
+    /// Root of the region containing `node`
+    pub fn root_of(&self, graph: &Graph, mut node: NodeId) -> NodeId {
+        for _ in 0..graph.length(node) % self.depth {
+            let side = graph.parent(node).expect("only the origin has no parent");
+            node = graph.neighbor(node, side).unwrap();
+        }
+        node
+    }
+
+    /// Whether `node` is the root of a region
+    pub fn is_root(&self, graph: &Graph, node: NodeId) -> bool {
+        graph.length(node) % self.depth == 0
+    }
+
+    /// Nodes of the region rooted at `root` other than the root itself, each after its parent
+    pub fn members(&self, graph: &Graph, root: NodeId) -> Vec<NodeId> {
+        let mut members = Vec::new();
+        let mut generation = vec![root];
+        for _ in 1..self.depth {
+            generation = generation
+                .into_iter()
+                .flat_map(|node| children(graph, node))
+                .collect();
+            if generation.is_empty() {
+                break;
+            }
+            members.extend_from_slice(&generation);
+        }
+        members
+    }
+
+    /// The encoding of the region rooted at `root`, if it's cached, marking it as recently sent
+    pub fn cached(&mut self, root: NodeId) -> Option<Arc<EncodedGraphRegion>> {
+        let &slot = self.slots.get(&root)?;
+        self.reused += 1;
+        Some(self.cache.get_mut(slot).region.clone())
+    }
+
+    /// Encode `region`, rooted at `root`, and cache the encoding, forgetting those sent least
+    /// recently if the cache has grown too large
+    pub fn insert(&mut self, root: NodeId, region: &GraphRegion) -> Arc<EncodedGraphRegion> {
+        self.invalidate_root(root);
+        let region = Arc::new(EncodedGraphRegion::encode(region));
+        let bytes = region.0.len();
+        self.encoded += 1;
+        let slot = self.cache.insert(CachedRegion {
+            root,
+            region: region.clone(),
+            _memory: mem_budget::GRAPH_REGIONS.reserve(bytes),
+        });
+        self.slots.insert(root, slot);
+        self.bytes += bytes;
+        while self.bytes > self.budget {
+            let lru = self.cache.lru().unwrap();
+            self.remove(lru);
+        }
+        region
+    }
+
+    /// Forget the encoding of the region containing `node`, which has changed
+    pub fn invalidate(&mut self, graph: &Graph, node: NodeId) {
+        let root = self.root_of(graph, node);
+        self.invalidate_root(root);
+    }
+
+    fn invalidate_root(&mut self, root: NodeId) {
+        if let Some(&slot) = self.slots.get(&root) {
+            self.remove(slot);
+        }
+    }
+
+    fn remove(&mut self, slot: SlotId) {
+        let evicted = self.cache.remove(slot);
+        self.slots.remove(&evicted.root);
+        self.bytes -= evicted.region.0.len();
+    }
+
+    pub fn stats(&self) -> GraphRegionStats {
+        GraphRegionStats {
+            cached: self.cache.len() as usize,
+            cached_bytes: self.bytes,
+            encoded: self.encoded,
+            reused: self.reused,
+        }
+    }
+}
+
+/// Nodes whose parent is `node`
+fn children(graph: &Graph, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+    Side::iter().filter_map(move |side| {
+        let child = graph.neighbor(node, side)?;
+        (graph.parent(child) == Some(side)).then_some(child)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+
+    use super::*;
+    use common::{node_path::NodePath, proto::Position, traversal::ensure_nearby};
+
+    fn graph() -> Graph {
+        let mut graph = Graph::new(12);
+        ensure_nearby(&mut graph, &Position::origin(), 3.0);
+        graph
+    }
+
+    fn all_nodes(graph: &Graph) -> Vec<NodeId> {
+        iter::once(NodeId::ROOT)
+            .chain(
+                graph
+                    .tree()
+                    .map(|(side, parent)| graph.neighbor(parent, side).unwrap()),
+            )
+            .collect()
+    }
+
+    /// A region rooted at `root` without any of its contents
+    fn bare_region(graph: &Graph, root: NodeId) -> GraphRegion {
+        GraphRegion {
+            root: NodePath::to(graph, root),
+            nodes: Vec::new(),
+            modified_chunks: Vec::new(),
+            chunk_diffs: Vec::new(),
+            block_entities: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn regions_partition_graph() {
+        let graph = graph();
+        let regions = GraphRegions::new(2);
+        let nodes = all_nodes(&graph);
+        let mut counts = FxHashMap::<NodeId, u32>::default();
+        for &node in &nodes {
+            let root = regions.root_of(&graph, node);
+            assert_eq!(graph.length(root) % 2, 0);
+            if node != root {
+                continue;
+            }
+            for member in iter::once(root).chain(regions.members(&graph, root)) {
+                assert_eq!(regions.root_of(&graph, member), root);
+                *counts.entry(member).or_default() += 1;
+            }
+        }
+        assert_eq!(counts.len(), nodes.len());
+        assert!(counts.values().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn least_recently_sent_forgotten() {
+        let graph = graph();
+        let roots = all_nodes(&graph)
+            .into_iter()
+            .filter(|&node| graph.length(node) == 2)
+            .take(3)
+            .collect::<Vec<_>>();
+        let len = EncodedGraphRegion::encode(&bare_region(&graph, roots[0]))
+            .0
+            .len();
+        let mut regions = GraphRegions::with_budget(2, 2 * len);
+        for &root in &roots[..2] {
+            regions.insert(root, &bare_region(&graph, root));
+        }
+        assert!(regions.cached(roots[0]).is_some());
+        regions.insert(roots[2], &bare_region(&graph, roots[2]));
+        assert!(regions.cached(roots[1]).is_none());
+        assert!(regions.cached(roots[0]).is_some());
+        assert!(regions.cached(roots[2]).is_some());
+
+        let stats = regions.stats();
+        assert_eq!(stats.cached, 2);
+        assert_eq!(stats.cached_bytes, 2 * len);
+        assert_eq!(stats.encoded, 3);
+        assert_eq!(stats.reused, 3);
+    }
+}
@@ -0,0 +1,184 @@
+//! Noticing players who've stopped playing without disconnecting
+//!
+//! A running client sends a command every step, so one that's stopped has been left behind, as by
+//! a laptop's lid being closed. Its character is marked away after a while, so that it no longer
+//! keeps the world around it awake, and may be disconnected after a while longer. Other messages,
+//! like keepalives, show that the client is still there, but not that anyone is playing.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// Time without a command after which a character is marked away, unless configured otherwise
+pub const DEFAULT_AFK_TIME: Duration = Duration::from_secs(300);
+
+/// How clients that stop sending commands are treated, and what becomes of the characters of
+/// those that leave
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IdlePolicy {
+    /// Time without a command after which a client's character is marked away, if ever
+    pub afk_after: Option<Duration>,
+    /// Time without a command after which a client is disconnected, if ever
+    pub kick_after: Option<Duration>,
+    /// What becomes of a character when its client disconnects, for whatever reason
+    pub departure: DeparturePolicy,
+}
+
+impl Default for IdlePolicy {
+    fn default() -> Self {
+        Self {
+            afk_after: Some(DEFAULT_AFK_TIME),
+            kick_after: None,
+            departure: DeparturePolicy::default(),
+        }
+    }
+}
+
+/// What becomes of a character when its player disconnects
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeparturePolicy {
+    /// Remove the character, so that the player starts afresh at a spawn point on returning
+    #[default]
+    Despawn,
+    /// Remove the character, saving where it was so that the player returns there
+    PersistAndDespawn,
+}
+
+/// When a client was last heard from, and whether its character is taken to be away
+#[derive(Debug, Clone)]
+pub struct IdleTimer {
+    /// When the client last sent a command, or connected if it's sent none
+    last_command: Instant,
+    /// When the client last sent anything at all, or connected if it's sent nothing
+    last_message: Instant,
+    afk: bool,
+}
+
+/// A change in how a client is treated for having gone quiet
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IdleChange {
+    /// The client has sent no commands for `IdlePolicy::afk_after`
+    Away,
+    /// The client of a character that was away has sent a command
+    Back,
+    /// The client has sent no commands for `IdlePolicy::kick_after`, and should be disconnected
+    Kick,
+}
+
+impl IdleTimer {
+    /// Start timing a client that connected at `now`
+    pub fn new(now: Instant) -> Self {
+        Self {
+            last_command: now,
+            last_message: now,
+            afk: false,
+        }
+    }
+
+    /// Note a message received at `now`, which shows the player to be playing if it's a command
+    pub fn heard(&mut self, now: Instant, command: bool) {
+        self.last_message = self.last_message.max(now);
+        if command {
+            self.last_command = self.last_command.max(now);
+        }
+    }
+
+    /// How the client is to be treated as of `now` under `policy`, if that's changed since the
+    /// last call
+    pub fn poll(&mut self, now: Instant, policy: &IdlePolicy) -> Option<IdleChange> {
+        let idle = self.idle(now);
+        if policy.kick_after.is_some_and(|x| idle >= x) {
+            return Some(IdleChange::Kick);
+        }
+        let afk = policy.afk_after.is_some_and(|x| idle >= x);
+        if afk == self.afk {
+            return None;
+        }
+        self.afk = afk;
+        Some(if afk {
+            IdleChange::Away
+        } else {
+            IdleChange::Back
+        })
+    }
+
+    /// Whether the client's character is taken to be away, as of the last call to `poll`
+    pub fn afk(&self) -> bool {
+        self.afk
+    }
+
+    /// Time the client had gone without sending a command as of `now`
+    pub fn idle(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_command)
+    }
+
+    /// Time the client had gone without sending anything as of `now`
+    pub fn silent(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> IdlePolicy {
+        IdlePolicy {
+            afk_after: Some(Duration::from_secs(10)),
+            kick_after: Some(Duration::from_secs(30)),
+            departure: DeparturePolicy::Despawn,
+        }
+    }
+
+    #[test]
+    fn commands_reset_thresholds() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let policy = policy();
+        let mut timer = IdleTimer::new(start);
+        assert_eq!(timer.poll(at(9), &policy), None);
+        assert_eq!(timer.poll(at(10), &policy), Some(IdleChange::Away));
+        assert!(timer.afk());
+        // Only reported once
+        assert_eq!(timer.poll(at(11), &policy), None);
+
+        timer.heard(at(12), true);
+        assert_eq!(timer.poll(at(12), &policy), Some(IdleChange::Back));
+        assert!(!timer.afk());
+        // Timed from the latest command rather than from connecting
+        assert_eq!(timer.poll(at(30), &policy), Some(IdleChange::Away));
+        assert_eq!(timer.poll(at(41), &policy), None);
+        assert_eq!(timer.poll(at(42), &policy), Some(IdleChange::Kick));
+    }
+
+    #[test]
+    fn other_messages_dont_count_as_playing() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let policy = policy();
+        let mut timer = IdleTimer::new(start);
+        for secs in 1..=30 {
+            timer.heard(at(secs), false);
+        }
+        assert_eq!(timer.silent(at(30)), Duration::ZERO);
+        assert_eq!(timer.idle(at(30)), Duration::from_secs(30));
+        assert_eq!(timer.poll(at(30), &policy), Some(IdleChange::Kick));
+    }
+
+    #[test]
+    fn thresholds_disabled() {
+        let start = Instant::now();
+        let policy = IdlePolicy {
+            afk_after: None,
+            kick_after: None,
+            departure: DeparturePolicy::Despawn,
+        };
+        let mut timer = IdleTimer::new(start);
+        assert_eq!(
+            timer.poll(start + Duration::from_secs(1 << 20), &policy),
+            None
+        );
+        assert!(!timer.afk());
+    }
+}
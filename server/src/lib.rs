@@ -1,34 +1,170 @@
 #![allow(clippy::needless_borrowed_reference)]
 
 extern crate nalgebra as na;
+mod activity;
+mod admin;
+mod autosave;
+mod bandwidth;
+mod console;
+mod edit_history;
+mod entity_ids;
+mod graph_regions;
+mod idle;
 mod input_queue;
+mod local;
+mod outgoing;
 mod postcard_helpers;
+mod pregenerate;
+mod protection;
+mod save_loader;
+mod scheduler;
+mod sequence_window;
 mod sim;
+mod spawn;
+mod stats;
+#[cfg(feature = "status")]
+mod status;
+mod update_lod;
 
-use std::{net::UdpSocket, sync::Arc, time::Instant};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Error, Result};
-use futures::{select, StreamExt};
+use futures::{select, FutureExt, StreamExt};
+use fxhash::FxHashSet;
 use hecs::Entity;
+use serde::Serialize;
 use slotmap::DenseSlotMap;
-use tokio::sync::mpsc;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    watch,
+};
 use tokio_stream::wrappers::{IntervalStream, ReceiverStream};
-use tracing::{debug, error, error_span, info, trace};
+use tracing::{debug, error, error_span, info, trace, warn};
 
-use common::{codec, proto, SimConfig};
+use admin::Actor;
+use autosave::Autosave;
+use bandwidth::{BulkPacer, ConnectionTraffic};
+use common::{
+    block_entity::{BlockEntityChange, SIGN_LINES},
+    codec::{self, BodyFormat},
+    graph::NodeId,
+    logging::EditId,
+    mem_budget,
+    node::{ChunkId, Coords},
+    protection::ProtectedRegion,
+    proto::{
+        self,
+        negotiation::{
+            Protocol, Refusal, IDLE_CLOSE_CODE, REFUSED_CLOSE_CODE, STOPPING_CLOSE_CODE,
+        },
+        Capabilities,
+    },
+    worldgen::WorldgenPath,
+    EntityId, SimConfig,
+};
+use console::ConsoleRequest;
+use idle::{IdleChange, IdleTimer};
 use input_queue::InputQueue;
-use save::Save;
-use sim::Sim;
+use outgoing::OutgoingCounters;
+use save::{Journal, Save};
+use save_loader::SaveLoader;
+use sim::{sends_graph_regions, Sim, TeleportError};
+use stats::TickTimes;
+use update_lod::UpdateSchedule;
+
+pub use admin::{AdminCommand, AdminError, Place, CONSOLE_ACTOR};
+pub use console::{parse_command, ParseError};
+pub use edit_history::{
+    DEFAULT_MAX_AGE as DEFAULT_EDIT_HISTORY_AGE,
+    DEFAULT_MAX_RECORDS as DEFAULT_EDIT_HISTORY_RECORDS,
+};
+pub use entity_ids::EntityIdAllocator;
+pub use graph_regions::DEFAULT_REGION_DEPTH as DEFAULT_GRAPH_REGION_DEPTH;
+pub use idle::{DeparturePolicy, IdlePolicy, DEFAULT_AFK_TIME};
+pub use local::{LocalClientId, LocalMessage, LocalServer};
+pub use pregenerate::{pregenerate, PregenerationSummary};
+pub use scheduler::{BlockChange, TaskId, TaskKind};
+pub use stats::{
+    ConnectionStats, GraphRegionStats, PhaseStats, RegionActivityStats, ServerStats, TaskStats,
+    TickStats,
+};
+
+/// Interval at which `ServerStats` are published
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of messages of each kind that can wait to be sent to a client
+const CLIENT_QUEUE_CAPACITY: usize = 32;
+
+/// Length of time a client can spend too far behind to be sent state deltas before it's
+/// disconnected
+const MAX_DELTA_GAP: Duration = Duration::from_secs(2);
+
+/// Most nodes a client may ask for the way to in one `ClientMessage::ResyncNodes`
+const MAX_RESYNC_NODES: usize = 64;
+
+/// Shortest time between node hints from a client that are heeded
+const MIN_NODE_HINT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Time between writes of the world to the save, unless configured otherwise
+pub const DEFAULT_AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
 
 pub struct NetParams {
     pub certificate_chain: Vec<rustls::Certificate>,
     pub private_key: rustls::PrivateKey,
     pub socket: UdpSocket,
+    /// Address to serve `ServerStats` on over HTTP, if any
+    pub status: Option<SocketAddr>,
+    /// Names of clients permitted to send administrative commands, such as
+    /// `ClientMessage::SetMovementModes`, `ClientMessage::Teleport`,
+    /// `ClientMessage::SetWaypoint`, `ClientMessage::SetProtectedRegion`,
+    /// `ClientMessage::TraceCollisions`, and `ClientMessage::Rollback`
+    pub admins: Vec<String>,
+    /// Names of clients whose block edits aren't paid for from their characters' inventories
+    pub creative: Vec<String>,
+    /// Regions to protect on startup, replacing any saved regions of the same name
+    pub protected_regions: Vec<ProtectedRegion>,
+    /// Asset pack suggested to clients that support `Capabilities::ASSET_PACKS`
+    pub asset_pack: Option<proto::AssetPackOffer>,
+    /// Generations of nodes in each region of the graph sent to clients that support
+    /// `Capabilities::GRAPH_REGIONS`
+    pub graph_region_depth: u32,
+    /// Most block edits remembered for administrators to roll back
+    pub edit_history_records: usize,
+    /// Longest block edits are remembered for administrators to roll back
+    pub edit_history_age: Duration,
+    /// Whether to take administrative commands from standard input, as typed at a terminal
+    pub console: bool,
+    /// Unix socket to take administrative commands from, for service managers without a terminal
+    pub console_socket: Option<PathBuf>,
+    /// Bytes per second of bulk traffic, like graph regions, sent to each client that takes it on
+    /// a stream of its own, beyond which the rest waits for later steps. Unlimited if `None`.
+    pub bulk_bytes_per_second: Option<u64>,
+    /// Whether to compress large messages to clients that support `Capabilities::COMPRESSION`
+    pub compression: bool,
+    /// How clients that stop sending commands are treated, and what becomes of the characters of
+    /// those that leave
+    pub idle: IdlePolicy,
+}
+
+pub struct SaveParams {
+    pub save: Save,
+    /// Journal for `save`, already opened to recover any interrupted writes
+    pub journal: Journal,
+    /// Time between writes of the world to the save
+    pub autosave_interval: Duration,
+    /// Whether to save and stop when the process is interrupted, rather than leaving the
+    /// interrupt to the host application
+    pub save_on_interrupt: bool,
 }
 
 #[tokio::main]
-pub async fn run(net: NetParams, mut sim: SimConfig, save: Save) -> Result<()> {
-    sim.chunk_size = save.meta().chunk_size as u8;
+pub async fn run(net: NetParams, mut sim: SimConfig, save: SaveParams) -> Result<()> {
+    sim.chunk_size = save.save.meta().chunk_size as u8;
     let server_config =
         quinn::ServerConfig::with_single_cert(net.certificate_chain, net.private_key)
             .context("parsing certificate")?;
@@ -40,8 +176,61 @@ pub async fn run(net: NetParams, mut sim: SimConfig, save: Save) -> Result<()> {
     )?;
     info!(address = %endpoint.local_addr().unwrap(), "listening");
 
-    let server = Server::new(sim, save);
-    server.run(endpoint).await;
+    let save_on_interrupt = save.save_on_interrupt;
+    let mut server = Server::new(sim, save, net.admins);
+    // Connections are accepted at once, with saved voxels read as they're needed
+    server.sim.read_save_in_background();
+    server.sim.set_graph_region_depth(net.graph_region_depth);
+    server
+        .sim
+        .set_edit_history_limits(net.edit_history_records, net.edit_history_age);
+    server.asset_pack = net.asset_pack;
+    server.creative = net.creative;
+    server.bulk_bytes_per_second = net.bulk_bytes_per_second;
+    server.compression = net.compression;
+    server.idle = net.idle;
+    for region in net.protected_regions {
+        let name = region.name.clone();
+        if let Err(e) = server.sim.set_protected_region(region) {
+            warn!(%name, "ignoring configured protected region: {}", e);
+        }
+    }
+    if let Some(address) = net.status {
+        serve_status(address, server.stats.subscribe()).await?;
+    }
+    let (console_send, console_recv) = mpsc::channel(16);
+    if net.console {
+        console::read_stdin(console_send.clone());
+    }
+    if let Some(path) = net.console_socket {
+        console::listen(&path, console_send)?;
+    }
+    server.run(endpoint, console_recv, save_on_interrupt).await;
+    Ok(())
+}
+
+/// Resolves when the process is interrupted, if `enabled`
+async fn interrupted(enabled: bool) {
+    if !enabled || tokio::signal::ctrl_c().await.is_err() {
+        futures::future::pending::<()>().await;
+    }
+}
+
+#[cfg(feature = "status")]
+async fn serve_status(address: SocketAddr, stats: watch::Receiver<ServerStats>) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .context("binding status socket")?;
+    info!(address = %listener.local_addr()?, "serving status");
+    tokio::spawn(status::serve(listener, stats));
+    Ok(())
+}
+
+#[cfg(not(feature = "status"))]
+async fn serve_status(_: SocketAddr, _: watch::Receiver<ServerStats>) -> Result<()> {
+    tracing::warn!(
+        "status endpoint configured, but this server was built without the \"status\" feature"
+    );
     Ok(())
 }
 
@@ -49,32 +238,116 @@ struct Server {
     cfg: Arc<SimConfig>,
     sim: Sim,
     clients: DenseSlotMap<ClientId, Client>,
-    save: Save,
+    save: Arc<Save>,
+    autosave: Autosave,
+    save_loader: SaveLoader,
+    tick_times: TickTimes,
+    stats: watch::Sender<ServerStats>,
+    stats_published: Instant,
+    admins: Vec<String>,
+    creative: Vec<String>,
+    asset_pack: Option<proto::AssetPackOffer>,
+    /// Budget for bulk traffic to each client that's paced, as in `NetParams`
+    bulk_bytes_per_second: Option<u64>,
+    /// Whether compression is offered to clients
+    compression: bool,
+    /// How clients that stop sending commands are treated, as in `NetParams`
+    idle: IdlePolicy,
+    /// Whether an administrator has asked the server to stop
+    stopping: bool,
 }
 
 impl Server {
-    fn new(params: SimConfig, save: Save) -> Self {
+    fn new(params: SimConfig, save: SaveParams, admins: Vec<String>) -> Self {
         let cfg = Arc::new(params);
+        let SaveParams {
+            save,
+            journal,
+            autosave_interval,
+            ..
+        } = save;
+        let save = Arc::new(save);
         Self {
-            sim: Sim::new(cfg.clone()),
+            sim: Sim::new(cfg.clone(), &save),
             cfg,
             clients: DenseSlotMap::default(),
+            autosave: Autosave::new(autosave_interval, save.clone(), journal),
+            save_loader: SaveLoader::new(save.clone()),
             save,
+            tick_times: TickTimes::default(),
+            stats: watch::channel(ServerStats::default()).0,
+            stats_published: Instant::now(),
+            admins,
+            creative: Vec::new(),
+            asset_pack: None,
+            bulk_bytes_per_second: None,
+            compression: true,
+            idle: IdlePolicy::default(),
+            stopping: false,
         }
     }
 
-    async fn run(mut self, endpoint: quinn::Endpoint) {
+    async fn run(
+        mut self,
+        endpoint: quinn::Endpoint,
+        console: mpsc::Receiver<ConsoleRequest>,
+        save_on_interrupt: bool,
+    ) {
         let mut ticks = IntervalStream::new(tokio::time::interval(self.cfg.step_interval)).fuse();
         let mut incoming = ReceiverStream::new(self.handle_incoming(endpoint)).fuse();
         let (client_events_send, client_events) = mpsc::channel(128);
         let mut client_events = ReceiverStream::new(client_events).fuse();
-        loop {
+        let mut console = ReceiverStream::new(console).fuse();
+        let mut interrupt = Box::pin(interrupted(save_on_interrupt)).fuse();
+        while !self.stopping {
             select! {
-                _ = ticks.next() => { self.on_step(); },
+                _ = ticks.next() => { self.on_step(Instant::now()); },
                 conn = incoming.select_next_some() => { self.on_connect(conn, client_events_send.clone()); }
-                e = client_events.select_next_some() => { self.on_client_event(e.0, e.1); }
+                e = client_events.select_next_some() => { self.on_client_event(e.0, e.1, Instant::now()); }
+                request = console.select_next_some() => { self.on_console(request); }
+                _ = interrupt => { break; }
             }
         }
+        self.shutdown();
+    }
+
+    /// Save everything, then disconnect every client, telling them why
+    ///
+    /// Characters are saved from the live world, so clients are only disconnected once they have
+    /// been, and the save is only closed once clients can no longer change it.
+    fn shutdown(mut self) {
+        info!("saving before exit");
+        let persist = self.idle.departure == DeparturePolicy::PersistAndDespawn;
+        for client in self.clients.values() {
+            if let Some(ref handles) = client.handles {
+                self.sim.record_departure(handles.character, persist);
+            }
+        }
+        self.flush();
+        for client in self.clients.values() {
+            if let Some(ref conn) = client.conn {
+                conn.close(STOPPING_CLOSE_CODE.into(), b"server stopping");
+            }
+        }
+        self.clients.clear();
+        self.autosave.finish();
+        info!("saved");
+    }
+
+    /// Run the administrative command typed at the console, answering with what became of it
+    fn on_console(&mut self, request: ConsoleRequest) {
+        let reply = match parse_command(&request.line) {
+            Ok(None) => return,
+            Ok(Some(command)) => match self.run_admin(&Actor::Console, command) {
+                Ok(done) => done,
+                Err(e) => e.to_string(),
+            },
+            Err(e) => {
+                warn!(line = %request.line, "can't understand console command: {}", e);
+                e.to_string()
+            }
+        };
+        let _ = request.reply.send(reply);
     }
 
     fn handle_incoming(&self, endpoint: quinn::Endpoint) -> mpsc::Receiver<quinn::Connection> {
@@ -86,7 +359,7 @@ impl Server {
                 tokio::spawn(async move {
                     match conn.await {
                         Err(e) => {
-                            error!("incoming connection failed: {}", e.to_string());
+                            error!(error = %e, "incoming connection failed");
                         }
                         Ok(connection) => {
                             let _ = incoming_send.send(connection).await;
@@ -98,114 +371,665 @@ impl Server {
         incoming_recv
     }
 
-    fn on_step(&mut self) {
-        let now = Instant::now();
+    /// Advance the simulation by one step, taking `now` to be the time at which it's due
+    fn on_step(&mut self, now: Instant) {
+        let started = Instant::now();
+        self.check_idle(now);
+
         // Apply queued inputs
         for (id, client) in &mut self.clients {
             if let Some(ref handles) = client.handles {
                 if let Some(cmd) = client.inputs.pop(now, self.cfg.input_queue_size) {
                     client.latest_input_processed = cmd.generation;
                     if let Err(e) = self.sim.command(handles.character, cmd) {
-                        error!(client = ?id, "couldn't process command: {}", e);
+                        error!(client = ?id.0, error = %e, "couldn't process command");
                     }
                 }
             }
         }
 
         // Step the simulation
-        let (spawns, delta) = self.sim.step();
-        let spawns = Arc::new(spawns);
+        for saved in self.save_loader.poll() {
+            self.sim.receive_saved(saved.node, saved.chunks);
+        }
+        let (spawns, delta, inventories) = self.sim.step(&self.save);
+        self.save_loader.request(self.sim.take_save_reads());
+        let rejected_block_updates = self.sim.take_rejected_block_updates();
+        let sounds = self.sim.take_sounds();
+        let block_entity_changes = self.sim.take_block_entity_changes();
+        let block_entity_changes =
+            (!block_entity_changes.is_empty()).then(|| Arc::new(block_entity_changes));
+        let whole = !spawns.is_empty()
+            && self.clients.values().any(|client| {
+                client.handles.is_some()
+                    && !client.capabilities.contains(Capabilities::SPLIT_UPDATES)
+            });
+        for &(author, ref update) in &spawns.block_updates {
+            debug!(edit = %EditId::new(author, update), "broadcasting block update");
+        }
+        for &(author, ref fill) in &spawns.block_fills {
+            let edit = EditId {
+                author,
+                sequence: fill.sequence,
+            };
+            debug!(%edit, "broadcasting block fill");
+        }
+        let spawns = SpawnMessages::new(spawns, whole);
         let mut overran = Vec::new();
         for (client_id, client) in &mut self.clients {
             if let Some(ref mut handles) = client.handles {
+                // Despawned, as by disconnecting
+                let lost = handles
+                    .followed
+                    .is_some_and(|x| self.sim.position(x).is_none());
+                if lost {
+                    info!(client = ?client_id.0, "followed character lost");
+                    handles.followed = None;
+                }
+                let regions = if sends_graph_regions(client.capabilities) {
+                    handles.unsent_regions(&mut self.sim)
+                } else {
+                    Vec::new()
+                };
                 let mut delta = delta.clone();
                 delta.latest_input = client.latest_input_processed;
-                let r1 = handles.unordered.try_send(delta);
-                let r2 = if !spawns.spawns.is_empty()
-                    || !spawns.despawns.is_empty()
-                    || !spawns.nodes.is_empty()
-                    || !spawns.block_updates.is_empty()
-                    || !spawns.modified_chunks.is_empty()
+                delta.tether = self.sim.tether(handles.character);
+                if let Some(viewer) = self.sim.viewer(handles.character, handles.viewpoint()) {
+                    handles.schedule.thin(&viewer, &mut delta);
+                }
+                let counters = &mut handles.counters;
+                let mut keep = counters.delta(handles.unordered.try_send(delta));
+                if lost && client.capabilities.contains(Capabilities::FOLLOW) {
+                    keep &= counters.ordered(handles.ordered.try_send(Ordered::Following(None)));
+                }
+                if let Some(msg) = spawns.message(client.capabilities) {
+                    keep &= counters.ordered(handles.ordered.queue(msg));
+                }
+                if let Some(ref changes) = block_entity_changes {
+                    keep &= counters.ordered(
+                        handles
+                            .ordered
+                            .try_send(Ordered::BlockEntities(changes.clone())),
+                    );
+                }
+                if !regions.is_empty() {
+                    keep &= counters.ordered(handles.ordered.queue(Ordered::GraphRegions(regions)));
+                }
+                if let Some((_, inventory)) = inventories
+                    .iter()
+                    .find(|&&(entity, _)| entity == handles.character)
                 {
-                    handles.ordered.try_send(spawns.clone())
-                } else {
-                    Ok(())
-                };
-                use mpsc::error::TrySendError::Full;
-                match (r1, r2) {
-                    (Err(Full(_)), _) | (_, Err(Full(_))) => {
-                        overran.push(client_id);
+                    keep &= counters.ordered(handles.ordered.try_send(Ordered::Inventory(
+                        proto::InventoryUpdate {
+                            latest_input: client.latest_input_processed,
+                            inventory: inventory.clone(),
+                        },
+                    )));
+                }
+                for (_, rejection) in rejected_block_updates
+                    .iter()
+                    .filter(|&&(entity, _)| entity == handles.character)
+                {
+                    keep &= counters.ordered(
+                        handles
+                            .ordered
+                            .try_send(Ordered::BlockUpdateRejected(rejection.clone())),
+                    );
+                }
+                if !sounds.is_empty() && client.capabilities.contains(Capabilities::SOUNDS) {
+                    let interest = self.sim.interest(handles.viewpoint());
+                    let audible = sounds
+                        .iter()
+                        .filter(|x| interest.contains(&x.source.node()))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    if !audible.is_empty() {
+                        keep &=
+                            counters.ordered(handles.ordered.try_send(Ordered::Sounds(audible)));
                     }
-                    _ => {}
+                }
+                handles.ordered.release_bulk(self.cfg.step_interval);
+                if !keep {
+                    overran.push(client_id);
                 }
             }
         }
-        for client_id in overran {
-            error!("dropping slow client {:?}", client_id.0);
-            self.clients[client_id]
-                .conn
-                .close(1u32.into(), b"client reading too slowly");
+        self.drop_slow_clients(overran);
+
+        if self.autosave.due() {
+            self.flush();
+        }
+
+        self.tick_times.record(started.elapsed());
+        self.tick_times.record_phases(self.sim.phase_times());
+        if self.stats_published.elapsed() >= STATS_INTERVAL {
+            self.stats_published = Instant::now();
+            let stats = self.collect_stats(now);
+            // Readers copy out what they need, so the lock is held only briefly
+            self.stats.send_replace(stats);
+        }
+    }
+
+    /// Mark the characters of clients that have stopped sending commands as away, or back again
+    /// once they resume, and disconnect those that have stopped for too long
+    fn check_idle(&mut self, now: Instant) {
+        let mut kicked = Vec::new();
+        for (client_id, client) in &mut self.clients {
+            let Some(ref handles) = client.handles else {
+                continue;
+            };
+            let afk = match client.idle.poll(now, &self.idle) {
+                None => continue,
+                Some(IdleChange::Kick) => {
+                    kicked.push(client_id);
+                    continue;
+                }
+                Some(IdleChange::Away) => true,
+                Some(IdleChange::Back) => false,
+            };
+            info!(client = ?client_id.0, afk, "idleness changed");
+            if let Err(e) = self.sim.set_afk(handles.character, afk) {
+                error!(client = ?client_id.0, error = %e, "couldn't mark character away");
+            }
+        }
+        for client_id in kicked {
+            info!(client = ?client_id.0, "disconnecting idle client");
+            if let Some(ref conn) = self.clients[client_id].conn {
+                conn.close(IDLE_CLOSE_CODE.into(), b"idle for too long");
+            }
             self.cleanup_client(client_id);
         }
+    }
 
-        // Save the world. Could be less frequent if it becomes a bottleneck.
-        if let Err(e) = self.sim.save(&mut self.save) {
-            error!("couldn't save: {}", e);
+    /// Disconnect clients that have fallen too far behind on the messages sent to them
+    fn drop_slow_clients(&mut self, clients: Vec<ClientId>) {
+        for client_id in clients {
+            error!(client = ?client_id.0, "dropping slow client");
+            if let Some(ref conn) = self.clients[client_id].conn {
+                conn.close(1u32.into(), b"client reading too slowly");
+            }
+            self.cleanup_client(client_id);
         }
     }
 
-    fn on_client_event(&mut self, client_id: ClientId, event: ClientEvent) {
-        let span = error_span!("client", id = ?client_id.0);
+    /// Hand everything that's changed to the autosave thread
+    fn flush(&mut self) {
+        self.autosave.flush(self.sim.take_changes());
+    }
+
+    /// Statistics as of `now`
+    fn collect_stats(&mut self, now: Instant) -> ServerStats {
+        ServerStats {
+            step: self.sim.current_step(),
+            connections: self
+                .clients
+                .values()
+                .map(|client| {
+                    let mut stats = ConnectionStats {
+                        name: client.name.clone(),
+                        rtt_ms: client
+                            .conn
+                            .as_ref()
+                            .map_or(0.0, |conn| conn.rtt().as_secs_f64() * 1e3),
+                        queued: 0,
+                        sent: 0,
+                        dropped: 0,
+                        bulk_waiting_bytes: 0,
+                        traffic_sent: Default::default(),
+                        traffic_received: Default::default(),
+                        idle_ms: client.idle.idle(now).as_secs_f64() * 1e3,
+                        silent_ms: client.idle.silent(now).as_secs_f64() * 1e3,
+                        afk: client.idle.afk(),
+                    };
+                    if let Some(ref handles) = client.handles {
+                        stats.queued = 2 * CLIENT_QUEUE_CAPACITY
+                            - handles.ordered.capacity()
+                            - handles.unordered.capacity();
+                        stats.sent = handles.counters.sent;
+                        stats.dropped = handles.counters.dropped;
+                        stats.bulk_waiting_bytes = handles.ordered.bulk_waiting_bytes();
+                    }
+                    let traffic = client.traffic.lock().unwrap();
+                    stats.traffic_sent = traffic.sent.clone();
+                    stats.traffic_received = traffic.received.clone();
+                    stats
+                })
+                .collect(),
+            tick: self.tick_times.summarize(),
+            nodes: self.sim.graph().len(),
+            chunks: self.sim.graph().chunk_counts(),
+            memory: mem_budget::report(),
+            worldgen_path: WorldgenPath::fastest().name(),
+            graph_regions: self.sim.graph_region_stats(),
+            regions: self.sim.region_activity_stats(),
+            tasks: self.sim.task_stats(),
+        }
+    }
+
+    /// Handle `event` from `client_id`, taking it to have arrived at `now`
+    fn on_client_event(&mut self, client_id: ClientId, event: ClientEvent, now: Instant) {
+        let span = error_span!("client", client = ?client_id.0);
         let _guard = span.enter();
         let Some(client) = self.clients.get_mut(client_id) else {
             // Skip messages from cleaned-up clients
             return;
         };
+        if !matches!(event, ClientEvent::Lost(_)) {
+            client
+                .idle
+                .heard(now, matches!(event, ClientEvent::Command(_)));
+        }
         match event {
             ClientEvent::Hello(hello) => {
-                assert!(client.handles.is_none());
-                let snapshot = Arc::new(self.sim.snapshot());
-                let (id, entity) = self.sim.spawn_character(hello);
-                let (ordered_send, ordered_recv) = mpsc::channel(32);
-                ordered_send.try_send(snapshot).unwrap();
-                let (unordered_send, unordered_recv) = mpsc::channel(32);
-                client.handles = Some(ClientHandles {
-                    character: entity,
-                    ordered: ordered_send,
-                    unordered: unordered_send,
-                });
-                let connection = client.conn.clone();
-                let server_hello = proto::ServerHello {
-                    character: id,
-                    sim_config: (*self.cfg).clone(),
-                };
-                tokio::spawn(async move {
-                    // Errors will be handled by recv task
-                    let _ =
-                        drive_send(connection, server_hello, unordered_recv, ordered_recv).await;
-                });
+                if let Err(refusal) = self.greet(client_id, hello) {
+                    warn!("refusing client: {}", refusal);
+                    if let Some(ref conn) = self.clients[client_id].conn {
+                        conn.close(REFUSED_CLOSE_CODE.into(), refusal.to_string().as_bytes());
+                    }
+                    self.cleanup_client(client_id);
+                }
             }
             ClientEvent::Lost(e) => {
-                error!("lost: {:#}", e);
-                client.conn.close(0u32.into(), b"");
+                error!(error = format_args!("{e:#}"), "lost");
+                if let Some(ref conn) = client.conn {
+                    conn.close(0u32.into(), b"");
+                }
                 self.cleanup_client(client_id);
             }
             ClientEvent::Command(cmd) => {
                 if cmd.generation.wrapping_sub(client.latest_input_received) < u16::max_value() / 2
                 {
                     client.latest_input_received = cmd.generation;
-                    client.inputs.push(cmd, Instant::now());
+                    client.inputs.push(cmd, now);
                 } else {
                     debug!("dropping obsolete command");
+                    // The client is waiting to hear what became of any block update or fill it
+                    // carried
+                    if let Some(ref handles) = client.handles {
+                        let input = &cmd.character_input;
+                        let sequences = (input.block_update.iter().map(|x| x.sequence))
+                            .chain(input.block_fill.iter().map(|x| x.sequence));
+                        for sequence in sequences {
+                            let _ = handles.ordered.try_send(Ordered::BlockUpdateRejected(
+                                proto::BlockUpdateRejection {
+                                    sequence,
+                                    reason: proto::RejectionReason::Refused,
+                                },
+                            ));
+                        }
+                    }
+                }
+            }
+            ClientEvent::Admin(command) => {
+                let Some(actor) = self.player_actor(client_id) else {
+                    warn!(
+                        command = command.name(),
+                        "ignoring command from client without a character"
+                    );
+                    return;
+                };
+                // Clients learn what came of commands from their effects; the outcome is logged
+                let _ = self.run_admin(&actor, command);
+            }
+            ClientEvent::Follow(character) => {
+                let Some(actor) = self.player_actor(client_id) else {
+                    return;
+                };
+                if self
+                    .run_admin(&actor, AdminCommand::Follow(character))
+                    .is_err()
+                {
+                    // Answered either way, so that the client doesn't wait on a refusal
+                    self.tell_followed(client_id);
+                }
+            }
+            ClientEvent::DumpCollisionTrace(character) => {
+                if !client
+                    .name
+                    .as_ref()
+                    .map_or(false, |name| self.admins.contains(name))
+                {
+                    warn!(%character, "refusing to send collision trace to non-administrator");
+                    return;
+                }
+                let trace = self
+                    .character_entity(&character)
+                    .and_then(|subject| self.sim.collision_trace(subject));
+                let Some(ref handles) = self.clients[client_id].handles else {
+                    return;
+                };
+                let _ = handles.ordered.try_send(Ordered::CollisionTrace(
+                    proto::CollisionTraceReport { character, trace },
+                ));
+            }
+            ClientEvent::ResyncNodes(mut nodes) => {
+                let Some(ref mut handles) = client.handles else {
+                    return;
+                };
+                if nodes.len() > MAX_RESYNC_NODES {
+                    warn!(
+                        count = nodes.len(),
+                        "truncating oversized node resync request"
+                    );
+                    nodes.truncate(MAX_RESYNC_NODES);
+                }
+                debug!(count = nodes.len(), "resending nodes");
+                if sends_graph_regions(client.capabilities) {
+                    // Whole regions rather than ways, since they're likely to be encoded already
+                    let regions = self
+                        .sim
+                        .graph_region_roots(nodes)
+                        .into_iter()
+                        .map(|root| {
+                            handles.regions_sent.insert(root);
+                            self.sim.graph_region(root)
+                        })
+                        .collect::<Vec<_>>();
+                    if !regions.is_empty() {
+                        let _ = handles.ordered.queue(Ordered::GraphRegions(regions));
+                    }
+                    return;
+                }
+                let split = client.capabilities.contains(Capabilities::SPLIT_UPDATES);
+                let spawns = SpawnMessages::new(self.sim.resync_nodes(&nodes), !split);
+                if let Some(msg) = spawns.message(client.capabilities) {
+                    let _ = handles.ordered.queue(msg);
+                }
+            }
+            ClientEvent::SetSignText {
+                chunk,
+                coords,
+                lines,
+            } => {
+                let Some(ref handles) = client.handles else {
+                    return;
+                };
+                if let Err(e) = self
+                    .sim
+                    .set_sign_text(handles.character, chunk, coords, lines)
+                {
+                    debug!(?chunk, ?coords, "refusing sign text: {}", e);
                 }
             }
+            ClientEvent::NodeInterestHint(hint) => {
+                let Some(ref mut handles) = client.handles else {
+                    return;
+                };
+                if handles
+                    .node_hinted
+                    .is_some_and(|at| now.saturating_duration_since(at) < MIN_NODE_HINT_INTERVAL)
+                {
+                    debug!("dropping node hint sent too soon after the last");
+                    return;
+                }
+                handles.node_hinted = Some(now);
+                let admin = client
+                    .name
+                    .as_ref()
+                    .map_or(false, |name| self.admins.contains(name));
+                match self.sim.hint_nodes(handles.viewpoint(), &hint, admin) {
+                    Ok(created) => {
+                        trace!(created, radius = hint.radius, "extended graph as hinted")
+                    }
+                    Err(e) => debug!(radius = hint.radius, "ignoring node hint: {}", e),
+                }
+            }
+            // Heard from, which is all a keepalive is for
+            ClientEvent::Keepalive => {}
+        }
+    }
+
+    /// Spawn a character for the client that sent `hello` and start sending it the world, unless
+    /// it can't be talked to
+    fn greet(&mut self, client_id: ClientId, hello: proto::ClientHello) -> Result<(), Refusal> {
+        let mut capabilities = Protocol::CURRENT.accept_client(&hello)?;
+        if !self.compression {
+            capabilities = capabilities.without(Capabilities::COMPRESSION);
+        }
+        let client = &mut self.clients[client_id];
+        assert!(client.handles.is_none());
+        client.name = Some(hello.name.clone());
+        client.capabilities = capabilities;
+        let split = capabilities.contains(Capabilities::SPLIT_UPDATES);
+        let snapshot = SpawnMessages::new(self.sim.snapshot(capabilities), !split);
+        let (id, entity) = self.sim.spawn_character(hello);
+        if self.admins.contains(client.name.as_ref().unwrap()) {
+            self.sim
+                .set_fill_limit(entity, self.cfg.admin_max_fill_volume)
+                .unwrap();
+        }
+        if self.creative.contains(client.name.as_ref().unwrap()) {
+            self.sim.set_creative(entity, true).unwrap();
+        }
+        self.sim.return_character(&self.save, entity);
+        let (ordered_send, ordered_recv) = mpsc::channel(CLIENT_QUEUE_CAPACITY);
+        let mut ordered = OrderedQueue::new(ordered_send, capabilities, self.bulk_bytes_per_second);
+        if let Some(msg) = snapshot.message(capabilities) {
+            ordered.queue(msg).unwrap();
+        }
+        let block_entities = self.sim.block_entities();
+        if !block_entities.is_empty() && !sends_graph_regions(capabilities) {
+            ordered
+                .try_send(Ordered::BlockEntities(Arc::new(block_entities)))
+                .unwrap();
+        }
+        let waypoints = self.sim.waypoints().cloned().collect::<Vec<_>>();
+        if !waypoints.is_empty() && capabilities.contains(Capabilities::SHARED_WAYPOINTS) {
+            ordered
+                .try_send(Ordered::Waypoints(proto::WaypointsUpdate {
+                    set: waypoints,
+                    removed: Vec::new(),
+                }))
+                .unwrap();
+        }
+        let (unordered_send, unordered_recv) = mpsc::channel(CLIENT_QUEUE_CAPACITY);
+        let max_dropped_deltas =
+            (MAX_DELTA_GAP.as_secs_f64() / self.cfg.step_interval.as_secs_f64()).ceil() as u32;
+        let mut handles = ClientHandles {
+            character: entity,
+            ordered,
+            unordered: unordered_send,
+            counters: OutgoingCounters::new(max_dropped_deltas),
+            schedule: UpdateSchedule::default(),
+            regions_sent: FxHashSet::default(),
+            regions_node: None,
+            node_hinted: None,
+            followed: None,
+        };
+        if sends_graph_regions(capabilities) {
+            let regions = handles.unsent_regions(&mut self.sim);
+            handles
+                .ordered
+                .queue(Ordered::GraphRegions(regions))
+                .unwrap();
+        }
+        client.handles = Some(handles);
+        let server_hello = proto::ServerHello {
+            header: proto::ServerHelloHeader {
+                protocol_version: Protocol::CURRENT.version,
+                capabilities,
+            },
+            character: id,
+            sim_config: (*self.cfg).clone(),
+            asset_pack: self
+                .asset_pack
+                .clone()
+                .filter(|_| capabilities.contains(Capabilities::ASSET_PACKS)),
+            materials: Some(proto::MaterialRegistry::current()),
+        };
+        match client.conn.clone() {
+            Some(connection) => {
+                let traffic = client.traffic.clone();
+                tokio::spawn(async move {
+                    // Errors will be handled by recv task
+                    let _ = drive_send(
+                        connection,
+                        server_hello,
+                        capabilities,
+                        traffic,
+                        unordered_recv,
+                        ordered_recv,
+                    )
+                    .await;
+                });
+            }
+            None => {
+                client.local = Some(LocalStreams {
+                    hello: Some(server_hello),
+                    ordered: ordered_recv,
+                    unordered: unordered_recv,
+                });
+            }
         }
+        Ok(())
     }
 
+    /// Tell every client about a change to the shared waypoints
+    fn broadcast_waypoints(&mut self, update: proto::WaypointsUpdate) {
+        let mut overran = Vec::new();
+        for (client_id, client) in &mut self.clients {
+            if !client.capabilities.contains(Capabilities::SHARED_WAYPOINTS) {
+                continue;
+            }
+            if let Some(ref mut handles) = client.handles {
+                let result = handles.ordered.try_send(Ordered::Waypoints(update.clone()));
+                if !handles.counters.ordered(result) {
+                    overran.push(client_id);
+                }
+            }
+        }
+        self.drop_slow_clients(overran);
+    }
+
+    /// The connected client whose character is called `name`
+    fn client_named(&self, name: &str) -> Option<ClientId> {
+        self.clients
+            .iter()
+            .find(|(_, x)| x.handles.is_some() && x.name.as_deref() == Some(name))
+            .map(|(id, _)| id)
+    }
+
+    /// The character called `name`
+    fn character_entity(&self, name: &str) -> Option<Entity> {
+        let handles = self.clients[self.client_named(name)?].handles.as_ref()?;
+        Some(handles.character)
+    }
+
+    /// The connected client whose character is `character`
+    fn client_of(&self, character: Entity) -> Option<ClientId> {
+        self.clients
+            .iter()
+            .find(|(_, x)| x.handles.as_ref().is_some_and(|x| x.character == character))
+            .map(|(id, _)| id)
+    }
+
+    /// Tell `client_id` of everything around `followed` in place of its own character's
+    /// surroundings, or of its own character's again if `None`
+    fn set_followed(&mut self, client_id: ClientId, followed: Option<Entity>) {
+        let Some(ref mut handles) = self.clients[client_id].handles else {
+            return;
+        };
+        handles.followed = followed.filter(|&x| x != handles.character);
+        self.tell_followed(client_id);
+    }
+
+    /// Let `client_id` know whose surroundings it's told of
+    fn tell_followed(&self, client_id: ClientId) {
+        let client = &self.clients[client_id];
+        let Some(ref handles) = client.handles else {
+            return;
+        };
+        if !client.capabilities.contains(Capabilities::FOLLOW) {
+            return;
+        }
+        let followed = handles.followed.and_then(|x| self.sim.entity_id(x));
+        let _ = handles.ordered.try_send(Ordered::Following(followed));
+    }
+
+    /// Who commands from `client_id` are run for, once it has a character
+    fn player_actor(&self, client_id: ClientId) -> Option<Actor> {
+        let client = &self.clients[client_id];
+        let name = client.name.clone()?;
+        Some(Actor::Player {
+            character: client.handles.as_ref()?.character,
+            admin: self.admins.contains(&name),
+            name,
+        })
+    }
+
+    /// Teleport the character `subject` to `destination`
+    fn teleport(
+        &mut self,
+        subject: Entity,
+        destination: proto::TeleportDestination,
+    ) -> Result<(), TeleportError> {
+        use proto::TeleportDestination::*;
+        match destination {
+            Character {
+                name,
+                offset_along_up,
+            } => {
+                let target = self
+                    .character_entity(&name)
+                    .ok_or(TeleportError::NoSuchEntity)?;
+                self.sim
+                    .teleport_to_entity(&self.save, subject, target, offset_along_up)
+            }
+            Path { path, local } => self.sim.teleport_to_path(&self.save, subject, &path, local),
+            Relative { translation } => {
+                self.sim
+                    .teleport_relative(&self.save, subject, &translation)
+            }
+        }
+    }
+
+    /// Change the movement modes a client's character may use, and let the client know
+    fn set_movement_modes(&mut self, client_id: ClientId, allowed: proto::MovementModes) {
+        let client = &self.clients[client_id];
+        let Some(ref handles) = client.handles else {
+            return;
+        };
+        if let Err(e) = self.sim.set_movement_modes(handles.character, allowed) {
+            error!(client = ?client_id.0, error = %e, "couldn't change movement modes");
+            return;
+        }
+        // Inputs still queued will be restricted when they're applied, so the client must restrict
+        // its predictions of them too
+        let _ = handles
+            .ordered
+            .try_send(Ordered::MovementModes(proto::MovementModesUpdate {
+                latest_input: client.latest_input_processed,
+                allowed,
+            }));
+    }
+
+    /// Forget `client`, removing its character as `IdlePolicy::departure` directs
     fn cleanup_client(&mut self, client: ClientId) {
         if let Some(ref x) = self.clients[client].handles {
+            let persist = self.idle.departure == DeparturePolicy::PersistAndDespawn;
+            self.sim.record_departure(x.character, persist);
             self.sim.destroy(x.character);
         }
+        let traffic = self.clients[client].traffic.lock().unwrap().clone();
+        let (sent, received) = (traffic.sent.total(), traffic.received.total());
+        info!(
+            client = ?client.0,
+            sent_messages = sent.messages,
+            sent_bytes = sent.bytes,
+            received_messages = received.messages,
+            received_bytes = received.bytes,
+            "connection closed"
+        );
+        for (kind, count) in traffic.sent.iter() {
+            debug!(
+                client = ?client.0,
+                kind,
+                messages = count.messages,
+                bytes = count.bytes,
+                uncompressed_bytes = count.uncompressed_bytes,
+                "traffic sent"
+            );
+        }
         self.clients.remove(client);
     }
 
@@ -214,10 +1038,12 @@ impl Server {
         connection: quinn::Connection,
         mut send: mpsc::Sender<(ClientId, ClientEvent)>,
     ) {
-        let id = self.clients.insert(Client::new(connection.clone()));
-        info!(id = ?id.0, address = %connection.remote_address(), "connection established");
+        let client = Client::new(Some(connection.clone()), Instant::now());
+        let traffic = client.traffic.clone();
+        let id = self.clients.insert(client);
+        info!(client = ?id.0, address = %connection.remote_address(), "connection established");
         tokio::spawn(async move {
-            if let Err(e) = drive_recv(id, connection, &mut send).await {
+            if let Err(e) = drive_recv(id, connection, traffic, &mut send).await {
                 // drive_recv returns an error when any connection-terminating issue occurs, so we
                 // send a `Lost` message to ensure the client is cleaned up. Note that this message may
                 // be redundant, as dropping a slow client also sends a `Lost` message.
@@ -234,10 +1060,18 @@ const MAX_CLIENT_MSG_SIZE: usize = 1 << 16;
 async fn drive_recv(
     id: ClientId,
     connection: quinn::Connection,
+    traffic: Arc<Mutex<ConnectionTraffic>>,
     send: &mut mpsc::Sender<(ClientId, ClientEvent)>,
 ) -> Result<()> {
-    let stream = connection.accept_uni().await.map_err(Error::msg)?;
-    let hello = codec::recv_whole::<proto::ClientHello>(MAX_CLIENT_MSG_SIZE, stream).await?;
+    let mut stream = connection.accept_uni().await.map_err(Error::msg)?;
+    // Decoded specially, since clients of every version must be understood well enough to refuse
+    let hello = stream.read_to_end(MAX_CLIENT_MSG_SIZE).await?;
+    traffic
+        .lock()
+        .unwrap()
+        .received
+        .record("ClientHello", hello.len(), hello.len());
+    let hello = proto::ClientHello::decode(&hello)?;
     let _ = send.send((id, ClientEvent::Hello(hello))).await;
 
     loop {
@@ -247,17 +1081,29 @@ async fn drive_recv(
         // We spawn a separate task to allow messages to be processed in a different order from when they were
         // initiated.
         let connection = connection.clone();
+        let traffic = traffic.clone();
         tokio::spawn(async move {
-            match codec::recv_whole::<proto::Command>(MAX_CLIENT_MSG_SIZE, stream).await {
+            let msg = codec::recv_whole_as::<proto::ClientMessage>(
+                MAX_CLIENT_MSG_SIZE,
+                stream,
+                BodyFormat::Plain,
+            )
+            .await;
+            match msg {
                 Err(e) => {
                     // This error can occur if the client sends a badly-formatted command. In this case,
                     // we want to drop the client. We close the connection, which will cause `drive_recv` to
                     // return eventually.
-                    tracing::error!("Error when parsing unordered stream from client: {e}");
+                    tracing::error!(error = %e, "Error when parsing unordered stream from client");
                     connection.close(2u32.into(), b"could not process stream");
                 }
-                Ok(msg) => {
-                    let _ = send.send((id, ClientEvent::Command(msg))).await;
+                Ok((msg, sizes)) => {
+                    traffic.lock().unwrap().received.record(
+                        msg.kind(),
+                        sizes.wire,
+                        sizes.uncompressed,
+                    );
+                    let _ = send.send((id, msg.into())).await;
                 }
             }
         });
@@ -267,33 +1113,111 @@ async fn drive_recv(
 async fn drive_send(
     conn: quinn::Connection,
     hello: proto::ServerHello,
+    capabilities: Capabilities,
+    traffic: Arc<Mutex<ConnectionTraffic>>,
     unordered: mpsc::Receiver<Unordered>,
     ordered: mpsc::Receiver<Ordered>,
 ) -> Result<()> {
     let mut stream = conn.open_uni().await?;
-    codec::send(&mut stream, &hello).await?;
+    let sizes = codec::send_as(&mut stream, BodyFormat::Plain, &hello, false).await?;
+    traffic
+        .lock()
+        .unwrap()
+        .sent
+        .record("ServerHello", sizes.wire, sizes.uncompressed);
+    let format = BodyFormat::negotiated(capabilities);
+
+    // The graph and edits streams, opened before any unordered stream so that the client can tell
+    // them apart by order
+    let mut others = Vec::new();
+    if capabilities.contains(Capabilities::SPLIT_UPDATES) {
+        for _ in 0..2 {
+            let stream = conn.open_uni().await?;
+            let (send, recv) = mpsc::channel(CLIENT_QUEUE_CAPACITY);
+            let traffic = traffic.clone();
+            tokio::spawn(async move {
+                // Errors will be handled by recv task
+                let _ = drive_send_ordered(stream, format, traffic, recv).await;
+            });
+            others.push(send);
+        }
+    }
 
-    tokio::spawn(async move {
-        // Errors will be handled by recv task
-        let _ = drive_send_unordered(conn.clone(), unordered).await;
-    });
+    {
+        let traffic = traffic.clone();
+        tokio::spawn(async move {
+            // Errors will be handled by recv task
+            let _ = drive_send_unordered(conn.clone(), format, traffic, unordered).await;
+        });
+    }
 
     let mut ordered = ReceiverStream::new(ordered);
     while let Some(msg) = ordered.next().await {
-        codec::send(&mut stream, &msg).await?;
+        for msg in msg.unbatch() {
+            let other = match msg.stream() {
+                OrderedStream::Main => None,
+                OrderedStream::Graph => others.first(),
+                OrderedStream::Edits => others.get(1),
+            };
+            match other {
+                Some(other) => other
+                    .send(msg)
+                    .await
+                    .map_err(|_| Error::msg("ordered stream closed"))?,
+                None => send_ordered(&mut stream, format, &traffic, &msg).await?,
+            }
+        }
     }
 
     Ok(())
 }
 
+/// Send messages on one of several ordered streams
+async fn drive_send_ordered(
+    mut stream: quinn::SendStream,
+    format: BodyFormat,
+    traffic: Arc<Mutex<ConnectionTraffic>>,
+    msgs: mpsc::Receiver<Ordered>,
+) -> Result<()> {
+    let mut msgs = ReceiverStream::new(msgs);
+    while let Some(msg) = msgs.next().await {
+        send_ordered(&mut stream, format, &traffic, &msg).await?;
+    }
+    Ok(())
+}
+
+/// Send `msg` on an ordered stream, counting it in `traffic`
+async fn send_ordered(
+    stream: &mut quinn::SendStream,
+    format: BodyFormat,
+    traffic: &Mutex<ConnectionTraffic>,
+    msg: &Ordered,
+) -> Result<()> {
+    let sizes = codec::send_as(stream, format, msg, msg.compressible()).await?;
+    traffic
+        .lock()
+        .unwrap()
+        .sent
+        .record(msg.kind(), sizes.wire, sizes.uncompressed);
+    Ok(())
+}
+
 async fn drive_send_unordered(
     conn: quinn::Connection,
+    format: BodyFormat,
+    traffic: Arc<Mutex<ConnectionTraffic>>,
     msgs: mpsc::Receiver<Unordered>,
 ) -> Result<()> {
     let mut msgs = ReceiverStream::new(msgs);
     while let Some(msg) = msgs.next().await {
         let stream = conn.open_uni().await?;
-        codec::send_whole(stream, &msg).await?;
+        // Realtime and superseded every step, so not worth delaying to compress
+        let sizes = codec::send_whole_as(stream, format, &msg, false).await?;
+        traffic
+            .lock()
+            .unwrap()
+            .sent
+            .record("StateDelta", sizes.wire, sizes.uncompressed);
     }
     Ok(())
 }
@@ -303,38 +1227,436 @@ slotmap::new_key_type! {
 }
 
 struct Client {
-    conn: quinn::Connection,
+    /// `None` for clients connected in-process through a `LocalServer`
+    conn: Option<quinn::Connection>,
+    /// Name of the client's character, filled in after receiving ClientHello
+    name: Option<String>,
     /// Filled in after receiving ClientHello
     handles: Option<ClientHandles>,
+    /// Optional protocol features both the client and the server support, negotiated on receipt
+    /// of ClientHello
+    capabilities: Capabilities,
     latest_input_received: u16,
     latest_input_processed: u16,
     inputs: InputQueue,
+    /// Messages awaiting collection by an in-process client, filled in after receiving ClientHello
+    local: Option<LocalStreams>,
+    /// Messages sent and received, counted by the tasks driving the connection
+    traffic: Arc<Mutex<ConnectionTraffic>>,
+    /// When the client was last heard from
+    idle: IdleTimer,
 }
 
 impl Client {
-    fn new(conn: quinn::Connection) -> Self {
+    /// A client that connected at `now`
+    fn new(conn: Option<quinn::Connection>, now: Instant) -> Self {
         Self {
             conn,
+            name: None,
             handles: None,
+            capabilities: Capabilities::NONE,
             latest_input_received: 0,
             latest_input_processed: 0,
             inputs: InputQueue::new(),
+            local: None,
+            traffic: Default::default(),
+            idle: IdleTimer::new(now),
         }
     }
 }
 
+/// The receiving ends of an in-process client's streams
+struct LocalStreams {
+    /// Taken when first collected
+    hello: Option<proto::ServerHello>,
+    ordered: mpsc::Receiver<Ordered>,
+    unordered: mpsc::Receiver<Unordered>,
+}
+
 struct ClientHandles {
     character: Entity,
-    ordered: mpsc::Sender<Ordered>,
+    ordered: OrderedQueue,
     unordered: mpsc::Sender<Unordered>,
+    counters: OutgoingCounters,
+    /// When each entity's state is next sent
+    schedule: UpdateSchedule,
+    /// Roots of the graph regions sent, for clients sent the graph by region
+    regions_sent: FxHashSet<NodeId>,
+    /// Node the character was in when the regions around it were last sent
+    regions_node: Option<NodeId>,
+    /// When the latest node hint the client sent was heeded
+    node_hinted: Option<Instant>,
+    /// Character the client follows, whose surroundings it's told of in place of its own
+    /// character's
+    followed: Option<Entity>,
+}
+
+impl ClientHandles {
+    /// The character whose surroundings the client is told of
+    fn viewpoint(&self) -> Entity {
+        self.followed.unwrap_or(self.character)
+    }
+
+    /// Regions of the graph around the viewpoint that haven't been sent yet, which are then taken
+    /// to have been
+    fn unsent_regions(&mut self, sim: &mut Sim) -> Vec<Arc<proto::EncodedGraphRegion>> {
+        let node = sim.position(self.viewpoint()).map(|x| x.node);
+        if node == self.regions_node {
+            // Nothing new can have come into view
+            return Vec::new();
+        }
+        self.regions_node = node;
+        sim.regions_of_interest(self.viewpoint())
+            .into_iter()
+            .filter(|&root| self.regions_sent.insert(root))
+            .map(|root| sim.graph_region(root))
+            .collect()
+    }
 }
 
 enum ClientEvent {
     Hello(proto::ClientHello),
     Command(proto::Command),
+    Admin(AdminCommand),
+    DumpCollisionTrace(String),
+    ResyncNodes(Vec<NodeId>),
+    NodeInterestHint(proto::NodeInterestHint),
+    Follow(Option<String>),
+    SetSignText {
+        chunk: ChunkId,
+        coords: Coords,
+        lines: [String; SIGN_LINES],
+    },
+    Keepalive,
     Lost(Error),
 }
 
+impl From<proto::ClientMessage> for ClientEvent {
+    fn from(msg: proto::ClientMessage) -> Self {
+        match msg {
+            proto::ClientMessage::Command(cmd) => ClientEvent::Command(cmd),
+            proto::ClientMessage::SetWorldTime(x) => {
+                ClientEvent::Admin(AdminCommand::SetWorldTime(x))
+            }
+            proto::ClientMessage::Save => ClientEvent::Admin(AdminCommand::Save),
+            proto::ClientMessage::SetMovementModes { character, allowed } => {
+                ClientEvent::Admin(AdminCommand::SetMovementModes { character, allowed })
+            }
+            proto::ClientMessage::Teleport {
+                character,
+                destination,
+            } => ClientEvent::Admin(AdminCommand::Teleport {
+                character,
+                destination,
+            }),
+            proto::ClientMessage::SetWaypoint(x) => {
+                ClientEvent::Admin(AdminCommand::SetWaypoint(x))
+            }
+            proto::ClientMessage::RemoveWaypoint(x) => {
+                ClientEvent::Admin(AdminCommand::RemoveWaypoint(x))
+            }
+            proto::ClientMessage::SetProtectedRegion(x) => {
+                ClientEvent::Admin(AdminCommand::SetProtectedRegion(x))
+            }
+            proto::ClientMessage::RemoveProtectedRegion(x) => {
+                ClientEvent::Admin(AdminCommand::RemoveProtectedRegion(x))
+            }
+            proto::ClientMessage::TraceCollisions { character, steps } => {
+                ClientEvent::Admin(AdminCommand::TraceCollisions { character, steps })
+            }
+            proto::ClientMessage::DumpCollisionTrace(x) => ClientEvent::DumpCollisionTrace(x),
+            proto::ClientMessage::ResyncNodes(x) => ClientEvent::ResyncNodes(x),
+            proto::ClientMessage::Rollback(x) => ClientEvent::Admin(AdminCommand::Rollback(x)),
+            proto::ClientMessage::NodeInterestHint(x) => ClientEvent::NodeInterestHint(x),
+            proto::ClientMessage::Follow(x) => ClientEvent::Follow(x),
+            proto::ClientMessage::SetSignText {
+                chunk,
+                coords,
+                lines,
+            } => ClientEvent::SetSignText {
+                chunk,
+                coords,
+                lines,
+            },
+            proto::ClientMessage::Keepalive => ClientEvent::Keepalive,
+        }
+    }
+}
+
 type Unordered = proto::StateDelta;
 
-type Ordered = Arc<proto::Spawns>;
+/// Messages on a client's ordered streams, encoded identically to the `proto::ServerMessage` the
+/// client decodes them as. Spawns are shared between clients to avoid copying them.
+#[derive(Serialize)]
+enum Ordered {
+    Spawns(Arc<proto::Spawns>),
+    Inventory(proto::InventoryUpdate),
+    BlockUpdateRejected(proto::BlockUpdateRejection),
+    MovementModes(proto::MovementModesUpdate),
+    Waypoints(proto::WaypointsUpdate),
+    CollisionTrace(proto::CollisionTraceReport),
+    Sounds(Vec<proto::SoundEvent>),
+    GraphRegions(Vec<Arc<proto::EncodedGraphRegion>>),
+    EntityUpdates(Arc<proto::EntityUpdates>),
+    GraphUpdates(Arc<proto::GraphUpdates>),
+    WorldEdits(Arc<proto::WorldEdits>),
+    Following(Option<EntityId>),
+    BlockEntities(Arc<Vec<BlockEntityChange>>),
+    /// Messages queued together, so that however finely a step's updates are split they take one
+    /// place in the queue. Sent as the messages it holds, never itself.
+    #[serde(skip)]
+    Batch(Vec<Ordered>),
+}
+
+impl Ordered {
+    /// The messages to send in place of this one, in order
+    fn unbatch(self) -> Vec<Ordered> {
+        match self {
+            Ordered::Batch(msgs) => msgs,
+            msg => vec![msg],
+        }
+    }
+
+    /// Name of the `proto::ServerMessage` variant the message is decoded as
+    fn kind(&self) -> &'static str {
+        match *self {
+            Ordered::Spawns(_) => "Spawns",
+            Ordered::Inventory(_) => "Inventory",
+            Ordered::BlockUpdateRejected(_) => "BlockUpdateRejected",
+            Ordered::MovementModes(_) => "MovementModes",
+            Ordered::Waypoints(_) => "Waypoints",
+            Ordered::CollisionTrace(_) => "CollisionTrace",
+            Ordered::Sounds(_) => "Sounds",
+            Ordered::GraphRegions(_) => "GraphRegions",
+            Ordered::EntityUpdates(_) => "EntityUpdates",
+            Ordered::GraphUpdates(_) => "GraphUpdates",
+            Ordered::WorldEdits(_) => "WorldEdits",
+            Ordered::Following(_) => "Following",
+            Ordered::BlockEntities(_) => "BlockEntities",
+            Ordered::Batch(_) => "Batch",
+        }
+    }
+
+    /// Whether the message is bulk traffic, which may be held back to pace a client's bandwidth,
+    /// rather than realtime
+    ///
+    /// Bulk messages all travel on the graph stream, so they can be held back without delaying
+    /// anything else.
+    fn is_bulk(&self) -> bool {
+        matches!(*self, Ordered::GraphUpdates(_) | Ordered::GraphRegions(_))
+    }
+
+    /// Whether the message is worth compressing when large, being mostly voxels, nodes, or text
+    fn compressible(&self) -> bool {
+        matches!(
+            *self,
+            Ordered::Spawns(_)
+                | Ordered::CollisionTrace(_)
+                | Ordered::GraphRegions(_)
+                | Ordered::EntityUpdates(_)
+                | Ordered::GraphUpdates(_)
+                | Ordered::WorldEdits(_)
+                | Ordered::BlockEntities(_)
+        )
+    }
+
+    /// The stream the message travels on to clients with `Capabilities::SPLIT_UPDATES`
+    fn stream(&self) -> OrderedStream {
+        match *self {
+            Ordered::GraphUpdates(_) | Ordered::GraphRegions(_) => OrderedStream::Graph,
+            Ordered::WorldEdits(_)
+            | Ordered::BlockUpdateRejected(_)
+            | Ordered::BlockEntities(_) => OrderedStream::Edits,
+            _ => OrderedStream::Main,
+        }
+    }
+}
+
+/// The sending end of a client's ordered streams, which may hold back bulk messages to pace them
+struct OrderedQueue {
+    send: mpsc::Sender<Ordered>,
+    /// Bulk messages waiting to be sent, for clients whose bulk traffic is paced. Only clients with
+    /// `Capabilities::SPLIT_UPDATES` are paced, since only they take bulk messages on a stream of
+    /// their own, which can wait without holding up anything else.
+    bulk: Option<BulkPacer<Ordered>>,
+}
+
+impl OrderedQueue {
+    fn new(
+        send: mpsc::Sender<Ordered>,
+        capabilities: Capabilities,
+        bulk_bytes_per_second: Option<u64>,
+    ) -> Self {
+        Self {
+            send,
+            bulk: bulk_bytes_per_second
+                .filter(|_| capabilities.contains(Capabilities::SPLIT_UPDATES))
+                .map(BulkPacer::new),
+        }
+    }
+
+    /// Queue `msg`, which mustn't be bulk, to be sent at once
+    fn try_send(&self, msg: Ordered) -> Result<(), TrySendError<Ordered>> {
+        debug_assert!(!msg.is_bulk());
+        self.send.try_send(msg)
+    }
+
+    /// Queue `msg`, holding back any bulk messages it carries for `release_bulk` if paced
+    fn queue(&mut self, msg: Ordered) -> Result<(), TrySendError<Ordered>> {
+        let Some(ref mut bulk) = self.bulk else {
+            return self.send.try_send(msg);
+        };
+        let mut rest = Vec::new();
+        for msg in msg.unbatch() {
+            match msg {
+                // One at a time, so that the budget can be spent on them in smaller pieces
+                Ordered::GraphRegions(regions) => {
+                    for region in regions {
+                        let msg = Ordered::GraphRegions(vec![region]);
+                        let bytes = codec::encoded_len(&msg);
+                        bulk.push(msg, bytes);
+                    }
+                }
+                msg if msg.is_bulk() => {
+                    let bytes = codec::encoded_len(&msg);
+                    bulk.push(msg, bytes);
+                }
+                msg => rest.push(msg),
+            }
+        }
+        match rest.len() {
+            0 => Ok(()),
+            1 => self.send.try_send(rest.pop().unwrap()),
+            _ => self.send.try_send(Ordered::Batch(rest)),
+        }
+    }
+
+    /// Send the bulk messages held back that the budget now allows, a step of length `dt` having
+    /// passed since last time
+    ///
+    /// Messages that don't fit in the queue wait for later, rather than counting against the client
+    /// as falling behind.
+    fn release_bulk(&mut self, dt: Duration) {
+        let Some(ref mut bulk) = self.bulk else {
+            return;
+        };
+        let send = &self.send;
+        bulk.release(dt, |msg| {
+            send.try_send(msg).map_err(TrySendError::into_inner)
+        });
+    }
+
+    /// Bytes of bulk messages held back
+    fn bulk_waiting_bytes(&self) -> usize {
+        self.bulk.as_ref().map_or(0, BulkPacer::waiting_bytes)
+    }
+
+    /// Number of messages that can be queued before the client is too far behind
+    fn capacity(&self) -> usize {
+        self.send.capacity()
+    }
+}
+
+/// One of the ordered streams to a client with `Capabilities::SPLIT_UPDATES`, each delivering its
+/// messages in order but independently of the others
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum OrderedStream {
+    /// Carries the server's hello, and everything not carried by the others
+    Main,
+    Graph,
+    /// World edits, the rejections of block updates that would have been among them, and the
+    /// block entities they create and remove
+    Edits,
+}
+
+/// A `proto::Spawns` ready to queue for any client, whole or split as the client understands
+struct SpawnMessages {
+    /// `None` if no client is to be sent it whole
+    whole: Option<Arc<proto::Spawns>>,
+    entities: Vec<Arc<proto::EntityUpdates>>,
+    graph: Vec<Arc<proto::GraphUpdates>>,
+    edits: Vec<Arc<proto::WorldEdits>>,
+}
+
+impl SpawnMessages {
+    /// Prepare `spawns`, keeping a whole copy if `whole`
+    fn new(spawns: proto::Spawns, whole: bool) -> Self {
+        let whole = whole.then(|| Arc::new(spawns.clone()));
+        let (entities, graph, edits) = spawns.split(proto::MAX_UPDATE_BYTES);
+        Self {
+            whole,
+            entities: entities.into_iter().map(Arc::new).collect(),
+            graph: graph.into_iter().map(Arc::new).collect(),
+            edits: edits.into_iter().map(Arc::new).collect(),
+        }
+    }
+
+    /// What to queue for a client with `capabilities`, if anything
+    fn message(&self, capabilities: Capabilities) -> Option<Ordered> {
+        if !capabilities.contains(Capabilities::SPLIT_UPDATES) {
+            return self.whole.clone().map(Ordered::Spawns);
+        }
+        // Graph updates first, so a client that receives everything at once defers nothing
+        let batch = self
+            .graph
+            .iter()
+            .cloned()
+            .map(Ordered::GraphUpdates)
+            .chain(self.entities.iter().cloned().map(Ordered::EntityUpdates))
+            .chain(self.edits.iter().cloned().map(Ordered::WorldEdits))
+            .collect::<Vec<_>>();
+        (!batch.is_empty()).then_some(Ordered::Batch(batch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    /// Crates that need graphics or windowing system libraries to build
+    const GRAPHICS_CRATES: &[&str] = &[
+        "ash",
+        "ash-window",
+        "lahar",
+        "raw-window-handle",
+        "renderdoc",
+        "vk-shader-macros",
+        "winit",
+    ];
+
+    /// The server must build on headless machines, so neither it nor any workspace crate it
+    /// depends on may pull in graphics crates
+    #[test]
+    fn headless() {
+        let mut pending = vec![PathBuf::from(env!("CARGO_MANIFEST_DIR"))];
+        let mut visited = Vec::new();
+        while let Some(dir) = pending.pop() {
+            let dir = dir.canonicalize().unwrap();
+            if visited.contains(&dir) {
+                continue;
+            }
+            let manifest = fs::read_to_string(dir.join("Cargo.toml"))
+                .unwrap()
+                .parse::<toml::Table>()
+                .unwrap();
+            let dependencies = manifest
+                .get("dependencies")
+                .and_then(|x| x.as_table())
+                .into_iter()
+                .flatten();
+            for (name, spec) in dependencies {
+                assert!(
+                    !GRAPHICS_CRATES.contains(&name.as_str()),
+                    "{} depends on {name}",
+                    dir.display()
+                );
+                if let Some(path) = spec.get("path").and_then(|x| x.as_str()) {
+                    pending.push(dir.join(path));
+                }
+            }
+            visited.push(dir);
+        }
+        // The server itself, common, and save
+        assert_eq!(visited.len(), 3);
+    }
+}
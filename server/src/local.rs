@@ -0,0 +1,240 @@
+//! Running a server in-process, with clients connected directly rather than over the network
+//!
+//! Time only passes when the server is stepped, so a `LocalServer` behaves identically from run to
+//! run. Messages are still encoded and decoded as they would be on the wire, so whatever a remote
+//! client would see, a local one does too.
+
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+
+use common::{
+    character_controller::Tether,
+    codec::{self, BodyFormat},
+    proto::{self, negotiation::Refusal},
+    EntityId, SimConfig,
+};
+
+use crate::{
+    admin::Actor, AdminCommand, AdminError, Client, ClientEvent, ClientId, IdlePolicy, SaveParams,
+    Server, ServerStats, TaskId, TaskKind,
+};
+
+/// A server whose clients are driven by the caller
+pub struct LocalServer {
+    server: Server,
+    /// Time at which the latest step was due
+    now: Instant,
+}
+
+/// Identifies a client connected to a `LocalServer`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LocalClientId(ClientId);
+
+/// A message received from a `LocalServer`
+#[derive(Debug)]
+pub enum LocalMessage {
+    Hello(proto::ServerHello),
+    /// A message from the ordered stream
+    Ordered(proto::ServerMessage),
+    /// A message from the unordered stream
+    Unordered(proto::StateDelta),
+}
+
+impl LocalServer {
+    /// Start a server, permitting clients with characters named in `admins` to send
+    /// administrative commands
+    pub fn new(mut sim: SimConfig, save: SaveParams, admins: Vec<String>) -> Self {
+        sim.chunk_size = save.save.meta().chunk_size as u8;
+        Self {
+            server: Server::new(sim, save, admins),
+            now: Instant::now(),
+        }
+    }
+
+    pub fn cfg(&self) -> &SimConfig {
+        &self.server.cfg
+    }
+
+    /// Connect a new client of this version whose character is called `name`
+    pub fn connect(&mut self, name: &str) -> LocalClientId {
+        self.connect_with(proto::ClientHello::new(name))
+            .expect("clients of the same version are always accepted")
+    }
+
+    /// Connect a new client that introduces itself with `hello`, as a client of any version might
+    pub fn connect_with(&mut self, hello: proto::ClientHello) -> Result<LocalClientId, Refusal> {
+        let id = self.server.clients.insert(Client::new(None, self.now));
+        if let Err(refusal) = self.server.greet(id, hello) {
+            self.server.clients.remove(id);
+            return Err(refusal);
+        }
+        Ok(LocalClientId(id))
+    }
+
+    /// Pace the graph updates sent to clients connecting from now on to `rate` bytes per second,
+    /// or not at all if `None`
+    pub fn set_bulk_bytes_per_second(&mut self, rate: Option<u64>) {
+        self.server.bulk_bytes_per_second = rate;
+    }
+
+    /// Treat clients that stop sending commands, and the characters of those that leave, as
+    /// `policy` directs
+    pub fn set_idle_policy(&mut self, policy: IdlePolicy) {
+        self.server.idle = policy;
+    }
+
+    /// Deliver `msg` from `client`
+    pub fn send(&mut self, client: LocalClientId, msg: &proto::ClientMessage) -> Result<()> {
+        let (msg, sizes) =
+            codec::reencode_as::<_, proto::ClientMessage>(BodyFormat::Plain, msg, false)?;
+        if let Some(x) = self.server.clients.get(client.0) {
+            x.traffic
+                .lock()
+                .unwrap()
+                .received
+                .record(msg.kind(), sizes.wire, sizes.uncompressed);
+        }
+        self.server.on_client_event(client.0, msg.into(), self.now);
+        Ok(())
+    }
+
+    /// Collect everything sent to `client` since the last call, ordered messages first
+    ///
+    /// Fails if the client has been disconnected, whether by `disconnect` or by the server.
+    pub fn recv(&mut self, client: LocalClientId) -> Result<Vec<LocalMessage>> {
+        let client = self
+            .server
+            .clients
+            .get_mut(client.0)
+            .ok_or_else(|| anyhow!("client disconnected"))?;
+        let format = BodyFormat::negotiated(client.capabilities);
+        let streams = client
+            .local
+            .as_mut()
+            .ok_or_else(|| anyhow!("client disconnected"))?;
+        let mut traffic = client.traffic.lock().unwrap();
+        let mut messages = Vec::new();
+        if let Some(hello) = streams.hello.take() {
+            let (hello, sizes) = codec::reencode_as(BodyFormat::Plain, &hello, false)?;
+            traffic
+                .sent
+                .record("ServerHello", sizes.wire, sizes.uncompressed);
+            messages.push(LocalMessage::Hello(hello));
+        }
+        while let Ok(msg) = streams.ordered.try_recv() {
+            for msg in msg.unbatch() {
+                let (decoded, sizes) = codec::reencode_as(format, &msg, msg.compressible())?;
+                traffic
+                    .sent
+                    .record(msg.kind(), sizes.wire, sizes.uncompressed);
+                messages.push(LocalMessage::Ordered(decoded));
+            }
+        }
+        while let Ok(msg) = streams.unordered.try_recv() {
+            let (msg, sizes) = codec::reencode_as(format, &msg, false)?;
+            traffic
+                .sent
+                .record("StateDelta", sizes.wire, sizes.uncompressed);
+            messages.push(LocalMessage::Unordered(msg));
+        }
+        Ok(messages)
+    }
+
+    /// Drop `client`, as if its connection had been lost
+    pub fn disconnect(&mut self, client: LocalClientId) {
+        self.server.on_client_event(
+            client.0,
+            ClientEvent::Lost(anyhow!("disconnected")),
+            self.now,
+        );
+    }
+
+    /// Where `client`'s character is, according to the server
+    pub fn position(&self, client: LocalClientId) -> Option<proto::Position> {
+        let handles = self.server.clients.get(client.0)?.handles.as_ref()?;
+        self.server.sim.position(handles.character)
+    }
+
+    /// Add an entity other than a character at `position`, such as something to be picked up
+    pub fn spawn(
+        &mut self,
+        position: proto::Position,
+        components: Vec<proto::Component>,
+    ) -> EntityId {
+        self.server.sim.spawn(position, components)
+    }
+
+    /// Knock characters within `radius` of `center` away from it, as an explosion would
+    pub fn blast(&mut self, center: &proto::Position, radius: f32, speed: f32) {
+        self.server.sim.blast(center, radius, speed);
+    }
+
+    /// Do `kind` `delay` steps from now, and every `repeat` steps after that if set, until
+    /// cancelled
+    pub fn schedule(&mut self, delay: u32, repeat: Option<u32>, kind: TaskKind) -> TaskId {
+        self.server.sim.schedule(delay, repeat, kind)
+    }
+
+    /// Stop the task `id` from being done, returning whether it was pending
+    pub fn cancel_task(&mut self, id: TaskId) -> bool {
+        self.server.sim.cancel_task(id)
+    }
+
+    /// Pull `client`'s character toward `tether`'s anchor, or stop pulling it if `None`
+    pub fn set_tether(&mut self, client: LocalClientId, tether: Option<Tether>) {
+        let Some(handles) = self
+            .server
+            .clients
+            .get(client.0)
+            .and_then(|x| x.handles.as_ref())
+        else {
+            return;
+        };
+        self.server
+            .sim
+            .set_tether(handles.character, tether)
+            .expect("characters can be tethered");
+    }
+
+    /// The server's statistics as of now, as it would publish them
+    pub fn stats(&mut self) -> ServerStats {
+        self.server.collect_stats(self.now)
+    }
+
+    /// Advance time by one step interval and simulate the step
+    pub fn step(&mut self) {
+        self.now += self.server.cfg.step_interval;
+        self.server.on_step(self.now);
+    }
+
+    /// Run `command` as if typed at the server's console
+    pub fn admin(&mut self, command: AdminCommand) -> Result<String, AdminError> {
+        self.server.run_admin(&Actor::Console, command)
+    }
+
+    /// Run `command` on behalf of `client`'s player, with whatever permissions they have
+    ///
+    /// Unlike `send`, reports what became of the command.
+    pub fn admin_as(
+        &mut self,
+        client: LocalClientId,
+        command: AdminCommand,
+    ) -> Result<String, AdminError> {
+        let actor = self
+            .server
+            .player_actor(client.0)
+            .expect("client has a character");
+        self.server.run_admin(&actor, command)
+    }
+
+    /// Whether an administrator has asked the server to stop, after which it should be shut down
+    pub fn stopping(&self) -> bool {
+        self.server.stopping
+    }
+
+    /// Save everything and disconnect every client, as a server does on stopping
+    pub fn shutdown(self) {
+        self.server.shutdown();
+    }
+}
@@ -2,30 +2,142 @@
 
 mod config;
 
-use std::{fs, net::UdpSocket, path::Path};
+use std::{
+    fs,
+    net::UdpSocket,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
 
-use anyhow::{anyhow, Context, Result};
-use tracing::{info, warn};
+use anyhow::{anyhow, bail, Context, Result};
+use tracing::{error, info, warn};
 
-use common::SimConfig;
-use config::Config;
-use save::Save;
+use common::{
+    logging::LogFilter,
+    proto::AssetPackOffer,
+    worldgen::{determinism::Golden, ChunkParams},
+    SimConfig,
+};
+use config::{Config, WorldgenCheck};
+use save::{Journal, Save};
 
 fn main() {
     // Set up logging
-    common::init_tracing();
+    let log_filter = common::init_tracing();
 
-    if let Err(e) = run() {
-        eprintln!("{e:#}");
+    if let Err(e) = run(log_filter) {
+        error!(error = format_args!("{e:#}"), "server failed");
         std::process::exit(1);
     }
 }
 
-pub fn run() -> Result<()> {
-    let cfg = match std::env::args_os().nth(1) {
+/// Number of nodes generated at once by `pregenerate`
+const PREGENERATION_WINDOW: usize = 64;
+
+/// Time between checks of the configured `log_filter_file` for changes
+const LOG_FILTER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Compare the chunks this build generates against those of the canonical build, if they were
+/// recorded for the world `cfg` describes
+fn check_worldgen(cfg: &SimConfig, check: WorldgenCheck) -> Result<()> {
+    if check == WorldgenCheck::Off {
+        return Ok(());
+    }
+    let golden = Golden::committed();
+    if !golden.applies_to(cfg.chunk_size, &cfg.terrain) {
+        info!("no golden chunks recorded for this world; skipping world generation check");
+        return Ok(());
+    }
+    let report = golden.verify(ChunkParams::generate_voxels);
+    if report.is_deterministic() {
+        info!(
+            chunks = report.checked,
+            "world generation matches the canonical build"
+        );
+        return Ok(());
+    }
+    for divergence in &report.divergences {
+        warn!("{}", divergence);
+    }
+    let summary = format!(
+        "{} of {} golden chunks generated differently than by the canonical build",
+        report.divergences.len(),
+        report.checked
+    );
+    if check == WorldgenCheck::Refuse {
+        bail!("{summary}");
+    }
+    warn!(
+        "{}; clients may see different terrain than the server simulates",
+        summary
+    );
+    Ok(())
+}
+
+/// The time configured as `seconds` for the setting `name`, or `None` if that's 0
+fn idle_threshold(name: &str, seconds: f32) -> Result<Option<Duration>> {
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f32(seconds)
+        .map(Some)
+        .with_context(|| format!("{name} must be a nonnegative number"))
+}
+
+/// Keep what's logged in line with the directives in the file at `path`, on top of the `base`
+/// directives, checking for changes in the background
+fn watch_log_filter(path: PathBuf, base: Option<String>, filter: LogFilter) {
+    thread::spawn(move || {
+        let mut applied = None;
+        loop {
+            // A missing file has no directives
+            let contents = fs::read_to_string(&path).unwrap_or_default();
+            let directives = base
+                .iter()
+                .map(String::as_str)
+                .chain(contents.lines().map(str::trim))
+                .filter(|x| !x.is_empty() && !x.starts_with('#'))
+                .collect::<Vec<_>>()
+                .join(",");
+            if applied.as_ref() != Some(&directives) {
+                match filter.set(&directives) {
+                    Ok(()) => info!(%directives, "changed what's logged"),
+                    Err(e) => warn!(path = %path.display(), error = %e, "ignoring log filter"),
+                }
+                applied = Some(directives);
+            }
+            thread::sleep(LOG_FILTER_POLL_INTERVAL);
+        }
+    });
+}
+
+pub fn run(log_filter: LogFilter) -> Result<()> {
+    let mut args = std::env::args_os().skip(1).peekable();
+    let pregenerate_radius = if args.peek().and_then(|x| x.to_str()) == Some("pregenerate") {
+        args.next();
+        match (args.next(), args.next()) {
+            (Some(flag), Some(radius)) if flag == "--radius" => Some(
+                radius
+                    .to_str()
+                    .and_then(|x| x.parse::<f64>().ok())
+                    .ok_or_else(|| anyhow!("radius must be a number"))?,
+            ),
+            _ => bail!("usage: server pregenerate --radius <absolute distance> [config]"),
+        }
+    } else {
+        None
+    };
+    let cfg = match args.next() {
         Some(path) => Config::load(Path::new(&path))?,
         None => Config::default(),
     };
+    if let Some(ref directives) = cfg.log_filter {
+        log_filter.set(directives).context("parsing log_filter")?;
+    }
+    if let Some(path) = cfg.log_filter_file.clone() {
+        watch_log_filter(path, cfg.log_filter.clone(), log_filter);
+    }
 
     let (certificate_chain, private_key) = match (&cfg.certificate_chain, &cfg.private_key) {
         (&Some(ref certificate_chain), &Some(ref private_key)) => (
@@ -66,18 +178,84 @@ pub fn run() -> Result<()> {
     };
 
     let sim_cfg = SimConfig::from_raw(&cfg.simulation);
+    check_worldgen(&sim_cfg, cfg.worldgen_check)?;
+    let asset_pack = cfg
+        .asset_pack
+        .map(|x| -> Result<_> {
+            Ok(AssetPackOffer {
+                hash: x.blake3.parse().context("parsing asset pack hash")?,
+                name: x.name,
+                url: x.url,
+            })
+        })
+        .transpose()?;
+    let graph_region_depth = cfg
+        .graph_region_depth
+        .unwrap_or(server::DEFAULT_GRAPH_REGION_DEPTH);
+    if graph_region_depth == 0 {
+        bail!("graph_region_depth must be at least 1");
+    }
+    let edit_history_age = match cfg.edit_history_hours {
+        None => server::DEFAULT_EDIT_HISTORY_AGE,
+        Some(hours) => Duration::try_from_secs_f32(hours * 3600.0)
+            .context("edit_history_hours must be a nonnegative number")?,
+    };
+    let idle = server::IdlePolicy {
+        afk_after: match cfg.afk_soft_seconds {
+            None => Some(server::DEFAULT_AFK_TIME),
+            Some(seconds) => idle_threshold("afk_soft_seconds", seconds)?,
+        },
+        kick_after: idle_threshold("afk_kick_seconds", cfg.afk_kick_seconds.unwrap_or(0.0))?,
+        departure: cfg.departure,
+    };
+
+    let save_path = cfg.save.unwrap_or_else(|| "hypermine.save".into());
+    info!("using save file {}", save_path.display());
+    let mut save = Save::open(&save_path, sim_cfg.chunk_size)?;
+    let (journal, recovery) = Journal::open(&Journal::dir_for(&save_path), &mut save)
+        .context("recovering interrupted writes")?;
+    if recovery.replayed > 0 || recovery.discarded > 0 {
+        warn!(
+            replayed = recovery.replayed,
+            discarded = recovery.discarded,
+            "recovered interrupted writes"
+        );
+    }
 
-    let save = cfg.save.unwrap_or_else(|| "hypermine.save".into());
-    info!("using save file {}", save.display());
-    let save = Save::open(&save, sim_cfg.chunk_size)?;
+    if let Some(radius) = pregenerate_radius {
+        server::pregenerate(&mut save, &sim_cfg.terrain, radius, PREGENERATION_WINDOW)?;
+        return Ok(());
+    }
 
     server::run(
         server::NetParams {
             certificate_chain,
             private_key,
             socket: UdpSocket::bind(cfg.listen).context("binding socket")?,
+            status: cfg.status_listen,
+            admins: cfg.admins,
+            creative: cfg.creative,
+            protected_regions: cfg.protected_regions,
+            asset_pack,
+            graph_region_depth,
+            edit_history_records: cfg
+                .edit_history_records
+                .unwrap_or(server::DEFAULT_EDIT_HISTORY_RECORDS),
+            edit_history_age,
+            console: cfg.console.unwrap_or(true),
+            console_socket: cfg.console_socket,
+            bulk_bytes_per_second: cfg.bulk_bytes_per_second,
+            compression: cfg.compression.unwrap_or(true),
+            idle,
         },
         sim_cfg,
-        save,
+        server::SaveParams {
+            save,
+            journal,
+            autosave_interval: cfg
+                .autosave_interval_seconds
+                .map_or(server::DEFAULT_AUTOSAVE_INTERVAL, Duration::from_secs_f32),
+            save_on_interrupt: true,
+        },
     )
 }
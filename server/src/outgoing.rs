@@ -0,0 +1,91 @@
+use tokio::sync::mpsc::error::TrySendError;
+
+/// Accounting for the messages queued to a single client, deciding when it's fallen too far behind
+///
+/// Each state delta supersedes the last, so a client that's briefly slow to read can miss a few
+/// without harm. Ordered messages can't be skipped without desynchronizing the client, so if those
+/// back up, or deltas are dropped for too long, the client is disconnected instead.
+#[derive(Debug, Clone)]
+pub struct OutgoingCounters {
+    /// Messages queued for sending
+    pub sent: u64,
+    /// State deltas discarded because the client's queue was full
+    pub dropped: u64,
+    /// Deltas discarded since one was last queued
+    consecutive_dropped: u32,
+    /// Number of consecutive deltas that can be discarded before the client is disconnected
+    max_consecutive_dropped: u32,
+}
+
+impl OutgoingCounters {
+    pub fn new(max_consecutive_dropped: u32) -> Self {
+        Self {
+            sent: 0,
+            dropped: 0,
+            consecutive_dropped: 0,
+            max_consecutive_dropped,
+        }
+    }
+
+    /// Record an attempt to queue a state delta, returning whether the client should be kept
+    pub fn delta<T>(&mut self, result: Result<(), TrySendError<T>>) -> bool {
+        match result {
+            Ok(()) => {
+                self.sent += 1;
+                self.consecutive_dropped = 0;
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                self.consecutive_dropped += 1;
+                self.consecutive_dropped <= self.max_consecutive_dropped
+            }
+            // The connection is already being cleaned up
+            Err(TrySendError::Closed(_)) => true,
+        }
+    }
+
+    /// Record an attempt to queue an ordered message, returning whether the client should be kept
+    pub fn ordered<T>(&mut self, result: Result<(), TrySendError<T>>) -> bool {
+        match result {
+            Ok(()) => {
+                self.sent += 1;
+                true
+            }
+            Err(TrySendError::Full(_)) => false,
+            Err(TrySendError::Closed(_)) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deltas_dropped_until_limit() {
+        let mut counters = OutgoingCounters::new(3);
+        assert!(counters.delta::<()>(Ok(())));
+        for _ in 0..3 {
+            assert!(counters.delta(Err(TrySendError::Full(()))));
+        }
+        // Catching up resets the limit
+        assert!(counters.delta::<()>(Ok(())));
+        for _ in 0..3 {
+            assert!(counters.delta(Err(TrySendError::Full(()))));
+        }
+        assert!(!counters.delta(Err(TrySendError::Full(()))));
+        assert_eq!(counters.sent, 2);
+        assert_eq!(counters.dropped, 7);
+    }
+
+    #[test]
+    fn ordered_never_dropped() {
+        let mut counters = OutgoingCounters::new(3);
+        assert!(counters.ordered::<()>(Ok(())));
+        assert!(counters.ordered(Err(TrySendError::Closed(()))));
+        assert!(!counters.ordered(Err(TrySendError::Full(()))));
+        assert_eq!(counters.sent, 1);
+        assert_eq!(counters.dropped, 0);
+    }
+}
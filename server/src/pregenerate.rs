@@ -0,0 +1,278 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use fxhash::FxHashMap;
+use tracing::info;
+
+use common::{
+    dodeca::{Side, Vertex},
+    graph::{Graph, NodeId},
+    math,
+    node::{populate_fresh_nodes, ChunkId},
+    worldgen::{ChunkParams, TerrainPassKind},
+};
+use save::Save;
+
+use crate::sim::encode_voxels;
+
+/// Minimum time between progress reports
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct PregenerationSummary {
+    /// Number of nodes within the radius
+    pub nodes: usize,
+    /// Number of nodes left alone because voxels were already saved for them
+    pub skipped: usize,
+    /// Number of chunks generated and saved
+    pub chunks: usize,
+    /// Largest number of chunks whose voxels were held in memory at once
+    pub peak_resident_chunks: usize,
+    /// Largest number of nodes held in a graph at once
+    pub peak_resident_nodes: usize,
+    pub elapsed: Duration,
+}
+
+/// A node within the region that hasn't been visited yet
+struct Pending {
+    /// Sides crossed to reach the node from the root
+    path: Vec<Side>,
+    /// Transform from the node's coordinates to the root's
+    transform: na::Matrix4<f64>,
+}
+
+/// Generate and save the chunks of every node whose center lies within `radius` of the origin
+/// using `terrain`, leaving alone any node whose voxels are already saved
+///
+/// Nodes are visited `window` at a time, depth first through the tree formed by linking each node
+/// to its shorter neighbor on the lowest side, whose nodes within the region are all reachable from
+/// the root without leaving it. Each window is given a graph of its own holding only the nodes
+/// needed to generate its chunks, and is written to `save` and dropped before the next is
+/// generated, so memory is bounded by `window` and the depth of the region rather than by the
+/// number of nodes in it.
+pub fn pregenerate(
+    save: &mut Save,
+    terrain: &[TerrainPassKind],
+    radius: f64,
+    window: usize,
+) -> Result<PregenerationSummary, save::DbError> {
+    let start = Instant::now();
+    let dimension = save.meta().chunk_size as u8;
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    info!(radius, threads, "pregenerating");
+
+    let mut summary = PregenerationSummary {
+        nodes: 0,
+        skipped: 0,
+        chunks: 0,
+        peak_resident_chunks: 0,
+        peak_resident_nodes: 0,
+        elapsed: Duration::ZERO,
+    };
+    let mut last_report = start;
+    let mut pending = vec![Pending {
+        path: Vec::new(),
+        transform: na::Matrix4::identity(),
+    }];
+    while !pending.is_empty() {
+        let batch = pending.split_off(pending.len().saturating_sub(window.max(1)));
+        let mut graph = Graph::new(dimension);
+        let mut nodes = Vec::with_capacity(batch.len());
+        for entry in batch {
+            let node = entry.path.iter().fold(NodeId::ROOT, |node, &side| {
+                graph.ensure_neighbor(node, side)
+            });
+            for side in Side::iter() {
+                let child = graph.ensure_neighbor(node, side);
+                if graph.length(child) <= graph.length(node)
+                    || graph.descenders(child).next().map(|(x, _)| x) != Some(side)
+                {
+                    continue;
+                }
+                let transform = entry.transform * side.reflection_f64();
+                if math::distance(&math::origin(), &(transform * math::origin())) > radius {
+                    continue;
+                }
+                let mut path = entry.path.clone();
+                path.push(side);
+                pending.push(Pending { path, transform });
+            }
+            nodes.push(node);
+        }
+        summary.nodes += nodes.len();
+
+        let reader_guard = save.read()?;
+        let reader = reader_guard.get()?;
+        let mut fresh = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            if reader.has_voxel_node(graph.hash_of(node))? {
+                summary.skipped += 1;
+            } else {
+                fresh.push(node);
+            }
+        }
+        drop(reader);
+        drop(reader_guard);
+
+        // Chunks depend on the nodes incident to each of their vertices
+        for &node in &fresh {
+            for vertex in Vertex::iter() {
+                for (_, path) in vertex.dual_vertices() {
+                    path.fold(node, |node, side| graph.ensure_neighbor(node, side));
+                }
+            }
+        }
+        populate_fresh_nodes(&mut graph);
+        summary.peak_resident_nodes = summary.peak_resident_nodes.max(graph.len() as usize);
+
+        let mut params = Vec::new();
+        for &node in &fresh {
+            for vertex in Vertex::iter() {
+                let chunk = ChunkId::new(node, vertex);
+                if let Some(x) = ChunkParams::new(dimension, &graph, chunk, terrain) {
+                    params.push((chunk, x));
+                }
+            }
+        }
+
+        // Generation is by far the most expensive part, and each chunk is independent
+        let per_thread = params.len() / threads + 1;
+        let voxels = thread::scope(|s| {
+            let workers = params
+                .chunks(per_thread)
+                .map(|params| {
+                    s.spawn(move || {
+                        params
+                            .iter()
+                            .map(|(_, x)| encode_voxels(&x.generate_voxels(), dimension))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        summary.peak_resident_chunks = summary.peak_resident_chunks.max(voxels.len());
+
+        let mut records = FxHashMap::<NodeId, save::VoxelNode>::default();
+        for (&(chunk, _), voxels) in params.iter().zip(voxels) {
+            records
+                .entry(chunk.node)
+                .or_default()
+                .chunks
+                .push(save::Chunk {
+                    vertex: chunk.vertex as u32,
+                    voxels,
+                    edit_generation: 0,
+                    block_entities: Vec::new(),
+                });
+        }
+        let mut tx = save.write()?;
+        let mut writer = tx.get()?;
+        for (node, record) in &records {
+            writer.put_voxel_node(graph.hash_of(*node), record)?;
+        }
+        drop(writer);
+        tx.commit()?;
+
+        summary.chunks += params.len();
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            info!(
+                nodes_done = summary.nodes,
+                nodes_found = summary.nodes + pending.len(),
+                chunks_per_second = summary.chunks as f64 / start.elapsed().as_secs_f64(),
+                "pregenerating"
+            );
+        }
+    }
+
+    summary.elapsed = start.elapsed();
+    info!(
+        nodes = summary.nodes,
+        skipped = summary.skipped,
+        chunks = summary.chunks,
+        elapsed = ?summary.elapsed,
+        "pregeneration complete"
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::dodeca;
+
+    use super::*;
+
+    #[test]
+    fn streaming_bounds_resident_chunks() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut save = Save::open(file.path(), 2).unwrap();
+        let window = 4;
+        let summary = pregenerate(&mut save, &TerrainPassKind::DEFAULT, 2.5, window).unwrap();
+        assert!(summary.nodes > 2 * window);
+        assert!(summary.peak_resident_chunks > 0);
+        assert!(summary.peak_resident_chunks <= window * dodeca::VERTEX_COUNT);
+
+        let root = Graph::new(2).hash_of(NodeId::ROOT);
+        let reader_guard = save.read().unwrap();
+        let record = reader_guard
+            .get()
+            .unwrap()
+            .get_voxel_node(root)
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.chunks.len(), dodeca::VERTEX_COUNT);
+    }
+
+    #[test]
+    fn streaming_bounds_resident_nodes() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut save = Save::open(file.path(), 2).unwrap();
+        let near = pregenerate(&mut save, &TerrainPassKind::DEFAULT, 2.5, 1).unwrap();
+        // Over a thousand nodes
+        let far = pregenerate(&mut save, &TerrainPassKind::DEFAULT, 4.0, 1).unwrap();
+        assert_eq!(far.skipped, near.nodes);
+        assert!(far.nodes > 10 * near.nodes);
+        assert!(
+            far.peak_resident_nodes * 4 < far.nodes,
+            "{} of {} nodes held at once",
+            far.peak_resident_nodes,
+            far.nodes
+        );
+    }
+
+    #[test]
+    fn saved_nodes_are_left_alone() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut save = Save::open(file.path(), 2).unwrap();
+        let root = Graph::new(2).hash_of(NodeId::ROOT);
+        let edited = save::VoxelNode {
+            chunks: vec![save::Chunk {
+                vertex: 0,
+                voxels: vec![1, 2, 3],
+                edit_generation: 7,
+                block_entities: vec![4, 5],
+            }],
+        };
+        let mut tx = save.write().unwrap();
+        tx.get().unwrap().put_voxel_node(root, &edited).unwrap();
+        tx.commit().unwrap();
+
+        let summary = pregenerate(&mut save, &TerrainPassKind::DEFAULT, 2.5, 4).unwrap();
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(summary.chunks, (summary.nodes - 1) * dodeca::VERTEX_COUNT);
+        let reader_guard = save.read().unwrap();
+        let record = reader_guard
+            .get()
+            .unwrap()
+            .get_voxel_node(root)
+            .unwrap()
+            .unwrap();
+        assert_eq!(record, edited);
+    }
+}
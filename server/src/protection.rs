@@ -0,0 +1,253 @@
+//! Regions where only some players may change blocks
+//!
+//! Every block update is checked against every region, so each region keeps the transforms into
+//! the coordinates of its center node from those of every node near enough to hold voxels within
+//! it. Updates elsewhere are passed over without computing a distance, and the rest cost a single
+//! matrix product. Those nodes are all built when the region is added, so that none built later
+//! can be missed.
+
+use std::{collections::BTreeMap, fmt};
+
+use fxhash::FxHashMap;
+
+use common::{
+    coords::voxel_center_position,
+    dodeca,
+    graph::{Graph, NodeId},
+    math,
+    node::{ChunkId, Coords},
+    protection::ProtectedRegion,
+    proto::Position,
+    traversal::{ensure_nearby, nearby_nodes},
+    waypoint::{self, InvalidName},
+};
+
+#[derive(Default)]
+pub struct ProtectedRegions {
+    regions: BTreeMap<String, Resolved>,
+}
+
+struct Resolved {
+    region: ProtectedRegion,
+    /// `region.radius` in absolute units
+    radius: f32,
+    /// Transforms into the center node's coordinates from those of each node whose origin is near
+    /// enough to the center for the node to hold voxels within `radius` of it
+    nodes: FxHashMap<NodeId, na::Matrix4<f32>>,
+}
+
+impl ProtectedRegions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ProtectedRegion> {
+        self.regions.get(name).map(|x| &x.region)
+    }
+
+    /// Protect `region`, replacing any of the same name, and extend `graph` to cover it
+    ///
+    /// Radii are limited to `max_radius` absolute units, since every node within that distance of
+    /// the center must be built.
+    pub fn insert(
+        &mut self,
+        graph: &mut Graph,
+        meters_to_absolute: f32,
+        max_radius: f32,
+        region: ProtectedRegion,
+    ) -> Result<(), InvalidRegion> {
+        waypoint::validate_name(&region.name).map_err(InvalidRegion::Name)?;
+        let radius = region.radius * meters_to_absolute;
+        if !(0.0..=max_radius).contains(&radius) {
+            return Err(InvalidRegion::Radius);
+        }
+        let center = Position {
+            node: region.center_path.ensure(graph),
+            local: na::Matrix4::identity(),
+        };
+        // Every voxel lies within the bounding sphere of its node
+        let reach = f64::from(radius) + dodeca::BOUNDING_SPHERE_RADIUS_F64;
+        ensure_nearby(graph, &center, reach);
+        let nodes = nearby_nodes(graph, &center, reach).into_iter().collect();
+        self.regions.insert(
+            region.name.clone(),
+            Resolved {
+                region,
+                radius,
+                nodes,
+            },
+        );
+        Ok(())
+    }
+
+    /// Lift the protection of the region called `name`, returning whether there was one
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.regions.remove(name).is_some()
+    }
+
+    /// The first region, in order of name, protecting the voxel at `coords` in `chunk` from the
+    /// character called `character`
+    pub fn protecting(
+        &self,
+        graph: &Graph,
+        chunk: ChunkId,
+        coords: Coords,
+        character: &str,
+    ) -> Option<&ProtectedRegion> {
+        let voxel = voxel_center_position(graph.layout(), chunk, coords);
+        self.regions
+            .values()
+            .find(|x| !x.region.allowed.contains(character) && x.contains(&voxel))
+            .map(|x| &x.region)
+    }
+}
+
+impl Resolved {
+    /// Distance in absolute units from the center to `position`, unless it's in a node too far
+    /// away to lie within the region
+    fn distance(&self, position: &Position) -> Option<f32> {
+        let transform = self.nodes.get(&position.node)?;
+        let point = transform * position.local * math::origin();
+        let distance = math::distance(&math::origin(), &point);
+        // Rounding can push points at the very center out of the domain of `acosh`
+        Some(if distance.is_nan() { 0.0 } else { distance })
+    }
+
+    fn contains(&self, position: &Position) -> bool {
+        self.distance(position)
+            .map_or(false, |distance| distance <= self.radius)
+    }
+}
+
+/// Why a region can't be protected
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvalidRegion {
+    Name(InvalidName),
+    /// Negative, or too large for the server to cover
+    Radius,
+}
+
+impl fmt::Display for InvalidRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            InvalidRegion::Name(e) => e.fmt(f),
+            InvalidRegion::Radius => f.pad("radius is negative or beyond the view distance"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use common::{
+        dodeca::{Side, Vertex},
+        node_path::NodePath,
+    };
+
+    fn region(path: Vec<Side>, radius: f32, allowed: &[&str]) -> ProtectedRegion {
+        ProtectedRegion {
+            name: "spawn".into(),
+            center_path: NodePath(path),
+            radius,
+            allowed: allowed.iter().map(|&x| x.into()).collect::<BTreeSet<_>>(),
+        }
+    }
+
+    #[test]
+    fn boundary_is_inclusive() {
+        let mut graph = Graph::new(4);
+        let mut regions = ProtectedRegions::new();
+        let chunk = ChunkId::new(NodeId::ROOT, Vertex::A);
+        let coords = Coords([1, 2, 0]);
+        regions
+            .insert(&mut graph, 1.0, 4.0, region(vec![Side::A], 4.0, &[]))
+            .unwrap();
+        let voxel = voxel_center_position(graph.layout(), chunk, coords);
+        let distance = regions.regions["spawn"].distance(&voxel).unwrap();
+        assert!(distance > 0.5);
+
+        // Exactly at the radius is inside
+        regions
+            .insert(&mut graph, 1.0, 4.0, region(vec![Side::A], distance, &[]))
+            .unwrap();
+        assert_eq!(
+            regions
+                .protecting(&graph, chunk, coords, "a")
+                .map(|x| &*x.name),
+            Some("spawn")
+        );
+        // Unless the character is allowed
+        regions
+            .insert(
+                &mut graph,
+                1.0,
+                4.0,
+                region(vec![Side::A], distance, &["a"]),
+            )
+            .unwrap();
+        assert!(regions.protecting(&graph, chunk, coords, "a").is_none());
+        assert!(regions.protecting(&graph, chunk, coords, "b").is_some());
+
+        // With the radius any smaller, it's outside
+        let just_short = f32::from_bits(distance.to_bits() - 1);
+        regions
+            .insert(&mut graph, 1.0, 4.0, region(vec![Side::A], just_short, &[]))
+            .unwrap();
+        assert!(regions.protecting(&graph, chunk, coords, "a").is_none());
+
+        assert_eq!(
+            regions.insert(&mut graph, 1.0, 4.0, region(vec![], -1.0, &[])),
+            Err(InvalidRegion::Radius)
+        );
+        assert_eq!(
+            regions.insert(&mut graph, 1.0, 4.0, region(vec![], 5.0, &[])),
+            Err(InvalidRegion::Radius)
+        );
+        assert!(regions.remove("spawn"));
+        assert!(regions.protecting(&graph, chunk, coords, "b").is_none());
+    }
+
+    /// No voxel within the radius is passed over for lying in a node the region doesn't consider
+    #[test]
+    fn node_filter_is_conservative() {
+        const RADIUS: f32 = 1.5;
+        let mut graph = Graph::new(2);
+        let mut regions = ProtectedRegions::new();
+        let path = NodePath(vec![Side::B, Side::D]);
+        regions
+            .insert(&mut graph, 1.0, 4.0, region(path.0.clone(), RADIUS, &[]))
+            .unwrap();
+        let center = Position {
+            node: path.resolve(&graph).unwrap(),
+            local: na::Matrix4::identity(),
+        };
+        let reach = f64::from(RADIUS) + 2.0 * dodeca::BOUNDING_SPHERE_RADIUS_F64;
+        ensure_nearby(&mut graph, &center, reach);
+
+        let (mut inside, mut outside) = (0, 0);
+        for (node, transform) in nearby_nodes(&graph, &center, reach) {
+            for vertex in Vertex::iter() {
+                let chunk = ChunkId::new(node, vertex);
+                for coords in (0..8).map(|i| Coords([i & 1, (i >> 1) & 1, i >> 2])) {
+                    let voxel = voxel_center_position(graph.layout(), chunk, coords);
+                    let point = transform * voxel.local * math::origin();
+                    let distance = math::distance(&math::origin(), &point);
+                    // Leave room for rounding, which the check is entitled to
+                    if distance < RADIUS - 1e-4 {
+                        inside += 1;
+                        assert!(
+                            regions.protecting(&graph, chunk, coords, "a").is_some(),
+                            "voxel {distance} from the center is unprotected"
+                        );
+                    } else if distance > RADIUS + 1e-4 {
+                        outside += 1;
+                        assert!(regions.protecting(&graph, chunk, coords, "a").is_none());
+                    }
+                }
+            }
+        }
+        assert!(inside > 0 && outside > 0);
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::{mpsc, Arc};
+
+use tracing::{error, trace};
+
+use common::{dodeca::Vertex, graph::NodeId};
+use save::Save;
+
+use crate::sim::{read_saved_chunks, SavedChunk};
+
+/// Reads saved voxels on the blocking thread pool, so that the simulation never waits on the disk
+/// for them
+pub struct SaveLoader {
+    save: Arc<Save>,
+    send: mpsc::Sender<Vec<SavedNode>>,
+    recv: mpsc::Receiver<Vec<SavedNode>>,
+}
+
+/// The saved voxels of a node
+pub struct SavedNode {
+    pub node: NodeId,
+    /// Chunks found in the save. The rest are generated.
+    pub chunks: Vec<(Vertex, SavedChunk)>,
+}
+
+impl SaveLoader {
+    pub fn new(save: Arc<Save>) -> Self {
+        let (send, recv) = mpsc::channel();
+        Self { save, send, recv }
+    }
+
+    /// Start reading the saved voxels of `nodes`, given with their hashes
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn request(&self, nodes: Vec<(NodeId, u128)>) {
+        if nodes.is_empty() {
+            return;
+        }
+        let save = self.save.clone();
+        let send = self.send.clone();
+        tokio::task::spawn_blocking(move || {
+            let dimension = save.meta().chunk_size as u8;
+            let guard = save.read();
+            let mut reader = match guard {
+                Ok(ref guard) => guard.get(),
+                Err(e) => Err(e),
+            };
+            if let Err(ref e) = reader {
+                // Generated instead, as when a chunk's saved voxels are malformed
+                error!(error = %e, "couldn't read save");
+            }
+            let saved = nodes
+                .into_iter()
+                .map(|(node, hash)| SavedNode {
+                    node,
+                    chunks: reader.as_mut().map_or_else(
+                        |_| Vec::new(),
+                        |reader| read_saved_chunks(reader, hash, dimension),
+                    ),
+                })
+                .collect::<Vec<_>>();
+            trace!(nodes = saved.len(), "read saved voxels");
+            // The loader may have been dropped while we were reading
+            let _ = send.send(saved);
+        });
+    }
+
+    /// Nodes read since the last call
+    pub fn poll(&self) -> impl Iterator<Item = SavedNode> + '_ {
+        self.recv.try_iter().flatten()
+    }
+}
@@ -0,0 +1,450 @@
+//! Things for the server to do at given steps, once or repeatedly
+//!
+//! Tasks are kept in order of the step they're due, compared with serial-number arithmetic so that
+//! the order holds as the step counter wraps around, provided no task lies more than `MAX_DELAY`
+//! steps ahead. Each step runs at most a fixed number of the tasks due, leaving the rest for the
+//! next in the same order, so that a burst of them can't stall the server. Tasks are data rather
+//! than closures so that they can be saved: a task pending when the server stops is due the same
+//! number of steps after it starts again, as though no time passed in between.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, BinaryHeap},
+    fmt,
+};
+
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use common::{
+    dodeca::Vertex,
+    node::Coords,
+    node_path::NodePath,
+    world::{Material, Shape},
+    EntityId, Step,
+};
+
+use crate::postcard_helpers;
+
+/// Version of the encoding of saved tasks' kinds, to be bumped whenever `TaskKind` changes such
+/// that those saved before can't be decoded
+pub const TASK_FORMAT: u32 = 1;
+
+/// Most tasks run in a single step
+pub const TASK_BUDGET: usize = 256;
+
+/// Furthest ahead in steps a task may be due, beyond which the order of tasks could no longer be
+/// told as the step counter wraps around
+pub const MAX_DELAY: u32 = i32::MAX as u32 / 2;
+
+/// Identifies a task for as long as it's pending, across restarts
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(pub u64);
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What a task does when it's due, carried out by whichever part of the server owns it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TaskKind {
+    /// Change blocks together, as when a door closes behind whoever opened it
+    SetBlocks(Vec<BlockChange>),
+    /// Knock characters away from a point, as when a fuse burns down
+    Blast {
+        path: NodePath,
+        /// Transform from the center of the blast to the coordinates of the node at `path`
+        local: na::Matrix4<f32>,
+        radius: f32,
+        speed: f32,
+    },
+    /// Return a character to its spawn point
+    Respawn(EntityId),
+}
+
+/// A block to be changed by a task, identified by its node's route from the root so that it
+/// means the same from one run to the next
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockChange {
+    pub path: NodePath,
+    pub vertex: Vertex,
+    pub coords: Coords,
+    pub material: Material,
+    pub shape: Shape,
+}
+
+/// Something to do at `fire_step`, and again every `repeat` steps after that if set
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledTask {
+    pub fire_step: Step,
+    pub repeat: Option<u32>,
+    pub kind: TaskKind,
+}
+
+/// A task's place in the queue
+///
+/// Cancelled tasks are left in the queue until they reach its head, where they're passed over.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Entry {
+    fire_step: Step,
+    /// Order in which the entry was queued, to break ties between tasks due at the same step
+    sequence: u64,
+    id: TaskId,
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed, so that the heap yields the earliest first
+        other
+            .fire_step
+            .wrapping_sub(self.fire_step)
+            .cmp(&0)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub struct Scheduler {
+    queue: BinaryHeap<Entry>,
+    /// Tasks yet to be done, by ID
+    tasks: FxHashMap<TaskId, ScheduledTask>,
+    next_id: u64,
+    next_sequence: u64,
+    /// Tasks scheduled, rescheduled, done, or cancelled since the last call to `take_changes`
+    dirty: BTreeSet<TaskId>,
+    /// Number of times a due task was left for a later step for want of budget
+    deferred: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            queue: BinaryHeap::new(),
+            tasks: FxHashMap::default(),
+            next_id: 0,
+            next_sequence: 0,
+            dirty: BTreeSet::new(),
+            deferred: 0,
+        }
+    }
+
+    /// Restore tasks read from the save
+    pub fn load(&mut self, saved: Vec<(u64, ScheduledTask)>) {
+        for (id, task) in saved {
+            self.next_id = self.next_id.max(id + 1);
+            self.insert(TaskId(id), task);
+        }
+    }
+
+    /// Do `kind` at `fire_step`, and every `repeat` steps after that if set, until cancelled
+    pub fn schedule(&mut self, fire_step: Step, repeat: Option<u32>, kind: TaskKind) -> TaskId {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.insert(
+            id,
+            ScheduledTask {
+                fire_step,
+                repeat: repeat.map(|x| x.clamp(1, MAX_DELAY)),
+                kind,
+            },
+        );
+        self.dirty.insert(id);
+        id
+    }
+
+    /// Forget the task `id` so that it's never done again, returning whether it was pending
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        if self.tasks.remove(&id).is_none() {
+            return false;
+        }
+        self.dirty.insert(id);
+        true
+    }
+
+    /// Number of tasks yet to be done
+    pub fn pending(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Number of times a due task was left for a later step for want of budget
+    pub fn deferred(&self) -> u64 {
+        self.deferred
+    }
+
+    /// Take up to `budget` of the tasks due at `step`, earliest first, rescheduling those that
+    /// repeat
+    ///
+    /// A repeating task is next due a whole period after it was last due rather than after it was
+    /// taken, so that it never drifts, even when deferred; one that has fallen more than a period
+    /// behind is due again at once, catching up a step at a time.
+    pub fn take_due(&mut self, step: Step, budget: usize) -> Vec<(TaskId, TaskKind)> {
+        let mut due = Vec::new();
+        // Queued again only once the rest are taken, so that none is taken twice in a step
+        let mut repeating = Vec::new();
+        while let Some(&entry) = self.queue.peek() {
+            if step.wrapping_sub(entry.fire_step) < 0 {
+                break;
+            }
+            if !self.tasks.contains_key(&entry.id) {
+                // Cancelled
+                self.queue.pop();
+                continue;
+            }
+            if due.len() == budget {
+                let deferred = self
+                    .queue
+                    .iter()
+                    .filter(|x| {
+                        step.wrapping_sub(x.fire_step) >= 0 && self.tasks.contains_key(&x.id)
+                    })
+                    .count();
+                self.deferred += deferred as u64;
+                warn!(deferred, total = self.deferred, "too many tasks due");
+                break;
+            }
+            self.queue.pop();
+            self.dirty.insert(entry.id);
+            let task = self.tasks.get_mut(&entry.id).unwrap();
+            match task.repeat {
+                Some(period) => {
+                    task.fire_step = task.fire_step.wrapping_add(period as i32);
+                    due.push((entry.id, task.kind.clone()));
+                    repeating.push((entry.id, task.fire_step));
+                }
+                None => {
+                    let task = self.tasks.remove(&entry.id).unwrap();
+                    due.push((entry.id, task.kind));
+                }
+            }
+        }
+        for (id, fire_step) in repeating {
+            self.enqueue(id, fire_step);
+        }
+        due
+    }
+
+    /// Record tasks changed since the last call in `batch`, as of `step`, which is `elapsed` steps
+    /// over every run
+    pub fn take_changes(&mut self, step: Step, elapsed: u64, batch: &mut save::Batch) {
+        for id in std::mem::take(&mut self.dirty) {
+            match self.tasks.get(&id) {
+                Some(task) => batch.put_task(id.0, encode_task(task, step, elapsed)),
+                // Recorded as gone, so that it's never done again
+                None => batch.remove_task(id.0),
+            }
+        }
+    }
+
+    fn insert(&mut self, id: TaskId, task: ScheduledTask) {
+        let fire_step = task.fire_step;
+        self.tasks.insert(id, task);
+        self.enqueue(id, fire_step);
+    }
+
+    fn enqueue(&mut self, id: TaskId, fire_step: Step) {
+        self.queue.push(Entry {
+            fire_step,
+            sequence: self.next_sequence,
+            id,
+        });
+        self.next_sequence += 1;
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Prepare `task` for saving as of `step`, which is `elapsed` steps over every run, with its
+/// `fire_step` counted in steps over every run
+pub fn encode_task(task: &ScheduledTask, step: Step, elapsed: u64) -> save::Task {
+    let mut kind = Vec::new();
+    postcard_helpers::serialize(&task.kind, &mut kind).unwrap();
+    save::Task {
+        // Overdue tasks are saved as due right away
+        fire_step: elapsed + task.fire_step.wrapping_sub(step).max(0) as u64,
+        repeat: task.repeat.unwrap_or(0),
+        kind,
+    }
+}
+
+/// Interpret a task read from the save as of `step`, which is `elapsed` steps over every run,
+/// unless it's malformed
+pub fn decode_task(stored: save::Task, step: Step, elapsed: u64) -> Option<ScheduledTask> {
+    // Overdue tasks, as when the save was written by a run that then stepped on unsaved, are due
+    // right away
+    let delay = stored
+        .fire_step
+        .saturating_sub(elapsed)
+        .min(MAX_DELAY.into());
+    Some(ScheduledTask {
+        fire_step: step.wrapping_add(delay as i32),
+        repeat: (stored.repeat != 0).then_some(stored.repeat.min(MAX_DELAY)),
+        kind: postcard::from_bytes(&stored.kind).ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A task telling itself apart from others by the entity it names
+    fn marker(n: u64) -> TaskKind {
+        TaskKind::Respawn(EntityId::from_bits(n))
+    }
+
+    fn markers(due: Vec<(TaskId, TaskKind)>) -> Vec<TaskKind> {
+        due.into_iter().map(|(_, kind)| kind).collect()
+    }
+
+    #[test]
+    fn order_holds_across_wraparound() {
+        let mut scheduler = Scheduler::new();
+        let start = i32::MAX - 2;
+        scheduler.schedule(start.wrapping_add(4), None, marker(3));
+        scheduler.schedule(start.wrapping_add(1), None, marker(1));
+        scheduler.schedule(start.wrapping_add(2), None, marker(2));
+        scheduler.schedule(start, None, marker(0));
+
+        let mut fired = Vec::new();
+        for offset in 0..6 {
+            let step = start.wrapping_add(offset);
+            for kind in markers(scheduler.take_due(step, TASK_BUDGET)) {
+                fired.push((offset, kind));
+            }
+        }
+        assert_eq!(
+            fired,
+            [
+                (0, marker(0)),
+                (1, marker(1)),
+                (2, marker(2)),
+                (4, marker(3))
+            ]
+        );
+    }
+
+    #[test]
+    fn repeats_without_drift() {
+        let mut scheduler = Scheduler::new();
+        let start = i32::MAX - 1000;
+        let id = scheduler.schedule(start, Some(7), marker(0));
+        let mut fired = Vec::new();
+        for offset in 0..7000 {
+            let step = start.wrapping_add(offset);
+            if !scheduler.take_due(step, TASK_BUDGET).is_empty() {
+                fired.push(offset);
+            }
+        }
+        assert_eq!(fired, (0..7000).step_by(7).collect::<Vec<_>>());
+
+        // Late steps catch up without shifting later ones
+        let step = scheduler.tasks[&id].fire_step;
+        assert_eq!(
+            scheduler.take_due(step.wrapping_add(15), TASK_BUDGET).len(),
+            1
+        );
+        assert_eq!(
+            scheduler.take_due(step.wrapping_add(16), TASK_BUDGET).len(),
+            1
+        );
+        assert_eq!(
+            scheduler.take_due(step.wrapping_add(17), TASK_BUDGET).len(),
+            1
+        );
+        assert_eq!(
+            scheduler.take_due(step.wrapping_add(18), TASK_BUDGET).len(),
+            0
+        );
+        assert_eq!(scheduler.tasks[&id].fire_step, step.wrapping_add(21));
+    }
+
+    #[test]
+    fn budget_defers_in_order() {
+        let mut scheduler = Scheduler::new();
+        for n in 0..10 {
+            scheduler.schedule(n as Step % 3, None, marker(n));
+        }
+        let first = markers(scheduler.take_due(2, 4));
+        assert_eq!(first, [0, 3, 6, 9].map(marker));
+        assert_eq!(scheduler.deferred(), 6);
+        let second = markers(scheduler.take_due(3, 4));
+        assert_eq!(second, [1, 4, 7, 2].map(marker));
+        assert_eq!(scheduler.deferred(), 8);
+        let third = markers(scheduler.take_due(4, 4));
+        assert_eq!(third, [5, 8].map(marker));
+        assert_eq!(scheduler.pending(), 0);
+    }
+
+    #[test]
+    fn cancelled_before_due() {
+        let mut scheduler = Scheduler::new();
+        let close = scheduler.schedule(5, None, marker(0));
+        let other = scheduler.schedule(5, Some(2), marker(1));
+        assert!(scheduler.take_due(4, TASK_BUDGET).is_empty());
+        // Cancelled in the very step it's due, before it's taken
+        assert!(scheduler.cancel(close));
+        assert!(!scheduler.cancel(close));
+        assert_eq!(markers(scheduler.take_due(5, TASK_BUDGET)), [marker(1)]);
+        // A repeating task is cancelled between repeats
+        assert!(scheduler.cancel(other));
+        assert!(scheduler.take_due(7, TASK_BUDGET).is_empty());
+        assert!(scheduler.queue.is_empty());
+    }
+
+    #[test]
+    fn persist_round_trip() {
+        let mut scheduler = Scheduler::new();
+        let fuse = TaskKind::Blast {
+            path: NodePath(vec![common::dodeca::Side::A]),
+            local: na::Matrix4::identity(),
+            radius: 2.0,
+            speed: 5.0,
+        };
+        let door = TaskKind::SetBlocks(vec![BlockChange {
+            path: NodePath::default(),
+            vertex: Vertex::A,
+            coords: Coords([1, 2, 3]),
+            material: Material::WhiteBrick,
+            shape: Shape::FULL,
+        }]);
+        let fuse_id = scheduler.schedule(i32::MAX, None, fuse.clone());
+        let door_id = scheduler.schedule(i32::MIN + 9, Some(20), door.clone());
+        let done = scheduler.schedule(i32::MAX - 5, None, marker(0));
+        scheduler.take_due(i32::MAX - 5, TASK_BUDGET);
+
+        // The run stops at its step `i32::MAX - 5`, after 1000 steps of earlier runs
+        let elapsed = 1000 + (i32::MAX - 5) as u64;
+        let mut batch = save::Batch::new();
+        scheduler.take_changes(i32::MAX - 5, elapsed, &mut batch);
+        assert_eq!(batch.task(done.0), Some(None));
+        let saved = [fuse_id, door_id].map(|id| (id.0, batch.task(id.0).unwrap().unwrap().clone()));
+        assert_eq!(saved[0].1.fire_step, elapsed + 5);
+        assert_eq!(saved[1].1.fire_step, elapsed + 15);
+
+        let mut restarted = Scheduler::new();
+        restarted.load(
+            saved
+                .into_iter()
+                .map(|(id, task)| (id, decode_task(task, 0, elapsed).unwrap()))
+                .collect(),
+        );
+        assert!(restarted.take_due(4, TASK_BUDGET).is_empty());
+        assert_eq!(markers(restarted.take_due(5, TASK_BUDGET)), [fuse]);
+        assert_eq!(markers(restarted.take_due(15, TASK_BUDGET)), [door.clone()]);
+        assert_eq!(markers(restarted.take_due(35, TASK_BUDGET)), [door]);
+        // IDs of saved tasks aren't given out again
+        let next = restarted.schedule(40, None, marker(1));
+        assert!(next > door_id && next > fuse_id);
+    }
+}
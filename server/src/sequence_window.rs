@@ -0,0 +1,93 @@
+/// Recently seen sequence numbers from a single client, used to discard duplicated requests
+///
+/// Only the `SIZE` sequence numbers up to and including the highest one seen are tracked.
+/// Anything older is assumed to be a duplicate, since clients assign sequence numbers in order
+/// and commands that fall that far behind are long obsolete.
+#[derive(Debug, Default)]
+pub struct SequenceWindow {
+    /// Highest sequence number seen, if any
+    latest: Option<u32>,
+    /// Bit `i` is set if `latest - i` has been seen
+    seen: u64,
+}
+
+impl SequenceWindow {
+    pub const SIZE: u32 = u64::BITS;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `sequence`, returning whether it's new
+    pub fn insert(&mut self, sequence: u32) -> bool {
+        let Some(latest) = self.latest else {
+            self.latest = Some(sequence);
+            self.seen = 1;
+            return true;
+        };
+        // Account for wrapping, as with input generations
+        let ahead = sequence.wrapping_sub(latest);
+        if ahead != 0 && ahead < u32::MAX / 2 {
+            self.seen = if ahead < Self::SIZE {
+                self.seen << ahead
+            } else {
+                0
+            };
+            self.seen |= 1;
+            self.latest = Some(sequence);
+            return true;
+        }
+        let behind = latest.wrapping_sub(sequence);
+        if behind >= Self::SIZE {
+            return false;
+        }
+        let bit = 1 << behind;
+        let new = self.seen & bit == 0;
+        self.seen |= bit;
+        new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_are_rejected() {
+        let mut window = SequenceWindow::new();
+        assert!(window.insert(0));
+        assert!(!window.insert(0));
+        assert!(window.insert(1));
+        assert!(!window.insert(0));
+        assert!(!window.insert(1));
+
+        // Requests may arrive out of order
+        assert!(window.insert(5));
+        assert!(window.insert(3));
+        assert!(!window.insert(3));
+        assert!(window.insert(4));
+        assert!(!window.insert(5));
+
+        // Anything that has left the window is assumed to be a replay
+        assert!(window.insert(2 + SequenceWindow::SIZE));
+        assert!(!window.insert(2));
+        assert!(window.insert(3 + SequenceWindow::SIZE));
+        assert!(!window.insert(3 + SequenceWindow::SIZE));
+
+        // Large jumps forget everything older
+        assert!(window.insert(1000));
+        assert!(!window.insert(1000));
+        assert!(window.insert(999));
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut window = SequenceWindow::new();
+        assert!(window.insert(u32::MAX - 1));
+        assert!(window.insert(1));
+        assert!(window.insert(u32::MAX));
+        assert!(window.insert(0));
+        assert!(!window.insert(u32::MAX));
+        assert!(!window.insert(1));
+    }
+}
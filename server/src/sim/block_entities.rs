@@ -0,0 +1,303 @@
+//! State attached to individual blocks, such as the text of signs
+
+use std::fmt;
+
+use hecs::Entity;
+
+use common::{
+    block_entity::{
+        self, BlockEntities, BlockEntity, BlockEntityChange, InvalidSignText, Sign, SIGN_LINES,
+    },
+    node::{ChunkId, Coords},
+    proto::Character,
+    reach::{self, Unreachable},
+    world::Material,
+};
+
+use super::Sim;
+use crate::postcard_helpers;
+
+impl Sim {
+    /// The block entity attached to the voxel at `coords` in `chunk`, if any
+    pub fn block_entity(&self, chunk: ChunkId, coords: Coords) -> Option<&BlockEntity> {
+        self.block_entities.get(chunk, coords)
+    }
+
+    /// Every block entity, for transmission to a new client not sent graph regions, which are
+    /// sent the block entities of each region with it instead
+    pub fn block_entities(&self) -> Vec<BlockEntityChange> {
+        self.block_entities
+            .iter()
+            .map(|(chunk, coords, entity)| (chunk, coords, Some(entity.clone())))
+            .collect()
+    }
+
+    /// Block entities created, changed, or removed since the last call, to be sent to every client
+    pub fn take_block_entity_changes(&mut self) -> Vec<BlockEntityChange> {
+        std::mem::take(&mut self.block_entity_changes)
+    }
+
+    /// Write `lines` on the sign at `coords` in `chunk` on behalf of the character `entity`
+    ///
+    /// Signs placed other than by a character, as by a block fill, have no text until a character
+    /// writes some, and face that character from then on.
+    pub fn set_sign_text(
+        &mut self,
+        entity: Entity,
+        chunk: ChunkId,
+        coords: Coords,
+        lines: [String; SIGN_LINES],
+    ) -> Result<(), SignEditError> {
+        let user = self.position(entity).ok_or(SignEditError::NoSuchEntity)?;
+        let name = self
+            .world
+            .get::<&Character>(entity)
+            .map_err(|_| SignEditError::NoSuchEntity)?
+            .name
+            .clone();
+        // Also covers a sign broken while its text was being written
+        if self.awaiting_save(chunk) || self.graph.get_block(chunk, coords) != Some(Material::Sign)
+        {
+            return Err(SignEditError::NoSign);
+        }
+        reach::block_in_reach(&self.cfg, &self.graph, &user, chunk, coords)
+            .map_err(SignEditError::Unreachable)?;
+        if let Some(region) = self
+            .protected_regions
+            .protecting(&self.graph, chunk, coords, &name)
+        {
+            return Err(SignEditError::Protected(region.name.clone()));
+        }
+        block_entity::validate_sign_text(&lines).map_err(SignEditError::InvalidText)?;
+        let face = match self.block_entities.get(chunk, coords) {
+            Some(BlockEntity::Sign(sign)) => sign.face,
+            None => Sign::facing(&self.graph, chunk, coords, &user).face,
+        };
+        self.set_block_entity(chunk, coords, BlockEntity::Sign(Sign { lines, face }));
+        Ok(())
+    }
+
+    /// Attach `entity` to the voxel at `coords` in `chunk`, to be saved with the chunk and sent to
+    /// clients
+    pub(super) fn set_block_entity(&mut self, chunk: ChunkId, coords: Coords, entity: BlockEntity) {
+        self.block_entities.insert(chunk, coords, entity.clone());
+        self.dirty_chunks.insert(chunk);
+        self.graph_regions.invalidate(&self.graph, chunk.node);
+        self.block_entity_changes
+            .push((chunk, coords, Some(entity)));
+    }
+
+    /// Remove the block entity of the voxel at `coords` in `chunk` if the block there no longer
+    /// calls for it, as when it's been broken or replaced
+    pub(super) fn prune_block_entity(&mut self, chunk: ChunkId, coords: Coords) {
+        let Some(entity) = self.block_entities.get(chunk, coords) else {
+            return;
+        };
+        if self
+            .graph
+            .get_block(chunk, coords)
+            .is_some_and(|material| entity.suits(material))
+        {
+            return;
+        }
+        self.block_entities.remove(chunk, coords);
+        self.block_entity_changes.push((chunk, coords, None));
+    }
+}
+
+/// Why text couldn't be written on a sign
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignEditError {
+    /// The writing character doesn't exist
+    NoSuchEntity,
+    /// There's no sign at the voxel, as when it's been broken in the meantime
+    NoSign,
+    Unreachable(Unreachable),
+    /// The sign lies in the named protected region, which the character isn't permitted to change
+    Protected(String),
+    InvalidText(InvalidSignText),
+}
+
+impl fmt::Display for SignEditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SignEditError::NoSuchEntity => f.pad("no such entity"),
+            SignEditError::NoSign => f.pad("no sign there"),
+            SignEditError::Unreachable(Unreachable::TooFar) => f.pad("out of reach"),
+            SignEditError::Unreachable(Unreachable::OutOfSight) => f.pad("out of sight"),
+            SignEditError::Protected(ref region) => write!(f, "protected by {region}"),
+            SignEditError::InvalidText(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SignEditError {}
+
+/// Encode the block entities of `chunk` for the save, as nothing at all if there are none
+pub(super) fn encode_block_entities(entities: &BlockEntities, chunk: ChunkId) -> Vec<u8> {
+    let entities = entities.in_chunk(chunk).collect::<Vec<_>>();
+    let mut encoded = Vec::new();
+    if !entities.is_empty() {
+        postcard_helpers::serialize(&entities, &mut encoded).unwrap();
+    }
+    encoded
+}
+
+/// Inverse of `encode_block_entities`. Returns `None` if `bytes` is malformed.
+pub(super) fn decode_block_entities(bytes: &[u8]) -> Option<Vec<(Coords, BlockEntity)>> {
+    if bytes.is_empty() {
+        return Some(Vec::new());
+    }
+    postcard::from_bytes(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        block_entity::MAX_SIGN_LINE_LEN,
+        node_path::NodePath,
+        proto::{ClientHello, InteractTarget, Position},
+        traversal::ensure_nearby,
+    };
+
+    use crate::sim::fixtures::{
+        alone_in_the_void, block_ahead, place_ahead, populate_nodes, stand_at_origin,
+    };
+
+    /// Text for a sign, with `first` on its first line
+    fn sign_text(first: &str) -> [String; SIGN_LINES] {
+        [first.into(), "---".into(), String::new(), String::new()]
+    }
+
+    #[test]
+    fn signs_follow_their_blocks() {
+        let (save, mut sim, entity, _file) = alone_in_the_void();
+        sim.set_creative(entity, true).unwrap();
+        let (_, other) = sim.spawn_character(ClientHello::new("other"));
+        *sim.world.get::<&mut Position>(other).unwrap() = Position::origin();
+        sim.set_creative(other, true).unwrap();
+        sim.take_block_entity_changes();
+
+        // Placed blank, facing its placer
+        let (chunk, coords) = place_ahead(&mut sim, &save, entity, 1, 3.0, Material::Sign);
+        assert_eq!(sim.graph.get_block(chunk, coords), Some(Material::Sign));
+        let placed = Sign::facing(&sim.graph, chunk, coords, &Position::origin());
+        assert_eq!(
+            sim.block_entity(chunk, coords),
+            Some(&BlockEntity::Sign(placed.clone()))
+        );
+        assert_eq!(
+            sim.take_block_entity_changes(),
+            [(chunk, coords, Some(BlockEntity::Sign(placed.clone())))]
+        );
+
+        // Written on, and sent to clients both as a change and with its region
+        sim.set_sign_text(entity, chunk, coords, sign_text("Home"))
+            .unwrap();
+        let written = BlockEntity::Sign(Sign {
+            lines: sign_text("Home"),
+            face: placed.face,
+        });
+        assert_eq!(sim.block_entity(chunk, coords), Some(&written));
+        assert_eq!(
+            sim.take_block_entity_changes(),
+            [(chunk, coords, Some(written.clone()))]
+        );
+        assert!(sim.dirty_chunks.contains(&chunk));
+        let root = sim.graph_region_roots([chunk.node])[0];
+        assert!(sim
+            .build_graph_region(root)
+            .block_entities
+            .contains(&(chunk, coords, written)));
+
+        // Broken by someone else while its placer is still choosing the words
+        place_ahead(&mut sim, &save, other, 1, 3.0, Material::Void);
+        assert_eq!(sim.graph.get_block(chunk, coords), Some(Material::Void));
+        assert_eq!(sim.block_entity(chunk, coords), None);
+        assert_eq!(sim.take_block_entity_changes(), [(chunk, coords, None)]);
+        assert_eq!(
+            sim.set_sign_text(entity, chunk, coords, sign_text("Home, sweet home")),
+            Err(SignEditError::NoSign)
+        );
+        assert!(sim.take_block_entity_changes().is_empty());
+        let root = sim.graph_region_roots([chunk.node])[0];
+        assert!(sim.build_graph_region(root).block_entities.is_empty());
+    }
+
+    #[test]
+    fn sign_text_checked() {
+        let (_save, mut sim, entity, _file) = alone_in_the_void();
+        let InteractTarget::Block(chunk, coords) = block_ahead(&mut sim, 3.0, Material::GreyBrick)
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            sim.set_sign_text(entity, chunk, coords, sign_text("Hi")),
+            Err(SignEditError::NoSign)
+        );
+
+        // Put in place other than by a character, so blank until first written on
+        block_ahead(&mut sim, 3.0, Material::Sign);
+        assert_eq!(sim.block_entity(chunk, coords), None);
+        let mut too_long = sign_text("");
+        too_long[1] = "x".repeat(MAX_SIGN_LINE_LEN + 1);
+        assert_eq!(
+            sim.set_sign_text(entity, chunk, coords, too_long),
+            Err(SignEditError::InvalidText(InvalidSignText::TooLong {
+                line: 1
+            }))
+        );
+        assert_eq!(
+            sim.set_sign_text(entity, chunk, coords, sign_text("\u{7}ring")),
+            Err(SignEditError::InvalidText(InvalidSignText::BadCharacter {
+                line: 0,
+                character: '\u{7}'
+            }))
+        );
+        assert_eq!(sim.block_entity(chunk, coords), None);
+        assert!(sim.take_block_entity_changes().is_empty());
+
+        sim.set_sign_text(entity, chunk, coords, sign_text("Hi"))
+            .unwrap();
+        let facing = Sign::facing(&sim.graph, chunk, coords, &Position::origin()).face;
+        assert_eq!(
+            sim.block_entity(chunk, coords),
+            Some(&BlockEntity::Sign(Sign {
+                lines: sign_text("Hi"),
+                face: facing,
+            }))
+        );
+    }
+
+    #[test]
+    fn signs_persist() {
+        let (save, mut sim, entity, _file) = alone_in_the_void();
+        sim.set_creative(entity, true).unwrap();
+        let (chunk, coords) = place_ahead(&mut sim, &save, entity, 1, 3.0, Material::Sign);
+        sim.set_sign_text(entity, chunk, coords, sign_text("Saved"))
+            .unwrap();
+        let written = sim.block_entity(chunk, coords).unwrap().clone();
+        let path = NodePath::to(&sim.graph, chunk.node);
+        save.apply(&sim.take_changes()).unwrap();
+        let cfg = sim.cfg.clone();
+        drop(sim);
+
+        let mut sim = Sim::new(cfg, &save);
+        stand_at_origin(&mut sim);
+        ensure_nearby(&mut sim.graph, &Position::origin(), 1.5);
+        let node = path.resolve(&sim.graph).unwrap();
+        populate_nodes(&mut sim, &save, &[node]);
+        let chunk = ChunkId::new(node, chunk.vertex);
+        assert_eq!(sim.block_entity(chunk, coords), Some(&written));
+        // Clients already connected are told of it too
+        assert_eq!(
+            sim.take_block_entity_changes(),
+            [(chunk, coords, Some(written))]
+        );
+
+        // Chunks saved before block entities existed have none
+        assert_eq!(decode_block_entities(&[]), Some(Vec::new()));
+        assert_eq!(decode_block_entities(&[0xff; 3]), None);
+    }
+}
@@ -0,0 +1,735 @@
+//! Characters' lifecycle: spawning, commands, teleportation, respawning, and disconnection
+
+use std::fmt;
+
+use hecs::Entity;
+use tracing::{error, info, trace, warn};
+
+use common::{
+    character_controller::{self, CollisionTrace, TraceDump},
+    dodeca,
+    graph::NodeId,
+    inventory::Inventory,
+    math,
+    node::ChunkId,
+    node_path::NodePath,
+    proto::{
+        Character, CharacterInput, CharacterState, ClientHello, Command, MovementInput,
+        MovementModes, Position, SoundEvent, SoundKind, SoundSource,
+    },
+    traversal::{ensure_nearby, nearby_nodes},
+    EntityId,
+};
+
+use super::{
+    edits::FillLimit,
+    interaction::LastUse,
+    movement::{Airborne, Influences},
+    persistence::{decode_path, path_from_origin},
+    projectiles::LastThrow,
+    Sim,
+};
+use crate::{
+    sequence_window::SequenceWindow,
+    spawn::{self, SpawnPoint},
+};
+
+/// Farthest in meters a character is moved to free it from terrain it's stuck inside
+const UNEMBED_DISTANCE: f32 = 4.0;
+
+/// Most steps of a character's movement kept while tracing its collisions
+const MAX_TRACED_STEPS: u32 = 1000;
+
+/// Loudness of a character vanishing from one place and appearing in another, relative to that of
+/// a block being broken or placed
+const TELEPORT_INTENSITY: f32 = 2.0;
+
+impl Sim {
+    pub fn spawn_character(&mut self, hello: ClientHello) -> (EntityId, Entity) {
+        let id = self.id_allocator.persistent();
+        info!(entity = %id, name = %hello.name, "spawning character");
+        let occupied = self
+            .world
+            .query::<&SpawnPoint>()
+            .iter()
+            .map(|(_, x)| x.0)
+            .collect::<Vec<_>>();
+        // Until the ground has been found beneath a spawn point, fall back to the origin
+        let position = self
+            .spawn_points
+            .choose(&self.graph, &occupied)
+            .unwrap_or_else(|| Position {
+                node: NodeId::ROOT,
+                local: math::translate_along(&(na::Vector3::y() * 1.4)),
+            });
+        let character = Character {
+            name: hello.name,
+            state: CharacterState {
+                orientation: spawn::facing_open_space(&self.cfg, &self.graph, &position),
+                velocity: na::Vector3::zeros(),
+                on_ground: false,
+                teleports: 0,
+                health: self.cfg.character.max_health,
+                afk: false,
+            },
+        };
+        let allowed_modes = self.cfg.default_movement_modes;
+        let initial_input = CharacterInput {
+            movement: MovementInput::zero(),
+            jump: false,
+            no_clip: allowed_modes.contains(MovementModes::NO_CLIP),
+            block_update: None,
+            block_fill: None,
+            use_target: None,
+            throw: None,
+            external: Default::default(),
+        };
+        let entity = self.world.spawn((
+            id,
+            position,
+            character,
+            initial_input,
+            allowed_modes,
+            Inventory::default(),
+            SequenceWindow::new(),
+            SpawnPoint(position),
+            Airborne::default(),
+            LastUse::default(),
+            LastThrow::default(),
+            Influences::default(),
+            FillLimit(self.cfg.max_fill_volume),
+        ));
+        self.graph_entities.insert(position.node, entity);
+        let previous = self.entity_ids.insert(id, entity);
+        debug_assert!(previous.is_none(), "entity ID {id} assigned twice");
+        self.spawns.push(entity);
+        self.dirty_nodes.insert(position.node);
+        (id, entity)
+    }
+
+    pub fn command(
+        &mut self,
+        entity: Entity,
+        command: Command,
+    ) -> Result<(), hecs::ComponentError> {
+        let allowed_modes = *self.world.get::<&MovementModes>(entity)?;
+        let mut input = self.world.get::<&mut CharacterInput>(entity)?;
+        *input = command.character_input;
+        if input.restrict(allowed_modes) {
+            trace!(generation = command.generation, "denied no-clip");
+        }
+        let mut ch = self.world.get::<&mut Character>(entity)?;
+        ch.state.orientation = command.orientation;
+        Ok(())
+    }
+
+    /// Change the movement modes a character may use, effective for inputs it's yet to process
+    ///
+    /// A character that's denied no-clip mid-flight may be left inside terrain, where it would
+    /// wedge the collision solver, so it's moved to the nearest open space.
+    pub fn set_movement_modes(
+        &mut self,
+        entity: Entity,
+        allowed: MovementModes,
+    ) -> Result<(), hecs::ComponentError> {
+        *self.world.get::<&mut MovementModes>(entity)? = allowed;
+        if !self
+            .world
+            .get::<&mut CharacterInput>(entity)?
+            .restrict(allowed)
+        {
+            return Ok(());
+        }
+        let mut position = self.world.get::<&mut Position>(entity)?;
+        let Some(free) = character_controller::nearest_free_position(
+            &self.cfg,
+            &self.graph,
+            &position,
+            UNEMBED_DISTANCE * self.cfg.meters_to_absolute,
+        ) else {
+            warn!("no open space near character leaving no-clip");
+            return Ok(());
+        };
+        drop(position);
+        self.move_character(entity, free)?;
+        self.world.get::<&mut Character>(entity)?.state.velocity = na::Vector3::zeros();
+        Ok(())
+    }
+
+    /// Record how collisions are handled for `entity` over its latest `steps` steps, discarding
+    /// anything recorded already, or stop recording if `steps` is 0
+    pub fn trace_collisions(
+        &mut self,
+        entity: Entity,
+        steps: u32,
+    ) -> Result<(), hecs::NoSuchEntity> {
+        if steps == 0 {
+            return match self.world.remove_one::<CollisionTrace>(entity) {
+                Ok(_) | Err(hecs::ComponentError::MissingComponent(_)) => Ok(()),
+                Err(hecs::ComponentError::NoSuchEntity) => Err(hecs::NoSuchEntity),
+            };
+        }
+        let steps = steps.min(MAX_TRACED_STEPS) as usize;
+        self.world.insert_one(entity, CollisionTrace::new(steps))
+    }
+
+    /// What's been recorded since `trace_collisions`, if `entity` is being traced
+    pub fn collision_trace(&self, entity: Entity) -> Option<TraceDump> {
+        let trace = self.world.get::<&CollisionTrace>(entity).ok()?;
+        Some(trace.dump())
+    }
+
+    /// Teleport `subject` to `target`'s position, raised by `offset_along_up` absolute units
+    pub fn teleport_to_entity(
+        &mut self,
+        save: &save::Save,
+        subject: Entity,
+        target: Entity,
+        offset_along_up: f32,
+    ) -> Result<(), TeleportError> {
+        let target = *self
+            .world
+            .get::<&Position>(target)
+            .map_err(|_| TeleportError::NoSuchEntity)?;
+        let up = self
+            .graph
+            .get_relative_up(&target)
+            .unwrap_or_else(na::Vector3::y_axis);
+        let destination = Position {
+            node: target.node,
+            local: target.local * math::translate_along(&(up.into_inner() * offset_along_up)),
+        };
+        self.teleport(save, subject, destination)
+    }
+
+    /// Teleport `subject` to `local` in the coordinates of the node at the end of `path`, creating
+    /// any nodes along the way
+    pub fn teleport_to_path(
+        &mut self,
+        save: &save::Save,
+        subject: Entity,
+        path: &NodePath,
+        local: na::Matrix4<f32>,
+    ) -> Result<(), TeleportError> {
+        let node = path.ensure(&mut self.graph);
+        self.teleport(save, subject, Position { node, local })
+    }
+
+    /// Teleport `subject` by `translation` in absolute units, in its own frame
+    pub fn teleport_relative(
+        &mut self,
+        save: &save::Save,
+        subject: Entity,
+        translation: &na::Vector3<f32>,
+    ) -> Result<(), TeleportError> {
+        let position = *self
+            .world
+            .get::<&Position>(subject)
+            .map_err(|_| TeleportError::NoSuchEntity)?;
+        let destination = Position {
+            node: position.node,
+            local: position.local * math::translate_along(translation),
+        };
+        self.teleport(save, subject, destination)
+    }
+
+    /// Move `subject` to the open space nearest `destination`, generating the surroundings first if
+    /// needed
+    ///
+    /// The character comes to rest there, and its client is told to adopt the new position
+    /// outright rather than reconciling its prediction across the jump.
+    fn teleport(
+        &mut self,
+        save: &save::Save,
+        subject: Entity,
+        mut destination: Position,
+    ) -> Result<(), TeleportError> {
+        if self.world.get::<&Character>(subject).is_err() {
+            return Err(TeleportError::NoSuchEntity);
+        }
+        let departure = *self.world.get::<&Position>(subject).unwrap();
+        // The destination may lie well outside the node it's given relative to, and beyond the
+        // graph built so far
+        destination.local = math::renormalize_isometry(&destination.local);
+        loop {
+            for side in dodeca::Side::iter() {
+                self.graph.ensure_neighbor(destination.node, side);
+            }
+            let (node, transform) = self
+                .graph
+                .normalize_transform(destination.node, &destination.local);
+            if node == destination.node {
+                break;
+            }
+            destination = Position {
+                node,
+                local: transform * destination.local,
+            };
+        }
+        let distance = self.chunk_generation_distance();
+        ensure_nearby(&mut self.graph, &destination, distance);
+        let nodes = nearby_nodes(&self.graph, &destination, distance)
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect::<Vec<_>>();
+        self.population
+            .run(&mut self.graph, nodes.iter().copied(), 0);
+        self.populate_chunks(
+            save,
+            nodes.iter().flat_map(|&node| {
+                dodeca::Vertex::iter().map(move |vertex| ChunkId::new(node, vertex))
+            }),
+            true,
+        );
+        let free = character_controller::nearest_free_position(
+            &self.cfg,
+            &self.graph,
+            &destination,
+            UNEMBED_DISTANCE * self.cfg.meters_to_absolute,
+        )
+        .ok_or(TeleportError::Obstructed)?;
+        self.move_character(subject, free)
+            .map_err(|_| TeleportError::NoSuchEntity)?;
+        let mut character = self.world.get::<&mut Character>(subject).unwrap();
+        character.state.velocity = na::Vector3::zeros();
+        character.state.teleports = character.state.teleports.wrapping_add(1);
+        drop(character);
+        // Heard both where the character left and where it arrived
+        for position in [departure, free] {
+            self.sounds.push(SoundEvent {
+                kind: SoundKind::Teleport,
+                source: SoundSource::Position(position),
+                intensity: TELEPORT_INTENSITY,
+                step: self.step,
+            });
+        }
+        Ok(())
+    }
+
+    /// Return a character to its spawn point, fully healed, as when it's run out of health
+    pub(super) fn respawn(&mut self, entity: Entity) {
+        let spawn_point = self.world.get::<&SpawnPoint>(entity).unwrap().0;
+        self.move_character(entity, spawn_point).unwrap();
+        let orientation = spawn::facing_open_space(&self.cfg, &self.graph, &spawn_point);
+        let mut character = self.world.get::<&mut Character>(entity).unwrap();
+        character.state.orientation = orientation;
+        character.state.velocity = na::Vector3::zeros();
+        character.state.health = self.cfg.character.max_health;
+        // Clients must jump straight there rather than reconcile their prediction across the world
+        character.state.teleports = character.state.teleports.wrapping_add(1);
+    }
+
+    /// Put `entity` at `position`, keeping track of the nodes it's in
+    pub(super) fn move_character(
+        &mut self,
+        entity: Entity,
+        position: Position,
+    ) -> Result<(), hecs::ComponentError> {
+        let mut current = self.world.get::<&mut Position>(entity)?;
+        if current.node != position.node {
+            self.dirty_nodes.insert(current.node);
+            self.graph_entities.remove(current.node, entity);
+            self.graph_entities.insert(position.node, entity);
+        }
+        *current = position;
+        self.dirty_nodes.insert(position.node);
+        Ok(())
+    }
+
+    /// Note that the player of the character `entity` is leaving, saving where it was so that
+    /// they return there if `persist`, or so that they start afresh otherwise
+    ///
+    /// The character itself is left for `destroy`.
+    pub fn record_departure(&mut self, entity: Entity, persist: bool) {
+        let Ok((position, ch)) = self.world.query_one_mut::<(&Position, &Character)>(entity) else {
+            return;
+        };
+        let character = save::Character {
+            path: path_from_origin(&self.graph, position.node),
+            local: if persist {
+                position.local.as_slice().to_vec()
+            } else {
+                Vec::new()
+            },
+        };
+        self.departures.insert(ch.name.clone(), character);
+    }
+
+    /// Move the newly spawned character `entity` to where it was when its player last left, if
+    /// that was saved for it by `record_departure`
+    pub fn return_character(&mut self, save: &save::Save, entity: Entity) {
+        let Ok(name) = self.world.get::<&Character>(entity).map(|x| x.name.clone()) else {
+            return;
+        };
+        let stored = match self.departures.remove(&name) {
+            Some(x) => Some(x),
+            None => {
+                let stored = save
+                    .read()
+                    .map_err(save::GetError::from)
+                    .and_then(|guard| guard.get()?.get_character(&name));
+                match stored {
+                    Ok(x) => x,
+                    Err(e) => {
+                        error!(%name, error = %e, "couldn't load character");
+                        return;
+                    }
+                }
+            }
+        };
+        // Characters saved while their players were present are started afresh
+        let Some(stored) = stored.filter(|x| !x.local.is_empty()) else {
+            return;
+        };
+        let Some(path) = decode_path(&stored.path) else {
+            warn!(%name, "ignoring malformed saved character");
+            return;
+        };
+        let Some(local) =
+            (stored.local.len() == 16).then(|| na::Matrix4::from_column_slice(&stored.local))
+        else {
+            warn!(%name, "ignoring malformed saved character");
+            return;
+        };
+        match self.teleport_to_path(save, entity, &path, local) {
+            Ok(()) => info!(%name, "returned character to where it was left"),
+            Err(e) => warn!(%name, "couldn't return character: {}", e),
+        }
+    }
+
+    /// Mark the character `entity` as away, so that it no longer keeps its surroundings active,
+    /// or as back
+    pub fn set_afk(&mut self, entity: Entity, afk: bool) -> Result<(), hecs::ComponentError> {
+        self.world.get::<&mut Character>(entity)?.state.afk = afk;
+        Ok(())
+    }
+}
+
+/// Why a character couldn't be teleported
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TeleportError {
+    /// The character or the entity it was to be teleported to doesn't exist
+    NoSuchEntity,
+    /// There's no open space near the destination
+    Obstructed,
+}
+
+impl fmt::Display for TeleportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match *self {
+            TeleportError::NoSuchEntity => "no such entity",
+            TeleportError::Obstructed => "destination obstructed",
+        })
+    }
+}
+
+impl std::error::Error for TeleportError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use common::{
+        coords::voxel_center_position,
+        graph::Graph,
+        node::{populate_fresh_nodes, Coords},
+        proto::BlockUpdate,
+        test_graphs::surface_elevation,
+        world::{Material, Shape},
+        worldgen::TerrainPassKind,
+        SimConfig, SimConfigRaw,
+    };
+
+    use crate::sim::fixtures::{alone_in_the_void, chunks_of, raise, standing_on};
+
+    fn flying(generation: u16) -> Command {
+        Command {
+            generation,
+            character_input: CharacterInput {
+                movement: MovementInput::new(na::Vector3::x()),
+                jump: false,
+                no_clip: true,
+                block_update: None,
+                block_fill: None,
+                use_target: None,
+                throw: None,
+                external: Default::default(),
+            },
+            orientation: na::one(),
+        }
+    }
+
+    #[test]
+    fn no_clip_requires_permission() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            allow_no_clip: Some(false),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg, &save);
+        let (_, entity) = sim.spawn_character(ClientHello::new("test"));
+        let no_clip = |sim: &Sim| sim.world.get::<&CharacterInput>(entity).unwrap().no_clip;
+        assert!(!no_clip(&sim));
+
+        sim.command(entity, flying(1)).unwrap();
+        assert!(!no_clip(&sim));
+        // Everything else about the input is kept
+        assert_eq!(
+            *sim.world
+                .get::<&CharacterInput>(entity)
+                .unwrap()
+                .movement
+                .vector(),
+            na::Vector3::x()
+        );
+
+        sim.set_movement_modes(entity, MovementModes::NO_CLIP)
+            .unwrap();
+        sim.command(entity, flying(2)).unwrap();
+        assert!(no_clip(&sim));
+
+        // Revoking permission applies to the input already in effect
+        sim.set_movement_modes(entity, MovementModes::NONE).unwrap();
+        assert!(!no_clip(&sim));
+    }
+
+    #[test]
+    fn revoking_no_clip_frees_embedded_character() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(10.0),
+            spawn_distance: Some(0),
+            terrain: Some(vec![TerrainPassKind::Flat {
+                height: 3.0,
+                material: Material::Dirt,
+            }]),
+            ..Default::default()
+        }));
+        let height = 3.0 * cfg.meters_to_absolute;
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let (_, entity) = sim.spawn_character(ClientHello::new("test"));
+        let state = &sim.graph.get(NodeId::ROOT).as_ref().unwrap().state;
+        let up = state.up_direction().xyz().normalize();
+        let elevation_offset = state.elevation();
+        // Flown a meter into the ground
+        *sim.world.get::<&mut Position>(entity).unwrap() = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(
+                &(up * (height - cfg.meters_to_absolute - elevation_offset)),
+            ),
+        };
+        // Characters may fly by default
+        assert!(sim.world.get::<&CharacterInput>(entity).unwrap().no_clip);
+        sim.step(&save);
+
+        sim.set_movement_modes(entity, MovementModes::NONE).unwrap();
+        let position = *sim.world.get::<&Position>(entity).unwrap();
+        let elevation = surface_elevation(&sim.graph, &position);
+        assert!(elevation > height, "{elevation}");
+        assert!(
+            elevation < height + 2.0 * cfg.meters_to_absolute,
+            "{elevation}"
+        );
+
+        // Now it can stand on the ground rather than being wedged inside it
+        for _ in 0..30 {
+            sim.step(&save);
+        }
+        assert!(sim.world.get::<&Character>(entity).unwrap().state.on_ground);
+    }
+
+    #[test]
+    fn teleport_to_ungenerated_path() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(10.0),
+            spawn_distance: Some(0),
+            terrain: Some(vec![TerrainPassKind::Flat {
+                height: 3.0,
+                material: Material::Dirt,
+            }]),
+            ..Default::default()
+        }));
+        let m = cfg.meters_to_absolute;
+        let height = 3.0 * m;
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let (_, entity) = sim.spawn_character(ClientHello::new("test"));
+        sim.world
+            .get::<&mut CharacterInput>(entity)
+            .unwrap()
+            .no_clip = false;
+        sim.step(&save);
+
+        // Alternating between two non-adjacent sides leads straight away from the origin
+        let far = dodeca::Side::iter()
+            .find(|&side| side != dodeca::Side::A && !dodeca::Side::A.adjacent_to(side))
+            .unwrap();
+        let path = NodePath([dodeca::Side::A, far].repeat(3));
+        assert_eq!(path.resolve(&sim.graph), None);
+        // A little above the ground there, as found in a graph of its own
+        let mut scratch = Graph::new(cfg.chunk_size);
+        let scratch_node = path.ensure(&mut scratch);
+        populate_fresh_nodes(&mut scratch);
+        let state = &scratch.get(scratch_node).as_ref().unwrap().state;
+        let up = state.up_direction().xyz().normalize();
+        let local = math::translate_along(&(up * (height + 2.0 * m - state.elevation())));
+
+        let generated = sim.chunks_generated;
+        sim.teleport_to_path(&save, entity, &path, local).unwrap();
+        assert!(path.resolve(&sim.graph).is_some());
+        assert!(sim.chunks_generated > generated);
+        let position = *sim.world.get::<&Position>(entity).unwrap();
+        assert!(
+            (surface_elevation(&sim.graph, &position) - (height + 2.0 * m)).abs() < 1e-3,
+            "{}",
+            surface_elevation(&sim.graph, &position)
+        );
+        let character = sim.world.get::<&Character>(entity).unwrap();
+        assert_eq!(character.state.teleports, 1);
+        assert_eq!(character.state.velocity, na::Vector3::zeros());
+        drop(character);
+
+        // It falls to the freshly generated ground and stands there
+        for _ in 0..30 {
+            sim.step(&save);
+        }
+        assert!(sim.world.get::<&Character>(entity).unwrap().state.on_ground);
+        let position = *sim.world.get::<&Position>(entity).unwrap();
+        let elevation = surface_elevation(&sim.graph, &position);
+        assert!(
+            (elevation - height - cfg.character.character_radius).abs()
+                < cfg.character.ground_distance_tolerance,
+            "{elevation}"
+        );
+    }
+
+    #[test]
+    fn teleport_into_solid_rejected() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(10.0),
+            spawn_distance: Some(0),
+            terrain: Some(vec![TerrainPassKind::Flat {
+                height: 3.0,
+                material: Material::Dirt,
+            }]),
+            ..Default::default()
+        }));
+        let m = cfg.meters_to_absolute;
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let (_, entity) = sim.spawn_character(ClientHello::new("test"));
+        sim.step(&save);
+        let before = *sim.world.get::<&Position>(entity).unwrap();
+
+        // Deep underground, out of reach of any open space
+        let state = &sim.graph.get(NodeId::ROOT).as_ref().unwrap().state;
+        let up = state.up_direction().xyz().normalize();
+        let local = math::translate_along(&(up * (3.0 * m - 20.0 * m - state.elevation())));
+        assert_eq!(
+            sim.teleport_to_path(&save, entity, &NodePath::default(), local),
+            Err(TeleportError::Obstructed)
+        );
+
+        // Nothing changed
+        let after = *sim.world.get::<&Position>(entity).unwrap();
+        assert_eq!(after.node, before.node);
+        assert_eq!(after.local, before.local);
+        let character = sim.world.get::<&Character>(entity).unwrap();
+        assert_eq!(character.state.teleports, 0);
+    }
+
+    #[test]
+    fn death_respawns_character() {
+        let (save, mut sim, entity, _file) = standing_on(Material::Dirt);
+        let spawn_point = sim.world.get::<&SpawnPoint>(entity).unwrap().0;
+        raise(&mut sim, entity, 30.0);
+        for _ in 0..50 {
+            sim.step(&save);
+            if sim.world.get::<&Character>(entity).unwrap().state.teleports != 0 {
+                break;
+            }
+        }
+        let character = sim.world.get::<&Character>(entity).unwrap();
+        assert_eq!(character.state.teleports, 1);
+        assert_eq!(character.state.health, sim.cfg.character.max_health);
+        assert_eq!(character.state.velocity, na::Vector3::zeros());
+        let position = *sim.world.get::<&Position>(entity).unwrap();
+        assert_eq!(position.node, spawn_point.node);
+        assert_eq!(position.local, spawn_point.local);
+    }
+
+    /// Fill every voxel around the origin whose center lies between `near` and `far` meters along
+    /// `normal`, a direction at the origin, walling off that side
+    fn wall_off(sim: &mut Sim, normal: &na::Vector3<f32>, near: f32, far: f32) {
+        let m = sim.cfg.meters_to_absolute;
+        let range = (near * m).tanh()..(far * m).tanh();
+        let dimension = sim.graph.layout().dimension();
+        for (node, transform) in nearby_nodes(&sim.graph, &Position::origin(), 1.0) {
+            for chunk_id in chunks_of(node) {
+                let all_coords = (0..dimension).flat_map(|x| {
+                    (0..dimension).flat_map(move |y| (0..dimension).map(move |z| Coords([x, y, z])))
+                });
+                for coords in all_coords {
+                    let center = voxel_center_position(sim.graph.layout(), chunk_id, coords);
+                    let point = transform * center.local * math::origin();
+                    if range.contains(&(point.xyz() / point.w).dot(normal)) {
+                        let _ = sim.graph.update_block(&BlockUpdate {
+                            chunk_id,
+                            coords,
+                            new_material: Material::Dirt,
+                            new_shape: Shape::FULL,
+                            sequence: 0,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// A level direction at the origin of `sim` with a wall a voxel off along it
+    fn wall_beside_origin(sim: &mut Sim) -> na::Vector3<f32> {
+        let up = sim.graph.get_relative_up(&Position::origin()).unwrap();
+        let toward = up.cross(&na::Vector3::x()).normalize();
+        wall_off(sim, &toward, 1.5, 3.5);
+        toward
+    }
+
+    #[test]
+    fn spawn_faces_away_from_wall() {
+        let (_save, mut sim, entity, _file) = alone_in_the_void();
+        let toward = wall_beside_origin(&mut sim);
+        sim.world.get::<&mut SpawnPoint>(entity).unwrap().0 = Position::origin();
+
+        sim.respawn(entity);
+        let orientation = sim
+            .world
+            .get::<&Character>(entity)
+            .unwrap()
+            .state
+            .orientation;
+        let up = sim.graph.get_relative_up(&Position::origin()).unwrap();
+        let facing = orientation * -na::Vector3::z();
+        assert!(facing.dot(&toward) < 0.0, "facing {facing} toward the wall");
+        // Level, and upright
+        assert!(facing.dot(&up).abs() < 1e-5);
+        assert!((orientation * na::Vector3::y()).dot(&up) > 1.0 - 1e-5);
+    }
+
+    #[test]
+    fn spawn_orientation_is_deterministic() {
+        let orientation = || {
+            let (_save, mut sim, _, _file) = alone_in_the_void();
+            wall_beside_origin(&mut sim);
+            spawn::facing_open_space(&sim.cfg, &sim.graph, &Position::origin())
+        };
+        let first = orientation();
+        assert_ne!(first, na::UnitQuaternion::identity());
+        assert_eq!(first, orientation());
+    }
+}
@@ -0,0 +1,740 @@
+//! Populating chunks, whether by world generation or from voxels read from the save
+
+use fxhash::FxHashSet;
+use tracing::{error, info, trace};
+
+use common::{
+    block_entity::BlockEntity,
+    dodeca,
+    graph::NodeId,
+    logging::chunk_span,
+    node::{Chunk, ChunkId, Coords, VoxelData},
+    proto::{Character, Position, SerializableVoxelData},
+    traversal::nearby_nodes,
+    world::{Material, Shape},
+    worldgen::ChunkParams,
+};
+
+use super::{block_entities::decode_block_entities, Sim};
+use crate::activity::RegionState;
+
+/// Most chunks read from the save ahead of need to populate per step, so that many reads finishing
+/// together don't stall the step
+const SAVED_CHUNK_BUDGET: usize = 64;
+
+impl Sim {
+    /// Start reading the saved voxels of every node within sight of a character, so they're ready
+    /// by the time they're needed
+    pub(super) fn read_ahead(&mut self) {
+        for (_, (position, _)) in self.world.query::<(&Position, &Character)>().iter() {
+            for (node, _) in nearby_nodes(&self.graph, position, f64::from(self.cfg.view_distance))
+            {
+                let hash = self.graph.hash_of(node);
+                if self.unread_nodes.remove(&hash) {
+                    self.save_reads.push((node, hash));
+                    self.reading.insert(node);
+                }
+            }
+        }
+    }
+
+    /// Distance from a character within which chunks must be populated before it's simulated
+    ///
+    /// Covers every chunk a character can interact with in a single step.
+    pub(super) fn chunk_generation_distance(&self) -> f64 {
+        dodeca::BOUNDING_SPHERE_RADIUS_F64
+            + self.cfg.character.character_radius as f64
+            + self.cfg.character.speed_cap as f64 * self.cfg.step_interval.as_secs_f64()
+            + self.cfg.character.ground_distance_tolerance as f64
+            + self.cfg.character.block_reach as f64
+            + 0.001
+    }
+
+    /// Populate those of `chunks` that are still fresh, loading them from the save where they're
+    /// found and generating the rest. Their nodes must already be populated.
+    ///
+    /// When reading in the background, chunks whose saved voxels haven't arrived are left to be
+    /// populated when they do, unless `wait`, in which case they're read now. Chunks in hibernating
+    /// regions are refused unless `wait`, which is reserved for chunks needed outright, as for a
+    /// spawn point or a teleport's destination.
+    pub(super) fn populate_chunks(
+        &mut self,
+        save: &save::Save,
+        chunks: impl IntoIterator<Item = ChunkId>,
+        wait: bool,
+    ) {
+        let inline = wait || !self.background_reads;
+        let (chunks_generated, chunks_loaded) = (self.chunks_generated, self.chunks_loaded);
+        for chunk in chunks {
+            if !wait && self.region_state(chunk.node) == RegionState::Hibernating {
+                trace!(?chunk, "refusing to populate chunk in hibernating region");
+                continue;
+            }
+            let state = self
+                .graph
+                .get_chunk(chunk)
+                .expect("all nodes must be populated before loading their chunks");
+            if matches!(state, Chunk::Fresh) || (inline && matches!(state, Chunk::Generating)) {
+                let hash = self.graph.hash_of(chunk.node);
+                if self.unread_nodes.remove(&hash) {
+                    if inline {
+                        self.read_saved_now(save, chunk.node, hash);
+                    } else {
+                        self.save_reads.push((chunk.node, hash));
+                        self.reading.insert(chunk.node);
+                    }
+                } else if inline && self.reading.remove(&chunk.node) {
+                    // Whatever the background read returns will now be ignored
+                    self.read_saved_now(save, chunk.node, hash);
+                    self.settle_node(chunk.node);
+                }
+                if !matches!(self.graph[chunk], Chunk::Fresh | Chunk::Generating) {
+                    continue;
+                }
+                if let Some(saved) = self.saved_chunks.remove(&chunk) {
+                    self.populate_saved(chunk, saved);
+                } else if self.reading.contains(&chunk.node) {
+                    // Held back until its saved voxels arrive, rather than generated only to be
+                    // replaced
+                    self.graph[chunk] = Chunk::Generating;
+                } else {
+                    self.generate_chunk(chunk);
+                }
+            }
+        }
+        if (chunks_generated, chunks_loaded) != (self.chunks_generated, self.chunks_loaded) {
+            trace!(
+                generated = self.chunks_generated - chunks_generated,
+                loaded = self.chunks_loaded - chunks_loaded,
+                total_generated = self.chunks_generated,
+                total_loaded = self.chunks_loaded,
+                "populated chunks"
+            );
+        }
+    }
+
+    /// Read the saved voxels of `node`, whose hash is `hash`, without waiting for the background
+    fn read_saved_now(&mut self, save: &save::Save, node: NodeId, hash: u128) {
+        let chunks = save.read().map_err(save::GetError::from).and_then(|guard| {
+            Ok(read_saved_chunks(
+                &mut guard.get()?,
+                hash,
+                self.cfg.chunk_size,
+            ))
+        });
+        match chunks {
+            Ok(chunks) => self.saved_chunks.extend(
+                chunks
+                    .into_iter()
+                    .map(|(vertex, saved)| (ChunkId::new(node, vertex), saved)),
+            ),
+            Err(e) => error!(error = %e, "couldn't read save"),
+        }
+    }
+
+    /// Populate `chunk` with voxels and block entities from the save, from whose edit generation
+    /// later edits count on
+    fn populate_saved(&mut self, chunk: ChunkId, saved: SavedChunk) {
+        let _span = chunk_span(chunk).entered();
+        self.graph.populate_chunk(chunk, saved.voxels, true);
+        self.edit_generations.insert(chunk, saved.edit_generation);
+        if !saved.block_entities.is_empty() {
+            // Regions encoded before now lack them
+            self.graph_regions.invalidate(&self.graph, chunk.node);
+        }
+        for (coords, entity) in saved.block_entities {
+            self.block_entities.insert(chunk, coords, entity.clone());
+            self.block_entity_changes
+                .push((chunk, coords, Some(entity)));
+        }
+        self.chunks_loaded += 1;
+        self.release_entities(chunk.node);
+    }
+
+    /// Populate `chunk` by world generation
+    fn generate_chunk(&mut self, chunk: ChunkId) {
+        let _span = chunk_span(chunk).entered();
+        if let Some(params) =
+            ChunkParams::new(self.cfg.chunk_size, &self.graph, chunk, &self.cfg.terrain)
+        {
+            self.graph
+                .populate_chunk(chunk, params.generate_voxels(), false);
+            self.chunks_generated += 1;
+        }
+        self.release_entities(chunk.node);
+    }
+
+    /// Spawn the saved entities that were waiting for `node` to be populated
+    fn release_entities(&mut self, node: NodeId) {
+        let Some(held) = self.held_entities.remove(&node) else {
+            return;
+        };
+        for (id, position, components) in held {
+            let entity = self.spawn_saved(id, position, components);
+            self.spawns.push(entity);
+        }
+    }
+
+    /// Read saved voxels in the background from now on, through `take_save_reads` and
+    /// `receive_saved`, rather than during `step`
+    pub fn read_save_in_background(&mut self) {
+        self.background_reads = true;
+    }
+
+    /// Nodes whose saved voxels should be read in the background, with their hashes, to be passed
+    /// to `receive_saved` once read
+    pub fn take_save_reads(&mut self) -> Vec<(NodeId, u128)> {
+        std::mem::take(&mut self.save_reads)
+    }
+
+    /// Accept the saved voxels of a node given out by `take_save_reads`
+    ///
+    /// Chunks already needed are populated at once, and the rest kept until they're needed or
+    /// there's time to spare for them.
+    pub fn receive_saved(&mut self, node: NodeId, chunks: Vec<(dodeca::Vertex, SavedChunk)>) {
+        // Nodes read in the meantime, or received twice, must not be populated again
+        if !self.reading.remove(&node) {
+            trace!(?node, "ignoring stale saved voxels");
+            return;
+        }
+        for (vertex, saved) in chunks {
+            let chunk = ChunkId::new(node, vertex);
+            if let Some(Chunk::Populated { .. }) = self.graph.get_chunk(chunk) {
+                error!(?chunk, "chunk populated before its saved voxels arrived");
+                continue;
+            }
+            self.saved_chunks.insert(chunk, saved);
+        }
+        self.settle_node(node);
+    }
+
+    /// Populate the chunks of `node` held back for its saved voxels, which have now been read
+    fn settle_node(&mut self, node: NodeId) {
+        for vertex in dodeca::Vertex::iter() {
+            let chunk = ChunkId::new(node, vertex);
+            if let Some(Chunk::Generating) = self.graph.get_chunk(chunk) {
+                match self.saved_chunks.remove(&chunk) {
+                    Some(saved) => self.populate_saved(chunk, saved),
+                    // Missing from the save, so waiting for nothing
+                    None => self.generate_chunk(chunk),
+                }
+            }
+        }
+    }
+
+    /// Populate up to `SAVED_CHUNK_BUDGET` chunks received from the save ahead of need
+    pub(super) fn apply_saved_chunks(&mut self) {
+        let ready = self
+            .saved_chunks
+            .keys()
+            .copied()
+            .filter(|&chunk| matches!(self.graph.get_chunk(chunk), Some(Chunk::Fresh)))
+            .take(SAVED_CHUNK_BUDGET)
+            .collect::<Vec<_>>();
+        for chunk in ready {
+            let saved = self.saved_chunks.remove(&chunk).unwrap();
+            self.populate_saved(chunk, saved);
+        }
+    }
+
+    /// Whether `chunk` has saved voxels being read or waiting to be populated
+    pub(super) fn awaiting_save(&self, chunk: ChunkId) -> bool {
+        self.reading.contains(&chunk.node) || self.saved_chunks.contains_key(&chunk)
+    }
+}
+
+/// A chunk's voxels as read from the save
+pub struct SavedChunk {
+    pub voxels: VoxelData,
+    /// Number of edits made to the chunk before it was saved
+    pub edit_generation: u32,
+    pub block_entities: Vec<(Coords, BlockEntity)>,
+}
+
+/// Fetch the saved voxels of the node whose hash is `node`, skipping any chunks that can't be
+/// decoded
+pub fn read_saved_chunks(
+    reader: &mut save::Reader<'_>,
+    node: u128,
+    dimension: u8,
+) -> Vec<(dodeca::Vertex, SavedChunk)> {
+    let record = match reader.get_voxel_node(node) {
+        Ok(x) => x,
+        Err(e) => {
+            error!(node, error = %e, "couldn't load voxels");
+            None
+        }
+    };
+    let Some(record) = record else {
+        return Vec::new();
+    };
+    record
+        .chunks
+        .iter()
+        .filter_map(|stored| {
+            let vertex = dodeca::Vertex::iter().nth(stored.vertex as usize);
+            let voxels = decode_voxels(&stored.voxels, dimension);
+            if vertex.is_none() || voxels.is_none() {
+                error!(node, vertex = stored.vertex, "stored voxels are malformed");
+            }
+            // The voxels are worth keeping without them
+            let block_entities = decode_block_entities(&stored.block_entities);
+            if block_entities.is_none() {
+                error!(
+                    node,
+                    vertex = stored.vertex,
+                    "stored block entities are malformed"
+                );
+            }
+            Some((
+                vertex?,
+                SavedChunk {
+                    voxels: voxels?,
+                    edit_generation: stored.edit_generation,
+                    block_entities: block_entities.unwrap_or_default(),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Encode a chunk's voxels for the save, as a single material tag if they're all the same and
+/// as a dense array of tags otherwise, followed by a byte per voxel for its shape unless every
+/// voxel is a full cube
+pub fn encode_voxels(voxels: &VoxelData, dimension: u8) -> Vec<u8> {
+    match *voxels {
+        VoxelData::Solid(material) => material.id().to_le_bytes().to_vec(),
+        VoxelData::Dense(_) | VoxelData::Shaped(..) => {
+            let serializable = voxels.to_serializable(dimension);
+            serializable
+                .voxels
+                .iter()
+                .flat_map(|&material| material.id().to_le_bytes())
+                .chain(serializable.shapes.iter().map(|&shape| u8::from(shape)))
+                .collect()
+        }
+    }
+}
+
+/// Inverse of `encode_voxels`. Returns `None` if `bytes` is malformed.
+pub fn decode_voxels(bytes: &[u8], dimension: u8) -> Option<VoxelData> {
+    let count = usize::from(dimension).pow(3);
+    let (tags, shapes) = if bytes.len() == 3 * count {
+        bytes.split_at(2 * count)
+    } else {
+        (bytes, &[][..])
+    };
+    if tags.len() % 2 != 0 {
+        return None;
+    }
+    // Materials this build doesn't know are kept as they are, to be written back unchanged
+    let mut materials = tags
+        .chunks_exact(2)
+        .map(|tag| Material::from_id(u16::from_le_bytes([tag[0], tag[1]])));
+    if tags.len() == 2 {
+        return Some(VoxelData::Solid(materials.next()?));
+    }
+    let voxels = materials.collect::<Vec<_>>();
+    let shapes = shapes
+        .iter()
+        .map(|&shape| Shape::try_from(shape).ok())
+        .collect::<Option<Vec<_>>>()?;
+    VoxelData::from_serializable(&SerializableVoxelData { voxels, shapes }, dimension)
+}
+
+/// Hashes of every node with voxels in `save`, read without the voxels themselves
+pub(super) fn index_saved_voxels(save: &save::Save) -> FxHashSet<u128> {
+    let ids = save
+        .read()
+        .map_err(save::GetError::from)
+        .and_then(|guard| guard.get()?.voxel_node_ids());
+    match ids {
+        Ok(ids) => {
+            info!(nodes = ids.len(), "indexed saved voxels");
+            ids.into_iter().collect()
+        }
+        Err(e) => {
+            error!(error = %e, "couldn't index saved voxels");
+            FxHashSet::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, time::Instant};
+
+    use common::{
+        graph::Graph,
+        node::{CoordAxis, CoordDirection},
+        node_path::NodePath,
+        proto::{BlockUpdate, ClientHello},
+        traversal::ensure_nearby,
+        worldgen::TerrainPassKind,
+        SimConfig, SimConfigRaw,
+    };
+
+    use crate::{
+        sim::fixtures::{chunks_of, populate_nodes, stand_at_origin, step_with_requests},
+        stats::TickTimes,
+    };
+
+    #[test]
+    fn pregenerated_chunks_are_loaded() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut save = save::Save::open(file.path(), 12).unwrap();
+        crate::pregenerate::pregenerate(&mut save, &TerrainPassKind::DEFAULT, 2.2, 16).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(45.0),
+            // Characters spawn at the origin
+            spawn_distance: Some(0),
+            ..Default::default()
+        }));
+
+        let mut sim = Sim::new(cfg, &save);
+        let (_, entity) = sim.spawn_character(ClientHello::new("test"));
+        *sim.world.get::<&mut Position>(entity).unwrap() = Position::origin();
+        sim.step(&save);
+
+        assert_eq!(sim.chunks_generated, 0);
+        assert!(sim.chunks_loaded > 0);
+        let populated = nearby_nodes(&sim.graph, &Position::origin(), 4.0)
+            .into_iter()
+            .flat_map(|(node, _)| {
+                dodeca::Vertex::iter().map(move |vertex| ChunkId::new(node, vertex))
+            })
+            .filter(|&chunk| matches!(sim.graph.get_chunk(chunk), Some(Chunk::Populated { .. })))
+            .count() as u64;
+        assert_eq!(populated, sim.chunks_loaded);
+    }
+
+    #[test]
+    fn shaped_voxels_round_trip() {
+        let dimension = 4;
+        let mut voxels = VoxelData::Solid(Material::Void);
+        voxels.data_mut(dimension)[Coords([1, 2, 3]).to_index(dimension)] = Material::Dirt;
+        let dense = encode_voxels(&voxels, dimension);
+        assert_eq!(dense.len(), 2 * usize::from(dimension).pow(3));
+
+        let stairs = Shape::stairs(
+            (CoordAxis::Y, CoordDirection::Plus),
+            (CoordAxis::X, CoordDirection::Minus),
+        );
+        voxels.set_shape(dimension, Coords([1, 2, 3]).to_index(dimension), stairs);
+        let shaped = encode_voxels(&voxels, dimension);
+        assert_eq!(shaped.len(), 3 * usize::from(dimension).pow(3));
+        let decoded = decode_voxels(&shaped, dimension).unwrap();
+        assert_eq!(decoded.view(dimension).shape(Coords([1, 2, 3])), stairs);
+        assert_eq!(encode_voxels(&decoded, dimension), shaped);
+
+        // Shapes that aren't slabs or stairs are rejected
+        let mut corrupt = shaped;
+        *corrupt.last_mut().unwrap() = 0b0000_0001;
+        assert!(decode_voxels(&corrupt, dimension).is_none());
+    }
+
+    #[test]
+    fn unknown_materials_round_trip() {
+        let dimension = 4;
+        // As written by a newer build that knows more materials
+        let unknown = Material::from_id(Material::COUNT as u16 + 3);
+        let solid = encode_voxels(&VoxelData::Solid(unknown), dimension);
+        assert!(matches!(
+            decode_voxels(&solid, dimension),
+            Some(VoxelData::Solid(material)) if material == unknown
+        ));
+
+        let mut voxels = VoxelData::Solid(Material::Dirt);
+        voxels.data_mut(dimension)[Coords([0, 1, 2]).to_index(dimension)] = unknown;
+        let dense = encode_voxels(&voxels, dimension);
+        let decoded = decode_voxels(&dense, dimension).unwrap();
+        assert_eq!(decoded.view(dimension).get(Coords([0, 1, 2])), unknown);
+        assert_eq!(encode_voxels(&decoded, dimension), dense);
+    }
+
+    /// A save with voxels for every chunk of the `count` nodes nearest the origin, all `material`,
+    /// and those nodes
+    fn populous_save(
+        file: &tempfile::NamedTempFile,
+        count: usize,
+        material: Material,
+    ) -> (save::Save, Vec<NodePath>) {
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let mut graph = Graph::new(12);
+        let mut radius = 2.0;
+        let nodes = loop {
+            ensure_nearby(&mut graph, &Position::origin(), radius);
+            let nodes = nearby_nodes(&graph, &Position::origin(), radius);
+            if nodes.len() >= count {
+                break nodes;
+            }
+            radius += 0.5;
+        };
+        let mut batch = save::Batch::new();
+        let voxels = encode_voxels(&VoxelData::Solid(material), 12);
+        for &(node, _) in &nodes[..count] {
+            for vertex in dodeca::Vertex::iter() {
+                batch.put_chunk(
+                    graph.hash_of(node),
+                    save::Chunk {
+                        vertex: vertex as u32,
+                        voxels: voxels.clone(),
+                        edit_generation: 0,
+                        block_entities: Vec::new(),
+                    },
+                );
+            }
+        }
+        save.apply(&batch).unwrap();
+        let paths = nodes[..count]
+            .iter()
+            .map(|&(node, _)| NodePath::to(&graph, node))
+            .collect();
+        (save, paths)
+    }
+
+    /// Perform the reads `sim` asks for, as the server's loader would, returning what was read
+    fn read_requested(
+        sim: &mut Sim,
+        save: &save::Save,
+    ) -> Vec<(NodeId, Vec<(dodeca::Vertex, SavedChunk)>)> {
+        let guard = save.read().unwrap();
+        let mut reader = guard.get().unwrap();
+        sim.take_save_reads()
+            .into_iter()
+            .map(|(node, hash)| {
+                (
+                    node,
+                    read_saved_chunks(&mut reader, hash, sim.cfg.chunk_size),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn large_save_joined_without_waiting() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        // Ten thousand chunks
+        let (save, _) = populous_save(&file, 500, Material::Stone);
+        let mut cfg = SimConfig::from_raw(&SimConfigRaw {
+            spawn_distance: Some(0),
+            ..Default::default()
+        });
+        // Far enough to take in hundreds of saved nodes
+        cfg.view_distance = 2.0;
+        let cfg = Arc::new(cfg);
+        let mut sim = Sim::new(cfg.clone(), &save);
+        assert_eq!(sim.unread_nodes.len(), 500);
+        sim.read_save_in_background();
+        stand_at_origin(&mut sim);
+
+        let mut ticks = TickTimes::default();
+        let started = Instant::now();
+        sim.step(&save);
+        ticks.record(started.elapsed());
+        // Nothing was read during the step, not even the chunks the character stands in
+        assert_eq!(sim.chunks_loaded, 0);
+        let origin = ChunkId::new(NodeId::ROOT, dodeca::Vertex::A);
+        let reads = read_requested(&mut sim, &save);
+        assert!(reads.iter().any(|&(node, _)| node == NodeId::ROOT));
+
+        // The chunks around the character arrive, and the rest are populated a little at a time
+        for (node, chunks) in reads {
+            sim.receive_saved(node, chunks);
+        }
+        assert!(matches!(
+            sim.graph.get_chunk(origin),
+            Some(Chunk::Populated {
+                voxels: VoxelData::Solid(Material::Stone),
+                ..
+            })
+        ));
+        for _ in 0..10 {
+            let loaded = sim.chunks_loaded;
+            let started = Instant::now();
+            sim.step(&save);
+            ticks.record(started.elapsed());
+            assert!(sim.chunks_loaded - loaded <= SAVED_CHUNK_BUDGET as u64);
+            for (node, chunks) in read_requested(&mut sim, &save) {
+                sim.receive_saved(node, chunks);
+            }
+        }
+        assert_eq!(sim.chunks_generated, 0);
+        let ticks = ticks.summarize();
+        assert!(
+            ticks.max_ms < cfg.step_interval.as_secs_f64() * 1e3,
+            "slowest step took {} ms",
+            ticks.max_ms
+        );
+    }
+
+    /// Voxels of `chunk`, for comparison
+    fn voxels_of(sim: &Sim, chunk: ChunkId) -> Vec<(Option<Material>, Option<Shape>)> {
+        Coords::all(sim.cfg.chunk_size)
+            .map(|coords| {
+                (
+                    sim.graph.get_block(chunk, coords),
+                    sim.graph.get_shape(chunk, coords),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edits_wait_for_saved_voxels() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let (save, _) = populous_save(&file, 1, Material::Dirt);
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            spawn_distance: Some(0),
+            ..Default::default()
+        }));
+        let chunk_id = ChunkId::new(NodeId::ROOT, dodeca::Vertex::A);
+        let breaking = |sequence| {
+            Some(BlockUpdate {
+                chunk_id,
+                coords: Coords([0, 0, 0]),
+                new_material: Material::Void,
+                new_shape: Shape::FULL,
+                sequence,
+            })
+        };
+
+        // Reading as the chunks are needed
+        let mut eager = Sim::new(cfg.clone(), &save);
+        let a = stand_at_origin(&mut eager);
+        eager.step(&save);
+        let (spawns, _) = step_with_requests(&mut eager, &save, &[(a, breaking(1))]);
+        assert_eq!(spawns.block_updates.len(), 1);
+
+        // Reading in the background, with the edit arriving first
+        let mut lazy = Sim::new(cfg, &save);
+        lazy.read_save_in_background();
+        let a = stand_at_origin(&mut lazy);
+        lazy.step(&save);
+        assert!(matches!(
+            lazy.graph.get_chunk(chunk_id),
+            Some(Chunk::Generating)
+        ));
+        let reads = read_requested(&mut lazy, &save);
+        let (spawns, _) = step_with_requests(&mut lazy, &save, &[(a, breaking(1))]);
+        assert!(spawns.block_updates.is_empty());
+        assert!(lazy.take_rejected_block_updates().is_empty());
+        assert_eq!(lazy.deferred_block_updates.len(), 1);
+        for (node, chunks) in reads {
+            lazy.receive_saved(node, chunks);
+        }
+        let (spawns, _, _) = lazy.step(&save);
+        assert_eq!(spawns.block_updates.len(), 1);
+        assert!(lazy.deferred_block_updates.is_empty());
+
+        assert_eq!(voxels_of(&lazy, chunk_id), voxels_of(&eager, chunk_id));
+        assert_eq!(
+            lazy.graph.get_block(chunk_id, Coords([0, 0, 0])),
+            Some(Material::Void)
+        );
+        assert_eq!(
+            lazy.graph.get_block(chunk_id, Coords([1, 0, 0])),
+            Some(Material::Dirt)
+        );
+        // Recorded as changed, to be saved like any other edit
+        assert!(lazy.dirty_chunks.contains(&chunk_id));
+    }
+
+    #[test]
+    fn saved_voxels_populate_each_chunk_once() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let (save, paths) = populous_save(&file, 40, Material::Dirt);
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(20.0),
+            spawn_distance: Some(0),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg, &save);
+        sim.read_save_in_background();
+        stand_at_origin(&mut sim);
+        sim.step(&save);
+        let mut reads = read_requested(&mut sim, &save);
+        assert!(reads.len() >= 3, "only {} reads", reads.len());
+        let saved = paths
+            .iter()
+            .map(|path| path.ensure(&mut sim.graph))
+            .collect::<FxHashSet<_>>();
+        assert!(reads.iter().all(|(node, _)| saved.contains(node)));
+        let origin = reads
+            .iter()
+            .position(|&(node, _)| node == NodeId::ROOT)
+            .unwrap();
+        let (root, root_chunks) = reads.remove(origin);
+        let chunk_id = ChunkId::new(root, dodeca::Vertex::A);
+
+        // Needed at once, so read again without waiting, then edited before the first read lands
+        populate_nodes(&mut sim, &save, &[root]);
+        let loaded = sim.chunks_loaded;
+        sim.graph.update_block(&BlockUpdate {
+            chunk_id,
+            coords: Coords([0, 0, 0]),
+            new_material: Material::Void,
+            new_shape: Shape::FULL,
+            sequence: 0,
+        });
+        sim.receive_saved(root, root_chunks);
+        assert_eq!(sim.chunks_loaded, loaded);
+        assert_eq!(
+            sim.graph.get_block(chunk_id, Coords([0, 0, 0])),
+            Some(Material::Void)
+        );
+
+        // Reads finishing out of order, one of them twice
+        let (last, last_chunks) = reads.pop().unwrap();
+        let duplicate = read_saved_chunks(
+            &mut save.read().unwrap().get().unwrap(),
+            sim.graph.hash_of(last),
+            sim.cfg.chunk_size,
+        );
+        sim.receive_saved(last, last_chunks);
+        for (node, chunks) in reads.into_iter().rev() {
+            sim.receive_saved(node, chunks);
+        }
+        let loaded = sim.chunks_loaded;
+        let pending = sim.saved_chunks.len();
+        sim.receive_saved(last, duplicate);
+        assert_eq!(
+            (sim.chunks_loaded, sim.saved_chunks.len()),
+            (loaded, pending)
+        );
+
+        // Everything read ends up populated from the save exactly once, and nothing saved is
+        // generated
+        for _ in 0..20 {
+            sim.step(&save);
+            for (node, chunks) in read_requested(&mut sim, &save) {
+                sim.receive_saved(node, chunks);
+            }
+        }
+        let populated = saved
+            .iter()
+            .flat_map(|&node| chunks_of(node))
+            .filter(|&chunk| {
+                matches!(
+                    sim.graph.get_chunk(chunk),
+                    Some(Chunk::Populated { modified: true, .. })
+                )
+            })
+            .count() as u64;
+        assert_eq!(populated, sim.chunks_loaded);
+        assert!(sim.reading.is_empty());
+        for &node in &saved {
+            for chunk in chunks_of(node) {
+                assert!(!matches!(
+                    sim.graph.get_chunk(chunk),
+                    Some(Chunk::Populated {
+                        modified: false,
+                        ..
+                    }) | Some(Chunk::Generating)
+                ));
+            }
+        }
+    }
+}
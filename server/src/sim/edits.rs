@@ -0,0 +1,1228 @@
+//! Block updates and fills requested by characters, and the history kept to roll them back
+
+use std::{fmt, time::Duration};
+
+use fxhash::FxHashMap;
+use hecs::Entity;
+use tracing::{error, trace, warn};
+
+use common::{
+    coords::voxel_center_position,
+    dodeca,
+    graph::Graph,
+    math,
+    node::{BlockUpdateOutcome, Chunk, ChunkId, Coords},
+    placement,
+    proto::{
+        BlockFill, BlockUpdate, BlockUpdateRejection, Character, Position, RejectionReason,
+        Rollback, RollbackScope, SoundEvent, SoundKind, SoundSource,
+    },
+    reach,
+    region::RegionCoverage,
+    traversal::nearby_nodes,
+    world::{Material, Shape},
+    worldgen::ChunkParams,
+    SimConfig,
+};
+
+use super::{steps_in, Sim};
+use crate::edit_history::{self, EditedChunk, Selection, Voxel};
+
+impl Sim {
+    /// Let `entity` fill boxes of up to `limit` voxels at once
+    pub fn set_fill_limit(
+        &mut self,
+        entity: Entity,
+        limit: u64,
+    ) -> Result<(), hecs::ComponentError> {
+        self.world.get::<&mut FillLimit>(entity)?.0 = limit;
+        Ok(())
+    }
+
+    /// Let `entity` place blocks without paying for them from its inventory, or make it pay again
+    pub fn set_creative(
+        &mut self,
+        entity: Entity,
+        creative: bool,
+    ) -> Result<(), hecs::NoSuchEntity> {
+        if creative {
+            return self.world.insert_one(entity, Creative);
+        }
+        match self.world.remove_one::<Creative>(entity) {
+            Ok(Creative) | Err(hecs::ComponentError::MissingComponent(_)) => Ok(()),
+            Err(hecs::ComponentError::NoSuchEntity) => Err(hecs::NoSuchEntity),
+        }
+    }
+
+    /// Remember at most `records` edits, for at most `age`, forgetting any beyond those limits
+    pub fn set_edit_history_limits(&mut self, records: usize, age: Duration) {
+        self.edit_history
+            .set_limits(records, steps_in(&self.cfg, age), self.step);
+    }
+
+    /// Undo the edits `rollback` selects on behalf of the administrator called `admin`, returning
+    /// the number of blocks changed
+    ///
+    /// Blocks in chunks that aren't populated are left alone.
+    pub fn rollback(&mut self, admin: &str, rollback: &Rollback) -> Result<usize, RollbackError> {
+        let duration = steps_in(&self.cfg, Duration::from_secs(rollback.seconds.into()));
+        // Transforms into the center node's coordinates from those of nearby nodes, the center,
+        // and the radius in absolute units
+        let mut region = None;
+        let mut chunks = Vec::new();
+        let selection = match rollback.scope {
+            RollbackScope::Player(ref name) => Selection::Player(name),
+            RollbackScope::Region {
+                ref path,
+                local_translation,
+                radius,
+            } => {
+                let radius = radius * self.cfg.meters_to_absolute;
+                if !(0.0..=self.cfg.view_distance).contains(&radius) {
+                    return Err(RollbackError::Radius);
+                }
+                let center = Position {
+                    node: path.ensure(&mut self.graph),
+                    local: math::translate_along(&local_translation),
+                };
+                // Every voxel lies within the bounding sphere of its node
+                let nodes = nearby_nodes(
+                    &self.graph,
+                    &center,
+                    f64::from(radius) + dodeca::BOUNDING_SPHERE_RADIUS_F64,
+                )
+                .into_iter()
+                .collect::<FxHashMap<_, _>>();
+                for &node in nodes.keys() {
+                    let hash = self.graph.hash_of(node);
+                    chunks.extend(
+                        dodeca::Vertex::iter().map(|vertex| EditedChunk { node: hash, vertex }),
+                    );
+                }
+                region = Some((nodes, center.local * math::origin(), radius));
+                Selection::Chunks(&chunks)
+            }
+        };
+        let reverts = self.edit_history.reverts(
+            self.step,
+            duration,
+            selection,
+            rollback.force,
+            |chunk, coords| {
+                let node = self.graph.node_with_hash(chunk.node)?;
+                let chunk = ChunkId::new(node, chunk.vertex);
+                if self.awaiting_save(chunk) {
+                    return None;
+                }
+                if let Some((nodes, center, radius)) = &region {
+                    let voxel = voxel_center_position(self.graph.layout(), chunk, coords);
+                    let point = nodes.get(&voxel.node)? * voxel.local * math::origin();
+                    if math::distance(center, &point) > *radius {
+                        return None;
+                    }
+                }
+                Some((
+                    self.graph.get_block(chunk, coords)?,
+                    self.graph.get_shape(chunk, coords)?,
+                ))
+            },
+        );
+        for revert in &reverts {
+            let node = self.graph.node_with_hash(revert.chunk.node).unwrap();
+            let block_update = BlockUpdate {
+                chunk_id: ChunkId::new(node, revert.chunk.vertex),
+                coords: revert.coords,
+                new_material: revert.voxel.0,
+                new_shape: revert.voxel.1,
+                // Unused, being authored by nobody
+                sequence: 0,
+            };
+            let previous = self.apply_block_update(&block_update);
+            // Recorded like any other edit, so that a mistaken rollback can itself be undone
+            self.edit_history.record(
+                self.step,
+                admin,
+                revert.chunk,
+                revert.coords,
+                previous,
+                revert.voxel,
+            );
+            self.server_block_updates.push(block_update);
+        }
+        Ok(reverts.len())
+    }
+
+    /// Check that `block_update`, made on behalf of the player called `author`, may change the
+    /// world, returning the material it replaces
+    ///
+    /// Placements mustn't trap any of the characters at `characters`. Whether the author can
+    /// afford the change is left to the caller.
+    pub(super) fn check_block_update(
+        &self,
+        block_update: &BlockUpdate,
+        author: &str,
+        characters: &[Position],
+    ) -> Result<Material, RejectionReason> {
+        let (chunk, coords) = (block_update.chunk_id, block_update.coords);
+        let Some(old_material) = self.graph.get_block(chunk, coords) else {
+            tracing::warn!("Block update received from ungenerated chunk");
+            return Err(RejectionReason::Refused);
+        };
+        // Materials this build doesn't know are carried through unchanged, but never placed
+        if !block_update.new_material.is_known() {
+            trace!(
+                ?block_update,
+                "rejected block update placing an unknown material"
+            );
+            return Err(RejectionReason::Refused);
+        }
+        if let Some(region) = self
+            .protected_regions
+            .protecting(&self.graph, chunk, coords, author)
+        {
+            trace!(?block_update, region = %region.name, "rejected block update in protected region");
+            return Err(RejectionReason::Protected(region.name.clone()));
+        }
+        // Characters that can't pass through blocks would be trapped
+        if characters.iter().any(|position| {
+            placement::obstructs(
+                &self.graph,
+                block_update,
+                position,
+                self.cfg.character.character_radius,
+            )
+        }) {
+            trace!(
+                ?block_update,
+                "rejected block update overlapping a character"
+            );
+            return Err(RejectionReason::Refused);
+        }
+        Ok(old_material)
+    }
+
+    /// Check that the character `entity` may make `block_fill`, returning each distinct voxel it
+    /// covers with what's there now and what it's to become
+    ///
+    /// As for block updates, placements mustn't trap any of the characters at `characters`, and
+    /// whether the fill can be afforded is left to the caller. Fills reaching chunks that aren't
+    /// populated, or whose saved voxels haven't arrived, are held until they are.
+    pub(super) fn check_block_fill(
+        &self,
+        entity: Entity,
+        block_fill: &BlockFill,
+        characters: &[Position],
+    ) -> Result<Vec<(ChunkId, Coords, Voxel, Voxel)>, FillHeld> {
+        let limit = self.world.get::<&FillLimit>(entity).unwrap().0;
+        // Checked first, so that huge fills cost nothing to refuse
+        if block_fill.volume() > limit {
+            trace!(volume = block_fill.volume(), limit, "block fill too large");
+            return Err(RejectionReason::Refused.into());
+        }
+        if !block_fill.is_consistent() {
+            trace!("block fill extents don't match its template");
+            return Err(RejectionReason::Refused.into());
+        }
+        let anchor = (block_fill.chunk_id, block_fill.coords);
+        let user = *self.world.get::<&Position>(entity).unwrap();
+        if let Err(e) = reach::block_in_reach(&self.cfg, &self.graph, &user, anchor.0, anchor.1) {
+            trace!(reason = ?e, "block fill out of reach");
+            return Err(RejectionReason::Refused.into());
+        }
+
+        let mut voxels = Vec::new();
+        let mut waiting = Vec::new();
+        let coverage = block_fill.plan(&self.graph, |chunk, coords, old, new| match old {
+            // Saved voxels would replace the fill's once they arrive
+            Some(old) if !self.awaiting_save(chunk) => voxels.push((chunk, coords, old, new)),
+            _ => {
+                if !waiting.contains(&chunk) {
+                    waiting.push(chunk);
+                }
+            }
+        });
+        if coverage == RegionCoverage::Partial {
+            trace!("block fill reaches beyond the graph");
+            return Err(RejectionReason::Refused.into());
+        }
+
+        let character = self.world.get::<&Character>(entity).unwrap();
+        for &(chunk, coords, ..) in &voxels {
+            if let Some(region) =
+                self.protected_regions
+                    .protecting(&self.graph, chunk, coords, &character.name)
+            {
+                trace!(region = %region.name, "block fill in protected region");
+                return Err(RejectionReason::Protected(region.name.clone()).into());
+            }
+        }
+        if !waiting.is_empty() {
+            return Err(FillHeld::Waiting(waiting));
+        }
+        for &(chunk, coords, _, (new_material, new_shape)) in &voxels {
+            let block_update = BlockUpdate {
+                chunk_id: chunk,
+                coords,
+                new_material,
+                new_shape,
+                sequence: block_fill.sequence,
+            };
+            if characters.iter().any(|position| {
+                placement::obstructs(
+                    &self.graph,
+                    &block_update,
+                    position,
+                    self.cfg.character.character_radius,
+                )
+            }) {
+                trace!("block fill overlapping a character");
+                return Err(RejectionReason::Refused.into());
+            }
+        }
+        if voxels.iter().any(|&(.., (new, _))| !new.is_known()) {
+            trace!("block fill placing an unknown material");
+            return Err(RejectionReason::Refused.into());
+        }
+        if voxels.iter().all(|&(_, _, old, new)| old == new) {
+            // Nothing to do, e.g. because someone else already filled the box
+            return Err(RejectionReason::Refused.into());
+        }
+        Ok(voxels)
+    }
+
+    /// Fill blocks as `block_fill` says, which must already have been judged acceptable, given
+    /// what was in each voxel before and what it becomes as found by `check_block_fill`
+    pub(super) fn apply_block_fill(
+        &mut self,
+        author: Entity,
+        block_fill: &BlockFill,
+        voxels: &[(ChunkId, Coords, Voxel, Voxel)],
+    ) {
+        for &(chunk, _, old, new) in voxels {
+            if old != new && !self.modified_chunks.contains_key(&chunk) {
+                // The chunk may have been changed before it was last saved
+                let changes = diff_from_worldgen(&self.cfg, &self.graph, chunk);
+                self.modified_chunks.insert(chunk, changes);
+            }
+        }
+        let edit = block_fill.apply(&mut self.graph);
+        debug_assert_eq!(edit.coverage, RegionCoverage::Complete);
+
+        let name = self.world.get::<&Character>(author).unwrap().name.clone();
+        for &(chunk, coords, old, new) in voxels {
+            if old == new {
+                continue;
+            }
+            if let Some(Some(changes)) = self.modified_chunks.get_mut(&chunk) {
+                changes.insert(coords, new);
+            }
+            self.prune_block_entity(chunk, coords);
+            let block_update = BlockUpdate {
+                chunk_id: chunk,
+                coords,
+                new_material: new.0,
+                new_shape: new.1,
+                sequence: block_fill.sequence,
+            };
+            self.record_edit_by(&name, &block_update, old);
+        }
+        for &chunk in &edit.changed {
+            self.dirty_chunks.insert(chunk);
+            let edit_generation = self.edit_generations.entry(chunk).or_default();
+            *edit_generation = edit_generation.wrapping_add(1);
+            self.graph_regions.invalidate(&self.graph, chunk.node);
+        }
+        // One sound for the whole fill, rather than a din of every block
+        let anchor = (block_fill.chunk_id, block_fill.coords);
+        self.sounds.push(SoundEvent {
+            kind: if block_fill.contents.is_void() {
+                SoundKind::BlockBroken
+            } else {
+                SoundKind::BlockPlaced
+            },
+            source: SoundSource::Voxel(anchor.0, anchor.1),
+            intensity: 1.0,
+            step: self.step,
+        });
+    }
+
+    /// Ask for `chunks` to be populated, as they would be if a character came near, along with
+    /// their nodes
+    pub(super) fn request_chunks(&mut self, save: &save::Save, chunks: Vec<ChunkId>) {
+        let nodes = chunks.iter().map(|chunk| chunk.node).collect::<Vec<_>>();
+        self.population.run(&mut self.graph, nodes, 0);
+        self.populate_chunks(save, chunks, false);
+    }
+
+    /// Change a block as `block_update` says, which must already have been judged acceptable,
+    /// returning what it was before
+    pub(super) fn apply_block_update(&mut self, block_update: &BlockUpdate) -> Voxel {
+        let (chunk, coords) = (block_update.chunk_id, block_update.coords);
+        if !self.modified_chunks.contains_key(&chunk) {
+            // The chunk may have been changed before it was last saved
+            let changes = diff_from_worldgen(&self.cfg, &self.graph, chunk);
+            self.modified_chunks.insert(chunk, changes);
+        }
+        let BlockUpdateOutcome::Applied {
+            previous,
+            previous_shape,
+        } = self.graph.update_block(block_update)
+        else {
+            panic!("accepted block update had no effect");
+        };
+        if let Some(Some(changes)) = self.modified_chunks.get_mut(&chunk) {
+            // Empty voxels are recorded as full cubes, whatever shape was requested
+            let voxel = (
+                self.graph.get_block(chunk, coords).unwrap(),
+                self.graph.get_shape(chunk, coords).unwrap(),
+            );
+            changes.insert(coords, voxel);
+        }
+        self.prune_block_entity(chunk, coords);
+        self.dirty_chunks.insert(chunk);
+        let edit_generation = self.edit_generations.entry(chunk).or_default();
+        *edit_generation = edit_generation.wrapping_add(1);
+        self.graph_regions.invalidate(&self.graph, chunk.node);
+        self.sounds.push(SoundEvent {
+            kind: SoundKind::of_block_update(block_update),
+            source: SoundSource::Voxel(chunk, coords),
+            intensity: 1.0,
+            step: self.step,
+        });
+        (previous, previous_shape)
+    }
+
+    /// Remember that the character `author` made `block_update`, which changed the block from
+    /// `previous`
+    pub(super) fn record_edit(
+        &mut self,
+        author: Entity,
+        block_update: &BlockUpdate,
+        previous: Voxel,
+    ) {
+        let name = self.world.get::<&Character>(author).unwrap().name.clone();
+        self.record_edit_by(&name, block_update, previous);
+    }
+
+    /// Remember that the player called `author` made `block_update`, which changed the block from
+    /// `previous`
+    pub(super) fn record_edit_by(
+        &mut self,
+        author: &str,
+        block_update: &BlockUpdate,
+        previous: Voxel,
+    ) {
+        let (chunk, coords) = (block_update.chunk_id, block_update.coords);
+        self.edit_history.record(
+            self.step,
+            author,
+            EditedChunk {
+                node: self.graph.hash_of(chunk.node),
+                vertex: chunk.vertex,
+            },
+            coords,
+            previous,
+            // Empty voxels are recorded as full cubes, whatever shape was requested
+            (
+                self.graph.get_block(chunk, coords).unwrap(),
+                self.graph.get_shape(chunk, coords).unwrap(),
+            ),
+        );
+    }
+
+    pub(super) fn reject_block_update(
+        &mut self,
+        entity: Entity,
+        sequence: u32,
+        reason: RejectionReason,
+    ) {
+        self.rejected_block_updates
+            .push((entity, BlockUpdateRejection { sequence, reason }));
+    }
+
+    /// Block updates refused since the last call, with the characters that requested them
+    pub fn take_rejected_block_updates(&mut self) -> Vec<(Entity, BlockUpdateRejection)> {
+        std::mem::take(&mut self.rejected_block_updates)
+    }
+}
+
+/// Why edits couldn't be rolled back
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RollbackError {
+    /// The region's radius is negative or beyond the view distance
+    Radius,
+}
+
+impl fmt::Display for RollbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match *self {
+            RollbackError::Radius => "radius is negative or beyond the view distance",
+        })
+    }
+}
+
+impl std::error::Error for RollbackError {}
+
+/// Most voxels a character may fill at once
+#[derive(Debug, Copy, Clone)]
+pub(super) struct FillLimit(pub(super) u64);
+
+/// Marks characters that place blocks without paying for them from their inventories
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Creative;
+
+/// Why a block fill wasn't made in a step
+#[derive(Debug)]
+pub(super) enum FillHeld {
+    Rejected(RejectionReason),
+    /// These chunks it reaches must be populated first
+    Waiting(Vec<ChunkId>),
+}
+
+impl From<RejectionReason> for FillHeld {
+    fn from(reason: RejectionReason) -> Self {
+        FillHeld::Rejected(reason)
+    }
+}
+
+/// Voxels of a populated chunk that differ from what world generation produces for it, or `None`
+/// if it can't be generated
+fn diff_from_worldgen(
+    cfg: &SimConfig,
+    graph: &Graph,
+    chunk: ChunkId,
+) -> Option<FxHashMap<Coords, (Material, Shape)>> {
+    let Some(Chunk::Populated { voxels, .. }) = graph.get_chunk(chunk) else {
+        return None;
+    };
+    let baseline = ChunkParams::new(cfg.chunk_size, graph, chunk, &cfg.terrain)?.generate_voxels();
+    let dimension = cfg.chunk_size;
+    let mut changes = FxHashMap::default();
+    let (voxels, baseline) = (voxels.view(dimension), baseline.view(dimension));
+    for coords in Coords::all(dimension) {
+        let voxel = (voxels.get(coords), voxels.shape(coords));
+        if voxel != (baseline.get(coords), baseline.shape(coords)) {
+            changes.insert(coords, voxel);
+        }
+    }
+    Some(changes)
+}
+
+/// Edits remembered in `save`, in order of sequence number
+pub(super) fn load_edits(save: &save::Save) -> Vec<(u64, edit_history::Edit)> {
+    let stored = save
+        .read()
+        .map_err(save::GetError::from)
+        .and_then(|guard| guard.get()?.get_edits());
+    let stored = match stored {
+        Ok(x) => x,
+        Err(e) => {
+            error!(error = %e, "couldn't load edit history");
+            return Vec::new();
+        }
+    };
+    stored
+        .into_iter()
+        .filter_map(|(sequence, stored)| {
+            let edit = edit_history::decode_edit(stored);
+            if edit.is_none() {
+                warn!(sequence, "ignoring malformed edit");
+            }
+            Some((sequence, edit?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use common::{
+        graph::NodeId,
+        inventory::Inventory,
+        node::{CoordAxis, CoordDirection},
+        node_path::NodePath,
+        protection::ProtectedRegion,
+        proto::{CharacterInput, ClientHello, FillContents, InteractTarget, Spawns},
+        template::{Orientation, Template},
+        EntityId, SimConfigRaw,
+    };
+
+    use crate::sim::{
+        chunks::encode_voxels,
+        fixtures::{
+            alone_in_the_void, block_ahead, place_ahead, request, step_with_requests, use_now,
+        },
+    };
+
+    #[test]
+    fn blocks_are_traded_with_inventory() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            inventory_capacity: Some(1),
+            // Characters spawn at the origin
+            spawn_distance: Some(0),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg, &save);
+        let (_, a) = sim.spawn_character(ClientHello::new("a"));
+        let (_, b) = sim.spawn_character(ClientHello::new("b"));
+        sim.step(&save);
+
+        let chunk_id = ChunkId::new(NodeId::ROOT, dodeca::Vertex::A);
+        let mut sequence = 0;
+        let mut block = |coords: [u8; 3], new_material| {
+            sequence += 1;
+            BlockUpdate {
+                chunk_id,
+                coords: Coords(coords),
+                new_material,
+                new_shape: Shape::FULL,
+                sequence,
+            }
+        };
+        for coords in [[0, 0, 0], [1, 0, 0]] {
+            assert!(matches!(
+                sim.graph.update_block(&block(coords, Material::Dirt)),
+                BlockUpdateOutcome::Applied { .. }
+            ));
+        }
+        let held = |sim: &Sim, entity: Entity| {
+            sim.world
+                .get::<&Inventory>(entity)
+                .unwrap()
+                .count(Material::Dirt)
+        };
+
+        // Both characters break the same block, but only the first is credited
+        let breaking = block([0, 0, 0], Material::Void);
+        let sequence = breaking.sequence;
+        let (spawns, inventories) = step_with_requests(
+            &mut sim,
+            &save,
+            &[(a, Some(breaking.clone())), (b, Some(breaking))],
+        );
+        assert_eq!(spawns.block_updates.len(), 1);
+        assert_eq!(spawns.block_updates[0].1.sequence, sequence);
+        assert_eq!(
+            sim.take_rejected_block_updates(),
+            [(
+                b,
+                BlockUpdateRejection {
+                    sequence,
+                    reason: RejectionReason::Refused
+                }
+            )]
+        );
+        assert_eq!(inventories.len(), 1);
+        assert_eq!(inventories[0].0, a);
+        assert_eq!(inventories[0].1.count(Material::Dirt), 1);
+        assert_eq!((held(&sim, a), held(&sim, b)), (1, 0));
+
+        // The inventory is full, so the other block can't be broken
+        let (spawns, inventories) = step_with_requests(
+            &mut sim,
+            &save,
+            &[(a, Some(block([1, 0, 0], Material::Void)))],
+        );
+        assert!(spawns.block_updates.is_empty());
+        assert!(inventories.is_empty());
+        assert_eq!(
+            sim.graph.get_block(chunk_id, Coords([1, 0, 0])),
+            Some(Material::Dirt)
+        );
+
+        // Placing requires the material
+        let placing = Some(block([0, 0, 0], Material::Dirt));
+        let (spawns, _) = step_with_requests(&mut sim, &save, &[(b, placing.clone())]);
+        assert!(spawns.block_updates.is_empty());
+        let (spawns, _) = step_with_requests(
+            &mut sim,
+            &save,
+            &[(a, Some(block([0, 0, 0], Material::WoodPlanks)))],
+        );
+        assert!(spawns.block_updates.is_empty());
+        assert_eq!(
+            sim.graph.get_block(chunk_id, Coords([0, 0, 0])),
+            Some(Material::Void)
+        );
+
+        // ...and consumes it
+        let (spawns, inventories) = step_with_requests(&mut sim, &save, &[(a, placing)]);
+        assert_eq!(spawns.block_updates.len(), 1);
+        assert_eq!(inventories[0].1, Inventory::default());
+        assert_eq!(held(&sim, a), 0);
+        assert_eq!(
+            sim.graph.get_block(chunk_id, Coords([0, 0, 0])),
+            Some(Material::Dirt)
+        );
+    }
+
+    #[test]
+    fn placements_refused_where_characters_stand() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            spawn_distance: Some(0),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let (_, a) = sim.spawn_character(ClientHello::new("a"));
+        sim.world.get::<&mut CharacterInput>(a).unwrap().no_clip = false;
+        sim.step(&save);
+
+        // The voxel beside the center of the root node, approached from below and to the side
+        let chunk_id = ChunkId::new(NodeId::ROOT, dodeca::Vertex::A);
+        let coords = Coords([11, 11, 11]);
+        let dual_to_grid = f64::from(sim.graph.layout().dual_to_grid_factor());
+        let at_grid = |x: f64, z: f64| Position {
+            node: NodeId::ROOT,
+            local: math::translate(
+                &math::origin(),
+                &math::lorentz_normalize(
+                    &(chunk_id.vertex.dual_to_node_f64()
+                        * (na::Vector3::new(x, 11.5, z) / dual_to_grid).push(1.0)),
+                ),
+            )
+            .cast(),
+        };
+        let (mut refused, mut placed) = (0, 0);
+        let mut sequence = 0;
+        for x in [10.4, 10.7, 11.0, 11.5] {
+            for z in [9.4, 9.6, 9.8, 10.0, 10.2, 10.4, 10.6, 10.8, 11.0] {
+                let _ = sim.graph.update_block(&BlockUpdate {
+                    chunk_id,
+                    coords,
+                    new_material: Material::Void,
+                    new_shape: Shape::FULL,
+                    sequence: 0,
+                });
+                *sim.world.get::<&mut Position>(a).unwrap() = at_grid(x, z);
+                sim.world.get::<&mut Character>(a).unwrap().state.velocity = na::zero();
+                let mut inventory = sim.world.get::<&mut Inventory>(a).unwrap();
+                *inventory = Inventory::default();
+                assert!(inventory.try_add(Material::Dirt, 1));
+                drop(inventory);
+
+                sequence += 1;
+                let placing = BlockUpdate {
+                    chunk_id,
+                    coords,
+                    new_material: Material::Dirt,
+                    new_shape: Shape::FULL,
+                    sequence,
+                };
+                step_with_requests(&mut sim, &save, &[(a, Some(placing.clone()))]);
+                let rejected = !sim.take_rejected_block_updates().is_empty();
+                // Clients judge their placements by where the step leaves them
+                let position = *sim.world.get::<&Position>(a).unwrap();
+                assert_eq!(
+                    rejected,
+                    placement::obstructs(
+                        &sim.graph,
+                        &placing,
+                        &position,
+                        cfg.character.character_radius
+                    ),
+                    "at ({x}, {z})"
+                );
+                if rejected {
+                    refused += 1;
+                } else {
+                    placed += 1;
+                }
+            }
+        }
+        // The positions straddle the boundary
+        assert!(
+            refused > 0 && placed > 0,
+            "{refused} refused, {placed} placed"
+        );
+    }
+
+    #[test]
+    fn repeated_block_updates_are_ignored() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            // Characters spawn at the origin
+            spawn_distance: Some(0),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg, &save);
+        let (id, a) = sim.spawn_character(ClientHello::new("a"));
+        sim.step(&save);
+
+        let chunk_id = ChunkId::new(NodeId::ROOT, dodeca::Vertex::A);
+        let fill = |sim: &mut Sim| {
+            let _ = sim.graph.update_block(&BlockUpdate {
+                chunk_id,
+                coords: Coords([0, 0, 0]),
+                new_material: Material::Dirt,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            });
+        };
+        let breaking = |sequence| BlockUpdate {
+            chunk_id,
+            coords: Coords([0, 0, 0]),
+            new_material: Material::Void,
+            new_shape: Shape::FULL,
+            sequence,
+        };
+        fill(&mut sim);
+        request(&mut sim, a, Some(breaking(7)));
+        let (spawns, _, _) = sim.step(&save);
+        assert_eq!(spawns.block_updates.len(), 1);
+        assert_eq!(spawns.block_updates[0].0, id);
+
+        // The input remains in effect until the next arrives, and the same command may be
+        // delivered again, but neither breaks the replaced block
+        fill(&mut sim);
+        let (spawns, _, _) = sim.step(&save);
+        assert!(spawns.block_updates.is_empty());
+        let (spawns, _) = step_with_requests(&mut sim, &save, &[(a, Some(breaking(7)))]);
+        assert!(spawns.block_updates.is_empty());
+        // Duplicates aren't rejections; the original was already resolved
+        assert!(sim.take_rejected_block_updates().is_empty());
+        assert_eq!(
+            sim.graph.get_block(chunk_id, Coords([0, 0, 0])),
+            Some(Material::Dirt)
+        );
+
+        // A new request for the same change goes through
+        let (spawns, _) = step_with_requests(&mut sim, &save, &[(a, Some(breaking(8)))]);
+        assert_eq!(spawns.block_updates.len(), 1);
+        assert_eq!(spawns.block_updates[0].1.sequence, 8);
+        assert_eq!(
+            sim.graph.get_block(chunk_id, Coords([0, 0, 0])),
+            Some(Material::Void)
+        );
+    }
+
+    #[test]
+    fn repeated_edits_are_saved_once() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            // Characters spawn at the origin
+            spawn_distance: Some(0),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let (_, a) = sim.spawn_character(ClientHello::new("a"));
+        sim.step(&save);
+        let _ = sim.take_changes();
+
+        let chunk_id = ChunkId::new(NodeId::ROOT, dodeca::Vertex::A);
+        for x in 0..3 {
+            let coords = Coords([x, 0, 0]);
+            let _ = sim.graph.update_block(&BlockUpdate {
+                chunk_id,
+                coords,
+                new_material: Material::Dirt,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            });
+            let breaking = BlockUpdate {
+                chunk_id,
+                coords,
+                new_material: Material::Void,
+                new_shape: Shape::FULL,
+                sequence: x.into(),
+            };
+            let (spawns, _) = step_with_requests(&mut sim, &save, &[(a, Some(breaking))]);
+            assert_eq!(spawns.block_updates.len(), 1);
+        }
+
+        // Every edit is captured in a single copy of the chunk
+        let changes = sim.take_changes();
+        assert_eq!(changes.chunk_count(), 1);
+        assert_eq!(sim.take_changes().chunk_count(), 0);
+        let saved = changes
+            .chunk(sim.graph.hash_of(NodeId::ROOT), dodeca::Vertex::A as u32)
+            .unwrap();
+        let Some(Chunk::Populated { voxels, .. }) = sim.graph.get_chunk(chunk_id) else {
+            unreachable!();
+        };
+        assert_eq!(saved, encode_voxels(voxels, cfg.chunk_size));
+
+        // The saved chunk is loaded in place of a freshly generated one
+        save.apply(&changes).unwrap();
+        let mut restored = Sim::new(cfg.clone(), &save);
+        restored.spawn_character(ClientHello::new("a"));
+        restored.step(&save);
+        let Some(Chunk::Populated {
+            voxels: restored_voxels,
+            ..
+        }) = restored.graph.get_chunk(chunk_id)
+        else {
+            unreachable!();
+        };
+        assert_eq!(
+            encode_voxels(restored_voxels, cfg.chunk_size),
+            encode_voxels(voxels, cfg.chunk_size)
+        );
+    }
+
+    #[test]
+    fn edit_generations_survive_reload() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            // Characters spawn at the origin
+            spawn_distance: Some(0),
+            ..Default::default()
+        }));
+        let chunk_id = ChunkId::new(NodeId::ROOT, dodeca::Vertex::A);
+        let hash = |sim: &Sim| sim.graph.hash_of(NodeId::ROOT);
+        // Break blocks `xs` along one edge of the chunk, filling them in first if need be
+        let edit = |sim: &mut Sim, a, xs: std::ops::Range<u8>| {
+            for x in xs {
+                let coords = Coords([x, 0, 0]);
+                let _ = sim.graph.update_block(&BlockUpdate {
+                    chunk_id,
+                    coords,
+                    new_material: Material::Dirt,
+                    new_shape: Shape::FULL,
+                    sequence: 0,
+                });
+                let breaking = BlockUpdate {
+                    chunk_id,
+                    coords,
+                    new_material: Material::Void,
+                    new_shape: Shape::FULL,
+                    sequence: x.into(),
+                };
+                let (spawns, _) = step_with_requests(sim, &save, &[(a, Some(breaking))]);
+                assert_eq!(spawns.block_updates.len(), 1);
+            }
+        };
+
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let (_, a) = sim.spawn_character(ClientHello::new("a"));
+        sim.step(&save);
+        let _ = sim.take_changes();
+        edit(&mut sim, a, 0..2);
+        let changes = sim.take_changes();
+        assert_eq!(
+            changes.edit_generation(hash(&sim), dodeca::Vertex::A as u32),
+            Some(2)
+        );
+        save.apply(&changes).unwrap();
+
+        // Edits after loading the chunk again count on from where the save left off
+        let mut restored = Sim::new(cfg, &save);
+        let (_, a) = restored.spawn_character(ClientHello::new("a"));
+        restored.step(&save);
+        assert_eq!(restored.edit_generations.get(&chunk_id), Some(&2));
+        edit(&mut restored, a, 2..3);
+        let changes = restored.take_changes();
+        assert_eq!(
+            changes.edit_generation(hash(&restored), dodeca::Vertex::A as u32),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn edits_rolled_back() {
+        let (save, mut sim, entity, _file) = alone_in_the_void();
+        let target = block_ahead(&mut sim, 3.0, Material::SwitchOff);
+        let InteractTarget::Block(chunk, coords) = target else {
+            unreachable!()
+        };
+        use_now(&mut sim, entity, target).unwrap();
+        assert_eq!(sim.graph.get_block(chunk, coords), Some(Material::SwitchOn));
+
+        let around_origin = |radius: f32| Rollback {
+            scope: RollbackScope::Region {
+                path: NodePath(Vec::new()),
+                local_translation: na::Vector3::zeros(),
+                radius,
+            },
+            seconds: 60,
+            force: false,
+        };
+        assert_eq!(sim.rollback("admin", &around_origin(2.0)), Ok(0));
+        assert_eq!(
+            sim.rollback("admin", &around_origin(1000.0)),
+            Err(RollbackError::Radius)
+        );
+        assert_eq!(sim.rollback("admin", &around_origin(4.0)), Ok(1));
+        assert_eq!(
+            sim.graph.get_block(chunk, coords),
+            Some(Material::SwitchOff)
+        );
+        // Sent to clients as the work of nobody
+        let (spawns, _, _) = sim.step(&save);
+        assert_eq!(spawns.block_updates.len(), 1);
+        assert_eq!(spawns.block_updates[0].0, EntityId::NOBODY);
+
+        // A rollback can itself be undone
+        let by_admin = Rollback {
+            scope: RollbackScope::Player("admin".into()),
+            seconds: 60,
+            force: false,
+        };
+        assert_eq!(sim.rollback("admin", &by_admin), Ok(1));
+        assert_eq!(sim.graph.get_block(chunk, coords), Some(Material::SwitchOn));
+        let changes = sim.take_changes();
+        assert!(changes
+            .edit(0)
+            .is_some_and(|edit| edit.is_some_and(|edit| edit.player == "test")));
+    }
+
+    /// Have `entity` request `block_fill` in a single step
+    fn step_with_fill(
+        sim: &mut Sim,
+        save: &save::Save,
+        entity: Entity,
+        block_fill: BlockFill,
+    ) -> Spawns {
+        sim.world
+            .get::<&mut CharacterInput>(entity)
+            .unwrap()
+            .block_fill = Some(block_fill);
+        let (spawns, _, _) = sim.step(save);
+        sim.world
+            .get::<&mut CharacterInput>(entity)
+            .unwrap()
+            .block_fill = None;
+        spawns
+    }
+
+    #[test]
+    fn block_fills_checked_and_broadcast() {
+        let (save, mut sim, entity, _file) = alone_in_the_void();
+        let id = *sim.world.get::<&EntityId>(entity).unwrap();
+        let InteractTarget::Block(chunk_id, coords) = block_ahead(&mut sim, 3.0, Material::Void)
+        else {
+            unreachable!()
+        };
+        let mut sequence = 0;
+        let mut fill = |extents, material| {
+            sequence += 1;
+            BlockFill {
+                chunk_id,
+                coords,
+                extents,
+                contents: FillContents::Material(material),
+                sequence,
+            }
+        };
+        let count = |sim: &Sim, extents, material| {
+            let (counts, coverage) = sim
+                .graph
+                .count_materials_in_box((chunk_id, coords), extents);
+            assert_eq!(coverage, RegionCoverage::Complete);
+            counts.get(&material).copied().unwrap_or(0)
+        };
+        for _ in 0..8 {
+            let mut inventory = sim.world.get::<&mut Inventory>(entity).unwrap();
+            assert!(inventory.try_add(Material::Dirt, sim.cfg.inventory_capacity));
+        }
+
+        // A fill is paid for block by block, and sent to clients whole
+        let placing = fill([2, 2, 2], Material::Dirt);
+        let spawns = step_with_fill(&mut sim, &save, entity, placing.clone());
+        assert_eq!(spawns.block_fills, [(id, placing)]);
+        assert!(sim.take_rejected_block_updates().is_empty());
+        assert_eq!(count(&sim, [2, 2, 2], Material::Dirt), 8);
+        let held = |sim: &Sim| {
+            sim.world
+                .get::<&Inventory>(entity)
+                .unwrap()
+                .count(Material::Dirt)
+        };
+        assert_eq!(held(&sim), 0);
+
+        // Only administrators may fill large boxes
+        let breaking = fill([5, 5, 3], Material::Void);
+        let spawns = step_with_fill(&mut sim, &save, entity, breaking.clone());
+        assert!(spawns.block_fills.is_empty());
+        assert_eq!(
+            sim.take_rejected_block_updates(),
+            [(
+                entity,
+                BlockUpdateRejection {
+                    sequence: breaking.sequence,
+                    reason: RejectionReason::Refused
+                }
+            )]
+        );
+        assert_eq!(count(&sim, [2, 2, 2], Material::Dirt), 8);
+        sim.set_fill_limit(entity, sim.cfg.admin_max_fill_volume)
+            .unwrap();
+        let spawns = step_with_fill(&mut sim, &save, entity, fill([5, 5, 3], Material::Void));
+        assert_eq!(spawns.block_fills.len(), 1);
+        assert_eq!(count(&sim, [5, 5, 3], Material::Void), 75);
+        assert_eq!(held(&sim), 8);
+
+        // Any protected voxel spoils the whole fill
+        sim.set_protected_region(ProtectedRegion {
+            name: "spawn".into(),
+            center_path: NodePath::default(),
+            radius: 5.0,
+            allowed: Default::default(),
+        })
+        .unwrap();
+        let placing = fill([2, 2, 2], Material::Dirt);
+        let spawns = step_with_fill(&mut sim, &save, entity, placing.clone());
+        assert!(spawns.block_fills.is_empty());
+        assert_eq!(
+            sim.take_rejected_block_updates(),
+            [(
+                entity,
+                BlockUpdateRejection {
+                    sequence: placing.sequence,
+                    reason: RejectionReason::Protected("spawn".into())
+                }
+            )]
+        );
+        assert_eq!(count(&sim, [2, 2, 2], Material::Dirt), 0);
+        assert_eq!(held(&sim), 8);
+    }
+
+    #[test]
+    fn template_pastes_checked_paid_for_and_deferred() {
+        let (save, mut sim, entity, _file) = alone_in_the_void();
+        let id = *sim.world.get::<&EntityId>(entity).unwrap();
+        let InteractTarget::Block(chunk_id, coords) = block_ahead(&mut sim, 3.0, Material::Void)
+        else {
+            unreachable!()
+        };
+        let up = (CoordAxis::Y, CoordDirection::Plus);
+        let parity = sim.graph.chunk_parity(chunk_id);
+        let template = |extents: [u32; 3]| {
+            let volume = extents.iter().product::<u32>() as usize;
+            let mut voxels = vec![(Material::Dirt, Shape::FULL); volume];
+            voxels[0] = (
+                Material::Dirt,
+                Shape::slab(CoordAxis::Y, CoordDirection::Minus),
+            );
+            Box::new(Template::new(extents, up, parity, voxels).unwrap())
+        };
+        let mut sequence = 0;
+        let mut paste = |template: Box<Template>, orientation: Orientation| {
+            sequence += 1;
+            BlockFill {
+                chunk_id,
+                coords,
+                extents: template.pasted_extents(orientation),
+                contents: FillContents::Template {
+                    template,
+                    orientation,
+                },
+                sequence,
+            }
+        };
+        let count = |sim: &Sim| {
+            let (counts, coverage) = sim
+                .graph
+                .count_materials_in_box((chunk_id, coords), [3, 3, 3]);
+            assert_eq!(coverage, RegionCoverage::Complete);
+            counts.get(&Material::Dirt).copied().unwrap_or(0)
+        };
+        let refused = |sim: &mut Sim, fill: &BlockFill| {
+            assert_eq!(
+                sim.take_rejected_block_updates(),
+                [(
+                    entity,
+                    BlockUpdateRejection {
+                        sequence: fill.sequence,
+                        reason: RejectionReason::Refused
+                    }
+                )]
+            );
+        };
+
+        // Pastes are capped like any other fill
+        let huge = paste(template([5, 5, 3]), Orientation::IDENTITY);
+        let spawns = step_with_fill(&mut sim, &save, entity, huge.clone());
+        assert!(spawns.block_fills.is_empty());
+        refused(&mut sim, &huge);
+
+        // The box must be the template's, as turned
+        let turn = Orientation::quarter_turn(CoordAxis::Y, CoordDirection::Plus);
+        let mut skewed = paste(template([2, 1, 1]), turn);
+        skewed.extents = [2, 1, 1];
+        let spawns = step_with_fill(&mut sim, &save, entity, skewed.clone());
+        assert!(spawns.block_fills.is_empty());
+        refused(&mut sim, &skewed);
+
+        // Each block costs one from the inventory, unless the character is creative
+        let small = paste(template([2, 1, 1]), turn);
+        let spawns = step_with_fill(&mut sim, &save, entity, small.clone());
+        assert!(spawns.block_fills.is_empty());
+        refused(&mut sim, &small);
+        assert_eq!(count(&sim), 0);
+        sim.set_creative(entity, true).unwrap();
+        let small = paste(template([2, 1, 1]), turn);
+        let spawns = step_with_fill(&mut sim, &save, entity, small.clone());
+        assert_eq!(spawns.block_fills, [(id, small)]);
+        assert_eq!(count(&sim), 2);
+        let held = sim
+            .world
+            .get::<&Inventory>(entity)
+            .unwrap()
+            .count(Material::Dirt);
+        assert_eq!(held, 0);
+
+        // Protection applies to creative characters too
+        sim.set_protected_region(ProtectedRegion {
+            name: "spawn".into(),
+            center_path: NodePath::default(),
+            radius: 5.0,
+            allowed: Default::default(),
+        })
+        .unwrap();
+        let protected = paste(template([1, 2, 1]), Orientation::IDENTITY);
+        let spawns = step_with_fill(&mut sim, &save, entity, protected.clone());
+        assert!(spawns.block_fills.is_empty());
+        assert_eq!(
+            sim.take_rejected_block_updates(),
+            [(
+                entity,
+                BlockUpdateRejection {
+                    sequence: protected.sequence,
+                    reason: RejectionReason::Protected("spawn".into())
+                }
+            )]
+        );
+        assert!(sim.remove_protected_region("spawn"));
+
+        // A paste over a chunk that hasn't been generated waits for it, rather than failing
+        sim.graph[chunk_id] = Chunk::Fresh;
+        let deferred = paste(template([1, 2, 1]), Orientation::IDENTITY);
+        let spawns = step_with_fill(&mut sim, &save, entity, deferred.clone());
+        assert!(spawns.block_fills.is_empty());
+        assert!(sim.take_rejected_block_updates().is_empty());
+        assert_eq!(sim.deferred_block_fills.len(), 1);
+        let (spawns, _, _) = sim.step(&save);
+        assert_eq!(spawns.block_fills, [(id, deferred)]);
+        assert!(sim.deferred_block_fills.is_empty());
+        assert_eq!(sim.graph.get_block(chunk_id, coords), Some(Material::Dirt));
+    }
+
+    #[test]
+    fn unknown_materials_not_placed() {
+        let (save, mut sim, entity, _file) = alone_in_the_void();
+        sim.set_creative(entity, true).unwrap();
+        let unknown = Material::from_id(Material::COUNT as u16);
+        let (chunk, coords) = place_ahead(&mut sim, &save, entity, 1, 3.0, unknown);
+        assert_eq!(sim.graph.get_block(chunk, coords), Some(Material::Void));
+
+        // But blocks of them already in the world can be broken like any other
+        let _ = sim.graph.set_block(chunk, coords, unknown, Shape::FULL);
+        assert_eq!(sim.graph.get_block(chunk, coords), Some(unknown));
+        place_ahead(&mut sim, &save, entity, 2, 3.0, Material::Void);
+        assert_eq!(sim.graph.get_block(chunk, coords), Some(Material::Void));
+    }
+}
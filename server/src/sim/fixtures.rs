@@ -0,0 +1,196 @@
+//! Worlds and helpers shared by the simulation's tests
+
+use std::sync::Arc;
+
+use hecs::Entity;
+
+use common::{
+    coords::locate_voxel,
+    dodeca,
+    graph::NodeId,
+    inventory::Inventory,
+    math,
+    node::{ChunkId, Coords},
+    proto::{
+        BlockUpdate, Character, CharacterInput, ClientHello, InteractTarget, Position, Spawns,
+    },
+    traversal::{ensure_nearby, nearby_nodes},
+    world::{Material, Shape},
+    worldgen::TerrainPassKind,
+    SimConfig, SimConfigRaw,
+};
+
+use super::{
+    interaction::{Interaction, LastUse, UseRefusal},
+    Sim,
+};
+
+/// Populate the chunks of `nodes`, as when a character comes near
+pub(super) fn populate_nodes(sim: &mut Sim, save: &save::Save, nodes: &[NodeId]) {
+    sim.population.run(&mut sim.graph, nodes.iter().copied(), 0);
+    sim.populate_chunks(save, nodes.iter().flat_map(|&node| chunks_of(node)), true);
+}
+
+pub(super) fn chunks_of(node: NodeId) -> impl Iterator<Item = ChunkId> {
+    dodeca::Vertex::iter().map(move |vertex| ChunkId::new(node, vertex))
+}
+
+/// A sim of flat ground of `material` three meters above the reference surface, with a
+/// character standing on it
+pub(super) fn standing_on(
+    material: Material,
+) -> (save::Save, Sim, Entity, tempfile::NamedTempFile) {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let save = save::Save::open(file.path(), 12).unwrap();
+    let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+        view_distance: Some(10.0),
+        spawn_distance: Some(0),
+        terrain: Some(vec![TerrainPassKind::Flat {
+            height: 3.0,
+            material,
+        }]),
+        ..Default::default()
+    }));
+    let mut sim = Sim::new(cfg, &save);
+    let (_, entity) = sim.spawn_character(ClientHello::new("test"));
+    sim.world
+        .get::<&mut CharacterInput>(entity)
+        .unwrap()
+        .no_clip = false;
+    raise(&mut sim, entity, 0.0);
+    for _ in 0..10 {
+        sim.step(&save);
+    }
+    assert!(sim.world.get::<&Character>(entity).unwrap().state.on_ground);
+    (save, sim, entity, file)
+}
+
+/// Put `entity` at rest with the bottom of its body `height` meters above the ground of
+/// `standing_on`
+pub(super) fn raise(sim: &mut Sim, entity: Entity, height: f32) {
+    let m = sim.cfg.meters_to_absolute;
+    let state = &sim.graph.get(NodeId::ROOT).as_ref().unwrap().state;
+    let up = state.up_direction().xyz().normalize();
+    let elevation = 3.0 * m + sim.cfg.character.character_radius + height * m;
+    let position = Position {
+        node: NodeId::ROOT,
+        local: math::translate_along(&(up * (elevation - state.elevation()))),
+    };
+    sim.move_character(entity, position).unwrap();
+    let mut character = sim.world.get::<&mut Character>(entity).unwrap();
+    character.state.velocity = na::Vector3::zeros();
+    character.state.on_ground = false;
+}
+
+/// Make `entity` request `block_update` in the next step, and nothing after
+pub(super) fn request(sim: &mut Sim, entity: Entity, block_update: Option<BlockUpdate>) {
+    sim.world
+        .get::<&mut CharacterInput>(entity)
+        .unwrap()
+        .block_update = block_update;
+}
+
+pub(super) fn step_with_requests(
+    sim: &mut Sim,
+    save: &save::Save,
+    requests: &[(Entity, Option<BlockUpdate>)],
+) -> (Spawns, Vec<(Entity, Inventory)>) {
+    for (entity, block_update) in requests {
+        request(sim, *entity, block_update.clone());
+    }
+    let (spawns, _, inventories) = sim.step(save);
+    for (entity, _) in requests {
+        request(sim, *entity, None);
+    }
+    (spawns, inventories)
+}
+
+pub(super) fn stand_at_origin(sim: &mut Sim) -> Entity {
+    let (_, entity) = sim.spawn_character(ClientHello::new("test"));
+    *sim.world.get::<&mut Position>(entity).unwrap() = Position::origin();
+    entity
+}
+
+/// A character standing at the origin of an empty world, amid populated nodes
+pub(super) fn alone_in_the_void() -> (save::Save, Sim, Entity, tempfile::NamedTempFile) {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let save = save::Save::open(file.path(), 12).unwrap();
+    let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+        view_distance: Some(20.0),
+        spawn_distance: Some(0),
+        terrain: Some(vec![TerrainPassKind::Void]),
+        ..Default::default()
+    }));
+    let mut sim = Sim::new(cfg, &save);
+    let entity = stand_at_origin(&mut sim);
+    ensure_nearby(&mut sim.graph, &Position::origin(), 1.5);
+    let nodes = nearby_nodes(&sim.graph, &Position::origin(), 1.5)
+        .into_iter()
+        .map(|(node, _)| node)
+        .collect::<Vec<_>>();
+    populate_nodes(&mut sim, &save, &nodes);
+    (save, sim, entity, file)
+}
+
+/// The position `offset` meters from the origin
+pub(super) fn offset_from_origin(sim: &Sim, offset: na::Vector3<f32>) -> Position {
+    Position {
+        node: NodeId::ROOT,
+        local: math::translate_along(&(offset * sim.cfg.meters_to_absolute)),
+    }
+}
+
+/// Fill the voxel `meters` from the origin along the x axis with `material`, returning it as
+/// something to use
+pub(super) fn block_ahead(sim: &mut Sim, meters: f32, material: Material) -> InteractTarget {
+    let position = offset_from_origin(sim, na::Vector3::x() * meters);
+    let (chunk_id, coords, _) = locate_voxel(&sim.graph, sim.graph.layout(), &position).unwrap();
+    let _ = sim.graph.update_block(&BlockUpdate {
+        chunk_id,
+        coords,
+        new_material: material,
+        new_shape: Shape::FULL,
+        sequence: 0,
+    });
+    InteractTarget::Block(chunk_id, coords)
+}
+
+/// Have `entity` use `target` at once, however recently it last used something
+pub(super) fn use_now(
+    sim: &mut Sim,
+    entity: Entity,
+    target: InteractTarget,
+) -> Result<Interaction, UseRefusal> {
+    sim.world.get::<&mut LastUse>(entity).unwrap().0 = None;
+    sim.interact(entity, target)
+}
+
+/// Have `entity` set the voxel `meters` from the origin along the x axis to `material` in a
+/// single step, as its player would with request `sequence`, returning the voxel
+pub(super) fn place_ahead(
+    sim: &mut Sim,
+    save: &save::Save,
+    entity: Entity,
+    sequence: u32,
+    meters: f32,
+    material: Material,
+) -> (ChunkId, Coords) {
+    let position = offset_from_origin(sim, na::Vector3::x() * meters);
+    let (chunk_id, coords, _) = locate_voxel(&sim.graph, sim.graph.layout(), &position).unwrap();
+    sim.world
+        .get::<&mut CharacterInput>(entity)
+        .unwrap()
+        .block_update = Some(BlockUpdate {
+        chunk_id,
+        coords,
+        new_material: material,
+        new_shape: Shape::FULL,
+        sequence,
+    });
+    sim.step(save);
+    sim.world
+        .get::<&mut CharacterInput>(entity)
+        .unwrap()
+        .block_update = None;
+    (chunk_id, coords)
+}
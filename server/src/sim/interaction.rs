@@ -0,0 +1,323 @@
+//! Using blocks and picking up items
+
+use std::{fmt, time::Duration};
+
+use hecs::Entity;
+use tracing::trace;
+
+use common::{
+    inventory::Inventory,
+    proto::{BlockUpdate, Character, InteractTarget, Interactable, Position},
+    reach::{self, Unreachable},
+    Step,
+};
+
+use super::Sim;
+
+/// Shortest time between a character's uses of things, so that a resent or repeated input can't
+/// flip a block back and forth faster than anyone could see
+const USE_INTERVAL: Duration = Duration::from_millis(250);
+
+impl Sim {
+    /// Have the character `user` use `target`
+    pub(super) fn interact(
+        &mut self,
+        user: Entity,
+        target: InteractTarget,
+    ) -> Result<Interaction, UseRefusal> {
+        {
+            let mut last_use = self.world.get::<&mut LastUse>(user).unwrap();
+            if let Some(step) = last_use.0 {
+                let elapsed = self.cfg.step_interval * u32::try_from(self.step - step).unwrap();
+                if elapsed < USE_INTERVAL {
+                    return Err(UseRefusal::TooSoon);
+                }
+            }
+            last_use.0 = Some(self.step);
+        }
+        let user_position = *self.world.get::<&Position>(user).unwrap();
+        match target {
+            InteractTarget::Entity(id) => {
+                let &entity = self.entity_ids.get(&id).ok_or(UseRefusal::Gone)?;
+                let (interactable, position) = {
+                    let mut query = self
+                        .world
+                        .query_one::<(&Interactable, &Position)>(entity)
+                        .unwrap();
+                    let (&interactable, &position) =
+                        query.get().ok_or(UseRefusal::NotInteractive)?;
+                    (interactable, position)
+                };
+                reach::entity_in_reach(&self.cfg, &self.graph, &user_position, &position)
+                    .map_err(UseRefusal::Unreachable)?;
+                match interactable {
+                    Interactable::Pickup(material) => {
+                        let added = self
+                            .world
+                            .get::<&mut Inventory>(user)
+                            .unwrap()
+                            .try_add(material, self.cfg.inventory_capacity);
+                        if !added {
+                            return Err(UseRefusal::InventoryFull);
+                        }
+                        trace!(entity = %id, ?material, "picked up");
+                        self.destroy(entity);
+                        Ok(Interaction::PickedUp)
+                    }
+                    Interactable::Prop => {
+                        trace!(entity = %id, "prop used");
+                        Ok(Interaction::Nothing)
+                    }
+                }
+            }
+            InteractTarget::Block(chunk, coords) => {
+                // A change to the generated voxels would be lost when the saved ones replace them
+                if self.awaiting_save(chunk) {
+                    return Err(UseRefusal::Unloaded);
+                }
+                let material = self
+                    .graph
+                    .get_block(chunk, coords)
+                    .ok_or(UseRefusal::Unloaded)?;
+                let toggled = material.toggled().ok_or(UseRefusal::NotInteractive)?;
+                reach::block_in_reach(&self.cfg, &self.graph, &user_position, chunk, coords)
+                    .map_err(UseRefusal::Unreachable)?;
+                let protecting = {
+                    let character = self.world.get::<&Character>(user).unwrap();
+                    self.protected_regions
+                        .protecting(&self.graph, chunk, coords, &character.name)
+                        .map(|region| region.name.clone())
+                };
+                if let Some(region) = protecting {
+                    return Err(UseRefusal::Protected(region));
+                }
+                let block_update = BlockUpdate {
+                    chunk_id: chunk,
+                    coords,
+                    new_material: toggled,
+                    new_shape: self.graph.get_shape(chunk, coords).unwrap(),
+                    // Unused, being authored by nobody
+                    sequence: 0,
+                };
+                let previous = self.apply_block_update(&block_update);
+                self.record_edit(user, &block_update, previous);
+                Ok(Interaction::Block(block_update))
+            }
+        }
+    }
+}
+
+/// What came of a character using something
+#[derive(Debug)]
+pub(super) enum Interaction {
+    /// An entity was taken into the character's inventory
+    PickedUp,
+    /// A block changed, to be broadcast
+    Block(BlockUpdate),
+    Nothing,
+}
+
+/// Why a character couldn't use something
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum UseRefusal {
+    /// The character used something else too recently
+    TooSoon,
+    /// The entity no longer exists
+    Gone,
+    /// Nothing happens when the target is used
+    NotInteractive,
+    /// The block's chunk isn't ready to be changed
+    Unloaded,
+    Unreachable(Unreachable),
+    /// The block lies in the named protected region, which the character isn't permitted to change
+    Protected(String),
+    InventoryFull,
+}
+
+impl fmt::Display for UseRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            UseRefusal::TooSoon => f.pad("used too soon after the last use"),
+            UseRefusal::Gone => f.pad("no such entity"),
+            UseRefusal::NotInteractive => f.pad("nothing to use"),
+            UseRefusal::Unloaded => f.pad("chunk not loaded"),
+            UseRefusal::Unreachable(Unreachable::TooFar) => f.pad("out of reach"),
+            UseRefusal::Unreachable(Unreachable::OutOfSight) => f.pad("out of sight"),
+            UseRefusal::Protected(ref region) => write!(f, "protected by {region}"),
+            UseRefusal::InventoryFull => f.pad("inventory full"),
+        }
+    }
+}
+
+/// Step of a character's latest use of something, if any
+#[derive(Debug, Default)]
+pub(super) struct LastUse(pub(super) Option<Step>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        node::{CoordAxis, CoordDirection},
+        proto::{CharacterInput, Component},
+        world::{Material, Shape},
+        EntityId,
+    };
+
+    use crate::sim::fixtures::{alone_in_the_void, block_ahead, offset_from_origin, use_now};
+
+    /// Make `entity` use `target` in the next step
+    fn request_use(sim: &mut Sim, entity: Entity, target: InteractTarget) {
+        sim.world
+            .get::<&mut CharacterInput>(entity)
+            .unwrap()
+            .use_target = Some(target);
+    }
+
+    #[test]
+    fn blocks_toggled_by_use() {
+        let (save, mut sim, entity, _file) = alone_in_the_void();
+        let target = block_ahead(&mut sim, 3.0, Material::SwitchOff);
+        let InteractTarget::Block(chunk, coords) = target else {
+            unreachable!()
+        };
+
+        request_use(&mut sim, entity, target);
+        let (spawns, _, _) = sim.step(&save);
+        assert_eq!(spawns.block_updates.len(), 1);
+        let (author, ref block_update) = spawns.block_updates[0];
+        // Not to be mistaken for the outcome of a prediction
+        assert_eq!(author, EntityId::NOBODY);
+        assert_eq!(block_update.new_material, Material::SwitchOn);
+        assert_eq!(sim.graph.get_block(chunk, coords), Some(Material::SwitchOn));
+        assert!(sim.dirty_chunks.contains(&chunk));
+        // Used once, however long the input stands
+        let (spawns, _, _) = sim.step(&save);
+        assert!(spawns.block_updates.is_empty());
+
+        // Too soon after the last use
+        request_use(&mut sim, entity, target);
+        let (spawns, _, _) = sim.step(&save);
+        assert!(spawns.block_updates.is_empty());
+
+        let interval = USE_INTERVAL.as_nanos() / sim.cfg.step_interval.as_nanos();
+        for _ in 0..interval {
+            sim.step(&save);
+        }
+        request_use(&mut sim, entity, target);
+        let (spawns, _, _) = sim.step(&save);
+        assert_eq!(spawns.block_updates.len(), 1);
+        assert_eq!(
+            sim.graph.get_block(chunk, coords),
+            Some(Material::SwitchOff)
+        );
+    }
+
+    #[test]
+    fn uses_refused_beyond_reach_or_sight() {
+        let (_save, mut sim, entity, _file) = alone_in_the_void();
+
+        let far = block_ahead(&mut sim, 15.0, Material::SwitchOff);
+        assert_eq!(
+            use_now(&mut sim, entity, far).unwrap_err(),
+            UseRefusal::Unreachable(Unreachable::TooFar)
+        );
+        // Nor are the materials of generated terrain, roads included
+        for material in [Material::Dirt, Material::GreyBrick, Material::WhiteBrick] {
+            let target = block_ahead(&mut sim, 3.0, material);
+            assert_eq!(
+                use_now(&mut sim, entity, target).unwrap_err(),
+                UseRefusal::NotInteractive
+            );
+        }
+        // Walled in on every side
+        let walled = block_ahead(&mut sim, 6.0, Material::SwitchOff);
+        let InteractTarget::Block(chunk, coords) = walled else {
+            unreachable!()
+        };
+        for axis in CoordAxis::iter() {
+            for direction in CoordDirection::iter() {
+                let (chunk_id, coords) = sim
+                    .graph
+                    .get_block_neighbor(chunk, coords, axis, direction)
+                    .unwrap();
+                let _ = sim.graph.update_block(&BlockUpdate {
+                    chunk_id,
+                    coords,
+                    new_material: Material::Dirt,
+                    new_shape: Shape::FULL,
+                    sequence: 0,
+                });
+            }
+        }
+        assert_eq!(
+            use_now(&mut sim, entity, walled).unwrap_err(),
+            UseRefusal::Unreachable(Unreachable::OutOfSight)
+        );
+        for target in [far, walled] {
+            let InteractTarget::Block(chunk, coords) = target else {
+                unreachable!()
+            };
+            assert_eq!(
+                sim.graph.get_block(chunk, coords),
+                Some(Material::SwitchOff)
+            );
+        }
+
+        let behind = -na::Vector3::z();
+        let distant = sim.spawn(
+            offset_from_origin(&sim, behind * 15.0),
+            vec![Component::Interactable(Interactable::Pickup(
+                Material::Sand,
+            ))],
+        );
+        assert_eq!(
+            use_now(&mut sim, entity, InteractTarget::Entity(distant)).unwrap_err(),
+            UseRefusal::Unreachable(Unreachable::TooFar)
+        );
+        let prop = sim.spawn(offset_from_origin(&sim, behind * 2.0), Vec::new());
+        assert_eq!(
+            use_now(&mut sim, entity, InteractTarget::Entity(prop)).unwrap_err(),
+            UseRefusal::NotInteractive
+        );
+        sim.destroy(sim.entity_ids[&prop]);
+        assert_eq!(
+            use_now(&mut sim, entity, InteractTarget::Entity(prop)).unwrap_err(),
+            UseRefusal::Gone
+        );
+        assert_eq!(
+            sim.world
+                .get::<&Inventory>(entity)
+                .unwrap()
+                .count(Material::Sand),
+            0
+        );
+    }
+
+    #[test]
+    fn pickups_taken_by_use() {
+        let (save, mut sim, entity, _file) = alone_in_the_void();
+        let pickup = sim.spawn(
+            offset_from_origin(&sim, -na::Vector3::z() * 2.0),
+            vec![Component::Interactable(Interactable::Pickup(
+                Material::Sand,
+            ))],
+        );
+        let (spawns, _, _) = sim.step(&save);
+        let (_, components) = spawns.spawns.iter().find(|x| x.0 == pickup).unwrap();
+        assert!(components.iter().any(|x| matches!(
+            x,
+            Component::Interactable(Interactable::Pickup(Material::Sand))
+        )));
+        save.apply(&sim.take_changes()).unwrap();
+
+        request_use(&mut sim, entity, InteractTarget::Entity(pickup));
+        let (spawns, _, inventories) = sim.step(&save);
+        assert_eq!(spawns.despawns, [pickup]);
+        assert_eq!(inventories.len(), 1);
+        assert_eq!(inventories[0].0, entity);
+        assert_eq!(inventories[0].1.count(Material::Sand), 1);
+        assert!(!sim.entity_ids.contains_key(&pickup));
+        // Gone from the save too
+        assert_eq!(sim.take_changes().entity(pickup.to_bits()), Some(None));
+    }
+}
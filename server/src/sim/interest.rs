@@ -0,0 +1,288 @@
+//! Which parts of the world characters are interested in, and what wakes them
+
+use std::fmt;
+
+use fxhash::FxHashSet;
+use hecs::Entity;
+
+use common::{
+    graph::NodeId,
+    math,
+    node_path::NodePath,
+    proto::{MovementModes, NodeInterestHint, Position, MAX_NODE_HINT_MARGIN},
+    traversal::{ensure_nearby, nearby_nodes},
+};
+
+use super::Sim;
+use crate::{activity::RegionState, stats::RegionActivityStats, update_lod::Viewer};
+
+impl Sim {
+    /// How much simulation the region of the graph containing `node` gets
+    pub fn region_state(&self, node: NodeId) -> RegionState {
+        self.activity
+            .state(self.graph_regions.root_of(&self.graph, node))
+    }
+
+    /// Number of regions of the graph in each state of activity
+    ///
+    /// Visits every node, so it's meant for occasional reports rather than every step.
+    pub fn region_activity_stats(&self) -> RegionActivityStats {
+        let total = self
+            .graph
+            .nodes()
+            .filter(|&node| self.graph_regions.is_root(&self.graph, node))
+            .count();
+        self.activity.stats(total)
+    }
+
+    /// Nodes near enough to `character` for its client to be told what happens in them, or none if
+    /// it doesn't exist
+    pub fn interest(&self, character: Entity) -> FxHashSet<NodeId> {
+        let Ok(position) = self.world.get::<&Position>(character) else {
+            return FxHashSet::default();
+        };
+        nearby_nodes(&self.graph, &position, f64::from(self.cfg.view_distance))
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect()
+    }
+
+    /// Roots of the graph regions overlapping the `interest` of `character`, nearest the origin
+    /// first
+    pub fn regions_of_interest(&self, character: Entity) -> Vec<NodeId> {
+        self.graph_region_roots(self.interest(character))
+    }
+
+    /// Extend the graph as `character`'s client expects to need it, returning the number of nodes
+    /// created, which are announced to every client in the next step
+    ///
+    /// The radius is limited to `MAX_NODE_HINT_MARGIN` beyond the view distance. Unless
+    /// `privileged`, or the character may fly through terrain and so go anywhere anyway, the
+    /// center must already exist within the view distance of the character. Hints only ever create
+    /// nodes, whose chunks are still generated only as characters approach them.
+    pub fn hint_nodes(
+        &mut self,
+        character: Entity,
+        hint: &NodeInterestHint,
+        privileged: bool,
+    ) -> Result<u32, NodeHintError> {
+        if hint.radius.is_nan() {
+            return Err(NodeHintError::Radius);
+        }
+        let radius = hint
+            .radius
+            .clamp(0.0, self.cfg.view_distance + MAX_NODE_HINT_MARGIN);
+        let position = *self
+            .world
+            .get::<&Position>(character)
+            .map_err(|_| NodeHintError::NoSuchEntity)?;
+        let privileged = privileged
+            || self
+                .world
+                .get::<&MovementModes>(character)
+                .is_ok_and(|modes| modes.contains(MovementModes::NO_CLIP));
+        let center = if privileged {
+            hint.center_path.ensure(&mut self.graph)
+        } else {
+            // Measured geometrically, so that a far-flung path is refused without being built out
+            let center = NodePath::to(&self.graph, position.node).transform_from(&hint.center_path)
+                * math::origin();
+            let character = position.local.cast::<f64>() * math::origin();
+            if math::distance(&character, &center) > f64::from(self.cfg.view_distance) {
+                return Err(NodeHintError::Distance);
+            }
+            hint.center_path
+                .resolve(&self.graph)
+                .ok_or(NodeHintError::Distance)?
+        };
+        let len = self.graph.len();
+        ensure_nearby(
+            &mut self.graph,
+            &Position {
+                node: center,
+                local: na::one(),
+            },
+            f64::from(radius),
+        );
+        Ok(self.graph.len() - len)
+    }
+
+    /// Where `viewpoint` is, for judging how often `character`'s client is told of other
+    /// entities, or `None` if either doesn't exist
+    ///
+    /// `viewpoint` is `character` itself unless its client follows another character.
+    pub fn viewer(&self, character: Entity, viewpoint: Entity) -> Option<Viewer> {
+        let id = self.entity_id(character)?;
+        let position = self.position(viewpoint)?;
+        Some(Viewer::new(
+            &self.graph,
+            id,
+            position,
+            self.cfg.view_distance,
+        ))
+    }
+}
+
+/// Why a `NodeInterestHint` was ignored
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NodeHintError {
+    /// The hinting character doesn't exist
+    NoSuchEntity,
+    /// The center is too far from the character, or not in the graph
+    Distance,
+    /// The radius isn't a number
+    Radius,
+}
+
+impl fmt::Display for NodeHintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match *self {
+            NodeHintError::NoSuchEntity => "no such entity",
+            NodeHintError::Distance => "center too far from the character",
+            NodeHintError::Radius => "radius is not a number",
+        })
+    }
+}
+
+impl std::error::Error for NodeHintError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use fxhash::FxHashMap;
+
+    use common::{
+        dodeca, graph::Graph, proto::CharacterInput, world::Material, worldgen::TerrainPassKind,
+        SimConfig, SimConfigRaw,
+    };
+
+    use crate::sim::fixtures::{alone_in_the_void, chunks_of, stand_at_origin};
+
+    #[test]
+    fn regions_wake_before_visible() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(3.0),
+            spawn_distance: Some(0),
+            terrain: Some(vec![TerrainPassKind::Flat {
+                height: 3.0,
+                material: Material::Dirt,
+            }]),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg, &save);
+        let entity = stand_at_origin(&mut sim);
+        sim.world
+            .get::<&mut CharacterInput>(entity)
+            .unwrap()
+            .no_clip = true;
+
+        // Walk straight ahead, noting when each region first wakes and first comes into view
+        let mut woken = FxHashMap::default();
+        let mut visible = FxHashSet::default();
+        let mut revealed = 0;
+        for i in 0..60 {
+            sim.step(&save);
+            for node in sim.graph.nodes() {
+                if sim.graph_regions.is_root(&sim.graph, node)
+                    && sim.region_state(node) == RegionState::Active
+                {
+                    woken.entry(node).or_insert(i);
+                }
+            }
+            for node in sim.interest(entity) {
+                let root = sim.graph_regions.root_of(&sim.graph, node);
+                if !visible.insert(root) || i == 0 {
+                    continue;
+                }
+                let woke = *woken.get(&root).expect("visible region never woke");
+                assert!(woke < i, "region visible at step {i} woke at step {woke}");
+                revealed += 1;
+            }
+
+            let position = *sim.world.get::<&Position>(entity).unwrap();
+            let local = position.local * math::translate_along(&(na::Vector3::x() * 0.3));
+            let (node, transform) = sim.graph.normalize_transform(position.node, &local);
+            sim.move_character(
+                entity,
+                Position {
+                    node,
+                    local: transform * local,
+                },
+            )
+            .unwrap();
+        }
+        assert!(revealed > 0);
+    }
+
+    #[test]
+    fn hibernating_regions_not_generated() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw::default()));
+        let mut sim = Sim::new(cfg, &save);
+        // Nobody has been anywhere
+        assert_eq!(sim.region_state(NodeId::ROOT), RegionState::Hibernating);
+        sim.population
+            .run(&mut sim.graph, std::iter::once(NodeId::ROOT), 0);
+        sim.populate_chunks(&save, chunks_of(NodeId::ROOT), false);
+        assert_eq!(sim.chunks_generated, 0);
+        // Unless the chunks are needed outright
+        sim.populate_chunks(&save, chunks_of(NodeId::ROOT), true);
+        assert!(sim.chunks_generated > 0);
+
+        // A character arriving wakes its surroundings
+        stand_at_origin(&mut sim);
+        sim.step(&save);
+        assert_eq!(sim.region_state(NodeId::ROOT), RegionState::Active);
+        let stats = sim.region_activity_stats();
+        assert!(stats.active > 0);
+        assert_eq!(stats.cooling, 0);
+    }
+
+    #[test]
+    fn abusive_node_hints_limited() {
+        let (_save, mut sim, entity, _file) = alone_in_the_void();
+        sim.set_movement_modes(entity, MovementModes::NONE).unwrap();
+        let hint = |sides: Vec<dodeca::Side>, radius: f32| NodeInterestHint {
+            center_path: NodePath(sides),
+            radius,
+        };
+
+        // However far a hint reaches, the graph grows only as far as it's allowed to
+        let limit = sim.cfg.view_distance + MAX_NODE_HINT_MARGIN;
+        assert!(sim
+            .hint_nodes(entity, &hint(Vec::new(), f32::INFINITY), false)
+            .is_ok());
+        let mut expected = Graph::new(12);
+        ensure_nearby(&mut expected, &Position::origin(), f64::from(limit));
+        assert_eq!(sim.graph.len(), expected.len());
+        assert_eq!(
+            sim.hint_nodes(entity, &hint(Vec::new(), f32::NAN), false),
+            Err(NodeHintError::Radius)
+        );
+
+        // Alternating between two non-adjacent sides leads straight away from the origin
+        let side = dodeca::Side::iter()
+            .find(|&side| side != dodeca::Side::A && !dodeca::Side::A.adjacent_to(side))
+            .unwrap();
+        let far = hint([dodeca::Side::A, side].repeat(4), 1.0);
+        let len = sim.graph.len();
+        assert_eq!(
+            sim.hint_nodes(entity, &far, false),
+            Err(NodeHintError::Distance)
+        );
+        assert_eq!(sim.graph.len(), len);
+
+        // Administrators and characters that may fly anywhere are trusted to look ahead
+        assert!(sim.hint_nodes(entity, &far, true).unwrap() > 0);
+        assert!(far.center_path.resolve(&sim.graph).is_some());
+        let farther = hint([dodeca::Side::A, side].repeat(6), 1.0);
+        sim.set_movement_modes(entity, MovementModes::NO_CLIP)
+            .unwrap();
+        assert!(sim.hint_nodes(entity, &farther, false).unwrap() > 0);
+    }
+}
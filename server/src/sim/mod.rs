@@ -0,0 +1,958 @@
+mod block_entities;
+mod characters;
+mod chunks;
+mod edits;
+#[cfg(test)]
+mod fixtures;
+mod interaction;
+mod interest;
+mod movement;
+mod persistence;
+mod projectiles;
+mod protected_regions;
+mod sync;
+mod tasks;
+mod waypoints;
+
+pub use characters::TeleportError;
+pub use chunks::{encode_voxels, read_saved_chunks, SavedChunk};
+pub use sync::sends_graph_regions;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use fxhash::{FxHashMap, FxHashSet};
+use hecs::Entity;
+use rayon::prelude::*;
+use tracing::{debug, info, trace, warn};
+
+use common::{
+    block_entity::{BlockEntities, BlockEntity, BlockEntityChange, Sign},
+    character_controller::{self, CollisionTrace},
+    dodeca,
+    graph::{Graph, NodeId},
+    inventory::Inventory,
+    logging::{edit_span, step_span, EditId},
+    node::{ChunkId, Coords, PopulationQueue},
+    projectile::Projectile,
+    proto::{
+        BlockFill, BlockUpdate, BlockUpdateRejection, Character, CharacterInput, Component,
+        FreshNode, Interactable, Position, RejectionReason, SoundEvent, Spawns, StateDelta,
+    },
+    traversal::{ensure_nearby, nearby_nodes},
+    waypoint::Waypoint,
+    world::{Material, Shape},
+    EntityId, GraphEntities, SimConfig, Step, ENTITY_ID_REUSE_DELAY,
+};
+
+use crate::{
+    activity::RegionActivity,
+    edit_history::{self, EditHistory},
+    entity_ids::EntityIdAllocator,
+    graph_regions::{GraphRegions, DEFAULT_REGION_DEPTH},
+    protection::ProtectedRegions,
+    scheduler::Scheduler,
+    sequence_window::SequenceWindow,
+    sim::{
+        chunks::index_saved_voxels,
+        edits::{load_edits, Creative, FillHeld},
+        interaction::Interaction,
+        movement::{Airborne, CharacterStep, Influences},
+        protected_regions::load_protected_regions,
+        sync::parents_first,
+        tasks::load_tasks,
+        waypoints::load_waypoints,
+    },
+    spawn::{self, SpawnPoints},
+    stats::PhaseTimes,
+};
+
+/// Seed for the choice of spawn points, fixed so that they're the same each time a world is loaded
+const SPAWN_SEED: u64 = 0;
+
+/// Most steps a block fill waits for the chunks it reaches to be populated before it's refused
+const MAX_FILL_WAIT_STEPS: Step = 100;
+
+pub struct Sim {
+    cfg: Arc<SimConfig>,
+    id_allocator: EntityIdAllocator,
+    step: Step,
+    /// Fraction of the day/night cycle elapsed, in [0, 1)
+    world_time: f32,
+    entity_ids: FxHashMap<EntityId, Entity>,
+    /// IDs of entities despawned within `ENTITY_ID_REUSE_DELAY`, with the step they were despawned
+    retired_ids: FxHashMap<EntityId, Step>,
+    world: hecs::World,
+    graph: Graph,
+    /// Nodes sent to clients but not yet populated
+    population: PopulationQueue,
+    spawn_points: SpawnPoints,
+    spawns: Vec<Entity>,
+    despawns: Vec<EntityId>,
+    graph_entities: GraphEntities,
+    dirty_nodes: FxHashSet<NodeId>,
+    /// Persistent entities other than characters spawned or despawned since the last call to
+    /// `take_changes`
+    dirty_entities: FxHashSet<EntityId>,
+    /// Where the characters of players who've left since the last call to `take_changes` were,
+    /// by name
+    departures: FxHashMap<String, save::Character>,
+    /// Chunks changed by block updates, with the voxels in each that differ from world generation,
+    /// or `None` if those couldn't be determined
+    modified_chunks: FxHashMap<ChunkId, Option<FxHashMap<Coords, (Material, Shape)>>>,
+    /// Chunks modified since the last call to `take_changes`
+    dirty_chunks: FxHashSet<ChunkId>,
+    /// Number of edits ever made to each modified chunk, counting those made before it was loaded
+    /// from the save, so that a chunk saved again never goes back to an earlier generation
+    edit_generations: FxHashMap<ChunkId, u32>,
+    /// Regions of the graph encoded for clients that are sent regions in place of the whole graph
+    graph_regions: GraphRegions,
+    /// Which regions of the graph are near enough to characters to be simulated
+    activity: RegionActivity,
+    /// Block updates refused since the last call to `take_rejected_block_updates`, with the
+    /// characters that requested them
+    rejected_block_updates: Vec<(Entity, BlockUpdateRejection)>,
+    /// Sounds made since the last call to `take_sounds`
+    sounds: Vec<SoundEvent>,
+    /// Number of chunks populated by world generation
+    chunks_generated: u64,
+    /// Number of chunks populated from the save
+    chunks_loaded: u64,
+    /// Whether saved voxels are read in the background, through `take_save_reads` and
+    /// `receive_saved`, rather than during `step`
+    background_reads: bool,
+    /// Hashes of nodes with voxels in the save that haven't been read yet
+    unread_nodes: FxHashSet<u128>,
+    /// Nodes whose saved voxels are to be read in the background, not yet taken by
+    /// `take_save_reads`
+    save_reads: Vec<(NodeId, u128)>,
+    /// Nodes whose saved voxels are being read in the background
+    reading: FxHashSet<NodeId>,
+    /// Chunks read from the save but not yet populated
+    saved_chunks: FxHashMap<ChunkId, SavedChunk>,
+    /// Block updates to chunks whose saved voxels haven't been populated yet, to be tried again
+    /// once they have
+    deferred_block_updates: Vec<(Entity, BlockUpdate)>,
+    /// Block fills reaching chunks that haven't been populated yet, to be tried again each step
+    /// until they have, with the step each was first tried in
+    deferred_block_fills: Vec<(Entity, BlockFill, Step)>,
+    /// Saved entities other than characters, held until their nodes are populated
+    held_entities: FxHashMap<NodeId, Vec<(EntityId, Position, Vec<Component>)>>,
+    /// Waypoints shared with every client, by name
+    waypoints: BTreeMap<String, Waypoint>,
+    /// Names of waypoints set or removed since the last call to `take_changes`
+    dirty_waypoints: BTreeSet<String>,
+    /// Regions where only some characters may change blocks
+    protected_regions: ProtectedRegions,
+    /// Names of protected regions set or removed since the last call to `take_changes`
+    dirty_protected_regions: BTreeSet<String>,
+    /// Data attached to individual blocks, such as the text of signs
+    block_entities: BlockEntities,
+    /// Block entities created, changed, or removed since the last call to
+    /// `take_block_entity_changes`
+    block_entity_changes: Vec<BlockEntityChange>,
+    /// Blocks recently changed by players, for administrators to undo
+    edit_history: EditHistory,
+    /// Block updates made by the server itself since the last step, undoing edits or carrying out
+    /// tasks, to be sent to clients with the next
+    server_block_updates: Vec<BlockUpdate>,
+    /// Things to do at later steps
+    scheduler: Scheduler,
+    /// Steps simulated by earlier runs using the same save
+    steps_before: u64,
+    /// Time taken by each phase of simulating characters in the latest step
+    phase_times: PhaseTimes,
+}
+
+impl Sim {
+    pub fn new(cfg: Arc<SimConfig>, save: &save::Save) -> Self {
+        let mut graph = Graph::new(cfg.chunk_size);
+        ensure_nearby(
+            &mut graph,
+            &Position::origin(),
+            f64::from(cfg.view_distance),
+        );
+        let spawn_points = SpawnPoints::new(&cfg, &mut graph, spawn::CANDIDATES, SPAWN_SEED);
+        let mut protected_regions = ProtectedRegions::new();
+        for region in load_protected_regions(save) {
+            let name = region.name.clone();
+            if let Err(e) = protected_regions.insert(
+                &mut graph,
+                cfg.meters_to_absolute,
+                cfg.view_distance,
+                region,
+            ) {
+                warn!(%name, "ignoring saved protected region: {}", e);
+            }
+        }
+        let mut edit_history = EditHistory::new(
+            edit_history::DEFAULT_MAX_RECORDS,
+            steps_in(&cfg, edit_history::DEFAULT_MAX_AGE),
+        );
+        edit_history.load(load_edits(save));
+        let steps_before = save.meta().steps;
+        let mut scheduler = Scheduler::new();
+        scheduler.load(load_tasks(save, steps_before));
+        let mut sim = Self {
+            id_allocator: EntityIdAllocator::new(save.meta().next_entity_id),
+            step: 0,
+            world_time: save.meta().world_time.rem_euclid(1.0),
+            entity_ids: FxHashMap::default(),
+            retired_ids: FxHashMap::default(),
+            world: hecs::World::new(),
+            graph,
+            population: PopulationQueue::new(),
+            spawn_points,
+            spawns: Vec::new(),
+            despawns: Vec::new(),
+            graph_entities: GraphEntities::new(),
+            dirty_nodes: FxHashSet::default(),
+            dirty_entities: FxHashSet::default(),
+            departures: FxHashMap::default(),
+            modified_chunks: FxHashMap::default(),
+            dirty_chunks: FxHashSet::default(),
+            edit_generations: FxHashMap::default(),
+            graph_regions: GraphRegions::new(DEFAULT_REGION_DEPTH),
+            activity: RegionActivity::new(cfg.view_distance, cfg.step_interval),
+            rejected_block_updates: Vec::new(),
+            sounds: Vec::new(),
+            chunks_generated: 0,
+            chunks_loaded: 0,
+            background_reads: false,
+            unread_nodes: index_saved_voxels(save),
+            save_reads: Vec::new(),
+            reading: FxHashSet::default(),
+            saved_chunks: FxHashMap::default(),
+            deferred_block_updates: Vec::new(),
+            deferred_block_fills: Vec::new(),
+            held_entities: FxHashMap::default(),
+            waypoints: load_waypoints(save),
+            dirty_waypoints: BTreeSet::new(),
+            protected_regions,
+            dirty_protected_regions: BTreeSet::new(),
+            block_entities: BlockEntities::default(),
+            block_entity_changes: Vec::new(),
+            edit_history,
+            server_block_updates: Vec::new(),
+            scheduler,
+            steps_before,
+            phase_times: PhaseTimes::default(),
+            cfg,
+        };
+        sim.load_entities(save);
+        sim
+    }
+
+    /// Add an entity other than a character, such as something to be picked up, at `position`,
+    /// which must lie in a node of the graph
+    pub fn spawn(&mut self, position: Position, components: Vec<Component>) -> EntityId {
+        let id = self.id_allocator.persistent();
+        trace!(entity = %id, "spawning entity");
+        let entity = self.spawn_saved(id, position, components);
+        self.spawns.push(entity);
+        self.dirty_entities.insert(id);
+        self.dirty_nodes.insert(position.node);
+        id
+    }
+
+    /// Add an entity restored from the save to the world
+    fn spawn_saved(
+        &mut self,
+        id: EntityId,
+        position: Position,
+        components: Vec<Component>,
+    ) -> Entity {
+        let mut builder = hecs::EntityBuilder::new();
+        builder.add(id).add(position);
+        for component in components {
+            match component {
+                Component::Character(x) => {
+                    builder.add(x);
+                }
+                Component::Interactable(x) => {
+                    builder.add(x);
+                }
+                // Thrown blocks are only ever spawned by throws, and never saved
+                Component::Projectile(_) => {}
+                // Saved separately, as a route and a transform
+                Component::Position(_) => {}
+            }
+        }
+        let entity = self.world.spawn(builder.build());
+        self.graph_entities.insert(position.node, entity);
+        let previous = self.entity_ids.insert(id, entity);
+        debug_assert!(previous.is_none(), "entity ID {id} assigned twice");
+        entity
+    }
+
+    /// Index of the next step to be simulated
+    pub fn current_step(&self) -> Step {
+        self.step
+    }
+
+    /// Time taken by each phase of simulating characters in the latest step
+    pub fn phase_times(&self) -> PhaseTimes {
+        self.phase_times
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    /// Where `entity` is, if it exists and has a position
+    pub fn position(&self, entity: Entity) -> Option<Position> {
+        self.world.get::<&Position>(entity).ok().map(|x| *x)
+    }
+
+    /// The ID clients know `entity` by, if it exists
+    pub fn entity_id(&self, entity: Entity) -> Option<EntityId> {
+        self.world.get::<&EntityId>(entity).ok().map(|x| *x)
+    }
+
+    /// Jump the day/night cycle to `fraction` of a day, which must be finite
+    pub fn set_world_time(&mut self, fraction: f32) {
+        debug_assert!(fraction.is_finite());
+        self.world_time = fraction.rem_euclid(1.0);
+    }
+
+    pub fn destroy(&mut self, entity: Entity) {
+        let id = *self.world.get::<&EntityId>(entity).unwrap();
+        if self.saved_alone(entity) {
+            self.dirty_entities.insert(id);
+        }
+        self.entity_ids.remove(&id);
+        if let Ok(position) = self.world.get::<&Position>(entity) {
+            self.graph_entities.remove(position.node, entity);
+        }
+        self.world.despawn(entity).unwrap();
+        if let Some(i) = self.spawns.iter().position(|&x| x == entity) {
+            // Clients never heard of it
+            self.spawns.remove(i);
+            return;
+        }
+        self.despawns.push(id);
+        self.retired_ids.insert(id, self.step);
+    }
+
+    /// Advance the simulation, returning changes to broadcast and the characters whose inventories
+    /// changed
+    pub fn step(&mut self, save: &save::Save) -> (Spawns, StateDelta, Vec<(Entity, Inventory)>) {
+        let span = step_span(self.step);
+        let _guard = span.enter();
+
+        self.run_tasks(save);
+
+        // Updates held back for saved voxels go first, being the oldest, unless their characters
+        // have since left
+        let mut pending_block_updates: Vec<(Entity, BlockUpdate)> =
+            std::mem::take(&mut self.deferred_block_updates)
+                .into_iter()
+                .filter(|&(entity, _)| self.world.contains(entity))
+                .collect();
+        let dt = self.cfg.step_interval.as_secs_f32();
+        let started = Instant::now();
+
+        // Characters are moved in parallel on the current rayon pool, which is sized to the machine
+        // unless `RAYON_NUM_THREADS` says otherwise, each changing only its own components while
+        // the graph is shared unchanged. Everything else they affect, the graph included, is left to the
+        // serial phase that follows, which takes them in the order they were collected in, so
+        // the outcome doesn't depend on how the work was divided between threads.
+        let mut characters = Vec::new();
+        // Likewise fills, which wait until every chunk they reach is ready
+        let mut pending_block_fills: Vec<(Entity, BlockFill, Step)> =
+            std::mem::take(&mut self.deferred_block_fills)
+                .into_iter()
+                .filter(|&(entity, ..)| self.world.contains(entity))
+                .collect();
+        let mut pending_uses = Vec::new();
+        let mut pending_throws = Vec::new();
+        for (
+            entity,
+            (
+                &id,
+                position,
+                character,
+                input,
+                block_updates_seen,
+                airborne,
+                influences,
+                collision_trace,
+            ),
+        ) in self.world.query_mut::<(
+            &EntityId,
+            &mut Position,
+            &mut Character,
+            &mut CharacterInput,
+            &mut SequenceWindow,
+            &mut Airborne,
+            &mut Influences,
+            Option<&mut CollisionTrace>,
+        )>() {
+            if let Some(ref block_update) = input.block_update {
+                // An input stays in effect until the next one arrives, and may have been sent more
+                // than once, but each block update must only be attempted once
+                if block_updates_seen.insert(block_update.sequence) {
+                    pending_block_updates.push((entity, block_update.clone()));
+                }
+            }
+            if let Some(ref block_fill) = input.block_fill {
+                if block_updates_seen.insert(block_fill.sequence) {
+                    pending_block_fills.push((entity, block_fill.clone(), self.step));
+                }
+            }
+            // Likewise each use, which carries no sequence to tell repeats by
+            if let Some(target) = input.use_target.take() {
+                pending_uses.push((entity, target));
+            }
+            if let Some(material) = input.throw.take() {
+                pending_throws.push((entity, material));
+            }
+            input.external = influences.take(&self.graph, position);
+            let input = &*input;
+            characters.push(CharacterStep {
+                entity,
+                id,
+                prev_node: position.node,
+                position,
+                character,
+                input,
+                airborne,
+                collision_trace,
+                damage: 0.0,
+            });
+        }
+        let pre_phase = started.elapsed();
+
+        let (cfg, graph) = (&*self.cfg, &self.graph);
+        // Spans aren't carried onto other threads, so the step's is entered again on each
+        characters
+            .par_iter_mut()
+            .for_each(|character| span.in_scope(|| character.run(cfg, graph, dt)));
+        let parallel_phase = started.elapsed() - pre_phase;
+
+        let mut deaths = Vec::new();
+        for CharacterStep {
+            entity,
+            prev_node,
+            position,
+            character,
+            damage,
+            ..
+        } in characters
+        {
+            if damage > 0.0 {
+                let health = &mut character.state.health;
+                *health = (*health - damage).clamp(0.0, self.cfg.character.max_health);
+                trace!(%damage, %health, "character landed hard");
+                if *health == 0.0 {
+                    deaths.push(entity);
+                }
+            }
+            if prev_node != position.node {
+                self.dirty_nodes.insert(prev_node);
+                self.graph_entities.remove(prev_node, entity);
+                self.graph_entities.insert(position.node, entity);
+            }
+            self.dirty_nodes.insert(position.node);
+            ensure_nearby(&mut self.graph, position, f64::from(self.cfg.view_distance));
+        }
+
+        for entity in deaths {
+            let id = *self.world.get::<&EntityId>(entity).unwrap();
+            info!(entity = %id, "character died");
+            self.respawn(entity);
+        }
+
+        // Push apart characters that have walked into each other, taking them in order of ID so
+        // that the result is the same however the world happens to store them
+        let mut separated = self
+            .world
+            .query::<(&EntityId, &Position, &Character, &CharacterInput)>()
+            .iter()
+            .filter(|(_, (_, _, _, input))| !input.no_clip)
+            .map(|(entity, (&id, &position, _, _))| (id, entity, position))
+            .collect::<Vec<_>>();
+        separated.sort_unstable_by_key(|&(id, _, _)| id);
+        let (entities, mut positions): (Vec<Entity>, Vec<Position>) = separated
+            .into_iter()
+            .map(|(_, entity, position)| (entity, position))
+            .unzip();
+        let separation = character_controller::separate_characters(
+            &self.cfg,
+            &self.graph,
+            &mut positions,
+            self.cfg.step_interval.as_secs_f32(),
+        );
+        trace!(
+            tested = separation.pairs_tested,
+            overlapping = separation.pairs_overlapping,
+            "separated characters"
+        );
+        for (entity, &new_position) in entities.into_iter().zip(&positions) {
+            let mut position = self.world.get::<&mut Position>(entity).unwrap();
+            if position.node == new_position.node && position.local == new_position.local {
+                // Not pushed this step
+                continue;
+            }
+            if position.node != new_position.node {
+                self.dirty_nodes.insert(position.node);
+                self.graph_entities.remove(position.node, entity);
+                self.graph_entities.insert(new_position.node, entity);
+            }
+            *position = new_position;
+            self.dirty_nodes.insert(new_position.node);
+        }
+
+        let mut accepted_block_updates: Vec<(EntityId, BlockUpdate)> =
+            std::mem::take(&mut self.server_block_updates)
+                .into_iter()
+                .map(|block_update| (EntityId::NOBODY, block_update))
+                .collect();
+        let mut changed_inventories: Vec<Entity> = vec![];
+
+        // Updates are applied in order, so when several characters change the same block, the
+        // first wins and the rest are rejected as no longer matching the block
+        for (entity, block_update) in pending_block_updates.into_iter() {
+            let id = *self.world.get::<&EntityId>(entity).unwrap();
+            let _span = edit_span(EditId::new(id, &block_update)).entered();
+            // Applied to the generated voxels, the update would be lost when the saved ones replace
+            // them
+            if self.awaiting_save(block_update.chunk_id) {
+                trace!(
+                    ?block_update,
+                    "deferring block update until saved voxels arrive"
+                );
+                self.deferred_block_updates.push((entity, block_update));
+                continue;
+            }
+            let checked = {
+                let character = self.world.get::<&Character>(entity).unwrap();
+                self.check_block_update(&block_update, &character.name, &positions)
+            };
+            let old_material = match checked {
+                Ok(x) => x,
+                Err(reason) => {
+                    self.reject_block_update(entity, block_update.sequence, reason);
+                    continue;
+                }
+            };
+            let mut inventory = self.world.get::<&mut Inventory>(entity).unwrap();
+            if !inventory.exchange_block(&self.cfg, old_material, block_update.new_material) {
+                trace!(?block_update, "rejected block update");
+                drop(inventory);
+                let sequence = block_update.sequence;
+                self.reject_block_update(entity, sequence, RejectionReason::Refused);
+                continue;
+            }
+            let previous = self.apply_block_update(&block_update);
+            self.record_edit(entity, &block_update, previous);
+            let (chunk, coords) = (block_update.chunk_id, block_update.coords);
+            if block_update.new_material == Material::Sign
+                && self.block_entities.get(chunk, coords).is_none()
+            {
+                // Written facing whoever placed it
+                let placer = self.position(entity).unwrap();
+                let sign = Sign::facing(&self.graph, chunk, coords, &placer);
+                self.set_block_entity(chunk, coords, BlockEntity::Sign(sign));
+            }
+            debug!("applied block update");
+            accepted_block_updates.push((id, block_update));
+            if !changed_inventories.contains(&entity) {
+                changed_inventories.push(entity);
+            }
+        }
+
+        // Uses follow block updates, so that a block broken this step can't also be used
+        for (entity, target) in pending_uses {
+            match self.interact(entity, target) {
+                Ok(Interaction::PickedUp) => {
+                    if !changed_inventories.contains(&entity) {
+                        changed_inventories.push(entity);
+                    }
+                }
+                Ok(Interaction::Block(block_update)) => {
+                    accepted_block_updates.push((EntityId::NOBODY, block_update));
+                }
+                Ok(Interaction::Nothing) => {}
+                Err(refusal) => trace!(?target, %refusal, "refused use"),
+            }
+        }
+
+        // Thrown blocks fly after characters have moved, and those that have settled land after
+        // characters' own block updates, which take precedence
+        for entity in self.step_projectiles(dt) {
+            if let Some(block_update) = self.land_projectile(entity, &positions) {
+                accepted_block_updates.push((EntityId::NOBODY, block_update));
+            }
+        }
+
+        // Fills follow every change to single blocks, as clients apply them
+        let mut accepted_block_fills: Vec<(EntityId, BlockFill)> = Vec::new();
+        for (entity, block_fill, first_tried) in pending_block_fills {
+            let id = *self.world.get::<&EntityId>(entity).unwrap();
+            let _span = edit_span(EditId {
+                author: id,
+                sequence: block_fill.sequence,
+            })
+            .entered();
+            let voxels = match self.check_block_fill(entity, &block_fill, &positions) {
+                Ok(x) => x,
+                Err(FillHeld::Waiting(chunks)) if self.step - first_tried < MAX_FILL_WAIT_STEPS => {
+                    trace!(
+                        chunks = chunks.len(),
+                        "deferring block fill until its chunks are ready"
+                    );
+                    self.request_chunks(save, chunks);
+                    self.deferred_block_fills
+                        .push((entity, block_fill, first_tried));
+                    continue;
+                }
+                Err(FillHeld::Waiting(_)) => {
+                    trace!("rejected block fill whose chunks never became ready");
+                    self.reject_block_update(entity, block_fill.sequence, RejectionReason::Refused);
+                    continue;
+                }
+                Err(FillHeld::Rejected(reason)) => {
+                    trace!(?reason, "rejected block fill");
+                    self.reject_block_update(entity, block_fill.sequence, reason);
+                    continue;
+                }
+            };
+            if self.world.get::<&Creative>(entity).is_err() {
+                let mut inventory = self.world.get::<&mut Inventory>(entity).unwrap();
+                // Either the whole fill is paid for or none of it is, a unit of material for each
+                // voxel whose material changes, as if each were changed alone
+                let mut paid = (*inventory).clone();
+                let affordable = voxels
+                    .iter()
+                    .filter(|&&(_, _, (old, _), (new, _))| old != new)
+                    .all(|&(_, _, (old, _), (new, _))| paid.exchange_block(&self.cfg, old, new));
+                if !affordable {
+                    trace!("rejected unaffordable block fill");
+                    drop(inventory);
+                    self.reject_block_update(entity, block_fill.sequence, RejectionReason::Refused);
+                    continue;
+                }
+                *inventory = paid;
+                if !changed_inventories.contains(&entity) {
+                    changed_inventories.push(entity);
+                }
+            }
+            self.apply_block_fill(entity, &block_fill, &voxels);
+            debug!(volume = block_fill.volume(), "applied block fill");
+            accepted_block_fills.push((id, block_fill));
+        }
+
+        // New throws wait for the next step to fly, so clients see where they're thrown from
+        for (entity, material) in pending_throws {
+            match self.throw(entity, material) {
+                Ok(()) => {
+                    if !changed_inventories.contains(&entity) {
+                        changed_inventories.push(entity);
+                    }
+                }
+                Err(refusal) => trace!(?material, %refusal, "refused throw"),
+            }
+        }
+        self.phase_times = PhaseTimes {
+            pre: pre_phase,
+            parallel: parallel_phase,
+            post: started.elapsed() - pre_phase - parallel_phase,
+        };
+        let changed_inventories = changed_inventories
+            .into_iter()
+            .map(|entity| {
+                let inventory = self.world.get::<&Inventory>(entity).unwrap();
+                (entity, (*inventory).clone())
+            })
+            .collect();
+
+        // Capture state changes for broadcast to clients
+        let mut spawns = Vec::with_capacity(self.spawns.len());
+        for entity in self.spawns.drain(..) {
+            let id = *self.world.get::<&EntityId>(entity).unwrap();
+            // Clients would mistake the new entity for the old one
+            debug_assert!(
+                !self.retired_ids.contains_key(&id),
+                "reused recently despawned entity ID {id}"
+            );
+            spawns.push((id, dump_entity(&self.world, entity)));
+        }
+        let step = self.step;
+        self.retired_ids
+            .retain(|_, &mut despawned| step - despawned < ENTITY_ID_REUSE_DELAY);
+        if !self.graph.fresh().is_empty() {
+            trace!(count = self.graph.fresh().len(), "broadcasting fresh nodes");
+        }
+        let spawns = Spawns {
+            step: self.step,
+            spawns,
+            despawns: std::mem::take(&mut self.despawns),
+            nodes: self
+                .graph
+                .fresh()
+                .iter()
+                .filter_map(|&id| {
+                    let side = self.graph.parent(id)?;
+                    Some(FreshNode {
+                        side,
+                        parent: self.graph.neighbor(id, side).unwrap(),
+                    })
+                })
+                .collect(),
+            block_updates: accepted_block_updates,
+            block_fills: accepted_block_fills,
+            modified_chunks: vec![],
+            chunk_diffs: vec![],
+        };
+        debug_assert!(parents_first(&self.graph, &spawns.nodes));
+        for &node in self.graph.fresh() {
+            self.graph_regions.invalidate(&self.graph, node);
+        }
+        self.population.enqueue_fresh(&mut self.graph);
+        self.activity.update(
+            &self.graph,
+            &self.graph_regions,
+            self.step,
+            // Nobody's watching the surroundings of characters whose players are away
+            self.world
+                .query::<(&Position, &Character)>()
+                .iter()
+                .filter(|(_, (_, ch))| !ch.state.afk)
+                .map(|(entity, (position, _))| (entity, position.node)),
+        );
+
+        let chunk_generation_distance = self.chunk_generation_distance();
+
+        // Load all chunks around entities corresponding to clients, which correspond to entities
+        // with a "Character" component, and those needed to find the ground beneath the next
+        // spawn point. Spawn points are prepared one per step, so that they're ready before
+        // anyone needs them without delaying any one step much. Chunks found in the save are used
+        // as-is, and the rest are generated.
+        let mut chunks = Vec::new();
+        let mut urgent = Vec::new();
+        for (_, (position, _)) in self.world.query::<(&Position, &Character)>().iter() {
+            for (node, _) in nearby_nodes(&self.graph, position, chunk_generation_distance) {
+                urgent.push(node);
+                chunks.extend(dodeca::Vertex::iter().map(|vertex| ChunkId::new(node, vertex)));
+            }
+        }
+        // Nodes further from characters can wait, so a burst of new nodes doesn't stall the step
+        self.population
+            .run(&mut self.graph, urgent, self.cfg.node_population_budget);
+        self.populate_chunks(save, chunks, false);
+        // The ground can't be found beneath a spawn point without every chunk along the way
+        let spawn_chunks = self.spawn_points.missing_chunks(&self.graph);
+        self.populate_chunks(save, spawn_chunks, true);
+        self.spawn_points.resolve_next(&self.cfg, &self.graph);
+        self.apply_saved_chunks();
+        if self.background_reads && !self.unread_nodes.is_empty() {
+            self.read_ahead();
+        }
+
+        // TODO: Omit unchanged (e.g. freshly spawned) entities (dirty flag?)
+        let delta = StateDelta {
+            latest_input: 0, // To be filled in by the caller
+            step: self.step,
+            positions: self
+                .world
+                .query::<(&EntityId, &Position)>()
+                .iter()
+                .map(|(_, (&id, &position))| (id, position))
+                .collect(),
+            character_states: self
+                .world
+                .query::<(&EntityId, &Character)>()
+                .iter()
+                .map(|(_, (&id, ch))| (id, ch.state.clone()))
+                .collect(),
+            world_time: self.world_time,
+            tether: None, // To be filled in by the caller
+        };
+
+        if self.cfg.day_length_seconds > 0.0 {
+            self.world_time = (self.world_time
+                + self.cfg.step_interval.as_secs_f32() / self.cfg.day_length_seconds)
+                .rem_euclid(1.0);
+        }
+        self.step += 1;
+        (spawns, delta, changed_inventories)
+    }
+
+    /// Sounds made since the last call, to be sent to the clients whose `interest` includes them
+    pub fn take_sounds(&mut self) -> Vec<SoundEvent> {
+        std::mem::take(&mut self.sounds)
+    }
+}
+
+/// Number of steps lasting at least `duration`
+fn steps_in(cfg: &SimConfig, duration: Duration) -> u64 {
+    (duration.as_secs_f64() / cfg.step_interval.as_secs_f64()).ceil() as u64
+}
+
+fn dump_entity(world: &hecs::World, entity: Entity) -> Vec<Component> {
+    let mut components = Vec::new();
+    if let Ok(x) = world.get::<&Position>(entity) {
+        components.push(Component::Position(*x));
+    }
+    if let Ok(x) = world.get::<&Character>(entity) {
+        components.push(Component::Character((*x).clone()));
+    }
+    if let Ok(x) = world.get::<&Interactable>(entity) {
+        components.push(Component::Interactable(*x));
+    }
+    if let Ok(x) = world.get::<&Projectile>(entity) {
+        components.push(Component::Projectile(x.material));
+    }
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        math,
+        proto::{ClientHello, MovementInput},
+        SimConfigRaw,
+    };
+
+    use crate::{postcard_helpers, sim::fixtures::standing_on};
+
+    #[test]
+    fn world_time_advances_and_persists() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            rate: Some(10),
+            view_distance: Some(1.0),
+            day_length_seconds: Some(1.0),
+            ..Default::default()
+        }));
+
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let mut previous = sim.step(&save).1.world_time;
+        assert_eq!(previous, 0.0);
+        for _ in 0..14 {
+            let (_, delta, _) = sim.step(&save);
+            assert!((delta.world_time - (previous + 0.1).fract()).abs() < 1e-4);
+            previous = delta.world_time;
+        }
+        // Fifteen steps of a tenth of a day have elapsed, wrapping around the end of the day
+        assert!((sim.world_time - 0.5).abs() < 1e-4);
+
+        sim.set_world_time(1.25);
+        assert_eq!(sim.world_time, 0.25);
+        save.apply(&sim.take_changes()).unwrap();
+        drop(sim);
+        drop(save);
+
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let mut sim = Sim::new(cfg, &save);
+        assert_eq!(sim.step(&save).1.world_time, 0.25);
+    }
+
+    #[test]
+    fn entity_ids_never_reused() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            ..Default::default()
+        }));
+        let hello = |name: &str| ClientHello::new(name);
+
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let (id, entity) = sim.spawn_character(hello("a"));
+        sim.step(&save);
+        sim.destroy(entity);
+        let (spawns, _, _) = sim.step(&save);
+        assert_eq!(spawns.despawns, [id]);
+        let (other_id, _) = sim.spawn_character(hello("b"));
+        assert_ne!(other_id, id);
+
+        // Gone before clients could hear of it, so they aren't told of its departure either
+        let (fleeting_id, fleeting) = sim.spawn_character(hello("c"));
+        sim.destroy(fleeting);
+        let (spawns, _, _) = sim.step(&save);
+        assert_eq!(
+            spawns.spawns.iter().map(|x| x.0).collect::<Vec<_>>(),
+            [other_id]
+        );
+        assert!(spawns.despawns.is_empty());
+
+        // Nor after a restart
+        save.apply(&sim.take_changes()).unwrap();
+        drop(sim);
+        drop(save);
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let mut sim = Sim::new(cfg, &save);
+        let (restarted_id, _) = sim.spawn_character(hello("d"));
+        assert!(![id, other_id, fleeting_id].contains(&restarted_id));
+    }
+
+    /// Characters in `crowd`
+    const CROWD: usize = 200;
+
+    /// A sim of flat ground with `CROWD` characters walking about on it in every direction, packed
+    /// closely enough to push one another apart
+    fn crowd() -> (save::Save, Sim, tempfile::NamedTempFile) {
+        let (save, mut sim, entity, file) = standing_on(Material::Dirt);
+        sim.destroy(entity);
+        let m = sim.cfg.meters_to_absolute;
+        let state = &sim.graph.get(NodeId::ROOT).as_ref().unwrap().state;
+        let up = state.up_direction().xyz().normalize();
+        let ground = up * (3.0 * m + sim.cfg.character.character_radius - state.elevation());
+        let across = up.cross(&na::Vector3::x()).normalize();
+        let along = up.cross(&across);
+        // Closer than the characters are wide
+        let spacing = 1.5 * sim.cfg.character.character_radius;
+        for i in 0..CROWD {
+            let (_, entity) = sim.spawn_character(ClientHello::new(format!("{i}")));
+            let (row, column) = ((i / 20) as f32, (i % 20) as f32);
+            let offset = (across * (column - 9.5) + along * (row - 4.5)) * spacing;
+            let position = Position {
+                node: NodeId::ROOT,
+                local: math::translate_along(&(ground + offset)),
+            };
+            sim.move_character(entity, position).unwrap();
+            let mut input = sim.world.get::<&mut CharacterInput>(entity).unwrap();
+            let angle = i as f32 * 2.4;
+            input.movement = MovementInput::new(across * angle.cos() + along * angle.sin());
+            input.jump = i % 7 == 0;
+            input.no_clip = false;
+        }
+        (save, sim, file)
+    }
+
+    /// Everything clients are told over some steps of a `crowd` moved by `threads` threads
+    fn crowd_history(threads: usize) -> Vec<u8> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let (save, mut sim, _file) = crowd();
+        let mut history = Vec::new();
+        pool.install(|| {
+            for _ in 0..20 {
+                let (spawns, delta, _) = sim.step(&save);
+                postcard_helpers::serialize(&spawns, &mut history).unwrap();
+                postcard_helpers::serialize(&delta, &mut history).unwrap();
+            }
+        });
+        history
+    }
+
+    #[test]
+    fn parallel_steps_are_deterministic() {
+        let serial = crowd_history(1);
+        for threads in [2, 8] {
+            assert!(
+                crowd_history(threads) == serial,
+                "{threads} threads diverged from one"
+            );
+        }
+    }
+}
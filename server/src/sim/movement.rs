@@ -0,0 +1,297 @@
+//! Moving characters: their parallel step, external influences, and falling
+
+use hecs::Entity;
+
+use common::{
+    character_controller::{self, CollisionTrace, Tether},
+    collision_math::Ray,
+    graph::{Graph, NodeId},
+    graph_ray_casting,
+    logging::entity_span,
+    math,
+    proto::{Character, CharacterInput, ExternalImpulse, ExternalInfluence, ImpulseMode, Position},
+    world::Material,
+    EntityId, SimConfig,
+};
+
+use super::Sim;
+
+impl Sim {
+    /// Change a character's velocity suddenly in its next step, as by knocking it back
+    ///
+    /// Impulses applied before the step combine, each added to those before it or overriding them.
+    pub fn apply_impulse(
+        &mut self,
+        entity: Entity,
+        impulse: ExternalImpulse,
+    ) -> Result<(), hecs::ComponentError> {
+        self.world
+            .get::<&mut Influences>(entity)?
+            .push_impulse(impulse);
+        Ok(())
+    }
+
+    /// Knock characters within `radius` of `center` away from it in their next step, at `speed`
+    /// at the center, falling off linearly to nothing at the edge
+    pub fn blast(&mut self, center: &Position, radius: f32, speed: f32) {
+        for (_, (position, influences)) in self.world.query_mut::<(&Position, &mut Influences)>() {
+            let offset = character_controller::relative_offset(&self.graph, position, center);
+            let distance = offset.norm();
+            if distance >= radius {
+                continue;
+            }
+            let away = match (-offset).try_normalize(1e-6) {
+                Some(away) => away,
+                // Straight up from the very center
+                None => match self.graph.get_relative_up(position) {
+                    Some(up) => up.into_inner(),
+                    None => continue,
+                },
+            };
+            influences.push_impulse(ExternalImpulse {
+                delta_velocity: away * speed * (1.0 - distance / radius),
+                mode: ImpulseMode::Add,
+            });
+        }
+    }
+
+    /// Pull a character toward `tether`'s anchor from its next step on, or stop pulling it if
+    /// `None`
+    pub fn set_tether(
+        &mut self,
+        entity: Entity,
+        tether: Option<Tether>,
+    ) -> Result<(), hecs::ComponentError> {
+        self.world.get::<&mut Influences>(entity)?.tether = tether;
+        Ok(())
+    }
+
+    /// What's pulling a character, if anything
+    pub fn tether(&self, entity: Entity) -> Option<Tether> {
+        self.world.get::<&Influences>(entity).ok()?.tether
+    }
+}
+
+/// A character's components, borrowed to move it in parallel with the others
+pub(super) struct CharacterStep<'a> {
+    pub(super) entity: Entity,
+    pub(super) id: EntityId,
+    /// Node the character was in at the start of the step
+    pub(super) prev_node: NodeId,
+    pub(super) position: &'a mut Position,
+    pub(super) character: &'a mut Character,
+    pub(super) input: &'a CharacterInput,
+    pub(super) airborne: &'a mut Airborne,
+    pub(super) collision_trace: Option<&'a mut CollisionTrace>,
+    /// Health lost landing during the step, for the serial phase to take away
+    pub(super) damage: f32,
+}
+
+impl CharacterStep<'_> {
+    pub(super) fn run(&mut self, cfg: &SimConfig, graph: &Graph, dt: f32) {
+        let _span = entity_span(self.id, self.position.node).entered();
+        let state = &mut self.character.state;
+        let output = character_controller::run_character_step(
+            cfg,
+            graph,
+            self.position,
+            &mut state.velocity,
+            &mut state.on_ground,
+            self.input,
+            &mut 0.0,
+            dt,
+            self.collision_trace.as_deref_mut(),
+        );
+        if let Some(landing) = output.landing {
+            self.damage = self.airborne.land(cfg, graph, self.position, &landing);
+        }
+        self.airborne.advance(state.on_ground, dt);
+    }
+}
+
+/// What moves a character besides its own input, awaiting its next step
+#[derive(Debug, Default)]
+pub(super) struct Influences {
+    /// Combination of the impulses applied since the last step
+    impulse: Option<ExternalImpulse>,
+    tether: Option<Tether>,
+}
+
+impl Influences {
+    fn push_impulse(&mut self, impulse: ExternalImpulse) {
+        self.impulse = Some(match (self.impulse, impulse.mode) {
+            (Some(prior), ImpulseMode::Add) => ExternalImpulse {
+                delta_velocity: prior.delta_velocity + impulse.delta_velocity,
+                mode: prior.mode,
+            },
+            _ => impulse,
+        });
+    }
+
+    /// What moves the character in its next step, from `position`
+    pub(super) fn take(&mut self, graph: &Graph, position: &Position) -> ExternalInfluence {
+        ExternalInfluence {
+            impulse: self.impulse.take(),
+            constraint: self.tether.map(|tether| tether.constraint(graph, position)),
+        }
+    }
+}
+
+/// Time in seconds a character had been airborne as of the end of the latest step
+#[derive(Debug, Default)]
+pub(super) struct Airborne(f32);
+
+impl Airborne {
+    /// Account for a character landing at `position`, returning the health it loses
+    ///
+    /// Characters briefly leave the ground all the time, as when walking down steps, so landings
+    /// soon after taking off never hurt, however fast the collision solver says they were.
+    fn land(
+        &self,
+        cfg: &SimConfig,
+        graph: &Graph,
+        position: &Position,
+        landing: &character_controller::Landing,
+    ) -> f32 {
+        if self.0 + landing.airborne_seconds < cfg.character.min_fall_seconds {
+            return 0.0;
+        }
+        let excess = landing.impact_speed - cfg.character.safe_fall_speed;
+        if excess <= 0.0 {
+            return 0.0;
+        }
+        let factor =
+            ground_material(cfg, graph, position).map_or(1.0, Material::landing_damage_factor);
+        excess * cfg.character.fall_damage * factor
+    }
+
+    /// Advance by a step of `dt` seconds that ended with the character `on_ground` or not
+    fn advance(&mut self, on_ground: bool, dt: f32) {
+        self.0 = if on_ground { 0.0 } else { self.0 + dt };
+    }
+}
+
+/// Material of the ground directly beneath a character standing at `position`, if any
+fn ground_material(cfg: &SimConfig, graph: &Graph, position: &Position) -> Option<Material> {
+    let up = graph.get_relative_up(position)?;
+    let ray = Ray::new(math::origin(), -up.into_inner().to_homogeneous());
+    let reach = cfg.character.character_radius + cfg.character.ground_distance_tolerance;
+    let hit = graph_ray_casting::ray_cast(graph, position, &ray, reach.tanh()).ok()??;
+    graph.get_block(hit.chunk, hit.voxel_coords)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use common::{
+        proto::ClientHello, test_graphs::surface_elevation, worldgen::TerrainPassKind, SimConfigRaw,
+    };
+
+    use crate::sim::fixtures::{raise, standing_on};
+
+    #[test]
+    fn flat_world_is_walkable() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(10.0),
+            spawn_distance: Some(0),
+            terrain: Some(vec![TerrainPassKind::Flat {
+                height: 3.0,
+                material: Material::Dirt,
+            }]),
+            ..Default::default()
+        }));
+        let height = 3.0 * cfg.meters_to_absolute;
+
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let (_, entity) = sim.spawn_character(ClientHello::new("test"));
+        // Drop the character from a little above the plane
+        let state = &sim.graph.get(NodeId::ROOT).as_ref().unwrap().state;
+        let up = state.up_direction().xyz().normalize();
+        *sim.world.get::<&mut Position>(entity).unwrap() = Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(
+                &(up * (height + 2.0 * cfg.meters_to_absolute - state.elevation())),
+            ),
+        };
+        sim.world
+            .get::<&mut CharacterInput>(entity)
+            .unwrap()
+            .no_clip = false;
+        for _ in 0..30 {
+            sim.step(&save);
+        }
+
+        assert!(sim.world.get::<&Character>(entity).unwrap().state.on_ground);
+        let position = *sim.world.get::<&Position>(entity).unwrap();
+        let elevation = surface_elevation(&sim.graph, &position);
+        assert!(
+            (elevation - height - cfg.character.character_radius).abs()
+                < cfg.character.ground_distance_tolerance,
+            "{elevation}"
+        );
+    }
+
+    /// Health lost by a character dropped from `height` meters onto flat ground of `material`
+    fn fall(material: Material, height: f32) -> f32 {
+        let (save, mut sim, entity, _file) = standing_on(material);
+        raise(&mut sim, entity, height);
+        for _ in 0..50 {
+            sim.step(&save);
+            if sim.world.get::<&Character>(entity).unwrap().state.on_ground {
+                break;
+            }
+        }
+        let character = sim.world.get::<&Character>(entity).unwrap();
+        assert!(character.state.on_ground);
+        assert_eq!(character.state.teleports, 0, "character died");
+        sim.cfg.character.max_health - character.state.health
+    }
+
+    #[test]
+    fn fall_damage_depends_on_height_and_material() {
+        let heights = [2.0, 6.0, 10.0];
+        let dirt = heights.map(|height| fall(Material::Dirt, height));
+        let snow = heights.map(|height| fall(Material::Snow, height));
+        // Short drops are safe, and longer ones increasingly harmful
+        assert_eq!(dirt[0], 0.0);
+        assert!(0.0 < dirt[1] && dirt[1] < dirt[2], "{dirt:?}");
+        // Snow cushions the very same falls
+        for (dirt, snow) in dirt.into_iter().zip(snow) {
+            assert!((snow - dirt * Material::Snow.landing_damage_factor()).abs() < 1e-3);
+        }
+        // Water absorbs them entirely
+        assert_eq!(fall(Material::Water, heights[2]), 0.0);
+    }
+
+    #[test]
+    fn brief_falls_are_harmless() {
+        let (save, mut sim, entity, _file) = standing_on(Material::Dirt);
+        let max_health = sim.cfg.character.max_health;
+        // Knocked into the ground from just above it, far faster than is safe
+        let slam = |sim: &mut Sim| {
+            raise(sim, entity, 0.1);
+            let position = *sim.world.get::<&Position>(entity).unwrap();
+            let up = sim.graph.get_relative_up(&position).unwrap();
+            let speed = 1.5 * sim.cfg.character.safe_fall_speed;
+            sim.world
+                .get::<&mut Character>(entity)
+                .unwrap()
+                .state
+                .velocity = -*up * speed;
+            sim.step(&save);
+            let character = sim.world.get::<&Character>(entity).unwrap();
+            assert!(character.state.on_ground);
+            character.state.health
+        };
+
+        // As when stepping down a stair, which the collision solver can see as a hard landing
+        assert_eq!(slam(&mut sim), max_health);
+        // The same impact after a long time in the air hurts
+        sim.world.get::<&mut Airborne>(entity).unwrap().0 = 1.0;
+        assert!(slam(&mut sim) < max_health);
+    }
+}
@@ -0,0 +1,409 @@
+//! Saving the world's entities and chunks, and restoring entities when loading
+
+use hecs::Entity;
+use tracing::{error, warn};
+
+use common::{
+    dodeca,
+    graph::{Graph, NodeId},
+    node::Chunk,
+    node_path::NodePath,
+    proto::{Character, Component, Position},
+    EntityId,
+};
+
+use super::{
+    block_entities::encode_block_entities, chunks::encode_voxels, dump_entity,
+    protected_regions::encode_protected_region, waypoints::encode_waypoint, Sim,
+};
+use crate::{postcard_helpers, scheduler};
+
+/// Version of the encoding of saved entities' components, to be bumped whenever `Component`
+/// changes such that those saved before can't be decoded
+const ENTITY_FORMAT: u32 = 1;
+
+impl Sim {
+    /// Prepare to respawn the persistent entities other than characters recorded in `save` once
+    /// their nodes are populated, skipping any that can't be placed
+    pub(super) fn load_entities(&mut self, save: &save::Save) {
+        let meta = save.meta();
+        if meta.entity_format > ENTITY_FORMAT {
+            error!(
+                format = meta.entity_format,
+                "ignoring entities saved by a newer version"
+            );
+            return;
+        }
+        let stored = save
+            .read()
+            .map_err(save::GetError::from)
+            .and_then(|guard| guard.get()?.get_entities());
+        let stored = match stored {
+            Ok(x) => x,
+            Err(e) => {
+                error!(error = %e, "couldn't load entities");
+                return;
+            }
+        };
+        for (bits, stored) in stored {
+            let id = EntityId::from_bits(bits);
+            // Only IDs already issued are safe from being issued again
+            if id.is_transient() || bits == 0 || bits >= meta.next_entity_id {
+                warn!(entity = %id, "ignoring saved entity with an unissued ID");
+                continue;
+            }
+            let Some((path, local, components)) = decode_entity(&stored) else {
+                warn!(entity = %id, "ignoring malformed saved entity");
+                continue;
+            };
+            // Characters are restored when their players return
+            if components
+                .iter()
+                .any(|x| matches!(x, Component::Character(_)))
+            {
+                warn!(entity = %id, "ignoring saved entity claiming to be a character");
+                continue;
+            }
+            let node = path.ensure(&mut self.graph);
+            self.held_entities.entry(node).or_default().push((
+                id,
+                Position { node, local },
+                components,
+            ));
+        }
+    }
+
+    /// Whether `entity` is saved by itself, being neither transient nor a character, which are
+    /// saved along with their names
+    pub(super) fn saved_alone(&self, entity: Entity) -> bool {
+        self.world
+            .get::<&EntityId>(entity)
+            .is_ok_and(|id| !id.is_transient())
+            && self.world.get::<&Position>(entity).is_ok()
+            && self.world.get::<&Character>(entity).is_err()
+    }
+
+    /// Copy everything that's changed since the last call, to be written to the save
+    pub fn take_changes(&mut self) -> save::Batch {
+        let mut batch = save::Batch::new();
+        batch.put_meta(save::Meta {
+            chunk_size: self.cfg.chunk_size.into(),
+            world_time: self.world_time,
+            // Every ID in the batch was issued before this
+            next_entity_id: self.id_allocator.next_persistent(),
+            entity_format: ENTITY_FORMAT,
+            steps: self.elapsed(),
+            task_format: scheduler::TASK_FORMAT,
+        });
+        for (name, character) in self.departures.drain() {
+            batch.put_character(name, character);
+        }
+        // Players who returned since leaving are saved where they are now instead
+        for (_, (pos, ch)) in self.world.query::<(&Position, &Character)>().iter() {
+            batch.put_character(
+                ch.name.clone(),
+                save::Character {
+                    path: path_from_origin(&self.graph, pos.node),
+                    local: Vec::new(),
+                },
+            );
+        }
+
+        let dirty_nodes = self.dirty_nodes.drain().collect::<Vec<_>>();
+        for node in dirty_nodes {
+            let entities = self.snapshot_node(node);
+            batch.put_entity_node(self.graph.hash_of(node), entities);
+        }
+
+        for id in std::mem::take(&mut self.dirty_entities) {
+            match self.entity_ids.get(&id) {
+                Some(&entity) => batch.put_entity(id.to_bits(), self.encode_entity(entity)),
+                // Recorded as gone, so that it stays gone
+                None => batch.remove_entity(id.to_bits()),
+            }
+        }
+
+        for chunk_id in self.dirty_chunks.drain() {
+            let Some(Chunk::Populated { voxels, .. }) = self.graph.get_chunk(chunk_id) else {
+                panic!("ungenerated chunk is marked as modified");
+            };
+            batch.put_chunk(
+                self.graph.hash_of(chunk_id.node),
+                save::Chunk {
+                    vertex: chunk_id.vertex as u32,
+                    voxels: encode_voxels(voxels, self.cfg.chunk_size),
+                    edit_generation: self
+                        .edit_generations
+                        .get(&chunk_id)
+                        .copied()
+                        .unwrap_or_default(),
+                    block_entities: encode_block_entities(&self.block_entities, chunk_id),
+                },
+            );
+        }
+
+        for name in std::mem::take(&mut self.dirty_waypoints) {
+            match self.waypoints.get(&name) {
+                Some(waypoint) => batch.put_waypoint(name, encode_waypoint(waypoint)),
+                None => batch.remove_waypoint(name),
+            }
+        }
+        for name in std::mem::take(&mut self.dirty_protected_regions) {
+            match self.protected_regions.get(&name) {
+                Some(region) => batch.put_protected_region(name, encode_protected_region(region)),
+                None => batch.remove_protected_region(name),
+            }
+        }
+        self.edit_history.take_changes(self.step, &mut batch);
+        self.scheduler
+            .take_changes(self.step, self.elapsed(), &mut batch);
+        batch
+    }
+
+    /// Steps simulated over every run using the same save
+    pub(super) fn elapsed(&self) -> u64 {
+        self.steps_before + u64::try_from(self.step).unwrap()
+    }
+
+    fn encode_entity(&self, entity: Entity) -> save::Entity {
+        let position = *self.world.get::<&Position>(entity).unwrap();
+        let components = dump_entity(&self.world, entity)
+            .into_iter()
+            .filter(|x| !matches!(x, Component::Position(_)))
+            .collect::<Vec<_>>();
+        let mut encoded = Vec::new();
+        postcard_helpers::serialize(&components, &mut encoded).unwrap();
+        save::Entity {
+            path: NodePath::to(&self.graph, position.node)
+                .0
+                .iter()
+                .map(|&side| side as u32)
+                .collect(),
+            local: position.local.as_slice().to_vec(),
+            components: encoded,
+        }
+    }
+
+    fn snapshot_node(&self, node: NodeId) -> save::EntityNode {
+        let mut ids = Vec::new();
+        let mut character_transforms = Vec::new();
+        let mut character_names = Vec::new();
+        let entities = self.graph_entities.get(node);
+
+        for &entity in entities {
+            // TODO: Handle entities other than characters
+            let mut q = self
+                .world
+                .query_one::<(&EntityId, &Position, &Character)>(entity)
+                .unwrap();
+            let Some((id, pos, ch)) = q.get() else {
+                continue;
+            };
+            ids.push(id.to_bits());
+            postcard_helpers::serialize(pos.local.as_ref(), &mut character_transforms).unwrap();
+            postcard_helpers::serialize(&ch.name, &mut character_names).unwrap();
+        }
+
+        save::EntityNode {
+            archetypes: vec![save::Archetype {
+                entities: ids,
+                component_types: vec![
+                    save::ComponentType::Position.into(),
+                    save::ComponentType::Name.into(),
+                ],
+                component_data: vec![character_transforms, character_names],
+            }],
+        }
+    }
+}
+
+/// Inverse of `Sim::encode_entity`, or `None` if `stored` is malformed
+fn decode_entity(stored: &save::Entity) -> Option<(NodePath, na::Matrix4<f32>, Vec<Component>)> {
+    let path = decode_path(&stored.path)?;
+    let local =
+        (stored.local.len() == 16).then(|| na::Matrix4::from_column_slice(&stored.local))?;
+    let components = postcard::from_bytes(&stored.components).ok()?;
+    Some((path, local, components))
+}
+
+/// The route from the origin to `node`, as saved
+pub(super) fn path_from_origin(graph: &Graph, mut node: NodeId) -> Vec<u32> {
+    let mut result = Vec::new();
+    while let Some(parent) = graph.parent(node) {
+        result.push(parent as u32);
+        node = graph.neighbor(node, parent).unwrap();
+    }
+    result.reverse();
+    result
+}
+
+/// Inverse of the routes written by `encode_waypoint`, `encode_protected_region`,
+/// `Sim::encode_entity`, and `path_from_origin`
+pub(super) fn decode_path(path: &[u32]) -> Option<NodePath> {
+    path.iter()
+        .map(|&x| (x < dodeca::SIDE_COUNT as u32).then(|| dodeca::Side::from_index(x as usize)))
+        .collect::<Option<Vec<_>>>()
+        .map(NodePath)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, time::Instant};
+
+    use common::{
+        math,
+        node::{populate_fresh_nodes, ChunkId, VoxelData},
+        proto::ClientHello,
+        traversal::{ensure_nearby, nearby_nodes},
+        world::Material,
+        SimConfig, SimConfigRaw,
+    };
+
+    use crate::sim::fixtures::populate_nodes;
+
+    /// Add a persistent entity with no components but its position, like a prop
+    fn spawn_prop(sim: &mut Sim, position: Position) -> EntityId {
+        let id = sim.id_allocator.persistent();
+        sim.spawn_saved(id, position, Vec::new());
+        sim.dirty_entities.insert(id);
+        id
+    }
+
+    #[test]
+    fn entities_persist() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let far_path = NodePath([dodeca::Side::B, dodeca::Side::D, dodeca::Side::F].to_vec());
+        let far = Position {
+            node: far_path.ensure(&mut sim.graph),
+            local: math::translate_along(&na::Vector3::new(0.2, 0.1, -0.3)),
+        };
+        let near = spawn_prop(&mut sim, Position::origin());
+        let distant = spawn_prop(&mut sim, far);
+        let doomed = spawn_prop(&mut sim, Position::origin());
+        let (character, _) = sim.spawn_character(ClientHello::new("a"));
+        let transient = sim.id_allocator.transient(|_| false);
+        sim.spawn_saved(transient, Position::origin(), Vec::new());
+        save.apply(&sim.take_changes()).unwrap();
+
+        sim.destroy(sim.entity_ids[&doomed]);
+        sim.destroy(sim.entity_ids[&transient]);
+        let batch = sim.take_changes();
+        assert_eq!(batch.entity(doomed.to_bits()), Some(None));
+        assert_eq!(batch.entity(transient.to_bits()), None);
+        save.apply(&batch).unwrap();
+        drop(sim);
+        drop(save);
+
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let mut sim = Sim::new(cfg, &save);
+        // Held until their nodes are populated
+        assert!(sim.entity_ids.is_empty());
+        let far_node = far_path.ensure(&mut sim.graph);
+        populate_nodes(&mut sim, &save, &[NodeId::ROOT, far_node]);
+        let mut restored = sim.entity_ids.keys().copied().collect::<Vec<_>>();
+        restored.sort_unstable_by_key(|id| id.to_bits());
+        assert_eq!(restored, [near, distant]);
+        assert!(!sim.entity_ids.contains_key(&character));
+        // Announced to clients like any other new entity
+        let (spawns, _, _) = sim.step(&save);
+        let mut announced = spawns.spawns.iter().map(|x| x.0).collect::<Vec<_>>();
+        announced.sort_unstable_by_key(|id| id.to_bits());
+        assert_eq!(announced, [near, distant]);
+
+        // Found again at the end of the same route
+        let entity = sim.entity_ids[&distant];
+        let position = sim.position(entity).unwrap();
+        assert_eq!(NodePath::to(&sim.graph, position.node), far_path);
+        assert_eq!(position.local, far.local);
+        assert!(sim.graph_entities.get(position.node).contains(&entity));
+    }
+
+    #[test]
+    fn malformed_entities_skipped() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let id = spawn_prop(&mut sim, Position::origin());
+        let mut batch = sim.take_changes();
+        let entity = batch.entity(id.to_bits()).unwrap().unwrap().clone();
+        // No dodecahedron has a thirteenth side
+        let lost = sim.id_allocator.persistent();
+        batch.put_entity(
+            lost.to_bits(),
+            save::Entity {
+                path: vec![1, 12],
+                ..entity.clone()
+            },
+        );
+        // Never issued, so it could be issued again
+        batch.put_entity(sim.id_allocator.next_persistent() + 5, entity);
+        batch.put_meta(save::Meta {
+            next_entity_id: sim.id_allocator.next_persistent(),
+            ..batch.meta().unwrap().clone()
+        });
+        save.apply(&batch).unwrap();
+        drop(sim);
+        drop(save);
+
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let mut sim = Sim::new(cfg, &save);
+        populate_nodes(&mut sim, &save, &[NodeId::ROOT]);
+        assert_eq!(sim.entity_ids.keys().copied().collect::<Vec<_>>(), [id]);
+    }
+
+    #[test]
+    fn flush_cost_is_bounded() {
+        const CHUNKS: usize = 500;
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg.clone(), &save);
+        ensure_nearby(&mut sim.graph, &Position::origin(), 3.0);
+        populate_fresh_nodes(&mut sim.graph);
+        let chunks = nearby_nodes(&sim.graph, &Position::origin(), 3.0)
+            .into_iter()
+            .flat_map(|(node, _)| {
+                dodeca::Vertex::iter().map(move |vertex| ChunkId::new(node, vertex))
+            })
+            .take(CHUNKS)
+            .collect::<Vec<_>>();
+        assert_eq!(chunks.len(), CHUNKS);
+        let len = (usize::from(cfg.chunk_size) + 2).pow(3);
+        for (i, &chunk) in chunks.iter().enumerate() {
+            let voxels = (0..len)
+                .map(|j| match (i + j) % 3 {
+                    0 => Material::Dirt,
+                    1 => Material::Sand,
+                    _ => Material::Void,
+                })
+                .collect::<Vec<_>>();
+            sim.graph
+                .populate_chunk(chunk, VoxelData::Dense(voxels.into()), true);
+            sim.dirty_chunks.insert(chunk);
+        }
+
+        // The tick only pays for copying; compression and I/O happen on the autosave thread
+        let start = Instant::now();
+        let changes = sim.take_changes();
+        let elapsed = start.elapsed();
+        assert_eq!(changes.chunk_count(), CHUNKS);
+        assert!(
+            elapsed < cfg.step_interval / 2,
+            "copying {CHUNKS} chunks took {elapsed:?}"
+        );
+    }
+}
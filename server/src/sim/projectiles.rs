@@ -0,0 +1,373 @@
+//! Thrown blocks, which fly until they land and become blocks again
+
+use std::{fmt, time::Duration};
+
+use hecs::Entity;
+use tracing::trace;
+
+use common::{
+    coords::locate_voxel,
+    inventory::Inventory,
+    projectile::Projectile,
+    proto::{BlockUpdate, Character, Component, Interactable, Position},
+    world::{Material, Shape},
+    EntityId, Step,
+};
+
+use super::Sim;
+use crate::activity::RegionState;
+
+/// Shortest time between a character's throws, so that a held key can't empty its inventory at
+/// once
+const THROW_INTERVAL: Duration = Duration::from_millis(250);
+
+impl Sim {
+    /// Have the character `thrower` throw a unit of `material` from its inventory, from its eyes
+    /// along where it's looking
+    pub(super) fn throw(
+        &mut self,
+        thrower: Entity,
+        material: Material,
+    ) -> Result<(), ThrowRefusal> {
+        {
+            let mut last_throw = self.world.get::<&mut LastThrow>(thrower).unwrap();
+            if let Some(step) = last_throw.0 {
+                let elapsed = self.cfg.step_interval * u32::try_from(self.step - step).unwrap();
+                if elapsed < THROW_INTERVAL {
+                    return Err(ThrowRefusal::TooSoon);
+                }
+            }
+            last_throw.0 = Some(self.step);
+        }
+        if !self
+            .world
+            .get::<&mut Inventory>(thrower)
+            .unwrap()
+            .try_remove(material)
+        {
+            return Err(ThrowRefusal::NotHeld);
+        }
+        let (position, velocity, name) = {
+            let mut query = self
+                .world
+                .query_one::<(&Position, &Character)>(thrower)
+                .unwrap();
+            let (&position, character) = query.get().unwrap();
+            let state = &character.state;
+            let forward = state.orientation * -na::Vector3::z();
+            (
+                position,
+                forward * self.cfg.throw_speed + state.velocity,
+                character.name.clone(),
+            )
+        };
+        let id = self.spawn_projectile(position, Projectile::new(material, velocity), name);
+        trace!(entity = %id, ?material, "thrown");
+        Ok(())
+    }
+
+    /// Add a block thrown by the player called `thrower` to the world, in flight from `position`
+    ///
+    /// Thrown blocks are transient, never being saved, so they're spawned apart from other
+    /// entities.
+    fn spawn_projectile(
+        &mut self,
+        position: Position,
+        projectile: Projectile,
+        thrower: String,
+    ) -> EntityId {
+        let (entity_ids, retired_ids) = (&self.entity_ids, &self.retired_ids);
+        let id = self
+            .id_allocator
+            .transient(|id| entity_ids.contains_key(&id) || retired_ids.contains_key(&id));
+        let entity = self
+            .world
+            .spawn((id, position, projectile, Thrower(thrower)));
+        self.graph_entities.insert(position.node, entity);
+        let previous = self.entity_ids.insert(id, entity);
+        debug_assert!(previous.is_none(), "entity ID {id} assigned twice");
+        self.spawns.push(entity);
+        id
+    }
+
+    /// Move thrown blocks through a step of `dt` seconds, returning those that have come to rest
+    ///
+    /// Those in hibernating regions are left hanging until a character comes near.
+    pub(super) fn step_projectiles(&mut self, dt: f32) -> Vec<Entity> {
+        let mut settled = Vec::new();
+        let mut transitions = Vec::new();
+        for (entity, (position, projectile)) in self
+            .world
+            .query::<(&mut Position, &mut Projectile)>()
+            .iter()
+        {
+            if self.region_state(position.node) == RegionState::Hibernating {
+                continue;
+            }
+            let prev_node = position.node;
+            projectile.step(&self.cfg, &self.graph, position, dt);
+            if position.node != prev_node {
+                transitions.push((entity, prev_node, position.node));
+            }
+            if projectile.at_rest() {
+                settled.push(entity);
+            }
+        }
+        for (entity, from, to) in transitions {
+            self.graph_entities.remove(from, entity);
+            self.graph_entities.insert(to, entity);
+        }
+        settled
+    }
+
+    /// Turn the settled projectile `entity` into a block in the voxel its center lies in, checked
+    /// as a placement by its thrower would be, returning the block update if it was placed
+    ///
+    /// Where no block can go, as when it would trap one of the characters at `characters`, the
+    /// projectile is dropped there as something to pick up instead. Projectiles in chunks whose
+    /// saved voxels haven't arrived are left as they are until they have.
+    pub(super) fn land_projectile(
+        &mut self,
+        entity: Entity,
+        characters: &[Position],
+    ) -> Option<BlockUpdate> {
+        let position = *self.world.get::<&Position>(entity).unwrap();
+        let voxel = locate_voxel(&self.graph, self.graph.layout(), &position);
+        if voxel.is_some_and(|(chunk, _, _)| self.awaiting_save(chunk)) {
+            return None;
+        }
+        let material = self.world.get::<&Projectile>(entity).unwrap().material;
+        let thrower = self.world.get::<&Thrower>(entity).unwrap().0.clone();
+        self.destroy(entity);
+        let block_update = voxel.map(|(chunk_id, coords, _)| BlockUpdate {
+            chunk_id,
+            coords,
+            new_material: material,
+            new_shape: Shape::FULL,
+            // Unused, being authored by nobody
+            sequence: 0,
+        });
+        let placed = block_update.filter(|block_update| {
+            // Thrown blocks only ever fill empty space
+            self.check_block_update(block_update, &thrower, characters) == Ok(Material::Void)
+        });
+        let Some(block_update) = placed else {
+            trace!(?material, "thrown block dropped");
+            self.spawn(
+                position,
+                vec![Component::Interactable(Interactable::Pickup(material))],
+            );
+            return None;
+        };
+        let previous = self.apply_block_update(&block_update);
+        self.record_edit_by(&thrower, &block_update, previous);
+        Some(block_update)
+    }
+}
+
+/// Why a character's throw was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum ThrowRefusal {
+    /// The character threw something too recently
+    TooSoon,
+    /// The character has none of the material to throw
+    NotHeld,
+}
+
+impl fmt::Display for ThrowRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ThrowRefusal::TooSoon => f.pad("thrown too soon after the last throw"),
+            ThrowRefusal::NotHeld => f.pad("material not held"),
+        }
+    }
+}
+
+/// Step of a character's latest throw, if any
+#[derive(Debug, Default)]
+pub(super) struct LastThrow(Option<Step>);
+
+/// Name of the player who threw a projectile, on whose behalf it's placed where it lands
+#[derive(Debug)]
+struct Thrower(String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        coords::voxel_center_position,
+        graph::NodeId,
+        math,
+        proto::{CharacterInput, Spawns},
+        test_graphs::surface_elevation,
+    };
+
+    use crate::sim::fixtures::standing_on;
+
+    /// The position `height` meters above the ground of `standing_on`, `ahead` meters to one side
+    /// of where characters are raised
+    fn above_ground(sim: &Sim, ahead: f32, height: f32) -> Position {
+        let m = sim.cfg.meters_to_absolute;
+        let state = &sim.graph.get(NodeId::ROOT).as_ref().unwrap().state;
+        let up = state.up_direction().xyz().normalize();
+        let across = (na::Vector3::x() - up * up.x).normalize();
+        Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(
+                &(up * ((3.0 + height) * m - state.elevation()) + across * ahead * m),
+            ),
+        }
+    }
+
+    /// Step `sim` until a projectile lands, returning the step's changes
+    fn until_landed(sim: &mut Sim, save: &save::Save, id: EntityId) -> Spawns {
+        for _ in 0..50 {
+            let (spawns, _, _) = sim.step(save);
+            if spawns.despawns.contains(&id) {
+                return spawns;
+            }
+        }
+        panic!("projectile never landed");
+    }
+
+    #[test]
+    fn settled_projectiles_become_blocks() {
+        let (save, mut sim, _, _file) = standing_on(Material::Dirt);
+        let id = sim.spawn_projectile(
+            above_ground(&sim, 3.0, 1.0),
+            Projectile::new(Material::WoodPlanks, na::Vector3::zeros()),
+            "test".into(),
+        );
+        assert!(id.is_transient());
+        let (spawns, _, _) = sim.step(&save);
+        let (_, components) = spawns.spawns.iter().find(|x| x.0 == id).unwrap();
+        assert!(components
+            .iter()
+            .any(|x| matches!(x, Component::Projectile(Material::WoodPlanks))));
+
+        let spawns = until_landed(&mut sim, &save, id);
+        assert!(spawns.spawns.is_empty());
+        assert_eq!(spawns.block_updates.len(), 1);
+        let (author, ref block_update) = spawns.block_updates[0];
+        // Not to be mistaken for the outcome of a prediction
+        assert_eq!(author, EntityId::NOBODY);
+        assert_eq!(block_update.new_material, Material::WoodPlanks);
+        let (chunk, coords) = (block_update.chunk_id, block_update.coords);
+        assert_eq!(
+            sim.graph.get_block(chunk, coords),
+            Some(Material::WoodPlanks)
+        );
+        // Just above the ground it fell on
+        let m = sim.cfg.meters_to_absolute;
+        let height = surface_elevation(
+            &sim.graph,
+            &voxel_center_position(sim.graph.layout(), chunk, coords),
+        );
+        assert!(
+            height > 3.0 * m && height < 4.5 * m,
+            "landed at {} m",
+            height / m
+        );
+
+        // Saved as a change to the chunk, never as an entity
+        let changes = sim.take_changes();
+        assert!(changes
+            .chunk(sim.graph.hash_of(chunk.node), chunk.vertex as u32)
+            .is_some());
+        assert_eq!(changes.entity(id.to_bits()), None);
+    }
+
+    #[test]
+    fn projectiles_landing_on_characters_are_dropped() {
+        let (save, mut sim, entity, _file) = standing_on(Material::Dirt);
+        // Falling through the character to land at its feet
+        let position = *sim.world.get::<&Position>(entity).unwrap();
+        let id = sim.spawn_projectile(
+            position,
+            Projectile::new(Material::WoodPlanks, na::Vector3::zeros()),
+            "test".into(),
+        );
+        let spawns = until_landed(&mut sim, &save, id);
+        assert!(spawns.block_updates.is_empty());
+        let [(pickup, ref components)] = spawns.spawns[..] else {
+            panic!("expected one spawn, got {:?}", spawns.spawns);
+        };
+        assert!(!pickup.is_transient());
+        assert!(components.iter().any(|x| matches!(
+            x,
+            Component::Interactable(Interactable::Pickup(Material::WoodPlanks))
+        )));
+        assert!(!components
+            .iter()
+            .any(|x| matches!(x, Component::Projectile(_))));
+    }
+
+    #[test]
+    fn throws_spend_inventory() {
+        let (save, mut sim, entity, _file) = standing_on(Material::Dirt);
+        let throw = |sim: &mut Sim| {
+            sim.world.get::<&mut CharacterInput>(entity).unwrap().throw =
+                Some(Material::WoodPlanks);
+            sim.step(&save)
+        };
+        let projectiles = |spawns: &Spawns| {
+            spawns
+                .spawns
+                .iter()
+                .filter(|(_, components)| {
+                    components
+                        .iter()
+                        .any(|x| matches!(x, Component::Projectile(_)))
+                })
+                .count()
+        };
+
+        // Nothing to throw
+        let (spawns, _, inventories) = throw(&mut sim);
+        assert_eq!(projectiles(&spawns), 0);
+        assert!(inventories.is_empty());
+
+        assert!(sim
+            .world
+            .get::<&mut Inventory>(entity)
+            .unwrap()
+            .try_add(Material::WoodPlanks, 2));
+        sim.world.get::<&mut LastThrow>(entity).unwrap().0 = None;
+        let (spawns, _, inventories) = throw(&mut sim);
+        assert_eq!(projectiles(&spawns), 1);
+        assert_eq!(inventories.len(), 1);
+        assert_eq!(inventories[0].1.count(Material::WoodPlanks), 1);
+        // From the character's eyes, along where it looks
+        let (id, _) = spawns.spawns[0];
+        let projectile = sim.entity_ids[&id];
+        let (from, eyes) = (
+            *sim.world.get::<&Position>(projectile).unwrap(),
+            *sim.world.get::<&Position>(entity).unwrap(),
+        );
+        assert_eq!((from.node, from.local), (eyes.node, eyes.local));
+        let expected = {
+            let state = &sim.world.get::<&Character>(entity).unwrap().state;
+            state.orientation * -na::Vector3::z() * sim.cfg.throw_speed + state.velocity
+        };
+        let velocity = sim.world.get::<&Projectile>(projectile).unwrap().velocity;
+        assert!((velocity - expected).norm() < 1e-6);
+
+        // Thrown once, however long the input stands
+        let (spawns, _, inventories) = sim.step(&save);
+        assert_eq!(projectiles(&spawns), 0);
+        assert!(inventories.is_empty());
+
+        // Too soon after the last throw
+        let (spawns, _, inventories) = throw(&mut sim);
+        assert_eq!(projectiles(&spawns), 0);
+        assert!(inventories.is_empty());
+
+        let interval = THROW_INTERVAL.as_nanos() / sim.cfg.step_interval.as_nanos();
+        for _ in 0..interval {
+            sim.step(&save);
+        }
+        let (spawns, _, inventories) = throw(&mut sim);
+        assert_eq!(projectiles(&spawns), 1);
+        assert_eq!(inventories[0].1.count(Material::WoodPlanks), 0);
+    }
+}
@@ -0,0 +1,190 @@
+//! Regions of the world where edits are restricted, persisted in the save
+
+use tracing::{error, warn};
+
+use common::protection::ProtectedRegion;
+
+use super::{persistence::decode_path, Sim};
+use crate::protection::InvalidRegion;
+
+impl Sim {
+    /// Protect `region`, replacing any of the same name
+    ///
+    /// Regions may extend no farther than the view distance, so that the nodes they cover are no
+    /// more than those built around a single character.
+    pub fn set_protected_region(&mut self, region: ProtectedRegion) -> Result<(), InvalidRegion> {
+        let name = region.name.clone();
+        self.protected_regions.insert(
+            &mut self.graph,
+            self.cfg.meters_to_absolute,
+            self.cfg.view_distance,
+            region,
+        )?;
+        self.dirty_protected_regions.insert(name);
+        Ok(())
+    }
+
+    /// Lift the protection of the region called `name`, returning whether there was one
+    pub fn remove_protected_region(&mut self, name: &str) -> bool {
+        if !self.protected_regions.remove(name) {
+            return false;
+        }
+        self.dirty_protected_regions.insert(name.into());
+        true
+    }
+}
+
+/// Read every protected region from `save`, skipping any that are malformed
+pub(super) fn load_protected_regions(save: &save::Save) -> Vec<ProtectedRegion> {
+    let stored = save
+        .read()
+        .map_err(save::GetError::from)
+        .and_then(|guard| guard.get()?.get_protected_regions());
+    let stored = match stored {
+        Ok(x) => x,
+        Err(e) => {
+            error!(error = %e, "couldn't load protected regions");
+            return Vec::new();
+        }
+    };
+    stored
+        .into_iter()
+        .filter_map(|(name, stored)| {
+            let region = decode_protected_region(name, stored);
+            if region.is_none() {
+                warn!("ignoring malformed protected region");
+            }
+            region
+        })
+        .collect()
+}
+
+pub(super) fn encode_protected_region(region: &ProtectedRegion) -> save::ProtectedRegion {
+    save::ProtectedRegion {
+        path: region
+            .center_path
+            .0
+            .iter()
+            .map(|&side| side as u32)
+            .collect(),
+        radius: region.radius,
+        allowed: region.allowed.iter().cloned().collect(),
+    }
+}
+
+fn decode_protected_region(name: String, stored: save::ProtectedRegion) -> Option<ProtectedRegion> {
+    Some(ProtectedRegion {
+        name,
+        center_path: decode_path(&stored.path)?,
+        radius: stored.radius,
+        allowed: stored.allowed.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use common::{
+        dodeca,
+        graph::NodeId,
+        node::{ChunkId, Coords},
+        node_path::NodePath,
+        proto::{BlockUpdate, BlockUpdateRejection, ClientHello, RejectionReason},
+        world::{Material, Shape},
+        SimConfig, SimConfigRaw,
+    };
+
+    use crate::sim::fixtures::step_with_requests;
+
+    #[test]
+    fn protected_regions_restrict_block_updates() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(4.0),
+            // Characters spawn at the origin
+            spawn_distance: Some(0),
+            ..Default::default()
+        }));
+        let region = ProtectedRegion {
+            name: "spawn".into(),
+            center_path: NodePath::default(),
+            radius: 3.0,
+            allowed: ["a".to_owned()].into(),
+        };
+        let mut sim = Sim::new(cfg.clone(), &save);
+        // Covering it would mean building nodes beyond the view distance
+        assert_eq!(
+            sim.set_protected_region(ProtectedRegion {
+                radius: 5.0,
+                ..region.clone()
+            }),
+            Err(InvalidRegion::Radius)
+        );
+        sim.set_protected_region(region.clone()).unwrap();
+        save.apply(&sim.take_changes()).unwrap();
+        drop(sim);
+
+        // Protection outlasts the server
+        let mut sim = Sim::new(cfg.clone(), &save);
+        assert_eq!(sim.protected_regions.get("spawn"), Some(&region));
+        let (_, a) = sim.spawn_character(ClientHello::new("a"));
+        let (_, b) = sim.spawn_character(ClientHello::new("b"));
+        sim.step(&save);
+
+        let chunk_id = ChunkId::new(NodeId::ROOT, dodeca::Vertex::A);
+        let coords = Coords([0, 0, 0]);
+        let fill = |sim: &mut Sim| {
+            let _ = sim.graph.update_block(&BlockUpdate {
+                chunk_id,
+                coords,
+                new_material: Material::Dirt,
+                new_shape: Shape::FULL,
+                sequence: 0,
+            });
+        };
+        let breaking = |sequence| {
+            Some(BlockUpdate {
+                chunk_id,
+                coords,
+                new_material: Material::Void,
+                new_shape: Shape::FULL,
+                sequence,
+            })
+        };
+        fill(&mut sim);
+        let (spawns, inventories) = step_with_requests(&mut sim, &save, &[(b, breaking(1))]);
+        assert!(spawns.block_updates.is_empty());
+        assert!(inventories.is_empty());
+        assert_eq!(
+            sim.take_rejected_block_updates(),
+            [(
+                b,
+                BlockUpdateRejection {
+                    sequence: 1,
+                    reason: RejectionReason::Protected("spawn".into())
+                }
+            )]
+        );
+        assert_eq!(sim.graph.get_block(chunk_id, coords), Some(Material::Dirt));
+
+        // Those allowed change blocks as usual
+        let (spawns, _) = step_with_requests(&mut sim, &save, &[(a, breaking(1))]);
+        assert_eq!(spawns.block_updates.len(), 1);
+
+        // Lifting the protection takes effect at once
+        fill(&mut sim);
+        assert!(sim.remove_protected_region("spawn"));
+        assert!(!sim.remove_protected_region("spawn"));
+        let (spawns, _) = step_with_requests(&mut sim, &save, &[(b, breaking(2))]);
+        assert_eq!(spawns.block_updates.len(), 1);
+        assert!(sim.take_rejected_block_updates().is_empty());
+
+        save.apply(&sim.take_changes()).unwrap();
+        drop(sim);
+        let sim = Sim::new(cfg, &save);
+        assert!(sim.protected_regions.get("spawn").is_none());
+    }
+}
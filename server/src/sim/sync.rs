@@ -0,0 +1,498 @@
+//! Bringing clients up to date with the graph and chunks they need
+
+use std::sync::Arc;
+
+use fxhash::FxHashSet;
+
+use common::{
+    dodeca,
+    graph::{Graph, NodeId},
+    node::{Chunk, ChunkId},
+    node_path::NodePath,
+    proto::{
+        Capabilities, ChunkDiff, EncodedGraphRegion, FreshNode, GraphRegion, SerializableVoxelData,
+        Spawns,
+    },
+    EntityId,
+};
+
+use super::{dump_entity, Sim};
+use crate::{activity::RegionActivity, graph_regions::GraphRegions, stats::GraphRegionStats};
+
+impl Sim {
+    /// Collect information about all entities, for transmission to a new client supporting
+    /// `capabilities`
+    ///
+    /// Clients sent graph regions are sent the graph and its modified chunks that way instead.
+    pub fn snapshot(&self, capabilities: Capabilities) -> Spawns {
+        let mut spawns = Spawns {
+            step: self.step,
+            spawns: Vec::new(),
+            despawns: Vec::new(),
+            nodes: Vec::new(),
+            block_updates: Vec::new(),
+            block_fills: Vec::new(),
+            modified_chunks: Vec::new(),
+            chunk_diffs: Vec::new(),
+        };
+        for (entity, &id) in &mut self.world.query::<&EntityId>() {
+            spawns.spawns.push((id, dump_entity(&self.world, entity)));
+        }
+        if sends_graph_regions(capabilities) {
+            return spawns;
+        }
+        spawns.nodes = self
+            .graph
+            .tree()
+            .map(|(side, parent)| FreshNode { side, parent })
+            .collect();
+        debug_assert!(parents_first(&self.graph, &spawns.nodes));
+        for &chunk_id in self.modified_chunks.keys() {
+            self.describe_modified_chunk(
+                chunk_id,
+                capabilities.contains(Capabilities::CHUNK_DIFFS),
+                &mut spawns.chunk_diffs,
+                &mut spawns.modified_chunks,
+            );
+        }
+        spawns
+    }
+
+    /// Add the changes made to the modified chunk `chunk_id` to `chunk_diffs` if `diffs` are
+    /// wanted and they're few enough, or its voxels to `modified_chunks` otherwise
+    fn describe_modified_chunk(
+        &self,
+        chunk_id: ChunkId,
+        diffs: bool,
+        chunk_diffs: &mut Vec<ChunkDiff>,
+        modified_chunks: &mut Vec<(ChunkId, SerializableVoxelData)>,
+    ) {
+        if let Some(changes) = &self.modified_chunks[&chunk_id] {
+            if diffs && changes.len() <= max_diff_len(self.cfg.chunk_size) {
+                chunk_diffs.push(ChunkDiff {
+                    chunk: chunk_id,
+                    changes: changes
+                        .iter()
+                        .map(|(&coords, &(material, shape))| (coords, material, shape))
+                        .collect(),
+                });
+                return;
+            }
+        }
+        let voxels = match self.graph.get(chunk_id.node).as_ref().unwrap().chunks[chunk_id.vertex] {
+            Chunk::Populated { ref voxels, .. } => voxels,
+            _ => panic!("ungenerated chunk is marked as modified"),
+        };
+        modified_chunks.push((chunk_id, voxels.to_serializable(self.cfg.chunk_size)));
+    }
+
+    /// Divide the graph into regions `depth` generations deep for sending to clients, forgetting
+    /// any regions already encoded
+    pub fn set_graph_region_depth(&mut self, depth: u32) {
+        self.graph_regions = GraphRegions::new(depth);
+        // Regions are found afresh around each character in the next step
+        self.activity = RegionActivity::new(self.cfg.view_distance, self.cfg.step_interval);
+    }
+
+    /// Roots of the graph regions containing each of `nodes` that exists, without repeats and
+    /// nearest the origin first
+    pub fn graph_region_roots(&self, nodes: impl IntoIterator<Item = NodeId>) -> Vec<NodeId> {
+        let mut seen = FxHashSet::default();
+        let mut roots = nodes
+            .into_iter()
+            .filter(|&node| self.graph.contains(node))
+            .map(|node| self.graph_regions.root_of(&self.graph, node))
+            .filter(|&root| seen.insert(root))
+            .collect::<Vec<_>>();
+        roots.sort_by_key(|&root| self.graph.length(root));
+        roots
+    }
+
+    /// The encoded graph region rooted at `root`, which must be the root of a region
+    pub fn graph_region(&mut self, root: NodeId) -> Arc<EncodedGraphRegion> {
+        if let Some(region) = self.graph_regions.cached(root) {
+            return region;
+        }
+        let region = self.build_graph_region(root);
+        self.graph_regions.insert(root, &region)
+    }
+
+    pub(super) fn build_graph_region(&self, root: NodeId) -> GraphRegion {
+        let members = self.graph_regions.members(&self.graph, root);
+        let mut region = GraphRegion {
+            root: NodePath::to(&self.graph, root),
+            nodes: members
+                .iter()
+                .map(|&node| {
+                    let side = self.graph.parent(node).unwrap();
+                    FreshNode {
+                        side,
+                        parent: self.graph.neighbor(node, side).unwrap(),
+                    }
+                })
+                .collect(),
+            modified_chunks: Vec::new(),
+            chunk_diffs: Vec::new(),
+            block_entities: Vec::new(),
+        };
+        for node in std::iter::once(root).chain(members) {
+            for vertex in dodeca::Vertex::iter() {
+                let chunk = ChunkId::new(node, vertex);
+                region.block_entities.extend(
+                    self.block_entities
+                        .in_chunk(chunk)
+                        .map(|(coords, entity)| (chunk, coords, entity.clone())),
+                );
+                if self.modified_chunks.contains_key(&chunk) {
+                    self.describe_modified_chunk(
+                        chunk,
+                        true,
+                        &mut region.chunk_diffs,
+                        &mut region.modified_chunks,
+                    );
+                }
+            }
+        }
+        region
+    }
+
+    pub fn graph_region_stats(&self) -> GraphRegionStats {
+        self.graph_regions.stats()
+    }
+
+    /// The way from the origin to each of `nodes` that exists, for a client that received nodes
+    /// branching from them but not the nodes themselves
+    pub fn resync_nodes(&self, nodes: &[NodeId]) -> Spawns {
+        let mut sent = FxHashSet::default();
+        let mut result = Vec::new();
+        for &node in nodes {
+            if !self.graph.contains(node) {
+                continue;
+            }
+            let mut path = Vec::new();
+            let mut child = node;
+            while let Some(side) = self.graph.parent(child) {
+                // Ways to different nodes share their beginnings, which need only be sent once
+                if !sent.insert(child) {
+                    break;
+                }
+                let parent = self.graph.neighbor(child, side).unwrap();
+                path.push(FreshNode { side, parent });
+                child = parent;
+            }
+            result.extend(path.into_iter().rev());
+        }
+        let spawns = Spawns {
+            step: self.step,
+            spawns: Vec::new(),
+            despawns: Vec::new(),
+            nodes: result,
+            block_updates: Vec::new(),
+            block_fills: Vec::new(),
+            modified_chunks: Vec::new(),
+            chunk_diffs: Vec::new(),
+        };
+        debug_assert!(parents_first(&self.graph, &spawns.nodes));
+        spawns
+    }
+}
+
+/// Whether a client supporting `capabilities` is sent the graph by region
+pub fn sends_graph_regions(capabilities: Capabilities) -> bool {
+    capabilities.contains(Capabilities::GRAPH_REGIONS | Capabilities::CHUNK_DIFFS)
+}
+
+/// Whether each of `nodes` that branches from another of them comes after it, so that clients can
+/// add them to their graphs in order
+pub(super) fn parents_first(graph: &Graph, nodes: &[FreshNode]) -> bool {
+    let children = nodes
+        .iter()
+        .map(|x| graph.neighbor(x.parent, x.side).unwrap())
+        .collect::<FxHashSet<_>>();
+    let mut added = FxHashSet::default();
+    nodes.iter().all(|node| {
+        let ready = !children.contains(&node.parent) || added.contains(&node.parent);
+        added.insert(graph.neighbor(node.parent, node.side).unwrap());
+        ready
+    })
+}
+
+/// Most voxels a modified chunk may differ from world generation by to be sent as a `ChunkDiff`
+///
+/// Each change costs about as much as two voxels of the whole chunk on the wire, so beyond this
+/// a diff would be the larger.
+fn max_diff_len(dimension: u8) -> usize {
+    usize::from(dimension).pow(3) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fxhash::FxHashMap;
+
+    use common::{
+        coords::locate_voxel,
+        node::{populate_fresh_nodes, CoordAxis, CoordDirection, Coords},
+        proto::{BlockUpdate, ClientHello, Position},
+        traversal::ensure_nearby,
+        world::{Material, Shape},
+        worldgen::ChunkParams,
+        SimConfig, SimConfigRaw,
+    };
+
+    use crate::sim::fixtures::{alone_in_the_void, offset_from_origin, step_with_requests};
+
+    #[test]
+    fn resync_sends_ways_to_nodes() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(2.0),
+            ..Default::default()
+        }));
+        let sim = Sim::new(cfg, &save);
+        let mut nodes = sim
+            .graph
+            .tree()
+            .map(|(side, parent)| sim.graph.neighbor(parent, side).unwrap())
+            .collect::<Vec<_>>();
+        nodes.sort_unstable_by_key(|&x| std::cmp::Reverse(sim.graph.length(x)));
+        // A node the server has never heard of
+        let mut farther = Graph::new(12);
+        ensure_nearby(&mut farther, &Position::origin(), 3.0);
+        let unknown = farther
+            .tree()
+            .map(|(side, parent)| farther.neighbor(parent, side).unwrap())
+            .find(|&x| !sim.graph.contains(x))
+            .unwrap();
+        let requested = [nodes[0], nodes[1], unknown];
+
+        let spawns = sim.resync_nodes(&requested);
+        assert!(parents_first(&sim.graph, &spawns.nodes));
+        // Nothing is sent twice
+        let distinct = spawns
+            .nodes
+            .iter()
+            .map(|x| (x.parent, x.side))
+            .collect::<FxHashSet<_>>();
+        assert_eq!(distinct.len(), spawns.nodes.len());
+        let mut graph = Graph::new(12);
+        for node in &spawns.nodes {
+            assert!(graph.contains(node.parent), "sent before its parent");
+            graph.ensure_neighbor(node.parent, node.side);
+        }
+        assert!(graph.contains(requested[0]) && graph.contains(requested[1]));
+    }
+
+    #[test]
+    fn modified_chunks_sync_as_diffs() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            // Characters spawn at the origin
+            spawn_distance: Some(0),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let (_, a) = sim.spawn_character(ClientHello::new("a"));
+        sim.step(&save);
+
+        // Filled in an earlier session, so known only from the chunk's contents
+        let chunk_id = ChunkId::new(NodeId::ROOT, dodeca::Vertex::A);
+        let filled = [Coords([0, 0, 0]), Coords([1, 0, 0]), Coords([2, 0, 0])];
+        let slab = Shape::slab(CoordAxis::Y, CoordDirection::Minus);
+        for (coords, new_shape) in filled.into_iter().zip([Shape::FULL, Shape::FULL, slab]) {
+            let _ = sim.graph.update_block(&BlockUpdate {
+                chunk_id,
+                coords,
+                new_material: Material::Dirt,
+                new_shape,
+                sequence: 0,
+            });
+        }
+        let (spawns, _) = step_with_requests(
+            &mut sim,
+            &save,
+            &[(
+                a,
+                Some(BlockUpdate {
+                    chunk_id,
+                    coords: filled[0],
+                    new_material: Material::Void,
+                    new_shape: Shape::FULL,
+                    sequence: 0,
+                }),
+            )],
+        );
+        assert_eq!(spawns.block_updates.len(), 1);
+
+        // Applying the diff to a freshly generated chunk reproduces the server's
+        let snapshot = sim.snapshot(Capabilities::ALL);
+        assert!(snapshot.modified_chunks.is_empty());
+        assert_eq!(snapshot.chunk_diffs.len(), 1);
+        let diff = &snapshot.chunk_diffs[0];
+        assert_eq!(diff.chunk, chunk_id);
+        assert_eq!(diff.changes.len(), filled.len());
+        let mut graph = Graph::new(cfg.chunk_size);
+        ensure_nearby(
+            &mut graph,
+            &Position::origin(),
+            f64::from(cfg.view_distance),
+        );
+        populate_fresh_nodes(&mut graph);
+        let voxels = ChunkParams::new(cfg.chunk_size, &graph, chunk_id, &cfg.terrain)
+            .unwrap()
+            .generate_voxels();
+        graph.populate_chunk(chunk_id, voxels, false);
+        for &(coords, material, shape) in &diff.changes {
+            let _ = graph.set_block(chunk_id, coords, material, shape);
+        }
+        for coords in filled {
+            assert_eq!(
+                graph.get_block(chunk_id, coords),
+                sim.graph.get_block(chunk_id, coords)
+            );
+            assert_eq!(
+                graph.get_shape(chunk_id, coords),
+                sim.graph.get_shape(chunk_id, coords)
+            );
+        }
+        assert_eq!(graph.get_shape(chunk_id, filled[2]), Some(slab));
+        assert_eq!(
+            sim.graph.get_block(chunk_id, filled[1]),
+            Some(Material::Dirt)
+        );
+
+        // Chunks that differ too much, or in unknown ways, or to clients that don't understand
+        // diffs, are sent whole
+        let limit = max_diff_len(cfg.chunk_size);
+        let changes = |n| {
+            (0..n)
+                .map(|i| {
+                    let coords = Coords([(i % 12) as u8, (i / 12 % 12) as u8, (i / 144) as u8]);
+                    (coords, (Material::Dirt, Shape::FULL))
+                })
+                .collect::<FxHashMap<_, _>>()
+        };
+        for (changes, as_diff) in [
+            (Some(changes(limit)), true),
+            (Some(changes(limit + 1)), false),
+            (None, false),
+        ] {
+            sim.modified_chunks.insert(chunk_id, changes);
+            let snapshot = sim.snapshot(Capabilities::ALL);
+            assert_eq!(snapshot.chunk_diffs.len(), usize::from(as_diff));
+            assert_eq!(snapshot.modified_chunks.len(), usize::from(!as_diff));
+            let snapshot = sim.snapshot(Capabilities::SHARED_WAYPOINTS);
+            assert!(snapshot.chunk_diffs.is_empty());
+            assert_eq!(snapshot.modified_chunks.len(), 1);
+        }
+    }
+
+    /// Every node of `sim`'s graph
+    fn all_nodes(sim: &Sim) -> Vec<NodeId> {
+        std::iter::once(NodeId::ROOT)
+            .chain(
+                sim.graph
+                    .tree()
+                    .map(|(side, parent)| sim.graph.neighbor(parent, side).unwrap()),
+            )
+            .collect()
+    }
+
+    /// Change the voxel `meters` from the origin along the x axis to `material` as a character
+    /// would
+    fn change_block_ahead(sim: &mut Sim, meters: f32, material: Material) -> (ChunkId, Coords) {
+        let position = offset_from_origin(sim, na::Vector3::x() * meters);
+        let (chunk_id, coords, _) =
+            locate_voxel(&sim.graph, sim.graph.layout(), &position).unwrap();
+        sim.apply_block_update(&BlockUpdate {
+            chunk_id,
+            coords,
+            new_material: material,
+            new_shape: Shape::FULL,
+            sequence: 0,
+        });
+        (chunk_id, coords)
+    }
+
+    #[test]
+    fn graph_region_round_trip() {
+        let (_save, mut sim, _, _file) = alone_in_the_void();
+        let (chunk, coords) = change_block_ahead(&mut sim, 3.0, Material::Dirt);
+        let root = sim.graph_region_roots([chunk.node])[0];
+        let members = sim.graph_regions.members(&sim.graph, root);
+        assert!(!members.is_empty());
+
+        let region = sim.graph_region(root).decode().unwrap();
+        let mut graph = Graph::new(12);
+        assert_eq!(region.root.ensure(&mut graph), root);
+        for node in &region.nodes {
+            assert!(graph.contains(node.parent), "sent before its parent");
+            graph.ensure_neighbor(node.parent, node.side);
+        }
+        assert!(members.iter().all(|&x| graph.contains(x)));
+        assert_eq!(region.nodes.len(), members.len());
+        assert!(region.modified_chunks.is_empty());
+        assert_eq!(
+            region.chunk_diffs,
+            [ChunkDiff {
+                chunk,
+                changes: vec![(coords, Material::Dirt, Shape::FULL)],
+            }]
+        );
+    }
+
+    #[test]
+    fn graph_regions_invalidated_by_changes_within() {
+        let (save, mut sim, _, _file) = alone_in_the_void();
+        sim.step(&save);
+        let roots = sim.graph_region_roots(all_nodes(&sim));
+        assert!(roots.len() > 2);
+        // Roots of the regions encoded anew when every region is asked for
+        let reencoded = |sim: &mut Sim| {
+            roots
+                .iter()
+                .copied()
+                .filter(|&root| {
+                    let encoded = sim.graph_region_stats().encoded;
+                    sim.graph_region(root);
+                    sim.graph_region_stats().encoded > encoded
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(reencoded(&mut sim), roots);
+        assert!(reencoded(&mut sim).is_empty());
+
+        let (chunk, _) = change_block_ahead(&mut sim, 3.0, Material::Dirt);
+        assert_eq!(reencoded(&mut sim), sim.graph_region_roots([chunk.node]));
+
+        // Extend the graph at its edge, where only some regions lie
+        let (parent, side) = roots
+            .iter()
+            .rev()
+            .find_map(|&root| {
+                dodeca::Side::iter()
+                    .find(|&side| sim.graph.neighbor(root, side).is_none())
+                    .map(|side| (root, side))
+            })
+            .unwrap();
+        sim.graph.ensure_neighbor(parent, side);
+        let (spawns, _, _) = sim.step(&save);
+        let added = spawns
+            .nodes
+            .iter()
+            .map(|x| sim.graph.neighbor(x.parent, x.side).unwrap())
+            .collect::<Vec<_>>();
+        let expected = sim
+            .graph_region_roots(added)
+            .into_iter()
+            .filter(|x| roots.contains(x))
+            .collect::<FxHashSet<_>>();
+        assert!(!expected.is_empty());
+        let actual = reencoded(&mut sim);
+        assert!(actual.len() < roots.len());
+        assert_eq!(actual.into_iter().collect::<FxHashSet<_>>(), expected);
+    }
+}
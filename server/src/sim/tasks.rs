@@ -0,0 +1,188 @@
+//! Tasks scheduled to run at a later step, and the block changes they carry out
+
+use tracing::{debug, error, trace, warn};
+
+use common::{
+    node::ChunkId,
+    proto::{BlockUpdate, Character, Position},
+    Step,
+};
+
+use super::Sim;
+use crate::{
+    scheduler::{self, BlockChange, ScheduledTask, TaskId, TaskKind},
+    stats::TaskStats,
+};
+
+impl Sim {
+    /// Do `kind` `delay` steps from now, and every `repeat` steps after that if set, until
+    /// cancelled
+    ///
+    /// Delays and periods are limited to `scheduler::MAX_DELAY`. A task due now is done at the
+    /// start of the next step.
+    pub fn schedule(&mut self, delay: u32, repeat: Option<u32>, kind: TaskKind) -> TaskId {
+        let fire_step = self
+            .step
+            .wrapping_add(delay.min(scheduler::MAX_DELAY) as Step);
+        let id = self.scheduler.schedule(fire_step, repeat, kind);
+        trace!(task = %id, fire_step, "scheduled task");
+        id
+    }
+
+    /// Stop the task `id` from being done, returning whether it was pending
+    pub fn cancel_task(&mut self, id: TaskId) -> bool {
+        self.scheduler.cancel(id)
+    }
+
+    pub fn task_stats(&self) -> TaskStats {
+        TaskStats {
+            pending: self.scheduler.pending(),
+            deferred: self.scheduler.deferred(),
+        }
+    }
+
+    /// Do the tasks due this step, as many as the budget allows
+    pub(super) fn run_tasks(&mut self, save: &save::Save) {
+        for (id, kind) in self.scheduler.take_due(self.step, scheduler::TASK_BUDGET) {
+            trace!(task = %id, "running task");
+            match kind {
+                TaskKind::SetBlocks(changes) => {
+                    for change in changes {
+                        self.set_block(save, &change);
+                    }
+                }
+                TaskKind::Blast {
+                    path,
+                    local,
+                    radius,
+                    speed,
+                } => {
+                    let node = path.ensure(&mut self.graph);
+                    self.blast(&Position { node, local }, radius, speed);
+                }
+                TaskKind::Respawn(entity_id) => match self.entity_ids.get(&entity_id) {
+                    Some(&entity) if self.world.get::<&Character>(entity).is_ok() => {
+                        self.respawn(entity)
+                    }
+                    _ => debug!(task = %id, entity = %entity_id, "not respawning absent character"),
+                },
+            }
+        }
+    }
+
+    /// Change a block on the server's own account, generating or loading its chunk first if
+    /// needed
+    fn set_block(&mut self, save: &save::Save, change: &BlockChange) {
+        let node = change.path.ensure(&mut self.graph);
+        self.population.run(&mut self.graph, [node], 0);
+        let chunk = ChunkId::new(node, change.vertex);
+        self.populate_chunks(save, [chunk], true);
+        let (Some(material), Some(shape)) = (
+            self.graph.get_block(chunk, change.coords),
+            self.graph.get_shape(chunk, change.coords),
+        ) else {
+            warn!(?chunk, "couldn't populate chunk for scheduled block change");
+            return;
+        };
+        if (material, shape) == (change.material, change.shape) {
+            // An update with no effect can't be applied
+            return;
+        }
+        let block_update = BlockUpdate {
+            chunk_id: chunk,
+            coords: change.coords,
+            new_material: change.material,
+            new_shape: change.shape,
+            // Unused, being authored by nobody
+            sequence: 0,
+        };
+        self.apply_block_update(&block_update);
+        self.server_block_updates.push(block_update);
+    }
+}
+
+/// Tasks pending in `save`, which was written after `steps_before` steps, due as though this run
+/// picked up where that one left off
+pub(super) fn load_tasks(save: &save::Save, steps_before: u64) -> Vec<(u64, ScheduledTask)> {
+    let format = save.meta().task_format;
+    if format > scheduler::TASK_FORMAT {
+        error!(format, "ignoring tasks saved by a newer version");
+        return Vec::new();
+    }
+    let stored = save
+        .read()
+        .map_err(save::GetError::from)
+        .and_then(|guard| guard.get()?.get_tasks());
+    let stored = match stored {
+        Ok(x) => x,
+        Err(e) => {
+            error!(error = %e, "couldn't load tasks");
+            return Vec::new();
+        }
+    };
+    stored
+        .into_iter()
+        .filter_map(|(id, stored)| {
+            let task = scheduler::decode_task(stored, 0, steps_before);
+            if task.is_none() {
+                warn!(task = id, "ignoring malformed task");
+            }
+            Some((id, task?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        coords::locate_voxel,
+        node_path::NodePath,
+        world::{Material, Shape},
+        EntityId,
+    };
+
+    use crate::sim::fixtures::{alone_in_the_void, offset_from_origin};
+
+    #[test]
+    fn scheduled_blocks_survive_restart() {
+        let (save, mut sim, _, _file) = alone_in_the_void();
+        let position = offset_from_origin(&sim, na::Vector3::x() * 3.0);
+        let (chunk, coords, _) = locate_voxel(&sim.graph, sim.graph.layout(), &position).unwrap();
+        let path = NodePath::to(&sim.graph, chunk.node);
+        let change = BlockChange {
+            path: path.clone(),
+            vertex: chunk.vertex,
+            coords,
+            material: Material::Dirt,
+            shape: Shape::FULL,
+        };
+        sim.schedule(5, None, TaskKind::SetBlocks(vec![change.clone()]));
+        let cancelled = sim.schedule(5, None, TaskKind::SetBlocks(vec![change]));
+        assert!(sim.cancel_task(cancelled));
+        assert!(!sim.cancel_task(cancelled));
+        for _ in 0..3 {
+            sim.step(&save);
+        }
+        save.apply(&sim.take_changes()).unwrap();
+        let cfg = sim.cfg.clone();
+        drop(sim);
+
+        // Due as many steps into the new run as were left in the old
+        let mut sim = Sim::new(cfg, &save);
+        assert_eq!(sim.task_stats().pending, 1);
+        for _ in 0..2 {
+            let (spawns, _, _) = sim.step(&save);
+            assert!(spawns.block_updates.is_empty());
+        }
+        let (spawns, _, _) = sim.step(&save);
+        assert_eq!(spawns.block_updates.len(), 1);
+        assert_eq!(spawns.block_updates[0].0, EntityId::NOBODY);
+        let chunk = ChunkId::new(path.resolve(&sim.graph).unwrap(), chunk.vertex);
+        assert_eq!(sim.graph.get_block(chunk, coords), Some(Material::Dirt));
+        assert_eq!(sim.task_stats().pending, 0);
+        // Done once, and so forgotten by the save too
+        let batch = sim.take_changes();
+        assert_eq!(batch.task(0), Some(None));
+    }
+}
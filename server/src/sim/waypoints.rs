@@ -0,0 +1,137 @@
+//! Named places shared by every player, persisted in the save
+
+use std::collections::BTreeMap;
+
+use tracing::{error, warn};
+
+use common::waypoint::{self, InvalidName, Waypoint};
+
+use super::{persistence::decode_path, Sim};
+
+impl Sim {
+    /// Every shared waypoint, in order of name
+    pub fn waypoints(&self) -> impl Iterator<Item = &Waypoint> {
+        self.waypoints.values()
+    }
+
+    /// Share `waypoint`, replacing any of the same name
+    pub fn set_waypoint(&mut self, waypoint: Waypoint) -> Result<(), InvalidName> {
+        waypoint::validate_name(&waypoint.name)?;
+        self.dirty_waypoints.insert(waypoint.name.clone());
+        self.waypoints.insert(waypoint.name.clone(), waypoint);
+        Ok(())
+    }
+
+    /// Stop sharing the waypoint called `name`, returning whether there was one
+    pub fn remove_waypoint(&mut self, name: &str) -> bool {
+        if self.waypoints.remove(name).is_none() {
+            return false;
+        }
+        self.dirty_waypoints.insert(name.into());
+        true
+    }
+}
+
+/// Read every shared waypoint from `save`, skipping any that are malformed
+pub(super) fn load_waypoints(save: &save::Save) -> BTreeMap<String, Waypoint> {
+    let stored = save
+        .read()
+        .map_err(save::GetError::from)
+        .and_then(|guard| guard.get()?.get_waypoints());
+    let stored = match stored {
+        Ok(x) => x,
+        Err(e) => {
+            error!(error = %e, "couldn't load waypoints");
+            return BTreeMap::new();
+        }
+    };
+    stored
+        .into_iter()
+        .filter_map(|(name, stored)| {
+            let waypoint = decode_waypoint(name, stored);
+            if waypoint.is_none() {
+                warn!("ignoring malformed waypoint");
+            }
+            waypoint
+        })
+        .map(|waypoint| (waypoint.name.clone(), waypoint))
+        .collect()
+}
+
+pub(super) fn encode_waypoint(waypoint: &Waypoint) -> save::Waypoint {
+    let [r, g, b] = waypoint.color;
+    save::Waypoint {
+        path: waypoint.path.0.iter().map(|&side| side as u32).collect(),
+        translation: waypoint.local_translation.iter().copied().collect(),
+        color: u32::from_be_bytes([0, r, g, b]),
+        owner: waypoint.owner.clone(),
+    }
+}
+
+fn decode_waypoint(name: String, stored: save::Waypoint) -> Option<Waypoint> {
+    let path = decode_path(&stored.path)?;
+    let translation: [f32; 3] = stored.translation.try_into().ok()?;
+    let [_, r, g, b] = stored.color.to_be_bytes();
+    Some(Waypoint {
+        name,
+        path,
+        local_translation: translation.into(),
+        color: [r, g, b],
+        owner: stored.owner,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use common::{
+        dodeca,
+        graph::{Graph, NodeId},
+        math,
+        proto::Position,
+        SimConfig, SimConfigRaw,
+    };
+
+    #[test]
+    fn waypoints_persist() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let cfg = Arc::new(SimConfig::from_raw(&SimConfigRaw {
+            view_distance: Some(1.0),
+            ..Default::default()
+        }));
+        let mut sim = Sim::new(cfg.clone(), &save);
+        let mut graph = Graph::new(cfg.chunk_size);
+        let node = graph.ensure_neighbor(NodeId::ROOT, dodeca::Side::B);
+        let position = Position {
+            node,
+            local: math::translate_along(&na::Vector3::new(0.2, 0.1, -0.3)),
+        };
+        let base = Waypoint::new(&graph, &position, "base".into(), [10, 20, 30], "a".into());
+        let cave = Waypoint {
+            name: "big cave".into(),
+            ..base.clone()
+        };
+        sim.set_waypoint(base.clone()).unwrap();
+        sim.set_waypoint(cave).unwrap();
+        assert_eq!(
+            sim.set_waypoint(Waypoint {
+                name: "a/b".into(),
+                ..base.clone()
+            }),
+            Err(InvalidName::BadCharacter('/'))
+        );
+        save.apply(&sim.take_changes()).unwrap();
+        assert!(sim.remove_waypoint("big cave"));
+        assert!(!sim.remove_waypoint("big cave"));
+        save.apply(&sim.take_changes()).unwrap();
+        drop(sim);
+        drop(save);
+
+        let save = save::Save::open(file.path(), 12).unwrap();
+        let sim = Sim::new(cfg, &save);
+        assert_eq!(sim.waypoints().collect::<Vec<_>>(), [&base]);
+    }
+}
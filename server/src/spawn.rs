@@ -0,0 +1,444 @@
+//! Choosing where new characters appear
+//!
+//! Candidate spawn points are scattered over the terrain around the origin, each found by a random
+//! walk of a fixed number of nodes outward from the root which keeps close to the surface. Before
+//! a candidate can be used, a ray is cast down from above it to find the ground, which requires
+//! the chunks along the ray to be generated. Candidates that start inside the terrain or find no
+//! ground are discarded. Each new character is placed at the candidate farthest from those already
+//! in use, so that characters joining together don't spawn inside each other, and faces whichever
+//! way around it is most open, so that it doesn't start out staring at a wall.
+
+use fxhash::FxHashSet;
+
+use common::{
+    collision_math::Ray,
+    coords::locate_voxel,
+    dodeca::{self, Side},
+    graph::{Graph, NodeId},
+    graph_ray_casting::ray_cast,
+    math,
+    node::{populate_fresh_nodes, Chunk, ChunkId},
+    proto::Position,
+    rng::{Pcg, RngPurpose},
+    traversal::{ensure_nearby, RayTraverser},
+    world::Material,
+    SimConfig,
+};
+
+/// Number of random walks used to find candidates. Walks that end at the same node produce a
+/// single candidate.
+pub const CANDIDATES: usize = 16;
+
+/// Height above the reference surface from which ground is searched for
+const SEARCH_HEIGHT: f64 = 0.5;
+
+/// Number of evenly spaced directions around a spawn point considered for a character to face
+const HEADINGS: usize = 12;
+
+/// Meters along each heading searched for obstructions. Headings open at least this far are
+/// equally good.
+const LOOKOUT_DISTANCE: f32 = 8.0;
+
+/// The spawn point assigned to a character
+#[derive(Debug, Copy, Clone)]
+pub struct SpawnPoint(pub Position);
+
+pub struct SpawnPoints {
+    candidates: Vec<Candidate>,
+}
+
+struct Candidate {
+    /// Point directly above the candidate from which the ground is searched for
+    search: Position,
+    state: CandidateState,
+}
+
+enum CandidateState {
+    Unresolved,
+    Ground {
+        position: Position,
+        /// Location of `position` relative to the root node
+        root_point: na::Vector4<f64>,
+    },
+    Rejected,
+}
+
+impl SpawnPoints {
+    /// Pick up to `count` candidates `cfg.spawn_distance` nodes from the origin, deterministically
+    /// for a given `seed`
+    pub fn new(cfg: &SimConfig, graph: &mut Graph, count: usize, seed: u64) -> Self {
+        let mut rng = Pcg::for_node(seed, graph.hash_of(NodeId::ROOT) as u64, RngPurpose::Spawn);
+        let mut candidates = Vec::new();
+        if cfg.spawn_distance == 0 {
+            return Self { candidates };
+        }
+        populate_fresh_nodes(graph);
+
+        let mut visited = FxHashSet::default();
+        for _ in 0..count {
+            let mut node = NodeId::ROOT;
+            for _ in 0..cfg.spawn_distance {
+                let mut options = Vec::new();
+                for side in Side::iter() {
+                    let neighbor = graph.ensure_neighbor(node, side);
+                    if graph.length(neighbor) > graph.length(node) {
+                        options.push(neighbor);
+                    }
+                }
+                populate_fresh_nodes(graph);
+                // Stay just beneath the surface, so that each candidate lies under a distinct
+                // patch of ground
+                options.retain(|&x| {
+                    let elevation = graph.get(x).as_ref().unwrap().state.elevation();
+                    (-dodeca::BOUNDING_SPHERE_RADIUS_F32..0.0).contains(&elevation)
+                });
+                if options.is_empty() {
+                    break;
+                }
+                node = options[rng.index(options.len())];
+            }
+            if !visited.insert(node) {
+                continue;
+            }
+
+            let search = search_start(graph, node);
+            ensure_nearby(
+                graph,
+                &search,
+                2.0 * SEARCH_HEIGHT + dodeca::BOUNDING_SPHERE_RADIUS_F64,
+            );
+            candidates.push(Candidate {
+                search,
+                state: CandidateState::Unresolved,
+            });
+        }
+        populate_fresh_nodes(graph);
+        Self { candidates }
+    }
+
+    /// Whether every candidate has been checked for ground
+    pub fn is_resolved(&self) -> bool {
+        self.next_unresolved().is_none()
+    }
+
+    /// Chunks that must be populated before `resolve_next` can find the ground beneath the next
+    /// unresolved candidate
+    pub fn missing_chunks(&self, graph: &Graph) -> Vec<ChunkId> {
+        let Some(candidate) = self.next_unresolved() else {
+            return Vec::new();
+        };
+        let ray = search_ray(graph, &candidate.search);
+        let mut traverser = RayTraverser::new(graph, candidate.search, &ray, 0.0);
+        let mut result = Vec::new();
+        while let Some((chunk, _)) = traverser.next(search_tanh_distance()) {
+            if let Some(chunk) = chunk {
+                if let Some(Chunk::Fresh | Chunk::Generating) = graph.get_chunk(chunk) {
+                    result.push(chunk);
+                }
+            }
+        }
+        result
+    }
+
+    /// Find the ground beneath the next unresolved candidate, rejecting it if there's none or its
+    /// chunks are missing
+    pub fn resolve_next(&mut self, cfg: &SimConfig, graph: &Graph) {
+        let Some(candidate) = self
+            .candidates
+            .iter_mut()
+            .find(|x| matches!(x.state, CandidateState::Unresolved))
+        else {
+            return;
+        };
+        candidate.state = match find_ground(cfg, graph, &candidate.search) {
+            Some(position) => CandidateState::Ground {
+                position,
+                root_point: root_point(graph, &position),
+            },
+            None => CandidateState::Rejected,
+        };
+    }
+
+    /// The resolved candidate farthest from any of `occupied`, if any
+    ///
+    /// Ties are broken in favor of the earliest candidate, so the result depends only on the
+    /// candidates and `occupied`.
+    pub fn choose(&self, graph: &Graph, occupied: &[Position]) -> Option<Position> {
+        let occupied = occupied
+            .iter()
+            .map(|x| root_point(graph, x))
+            .collect::<Vec<_>>();
+        let mut best: Option<(f64, Position)> = None;
+        for candidate in &self.candidates {
+            let CandidateState::Ground {
+                position,
+                root_point,
+            } = candidate.state
+            else {
+                continue;
+            };
+            let separation = occupied
+                .iter()
+                .map(|x| math::distance(x, &root_point))
+                .fold(f64::INFINITY, f64::min);
+            if !matches!(best, Some((x, _)) if x >= separation) {
+                best = Some((separation, position));
+            }
+        }
+        best.map(|(_, position)| position)
+    }
+
+    fn next_unresolved(&self) -> Option<&Candidate> {
+        self.candidates
+            .iter()
+            .find(|x| matches!(x.state, CandidateState::Unresolved))
+    }
+}
+
+/// Level orientation, relative to `position`, of a character there facing the most open of
+/// `HEADINGS` directions
+///
+/// Each heading is judged by how far it's unobstructed, counting those on either side at half
+/// weight so that a character faces the middle of an opening rather than skimming along a wall.
+/// Headings into chunks not yet populated count as obstructed at once. Ties go to the earliest
+/// heading, counting from the direction nearest `position`'s own forward, so the result depends
+/// only on `position` and the voxels around it.
+pub fn facing_open_space(
+    cfg: &SimConfig,
+    graph: &Graph,
+    position: &Position,
+) -> na::UnitQuaternion<f32> {
+    let Some(up) = graph.get_relative_up(position) else {
+        return na::one();
+    };
+    // Level reference direction, from the forward direction unless that's nearly vertical
+    let reference = if up.z.abs() < 0.9 {
+        -na::Vector3::z()
+    } else {
+        na::Vector3::x()
+    };
+    let reference = reference - up.into_inner() * up.dot(&reference);
+    let max_tanh_distance = (LOOKOUT_DISTANCE * cfg.meters_to_absolute).tanh();
+
+    let headings = (0..HEADINGS)
+        .map(|i| {
+            let angle = std::f32::consts::TAU * i as f32 / HEADINGS as f32;
+            (na::UnitQuaternion::from_axis_angle(&up, angle) * reference).normalize()
+        })
+        .collect::<Vec<_>>();
+    let openness = headings
+        .iter()
+        .map(|heading| {
+            let ray = Ray::new(math::origin(), heading.push(0.0));
+            match ray_cast(graph, position, &ray, max_tanh_distance) {
+                Ok(Some(hit)) => hit.tanh_distance,
+                Ok(None) => max_tanh_distance,
+                Err(_) => 0.0,
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut best: Option<(f32, usize)> = None;
+    for i in 0..HEADINGS {
+        let score = openness[(i + HEADINGS - 1) % HEADINGS]
+            + 2.0 * openness[i]
+            + openness[(i + 1) % HEADINGS];
+        if !matches!(best, Some((x, _)) if x >= score) {
+            best = Some((score, i));
+        }
+    }
+    let heading = headings[best.unwrap().1];
+    // Characters look along their -Z axis
+    na::UnitQuaternion::face_towards(&-heading, &up)
+}
+
+/// A point `SEARCH_HEIGHT` above the reference surface, directly over the center of `node`
+fn search_start(graph: &Graph, node: NodeId) -> Position {
+    let state = &graph.get(node).as_ref().unwrap().state;
+    let up = state.up_direction().cast::<f64>().xyz().normalize();
+    let local = math::translate_along(&(up * (SEARCH_HEIGHT - f64::from(state.elevation()))));
+    let (node, transform) = graph.normalize_transform(node, &local);
+    Position {
+        node,
+        local: (transform * local).cast(),
+    }
+}
+
+/// A ray pointing straight down from `search`
+fn search_ray(graph: &Graph, search: &Position) -> Ray {
+    let up = graph.get_relative_up(search).unwrap();
+    Ray::new(math::origin(), -up.into_inner().push(0.0))
+}
+
+fn search_tanh_distance() -> f32 {
+    (2.0 * SEARCH_HEIGHT).tanh() as f32
+}
+
+/// Where a character standing on the ground beneath `search` would be
+fn find_ground(cfg: &SimConfig, graph: &Graph, search: &Position) -> Option<Position> {
+    if material_at(graph, search)? != Material::Void {
+        return None;
+    }
+    let ray = search_ray(graph, search);
+    let hit = ray_cast(graph, search, &ray, search_tanh_distance()).ok()??;
+    let up = graph.get_relative_up(search).unwrap();
+    let feet = math::lorentz_normalize(&ray.ray_point(hit.tanh_distance));
+    let local = search.local
+        * math::translate(&math::origin(), &feet)
+        * math::translate_along(&(up.into_inner() * cfg.character.character_radius));
+    let (node, transform) = graph.normalize_transform(search.node, &local);
+    Some(Position {
+        node,
+        local: transform * local,
+    })
+}
+
+/// Material of the voxel containing `position`, or `None` if its chunk isn't populated
+fn material_at(graph: &Graph, position: &Position) -> Option<Material> {
+    let (chunk, coords, _) = locate_voxel(graph, graph.layout(), position)?;
+    graph.get_block(chunk, coords)
+}
+
+/// Location of `position` relative to the root node
+fn root_point(graph: &Graph, position: &Position) -> na::Vector4<f64> {
+    let mut node = position.node;
+    let mut transform = na::Matrix4::identity();
+    while let Some(side) = graph.parent(node) {
+        transform = side.reflection_f64() * transform;
+        node = graph.neighbor(node, side).unwrap();
+    }
+    transform * position.local.cast::<f64>() * math::origin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        node::{MarginCoords, VoxelData},
+        SimConfigRaw,
+    };
+
+    fn config(spawn_distance: u32) -> SimConfig {
+        SimConfig::from_raw(&SimConfigRaw {
+            chunk_size: Some(4),
+            spawn_distance: Some(spawn_distance),
+            ..Default::default()
+        })
+    }
+
+    /// Terrain that's solid exactly beneath the reference surface
+    fn flat(graph: &Graph, chunk: ChunkId) -> VoxelData {
+        let dimension = graph.layout().dimension();
+        let up = graph
+            .get(chunk.node)
+            .as_ref()
+            .unwrap()
+            .state
+            .up_direction()
+            .cast::<f64>();
+        let voxels = MarginCoords::all(dimension)
+            .map(|coords| {
+                // Voxel centers, accounting for the margin
+                let coords = na::Vector3::from(coords.0)
+                    .map(|x| (f64::from(x) - 0.5) / f64::from(dimension));
+                let point = chunk.vertex.chunk_to_node_f64() * coords.push(1.0);
+                if math::mip(&up, &point) < 0.0 {
+                    Material::Dirt
+                } else {
+                    Material::Void
+                }
+            })
+            .collect::<Vec<_>>();
+        VoxelData::Dense(voxels.into())
+    }
+
+    fn resolve(
+        cfg: &SimConfig,
+        graph: &mut Graph,
+        points: &mut SpawnPoints,
+        terrain: impl Fn(&Graph, ChunkId) -> VoxelData,
+    ) {
+        while !points.is_resolved() {
+            for chunk in points.missing_chunks(graph) {
+                let voxels = terrain(graph, chunk);
+                graph.populate_chunk(chunk, voxels, false);
+            }
+            points.resolve_next(cfg, graph);
+        }
+    }
+
+    fn join(graph: &Graph, points: &SpawnPoints, count: usize) -> Vec<Position> {
+        let mut occupied = Vec::new();
+        for _ in 0..count {
+            let position = points.choose(graph, &occupied).unwrap();
+            occupied.push(position);
+        }
+        occupied
+    }
+
+    #[test]
+    fn joins_are_spread_out() {
+        let cfg = config(3);
+        let mut graph = Graph::new(cfg.chunk_size);
+        let mut points = SpawnPoints::new(&cfg, &mut graph, 64, 0);
+        resolve(&cfg, &mut graph, &mut points, flat);
+
+        let spawned = join(&graph, &points, 10);
+        for (i, a) in spawned.iter().enumerate() {
+            // Everyone starts just above the ground
+            assert_eq!(material_at(&graph, a), Some(Material::Void));
+            for b in &spawned[..i] {
+                let separation = math::distance(&root_point(&graph, a), &root_point(&graph, b));
+                assert!(separation > 0.8, "spawned {separation} apart");
+            }
+        }
+    }
+
+    #[test]
+    fn buried_candidates_are_rejected() {
+        let cfg = config(2);
+        let mut graph = Graph::new(cfg.chunk_size);
+        let mut points = SpawnPoints::new(&cfg, &mut graph, 8, 0);
+        resolve(&cfg, &mut graph, &mut points, |_, _| {
+            VoxelData::Solid(Material::Dirt)
+        });
+        assert!(points
+            .candidates
+            .iter()
+            .all(|x| matches!(x.state, CandidateState::Rejected)));
+        assert!(points.choose(&graph, &[]).is_none());
+
+        // Likewise with nothing to stand on
+        let mut graph = Graph::new(cfg.chunk_size);
+        let mut points = SpawnPoints::new(&cfg, &mut graph, 8, 0);
+        resolve(&cfg, &mut graph, &mut points, |_, _| {
+            VoxelData::Solid(Material::Void)
+        });
+        assert!(points.choose(&graph, &[]).is_none());
+    }
+
+    #[test]
+    fn selection_is_deterministic() {
+        let cfg = config(2);
+        let spawn = || {
+            let mut graph = Graph::new(cfg.chunk_size);
+            let mut points = SpawnPoints::new(&cfg, &mut graph, CANDIDATES, 42);
+            resolve(&cfg, &mut graph, &mut points, flat);
+            join(&graph, &points, 4)
+                .into_iter()
+                .map(|x| (graph.hash_of(x.node), x.local))
+                .collect::<Vec<_>>()
+        };
+        let first = spawn();
+        assert!(!first.is_empty());
+        assert_eq!(first, spawn());
+    }
+
+    #[test]
+    fn zero_distance_disables_candidates() {
+        let cfg = config(0);
+        let mut graph = Graph::new(cfg.chunk_size);
+        let points = SpawnPoints::new(&cfg, &mut graph, CANDIDATES, 0);
+        assert!(points.is_resolved());
+        assert!(points.choose(&graph, &[]).is_none());
+    }
+}
@@ -0,0 +1,296 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use common::{graph::ChunkCounts, mem_budget::MemReport, traffic::Traffic, Step};
+
+/// Summary of the server's state, published periodically for monitoring
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ServerStats {
+    pub step: Step,
+    pub connections: Vec<ConnectionStats>,
+    /// Time taken by each step since the previous snapshot
+    pub tick: TickStats,
+    /// Number of nodes in the graph
+    pub nodes: u32,
+    pub chunks: ChunkCounts,
+    /// Memory held by each pool in the server's process
+    pub memory: MemReport,
+    /// How chunks are being generated, as named by `WorldgenPath::name`
+    pub worldgen_path: &'static str,
+    pub graph_regions: GraphRegionStats,
+    /// How many regions of the graph are being simulated
+    pub regions: RegionActivityStats,
+    pub tasks: TaskStats,
+}
+
+impl ServerStats {
+    /// Number of connections that have spawned a character
+    pub fn players(&self) -> usize {
+        self.connections.iter().filter(|x| x.name.is_some()).count()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStats {
+    /// Name of the character, if one has been spawned
+    pub name: Option<String>,
+    /// Estimated round trip time in milliseconds
+    pub rtt_ms: f64,
+    /// Messages waiting to be sent
+    pub queued: usize,
+    /// Messages queued for sending since the character was spawned
+    pub sent: u64,
+    /// State deltas discarded because too many messages were waiting
+    pub dropped: u64,
+    /// Bytes of graph updates held back to stay under the configured bulk bandwidth
+    pub bulk_waiting_bytes: usize,
+    /// Messages sent since the connection was opened, by kind
+    pub traffic_sent: Traffic,
+    /// Messages received since the connection was opened, by kind
+    pub traffic_received: Traffic,
+    /// Milliseconds since the client last sent a command, or connected if it's sent none
+    pub idle_ms: f64,
+    /// Milliseconds since the client last sent anything, keepalives included
+    pub silent_ms: f64,
+    /// Whether the client's character is marked away for its idleness
+    pub afk: bool,
+}
+
+/// Use of the encoded regions of the graph kept for sending to clients
+#[derive(Debug, Copy, Clone, Default, Serialize)]
+pub struct GraphRegionStats {
+    /// Regions whose encodings are kept
+    pub cached: usize,
+    /// Bytes of the encodings kept
+    pub cached_bytes: usize,
+    /// Regions encoded since the server started
+    pub encoded: u64,
+    /// Regions sent without being encoded again since the server started
+    pub reused: u64,
+}
+
+/// Number of regions of the graph in each state of activity
+#[derive(Debug, Copy, Clone, Default, Serialize)]
+pub struct RegionActivityStats {
+    /// Within the wake distance of a character
+    pub active: usize,
+    /// Recently active
+    pub cooling: usize,
+    pub hibernating: usize,
+}
+
+/// Things the server is scheduled to do
+#[derive(Debug, Copy, Clone, Default, Serialize)]
+pub struct TaskStats {
+    /// Tasks yet to be done
+    pub pending: usize,
+    /// Times a due task was put off to a later step for want of budget since the server started
+    pub deferred: u64,
+}
+
+/// Distribution of step durations, in milliseconds
+#[derive(Debug, Copy, Clone, Default, Serialize)]
+pub struct TickStats {
+    /// Number of steps measured
+    pub count: usize,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+    pub phases: PhaseStats,
+}
+
+/// Mean time taken by each phase of simulating characters in a step, in milliseconds
+#[derive(Debug, Copy, Clone, Default, Serialize)]
+pub struct PhaseStats {
+    pub pre_ms: f64,
+    pub parallel_ms: f64,
+    pub post_ms: f64,
+}
+
+/// Time taken by each phase of simulating characters in a step
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PhaseTimes {
+    /// Collecting characters and the block updates they request, serially
+    pub pre: Duration,
+    /// Moving characters, in parallel
+    pub parallel: Duration,
+    /// Applying what characters did to the rest of the world, serially
+    pub post: Duration,
+}
+
+/// Durations of steps since the last call to `summarize`
+#[derive(Default)]
+pub struct TickTimes {
+    samples: Vec<Duration>,
+    /// Sum of the phase times recorded
+    phases: PhaseTimes,
+    phase_samples: u32,
+}
+
+impl TickTimes {
+    pub fn record(&mut self, duration: Duration) {
+        self.samples.push(duration);
+    }
+
+    pub fn record_phases(&mut self, phases: PhaseTimes) {
+        self.phases.pre += phases.pre;
+        self.phases.parallel += phases.parallel;
+        self.phases.post += phases.post;
+        self.phase_samples += 1;
+    }
+
+    /// Compute the distribution of recorded durations and forget them
+    pub fn summarize(&mut self) -> TickStats {
+        self.samples.sort_unstable();
+        let percentile = |p: f64| {
+            let Some(last) = self.samples.len().checked_sub(1) else {
+                return 0.0;
+            };
+            let index = (p * last as f64).round() as usize;
+            self.samples[index].as_secs_f64() * 1e3
+        };
+        let mean = |total: Duration| match self.phase_samples {
+            0 => 0.0,
+            n => total.as_secs_f64() * 1e3 / f64::from(n),
+        };
+        let result = TickStats {
+            count: self.samples.len(),
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+            max_ms: percentile(1.0),
+            phases: PhaseStats {
+                pre_ms: mean(self.phases.pre),
+                parallel_ms: mean(self.phases.parallel),
+                post_ms: mean(self.phases.post),
+            },
+        };
+        self.samples.clear();
+        self.phases = PhaseTimes::default();
+        self.phase_samples = 0;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::mem_budget::PoolUsage;
+
+    #[test]
+    fn tick_percentiles() {
+        let mut times = TickTimes::default();
+        for ms in (1..=100).rev() {
+            times.record(Duration::from_millis(ms));
+        }
+        let stats = times.summarize();
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.p50_ms.round(), 51.0);
+        assert_eq!(stats.p90_ms.round(), 90.0);
+        assert_eq!(stats.p99_ms.round(), 99.0);
+        assert_eq!(stats.max_ms.round(), 100.0);
+
+        // Samples are consumed
+        let stats = times.summarize();
+        assert_eq!(stats.count, 0);
+        assert_eq!(stats.max_ms, 0.0);
+    }
+
+    #[test]
+    fn phase_means() {
+        let mut times = TickTimes::default();
+        for ms in [1, 3] {
+            times.record_phases(PhaseTimes {
+                pre: Duration::from_millis(ms),
+                parallel: Duration::from_millis(10 * ms),
+                post: Duration::ZERO,
+            });
+        }
+        let phases = times.summarize().phases;
+        assert_eq!(phases.pre_ms, 2.0);
+        assert_eq!(phases.parallel_ms, 20.0);
+        assert_eq!(phases.post_ms, 0.0);
+        assert_eq!(times.summarize().phases.pre_ms, 0.0);
+    }
+
+    #[test]
+    fn json_snapshot() {
+        let stats = ServerStats {
+            step: 42,
+            connections: vec![
+                ConnectionStats {
+                    name: Some("alice".into()),
+                    rtt_ms: 12.5,
+                    queued: 3,
+                    sent: 1200,
+                    dropped: 4,
+                    bulk_waiting_bytes: 0,
+                    traffic_sent: Traffic::default(),
+                    traffic_received: Traffic::default(),
+                    idle_ms: 1500.0,
+                    silent_ms: 100.0,
+                    afk: false,
+                },
+                ConnectionStats {
+                    name: None,
+                    rtt_ms: 80.0,
+                    queued: 0,
+                    sent: 0,
+                    dropped: 0,
+                    bulk_waiting_bytes: 0,
+                    traffic_sent: Traffic::default(),
+                    traffic_received: Traffic::default(),
+                    idle_ms: 0.0,
+                    silent_ms: 0.0,
+                    afk: false,
+                },
+            ],
+            tick: TickStats {
+                count: 30,
+                p50_ms: 1.5,
+                p90_ms: 2.0,
+                p99_ms: 4.25,
+                max_ms: 5.0,
+                phases: PhaseStats {
+                    pre_ms: 0.25,
+                    parallel_ms: 0.5,
+                    post_ms: 0.75,
+                },
+            },
+            nodes: 1234,
+            chunks: ChunkCounts {
+                populated: 5000,
+                modified: 7,
+            },
+            memory: MemReport {
+                pools: vec![PoolUsage {
+                    name: "graph",
+                    bytes: 4096,
+                }],
+            },
+            worldgen_path: "avx2",
+            graph_regions: GraphRegionStats {
+                cached: 2,
+                cached_bytes: 512,
+                encoded: 5,
+                reused: 9,
+            },
+            regions: RegionActivityStats {
+                active: 3,
+                cooling: 1,
+                hibernating: 10,
+            },
+            tasks: TaskStats {
+                pending: 6,
+                deferred: 8,
+            },
+        };
+        assert_eq!(stats.players(), 1);
+        assert_eq!(
+            serde_json::to_string(&stats).unwrap(),
+            r#"{"step":42,"connections":[{"name":"alice","rtt_ms":12.5,"queued":3,"sent":1200,"dropped":4,"bulk_waiting_bytes":0,"traffic_sent":{},"traffic_received":{},"idle_ms":1500.0,"silent_ms":100.0,"afk":false},{"name":null,"rtt_ms":80.0,"queued":0,"sent":0,"dropped":0,"bulk_waiting_bytes":0,"traffic_sent":{},"traffic_received":{},"idle_ms":0.0,"silent_ms":0.0,"afk":false}],"tick":{"count":30,"p50_ms":1.5,"p90_ms":2.0,"p99_ms":4.25,"max_ms":5.0,"phases":{"pre_ms":0.25,"parallel_ms":0.5,"post_ms":0.75}},"nodes":1234,"chunks":{"populated":5000,"modified":7},"memory":{"pools":[{"name":"graph","bytes":4096}]},"worldgen_path":"avx2","graph_regions":{"cached":2,"cached_bytes":512,"encoded":5,"reused":9},"regions":{"active":3,"cooling":1,"hibernating":10},"tasks":{"pending":6,"deferred":8}}"#
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! Minimal HTTP endpoint reporting `ServerStats` for monitoring
+//!
+//! `/` serves the latest statistics as JSON, and `/metrics` serves the same numbers in the
+//! Prometheus text format for scraping.
+
+use std::{fmt::Write as _, io};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+use tracing::{debug, warn};
+
+use common::traffic::Traffic;
+
+use crate::{ConnectionStats, ServerStats};
+
+/// Largest request accepted, which is plenty for a request line and a few headers
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// Serve requests on `listener` until the server shuts down
+pub async fn serve(listener: TcpListener, stats: watch::Receiver<ServerStats>) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("couldn't accept status connection: {}", e);
+                continue;
+            }
+        };
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, stats).await {
+                debug!(%peer, "status request failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, stats: watch::Receiver<ServerStats>) -> io::Result<()> {
+    // Read until the end of the headers; any body is ignored
+    let mut request = Vec::new();
+    let mut buf = [0; 512];
+    while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST_SIZE {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let (status, content_type, body) = respond(&request, &stats.borrow());
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Status line, content type, and body of the response to `request`
+fn respond(request: &[u8], stats: &ServerStats) -> (&'static str, &'static str, String) {
+    let request = String::from_utf8_lossy(request);
+    let mut request_line = request.lines().next().unwrap_or("").split(' ');
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/")) => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string_pretty(stats).unwrap(),
+        ),
+        (Some("GET"), Some("/metrics")) => {
+            ("200 OK", "text/plain; version=0.0.4", metrics_text(stats))
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", "not found\n".into()),
+        _ => (
+            "400 Bad Request",
+            "text/plain",
+            "only GET is supported\n".into(),
+        ),
+    }
+}
+
+/// Render `stats` in the Prometheus text exposition format
+fn metrics_text(stats: &ServerStats) -> String {
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, value: &dyn std::fmt::Display| {
+        writeln!(out, "# HELP hypermine_{name} {help}").unwrap();
+        writeln!(out, "# TYPE hypermine_{name} gauge").unwrap();
+        writeln!(out, "hypermine_{name} {value}").unwrap();
+    };
+    gauge("step", "Current simulation step", &stats.step);
+    gauge("players", "Connected players", &stats.players());
+    gauge("connections", "Open connections", &stats.connections.len());
+    gauge("nodes", "Nodes in the graph", &stats.nodes);
+    gauge(
+        "chunks_populated",
+        "Populated chunks",
+        &stats.chunks.populated,
+    );
+    gauge("chunks_modified", "Modified chunks", &stats.chunks.modified);
+    gauge("tick_count", "Steps in the last period", &stats.tick.count);
+    gauge(
+        "graph_regions_cached",
+        "Encoded graph regions kept for sending to clients",
+        &stats.graph_regions.cached,
+    );
+    gauge(
+        "tasks_pending",
+        "Scheduled tasks yet to be done",
+        &stats.tasks.pending,
+    );
+
+    writeln!(
+        out,
+        "# HELP hypermine_worldgen_path How chunks are being generated"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hypermine_worldgen_path gauge").unwrap();
+    writeln!(
+        out,
+        "hypermine_worldgen_path{{path=\"{}\"}} 1",
+        escape_label(stats.worldgen_path)
+    )
+    .unwrap();
+
+    writeln!(
+        out,
+        "# HELP hypermine_memory_bytes Memory held by each pool"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hypermine_memory_bytes gauge").unwrap();
+    for pool in &stats.memory.pools {
+        writeln!(
+            out,
+            "hypermine_memory_bytes{{pool=\"{}\"}} {}",
+            escape_label(pool.name),
+            pool.bytes
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP hypermine_regions Regions of the graph in each state of activity"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hypermine_regions gauge").unwrap();
+    let regions = &stats.regions;
+    for (state, count) in [
+        ("active", regions.active),
+        ("cooling", regions.cooling),
+        ("hibernating", regions.hibernating),
+    ] {
+        writeln!(out, "hypermine_regions{{state=\"{state}\"}} {count}").unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP hypermine_tick_seconds Step duration over the last period"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hypermine_tick_seconds gauge").unwrap();
+    for (quantile, ms) in [
+        ("0.5", stats.tick.p50_ms),
+        ("0.9", stats.tick.p90_ms),
+        ("0.99", stats.tick.p99_ms),
+        ("1", stats.tick.max_ms),
+    ] {
+        writeln!(
+            out,
+            "hypermine_tick_seconds{{quantile=\"{quantile}\"}} {}",
+            ms / 1e3
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP hypermine_tick_phase_seconds Mean time per step spent in each phase of simulating characters"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hypermine_tick_phase_seconds gauge").unwrap();
+    let phases = &stats.tick.phases;
+    for (phase, ms) in [
+        ("pre", phases.pre_ms),
+        ("parallel", phases.parallel_ms),
+        ("post", phases.post_ms),
+    ] {
+        writeln!(
+            out,
+            "hypermine_tick_phase_seconds{{phase=\"{phase}\"}} {}",
+            ms / 1e3
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP hypermine_rtt_seconds Round trip time of each connection"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hypermine_rtt_seconds gauge").unwrap();
+    for (i, connection) in stats.connections.iter().enumerate() {
+        let name = connection.name.as_deref().unwrap_or("");
+        writeln!(
+            out,
+            "hypermine_rtt_seconds{{connection=\"{i}\",name=\"{}\"}} {}",
+            escape_label(name),
+            connection.rtt_ms / 1e3
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP hypermine_deferred_tasks_total Due tasks put off to a later step for want of budget"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hypermine_deferred_tasks_total counter").unwrap();
+    writeln!(
+        out,
+        "hypermine_deferred_tasks_total {}",
+        stats.tasks.deferred
+    )
+    .unwrap();
+
+    writeln!(
+        out,
+        "# HELP hypermine_dropped_deltas_total State deltas discarded because a connection fell behind"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE hypermine_dropped_deltas_total counter").unwrap();
+    for (i, connection) in stats.connections.iter().enumerate() {
+        let name = connection.name.as_deref().unwrap_or("");
+        writeln!(
+            out,
+            "hypermine_dropped_deltas_total{{connection=\"{i}\",name=\"{}\"}} {}",
+            escape_label(name),
+            connection.dropped
+        )
+        .unwrap();
+    }
+
+    let directions: [(&str, &str, fn(&ConnectionStats) -> &Traffic); 2] = [
+        (
+            "sent",
+            "Bytes sent to each connection on the wire, by kind of message",
+            |connection| &connection.traffic_sent,
+        ),
+        (
+            "received",
+            "Bytes received from each connection on the wire, by kind of message",
+            |connection| &connection.traffic_received,
+        ),
+    ];
+    for (direction, help, traffic) in directions {
+        writeln!(out, "# HELP hypermine_{direction}_bytes_total {help}").unwrap();
+        writeln!(out, "# TYPE hypermine_{direction}_bytes_total counter").unwrap();
+        for (i, connection) in stats.connections.iter().enumerate() {
+            let name = connection.name.as_deref().unwrap_or("");
+            for (kind, count) in traffic(connection).iter() {
+                writeln!(
+                    out,
+                    "hypermine_{direction}_bytes_total{{connection=\"{i}\",name=\"{}\",kind=\"{kind}\"}} {}",
+                    escape_label(name),
+                    count.bytes
+                )
+                .unwrap();
+            }
+        }
+    }
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PhaseStats, RegionActivityStats, TaskStats, TickStats};
+    use common::mem_budget::{MemReport, PoolUsage};
+
+    #[test]
+    fn routes() {
+        let mut traffic_sent = Traffic::default();
+        traffic_sent.record("StateDelta", 120, 120);
+        traffic_sent.record("StateDelta", 130, 130);
+        let stats = ServerStats {
+            step: 7,
+            connections: vec![ConnectionStats {
+                name: Some("a \"b\"".into()),
+                rtt_ms: 20.0,
+                queued: 0,
+                sent: 100,
+                dropped: 2,
+                bulk_waiting_bytes: 0,
+                traffic_sent,
+                traffic_received: Traffic::default(),
+                idle_ms: 0.0,
+                silent_ms: 0.0,
+                afk: false,
+            }],
+            memory: MemReport {
+                pools: vec![PoolUsage {
+                    name: "dense voxels",
+                    bytes: 1024,
+                }],
+            },
+            tick: TickStats {
+                phases: PhaseStats {
+                    parallel_ms: 4.0,
+                    ..PhaseStats::default()
+                },
+                ..TickStats::default()
+            },
+            worldgen_path: "avx2",
+            regions: RegionActivityStats {
+                hibernating: 10,
+                ..RegionActivityStats::default()
+            },
+            tasks: TaskStats {
+                pending: 2,
+                deferred: 3,
+            },
+            ..ServerStats::default()
+        };
+        let (status, content_type, body) = respond(b"GET / HTTP/1.1\r\n\r\n", &stats);
+        assert_eq!((status, content_type), ("200 OK", "application/json"));
+        assert!(body.contains("\"step\": 7"));
+
+        let (status, _, body) = respond(b"GET /metrics HTTP/1.1\r\n\r\n", &stats);
+        assert_eq!(status, "200 OK");
+        assert!(body.contains("\nhypermine_step 7\n"));
+        assert!(body.contains("\nhypermine_players 1\n"));
+        assert!(
+            body.contains("hypermine_rtt_seconds{connection=\"0\",name=\"a \\\"b\\\"\"} 0.02\n")
+        );
+        assert!(body.contains("\nhypermine_memory_bytes{pool=\"dense voxels\"} 1024\n"));
+        assert!(body.contains("\nhypermine_worldgen_path{path=\"avx2\"} 1\n"));
+        assert!(body.contains("\nhypermine_regions{state=\"hibernating\"} 10\n"));
+        assert!(body.contains("\nhypermine_tick_phase_seconds{phase=\"parallel\"} 0.004\n"));
+        assert!(body.contains("\nhypermine_tasks_pending 2\n"));
+        assert!(body.contains("\nhypermine_deferred_tasks_total 3\n"));
+        assert!(body
+            .contains("hypermine_dropped_deltas_total{connection=\"0\",name=\"a \\\"b\\\"\"} 2\n"));
+        assert!(body.contains(
+            "hypermine_sent_bytes_total{connection=\"0\",name=\"a \\\"b\\\"\",kind=\"StateDelta\"} 250\n"
+        ));
+        assert!(body.contains("# TYPE hypermine_received_bytes_total counter\n"));
+
+        assert_eq!(
+            respond(b"GET /nope HTTP/1.1\r\n\r\n", &stats).0,
+            "404 Not Found"
+        );
+        assert_eq!(
+            respond(b"POST / HTTP/1.1\r\n\r\n", &stats).0,
+            "400 Bad Request"
+        );
+        assert_eq!(respond(b"", &stats).0, "400 Bad Request");
+    }
+}
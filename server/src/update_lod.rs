@@ -0,0 +1,261 @@
+//! Updating distant entities less often than nearby ones
+//!
+//! Each client is told the state of entities near its character every step, and that of farther
+//! ones only every few steps. Entities updated at the same stride take turns by ID, so that each
+//! `StateDelta` is about the same size. Clients carry entities along their last known velocity in
+//! between. Spawns and despawns travel separately, and are never held back.
+
+use fxhash::{FxHashMap, FxHashSet};
+
+use common::{
+    dodeca,
+    graph::{Graph, NodeId},
+    math,
+    proto::{Position, StateDelta},
+    traversal::nearby_nodes,
+    EntityId, Step, MAX_UPDATE_STRIDE,
+};
+
+/// Distance within which entities are updated every step, enough to cover the adjacent nodes
+const ADJACENT_DISTANCE: f32 = 2.0 * dodeca::BOUNDING_SPHERE_RADIUS_F32;
+
+/// Steps between updates of an entity `distance` away from a viewer who sees as far as
+/// `view_distance`, both in absolute units
+pub fn stride(distance: f32, view_distance: f32) -> Step {
+    if distance <= ADJACENT_DISTANCE {
+        1
+    } else if distance <= 0.5 * view_distance {
+        2
+    } else if distance <= 0.75 * view_distance {
+        4
+    } else {
+        MAX_UPDATE_STRIDE
+    }
+}
+
+/// Whether an entity updated every `stride` steps is due at `step`
+fn is_turn(step: Step, id: EntityId, stride: Step) -> bool {
+    // Strides divide 2^32, so taking turns is unaffected by the step counter wrapping
+    (step as u32).wrapping_add(id.to_bits() as u32) % stride as u32 == 0
+}
+
+/// A client's character, from which the distances of entities are judged
+pub struct Viewer {
+    id: EntityId,
+    position: Position,
+    view_distance: f32,
+    /// Transforms into the frame of the viewer's node from those of nodes within the view distance
+    nodes: FxHashMap<NodeId, na::Matrix4<f32>>,
+}
+
+impl Viewer {
+    pub fn new(graph: &Graph, id: EntityId, position: Position, view_distance: f32) -> Self {
+        Self {
+            id,
+            position,
+            view_distance,
+            nodes: nearby_nodes(graph, &position, f64::from(view_distance))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Distance in absolute units to `position`, or infinity beyond the view distance
+    fn distance(&self, position: &Position) -> f32 {
+        let Some(transform) = self.nodes.get(&position.node) else {
+            return f32::INFINITY;
+        };
+        let point = math::lorentz_normalize(
+            &(math::mtranspose(&self.position.local) * transform * position.local * math::origin()),
+        );
+        math::distance(&math::origin(), &point)
+    }
+}
+
+/// What a client was last told of each entity, deciding when it's next told
+#[derive(Default)]
+pub struct UpdateSchedule {
+    sent: FxHashMap<EntityId, Sent>,
+}
+
+#[derive(Copy, Clone)]
+struct Sent {
+    /// Stride the entity was on when last sent
+    stride: Step,
+    /// `CharacterState::teleports` as last sent, or zero for other entities
+    teleports: u16,
+}
+
+impl UpdateSchedule {
+    /// Remove from `delta` the state of entities that `viewer` needn't be told of at this step
+    ///
+    /// Entities are sent at once when first seen, when they draw near enough to be updated more
+    /// often, and when they teleport.
+    pub fn thin(&mut self, viewer: &Viewer, delta: &mut StateDelta) {
+        let teleports = delta
+            .character_states
+            .iter()
+            .map(|&(id, ref state)| (id, state.teleports))
+            .collect::<FxHashMap<_, _>>();
+        let mut due = FxHashSet::default();
+        // Rebuilt from scratch so that despawned entities are forgotten
+        let mut sent = FxHashMap::default();
+        for &(id, ref position) in &delta.positions {
+            let stride = if id == viewer.id {
+                1
+            } else {
+                stride(viewer.distance(position), viewer.view_distance)
+            };
+            let teleports = teleports.get(&id).copied().unwrap_or(0);
+            let previous = self.sent.get(&id).copied();
+            let urgent = match previous {
+                None => true,
+                Some(x) => stride < x.stride || teleports != x.teleports,
+            };
+            if urgent || is_turn(delta.step, id, stride) {
+                due.insert(id);
+                sent.insert(id, Sent { stride, teleports });
+            } else if let Some(previous) = previous {
+                sent.insert(id, previous);
+            }
+        }
+        self.sent = sent;
+        delta.positions.retain(|(id, _)| due.contains(id));
+        delta.character_states.retain(|(id, _)| due.contains(id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::proto::CharacterState;
+
+    const VIEW_DISTANCE: f32 = 8.0;
+
+    fn at(distance: f32) -> Position {
+        Position {
+            node: NodeId::ROOT,
+            local: math::translate_along(&(na::Vector3::x() * distance)),
+        }
+    }
+
+    fn state(teleports: u16) -> CharacterState {
+        CharacterState {
+            velocity: na::zero(),
+            on_ground: false,
+            orientation: na::one(),
+            teleports,
+            health: 1.0,
+            afk: false,
+        }
+    }
+
+    fn delta(step: Step, entities: &[(EntityId, Position)]) -> StateDelta {
+        StateDelta {
+            step,
+            latest_input: 0,
+            positions: entities.to_vec(),
+            character_states: entities.iter().map(|&(id, _)| (id, state(0))).collect(),
+            world_time: 0.0,
+            tether: None,
+        }
+    }
+
+    fn ids(delta: &StateDelta) -> Vec<EntityId> {
+        delta.positions.iter().map(|&(id, _)| id).collect()
+    }
+
+    #[test]
+    fn strides_by_distance() {
+        assert_eq!(stride(0.5, VIEW_DISTANCE), 1);
+        assert_eq!(stride(3.5, VIEW_DISTANCE), 2);
+        assert_eq!(stride(5.5, VIEW_DISTANCE), 4);
+        assert_eq!(stride(7.5, VIEW_DISTANCE), MAX_UPDATE_STRIDE);
+        assert_eq!(stride(f32::INFINITY, VIEW_DISTANCE), MAX_UPDATE_STRIDE);
+    }
+
+    #[test]
+    fn payload_smoothed() {
+        let graph = Graph::new(12);
+        let viewer_id = EntityId::from_bits(1000);
+        let viewer = Viewer::new(&graph, viewer_id, at(0.0), VIEW_DISTANCE);
+        // A hundred entities in each band
+        let mut entities = vec![(viewer_id, at(0.0))];
+        for (band, distance) in [0.5, 3.5, 5.5, 7.5].into_iter().enumerate() {
+            entities.extend(
+                (0..100).map(|i| (EntityId::from_bits(band as u64 * 100 + i), at(distance))),
+            );
+        }
+
+        let mut schedule = UpdateSchedule::default();
+        let mut first = delta(0, &entities);
+        schedule.thin(&viewer, &mut first);
+        assert_eq!(first.positions.len(), entities.len(), "all newly seen");
+
+        let mut counts = FxHashMap::<EntityId, u32>::default();
+        for step in 1..=8 {
+            let mut delta = delta(step, &entities);
+            schedule.thin(&viewer, &mut delta);
+            // 1 + 100 + 100/2 + 100/4 + 100/8, the last rounding either way
+            assert!(
+                (188..=189).contains(&delta.positions.len()),
+                "{} sent at step {step}",
+                delta.positions.len()
+            );
+            assert_eq!(delta.character_states.len(), delta.positions.len());
+            for id in ids(&delta) {
+                *counts.entry(id).or_default() += 1;
+            }
+        }
+        // Every entity is sent once per stride
+        assert_eq!(counts[&viewer_id], 8);
+        for (band, expected) in [8, 4, 2, 1].into_iter().enumerate() {
+            for i in 0..100 {
+                assert_eq!(
+                    counts[&EntityId::from_bits(band as u64 * 100 + i)],
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn critical_updates_bypass_stride() {
+        let graph = Graph::new(12);
+        let viewer_id = EntityId::from_bits(1);
+        let viewer = Viewer::new(&graph, viewer_id, at(0.0), VIEW_DISTANCE);
+        let far = EntityId::from_bits(2);
+        let mut entities = vec![(viewer_id, at(0.0)), (far, at(7.5))];
+        let mut schedule = UpdateSchedule::default();
+        schedule.thin(&viewer, &mut delta(0, &entities));
+
+        // Not its turn, so it's held back
+        let step = 1;
+        assert!(!is_turn(step, far, MAX_UPDATE_STRIDE));
+        let mut held = delta(step, &entities);
+        schedule.thin(&viewer, &mut held);
+        assert_eq!(ids(&held), [viewer_id]);
+
+        // Unless it teleports
+        let mut teleported = delta(step, &entities);
+        teleported.character_states[1].1.teleports = 1;
+        schedule.thin(&viewer, &mut teleported);
+        assert_eq!(ids(&teleported), [viewer_id, far]);
+
+        // Or draws near
+        entities[1].1 = at(3.5);
+        let mut approached = delta(step, &entities);
+        approached.character_states[1].1.teleports = 1;
+        schedule.thin(&viewer, &mut approached);
+        assert_eq!(ids(&approached), [viewer_id, far]);
+
+        // Or is newly seen
+        let new = EntityId::from_bits(3);
+        entities.push((new, at(7.5)));
+        let mut appeared = delta(step + 1, &entities);
+        appeared.character_states[1].1.teleports = 1;
+        appeared.character_states[2].1.teleports = 1;
+        schedule.thin(&viewer, &mut appeared);
+        assert!(ids(&appeared).contains(&new));
+    }
+}